@@ -0,0 +1,50 @@
+//! Storage backends for the cache.
+
+use key::CacheKey;
+use std::collections::HashMap;
+
+/// A key-value store holding the entries of a cache.
+///
+/// The entries are opaque byte strings. A backend may find entries by the full key or by its
+/// `digest()` only: the cache checks that an entry belongs to the requested key before using it,
+/// and ignores entries that can't be decoded.
+pub trait CacheBackend {
+    /// Get the entry stored for `key`, if any.
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>>;
+
+    /// Store `entry` for `key`, replacing any existing entry.
+    fn put(&mut self, key: &CacheKey, entry: Vec<u8>);
+}
+
+/// A backend keeping the entries in memory, indexed by the digests of their keys.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    entries: HashMap<u64, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Are there no entries?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        self.entries.get(&key.digest()).cloned()
+    }
+
+    fn put(&mut self, key: &CacheKey, entry: Vec<u8>) {
+        self.entries.insert(key.digest(), entry);
+    }
+}
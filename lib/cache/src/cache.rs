@@ -0,0 +1,206 @@
+//! The cache of compiled functions.
+
+use backend::CacheBackend;
+use cretonne::binemit::{decode_compiled, encode_compiled};
+use cretonne::isa::TargetIsa;
+use cretonne::result::CtonError;
+use cretonne::{compile_function, CompiledFunction, Context};
+use key::CacheKey;
+
+/// A cache of compiled functions stored in the backend `B`.
+///
+/// Each entry holds the length of the full cache key as a little-endian `u32`, the key itself, and
+/// the compiled function encoded by `cretonne::binemit::encode_compiled`.
+pub struct Cache<B: CacheBackend> {
+    backend: B,
+    hits: usize,
+    misses: usize,
+}
+
+impl<B: CacheBackend> Cache<B> {
+    /// Create a cache storing its entries in `backend`.
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Get a reference to the backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Get a mutable reference to the backend.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Get the number of lookups that found a usable entry.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Get the number of lookups that didn't find a usable entry.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Look up the compiled function stored for `key`.
+    ///
+    /// Entries that belong to a different key or can't be decoded are treated as missing.
+    pub fn get(&mut self, key: &CacheKey) -> Option<CompiledFunction> {
+        let compiled = self.backend.get(key).and_then(|entry| decode_entry(key, &entry));
+        if compiled.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        compiled
+    }
+
+    /// Store `compiled` as the compiled function for `key`.
+    pub fn put(&mut self, key: &CacheKey, compiled: &CompiledFunction) {
+        let key_bytes = key.as_bytes();
+        let len = key_bytes.len() as u32;
+        let mut entry = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+        entry.extend_from_slice(key_bytes);
+        entry.extend_from_slice(&encode_compiled(compiled));
+        self.backend.put(key, entry);
+    }
+
+    /// Compile the function in `ctx` for `isa`, or get it from the cache.
+    ///
+    /// On a cache hit, the function in `ctx` is left as it is. Otherwise it is compiled with
+    /// `cretonne::compile_function` and the result is stored in the cache. The key is computed by
+    /// `CacheKey::new`, so `ctx` must not have custom passes or a trap handler that change the
+    /// generated code. Use `get` and `put` with a key describing that configuration instead.
+    pub fn compile(
+        &mut self,
+        ctx: &mut Context,
+        isa: &TargetIsa,
+    ) -> Result<CompiledFunction, CtonError> {
+        let key = CacheKey::new(&ctx.func, isa);
+        if let Some(compiled) = self.get(&key) {
+            return Ok(compiled);
+        }
+        let compiled = compile_function(ctx, isa)?;
+        self.put(&key, &compiled);
+        Ok(compiled)
+    }
+}
+
+/// Decode `entry` if it is the entry for `key`.
+fn decode_entry(key: &CacheKey, entry: &[u8]) -> Option<CompiledFunction> {
+    if entry.len() < 4 {
+        return None;
+    }
+    let (len, rest) = entry.split_at(4);
+    let len = u32::from(len[0]) | u32::from(len[1]) << 8 | u32::from(len[2]) << 16 |
+        u32::from(len[3]) << 24;
+    let key_bytes = key.as_bytes();
+    if len as usize != key_bytes.len() || !rest.starts_with(key_bytes) {
+        return None;
+    }
+    decode_compiled(&rest[key_bytes.len()..]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::MemoryBackend;
+    use cretonne::cursor::{Cursor, FuncCursor};
+    use cretonne::ir::{types, AbiParam, ExternalName, Function, InstBuilder};
+    use cretonne::isa;
+    use cretonne::settings::{self, Configurable};
+
+    fn intel(opt_level: &str) -> Box<TargetIsa> {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder))
+    }
+
+    /// Build a function that calls `%callee`, creating `spare` unused EBBs first to change the
+    /// numbering.
+    fn func(name: &str, spare: usize) -> Function {
+        let mut func = Function::new();
+        func.name = ExternalName::testcase(name);
+        func.signature.returns.push(AbiParam::new(types::I64));
+        for _ in 0..spare {
+            func.dfg.make_ebb();
+        }
+        let sig = func.import_signature(func.signature.clone());
+        let callee = func.import_function(::cretonne::ir::ExtFuncData {
+            name: ExternalName::testcase("callee"),
+            signature: sig,
+            colocated: false,
+        });
+        let ebb = func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb);
+            let call = pos.ins().call(callee, &[]);
+            let v = pos.func.dfg.first_result(call);
+            pos.ins().trapz(v, ::cretonne::ir::TrapCode::User(3));
+            pos.ins().return_(&[v]);
+        }
+        func
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let isa = intel("best");
+        let mut cache = Cache::new(MemoryBackend::new());
+
+        let first = cache.compile(&mut Context::for_function(func("a", 0)), &*isa).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        assert_eq!(first.relocs.len(), 1);
+        assert_eq!(first.traps.sites.len(), 1);
+
+        // The same body with a different name and numbering is a hit.
+        let second = cache.compile(&mut Context::for_function(func("b", 3)), &*isa).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(second.code, first.code);
+        assert_eq!(second.relocs.as_slice(), first.relocs.as_slice());
+        assert_eq!(second.traps.sites, first.traps.sites);
+
+        // Different settings are a miss.
+        let isa2 = intel("fastest");
+        cache.compile(&mut Context::for_function(func("a", 0)), &*isa2).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+        assert_eq!(cache.backend().len(), 2);
+    }
+
+    #[test]
+    fn key_mismatch() {
+        let isa = intel("best");
+        let key = CacheKey::new(&func("a", 0), &*isa);
+        let mut other = key.clone();
+        other.add_config("trap handler");
+        assert_ne!(key.digest(), other.digest());
+
+        // A backend that returns the same entry for every key.
+        struct OneEntry(Option<Vec<u8>>);
+        impl CacheBackend for OneEntry {
+            fn get(&self, _: &CacheKey) -> Option<Vec<u8>> {
+                self.0.clone()
+            }
+            fn put(&mut self, _: &CacheKey, entry: Vec<u8>) {
+                self.0 = Some(entry);
+            }
+        }
+
+        let mut cache = Cache::new(OneEntry(None));
+        let compiled = compile_function(&mut Context::for_function(func("a", 0)), &*isa).unwrap();
+        cache.put(&key, &compiled);
+        assert!(cache.get(&key).is_some());
+        assert!(cache.get(&other).is_none());
+
+        // A corrupt entry is a miss.
+        cache.backend_mut().0.as_mut().unwrap().pop();
+        assert!(cache.get(&key).is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+}
@@ -0,0 +1,79 @@
+//! Cache keys.
+
+use cretonne::dedup::canonical_text;
+use cretonne::ir::Function;
+use cretonne::isa::TargetIsa;
+use cretonne::VERSION;
+
+/// The key identifying the generated code of a function.
+///
+/// The key consists of the Cretonne version, the name and settings of the target ISA, and the
+/// canonical form of the function's IR as computed by `cretonne::dedup`. It is stored in full with
+/// each cache entry so a lookup never returns the code of a different function, even if the
+/// backend only uses the `digest()` of the key to find the entry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    text: String,
+    digest: u64,
+}
+
+impl CacheKey {
+    /// Create the key for compiling `func` for `isa`.
+    ///
+    /// This must be called before the function is compiled, since compilation modifies it.
+    pub fn new(func: &Function, isa: &TargetIsa) -> Self {
+        let text = format!(
+            "cretonne {}\nisa {}\n{}\n{}",
+            VERSION,
+            isa.name(),
+            isa,
+            canonical_text(func)
+        );
+        Self {
+            digest: fnv1a(text.as_bytes()),
+            text,
+        }
+    }
+
+    /// Add embedder configuration that affects the generated code to the key.
+    ///
+    /// A `Context` with custom passes or a trap handler can generate different code for the same
+    /// function, so the embedder must describe that configuration in the key.
+    pub fn add_config(&mut self, config: &str) {
+        self.text.push_str("\nconfig ");
+        self.text.push_str(config);
+        self.digest = fnv1a(self.text.as_bytes());
+    }
+
+    /// Get a 64-bit digest of the key.
+    ///
+    /// The digest is computed with a fixed hash function, so it is stable across runs and can be
+    /// used as a file name or database key by a persistent backend.
+    pub fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    /// Get the full key as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.text.as_bytes()
+    }
+}
+
+/// Compute the 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fnv1a;
+
+    #[test]
+    fn fnv() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
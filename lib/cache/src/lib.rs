@@ -0,0 +1,55 @@
+//! Cache of compiled functions.
+//!
+//! Compiling the same functions again on every run of an embedder is wasted work. This crate
+//! stores the machine code, relocations, and trap sites of compiled functions in a key-value
+//! backend supplied by the embedder, and retrieves them when an identical function is compiled
+//! for the same target again.
+//!
+//! A `CacheKey` identifies the generated code of a function. It is derived from the function's IR
+//! together with the Cretonne version and the settings of the target ISA. The function name and
+//! the numbering of its entities don't affect the key, so identical functions with different names
+//! share a cache entry.
+//!
+//! The `Cache` type ties a `CacheBackend` to the compilation of functions:
+//!
+//! ```
+//! # extern crate cretonne;
+//! # extern crate cton_cache;
+//! # use cretonne::{isa, settings, Context};
+//! # use cton_cache::{Cache, MemoryBackend};
+//! # fn main() {
+//! # let isa = match isa::lookup("riscv") {
+//! #     Ok(b) => b.finish(settings::Flags::new(&settings::builder())),
+//! #     Err(_) => return,
+//! # };
+//! let mut cache = Cache::new(MemoryBackend::new());
+//! let mut ctx = Context::new();
+//! // ... build the function in `ctx.func` ...
+//! # {
+//! #     use cretonne::ir::InstBuilder;
+//! #     let ebb = ctx.func.dfg.make_ebb();
+//! #     let mut pos = cretonne::cursor::FuncCursor::new(&mut ctx.func);
+//! #     cretonne::cursor::Cursor::insert_ebb(&mut pos, ebb);
+//! #     pos.ins().return_(&[]);
+//! # }
+//! let compiled = cache.compile(&mut ctx, &*isa).unwrap();
+//! # assert!(!compiled.code.is_empty());
+//! # }
+//! ```
+
+#![deny(missing_docs,
+        trivial_numeric_casts,
+        unused_extern_crates)]
+
+#![cfg_attr(feature="cargo-clippy",
+            allow(new_without_default, redundant_field_names))]
+
+extern crate cretonne;
+
+pub use backend::{CacheBackend, MemoryBackend};
+pub use cache::Cache;
+pub use key::CacheKey;
+
+mod backend;
+mod cache;
+mod key;
@@ -10,6 +10,8 @@ from cdsl.formats import InstructionFormat
 from cdsl.operands import VALUE, VARIABLE_ARGS
 from .immediates import imm64, uimm8, uimm32, ieee32, ieee64, offset32
 from .immediates import boolean, intcc, floatcc, memflags, regunit, trapcode
+from .immediates import atomic_ordering, atomic_rmw_op, barrier_kind
+from .immediates import custom_opcode
 from . import entities
 from .entities import ebb, sig_ref, func_ref, stack_slot, heap
 
@@ -19,6 +21,7 @@ UnaryIeee32 = InstructionFormat(ieee32)
 UnaryIeee64 = InstructionFormat(ieee64)
 UnaryBool = InstructionFormat(boolean)
 UnaryGlobalVar = InstructionFormat(entities.global_var)
+UnaryConst = InstructionFormat(entities.constant)
 
 Binary = InstructionFormat(VALUE, VALUE)
 BinaryImm = InstructionFormat(VALUE, imm64)
@@ -51,14 +54,31 @@ BranchInt = InstructionFormat(intcc, VALUE, ebb, VARIABLE_ARGS)
 BranchFloat = InstructionFormat(floatcc, VALUE, ebb, VARIABLE_ARGS)
 BranchIcmp = InstructionFormat(intcc, VALUE, VALUE, ebb, VARIABLE_ARGS)
 BranchTable = InstructionFormat(VALUE, entities.jump_table)
+BranchTableBase = InstructionFormat(entities.jump_table)
 
 Call = InstructionFormat(func_ref, VARIABLE_ARGS)
 IndirectCall = InstructionFormat(sig_ref, VALUE, VARIABLE_ARGS)
+Invoke = InstructionFormat(func_ref, ebb, VARIABLE_ARGS)
 FuncAddr = InstructionFormat(func_ref)
+# A call with no arguments whose callee also needs a global variable, like a
+# TLS address computation through `__tls_get_addr`.
+CallGlobalVar = InstructionFormat(func_ref, entities.global_var)
 
 Load = InstructionFormat(memflags, VALUE, offset32)
 Store = InstructionFormat(memflags, VALUE, VALUE, offset32)
 
+# Atomic memory operations. The controlling type is the type of the value
+# loaded or stored, not the address type.
+AtomicLoad = InstructionFormat(memflags, atomic_ordering, VALUE)
+AtomicStore = InstructionFormat(memflags, atomic_ordering, VALUE, VALUE)
+AtomicCas = InstructionFormat(
+        memflags, atomic_ordering, VALUE, VALUE, VALUE, typevar_operand=1)
+AtomicRmw = InstructionFormat(
+        atomic_rmw_op, memflags, atomic_ordering, VALUE, VALUE,
+        typevar_operand=1)
+Fence = InstructionFormat(atomic_ordering)
+Barrier = InstructionFormat(barrier_kind)
+
 StackLoad = InstructionFormat(stack_slot, offset32)
 StackStore = InstructionFormat(VALUE, stack_slot, offset32)
 
@@ -76,6 +96,10 @@ Trap = InstructionFormat(trapcode)
 CondTrap = InstructionFormat(VALUE, trapcode)
 IntCondTrap = InstructionFormat(intcc, VALUE, trapcode)
 FloatCondTrap = InstructionFormat(floatcc, VALUE, trapcode)
+BinaryTrap = InstructionFormat(VALUE, VALUE, trapcode)
+
+# Embedder-defined operations, expanded by the legalizer.
+Custom = InstructionFormat(custom_opcode, VARIABLE_ARGS)
 
 # Finally extract the names of global variables in this module.
 InstructionFormat.extract_names(globals())
@@ -66,6 +66,8 @@ intcc = ImmediateKind(
             'ugt': 'UnsignedGreaterThan',
             'ule': 'UnsignedLessThanOrEqual',
             'ult': 'UnsignedLessThan',
+            'of':  'Overflow',
+            'nof': 'NotOverflow',
         })
 
 #: A condition code for comparing floating point values.
@@ -100,6 +102,55 @@ memflags = ImmediateKind(
         'Memory operation flags',
         default_member='flags', rust_type='ir::MemFlags')
 
+#: A memory ordering constraint for the atomic memory operations.
+#:
+#: This enumerated operand kind is used for :cton:inst:`atomic_load`,
+#: :cton:inst:`fence` and the other atomic instructions, and corresponds to the
+#: `ir::AtomicOrdering` Rust type.
+atomic_ordering = ImmediateKind(
+        'atomic_ordering',
+        'A memory ordering for atomic operations.',
+        default_member='ordering',
+        rust_type='ir::AtomicOrdering',
+        values={
+            'relaxed': 'Relaxed',
+            'acquire': 'Acquire',
+            'release': 'Release',
+            'acq_rel': 'AcqRel',
+            'seq_cst': 'SeqCst',
+        })
+
+#: An operation for the :cton:inst:`atomic_rmw` instruction.
+#:
+#: This enumerated operand kind corresponds to the `ir::AtomicRmwOp` Rust type.
+atomic_rmw_op = ImmediateKind(
+        'atomic_rmw_op',
+        'An atomic read-modify-write operation.',
+        default_member='op',
+        rust_type='ir::AtomicRmwOp',
+        values={
+            'add': 'Add',
+            'sub': 'Sub',
+            'and': 'And',
+            'or': 'Or',
+            'xor': 'Xor',
+            'xchg': 'Xchg',
+        })
+
+#: The kind of memory accesses ordered by a :cton:inst:`barrier`.
+#:
+#: This enumerated operand kind corresponds to the `ir::BarrierKind` Rust type.
+barrier_kind = ImmediateKind(
+        'barrier_kind',
+        'The memory accesses ordered by a compiler barrier.',
+        default_member='kind',
+        rust_type='ir::BarrierKind',
+        values={
+            'loads': 'Loads',
+            'stores': 'Stores',
+            'all': 'All',
+        })
+
 #: A register unit in the current target ISA.
 regunit = ImmediateKind(
         'regunit',
@@ -118,6 +169,17 @@ trapcode = ImmediateKind(
         values={
             "stk_ovf": 'StackOverflow',
             "heap_oob": 'HeapOutOfBounds',
+            "heap_misaligned": 'HeapMisaligned',
             "int_ovf": 'IntegerOverflow',
             "int_divz": 'IntegerDivisionByZero',
         })
+
+#: An opcode defined by the embedder.
+#:
+#: This operand kind is used for the :cton:inst:`custom` instruction and
+#: corresponds to the `ir::CustomOpcode` Rust type.
+custom_opcode = ImmediateKind(
+        'custom_opcode',
+        'An embedder-defined opcode.',
+        default_member='op',
+        rust_type='ir::CustomOpcode')
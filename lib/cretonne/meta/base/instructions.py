@@ -11,7 +11,8 @@ from cdsl.instructions import Instruction, InstructionGroup
 from base.types import f32, f64, b1, iflags, fflags
 from base.immediates import imm64, uimm8, uimm32, ieee32, ieee64, offset32
 from base.immediates import boolean, intcc, floatcc, memflags, regunit
-from base.immediates import trapcode
+from base.immediates import trapcode, atomic_ordering, atomic_rmw_op
+from base.immediates import barrier_kind, custom_opcode
 from base import entities
 from cdsl.ti import WiderOrEq
 import base.formats  # noqa
@@ -38,6 +39,9 @@ Mem = TypeVar(
 MemTo = TypeVar(
         'MemTo', 'Any type that can be stored in memory',
         ints=True, floats=True, simd=True)
+AnyTo = TypeVar(
+        'AnyTo', 'Any integer, float, or boolean scalar or vector type',
+        ints=True, floats=True, bools=True, scalars=True, simd=True)
 
 addr = Operand('addr', iAddr)
 
@@ -144,6 +148,31 @@ br_table = Instruction(
         """,
         ins=(x, JT), is_branch=True)
 
+jump_table_base = Instruction(
+        'jump_table_base', r"""
+        Get the address of the jump table ``JT``.
+
+        The jump table is emitted after the function code as a sequence of
+        32-bit entries, one for each EBB in ``JT``, holding the offset of the
+        EBB relative to ``addr``. The jump table must not have any missing
+        entries.
+
+        This instruction is used by the legalizer to lower :inst:`br_table`.
+        """,
+        ins=JT, outs=addr)
+
+dest = Operand('dest', iAddr, doc='address of the destination EBB')
+indirect_jump_table_br = Instruction(
+        'indirect_jump_table_br', r"""
+        Branch indirectly to ``dest`` which is the address of one of the EBBs
+        in the jump table ``JT``.
+
+        The jump table only describes the possible destinations of the branch
+        to the control flow graph. The address is normally computed from an
+        entry of the table located with :inst:`jump_table_base`.
+        """,
+        ins=(dest, JT), is_branch=True, is_terminator=True)
+
 code = Operand('code', trapcode)
 trap = Instruction(
         'trap', r"""
@@ -224,6 +253,46 @@ call_indirect = Instruction(
         """,
         ins=(SIG, callee, args), outs=rvals, is_call=True)
 
+invoke = Instruction(
+        'invoke', r"""
+        Direct function call with a landing pad.
+
+        Call a function which has been declared in the preamble, like
+        :inst:`call`. When the called function returns normally, execution
+        continues with the following instruction, just like a conditional
+        branch that isn't taken. A :inst:`jump` can follow to transfer control
+        to a separate normal successor EBB.
+
+        When an exception unwinds the stack through the call, the unwinder
+        resumes execution at the landing pad ``EBB`` instead. The landing pad
+        can't have EBB parameters, and it can't use the results of the call.
+        The values live into the landing pad are always kept in stack slots.
+        """,
+        ins=(FN, EBB, args), outs=rvals, is_call=True, is_branch=True)
+
+return_call = Instruction(
+        'return_call', r"""
+        Direct tail call.
+
+        Call a function which has been declared in the preamble, reusing the
+        stack frame of the current function. The called function returns
+        directly to the caller of the current function, so its return types
+        must match the current function's signature. The argument types must
+        match the called function's signature.
+        """,
+        ins=(FN, args), is_call=True, is_terminator=True)
+
+return_call_indirect = Instruction(
+        'return_call_indirect', r"""
+        Indirect tail call.
+
+        Call the function pointed to by `callee` with the given arguments,
+        reusing the stack frame of the current function. The called function
+        must match the specified signature, and its return types must match
+        the current function's signature.
+        """,
+        ins=(SIG, callee, args), is_call=True, is_terminator=True)
+
 func_addr = Instruction(
         'func_addr', r"""
         Get the address of a function.
@@ -355,6 +424,106 @@ istore32 = Instruction(
         """,
         ins=(Flags, x, p, Offset), can_store=True)
 
+#
+# Atomic memory operations
+#
+
+iAtomic = TypeVar(
+        'iAtomic', 'An integer type that can be accessed atomically',
+        ints=(8, 64))
+Ordering = Operand('Ordering', atomic_ordering, 'Memory ordering constraint')
+x = Operand('x', iAtomic)
+a = Operand('a', iAtomic)
+
+atomic_load = Instruction(
+        'atomic_load', r"""
+        Atomically load from memory at ``p``.
+
+        The address must be aligned to the size of the loaded type. The
+        ordering can't be ``release`` or ``acq_rel``.
+        """,
+        ins=(Flags, Ordering, p), outs=a,
+        can_load=True, other_side_effects=True)
+
+atomic_store = Instruction(
+        'atomic_store', r"""
+        Atomically store ``x`` to memory at ``p``.
+
+        The address must be aligned to the size of the stored type. The
+        ordering can't be ``acquire`` or ``acq_rel``.
+        """,
+        ins=(Flags, Ordering, x, p),
+        can_store=True, other_side_effects=True)
+
+e = Operand('e', iAtomic, doc='Expected value')
+x = Operand('x', iAtomic, doc='Replacement value')
+a = Operand('a', iAtomic, doc='Value loaded')
+
+atomic_cas = Instruction(
+        'atomic_cas', r"""
+        Atomic compare-and-swap.
+
+        Atomically load the value at ``p`` and store ``x`` in its place if it
+        is equal to ``e``. The loaded value is returned either way, so the
+        exchange succeeded if ``a == e``.
+
+        The address must be aligned to the size of the accessed type. The
+        ordering applies to both the load and the store.
+        """,
+        ins=(Flags, Ordering, p, e, x), outs=a,
+        can_load=True, can_store=True, other_side_effects=True)
+
+Op = Operand('Op', atomic_rmw_op, 'Operation to apply')
+x = Operand('x', iAtomic, doc='Second operand of ``Op``')
+
+atomic_rmw = Instruction(
+        'atomic_rmw', r"""
+        Atomic read-modify-write.
+
+        Atomically load the value at ``p``, combine it with ``x`` and store
+        the result in its place. The loaded value is returned.
+
+        The ``add``, ``sub``, ``and``, ``or`` and ``xor`` operations compute
+        ``a Op x``, while ``xchg`` simply stores ``x``. Targets without a
+        native instruction for an operation legalize it into an
+        :inst:`atomic_cas` loop.
+
+        The address must be aligned to the size of the accessed type. The
+        ordering applies to both the load and the store.
+        """,
+        ins=(Op, Flags, Ordering, p, x), outs=a,
+        can_load=True, can_store=True, other_side_effects=True)
+
+fence = Instruction(
+        'fence', r"""
+        A memory fence.
+
+        Prevent memory accesses from being reordered across the fence as
+        required by ``Ordering``, both by the compiler and by the processor.
+        An ``acquire`` fence orders the preceding loads before the following
+        memory accesses, and a ``release`` fence orders the preceding memory
+        accesses before the following stores. The ordering can't be
+        ``relaxed``.
+        """,
+        ins=Ordering, other_side_effects=True)
+
+Kind = Operand('Kind', barrier_kind)
+
+barrier = Instruction(
+        'barrier', r"""
+        A compiler barrier.
+
+        Prevent the compiler from moving the memory accesses selected by
+        ``Kind`` across the barrier. A ``loads`` barrier keeps loads in place,
+        a ``stores`` barrier keeps stores in place, and an ``all`` barrier
+        keeps both. Unlike :inst:`fence`, the barrier doesn't constrain the
+        processor, so it doesn't generate any machine code.
+
+        This can be used to order volatile accesses, or memory accesses that
+        are observed by a signal handler on the same thread.
+        """,
+        ins=Kind, other_side_effects=True)
+
 x = Operand('x', Mem, doc='Value to be stored')
 a = Operand('a', Mem, doc='Value loaded')
 Offset = Operand('Offset', offset32, 'In-bounds offset into stack slot')
@@ -415,6 +584,17 @@ globalsym_addr = Instruction(
         """,
         ins=GV, outs=addr)
 
+# A specialized form of global_addr instructions that only handles
+# thread-local variables.
+tls_addr = Instruction(
+        'tls_addr', r"""
+        Compute the address of global variable GV, which is thread-local.
+
+        The address is computed for the current thread, using the access model
+        of GV.
+        """,
+        ins=GV, outs=addr)
+
 #
 # WebAssembly bounds-checked heap accesses.
 #
@@ -474,6 +654,18 @@ f64const = Instruction(
         """,
         ins=N, outs=a)
 
+C = Operand('C', entities.constant)
+a = Operand('a', Mem, doc='Value loaded')
+const_load = Instruction(
+        'const_load', r"""
+        Load a value from the constant pool.
+
+        The constant ``C`` must have the same size as the loaded type. The
+        constant pool can't be modified, so this instruction has no side
+        effects.
+        """,
+        ins=C, outs=a)
+
 N = Operand('N', boolean)
 a = Operand('a', Bool, doc='A constant boolean scalar or vector value')
 bconst = Instruction(
@@ -756,6 +948,9 @@ icmp = Instruction(
         sle    ule      Less than or equal
         ====== ======== =========
 
+        The ``of`` and ``nof`` condition codes test for signed overflow of the
+        subtraction ``x - y``.
+
         When this instruction compares integer vectors, it returns a boolean
         vector of lane-wise comparisons.
         """,
@@ -1062,6 +1257,138 @@ isub_borrow = Instruction(
         """,
         ins=(x, y, b_in), outs=(a, b_out))
 
+c_if_out = Operand('c_out', iflags, doc='CPU flags of the addition')
+iadd_ifcout = Instruction(
+        'iadd_ifcout', r"""
+        Add integers with carry and overflow flags out.
+
+        Same as :inst:`iadd_cout`, but the carry and signed overflow are
+        returned in the CPU flags. The carry out can be tested with the
+        ``ult`` and ``uge`` condition codes, and the signed overflow with the
+        ``of`` and ``nof`` condition codes.
+
+        This instruction is only available on ISAs with CPU flags.
+        """,
+        ins=(x, y), outs=(a, c_if_out))
+
+b_if_out = Operand('b_out', iflags, doc='CPU flags of the subtraction')
+isub_ifbout = Instruction(
+        'isub_ifbout', r"""
+        Subtract integers with borrow and overflow flags out.
+
+        Same as :inst:`isub_bout`, but the borrow and signed overflow are
+        returned in the CPU flags. The flags are the same as those produced by
+        ``ifcmp x, y``, so all the integer condition codes can be used to test
+        them. In particular, the borrow out is ``ult`` and the signed overflow
+        is ``of``.
+
+        This instruction is only available on ISAs with CPU flags.
+        """,
+        ins=(x, y), outs=(a, b_if_out))
+
+o_out = Operand('o_out', b1, doc='Signed overflow')
+imul_oflow = Instruction(
+        'imul_oflow', r"""
+        Multiply integers with signed overflow out.
+
+        Same as :inst:`imul` with an additional output which is true when the
+        signed product doesn't fit in the result type.
+
+        .. math::
+
+            a &= x y \pmod 2^B \\
+            o_{out} &= x y < -2^{B-1} \lor x y >= 2^{B-1}
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y), outs=(a, o_out))
+
+o_if_out = Operand('o_out', iflags, doc='CPU flags of the multiplication')
+imul_ifoflow = Instruction(
+        'imul_ifoflow', r"""
+        Multiply integers with signed overflow flags out.
+
+        Same as :inst:`imul_oflow`, but the signed overflow is returned in the
+        CPU flags. Only the ``of`` and ``nof`` condition codes are meaningful
+        for testing the flags.
+
+        This instruction is only available on ISAs with CPU flags.
+        """,
+        ins=(x, y), outs=(a, o_if_out))
+
+sadd_trap = Instruction(
+        'sadd_trap', r"""
+        Add signed integers, trapping on overflow.
+
+        Same as :inst:`iadd`, but traps with ``code`` when the sum of the
+        signed operands doesn't fit in the result type.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
+uadd_trap = Instruction(
+        'uadd_trap', r"""
+        Add unsigned integers, trapping on overflow.
+
+        Same as :inst:`iadd`, but traps with ``code`` when the addition
+        carries out.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
+ssub_trap = Instruction(
+        'ssub_trap', r"""
+        Subtract signed integers, trapping on overflow.
+
+        Same as :inst:`isub`, but traps with ``code`` when the difference of
+        the signed operands doesn't fit in the result type.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
+usub_trap = Instruction(
+        'usub_trap', r"""
+        Subtract unsigned integers, trapping on overflow.
+
+        Same as :inst:`isub`, but traps with ``code`` when the subtraction
+        borrows, that is when ``y > x`` as unsigned integers.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
+smul_trap = Instruction(
+        'smul_trap', r"""
+        Multiply signed integers, trapping on overflow.
+
+        Same as :inst:`imul`, but traps with ``code`` when the product of the
+        signed operands doesn't fit in the result type.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
+umul_trap = Instruction(
+        'umul_trap', r"""
+        Multiply unsigned integers, trapping on overflow.
+
+        Same as :inst:`imul`, but traps with ``code`` when the product of the
+        unsigned operands doesn't fit in the result type.
+
+        Polymorphic over all scalar integer types, but does not support vector
+        types.
+        """,
+        ins=(x, y, code), outs=a, can_trap=True)
+
 #
 # Bitwise operations.
 #
@@ -1565,6 +1892,21 @@ bitcast = Instruction(
         """,
         ins=x, outs=a)
 
+x = Operand('x', Any)
+a = Operand('a', AnyTo, 'Bits of `x` reinterpreted')
+
+raw_bitcast = Instruction(
+        'raw_bitcast', r"""
+        Reinterpret the bits in `x` as a different type of the same size.
+
+        Unlike :inst:`bitcast`, this also works for boolean and vector types
+        that can't be stored to memory. The bits are not changed, so the
+        result can only be used reliably by operations that don't depend on
+        the representation of its type, like bitwise operations, or by a
+        :inst:`raw_bitcast` back to the original type.
+        """,
+        ins=x, outs=a)
+
 Bool = TypeVar(
         'Bool',
         'A scalar or vector boolean type',
@@ -1781,7 +2123,7 @@ fcvt_from_sint = Instruction(
 
 WideInt = TypeVar(
         'WideInt', 'An integer type with lanes from `i16` upwards',
-        ints=(16, 64), simd=True)
+        ints=(16, 128), simd=True)
 x = Operand('x', WideInt)
 lo = Operand(
         'lo', WideInt.half_width(), 'The low bits of `x`')
@@ -1802,8 +2144,8 @@ isplit = Instruction(
 
 
 NarrowInt = TypeVar(
-        'NarrowInt', 'An integer type with lanes type to `i32`',
-        ints=(8, 32), simd=True)
+        'NarrowInt', 'An integer type with lanes type to `i64`',
+        ints=(8, 64), simd=True)
 lo = Operand('lo', NarrowInt)
 hi = Operand('hi', NarrowInt)
 a = Operand(
@@ -1820,4 +2162,28 @@ iconcat = Instruction(
         """,
         ins=(lo, hi), outs=a)
 
+#
+# Embedder-defined operations.
+#
+
+Op = Operand('Op', custom_opcode, doc='Embedder-defined opcode')
+args = Operand('args', VARIABLE_ARGS, doc='Operands of the operation')
+a = Operand('a', Any, doc='Result of the operation')
+
+custom = Instruction(
+        'custom', r"""
+        An operation defined by the embedder.
+
+        Frontends for domain-specific languages can carry their own operations
+        through the optimization passes, and have them lowered into standard
+        instructions late in the pipeline. The expansion of ``Op`` must be
+        registered in the ``CustomOpcodes`` given to the legalizer, which
+        replaces the instruction by its expansion.
+
+        Cretonne doesn't know the semantics of the operation, so it is assumed
+        to access memory and to have other side effects.
+        """,
+        ins=(Op, args), outs=a,
+        can_load=True, can_store=True, other_side_effects=True)
+
 GROUP.close()
@@ -12,7 +12,7 @@ from . import instructions as insts
 from . import types
 from .instructions import iadd, iadd_cout, iadd_cin, iadd_carry, iadd_imm
 from .instructions import isub, isub_bin, isub_bout, isub_borrow, irsub_imm
-from .instructions import imul, imul_imm
+from .instructions import imul, imul_imm, imul_oflow, umulhi, smulhi
 from .instructions import sdiv, sdiv_imm, udiv, udiv_imm
 from .instructions import srem, srem_imm, urem, urem_imm
 from .instructions import band, bor, bxor, isplit, iconcat
@@ -69,20 +69,26 @@ expand.custom_legalize(insts.trapz, 'expand_cond_trap')
 expand.custom_legalize(insts.trapnz, 'expand_cond_trap')
 expand.custom_legalize(insts.br_table, 'expand_br_table')
 expand.custom_legalize(insts.select, 'expand_select')
+expand.custom_legalize(insts.atomic_rmw, 'expand_atomic_rmw')
 
 # Custom expansions for floating point constants.
 # These expansions require bit-casting or creating constant pool entries.
 expand.custom_legalize(insts.f32const, 'expand_fconst')
 expand.custom_legalize(insts.f64const, 'expand_fconst')
+expand.custom_legalize(insts.const_load, 'expand_const_load')
 
 x = Var('x')
 y = Var('y')
 a = Var('a')
 a1 = Var('a1')
 a2 = Var('a2')
+a3 = Var('a3')
+a4 = Var('a4')
+a5 = Var('a5')
 b = Var('b')
 b1 = Var('b1')
 b2 = Var('b2')
+b3 = Var('b3')
 b_in = Var('b_in')
 b_int = Var('b_int')
 c = Var('c')
@@ -90,6 +96,8 @@ c1 = Var('c1')
 c2 = Var('c2')
 c_in = Var('c_in')
 c_int = Var('c_int')
+x1 = Var('x1')
+y1 = Var('y1')
 xl = Var('xl')
 xh = Var('xh')
 yl = Var('yl')
@@ -97,6 +105,12 @@ yh = Var('yh')
 al = Var('al')
 ah = Var('ah')
 cc = Var('cc')
+big = Var('big')
+zero = Var('zero')
+sign = Var('sign')
+carry = Var('carry')
+f = Var('f')
+code = Var('code')
 
 narrow.legalize(
         a << iadd(x, y),
@@ -139,6 +153,218 @@ narrow.legalize(
             a << iconcat(al, ah)
         ))
 
+narrow.legalize(
+        a << imul(x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            (yl, yh) << isplit(y),
+            a1 << imul(xh, yl),
+            a2 << imul(xl, yh),
+            a3 << iadd(a1, a2),
+            a4 << umulhi(xl, yl),
+            ah << iadd(a3, a4),
+            al << imul(xl, yl),
+            a << iconcat(al, ah)
+        ))
+
+narrow.legalize(
+        b << icmp(intcc.eq, x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            (yl, yh) << isplit(y),
+            b1 << icmp(intcc.eq, xl, yl),
+            b2 << icmp(intcc.eq, xh, yh),
+            b << band(b1, b2)
+        ))
+
+narrow.legalize(
+        b << icmp(intcc.ne, x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            (yl, yh) << isplit(y),
+            b1 << icmp(intcc.ne, xl, yl),
+            b2 << icmp(intcc.ne, xh, yh),
+            b << bor(b1, b2)
+        ))
+
+# An ordered comparison is decided by the high halves unless they are equal.
+# The low halves are always compared as unsigned numbers.
+for cond,       hi_cond,    lo_cond in [
+        (intcc.slt, intcc.slt, intcc.ult),
+        (intcc.sle, intcc.slt, intcc.ule),
+        (intcc.sgt, intcc.sgt, intcc.ugt),
+        (intcc.sge, intcc.sgt, intcc.uge),
+        (intcc.ult, intcc.ult, intcc.ult),
+        (intcc.ule, intcc.ult, intcc.ule),
+        (intcc.ugt, intcc.ugt, intcc.ugt),
+        (intcc.uge, intcc.ugt, intcc.uge)]:
+    narrow.legalize(
+            b << icmp(cond, x, y),
+            Rtl(
+                (xl, xh) << isplit(x),
+                (yl, yh) << isplit(y),
+                b1 << icmp(hi_cond, xh, yh),
+                b2 << icmp(intcc.eq, xh, yh),
+                b3 << icmp(lo_cond, xl, yl),
+                c << band(b2, b3),
+                b << bor(b1, c)
+            ))
+
+# The following narrowings use constants that depend on the type, so they are
+# only defined for splitting `i128` into `i64` halves.
+#
+# The 64-bit immediate of a 128-bit constant is sign-extended.
+narrow.legalize(
+        a << iconst.i128(y),
+        Rtl(
+            al << iconst.i64(y),
+            ah << sshr_imm(al, imm64(63)),
+            a << iconcat(al, ah)
+        ))
+
+# The immediate forms are narrowed through `iconst`.
+for inst_imm,      inst in [
+        (iadd_imm, iadd),
+        (imul_imm, imul),
+        (band_imm, band),
+        (bor_imm, bor),
+        (bxor_imm, bxor)]:
+    narrow.legalize(
+            a << inst_imm.i128(x, y),
+            Rtl(
+                a1 << iconst(y),
+                a << inst(x, a1)
+            ))
+
+narrow.legalize(
+        b << icmp_imm.i128(cc, x, y),
+        Rtl(
+            a1 << iconst(y),
+            b << icmp(cc, x, a1)
+        ))
+
+for inst_imm,      inst in [
+        (ishl_imm, ishl),
+        (sshr_imm, sshr),
+        (ushr_imm, ushr)]:
+    narrow.legalize(
+            a << inst_imm.i128(x, y),
+            Rtl(
+                a1 << iconst.i32(y),
+                a << inst(x, a1)
+            ))
+
+# Shifts of the halves take the shift amount modulo 64, so the bits moving
+# between the halves are shifted by one first and then by `63 - n`, computed
+# as `n ^ 63`. This also works for a zero shift amount. The results are
+# selected depending on bit 6 of the shift amount.
+narrow.legalize(
+        a << ishl.i128(x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            a1 << ishl(xl, y),
+            a2 << ishl(xh, y),
+            a3 << ushr_imm(xl, imm64(1)),
+            a4 << bxor_imm(y, imm64(63)),
+            carry << ushr(a3, a4),
+            ah << bor(a2, carry),
+            big << band_imm(y, imm64(64)),
+            zero << iconst.i64(imm64(0)),
+            al << select(big, zero, a1),
+            a5 << select(big, a1, ah),
+            a << iconcat(al, a5)
+        ))
+
+narrow.legalize(
+        a << ushr.i128(x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            a1 << ushr(xh, y),
+            a2 << ushr(xl, y),
+            a3 << ishl_imm(xh, imm64(1)),
+            a4 << bxor_imm(y, imm64(63)),
+            carry << ishl(a3, a4),
+            al << bor(a2, carry),
+            big << band_imm(y, imm64(64)),
+            zero << iconst.i64(imm64(0)),
+            ah << select(big, zero, a1),
+            a5 << select(big, a1, al),
+            a << iconcat(a5, ah)
+        ))
+
+narrow.legalize(
+        a << sshr.i128(x, y),
+        Rtl(
+            (xl, xh) << isplit(x),
+            a1 << sshr(xh, y),
+            a2 << ushr(xl, y),
+            a3 << ishl_imm(xh, imm64(1)),
+            a4 << bxor_imm(y, imm64(63)),
+            carry << ishl(a3, a4),
+            al << bor(a2, carry),
+            big << band_imm(y, imm64(64)),
+            sign << sshr_imm(xh, imm64(63)),
+            ah << select(big, sign, a1),
+            a5 << select(big, a1, al),
+            a << iconcat(a5, ah)
+        ))
+
+# Widen 8- and 16-bit integer operations to 32 bits for ISAs that only have
+# 32-bit and 64-bit integer instructions. The operations below only depend on
+# the low bits of their operands, so the high bits of the widened operands
+# don't matter, and the result is reduced again.
+for ty in [types.i8, types.i16]:
+    widen.legalize(
+            a << iconst.bind(ty)(y),
+            Rtl(
+                a1 << iconst.i32(y),
+                a << insts.ireduce.bind(ty)(a1)
+            ))
+
+    for inst in [iadd, isub, imul, band, bor, bxor, band_not, bor_not,
+                 bxor_not]:
+        widen.legalize(
+                a << inst.bind(ty)(x, y),
+                Rtl(
+                    x1 << insts.uextend.i32(x),
+                    y1 << insts.uextend.i32(y),
+                    a1 << inst(x1, y1),
+                    a << insts.ireduce.bind(ty)(a1)
+                ))
+
+    for inst in [iadd_imm, imul_imm, band_imm, bor_imm, bxor_imm, irsub_imm]:
+        widen.legalize(
+                a << inst.bind(ty)(x, y),
+                Rtl(
+                    x1 << insts.uextend.i32(x),
+                    a1 << inst(x1, y),
+                    a << insts.ireduce.bind(ty)(a1)
+                ))
+
+    widen.legalize(
+            a << bnot.bind(ty)(x),
+            Rtl(
+                x1 << insts.uextend.i32(x),
+                a1 << bnot(x1),
+                a << insts.ireduce.bind(ty)(a1)
+            ))
+
+    # Sign extension keeps both the signed and the unsigned order, so it works
+    # for all the condition codes.
+    widen.legalize(
+            b << icmp.bind(ty)(cc, x, y),
+            Rtl(
+                x1 << insts.sextend.i32(x),
+                y1 << insts.sextend.i32(y),
+                b << icmp(cc, x1, y1)
+            ))
+    widen.legalize(
+            b << icmp_imm.bind(ty)(cc, x, y),
+            Rtl(
+                a1 << iconst.bind(ty)(y),
+                b << icmp(cc, x, a1)
+            ))
+
 # Expand integer operations with carry for RISC architectures that don't have
 # the flags.
 expand.legalize(
@@ -155,6 +381,76 @@ expand.legalize(
             b << icmp(intcc.ugt, a, x)
         ))
 
+# Signed overflow of a multiplication happens when the high part of the full
+# product isn't just the sign extension of the low part.
+expand.legalize(
+        (a, c) << imul_oflow(x, y),
+        Rtl(
+            a << imul(x, y),
+            ah << smulhi(x, y),
+            c1 << icmp_imm(intcc.slt, a, imm64(0)),
+            c_int << bint(c1),
+            sign << irsub_imm(c_int, imm64(0)),
+            c << icmp(intcc.ne, ah, sign)
+        ))
+
+# Arithmetic trapping on overflow computes the overflow condition like the
+# instructions above and traps on it.
+# The sum overflows as signed integers when it doesn't have the sign of either
+# operand.
+expand.legalize(
+        a << insts.sadd_trap(x, y, code),
+        Rtl(
+            a << iadd(x, y),
+            a1 << bxor(x, a),
+            a2 << bxor(y, a),
+            a3 << band(a1, a2),
+            c << icmp_imm(intcc.slt, a3, imm64(0)),
+            insts.trapnz(c, code)
+        ))
+
+expand.legalize(
+        a << insts.uadd_trap(x, y, code),
+        Rtl(
+            (a, c) << iadd_cout(x, y),
+            insts.trapnz(c, code)
+        ))
+
+# The difference overflows as signed integers when the operands have different
+# signs and the difference doesn't have the sign of `x`.
+expand.legalize(
+        a << insts.ssub_trap(x, y, code),
+        Rtl(
+            a << isub(x, y),
+            a1 << bxor(x, y),
+            a2 << bxor(x, a),
+            a3 << band(a1, a2),
+            c << icmp_imm(intcc.slt, a3, imm64(0)),
+            insts.trapnz(c, code)
+        ))
+
+expand.legalize(
+        a << insts.usub_trap(x, y, code),
+        Rtl(
+            (a, b) << isub_bout(x, y),
+            insts.trapnz(b, code)
+        ))
+
+expand.legalize(
+        a << insts.smul_trap(x, y, code),
+        Rtl(
+            (a, c) << imul_oflow(x, y),
+            insts.trapnz(c, code)
+        ))
+
+expand.legalize(
+        a << insts.umul_trap(x, y, code),
+        Rtl(
+            a << imul(x, y),
+            ah << umulhi(x, y),
+            insts.trapnz(ah, code)
+        ))
+
 expand.legalize(
         a << iadd_cin(x, y, c),
         Rtl(
@@ -199,7 +495,7 @@ for inst_imm,      inst in [
         (urem_imm, urem),
         (band_imm, band),
         (bor_imm, bor),
-        (bxor_imm, bor),
+        (bxor_imm, bxor),
         (ifcmp_imm, ifcmp)]:
     expand.legalize(
             a << inst_imm(x, y),
@@ -276,6 +572,60 @@ for ty,             minus_zero in [
 # Expansions using CPU flags.
 expand_flags.custom_legalize(insts.stack_check, 'expand_stack_check')
 
+# The carry, borrow, and overflow outputs can be computed by the instruction
+# itself on ISAs with flags.
+expand_flags.legalize(
+    (a, c) << iadd_cout(x, y),
+    Rtl(
+        (a, f) << insts.iadd_ifcout(x, y),
+        c << insts.trueif(intcc.ult, f)
+    ))
+expand_flags.legalize(
+    (a, b) << isub_bout(x, y),
+    Rtl(
+        (a, f) << insts.isub_ifbout(x, y),
+        b << insts.trueif(intcc.ult, f)
+    ))
+expand_flags.legalize(
+    (a, c) << imul_oflow(x, y),
+    Rtl(
+        (a, f) << insts.imul_ifoflow(x, y),
+        c << insts.trueif(intcc.of, f)
+    ))
+
+# Arithmetic trapping on overflow tests the flags of the arithmetic instruction
+# directly instead of materializing the overflow condition.
+expand_flags.legalize(
+    a << insts.sadd_trap(x, y, code),
+    Rtl(
+        (a, f) << insts.iadd_ifcout(x, y),
+        insts.trapif(intcc.of, f, code)
+    ))
+expand_flags.legalize(
+    a << insts.uadd_trap(x, y, code),
+    Rtl(
+        (a, f) << insts.iadd_ifcout(x, y),
+        insts.trapif(intcc.ult, f, code)
+    ))
+expand_flags.legalize(
+    a << insts.ssub_trap(x, y, code),
+    Rtl(
+        (a, f) << insts.isub_ifbout(x, y),
+        insts.trapif(intcc.of, f, code)
+    ))
+expand_flags.legalize(
+    a << insts.usub_trap(x, y, code),
+    Rtl(
+        (a, f) << insts.isub_ifbout(x, y),
+        insts.trapif(intcc.ult, f, code)
+    ))
+expand_flags.legalize(
+    a << insts.smul_trap(x, y, code),
+    Rtl(
+        (a, f) << insts.imul_ifoflow(x, y),
+        insts.trapif(intcc.of, f, code)
+    ))
+
 expand_flags.legalize(
     insts.trapnz(x, c),
     Rtl(
@@ -2,7 +2,7 @@ from __future__ import absolute_import
 from semantics.primitives import prim_to_bv, prim_from_bv, bvsplit, bvconcat,\
     bvadd, bvzeroext, bvsignext
 from semantics.primitives import bveq, bvne, bvsge, bvsgt, bvsle, bvslt,\
-        bvuge, bvugt, bvule, bvult
+        bvuge, bvugt, bvule, bvult, bvsof, bvnsof
 from semantics.macros import bool2bv
 from .instructions import vsplit, vconcat, iadd, iadd_cout, icmp, bextend, \
     isplit, iconcat, iadd_cin, iadd_carry
@@ -179,7 +179,9 @@ icmp.set_semantics(
     create_comp_xform(intcc.uge, bvuge),
     create_comp_xform(intcc.ugt, bvugt),
     create_comp_xform(intcc.ule, bvule),
-    create_comp_xform(intcc.ult, bvult))
+    create_comp_xform(intcc.ult, bvult),
+    create_comp_xform(intcc.of, bvsof),
+    create_comp_xform(intcc.nof, bvnsof))
 
 #
 # Legalization helper instructions.
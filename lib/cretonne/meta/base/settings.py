@@ -15,8 +15,22 @@ opt_level = EnumSetting(
         - default: Very profitable optimizations enabled, none slow.
         - best: Enable all optimizations
         - fastest: Optimize for compile time by disabling most optimizations.
+        - smallest: Optimize for code size: Use the shortest instruction encodings and avoid
+          transformations that grow the code, like loop unrolling.
         """,
-        'default', 'best', 'fastest')
+        'default', 'best', 'fastest', 'smallest')
+
+regalloc = EnumSetting(
+        """
+        Register allocation algorithm:
+
+        - coloring: Spill as few values as possible, and assign registers by
+          coloring the interference graph.
+        - fast: Spill every value when it is defined and reload it at each use.
+          This trades code quality for compile time, which is useful for a
+          baseline tier in a JIT.
+        """,
+        'coloring', 'fast')
 
 enable_verifier = BoolSetting(
         """
@@ -27,6 +41,52 @@ enable_verifier = BoolSetting(
         """,
         default=True)
 
+enable_regalloc_verifier = BoolSetting(
+        """
+        Check the register assignment against a fresh liveness analysis.
+
+        When the locations verifier runs after register allocation and the
+        passes following it, also recompute the live ranges of all values and
+        check that no two values that are live at the same time share a
+        register or a stack slot. This is much slower than the normal
+        verifier, so it is meant for debugging the register allocator. It has
+        no effect unless `enable_verifier` is also set.
+        """)
+
+check_emission = BoolSetting(
+        """
+        Check every instruction against its encoding recipe when emitting
+        machine code.
+
+        Before an instruction is emitted, its encoding must be legal for the
+        instruction, and its operands must be in locations allowed by the
+        recipe's register constraints. Afterwards, the number of bytes emitted
+        must match the size of the recipe. A violation panics with the
+        offending instruction instead of producing corrupt machine code. This
+        makes code emission slower, so it is meant for debugging backends.
+        """)
+
+track_inst_origins = BoolSetting(
+        """
+        Record which compilation pass created or last rewrote each
+        instruction.
+
+        The origins are kept in a side table in the function. They are
+        printed as comments on the instructions in IR dumps, and included in
+        verifier errors. This is useful for finding the pass that introduced
+        a bad transformation, but it makes compilation a lot slower.
+        """)
+
+snapshot_ir_on_error = BoolSetting(
+        """
+        Keep the function in the text format when compilation fails.
+
+        The `CompileError` returned by `Context::try_compile` then contains
+        the function as it was when the failing pass stopped, which can be
+        attached to a bug report. Printing the function takes time, but only
+        when compilation fails.
+        """)
+
 is_64bit = BoolSetting("Enable 64-bit code generation")
 
 is_pic = BoolSetting("Enable Position-Independent Code generation")
@@ -53,6 +113,46 @@ avoid_div_traps = BoolSetting(
         this setting has no effect - explicit checks are always inserted.
         """)
 
+fuse_heap_checks = BoolSetting(
+        """
+        Check the bounds of groups of heap accesses only once.
+
+        When several `heap_addr` instructions in an EBB use the same heap and
+        index, and no stores, calls, or other traps happen between them, the
+        first bounds check is widened to cover all of them and the others are
+        removed. An out-of-bounds access in the group may then trap at the
+        first access instead of the faulting one.
+        """)
+
+enable_heap_access_spectre_mitigation = BoolSetting(
+        """
+        Harden the bounds checks of `heap_addr` against speculative execution.
+
+        The trapping bounds check only stops an out-of-bounds access once the
+        branch is resolved, so a processor that mispredicts it can still
+        access memory outside the heap speculatively. With this setting, the
+        computed address is also replaced by a null pointer with a conditional
+        move when the access is out of bounds, which doesn't depend on branch
+        prediction.
+
+        This requires a target with conditional moves, like Intel.
+        """)
+
+unaligned_vector_access = EnumSetting(
+        """
+        How to access vectors in memory when the `aligned` flag isn't set.
+
+        - native: Use the target's unaligned vector memory instructions.
+        - trap: Use the aligned vector memory instructions anyway, so a
+          misaligned access faults.
+        - split: Access the vector one lane at a time with scalar loads and
+          stores.
+
+        Vector memory accesses with the `aligned` flag always use the aligned
+        instructions.
+        """,
+        'native', 'trap', 'split')
+
 is_compressed = BoolSetting("Enable compressed instructions")
 
 enable_float = BoolSetting(
@@ -71,6 +171,96 @@ enable_atomics = BoolSetting(
         """Enable the use of atomic instructions""",
         default=True)
 
+unroll_threshold = NumSetting(
+        """
+        Size budget for loop unrolling, in instructions.
+
+        Small loops with a constant trip count are unrolled when the unrolled
+        loop body has at most this many instructions. Zero disables loop
+        unrolling.
+        """)
+
+inline_size_budget = NumSetting(
+        """
+        Size budget for function inlining, in instructions.
+
+        The inliner replaces a call with the body of the callee when the callee
+        has at most this many instructions, unless the embedder's inline oracle
+        decides otherwise. Zero disables inlining that isn't forced by the
+        oracle.
+        """,
+        default=20)
+
+inline_max_depth = NumSetting(
+        """
+        Maximum depth of nested inlining.
+
+        With a depth of 1, only the calls in the original function are
+        inlined. Larger depths also inline the calls found in the inlined
+        bodies. The limit also applies to call sites where the inline oracle
+        forces inlining, which guarantees termination for recursive functions.
+        Zero disables inlining.
+        """,
+        default=1)
+
+inline_loop_bonus = NumSetting(
+        """
+        Extra inlining budget for call sites inside loops, in instructions.
+
+        Calls in loops are executed more often, so inlining them pays off for
+        larger callees. When the function has a profile, the bonus also
+        applies to the calls that run at least once per call of the function.
+        """)
+
+legalizer_expansion_limit = NumSetting(
+        """
+        Maximum number of legalizer expansions per instruction in the input
+        function.
+
+        Expansion patterns that keep reintroducing illegal instructions would
+        otherwise make the legalizer loop forever. When the limit is exceeded,
+        legalization fails with an error describing the offending instruction.
+        Zero disables the limit.
+        """,
+        default=100)
+
+enable_entry_exit_hooks = BoolSetting(
+        """
+        Call instrumentation hooks when entering and leaving functions.
+
+        A call to the `EnterFunction` library routine is inserted at the
+        function entry, and a call to the `ExitFunction` routine is inserted
+        before every return and tail call. Both routines take the `i32` index
+        of the function's user name as their only argument. The embedder
+        decides which functions they resolve to, typically to record a call
+        graph profile or the coverage of the generated code.
+        """)
+
+enable_nan_canonicalization = BoolSetting(
+        """
+        Replace the NaN results of floating point arithmetic with the
+        canonical quiet NaN.
+
+        The NaN bit patterns produced by floating point instructions differ
+        between targets. With this setting, every NaN computed by an
+        arithmetic instruction is replaced with the positive quiet NaN
+        without payload, so the results are bit-identical on all targets.
+        This is useful for deterministic execution, at the cost of a compare
+        and select after every such instruction.
+        """)
+
+enable_probestack = BoolSetting(
+        """
+        Probe the stack pages of large frames in the prologue.
+
+        On targets that require it, like Windows x64, the prologue of a
+        function allocating more than a page of stack calls the `Probestack`
+        library routine to touch the pages in order. Disable this when the
+        runtime commits the whole stack up front and doesn't provide the
+        routine.
+        """,
+        default=True)
+
 #
 # Settings specific to the `spiderwasm` calling convention.
 #
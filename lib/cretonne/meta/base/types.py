@@ -10,11 +10,13 @@ b8 = BoolType(8)    #: 8-bit bool.
 b16 = BoolType(16)  #: 16-bit bool.
 b32 = BoolType(32)  #: 32-bit bool.
 b64 = BoolType(64)  #: 64-bit bool.
+b128 = BoolType(128)  #: 128-bit bool.
 
 i8 = IntType(8)     #: 8-bit int.
 i16 = IntType(16)   #: 16-bit int.
 i32 = IntType(32)   #: 32-bit int.
 i64 = IntType(64)   #: 64-bit int.
+i128 = IntType(128)  #: 128-bit int.
 
 #: IEEE single precision.
 f32 = FloatType(
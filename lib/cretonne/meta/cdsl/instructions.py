@@ -201,7 +201,10 @@ class Instruction(object):
         # Prefer to use the typevar_operand to infer the controlling typevar.
         self.use_typevar_operand = False
         typevar_error = None
-        if self.format.typevar_operand is not None:
+        # Formats with a value list default to its first value, which isn't
+        # one of the fixed value operands of the instruction.
+        if (self.format.typevar_operand is not None and
+                self.format.typevar_operand < len(self.value_opnums)):
             try:
                 opnum = self.value_opnums[self.format.typevar_operand]
                 tv = self.ins[opnum].typevar
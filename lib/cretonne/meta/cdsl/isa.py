@@ -469,7 +469,12 @@ class Encoding(object):
                 "Format {} must match recipe: {}".format(
                     self.inst.format, recipe.format))
 
-        if self.inst.is_branch:
+        # The landing pad of a call is only reached by unwinding, and
+        # branches without an EBB operand are indirect, so the encoding of
+        # these branches doesn't depend on their code offset.
+        direct = any(
+                f.kind.name == 'ebb' for f in self.inst.format.imm_fields)
+        if self.inst.is_branch and not self.inst.is_call and direct:
             assert recipe.branch_range, (
                     'Recipe {} for {} must have a branch_range'
                     .format(recipe, self.inst.name))
@@ -309,6 +309,36 @@ class IsUnsignedInt(FieldPredicate):
         assert scale >= 0 and scale < width
 
 
+class IsColocatedFunc(FieldPredicate):
+    """
+    Instruction predicate that checks if a function reference field refers to
+    a colocated function.
+
+    The predicate looks up the function in the data flow graph, so it can only
+    be used as an encoding predicate, not a recipe predicate.
+
+    :param field: `FormatField` to be checked.
+    """
+
+    def __init__(self, field):
+        # type: (FormatField) -> None
+        super(IsColocatedFunc, self).__init__(
+                field, 'is_colocated_func', ('dfg',))
+
+
+class IsAligned(FieldPredicate):
+    """
+    Instruction predicate that checks if a memory flags field has the
+    `aligned` flag set.
+
+    :param field: `FormatField` to be checked.
+    """
+
+    def __init__(self, field):
+        # type: (FormatField) -> None
+        super(IsAligned, self).__init__(field, 'is_aligned', ())
+
+
 class TypePredicate(object):
     """
     An instruction predicate that checks the type of an SSA argument value.
@@ -79,7 +79,10 @@ class TestTypeSet(TestCase):
                          TypeSet(ints=(16, 64)))
 
         self.assertEqual(TypeSet(ints=(32, 64)).double_width(),
-                         TypeSet(ints=(64, 64)))
+                         TypeSet(ints=(64, 128)))
+
+        self.assertEqual(TypeSet(ints=(64, 128)).double_width(),
+                         TypeSet(ints=(128, 128)))
 
         # Should produce an empty ts
         self.assertEqual(TypeSet(floats=(32, 32)).half_width(),
@@ -107,7 +110,7 @@ class TestTypeSet(TestCase):
         self.assertEqual(TypeSet(bools=(1, 16)).double_width(), t)
 
         self.assertEqual(TypeSet(bools=(32, 64)).double_width(),
-                         TypeSet(bools=(64, 64)))
+                         TypeSet(bools=(64, 128)))
 
     def test_get_singleton(self):
         # Raise error when calling get_singleton() on non-singleton TS
@@ -136,7 +139,7 @@ class TestTypeSet(TestCase):
                          TypeSet().preimage(TypeVar.LANEOF))
 
         # ASBOOL
-        t = TypeSet(lanes=(1, 4), bools=(1, 64))
+        t = TypeSet(lanes=(1, 4), bools=(1, 128))
         self.assertEqual(t.preimage(TypeVar.ASBOOL),
                          TypeSet(lanes=(1, 4), ints=True, bools=True,
                                  floats=True))
@@ -157,17 +160,17 @@ class TestTypeSet(TestCase):
 
         # Half/Double Width
         t = TypeSet(ints=(8, 8), floats=(32, 32), bools=(1, 8))
-        t1 = TypeSet(ints=(64, 64), floats=(64, 64), bools=(64, 64))
+        t1 = TypeSet(ints=(128, 128), floats=(64, 64), bools=(128, 128))
         self.assertEqual(t.preimage(TypeVar.DOUBLEWIDTH).size(), 0)
         self.assertEqual(t1.preimage(TypeVar.HALFWIDTH).size(), 0)
 
         t = TypeSet(lanes=(1, 16), ints=(8, 16), floats=(32, 64))
-        t1 = TypeSet(lanes=(64, 256), bools=(1, 64))
+        t1 = TypeSet(lanes=(64, 256), bools=(1, 128))
 
         self.assertEqual(t.preimage(TypeVar.DOUBLEWIDTH),
                          TypeSet(lanes=(1, 16), ints=(8, 8), floats=(32, 32)))
         self.assertEqual(t1.preimage(TypeVar.HALFWIDTH),
-                         TypeSet(lanes=(64, 256), bools=(16, 64)))
+                         TypeSet(lanes=(64, 256), bools=(16, 128)))
 
 
 def has_non_bijective_derived_f(iterable):
@@ -182,7 +185,7 @@ class TestTypeVar(TestCase):
         with self.assertRaises(AssertionError):
             x.half_width()
 
-        x2 = TypeVar('x2', 'i16 and up', ints=(16, 64))
+        x2 = TypeVar('x2', 'i16 and up', ints=(16, 128))
         with self.assertRaises(AssertionError):
             x2.double_width()
         self.assertEqual(str(x2.half_width()), '`half_width(x2)`')
@@ -22,7 +22,8 @@ except ImportError:
     pass
 
 MAX_LANES = 256
-MAX_BITS = 64
+MAX_BITS = 128
+MAX_FLOAT_BITS = 64
 MAX_BITVEC = MAX_BITS * MAX_LANES
 
 
@@ -152,17 +153,17 @@ class TypeSet(object):
     Passing `True` instead of a range selects all available scalar types:
 
     >>> TypeSet(ints=True)
-    TypeSet(lanes={1}, ints={8, 16, 32, 64})
+    TypeSet(lanes={1}, ints={8, 16, 32, 64, 128})
     >>> TypeSet(floats=True)
     TypeSet(lanes={1}, floats={32, 64})
     >>> TypeSet(bools=True)
-    TypeSet(lanes={1}, bools={1, 8, 16, 32, 64})
+    TypeSet(lanes={1}, bools={1, 8, 16, 32, 64, 128})
 
     Similarly, passing `True` for the lanes selects all possible scalar and
     vector types:
 
     >>> TypeSet(lanes=True, ints=True)
-    TypeSet(lanes={1, 2, 4, 8, 16, 32, 64, 128, 256}, ints={8, 16, 32, 64})
+    TypeSet(lanes={1, 2, 4, 8, 16, 32, 64, 128, 256}, ints={8, 16, 32, 64, 128})
 
     Finally, a type set can contain special types (derived from `SpecialType`)
     which can't appear as lane types.
@@ -191,7 +192,7 @@ class TypeSet(object):
         # type: (...) -> None
         self.lanes = interval_to_set(decode_interval(lanes, (1, MAX_LANES), 1))
         self.ints = interval_to_set(decode_interval(ints, (8, MAX_BITS)))
-        self.floats = interval_to_set(decode_interval(floats, (32, 64)))
+        self.floats = interval_to_set(decode_interval(floats, (32, MAX_FLOAT_BITS)))
         self.bools = interval_to_set(decode_interval(bools, (1, MAX_BITS)))
         self.bools = set(filter(legal_bool, self.bools))
         self.bitvecs = interval_to_set(decode_interval(bitvecs,
@@ -365,7 +366,7 @@ class TypeSet(object):
         """
         new = self.copy()
         new.ints = set([x*2 for x in self.ints if x < MAX_BITS])
-        new.floats = set([x*2 for x in self.floats if x < MAX_BITS])
+        new.floats = set([x*2 for x in self.floats if x < MAX_FLOAT_BITS])
         new.bools = set(filter(legal_bool,
                                set([x*2 for x in self.bools if x < MAX_BITS])))
         new.bitvecs = set([x*2 for x in self.bitvecs if x < MAX_BITVEC])
@@ -463,7 +464,7 @@ class TypeSet(object):
                 # pre-image, as as_bool() of scalars is always b1.
                 new.lanes = self.lanes.difference(set([1]))
             else:
-                new.ints = set([2**x for x in range(3, 7)])
+                new.ints = set([2**x for x in range(3, int_log2(MAX_BITS)+1)])
                 new.floats = set([32, 64])
 
             return new
@@ -481,7 +482,7 @@ class TypeSet(object):
             # Start with all possible lanes/ints/floats/bools
             lanes = interval_to_set(decode_interval(True, (1, MAX_LANES), 1))
             ints = interval_to_set(decode_interval(True, (8, MAX_BITS)))
-            floats = interval_to_set(decode_interval(True, (32, 64)))
+            floats = interval_to_set(decode_interval(True, (32, MAX_FLOAT_BITS)))
             bools = interval_to_set(decode_interval(True, (1, MAX_BITS)))
 
             # See which combinations have a size that appears in self.bitvecs
@@ -746,7 +747,7 @@ class TypeVar(object):
                 assert max(ts.ints) < MAX_BITS,\
                     "Can't double all integer types."
             if len(ts.floats) > 0:
-                assert max(ts.floats) < MAX_BITS,\
+                assert max(ts.floats) < MAX_FLOAT_BITS,\
                     "Can't double all float types."
             if len(ts.bools) > 0:
                 assert max(ts.bools) < MAX_BITS, "Can't double all bool types."
@@ -29,7 +29,7 @@ def gen_enum_types(sgrp, fmt):
             continue
         ty = camel_case(setting.name)
         fmt.doc_comment('Values for `{}`.'.format(setting))
-        fmt.line('#[derive(Debug, PartialEq, Eq)]')
+        fmt.line('#[derive(Clone, Copy, Debug, PartialEq, Eq)]')
         with fmt.indented('pub enum {} {{'.format(ty), '}'):
             for v in setting.values:
                 fmt.doc_comment('`{}`.'.format(v))
@@ -6,10 +6,25 @@ Commonly used definitions.
 from __future__ import absolute_import
 from cdsl.isa import TargetISA, CPUMode
 import base.instructions
-from base.legalize import narrow
+from base.immediates import floatcc
+from . import instructions as a64
 
-ISA = TargetISA('arm64', [base.instructions.GROUP])
+ISA = TargetISA('arm64', [base.instructions.GROUP, a64.GROUP])
 A64 = CPUMode('A64', ISA)
 
-# TODO: Refine these
-A64.legalize_type(narrow)
+# The set of floating point condition codes that can be tested with a single
+# condition after an `fcmp` instruction. The `one` and `ueq` conditions need
+# two tests.
+supported_floatccs = [
+        floatcc.ord,
+        floatcc.uno,
+        floatcc.eq,
+        floatcc.ne,
+        floatcc.lt,
+        floatcc.le,
+        floatcc.gt,
+        floatcc.ge,
+        floatcc.ult,
+        floatcc.ule,
+        floatcc.ugt,
+        floatcc.uge]
@@ -0,0 +1,393 @@
+"""
+ARM64 Encodings.
+"""
+from __future__ import absolute_import
+from cdsl.predicates import IsSignedInt, IsUnsignedInt, Or, Not
+from base import instructions as base
+from base.formats import UnaryImm, Load, Store
+from base.types import i8, i16, i32, i64, f32, f64, b1
+from base.legalize import narrow, widen, expand
+from base.settings import is_pic
+from .defs import A64
+from . import recipes as r
+from . import instructions as a64
+from .recipes import R3, DP1, FP1, FPINT, ADDSUB_IMM, BFM, MOVW, LDST, HI16
+from .recipes import BR
+from .legalize import arm64_expand
+
+try:
+    from typing import TYPE_CHECKING, Any  # noqa
+    if TYPE_CHECKING:
+        from cdsl.instructions import MaybeBoundInst  # noqa
+        from cdsl.isa import PredNode  # noqa
+except ImportError:
+    pass
+
+
+A64.legalize_monomorphic(expand)
+A64.legalize_type(
+    default=narrow,
+    b1=expand,
+    i8=widen,
+    i16=widen,
+    i32=arm64_expand,
+    i64=arm64_expand,
+    f32=arm64_expand,
+    f64=arm64_expand)
+
+
+def enc_w_x(inst, recipe, w, x):
+    # type: (MaybeBoundInst, r.EncRecipe, int, int) -> None
+    """
+    Add encodings for `inst.i32` and `inst.i64` with the given 32-bit and
+    64-bit encoding bits.
+    """
+    A64.enc(inst.i32, recipe, w)
+    A64.enc(inst.i64, recipe, x)
+
+
+def mem_offset(iform, size):
+    # type: (Any, int) -> PredNode
+    """
+    Return an instruction predicate that checks if the offset of a load or
+    store accessing `2^size` bytes can be encoded directly.
+    """
+    return Or(
+            IsUnsignedInt(iform.offset, 12 + size, size),
+            IsSignedInt(iform.offset, 9))
+
+
+#
+# Integer arithmetic.
+#
+
+for inst,           w,          x in [
+        (base.iadd, 0x0b000000, 0x8b000000),
+        (base.isub, 0x4b000000, 0xcb000000),
+        (base.band, 0x0a000000, 0x8a000000),
+        (base.bor,  0x2a000000, 0xaa000000),
+        (base.bxor, 0x4a000000, 0xca000000),
+        (base.band_not, 0x0a200000, 0x8a200000),
+        (base.bor_not,  0x2a200000, 0xaa200000),
+        (base.bxor_not, 0x4a200000, 0xca200000),
+        (base.imul, 0x1b007c00, 0x9b007c00),
+        (a64.udiv,  0x1ac00800, 0x9ac00800),
+        (a64.sdiv,  0x1ac00c00, 0x9ac00c00)]:
+    enc_w_x(inst, r.rrr, R3(w), R3(x))
+
+# Boolean operations use the 32-bit instructions.
+for inst,           w in [
+        (base.band, 0x0a000000),
+        (base.bor,  0x2a000000),
+        (base.bxor, 0x4a000000)]:
+    A64.enc(inst.b1, r.rrr, R3(w))
+
+A64.enc(base.umulhi.i64, r.rrr, R3(0x9bc07c00))
+A64.enc(base.smulhi.i64, r.rrr, R3(0x9b407c00))
+A64.enc(base.umulhi.i32, r.mulhi32, R3(0x9ba07c00))
+A64.enc(base.smulhi.i32, r.mulhi32, R3(0x9b207c00))
+
+# `mvn` is `orn` with xzr.
+enc_w_x(base.bnot, r.rzr, R3(0x2a200000), R3(0xaa200000))
+A64.enc(base.bnot.b1, r.rnot_b1, 0)
+
+# Add immediate. Negative immediates are emitted as a subtraction.
+enc_w_x(base.iadd_imm, r.rri, ADDSUB_IMM(0, 0, 0), ADDSUB_IMM(1, 0, 0))
+enc_w_x(base.iadd_imm, r.rri2, ADDSUB_IMM(0, 0, 0), ADDSUB_IMM(1, 0, 0))
+
+# Dynamic shifts and rotates only use the low bits of the shift amount, like
+# the base instructions. The amount can have either type.
+for inst,           w,          x in [
+        (base.ishl, 0x1ac02000, 0x9ac02000),
+        (base.ushr, 0x1ac02400, 0x9ac02400),
+        (base.sshr, 0x1ac02800, 0x9ac02800),
+        (base.rotr, 0x1ac02c00, 0x9ac02c00)]:
+    for ty in [i32, i64]:
+        A64.enc(inst.i32.bind(ty), r.rrr, R3(w))
+        A64.enc(inst.i64.bind(ty), r.rrr, R3(x))
+
+# Immediate shifts are aliases of the bitfield moves.
+enc_w_x(base.ishl_imm, r.lsl_imm, BFM(0, 0b10), BFM(1, 0b10))
+enc_w_x(base.ushr_imm, r.shr_imm, BFM(0, 0b10), BFM(1, 0b10))
+enc_w_x(base.sshr_imm, r.shr_imm, BFM(0, 0b00), BFM(1, 0b00))
+enc_w_x(base.rotr_imm, r.ror_imm, HI16(0x13800000), HI16(0x93c00000))
+enc_w_x(base.rotl_imm, r.rol_imm, HI16(0x13800000), HI16(0x93c00000))
+
+# Bit counting.
+enc_w_x(base.clz, r.rr, DP1(0, 0b000100), DP1(1, 0b000100))
+enc_w_x(base.cls, r.rr, DP1(0, 0b000101), DP1(1, 0b000101))
+enc_w_x(base.ctz, r.ctz, DP1(0, 0b000000), DP1(1, 0b000000))
+
+#
+# Copies and conversions.
+#
+
+# `mov` is `orr` with xzr. A 32-bit move clears the high 32 bits.
+enc_w_x(base.copy, r.rzr, R3(0x2a000000), R3(0xaa000000))
+A64.enc(base.copy.b1, r.rzr, R3(0x2a000000))
+enc_w_x(base.regmove, r.rmov, R3(0x2a000000), R3(0xaa000000))
+A64.enc(base.regmove.b1, r.rmov, R3(0x2a000000))
+for ty in [i8, i16]:
+    A64.enc(base.copy.bind(ty), r.rzr, R3(0x2a000000))
+    A64.enc(base.regmove.bind(ty), r.rmov, R3(0x2a000000))
+
+# The 32-bit instructions ignore the high bits of their inputs.
+A64.enc(base.ireduce.i32.i64, r.null, 0)
+for ty in [i8, i16]:
+    A64.enc(base.ireduce.bind(ty).i32, r.null, 0)
+    A64.enc(base.ireduce.bind(ty).i64, r.null, 0)
+
+# Sign and zero extensions are bitfield moves.
+for ty,  bits in [(i8, 8), (i16, 16)]:
+    A64.enc(base.sextend.i32.bind(ty), r.bfm, BFM(0, 0b00, 0, bits - 1))
+    A64.enc(base.sextend.i64.bind(ty), r.bfm, BFM(1, 0b00, 0, bits - 1))
+    A64.enc(base.uextend.i32.bind(ty), r.bfm, BFM(0, 0b10, 0, bits - 1))
+    # The 32-bit instruction clears the high bits.
+    A64.enc(base.uextend.i64.bind(ty), r.bfm, BFM(0, 0b10, 0, bits - 1))
+A64.enc(base.sextend.i64.i32, r.bfm, BFM(1, 0b00, 0, 31))
+A64.enc(base.uextend.i64.i32, r.rzr, R3(0x2a000000))
+
+# Convert a `b1` to 0 or 1 by isolating the low bit.
+A64.enc(base.bint.i32.b1, r.bfm, BFM(0, 0b10, 0, 0))
+A64.enc(base.bint.i64.b1, r.bfm, BFM(0, 0b10, 0, 0))
+
+#
+# Constants.
+#
+
+enc_w_x(base.iconst, r.movz, MOVW(0, 0b10), MOVW(1, 0b10))
+A64.enc(base.iconst.i32, r.mov2, MOVW(0, 0b10))
+A64.enc(base.iconst.i64, r.mov2, MOVW(1, 0b10),
+        instp=Or(IsSignedInt(UnaryImm.imm, 32),
+                 IsUnsignedInt(UnaryImm.imm, 32)))
+A64.enc(base.iconst.i64, r.mov4, MOVW(1, 0b10))
+A64.enc(base.bconst.b1, r.bconst, MOVW(0, 0b10))
+
+# Load from the constant pool with `ldr` (literal).
+A64.enc(base.const_load.i32, r.ldr_lit, BR(0x18000000))
+A64.enc(base.const_load.i64, r.ldr_lit, BR(0x58000000))
+A64.enc(base.const_load.f32, r.fldr_lit, BR(0x1c000000))
+A64.enc(base.const_load.f64, r.fldr_lit, BR(0x5c000000))
+
+# Absolute addresses are stored inline, which doesn't work for PIC.
+A64.enc(base.func_addr.i64, r.fnaddr, 0, isap=Not(is_pic))
+A64.enc(base.globalsym_addr.i64, r.gvaddr, 0, isap=Not(is_pic))
+
+#
+# Comparisons and flags.
+#
+
+enc_w_x(base.icmp, r.icscc, R3(0x6b000000), R3(0xeb000000))
+enc_w_x(base.icmp_imm, r.icscc_imm, ADDSUB_IMM(0, 1, 1), ADDSUB_IMM(1, 1, 1))
+enc_w_x(base.ifcmp, r.rcmp, R3(0x6b000000), R3(0xeb000000))
+enc_w_x(base.ifcmp_imm, r.rcmp_imm, ADDSUB_IMM(0, 1, 1), ADDSUB_IMM(1, 1, 1))
+A64.enc(base.ifcmp_sp.i64, r.rcmp_sp, R3(0xeb000000))
+
+A64.enc(base.fcmp.f32, r.fcscc, R3(0x1e202000))
+A64.enc(base.fcmp.f64, r.fcscc, R3(0x1e602000))
+A64.enc(base.ffcmp.f32, r.fcmp, R3(0x1e202000))
+A64.enc(base.ffcmp.f64, r.fcmp, R3(0x1e602000))
+
+A64.enc(base.trueif, r.cset, 0)
+A64.enc(base.trueff, r.fcset, 0)
+
+enc_w_x(base.selectif, r.csel, R3(0x1a800000), R3(0x9a800000))
+A64.enc(base.selectif.f32, r.fcsel, R3(0x1e200c00))
+A64.enc(base.selectif.f64, r.fcsel, R3(0x1e600c00))
+
+#
+# Floating point.
+#
+
+for inst,           opc in [
+        (base.fmul, 0b0000),
+        (base.fdiv, 0b0001),
+        (base.fadd, 0b0010),
+        (base.fsub, 0b0011),
+        (base.fmax, 0b0100),
+        (base.fmin, 0b0101)]:
+    A64.enc(inst.f32, r.frrr, R3(0x1e200800 | (opc << 12)))
+    A64.enc(inst.f64, r.frrr, R3(0x1e600800 | (opc << 12)))
+
+A64.enc(base.fma.f32, r.frrrr, R3(0x1f000000))
+A64.enc(base.fma.f64, r.frrrr, R3(0x1f400000))
+
+for inst,           opc in [
+        (base.copy,    0b000000),
+        (base.fabs,    0b000001),
+        (base.fneg,    0b000010),
+        (base.sqrt,    0b000011),
+        (base.nearest, 0b001000),
+        (base.ceil,    0b001001),
+        (base.floor,   0b001010),
+        (base.trunc,   0b001011)]:
+    A64.enc(inst.f32, r.frr, FP1(0b00, opc))
+    A64.enc(inst.f64, r.frr, FP1(0b01, opc))
+
+A64.enc(base.regmove.f32, r.fmov, FP1(0b00, 0b000000))
+A64.enc(base.regmove.f64, r.fmov, FP1(0b01, 0b000000))
+
+A64.enc(base.fpromote.f64.f32, r.frr, FP1(0b00, 0b000101))
+A64.enc(base.fdemote.f32.f64, r.frr, FP1(0b01, 0b000100))
+
+# Bitwise operations on floating point values use the 64-bit vector forms.
+for inst,               bits in [
+        (base.band,     0x0e201c00),
+        (base.bor,      0x0ea01c00),
+        (base.bxor,     0x2e201c00),
+        (base.band_not, 0x0e601c00)]:
+    A64.enc(inst.f32, r.frrr, R3(bits))
+    A64.enc(inst.f64, r.frrr, R3(bits))
+
+# Conversions between integers and floating point numbers.
+for ity, sf in [(i32, 0), (i64, 1)]:
+    for fty, ty in [(f32, 0b00), (f64, 0b01)]:
+        A64.enc(base.fcvt_from_sint.bind(fty).bind(ity), r.rr2f,
+                FPINT(sf, ty, 0b00, 0b010))
+        A64.enc(base.fcvt_from_uint.bind(fty).bind(ity), r.rr2f,
+                FPINT(sf, ty, 0b00, 0b011))
+        A64.enc(a64.fcvtzs.bind(ity).bind(fty), r.frr2r,
+                FPINT(sf, ty, 0b11, 0b000))
+        A64.enc(a64.fcvtzu.bind(ity).bind(fty), r.frr2r,
+                FPINT(sf, ty, 0b11, 0b001))
+
+A64.enc(base.bitcast.i32.f32, r.frr2r, FPINT(0, 0b00, 0b00, 0b110))
+A64.enc(base.bitcast.f32.i32, r.rr2f, FPINT(0, 0b00, 0b00, 0b111))
+A64.enc(base.bitcast.i64.f64, r.frr2r, FPINT(1, 0b01, 0b00, 0b110))
+A64.enc(base.bitcast.f64.i64, r.rr2f, FPINT(1, 0b01, 0b00, 0b111))
+
+#
+# Loads and stores.
+#
+
+for inst,                     size, template in [
+        (base.load.i32.any,    2, 0xb9400000),
+        (base.load.i64.any,    3, 0xf9400000),
+        (base.uload8.i32.any,  0, 0x39400000),
+        (base.uload8.i64.any,  0, 0x39400000),
+        (base.sload8.i32.any,  0, 0x39c00000),
+        (base.sload8.i64.any,  0, 0x39800000),
+        (base.uload16.i32.any, 1, 0x79400000),
+        (base.uload16.i64.any, 1, 0x79400000),
+        (base.sload16.i32.any, 1, 0x79c00000),
+        (base.sload16.i64.any, 1, 0x79800000),
+        (base.uload32.i64,     2, 0xb9400000),
+        (base.sload32.i64,     2, 0xb9800000)]:
+    A64.enc(inst, r.ld, LDST(template), instp=mem_offset(Load, size))
+
+A64.enc(base.load.f32.any, r.fld, LDST(0xbd400000),
+        instp=mem_offset(Load, 2))
+A64.enc(base.load.f64.any, r.fld, LDST(0xfd400000),
+        instp=mem_offset(Load, 3))
+
+for inst,                      size, template in [
+        (base.store.i32.any,    2, 0xb9000000),
+        (base.store.i64.any,    3, 0xf9000000),
+        (base.istore8.i32.any,  0, 0x39000000),
+        (base.istore8.i64.any,  0, 0x39000000),
+        (base.istore16.i32.any, 1, 0x79000000),
+        (base.istore16.i64.any, 1, 0x79000000),
+        (base.istore32.i64.any, 2, 0xb9000000)]:
+    A64.enc(inst, r.st, LDST(template), instp=mem_offset(Store, size))
+
+A64.enc(base.store.f32.any, r.fst, LDST(0xbd000000),
+        instp=mem_offset(Store, 2))
+A64.enc(base.store.f64.any, r.fst, LDST(0xfd000000),
+        instp=mem_offset(Store, 3))
+
+# Spills and fills use 32-bit accesses for `b1` and the narrow integers like
+# for `i32`.
+for ty,  ld,         st in [
+        (i32, 0xb9400000, 0xb9000000),
+        (b1,  0xb9400000, 0xb9000000),
+        (i8,  0xb9400000, 0xb9000000),
+        (i16, 0xb9400000, 0xb9000000),
+        (i64, 0xf9400000, 0xf9000000)]:
+    A64.enc(base.spill.bind(ty), r.spill, LDST(st))
+    A64.enc(base.regspill.bind(ty), r.regspill, LDST(st))
+    A64.enc(base.fill.bind(ty), r.fill, LDST(ld))
+    A64.enc(base.regfill.bind(ty), r.regfill, LDST(ld))
+
+for ty,  ld,         st in [
+        (f32, 0xbd400000, 0xbd000000),
+        (f64, 0xfd400000, 0xfd000000)]:
+    A64.enc(base.spill.bind(ty), r.fspill, LDST(st))
+    A64.enc(base.regspill.bind(ty), r.fregspill, LDST(st))
+    A64.enc(base.fill.bind(ty), r.ffill, LDST(ld))
+    A64.enc(base.regfill.bind(ty), r.fregfill, LDST(ld))
+
+#
+# Stack frames.
+#
+
+A64.enc(a64.push.i64, r.push, LDST(0xf9000000))
+A64.enc(a64.push.f64, r.fpush, LDST(0xfd000000))
+A64.enc(a64.pop.i64, r.pop, LDST(0xf9400000))
+A64.enc(a64.pop.f64, r.fpop, LDST(0xfd400000))
+A64.enc(a64.push_pair.i64, r.push_pair, LDST(0xa9000000))
+A64.enc(a64.push_pair.f64, r.fpush_pair, LDST(0x6d000000))
+A64.enc(a64.pop_pair.i64, r.pop_pair, LDST(0xa9400000))
+A64.enc(a64.pop_pair.f64, r.fpop_pair, LDST(0x6d400000))
+
+A64.enc(base.copy_special, r.copysp, ADDSUB_IMM(1, 0, 0))
+A64.enc(base.adjust_sp_imm, r.adjustsp, ADDSUB_IMM(1, 0, 0))
+A64.enc(base.adjust_sp_imm, r.adjustsp2, ADDSUB_IMM(1, 0, 0))
+
+#
+# Atomic memory operations.
+#
+
+A64.enc(base.atomic_load.i32.any, r.ald, HI16(0x88c00000))
+A64.enc(base.atomic_load.i64.any, r.ald, HI16(0xc8c00000))
+A64.enc(base.atomic_store.i32.any, r.ast, HI16(0x88800000))
+A64.enc(base.atomic_store.i64.any, r.ast, HI16(0xc8800000))
+A64.enc(base.atomic_cas.i32.any, r.acas, 0)
+A64.enc(base.atomic_cas.i64.any, r.acas, 1)
+A64.enc(base.fence, r.dmb_ld, 0)
+A64.enc(base.fence, r.dmb, 0)
+A64.enc(base.barrier, r.null_barrier, 0)
+
+#
+# Traps.
+#
+
+A64.enc(base.trap, r.trap, 0)
+A64.enc(base.trapif, r.trapif, 0)
+A64.enc(base.trapff, r.trapff, 0)
+
+#
+# Calls and returns.
+#
+
+A64.enc(base.call, r.call, BR(0x94000000))
+A64.enc(base.invoke, r.invoke, BR(0x94000000))
+A64.enc(base.return_call, r.call, BR(0x14000000))
+A64.enc(base.call_indirect.i64, r.call_r, HI16(0xd63f0000))
+A64.enc(base.return_call_indirect.i64, r.tailcall_r, HI16(0xd61f0000))
+A64.enc(base.x_return, r.ret, HI16(0xd65f0000))
+
+#
+# Branches.
+#
+# The short form of each branch comes first. Branch relaxation switches to the
+# long form when the destination is out of range.
+#
+
+A64.enc(base.jump, r.b, BR(0x14000000))
+
+A64.enc(base.brif, r.bcond, BR(0x54000000))
+A64.enc(base.brif, r.bcond_long, BR(0x54000000))
+A64.enc(base.brff, r.fbcond, BR(0x54000000))
+A64.enc(base.brff, r.fbcond_long, BR(0x54000000))
+
+for ty, cbz, cbnz in [
+        (i32, 0x34000000, 0x35000000),
+        (b1,  0x34000000, 0x35000000),
+        (i64, 0xb4000000, 0xb5000000)]:
+    A64.enc(base.brz.bind(ty), r.cbz, BR(cbz))
+    A64.enc(base.brz.bind(ty), r.cbz_long, BR(cbz))
+    A64.enc(base.brnz.bind(ty), r.cbz, BR(cbnz))
+    A64.enc(base.brnz.bind(ty), r.cbz_long, BR(cbnz))
+
+enc_w_x(base.br_icmp, r.brcmp, R3(0x6b000000), R3(0xeb000000))
+enc_w_x(base.br_icmp, r.brcmp_long, R3(0x6b000000), R3(0xeb000000))
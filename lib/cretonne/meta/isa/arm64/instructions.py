@@ -0,0 +1,114 @@
+"""
+Supplementary instruction definitions for ARM64.
+
+This module defines additional instructions that are useful only to the ARM64
+target ISA.
+"""
+
+from cdsl.operands import Operand
+from cdsl.typevar import TypeVar
+from cdsl.instructions import Instruction, InstructionGroup
+
+
+GROUP = InstructionGroup("arm64", "ARM64-specific instruction set")
+
+iWord = TypeVar('iWord', 'A scalar integer machine word', ints=(32, 64))
+
+x = Operand('x', iWord, doc='Dividend')
+y = Operand('y', iWord, doc='Divisor')
+q = Operand('q', iWord, doc='Quotient')
+
+udiv = Instruction(
+        'a64_udiv', r"""
+        Unsigned integer division that doesn't trap.
+
+        This is the ARM64 ``udiv`` instruction which returns 0 when the
+        divisor is 0.
+        """,
+        ins=(x, y), outs=q)
+
+sdiv = Instruction(
+        'a64_sdiv', r"""
+        Signed integer division that doesn't trap.
+
+        This is the ARM64 ``sdiv`` instruction which returns 0 when the divisor
+        is 0 and wraps around when the quotient overflows.
+        """,
+        ins=(x, y), outs=q)
+
+Float = TypeVar('Float', 'A scalar floating point number', floats=True)
+IntTo = TypeVar('IntTo', 'A scalar integer machine word', ints=(32, 64))
+
+x = Operand('x', Float)
+a = Operand('a', IntTo)
+
+fcvtzs = Instruction(
+        'a64_fcvtzs', r"""
+        Convert with truncation floating point to signed integer.
+
+        The source floating point operand is converted to a signed integer by
+        rounding towards zero. Values that can't be represented in the output
+        type saturate to the smallest or largest signed value, and NaN converts
+        to 0.
+
+        This instruction does not trap.
+        """,
+        ins=x, outs=a)
+
+fcvtzu = Instruction(
+        'a64_fcvtzu', r"""
+        Convert with truncation floating point to unsigned integer.
+
+        The source floating point operand is converted to an unsigned integer
+        by rounding towards zero. Values that can't be represented in the
+        output type saturate to 0 or the largest unsigned value, and NaN
+        converts to 0.
+
+        This instruction does not trap.
+        """,
+        ins=x, outs=a)
+
+Word = TypeVar(
+        'Word', 'A register saved on the stack',
+        ints=(64, 64), floats=(64, 64))
+
+x = Operand('x', Word)
+y = Operand('y', Word)
+
+push = Instruction(
+        'a64_push', r"""
+        Push a register on the stack.
+
+        Decrements the stack pointer by 16 bytes and stores `x` at the new top
+        of the stack. The stack pointer stays 16-byte aligned.
+        """,
+        ins=x, can_store=True, other_side_effects=True)
+
+pop = Instruction(
+        'a64_pop', r"""
+        Pop a register pushed by `a64_push` from the stack.
+
+        Loads `x` from the top of the stack and then increments the stack
+        pointer by 16 bytes.
+        """,
+        outs=x, can_load=True, other_side_effects=True)
+
+push_pair = Instruction(
+        'a64_push_pair', r"""
+        Push a pair of registers on the stack.
+
+        Decrements the stack pointer by 16 bytes and stores `x` and `y` at the
+        new top of the stack, with `x` at the lower address.
+        """,
+        ins=(x, y), can_store=True, other_side_effects=True)
+
+pop_pair = Instruction(
+        'a64_pop_pair', r"""
+        Pop a pair of registers pushed by `a64_push_pair` from the stack.
+
+        Loads `x` and `y` from the top of the stack and then increments the
+        stack pointer by 16 bytes.
+        """,
+        outs=(x, y), can_load=True, other_side_effects=True)
+
+GROUP.close()
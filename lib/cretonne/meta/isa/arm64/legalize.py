@@ -0,0 +1,119 @@
+"""
+Custom legalization patterns for ARM64.
+"""
+from __future__ import absolute_import
+from cdsl.ast import Var
+from cdsl.xform import Rtl, XFormGroup
+from base.immediates import imm64, floatcc
+from base import legalize as shared
+from base import instructions as insts
+from base.types import i32, i64
+from .defs import ISA
+
+arm64_expand = XFormGroup(
+        'arm64_expand',
+        """
+        Legalize instructions by expansion.
+
+        Use ARM64-specific instructions if needed.
+
+        This chains to the generic `expand` group rather than `expand_flags`:
+        The carry flag of an ARM64 subtraction is the inverse of a borrow, so
+        the flag-based borrow expansions don't apply.
+        """,
+        isa=ISA, chain=shared.expand)
+
+a = Var('a')
+x = Var('x')
+y = Var('y')
+a1 = Var('a1')
+a2 = Var('a2')
+
+# The stack limit check only needs `ifcmp_sp` and `trapif`.
+arm64_expand.custom_legalize(insts.stack_check, 'expand_stack_check')
+
+#
+# Division and remainder.
+#
+# The `sdiv` and `udiv` instructions don't trap, so the division by zero and
+# overflow checks are inserted explicitly.
+arm64_expand.custom_legalize(insts.sdiv, 'expand_sdivrem')
+arm64_expand.custom_legalize(insts.srem, 'expand_sdivrem')
+arm64_expand.custom_legalize(insts.udiv, 'expand_udivrem')
+arm64_expand.custom_legalize(insts.urem, 'expand_udivrem')
+
+# Conversions from float to int saturate instead of trapping.
+arm64_expand.custom_legalize(insts.fcvt_to_sint, 'expand_fcvt_to_sint')
+arm64_expand.custom_legalize(insts.fcvt_to_uint, 'expand_fcvt_to_uint')
+
+# Loads and stores can only encode a 9-bit signed offset or a 12-bit scaled
+# unsigned offset. Other offsets are added to the address first.
+for inst in [
+        insts.load, insts.uload8, insts.sload8, insts.uload16, insts.sload16,
+        insts.uload32, insts.sload32,
+        insts.store, insts.istore8, insts.istore16, insts.istore32]:
+    arm64_expand.custom_legalize(inst, 'expand_mem_offset')
+
+# There is no rotate left instruction. Rotate right by the negated amount
+# instead. Only the low bits of the amount are used, so this also works for a
+# zero amount.
+arm64_expand.legalize(
+        a << insts.rotl(x, y),
+        Rtl(
+            a1 << insts.irsub_imm(y, imm64(0)),
+            a << insts.rotr(x, a1)
+        ))
+
+# Floating point condition codes.
+#
+# The `one` and `ueq` conditions can't be tested with a single condition code
+# after an `fcmp` instruction.
+arm64_expand.legalize(
+        a << insts.fcmp(floatcc.one, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.lt, x, y),
+            a2 << insts.fcmp(floatcc.gt, x, y),
+            a << insts.bor(a1, a2)
+        ))
+arm64_expand.legalize(
+        a << insts.fcmp(floatcc.ueq, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.uno, x, y),
+            a2 << insts.fcmp(floatcc.eq, x, y),
+            a << insts.bor(a1, a2)
+        ))
+
+# Population count.
+#
+# The `cnt` instruction only operates on vector registers, so count the bits
+# in a general purpose register with the usual shift and mask sequence.
+for ty, bits in [(i32, 32), (i64, 64)]:
+    m1 = Var('m1')
+    m2 = Var('m2')
+    m4 = Var('m4')
+    h01 = Var('h01')
+    v = [Var('v{}'.format(i)) for i in range(10)]
+    arm64_expand.legalize(
+        v[9] << insts.popcnt.bind(ty)(x),
+        Rtl(
+            m1 << insts.iconst(imm64(0x5555555555555555 >> (64 - bits))),
+            m2 << insts.iconst(imm64(0x3333333333333333 >> (64 - bits))),
+            m4 << insts.iconst(imm64(0x0f0f0f0f0f0f0f0f >> (64 - bits))),
+            h01 << insts.iconst(imm64(0x0101010101010101 >> (64 - bits))),
+            # Count bits in pairs.
+            v[0] << insts.ushr_imm(x, imm64(1)),
+            v[1] << insts.band(v[0], m1),
+            v[2] << insts.isub(x, v[1]),
+            # Sum adjacent pairs into nibbles.
+            v[3] << insts.band(v[2], m2),
+            v[4] << insts.ushr_imm(v[2], imm64(2)),
+            v[5] << insts.band(v[4], m2),
+            v[6] << insts.iadd(v[3], v[5]),
+            # Sum adjacent nibbles into bytes.
+            v[7] << insts.ushr_imm(v[6], imm64(4)),
+            v[8] << insts.iadd(v[6], v[7]),
+            a1 << insts.band(v[8], m4),
+            # Add up the bytes in the top byte.
+            a2 << insts.imul(a1, h01),
+            v[9] << insts.ushr_imm(a2, imm64(bits - 8))
+        ))
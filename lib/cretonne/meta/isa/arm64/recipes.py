@@ -0,0 +1,902 @@
+"""
+ARM64 Encoding recipes.
+
+All A64 instructions are 32 bits wide, but the encoding bits of a recipe only
+have 16 bits. Each class of instructions below has a function that compresses
+the fixed bits of an instruction template into encoding bits, and a matching
+`put_*` function in `binemit.rs` that expands them again.
+
+The instruction templates are the ones listed in the ARMv8 Architecture
+Reference Manual with all register and immediate fields set to 0.
+"""
+from __future__ import absolute_import
+from cdsl.isa import EncRecipe
+from cdsl.predicates import IsSignedInt, IsEqual, Or, Not
+from cdsl.registers import Stack
+from base.formats import Unary, UnaryImm, UnaryBool, Binary, BinaryImm
+from base.formats import Ternary, MultiAry, NullAry, Trap
+from base.formats import IntCompare, IntCompareImm, FloatCompare
+from base.formats import IntCond, FloatCond, IntSelect
+from base.formats import IntCondTrap, FloatCondTrap
+from base.formats import Jump, Branch, BranchInt, BranchFloat, BranchIcmp
+from base.formats import Call, IndirectCall, Invoke, FuncAddr
+from base.formats import UnaryGlobalVar, UnaryConst, Load, Store
+from base.formats import RegMove, RegSpill, RegFill, CopySpecial
+from base.formats import AtomicLoad, AtomicStore, AtomicCas, Fence, Barrier
+from base.immediates import atomic_ordering
+from .registers import GPR, FPR, FLAG
+from .defs import supported_floatccs
+
+try:
+    from typing import Sequence, Any  # noqa
+    from cdsl.instructions import InstructionFormat  # noqa
+    from cdsl.isa import PredNode  # noqa
+except ImportError:
+    pass
+
+
+def R3(template):
+    # type: (int) -> int
+    """
+    Three-register instructions with `Rm` in bits 20:16, `Rn` in bits 9:5,
+    and `Rd` in bits 4:0.
+
+    The encoding bits are `template[31:21] | template[14:10] << 11`. Bit 15 is
+    always 0, and the fixed `Ra` field of the multiplications fits in bits
+    14:10.
+    """
+    assert template & 0x001f83ff == 0, "Unexpected R3 template bits"
+    return (template >> 21) | (((template >> 10) & 0x1f) << 11)
+
+
+def DP1(sf, opcode):
+    # type: (int, int) -> int
+    """
+    Data-processing (1 source) instructions like `clz` and `rbit`. These use
+    the R3 encoding with a zero `Rm` field.
+    """
+    assert sf <= 1 and opcode <= 0b111111
+    return R3(0x5ac00000 | (sf << 31) | (opcode << 10))
+
+
+def FP1(ty, opcode):
+    # type: (int, int) -> int
+    """
+    Floating-point data-processing (1 source) instructions.
+
+    The `ty` field is 0 for single precision and 1 for double precision.
+    Encoding bits: `opcode[20:15] | ty << 6 | 1 << 9`.
+    """
+    assert ty <= 0b11 and opcode <= 0b111111
+    return opcode | (ty << 6) | (1 << 9)
+
+
+def FPINT(sf, ty, rmode, opcode):
+    # type: (int, int, int, int) -> int
+    """
+    Conversions between floating-point and integer registers.
+
+    Encoding bits: `opcode | rmode << 3 | ty << 6 | sf << 8`, which puts the
+    fields in the same positions as `FP1`.
+    """
+    assert sf <= 1 and ty <= 0b11 and rmode <= 0b11 and opcode <= 0b111
+    return (opcode << 1) | (rmode << 4) | (ty << 6) | (sf << 8)
+
+
+def ADDSUB_IMM(sf, op, s):
+    # type: (int, int, int) -> int
+    """
+    Add/subtract with a 12-bit immediate, optionally shifted left by 12.
+
+    Negative immediates are emitted by flipping the `op` bit.
+    """
+    assert sf <= 1 and op <= 1 and s <= 1
+    return s | (op << 1) | (sf << 2)
+
+
+def BFM(sf, opc, immr=0, imms=0):
+    # type: (int, int, int, int) -> int
+    """
+    Bitfield move instructions: `sbfm` (opc=00) and `ubfm` (opc=10).
+
+    Encoding bits: `imms | immr << 6 | opc << 12 | sf << 14`.
+    """
+    assert sf <= 1 and opc <= 0b11 and immr <= 63 and imms <= 63
+    return imms | (immr << 6) | (opc << 12) | (sf << 14)
+
+
+def MOVW(sf, opc):
+    # type: (int, int) -> int
+    """
+    Move wide immediate: `movn` (opc=00), `movz` (opc=10), `movk` (opc=11).
+    """
+    assert sf <= 1 and opc <= 0b11
+    return opc | (sf << 2)
+
+
+def LDST(template):
+    # type: (int) -> int
+    """
+    Loads and stores with an unsigned scaled 12-bit offset.
+
+    The encoding bits are `template[31:22]`. The emitter switches to the
+    unscaled 9-bit offset form when needed.
+    """
+    assert template & 0x003fffff == 0, "Unexpected LDST template bits"
+    return template >> 22
+
+
+def HI16(template):
+    # type: (int) -> int
+    """
+    Instructions with all their fixed bits in the upper half-word.
+    """
+    assert template & 0xffff == 0, "Unexpected HI16 template bits"
+    return template >> 16
+
+
+def BR(template):
+    # type: (int) -> int
+    """
+    Immediate branches: `b`, `bl`, `b.cond`, `cbz`, and `cbnz`.
+
+    Encoding bits: `template[31:24]`.
+    """
+    assert template & 0x00ffffff == 0, "Unexpected BR template bits"
+    return template >> 24
+
+
+def floatccs(iform, ccs=supported_floatccs):
+    # type: (InstructionFormat, Sequence[Any]) -> PredNode
+    """
+    Return an instruction predicate that checks in `iform.cond` is one of the
+    directly supported floating point condition codes.
+    """
+    return Or(*(IsEqual(iform.cond, cc) for cc in ccs))
+
+
+def addsub_imm(field):
+    # type: (Any) -> PredNode
+    """
+    Return an instruction predicate that checks if `field` can be encoded as
+    an add/subtract immediate, possibly negated and shifted.
+    """
+    return Or(IsSignedInt(field, 13), IsSignedInt(field, 24, 12))
+
+
+# A null unary instruction that takes a GPR register. Can be used for identity
+# copies and no-op conversions.
+null = EncRecipe('null', Unary, size=0, ins=GPR, outs=0, emit='')
+
+#
+# Integer arithmetic.
+#
+
+# Three-register instructions.
+rrr = EncRecipe(
+        'rrr', Binary, size=4, ins=(GPR, GPR), outs=GPR,
+        clobbers_flags=False,
+        emit='put_rrr(bits, in_reg0, in_reg1, out_reg0, sink);')
+
+# Unary instructions using the R3 encoding with `Rm` = 0.
+rr = EncRecipe(
+        'rr', Unary, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='put_rrr(bits, in_reg0, 0, out_reg0, sink);')
+
+# Unary instructions using the R3 encoding with `Rn` = xzr, like `mvn` and
+# `mov`.
+rzr = EncRecipe(
+        'rzr', Unary, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='put_rrr(bits, ZR, in_reg0, out_reg0, sink);')
+
+# Register move using `orr rd, xzr, rm`.
+rmov = EncRecipe(
+        'rmov', RegMove, size=4, ins=GPR, outs=(),
+        clobbers_flags=False,
+        emit='put_rrr(bits, ZR, src, dst, sink);')
+
+# Boolean negation of a `b1` value: `eor wd, wn, #1`.
+rnot_b1 = EncRecipe(
+        'rnot_b1', Unary, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='put_rr(0x52000000, in_reg0, out_reg0, sink);')
+
+# Add/subtract immediate.
+rri = EncRecipe(
+        'rri', BinaryImm, size=4, ins=GPR, outs=GPR,
+        instp=addsub_imm(BinaryImm.imm),
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm(bits, in_reg0, imm, out_reg0, sink);
+        ''')
+
+# Macro: Add a 24-bit immediate in two steps, low bits first.
+rri2 = EncRecipe(
+        'rri2', BinaryImm, size=8, ins=GPR, outs=GPR,
+        instp=IsSignedInt(BinaryImm.imm, 24),
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm2(bits, in_reg0, imm, out_reg0, sink);
+        ''')
+
+# Multiply-add with `Ra` in bits 14:10.
+frrrr = EncRecipe(
+        'frrrr', Ternary, size=4, ins=(FPR, FPR, FPR), outs=FPR,
+        clobbers_flags=False,
+        emit='put_rrrr(bits, in_reg0, in_reg1, in_reg2, out_reg0, sink);')
+
+# Macro: High half of a 32-bit multiplication. This is a widening
+# `umull`/`smull` followed by a shift right by 32.
+mulhi32 = EncRecipe(
+        'mulhi32', Binary, size=8, ins=(GPR, GPR), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        put_rrr(bits, in_reg0, in_reg1, out_reg0, sink);
+        // The `U` bit selects between `lsr` and `asr`.
+        let opc = if bits & 0x4 != 0 { 0b10 } else { 0b00 };
+        let shift = BFM_X | (opc << 12) | (32 << 6) | 63;
+        put_bfm(shift, out_reg0, out_reg0, sink);
+        ''')
+
+# Macro: Count trailing zeros as `rbit` followed by `clz`. The encoding bits
+# are the `rbit` instruction, `clz` is the following opcode.
+ctz = EncRecipe(
+        'ctz', Unary, size=8, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        put_rrr(bits, in_reg0, 0, out_reg0, sink);
+        put_rrr(bits | (0b00100 << 11), out_reg0, 0, out_reg0, sink);
+        ''')
+
+# Bitfield moves with the fields in the encoding bits. Used for sign and zero
+# extensions.
+bfm = EncRecipe(
+        'bfm', Unary, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='put_bfm(bits, in_reg0, out_reg0, sink);')
+
+# Shift left by an immediate amount: `ubfm` with computed fields.
+lsl_imm = EncRecipe(
+        'lsl_imm', BinaryImm, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_lsl_imm(bits, in_reg0, imm, out_reg0, sink);
+        ''')
+
+# Shift right by an immediate amount: `ubfm` or `sbfm` with computed fields.
+shr_imm = EncRecipe(
+        'shr_imm', BinaryImm, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_shr_imm(bits, in_reg0, imm, out_reg0, sink);
+        ''')
+
+# Rotate right by an immediate amount: `extr rd, rn, rn, #imm`.
+ror_imm = EncRecipe(
+        'ror_imm', BinaryImm, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_extr(bits, in_reg0, imm, out_reg0, sink);
+        ''')
+
+# Rotate left by an immediate amount, encoded as a right rotation.
+rol_imm = EncRecipe(
+        'rol_imm', BinaryImm, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_extr(bits, in_reg0, imm.wrapping_neg(), out_reg0, sink);
+        ''')
+
+#
+# Constants.
+#
+
+# Move a 17-bit signed immediate with a single `movz` or `movn`.
+movz = EncRecipe(
+        'movz', UnaryImm, size=4, ins=(), outs=GPR,
+        instp=IsSignedInt(UnaryImm.imm, 17),
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_mov_imm(bits, imm, 1, out_reg0, sink);
+        ''')
+
+# Macro: Move a 32-bit immediate with `movz`/`movn` followed by a `movk`.
+mov2 = EncRecipe(
+        'mov2', UnaryImm, size=8, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_mov_imm(bits, imm, 2, out_reg0, sink);
+        ''')
+
+# Macro: Move a 64-bit immediate with `movz` followed by three `movk`.
+mov4 = EncRecipe(
+        'mov4', UnaryImm, size=16, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_mov_imm(bits, imm, 4, out_reg0, sink);
+        ''')
+
+# Boolean constants.
+bconst = EncRecipe(
+        'bconst', UnaryBool, size=4, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='put_mov_imm(bits, if imm { 1 } else { 0 }, 1, out_reg0, sink);')
+
+# Load a constant from the constant pool with a PC-relative `ldr` (literal).
+# The encoding bits are `template[31:24]`.
+ldr_lit = EncRecipe(
+        'ldr_lit', UnaryConst, size=4, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        let disp = const_disp(constant, func, sink);
+        put_b19(bits, disp, u32::from(out_reg0), sink);
+        ''')
+
+fldr_lit = EncRecipe(
+        'fldr_lit', UnaryConst, size=4, ins=(), outs=FPR,
+        clobbers_flags=False,
+        emit='''
+        let disp = const_disp(constant, func, sink);
+        put_b19(bits, disp, u32::from(out_reg0), sink);
+        ''')
+
+# Macro: Load an absolute 64-bit function address stored inline. The address
+# is skipped by a branch.
+fnaddr = EncRecipe(
+        'fnaddr', FuncAddr, size=16, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        put_ldr_inline(out_reg0, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::Arm64Abs8,
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
+        sink.put8(0);
+        ''')
+
+# Same as `fnaddr` for the address of a global symbol.
+gvaddr = EncRecipe(
+        'gvaddr', UnaryGlobalVar, size=16, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        put_ldr_inline(out_reg0, sink);
+        let gv = &func.global_vars[global_var];
+        sink.reloc_external(Reloc::Arm64Abs8,
+                            gv.symbol_name(),
+                            0,
+                            SymbolInfo::global_var(gv));
+        sink.put8(0);
+        ''')
+
+#
+# Comparisons and flags.
+#
+
+# Compare two registers: `subs xzr, xn, xm`.
+rcmp = EncRecipe(
+        'rcmp', Binary, size=4, ins=(GPR, GPR), outs=FLAG.nzcv,
+        emit='put_rrr(bits, in_reg0, in_reg1, ZR, sink);')
+
+# Compare with an immediate: `subs xzr, xn, #imm`.
+rcmp_imm = EncRecipe(
+        'rcmp_imm', BinaryImm, size=4, ins=GPR, outs=FLAG.nzcv,
+        instp=addsub_imm(BinaryImm.imm),
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm(bits, in_reg0, imm, ZR, sink);
+        ''')
+
+# Macro: Compare with the stack pointer. The `subs` instruction can't read the
+# stack pointer as its second operand, so copy it to the reserved %x16 first.
+rcmp_sp = EncRecipe(
+        'rcmp_sp', Unary, size=8, ins=GPR, outs=FLAG.nzcv,
+        emit='''
+        put_addsub_imm(ADD_X, SP, 0, TMP0, sink);
+        put_rrr(bits, in_reg0, TMP0, ZR, sink);
+        ''')
+
+# Compare two floating point registers.
+fcmp = EncRecipe(
+        'fcmp', Binary, size=4, ins=(FPR, FPR), outs=FLAG.nzcv,
+        emit='put_rrr(bits, in_reg0, in_reg1, 0, sink);')
+
+# Macro: Integer comparison followed by `cset`.
+icscc = EncRecipe(
+        'icscc', IntCompare, size=8, ins=(GPR, GPR), outs=GPR,
+        emit='''
+        put_rrr(bits, in_reg0, in_reg1, ZR, sink);
+        put_cset(icc2cond(cond), out_reg0, sink);
+        ''')
+
+# Macro: Integer comparison with an immediate followed by `cset`.
+icscc_imm = EncRecipe(
+        'icscc_imm', IntCompareImm, size=8, ins=GPR, outs=GPR,
+        instp=addsub_imm(IntCompareImm.imm),
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm(bits, in_reg0, imm, ZR, sink);
+        put_cset(icc2cond(cond), out_reg0, sink);
+        ''')
+
+# Macro: Floating point comparison followed by `cset`.
+fcscc = EncRecipe(
+        'fcscc', FloatCompare, size=8, ins=(FPR, FPR), outs=GPR,
+        instp=floatccs(FloatCompare),
+        emit='''
+        put_rrr(bits, in_reg0, in_reg1, 0, sink);
+        put_cset(fcc2cond(cond), out_reg0, sink);
+        ''')
+
+# Materialize an integer condition as a `b1` value.
+cset = EncRecipe(
+        'cset', IntCond, size=4, ins=FLAG.nzcv, outs=GPR,
+        clobbers_flags=False,
+        emit='put_cset(icc2cond(cond), out_reg0, sink);')
+
+fcset = EncRecipe(
+        'fcset', FloatCond, size=4, ins=FLAG.nzcv, outs=GPR,
+        clobbers_flags=False,
+        instp=floatccs(FloatCond),
+        emit='put_cset(fcc2cond(cond), out_reg0, sink);')
+
+# Conditional select on integer flags.
+csel = EncRecipe(
+        'csel', IntSelect, size=4, ins=(FLAG.nzcv, GPR, GPR), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        put_csel(bits, in_reg1, in_reg2, icc2cond(cond), out_reg0, sink);
+        ''')
+
+fcsel = EncRecipe(
+        'fcsel', IntSelect, size=4, ins=(FLAG.nzcv, FPR, FPR), outs=FPR,
+        clobbers_flags=False,
+        emit='''
+        put_csel(bits, in_reg1, in_reg2, icc2cond(cond), out_reg0, sink);
+        ''')
+
+#
+# Floating point.
+#
+
+frrr = EncRecipe(
+        'frrr', Binary, size=4, ins=(FPR, FPR), outs=FPR,
+        clobbers_flags=False,
+        emit='put_rrr(bits, in_reg0, in_reg1, out_reg0, sink);')
+
+# Floating point unary instructions in the `FP1` and `FPINT` classes.
+frr = EncRecipe(
+        'frr', Unary, size=4, ins=FPR, outs=FPR,
+        clobbers_flags=False,
+        emit='put_fp(bits, in_reg0, out_reg0, sink);')
+
+# Conversion from an FPR to a GPR.
+frr2r = EncRecipe(
+        'frr2r', Unary, size=4, ins=FPR, outs=GPR,
+        clobbers_flags=False,
+        emit='put_fp(bits, in_reg0, out_reg0, sink);')
+
+# Conversion from a GPR to an FPR.
+rr2f = EncRecipe(
+        'rr2f', Unary, size=4, ins=GPR, outs=FPR,
+        clobbers_flags=False,
+        emit='put_fp(bits, in_reg0, out_reg0, sink);')
+
+# Floating point register move.
+fmov = EncRecipe(
+        'fmov', RegMove, size=4, ins=FPR, outs=(),
+        clobbers_flags=False,
+        emit='put_fp(bits, src, dst, sink);')
+
+#
+# Loads and stores.
+#
+# The base register can never be the stack pointer, so the value must be
+# moved to a normal register first.
+#
+
+ld = EncRecipe(
+        'ld', Load, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+fld = EncRecipe(
+        'fld', Load, size=4, ins=GPR, outs=FPR,
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+st = EncRecipe(
+        'st', Store, size=4, ins=(GPR, GPR), outs=(),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst(bits, in_reg1, offset.into(), in_reg0, sink);
+        ''')
+
+fst = EncRecipe(
+        'fst', Store, size=4, ins=(FPR, GPR), outs=(),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst(bits, in_reg1, offset.into(), in_reg0, sink);
+        ''')
+
+# Spill and fill relative to the stack pointer.
+spill = EncRecipe(
+        'spill', Unary, size=4, ins=GPR, outs=Stack(GPR),
+        clobbers_flags=False,
+        emit='put_ldst(bits, SP, out_stk0.offset, in_reg0, sink);')
+
+fspill = EncRecipe(
+        'fspill', Unary, size=4, ins=FPR, outs=Stack(FPR),
+        clobbers_flags=False,
+        emit='put_ldst(bits, SP, out_stk0.offset, in_reg0, sink);')
+
+fill = EncRecipe(
+        'fill', Unary, size=4, ins=Stack(GPR), outs=GPR,
+        clobbers_flags=False,
+        emit='put_ldst(bits, SP, in_stk0.offset, out_reg0, sink);')
+
+ffill = EncRecipe(
+        'ffill', Unary, size=4, ins=Stack(FPR), outs=FPR,
+        clobbers_flags=False,
+        emit='put_ldst(bits, SP, in_stk0.offset, out_reg0, sink);')
+
+regspill = EncRecipe(
+        'regspill', RegSpill, size=4, ins=GPR, outs=(),
+        clobbers_flags=False,
+        emit='''
+        let dst = StackRef::sp(dst, &func.stack_slots);
+        put_ldst(bits, SP, dst.offset, src, sink);
+        ''')
+
+fregspill = EncRecipe(
+        'fregspill', RegSpill, size=4, ins=FPR, outs=(),
+        clobbers_flags=False,
+        emit='''
+        let dst = StackRef::sp(dst, &func.stack_slots);
+        put_ldst(bits, SP, dst.offset, src, sink);
+        ''')
+
+regfill = EncRecipe(
+        'regfill', RegFill, size=4, ins=Stack(GPR), outs=(),
+        clobbers_flags=False,
+        emit='''
+        let src = StackRef::sp(src, &func.stack_slots);
+        put_ldst(bits, SP, src.offset, dst, sink);
+        ''')
+
+fregfill = EncRecipe(
+        'fregfill', RegFill, size=4, ins=Stack(FPR), outs=(),
+        clobbers_flags=False,
+        emit='''
+        let src = StackRef::sp(src, &func.stack_slots);
+        put_ldst(bits, SP, src.offset, dst, sink);
+        ''')
+
+# Push a register with a pre-indexed store, keeping the stack pointer 16-byte
+# aligned.
+push = EncRecipe(
+        'push', Unary, size=4, ins=GPR, outs=(),
+        clobbers_flags=False,
+        emit='put_ldst_idx(bits, IDX_PRE, SP, -16, in_reg0, sink);')
+
+fpush = EncRecipe(
+        'fpush', Unary, size=4, ins=FPR, outs=(),
+        clobbers_flags=False,
+        emit='put_ldst_idx(bits, IDX_PRE, SP, -16, in_reg0, sink);')
+
+# Pop a register with a post-indexed load.
+pop = EncRecipe(
+        'pop', NullAry, size=4, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='put_ldst_idx(bits, IDX_POST, SP, 16, out_reg0, sink);')
+
+fpop = EncRecipe(
+        'fpop', NullAry, size=4, ins=(), outs=FPR,
+        clobbers_flags=False,
+        emit='put_ldst_idx(bits, IDX_POST, SP, 16, out_reg0, sink);')
+
+# Push a pair of registers with a pre-indexed `stp`.
+push_pair = EncRecipe(
+        'push_pair', Binary, size=4, ins=(GPR, GPR), outs=(),
+        clobbers_flags=False,
+        emit='put_ldst_pair(bits, IDX_PRE, SP, -16, in_reg0, in_reg1, sink);')
+
+fpush_pair = EncRecipe(
+        'fpush_pair', Binary, size=4, ins=(FPR, FPR), outs=(),
+        clobbers_flags=False,
+        emit='put_ldst_pair(bits, IDX_PRE, SP, -16, in_reg0, in_reg1, sink);')
+
+# Pop a pair of registers with a post-indexed `ldp`.
+pop_pair = EncRecipe(
+        'pop_pair', NullAry, size=4, ins=(), outs=(GPR, GPR),
+        clobbers_flags=False,
+        emit='''
+        put_ldst_pair(bits, IDX_POST, SP, 16, out_reg0, out_reg1, sink);
+        ''')
+
+fpop_pair = EncRecipe(
+        'fpop_pair', NullAry, size=4, ins=(), outs=(FPR, FPR),
+        clobbers_flags=False,
+        emit='''
+        put_ldst_pair(bits, IDX_POST, SP, 16, out_reg0, out_reg1, sink);
+        ''')
+
+# Copy between the stack pointer and the frame pointer: `add xd, xn, #0`.
+copysp = EncRecipe(
+        'copysp', CopySpecial, size=4, ins=(), outs=(),
+        clobbers_flags=False,
+        emit='put_addsub_imm(bits, src, 0, dst, sink);')
+
+# Adjust the stack pointer by an immediate.
+adjustsp = EncRecipe(
+        'adjustsp', UnaryImm, size=4, ins=(), outs=(),
+        instp=addsub_imm(UnaryImm.imm),
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm(bits, SP, imm, SP, sink);
+        ''')
+
+adjustsp2 = EncRecipe(
+        'adjustsp2', UnaryImm, size=8, ins=(), outs=(),
+        instp=IsSignedInt(UnaryImm.imm, 24),
+        clobbers_flags=False,
+        emit='''
+        let imm: i64 = imm.into();
+        put_addsub_imm2(bits, SP, imm, SP, sink);
+        ''')
+
+#
+# Atomic memory operations.
+#
+# Loads and stores always use the acquire and release forms. These are
+# sequentially consistent with each other.
+#
+
+ald = EncRecipe(
+        'ald', AtomicLoad, size=4, ins=GPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst_excl(bits, ZR, in_reg0, out_reg0, sink);
+        ''')
+
+ast = EncRecipe(
+        'ast', AtomicStore, size=4, ins=(GPR, GPR), outs=(),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_ldst_excl(bits, ZR, in_reg1, in_reg0, sink);
+        ''')
+
+# Macro: Compare-and-swap loop with load-acquire exclusive and
+# store-release exclusive. The loaded value is kept in %x16 until the loop is
+# done so the result register can be the same as one of the inputs.
+acas = EncRecipe(
+        'acas', AtomicCas, size=24, ins=(GPR, GPR, GPR), outs=GPR,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_cas_loop(bits, in_reg0, in_reg1, in_reg2, out_reg0, sink);
+        ''')
+
+# `dmb ishld` orders the earlier loads against everything after it.
+dmb_ld = EncRecipe(
+        'dmb_ld', Fence, size=4, ins=(), outs=(),
+        instp=IsEqual(Fence.ordering, atomic_ordering.acquire),
+        clobbers_flags=False,
+        emit='sink.put4(0xd50339bf);')
+
+# `dmb ish` for all other orderings.
+dmb = EncRecipe(
+        'dmb', Fence, size=4, ins=(), outs=(),
+        instp=Not(IsEqual(Fence.ordering, atomic_ordering.acquire)),
+        clobbers_flags=False,
+        emit='sink.put4(0xd5033bbf);')
+
+# Compiler barriers don't generate any code.
+null_barrier = EncRecipe(
+        'null_barrier', Barrier, size=0, ins=(), outs=(),
+        clobbers_flags=False,
+        emit='')
+
+#
+# Traps.
+#
+
+# `udf #0` is permanently undefined.
+trap = EncRecipe(
+        'trap', Trap, size=4, ins=(), outs=(),
+        clobbers_flags=False,
+        emit='''
+        sink.trap(code, func.srclocs[inst]);
+        sink.put4(UDF);
+        ''')
+
+# Macro: Conditional branch over a `udf`.
+trapif = EncRecipe(
+        'trapif', IntCondTrap, size=8, ins=FLAG.nzcv, outs=(),
+        clobbers_flags=False,
+        emit='''
+        put_b19(BCOND, 8, icc2cond(cond.inverse()), sink);
+        sink.trap(code, func.srclocs[inst]);
+        sink.put4(UDF);
+        ''')
+
+trapff = EncRecipe(
+        'trapff', FloatCondTrap, size=8, ins=FLAG.nzcv, outs=(),
+        clobbers_flags=False,
+        instp=floatccs(FloatCondTrap),
+        emit='''
+        put_b19(BCOND, 8, fcc2cond(cond.inverse()), sink);
+        sink.trap(code, func.srclocs[inst]);
+        sink.put4(UDF);
+        ''')
+
+#
+# Call/return.
+#
+
+call = EncRecipe(
+        'call', Call, size=4, ins=(), outs=(),
+        emit='''
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::Arm64Call,
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
+        put_b26(bits, 0, sink);
+        ''')
+
+# Calls with a landing pad are encoded like plain calls. The landing pad only
+# appears in the call site table.
+invoke = EncRecipe(
+        'invoke', Invoke, size=4, ins=(), outs=(),
+        emit='''
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::Arm64Call,
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
+        put_b26(bits, 0, sink);
+        ''')
+
+# Branch to a register: `blr`, `br`, and `ret`.
+call_r = EncRecipe(
+        'call_r', IndirectCall, size=4, ins=GPR, outs=(),
+        emit='put_br(bits, in_reg0, sink);')
+
+# Indirect tail call. The callee address can't be in a callee-saved register
+# since the epilogue restores those, and it can't be in an argument register,
+# so use %x9 which is neither.
+tailcall_r = EncRecipe(
+        'tailcall_r', IndirectCall, size=4, ins=GPR.x9, outs=(),
+        emit='put_br(bits, 9, sink);')
+
+# Return to the address in the link register %x30.
+# The variable return values are not encoded.
+ret = EncRecipe(
+        'ret', MultiAry, size=4, ins=(), outs=(),
+        emit='put_br(bits, LR, sink);')
+
+#
+# Branches.
+#
+# Each branch recipe has a macro with a longer range that is selected by
+# branch relaxation. The long forms branch over an unconditional `b`.
+#
+
+b = EncRecipe(
+        'b', Jump, size=4, ins=(), outs=(),
+        branch_range=(0, 28),
+        clobbers_flags=False,
+        emit='put_b26(bits, branch_disp(destination, func, sink), sink);')
+
+bcond = EncRecipe(
+        'bcond', BranchInt, size=4, ins=FLAG.nzcv, outs=(),
+        branch_range=(0, 21),
+        clobbers_flags=False,
+        emit='''
+        let disp = branch_disp(destination, func, sink);
+        put_b19(bits, disp, icc2cond(cond), sink);
+        ''')
+
+bcond_long = EncRecipe(
+        'bcond_long', BranchInt, size=8, ins=FLAG.nzcv, outs=(),
+        branch_range=(4, 28),
+        clobbers_flags=False,
+        emit='''
+        put_b19(bits, 8, icc2cond(cond.inverse()), sink);
+        put_b26(B, branch_disp(destination, func, sink), sink);
+        ''')
+
+fbcond = EncRecipe(
+        'fbcond', BranchFloat, size=4, ins=FLAG.nzcv, outs=(),
+        branch_range=(0, 21),
+        clobbers_flags=False,
+        instp=floatccs(BranchFloat),
+        emit='''
+        let disp = branch_disp(destination, func, sink);
+        put_b19(bits, disp, fcc2cond(cond), sink);
+        ''')
+
+fbcond_long = EncRecipe(
+        'fbcond_long', BranchFloat, size=8, ins=FLAG.nzcv, outs=(),
+        branch_range=(4, 28),
+        clobbers_flags=False,
+        instp=floatccs(BranchFloat),
+        emit='''
+        put_b19(bits, 8, fcc2cond(cond.inverse()), sink);
+        put_b26(B, branch_disp(destination, func, sink), sink);
+        ''')
+
+# Compare and branch on zero: `cbz` and `cbnz`.
+cbz = EncRecipe(
+        'cbz', Branch, size=4, ins=GPR, outs=(),
+        branch_range=(0, 21),
+        clobbers_flags=False,
+        emit='''
+        let disp = branch_disp(destination, func, sink);
+        put_b19(bits, disp, u32::from(in_reg0), sink);
+        ''')
+
+# The low bit of the encoding bits flips `cbz` and `cbnz`.
+cbz_long = EncRecipe(
+        'cbz_long', Branch, size=8, ins=GPR, outs=(),
+        branch_range=(4, 28),
+        clobbers_flags=False,
+        emit='''
+        put_b19(bits ^ 1, 8, u32::from(in_reg0), sink);
+        put_b26(B, branch_disp(destination, func, sink), sink);
+        ''')
+
+# Macro: Compare two registers and branch.
+brcmp = EncRecipe(
+        'brcmp', BranchIcmp, size=8, ins=(GPR, GPR), outs=(),
+        branch_range=(4, 21),
+        emit='''
+        put_rrr(bits, in_reg0, in_reg1, ZR, sink);
+        let disp = branch_disp(destination, func, sink);
+        put_b19(BCOND, disp, icc2cond(cond), sink);
+        ''')
+
+brcmp_long = EncRecipe(
+        'brcmp_long', BranchIcmp, size=12, ins=(GPR, GPR), outs=(),
+        branch_range=(8, 28),
+        emit='''
+        put_rrr(bits, in_reg0, in_reg1, ZR, sink);
+        put_b19(BCOND, 8, icc2cond(cond.inverse()), sink);
+        put_b26(B, branch_disp(destination, func, sink), sink);
+        ''')
@@ -26,3 +26,15 @@ supported_floatccs = [
         floatcc.ge,
         floatcc.ult,
         floatcc.ule]
+
+# The set of floating point condition codes that are directly supported by the
+# `cmpps` and `cmppd` vector comparisons.
+supported_vector_floatccs = [
+        floatcc.eq,
+        floatcc.lt,
+        floatcc.le,
+        floatcc.uno,
+        floatcc.ne,
+        floatcc.uge,
+        floatcc.ugt,
+        floatcc.ord]
@@ -2,33 +2,60 @@
 Intel Encodings.
 """
 from __future__ import absolute_import
-from cdsl.predicates import IsUnsignedInt, Not, And
+from cdsl.predicates import IsUnsignedInt, IsEqual, IsColocatedFunc, Not, And
+from cdsl.predicates import IsAligned
 from base import instructions as base
-from base.formats import UnaryImm
+from base.formats import UnaryImm, IntCompare, InsertLane, FuncAddr, Call
+from base.formats import Invoke, Load, Store
+from base.immediates import intcc
+from base.types import i8, i16, i32, i64, f32, f64, b8, b16, b32, b64
 from .defs import X86_64, X86_32
 from . import recipes as r
 from . import settings as cfg
 from . import instructions as x86
-from .legalize import intel_expand
+from .legalize import intel_expand, intel_simd
 from base.legalize import narrow, expand_flags
 from base.settings import allones_funcaddrs, is_pic
 from .settings import use_sse41
 
 try:
-    from typing import TYPE_CHECKING, Any  # noqa
+    from typing import TYPE_CHECKING, Any, Dict  # noqa
     if TYPE_CHECKING:
         from cdsl.instructions import MaybeBoundInst  # noqa
+        from cdsl.isa import PredNode  # noqa
 except ImportError:
     pass
 
 
+# The 128-bit vector types that fit in an XMM register.
+i8x16 = i8.by(16)
+i16x8 = i16.by(8)
+i32x4 = i32.by(4)
+i64x2 = i64.by(2)
+f32x4 = f32.by(4)
+f64x2 = f64.by(2)
+int_vectors = [i8x16, i16x8, i32x4, i64x2]
+float_vectors = [f32x4, f64x2]
+bool_vectors = [b8.by(16), b16.by(8), b32.by(4), b64.by(2)]
+vectors = int_vectors + float_vectors + bool_vectors
+
 X86_32.legalize_monomorphic(expand_flags)
 X86_32.legalize_type(
     default=narrow,
     b1=expand_flags,
     i32=intel_expand,
     f32=intel_expand,
-    f64=intel_expand)
+    f64=intel_expand,
+    i8x16=intel_simd,
+    i16x8=intel_simd,
+    i32x4=intel_simd,
+    i64x2=intel_simd,
+    f32x4=intel_simd,
+    f64x2=intel_simd,
+    b8x16=intel_simd,
+    b16x8=intel_simd,
+    b32x4=intel_simd,
+    b64x2=intel_simd)
 
 X86_64.legalize_monomorphic(expand_flags)
 X86_64.legalize_type(
@@ -37,7 +64,17 @@ X86_64.legalize_type(
     i32=intel_expand,
     i64=intel_expand,
     f32=intel_expand,
-    f64=intel_expand)
+    f64=intel_expand,
+    i8x16=intel_simd,
+    i16x8=intel_simd,
+    i32x4=intel_simd,
+    i64x2=intel_simd,
+    f32x4=intel_simd,
+    f64x2=intel_simd,
+    b8x16=intel_simd,
+    b16x8=intel_simd,
+    b32x4=intel_simd,
+    b64x2=intel_simd)
 
 
 #
@@ -62,6 +99,22 @@ def enc_both(inst, recipe, *args, **kwargs):
     enc_x86_64(inst, recipe, *args, **kwargs)
 
 
+def enc_vec(inst, recipe, *args, **kwargs):
+    # type: (MaybeBoundInst, r.TailRecipe, *int, **Any) -> None
+    """
+    Add encodings for the vector instruction `inst` to both X86_32 and X86_64.
+
+    The `instp` and `isap` keyword arguments are passed on to the encodings.
+    """
+    preds = dict()  # type: Dict[str, PredNode]
+    for key in ('instp', 'isap'):
+        if key in kwargs:
+            preds[key] = kwargs.pop(key)
+    X86_32.enc(inst, *recipe(*args, **kwargs), **preds)
+    X86_64.enc(inst, *recipe.rex(*args, **kwargs), **preds)
+    X86_64.enc(inst, *recipe(*args, **kwargs), **preds)
+
+
 def enc_i32_i64(inst, recipe, *args, **kwargs):
     # type: (MaybeBoundInst, r.TailRecipe, *int, **int) -> None
     """
@@ -117,6 +170,11 @@ enc_both(base.bor.b1,  r.rr, 0x09)
 enc_both(base.bxor.b1, r.rr, 0x31)
 
 enc_i32_i64(base.imul, r.rrx, 0x0f, 0xaf)
+
+# Arithmetic producing the carry and overflow flags.
+enc_i32_i64(base.iadd_ifcout, r.rout, 0x01)
+enc_i32_i64(base.isub_ifbout, r.rout, 0x29)
+enc_i32_i64(base.imul_ifoflow, r.rrxout, 0x0f, 0xaf)
 enc_i32_i64(x86.sdivmodx, r.div, 0xf7, rrr=7)
 enc_i32_i64(x86.udivmodx, r.div, 0xf7, rrr=6)
 
@@ -128,6 +186,11 @@ enc_both(base.copy.b1, r.umr, 0x89)
 enc_i32_i64(base.regmove, r.rmov, 0x89)
 enc_both(base.regmove.b1, r.rmov, 0x89)
 
+# Narrow integers are moved with 32-bit moves like `b1`.
+for ty in [i8, i16]:
+    enc_both(base.copy.bind(ty), r.umr, 0x89)
+    enc_both(base.regmove.bind(ty), r.rmov, 0x89)
+
 # Immediate instructions with sign-extended 8-bit and 32-bit immediate.
 for inst,               rrr in [
         (base.iadd_imm, 0),
@@ -155,6 +218,17 @@ X86_64.enc(base.iconst.i64, *r.uid.rex(0xc7, rrr=0, w=1))
 # Finally, the 0xb8 opcode takes an 8-byte immediate with a REX.W prefix.
 X86_64.enc(base.iconst.i64, *r.puiq.rex(0xb8, w=1))
 
+# Shifts without flags, BMI2. These take the shift amount in any register, so
+# they come before the legacy shifts that need it in %rcx.
+for inst,           prefix in [
+        (base.ishl, 0x66),
+        (base.ushr, 0xf2),
+        (base.sshr, 0xf3)]:
+    X86_32.enc(inst.i32.any, r.shx, r.vex_bits(prefix, 0x0f, 0x38, 0xf7))
+    X86_64.enc(inst.i64.any, r.shx,
+               r.vex_bits(prefix, 0x0f, 0x38, 0xf7, w=1))
+    X86_64.enc(inst.i32.any, r.shx, r.vex_bits(prefix, 0x0f, 0x38, 0xf7))
+
 # Shifts and rotates.
 # Note that the dynamic shift amount is only masked by 5 or 6 bits; the 8-bit
 # and 16-bit shifts would need explicit masking.
@@ -214,6 +288,12 @@ enc_i32_i64(base.regspill, r.regspill32, 0x89)
 enc_both(base.spill.b1, r.spillSib32, 0x89)
 enc_both(base.regspill.b1, r.regspill32, 0x89)
 
+# The narrow integer types are spilled the same way, so values like `i8`
+# function parameters can be live across calls.
+for ty in [i8, i16]:
+    enc_both(base.spill.bind(ty), r.spillSib32, 0x89)
+    enc_both(base.regspill.bind(ty), r.regspill32, 0x89)
+
 for recipe in [r.ld, r.ldDisp8, r.ldDisp32]:
     enc_i32_i64_ld_st(base.load, True, recipe, 0x8b)
     enc_x86_64(base.uload32.i64, recipe, 0x8b)
@@ -230,6 +310,10 @@ enc_i32_i64(base.regfill, r.regfill32, 0x8b)
 enc_both(base.fill.b1, r.fillSib32, 0x8b)
 enc_both(base.regfill.b1, r.regfill32, 0x8b)
 
+for ty in [i8, i16]:
+    enc_both(base.fill.bind(ty), r.fillSib32, 0x8b)
+    enc_both(base.regfill.bind(ty), r.regfill32, 0x8b)
+
 # Push and Pop
 X86_32.enc(x86.push.i32, *r.pushq(0x50))
 enc_x86_64(x86.push.i64, r.pushq, 0x50)
@@ -291,9 +375,31 @@ X86_32.enc(base.func_addr.i32, *r.allones_fnaddr4(0xb8),
 X86_64.enc(base.func_addr.i64, *r.allones_fnaddr8.rex(0xb8, w=1),
            isap=And(allones_funcaddrs, Not(is_pic)))
 
+X86_64.enc(base.func_addr.i64, *r.pcrel_fnaddr8.rex(0x8d, w=1),
+           isap=is_pic, instp=IsColocatedFunc(FuncAddr.func_ref))
 X86_64.enc(base.func_addr.i64, *r.got_fnaddr8.rex(0x8b, w=1),
            isap=is_pic)
 
+#
+# Stack slot addresses.
+#
+
+X86_32.enc(base.stack_addr.i32, *r.spaddr_id(0x8d))
+X86_64.enc(base.stack_addr.i64, *r.spaddr_id.rex(0x8d, w=1))
+
+#
+# Constant pool loads.
+#
+# The constant pool is addressed RIP-relative, so these are only available in
+# 64-bit mode. The pool is 16-byte aligned which permits `movaps` for vectors.
+
+enc_x86_64(base.const_load.i32, r.const_ld, 0x8b)
+X86_64.enc(base.const_load.i64, *r.const_ld.rex(0x8b, w=1))
+enc_x86_64(base.const_load.f32, r.const_fld, 0x66, 0x0f, 0x6e)
+enc_x86_64(base.const_load.f64, r.const_fld, 0xf3, 0x0f, 0x7e)
+for ty in int_vectors + float_vectors:
+    enc_x86_64(base.const_load.bind(ty), r.const_fld, 0x0f, 0x28)
+
 #
 # Global addresses.
 #
@@ -302,20 +408,38 @@ X86_32.enc(base.globalsym_addr.i32, *r.gvaddr4(0xb8))
 X86_64.enc(base.globalsym_addr.i64, *r.gvaddr8.rex(0xb8, w=1),
            isap=Not(is_pic))
 
-X86_64.enc(base.globalsym_addr.i64, *r.got_gvaddr8.rex(0x8b, w=1),
+X86_64.enc(base.globalsym_addr.i64, *r.pic_gvaddr8.rex(0x8b, w=1),
            isap=is_pic)
 
+X86_64.enc(x86.elf_tls_ie, *r.tls_ie.rex(0x8b, w=1))
+X86_64.enc(x86.elf_tls_gd, *r.tls_gd(0xe8))
+
 #
 # Call/return
 #
 X86_32.enc(base.call, *r.call_id(0xe8))
 X86_64.enc(base.call, *r.call_id(0xe8), isap=Not(is_pic))
+X86_64.enc(base.call, *r.call_id(0xe8),
+           isap=is_pic, instp=IsColocatedFunc(Call.func_ref))
 X86_64.enc(base.call, *r.call_plt_id(0xe8), isap=is_pic)
 
+X86_32.enc(base.invoke, *r.invoke_id(0xe8))
+X86_64.enc(base.invoke, *r.invoke_id(0xe8), isap=Not(is_pic))
+X86_64.enc(base.invoke, *r.invoke_id(0xe8),
+           isap=is_pic, instp=IsColocatedFunc(Invoke.func_ref))
+X86_64.enc(base.invoke, *r.invoke_plt_id(0xe8), isap=is_pic)
+
 X86_32.enc(base.call_indirect.i32, *r.call_r(0xff, rrr=2))
 X86_64.enc(base.call_indirect.i64, *r.call_r.rex(0xff, rrr=2))
 X86_64.enc(base.call_indirect.i64, *r.call_r(0xff, rrr=2))
 
+# Tail calls jump to the callee with `jmp rel32` or `jmp *%r11`.
+X86_64.enc(base.return_call, *r.call_id(0xe9), isap=Not(is_pic))
+X86_64.enc(base.return_call, *r.call_id(0xe9),
+           isap=is_pic, instp=IsColocatedFunc(Call.func_ref))
+X86_64.enc(base.return_call, *r.call_plt_id(0xe9), isap=is_pic)
+X86_64.enc(base.return_call_indirect.i64, *r.tailcall_r.rex(0xff, rrr=4))
+
 X86_32.enc(base.x_return, *r.ret(0xc3))
 X86_64.enc(base.x_return, *r.ret(0xc3))
 
@@ -325,6 +449,12 @@ X86_64.enc(base.x_return, *r.ret(0xc3))
 enc_both(base.jump, r.jmpb, 0xeb)
 enc_both(base.jump, r.jmpd, 0xe9)
 
+# Jump tables are only supported in 64-bit mode where they can be addressed
+# relative to %rip.
+X86_64.enc(base.jump_table_base.i64, *r.jt_base.rex(0x8d, w=1))
+X86_64.enc(base.indirect_jump_table_br.i64, *r.indirect_jmp.rex(0xff, rrr=4))
+X86_64.enc(base.indirect_jump_table_br.i64, *r.indirect_jmp(0xff, rrr=4))
+
 enc_both(base.brif, r.brib, 0x70)
 enc_both(base.brif, r.brid, 0x0f, 0x80)
 
@@ -351,6 +481,28 @@ enc_both(base.brz.b1, r.t8jccd_abcd, 0x84)
 enc_both(base.brnz.b1, r.t8jccb_abcd, 0x75)
 enc_both(base.brnz.b1, r.t8jccd_abcd, 0x85)
 
+#
+# Atomic memory operations.
+#
+
+enc_i32_i64_ld_st(base.atomic_load, True, r.ald, 0x8b)
+enc_i32_i64_ld_st(base.atomic_store, True, r.ast, 0x89)
+enc_i32_i64_ld_st(base.atomic_store, True, r.ast_mfence, 0x89)
+
+# lock cmpxchg
+enc_i32_i64_ld_st(base.atomic_cas, True, r.acas, 0x0f, 0xb1)
+
+# lock xadd. Other operations are legalized into cmpxchg loops.
+enc_i32_i64_ld_st(base.atomic_rmw, True, r.axadd, 0x0f, 0xc1)
+enc_i32_i64_ld_st(base.atomic_rmw, True, r.axchg, 0x87)
+
+X86_32.enc(base.fence, *r.mfence(0x0f, 0xae))
+X86_64.enc(base.fence, *r.mfence(0x0f, 0xae))
+X86_32.enc(base.fence, r.null_fence, 0)
+X86_64.enc(base.fence, r.null_fence, 0)
+X86_32.enc(base.barrier, r.null_barrier, 0)
+X86_64.enc(base.barrier, r.null_barrier, 0)
+
 #
 # Trap as ud2
 #
@@ -502,6 +654,12 @@ X86_64.enc(x86.cvtt2si.i64.f64, *r.rfurm.rex(0xf2, 0x0f, 0x2c, w=1))
 enc_both(base.sqrt.f32, r.furm, 0xf3, 0x0f, 0x51)
 enc_both(base.sqrt.f64, r.furm, 0xf2, 0x0f, 0x51)
 
+# Fused multiply-add: vfmadd231ss and vfmadd231sd.
+X86_32.enc(base.fma.f32, r.fma3, r.vex_bits(0x66, 0x0f, 0x38, 0xb9))
+X86_64.enc(base.fma.f32, r.fma3, r.vex_bits(0x66, 0x0f, 0x38, 0xb9))
+X86_32.enc(base.fma.f64, r.fma3, r.vex_bits(0x66, 0x0f, 0x38, 0xb9, w=1))
+X86_64.enc(base.fma.f64, r.fma3, r.vex_bits(0x66, 0x0f, 0x38, 0xb9, w=1))
+
 # Rounding. The recipe looks at the opcode to pick an immediate.
 for inst in [
         base.nearest,
@@ -544,3 +702,179 @@ enc_both(base.fcmp.f64, r.fcscc, 0x66, 0x0f, 0x2e)
 
 enc_both(base.ffcmp.f32, r.fcmp, 0x0f, 0x2e)
 enc_both(base.ffcmp.f64, r.fcmp, 0x66, 0x0f, 0x2e)
+
+
+#
+# SIMD vectors.
+#
+# The 128-bit vector types live in the XMM registers. The SSE2 instructions are
+# always available, the instructions from later SSE extensions are gated on
+# the ISA settings. The `intel_simd` legalizations provide SSE2 fallbacks.
+#
+
+for ty in vectors:
+    # movaps
+    enc_vec(base.copy.bind(ty), r.furm, 0x0f, 0x28)
+    enc_vec(base.regmove.bind(ty), r.frmov, 0x0f, 0x28)
+
+    # movups, so the stack slots don't need to be 16-byte aligned.
+    enc_vec(base.spill.bind(ty), r.fspillSib32, 0x0f, 0x11)
+    enc_vec(base.regspill.bind(ty), r.fregspill32, 0x0f, 0x11)
+    enc_vec(base.fill.bind(ty), r.ffillSib32, 0x0f, 0x10)
+    enc_vec(base.regfill.bind(ty), r.fregfill32, 0x0f, 0x10)
+
+    # Bitwise operations don't depend on the lane type.
+    enc_vec(base.band.bind(ty), r.fa, 0x66, 0x0f, 0xdb)
+    enc_vec(base.bor.bind(ty), r.fa, 0x66, 0x0f, 0xeb)
+    enc_vec(base.bxor.bind(ty), r.fa, 0x66, 0x0f, 0xef)
+    # The `pandn(x,y)` instruction computes `~x&y`, while band_not(x,y)` is
+    # `x&~y.
+    enc_vec(base.band_not.bind(ty), r.fax, 0x66, 0x0f, 0xdf)
+
+    # pshufd
+    enc_vec(x86.pshufd.bind(ty), r.furmi, 0x66, 0x0f, 0x70)
+
+    # Conversions between vector types don't change any bits.
+    for from_ty in vectors:
+        X86_32.enc(base.raw_bitcast.bind(ty).bind(from_ty), r.null_fpr, 0)
+        X86_64.enc(base.raw_bitcast.bind(ty).bind(from_ty), r.null_fpr, 0)
+        if from_ty == ty or from_ty in bool_vectors or ty in bool_vectors:
+            continue
+        X86_32.enc(base.bitcast.bind(ty).bind(from_ty), r.null_fpr, 0)
+        X86_64.enc(base.bitcast.bind(ty).bind(from_ty), r.null_fpr, 0)
+
+# movaps for aligned accesses, movups for the rest. The legalizer sets the
+# `aligned` flag or splits the accesses according to the
+# `unaligned_vector_access` setting.
+for ty in int_vectors + float_vectors:
+    for recipe in [r.fld, r.fldDisp8, r.fldDisp32]:
+        enc_vec(base.load.bind(ty).any, recipe, 0x0f, 0x28,
+                instp=IsAligned(Load.flags))
+        enc_vec(base.load.bind(ty).any, recipe, 0x0f, 0x10)
+    for recipe in [r.fst, r.fstDisp8, r.fstDisp32]:
+        enc_vec(base.store.bind(ty).any, recipe, 0x0f, 0x29,
+                instp=IsAligned(Store.flags))
+        enc_vec(base.store.bind(ty).any, recipe, 0x0f, 0x11)
+
+# Integer arithmetic.
+for ty,     add,  sub,  eq,                 gt,                 isap in [
+        (i8x16, 0xfc, 0xf8, (0x74,),            (0x64,),            None),
+        (i16x8, 0xfd, 0xf9, (0x75,),            (0x65,),            None),
+        (i32x4, 0xfe, 0xfa, (0x76,),            (0x66,),            None),
+        (i64x2, 0xd4, 0xfb, (0x38, 0x29),       (0x38, 0x37),       True)]:
+    enc_vec(base.iadd.bind(ty), r.fa, 0x66, 0x0f, add)
+    enc_vec(base.isub.bind(ty), r.fa, 0x66, 0x0f, sub)
+
+    # pcmpeq and pcmpgt. The `pcmpeqq` instruction is from SSE 4.1 and
+    # `pcmpgtq` is from SSE 4.2.
+    enc_vec(
+        base.icmp.bind(ty), r.icscc_fpr, 0x66, 0x0f, *eq,
+        instp=IsEqual(IntCompare.cond, intcc.eq),
+        isap=cfg.use_sse41 if isap else None)
+    enc_vec(
+        base.icmp.bind(ty), r.icscc_fpr, 0x66, 0x0f, *gt,
+        instp=IsEqual(IntCompare.cond, intcc.sgt),
+        isap=cfg.use_sse42 if isap else None)
+
+# pmullw
+enc_vec(base.imul.bind(i16x8), r.fa, 0x66, 0x0f, 0xd5)
+# pmulld
+enc_vec(base.imul.bind(i32x4), r.fa, 0x66, 0x0f, 0x38, 0x40,
+        isap=cfg.use_sse41)
+
+# Shifts by an immediate: psll*, psrl* and psra*.
+for ty,         opc in [
+        (i16x8, 0x71),
+        (i32x4, 0x72),
+        (i64x2, 0x73)]:
+    enc_vec(base.ishl_imm.bind(ty), r.fib, 0x66, 0x0f, opc, rrr=6)
+    enc_vec(base.ushr_imm.bind(ty), r.fib, 0x66, 0x0f, opc, rrr=2)
+    if ty != i64x2:
+        enc_vec(base.sshr_imm.bind(ty), r.fib, 0x66, 0x0f, opc, rrr=4)
+
+# pshufb
+enc_vec(x86.pshufb.bind(i8x16), r.fa, 0x66, 0x0f, 0x38, 0x00,
+        isap=cfg.use_ssse3)
+
+# pblendvb, blendvps, and blendvpd. The lanes of a boolean vector are all ones
+# or all zeros, so any blend with lanes at most as wide as the vector lanes
+# selects the same bits.
+for ty,         opc in [
+        (i8x16, 0x10),
+        (i16x8, 0x10),
+        (i32x4, 0x14),
+        (f32x4, 0x14),
+        (i64x2, 0x15),
+        (f64x2, 0x15)]:
+    enc_vec(base.vselect.bind(ty), r.fblendv, 0x66, 0x0f, 0x38, opc,
+            isap=cfg.use_sse41)
+
+# Floating point arithmetic. The `ps` and `pd` forms are distinguished by a
+# 0x66 prefix.
+for ty, pfx in [(f32x4, ()), (f64x2, (0x66,))]:
+    for inst,           opc in [
+            (base.fadd, 0x58),
+            (base.fsub, 0x5c),
+            (base.fmul, 0x59),
+            (base.fdiv, 0x5e),
+            (x86.fmin,  0x5d),
+            (x86.fmax,  0x5f)]:
+        enc_vec(inst.bind(ty), r.fa, *(pfx + (0x0f, opc)))
+
+    enc_vec(base.sqrt.bind(ty), r.furm, *(pfx + (0x0f, 0x51)))
+
+    # cmpps and cmppd. The remaining condition codes are legalized.
+    enc_vec(base.fcmp.bind(ty), r.fcscc_fpr, *(pfx + (0x0f, 0xc2)))
+
+# Moving scalars into vectors. A scalar float is already in an XMM register.
+for ty in [i8x16, i16x8, i32x4]:
+    # movd
+    enc_vec(x86.scalar_to_vector.bind(ty), r.frurm, 0x66, 0x0f, 0x6e)
+# movq
+X86_64.enc(x86.scalar_to_vector.bind(i64x2),
+           *r.frurm.rex(0x66, 0x0f, 0x6e, w=1))
+for ty in float_vectors:
+    X86_32.enc(x86.scalar_to_vector.bind(ty), r.null_fpr, 0)
+    X86_64.enc(x86.scalar_to_vector.bind(ty), r.null_fpr, 0)
+
+# Lane extraction. Lane 0 can always be moved out with movd or movq, and the
+# SSE 4.1 pextr* instructions handle the other lanes. The 16-bit lanes have
+# the SSE2 pextrw instruction.
+for ty in [i8x16, i16x8, i32x4]:
+    enc_vec(base.extractlane.bind(ty), r.rfumr_lane0, 0x66, 0x0f, 0x7e)
+X86_64.enc(base.extractlane.bind(i64x2),
+           *r.rfumr_lane0.rex(0x66, 0x0f, 0x7e, w=1))
+for ty in float_vectors:
+    X86_32.enc(base.extractlane.bind(ty), r.null_lane0, 0)
+    X86_64.enc(base.extractlane.bind(ty), r.null_lane0, 0)
+
+# pextrb and pextrd
+enc_vec(base.extractlane.bind(i8x16), r.rfumri, 0x66, 0x0f, 0x3a, 0x14,
+        isap=cfg.use_sse41)
+enc_vec(base.extractlane.bind(i32x4), r.rfumri, 0x66, 0x0f, 0x3a, 0x16,
+        isap=cfg.use_sse41)
+# pextrq
+X86_64.enc(base.extractlane.bind(i64x2),
+           *r.rfumri.rex(0x66, 0x0f, 0x3a, 0x16, w=1), isap=cfg.use_sse41)
+# pextrw
+enc_vec(base.extractlane.bind(i16x8), r.rfurmi, 0x66, 0x0f, 0xc5)
+
+# Lane insertion. Only the 16-bit lanes have an SSE2 pinsrw instruction, the
+# rest need SSE 4.1.
+enc_vec(base.insertlane.bind(i16x8), r.frinsi, 0x66, 0x0f, 0xc4)
+# pinsrb and pinsrd
+enc_vec(base.insertlane.bind(i8x16), r.frinsi, 0x66, 0x0f, 0x3a, 0x20,
+        isap=cfg.use_sse41)
+enc_vec(base.insertlane.bind(i32x4), r.frinsi, 0x66, 0x0f, 0x3a, 0x22,
+        isap=cfg.use_sse41)
+# pinsrq
+X86_64.enc(base.insertlane.bind(i64x2),
+           *r.frinsi.rex(0x66, 0x0f, 0x3a, 0x22, w=1), isap=cfg.use_sse41)
+# insertps
+enc_vec(base.insertlane.bind(f32x4), r.finsi, 0x66, 0x0f, 0x3a, 0x21,
+        isap=cfg.use_sse41)
+# movsd and movlhps
+enc_vec(base.insertlane.bind(f64x2), r.fins, 0xf2, 0x0f, 0x10,
+        instp=IsEqual(InsertLane.lane, 0))
+enc_vec(base.insertlane.bind(f64x2), r.fins, 0x0f, 0x16,
+        instp=IsEqual(InsertLane.lane, 1))
@@ -5,8 +5,10 @@ This module defines additional instructions that are useful only to the Intel
 target ISA.
 """
 
-from base.types import iflags
-from cdsl.operands import Operand
+from base.types import i64, iflags
+from base.immediates import uimm8
+from base import entities
+from cdsl.operands import Operand, VARIABLE_ARGS
 from cdsl.typevar import TypeVar
 from cdsl.instructions import Instruction, InstructionGroup
 
@@ -170,4 +172,77 @@ bsf = Instruction(
     """,
     ins=x, outs=(y, rflags))
 
+TxN = TypeVar(
+        'TxN', 'A SIMD vector type',
+        ints=True, floats=True, bools=True, scalars=False, simd=True)
+IxN = TypeVar(
+        'IxN', 'A SIMD vector of integers',
+        ints=True, scalars=False, simd=True)
+
+x = Operand('x', TxN.lane_of())
+a = Operand('a', TxN)
+
+scalar_to_vector = Instruction(
+    'x86_scalar_to_vector', r"""
+    Move the scalar `x` into lane 0 of a vector.
+
+    The other lanes of the result are undefined.
+    """,
+    ins=x, outs=a)
+
+x = Operand('x', TxN)
+Order = Operand('Order', uimm8, doc='Lane selectors')
+
+pshufd = Instruction(
+    'x86_pshufd', r"""
+    Shuffle the 32-bit lanes of `x`.
+
+    Lane `i` of the result is the 32-bit lane of `x` selected by bits
+    `2i+1:2i` of `Order`. Vectors with other lane sizes are shuffled as if
+    they were `i32x4` vectors.
+    """,
+    ins=(x, Order), outs=a)
+
+x = Operand('x', IxN)
+y = Operand('y', IxN, doc='Byte selectors')
+a = Operand('a', IxN)
+
+pshufb = Instruction(
+    'x86_pshufb', r"""
+    Shuffle the bytes of `x`.
+
+    Byte `i` of the result is the byte of `x` selected by the low 4 bits of
+    byte `i` of `y`, or zero if the high bit of byte `i` of `y` is set.
+    Vectors with other lane sizes are shuffled as if they were `i8x16`
+    vectors.
+    """,
+    ins=(x, y), outs=a)
+
+GV = Operand('GV', entities.global_var)
+addr = Operand('addr', i64)
+
+elf_tls_ie = Instruction(
+    'x86_elf_tls_ie', r"""
+    Compute the address of the thread-local variable GV using the ELF
+    initial-exec TLS model.
+
+    The offset of GV from the thread pointer is loaded from the GOT and added
+    to the thread pointer in `%fs:0`.
+    """,
+    ins=GV, outs=addr, can_load=True)
+
+FN = Operand('FN', entities.func_ref, doc='the `__tls_get_addr` function')
+rvals = Operand('rvals', VARIABLE_ARGS, doc='return values')
+
+elf_tls_gd = Instruction(
+    'x86_elf_tls_gd', r"""
+    Compute the address of the thread-local variable GV using the ELF
+    general-dynamic TLS model.
+
+    This calls FN, which must be `__tls_get_addr` with a signature returning
+    the address. The TLS descriptor of GV is passed to FN in a way the linker
+    recognizes, so it can relax the call to a cheaper access model.
+    """,
+    ins=(FN, GV), outs=rvals, is_call=True)
+
 GROUP.close()
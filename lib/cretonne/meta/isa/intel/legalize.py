@@ -98,6 +98,9 @@ intel_expand.custom_legalize(insts.fcvt_from_uint, 'expand_fcvt_from_uint')
 intel_expand.custom_legalize(insts.fcvt_to_sint, 'expand_fcvt_to_sint')
 intel_expand.custom_legalize(insts.fcvt_to_uint, 'expand_fcvt_to_uint')
 
+# The TLS access sequence depends on the access model of the global variable.
+intel_expand.custom_legalize(insts.tls_addr, 'expand_tls_addr')
+
 # Count leading and trailing zeroes, for baseline x86_64
 c_minus_one = Var('c_minus_one')
 c_thirty_one = Var('c_thirty_one')
@@ -225,3 +228,51 @@ intel_expand.legalize(
         lv15 << insts.imul(lv14, lc01),
         lv16 << insts.ushr_imm(lv15, imm64(24))
     ))
+
+
+intel_simd = XFormGroup(
+        'intel_simd',
+        """
+        Legalize 128-bit vector instructions.
+
+        Use SSE2 instruction sequences when the wider SSE extensions are not
+        available.
+        """,
+        isa=ISA, chain=shared.expand_flags)
+
+# Vector splat, lane extraction and insertion, and integer comparisons depend
+# on the lane type and the available SSE extensions.
+intel_simd.custom_legalize(insts.splat, 'expand_splat')
+intel_simd.custom_legalize(insts.extractlane, 'expand_extractlane')
+intel_simd.custom_legalize(insts.insertlane, 'expand_insertlane')
+intel_simd.custom_legalize(insts.icmp, 'expand_vector_icmp')
+intel_simd.custom_legalize(insts.vselect, 'expand_vselect')
+
+# The `cmpps` and `cmppd` instructions support the floating point condition
+# codes in `supported_vector_floatccs`. Reverse the operands or combine two
+# comparisons for the others.
+for cc,               rev_cc in [
+        (floatcc.gt,  floatcc.lt),
+        (floatcc.ge,  floatcc.le),
+        (floatcc.ult, floatcc.ugt),
+        (floatcc.ule, floatcc.uge)]:
+    intel_simd.legalize(
+            a << insts.fcmp(cc, x, y),
+            Rtl(
+                a << insts.fcmp(rev_cc, y, x)
+            ))
+
+intel_simd.legalize(
+        a << insts.fcmp(floatcc.one, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.ord, x, y),
+            a2 << insts.fcmp(floatcc.ne, x, y),
+            a << insts.band(a1, a2)
+        ))
+intel_simd.legalize(
+        a << insts.fcmp(floatcc.ueq, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.uno, x, y),
+            a2 << insts.fcmp(floatcc.eq, x, y),
+            a << insts.bor(a1, a2)
+        ))
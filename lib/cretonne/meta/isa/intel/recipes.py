@@ -3,20 +3,25 @@ Intel Encoding recipes.
 """
 from __future__ import absolute_import
 from cdsl.isa import EncRecipe
-from cdsl.predicates import IsSignedInt, IsEqual, Or
-from cdsl.registers import RegClass
+from cdsl.predicates import IsSignedInt, IsEqual, Or, Not
+from cdsl.registers import RegClass, Stack
 from base.formats import Unary, UnaryImm, Binary, BinaryImm, MultiAry, NullAry
-from base.formats import Trap, Call, IndirectCall, Store, Load
+from base.formats import Trap, Call, IndirectCall, CallGlobalVar, Store, Load
+from base.formats import Invoke
 from base.formats import IntCompare, FloatCompare, IntCond, FloatCond
 from base.formats import IntSelect, IntCondTrap, FloatCondTrap
 from base.formats import Jump, Branch, BranchInt, BranchFloat
-from base.formats import Ternary, FuncAddr, UnaryGlobalVar
+from base.formats import Ternary, FuncAddr, UnaryGlobalVar, UnaryConst
 from base.formats import RegMove, RegSpill, RegFill, CopySpecial
+from base.formats import InsertLane, ExtractLane
+from base.formats import AtomicLoad, AtomicStore, AtomicCas, AtomicRmw, Fence
+from base.formats import Barrier, StackLoad, BranchTable, BranchTableBase
+from base.immediates import atomic_ordering, atomic_rmw_op
 from .registers import GPR, ABCD, FPR, GPR_DEREF_SAFE, GPR_ZERO_DEREF_SAFE
 from .registers import GPR8, FPR8, GPR8_DEREF_SAFE, GPR8_ZERO_DEREF_SAFE, FLAG
 from .registers import StackGPR32, StackFPR32
-from .defs import supported_floatccs
-from .settings import use_sse41
+from .defs import supported_floatccs, supported_vector_floatccs
+from .settings import use_sse41, use_fma, use_bmi2
 
 try:
     from typing import Tuple, Dict, Sequence, Any  # noqa
@@ -91,6 +96,18 @@ def decode_ops(ops, rrr=0, w=0):
     return (name, op | (mmpp << 8) | (rrr << 12) | (w << 15))
 
 
+def vex_bits(*ops, **kwargs):
+    # type: (*int, **int) -> int
+    """
+    Compute the encoding bits for an instruction with a VEX prefix.
+
+    The mandatory prefix and the opcode map are folded into the VEX prefix,
+    using the same pp+mm bits as the legacy encodings.
+    """
+    name, bits = decode_ops(ops, **kwargs)
+    return bits
+
+
 def replace_put_op(emit, prefix):
     # type: (str, str) -> str
     """
@@ -114,8 +131,18 @@ NOREX_MAP = {
 
 def map_regs_norex(regs):
     # type: (Sequence[OperandConstraint]) -> Sequence[OperandConstraint]
-    return tuple(NOREX_MAP.get(rc, rc) if isinstance(rc, RegClass) else rc
-                 for rc in regs)
+    return tuple(map_reg_norex(rc) for rc in regs)
+
+
+def map_reg_norex(rc):
+    # type: (OperandConstraint) -> OperandConstraint
+    if isinstance(rc, RegClass):
+        return NOREX_MAP.get(rc, rc)
+    # The register class of a stack operand is used for the register named by
+    # a `regfill` instruction.
+    if isinstance(rc, Stack):
+        return Stack(NOREX_MAP.get(rc.regclass, rc.regclass))
+    return rc
 
 
 class TailRecipe:
@@ -261,23 +288,36 @@ class TailRecipe:
                 assert name == obj.name, "Mismatched TailRecipe name: " + name
 
 
-def floatccs(iform):
-    # type: (InstructionFormat) -> PredNode
+def floatccs(iform, ccs=supported_floatccs):
+    # type: (InstructionFormat, Sequence[Any]) -> PredNode
     """
     Return an instruction predicate that checks in `iform.cond` is one of the
     directly supported floating point condition codes.
     """
-    return Or(*(IsEqual(iform.cond, cc) for cc in supported_floatccs))
+    return Or(*(IsEqual(iform.cond, cc) for cc in ccs))
 
 
 # A null unary instruction that takes a GPR register. Can be used for identity
 # copies and no-op conversions.
 null = EncRecipe('null', Unary, size=0, ins=GPR, outs=0, emit='')
 
+# Same as null, but for FPR registers. Used for no-op conversions between
+# vector types.
+null_fpr = EncRecipe('null_fpr', Unary, size=0, ins=FPR, outs=0, emit='')
+
+# Extract lane 0 of a vector as a scalar in the same FPR register.
+null_lane0 = EncRecipe(
+        'null_lane0', ExtractLane, size=0, ins=FPR, outs=0,
+        instp=IsEqual(ExtractLane.lane, 0),
+        emit='')
+
 # XX opcode, no ModR/M.
 trap = TailRecipe(
         'trap', Trap, size=0, ins=(), outs=(),
-        emit='PUT_OP(bits, BASE_REX, sink);')
+        emit='''
+        sink.trap(code, func.srclocs[inst]);
+        PUT_OP(bits, BASE_REX, sink);
+        ''')
 
 # Macro: conditional jump over a ud2.
 trapif = EncRecipe(
@@ -288,6 +328,7 @@ trapif = EncRecipe(
         sink.put1(0x70 | (icc2opc(cond.inverse()) as u8));
         sink.put1(2);
         // ud2.
+        sink.trap(code, func.srclocs[inst]);
         sink.put1(0x0f);
         sink.put1(0x0b);
         ''')
@@ -301,6 +342,7 @@ trapff = EncRecipe(
         sink.put1(0x70 | (fcc2opc(cond.inverse()) as u8));
         sink.put1(2);
         // ud2.
+        sink.trap(code, func.srclocs[inst]);
         sink.put1(0x0f);
         sink.put1(0x0b);
         ''')
@@ -322,6 +364,48 @@ rrx = TailRecipe(
         modrm_rr(in_reg1, in_reg0, sink);
         ''')
 
+# XX /r, producing the CPU flags as a second result.
+rout = TailRecipe(
+        'rout', Binary, size=1, ins=(GPR, GPR), outs=(0, FLAG.eflags),
+        emit='''
+        PUT_OP(bits, rex2(in_reg0, in_reg1), sink);
+        modrm_rr(in_reg0, in_reg1, sink);
+        ''')
+
+# XX /r with operands swapped (RM form), producing the CPU flags.
+rrxout = TailRecipe(
+        'rrxout', Binary, size=1, ins=(GPR, GPR), outs=(0, FLAG.eflags),
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        ''')
+
+# VEX.LIG XX /r with three FPR ins, the third tied to the output. This is the
+# `231` form of the FMA3 instructions: out = in0 * in1 + in2.
+#
+# The VEX prefix can address all 16 registers, so there is no REX variant.
+# Use `vex_bits()` to compute the encoding bits.
+fma3 = EncRecipe(
+        'fma3', Ternary, size=5, ins=(FPR, FPR, FPR), outs=2,
+        isap=use_fma,
+        emit='''
+        put_vex3(bits, in_reg1, in_reg2, in_reg0, sink);
+        modrm_rr(in_reg1, in_reg2, sink);
+        ''')
+
+# VEX.LZ XX /r with the shift amount in the vvvv field. This is the form of
+# the BMI2 shifts that take the amount in any register and leave the CPU flags
+# alone: out = in0 shifted by in1.
+#
+# Use `vex_bits()` to compute the encoding bits.
+shx = EncRecipe(
+        'shx', Binary, size=5, ins=(GPR, GPR), outs=GPR,
+        clobbers_flags=False, isap=use_bmi2,
+        emit='''
+        put_vex3(bits, in_reg0, out_reg0, in_reg1, sink);
+        modrm_rr(in_reg0, out_reg0, sink);
+        ''')
+
 # XX /r with FPR ins and outs. A form.
 fa = TailRecipe(
         'fa', Binary, size=1, ins=(FPR, FPR), outs=0,
@@ -330,6 +414,17 @@ fa = TailRecipe(
         modrm_rr(in_reg1, in_reg0, sink);
         ''')
 
+# XX /r with the mask in %xmm0 and the output tied to the third input. This is
+# the form of the SSE 4.1 variable blends: out = in0 ? in1 : in2, using the
+# sign bit of each mask lane.
+fblendv = TailRecipe(
+        'fblendv', Ternary, size=1, ins=(FPR.xmm0, FPR, FPR), outs=2,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg2), sink);
+        modrm_rr(in_reg1, in_reg2, sink);
+        ''')
+
 # XX /r with FPR ins and outs. A form with input operands swapped.
 fax = TailRecipe(
         'fax', Binary, size=1, ins=(FPR, FPR), outs=1,
@@ -418,6 +513,89 @@ furmi_rnd = TailRecipe(
         });
         ''')
 
+# XX /r ib, RMI form with the `lane` field as immediate, FPR -> FPR.
+furmi = TailRecipe(
+        'furmi', ExtractLane, size=2, ins=FPR, outs=FPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
+        modrm_rr(in_reg0, out_reg0, sink);
+        sink.put1(lane);
+        ''')
+
+# XX /r ib, RMI form with the `lane` field as immediate, FPR -> GPR.
+rfurmi = TailRecipe(
+        'rfurmi', ExtractLane, size=2, ins=FPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
+        modrm_rr(in_reg0, out_reg0, sink);
+        sink.put1(lane);
+        ''')
+
+# XX /r ib, MRI form with the `lane` field as immediate, FPR -> GPR.
+rfumri = TailRecipe(
+        'rfumri', ExtractLane, size=2, ins=FPR, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(out_reg0, in_reg0), sink);
+        modrm_rr(out_reg0, in_reg0, sink);
+        sink.put1(lane);
+        ''')
+
+# XX /r, MR form extracting lane 0, FPR -> GPR.
+rfumr_lane0 = TailRecipe(
+        'rfumr_lane0', ExtractLane, size=1, ins=FPR, outs=GPR,
+        instp=IsEqual(ExtractLane.lane, 0),
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(out_reg0, in_reg0), sink);
+        modrm_rr(out_reg0, in_reg0, sink);
+        ''')
+
+# XX /r ib, RMI form inserting a GPR lane into an FPR vector.
+frinsi = TailRecipe(
+        'frinsi', InsertLane, size=2, ins=(FPR, GPR), outs=0,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        sink.put1(lane);
+        ''')
+
+# XX /r ib, RMI form inserting an FPR lane into an FPR vector. The immediate
+# is the destination lane in bits 4-5, like `insertps` expects.
+finsi = TailRecipe(
+        'finsi', InsertLane, size=2, ins=(FPR, FPR), outs=0,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        sink.put1(lane << 4);
+        ''')
+
+# XX /r, RM form inserting an FPR lane into an FPR vector. The lane is implied
+# by the opcode.
+fins = TailRecipe(
+        'fins', InsertLane, size=1, ins=(FPR, FPR), outs=0,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        ''')
+
+# XX /n ib, vector shift by an immediate which is masked to the lane size.
+fib = TailRecipe(
+        'fib', BinaryImm, size=2, ins=FPR, outs=0,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex1(in_reg0), sink);
+        modrm_r_bits(in_reg0, bits, sink);
+        let imm: i64 = imm.into();
+        let mask = func.dfg.ctrl_typevar(inst).lane_bits() - 1;
+        sink.put1(imm as u8 & mask);
+        ''')
+
 # XX /r, for regmove instructions.
 rmov = TailRecipe(
         'rmov', RegMove, size=1, ins=GPR, outs=(),
@@ -449,6 +627,7 @@ div = TailRecipe(
         'div', Ternary, size=1,
         ins=(GPR.rax, GPR.rdx, GPR), outs=(GPR.rax, GPR.rdx),
         emit='''
+        sink.trap(TrapCode::IntegerDivisionByZero, func.srclocs[inst]);
         PUT_OP(bits, rex1(in_reg2), sink);
         modrm_r_bits(in_reg2, bits, sink);
         ''')
@@ -562,9 +741,11 @@ fnaddr4 = TailRecipe(
         'fnaddr4', FuncAddr, size=4, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelAbs4,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
         sink.put4(0);
         ''')
 
@@ -573,9 +754,11 @@ fnaddr8 = TailRecipe(
         'fnaddr8', FuncAddr, size=8, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelAbs8,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
         sink.put8(0);
         ''')
 
@@ -584,9 +767,11 @@ allones_fnaddr4 = TailRecipe(
         'allones_fnaddr4', FuncAddr, size=4, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelAbs4,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
         // Write the immediate as `!0` for the benefit of BaldrMonkey.
         sink.put4(!0);
         ''')
@@ -596,13 +781,32 @@ allones_fnaddr8 = TailRecipe(
         'allones_fnaddr8', FuncAddr, size=8, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelAbs8,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
         // Write the immediate as `!0` for the benefit of BaldrMonkey.
         sink.put8(!0);
         ''')
 
+# XX /r with a RIP-relative PCRel4 function relocation. Used with `lea` to
+# compute the address of a colocated function.
+pcrel_fnaddr8 = TailRecipe(
+        'pcrel_fnaddr8', FuncAddr, size=5, ins=(), outs=GPR,
+        emit='''
+        PUT_OP(bits, rex2(0, out_reg0), sink);
+        modrm_riprel(out_reg0, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::IntelPCRel4,
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
+        sink.put4(0);
+        ''')
+
+# XX /r with a RIP-relative GOTPCRel4 function relocation. Used with `mov` to
+# load the address of a function from the GOT.
 got_fnaddr8 = TailRecipe(
         'got_fnaddr8', FuncAddr, size=5, ins=(), outs=GPR,
         # rex2 gets passed 0 for r/m register because the upper bit of
@@ -610,23 +814,80 @@ got_fnaddr8 = TailRecipe(
         emit='''
         PUT_OP(bits, rex2(0, out_reg0), sink);
         modrm_riprel(out_reg0, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         // The addend adjusts for the difference between the end of the
         // instruction and the beginning of the immediate field.
         sink.reloc_external(Reloc::IntelGOTPCRel4,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            -4);
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
         sink.put4(0);
         ''')
 
+# XX /r lea of a stack slot with SIB and 32-bit displacement.
+spaddr_id = TailRecipe(
+        'spaddr_id', StackLoad, size=6, ins=(), outs=GPR,
+        emit='''
+        let sp = StackRef::sp(stack_slot, &func.stack_slots);
+        let base = stk_base(sp.base);
+        PUT_OP(bits, rex2(base, out_reg0), sink);
+        modrm_sib_disp32(out_reg0, sink);
+        sib_noindex(base, sink);
+        let offset: i32 = offset.into();
+        sink.put4(sp.offset.checked_add(offset).unwrap() as u32);
+        ''')
+
+# XX /r load from a RIP-relative constant pool entry. The constant pool is
+# emitted after the code, so the displacement is known once the branches have
+# been relaxed.
+const_ld = TailRecipe(
+        'const_ld', UnaryConst, size=5, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(0, out_reg0), sink);
+        modrm_riprel(out_reg0, sink);
+        // The displacement is relative to the end of the instruction.
+        let disp = func.constant_offsets[constant] - (sink.offset() + 4);
+        sink.put4(disp);
+        ''')
+
+# XX /r float load from a RIP-relative constant pool entry.
+const_fld = TailRecipe(
+        'const_fld', UnaryConst, size=5, ins=(), outs=FPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(0, out_reg0), sink);
+        modrm_riprel(out_reg0, sink);
+        // The displacement is relative to the end of the instruction.
+        let disp = func.constant_offsets[constant] - (sink.offset() + 4);
+        sink.put4(disp);
+        ''')
+
+
+# XX /r lea of a RIP-relative jump table. The jump tables are emitted after
+# the code, before the constant pool.
+jt_base = TailRecipe(
+        'jt_base', BranchTableBase, size=5, ins=(), outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(0, out_reg0), sink);
+        modrm_riprel(out_reg0, sink);
+        // The displacement is relative to the end of the instruction.
+        let disp = func.jump_table_offsets[table] - (sink.offset() + 4);
+        sink.put4(disp);
+        ''')
+
 
 # XX+rd id with Abs4 globalsym relocation.
 gvaddr4 = TailRecipe(
         'gvaddr4', UnaryGlobalVar, size=4, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let gv = &func.global_vars[global_var];
         sink.reloc_external(Reloc::IntelAbs4,
-                            &func.global_vars[global_var].symbol_name(),
-                            0);
+                            gv.symbol_name(),
+                            0,
+                            SymbolInfo::global_var(gv));
         sink.put4(0);
         ''')
 
@@ -635,23 +896,59 @@ gvaddr8 = TailRecipe(
         'gvaddr8', UnaryGlobalVar, size=8, ins=(), outs=GPR,
         emit='''
         PUT_OP(bits | (out_reg0 & 7), rex1(out_reg0), sink);
+        let gv = &func.global_vars[global_var];
         sink.reloc_external(Reloc::IntelAbs8,
-                            &func.global_vars[global_var].symbol_name(),
-                            0);
+                            gv.symbol_name(),
+                            0,
+                            SymbolInfo::global_var(gv));
         sink.put8(0);
         ''')
 
-# XX+rd iq with Abs8 globalsym relocation.
-got_gvaddr8 = TailRecipe(
-        'got_gvaddr8', UnaryGlobalVar, size=5, ins=(), outs=GPR,
+# RIP-relative address of a globalsym. Global variables aren't visible to
+# encoding predicates, so the choice is made when emitting: A colocated symbol
+# is addressed directly with `lea`, other symbols have their address loaded
+# from the GOT with `mov`. The two forms only differ in the opcode byte.
+pic_gvaddr8 = TailRecipe(
+        'pic_gvaddr8', UnaryGlobalVar, size=5, ins=(), outs=GPR,
         emit='''
+        let (bits, reloc) = match func.global_vars[global_var] {
+            GlobalVarData::Sym { colocated: true, .. } => {
+                ((bits & !0xff) | 0x8d, Reloc::IntelPCRel4)
+            }
+            _ => (bits, Reloc::IntelGOTPCRel4),
+        };
         PUT_OP(bits, rex2(0, out_reg0), sink);
-        modrm_rm(5, out_reg0, sink);
+        modrm_riprel(out_reg0, sink);
+        let gv = &func.global_vars[global_var];
         // The addend adjusts for the difference between the end of the
         // instruction and the beginning of the immediate field.
-        sink.reloc_external(Reloc::IntelGOTPCRel4,
-                            &func.global_vars[global_var].symbol_name(),
-                            -4);
+        sink.reloc_external(reloc,
+                            gv.symbol_name(),
+                            -4,
+                            SymbolInfo::global_var(gv));
+        sink.put4(0);
+        ''')
+
+# Initial-exec TLS address: Load the offset from the thread pointer from the
+# GOT, and add the thread pointer.
+tls_ie = TailRecipe(
+        'tls_ie', UnaryGlobalVar, size=14, ins=(), outs=GPR,
+        emit='''
+        // movq GV@GOTTPOFF(%rip), %out_reg0
+        PUT_OP(bits, rex2(0, out_reg0), sink);
+        modrm_riprel(out_reg0, sink);
+        let gv = &func.global_vars[global_var];
+        sink.reloc_external(Reloc::IntelGOTTPOff4,
+                            gv.symbol_name(),
+                            -4,
+                            SymbolInfo::global_var(gv));
+        sink.put4(0);
+        // addq %fs:0, %out_reg0
+        sink.put1(0x64);
+        rex_prefix(bits, rex2(0, out_reg0), sink);
+        sink.put1(0x03);
+        modrm_rm(0b100, out_reg0, sink);
+        sink.put1(0x25);
         sink.put4(0);
         ''')
 
@@ -666,6 +963,9 @@ st = TailRecipe(
         instp=IsEqual(Store.offset, 0),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_rm(in_reg1, in_reg0, sink);
         ''')
@@ -678,6 +978,9 @@ st_abcd = TailRecipe(
         when_prefixed=st,
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_rm(in_reg1, in_reg0, sink);
         ''')
@@ -688,6 +991,9 @@ fst = TailRecipe(
         instp=IsEqual(Store.offset, 0),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_rm(in_reg1, in_reg0, sink);
         ''')
@@ -698,6 +1004,9 @@ stDisp8 = TailRecipe(
         instp=IsSignedInt(Store.offset, 8),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp8(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -709,6 +1018,9 @@ stDisp8_abcd = TailRecipe(
         when_prefixed=stDisp8,
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp8(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -719,6 +1031,9 @@ fstDisp8 = TailRecipe(
         instp=IsSignedInt(Store.offset, 8),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp8(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -730,6 +1045,9 @@ stDisp32 = TailRecipe(
         'stDisp32', Store, size=5, ins=(GPR, GPR_DEREF_SAFE), outs=(),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp32(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -740,6 +1058,9 @@ stDisp32_abcd = TailRecipe(
         when_prefixed=stDisp32,
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp32(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -749,6 +1070,9 @@ fstDisp32 = TailRecipe(
         'fstDisp32', Store, size=5, ins=(FPR, GPR_DEREF_SAFE), outs=(),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
         modrm_disp32(in_reg1, in_reg0, sink);
         let offset: i32 = offset.into();
@@ -815,6 +1139,9 @@ ld = TailRecipe(
         instp=IsEqual(Load.offset, 0),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_rm(in_reg0, out_reg0, sink);
         ''')
@@ -825,6 +1152,9 @@ fld = TailRecipe(
         instp=IsEqual(Load.offset, 0),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_rm(in_reg0, out_reg0, sink);
         ''')
@@ -835,6 +1165,9 @@ ldDisp8 = TailRecipe(
         instp=IsSignedInt(Load.offset, 8),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_disp8(in_reg0, out_reg0, sink);
         let offset: i32 = offset.into();
@@ -847,6 +1180,9 @@ fldDisp8 = TailRecipe(
         instp=IsSignedInt(Load.offset, 8),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_disp8(in_reg0, out_reg0, sink);
         let offset: i32 = offset.into();
@@ -859,6 +1195,9 @@ ldDisp32 = TailRecipe(
         instp=IsSignedInt(Load.offset, 32),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_disp32(in_reg0, out_reg0, sink);
         let offset: i32 = offset.into();
@@ -871,6 +1210,9 @@ fldDisp32 = TailRecipe(
         instp=IsSignedInt(Load.offset, 32),
         clobbers_flags=False,
         emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
         PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
         modrm_disp32(in_reg0, out_reg0, sink);
         let offset: i32 = offset.into();
@@ -927,6 +1269,119 @@ fregfill32 = TailRecipe(
         sink.put4(src.offset as u32);
         ''')
 
+#
+# Atomic memory operations.
+#
+# Intel processors don't reorder loads with other loads or stores with other
+# stores, so plain moves implement the acquire loads and release stores. Only
+# the sequentially consistent stores and fences need an `mfence`. The locked
+# instructions are full barriers.
+#
+
+# XX /r atomic load with no offset.
+ald = TailRecipe(
+        'ald', AtomicLoad, size=1, ins=GPR_ZERO_DEREF_SAFE, outs=GPR,
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        PUT_OP(bits, rex2(in_reg0, out_reg0), sink);
+        modrm_rm(in_reg0, out_reg0, sink);
+        ''')
+
+# XX /r atomic store with no offset.
+ast = TailRecipe(
+        'ast', AtomicStore, size=1, ins=(GPR, GPR_ZERO_DEREF_SAFE), outs=(),
+        instp=Not(IsEqual(AtomicStore.ordering, atomic_ordering.seq_cst)),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rm(in_reg1, in_reg0, sink);
+        ''')
+
+# XX /r atomic store followed by an `mfence`.
+ast_mfence = TailRecipe(
+        'ast_mfence', AtomicStore, size=4, ins=(GPR, GPR_ZERO_DEREF_SAFE),
+        outs=(),
+        instp=IsEqual(AtomicStore.ordering, atomic_ordering.seq_cst),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rm(in_reg1, in_reg0, sink);
+        sink.put1(0x0f);
+        sink.put1(0xae);
+        sink.put1(0xf0);
+        ''')
+
+# lock XX /r compare-and-swap with the expected value and the result in %rax.
+acas = TailRecipe(
+        'acas', AtomicCas, size=2,
+        ins=(GPR_ZERO_DEREF_SAFE, GPR.rax, GPR), outs=GPR.rax,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        sink.put1(0xf0);
+        PUT_OP(bits, rex2(in_reg0, in_reg2), sink);
+        modrm_rm(in_reg0, in_reg2, sink);
+        ''')
+
+# lock XX /r exchange-and-add, returning the old value in the input register.
+axadd = TailRecipe(
+        'axadd', AtomicRmw, size=2, ins=(GPR_ZERO_DEREF_SAFE, GPR), outs=1,
+        instp=IsEqual(AtomicRmw.op, atomic_rmw_op.add),
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        sink.put1(0xf0);
+        PUT_OP(bits, rex2(in_reg0, in_reg1), sink);
+        modrm_rm(in_reg0, in_reg1, sink);
+        ''')
+
+# XX /r exchange with memory. This is always locked.
+axchg = TailRecipe(
+        'axchg', AtomicRmw, size=1, ins=(GPR_ZERO_DEREF_SAFE, GPR), outs=1,
+        instp=IsEqual(AtomicRmw.op, atomic_rmw_op.xchg),
+        clobbers_flags=False,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        PUT_OP(bits, rex2(in_reg0, in_reg1), sink);
+        modrm_rm(in_reg0, in_reg1, sink);
+        ''')
+
+# 0F AE F0 mfence for sequentially consistent fences.
+mfence = TailRecipe(
+        'mfence', Fence, size=1, ins=(), outs=(),
+        instp=IsEqual(Fence.ordering, atomic_ordering.seq_cst),
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, BASE_REX, sink);
+        sink.put1(0xf0);
+        ''')
+
+# The weaker fences only constrain the compiler.
+null_fence = EncRecipe(
+        'null_fence', Fence, size=0, ins=(), outs=(),
+        instp=Not(IsEqual(Fence.ordering, atomic_ordering.seq_cst)),
+        clobbers_flags=False,
+        emit='')
+
+# Compiler barriers don't generate any code.
+null_barrier = EncRecipe(
+        'null_barrier', Barrier, size=0, ins=(), outs=(),
+        clobbers_flags=False,
+        emit='')
+
 #
 # Call/return
 #
@@ -934,9 +1389,11 @@ call_id = TailRecipe(
         'call_id', Call, size=4, ins=(), outs=(),
         emit='''
         PUT_OP(bits, BASE_REX, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelPCRel4,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
         sink.put4(0);
         ''')
 
@@ -944,9 +1401,67 @@ call_plt_id = TailRecipe(
         'call_plt_id', Call, size=4, ins=(), outs=(),
         emit='''
         PUT_OP(bits, BASE_REX, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::IntelPLTRel4,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            -4);
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
+        sink.put4(0);
+        ''')
+
+# Calls with a landing pad are encoded like plain calls. The landing pad only
+# appears in the call site table.
+invoke_id = TailRecipe(
+        'invoke_id', Invoke, size=4, ins=(), outs=(),
+        emit='''
+        PUT_OP(bits, BASE_REX, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::IntelPCRel4,
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
+        sink.put4(0);
+        ''')
+
+invoke_plt_id = TailRecipe(
+        'invoke_plt_id', Invoke, size=4, ins=(), outs=(),
+        emit='''
+        PUT_OP(bits, BASE_REX, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::IntelPLTRel4,
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
+        sink.put4(0);
+        ''')
+
+# General-dynamic TLS address: The canonical code sequence passing the TLS
+# descriptor to `__tls_get_addr`. The padding prefixes make room for the
+# linker to relax the sequence to the initial-exec or local-exec models.
+tls_gd = TailRecipe(
+        'tls_gd', CallGlobalVar, size=15, ins=(), outs=(),
+        emit='''
+        // data16 leaq GV@tlsgd(%rip), %rdi
+        sink.put1(0x66);
+        sink.put1(0x48);
+        sink.put1(0x8d);
+        modrm_riprel(RU::rdi as RegUnit, sink);
+        let gv = &func.global_vars[global_var];
+        sink.reloc_external(Reloc::IntelTLSGD4,
+                            gv.symbol_name(),
+                            -4,
+                            SymbolInfo::global_var(gv));
+        sink.put4(0);
+        // data16 data16 rex.W call __tls_get_addr@PLT
+        sink.put1(0x66);
+        sink.put1(0x66);
+        sink.put1(0x48);
+        PUT_OP(bits, BASE_REX, sink);
+        let callee = &func.dfg.ext_funcs[func_ref];
+        sink.reloc_external(Reloc::IntelPLTRel4,
+                            &callee.name,
+                            -4,
+                            SymbolInfo::function(callee));
         sink.put4(0);
         ''')
 
@@ -957,6 +1472,25 @@ call_r = TailRecipe(
         modrm_r_bits(in_reg0, bits, sink);
         ''')
 
+# Indirect jump to one of the EBBs of a jump table.
+indirect_jmp = TailRecipe(
+        'indirect_jmp', BranchTable, size=1, ins=GPR, outs=(),
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex1(in_reg0), sink);
+        modrm_r_bits(in_reg0, bits, sink);
+        ''')
+
+# Indirect tail call. The callee address can't be in a callee-saved register
+# since the epilogue restores those, and it can't be in an argument register,
+# so use %r11 which is neither.
+tailcall_r = TailRecipe(
+        'tailcall_r', IndirectCall, size=1, ins=GPR.r11, outs=(),
+        emit='''
+        PUT_OP(bits, rex1(RU::r11.into()), sink);
+        modrm_r_bits(RU::r11.into(), bits, sink);
+        ''')
+
 ret = TailRecipe(
         'ret', MultiAry, size=0, ins=(), outs=(),
         emit='''
@@ -1291,6 +1825,8 @@ icscc = TailRecipe(
             UnsignedGreaterThanOrEqual => 0x93,
             UnsignedGreaterThan => 0x97,
             UnsignedLessThanOrEqual => 0x96,
+            Overflow => 0x90,
+            NotOverflow => 0x91,
         };
         sink.put1(0x0f);
         sink.put1(setcc);
@@ -1343,4 +1879,40 @@ fcscc = TailRecipe(
         modrm_rr(out_reg0, 0, sink);
         ''')
 
+
+# Vector comparisons.
+#
+# The `pcmpeq` and `pcmpgt` instructions write a boolean vector over their
+# first operand.
+icscc_fpr = TailRecipe(
+        'icscc_fpr', IntCompare, size=1, ins=(FPR, FPR), outs=0,
+        clobbers_flags=False,
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        ''')
+
+# The `cmpps` and `cmppd` instructions take the condition code as an
+# immediate. Only the condition codes in `supported_vector_floatccs` are
+# available.
+fcscc_fpr = TailRecipe(
+        'fcscc_fpr', FloatCompare, size=2, ins=(FPR, FPR), outs=0,
+        clobbers_flags=False,
+        instp=floatccs(FloatCompare, supported_vector_floatccs),
+        emit='''
+        PUT_OP(bits, rex2(in_reg1, in_reg0), sink);
+        modrm_rr(in_reg1, in_reg0, sink);
+        sink.put1(match cond {
+            FloatCC::Equal => 0,
+            FloatCC::LessThan => 1,
+            FloatCC::LessThanOrEqual => 2,
+            FloatCC::Unordered => 3,
+            FloatCC::NotEqual => 4,
+            FloatCC::UnorderedOrGreaterThanOrEqual => 5,
+            FloatCC::UnorderedOrGreaterThan => 6,
+            FloatCC::Ordered => 7,
+            _ => panic!("{} not supported by fcscc_fpr", cond),
+        });
+        ''')
+
 TailRecipe.check_names(globals())
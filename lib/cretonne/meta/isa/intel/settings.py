@@ -18,6 +18,7 @@ has_sse41 = BoolSetting("SSE4.1: CPUID.01H:ECX.SSE4_1[bit 19]")
 has_sse42 = BoolSetting("SSE4.2: CPUID.01H:ECX.SSE4_2[bit 20]")
 has_popcnt = BoolSetting("POPCNT: CPUID.01H:ECX.POPCNT[bit 23]")
 has_avx = BoolSetting("AVX: CPUID.01H:ECX.AVX[bit 28]")
+has_fma = BoolSetting("FMA3: CPUID.01H:ECX.FMA[bit 12]")
 
 # CPUID.(EAX=07H, ECX=0H):EBX
 has_bmi1 = BoolSetting("BMI1: CPUID.(EAX=07H, ECX=0H):EBX.BMI1[bit 3]")
@@ -29,17 +30,39 @@ has_lzcnt = BoolSetting("LZCNT: CPUID.EAX=80000001H:ECX.LZCNT[bit 5]")
 
 # The use_* settings here are used to determine if a feature can be used.
 
+use_ssse3 = And(has_ssse3)
 use_sse41 = And(has_sse41)
 use_sse42 = And(has_sse42, use_sse41)
 use_popcnt = And(has_popcnt, has_sse42)
 use_bmi1 = And(has_bmi1)
+use_bmi2 = And(has_bmi2)
 use_lzcnt = And(has_lzcnt)
+use_fma = And(has_fma, has_avx)
+
+# Workarounds for CPU errata and performance hazards. They don't change the
+# meaning of the generated code, so they can be enabled for a fleet of mixed
+# CPUs. The presets below enable the workarounds for the affected CPUs.
+
+avoid_jcc_erratum = BoolSetting(
+        """
+        Skylake JCC erratum: keep jumps from crossing or ending at a 32-byte
+        boundary.
+
+        On Intel CPUs from Skylake to Cascade Lake, a microcode update
+        disables the decoded instruction cache for jumps, calls, and returns
+        that cross or end at a 32-byte boundary, which can make tight loops
+        much slower. This workaround inserts padding before EBBs so the jumps
+        are placed away from the boundaries, assuming the function itself is
+        32-byte aligned.
+        """)
 
 # Presets corresponding to Intel CPUs.
 
 baseline = Preset()
 nehalem = Preset(
         has_sse3, has_ssse3, has_sse41, has_sse42, has_popcnt)
-haswell = Preset(nehalem, has_bmi1, has_lzcnt)
+haswell = Preset(
+        nehalem, has_bmi1, has_bmi2, has_lzcnt, has_avx, has_fma)
+skylake = Preset(haswell, avoid_jcc_erratum)
 
 ISA.settings.close(globals())
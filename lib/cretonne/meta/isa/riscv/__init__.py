@@ -4,7 +4,7 @@ RISC-V Target
 
 `RISC-V <https://riscv.org/>`_ is an open instruction set architecture
 originally developed at UC Berkeley. It is a RISC-style ISA with either a
-32-bit (RV32I) or 64-bit (RV32I) base instruction set and a number of optional
+32-bit (RV32I) or 64-bit (RV64I) base instruction set and a number of optional
 extensions:
 
 RV32M / RV64M
@@ -23,6 +23,10 @@ RV32G / RV64G
     General purpose instruction sets. This represents the union of the I, M, A,
     F, and D instruction sets listed above.
 
+RV32C / RV64C
+    Compressed 16-bit encodings of the most common instructions. These are used
+    when the `is_compressed` setting is enabled.
+
 """
 from __future__ import absolute_import
 from . import defs
@@ -6,8 +6,9 @@ Commonly used definitions.
 from __future__ import absolute_import
 from cdsl.isa import TargetISA, CPUMode
 import base.instructions
+from . import instructions as rv
 
-ISA = TargetISA('riscv', [base.instructions.GROUP])
+ISA = TargetISA('riscv', [base.instructions.GROUP, rv.GROUP])
 
 # CPU modes for 32-bit and 64-bit operation.
 RV32 = CPUMode('RV32', ISA)
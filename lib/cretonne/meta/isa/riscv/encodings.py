@@ -3,30 +3,44 @@ RISC-V Encodings.
 """
 from __future__ import absolute_import
 from base import instructions as base
-from base.immediates import intcc
+from base.immediates import intcc, floatcc
+from base.formats import BinaryImm, Load, Store
+from base.types import i32, i64, f32, f64, b1
+from cdsl.predicates import IsSignedInt, IsUnsignedInt, And
 from .defs import RV32, RV64
+from . import instructions as rv
 from .recipes import OPIMM, OPIMM32, OP, OP32, LUI, BRANCH, JALR, JAL
-from .recipes import LOAD, STORE
-from .recipes import R, Rshamt, Ricmp, Ii, Iz, Iicmp, Iret, Icall, Icopy
+from .recipes import LOAD, STORE, LOAD_FP, STORE_FP, MADD, OP_FP, OP_FP_RS2
+from .recipes import SYSTEM, C
+from .recipes import R, Rshamt, Ricmp, Ricmpz, Ii, Iz, Iicmp, Iret, Icall
+from .recipes import Icopy, Inot, Inotb, Iload, Ifload, Iadjsp, Itrap, S, Sf
 from .recipes import U, UJ, UJcall, SB, SBzero, GPsp, GPfi, Irmov
-from .settings import use_m
+from .recipes import GPrsp, GPrfi, FPsp, FPfi, FPrsp, FPrfi
+from .recipes import Rf, Rfcmp, Rfcopy, Rfrmov, Rfu, Rfx, Rxf, R4
+from .recipes import CRmov, CRrmov, CRadd, CRret, CRcall
+from .recipes import CIaddi, CIslli, CIli, CIlui, CIsp, CA, CBimm, CBbranch
+from .recipes import CJ, CL, CLf, CS, CSf, Ctrap, nonzero_imm
+from .settings import use_m, use_f, use_d
+from .legalize import riscv_expand
 from cdsl.ast import Var
 from base.legalize import narrow, expand
 
 RV32.legalize_monomorphic(expand)
 RV32.legalize_type(
         default=narrow,
-        i32=expand,
-        f32=expand,
-        f64=expand)
+        b1=expand,
+        i32=riscv_expand,
+        f32=riscv_expand,
+        f64=riscv_expand)
 
 RV64.legalize_monomorphic(expand)
 RV64.legalize_type(
         default=narrow,
-        i32=expand,
-        i64=expand,
-        f32=expand,
-        f64=expand)
+        b1=expand,
+        i32=riscv_expand,
+        i64=riscv_expand,
+        f32=riscv_expand,
+        f64=riscv_expand)
 
 # Dummies for instruction predicates.
 x = Var('x')
@@ -34,6 +48,9 @@ y = Var('y')
 dest = Var('dest')
 args = Var('args')
 
+# In RV64, 32-bit values are kept sign-extended in the 64-bit registers. The
+# 64-bit instructions that preserve this can be used for 32-bit operations.
+
 # Basic arithmetic binary instructions are encoded in an R-type instruction.
 for inst,           inst_imm,      f3,    f7 in [
         (base.iadd, base.iadd_imm, 0b000, 0b0000000),
@@ -53,8 +70,25 @@ for inst,           inst_imm,      f3,    f7 in [
 # 32-bit ops in RV64.
 RV64.enc(base.iadd.i32, R, OP32(0b000, 0b0000000))
 RV64.enc(base.isub.i32, R, OP32(0b000, 0b0100000))
-# There are no andiw/oriw/xoriw variations.
+# There are no andiw/oriw/xoriw variations, but the bitwise operations preserve
+# the sign extension.
 RV64.enc(base.iadd_imm.i32, Ii, OPIMM32(0b000))
+for inst,           inst_imm,      f3 in [
+        (base.bxor, base.bxor_imm, 0b100),
+        (base.bor,  base.bor_imm,  0b110),
+        (base.band, base.band_imm, 0b111)
+        ]:
+    RV64.enc(inst.i32, R, OP(f3, 0b0000000))
+    RV64.enc(inst_imm.i32, Ii, OPIMM(f3))
+    RV32.enc(inst.b1, R, OP(f3, 0b0000000))
+    RV64.enc(inst.b1, R, OP(f3, 0b0000000))
+
+# Bitwise not is `xori` with -1, or with 1 for booleans.
+RV32.enc(base.bnot.i32, Inot, OPIMM(0b100))
+RV64.enc(base.bnot.i64, Inot, OPIMM(0b100))
+RV64.enc(base.bnot.i32, Inot, OPIMM(0b100))
+RV32.enc(base.bnot.b1, Inotb, OPIMM(0b100))
+RV64.enc(base.bnot.b1, Inotb, OPIMM(0b100))
 
 # Use iadd_imm with %x0 to materialize constants.
 RV32.enc(base.iconst.i32, Iz, OPIMM(0b000))
@@ -91,6 +125,27 @@ RV64.enc(base.icmp_imm.i64(intcc.slt, x, y), Iicmp, OPIMM(0b010))
 RV32.enc(base.icmp_imm.i32(intcc.ult, x, y), Iicmp, OPIMM(0b011))
 RV64.enc(base.icmp_imm.i64(intcc.ult, x, y), Iicmp, OPIMM(0b011))
 
+# The sign extension of 32-bit values in RV64 preserves both the signed and the
+# unsigned order.
+RV64.enc(base.icmp.i32(intcc.slt, x, y), Ricmp, OP(0b010, 0b0000000))
+RV64.enc(base.icmp.i32(intcc.ult, x, y), Ricmp, OP(0b011, 0b0000000))
+RV64.enc(base.icmp_imm.i32(intcc.slt, x, y), Iicmp, OPIMM(0b010))
+RV64.enc(base.icmp_imm.i32(intcc.ult, x, y), Iicmp, OPIMM(0b011))
+
+# `sltu rd, x0, rs` tests for a non-zero value.
+RV32.enc(base.icmp_imm.i32(intcc.ne, x, y), Ricmpz, OP(0b011, 0b0000000))
+RV64.enc(base.icmp_imm.i64(intcc.ne, x, y), Ricmpz, OP(0b011, 0b0000000))
+RV64.enc(base.icmp_imm.i32(intcc.ne, x, y), Ricmpz, OP(0b011, 0b0000000))
+
+# Booleans are 0 or 1, so `bint` is a copy.
+RV32.enc(base.bint.i32.b1, Icopy, OPIMM(0b000))
+RV64.enc(base.bint.i64.b1, Icopy, OPIMM(0b000))
+RV64.enc(base.bint.i32.b1, Icopy, OPIMM(0b000))
+
+# Sign extension and reduction of 32-bit values in RV64 are `addiw`.
+RV64.enc(base.sextend.i64.i32, Icopy, OPIMM32(0b000))
+RV64.enc(base.ireduce.i32.i64, Icopy, OPIMM32(0b000))
+
 # Integer constants with the low 12 bits clear are materialized by lui.
 RV32.enc(base.iconst.i32, U, LUI())
 RV64.enc(base.iconst.i32, U, LUI())
@@ -102,6 +157,25 @@ RV32.enc(base.imul.i32, R, OP(0b000, 0b0000001), isap=use_m)
 RV64.enc(base.imul.i64, R, OP(0b000, 0b0000001), isap=use_m)
 RV64.enc(base.imul.i32, R, OP32(0b000, 0b0000001), isap=use_m)
 
+for inst,             f3 in [
+        (base.smulhi, 0b001),
+        (base.umulhi, 0b011)
+        ]:
+    RV32.enc(inst.i32, R, OP(f3, 0b0000001), isap=use_m)
+    RV64.enc(inst.i64, R, OP(f3, 0b0000001), isap=use_m)
+
+# The division instructions don't trap. They are used by the `sdiv`, `udiv`,
+# `srem`, and `urem` expansions which check the operands first.
+for inst,        f3 in [
+        (rv.div,  0b100),
+        (rv.divu, 0b101),
+        (rv.rem,  0b110),
+        (rv.remu, 0b111)
+        ]:
+    RV32.enc(inst.i32, R, OP(f3, 0b0000001), isap=use_m)
+    RV64.enc(inst.i64, R, OP(f3, 0b0000001), isap=use_m)
+    RV64.enc(inst.i32, R, OP32(f3, 0b0000001), isap=use_m)
+
 # Control flow.
 
 # Unconditional branches.
@@ -121,6 +195,7 @@ for cond,           f3 in [
         ]:
     RV32.enc(base.br_icmp.i32(cond, x, y, dest, args), SB, BRANCH(f3))
     RV64.enc(base.br_icmp.i64(cond, x, y, dest, args), SB, BRANCH(f3))
+    RV64.enc(base.br_icmp.i32(cond, x, y, dest, args), SB, BRANCH(f3))
 
 for inst,           f3 in [
         (base.brz,  0b000),
@@ -128,6 +203,7 @@ for inst,           f3 in [
         ]:
     RV32.enc(inst.i32, SBzero, BRANCH(f3))
     RV64.enc(inst.i64, SBzero, BRANCH(f3))
+    RV64.enc(inst.i32, SBzero, BRANCH(f3))
     RV32.enc(inst.b1, SBzero, BRANCH(f3))
     RV64.enc(inst.b1, SBzero, BRANCH(f3))
 
@@ -139,6 +215,40 @@ RV64.enc(base.x_return, Iret, JALR())
 RV32.enc(base.call_indirect.i32, Icall, JALR())
 RV64.enc(base.call_indirect.i64, Icall, JALR())
 
+# Traps.
+RV32.enc(base.trap, Itrap, SYSTEM(0b001))
+RV64.enc(base.trap, Itrap, SYSTEM(0b001))
+
+# Loads and stores.
+for inst,            f3 in [
+        (base.sload8,  0b000),
+        (base.sload16, 0b001),
+        (base.uload8,  0b100),
+        (base.uload16, 0b101)
+        ]:
+    RV32.enc(inst.i32.any, Iload, LOAD(f3))
+    RV64.enc(inst.i64.any, Iload, LOAD(f3))
+    RV64.enc(inst.i32.any, Iload, LOAD(f3))
+
+RV32.enc(base.load.i32.any, Iload, LOAD(0b010))
+RV64.enc(base.load.i32.any, Iload, LOAD(0b010))
+RV64.enc(base.load.i64.any, Iload, LOAD(0b011))
+RV64.enc(base.sload32.i64, Iload, LOAD(0b010))
+RV64.enc(base.uload32.i64, Iload, LOAD(0b110))
+
+for inst,             f3 in [
+        (base.istore8,  0b000),
+        (base.istore16, 0b001)
+        ]:
+    RV32.enc(inst.i32.any, S, STORE(f3))
+    RV64.enc(inst.i64.any, S, STORE(f3))
+    RV64.enc(inst.i32.any, S, STORE(f3))
+
+RV32.enc(base.store.i32.any, S, STORE(0b010))
+RV64.enc(base.store.i32.any, S, STORE(0b010))
+RV64.enc(base.store.i64.any, S, STORE(0b011))
+RV64.enc(base.istore32.i64.any, S, STORE(0b010))
+
 # Spill and fill.
 RV32.enc(base.spill.i32, GPsp, STORE(0b010))
 RV64.enc(base.spill.i32, GPsp, STORE(0b010))
@@ -147,6 +257,26 @@ RV32.enc(base.fill.i32, GPfi, LOAD(0b010))
 RV64.enc(base.fill.i32, GPfi, LOAD(0b010))
 RV64.enc(base.fill.i64, GPfi, LOAD(0b011))
 
+RV32.enc(base.regspill.i32, GPrsp, STORE(0b010))
+RV64.enc(base.regspill.i32, GPrsp, STORE(0b010))
+RV64.enc(base.regspill.i64, GPrsp, STORE(0b011))
+RV32.enc(base.regfill.i32, GPrfi, LOAD(0b010))
+RV64.enc(base.regfill.i32, GPrfi, LOAD(0b010))
+RV64.enc(base.regfill.i64, GPrfi, LOAD(0b011))
+
+RV32.enc(base.spill.b1, GPsp, STORE(0b010))
+RV64.enc(base.spill.b1, GPsp, STORE(0b010))
+RV32.enc(base.fill.b1, GPfi, LOAD(0b010))
+RV64.enc(base.fill.b1, GPfi, LOAD(0b010))
+RV32.enc(base.regspill.b1, GPrsp, STORE(0b010))
+RV64.enc(base.regspill.b1, GPrsp, STORE(0b010))
+RV32.enc(base.regfill.b1, GPrfi, LOAD(0b010))
+RV64.enc(base.regfill.b1, GPrfi, LOAD(0b010))
+
+# Stack pointer adjustments in the prologue and epilogue.
+RV32.enc(base.adjust_sp_imm, Iadjsp, OPIMM(0b000))
+RV64.enc(base.adjust_sp_imm, Iadjsp, OPIMM(0b000))
+
 # Register copies.
 RV32.enc(base.copy.i32, Icopy, OPIMM(0b000))
 RV64.enc(base.copy.i64, Icopy, OPIMM(0b000))
@@ -160,3 +290,213 @@ RV32.enc(base.copy.b1, Icopy, OPIMM(0b000))
 RV64.enc(base.copy.b1, Icopy, OPIMM(0b000))
 RV32.enc(base.regmove.b1, Irmov, OPIMM(0b000))
 RV64.enc(base.regmove.b1, Irmov, OPIMM(0b000))
+
+#
+# "F" and "D" Standard Extensions for Single and Double-Precision Floating
+# Point. Gated by the `use_f` and `use_d` flags.
+#
+# The low bits of funct7 encode the format: 0 for single and 1 for double
+# precision.
+#
+for ty,  fmt, isap, fp_load, fp_store in [
+        (f32, 0,  use_f, LOAD_FP(0b010), STORE_FP(0b010)),
+        (f64, 1,  use_d, LOAD_FP(0b011), STORE_FP(0b011))
+        ]:
+    for mode in [RV32, RV64]:
+        # Arithmetic rounds to nearest, ties to even.
+        for inst,           f7 in [
+                (base.fadd, 0b0000000),
+                (base.fsub, 0b0000100),
+                (base.fmul, 0b0001000),
+                (base.fdiv, 0b0001100)
+                ]:
+            mode.enc(inst.bind(ty), Rf, OP_FP(0b000, f7 | fmt), isap=isap)
+        mode.enc(
+                base.sqrt.bind(ty), Rfu, OP_FP_RS2(0, 0b000, 0b0101100 | fmt),
+                isap=isap)
+        mode.enc(base.fma.bind(ty), R4, MADD(fmt), isap=isap)
+
+        # Sign injection.
+        mode.enc(
+                base.fcopysign.bind(ty), Rf, OP_FP(0b000, 0b0010000 | fmt),
+                isap=isap)
+        mode.enc(
+                base.fneg.bind(ty), Rfcopy, OP_FP(0b001, 0b0010000 | fmt),
+                isap=isap)
+        mode.enc(
+                base.fabs.bind(ty), Rfcopy, OP_FP(0b010, 0b0010000 | fmt),
+                isap=isap)
+        mode.enc(
+                base.copy.bind(ty), Rfcopy, OP_FP(0b000, 0b0010000 | fmt),
+                isap=isap)
+        mode.enc(
+                base.regmove.bind(ty), Rfrmov, OP_FP(0b000, 0b0010000 | fmt),
+                isap=isap)
+
+        # Comparisons.
+        for cond,          f3 in [
+                (floatcc.eq, 0b010),
+                (floatcc.lt, 0b001),
+                (floatcc.le, 0b000)
+                ]:
+            mode.enc(
+                    base.fcmp.bind(ty)(cond, x, y), Rfcmp,
+                    OP_FP(f3, 0b1010000 | fmt), isap=isap)
+
+        # Conversions from 32-bit integers. The `w` variants read the low 32
+        # bits of the register in RV64.
+        mode.enc(
+                base.fcvt_from_sint.bind(ty).i32, Rxf,
+                OP_FP_RS2(0, 0b000, 0b1101000 | fmt), isap=isap)
+        mode.enc(
+                base.fcvt_from_uint.bind(ty).i32, Rxf,
+                OP_FP_RS2(1, 0b000, 0b1101000 | fmt), isap=isap)
+
+        # Conversions to 32-bit integers round towards zero.
+        mode.enc(
+                rv.fcvt_to_sint.i32.bind(ty), Rfx,
+                OP_FP_RS2(0, 0b001, 0b1100000 | fmt), isap=isap)
+        mode.enc(
+                rv.fcvt_to_uint.i32.bind(ty), Rfx,
+                OP_FP_RS2(1, 0b001, 0b1100000 | fmt), isap=isap)
+
+        # Loads and stores.
+        mode.enc(base.load.bind(ty).any, Ifload, fp_load, isap=isap)
+        mode.enc(base.store.bind(ty).any, Sf, fp_store, isap=isap)
+        mode.enc(base.spill.bind(ty), FPsp, fp_store, isap=isap)
+        mode.enc(base.fill.bind(ty), FPfi, fp_load, isap=isap)
+        mode.enc(base.regspill.bind(ty), FPrsp, fp_store, isap=isap)
+        mode.enc(base.regfill.bind(ty), FPrfi, fp_load, isap=isap)
+
+    # Conversions involving 64-bit integers are only available in RV64.
+    RV64.enc(
+            base.fcvt_from_sint.bind(ty).i64, Rxf,
+            OP_FP_RS2(2, 0b000, 0b1101000 | fmt), isap=isap)
+    RV64.enc(
+            base.fcvt_from_uint.bind(ty).i64, Rxf,
+            OP_FP_RS2(3, 0b000, 0b1101000 | fmt), isap=isap)
+    RV64.enc(
+            rv.fcvt_to_sint.i64.bind(ty), Rfx,
+            OP_FP_RS2(2, 0b001, 0b1100000 | fmt), isap=isap)
+    RV64.enc(
+            rv.fcvt_to_uint.i64.bind(ty), Rfx,
+            OP_FP_RS2(3, 0b001, 0b1100000 | fmt), isap=isap)
+
+# Conversions between the formats.
+for mode in [RV32, RV64]:
+    mode.enc(
+            base.fpromote.f64.f32, Rfu, OP_FP_RS2(0, 0b000, 0b0100001),
+            isap=use_d)
+    mode.enc(
+            base.fdemote.f32.f64, Rfu, OP_FP_RS2(1, 0b000, 0b0100000),
+            isap=use_d)
+
+# Moves between integer and floating point registers.
+RV32.enc(base.bitcast.i32.f32, Rfx, OP_FP_RS2(0, 0b000, 0b1110000), isap=use_f)
+RV32.enc(base.bitcast.f32.i32, Rxf, OP_FP_RS2(0, 0b000, 0b1111000), isap=use_f)
+RV64.enc(base.bitcast.i32.f32, Rfx, OP_FP_RS2(0, 0b000, 0b1110000), isap=use_f)
+RV64.enc(base.bitcast.f32.i32, Rxf, OP_FP_RS2(0, 0b000, 0b1111000), isap=use_f)
+RV64.enc(base.bitcast.i64.f64, Rfx, OP_FP_RS2(0, 0b000, 0b1110001), isap=use_d)
+RV64.enc(base.bitcast.f64.i64, Rxf, OP_FP_RS2(0, 0b000, 0b1111001), isap=use_d)
+
+#
+# "C" Standard Extension for Compressed Instructions.
+#
+# The compressed recipes are gated by the `use_c` flag. These encodings must
+# come after the 32-bit encodings so the legalizer picks the unconstrained
+# 32-bit encodings before register allocation.
+#
+
+# Register-register operations.
+RV32.enc(base.iadd.i32, CRadd, C(0b10, 0b100, 1 << 12))
+RV64.enc(base.iadd.i64, CRadd, C(0b10, 0b100, 1 << 12))
+for inst,           f2 in [
+        (base.isub, 0b00),
+        (base.bxor, 0b01),
+        (base.bor,  0b10),
+        (base.band, 0b11)
+        ]:
+    RV32.enc(inst.i32, CA, C(0b01, 0b100, (0b011 << 10) | (f2 << 5)))
+    RV64.enc(inst.i64, CA, C(0b01, 0b100, (0b011 << 10) | (f2 << 5)))
+    if inst is not base.isub:
+        RV64.enc(inst.i32, CA, C(0b01, 0b100, (0b011 << 10) | (f2 << 5)))
+        RV32.enc(inst.b1, CA, C(0b01, 0b100, (0b011 << 10) | (f2 << 5)))
+        RV64.enc(inst.b1, CA, C(0b01, 0b100, (0b011 << 10) | (f2 << 5)))
+RV64.enc(base.isub.i32, CA, C(0b01, 0b100, (0b111 << 10) | (0b00 << 5)))
+RV64.enc(base.iadd.i32, CA, C(0b01, 0b100, (0b111 << 10) | (0b01 << 5)))
+
+# Register-immediate operations.
+RV32.enc(base.iadd_imm.i32, CIaddi, C(0b01, 0b000))
+RV64.enc(base.iadd_imm.i64, CIaddi, C(0b01, 0b000))
+RV64.enc(base.iadd_imm.i32, CIaddi, C(0b01, 0b001))
+RV32.enc(base.band_imm.i32, CBimm, C(0b01, 0b100, 0b10 << 10),
+         instp=IsSignedInt(BinaryImm.imm, 6))
+RV64.enc(base.band_imm.i64, CBimm, C(0b01, 0b100, 0b10 << 10),
+         instp=IsSignedInt(BinaryImm.imm, 6))
+RV64.enc(base.band_imm.i32, CBimm, C(0b01, 0b100, 0b10 << 10),
+         instp=IsSignedInt(BinaryImm.imm, 6))
+
+# Shifts by a non-zero amount.
+RV32.enc(base.ishl_imm.i32, CIslli, C(0b10, 0b000),
+         instp=IsUnsignedInt(BinaryImm.imm, 5))
+RV64.enc(base.ishl_imm.i64, CIslli, C(0b10, 0b000),
+         instp=IsUnsignedInt(BinaryImm.imm, 6))
+for inst,               f2 in [
+        (base.ushr_imm, 0b00),
+        (base.sshr_imm, 0b01)
+        ]:
+    RV32.enc(inst.i32, CBimm, C(0b01, 0b100, f2 << 10),
+             instp=And(IsUnsignedInt(BinaryImm.imm, 5), nonzero_imm))
+    RV64.enc(inst.i64, CBimm, C(0b01, 0b100, f2 << 10),
+             instp=And(IsUnsignedInt(BinaryImm.imm, 6), nonzero_imm))
+
+# Constants.
+for mode, ty in [(RV32, i32), (RV64, i64), (RV64, i32)]:
+    mode.enc(base.iconst.bind(ty), CIli, C(0b01, 0b010))
+    mode.enc(base.iconst.bind(ty), CIlui, C(0b01, 0b011))
+
+# Copies.
+for mode, ty in [
+        (RV32, i32), (RV64, i64), (RV64, i32), (RV32, b1), (RV64, b1)]:
+    mode.enc(base.copy.bind(ty), CRmov, C(0b10, 0b100))
+    mode.enc(base.regmove.bind(ty), CRrmov, C(0b10, 0b100))
+
+# Loads and stores. The offsets are scaled by the access size.
+for mode in [RV32, RV64]:
+    mode.enc(base.load.i32.any, CL, C(0b00, 0b010),
+             instp=IsUnsignedInt(Load.offset, 7, 2))
+    mode.enc(base.store.i32.any, CS, C(0b00, 0b110),
+             instp=IsUnsignedInt(Store.offset, 7, 2))
+    mode.enc(base.load.f64.any, CLf, C(0b00, 0b001), isap=use_d,
+             instp=IsUnsignedInt(Load.offset, 8, 3))
+    mode.enc(base.store.f64.any, CSf, C(0b00, 0b101), isap=use_d,
+             instp=IsUnsignedInt(Store.offset, 8, 3))
+RV64.enc(base.sload32.i64, CL, C(0b00, 0b010),
+         instp=IsUnsignedInt(Load.offset, 7, 2))
+RV64.enc(base.istore32.i64.any, CS, C(0b00, 0b110),
+         instp=IsUnsignedInt(Store.offset, 7, 2))
+RV64.enc(base.load.i64.any, CL, C(0b00, 0b011),
+         instp=IsUnsignedInt(Load.offset, 8, 3))
+RV64.enc(base.store.i64.any, CS, C(0b00, 0b111),
+         instp=IsUnsignedInt(Store.offset, 8, 3))
+
+# Control flow.
+for mode in [RV32, RV64]:
+    mode.enc(base.jump, CJ, C(0b01, 0b101))
+    mode.enc(base.x_return, CRret, C(0b10, 0b100))
+    mode.enc(base.trap, Ctrap, C(0b00, 0b000))
+    mode.enc(base.adjust_sp_imm, CIsp, C(0b01, 0b011))
+    for inst,       f3 in [
+            (base.brz,  0b110),
+            (base.brnz, 0b111)
+            ]:
+        mode.enc(inst.b1, CBbranch, C(0b01, f3))
+RV32.enc(base.call_indirect.i32, CRcall, C(0b10, 0b100, 1 << 12))
+RV64.enc(base.call_indirect.i64, CRcall, C(0b10, 0b100, 1 << 12))
+for inst,       f3 in [
+        (base.brz,  0b110),
+        (base.brnz, 0b111)
+        ]:
+    RV32.enc(inst.i32, CBbranch, C(0b01, f3))
+    RV64.enc(inst.i64, CBbranch, C(0b01, f3))
+    RV64.enc(inst.i32, CBbranch, C(0b01, f3))
@@ -0,0 +1,90 @@
+"""
+Supplementary instruction definitions for RISC-V.
+
+This module defines additional instructions that are useful only to the RISC-V
+target ISA.
+"""
+
+from cdsl.operands import Operand
+from cdsl.typevar import TypeVar
+from cdsl.instructions import Instruction, InstructionGroup
+
+
+GROUP = InstructionGroup("riscv", "RISC-V specific instruction set")
+
+iWord = TypeVar('iWord', 'A scalar integer machine word', ints=(32, 64))
+
+x = Operand('x', iWord, doc='Dividend')
+y = Operand('y', iWord, doc='Divisor')
+q = Operand('q', iWord, doc='Quotient')
+r = Operand('r', iWord, doc='Remainder')
+
+div = Instruction(
+        'rv_div', r"""
+        Signed integer division that doesn't trap.
+
+        This is the RISC-V ``div`` instruction which returns -1 when the
+        divisor is 0 and wraps around when the quotient overflows.
+        """,
+        ins=(x, y), outs=q)
+
+divu = Instruction(
+        'rv_divu', r"""
+        Unsigned integer division that doesn't trap.
+
+        This is the RISC-V ``divu`` instruction which returns the largest
+        unsigned value when the divisor is 0.
+        """,
+        ins=(x, y), outs=q)
+
+rem = Instruction(
+        'rv_rem', r"""
+        Signed integer remainder that doesn't trap.
+
+        This is the RISC-V ``rem`` instruction which returns the dividend when
+        the divisor is 0.
+        """,
+        ins=(x, y), outs=r)
+
+remu = Instruction(
+        'rv_remu', r"""
+        Unsigned integer remainder that doesn't trap.
+
+        This is the RISC-V ``remu`` instruction which returns the dividend
+        when the divisor is 0.
+        """,
+        ins=(x, y), outs=r)
+
+Float = TypeVar('Float', 'A scalar floating point number', floats=True)
+IntTo = TypeVar('IntTo', 'A scalar integer machine word', ints=(32, 64))
+
+x = Operand('x', Float)
+a = Operand('a', IntTo)
+
+fcvt_to_sint = Instruction(
+        'rv_fcvt_to_sint', r"""
+        Convert with truncation floating point to signed integer.
+
+        The source floating point operand is converted to a signed integer by
+        rounding towards zero. Values that can't be represented in the output
+        type saturate to the smallest or largest signed value, and NaN converts
+        to the largest signed value.
+
+        This instruction does not trap.
+        """,
+        ins=x, outs=a)
+
+fcvt_to_uint = Instruction(
+        'rv_fcvt_to_uint', r"""
+        Convert with truncation floating point to unsigned integer.
+
+        The source floating point operand is converted to an unsigned integer
+        by rounding towards zero. Values that can't be represented in the
+        output type saturate to 0 or the largest unsigned value, and NaN
+        converts to the largest unsigned value.
+
+        This instruction does not trap.
+        """,
+        ins=x, outs=a)
+
+GROUP.close()
@@ -0,0 +1,266 @@
+"""
+Custom legalization patterns for RISC-V.
+"""
+from __future__ import absolute_import
+from cdsl.ast import Var
+from cdsl.xform import Rtl, XFormGroup
+from base.immediates import imm64, intcc, floatcc
+from base import legalize as shared
+from base import instructions as insts
+from base.types import i32, i64
+from .defs import ISA
+
+riscv_expand = XFormGroup(
+        'riscv_expand',
+        """
+        Legalize instructions by expansion.
+
+        Use RISC-V specific instructions if needed.
+        """,
+        isa=ISA, chain=shared.expand)
+
+a = Var('a')
+x = Var('x')
+y = Var('y')
+a1 = Var('a1')
+a2 = Var('a2')
+a3 = Var('a3')
+
+# Integer constants that don't fit in a `lui` or an `addi` are built from
+# smaller constants.
+riscv_expand.custom_legalize(insts.iconst, 'expand_iconst')
+
+#
+# Division and remainder.
+#
+# The `div` and `rem` instructions don't trap, so the division by zero and
+# overflow checks are inserted explicitly.
+riscv_expand.custom_legalize(insts.sdiv, 'expand_sdivrem')
+riscv_expand.custom_legalize(insts.srem, 'expand_sdivrem')
+riscv_expand.custom_legalize(insts.udiv, 'expand_udivrem')
+riscv_expand.custom_legalize(insts.urem, 'expand_udivrem')
+
+# Conversions from float to int saturate instead of trapping.
+riscv_expand.custom_legalize(insts.fcvt_to_sint, 'expand_fcvt_to_sint')
+riscv_expand.custom_legalize(insts.fcvt_to_uint, 'expand_fcvt_to_uint')
+
+# Loads and stores can only encode a 12-bit signed offset. Other offsets are
+# added to the address first.
+for inst in [
+        insts.load, insts.uload8, insts.sload8, insts.uload16, insts.sload16,
+        insts.uload32, insts.sload32,
+        insts.store, insts.istore8, insts.istore16, insts.istore32]:
+    riscv_expand.custom_legalize(inst, 'expand_mem_offset')
+
+# Conditional branches can only test `eq`, `ne`, `slt`, `sge`, `ult`, and
+# `uge`. The other conditions are tested by swapping the operands.
+riscv_expand.custom_legalize(insts.br_icmp, 'expand_br_icmp')
+
+#
+# Integer comparisons.
+#
+# Only `slt` and `ult` have instructions. The other conditions swap the
+# operands or negate the result.
+for cc,         swapped in [
+        (intcc.sgt, intcc.slt),
+        (intcc.ugt, intcc.ult)]:
+    riscv_expand.legalize(
+            a << insts.icmp(cc, x, y),
+            Rtl(
+                a << insts.icmp(swapped, y, x)
+            ))
+
+for cc,         inverse in [
+        (intcc.sge, intcc.slt),
+        (intcc.uge, intcc.ult)]:
+    riscv_expand.legalize(
+            a << insts.icmp(cc, x, y),
+            Rtl(
+                a1 << insts.icmp(inverse, x, y),
+                a << insts.bnot(a1)
+            ))
+
+for cc,         inverse in [
+        (intcc.sle, intcc.slt),
+        (intcc.ule, intcc.ult)]:
+    riscv_expand.legalize(
+            a << insts.icmp(cc, x, y),
+            Rtl(
+                a1 << insts.icmp(inverse, y, x),
+                a << insts.bnot(a1)
+            ))
+
+# Equality is tested by comparing the difference of the operands with 0.
+riscv_expand.legalize(
+        a << insts.icmp(intcc.eq, x, y),
+        Rtl(
+            a1 << insts.bxor(x, y),
+            a << insts.icmp_imm(intcc.ult, a1, imm64(1))
+        ))
+riscv_expand.legalize(
+        a << insts.icmp(intcc.ne, x, y),
+        Rtl(
+            a1 << insts.bxor(x, y),
+            a << insts.icmp_imm(intcc.ne, a1, imm64(0))
+        ))
+riscv_expand.legalize(
+        a << insts.icmp_imm(intcc.eq, x, y),
+        Rtl(
+            a1 << insts.bxor_imm(x, y),
+            a << insts.icmp_imm(intcc.ult, a1, imm64(1))
+        ))
+riscv_expand.legalize(
+        a << insts.icmp_imm(intcc.ne, x, y),
+        Rtl(
+            a1 << insts.bxor_imm(x, y),
+            a << insts.icmp_imm(intcc.ne, a1, imm64(0))
+        ))
+
+#
+# Floating point comparisons.
+#
+# Only `eq`, `lt`, and `le` have instructions. They return false for unordered
+# operands, so the unordered conditions are the negated ordered conditions.
+for cc,           swapped in [
+        (floatcc.gt, floatcc.lt),
+        (floatcc.ge, floatcc.le)]:
+    riscv_expand.legalize(
+            a << insts.fcmp(cc, x, y),
+            Rtl(
+                a << insts.fcmp(swapped, y, x)
+            ))
+
+for cc,            inverse in [
+        (floatcc.uno, floatcc.ord),
+        (floatcc.ne,  floatcc.eq),
+        (floatcc.ueq, floatcc.one),
+        (floatcc.ult, floatcc.ge),
+        (floatcc.ule, floatcc.gt),
+        (floatcc.ugt, floatcc.le),
+        (floatcc.uge, floatcc.lt)]:
+    riscv_expand.legalize(
+            a << insts.fcmp(cc, x, y),
+            Rtl(
+                a1 << insts.fcmp(inverse, x, y),
+                a << insts.bnot(a1)
+            ))
+
+# A value is ordered when it compares equal to itself.
+riscv_expand.legalize(
+        a << insts.fcmp(floatcc.ord, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.eq, x, x),
+            a2 << insts.fcmp(floatcc.eq, y, y),
+            a << insts.band(a1, a2)
+        ))
+riscv_expand.legalize(
+        a << insts.fcmp(floatcc.one, x, y),
+        Rtl(
+            a1 << insts.fcmp(floatcc.lt, x, y),
+            a2 << insts.fcmp(floatcc.lt, y, x),
+            a << insts.bor(a1, a2)
+        ))
+
+#
+# Extensions.
+#
+# 32-bit values are kept sign-extended in 64-bit registers, so `sextend` and
+# `ireduce` are simple `addiw` instructions, but `uextend` needs to clear the
+# high bits.
+riscv_expand.legalize(
+        a << insts.uextend.i64.i32(x),
+        Rtl(
+            a1 << insts.sextend.i64(x),
+            a2 << insts.ishl_imm(a1, imm64(32)),
+            a << insts.ushr_imm(a2, imm64(32))
+        ))
+
+#
+# Bit manipulation.
+#
+# There are no rotate instructions. Combine two shifts instead. Only the low
+# bits of the shift amounts are used, so this also works for a zero amount.
+for inst,        fwd,         rev in [
+        (insts.rotl, insts.ishl, insts.ushr),
+        (insts.rotr, insts.ushr, insts.ishl)]:
+    riscv_expand.legalize(
+            a << inst(x, y),
+            Rtl(
+                a1 << fwd(x, y),
+                a2 << insts.irsub_imm(y, imm64(0)),
+                a3 << rev(x, a2),
+                a << insts.bor(a1, a3)
+            ))
+
+# Count the bits with the usual shift and mask sequence. The other counting
+# instructions are expressed in terms of `popcnt`.
+for ty, bits in [(i32, 32), (i64, 64)]:
+    m1 = Var('m1')
+    m2 = Var('m2')
+    m4 = Var('m4')
+    h01 = Var('h01')
+    v = [Var('v{}'.format(i)) for i in range(10)]
+    riscv_expand.legalize(
+        v[9] << insts.popcnt.bind(ty)(x),
+        Rtl(
+            m1 << insts.iconst(imm64(0x5555555555555555 >> (64 - bits))),
+            m2 << insts.iconst(imm64(0x3333333333333333 >> (64 - bits))),
+            m4 << insts.iconst(imm64(0x0f0f0f0f0f0f0f0f >> (64 - bits))),
+            h01 << insts.iconst(imm64(0x0101010101010101 >> (64 - bits))),
+            # Count bits in pairs.
+            v[0] << insts.ushr_imm(x, imm64(1)),
+            v[1] << insts.band(v[0], m1),
+            v[2] << insts.isub(x, v[1]),
+            # Sum adjacent pairs into nibbles.
+            v[3] << insts.band(v[2], m2),
+            v[4] << insts.ushr_imm(v[2], imm64(2)),
+            v[5] << insts.band(v[4], m2),
+            v[6] << insts.iadd(v[3], v[5]),
+            # Sum adjacent nibbles into bytes.
+            v[7] << insts.ushr_imm(v[6], imm64(4)),
+            v[8] << insts.iadd(v[6], v[7]),
+            a1 << insts.band(v[8], m4),
+            # Add up the bytes in the top byte.
+            a2 << insts.imul(a1, h01),
+            v[9] << insts.ushr_imm(a2, imm64(bits - 8))
+        ))
+
+    # Smear the leading one bit into all the lower bits, and count the zeros
+    # that are left.
+    s = [Var('s{}'.format(i)) for i in range(12)]
+    smear = []
+    last = x
+    shift = 1
+    i = 0
+    while shift < bits:
+        smear.append(s[i] << insts.ushr_imm(last, imm64(shift)))
+        smear.append(s[i + 1] << insts.bor(last, s[i]))
+        last = s[i + 1]
+        shift *= 2
+        i += 2
+    riscv_expand.legalize(
+        a << insts.clz.bind(ty)(x),
+        Rtl(*(smear + [
+            a1 << insts.bnot(last),
+            a << insts.popcnt(a1)
+        ])))
+
+    # The sign bits are the leading zeros of `x` XOR its sign, minus the sign
+    # bit itself.
+    riscv_expand.legalize(
+        a << insts.cls.bind(ty)(x),
+        Rtl(
+            a1 << insts.sshr_imm(x, imm64(bits - 1)),
+            a2 << insts.bxor(x, a1),
+            a3 << insts.clz(a2),
+            a << insts.iadd_imm(a3, imm64(-1))
+        ))
+
+# The trailing zeros of `x` are the ones in `!x & (x - 1)`.
+riscv_expand.legalize(
+        a << insts.ctz(x),
+        Rtl(
+            a1 << insts.iadd_imm(x, imm64(-1)),
+            a2 << insts.band_not(a1, x),
+            a << insts.popcnt(a2)
+        ))
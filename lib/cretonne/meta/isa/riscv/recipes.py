@@ -10,12 +10,14 @@ instruction formats described in the reference:
 """
 from __future__ import absolute_import
 from cdsl.isa import EncRecipe
-from cdsl.predicates import IsSignedInt
+from cdsl.predicates import IsSignedInt, IsEqual, And, Not
 from cdsl.registers import Stack
 from base.formats import Binary, BinaryImm, MultiAry, IntCompare, IntCompareImm
 from base.formats import Unary, UnaryImm, BranchIcmp, Branch, Jump
-from base.formats import Call, IndirectCall, RegMove
-from .registers import GPR
+from base.formats import Call, IndirectCall, RegMove, RegSpill, RegFill
+from base.formats import Load, Store, Ternary, FloatCompare, Trap
+from .registers import GPR, FPR, GPRC, FPRC
+from .settings import use_c
 
 # The low 7 bits of a RISC-V instruction is the base opcode. All 32-bit
 # instructions have 11 as the two low bits, with bits 6:2 determining the base
@@ -31,12 +33,53 @@ def LOAD(funct3):
     return 0b00000 | (funct3 << 5)
 
 
+def LOAD_FP(funct3):
+    # type: (int) -> int
+    assert funct3 <= 0b111
+    return 0b00001 | (funct3 << 5)
+
+
 def STORE(funct3):
     # type: (int) -> int
     assert funct3 <= 0b111
     return 0b01000 | (funct3 << 5)
 
 
+def STORE_FP(funct3):
+    # type: (int) -> int
+    assert funct3 <= 0b111
+    return 0b01001 | (funct3 << 5)
+
+
+def MADD(fmt, funct3=0):
+    # type: (int, int) -> int
+    assert fmt <= 0b11
+    assert funct3 <= 0b111
+    return 0b10000 | (funct3 << 5) | (fmt << 8)
+
+
+def OP_FP(funct3, funct7):
+    # type: (int, int) -> int
+    assert funct3 <= 0b111
+    assert funct7 <= 0b1111111
+    return 0b10100 | (funct3 << 5) | (funct7 << 8)
+
+
+def OP_FP_RS2(rs2, funct3, funct7):
+    # type: (int, int, int) -> int
+    """
+    Encoding bits for an OP-FP instruction where the rs2 field selects a
+    variant of the instruction instead of a register.
+
+    The recipes using these encoding bits imply the OP-FP opcode, so the
+    opcode bits hold the rs2 field instead.
+    """
+    assert rs2 <= 0b11111
+    assert funct3 <= 0b111
+    assert funct7 <= 0b1111111
+    return rs2 | (funct3 << 5) | (funct7 << 8)
+
+
 def BRANCH(funct3):
     # type: (int) -> int
     assert funct3 <= 0b111
@@ -90,6 +133,28 @@ def LUI():
     return 0b01101
 
 
+def SYSTEM(funct3=0):
+    # type: (int) -> int
+    assert funct3 <= 0b111
+    return 0b11100 | (funct3 << 5)
+
+
+# The 16-bit compressed instructions have 00, 01, or 10 as the two low bits,
+# and a funct3 field in bits 15:13.
+#
+# Encbits for the 16-bit recipes are the bits of the instruction with all the
+# operand fields cleared. The function below computes them from the two low
+# bits, funct3, and any other fixed bits in their final position.
+
+
+def C(op, funct3, fixed=0):
+    # type: (int, int, int) -> int
+    assert op <= 0b10
+    assert funct3 <= 0b111
+    assert fixed & 0xe003 == 0
+    return (funct3 << 13) | fixed | op
+
+
 # R-type 32-bit instructions: These are mostly binary arithmetic instructions.
 # The encbits are `opcode[6:2] | (funct3 << 5) | (funct7 << 8)
 R = EncRecipe(
@@ -123,6 +188,58 @@ Iicmp = EncRecipe(
         instp=IsSignedInt(IntCompareImm.imm, 12),
         emit='put_i(bits, in_reg0, imm.into(), out_reg0, sink);')
 
+# R-type encoding of a comparison with 0, using %x0 as rs1.
+Ricmpz = EncRecipe(
+        'Ricmpz', IntCompareImm, size=4, ins=GPR, outs=GPR,
+        instp=IsEqual(IntCompareImm.imm, 0),
+        emit='put_r(bits, 0, in_reg0, out_reg0, sink);')
+
+# I-type encoding of `bnot` as an `xori` with -1.
+Inot = EncRecipe(
+        'Inot', Unary, size=4, ins=GPR, outs=GPR,
+        emit='put_i(bits, in_reg0, -1, out_reg0, sink);')
+
+# I-type encoding of a boolean `bnot` as an `xori` with 1.
+Inotb = EncRecipe(
+        'Inotb', Unary, size=4, ins=GPR, outs=GPR,
+        emit='put_i(bits, in_reg0, 1, out_reg0, sink);')
+
+# I-type loads.
+Iload = EncRecipe(
+        'Iload', Load, size=4, ins=GPR, outs=GPR,
+        instp=IsSignedInt(Load.offset, 12),
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_i(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+Ifload = EncRecipe(
+        'Ifload', Load, size=4, ins=GPR, outs=FPR,
+        instp=IsSignedInt(Load.offset, 12),
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_i(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+# I-type `addi` adjusting the stack pointer.
+Iadjsp = EncRecipe(
+        'Iadjsp', UnaryImm, size=4, ins=(), outs=(),
+        instp=IsSignedInt(UnaryImm.imm, 12),
+        emit='put_i(bits, SP, imm.into(), SP, sink);')
+
+# The permanently unimplemented instruction is `csrrw x0, cycle, x0`, an
+# attempt to write the read-only `cycle` CSR.
+Itrap = EncRecipe(
+        'Itrap', Trap, size=4, ins=(), outs=(),
+        emit='''
+        sink.trap(code, func.srclocs[inst]);
+        put_i(bits, 0, 0xc00, 0, sink);
+        ''')
+
 # I-type encoding for `jalr` as a return instruction. We won't use the
 # immediate offset.
 # The variable return values are not encoded.
@@ -165,6 +282,27 @@ Irmov = EncRecipe(
         'Irmov', RegMove, size=4, ins=GPR, outs=(),
         emit='put_i(bits, src, 0, dst, sink);')
 
+# S-type stores.
+S = EncRecipe(
+        'S', Store, size=4, ins=(GPR, GPR), outs=(),
+        instp=IsSignedInt(Store.offset, 12),
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_s(bits, in_reg1, in_reg0, offset.into(), sink);
+        ''')
+
+Sf = EncRecipe(
+        'Sf', Store, size=4, ins=(FPR, GPR), outs=(),
+        instp=IsSignedInt(Store.offset, 12),
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_s(bits, in_reg1, in_reg0, offset.into(), sink);
+        ''')
+
 # U-type instructions have a 20-bit immediate that targets bits 12-31.
 U = EncRecipe(
         'U', UnaryImm, size=4, ins=(), outs=GPR,
@@ -183,9 +321,11 @@ UJ = EncRecipe(
 UJcall = EncRecipe(
         'UJcall', Call, size=4, ins=(), outs=(),
         emit='''
+        let callee = &func.dfg.ext_funcs[func_ref];
         sink.reloc_external(Reloc::RiscvCall,
-                            &func.dfg.ext_funcs[func_ref].name,
-                            0);
+                            &callee.name,
+                            0,
+                            SymbolInfo::function(callee));
         // rd=%x1 is the standard link register.
         put_uj(bits, 0, 1, sink);
         ''')
@@ -216,10 +356,255 @@ SBzero = EncRecipe(
 GPsp = EncRecipe(
         'GPsp', Unary, size=4,
         ins=GPR, outs=Stack(GPR),
-        emit='unimplemented!();')
+        emit='put_s(bits, SP, in_reg0, out_stk0.offset.into(), sink);')
 
 # Fill of a GPR.
 GPfi = EncRecipe(
         'GPfi', Unary, size=4,
         ins=Stack(GPR), outs=GPR,
-        emit='unimplemented!();')
+        emit='put_i(bits, SP, in_stk0.offset.into(), out_reg0, sink);')
+
+# Spill of a GPR by the register allocator.
+GPrsp = EncRecipe(
+        'GPrsp', RegSpill, size=4,
+        ins=GPR, outs=(),
+        emit='''
+        let dst = StackRef::sp(dst, &func.stack_slots);
+        put_s(bits, SP, src, dst.offset.into(), sink);
+        ''')
+
+# Fill of a GPR by the register allocator.
+GPrfi = EncRecipe(
+        'GPrfi', RegFill, size=4,
+        ins=Stack(GPR), outs=(),
+        emit='''
+        let src = StackRef::sp(src, &func.stack_slots);
+        put_i(bits, SP, src.offset.into(), dst, sink);
+        ''')
+
+# Spill and fill of an FPR.
+FPsp = EncRecipe(
+        'FPsp', Unary, size=4,
+        ins=FPR, outs=Stack(FPR),
+        emit='put_s(bits, SP, in_reg0, out_stk0.offset.into(), sink);')
+
+FPfi = EncRecipe(
+        'FPfi', Unary, size=4,
+        ins=Stack(FPR), outs=FPR,
+        emit='put_i(bits, SP, in_stk0.offset.into(), out_reg0, sink);')
+
+FPrsp = EncRecipe(
+        'FPrsp', RegSpill, size=4,
+        ins=FPR, outs=(),
+        emit='''
+        let dst = StackRef::sp(dst, &func.stack_slots);
+        put_s(bits, SP, src, dst.offset.into(), sink);
+        ''')
+
+FPrfi = EncRecipe(
+        'FPrfi', RegFill, size=4,
+        ins=Stack(FPR), outs=(),
+        emit='''
+        let src = StackRef::sp(src, &func.stack_slots);
+        put_i(bits, SP, src.offset.into(), dst, sink);
+        ''')
+
+#
+# Floating point.
+#
+# The rounding mode is encoded in funct3. The arithmetic instructions always
+# round to nearest, ties to even, and the conversions to integers round towards
+# zero.
+#
+
+# R-type floating point binary arithmetic.
+Rf = EncRecipe(
+        'Rf', Binary, size=4, ins=(FPR, FPR), outs=FPR,
+        emit='put_r(bits, in_reg0, in_reg1, out_reg0, sink);')
+
+# R-type floating point comparison producing a boolean in a GPR.
+Rfcmp = EncRecipe(
+        'Rfcmp', FloatCompare, size=4, ins=(FPR, FPR), outs=GPR,
+        emit='put_r(bits, in_reg0, in_reg1, out_reg0, sink);')
+
+# Sign injection with the same register for both operands. This implements
+# copies, `fneg`, and `fabs`.
+Rfcopy = EncRecipe(
+        'Rfcopy', Unary, size=4, ins=FPR, outs=FPR,
+        emit='put_r(bits, in_reg0, in_reg0, out_reg0, sink);')
+
+Rfrmov = EncRecipe(
+        'Rfrmov', RegMove, size=4, ins=FPR, outs=(),
+        emit='put_r(bits, src, src, dst, sink);')
+
+# OP-FP instructions with a fixed rs2 field. The encoding bits come from
+# `OP_FP_RS2()`.
+Rfu = EncRecipe(
+        'Rfu', Unary, size=4, ins=FPR, outs=FPR,
+        emit='put_rfixed(bits, in_reg0, out_reg0, sink);')
+
+# Conversion from an FPR to a GPR.
+Rfx = EncRecipe(
+        'Rfx', Unary, size=4, ins=FPR, outs=GPR,
+        emit='put_rfixed(bits, in_reg0, out_reg0, sink);')
+
+# Conversion from a GPR to an FPR.
+Rxf = EncRecipe(
+        'Rxf', Unary, size=4, ins=GPR, outs=FPR,
+        emit='put_rfixed(bits, in_reg0, out_reg0, sink);')
+
+# R4-type fused multiply-add.
+R4 = EncRecipe(
+        'R4', Ternary, size=4, ins=(FPR, FPR, FPR), outs=FPR,
+        emit='put_r4(bits, in_reg0, in_reg1, in_reg2, out_reg0, sink);')
+
+#
+# Compressed instructions.
+#
+# These 16-bit encodings are only used when the 'C' extension is enabled. They
+# mostly have tied operands or restricted register classes, so they are listed
+# after the 32-bit encodings. The `shrink_instructions` pass selects them after
+# register allocation when the operands permit it.
+#
+
+# An immediate operand that is not 0.
+nonzero_imm = Not(IsEqual(BinaryImm.imm, 0))
+
+# CR-type `c.mv`.
+CRmov = EncRecipe(
+        'CRmov', Unary, size=2, ins=GPR, outs=GPR, isap=use_c,
+        emit='put_cr(bits, out_reg0, in_reg0, sink);')
+
+CRrmov = EncRecipe(
+        'CRrmov', RegMove, size=2, ins=GPR, outs=(), isap=use_c,
+        emit='put_cr(bits, dst, src, sink);')
+
+# CR-type `c.add` with rd tied to rs1.
+CRadd = EncRecipe(
+        'CRadd', Binary, size=2, ins=(GPR, GPR), outs=0, isap=use_c,
+        emit='put_cr(bits, in_reg0, in_reg1, sink);')
+
+# CR-type `c.jr %x1` as a return instruction.
+CRret = EncRecipe(
+        'CRret', MultiAry, size=2, ins=(), outs=(), isap=use_c,
+        emit='put_cr(bits, 1, 0, sink);')
+
+# CR-type `c.jalr` as an indirect call.
+CRcall = EncRecipe(
+        'CRcall', IndirectCall, size=2, ins=GPR, outs=(), isap=use_c,
+        emit='put_cr(bits, in_reg0, 0, sink);')
+
+# CI-type `c.addi` and `c.addiw` with rd tied to rs1.
+CIaddi = EncRecipe(
+        'CIaddi', BinaryImm, size=2, ins=GPR, outs=0, isap=use_c,
+        instp=And(IsSignedInt(BinaryImm.imm, 6), nonzero_imm),
+        emit='put_ci(bits, in_reg0, imm.into(), sink);')
+
+# CI-type `c.slli` with rd tied to rs1. The encodings check the range of the
+# shift amount.
+CIslli = EncRecipe(
+        'CIslli', BinaryImm, size=2, ins=GPR, outs=0, isap=use_c,
+        instp=nonzero_imm,
+        emit='put_ci(bits, in_reg0, imm.into(), sink);')
+
+# CI-type `c.li`.
+CIli = EncRecipe(
+        'CIli', UnaryImm, size=2, ins=(), outs=GPR, isap=use_c,
+        instp=IsSignedInt(UnaryImm.imm, 6),
+        emit='put_ci(bits, out_reg0, imm.into(), sink);')
+
+# CI-type `c.lui`.
+CIlui = EncRecipe(
+        'CIlui', UnaryImm, size=2, ins=(), outs=GPR, isap=use_c,
+        instp=And(
+            IsSignedInt(UnaryImm.imm, 18, 12),
+            Not(IsEqual(UnaryImm.imm, 0))),
+        emit='''
+        let imm: i64 = imm.into();
+        put_ci(bits, out_reg0, imm >> 12, sink);
+        ''')
+
+# CI-type `c.addi16sp` adjusting the stack pointer by a multiple of 16.
+CIsp = EncRecipe(
+        'CIsp', UnaryImm, size=2, ins=(), outs=(), isap=use_c,
+        instp=And(
+            IsSignedInt(UnaryImm.imm, 10, 4),
+            Not(IsEqual(UnaryImm.imm, 0))),
+        emit='put_ci16sp(bits, imm.into(), sink);')
+
+# CA-type arithmetic with rd' tied to rs1'.
+CA = EncRecipe(
+        'CA', Binary, size=2, ins=(GPRC, GPRC), outs=0, isap=use_c,
+        emit='put_ca(bits, in_reg0, in_reg1, sink);')
+
+# CB-type `c.andi`, `c.srli`, and `c.srai` with rd' tied to rs1'. The
+# encodings check the range of the immediate.
+CBimm = EncRecipe(
+        'CBimm', BinaryImm, size=2, ins=GPRC, outs=0, isap=use_c,
+        emit='put_cb(bits, in_reg0, imm.into(), sink);')
+
+# CB-type `c.beqz` and `c.bnez`.
+CBbranch = EncRecipe(
+        'CBbranch', Branch, size=2, ins=GPRC, outs=(), isap=use_c,
+        branch_range=(0, 9),
+        emit='''
+        let dest = i64::from(func.offsets[destination]);
+        let disp = dest - i64::from(sink.offset());
+        put_cbranch(bits, disp, in_reg0, sink);
+        ''')
+
+# CJ-type `c.j`.
+CJ = EncRecipe(
+        'CJ', Jump, size=2, ins=(), outs=(), isap=use_c,
+        branch_range=(0, 12),
+        emit='''
+        let dest = i64::from(func.offsets[destination]);
+        let disp = dest - i64::from(sink.offset());
+        put_cj(bits, disp, sink);
+        ''')
+
+# CL-type loads. The encodings check the range of the scaled offset.
+CL = EncRecipe(
+        'CL', Load, size=2, ins=GPRC, outs=GPRC, isap=use_c,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_cl(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+CLf = EncRecipe(
+        'CLf', Load, size=2, ins=GPRC, outs=FPRC, isap=use_c,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_cl(bits, in_reg0, offset.into(), out_reg0, sink);
+        ''')
+
+# CS-type stores.
+CS = EncRecipe(
+        'CS', Store, size=2, ins=(GPRC, GPRC), outs=(), isap=use_c,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_cl(bits, in_reg1, offset.into(), in_reg0, sink);
+        ''')
+
+CSf = EncRecipe(
+        'CSf', Store, size=2, ins=(FPRC, GPRC), outs=(), isap=use_c,
+        emit='''
+        if !flags.notrap() {
+            sink.trap(TrapCode::HeapOutOfBounds, func.srclocs[inst]);
+        }
+        put_cl(bits, in_reg1, offset.into(), in_reg0, sink);
+        ''')
+
+# `c.unimp` is all zeros.
+Ctrap = EncRecipe(
+        'Ctrap', Trap, size=2, ins=(), outs=(), isap=use_c,
+        emit='''
+        sink.trap(code, func.srclocs[inst]);
+        sink.put2(bits);
+        ''')
@@ -20,4 +20,9 @@ FloatRegs = RegBank(
 GPR = RegClass(IntRegs)
 FPR = RegClass(FloatRegs)
 
+# Most of the compressed instructions can only encode the registers `x8` -
+# `x15` and `f8` - `f15` in a 3-bit field.
+GPRC = GPR[8:16]
+FPRC = FPR[8:16]
+
 RegClass.extract_names(globals())
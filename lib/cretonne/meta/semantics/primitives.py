@@ -117,6 +117,12 @@ bvule = Instruction(
 bvult = Instruction(
         'bvult', r"""Unsigned bitvector less than""",
         ins=(x, y), outs=cond)
+bvsof = Instruction(
+        'bvsof', r"""Signed overflow of bitvector subtraction""",
+        ins=(x, y), outs=cond)
+bvnsof = Instruction(
+        'bvnsof', r"""No signed overflow of bitvector subtraction""",
+        ins=(x, y), outs=cond)
 
 # Extensions
 ToBV = TypeVar('ToBV', 'A bitvector type.', bitvecs=True)
@@ -3,7 +3,7 @@
 //! This module provides functions and data structures that are useful for implementing the
 //! `TargetIsa::legalize_signature()` method.
 
-use ir::{ArgumentLoc, AbiParam, ArgumentExtension, Type};
+use ir::{ArgumentLoc, AbiParam, ArgumentExtension, ArgumentPurpose, Signature, Type};
 use std::cmp::Ordering;
 use std::vec::Vec;
 
@@ -122,6 +122,65 @@ pub fn legalize_args<AA: ArgAssigner>(args: &mut Vec<AbiParam>, aa: &mut AA) {
     }
 }
 
+/// Legalize the return values in `sig` using the `rets` assigner, and return them in memory if
+/// they don't all fit in registers.
+///
+/// Return values in memory are replaced by a `StructReturn` parameter of type `pointer_type`,
+/// inserted before the other parameters, which must be legalized after this. The callee stores
+/// the return values in the memory it points to, laid out by `struct_return_layout()`.
+/// Signatures that already have a `StructReturn` parameter, or return values with pre-assigned
+/// locations, are left alone.
+///
+/// Returns true if the return values were moved to memory. The ISA may then add a
+/// `StructReturn` return value if its ABI requires the pointer to be returned.
+pub fn legalize_struct_return<AA: ArgAssigner>(
+    sig: &mut Signature,
+    rets: &mut AA,
+    pointer_type: Type,
+) -> bool {
+    let preassigned = sig.returns.iter().any(|r| r.location.is_assigned());
+    let mut returns = sig.returns.clone();
+    legalize_args(&mut returns, rets);
+    if sig.uses_struct_return_param() || preassigned ||
+        !returns.iter().any(|r| r.location.is_stack())
+    {
+        sig.returns = returns;
+        return false;
+    }
+
+    sig.returns.clear();
+    sig.params.insert(
+        0,
+        AbiParam::special(pointer_type, ArgumentPurpose::StructReturn),
+    );
+    if let Some(ref mut fixed) = sig.fixed_params {
+        *fixed += 1;
+    }
+    true
+}
+
+/// Compute the memory layout of return values of types `types` returned through a `StructReturn`
+/// pointer.
+///
+/// The values are laid out like the fields of a C struct, each one aligned to its size. Returns
+/// the offset of each value and the size of the memory, padded to the largest alignment.
+pub fn struct_return_layout(types: &[Type]) -> (Vec<u32>, u32) {
+    let align_to = |offset: u32, align: u32| (offset + align - 1) & !(align - 1);
+    let mut size = 0;
+    let mut max_align = 1;
+    let offsets = types
+        .iter()
+        .map(|ty| {
+            let bytes = ty.bytes();
+            let offset = align_to(size, bytes);
+            size = offset + bytes;
+            max_align = max_align.max(bytes);
+            offset
+        })
+        .collect();
+    (offsets, align_to(size, max_align))
+}
+
 /// Determine the right action to take when passing a `have` value type to a call signature where
 /// the next argument is `arg` which has a different value type.
 ///
@@ -223,4 +282,17 @@ mod tests {
             ValueConversion::IntBits
         );
     }
+
+    #[test]
+    fn struct_return() {
+        assert_eq!(
+            struct_return_layout(&[types::I8, types::I64, types::F32, types::B1]),
+            (vec![0, 8, 16, 20], 24)
+        );
+        assert_eq!(
+            struct_return_layout(&[types::F32, types::I32X4]),
+            (vec![0, 16], 32)
+        );
+        assert_eq!(struct_return_layout(&[]), (vec![], 0));
+    }
 }
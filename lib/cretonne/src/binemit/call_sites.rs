@@ -0,0 +1,97 @@
+//! Call site tables for exception handling.
+//!
+//! An `invoke` instruction is a call with a landing pad. When an exception unwinds the stack
+//! through the call, the embedder's unwinder looks up the return address of the call in the call
+//! site table of the function, and resumes execution at the landing pad instead of returning.
+//! The stack pointer and the callee-saved registers have the values they would have after a
+//! normal return from the call.
+
+use binemit::CodeOffset;
+use ir::Function;
+use isa::TargetIsa;
+use std::vec::Vec;
+
+/// A call with a landing pad in the generated code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    /// Code offset following the call instruction, which is the return address of the call.
+    pub return_address: CodeOffset,
+
+    /// Code offset of the landing pad.
+    pub landing_pad: CodeOffset,
+}
+
+/// Collect the call sites of the `invoke` instructions in `func`, in code order.
+///
+/// This function can only be used after the code layout has been computed by the
+/// `binemit::relax_branches()` function.
+pub fn call_site_table(func: &Function, isa: &TargetIsa) -> Vec<CallSite> {
+    let encinfo = isa.encoding_info();
+    let mut sites = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            let opcode = func.dfg[inst].opcode();
+            if !opcode.is_call() || !opcode.is_branch() {
+                continue;
+            }
+            if let Some(dest) = func.dfg[inst].branch_destination() {
+                sites.push(CallSite {
+                    return_address: offset + size,
+                    landing_pad: func.offsets[dest],
+                });
+            }
+        }
+    }
+    sites
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, CallConv, ExtFuncData, ExternalName, InstBuilder, Signature};
+    use isa;
+    use settings::{self, Configurable};
+
+    #[test]
+    fn landing_pad() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I64));
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I64));
+        let signature = ctx.func.import_signature(sig);
+        let callee = ctx.func.import_function(ExtFuncData {
+            name: ExternalName::testcase("may_throw"),
+            signature,
+            colocated: false,
+        });
+
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let ebb1 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let call = pos.ins().invoke(callee, ebb1, &[]);
+            let v1 = pos.func.dfg.first_result(call);
+            pos.ins().return_(&[v1]);
+
+            // The landing pad returns the parameter which is only live on the exceptional path.
+            pos.insert_ebb(ebb1);
+            pos.ins().return_(&[v0]);
+        }
+        ctx.compile(&*isa).unwrap();
+
+        let sites = call_site_table(&ctx.func, &*isa);
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites[0].landing_pad, ctx.func.offsets[ebb1]);
+        assert!(sites[0].return_address <= sites[0].landing_pad);
+    }
+}
@@ -0,0 +1,255 @@
+//! Self-checking code emission.
+//!
+//! Backend bugs that assign a value to a register the encoding recipe can't use, or that pick an
+//! encoding whose immediate range doesn't fit the instruction, silently produce corrupt machine
+//! code. With the `check_emission` setting, every instruction is checked against its encoding
+//! recipe as it is emitted, so these bugs fail immediately at the offending instruction.
+
+use binemit::{emit_function, CodeOffset, CodeSink};
+use ir::{Function, Inst, Value, ValueLoc};
+use isa::{ConstraintKind, OperandConstraint, TargetIsa};
+use regalloc::RegDiversions;
+use std::string::String;
+
+/// Emit a function to `sink` like `emit_function`, checking each instruction as it is emitted.
+///
+/// Before an instruction is emitted, its encoding must still be one of the legal encodings for
+/// the instruction in `isa`, and its operand and result locations must satisfy the constraints of
+/// the encoding recipe. After it is emitted, the number of bytes emitted must be the size of the
+/// recipe. A violation panics with a message identifying the function and the instruction.
+///
+/// The byte order of `sink` must be the byte order of `isa`.
+pub fn emit_function_checked<CS, EI, EP>(
+    isa: &TargetIsa,
+    func: &Function,
+    emit_inst: EI,
+    emit_padding: EP,
+    sink: &mut CS,
+) where
+    CS: CodeSink,
+    EI: Fn(&Function, Inst, &mut RegDiversions, &mut CS),
+    EP: Fn(CodeOffset, &mut CS),
+{
+    assert_eq!(
+        sink.endianness(),
+        isa.endianness(),
+        "Wrong byte order for {}",
+        func.name
+    );
+    let encinfo = isa.encoding_info();
+    emit_function(
+        func,
+        |func, inst, divert, sink| {
+            if let Err(msg) = check_inst(isa, func, inst, divert) {
+                emission_failure(func, inst, &msg);
+            }
+            let start = sink.offset();
+            emit_inst(func, inst, divert, sink);
+            let size = sink.offset() - start;
+            let expected = encinfo.bytes(func.encodings[inst]);
+            if size != expected {
+                let msg = format!(
+                    "emitted {} bytes for {}, expected {}",
+                    size,
+                    encinfo.display(func.encodings[inst]),
+                    expected
+                );
+                emission_failure(func, inst, &msg);
+            }
+        },
+        emit_padding,
+        sink,
+    )
+}
+
+/// Check the encoding of `inst` and the locations of its operands before emitting it.
+///
+/// The locations are the ones in `func.locations`, as diverted by `divert`.
+pub fn check_inst(
+    isa: &TargetIsa,
+    func: &Function,
+    inst: Inst,
+    divert: &RegDiversions,
+) -> Result<(), String> {
+    let enc = func.encodings[inst];
+    if !enc.is_legal() {
+        return Ok(());
+    }
+    let encinfo = isa.encoding_info();
+
+    let ctrl_type = func.dfg.ctrl_typevar(inst);
+    if !isa.legal_encodings(&func.dfg, &func.dfg[inst], ctrl_type)
+        .any(|e| e == enc)
+    {
+        return Err(format!(
+            "encoding {} is not legal for the instruction",
+            encinfo.display(enc)
+        ));
+    }
+
+    let constraints = match encinfo.operand_constraints(enc) {
+        Some(constraints) => constraints,
+        None => return Err(format!("unknown recipe in {}", encinfo.display(enc))),
+    };
+    let results = func.dfg.inst_results(inst);
+    for (idx, (&arg, constraint)) in func.dfg
+        .inst_args(inst)
+        .iter()
+        .zip(constraints.ins)
+        .enumerate()
+    {
+        let loc = divert.get(arg, &func.locations);
+        check_operand(isa, "argument", idx, arg, loc, constraint)?;
+        if let ConstraintKind::Tied(out) = constraint.kind {
+            let result = results[out as usize];
+            if func.locations[result] != loc {
+                return Err(format!(
+                    "argument {} ({}) is not in the same location as the tied result {}",
+                    idx,
+                    arg,
+                    result
+                ));
+            }
+        }
+    }
+    for (idx, (&result, constraint)) in results.iter().zip(constraints.outs).enumerate() {
+        let loc = divert.get(result, &func.locations);
+        check_operand(isa, "result", idx, result, loc, constraint)?;
+    }
+    Ok(())
+}
+
+/// Check that the location `loc` of the operand `value` satisfies `constraint`.
+fn check_operand(
+    isa: &TargetIsa,
+    what: &str,
+    idx: usize,
+    value: Value,
+    loc: ValueLoc,
+    constraint: &OperandConstraint,
+) -> Result<(), String> {
+    if constraint.satisfied(loc) {
+        return Ok(());
+    }
+    let expected = match constraint.kind {
+        ConstraintKind::Reg |
+        ConstraintKind::Tied(_) => format!("a {} register", constraint.regclass),
+        ConstraintKind::FixedReg(reg) |
+        ConstraintKind::FixedTied(reg) => {
+            format!("{}", isa.register_info().display_regunit(reg))
+        }
+        ConstraintKind::Stack => String::from("a stack slot"),
+    };
+    Err(format!(
+        "{} {} ({}) is in {}, but the recipe requires {}",
+        what,
+        idx,
+        value,
+        loc.display(&isa.register_info()),
+        expected
+    ))
+}
+
+/// Report an emission check failure.
+#[inline(never)]
+fn emission_failure(func: &Function, inst: Inst, msg: &str) -> ! {
+    panic!(
+        "Emission check failed in {} at {}: {}",
+        func.name,
+        func.dfg.display_inst(inst, None),
+        msg
+    );
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use binemit::{MemoryCodeSink, NullTrapSink, Relocations};
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+    use std::boxed::Box;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::vec::Vec;
+
+    fn intel() -> Box<TargetIsa> {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        flag_builder.enable("check_emission").unwrap();
+        isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder))
+    }
+
+    /// Compile a function adding its two parameters.
+    fn compile(isa: &TargetIsa) -> Context {
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let x = pos.func.dfg.append_ebb_param(ebb0, I32);
+            let y = pos.func.dfg.append_ebb_param(ebb0, I32);
+            let sum = pos.ins().iadd(x, y);
+            pos.ins().return_(&[sum]);
+        }
+        ctx.compile(isa).unwrap();
+        ctx
+    }
+
+    fn emit(ctx: &Context, isa: &TargetIsa) -> Vec<u8> {
+        let mut code = vec![0; 256];
+        let mut relocs = Relocations::new();
+        isa.emit_function(
+            &ctx.func,
+            &mut MemoryCodeSink::new(
+                code.as_mut_ptr(),
+                &mut relocs,
+                &mut NullTrapSink {},
+                isa.endianness(),
+            ),
+        );
+        code
+    }
+
+    #[test]
+    fn valid() {
+        let isa = intel();
+        let ctx = compile(&*isa);
+        let mut divert = RegDiversions::new();
+        let ebb = ctx.func.layout.entry_block().unwrap();
+        for inst in ctx.func.layout.ebb_insts(ebb) {
+            assert_eq!(check_inst(&*isa, &ctx.func, inst, &divert), Ok(()));
+            divert.apply(&ctx.func.dfg[inst]);
+        }
+        emit(&ctx, &*isa);
+    }
+
+    #[test]
+    fn bad_location() {
+        let isa = intel();
+        let mut ctx = compile(&*isa);
+
+        // Move the first argument of the addition to a stack slot.
+        let ebb = ctx.func.layout.entry_block().unwrap();
+        let iadd = ctx.func
+            .layout
+            .ebb_insts(ebb)
+            .find(|&inst| ctx.func.dfg[inst].opcode() == ::ir::Opcode::Iadd)
+            .unwrap();
+        let arg = ctx.func.dfg.inst_args(iadd)[0];
+        let ss = ctx.func.stack_slots.make_spill_slot(I32);
+        ctx.func.locations[arg] = ValueLoc::Stack(ss);
+
+        let divert = RegDiversions::new();
+        let msg = check_inst(&*isa, &ctx.func, iadd, &divert).unwrap_err();
+        assert!(msg.starts_with("argument 0 "), "{}", msg);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| emit(&ctx, &*isa)));
+        assert!(result.is_err());
+    }
+}
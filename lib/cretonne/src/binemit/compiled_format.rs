@@ -0,0 +1,363 @@
+//! Binary encoding of compiled functions.
+//!
+//! An embedder that caches compiled code across runs needs to store the relocations and trap
+//! sites along with the machine code. `encode_compiled` encodes a `CompiledFunction` into a byte
+//! string, and `decode_compiled` turns it back into an identical `CompiledFunction`.
+//!
+//! The encoding is made of unsigned LEB128 numbers, except for the leading version byte and the
+//! raw bytes of the code and of strings:
+//!
+//! ```text
+//! compiled := version:u8 code_size code:u8* num_relocs reloc* num_traps trap*
+//! reloc    := kind offset name addend_lo addend_hi symbol
+//! name     := 0 namespace index | 1 string | 2 string | 3 string
+//! symbol   := symbol_kind preemptible
+//! trap     := offset string
+//! string   := length byte*
+//! ```
+//!
+//! The relocation `kind` is the index of the `Reloc` variant in declaration order, and the
+//! `symbol_kind` is the index of the `SymbolKind` variant. `preemptible` is 0 or 1. An external
+//! name is a user name, a test case name, the name of a `LibCall` variant, or a symbol name. Trap
+//! codes are stored as their names in the text format. Library calls and trap codes are stored by
+//! name so adding new ones doesn't change the encoding of the existing ones.
+
+use binemit::{Reloc, Relocation, Relocations, SymbolInfo, SymbolKind, TrapReport, TrapSite};
+use super::leb128::{Leb128Error, get_uleb128, put_uleb128};
+use driver::CompiledFunction;
+use ir::ExternalName;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str;
+use std::string::ToString;
+use std::vec::Vec;
+
+/// The version of the encoding produced by `encode_compiled`.
+pub const COMPILED_FORMAT_VERSION: u8 = 2;
+
+/// The relocation kinds, in the order of their encoding.
+const RELOC_KINDS: [Reloc; 11] = [
+    Reloc::IntelPCRel4,
+    Reloc::IntelAbs4,
+    Reloc::IntelAbs8,
+    Reloc::IntelGOTPCRel4,
+    Reloc::IntelPLTRel4,
+    Reloc::IntelGOTTPOff4,
+    Reloc::IntelTLSGD4,
+    Reloc::Arm32Call,
+    Reloc::Arm64Call,
+    Reloc::RiscvCall,
+    Reloc::Arm64Abs8,
+];
+
+/// The symbol kinds, in the order of their encoding.
+const SYMBOL_KINDS: [SymbolKind; 4] = [
+    SymbolKind::Code,
+    SymbolKind::Data,
+    SymbolKind::ReadOnlyData,
+    SymbolKind::ThreadLocal,
+];
+
+/// An error found while decoding a compiled function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompiledFormatError {
+    /// The data was encoded with an unknown version of the format.
+    UnsupportedVersion(u8),
+
+    /// The data ends in the middle of the function.
+    Truncated,
+
+    /// A number, name, or trap code in the data is invalid.
+    Corrupt,
+}
+
+impl fmt::Display for CompiledFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompiledFormatError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported compiled function format version {}", v)
+            }
+            CompiledFormatError::Truncated => write!(f, "Truncated compiled function data"),
+            CompiledFormatError::Corrupt => write!(f, "Corrupt compiled function data"),
+        }
+    }
+}
+
+impl StdError for CompiledFormatError {
+    fn description(&self) -> &str {
+        match *self {
+            CompiledFormatError::UnsupportedVersion(_) => {
+                "Unsupported compiled function format version"
+            }
+            CompiledFormatError::Truncated => "Truncated compiled function data",
+            CompiledFormatError::Corrupt => "Corrupt compiled function data",
+        }
+    }
+}
+
+/// Result of decoding a compiled function.
+pub type Result<T> = ::std::result::Result<T, CompiledFormatError>;
+
+impl From<Leb128Error> for CompiledFormatError {
+    fn from(e: Leb128Error) -> Self {
+        match e {
+            Leb128Error::Truncated => CompiledFormatError::Truncated,
+            Leb128Error::Overflow => CompiledFormatError::Corrupt,
+        }
+    }
+}
+
+/// Encode `func` as a byte string.
+pub fn encode_compiled(func: &CompiledFunction) -> Vec<u8> {
+    let mut out = Vec::with_capacity(func.code.len() + 16);
+    out.push(COMPILED_FORMAT_VERSION);
+    put_bytes(&mut out, &func.code);
+
+    put_uleb128(&mut out, func.relocs.len() as u32);
+    for reloc in &func.relocs {
+        let kind = RELOC_KINDS.iter().position(|&k| k == reloc.kind).unwrap();
+        put_uleb128(&mut out, kind as u32);
+        put_uleb128(&mut out, reloc.offset);
+        match reloc.name {
+            ExternalName::User { namespace, index } => {
+                put_uleb128(&mut out, 0);
+                put_uleb128(&mut out, namespace);
+                put_uleb128(&mut out, index);
+            }
+            ExternalName::TestCase { length, ascii } => {
+                put_uleb128(&mut out, 1);
+                put_bytes(&mut out, &ascii[..length as usize]);
+            }
+            ExternalName::LibCall(lc) => {
+                put_uleb128(&mut out, 2);
+                put_bytes(&mut out, format!("{:?}", lc).as_bytes());
+            }
+            ExternalName::Name(ref bytes) => {
+                put_uleb128(&mut out, 3);
+                put_bytes(&mut out, bytes);
+            }
+        }
+        let addend = reloc.addend as u64;
+        put_uleb128(&mut out, addend as u32);
+        put_uleb128(&mut out, (addend >> 32) as u32);
+        let symbol_kind = SYMBOL_KINDS
+            .iter()
+            .position(|&k| k == reloc.symbol.kind)
+            .unwrap();
+        put_uleb128(&mut out, symbol_kind as u32);
+        put_uleb128(&mut out, reloc.symbol.preemptible as u32);
+    }
+
+    put_uleb128(&mut out, func.traps.sites.len() as u32);
+    for site in &func.traps.sites {
+        put_uleb128(&mut out, site.offset);
+        put_bytes(&mut out, site.code.to_string().as_bytes());
+    }
+    out
+}
+
+/// Decode a compiled function encoded by `encode_compiled`.
+pub fn decode_compiled(data: &[u8]) -> Result<CompiledFunction> {
+    let (&version, mut data) = data.split_first().ok_or(CompiledFormatError::Truncated)?;
+    if version != COMPILED_FORMAT_VERSION {
+        return Err(CompiledFormatError::UnsupportedVersion(version));
+    }
+    let code = get_bytes(&mut data)?.to_vec();
+
+    let mut relocs = Relocations::new();
+    for _ in 0..get_uleb128(&mut data)? {
+        let kind = *RELOC_KINDS.get(get_uleb128(&mut data)? as usize).ok_or(
+            CompiledFormatError::Corrupt,
+        )?;
+        let offset = get_uleb128(&mut data)?;
+        let name = match get_uleb128(&mut data)? {
+            0 => {
+                let namespace = get_uleb128(&mut data)?;
+                ExternalName::user(namespace, get_uleb128(&mut data)?)
+            }
+            1 => ExternalName::testcase(get_bytes(&mut data)?),
+            2 => ExternalName::LibCall(get_str(&mut data)?.parse().map_err(|_| {
+                CompiledFormatError::Corrupt
+            })?),
+            3 => ExternalName::name(get_bytes(&mut data)?),
+            _ => return Err(CompiledFormatError::Corrupt),
+        };
+        let addend_lo = u64::from(get_uleb128(&mut data)?);
+        let addend_hi = u64::from(get_uleb128(&mut data)?);
+        let symbol = SymbolInfo {
+            kind: *SYMBOL_KINDS.get(get_uleb128(&mut data)? as usize).ok_or(
+                CompiledFormatError::Corrupt,
+            )?,
+            preemptible: match get_uleb128(&mut data)? {
+                0 => false,
+                1 => true,
+                _ => return Err(CompiledFormatError::Corrupt),
+            },
+        };
+        relocs.push(Relocation {
+            kind,
+            offset,
+            name,
+            addend: (addend_hi << 32 | addend_lo) as i64,
+            symbol,
+        });
+    }
+
+    let mut traps = TrapReport::default();
+    for _ in 0..get_uleb128(&mut data)? {
+        let offset = get_uleb128(&mut data)?;
+        let code = get_str(&mut data)?.parse().map_err(
+            |_| CompiledFormatError::Corrupt,
+        )?;
+        traps.sites.push(TrapSite { offset, code });
+    }
+
+    if !data.is_empty() {
+        return Err(CompiledFormatError::Corrupt);
+    }
+    Ok(CompiledFunction {
+        code,
+        relocs,
+        traps,
+    })
+}
+
+/// Append the length of `bytes` followed by the bytes themselves.
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_uleb128(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// Read a byte string written by `put_bytes` from the front of `data`.
+fn get_bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = get_uleb128(data)? as usize;
+    if len > data.len() {
+        return Err(CompiledFormatError::Truncated);
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Ok(bytes)
+}
+
+/// Read a UTF-8 string written by `put_bytes` from the front of `data`.
+fn get_str<'a>(data: &mut &'a [u8]) -> Result<&'a str> {
+    str::from_utf8(get_bytes(data)?).map_err(|_| CompiledFormatError::Corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::{LibCall, TrapCode};
+
+    fn compiled() -> CompiledFunction {
+        let mut relocs = Relocations::new();
+        relocs.push(Relocation {
+            kind: Reloc::IntelPCRel4,
+            offset: 3,
+            name: ExternalName::testcase("callee"),
+            addend: -4,
+            symbol: SymbolInfo {
+                kind: SymbolKind::Code,
+                preemptible: false,
+            },
+        });
+        relocs.push(Relocation {
+            kind: Reloc::IntelAbs8,
+            offset: 300,
+            name: ExternalName::user(1, 2),
+            addend: 0x1_0000_0000,
+            symbol: SymbolInfo {
+                kind: SymbolKind::ReadOnlyData,
+                preemptible: true,
+            },
+        });
+        relocs.push(Relocation {
+            kind: Reloc::RiscvCall,
+            offset: 12,
+            name: ExternalName::LibCall(LibCall::FloorF64),
+            addend: 0,
+            symbol: SymbolInfo {
+                kind: SymbolKind::Code,
+                preemptible: true,
+            },
+        });
+        relocs.push(Relocation {
+            kind: Reloc::IntelGOTPCRel4,
+            offset: 20,
+            name: ExternalName::name("_ZN4core3mem4swapE"),
+            addend: -4,
+            symbol: SymbolInfo {
+                kind: SymbolKind::Data,
+                preemptible: true,
+            },
+        });
+        let mut traps = TrapReport::default();
+        traps.sites.push(TrapSite {
+            offset: 7,
+            code: TrapCode::HeapOutOfBounds,
+        });
+        traps.sites.push(TrapSite {
+            offset: 9,
+            code: TrapCode::User(42),
+        });
+        CompiledFunction {
+            code: (0..256).map(|b| b as u8).collect(),
+            relocs,
+            traps,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let func = compiled();
+        let data = encode_compiled(&func);
+        assert_eq!(data[0], COMPILED_FORMAT_VERSION);
+
+        let decoded = decode_compiled(&data).unwrap();
+        assert_eq!(decoded.code, func.code);
+        assert_eq!(decoded.relocs.as_slice(), func.relocs.as_slice());
+        assert_eq!(decoded.traps.sites, func.traps.sites);
+
+        let empty = CompiledFunction {
+            code: Vec::new(),
+            relocs: Relocations::new(),
+            traps: TrapReport::default(),
+        };
+        assert_eq!(encode_compiled(&empty), [2, 0, 0, 0]);
+        assert!(decode_compiled(&[2, 0, 0, 0]).unwrap().code.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let data = encode_compiled(&compiled());
+        let error = |data: &[u8]| decode_compiled(data).err();
+        assert_eq!(error(&[]), Some(CompiledFormatError::Truncated));
+        assert_eq!(
+            error(&[1, 0, 0, 0]),
+            Some(CompiledFormatError::UnsupportedVersion(1))
+        );
+        for len in 1..data.len() {
+            assert_eq!(error(&data[..len]), Some(CompiledFormatError::Truncated));
+        }
+        let mut extra = data.clone();
+        extra.push(0);
+        assert_eq!(error(&extra), Some(CompiledFormatError::Corrupt));
+
+        // An unknown relocation kind.
+        assert_eq!(
+            error(&[2, 0, 1, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Some(CompiledFormatError::Corrupt)
+        );
+
+        // An unknown symbol kind, and a bad preemptible flag.
+        assert_eq!(
+            error(&[2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0]),
+            Some(CompiledFormatError::Corrupt)
+        );
+        assert_eq!(
+            error(&[2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0]),
+            Some(CompiledFormatError::Corrupt)
+        );
+        assert!(decode_compiled(&[2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]).is_ok());
+    }
+}
@@ -0,0 +1,479 @@
+//! In-memory ELF images describing JIT-compiled functions to debuggers.
+//!
+//! GDB and LLDB only know about code that is described by an object file. `debug_elf_image()`
+//! creates a minimal ELF image for one function emitted into executable memory, with a symbol for
+//! the function and a DWARF line table mapping its code to source lines. This is enough to set
+//! breakpoints on the function name or a source line, and to see the function in backtraces.
+//!
+//! With the `gdb-jit` feature, a `GdbJitImage` registers the image with an attached debugger
+//! through the GDB JIT interface.
+
+use binemit::Endianness;
+use isa::TargetIsa;
+use std::vec::Vec;
+use super::leb128::{put_sleb128, put_uleb128};
+use super::perf::{PerfFunction, elf_machine};
+
+// ELF constants.
+const ET_EXEC: u16 = 2;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const STB_GLOBAL_STT_FUNC: u8 = 0x12;
+const ELF_HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: u64 = 24;
+
+// DWARF constants.
+const DW_TAG_COMPILE_UNIT: u32 = 0x11;
+const DW_TAG_SUBPROGRAM: u32 = 0x2e;
+const DW_AT_NAME: u32 = 0x03;
+const DW_AT_STMT_LIST: u32 = 0x10;
+const DW_AT_LOW_PC: u32 = 0x11;
+const DW_AT_HIGH_PC: u32 = 0x12;
+const DW_AT_EXTERNAL: u32 = 0x3f;
+const DW_FORM_ADDR: u32 = 0x01;
+const DW_FORM_DATA4: u32 = 0x06;
+const DW_FORM_STRING: u32 = 0x08;
+const DW_FORM_FLAG: u32 = 0x0c;
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+// Line program parameters, and the operand counts of the standard opcodes they imply.
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+/// A byte buffer that writes numbers in the byte order of the target.
+struct Buffer {
+    data: Vec<u8>,
+    endianness: Endianness,
+}
+
+impl Buffer {
+    fn new(endianness: Endianness) -> Self {
+        Self {
+            data: Vec::new(),
+            endianness,
+        }
+    }
+
+    fn put_int(&mut self, x: u64, size: usize) {
+        for i in 0..size {
+            let shift = match self.endianness {
+                Endianness::Little => i,
+                Endianness::Big => size - 1 - i,
+            };
+            self.data.push((x >> (8 * shift)) as u8);
+        }
+    }
+
+    fn put1(&mut self, x: u8) {
+        self.data.push(x);
+    }
+
+    fn put2(&mut self, x: u16) {
+        self.put_int(u64::from(x), 2);
+    }
+
+    fn put4(&mut self, x: u32) {
+        self.put_int(u64::from(x), 4);
+    }
+
+    fn put8(&mut self, x: u64) {
+        self.put_int(x, 8);
+    }
+
+    fn put_uleb(&mut self, x: u32) {
+        put_uleb128(&mut self.data, x);
+    }
+
+    fn put_sleb(&mut self, x: i32) {
+        put_sleb128(&mut self.data, x);
+    }
+
+    /// Append `s` as a NUL-terminated string.
+    fn put_str(&mut self, s: &str) {
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+    }
+
+    /// Overwrite the 4 bytes at `offset` with `x`.
+    fn patch4(&mut self, offset: usize, x: u32) {
+        let mut bytes = Buffer::new(self.endianness);
+        bytes.put4(x);
+        self.data[offset..offset + 4].copy_from_slice(&bytes.data);
+    }
+
+    fn align(&mut self, align: usize) {
+        while self.data.len() % align != 0 {
+            self.data.push(0);
+        }
+    }
+}
+
+/// A string table section.
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        Self { data: vec![0] }
+    }
+
+    /// Add `s` to the table, and return its offset.
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+/// Create an ELF image describing `func` to a debugger.
+///
+/// The image has a `.text` section at the address of the function without a copy of the code, a
+/// global function symbol with the name of the function, and the DWARF sections for one
+/// compilation unit with a line table made from `func.lines`. The compilation unit is named after
+/// the source file of the first line, or after the function if there are no lines.
+///
+/// Only 64-bit targets are supported.
+pub fn debug_elf_image(func: &PerfFunction, isa: &TargetIsa) -> Vec<u8> {
+    assert!(
+        isa.flags().is_64bit(),
+        "Debug images are only supported for 64-bit targets"
+    );
+    let endianness = isa.endianness();
+    let low_pc = func.addr;
+    let high_pc = func.addr + func.size();
+
+    // The source files in order of appearance. DWARF file numbers start at 1.
+    let mut files: Vec<&str> = Vec::new();
+    for line in &func.lines {
+        if !files.contains(&line.file) {
+            files.push(line.file);
+        }
+    }
+    let unit_name = files.first().cloned().unwrap_or(func.name);
+
+    let mut abbrev = Buffer::new(endianness);
+    abbrev.put_uleb(1);
+    abbrev.put_uleb(DW_TAG_COMPILE_UNIT);
+    abbrev.put1(1);
+    for &(attr, form) in &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_STMT_LIST, DW_FORM_DATA4),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_ADDR),
+        (0, 0),
+    ]
+    {
+        abbrev.put_uleb(attr);
+        abbrev.put_uleb(form);
+    }
+    abbrev.put_uleb(2);
+    abbrev.put_uleb(DW_TAG_SUBPROGRAM);
+    abbrev.put1(0);
+    for &(attr, form) in &[
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_ADDR),
+        (DW_AT_EXTERNAL, DW_FORM_FLAG),
+        (0, 0),
+    ]
+    {
+        abbrev.put_uleb(attr);
+        abbrev.put_uleb(form);
+    }
+    abbrev.put_uleb(0);
+
+    // DWARF 2 compilation unit with the function as its only child.
+    let mut info = Buffer::new(endianness);
+    info.put4(0);
+    info.put2(2);
+    info.put4(0);
+    info.put1(8);
+    info.put_uleb(1);
+    info.put_str(unit_name);
+    info.put4(0);
+    info.put8(low_pc);
+    info.put8(high_pc);
+    info.put_uleb(2);
+    info.put_str(func.name);
+    info.put8(low_pc);
+    info.put8(high_pc);
+    info.put1(1);
+    info.put_uleb(0);
+    let unit_length = info.data.len() as u32 - 4;
+    info.patch4(0, unit_length);
+
+    // DWARF 2 line program with one sequence covering the function.
+    let mut line = Buffer::new(endianness);
+    line.put4(0);
+    line.put2(2);
+    line.put4(0);
+    let header_start = line.data.len();
+    line.put1(1);
+    line.put1(1);
+    line.put1(LINE_BASE as u8);
+    line.put1(LINE_RANGE);
+    line.put1(OPCODE_BASE);
+    line.data.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+    // No include directories.
+    line.put1(0);
+    for file in &files {
+        line.put_str(file);
+        line.put_uleb(0);
+        line.put_uleb(0);
+        line.put_uleb(0);
+    }
+    line.put1(0);
+    let header_length = (line.data.len() - header_start) as u32;
+    line.patch4(6, header_length);
+
+    line.put1(0);
+    line.put_uleb(9);
+    line.put1(DW_LNE_SET_ADDRESS);
+    line.put8(low_pc);
+    let (mut file, mut row, mut offset) = (1, 1, 0);
+    for entry in &func.lines {
+        let entry_file = files.iter().position(|&f| f == entry.file).unwrap() as u32 + 1;
+        if entry_file != file {
+            line.put1(DW_LNS_SET_FILE);
+            line.put_uleb(entry_file);
+            file = entry_file;
+        }
+        if entry.offset != offset {
+            line.put1(DW_LNS_ADVANCE_PC);
+            line.put_uleb(entry.offset - offset);
+            offset = entry.offset;
+        }
+        if entry.line != row {
+            line.put1(DW_LNS_ADVANCE_LINE);
+            line.put_sleb(entry.line.wrapping_sub(row) as i32);
+            row = entry.line;
+        }
+        line.put1(DW_LNS_COPY);
+    }
+    let end = func.size() as u32;
+    if end != offset {
+        line.put1(DW_LNS_ADVANCE_PC);
+        line.put_uleb(end - offset);
+    }
+    line.put1(0);
+    line.put_uleb(1);
+    line.put1(DW_LNE_END_SEQUENCE);
+    let unit_length = line.data.len() as u32 - 4;
+    line.patch4(0, unit_length);
+
+    let mut strtab = StringTable::new();
+    let symbol_name = strtab.add(func.name);
+    let mut symtab = Buffer::new(endianness);
+    symtab.data.resize(SYMBOL_SIZE as usize, 0);
+    symtab.put4(symbol_name);
+    symtab.put1(STB_GLOBAL_STT_FUNC);
+    symtab.put1(0);
+    symtab.put2(1);
+    symtab.put8(low_pc);
+    symtab.put8(func.size());
+
+    // The sections after the null section, as (name, type, flags, addr, contents, link, info,
+    // entsize). The `.text` section has no contents in the image.
+    let mut shstrtab = StringTable::new();
+    let sections = [
+        (".text", SHT_NOBITS, SHF_ALLOC | SHF_EXECINSTR, low_pc, Vec::new(), 0, 0, 0),
+        (".symtab", SHT_SYMTAB, 0, 0, symtab.data, 3, 1, SYMBOL_SIZE),
+        (".strtab", SHT_STRTAB, 0, 0, strtab.data, 0, 0, 0),
+        (".debug_abbrev", SHT_PROGBITS, 0, 0, abbrev.data, 0, 0, 0),
+        (".debug_info", SHT_PROGBITS, 0, 0, info.data, 0, 0, 0),
+        (".debug_line", SHT_PROGBITS, 0, 0, line.data, 0, 0, 0),
+    ];
+    let names: Vec<u32> = sections.iter().map(|s| shstrtab.add(s.0)).collect();
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    let num_sections = sections.len() as u16 + 2;
+
+    // Write the section contents after the ELF header, and the section headers at the end.
+    let mut elf = Buffer::new(endianness);
+    elf.data.resize(ELF_HEADER_SIZE, 0);
+    let mut offsets = Vec::new();
+    for section in &sections {
+        elf.align(8);
+        offsets.push(elf.data.len() as u64);
+        elf.data.extend_from_slice(&section.4);
+    }
+    let shstrtab_offset = elf.data.len() as u64;
+    elf.data.extend_from_slice(&shstrtab.data);
+    elf.align(8);
+    let shoff = elf.data.len() as u64;
+
+    elf.data.resize(shoff as usize + SECTION_HEADER_SIZE, 0);
+    for (i, section) in sections.iter().enumerate() {
+        let size = match section.1 {
+            SHT_NOBITS => func.size(),
+            _ => section.4.len() as u64,
+        };
+        elf.put4(names[i]);
+        elf.put4(section.1);
+        elf.put8(section.2);
+        elf.put8(section.3);
+        elf.put8(offsets[i]);
+        elf.put8(size);
+        elf.put4(section.5);
+        elf.put4(section.6);
+        elf.put8(if section.1 == SHT_PROGBITS { 1 } else { 8 });
+        elf.put8(section.7);
+    }
+    elf.put4(shstrtab_name);
+    elf.put4(SHT_STRTAB);
+    elf.put8(0);
+    elf.put8(0);
+    elf.put8(shstrtab_offset);
+    elf.put8(shstrtab.data.len() as u64);
+    elf.put4(0);
+    elf.put4(0);
+    elf.put8(1);
+    elf.put8(0);
+
+    let mut header = Buffer::new(endianness);
+    header.data.extend_from_slice(b"\x7fELF");
+    // 64-bit, byte order, version 1, System V ABI.
+    header.put1(2);
+    header.put1(match endianness {
+        Endianness::Little => 1,
+        Endianness::Big => 2,
+    });
+    header.put1(1);
+    header.data.resize(16, 0);
+    header.put2(ET_EXEC);
+    header.put2(elf_machine(isa) as u16);
+    header.put4(1);
+    // No entry point or program headers.
+    header.put8(0);
+    header.put8(0);
+    header.put8(shoff);
+    header.put4(0);
+    header.put2(ELF_HEADER_SIZE as u16);
+    header.put2(0);
+    header.put2(0);
+    header.put2(SECTION_HEADER_SIZE as u16);
+    header.put2(num_sections);
+    header.put2(num_sections - 1);
+    elf.data[..ELF_HEADER_SIZE].copy_from_slice(&header.data);
+    elf.data
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use binemit::PerfLine;
+    use isa;
+    use settings::{self, Configurable};
+
+    fn get(data: &[u8], offset: usize, size: usize) -> u64 {
+        (0..size).fold(0, |x, i| x | u64::from(data[offset + i]) << (8 * i))
+    }
+
+    #[test]
+    fn image() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let code = [0x90; 32];
+        let func = PerfFunction {
+            name: "foo",
+            addr: 0x1000,
+            code: &code,
+            lines: vec![
+                PerfLine {
+                    offset: 0,
+                    file: "a.rs",
+                    line: 10,
+                },
+                PerfLine {
+                    offset: 4,
+                    file: "a.rs",
+                    line: 8,
+                },
+                PerfLine {
+                    offset: 12,
+                    file: "b.rs",
+                    line: 200,
+                },
+            ],
+        };
+        let elf = debug_elf_image(&func, &*isa);
+        assert_eq!(&elf[..7], b"\x7fELF\x02\x01\x01");
+        assert_eq!(get(&elf, 18, 2), 62);
+
+        // Find the sections by name.
+        let shoff = get(&elf, 40, 8) as usize;
+        let shnum = get(&elf, 60, 2) as usize;
+        let shstrndx = get(&elf, 62, 2) as usize;
+        assert_eq!(shnum, 8);
+        let header = |i: usize| shoff + i * SECTION_HEADER_SIZE;
+        let names = get(&elf, header(shstrndx) + 24, 8) as usize;
+        let section = |name: &str| {
+            (1..shnum)
+                .find(|&i| {
+                    let start = names + get(&elf, header(i), 4) as usize;
+                    elf[start..].starts_with(name.as_bytes()) && elf[start + name.len()] == 0
+                })
+                .map(|i| {
+                    let offset = get(&elf, header(i) + 24, 8) as usize;
+                    let size = get(&elf, header(i) + 32, 8) as usize;
+                    (get(&elf, header(i) + 16, 8), &elf[offset..offset + size])
+                })
+                .unwrap()
+        };
+
+        // The `.text` section has the size of the code, but no contents in the image.
+        let (addr, text) = section(".text");
+        assert_eq!((addr, text.len()), (0x1000, 32));
+        assert_eq!(get(&elf, header(1) + 4, 4), u64::from(SHT_NOBITS));
+
+        // The function symbol.
+        let (_, symtab) = section(".symtab");
+        let (_, strtab) = section(".strtab");
+        assert_eq!(symtab.len(), 48);
+        assert_eq!(&strtab[get(symtab, 24, 4) as usize..][..4], b"foo\0");
+        assert_eq!(symtab[28], STB_GLOBAL_STT_FUNC);
+        assert_eq!(get(symtab, 32, 8), 0x1000);
+        assert_eq!(get(symtab, 40, 8), 32);
+
+        // The line program after the header with the two file names.
+        let (_, line) = section(".debug_line");
+        assert_eq!(get(line, 0, 4) as usize, line.len() - 4);
+        let program = &line[10 + get(line, 6, 4) as usize..];
+        assert_eq!(
+            program,
+            &[
+                0, 9, DW_LNE_SET_ADDRESS, 0, 0x10, 0, 0, 0, 0, 0, 0,
+                DW_LNS_ADVANCE_LINE, 9,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_PC, 4,
+                DW_LNS_ADVANCE_LINE, 0x7e,
+                DW_LNS_COPY,
+                DW_LNS_SET_FILE, 2,
+                DW_LNS_ADVANCE_PC, 8,
+                DW_LNS_ADVANCE_LINE, 0xc0, 0x01,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_PC, 20,
+                0, 1, DW_LNE_END_SEQUENCE,
+            ][..]
+        );
+        assert!(line.windows(5).any(|w| w == b"b.rs\0"));
+
+        // The compilation unit is named after the first file.
+        let (_, info) = section(".debug_info");
+        assert_eq!(&info[12..17], b"a.rs\0");
+    }
+}
@@ -0,0 +1,255 @@
+//! Code sink that writes linked machine code directly into executable memory.
+//!
+//! A JIT embedder using `Context::emit_to_memory()` gets the relocations in a `RelocSink` and has
+//! to apply them in a second pass over the code. An embedder that compiles many small functions
+//! into memory it has already mapped can instead use a `DirectCodeSink`, which resolves every
+//! external symbol with a callback while the code is emitted. The code is written exactly once,
+//! and nothing is allocated per function or per relocation.
+
+use ir::{ExternalName, JumpTable, SourceLoc, TrapCode};
+use super::{Addend, CodeOffset, CodeSink, Endianness, Reloc, RelocError, SymbolInfo, TrapSink};
+use super::relocs::RelocField;
+
+/// A `CodeSink` that writes machine code into a slice and resolves relocations immediately.
+///
+/// The `resolve` callback provides the address of each external symbol, and the relocations are
+/// applied like `Relocation::apply()` does, given the address `code_addr` that the code will
+/// execute from. This can differ from the address of the slice when the executable memory is
+/// mapped twice. The trap sites are forwarded to the `TrapSink` trait object.
+///
+/// The `CodeSink` methods can't report errors, so the first relocation error is kept and returned
+/// by `finish()`. The code is not valid after an error.
+pub struct DirectCodeSink<'a, F>
+where
+    F: FnMut(&ExternalName) -> Option<u64>,
+{
+    code: &'a mut [u8],
+    code_addr: u64,
+    offset: CodeOffset,
+    traps: &'a mut TrapSink,
+    endianness: Endianness,
+    resolve: F,
+    // A relocation whose field hasn't been emitted yet, and the address of its symbol.
+    pending: Option<(RelocField, u64)>,
+    error: Option<RelocError>,
+}
+
+impl<'a, F> DirectCodeSink<'a, F>
+where
+    F: FnMut(&ExternalName) -> Option<u64>,
+{
+    /// Create a code sink that writes a function to `code` which will execute from `code_addr`.
+    ///
+    /// The slice must be large enough for the code size returned by `Context::compile()`.
+    pub fn new(
+        code: &'a mut [u8],
+        code_addr: u64,
+        traps: &'a mut TrapSink,
+        endianness: Endianness,
+        resolve: F,
+    ) -> Self {
+        Self {
+            code,
+            code_addr,
+            offset: 0,
+            traps,
+            endianness,
+            resolve,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Apply the last relocation, and return the number of bytes emitted or the first relocation
+    /// error.
+    pub fn finish(mut self) -> Result<CodeOffset, RelocError> {
+        self.apply_pending();
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self.offset),
+        }
+    }
+
+    /// Append `bytes` to the code.
+    fn put(&mut self, bytes: &[u8]) {
+        let start = self.offset as usize;
+        self.code[start..start + bytes.len()].copy_from_slice(bytes);
+        self.offset += bytes.len() as CodeOffset;
+    }
+
+    /// Apply the pending relocation.
+    ///
+    /// A relocation is emitted before the bytes it patches, so it can only be applied once the
+    /// sink has moved on to the next relocation or the end of the function.
+    fn apply_pending(&mut self) {
+        if let Some((field, target)) = self.pending.take() {
+            let result = field.apply(self.code, self.code_addr, target, self.endianness);
+            if let Err(e) = result {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+}
+
+impl<'a, F> CodeSink for DirectCodeSink<'a, F>
+where
+    F: FnMut(&ExternalName) -> Option<u64>,
+{
+    fn offset(&self) -> CodeOffset {
+        self.offset
+    }
+
+    fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    fn put1(&mut self, x: u8) {
+        self.put(&[x]);
+    }
+
+    fn put2(&mut self, x: u16) {
+        match self.endianness {
+            Endianness::Little => self.put(&[x as u8, (x >> 8) as u8]),
+            Endianness::Big => self.put(&[(x >> 8) as u8, x as u8]),
+        }
+    }
+
+    fn put4(&mut self, x: u32) {
+        let (first, second) = match self.endianness {
+            Endianness::Little => (x as u16, (x >> 16) as u16),
+            Endianness::Big => ((x >> 16) as u16, x as u16),
+        };
+        self.put2(first);
+        self.put2(second);
+    }
+
+    fn put8(&mut self, x: u64) {
+        let (first, second) = match self.endianness {
+            Endianness::Little => (x as u32, (x >> 32) as u32),
+            Endianness::Big => ((x >> 32) as u32, x as u32),
+        };
+        self.put4(first);
+        self.put4(second);
+    }
+
+    fn reloc_ebb(&mut self, kind: Reloc, _: CodeOffset) {
+        // Branches within the function are resolved during emission.
+        panic!("Unexpected EBB relocation {:?} at offset {}", kind, self.offset);
+    }
+
+    fn reloc_external(&mut self, kind: Reloc, name: &ExternalName, addend: Addend, _: SymbolInfo) {
+        self.apply_pending();
+        let field = RelocField {
+            kind,
+            offset: self.offset,
+            addend,
+        };
+        match (self.resolve)(name) {
+            Some(target) => self.pending = Some((field, target)),
+            None => {
+                self.error.get_or_insert(RelocError::Unresolved(name.clone()));
+            }
+        }
+    }
+
+    fn reloc_jt(&mut self, kind: Reloc, _: JumpTable) {
+        panic!("Unexpected jump table relocation {:?} at offset {}", kind, self.offset);
+    }
+
+    fn trap(&mut self, code: TrapCode, srcloc: SourceLoc) {
+        self.traps.trap(self.offset, srcloc, code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use binemit::{NullTrapSink, Relocations};
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, CallConv, ExtFuncData, InstBuilder, Signature};
+    use isa;
+    use settings::{self, Configurable};
+    use std::vec::Vec;
+
+    // A function calling `foo` twice, with a colocated call and an absolute one.
+    fn function() -> Context {
+        let mut ctx = Context::new();
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I32));
+        let signature = ctx.func.import_signature(sig);
+        let near = ctx.func.import_function(ExtFuncData {
+            name: ExternalName::testcase("foo"),
+            signature,
+            colocated: true,
+        });
+        let far = ctx.func.import_function(ExtFuncData {
+            name: ExternalName::testcase("foo"),
+            signature,
+            colocated: false,
+        });
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let mut pos = FuncCursor::new(&mut ctx.func);
+        pos.insert_ebb(ebb0);
+        let call = pos.ins().call(near, &[]);
+        let v0 = pos.func.dfg.first_result(call);
+        let call = pos.ins().call(far, &[]);
+        let v1 = pos.func.dfg.first_result(call);
+        let v2 = pos.ins().iadd(v0, v1);
+        pos.ins().return_(&[v2]);
+        ctx
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn same_as_relocated() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let mut ctx = function();
+        let size = ctx.compile(&*isa).unwrap();
+        let resolve = |name: &ExternalName| if *name == ExternalName::testcase("foo") {
+            Some(0x1000 - 0x20)
+        } else {
+            None
+        };
+
+        let mut mem = vec![0; size as usize];
+        let mut relocs = Relocations::new();
+        ctx.emit_to_memory(mem.as_mut_ptr(), &mut relocs, &mut NullTrapSink {}, &*isa);
+        assert_eq!(relocs.len(), 2);
+        relocs.apply(&mut mem, 0x1000, resolve).unwrap();
+
+        let mut code = vec![0; size as usize];
+        let mut traps = NullTrapSink {};
+        let written = ctx.emit_to_memory_linked(&mut code, 0x1000, resolve, &mut traps, &*isa)
+            .unwrap();
+        assert_eq!(written, size);
+        assert_eq!(code, mem);
+
+        // The first unresolved symbol is reported.
+        let mut code = vec![0; size as usize];
+        assert_eq!(
+            ctx.emit_to_memory_linked(&mut code, 0x1000, |_| None, &mut traps, &*isa),
+            Err(RelocError::Unresolved(ExternalName::testcase("foo")))
+        );
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut code = Vec::new();
+        code.resize(15, 0);
+        let mut traps = NullTrapSink {};
+        {
+            let mut sink = DirectCodeSink::new(&mut code, 0, &mut traps, Endianness::Big, |_| None);
+            sink.put1(1);
+            sink.put2(0x0203);
+            sink.put4(0x0405_0607);
+            sink.put8(0x0809_0a0b_0c0d_0e0f);
+            assert_eq!(sink.finish(), Ok(15));
+        }
+        assert_eq!(code, (1..16).collect::<Vec<u8>>());
+    }
+}
@@ -0,0 +1,428 @@
+//! Frame descriptions for walking the stack frames of compiled functions.
+//!
+//! Capturing a stack trace of generated code doesn't require a full unwinder. Every frame of a
+//! compiled function has a fixed size once its prologue has run, so the caller's frame can be
+//! found from the stack pointer alone. The target ISA describes the frame of a function with
+//! `TargetIsa::frame_description()`.
+//!
+//! An embedder collects the descriptions of all the functions in a code region with a
+//! `FrameTableWriter`, and walks the stack with a `FrameWalker` reading the encoded table. The
+//! encoding is made of unsigned LEB128 numbers, except for the leading version byte:
+//!
+//! ```text
+//! table := version:u8 word_size count entry*
+//! entry := start_delta code_size prologue_end frame_words ra_word fp_word
+//! ```
+//!
+//! The entries are sorted by code offset, and `start_delta` is the distance from the end of the
+//! previous function, or from 0 for the first function. The frame size and the offsets of the
+//! saved return address and frame pointer are counted in words. The `fp_word` is one more than
+//! the word offset of the saved frame pointer, or 0 if the function doesn't save it.
+
+use binemit::CodeOffset;
+use super::leb128::{Leb128Error, get_uleb128, put_uleb128};
+use std::error::Error as StdError;
+use std::fmt;
+use std::vec::Vec;
+
+/// The version of the encoding produced by `FrameTableWriter`.
+pub const FRAME_TABLE_FORMAT_VERSION: u8 = 1;
+
+/// A description of the stack frame of a compiled function.
+///
+/// The description applies to all the code offsets following the prologue, except inside the
+/// epilogues and the frameless exits of a shrink-wrapped prologue. That includes all the call
+/// sites of the function, so it holds for every frame of a stack trace except possibly the
+/// innermost one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDescription {
+    /// Code offset following the last instruction of the prologue.
+    pub prologue_end: CodeOffset,
+
+    /// Number of bytes to add to the stack pointer to get the stack pointer of the caller after
+    /// the function returns.
+    pub frame_size: u32,
+
+    /// Offset from the stack pointer to the saved return address.
+    pub return_address_offset: u32,
+
+    /// Offset from the stack pointer to the saved frame pointer of the caller, if the function
+    /// uses a frame pointer.
+    pub frame_pointer_offset: Option<u32>,
+}
+
+/// An error found while decoding a frame table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameTableError {
+    /// The data was encoded with an unknown version of the format.
+    UnsupportedVersion(u8),
+
+    /// The data ends in the middle of an entry.
+    Truncated,
+
+    /// A number in the data is out of range.
+    Corrupt,
+}
+
+impl fmt::Display for FrameTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameTableError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported frame table format version {}", v)
+            }
+            FrameTableError::Truncated => write!(f, "Truncated frame table"),
+            FrameTableError::Corrupt => write!(f, "Corrupt frame table"),
+        }
+    }
+}
+
+impl StdError for FrameTableError {
+    fn description(&self) -> &str {
+        match *self {
+            FrameTableError::UnsupportedVersion(_) => "Unsupported frame table format version",
+            FrameTableError::Truncated => "Truncated frame table",
+            FrameTableError::Corrupt => "Corrupt frame table",
+        }
+    }
+}
+
+impl From<Leb128Error> for FrameTableError {
+    fn from(e: Leb128Error) -> Self {
+        match e {
+            Leb128Error::Truncated => FrameTableError::Truncated,
+            Leb128Error::Overflow => FrameTableError::Corrupt,
+        }
+    }
+}
+
+/// Result of decoding a frame table.
+pub type Result<T> = ::std::result::Result<T, FrameTableError>;
+
+/// A writer encoding the frame descriptions of the functions in a code region.
+#[derive(Clone, Debug)]
+pub struct FrameTableWriter {
+    word_size: u32,
+    count: u32,
+    end: CodeOffset,
+    entries: Vec<u8>,
+}
+
+impl FrameTableWriter {
+    /// Create a writer for frames where all the sizes and offsets are multiples of `word_size`.
+    ///
+    /// This is normally the pointer size of the target.
+    pub fn new(word_size: u32) -> Self {
+        assert!(word_size > 0, "Zero word size");
+        Self {
+            word_size,
+            count: 0,
+            end: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add the description of a function whose code is at `start..start + code_size` in the code
+    /// region.
+    ///
+    /// Functions must be added in code order, and they must not overlap.
+    pub fn add_function(
+        &mut self,
+        start: CodeOffset,
+        code_size: CodeOffset,
+        frame: &FrameDescription,
+    ) {
+        assert!(start >= self.end, "Functions must be added in code order");
+        let words = |bytes: u32| {
+            assert_eq!(bytes % self.word_size, 0, "Misaligned frame offset {}", bytes);
+            bytes / self.word_size
+        };
+        let frame_words = words(frame.frame_size);
+        let ra_word = words(frame.return_address_offset);
+        let fp_word = frame.frame_pointer_offset.map_or(0, |offset| words(offset) + 1);
+
+        put_uleb128(&mut self.entries, start - self.end);
+        put_uleb128(&mut self.entries, code_size);
+        put_uleb128(&mut self.entries, frame.prologue_end);
+        put_uleb128(&mut self.entries, frame_words);
+        put_uleb128(&mut self.entries, ra_word);
+        put_uleb128(&mut self.entries, fp_word);
+        self.end = start + code_size;
+        self.count += 1;
+    }
+
+    /// Get the encoded frame table.
+    pub fn finish(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.entries.len() + 6);
+        out.push(FRAME_TABLE_FORMAT_VERSION);
+        put_uleb128(&mut out, self.word_size);
+        put_uleb128(&mut out, self.count);
+        out.extend_from_slice(&self.entries);
+        out
+    }
+}
+
+/// A reader for frame tables encoded by `FrameTableWriter`.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTableReader<'a> {
+    word_size: u32,
+    count: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> FrameTableReader<'a> {
+    /// Create a reader for the encoded frame table in `data`.
+    ///
+    /// Only the header is checked here.
+    pub fn new(mut data: &'a [u8]) -> Result<Self> {
+        let (&version, rest) = data.split_first().ok_or(FrameTableError::Truncated)?;
+        if version != FRAME_TABLE_FORMAT_VERSION {
+            return Err(FrameTableError::UnsupportedVersion(version));
+        }
+        data = rest;
+        let word_size = get_uleb128(&mut data)?;
+        if word_size == 0 || word_size > 8 {
+            return Err(FrameTableError::Corrupt);
+        }
+        let count = get_uleb128(&mut data)?;
+        Ok(Self {
+            word_size,
+            count,
+            entries: data,
+        })
+    }
+
+    /// Get the size in bytes of the words read from stack frames.
+    pub fn word_size(&self) -> u32 {
+        self.word_size
+    }
+
+    /// Find the function containing the code offset `offset`.
+    ///
+    /// Returns the code offset of the start of the function and its frame description.
+    pub fn lookup(&self, offset: CodeOffset) -> Result<Option<(CodeOffset, FrameDescription)>> {
+        let mut data = self.entries;
+        let mut end: CodeOffset = 0;
+        for _ in 0..self.count {
+            let start = end.checked_add(get_uleb128(&mut data)?).ok_or(
+                FrameTableError::Corrupt,
+            )?;
+            let code_size = get_uleb128(&mut data)?;
+            end = start.checked_add(code_size).ok_or(FrameTableError::Corrupt)?;
+            let mut fields = [0; 4];
+            for field in &mut fields {
+                *field = get_uleb128(&mut data)?;
+            }
+            if offset < start {
+                break;
+            }
+            if offset < end {
+                let bytes = |words: u32| {
+                    words.checked_mul(self.word_size).ok_or(
+                        FrameTableError::Corrupt,
+                    )
+                };
+                let frame = FrameDescription {
+                    prologue_end: fields[0],
+                    frame_size: bytes(fields[1])?,
+                    return_address_offset: bytes(fields[2])?,
+                    frame_pointer_offset: match fields[3] {
+                        0 => None,
+                        w => Some(bytes(w - 1)?),
+                    },
+                };
+                return Ok(Some((start, frame)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The registers identifying a stack frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalkFrame {
+    /// The program counter. For all but the innermost frame, this is a return address.
+    pub pc: u64,
+
+    /// The stack pointer.
+    pub sp: u64,
+
+    /// The frame pointer.
+    pub fp: u64,
+}
+
+/// An iterator over the frames of compiled functions on a stack.
+///
+/// The walk starts from a frame whose program counter is at a call site or a trap site in a
+/// function described by the frame table. Each step reads the return address and saved frame
+/// pointer with a `read_word` callback, so the walker never touches memory directly. The walk
+/// ends at the first frame outside the code region, which is available from `exit_frame()`.
+pub struct FrameWalker<'a, R> {
+    table: FrameTableReader<'a>,
+    code_base: u64,
+    read_word: R,
+    next: Option<WalkFrame>,
+    exit: Option<WalkFrame>,
+}
+
+impl<'a, R> FrameWalker<'a, R>
+where
+    R: FnMut(u64) -> Option<u64>,
+{
+    /// Create a walker starting at `frame` for the code region at `code_base` described by
+    /// `table`.
+    ///
+    /// The `read_word` callback reads a word of `table.word_size()` bytes at an address, or
+    /// returns `None` if the address can't be read.
+    pub fn new(
+        table: FrameTableReader<'a>,
+        code_base: u64,
+        frame: WalkFrame,
+        read_word: R,
+    ) -> Self {
+        Self {
+            table,
+            code_base,
+            read_word,
+            next: Some(frame),
+            exit: None,
+        }
+    }
+
+    /// Get the first frame outside the code region, once the walk has reached it.
+    ///
+    /// This is typically a frame of the embedder which called into the generated code.
+    pub fn exit_frame(&self) -> Option<WalkFrame> {
+        self.exit
+    }
+
+    /// Compute the caller of `frame` in a function starting at `start`.
+    fn step(
+        &mut self,
+        frame: WalkFrame,
+        start: CodeOffset,
+        desc: FrameDescription,
+    ) -> Option<WalkFrame> {
+        if frame.pc < self.code_base + u64::from(start + desc.prologue_end) {
+            // The frame isn't set up yet.
+            return None;
+        }
+        let pc = (self.read_word)(frame.sp + u64::from(desc.return_address_offset))?;
+        let fp = match desc.frame_pointer_offset {
+            Some(offset) => (self.read_word)(frame.sp + u64::from(offset))?,
+            None => frame.fp,
+        };
+        Some(WalkFrame {
+            pc,
+            sp: frame.sp + u64::from(desc.frame_size),
+            fp,
+        })
+    }
+}
+
+impl<'a, R> Iterator for FrameWalker<'a, R>
+where
+    R: FnMut(u64) -> Option<u64>,
+{
+    type Item = Result<WalkFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.next.take()?;
+        let offset = frame.pc.wrapping_sub(self.code_base);
+        let found = if offset <= u64::from(CodeOffset::max_value()) {
+            match self.table.lookup(offset as CodeOffset) {
+                Ok(found) => found,
+                Err(e) => return Some(Err(e)),
+            }
+        } else {
+            None
+        };
+        match found {
+            Some((start, desc)) => {
+                self.next = self.step(frame, start, desc);
+                Some(Ok(frame))
+            }
+            None => {
+                self.exit = Some(frame);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn desc(prologue_end: u32, frame_size: u32, fp: Option<u32>) -> FrameDescription {
+        FrameDescription {
+            prologue_end,
+            frame_size,
+            return_address_offset: frame_size - 8,
+            frame_pointer_offset: fp,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut writer = FrameTableWriter::new(8);
+        writer.add_function(0, 100, &desc(4, 16, Some(0)));
+        writer.add_function(112, 300, &desc(10, 64, Some(48)));
+        let data = writer.finish();
+        assert_eq!(&data[..9], &[1, 8, 2, 0, 100, 4, 2, 1, 1]);
+
+        let reader = FrameTableReader::new(&data).unwrap();
+        assert_eq!(reader.lookup(0), Ok(Some((0, desc(4, 16, Some(0))))));
+        assert_eq!(reader.lookup(99), Ok(Some((0, desc(4, 16, Some(0))))));
+        assert_eq!(reader.lookup(100), Ok(None));
+        assert_eq!(reader.lookup(411), Ok(Some((112, desc(10, 64, Some(48))))));
+        assert_eq!(reader.lookup(412), Ok(None));
+
+        assert_eq!(
+            FrameTableReader::new(&[2, 8, 0]).unwrap_err(),
+            FrameTableError::UnsupportedVersion(2)
+        );
+        let reader = FrameTableReader::new(&data[..10]).unwrap();
+        assert_eq!(reader.lookup(200), Err(FrameTableError::Truncated));
+    }
+
+    #[test]
+    fn walk() {
+        let mut writer = FrameTableWriter::new(8);
+        writer.add_function(0, 100, &desc(4, 32, Some(16)));
+        writer.add_function(100, 100, &desc(4, 16, None));
+        let data = writer.finish();
+        let table = FrameTableReader::new(&data).unwrap();
+
+        // The function at 0x1100 was called from the one at 0x1000, which was called from the
+        // embedder at 0x5000.
+        let mut memory = HashMap::new();
+        memory.insert(0x7f08, 0x1040);
+        memory.insert(0x7f20, 0x7fa0);
+        memory.insert(0x7f28, 0x5000);
+        let start = WalkFrame {
+            pc: 0x1080,
+            sp: 0x7f00,
+            fp: 0x7f20,
+        };
+        let mut walker = FrameWalker::new(table, 0x1000, start, |addr| memory.get(&addr).cloned());
+        assert_eq!(walker.next(), Some(Ok(start)));
+        assert_eq!(
+            walker.next(),
+            Some(Ok(WalkFrame {
+                pc: 0x1040,
+                sp: 0x7f10,
+                fp: 0x7f20,
+            }))
+        );
+        assert_eq!(walker.next(), None);
+        assert_eq!(
+            walker.exit_frame(),
+            Some(WalkFrame {
+                pc: 0x5000,
+                sp: 0x7f30,
+                fp: 0x7fa0,
+            })
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Registration of debug images with the GDB JIT interface.
+//!
+//! GDB and LLDB find JIT-compiled code through a list of in-memory object files rooted in the
+//! `__jit_debug_descriptor` symbol. The debugger sets a breakpoint in `__jit_debug_register_code`,
+//! which is called every time an object file is added to or removed from the list.
+//!
+//! These symbols must be unique in a process, so this module is only included with the `gdb-jit`
+//! feature.
+
+use std::boxed::Box;
+use std::ptr;
+use std::sync::Mutex;
+use std::vec::Vec;
+
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+/// An entry in the list of object files, as defined by the GDB JIT interface.
+#[repr(C)]
+#[doc(hidden)]
+pub struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+/// The root of the list of object files, as defined by the GDB JIT interface.
+#[repr(C)]
+#[doc(hidden)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+/// The descriptor read by the debugger.
+#[no_mangle]
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: JIT_NOACTION,
+    relevant_entry: ptr::null_mut(),
+    first_entry: ptr::null_mut(),
+};
+
+/// The function the debugger sets a breakpoint in.
+#[no_mangle]
+#[inline(never)]
+#[doc(hidden)]
+pub extern "C" fn __jit_debug_register_code() {
+    // Keep the call from being optimized away.
+    unsafe {
+        ptr::read_volatile(&0u8);
+    }
+}
+
+/// Serializes the changes to `__jit_debug_descriptor`.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Notify the debugger that `entry` has been added or removed.
+unsafe fn notify(action: u32, entry: *mut JitCodeEntry) {
+    let descriptor = ptr::addr_of_mut!(__jit_debug_descriptor);
+    (*descriptor).action_flag = action;
+    (*descriptor).relevant_entry = entry;
+    __jit_debug_register_code();
+    (*descriptor).action_flag = JIT_NOACTION;
+    (*descriptor).relevant_entry = ptr::null_mut();
+}
+
+/// A debug image registered with the GDB JIT interface.
+///
+/// The image is unregistered when this is dropped, which must happen before the code it describes
+/// is freed.
+pub struct GdbJitImage {
+    entry: Box<JitCodeEntry>,
+    image: Vec<u8>,
+}
+
+// The raw pointers are only accessed while holding `LOCK`.
+unsafe impl Send for GdbJitImage {}
+
+impl GdbJitImage {
+    /// Register `image`, normally created by `binemit::debug_elf_image()`, with the debugger.
+    pub fn register(image: Vec<u8>) -> Self {
+        let mut entry = Box::new(JitCodeEntry {
+            next_entry: ptr::null_mut(),
+            prev_entry: ptr::null_mut(),
+            symfile_addr: image.as_ptr(),
+            symfile_size: image.len() as u64,
+        });
+        let _guard = LOCK.lock().unwrap();
+        unsafe {
+            let descriptor = ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *entry;
+            entry.next_entry = (*descriptor).first_entry;
+            if !entry.next_entry.is_null() {
+                (*entry.next_entry).prev_entry = entry_ptr;
+            }
+            (*descriptor).first_entry = entry_ptr;
+            notify(JIT_REGISTER_FN, entry_ptr);
+        }
+        Self { entry, image }
+    }
+
+    /// Get the registered image.
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+}
+
+impl Drop for GdbJitImage {
+    fn drop(&mut self) {
+        let _guard = LOCK.lock().unwrap();
+        unsafe {
+            let descriptor = ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry_ptr: *mut JitCodeEntry = &mut *self.entry;
+            let (prev, next) = (self.entry.prev_entry, self.entry.next_entry);
+            if prev.is_null() {
+                (*descriptor).first_entry = next;
+            } else {
+                (*prev).next_entry = next;
+            }
+            if !next.is_null() {
+                (*next).prev_entry = prev;
+            }
+            notify(JIT_UNREGISTER_FN, entry_ptr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The registered images, from the first entry.
+    fn registered() -> Vec<*const u8> {
+        let _guard = LOCK.lock().unwrap();
+        let mut images = Vec::new();
+        unsafe {
+            let mut entry = (*ptr::addr_of!(__jit_debug_descriptor)).first_entry;
+            while !entry.is_null() {
+                images.push((*entry).symfile_addr);
+                entry = (*entry).next_entry;
+            }
+        }
+        images
+    }
+
+    #[test]
+    fn register() {
+        let a = GdbJitImage::register(vec![1; 4]);
+        let b = GdbJitImage::register(vec![2; 8]);
+        let c = GdbJitImage::register(vec![3; 16]);
+        assert_eq!(
+            registered(),
+            [c.image().as_ptr(), b.image().as_ptr(), a.image().as_ptr()]
+        );
+
+        let (a_ptr, c_ptr) = (a.image().as_ptr(), c.image().as_ptr());
+        drop(b);
+        assert_eq!(registered(), [c_ptr, a_ptr]);
+        drop(c);
+        assert_eq!(registered(), [a_ptr]);
+        drop(a);
+        assert!(registered().is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+//! LEB128 numbers for the compact binary formats produced by `binemit`.
+
+use std::vec::Vec;
+
+/// An error found while reading a LEB128 number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Leb128Error {
+    /// The data ends in the middle of the number.
+    Truncated,
+
+    /// The number doesn't fit in 32 bits.
+    Overflow,
+}
+
+/// Append `value` to `out` as an unsigned LEB128 number.
+pub fn put_uleb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append `value` to `out` as a signed LEB128 number.
+pub fn put_sleb128(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // Stop when the sign bit of `byte` matches the remaining bits.
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Read an unsigned LEB128 number from the front of `data`.
+pub fn get_uleb128(data: &mut &[u8]) -> Result<u32, Leb128Error> {
+    let mut value: u32 = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = data.split_first().ok_or(Leb128Error::Truncated)?;
+        *data = rest;
+        if shift > 28 || (shift == 28 && byte & 0x70 != 0) {
+            return Err(Leb128Error::Overflow);
+        }
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
@@ -11,11 +11,12 @@
 //! The `MemoryCodeSink` type fixes the performance problem because it is a type known to
 //! `TargetIsa` so it can specialize its machine code generation for the type. The trade-off is
 //! that a `MemoryCodeSink` will always write binary machine code to raw memory. It forwards any
-//! relocations to a `RelocSink` trait object. Relocations are less frequent than the
-//! `CodeSink::put*` methods, so the performance impact of the virtual callbacks is less severe.
+//! relocations to a `RelocSink` trait object, and any trap sites to a `TrapSink` trait object.
+//! Relocations and traps are less frequent than the `CodeSink::put*` methods, so the performance
+//! impact of the virtual callbacks is less severe.
 
-use ir::{ExternalName, JumpTable};
-use super::{CodeSink, CodeOffset, Reloc, Addend};
+use ir::{ExternalName, JumpTable, SourceLoc, TrapCode};
+use super::{CodeSink, CodeOffset, Endianness, Reloc, Addend, SymbolInfo};
 use std::ptr::write_unaligned;
 
 /// A `CodeSink` that writes binary machine code directly into memory.
@@ -25,23 +26,33 @@ use std::ptr::write_unaligned;
 /// sure to allocate enough memory for the whole function. The number of bytes required is returned
 /// by the `Context::compile()` function.
 ///
-/// Any relocations in the function are forwarded to the `RelocSink` trait object.
+/// Any relocations in the function are forwarded to the `RelocSink` trait object, and the trap
+/// sites are forwarded to the `TrapSink` trait object.
 ///
-/// Note that `MemoryCodeSink` writes multi-byte values in the native byte order of the host. This
-/// is not the right thing to do for cross compilation.
+/// Multi-byte values are written in the byte order given to `new()`, which should be the
+/// `TargetIsa::endianness()` of the target.
 pub struct MemoryCodeSink<'a> {
     data: *mut u8,
     offset: isize,
     relocs: &'a mut RelocSink,
+    traps: &'a mut TrapSink,
+    endianness: Endianness,
 }
 
 impl<'a> MemoryCodeSink<'a> {
     /// Create a new memory code sink that writes a function to the memory pointed to by `data`.
-    pub fn new(data: *mut u8, relocs: &mut RelocSink) -> MemoryCodeSink {
+    pub fn new(
+        data: *mut u8,
+        relocs: &'a mut RelocSink,
+        traps: &'a mut TrapSink,
+        endianness: Endianness,
+    ) -> Self {
         MemoryCodeSink {
             data,
             offset: 0,
             relocs,
+            traps,
+            endianness,
         }
     }
 }
@@ -51,18 +62,39 @@ pub trait RelocSink {
     /// Add a relocation referencing an EBB at the current offset.
     fn reloc_ebb(&mut self, CodeOffset, Reloc, CodeOffset);
 
-    /// Add a relocation referencing an external symbol at the current offset.
-    fn reloc_external(&mut self, CodeOffset, Reloc, &ExternalName, Addend);
+    /// Add a relocation referencing an external symbol plus the addend at the current offset.
+    fn reloc_external(&mut self, CodeOffset, Reloc, &ExternalName, Addend, SymbolInfo);
 
     /// Add a relocation referencing a jump table.
     fn reloc_jt(&mut self, CodeOffset, Reloc, JumpTable);
 }
 
+/// A trait for receiving trap codes and offsets.
+///
+/// If you don't need information about possible traps, you can use the `NullTrapSink`
+/// implementation.
+pub trait TrapSink {
+    /// Add trap information for a specific offset.
+    fn trap(&mut self, CodeOffset, SourceLoc, TrapCode);
+}
+
+/// A `TrapSink` implementation that does nothing, which is convenient when compiling code that
+/// doesn't need trap information.
+pub struct NullTrapSink {}
+
+impl TrapSink for NullTrapSink {
+    fn trap(&mut self, _offset: CodeOffset, _srcloc: SourceLoc, _code: TrapCode) {}
+}
+
 impl<'a> CodeSink for MemoryCodeSink<'a> {
     fn offset(&self) -> CodeOffset {
         self.offset as CodeOffset
     }
 
+    fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
     fn put1(&mut self, x: u8) {
         unsafe {
             write_unaligned(self.data.offset(self.offset), x);
@@ -71,6 +103,10 @@ impl<'a> CodeSink for MemoryCodeSink<'a> {
     }
 
     fn put2(&mut self, x: u16) {
+        let x = match self.endianness {
+            Endianness::Little => x.to_le(),
+            Endianness::Big => x.to_be(),
+        };
         unsafe {
             write_unaligned(self.data.offset(self.offset) as *mut u16, x);
         }
@@ -78,6 +114,10 @@ impl<'a> CodeSink for MemoryCodeSink<'a> {
     }
 
     fn put4(&mut self, x: u32) {
+        let x = match self.endianness {
+            Endianness::Little => x.to_le(),
+            Endianness::Big => x.to_be(),
+        };
         unsafe {
             write_unaligned(self.data.offset(self.offset) as *mut u32, x);
         }
@@ -85,6 +125,10 @@ impl<'a> CodeSink for MemoryCodeSink<'a> {
     }
 
     fn put8(&mut self, x: u64) {
+        let x = match self.endianness {
+            Endianness::Little => x.to_le(),
+            Endianness::Big => x.to_be(),
+        };
         unsafe {
             write_unaligned(self.data.offset(self.offset) as *mut u64, x);
         }
@@ -96,13 +140,24 @@ impl<'a> CodeSink for MemoryCodeSink<'a> {
         self.relocs.reloc_ebb(ofs, rel, ebb_offset);
     }
 
-    fn reloc_external(&mut self, rel: Reloc, name: &ExternalName, addend: Addend) {
+    fn reloc_external(
+        &mut self,
+        rel: Reloc,
+        name: &ExternalName,
+        addend: Addend,
+        symbol: SymbolInfo,
+    ) {
         let ofs = self.offset();
-        self.relocs.reloc_external(ofs, rel, name, addend);
+        self.relocs.reloc_external(ofs, rel, name, addend, symbol);
     }
 
     fn reloc_jt(&mut self, rel: Reloc, jt: JumpTable) {
         let ofs = self.offset();
         self.relocs.reloc_jt(ofs, rel, jt);
     }
+
+    fn trap(&mut self, code: TrapCode, srcloc: SourceLoc) {
+        let ofs = self.offset();
+        self.traps.trap(ofs, srcloc, code);
+    }
 }
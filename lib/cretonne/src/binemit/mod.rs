@@ -3,14 +3,56 @@
 //! The `binemit` module contains code for translating Cretonne's intermediate representation into
 //! binary machine code.
 
+mod call_sites;
+mod check;
+mod compiled_format;
+mod debug_elf;
+mod directsink;
+mod frames;
+#[cfg(feature = "gdb-jit")]
+mod gdbjit;
+mod leb128;
 mod relaxation;
 mod memorysink;
+mod perf;
+mod region;
+mod relocs;
+mod srclocs;
+mod stackmap;
+mod stackmap_format;
+mod textsink;
+mod traps;
+mod unwind;
+mod value_labels;
+mod writersink;
 
 pub use regalloc::RegDiversions;
+pub use self::call_sites::{CallSite, call_site_table};
+pub use self::check::{check_inst, emit_function_checked};
+pub use self::debug_elf::debug_elf_image;
+pub use self::directsink::DirectCodeSink;
+pub use self::compiled_format::{COMPILED_FORMAT_VERSION, CompiledFormatError, decode_compiled,
+                               encode_compiled};
+pub use self::frames::{FRAME_TABLE_FORMAT_VERSION, FrameDescription, FrameTableError,
+                       FrameTableReader, FrameTableWriter, FrameWalker, WalkFrame};
+#[cfg(feature = "gdb-jit")]
+pub use self::gdbjit::GdbJitImage;
 pub use self::relaxation::relax_branches;
-pub use self::memorysink::{MemoryCodeSink, RelocSink};
+pub use self::memorysink::{MemoryCodeSink, NullTrapSink, RelocSink, TrapSink};
+pub use self::perf::{JitDumpWriter, PerfFunction, PerfLine, PerfMapWriter, PerfSink};
+pub use self::region::{CodeRegion, RegionAlias, RegionFunction};
+pub use self::relocs::{Relocation, Relocations, RelocError};
+pub use self::srclocs::{SourceLocEntry, SourceLocTable, srcloc_table};
+pub use self::stackmap::{Stackmap, StackmapSink, emit_stackmaps};
+pub use self::stackmap_format::{StackmapFormatError, StackmapIter, StackmapReader, StackmapWriter,
+                               STACKMAP_FORMAT_VERSION};
+pub use self::textsink::TextSink;
+pub use self::traps::{TrapReport, TrapSite, trap_report};
+pub use self::unwind::{FrameUnwindKind, FrameUnwindSink};
+pub use self::value_labels::{ValueLabelLoc, ValueLabelRange, value_label_ranges};
+pub use self::writersink::{DEFAULT_CHUNK_SIZE, WriterCodeSink};
 
-use ir::{ExternalName, JumpTable, Function, Inst};
+use ir::{ExternalName, ExtFuncData, GlobalVarData, JumpTable, Function, Inst, SourceLoc, TrapCode};
 use std::fmt;
 
 /// Offset in bytes from the beginning of the function.
@@ -22,8 +64,28 @@ pub type CodeOffset = u32;
 /// Addend to add to the symbol value.
 pub type Addend = i64;
 
+/// The byte order of multi-byte values in the machine code and data of a target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// The least significant byte comes first.
+    Little,
+    /// The most significant byte comes first.
+    Big,
+}
+
+impl Endianness {
+    /// Get the byte order of the host.
+    pub fn host() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
+
 /// Relocation kinds for every ISA
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Reloc {
     /// Intel PC-relative 4-byte
     IntelPCRel4,
@@ -35,12 +97,18 @@ pub enum Reloc {
     IntelGOTPCRel4,
     /// Intel PLT-relative 4-byte
     IntelPLTRel4,
+    /// Intel GOT PC-relative 4-byte offset of a TLS variable from the thread pointer
+    IntelGOTTPOff4,
+    /// Intel PC-relative 4-byte TLS general-dynamic descriptor
+    IntelTLSGD4,
     /// Arm32 call target
     Arm32Call,
     /// Arm64 call target
     Arm64Call,
     /// RISC-V call target
     RiscvCall,
+    /// Arm64 absolute 8-byte
+    Arm64Abs8,
 }
 
 impl fmt::Display for Reloc {
@@ -50,14 +118,94 @@ impl fmt::Display for Reloc {
         match *self {
             Reloc::IntelPCRel4 => write!(f, "{}", "PCRel4"),
             Reloc::IntelAbs4 => write!(f, "{}", "Abs4"),
-            Reloc::IntelAbs8 => write!(f, "{}", "Abs8"),
+            Reloc::IntelAbs8 | Reloc::Arm64Abs8 => write!(f, "{}", "Abs8"),
             Reloc::IntelGOTPCRel4 => write!(f, "{}", "GOTPCRel4"),
             Reloc::IntelPLTRel4 => write!(f, "{}", "PLTRel4"),
+            Reloc::IntelGOTTPOff4 => write!(f, "{}", "GOTTPOff4"),
+            Reloc::IntelTLSGD4 => write!(f, "{}", "TLSGD4"),
             Reloc::Arm32Call | Reloc::Arm64Call | Reloc::RiscvCall => write!(f, "{}", "Call"),
         }
     }
 }
 
+/// The kind of section containing the symbol referenced by a relocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// Executable code, like a function.
+    Code,
+    /// Writable data.
+    Data,
+    /// Read-only data.
+    ReadOnlyData,
+    /// Thread-local data.
+    ThreadLocal,
+}
+
+/// What an object file writer needs to know about the symbol referenced by a relocation.
+///
+/// The relocation kind alone doesn't say whether the symbol is code or data, or whether it is
+/// bound within the module, and guessing from it goes wrong for things like weak symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SymbolInfo {
+    /// The kind of section the symbol is defined in.
+    pub kind: SymbolKind,
+
+    /// May the symbol be preempted by a definition in another module?
+    ///
+    /// Symbols that aren't colocated in the IL are preemptible. This includes weak symbols and
+    /// symbols that may resolve to a different definition at load time, so the object file writer
+    /// must not bind references to them within the module.
+    pub preemptible: bool,
+}
+
+impl SymbolInfo {
+    /// Get the symbol information for a call to or the address of the external function `data`.
+    pub fn function(data: &ExtFuncData) -> Self {
+        Self {
+            kind: SymbolKind::Code,
+            preemptible: !data.colocated,
+        }
+    }
+
+    /// Get the symbol information for the global variable `data`, which must be a symbol.
+    pub fn global_var(data: &GlobalVarData) -> Self {
+        match *data {
+            GlobalVarData::Sym {
+                colocated,
+                readonly,
+                ..
+            } => Self {
+                kind: if readonly {
+                    SymbolKind::ReadOnlyData
+                } else {
+                    SymbolKind::Data
+                },
+                preemptible: !colocated,
+            },
+            GlobalVarData::TLS { .. } => Self {
+                kind: SymbolKind::ThreadLocal,
+                preemptible: true,
+            },
+            _ => panic!("only symbols have symbol information"),
+        }
+    }
+}
+
+impl fmt::Display for SymbolInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.kind {
+            SymbolKind::Code => "code",
+            SymbolKind::Data => "data",
+            SymbolKind::ReadOnlyData => "rodata",
+            SymbolKind::ThreadLocal => "tls",
+        })?;
+        if self.preemptible {
+            f.write_str(" preemptible")?;
+        }
+        Ok(())
+    }
+}
+
 /// Abstract interface for adding bytes to the code segment.
 ///
 /// A `CodeSink` will receive all of the machine code for a function. It also accepts relocations
@@ -66,6 +214,9 @@ pub trait CodeSink {
     /// Get the current position.
     fn offset(&self) -> CodeOffset;
 
+    /// Get the byte order of the multi-byte values added by `put2`, `put4`, and `put8`.
+    fn endianness(&self) -> Endianness;
+
     /// Add 1 byte to the code section.
     fn put1(&mut self, u8);
 
@@ -82,10 +233,16 @@ pub trait CodeSink {
     fn reloc_ebb(&mut self, Reloc, CodeOffset);
 
     /// Add a relocation referencing an external symbol plus the addend at the current offset.
-    fn reloc_external(&mut self, Reloc, &ExternalName, Addend);
+    fn reloc_external(&mut self, Reloc, &ExternalName, Addend, SymbolInfo);
 
     /// Add a relocation referencing a jump table.
     fn reloc_jt(&mut self, Reloc, JumpTable);
+
+    /// Add trap information for the instruction at the current offset.
+    ///
+    /// This is called before the bytes of an instruction that can trap, with the trap code that
+    /// the embedder's signal handler should report and the source location of the instruction.
+    fn trap(&mut self, TrapCode, SourceLoc);
 }
 
 /// Report a bad encoding error.
@@ -98,21 +255,80 @@ pub fn bad_encoding(func: &Function, inst: Inst) -> ! {
     );
 }
 
+/// Padding emitter for ISAs that never insert padding between EBBs.
+pub fn no_padding<CS: CodeSink + ?Sized>(bytes: CodeOffset, _sink: &mut CS) {
+    panic!("Unexpected {} bytes of padding", bytes);
+}
+
 /// Emit a function to `sink`, given an instruction emitter function.
 ///
 /// This function is called from the `TargetIsa::emit_function()` implementations with the
-/// appropriate instruction emitter.
-pub fn emit_function<CS, EI>(func: &Function, emit_inst: EI, sink: &mut CS)
+/// appropriate instruction and padding emitters.
+pub fn emit_function<CS, EI, EP>(func: &Function, emit_inst: EI, emit_padding: EP, sink: &mut CS)
 where
     CS: CodeSink,
     EI: Fn(&Function, Inst, &mut RegDiversions, &mut CS),
+    EP: Fn(CodeOffset, &mut CS),
 {
     let mut divert = RegDiversions::new();
     for ebb in func.layout.ebbs() {
         divert.clear();
+        // The ISA may have asked for padding before the EBB in `TargetIsa::ebb_padding()`.
+        debug_assert!(func.offsets[ebb] >= sink.offset());
+        let padding = func.offsets[ebb] - sink.offset();
+        if padding > 0 {
+            emit_padding(padding, sink);
+        }
         debug_assert_eq!(func.offsets[ebb], sink.offset());
         for inst in func.layout.ebb_insts(ebb) {
             emit_inst(func, inst, &mut divert, sink);
         }
     }
+    emit_jump_tables(func, sink);
+    emit_constants(func, sink);
+}
+
+/// Emit the jump tables of `func` to `sink` after the code.
+///
+/// The offsets of the jump tables must have been computed by `relax_branches()`. Each entry is
+/// emitted as a 32-bit number holding the offset of its EBB relative to the start of the table.
+pub fn emit_jump_tables<CS: CodeSink>(func: &Function, sink: &mut CS) {
+    for jt in func.jump_tables.keys() {
+        let jt_offset = func.jump_table_offsets[jt];
+        if jt_offset == 0 {
+            continue;
+        }
+        while sink.offset() < jt_offset {
+            sink.put1(0);
+        }
+        for (_, ebb) in func.jump_tables[jt].entries() {
+            sink.put4(func.offsets[ebb].wrapping_sub(jt_offset));
+        }
+    }
+}
+
+/// Emit the constant pool of `func` to `sink` after the code.
+///
+/// The offsets of the constant pool entries must have been computed by `relax_branches()`. The
+/// padding before each entry is filled with zero bytes. Each constant is emitted as a single
+/// number in the byte order of the sink.
+pub fn emit_constants<CS: CodeSink>(func: &Function, sink: &mut CS) {
+    for constant in func.constants.keys() {
+        let data = &func.constants[constant];
+        while sink.offset() < func.constant_offsets[constant] {
+            sink.put1(0);
+        }
+        match sink.endianness() {
+            Endianness::Little => {
+                for &byte in data.bytes() {
+                    sink.put1(byte);
+                }
+            }
+            Endianness::Big => {
+                for &byte in data.bytes().iter().rev() {
+                    sink.put1(byte);
+                }
+            }
+        }
+    }
 }
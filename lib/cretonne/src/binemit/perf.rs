@@ -0,0 +1,376 @@
+//! Profiling support for JIT-compiled code.
+//!
+//! Linux `perf` can't symbolize code in anonymous executable memory by itself. A JIT embedder can
+//! describe each function it emits in one of the two formats `perf` understands:
+//!
+//! - A perf map is a text file named `/tmp/perf-<pid>.map` with the address range and name of each
+//!   function. It is read by `perf report` directly.
+//! - A jitdump file named `jit-<pid>.dump` also has a copy of the machine code and the source
+//!   lines of each function. The process must `mmap` the file with execute permission so `perf
+//!   record -k 1` notices it, and `perf inject --jit` turns the recorded profile into one that can
+//!   be annotated with source lines.
+//!
+//! A `PerfFunction` describes an emitted function, and a `PerfMapWriter` or a `JitDumpWriter` adds
+//! it to the file.
+
+use binemit::CodeOffset;
+use ir::Function;
+use isa::TargetIsa;
+use std::io::{self, Write};
+use std::vec::Vec;
+
+/// A source line of the code in a function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PerfLine<'a> {
+    /// Offset of the first byte of code for the line.
+    pub offset: CodeOffset,
+
+    /// Name of the source file.
+    pub file: &'a str,
+
+    /// The line number in `file`.
+    pub line: u32,
+}
+
+/// A function emitted into executable memory, as described to a profiler.
+#[derive(Clone, Debug)]
+pub struct PerfFunction<'a> {
+    /// The symbol name of the function.
+    pub name: &'a str,
+
+    /// The address the code executes from.
+    pub addr: u64,
+
+    /// The machine code of the function.
+    pub code: &'a [u8],
+
+    /// The source lines of the code in code order.
+    ///
+    /// Consecutive instructions with the same line share an entry, and instructions without a
+    /// known source position belong to the line before them.
+    pub lines: Vec<PerfLine<'a>>,
+}
+
+impl<'a> PerfFunction<'a> {
+    /// Describe `func`, which was emitted as `code` and executes from `addr`.
+    ///
+    /// The source lines are found with `Function::source_position()`. This function can only be
+    /// used after the code layout has been computed by the `binemit::relax_branches()` function.
+    pub fn new(
+        func: &'a Function,
+        isa: &TargetIsa,
+        name: &'a str,
+        addr: u64,
+        code: &'a [u8],
+    ) -> Self {
+        let encinfo = isa.encoding_info();
+        let mut lines: Vec<PerfLine> = Vec::new();
+        for ebb in func.layout.ebbs() {
+            for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+                let pos = match func.source_position(inst) {
+                    Some(pos) if size > 0 => pos,
+                    _ => continue,
+                };
+                let line = PerfLine {
+                    offset,
+                    file: func.source_files.file_name(pos.file),
+                    line: pos.line,
+                };
+                match lines.last() {
+                    Some(last) if (last.file, last.line) == (line.file, line.line) => {}
+                    _ => lines.push(line),
+                }
+            }
+        }
+        Self {
+            name,
+            addr,
+            code,
+            lines,
+        }
+    }
+
+    /// The size of the code in bytes.
+    pub fn size(&self) -> u64 {
+        self.code.len() as u64
+    }
+}
+
+/// A trait for receiving the functions to describe to a profiler.
+pub trait PerfSink {
+    /// Add `func` to the profiling information.
+    fn add_function(&mut self, func: &PerfFunction) -> io::Result<()>;
+}
+
+/// A `PerfSink` that writes a perf map.
+///
+/// Each function is a line with its hexadecimal address and size, and its name.
+pub struct PerfMapWriter<'a> {
+    out: &'a mut Write,
+}
+
+impl<'a> PerfMapWriter<'a> {
+    /// Create a perf map writer that writes to `out`, normally the file `/tmp/perf-<pid>.map`.
+    pub fn new(out: &'a mut Write) -> Self {
+        Self { out }
+    }
+}
+
+impl<'a> PerfSink for PerfMapWriter<'a> {
+    fn add_function(&mut self, func: &PerfFunction) -> io::Result<()> {
+        writeln!(self.out, "{:x} {:x} {}", func.addr, func.size(), func.name)
+    }
+}
+
+/// Magic number at the start of a jitdump file, "JiTD" in host byte order.
+const JITDUMP_MAGIC: u32 = 0x4a69_5444;
+
+/// Version of the jitdump format.
+const JITDUMP_VERSION: u32 = 1;
+
+/// Size of the jitdump file header.
+const JITDUMP_HEADER_SIZE: u32 = 40;
+
+/// Record kind of a function's code.
+const JIT_CODE_LOAD: u32 = 0;
+
+/// Record kind of a function's source lines.
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+
+/// A `PerfSink` that writes a jitdump file.
+///
+/// The file is written in the byte order of the host. Every function gets a code record, preceded
+/// by a debug info record if it has source lines.
+///
+/// The records are timestamped with the `clock` function, which must read `CLOCK_MONOTONIC` in
+/// nanoseconds for `perf` to order them correctly with the samples. The default clock returns 0,
+/// which makes every function look like it was loaded before the first sample. This is fine unless
+/// the embedder reuses the memory of functions that are freed.
+pub struct JitDumpWriter<'a> {
+    out: &'a mut Write,
+    pid: u32,
+    code_index: u64,
+    clock: fn() -> u64,
+}
+
+impl<'a> JitDumpWriter<'a> {
+    /// Create a jitdump writer for code generated by `isa` in process `pid`, and write the file
+    /// header to `out`.
+    pub fn new(out: &'a mut Write, isa: &TargetIsa, pid: u32) -> io::Result<Self> {
+        let writer = Self {
+            out,
+            pid,
+            code_index: 0,
+            clock: || 0,
+        };
+        let mut header = Vec::with_capacity(JITDUMP_HEADER_SIZE as usize);
+        put4(&mut header, JITDUMP_MAGIC);
+        put4(&mut header, JITDUMP_VERSION);
+        put4(&mut header, JITDUMP_HEADER_SIZE);
+        put4(&mut header, elf_machine(isa));
+        put4(&mut header, 0);
+        put4(&mut header, pid);
+        put8(&mut header, (writer.clock)());
+        put8(&mut header, 0);
+        writer.out.write_all(&header)?;
+        Ok(writer)
+    }
+
+    /// Set the function used to timestamp the records.
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    /// Write a record with the header for `kind`, followed by `body`.
+    fn write_record(&mut self, kind: u32, body: &[u8]) -> io::Result<()> {
+        let mut header = Vec::with_capacity(16);
+        put4(&mut header, kind);
+        put4(&mut header, 16 + body.len() as u32);
+        put8(&mut header, (self.clock)());
+        self.out.write_all(&header)?;
+        self.out.write_all(body)
+    }
+}
+
+impl<'a> PerfSink for JitDumpWriter<'a> {
+    fn add_function(&mut self, func: &PerfFunction) -> io::Result<()> {
+        // The source lines of a function must come before its code.
+        if !func.lines.is_empty() {
+            let mut body = Vec::new();
+            put8(&mut body, func.addr);
+            put8(&mut body, func.lines.len() as u64);
+            for line in &func.lines {
+                put8(&mut body, func.addr + u64::from(line.offset));
+                put4(&mut body, line.line);
+                // The discriminator isn't used.
+                put4(&mut body, 0);
+                put_str(&mut body, line.file);
+            }
+            self.write_record(JIT_CODE_DEBUG_INFO, &body)?;
+        }
+
+        // The thread ID isn't available from the standard library, so the process ID is used.
+        let mut body = Vec::with_capacity(40 + func.name.len() + 1 + func.code.len());
+        put4(&mut body, self.pid);
+        put4(&mut body, self.pid);
+        put8(&mut body, func.addr);
+        put8(&mut body, func.addr);
+        put8(&mut body, func.size());
+        put8(&mut body, self.code_index);
+        put_str(&mut body, func.name);
+        body.extend_from_slice(func.code);
+        self.code_index += 1;
+        self.write_record(JIT_CODE_LOAD, &body)
+    }
+}
+
+/// Get the ELF machine number of `isa`.
+pub fn elf_machine(isa: &TargetIsa) -> u32 {
+    let is_64bit = isa.flags().is_64bit();
+    match isa.name() {
+        "intel" if is_64bit => 62,
+        "intel" => 3,
+        "arm32" => 40,
+        "arm64" => 183,
+        "riscv" => 243,
+        _ => 0,
+    }
+}
+
+fn put4(out: &mut Vec<u8>, x: u32) {
+    for i in 0..4 {
+        let shift = if cfg!(target_endian = "big") { 3 - i } else { i };
+        out.push((x >> (8 * shift)) as u8);
+    }
+}
+
+fn put8(out: &mut Vec<u8>, x: u64) {
+    let (first, second) = if cfg!(target_endian = "big") {
+        ((x >> 32) as u32, x as u32)
+    } else {
+        (x as u32, (x >> 32) as u32)
+    };
+    put4(out, first);
+    put4(out, second);
+}
+
+/// Append `s` as a NUL-terminated string.
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use binemit::{NullTrapSink, Relocations};
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder, SourceLoc};
+    use isa;
+    use settings::{self, Configurable};
+    use std::str;
+
+    fn function(isa: &TargetIsa) -> (Context, Vec<u8>) {
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let file = ctx.func.source_files.add_file("lib.rs");
+        ctx.func.source_files.set_position(SourceLoc::new(1), file, 10);
+        ctx.func.source_files.set_position(SourceLoc::new(2), file, 11);
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.set_srcloc(SourceLoc::new(1));
+            let v1 = pos.ins().iadd(v0, v0);
+            let v2 = pos.ins().imul(v1, v0);
+            pos.set_srcloc(SourceLoc::new(2));
+            pos.ins().return_(&[v2]);
+        }
+        let size = ctx.compile(isa).unwrap();
+        let mut code = vec![0; size as usize];
+        ctx.emit_to_memory(
+            code.as_mut_ptr(),
+            &mut Relocations::new(),
+            &mut NullTrapSink {},
+            isa,
+        );
+        (ctx, code)
+    }
+
+    #[test]
+    fn perf_map() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let (ctx, code) = function(&*isa);
+        let func = PerfFunction::new(&ctx.func, &*isa, "foo", 0x1000, &code);
+        assert_eq!(func.lines.len(), 2);
+        assert_eq!(
+            (func.lines[0].file, func.lines[0].line, func.lines[1].line),
+            ("lib.rs", 10, 11)
+        );
+        assert!(func.lines[0].offset < func.lines[1].offset);
+
+        let mut out = Vec::new();
+        PerfMapWriter::new(&mut out).add_function(&func).unwrap();
+        assert_eq!(
+            str::from_utf8(&out).unwrap(),
+            format!("1000 {:x} foo\n", code.len())
+        );
+    }
+
+    #[test]
+    fn jitdump() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let (ctx, code) = function(&*isa);
+        let func = PerfFunction::new(&ctx.func, &*isa, "foo", 0x1000, &code);
+
+        let mut out = Vec::new();
+        {
+            let mut writer = JitDumpWriter::new(&mut out, &*isa, 17).unwrap();
+            writer.set_clock(|| 5);
+            writer.add_function(&func).unwrap();
+        }
+
+        let mut header = Vec::new();
+        put4(&mut header, JITDUMP_MAGIC);
+        put4(&mut header, JITDUMP_VERSION);
+        put4(&mut header, JITDUMP_HEADER_SIZE);
+        put4(&mut header, 62);
+        assert_eq!(&out[..16], &header[..]);
+
+        // The debug info record with two lines comes first.
+        let debug_size = 16 + 16 + 2 * (16 + "lib.rs\0".len());
+        let mut record = Vec::new();
+        put4(&mut record, JIT_CODE_DEBUG_INFO);
+        put4(&mut record, debug_size as u32);
+        put8(&mut record, 5);
+        put8(&mut record, 0x1000);
+        put8(&mut record, 2);
+        put8(&mut record, 0x1000 + u64::from(func.lines[0].offset));
+        put4(&mut record, 10);
+        assert_eq!(&out[40..40 + record.len()], &record[..]);
+
+        // The code record ends with the name and the code.
+        let load = &out[40 + debug_size..];
+        let mut record = Vec::new();
+        put4(&mut record, JIT_CODE_LOAD);
+        put4(&mut record, (16 + 40 + "foo\0".len() + code.len()) as u32);
+        put8(&mut record, 5);
+        put4(&mut record, 17);
+        put4(&mut record, 17);
+        put8(&mut record, 0x1000);
+        put8(&mut record, 0x1000);
+        put8(&mut record, code.len() as u64);
+        put8(&mut record, 0);
+        put_str(&mut record, "foo");
+        record.extend_from_slice(&code);
+        assert_eq!(load, &record[..]);
+    }
+}
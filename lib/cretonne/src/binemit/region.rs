@@ -0,0 +1,308 @@
+//! Emitting multiple functions into one contiguous code region.
+//!
+//! A JIT embedder that compiles a whole module at once can lay out all of its functions in a
+//! single block of memory. Calls between functions in the same region are PC-relative, so they
+//! can be resolved as soon as the layout is known, independently of where the region ends up in
+//! memory. Only references to symbols outside the region are left as relocations for the
+//! embedder to apply.
+//!
+//! A region can also declare symbols as aliases of other symbols. References to an alias are
+//! redirected to its target, so a function found to be identical to an earlier one by
+//! `dedup::Deduplicator` can share the earlier function's code instead of being emitted again.
+
+use binemit::{CodeOffset, CodeSink, Endianness, MemoryCodeSink, Reloc, RelocError, Relocation,
+              Relocations, NullTrapSink};
+use ir::{ExternalName, Function};
+use isa::TargetIsa;
+use std::mem;
+use std::vec::Vec;
+
+/// A function that has been added to a `CodeRegion`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionFunction {
+    /// The name of the function.
+    pub name: ExternalName,
+
+    /// Offset of the function's first instruction from the beginning of the region.
+    pub offset: CodeOffset,
+
+    /// Size of the function's code in bytes.
+    pub size: CodeOffset,
+}
+
+/// A symbol declared as an alias in a `CodeRegion`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionAlias {
+    /// The name of the alias.
+    pub name: ExternalName,
+
+    /// The symbol the alias refers to. This is a function in the region, another alias, or a
+    /// symbol outside the region.
+    pub target: ExternalName,
+}
+
+/// Builder for a contiguous region of machine code containing multiple functions.
+///
+/// Functions are added in layout order with `add_function()`. Each function starts at a multiple
+/// of the region's alignment, and the padding between functions is filled with zero bytes.
+pub struct CodeRegion {
+    alignment: CodeOffset,
+    code: Vec<u8>,
+    functions: Vec<RegionFunction>,
+    aliases: Vec<RegionAlias>,
+    relocs: Vec<Relocation>,
+    endianness: Endianness,
+}
+
+impl CodeRegion {
+    /// Create an empty code region where functions are aligned to `alignment` bytes.
+    ///
+    /// The alignment must be a power of two.
+    pub fn new(alignment: CodeOffset) -> Self {
+        assert!(alignment.is_power_of_two(), "Bad alignment {}", alignment);
+        Self {
+            alignment,
+            code: Vec::new(),
+            functions: Vec::new(),
+            aliases: Vec::new(),
+            relocs: Vec::new(),
+            endianness: Endianness::host(),
+        }
+    }
+
+    /// Emit the compiled function `func` at the end of the region.
+    ///
+    /// The `code_size` is the size of the function's code as returned by `Context::compile()`.
+    /// Returns the offset of the function in the region.
+    pub fn add_function(
+        &mut self,
+        func: &Function,
+        code_size: CodeOffset,
+        isa: &TargetIsa,
+    ) -> CodeOffset {
+        let mask = self.alignment - 1;
+        let offset = (self.code.len() as CodeOffset + mask) & !mask;
+        let end = offset as usize + code_size as usize;
+        self.code.resize(end, 0);
+
+        // All the functions in a region are for the same ISA.
+        self.endianness = isa.endianness();
+        let mut relocs = Relocations::new();
+        let mut traps = NullTrapSink {};
+        let mut sink = MemoryCodeSink::new(
+            self.code[offset as usize..].as_mut_ptr(),
+            &mut relocs,
+            &mut traps,
+            self.endianness,
+        );
+        isa.emit_function(func, &mut sink);
+        debug_assert_eq!(sink.offset(), code_size, "Wrong code size for {}", func.name);
+
+        self.relocs.extend(relocs.iter().map(|reloc| {
+            Relocation {
+                offset: reloc.offset + offset,
+                ..reloc.clone()
+            }
+        }));
+        self.functions.push(RegionFunction {
+            name: func.name.clone(),
+            offset,
+            size: code_size,
+        });
+        offset
+    }
+
+    /// Declare `name` as an alias of `target`.
+    ///
+    /// All references to `name` from functions in the region are redirected to `target`. The
+    /// target doesn't need to be known yet, so an alias can be declared before the function it
+    /// refers to is added. The name must not be the name of a function or another alias, and the
+    /// alias must not refer back to itself.
+    pub fn add_alias(&mut self, name: ExternalName, target: ExternalName) {
+        assert!(
+            self.functions.iter().all(|f| f.name != name) &&
+                self.aliases.iter().all(|a| a.name != name),
+            "{} is already defined",
+            name
+        );
+        assert!(*self.resolve(&target) != name, "Alias cycle at {}", name);
+        self.aliases.push(RegionAlias { name, target });
+    }
+
+    /// Get the functions in the region, in layout order.
+    pub fn functions(&self) -> &[RegionFunction] {
+        &self.functions
+    }
+
+    /// Get the aliases declared in the region, in declaration order.
+    pub fn aliases(&self) -> &[RegionAlias] {
+        &self.aliases
+    }
+
+    /// Get the offset of the function named `name` in the region.
+    ///
+    /// If `name` is an alias, this is the offset of the function it refers to.
+    pub fn function_offset(&self, name: &ExternalName) -> Option<CodeOffset> {
+        let name = self.resolve(name);
+        self.functions.iter().find(|f| f.name == *name).map(
+            |f| f.offset,
+        )
+    }
+
+    /// Follow the aliases starting at `name` to the symbol they refer to.
+    fn resolve<'a>(&'a self, mut name: &'a ExternalName) -> &'a ExternalName {
+        while let Some(alias) = self.aliases.iter().find(|a| a.name == *name) {
+            name = &alias.target;
+        }
+        name
+    }
+
+    /// Resolve the PC-relative references between functions in the region.
+    ///
+    /// Returns the code of the region together with the relocations that remain. They reference
+    /// symbols outside the region, or they are absolute references to functions in the region
+    /// which depend on the final address of the code. Their offsets are relative to the beginning
+    /// of the region. References to aliases are redirected to the symbols they refer to, so the
+    /// remaining relocations never name an alias.
+    pub fn finish(mut self) -> Result<(Vec<u8>, Vec<Relocation>), RelocError> {
+        let mut code = mem::replace(&mut self.code, Vec::new());
+        let mut external = Vec::new();
+        for mut reloc in mem::replace(&mut self.relocs, Vec::new()) {
+            reloc.name = self.resolve(&reloc.name).clone();
+            let target = match reloc.kind {
+                Reloc::IntelPCRel4 | Reloc::IntelPLTRel4 | Reloc::Arm64Call | Reloc::RiscvCall => {
+                    self.functions.iter().find(|f| f.name == reloc.name)
+                }
+                _ => None,
+            };
+            match target {
+                // The displacement doesn't depend on the region's address, so pretend it is 0.
+                Some(f) => {
+                    reloc.apply_with_endianness(
+                        &mut code,
+                        0,
+                        u64::from(f.offset),
+                        self.endianness,
+                    )?
+                }
+                None => external.push(reloc),
+            }
+        }
+        Ok((code, external))
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, CallConv, ExtFuncData, InstBuilder, Signature};
+    use isa;
+    use settings::{self, Configurable};
+    use std::ptr::read_unaligned;
+
+    fn function(name: ExternalName, callee: ExternalName) -> Context {
+        let mut ctx = Context::new();
+        ctx.func.name = name;
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I32));
+        let signature = ctx.func.import_signature(sig);
+        let fref = ctx.func.import_function(ExtFuncData {
+            name: callee,
+            signature,
+            colocated: true,
+        });
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let mut pos = FuncCursor::new(&mut ctx.func);
+        pos.insert_ebb(ebb0);
+        let call = pos.ins().call(fref, &[]);
+        let v0 = pos.func.dfg.first_result(call);
+        pos.ins().return_(&[v0]);
+        ctx
+    }
+
+    #[test]
+    fn calls() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let foo = ExternalName::testcase("foo");
+        let bar = ExternalName::testcase("bar");
+        let ext = ExternalName::testcase("ext");
+        let mut region = CodeRegion::new(16);
+
+        // `foo` calls `bar` which is placed after it, and `bar` calls an external function.
+        let mut ctx = function(foo.clone(), bar.clone());
+        let size = ctx.compile(&*isa).unwrap();
+        assert_eq!(region.add_function(&ctx.func, size, &*isa), 0);
+        let encinfo = isa.encoding_info();
+        let ebb = ctx.func.layout.entry_block().unwrap();
+        let call_end = ctx.func
+            .inst_offsets(ebb, &encinfo)
+            .find(|&(_, inst, _)| ctx.func.dfg[inst].opcode().is_call())
+            .map(|(offset, _, size)| offset + size)
+            .unwrap();
+
+        let mut ctx = function(bar.clone(), ext.clone());
+        let size = ctx.compile(&*isa).unwrap();
+        let bar_offset = region.add_function(&ctx.func, size, &*isa);
+        assert_eq!(bar_offset % 16, 0);
+        assert!(bar_offset > 0);
+        assert_eq!(region.function_offset(&bar), Some(bar_offset));
+        assert_eq!(region.functions().len(), 2);
+
+        let (code, relocs) = region.finish().unwrap();
+        assert_eq!(code.len() as CodeOffset, bar_offset + size);
+
+        // The call from `foo` to `bar` is resolved in place.
+        let disp = unsafe { read_unaligned(code[call_end as usize - 4..].as_ptr() as *const i32) };
+        assert_eq!(call_end as i32 + disp, bar_offset as i32);
+
+        // The call to `ext` is left for the embedder.
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(relocs[0].name, ext);
+        assert!(relocs[0].offset > bar_offset);
+    }
+
+    #[test]
+    fn aliases() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let foo = ExternalName::testcase("foo");
+        let bar = ExternalName::testcase("bar");
+        let baz = ExternalName::testcase("baz");
+        let ext = ExternalName::testcase("ext");
+        let ext_alias = ExternalName::testcase("ext_alias");
+        let mut region = CodeRegion::new(16);
+
+        // `foo` calls `baz`, which is an alias of `bar`, which is in turn an alias of `foo`. `qux`
+        // calls `ext_alias`, an alias of the external function `ext`.
+        region.add_alias(baz.clone(), bar.clone());
+        let mut ctx = function(foo.clone(), baz.clone());
+        let size = ctx.compile(&*isa).unwrap();
+        region.add_function(&ctx.func, size, &*isa);
+        region.add_alias(bar.clone(), foo.clone());
+        let mut ctx = function(ExternalName::testcase("qux"), ext_alias.clone());
+        let size = ctx.compile(&*isa).unwrap();
+        let qux_offset = region.add_function(&ctx.func, size, &*isa);
+        region.add_alias(ext_alias.clone(), ext.clone());
+
+        assert_eq!(region.aliases().len(), 3);
+        assert_eq!(region.aliases()[0].target, bar);
+        assert_eq!(region.function_offset(&baz), Some(0));
+        assert_eq!(region.function_offset(&ext_alias), None);
+
+        // The call through the aliases is resolved in place, and the call to `ext_alias` is
+        // redirected to `ext`.
+        let (_, relocs) = region.finish().unwrap();
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(relocs[0].name, ext);
+        assert!(relocs[0].offset > qux_offset);
+    }
+}
@@ -26,17 +26,45 @@
 //!     jump ebb17
 //! ebb23:
 //! ```
+//!
+//! # Cold EBBs
+//!
+//! EBBs that are marked as cold in the layout are moved after all the other EBBs before the
+//! offsets are computed. This keeps rarely executed code like trap handler calls from being
+//! interleaved with the hot code. The EBBs that never ran according to the profile attached to the
+//! function are moved the same way.
+//!
+//! # Padding
+//!
+//! Workarounds for CPU errata can require padding before EBB headers. The ISA computes the padding
+//! in `TargetIsa::ebb_padding()`, and it becomes part of the EBB offsets.
+//!
+//! # Jump tables
+//!
+//! The jump tables used by `jump_table_base` instructions are placed after the code, aligned to 4
+//! bytes. Their offsets are recorded in the `func.jump_table_offsets` table.
+//!
+//! # Constant pool
+//!
+//! The function's constant pool is placed after the code and the jump tables, aligned to 16
+//! bytes. The offsets of the
+//! individual entries are recorded in the `func.constant_offsets` table once the code size is
+//! known.
 
 use binemit::CodeOffset;
 use cursor::{Cursor, FuncCursor};
-use ir::{Function, InstructionData, Opcode};
-use isa::{TargetIsa, EncInfo};
+use entity::EntitySet;
+use ir::{Ebb, Function, InstructionData, Opcode};
+use isa::{TargetIsa, EncInfo, RecipeConstraints};
 use iterators::IteratorExtras;
 use result::CtonError;
+use std::vec::Vec;
 
 /// Relax branches and compute the final layout of EBB headers in `func`.
 ///
-/// Fill in the `func.offsets` table so the function is ready for binary emission.
+/// Fill in the `func.offsets`, `func.jump_table_offsets`, and `func.constant_offsets` tables so
+/// the function is ready for binary emission. Return the total size of the code, the jump tables,
+/// and the constant pool.
 pub fn relax_branches(func: &mut Function, isa: &TargetIsa) -> Result<CodeOffset, CtonError> {
     let encinfo = isa.encoding_info();
 
@@ -44,7 +72,8 @@ pub fn relax_branches(func: &mut Function, isa: &TargetIsa) -> Result<CodeOffset
     func.offsets.clear();
     func.offsets.resize(func.dfg.num_ebbs());
 
-    // Start by inserting fall through instructions.
+    // Move the cold EBBs out of the way, then insert fall through instructions.
+    move_cold_ebbs(func);
     fallthroughs(func);
 
     let mut offset = 0;
@@ -58,6 +87,8 @@ pub fn relax_branches(func: &mut Function, isa: &TargetIsa) -> Result<CodeOffset
         // Visit all instructions in layout order
         let mut cur = FuncCursor::new(func);
         while let Some(ebb) = cur.next_ebb() {
+            offset += isa.ebb_padding(cur.func, ebb, offset);
+
             // Record the offset for `ebb` and make sure we iterate until offsets are stable.
             if cur.func.offsets[ebb] != offset {
                 debug_assert!(
@@ -92,7 +123,88 @@ pub fn relax_branches(func: &mut Function, isa: &TargetIsa) -> Result<CodeOffset
         }
     }
 
-    Ok(offset)
+    let offset = layout_jump_tables(func, offset);
+    Ok(layout_constants(func, offset))
+}
+
+/// Assign offsets to the jump tables used by `jump_table_base` instructions in `func`, placing
+/// them after `code_size`.
+///
+/// Return the end offset of the jump tables.
+fn layout_jump_tables(func: &mut Function, code_size: CodeOffset) -> CodeOffset {
+    func.jump_table_offsets.clear();
+    let mut used = EntitySet::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if let InstructionData::BranchTableBase { table, .. } = func.dfg[inst] {
+                used.insert(table);
+            }
+        }
+    }
+
+    let mut offset = code_size;
+    for jt in func.jump_tables.keys().filter(|&jt| used.contains(jt)) {
+        offset = align_to(offset, 4);
+        func.jump_table_offsets[jt] = offset;
+        offset += 4 * func.jump_tables[jt].len() as CodeOffset;
+    }
+    offset
+}
+
+/// Assign offsets to the constant pool entries of `func`, placing the pool at the first 16-byte
+/// aligned offset after `code_size`.
+///
+/// Return the end offset of the constant pool.
+fn layout_constants(func: &mut Function, code_size: CodeOffset) -> CodeOffset {
+    func.constant_offsets.clear();
+    if func.constants.is_empty() {
+        return code_size;
+    }
+
+    let mut offset = align_to(code_size, 16);
+    for constant in func.constants.keys() {
+        let data = &func.constants[constant];
+        offset = align_to(offset, data.alignment());
+        func.constant_offsets[constant] = offset;
+        offset += data.len() as CodeOffset;
+    }
+    offset
+}
+
+/// Round `offset` up to a multiple of `align` which must be a power of two.
+fn align_to(offset: CodeOffset, align: CodeOffset) -> CodeOffset {
+    debug_assert!(align.is_power_of_two());
+    (offset + align - 1) & !(align - 1)
+}
+
+/// Move the EBBs that are marked as cold or that never ran in the profile to the end of the layout,
+/// preserving their relative order.
+///
+/// The entry block is never moved, and neither are EBBs that are involved in an existing
+/// `fallthrough` instruction.
+fn move_cold_ebbs(func: &mut Function) {
+    let entry = func.layout.entry_block();
+    let cold: Vec<Ebb> = func.layout
+        .ebbs()
+        .filter(|&ebb| {
+            (func.layout.is_cold(ebb) || func.profile.never_executed(ebb)) &&
+                Some(ebb) != entry && !ends_in_fallthrough(func, ebb) &&
+                !func.layout.prev_ebb(ebb).map_or(
+                    false,
+                    |prev| ends_in_fallthrough(func, prev),
+                )
+        })
+        .collect();
+    for ebb in cold {
+        func.layout.move_ebb_to_end(ebb);
+    }
+}
+
+/// Does `ebb` end in a `fallthrough` instruction?
+fn ends_in_fallthrough(func: &Function, ebb: Ebb) -> bool {
+    func.layout.last_inst(ebb).map_or(false, |inst| {
+        func.dfg[inst].opcode() == Opcode::Fallthrough
+    })
 }
 
 /// Convert `jump` instructions to `fallthrough` instructions where possible and verify that any
@@ -146,6 +258,7 @@ fn relax_branch(
     );
 
     // Pick the first encoding that can handle the branch range.
+    let old_constraints = encinfo.operand_constraints(cur.func.encodings[inst]);
     let dfg = &cur.func.dfg;
     let ctrl_type = dfg.ctrl_typevar(inst);
     if let Some(enc) = isa.legal_encodings(dfg, &dfg[inst], ctrl_type).find(
@@ -154,14 +267,12 @@ fn relax_branch(
             if !range.contains(offset, dest_offset) {
                 dbg!("  trying [{}]: out of range", encinfo.display(enc));
                 false
-            } else if encinfo.operand_constraints(enc) !=
-                       encinfo.operand_constraints(cur.func.encodings[inst])
-            {
-                // Conservatively give up if the encoding has different constraints
-                // than the original, so that we don't risk picking a new encoding
-                // which the existing operands don't satisfy. We can't check for
-                // validity directly because we don't have a RegDiversions active so
-                // we don't know which registers are actually in use.
+            } else if !constraints_imply(old_constraints, encinfo.operand_constraints(enc)) {
+                // Conservatively give up if the encoding has constraints that the operands of the
+                // original encoding may not satisfy, like a compressed branch relaxed to a
+                // longer branch with the same register class. We can't check for validity
+                // directly because we don't have a RegDiversions active so we don't know which
+                // registers are actually in use.
                 dbg!("  trying [{}]: constraints differ", encinfo.display(enc));
                 false
             } else {
@@ -196,3 +307,12 @@ fn relax_branch(
     // This assumes solution 2. above:
     panic!("No branch in range for {:#x}-{:#x}", offset, dest_offset);
 }
+
+/// Can an instruction satisfying the `old` operand constraints be given an encoding with the
+/// `new` constraints?
+fn constraints_imply(old: Option<&RecipeConstraints>, new: Option<&RecipeConstraints>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old.implies(new),
+        (old, new) => old.is_none() && new.is_none(),
+    }
+}
@@ -0,0 +1,489 @@
+//! Relocations of a compiled function.
+//!
+//! The `RelocSink` trait receives the relocations of a function one at a time as the machine code
+//! is emitted. Embedders that use a module layer or an object file writer will want to handle them
+//! there, but a small JIT embedder often just needs to patch the code in place once the addresses
+//! of the external symbols are known. The `Relocations` type collects all the relocations of a
+//! function and can apply them to the emitted code given a resolver for the external names.
+
+use binemit::{Addend, CodeOffset, Endianness, Reloc, RelocSink, SymbolInfo};
+use ir::{ExternalName, JumpTable};
+use predicates::is_signed_int;
+use std::error::Error as StdError;
+use std::fmt;
+use std::ptr::{read_unaligned, write_unaligned};
+use std::vec::Vec;
+
+/// A single relocation in the machine code of a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// The kind of relocation, which determines how the code is patched.
+    pub kind: Reloc,
+
+    /// Offset of the patched bytes from the beginning of the function.
+    pub offset: CodeOffset,
+
+    /// The external symbol being referenced.
+    pub name: ExternalName,
+
+    /// Addend to add to the symbol address.
+    pub addend: Addend,
+
+    /// What kind of symbol is referenced, and how it can be bound.
+    pub symbol: SymbolInfo,
+}
+
+/// An error that occurred while applying relocations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelocError {
+    /// The resolver didn't provide an address for the named symbol.
+    Unresolved(ExternalName),
+
+    /// The relocation kind can't be applied without a linker, e.g. because it needs a GOT.
+    Unsupported(Reloc),
+
+    /// The resolved value doesn't fit in the relocated field.
+    OutOfRange(Reloc, CodeOffset),
+}
+
+impl fmt::Display for RelocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RelocError::Unresolved(ref name) => write!(f, "Unresolved symbol {}", name),
+            RelocError::Unsupported(kind) => write!(f, "Unsupported relocation {:?}", kind),
+            RelocError::OutOfRange(kind, offset) => {
+                write!(f, "Relocation {:?} at offset {} out of range", kind, offset)
+            }
+        }
+    }
+}
+
+impl StdError for RelocError {
+    fn description(&self) -> &str {
+        match *self {
+            RelocError::Unresolved(_) => "Unresolved symbol",
+            RelocError::Unsupported(_) => "Unsupported relocation",
+            RelocError::OutOfRange(..) => "Relocation out of range",
+        }
+    }
+}
+
+impl Relocation {
+    /// Patch `code` for this relocation, given the address of the referenced symbol.
+    ///
+    /// The function's code is in `code`, and `code_addr` is the address it will execute from.
+    /// Values are read and written in the native byte order of the host, since the code is going
+    /// to run there.
+    ///
+    /// `IntelPLTRel4` relocations are resolved directly to the symbol address since there is no
+    /// PLT. `IntelGOTPCRel4`, the TLS relocations, and `Arm32Call` are not supported.
+    pub fn apply(&self, code: &mut [u8], code_addr: u64, target: u64) -> Result<(), RelocError> {
+        self.apply_with_endianness(code, code_addr, target, Endianness::host())
+    }
+
+    /// Patch `code` for this relocation like `apply()`, but read and write values in the byte
+    /// order `endianness` of the target instead of the host.
+    pub fn apply_with_endianness(
+        &self,
+        code: &mut [u8],
+        code_addr: u64,
+        target: u64,
+        endianness: Endianness,
+    ) -> Result<(), RelocError> {
+        let field = RelocField {
+            kind: self.kind,
+            offset: self.offset,
+            addend: self.addend,
+        };
+        field.apply(code, code_addr, target, endianness)
+    }
+}
+
+/// The field patched by a relocation.
+///
+/// This is a `Relocation` without the symbol name, for code sinks that resolve the symbol as soon
+/// as the relocation is emitted.
+#[derive(Clone, Copy, Debug)]
+pub struct RelocField {
+    /// The kind of relocation.
+    pub kind: Reloc,
+
+    /// Offset of the patched bytes from the beginning of the function.
+    pub offset: CodeOffset,
+
+    /// Addend to add to the symbol address.
+    pub addend: Addend,
+}
+
+impl RelocField {
+    /// Patch the field in `code` which will execute from `code_addr`, given the address of the
+    /// referenced symbol. See `Relocation::apply()`.
+    pub fn apply(
+        &self,
+        code: &mut [u8],
+        code_addr: u64,
+        target: u64,
+        endianness: Endianness,
+    ) -> Result<(), RelocError> {
+        let value = target.wrapping_add(self.addend as u64);
+        let pcrel = value.wrapping_sub(code_addr + u64::from(self.offset)) as i64;
+        match self.kind {
+            Reloc::IntelAbs4 => {
+                if value > u64::from(u32::max_value()) {
+                    return Err(self.out_of_range());
+                }
+                self.write4(code, value as u32, endianness)
+            }
+            Reloc::IntelAbs8 |
+            Reloc::Arm64Abs8 => self.write8(code, value, endianness),
+            Reloc::IntelPCRel4 |
+            Reloc::IntelPLTRel4 => {
+                if !is_signed_int(pcrel, 32, 0) {
+                    return Err(self.out_of_range());
+                }
+                self.write4(code, pcrel as u32, endianness)
+            }
+            Reloc::RiscvCall => {
+                // Fill in the displacement of a `jal` instruction.
+                if !is_signed_int(pcrel, 21, 1) {
+                    return Err(self.out_of_range());
+                }
+                let imm = pcrel as u32;
+                let mut inst = self.read4(code, endianness)?;
+                inst &= 0xfff;
+                inst |= imm & 0xff000;
+                inst |= ((imm >> 11) & 0x1) << 20;
+                inst |= ((imm >> 1) & 0x3ff) << 21;
+                inst |= ((imm >> 20) & 0x1) << 31;
+                self.write4(code, inst, endianness)
+            }
+            Reloc::Arm64Call => {
+                // Fill in the displacement of a `bl` or `b` instruction.
+                if !is_signed_int(pcrel, 28, 2) {
+                    return Err(self.out_of_range());
+                }
+                let mut inst = self.read4(code, endianness)?;
+                inst &= 0xfc00_0000;
+                inst |= (pcrel as u32 >> 2) & 0x03ff_ffff;
+                self.write4(code, inst, endianness)
+            }
+            Reloc::IntelGOTPCRel4 |
+            Reloc::IntelGOTTPOff4 |
+            Reloc::IntelTLSGD4 |
+            Reloc::Arm32Call => Err(RelocError::Unsupported(self.kind)),
+        }
+    }
+
+    fn out_of_range(&self) -> RelocError {
+        RelocError::OutOfRange(self.kind, self.offset)
+    }
+
+    /// Get a pointer to the `T`-sized field being relocated in `code`.
+    fn field<T>(&self, code: &[u8]) -> Result<*const T, RelocError> {
+        let start = self.offset as usize;
+        if start + ::std::mem::size_of::<T>() > code.len() {
+            return Err(self.out_of_range());
+        }
+        Ok(code[start..].as_ptr() as *const T)
+    }
+
+    fn read4(&self, code: &[u8], endianness: Endianness) -> Result<u32, RelocError> {
+        let x = unsafe { read_unaligned(self.field::<u32>(code)?) };
+        Ok(match endianness {
+            Endianness::Little => u32::from_le(x),
+            Endianness::Big => u32::from_be(x),
+        })
+    }
+
+    fn write4(&self, code: &mut [u8], x: u32, endianness: Endianness) -> Result<(), RelocError> {
+        let x = match endianness {
+            Endianness::Little => x.to_le(),
+            Endianness::Big => x.to_be(),
+        };
+        unsafe {
+            write_unaligned(self.field::<u32>(code)? as *mut u32, x);
+        }
+        Ok(())
+    }
+
+    fn write8(&self, code: &mut [u8], x: u64, endianness: Endianness) -> Result<(), RelocError> {
+        let x = match endianness {
+            Endianness::Little => x.to_le(),
+            Endianness::Big => x.to_be(),
+        };
+        unsafe {
+            write_unaligned(self.field::<u64>(code)? as *mut u64, x);
+        }
+        Ok(())
+    }
+}
+
+/// All the relocations of a compiled function.
+///
+/// This is a `RelocSink` which can be passed to `Context::emit_to_memory()`. The relocations are
+/// listed in code order.
+#[derive(Clone, Debug, Default)]
+pub struct Relocations {
+    relocs: Vec<Relocation>,
+}
+
+impl Relocations {
+    /// Create an empty list of relocations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a relocation at the end of the list.
+    pub fn push(&mut self, reloc: Relocation) {
+        self.relocs.push(reloc)
+    }
+
+    /// Remove all relocations so the list can be reused for another function.
+    pub fn clear(&mut self) {
+        self.relocs.clear()
+    }
+
+    /// Get the relocations as a slice.
+    pub fn as_slice(&self) -> &[Relocation] {
+        &self.relocs
+    }
+
+    /// Iterate over the relocations.
+    pub fn iter(&self) -> ::std::slice::Iter<Relocation> {
+        self.relocs.iter()
+    }
+
+    /// Get the number of relocations.
+    pub fn len(&self) -> usize {
+        self.relocs.len()
+    }
+
+    /// Are there any relocations?
+    pub fn is_empty(&self) -> bool {
+        self.relocs.is_empty()
+    }
+
+    /// Apply all the relocations to `code` which will execute from `code_addr`.
+    ///
+    /// The `resolve` function provides the address of each external symbol. See
+    /// `Relocation::apply()` for the supported relocation kinds.
+    pub fn apply<F>(
+        &self,
+        code: &mut [u8],
+        code_addr: u64,
+        mut resolve: F,
+    ) -> Result<(), RelocError>
+    where
+        F: FnMut(&ExternalName) -> Option<u64>,
+    {
+        for reloc in &self.relocs {
+            let target = resolve(&reloc.name).ok_or_else(|| {
+                RelocError::Unresolved(reloc.name.clone())
+            })?;
+            reloc.apply(code, code_addr, target)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a Relocations {
+    type Item = &'a Relocation;
+    type IntoIter = ::std::slice::Iter<'a, Relocation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl RelocSink for Relocations {
+    fn reloc_ebb(&mut self, offset: CodeOffset, kind: Reloc, _: CodeOffset) {
+        // Branches within the function are resolved during emission.
+        panic!("Unexpected EBB relocation {:?} at offset {}", kind, offset);
+    }
+
+    fn reloc_external(
+        &mut self,
+        offset: CodeOffset,
+        kind: Reloc,
+        name: &ExternalName,
+        addend: Addend,
+        symbol: SymbolInfo,
+    ) {
+        self.relocs.push(Relocation {
+            kind,
+            offset,
+            name: name.clone(),
+            addend,
+            symbol,
+        });
+    }
+
+    fn reloc_jt(&mut self, offset: CodeOffset, kind: Reloc, _: JumpTable) {
+        panic!("Unexpected jump table relocation {:?} at offset {}", kind, offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use binemit::{NullTrapSink, SymbolKind};
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, ExtFuncData, GlobalVarData, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    fn reloc(kind: Reloc, offset: CodeOffset, addend: Addend) -> Relocations {
+        let mut relocs = Relocations::new();
+        let symbol = SymbolInfo {
+            kind: SymbolKind::Code,
+            preemptible: false,
+        };
+        relocs.reloc_external(offset, kind, &ExternalName::testcase("foo"), addend, symbol);
+        relocs
+    }
+
+    #[test]
+    fn pcrel() {
+        let mut code = [0xe8, 0, 0, 0, 0];
+        let relocs = reloc(Reloc::IntelPCRel4, 1, -4);
+        assert_eq!(relocs.len(), 1);
+        relocs.apply(&mut code, 0x1000, |_| Some(0x1100)).unwrap();
+        let disp = unsafe { read_unaligned(code[1..].as_ptr() as *const u32) };
+        assert_eq!(disp, 0x100 - 5);
+
+        assert_eq!(
+            relocs.apply(&mut code, 0x1000, |_| None),
+            Err(RelocError::Unresolved(ExternalName::testcase("foo")))
+        );
+        assert_eq!(
+            relocs.apply(&mut code, 0x1000, |_| Some(0x1_0000_1000)),
+            Err(RelocError::OutOfRange(Reloc::IntelPCRel4, 1))
+        );
+    }
+
+    #[test]
+    fn abs() {
+        let mut code = [0; 10];
+        let relocs = reloc(Reloc::IntelAbs8, 2, 8);
+        relocs.apply(&mut code, 0x1000, |_| Some(0x1234_5678_9000)).unwrap();
+        let value = unsafe { read_unaligned(code[2..].as_ptr() as *const u64) };
+        assert_eq!(value, 0x1234_5678_9008);
+
+        // The field must fit in the code.
+        let relocs = reloc(Reloc::IntelAbs8, 4, 0);
+        assert_eq!(
+            relocs.apply(&mut code, 0x1000, |_| Some(0)),
+            Err(RelocError::OutOfRange(Reloc::IntelAbs8, 4))
+        );
+    }
+
+    #[test]
+    fn riscv_call() {
+        // jal x1, 0
+        let jal: u32 = 0x0000_00ef;
+        let mut code = [0; 4];
+        unsafe { write_unaligned(code.as_mut_ptr() as *mut u32, jal) };
+        let relocs = reloc(Reloc::RiscvCall, 0, 0);
+        relocs.apply(&mut code, 0x1000, |_| Some(0x1000 - 8)).unwrap();
+        let inst = unsafe { read_unaligned(code.as_ptr() as *const u32) };
+        // jal x1, -8
+        assert_eq!(inst, 0xff9f_f0ef);
+    }
+
+    #[test]
+    fn arm64_call() {
+        // bl 0
+        let bl: u32 = 0x9400_0000;
+        let mut code = [0; 4];
+        unsafe { write_unaligned(code.as_mut_ptr() as *mut u32, bl) };
+        let relocs = reloc(Reloc::Arm64Call, 0, 0);
+        relocs.apply(&mut code, 0x1000, |_| Some(0x1000 - 8)).unwrap();
+        let inst = unsafe { read_unaligned(code.as_ptr() as *const u32) };
+        // bl -8
+        assert_eq!(inst, 0x97ff_fffe);
+
+        // The target must be within 128 MB.
+        assert_eq!(
+            relocs.apply(&mut code, 0x1000, |_| Some(0x1000 + (1 << 27))),
+            Err(RelocError::OutOfRange(Reloc::Arm64Call, 0))
+        );
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn symbols() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let sig = ctx.func.import_signature(ctx.func.signature.clone());
+        let callee = ctx.func.import_function(ExtFuncData {
+            name: ExternalName::testcase("callee"),
+            signature: sig,
+            colocated: false,
+        });
+        let table = ctx.func.create_global_var(GlobalVarData::Sym {
+            name: ExternalName::testcase("table"),
+            colocated: true,
+            readonly: true,
+        });
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().func_addr(I64, callee);
+            let v1 = pos.ins().global_addr(I64, table);
+            let v2 = pos.ins().iadd(v0, v1);
+            pos.ins().return_(&[v2]);
+        }
+        let size = ctx.compile(&*isa).unwrap();
+        let mut code = vec![0; size as usize];
+        let mut relocs = Relocations::new();
+        ctx.emit_to_memory(code.as_mut_ptr(), &mut relocs, &mut NullTrapSink {}, &*isa);
+
+        let symbols: Vec<_> = relocs.iter().map(|r| (r.name.to_string(), r.symbol)).collect();
+        assert_eq!(
+            symbols,
+            [
+                (
+                    "%callee".to_string(),
+                    SymbolInfo {
+                        kind: SymbolKind::Code,
+                        preemptible: true,
+                    },
+                ),
+                (
+                    "%table".to_string(),
+                    SymbolInfo {
+                        kind: SymbolKind::ReadOnlyData,
+                        preemptible: false,
+                    },
+                ),
+            ]
+        );
+        assert_eq!(symbols[0].1.to_string(), "code preemptible");
+        assert_eq!(symbols[1].1.to_string(), "rodata");
+    }
+
+    #[test]
+    fn big_endian() {
+        let relocs = reloc(Reloc::IntelAbs8, 0, 0);
+        let mut code = [0; 8];
+        relocs.as_slice()[0]
+            .apply_with_endianness(&mut code, 0x1000, 0x0102_0304_0506_0708, Endianness::Big)
+            .unwrap();
+        assert_eq!(code, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // bl 0, stored big-endian.
+        let mut code = [0x94, 0, 0, 0];
+        let relocs = reloc(Reloc::Arm64Call, 0, 0);
+        relocs.as_slice()[0]
+            .apply_with_endianness(&mut code, 0x1000, 0x1000 - 8, Endianness::Big)
+            .unwrap();
+        assert_eq!(code, [0x97, 0xff, 0xff, 0xfe]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Source location tables.
+//!
+//! A profiler sampling the program counter, or an embedder building a backtrace of guest code,
+//! needs to map any code offset in a function back to its source location, not just the trap
+//! sites. The `SourceLocTable` of a function maps each range of code offsets to the `SourceLoc` of
+//! the instructions emitted there.
+//!
+//! The table is compact: consecutive instructions with the same source location share an entry,
+//! so the size of the table grows with the number of source location changes in the code rather
+//! than the number of instructions.
+
+use binemit::CodeOffset;
+use ir::{Function, SourceLoc};
+use isa::TargetIsa;
+use std::vec::Vec;
+
+/// The start of a range of code with the same source location.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLocEntry {
+    /// Code offset of the first instruction in the range.
+    pub offset: CodeOffset,
+
+    /// The source location of the instructions in the range.
+    ///
+    /// The code generated without a source location, like the prologue, has the default
+    /// `SourceLoc`.
+    pub srcloc: SourceLoc,
+}
+
+/// A table mapping the code offsets of a function to source locations.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceLocTable {
+    /// The ranges in code order. Each range ends where the next one starts, and the last one ends
+    /// at `code_size`.
+    pub entries: Vec<SourceLocEntry>,
+
+    /// Code offset following the last instruction of the function.
+    ///
+    /// The constant pool following the code is not covered by the table.
+    pub code_size: CodeOffset,
+}
+
+impl SourceLocTable {
+    /// Get the source location of the instruction containing the code offset `offset`.
+    ///
+    /// Returns `None` when `offset` is outside the code of the function.
+    pub fn lookup(&self, offset: CodeOffset) -> Option<SourceLoc> {
+        if offset >= self.code_size {
+            return None;
+        }
+        let idx = match self.entries.binary_search_by_key(&offset, |e| e.offset) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        Some(self.entries[idx].srcloc)
+    }
+}
+
+/// Compute the source location table of `func`.
+///
+/// This function can only be used after the code layout has been computed by the
+/// `binemit::relax_branches()` function.
+pub fn srcloc_table(func: &Function, isa: &TargetIsa) -> SourceLocTable {
+    let encinfo = isa.encoding_info();
+    let mut table = SourceLocTable::default();
+    for ebb in func.layout.ebbs() {
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            // Instructions without machine code, like `fallthrough`, don't start a range.
+            if size == 0 {
+                continue;
+            }
+            let srcloc = func.srclocs[inst];
+            if table.entries.last().map(|e| e.srcloc) != Some(srcloc) {
+                table.entries.push(SourceLocEntry { offset, srcloc });
+            }
+            table.code_size = offset + size;
+        }
+    }
+    table
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    #[test]
+    fn ranges() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.set_srcloc(SourceLoc::new(10));
+            let v1 = pos.ins().imul(v0, v0);
+            let v2 = pos.ins().iadd(v1, v0);
+            pos.set_srcloc(SourceLoc::new(20));
+            let v3 = pos.ins().bxor(v2, v0);
+            pos.ins().return_(&[v3]);
+        }
+        let code_size = ctx.compile(&*isa).unwrap();
+
+        let table = ctx.srcloc_table(&*isa);
+        // The prologue and the epilogue get the source locations of the neighboring instructions.
+        let srclocs: Vec<SourceLoc> = table.entries.iter().map(|e| e.srcloc).collect();
+        assert_eq!(srclocs, [SourceLoc::new(10), SourceLoc::new(20)]);
+        assert_eq!(table.entries[0].offset, 0);
+        assert!(table.code_size <= code_size);
+        let second = table.entries[1].offset;
+        assert_eq!(table.lookup(second - 1), Some(SourceLoc::new(10)));
+        assert_eq!(table.lookup(second), Some(SourceLoc::new(20)));
+        assert_eq!(table.lookup(table.code_size), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Stack maps for precise garbage collection.
+//!
+//! A stack map describes where the live GC references are stored in the stack frame at a
+//! safepoint. The stack maps for a function are computed from `Function::safepoints` after the
+//! stack frame layout is final, and they are passed to a `StackmapSink` trait object.
+//!
+//! The `StackmapWriter` sink encodes them in a compact format which can be stored by the runtime
+//! and decoded by a `StackmapReader`.
+
+use binemit::CodeOffset;
+use ir::stackslot::StackOffset;
+use ir::{Function, ValueLoc};
+use isa::{StackRef, TargetIsa};
+use std::vec::Vec;
+
+/// The locations of the live GC references at a single safepoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stackmap {
+    /// Offsets of the live references relative to the stack pointer at the safepoint, in
+    /// ascending order.
+    pub sp_offsets: Vec<StackOffset>,
+}
+
+/// A trait for receiving the stack maps of a function.
+pub trait StackmapSink {
+    /// Add a stack map for the safepoint whose return address is at `offset`.
+    fn add_stackmap(&mut self, offset: CodeOffset, stackmap: &Stackmap);
+}
+
+/// Emit the stack maps for all the safepoints in `func` to `sink`.
+///
+/// Each stack map is keyed by the code offset immediately following the call instruction, which
+/// is the return address found on the stack when walking the frames.
+///
+/// This function can only be used after the code layout has been computed by the
+/// `binemit::relax_branches()` function.
+pub fn emit_stackmaps(func: &Function, isa: &TargetIsa, sink: &mut StackmapSink) {
+    if func.gc_refs.is_empty() {
+        return;
+    }
+
+    let encinfo = isa.encoding_info();
+    let mut stackmap = Stackmap::default();
+    for ebb in func.layout.ebbs() {
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            let live = func.safepoints[inst].as_slice(&func.dfg.value_lists);
+            if live.is_empty() {
+                continue;
+            }
+
+            stackmap.sp_offsets.clear();
+            for &value in live {
+                match func.locations[value] {
+                    ValueLoc::Stack(ss) => {
+                        stackmap.sp_offsets.push(StackRef::sp(ss, &func.stack_slots).offset)
+                    }
+                    loc => panic!("GC reference {} is not on the stack: {:?}", value, loc),
+                }
+            }
+            stackmap.sp_offsets.sort();
+            sink.add_stackmap(offset + size, &stackmap);
+        }
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, CallConv, ExtFuncData, ExternalName, InstBuilder, Signature};
+    use isa;
+    use settings::{self, Configurable};
+
+    struct Maps(Vec<(CodeOffset, Stackmap)>);
+
+    impl StackmapSink for Maps {
+        fn add_stackmap(&mut self, offset: CodeOffset, stackmap: &Stackmap) {
+            self.0.push((offset, stackmap.clone()));
+        }
+    }
+
+    #[test]
+    fn ref_live_across_call() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I64));
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let sig = ctx.func.import_signature(Signature::new(CallConv::Native));
+        let callee = ctx.func.import_function(ExtFuncData {
+            name: ExternalName::testcase("callee"),
+            signature: sig,
+            colocated: false,
+        });
+
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().call(callee, &[]);
+            pos.ins().call(callee, &[]);
+            pos.ins().return_(&[v0]);
+        }
+        ctx.func.mark_gc_ref(v0);
+        ctx.compile(&*isa).unwrap();
+
+        let mut maps = Maps(Vec::new());
+        ctx.emit_stackmaps(&mut maps, &*isa);
+        assert_eq!(maps.0.len(), 2);
+        assert!(maps.0[0].0 < maps.0[1].0);
+        assert_eq!(maps.0[0].1.sp_offsets.len(), 1);
+        assert_eq!(maps.0[0].1, maps.0[1].1);
+    }
+}
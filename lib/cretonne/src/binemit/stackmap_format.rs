@@ -0,0 +1,344 @@
+//! Compact binary encoding of stack maps.
+//!
+//! A runtime may need to keep the stack maps of thousands of functions around, but it only looks
+//! at them during a collection. The `StackmapWriter` sink encodes the stack maps of a function
+//! into a compact byte string, and the `StackmapReader` finds the stack map for a return address
+//! without decoding the others.
+//!
+//! The encoding is made of unsigned LEB128 numbers, except for the leading version byte:
+//!
+//! ```text
+//! stackmaps := version:u8 word_size count entry*
+//! entry     := offset_delta num_runs run_length*
+//! ```
+//!
+//! The entries are sorted by code offset, and `offset_delta` is the distance from the code offset
+//! of the previous entry, or from 0 for the first entry. The live references of an entry are
+//! represented as a bitset with one bit per `word_size` bytes of the stack frame, starting at the
+//! stack pointer. The bitset is run-length encoded as alternating runs of clear and set bits,
+//! starting with a run of clear bits which may be empty.
+
+use binemit::{CodeOffset, Stackmap, StackmapSink};
+use super::leb128::{Leb128Error, get_uleb128, put_uleb128};
+use ir::stackslot::StackOffset;
+use std::error::Error as StdError;
+use std::fmt;
+use std::vec::Vec;
+
+/// The version of the encoding produced by `StackmapWriter`.
+pub const STACKMAP_FORMAT_VERSION: u8 = 1;
+
+/// An error found while decoding stack maps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackmapFormatError {
+    /// The data was encoded with an unknown version of the format.
+    UnsupportedVersion(u8),
+
+    /// The data ends in the middle of an entry.
+    Truncated,
+
+    /// A number in the data is out of range.
+    Corrupt,
+}
+
+impl fmt::Display for StackmapFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StackmapFormatError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported stack map format version {}", v)
+            }
+            StackmapFormatError::Truncated => write!(f, "Truncated stack map data"),
+            StackmapFormatError::Corrupt => write!(f, "Corrupt stack map data"),
+        }
+    }
+}
+
+impl StdError for StackmapFormatError {
+    fn description(&self) -> &str {
+        match *self {
+            StackmapFormatError::UnsupportedVersion(_) => "Unsupported stack map format version",
+            StackmapFormatError::Truncated => "Truncated stack map data",
+            StackmapFormatError::Corrupt => "Corrupt stack map data",
+        }
+    }
+}
+
+/// Result of decoding stack maps.
+pub type Result<T> = ::std::result::Result<T, StackmapFormatError>;
+
+impl From<Leb128Error> for StackmapFormatError {
+    fn from(e: Leb128Error) -> Self {
+        match e {
+            Leb128Error::Truncated => StackmapFormatError::Truncated,
+            Leb128Error::Overflow => StackmapFormatError::Corrupt,
+        }
+    }
+}
+
+/// A `StackmapSink` which encodes the stack maps of a function.
+///
+/// Pass it to `Context::emit_stackmaps()`, and then call `finish()` to get the encoded stack maps.
+#[derive(Clone, Debug)]
+pub struct StackmapWriter {
+    word_size: u32,
+    count: u32,
+    last_offset: CodeOffset,
+    entries: Vec<u8>,
+}
+
+impl StackmapWriter {
+    /// Create a writer for stack maps where all references are aligned to `word_size` bytes.
+    ///
+    /// This is normally the pointer size of the target.
+    pub fn new(word_size: u32) -> Self {
+        assert!(word_size > 0, "Zero word size");
+        Self {
+            word_size,
+            count: 0,
+            last_offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Get the encoded stack maps.
+    pub fn finish(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.entries.len() + 6);
+        out.push(STACKMAP_FORMAT_VERSION);
+        put_uleb128(&mut out, self.word_size);
+        put_uleb128(&mut out, self.count);
+        out.extend_from_slice(&self.entries);
+        out
+    }
+}
+
+impl StackmapSink for StackmapWriter {
+    fn add_stackmap(&mut self, offset: CodeOffset, stackmap: &Stackmap) {
+        assert!(
+            self.count == 0 || offset > self.last_offset,
+            "Stack maps must be added in code order"
+        );
+        put_uleb128(&mut self.entries, offset - self.last_offset);
+        self.last_offset = offset;
+        self.count += 1;
+
+        // Compute the runs of the bitset from the sorted offsets.
+        let mut runs = Vec::new();
+        let mut next_bit = 0;
+        for &sp_offset in &stackmap.sp_offsets {
+            assert!(
+                sp_offset >= 0 && sp_offset as u32 % self.word_size == 0,
+                "Misaligned GC reference at sp+{}",
+                sp_offset
+            );
+            let bit = sp_offset as u32 / self.word_size;
+            if bit == next_bit && !runs.is_empty() {
+                // Extend the current run of set bits.
+                *runs.last_mut().unwrap() += 1;
+            } else {
+                runs.push(bit - next_bit);
+                runs.push(1);
+            }
+            next_bit = bit + 1;
+        }
+
+        put_uleb128(&mut self.entries, runs.len() as u32);
+        for run in runs {
+            put_uleb128(&mut self.entries, run);
+        }
+    }
+}
+
+/// A reader for stack maps encoded by `StackmapWriter`.
+///
+/// The entries are decoded lazily, so creating a reader is cheap.
+#[derive(Clone, Copy, Debug)]
+pub struct StackmapReader<'a> {
+    word_size: u32,
+    count: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> StackmapReader<'a> {
+    /// Create a reader for the encoded stack maps in `data`.
+    ///
+    /// Only the header is checked here.
+    pub fn new(mut data: &'a [u8]) -> Result<Self> {
+        let (&version, rest) = data.split_first().ok_or(StackmapFormatError::Truncated)?;
+        if version != STACKMAP_FORMAT_VERSION {
+            return Err(StackmapFormatError::UnsupportedVersion(version));
+        }
+        data = rest;
+        let word_size = get_uleb128(&mut data)?;
+        if word_size == 0 {
+            return Err(StackmapFormatError::Corrupt);
+        }
+        let count = get_uleb128(&mut data)?;
+        Ok(Self {
+            word_size,
+            count,
+            entries: data,
+        })
+    }
+
+    /// Get the number of stack maps.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Are there any stack maps?
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterate over the stack maps in code order.
+    pub fn iter(&self) -> StackmapIter<'a> {
+        StackmapIter {
+            reader: *self,
+            index: 0,
+            offset: 0,
+            data: self.entries,
+        }
+    }
+
+    /// Find the stack map for the safepoint whose return address is at `offset`.
+    ///
+    /// Only the run lengths of the preceding entries are skipped, they are not decoded into stack
+    /// maps.
+    pub fn lookup(&self, offset: CodeOffset) -> Result<Option<Stackmap>> {
+        let mut iter = self.iter();
+        while iter.index < self.count {
+            iter.index += 1;
+            iter.offset = iter.next_offset()?;
+            if iter.offset == offset {
+                return iter.decode_runs().map(Some);
+            }
+            if iter.offset > offset {
+                break;
+            }
+            let num_runs = get_uleb128(&mut iter.data)?;
+            for _ in 0..num_runs {
+                get_uleb128(&mut iter.data)?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Iterator over the stack maps in a `StackmapReader`.
+#[derive(Clone, Debug)]
+pub struct StackmapIter<'a> {
+    reader: StackmapReader<'a>,
+    index: u32,
+    offset: CodeOffset,
+    data: &'a [u8],
+}
+
+impl<'a> StackmapIter<'a> {
+    /// Decode the code offset of the next entry.
+    fn next_offset(&mut self) -> Result<CodeOffset> {
+        let delta = get_uleb128(&mut self.data)?;
+        self.offset.checked_add(delta).ok_or(
+            StackmapFormatError::Corrupt,
+        )
+    }
+
+    /// Decode the bitset runs of the current entry.
+    fn decode_runs(&mut self) -> Result<Stackmap> {
+        let word_size = self.reader.word_size;
+        let num_runs = get_uleb128(&mut self.data)?;
+        let mut stackmap = Stackmap::default();
+        let mut bit: u32 = 0;
+        for run in 0..num_runs {
+            let len = get_uleb128(&mut self.data)?;
+            let end = bit.checked_add(len).ok_or(StackmapFormatError::Corrupt)?;
+            if run % 2 == 1 {
+                for b in bit..end {
+                    let sp_offset = b.checked_mul(word_size)
+                        .filter(|&o| o <= StackOffset::max_value() as u32)
+                        .ok_or(StackmapFormatError::Corrupt)?;
+                    stackmap.sp_offsets.push(sp_offset as StackOffset);
+                }
+            }
+            bit = end;
+        }
+        Ok(stackmap)
+    }
+}
+
+impl<'a> Iterator for StackmapIter<'a> {
+    type Item = Result<(CodeOffset, Stackmap)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.reader.count {
+            return None;
+        }
+        self.index += 1;
+        let entry = self.next_offset().and_then(|offset| {
+            self.offset = offset;
+            self.decode_runs().map(|stackmap| (offset, stackmap))
+        });
+        if entry.is_err() {
+            // Don't keep decoding garbage.
+            self.index = self.reader.count;
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(sp_offsets: &[StackOffset]) -> Stackmap {
+        Stackmap { sp_offsets: sp_offsets.to_vec() }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StackmapWriter::new(8);
+        writer.add_stackmap(5, &map(&[0, 8, 16, 40]));
+        writer.add_stackmap(300, &map(&[]));
+        writer.add_stackmap(310, &map(&[2048]));
+        let data = writer.finish();
+        assert_eq!(&data[..12], &[1, 8, 3, 5, 4, 0, 3, 2, 1, 0xa7, 0x02, 0]);
+
+        let reader = StackmapReader::new(&data).unwrap();
+        assert_eq!(reader.len(), 3);
+        let maps: Vec<_> = reader.iter().map(|e| e.unwrap()).collect();
+        assert_eq!(
+            maps,
+            [
+                (5, map(&[0, 8, 16, 40])),
+                (300, map(&[])),
+                (310, map(&[2048])),
+            ]
+        );
+
+        assert_eq!(reader.lookup(310), Ok(Some(map(&[2048]))));
+        assert_eq!(reader.lookup(300), Ok(Some(map(&[]))));
+        assert_eq!(reader.lookup(6), Ok(None));
+        assert_eq!(reader.lookup(1000), Ok(None));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            StackmapReader::new(&[]).unwrap_err(),
+            StackmapFormatError::Truncated
+        );
+        assert_eq!(
+            StackmapReader::new(&[7, 8, 0]).unwrap_err(),
+            StackmapFormatError::UnsupportedVersion(7)
+        );
+        assert_eq!(
+            StackmapReader::new(&[1, 0, 0]).unwrap_err(),
+            StackmapFormatError::Corrupt
+        );
+
+        let reader = StackmapReader::new(&[1, 8, 2, 4, 2, 1]).unwrap();
+        let mut iter = reader.iter();
+        assert_eq!(iter.next(), Some(Err(StackmapFormatError::Truncated)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(reader.lookup(4), Err(StackmapFormatError::Truncated));
+    }
+}
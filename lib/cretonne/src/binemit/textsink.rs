@@ -0,0 +1,127 @@
+//! Code sink that renders binary machine code as text.
+//!
+//! The text format is used by the `bin:` directives of the `binemit` file tests and by the
+//! `bytes` annotations of the printed functions. Every value emitted is written in hexadecimal
+//! with as many digits as its size, followed by a space, and relocations are written with their
+//! target in parentheses:
+//!
+//! ```text
+//! e8 PCRel4(%foo-4) 00000000
+//! ```
+
+use ir::{ExternalName, JumpTable, SourceLoc, TrapCode};
+use super::{Addend, CodeOffset, CodeSink, Endianness, Reloc, SymbolInfo};
+use std::fmt::Write;
+use std::mem;
+use std::string::String;
+
+/// A `CodeSink` that renders the emitted machine code as text.
+pub struct TextSink {
+    offset: CodeOffset,
+    endianness: Endianness,
+    text: String,
+}
+
+impl TextSink {
+    /// Create a sink for code starting at `offset` from the beginning of the function.
+    ///
+    /// The offset matters to the PC-relative branches, which are emitted relative to the EBB
+    /// offsets computed by `relax_branches()`.
+    pub fn new(offset: CodeOffset, endianness: Endianness) -> Self {
+        Self {
+            offset,
+            endianness,
+            text: String::new(),
+        }
+    }
+
+    /// Get the text of the code emitted since the last call to `take_text()`, without the
+    /// trailing space.
+    pub fn text(&self) -> &str {
+        self.text.trim_right()
+    }
+
+    /// Take the text of the code emitted so far, leaving the sink empty.
+    pub fn take_text(&mut self) -> String {
+        let mut text = mem::replace(&mut self.text, String::new());
+        text.pop();
+        text
+    }
+}
+
+impl CodeSink for TextSink {
+    fn offset(&self) -> CodeOffset {
+        self.offset
+    }
+
+    fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    fn put1(&mut self, x: u8) {
+        let _ = write!(self.text, "{:02x} ", x);
+        self.offset += 1;
+    }
+
+    fn put2(&mut self, x: u16) {
+        let _ = write!(self.text, "{:04x} ", x);
+        self.offset += 2;
+    }
+
+    fn put4(&mut self, x: u32) {
+        let _ = write!(self.text, "{:08x} ", x);
+        self.offset += 4;
+    }
+
+    fn put8(&mut self, x: u64) {
+        let _ = write!(self.text, "{:016x} ", x);
+        self.offset += 8;
+    }
+
+    fn reloc_ebb(&mut self, reloc: Reloc, ebb_offset: CodeOffset) {
+        let _ = write!(self.text, "{}({}) ", reloc, ebb_offset);
+    }
+
+    fn reloc_external(
+        &mut self,
+        reloc: Reloc,
+        name: &ExternalName,
+        addend: Addend,
+        _symbol: SymbolInfo,
+    ) {
+        let _ = if addend != 0 {
+            write!(self.text, "{}({}{:+}) ", reloc, name, addend)
+        } else {
+            write!(self.text, "{}({}) ", reloc, name)
+        };
+    }
+
+    fn reloc_jt(&mut self, reloc: Reloc, jt: JumpTable) {
+        let _ = write!(self.text, "{}({}) ", reloc, jt);
+    }
+
+    fn trap(&mut self, _code: TrapCode, _srcloc: SourceLoc) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binemit::SymbolKind;
+
+    #[test]
+    fn text() {
+        let mut sink = TextSink::new(16, Endianness::Little);
+        let symbol = SymbolInfo {
+            kind: SymbolKind::Code,
+            preemptible: false,
+        };
+        sink.put1(0xe8);
+        sink.reloc_external(Reloc::IntelPCRel4, &ExternalName::testcase("foo"), -4, symbol);
+        sink.put4(0);
+        assert_eq!(sink.offset(), 21);
+        assert_eq!(sink.text(), "e8 PCRel4(%foo-4) 00000000");
+        assert_eq!(sink.take_text(), "e8 PCRel4(%foo-4) 00000000");
+        assert_eq!(sink.text(), "");
+        assert_eq!(sink.take_text(), "");
+    }
+}
@@ -0,0 +1,175 @@
+//! Reporting of the trap sites in a function.
+//!
+//! After code generation, an embedder may want to know which trap instructions ended up in a
+//! function, for example to build its own trap tables or to check that a function doesn't trap
+//! with a code it can't handle.
+
+use binemit::CodeOffset;
+use ir::{Function, TrapCode};
+use isa::TargetIsa;
+use std::collections::HashMap;
+use std::vec::Vec;
+
+/// A trap instruction in the generated code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrapSite {
+    /// Code offset of the trap instruction.
+    pub offset: CodeOffset,
+
+    /// The trap code of the instruction.
+    pub code: TrapCode,
+}
+
+/// Report of the trap sites in a function.
+#[derive(Clone, Debug, Default)]
+pub struct TrapReport {
+    /// The trap sites in code order.
+    pub sites: Vec<TrapSite>,
+}
+
+impl TrapReport {
+    /// Get the number of trap sites with the trap code `code`.
+    pub fn count(&self, code: TrapCode) -> usize {
+        self.sites.iter().filter(|site| site.code == code).count()
+    }
+
+    /// Get the number of trap sites for each trap code present in the function.
+    pub fn counts(&self) -> HashMap<TrapCode, usize> {
+        let mut counts = HashMap::new();
+        for site in &self.sites {
+            *counts.entry(site.code).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// Collect the trap sites of the explicit trap instructions in `func`.
+///
+/// Implicit traps, like the faults of loads from the heap guard pages or the native trapping
+/// divisions, are not included. A `TrapSink` passed to `Context::emit_to_memory()` receives all
+/// of them, with the exact offset of the trapping machine instruction and its source location.
+///
+/// This function can only be used after the code layout has been computed by the
+/// `binemit::relax_branches()` function.
+pub fn trap_report(func: &Function, isa: &TargetIsa) -> TrapReport {
+    let encinfo = isa.encoding_info();
+    let mut report = TrapReport::default();
+    for ebb in func.layout.ebbs() {
+        for (offset, inst, _) in func.inst_offsets(ebb, &encinfo) {
+            if let Some(code) = func.dfg[inst].trap_code() {
+                report.sites.push(TrapSite { offset, code });
+            }
+        }
+    }
+    report
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use binemit::{Relocations, TrapSink};
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, ExternalName, InstBuilder, MemFlags, SourceLoc};
+    use isa;
+    use legalizer::TrapHandler;
+    use settings::{self, Configurable};
+
+    fn trapping_function() -> Context {
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let ebb1 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().trapz(v0, TrapCode::IntegerDivisionByZero);
+            pos.ins().trapnz(v0, TrapCode::HeapOutOfBounds);
+            pos.ins().brz(v0, ebb1, &[]);
+            pos.ins().trap(TrapCode::HeapOutOfBounds);
+            pos.insert_ebb(ebb1);
+            pos.ins().return_(&[]);
+        }
+        ctx
+    }
+
+    #[test]
+    fn report_and_handler() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = trapping_function();
+        ctx.compile(&*isa).unwrap();
+        let report = ctx.trap_report(&*isa);
+        assert_eq!(report.sites.len(), 3);
+        assert!(report.sites[0].offset < report.sites[1].offset);
+        assert_eq!(report.count(TrapCode::HeapOutOfBounds), 2);
+        assert_eq!(report.counts()[&TrapCode::IntegerDivisionByZero], 1);
+
+        // Only the trap instructions after the handler calls remain.
+        let mut ctx = trapping_function();
+        ctx.set_trap_handler(Some(TrapHandler {
+            name: ExternalName::testcase("handler"),
+            codes: vec![TrapCode::HeapOutOfBounds],
+        }));
+        ctx.compile(&*isa).unwrap();
+        assert_eq!(ctx.func.dfg.ext_funcs.len(), 1);
+        let calls = ctx.func
+            .layout
+            .ebbs()
+            .flat_map(|ebb| ctx.func.layout.ebb_insts(ebb))
+            .filter(|&inst| ctx.func.dfg[inst].opcode().is_call())
+            .count();
+        assert_eq!(calls, 2);
+        assert_eq!(ctx.trap_report(&*isa).count(TrapCode::HeapOutOfBounds), 2);
+    }
+
+    // A trap sink that records every trap.
+    struct Traps(Vec<(CodeOffset, SourceLoc, TrapCode)>);
+
+    impl TrapSink for Traps {
+        fn trap(&mut self, offset: CodeOffset, srcloc: SourceLoc, code: TrapCode) {
+            self.0.push((offset, srcloc, code));
+        }
+    }
+
+    #[test]
+    fn trap_sink() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I64));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.set_srcloc(SourceLoc::new(1));
+            let v1 = pos.ins().load(I32, MemFlags::new(), v0, 0);
+            let mut notrap = MemFlags::new();
+            notrap.set_notrap();
+            pos.set_srcloc(SourceLoc::new(2));
+            pos.ins().store(notrap, v1, v0, 4);
+            pos.set_srcloc(SourceLoc::new(3));
+            pos.ins().trapz(v1, TrapCode::User(7));
+            pos.ins().return_(&[]);
+        }
+        let size = ctx.compile(&*isa).unwrap();
+        let mut code = vec![0; size as usize];
+        let mut traps = Traps(Vec::new());
+        ctx.emit_to_memory(code.as_mut_ptr(), &mut Relocations::new(), &mut traps, &*isa);
+
+        // The `notrap` store is not a trap site, and the conditional trap is at the `ud2`.
+        let traps = traps.0;
+        assert_eq!(traps.len(), 2);
+        assert_eq!((traps[0].1, traps[0].2), (SourceLoc::new(1), TrapCode::HeapOutOfBounds));
+        assert_eq!((traps[1].1, traps[1].2), (SourceLoc::new(3), TrapCode::User(7)));
+        let ud2 = traps[1].0 as usize;
+        assert_eq!(&code[ud2..ud2 + 2], &[0x0f, 0x0b]);
+    }
+}
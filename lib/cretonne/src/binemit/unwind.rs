@@ -0,0 +1,28 @@
+//! Unwind information for compiled functions.
+//!
+//! Native stack walkers, debuggers, and exception handlers need a description of how each
+//! function's prologue and epilogue adjust the stack frame in order to unwind through it. The
+//! format of this information depends on the platform, and it is produced by the target ISA with
+//! `TargetIsa::emit_unwind_info()`.
+
+/// The format of the unwind information to generate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameUnwindKind {
+    /// DWARF call frame instructions as used in `.eh_frame` and `.debug_frame` on System V
+    /// platforms.
+    ///
+    /// Only the call frame instructions for the function's FDE are produced. They assume a CIE
+    /// with a code alignment factor of 1, a data alignment factor of minus the pointer size, and
+    /// initial instructions describing the frame at the function entry point.
+    SystemV,
+
+    /// A Windows x64 `UNWIND_INFO` structure, suitable for referencing from a `RUNTIME_FUNCTION`
+    /// entry.
+    Windows,
+}
+
+/// A trait for receiving the unwind information of a function.
+pub trait FrameUnwindSink {
+    /// Add bytes to the unwind information.
+    fn bytes(&mut self, data: &[u8]);
+}
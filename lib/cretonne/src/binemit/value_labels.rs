@@ -0,0 +1,164 @@
+//! Machine code ranges of labeled values.
+//!
+//! A frontend can attach `ValueLabel`s to SSA values with `Function::set_value_label()`. After
+//! compilation, the live ranges of the labeled values are translated into machine code offset
+//! ranges along with the register or stack location holding the value. This is the information a
+//! debugger needs to locate source-level variables, as in DWARF location lists.
+
+use binemit::CodeOffset;
+use entity::EntityMap;
+use ir::stackslot::StackOffset;
+use ir::{ExpandedProgramPoint, Function, Inst, Value, ValueLabel, ValueLoc};
+use isa::{RegUnit, StackRef, TargetIsa};
+use regalloc::liveness::Liveness;
+use std::vec::Vec;
+
+/// The location of a labeled value in a range of machine code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueLabelLoc {
+    /// The value is in a register.
+    Reg(RegUnit),
+
+    /// The value is in memory at an offset from the stack pointer.
+    SPOffset(StackOffset),
+}
+
+/// A range of machine code where a labeled value is available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueLabelRange {
+    /// The label of the value.
+    pub label: ValueLabel,
+
+    /// Offset of the first byte of code where the value is available.
+    pub start: CodeOffset,
+
+    /// Offset of the first byte of code following the range.
+    pub end: CodeOffset,
+
+    /// Where the value can be found in this range.
+    pub loc: ValueLabelLoc,
+}
+
+/// Compute the machine code ranges of the labeled values in `func`.
+///
+/// The `liveness` analysis must be the one computed by the register allocator for `func`, and this
+/// function can only be used after the code layout has been computed by the
+/// `binemit::relax_branches()` function.
+///
+/// Values are reported in the location assigned by the register allocator. Temporary register
+/// diversions around individual instructions are not tracked. The returned ranges are sorted by
+/// label and start offset.
+pub fn value_label_ranges(
+    func: &Function,
+    isa: &TargetIsa,
+    liveness: &Liveness,
+) -> Vec<ValueLabelRange> {
+    let mut ranges = Vec::new();
+    let labels: Vec<(Value, ValueLabel)> = func.value_labels
+        .keys()
+        .filter_map(|v| func.value_labels[v].expand().map(|l| (v, l)))
+        .filter(|&(v, _)| func.dfg.value_is_valid(v))
+        .collect();
+    if labels.is_empty() {
+        return ranges;
+    }
+
+    // Code offsets of the end of each instruction.
+    let encinfo = isa.encoding_info();
+    let mut inst_ends = EntityMap::<Inst, CodeOffset>::new();
+    for ebb in func.layout.ebbs() {
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            inst_ends[inst] = offset + size;
+        }
+    }
+    let end_of = |pp: ExpandedProgramPoint| match pp {
+        ExpandedProgramPoint::Inst(inst) => inst_ends[inst],
+        ExpandedProgramPoint::Ebb(ebb) => func.offsets[ebb],
+    };
+
+    let ctx = liveness.context(&func.layout);
+    for (value, label) in labels {
+        // Labels on aliases describe the value they resolve to.
+        let value = func.dfg.resolve_aliases(value);
+        let lr = match liveness.get(value) {
+            Some(lr) => lr,
+            None => continue,
+        };
+        let loc = match func.locations[value] {
+            ValueLoc::Reg(ru) => ValueLabelLoc::Reg(ru),
+            ValueLoc::Stack(ss) => {
+                ValueLabelLoc::SPOffset(StackRef::sp(ss, &func.stack_slots).offset)
+            }
+            ValueLoc::Unassigned => continue,
+        };
+        let mut push = |start: CodeOffset, end: CodeOffset| if start < end {
+            ranges.push(ValueLabelRange {
+                label,
+                start,
+                end,
+                loc,
+            });
+        };
+
+        // The local interval in the defining EBB starts after the definition.
+        push(end_of(lr.def().into()), end_of(lr.def_local_end().into()));
+
+        // Add an interval for each EBB where the value is live-in.
+        for ebb in func.layout.ebbs() {
+            if let Some(end) = lr.livein_local_end(ebb, ctx) {
+                push(func.offsets[ebb], inst_ends[end]);
+            }
+        }
+    }
+
+    ranges.sort_by_key(|r| (r.label, r.start));
+    ranges
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    #[test]
+    fn param_and_result() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I64));
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I64);
+        let v1;
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            v1 = pos.ins().iadd(v0, v0);
+            let v2 = pos.ins().iadd(v1, v0);
+            pos.ins().return_(&[v2]);
+        }
+        let x = ValueLabel::with_number(0).unwrap();
+        let y = ValueLabel::with_number(1).unwrap();
+        ctx.func.set_value_label(v0, x);
+        ctx.func.set_value_label(v1, y);
+        let code_size = ctx.compile(&*isa).unwrap();
+
+        let ranges = ctx.value_label_ranges(&*isa);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].label, x);
+        assert_eq!(ranges[1].label, y);
+        for range in &ranges {
+            assert!(range.start < range.end);
+            assert!(range.end <= code_size);
+        }
+        // `v1` is defined after `v0` is available.
+        assert!(ranges[0].start < ranges[1].start);
+    }
+}
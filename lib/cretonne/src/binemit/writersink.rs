@@ -0,0 +1,294 @@
+//! Code sink that streams binary machine code to a writer.
+//!
+//! A `MemoryCodeSink` needs a buffer for the whole function. Machine-generated functions with
+//! hundreds of thousands of instructions can have megabytes of code, and an embedder that writes
+//! the code to a file anyway doesn't need to hold all of it in memory. The `WriterCodeSink` keeps
+//! at most one chunk of code in memory, and passes every full chunk on to an `io::Write`.
+
+use ir::{ExternalName, JumpTable, SourceLoc, TrapCode};
+use super::{Addend, CodeOffset, CodeSink, Endianness, Reloc, RelocSink, SymbolInfo, TrapSink};
+use std::io::{self, Write};
+use std::mem;
+use std::vec::Vec;
+
+/// The chunk size used by `Context::emit_to_writer()`.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A `CodeSink` that writes binary machine code to an `io::Write` in chunks.
+///
+/// Multi-byte values are written in the byte order given to `new()`. Any relocations and trap
+/// sites in the function are forwarded to the `RelocSink` and `TrapSink` trait objects, with
+/// offsets from the beginning of the function.
+///
+/// The `CodeSink` methods can't report errors, so the first write error is kept and returned by
+/// `finish()`. Nothing more is written after an error.
+pub struct WriterCodeSink<'a> {
+    out: &'a mut Write,
+    relocs: &'a mut RelocSink,
+    traps: &'a mut TrapSink,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    offset: CodeOffset,
+    endianness: Endianness,
+    error: Option<io::Error>,
+}
+
+impl<'a> WriterCodeSink<'a> {
+    /// Create a code sink that writes to `out` in chunks of `chunk_size` bytes.
+    pub fn new(
+        out: &'a mut Write,
+        chunk_size: usize,
+        relocs: &'a mut RelocSink,
+        traps: &'a mut TrapSink,
+        endianness: Endianness,
+    ) -> Self {
+        assert!(chunk_size > 0, "Zero chunk size");
+        Self {
+            out,
+            relocs,
+            traps,
+            chunk: Vec::with_capacity(chunk_size),
+            chunk_size,
+            offset: 0,
+            endianness,
+            error: None,
+        }
+    }
+
+    /// Write the last partial chunk and flush the writer.
+    ///
+    /// Returns the number of bytes emitted, or the first error returned by the writer.
+    pub fn finish(mut self) -> io::Result<CodeOffset> {
+        self.write_chunk();
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.offset)
+    }
+
+    /// Append `bytes` to the current chunk, writing it out when it is full.
+    fn put(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as CodeOffset;
+        self.chunk.extend_from_slice(bytes);
+        if self.chunk.len() >= self.chunk_size {
+            self.write_chunk();
+        }
+    }
+
+    /// Append the low `size` bytes of `x` in the byte order of the sink.
+    fn put_int(&mut self, x: u32, size: usize) {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes[..size].iter_mut().enumerate() {
+            let shift = match self.endianness {
+                Endianness::Little => i,
+                Endianness::Big => size - 1 - i,
+            };
+            *byte = (x >> (8 * shift)) as u8;
+        }
+        self.put(&bytes[..size]);
+    }
+
+    /// Write out the current chunk, unless an error has happened before.
+    fn write_chunk(&mut self) {
+        let chunk = mem::replace(&mut self.chunk, Vec::new());
+        if self.error.is_none() && !chunk.is_empty() {
+            self.error = self.out.write_all(&chunk).err();
+        }
+        // Reuse the allocation for the next chunk.
+        self.chunk = chunk;
+        self.chunk.clear();
+    }
+}
+
+impl<'a> CodeSink for WriterCodeSink<'a> {
+    fn offset(&self) -> CodeOffset {
+        self.offset
+    }
+
+    fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    fn put1(&mut self, x: u8) {
+        self.put(&[x]);
+    }
+
+    fn put2(&mut self, x: u16) {
+        self.put_int(u32::from(x), 2);
+    }
+
+    fn put4(&mut self, x: u32) {
+        self.put_int(x, 4);
+    }
+
+    fn put8(&mut self, x: u64) {
+        let (first, second) = match self.endianness {
+            Endianness::Little => (x as u32, (x >> 32) as u32),
+            Endianness::Big => ((x >> 32) as u32, x as u32),
+        };
+        self.put4(first);
+        self.put4(second);
+    }
+
+    fn reloc_ebb(&mut self, rel: Reloc, ebb_offset: CodeOffset) {
+        let ofs = self.offset();
+        self.relocs.reloc_ebb(ofs, rel, ebb_offset);
+    }
+
+    fn reloc_external(
+        &mut self,
+        rel: Reloc,
+        name: &ExternalName,
+        addend: Addend,
+        symbol: SymbolInfo,
+    ) {
+        let ofs = self.offset();
+        self.relocs.reloc_external(ofs, rel, name, addend, symbol);
+    }
+
+    fn reloc_jt(&mut self, rel: Reloc, jt: JumpTable) {
+        let ofs = self.offset();
+        self.relocs.reloc_jt(ofs, rel, jt);
+    }
+
+    fn trap(&mut self, code: TrapCode, srcloc: SourceLoc) {
+        let ofs = self.offset();
+        self.traps.trap(ofs, srcloc, code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use binemit::{NullTrapSink, Relocations, emit_constants};
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, ConstantData, Function, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    /// A writer that records the size of each write, and fails after `limit` bytes.
+    struct Recorder {
+        data: Vec<u8>,
+        writes: Vec<usize>,
+        limit: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.data.len() + buf.len() > self.limit {
+                return Err(io::Error::new(io::ErrorKind::Other, "full"));
+            }
+            self.data.extend_from_slice(buf);
+            self.writes.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn recorder(limit: usize) -> Recorder {
+        Recorder {
+            data: Vec::new(),
+            writes: Vec::new(),
+            limit,
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let mut out = recorder(100);
+        let mut relocs = Relocations::new();
+        let mut traps = NullTrapSink {};
+        {
+            let mut sink =
+                WriterCodeSink::new(&mut out, 4, &mut relocs, &mut traps, Endianness::Little);
+            sink.put1(1);
+            sink.put2(0x0302);
+            sink.put8(0x0b0a_0908_0706_0504);
+            sink.put1(12);
+            assert_eq!(sink.offset(), 12);
+            assert_eq!(sink.finish().unwrap(), 12);
+        }
+        assert_eq!(out.data, (1..13).collect::<Vec<u8>>());
+        assert_eq!(out.writes, [7, 4, 1]);
+
+        // The write error is reported by `finish()`.
+        let mut out = recorder(5);
+        let sink = {
+            let mut sink =
+                WriterCodeSink::new(&mut out, 4, &mut relocs, &mut traps, Endianness::Little);
+            sink.put8(0);
+            sink.put1(0);
+            sink.finish()
+        };
+        assert!(sink.is_err());
+        assert_eq!(out.data, [0; 4]);
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut out = recorder(100);
+        let mut relocs = Relocations::new();
+        let mut traps = NullTrapSink {};
+        {
+            let mut sink =
+                WriterCodeSink::new(&mut out, 4, &mut relocs, &mut traps, Endianness::Big);
+            assert_eq!(sink.endianness(), Endianness::Big);
+            sink.put1(1);
+            sink.put2(0x0203);
+            sink.put4(0x0405_0607);
+            sink.put8(0x0809_0a0b_0c0d_0e0f);
+            assert_eq!(sink.finish().unwrap(), 15);
+        }
+        assert_eq!(out.data, (1..16).collect::<Vec<u8>>());
+
+        // Constants are emitted as numbers in the same byte order.
+        let mut func = Function::new();
+        let constant = func.create_constant(ConstantData::from_bits(0x0102_0304, 4));
+        func.constant_offsets[constant] = 4;
+        let mut out = recorder(100);
+        {
+            let mut sink =
+                WriterCodeSink::new(&mut out, 4, &mut relocs, &mut traps, Endianness::Big);
+            emit_constants(&func, &mut sink);
+            sink.finish().unwrap();
+        }
+        assert_eq!(out.data, [0, 0, 0, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn same_as_memory() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let mut v = pos.ins().iconst(I64, 0x1234_5678_9abc);
+            for i in 0..100 {
+                v = pos.ins().iadd_imm(v, i);
+            }
+            pos.ins().return_(&[v]);
+        }
+        let size = ctx.compile(&*isa).unwrap();
+        let mut relocs = Relocations::new();
+        let mut traps = NullTrapSink {};
+        let mut mem = vec![0; size as usize];
+        ctx.emit_to_memory(mem.as_mut_ptr(), &mut relocs, &mut traps, &*isa);
+
+        let mut out = Vec::new();
+        let written = ctx.emit_to_writer(&mut out, &mut relocs, &mut traps, &*isa).unwrap();
+        assert_eq!(written, size);
+        assert_eq!(out, mem);
+    }
+}
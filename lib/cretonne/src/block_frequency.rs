@@ -0,0 +1,476 @@
+//! Static estimation of branch probabilities and EBB frequencies.
+//!
+//! Without a profile, the compiler can still make a reasonable guess about which parts of a
+//! function run often. This analysis predicts the probability of each CFG edge with a few static
+//! heuristics in the style of Ball and Larus:
+//!
+//! - Branches that stay in a loop are more likely than branches that leave it.
+//! - Branches to EBBs that are marked cold or that end in a `trap` are very unlikely.
+//! - Branches to EBBs that return from the function are less likely than the alternatives.
+//!
+//! The edge probabilities are then propagated through the CFG to estimate how often each EBB is
+//! executed per call of the function. Loops are handled by computing the probability of going
+//! around each loop, innermost loop first, and scaling the frequency of the loop header by the
+//! expected number of iterations.
+//!
+//! Frequencies are relative to the entry EBB which has a frequency of 1. An EBB in a loop
+//! typically has a frequency larger than 1, and an unlikely EBB a frequency close to 0.
+//!
+//! When the function has a profile in `func.profile`, the measured counts replace the estimates.
+//! The probabilities of the edges leaving an EBB are derived from the counts of the EBB and its
+//! conditional branches, and the frequency of an EBB with a count is its count relative to the
+//! count of the entry EBB. The EBBs that never ran have a frequency of 0. The heuristics are still
+//! used for the EBBs and branches without counts.
+
+use dominator_tree::DominatorTree;
+use entity::EntityMap;
+use flowgraph::ControlFlowGraph;
+use ir::instructions::BranchInfo;
+use ir::{Ebb, Function, Opcode};
+use loop_analysis::LoopAnalysis;
+use std::vec::Vec;
+use timing;
+
+/// Relative weight of a branch that stays in the current loop compared to one that leaves it.
+const LOOP_WEIGHT: f64 = 8.0;
+
+/// Relative weight of a branch to an EBB that returns compared to other branches.
+const RETURN_WEIGHT: f64 = 0.5;
+
+/// Relative weight of a branch to a cold or trapping EBB compared to other branches.
+const UNLIKELY_WEIGHT: f64 = 0.001;
+
+/// Upper bound on the expected number of iterations of a single loop.
+const MAX_LOOP_SCALE: f64 = 1000.0;
+
+/// Estimated branch probabilities and EBB frequencies for a function.
+pub struct BlockFrequency {
+    /// The reachable EBBs in reverse post-order.
+    rpo: Vec<Ebb>,
+
+    /// Position of each EBB in `rpo`, plus one. Unreachable EBBs are 0.
+    rpo_number: EntityMap<Ebb, u32>,
+
+    /// Successor EBBs and the probability of branching to them, for all EBBs.
+    succs: Vec<(Ebb, f64)>,
+
+    /// The range of `succs` belonging to each EBB.
+    succ_range: EntityMap<Ebb, (u32, u32)>,
+
+    /// Expected number of iterations of the loop headed by each EBB, or 1 for other EBBs.
+    scale: EntityMap<Ebb, f64>,
+
+    /// Estimated execution frequency of each EBB.
+    freq: EntityMap<Ebb, f64>,
+
+    valid: bool,
+}
+
+impl BlockFrequency {
+    /// Allocate a new blank block frequency analysis. Use `compute` to compute the frequencies of
+    /// a function.
+    pub fn new() -> Self {
+        Self {
+            rpo: Vec::new(),
+            rpo_number: EntityMap::new(),
+            succs: Vec::new(),
+            succ_range: EntityMap::new(),
+            scale: EntityMap::new(),
+            freq: EntityMap::new(),
+            valid: false,
+        }
+    }
+
+    /// Allocate and compute the block frequencies of `func`.
+    pub fn with_function(
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        domtree: &DominatorTree,
+        loops: &LoopAnalysis,
+    ) -> Self {
+        let mut bf = Self::new();
+        bf.compute(func, cfg, domtree, loops);
+        bf
+    }
+
+    /// Estimate the branch probabilities and EBB frequencies of `func`.
+    ///
+    /// Needs the control flow graph, the dominator tree, and the loop analysis.
+    pub fn compute(
+        &mut self,
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        domtree: &DominatorTree,
+        loops: &LoopAnalysis,
+    ) {
+        let _tt = timing::block_frequency();
+        debug_assert!(domtree.is_valid());
+        debug_assert!(loops.is_valid());
+        self.clear();
+        let num_ebbs = func.dfg.num_ebbs();
+        self.rpo_number.resize(num_ebbs);
+        self.succ_range.resize(num_ebbs);
+        self.scale.resize(num_ebbs);
+        self.freq.resize(num_ebbs);
+
+        self.rpo.extend(domtree.cfg_postorder().iter().rev());
+        for (n, &ebb) in self.rpo.iter().enumerate() {
+            self.rpo_number[ebb] = n as u32 + 1;
+        }
+        self.compute_probabilities(func, cfg, loops);
+        self.compute_loop_scales(loops);
+        self.compute_frequencies(func);
+        self.valid = true;
+    }
+
+    /// Check if the block frequencies are in a valid state.
+    ///
+    /// Note that this doesn't perform any kind of validity checks. It simply checks if the
+    /// `compute()` method has been called since the last `clear()`.
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Clear all the data structures, but keep the allocated memory.
+    pub fn clear(&mut self) {
+        self.rpo.clear();
+        self.rpo_number.clear();
+        self.succs.clear();
+        self.succ_range.clear();
+        self.scale.clear();
+        self.freq.clear();
+        self.valid = false;
+    }
+
+    /// Get the estimated number of times `ebb` executes per call of the function.
+    ///
+    /// The entry EBB has a frequency of 1 unless it is a loop header. Unreachable EBBs and the
+    /// EBBs that the profile shows never ran have a frequency of 0.
+    pub fn frequency(&self, ebb: Ebb) -> f64 {
+        self.freq.get(ebb).cloned().unwrap_or(0.0)
+    }
+
+    /// Get the frequency of `ebb` as an integer weight of at least 1.
+    ///
+    /// This is convenient for scaling counts that would otherwise treat every EBB the same.
+    pub fn weight(&self, ebb: Ebb) -> u32 {
+        self.frequency(ebb).round().max(1.0).min(f64::from(u16::max_value())) as u32
+    }
+
+    /// Get the estimated probability that `from` branches to `to` when it executes.
+    ///
+    /// Returns 0 if `to` isn't a successor of `from`.
+    pub fn edge_probability(&self, from: Ebb, to: Ebb) -> f64 {
+        self.successors(from)
+            .iter()
+            .find(|&&(succ, _)| succ == to)
+            .map_or(0.0, |&(_, prob)| prob)
+    }
+
+    /// Get the successors of `ebb` with their probabilities.
+    fn successors(&self, ebb: Ebb) -> &[(Ebb, f64)] {
+        match self.succ_range.get(ebb) {
+            Some(&(start, end)) => &self.succs[start as usize..end as usize],
+            None => &[],
+        }
+    }
+
+    /// Is the edge `from -> to` a back edge or an irreducible edge which goes backwards in RPO?
+    fn is_retreating(&self, from: Ebb, to: Ebb) -> bool {
+        self.rpo_number[to] <= self.rpo_number[from]
+    }
+
+    /// Predict the probability of each CFG edge.
+    ///
+    /// The probabilities of the edges leaving an EBB come from the profile when the counts are
+    /// known, and from the heuristic weights of the destinations otherwise.
+    fn compute_probabilities(
+        &mut self,
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        loops: &LoopAnalysis,
+    ) {
+        let mut counts = Vec::new();
+        for &ebb in &self.rpo {
+            let start = self.succs.len();
+            if profiled_edges(func, ebb, &mut counts) {
+                for succ in cfg.succ_iter(ebb) {
+                    let count: u64 = counts
+                        .iter()
+                        .filter(|&&(dest, _)| dest == succ)
+                        .map(|&(_, count)| count)
+                        .sum();
+                    self.succs.push((succ, count as f64));
+                }
+            } else {
+                let lp = loops.innermost_loop(ebb);
+                for succ in cfg.succ_iter(ebb) {
+                    let mut weight = 1.0;
+                    if let Some(lp) = lp {
+                        if loops.is_in_loop(succ, lp) {
+                            weight *= LOOP_WEIGHT;
+                        }
+                    }
+                    if func.layout.is_cold(succ) {
+                        weight *= UNLIKELY_WEIGHT;
+                    } else {
+                        match func.layout.last_inst(succ).map(|inst| func.dfg[inst].opcode()) {
+                            Some(Opcode::Trap) => weight *= UNLIKELY_WEIGHT,
+                            Some(opcode) if opcode.is_return() => weight *= RETURN_WEIGHT,
+                            _ => {}
+                        }
+                    }
+                    self.succs.push((succ, weight));
+                }
+            }
+
+            let succs = &mut self.succs[start..];
+            let total: f64 = succs.iter().map(|&(_, weight)| weight).sum();
+            for succ in succs {
+                succ.1 /= total;
+            }
+            self.succ_range[ebb] = (start as u32, self.succs.len() as u32);
+        }
+    }
+
+    /// Compute the expected number of iterations of each loop.
+    ///
+    /// The frequencies inside a loop are propagated from its header with a frequency of 1. The
+    /// probability of returning to the header through the back edges is then the cyclic
+    /// probability `cp` of the loop, and the header executes `1 / (1 - cp)` times for each entry
+    /// into the loop. Inner loops come after their parents in RPO, so visiting the headers
+    /// backwards computes the scales of inner loops before they're needed by the outer loop.
+    fn compute_loop_scales(&mut self, loops: &LoopAnalysis) {
+        for &ebb in &self.rpo {
+            self.scale[ebb] = 1.0;
+        }
+        let mut headers: Vec<_> = loops.loops().map(|lp| (loops.loop_header(lp), lp)).collect();
+        headers.sort_by_key(|&(header, _)| self.rpo_number[header]);
+
+        for &(header, lp) in headers.iter().rev() {
+            let first = self.rpo_number[header] as usize - 1;
+            for &ebb in &self.rpo[first..] {
+                self.freq[ebb] = 0.0;
+            }
+            self.freq[header] = 1.0;
+
+            let mut cyclic = 0.0;
+            for &ebb in &self.rpo[first..] {
+                if !loops.is_in_loop(ebb, lp) {
+                    continue;
+                }
+                let freq = if ebb == header {
+                    1.0
+                } else {
+                    self.freq[ebb] * self.scale[ebb]
+                };
+                self.freq[ebb] = freq;
+                let (start, end) = self.succ_range[ebb];
+                for i in start as usize..end as usize {
+                    let (succ, prob) = self.succs[i];
+                    if succ == header {
+                        cyclic += freq * prob;
+                    } else if !self.is_retreating(ebb, succ) {
+                        self.freq[succ] += freq * prob;
+                    }
+                }
+            }
+            let cyclic = cyclic.min(1.0 - 1.0 / MAX_LOOP_SCALE);
+            self.scale[header] = 1.0 / (1.0 - cyclic);
+        }
+    }
+
+    /// Propagate the frequencies from the entry EBB in RPO, ignoring retreating edges.
+    ///
+    /// The effect of the back edges is already captured by the scale of the loop headers. The
+    /// EBBs with a profile count get their measured frequency instead.
+    fn compute_frequencies(&mut self, func: &Function) {
+        for &ebb in &self.rpo {
+            self.freq[ebb] = 0.0;
+        }
+        let mut entry_count = None;
+        if let Some(&entry) = self.rpo.first() {
+            self.freq[entry] = 1.0;
+            entry_count = func.profile.ebb_count(entry).filter(|&count| count > 0);
+        }
+        for &ebb in &self.rpo {
+            let freq = match (func.profile.ebb_count(ebb), entry_count) {
+                (Some(count), Some(entry_count)) => count as f64 / entry_count as f64,
+                _ => self.freq[ebb] * self.scale[ebb],
+            };
+            self.freq[ebb] = freq;
+            let (start, end) = self.succ_range[ebb];
+            for i in start as usize..end as usize {
+                let (succ, prob) = self.succs[i];
+                if !self.is_retreating(ebb, succ) {
+                    self.freq[succ] += freq * prob;
+                }
+            }
+        }
+    }
+}
+
+/// Get the number of times `ebb` branched to each destination according to the profile.
+///
+/// Fill `counts` with the destinations of the branches in `ebb` and the number of times they were
+/// taken. Returns false if the counts aren't known or if `ebb` never ran.
+fn profiled_edges(func: &Function, ebb: Ebb, counts: &mut Vec<(Ebb, u64)>) -> bool {
+    counts.clear();
+    let mut remaining = match func.profile.ebb_count(ebb) {
+        Some(count) if count > 0 => count,
+        _ => return false,
+    };
+    for inst in func.layout.ebb_insts(ebb) {
+        let dest = match func.dfg.analyze_branch(inst) {
+            BranchInfo::NotABranch => continue,
+            BranchInfo::SingleDest(dest, _) => dest,
+            BranchInfo::Table(_) => return false,
+        };
+        let taken = if func.dfg[inst].opcode().is_terminator() {
+            remaining
+        } else {
+            match func.profile.branch_count(inst) {
+                Some(taken) => taken.min(remaining),
+                None => return false,
+            }
+        };
+        remaining -= taken;
+        counts.push((dest, taken));
+    }
+    counts.iter().any(|&(_, count)| count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::{InstBuilder, TrapCode, types};
+
+    fn analyze(func: &Function) -> BlockFrequency {
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        let mut loops = LoopAnalysis::new();
+        loops.compute(func, &cfg, &domtree);
+        BlockFrequency::with_function(func, &cfg, &domtree, &loops)
+    }
+
+    fn assert_near(x: f64, y: f64) {
+        assert!((x - y).abs() < 1e-9, "{} != {}", x, y);
+    }
+
+    #[test]
+    fn diamond() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let ebb4 = func.dfg.make_ebb();
+        let cond = func.dfg.append_ebb_param(ebb0, types::I32);
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_ebb(ebb0);
+            cur.ins().brz(cond, ebb1, &[]);
+            cur.ins().jump(ebb2, &[]);
+            cur.insert_ebb(ebb1);
+            cur.ins().brnz(cond, ebb4, &[]);
+            cur.ins().jump(ebb3, &[]);
+            cur.insert_ebb(ebb2);
+            cur.ins().jump(ebb3, &[]);
+            cur.insert_ebb(ebb3);
+            cur.ins().return_(&[]);
+            cur.insert_ebb(ebb4);
+            cur.ins().trap(TrapCode::User(0));
+        }
+
+        let bf = analyze(&func);
+        assert_near(bf.frequency(ebb0), 1.0);
+        assert_near(bf.edge_probability(ebb0, ebb1), 0.5);
+        assert_near(bf.edge_probability(ebb0, ebb3), 0.0);
+        assert_near(bf.frequency(ebb1) + bf.frequency(ebb2), 1.0);
+        assert_near(bf.frequency(ebb3), 1.0 - bf.frequency(ebb4));
+        assert!(bf.frequency(ebb4) < 0.01);
+        assert_eq!(bf.weight(ebb4), 1);
+    }
+
+    #[test]
+    fn nested_loops() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let ebb4 = func.dfg.make_ebb();
+        let ebb5 = func.dfg.make_ebb();
+        let cond = func.dfg.append_ebb_param(ebb0, types::I32);
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_ebb(ebb0);
+            cur.ins().jump(ebb1, &[]);
+            cur.insert_ebb(ebb1);
+            cur.ins().jump(ebb2, &[]);
+            cur.insert_ebb(ebb2);
+            cur.ins().brnz(cond, ebb2, &[]);
+            cur.ins().jump(ebb3, &[]);
+            cur.insert_ebb(ebb3);
+            cur.ins().brnz(cond, ebb1, &[]);
+            cur.ins().jump(ebb4, &[]);
+            cur.insert_ebb(ebb4);
+            cur.ins().jump(ebb5, &[]);
+            cur.insert_ebb(ebb5);
+            cur.ins().return_(&[]);
+        }
+
+        let bf = analyze(&func);
+        // Both loops go around with probability 8/9, so they each iterate 9 times on average.
+        assert_near(bf.edge_probability(ebb2, ebb2), 8.0 / 9.0);
+        assert_near(bf.frequency(ebb0), 1.0);
+        assert_near(bf.frequency(ebb1), 9.0);
+        assert_near(bf.frequency(ebb2), 81.0);
+        assert_near(bf.frequency(ebb3), 9.0);
+        assert_near(bf.frequency(ebb5), 1.0);
+        assert_eq!(bf.weight(ebb2), 81);
+    }
+
+    #[test]
+    fn profile() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let cond = func.dfg.append_ebb_param(ebb0, types::I32);
+        let branch;
+        {
+            let mut cur = FuncCursor::new(&mut func);
+            cur.insert_ebb(ebb0);
+            branch = cur.ins().brz(cond, ebb1, &[]);
+            cur.ins().jump(ebb2, &[]);
+            cur.insert_ebb(ebb1);
+            cur.ins().trap(TrapCode::User(0));
+            cur.insert_ebb(ebb2);
+            cur.ins().brnz(cond, ebb2, &[]);
+            cur.ins().jump(ebb3, &[]);
+            cur.insert_ebb(ebb3);
+            cur.ins().return_(&[]);
+        }
+
+        // The trap is predicted to be unlikely, but the profile shows it is taken most of the time.
+        let estimated = analyze(&func);
+        func.profile.set_ebb_count(ebb0, 100);
+        func.profile.set_branch_count(branch, 75);
+        func.profile.set_ebb_count(ebb1, 75);
+        func.profile.set_ebb_count(ebb2, 500);
+
+        let bf = analyze(&func);
+        assert_near(bf.edge_probability(ebb0, ebb1), 0.75);
+        assert_near(bf.edge_probability(ebb0, ebb2), 0.25);
+        assert_near(bf.frequency(ebb1), 0.75);
+        assert_near(bf.frequency(ebb2), 5.0);
+
+        // The loop has no branch count, so the static estimate is used for its exit.
+        let exit = estimated.edge_probability(ebb2, ebb3);
+        assert_near(bf.edge_probability(ebb2, ebb3), exit);
+        assert_near(bf.frequency(ebb3), 5.0 * exit);
+    }
+}
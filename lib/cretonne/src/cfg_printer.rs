@@ -1,24 +1,72 @@
 //! The `CFGPrinter` utility.
 
 use std::fmt::{Result, Write, Display, Formatter};
+use std::vec::Vec;
 
+use dominator_tree::DominatorTree;
+use entity::EntityMap;
 use flowgraph::ControlFlowGraph;
-use ir::Function;
+use ir::{Ebb, Function, Value, ValueDef};
 use ir::instructions::BranchInfo;
+use loop_analysis::{Loop, LoopAnalysis};
+
+/// Extra information to include in the graph printed by a `CFGPrinter`.
+#[derive(Clone, Copy, Default)]
+pub struct CFGAnnotations {
+    /// Add a dashed edge from the immediate dominator of each EBB.
+    pub domtree: bool,
+
+    /// Group the EBBs of each loop in a nested cluster.
+    pub loops: bool,
+
+    /// List the values that are live into each EBB in its node.
+    ///
+    /// EBB parameters are not included, since they are defined by the EBB.
+    pub live_ins: bool,
+}
 
 /// A utility for pretty-printing the CFG of a `Function`.
 pub struct CFGPrinter<'a> {
     func: &'a Function,
     cfg: ControlFlowGraph,
+    domtree: Option<DominatorTree>,
+    loops: Option<LoopAnalysis>,
+    live_ins: Option<EntityMap<Ebb, Vec<Value>>>,
 }
 
 /// A utility for pretty-printing the CFG of a `Function`.
 impl<'a> CFGPrinter<'a> {
     /// Create a new CFGPrinter.
     pub fn new(func: &'a Function) -> CFGPrinter<'a> {
+        Self::with_annotations(func, CFGAnnotations::default())
+    }
+
+    /// Create a new CFGPrinter that includes `annotations` in the graph.
+    pub fn with_annotations(func: &'a Function, annotations: CFGAnnotations) -> CFGPrinter<'a> {
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = if annotations.domtree || annotations.loops {
+            Some(DominatorTree::with_function(func, &cfg))
+        } else {
+            None
+        };
+        let loops = if annotations.loops {
+            let mut loops = LoopAnalysis::new();
+            loops.compute(func, &cfg, domtree.as_ref().unwrap());
+            Some(loops)
+        } else {
+            None
+        };
+        let live_ins = if annotations.live_ins {
+            Some(compute_live_ins(func, &cfg))
+        } else {
+            None
+        };
         CFGPrinter {
             func,
-            cfg: ControlFlowGraph::with_function(func),
+            cfg,
+            domtree: if annotations.domtree { domtree } else { None },
+            loops,
+            live_ins,
         }
     }
 
@@ -27,6 +75,7 @@ impl<'a> CFGPrinter<'a> {
         self.header(w)?;
         self.ebb_nodes(w)?;
         self.cfg_connections(w)?;
+        self.domtree_connections(w)?;
         writeln!(w, "}}")
     }
 
@@ -39,26 +88,77 @@ impl<'a> CFGPrinter<'a> {
     }
 
     fn ebb_nodes(&self, w: &mut Write) -> Result {
-        for ebb in &self.func.layout {
-            write!(w, "    {} [shape=record, label=\"{{{}", ebb, ebb)?;
-            // Add all outgoing branch instructions to the label.
-            for inst in self.func.layout.ebb_insts(ebb) {
-                let idata = &self.func.dfg[inst];
-                match idata.analyze_branch(&self.func.dfg.value_lists) {
-                    BranchInfo::SingleDest(dest, _) => {
-                        write!(w, " | <{}>{} {}", inst, idata.opcode(), dest)?
+        match self.loops {
+            Some(ref loops) => {
+                // The EBBs outside any loop, then the top-level loops with their nested loops.
+                for ebb in &self.func.layout {
+                    if loops.innermost_loop(ebb).is_none() {
+                        self.ebb_node(w, ebb, 1)?;
                     }
-                    BranchInfo::Table(table) => {
-                        write!(w, " | <{}>{} {}", inst, idata.opcode(), table)?
+                }
+                for lp in loops.loops() {
+                    if loops.loop_parent(lp).is_none() {
+                        self.loop_cluster(w, loops, lp, 1)?;
                     }
-                    BranchInfo::NotABranch => {}
                 }
             }
-            writeln!(w, "}}\"]")?
+            None => {
+                for ebb in &self.func.layout {
+                    self.ebb_node(w, ebb, 1)?;
+                }
+            }
         }
         Ok(())
     }
 
+    fn ebb_node(&self, w: &mut Write, ebb: Ebb, depth: usize) -> Result {
+        write!(w, "{:1$}{2} [shape=record, label=\"{{{2}", "", depth * 4, ebb)?;
+        if let Some(ref live_ins) = self.live_ins {
+            write!(w, " | live-in:")?;
+            for value in &live_ins[ebb] {
+                write!(w, " {}", value)?;
+            }
+        }
+        // Add all outgoing branch instructions to the label.
+        for inst in self.func.layout.ebb_insts(ebb) {
+            let idata = &self.func.dfg[inst];
+            match idata.analyze_branch(&self.func.dfg.value_lists) {
+                BranchInfo::SingleDest(dest, _) => {
+                    write!(w, " | <{}>{} {}", inst, idata.opcode(), dest)?
+                }
+                BranchInfo::Table(table) => {
+                    write!(w, " | <{}>{} {}", inst, idata.opcode(), table)?
+                }
+                BranchInfo::NotABranch => {}
+            }
+        }
+        writeln!(w, "}}\"]")
+    }
+
+    fn loop_cluster(&self, w: &mut Write, loops: &LoopAnalysis, lp: Loop, depth: usize) -> Result {
+        let indent = depth * 4;
+        writeln!(w, "{:1$}subgraph cluster_{2} {{", "", indent, lp)?;
+        writeln!(
+            w,
+            "{:1$}label=\"{2} (header {3})\"",
+            "",
+            indent + 4,
+            lp,
+            loops.loop_header(lp)
+        )?;
+        for ebb in &self.func.layout {
+            if loops.innermost_loop(ebb) == Some(lp) {
+                self.ebb_node(w, ebb, depth + 1)?;
+            }
+        }
+        for child in loops.loops() {
+            if loops.loop_parent(child) == Some(lp) {
+                self.loop_cluster(w, loops, child, depth + 1)?;
+            }
+        }
+        writeln!(w, "{:1$}}}", "", indent)
+    }
+
     fn cfg_connections(&self, w: &mut Write) -> Result {
         for ebb in &self.func.layout {
             for (parent, inst) in self.cfg.pred_iter(ebb) {
@@ -67,6 +167,23 @@ impl<'a> CFGPrinter<'a> {
         }
         Ok(())
     }
+
+    fn domtree_connections(&self, w: &mut Write) -> Result {
+        if let Some(ref domtree) = self.domtree {
+            for ebb in &self.func.layout {
+                if let Some(idom) = domtree.idom(ebb) {
+                    let idom_ebb = self.func.layout.inst_ebb(idom).unwrap();
+                    writeln!(
+                        w,
+                        "    {} -> {} [style=dashed, color=blue, constraint=false]",
+                        idom_ebb,
+                        ebb
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<'a> Display for CFGPrinter<'a> {
@@ -74,3 +191,96 @@ impl<'a> Display for CFGPrinter<'a> {
         self.write(f)
     }
 }
+
+/// Compute the values that are live into each EBB of `func`, sorted by value number.
+///
+/// A value is live into an EBB if it is used in the EBB or live into one of its successors, and it
+/// isn't defined in the EBB.
+fn compute_live_ins(func: &Function, cfg: &ControlFlowGraph) -> EntityMap<Ebb, Vec<Value>> {
+    let mut live_ins = EntityMap::new();
+    for ebb in &func.layout {
+        live_ins[ebb] = Vec::new();
+    }
+    let defined_in = |value: Value, ebb: Ebb| match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => func.layout.inst_ebb(inst) == Some(ebb),
+        ValueDef::Param(def_ebb, _) => def_ebb == ebb,
+    };
+
+    // Propagate the uses backwards along the CFG edges until nothing changes.
+    let mut worklist: Vec<(Ebb, Value)> = Vec::new();
+    for ebb in &func.layout {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                worklist.push((ebb, func.dfg.resolve_aliases(arg)));
+            }
+        }
+    }
+    while let Some((ebb, value)) = worklist.pop() {
+        if defined_in(value, ebb) || live_ins[ebb].contains(&value) {
+            continue;
+        }
+        live_ins[ebb].push(value);
+        for (pred, _) in cfg.pred_iter(ebb) {
+            worklist.push((pred, value));
+        }
+    }
+
+    for ebb in &func.layout {
+        live_ins[ebb].sort();
+    }
+    live_ins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::InstBuilder;
+    use std::string::ToString;
+
+    #[test]
+    fn annotations() {
+        let mut func = Function::new();
+        let entry = func.dfg.make_ebb();
+        let header = func.dfg.make_ebb();
+        let exit = func.dfg.make_ebb();
+        let (n, i) = {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(entry);
+            let n = pos.func.dfg.append_ebb_param(entry, I32);
+            pos.ins().jump(header, &[n]);
+
+            pos.insert_ebb(header);
+            let i = pos.func.dfg.append_ebb_param(header, I32);
+            let next = pos.ins().iadd(i, n);
+            pos.ins().brnz(next, header, &[next]);
+            pos.ins().jump(exit, &[]);
+
+            pos.insert_ebb(exit);
+            pos.ins().return_(&[n]);
+            (n, i)
+        };
+        assert_eq!((n.to_string(), i.to_string()), ("v0".to_string(), "v1".to_string()));
+
+        let plain = CFGPrinter::new(&func).to_string();
+        assert!(!plain.contains("cluster"));
+        assert!(!plain.contains("dashed"));
+        assert!(!plain.contains("live-in"));
+
+        let annotations = CFGAnnotations {
+            domtree: true,
+            loops: true,
+            live_ins: true,
+        };
+        let text = CFGPrinter::with_annotations(&func, annotations).to_string();
+        assert!(text.contains("    ebb0 [shape=record, label=\"{ebb0 | live-in: | <inst0>jump"));
+        assert!(text.contains(
+            "    subgraph cluster_loop0 {\n        label=\"loop0 (header ebb1)\"\n",
+        ));
+        assert!(text.contains("        ebb1 [shape=record, label=\"{ebb1 | live-in: v0 | "));
+        assert!(text.contains("    ebb2 [shape=record, label=\"{ebb2 | live-in: v0}\"]"));
+        assert!(text.contains("    ebb0 -> ebb1 [style=dashed, color=blue, constraint=false]"));
+        assert!(text.contains("    ebb1 -> ebb2 [style=dashed, color=blue, constraint=false]"));
+    }
+}
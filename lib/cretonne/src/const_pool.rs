@@ -0,0 +1,285 @@
+//! Sharing of repeated large integer constants.
+//!
+//! A 64-bit immediate that doesn't fit in a sign-extended 32-bit field needs a long encoding or
+//! a multi-instruction sequence to materialize, and every `iconst` in the function builds it
+//! again from scratch. When the same large immediate appears more than once, this pass makes the
+//! copies share a single materialization. How they share depends on the integer register
+//! pressure measured by a liveness analysis:
+//!
+//! - When there is a register to spare, one `iconst` is hoisted to the common dominator of the
+//!   copies, and the copies become aliases of its result.
+//! - When there isn't, a hoisted constant would just be spilled and reloaded. Instead every copy
+//!   is replaced with a `const_load` from one shared constant pool entry, if the target can
+//!   encode it.
+//!
+//! Copies whose results are never used are removed instead of shared.
+//!
+//! The pass runs after legalization, so any new instructions are encoded as they are inserted.
+
+use cursor::{Cursor, EncCursor};
+use dominator_tree::DominatorTree;
+use flowgraph::ControlFlowGraph;
+use ir::types::I64;
+use ir::{ConstantData, Function, Inst, InstBuilder, InstructionData, Opcode};
+use isa::TargetIsa;
+use regalloc::live_value_tracker::LiveValueTracker;
+use regalloc::liveness::Liveness;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::vec::Vec;
+use timing;
+use topo_order::TopoOrder;
+
+/// Share the materializations of repeated large constants in `func`.
+pub fn pool_constants(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    isa: &TargetIsa,
+) {
+    let _tt = timing::const_pool();
+    debug_assert!(domtree.is_valid());
+
+    let groups = find_repeated_constants(func);
+    if groups.is_empty() {
+        return;
+    }
+
+    let rc = isa.regclass_for_abi_type(I64);
+    let available = isa.allocatable_registers(func).iter(rc).count();
+    let mut liveness = Liveness::new();
+    liveness.compute(isa, func, cfg);
+    let mut pressure = max_int_pressure(func, domtree, &liveness);
+
+    for (imm, mut insts) in groups {
+        insts.retain(|&inst| {
+            let dead = liveness[func.dfg.first_result(inst)].is_dead();
+            if dead {
+                func.layout.remove_inst(inst);
+            }
+            !dead
+        });
+        if insts.len() < 2 {
+            continue;
+        }
+
+        // Each hoisted constant stays live across its whole region, adding to the pressure.
+        if pressure + 1 < available {
+            hoist_constant(func, domtree, isa, imm, &insts);
+            pressure += 1;
+        } else {
+            load_from_pool(func, isa, imm, &insts);
+        }
+    }
+}
+
+/// Find the large `iconst.i64` immediates that appear more than once in `func`.
+///
+/// Returns the immediates and their instructions in layout order.
+fn find_repeated_constants(func: &Function) -> Vec<(i64, Vec<Inst>)> {
+    let mut order = Vec::new();
+    let mut uses: HashMap<i64, Vec<Inst>> = HashMap::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            let imm: i64 = match func.dfg[inst] {
+                InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => imm.into(),
+                _ => continue,
+            };
+            if func.dfg.ctrl_typevar(inst) != I64 || imm == i64::from(imm as i32) {
+                continue;
+            }
+            let insts = uses.entry(imm).or_insert_with(Vec::new);
+            if insts.is_empty() {
+                order.push(imm);
+            }
+            insts.push(inst);
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|imm| {
+            let insts = uses.remove(&imm).unwrap();
+            if insts.len() > 1 { Some((imm, insts)) } else { None }
+        })
+        .collect()
+}
+
+/// Compute the largest number of integer values in registers that are live at the same time.
+fn max_int_pressure(func: &Function, domtree: &DominatorTree, liveness: &Liveness) -> usize {
+    let mut tracker = LiveValueTracker::new();
+    let mut topo = TopoOrder::new();
+    let mut pressure = 0;
+    let int_live = |func: &Function, tracker: &LiveValueTracker| {
+        tracker
+            .live()
+            .iter()
+            .filter(|lv| {
+                lv.affinity.is_reg() && func.dfg.value_type(lv.value).is_int()
+            })
+            .count()
+    };
+
+    topo.reset(func.layout.ebbs());
+    while let Some(ebb) = topo.next(&func.layout, domtree) {
+        tracker.ebb_top(ebb, &func.dfg, liveness, &func.layout, domtree);
+        tracker.drop_dead_params();
+        pressure = max(pressure, int_live(func, &tracker));
+        for inst in func.layout.ebb_insts(ebb) {
+            tracker.process_inst(inst, &func.dfg, liveness);
+            pressure = max(pressure, int_live(func, &tracker));
+            tracker.drop_dead(inst);
+        }
+    }
+    pressure
+}
+
+/// Replace `insts` with a single `iconst` at their common dominator.
+fn hoist_constant(
+    func: &mut Function,
+    domtree: &DominatorTree,
+    isa: &TargetIsa,
+    imm: i64,
+    insts: &[Inst],
+) {
+    let mut point = (func.layout.inst_ebb(insts[0]).unwrap(), insts[0]);
+    for &inst in &insts[1..] {
+        let bb = (func.layout.inst_ebb(inst).unwrap(), inst);
+        point = domtree.common_dominator(point, bb, &func.layout);
+    }
+
+    // Reuse one of the copies if it is already at the common dominator.
+    let hoisted = if insts.contains(&point.1) {
+        point.1
+    } else {
+        let mut pos = EncCursor::new(func, isa).at_inst(point.1);
+        pos.ins().iconst(I64, imm);
+        pos.built_inst()
+    };
+
+    for &inst in insts {
+        if inst != hoisted {
+            func.dfg.replace_with_aliases(inst, hoisted);
+            func.layout.remove_inst(inst);
+        }
+    }
+}
+
+/// Replace `insts` with loads from a shared constant pool entry, if the target supports it.
+fn load_from_pool(func: &mut Function, isa: &TargetIsa, imm: i64, insts: &[Inst]) {
+    let constant = func.create_constant(ConstantData::from_bits(imm as u64, 8));
+    let data = InstructionData::UnaryConst {
+        opcode: Opcode::ConstLoad,
+        constant,
+    };
+    let encoding = match isa.encode(&func.dfg, &data, I64) {
+        Ok(encoding) => encoding,
+        Err(_) => return,
+    };
+
+    for &inst in insts {
+        func.dfg.replace(inst).const_load(I64, constant);
+        func.encodings[inst] = encoding;
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use cursor::FuncCursor;
+    use ir::{Ebb, Function, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    fn intel() -> Box<TargetIsa> {
+        let mut b = settings::builder();
+        b.enable("is_64bit").unwrap();
+        isa::lookup("intel").unwrap().finish(settings::Flags::new(&b))
+    }
+
+    /// Build a function with `count` copies of a large constant in two EBBs, keeping `live`
+    /// other values alive across them.
+    fn build(count: usize, live: usize) -> (Function, Ebb) {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let held: Vec<_> = (0..live).map(|i| pos.ins().iconst(I64, i as i64)).collect();
+            let mut sum = pos.ins().iconst(I64, 0x1234_5678_9abc);
+            pos.ins().jump(ebb1, &[]);
+            pos.insert_ebb(ebb1);
+            for _ in 1..count {
+                let c = pos.ins().iconst(I64, 0x1234_5678_9abc);
+                sum = pos.ins().iadd(sum, c);
+            }
+            for v in held {
+                sum = pos.ins().iadd(sum, v);
+            }
+            pos.ins().return_(&[sum]);
+        }
+        (func, ebb0)
+    }
+
+    fn count(func: &Function, opcode: Opcode) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == opcode)
+            .count()
+    }
+
+    fn compile(func: Function, isa: &TargetIsa) -> Function {
+        let mut ctx = ::Context::for_function(func);
+        ctx.func.signature.returns.push(::ir::AbiParam::new(I64));
+        ctx.compute_cfg();
+        ctx.legalize(isa).unwrap();
+        ctx.compute_domtree();
+        pool_constants(&mut ctx.func, &ctx.cfg, &ctx.domtree, isa);
+        ctx.verify(isa).unwrap();
+        ctx.func
+    }
+
+    #[test]
+    fn hoist() {
+        let isa = intel();
+        let (func, ebb0) = build(3, 0);
+        let func = compile(func, &*isa);
+        assert_eq!(count(&func, Opcode::ConstLoad), 0);
+        let big: Vec<_> = func.layout
+            .ebb_insts(ebb0)
+            .filter(|&inst| match func.dfg[inst] {
+                InstructionData::UnaryImm { imm, .. } => imm == 0x1234_5678_9abc.into(),
+                _ => false,
+            })
+            .collect();
+        assert_eq!(big.len(), 1);
+        assert_eq!(count(&func, Opcode::Iconst), 1);
+    }
+
+    #[test]
+    fn pool() {
+        let isa = intel();
+        let (func, _) = build(3, 16);
+        let func = compile(func, &*isa);
+        assert_eq!(count(&func, Opcode::ConstLoad), 3);
+        assert_eq!(func.constants.len(), 1);
+    }
+
+    #[test]
+    fn small_constants() {
+        let isa = intel();
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let a = pos.ins().iconst(I64, -5);
+            let b = pos.ins().iconst(I64, -5);
+            let sum = pos.ins().iadd(a, b);
+            pos.ins().return_(&[sum]);
+        }
+        let func = compile(func, &*isa);
+        assert_eq!(count(&func, Opcode::Iconst), 2);
+    }
+}
@@ -9,22 +9,57 @@
 //! contexts concurrently. Typically, you would have one context per compilation thread and only a
 //! single ISA instance.
 
-use binemit::{CodeOffset, relax_branches, MemoryCodeSink, RelocSink};
+use binemit::{CodeOffset, relax_branches, MemoryCodeSink, RelocSink, StackmapSink,
+              emit_stackmaps, ValueLabelRange, value_label_ranges, FrameUnwindKind,
+              FrameDescription, FrameUnwindSink, TrapReport, trap_report, CallSite,
+              call_site_table, SourceLocTable, srcloc_table, emit_function,
+              emit_function_checked, WriterCodeSink, DEFAULT_CHUNK_SIZE, DirectCodeSink,
+              RelocError, TrapSink};
+use block_frequency::BlockFrequency;
+use const_pool::pool_constants;
+use custom_pass::{CustomPass, PassPoint};
 use dominator_tree::DominatorTree;
+use entry_exit_hooks::insert_entry_exit_hooks;
 use flowgraph::ControlFlowGraph;
-use ir::Function;
+use fuel::insert_fuel_checks;
+use heap_checks::{eliminate_heap_checks, fuse_heap_checks};
+use if_conversion::convert_diamonds;
+use inline::{InlineOracle, inline_calls};
+use entity::EntityMap;
+use ir::{ExternalName, Function, Inst, Opcode};
 use loop_analysis::LoopAnalysis;
-use isa::TargetIsa;
-use legalize_function;
+use nan_canonicalization::canonicalize_nans;
+use isa::{OverriddenIsa, TargetIsa};
+use legalizer::{legalize_function, legalize_function_with_custom_opcodes,
+                legalize_function_with_trap_handler, legalize_new_insts, CustomOpcodes,
+                StreamingLegalizer, TrapHandler};
 use regalloc;
-use result::{CtonError, CtonResult};
+use result::{panic_message, CompileError, CtonError, CtonResult};
 use settings::{FlagsOrIsa, OptLevel};
 use unreachable_code::eliminate_unreachable_code;
 use verifier;
 use simple_gvn::do_simple_gvn;
+use simplify_cfg::simplify_cfg;
 use licm::do_licm;
+use outline::outline_sequences;
+use pipeline::{BuiltinPass, Pipeline, PipelinePass, Stage};
+use peel::do_peel;
+use peephole::do_peephole;
 use preopt::do_preopt;
+use ref_slice::ref_slice_mut;
+use redundant_extend::eliminate_redundant_extends;
+use redundant_load::eliminate_redundant_loads;
+use dead_store::eliminate_dead_stores;
+use sccp::do_sccp;
+use schedule::schedule_instructions;
+use shrink::shrink_instructions;
 use timing;
+use unroll::do_unroll;
+use std::boxed::Box;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::string::{String, ToString};
+use std::vec::Vec;
 
 /// Persistent data structures and compilation pipeline.
 pub struct Context {
@@ -42,6 +77,27 @@ pub struct Context {
 
     /// Loop analysis of `func`.
     pub loop_analysis: LoopAnalysis,
+
+    /// Estimated EBB frequencies of `func`.
+    pub block_frequency: BlockFrequency,
+
+    /// Embedder-defined passes and the pipeline points where they run.
+    custom_passes: Vec<(PassPoint, Box<CustomPass>)>,
+
+    /// Embedder-defined function called instead of trapping.
+    trap_handler: Option<TrapHandler>,
+
+    /// Expansions of the embedder-defined opcodes.
+    custom_opcodes: Option<CustomOpcodes>,
+
+    /// Embedder-configured pipelines, used instead of the default pipeline for their opt level.
+    pipelines: Vec<(OptLevel, Pipeline)>,
+
+    /// Pass timings and statistics of the last call to `compile`.
+    pass_times: timing::PassTimes,
+
+    /// Was `func` legalized while it was built? See `finish_streaming`.
+    streamed: bool,
 }
 
 impl Context {
@@ -64,16 +120,77 @@ impl Context {
             domtree: DominatorTree::new(),
             regalloc: regalloc::Context::new(),
             loop_analysis: LoopAnalysis::new(),
+            block_frequency: BlockFrequency::new(),
+            custom_passes: Vec::new(),
+            trap_handler: None,
+            custom_opcodes: None,
+            pipelines: Vec::new(),
+            pass_times: Default::default(),
+            streamed: false,
         }
     }
 
     /// Clear all data structures in this context.
+    ///
+    /// Registered custom passes, the trap handler, the custom opcodes, and the configured pipelines
+    /// are kept so they apply to the next function compiled.
     pub fn clear(&mut self) {
         self.func.clear();
         self.cfg.clear();
         self.domtree.clear();
         self.regalloc.clear();
         self.loop_analysis.clear();
+        self.block_frequency.clear();
+        self.pass_times = Default::default();
+        self.streamed = false;
+    }
+
+    /// Register an embedder-defined pass to run at `point` in the compilation pipeline.
+    ///
+    /// Passes registered for the same point run in registration order.
+    pub fn add_custom_pass(&mut self, point: PassPoint, pass: Box<CustomPass>) {
+        self.custom_passes.push((point, pass));
+    }
+
+    /// Replace the trap instructions with the trap codes selected by `handler` by calls to the
+    /// handler function during legalization.
+    ///
+    /// Pass `None` to go back to emitting trap instructions.
+    pub fn set_trap_handler(&mut self, handler: Option<TrapHandler>) {
+        self.trap_handler = handler;
+    }
+
+    /// Expand the `custom` instructions with the expansions registered in `custom_opcodes` during
+    /// legalization.
+    ///
+    /// Pass `None` to forget the registered opcodes. Compiling a function with `custom`
+    /// instructions then fails with `CtonError::InvalidInput`.
+    pub fn set_custom_opcodes(&mut self, custom_opcodes: Option<CustomOpcodes>) {
+        self.custom_opcodes = custom_opcodes;
+    }
+
+    /// Use `pipeline` instead of the default pipeline when compiling with `opt_level`.
+    ///
+    /// Pass `None` to go back to the default pipeline. Returns the pipeline that was previously
+    /// configured for `opt_level`, if any.
+    pub fn set_pipeline(
+        &mut self,
+        opt_level: OptLevel,
+        pipeline: Option<Pipeline>,
+    ) -> Option<Pipeline> {
+        let old = self.take_pipeline(opt_level);
+        if let Some(pipeline) = pipeline {
+            self.pipelines.push((opt_level, pipeline));
+        }
+        old
+    }
+
+    /// Remove the pipeline configured for `opt_level` from the context.
+    fn take_pipeline(&mut self, opt_level: OptLevel) -> Option<Pipeline> {
+        self.pipelines
+            .iter()
+            .position(|&(level, _)| level == opt_level)
+            .map(|idx| self.pipelines.swap_remove(idx).1)
     }
 
     /// Compile the function.
@@ -82,27 +199,223 @@ impl Context {
     /// represented by `isa`. This does not include the final step of emitting machine code into a
     /// code sink.
     ///
+    /// The optimization passes are run as configured by `set_pipeline` for the opt level of `isa`,
+    /// or as in the default `Pipeline` for the settings of `isa`. The settings in
+    /// `Function::settings` override the flags of `isa` for this function.
+    ///
     /// Returns the size of the function's code.
     pub fn compile(&mut self, isa: &TargetIsa) -> Result<CodeOffset, CtonError> {
+        self.collect_times(|ctx| ctx.compile_passes(isa))
+    }
+
+    /// Compile the function like `compile`, but report a failure with the pass that failed.
+    ///
+    /// A panic while compiling is caught and reported as `CtonError::Panic`, so an embedder can
+    /// keep running after a bug in Cretonne. The function and the analyses in the context may be
+    /// left in an inconsistent state by a panic, so the context must be cleared before it is
+    /// reused. With the `snapshot_ir_on_error` setting, the error also contains the function as it
+    /// was when compilation stopped.
+    pub fn try_compile(&mut self, isa: &TargetIsa) -> Result<CodeOffset, CompileError> {
+        let result = self.collect_times(|ctx| {
+            panic::catch_unwind(AssertUnwindSafe(|| ctx.compile_passes(isa)))
+        });
+        let error = match result {
+            Ok(Ok(code_size)) => return Ok(code_size),
+            Ok(Err(error)) => error,
+            Err(payload) => CtonError::Panic(panic_message(&*payload)),
+        };
+        // The failed pass didn't get to record its instruction counts, so it is still the last
+        // pass.
+        let pass = timing::last_pass();
+        let flags = isa.flags().with_overrides(&self.func.settings);
+        let ir = if flags.snapshot_ir_on_error() {
+            // The function may be too broken to print after a panic.
+            panic::catch_unwind(AssertUnwindSafe(|| self.func.display(isa).to_string())).ok()
+        } else {
+            None
+        };
+        Err(CompileError { error, pass, ir })
+    }
+
+    /// Run `f`, and collect the timings of the passes it runs separately in `pass_times`.
+    ///
+    /// The timings are added back to the current thread's timings afterwards.
+    fn collect_times<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        let outer_times = timing::take_current();
+        let result = f(self);
+        self.pass_times = timing::take_current();
+        timing::add_to_current(&outer_times);
+        timing::add_to_current(&self.pass_times);
+        result
+    }
+
+    /// Get the pass timings and statistics of the last call to `compile`.
+    ///
+    /// The statistics include the number of instructions in the function before and after each
+    /// pass, and the number of spill instructions inserted by each pass.
+    pub fn pass_times(&self) -> &timing::PassTimes {
+        &self.pass_times
+    }
+
+    /// Run all the compilation passes for `compile`, with the settings of the function.
+    fn compile_passes(&mut self, isa: &TargetIsa) -> Result<CodeOffset, CtonError> {
+        if self.func.settings.is_empty() {
+            self.compile_with_flags(isa)
+        } else {
+            let isa = OverriddenIsa::new(isa, &self.func.settings);
+            self.compile_with_flags(&isa)
+        }
+    }
+
+    /// Run all the compilation passes with the flags of `isa`.
+    fn compile_with_flags(&mut self, isa: &TargetIsa) -> Result<CodeOffset, CtonError> {
         let _tt = timing::compile();
         self.verify_if(isa)?;
 
+        let opt_level = isa.flags().opt_level();
+        let (mut pipeline, configured) = match self.take_pipeline(opt_level) {
+            Some(pipeline) => (pipeline, true),
+            None => (Pipeline::new(isa.flags()), false),
+        };
+        let result = self.run_pipeline(&mut pipeline, isa);
+        if configured {
+            self.pipelines.push((opt_level, pipeline));
+        }
+        result
+    }
+
+    /// Run the passes in `pipeline` around the passes required for generating code.
+    fn run_pipeline(
+        &mut self,
+        pipeline: &mut Pipeline,
+        isa: &TargetIsa,
+    ) -> Result<CodeOffset, CtonError> {
         self.compute_cfg();
-        self.preopt(isa)?;
-        self.legalize(isa)?;
-        if isa.flags().opt_level() == OptLevel::Best {
-            self.compute_domtree();
-            /* TODO: Re-enable LICM.
-            self.compute_loop_analysis();
-            self.licm(isa)?;
-            */
-            self.simple_gvn(isa)?;
+        if self.func.fuel.is_some() {
+            self.counted(isa, |ctx| ctx.insert_fuel_checks(isa))?;
+        }
+        if self.streamed {
+            // Only the instructions inserted since the function was built need legalizing.
+            self.streamed = false;
+            self.counted(isa, |ctx| ctx.legalize_new_insts(isa))?;
+        } else {
+            self.run_stage(pipeline, Stage::PreLegalize, isa)?;
+            self.counted(isa, |ctx| ctx.run_custom_passes(PassPoint::PreLegalize, isa))?;
+            self.counted(isa, |ctx| ctx.legalize(isa))?;
+        }
+        if isa.flags().enable_nan_canonicalization() {
+            self.counted(isa, |ctx| ctx.canonicalize_nans(isa))?;
+        }
+        self.counted(isa, |ctx| ctx.run_custom_passes(PassPoint::PostLegalize, isa))?;
+        self.run_stage(pipeline, Stage::PostLegalize, isa)?;
+        self.counted(isa, |ctx| ctx.run_custom_passes(PassPoint::PreRegalloc, isa))?;
+        self.counted(isa, |ctx| ctx.regalloc(isa))?;
+        self.counted(isa, |ctx| ctx.prologue_epilogue(isa))?;
+        self.run_stage(pipeline, Stage::PostRegalloc, isa)?;
+        self.counted(isa, |ctx| ctx.relax_branches(isa))
+    }
+
+    /// Run the passes in `stage` of `pipeline`.
+    fn run_stage(&mut self, pipeline: &mut Pipeline, stage: Stage, isa: &TargetIsa) -> CtonResult {
+        for pass in pipeline.passes_mut(stage) {
+            match *pass {
+                PipelinePass::Builtin(pass) => self.counted(isa, |ctx| ctx.run_pass(pass, isa))?,
+                PipelinePass::Custom(ref mut pass) => {
+                    self.counted(isa, |ctx| ctx.run_custom_pass(&mut **pass, isa))?;
+                    if stage == Stage::PostRegalloc {
+                        self.verify_locations_if(isa)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the built-in optimization pass `pass`, computing the analyses it needs first.
+    pub fn run_pass(&mut self, pass: BuiltinPass, isa: &TargetIsa) -> CtonResult {
+        match pass {
+            BuiltinPass::EliminateUnreachableCode => {
+                self.compute_domtree();
+                self.eliminate_unreachable_code(isa)
+            }
+            BuiltinPass::Preopt => self.preopt(isa),
+            BuiltinPass::Sccp => self.sccp(isa),
+            BuiltinPass::EliminateRedundantExtends => self.eliminate_redundant_extends(isa),
+            BuiltinPass::EliminateRedundantLoads => self.eliminate_redundant_loads(isa),
+            BuiltinPass::EliminateDeadStores => self.eliminate_dead_stores(isa),
+            BuiltinPass::ConvertDiamonds => self.convert_diamonds(isa),
+            BuiltinPass::UnrollLoops => {
+                self.compute_domtree();
+                self.compute_loop_analysis();
+                self.unroll_loops(isa)
+            }
+            BuiltinPass::PeelLoops => {
+                self.compute_domtree();
+                self.compute_loop_analysis();
+                self.peel_loops(isa)
+            }
+            BuiltinPass::FuseHeapChecks => self.fuse_heap_checks(isa),
+            BuiltinPass::EliminateHeapChecks => {
+                self.compute_domtree();
+                self.compute_loop_analysis();
+                self.eliminate_heap_checks(isa)
+            }
+            BuiltinPass::InsertEntryExitHooks => self.insert_entry_exit_hooks(isa),
+            BuiltinPass::SimpleGvn => {
+                self.compute_domtree();
+                self.simple_gvn(isa)
+            }
+            BuiltinPass::Licm => {
+                self.compute_domtree();
+                self.compute_loop_analysis();
+                self.licm(isa)
+            }
+            BuiltinPass::SimplifyCfg => {
+                self.compute_domtree();
+                self.simplify_cfg(isa)
+            }
+            BuiltinPass::PoolConstants => {
+                self.compute_domtree();
+                self.pool_constants(isa)
+            }
+            BuiltinPass::ScheduleInstructions => self.schedule_instructions(isa),
+            BuiltinPass::Peephole => self.peephole(isa),
+            BuiltinPass::ShrinkInstructions => self.shrink_instructions(isa),
         }
-        self.compute_domtree();
-        self.eliminate_unreachable_code(isa)?;
-        self.regalloc(isa)?;
-        self.prologue_epilogue(isa)?;
-        self.relax_branches(isa)
+    }
+
+    /// Run `pass` and record the size of the function before and after it.
+    ///
+    /// See `timing::record_insts()`. With the `track_inst_origins` setting, this also records the
+    /// pass as the origin of the instructions it created or changed, even when it fails.
+    fn counted<F, T>(&mut self, isa: &TargetIsa, pass: F) -> Result<T, CtonError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, CtonError>,
+    {
+        let before = count_insts(&self.func);
+        let snapshot = if isa.flags().track_inst_origins() {
+            Some(snapshot_insts(&self.func))
+        } else {
+            None
+        };
+        let result = pass(self);
+        if let Some(snapshot) = snapshot {
+            if let Some(pass) = timing::last_pass() {
+                record_origins(&mut self.func, &snapshot, pass);
+            }
+        }
+        let result = result.map_err(|e| match e {
+            CtonError::Verifier(mut e) => {
+                e.set_origin(&self.func);
+                CtonError::Verifier(e)
+            }
+            e => e,
+        })?;
+        timing::record_insts(before, count_insts(&self.func));
+        Ok(result)
     }
 
     /// Emit machine code directly into raw memory.
@@ -111,9 +424,139 @@ impl Context {
     /// code is returned by `compile` above.
     ///
     /// The machine code is not relocated. Instead, any relocations are emitted into `relocs`.
-    pub fn emit_to_memory(&self, mem: *mut u8, relocs: &mut RelocSink, isa: &TargetIsa) {
+    /// A `binemit::Relocations` sink collects them so they can be applied once the addresses of
+    /// the external symbols are known. If the addresses are already known, `emit_to_memory_linked`
+    /// avoids the second pass.
+    ///
+    /// The offset, source location, and trap code of every instruction that can trap are emitted
+    /// into `traps`. Use a `binemit::NullTrapSink` to ignore them.
+    pub fn emit_to_memory(
+        &self,
+        mem: *mut u8,
+        relocs: &mut RelocSink,
+        traps: &mut TrapSink,
+        isa: &TargetIsa,
+    ) {
         let _tt = timing::binemit();
-        isa.emit_function(&self.func, &mut MemoryCodeSink::new(mem, relocs));
+        let mut sink = MemoryCodeSink::new(mem, relocs, traps, isa.endianness());
+        isa.emit_function(&self.func, &mut sink);
+    }
+
+    /// Emit machine code directly into executable memory, resolving relocations as they go.
+    ///
+    /// Write all of the function's machine code to `code`, which must be at least the size
+    /// returned by `compile`. The code will execute from the address `code_addr`, and `resolve`
+    /// provides the address of each external symbol. The trap sites are emitted into `traps`.
+    /// Returns the number of bytes written, or the first relocation error. See
+    /// `binemit::DirectCodeSink`.
+    pub fn emit_to_memory_linked<F>(
+        &self,
+        code: &mut [u8],
+        code_addr: u64,
+        resolve: F,
+        traps: &mut TrapSink,
+        isa: &TargetIsa,
+    ) -> Result<CodeOffset, RelocError>
+    where
+        F: FnMut(&ExternalName) -> Option<u64>,
+    {
+        let _tt = timing::binemit();
+        let mut sink = DirectCodeSink::new(code, code_addr, traps, isa.endianness(), resolve);
+        let emit_inst = |func: &Function, inst, divert: &mut _, sink: &mut DirectCodeSink<F>| {
+            isa.emit_inst(func, inst, divert, sink)
+        };
+        let emit_padding = |bytes, sink: &mut DirectCodeSink<F>| isa.emit_padding(bytes, sink);
+        if isa.flags().check_emission() {
+            emit_function_checked(isa, &self.func, emit_inst, emit_padding, &mut sink);
+        } else {
+            emit_function(&self.func, emit_inst, emit_padding, &mut sink);
+        }
+        sink.finish()
+    }
+
+    /// Emit machine code to a writer.
+    ///
+    /// This is like `emit_to_memory`, except the machine code is written to `out` in chunks of
+    /// `binemit::DEFAULT_CHUNK_SIZE` bytes, so the code for a huge function doesn't need to be
+    /// held in memory all at once. Returns the number of bytes written, which is the size returned
+    /// by `compile`, or the first error returned by `out`.
+    pub fn emit_to_writer(
+        &self,
+        out: &mut io::Write,
+        relocs: &mut RelocSink,
+        traps: &mut TrapSink,
+        isa: &TargetIsa,
+    ) -> io::Result<CodeOffset> {
+        let _tt = timing::binemit();
+        let mut sink =
+            WriterCodeSink::new(out, DEFAULT_CHUNK_SIZE, relocs, traps, isa.endianness());
+        let emit_inst = |func: &Function, inst, divert: &mut _, sink: &mut WriterCodeSink| {
+            isa.emit_inst(func, inst, divert, sink)
+        };
+        let emit_padding = |bytes, sink: &mut WriterCodeSink| isa.emit_padding(bytes, sink);
+        if isa.flags().check_emission() {
+            emit_function_checked(isa, &self.func, emit_inst, emit_padding, &mut sink);
+        } else {
+            emit_function(&self.func, emit_inst, emit_padding, &mut sink);
+        }
+        sink.finish()
+    }
+
+    /// Emit the stack maps for the GC references live at each call.
+    ///
+    /// This must be called after `compile`. Nothing is emitted if the function has no values
+    /// marked as GC references.
+    pub fn emit_stackmaps(&self, sink: &mut StackmapSink, isa: &TargetIsa) {
+        emit_stackmaps(&self.func, isa, sink);
+    }
+
+    /// Get a report of the explicit trap instructions in the function.
+    ///
+    /// This must be called after `compile`. See `binemit::trap_report()`.
+    pub fn trap_report(&self, isa: &TargetIsa) -> TrapReport {
+        trap_report(&self.func, isa)
+    }
+
+    /// Get the call sites of the `invoke` instructions in the function and their landing pads.
+    ///
+    /// This must be called after `compile`. See `binemit::call_site_table()`.
+    pub fn call_site_table(&self, isa: &TargetIsa) -> Vec<CallSite> {
+        call_site_table(&self.func, isa)
+    }
+
+    /// Get the table mapping the code offsets of the function to source locations.
+    ///
+    /// This must be called after `compile`. See `binemit::srcloc_table()`.
+    pub fn srcloc_table(&self, isa: &TargetIsa) -> SourceLocTable {
+        srcloc_table(&self.func, isa)
+    }
+
+    /// Emit unwind information for the function in the format `kind`.
+    ///
+    /// This must be called after `compile`. Nothing is emitted if the target ISA doesn't support
+    /// the requested format.
+    pub fn emit_unwind_info(
+        &self,
+        isa: &TargetIsa,
+        kind: FrameUnwindKind,
+        sink: &mut FrameUnwindSink,
+    ) {
+        isa.emit_unwind_info(&self.func, kind, sink);
+    }
+
+    /// Describe the stack frame of the function for walking the stack.
+    ///
+    /// This must be called after `compile`. Returns `None` if the target ISA can't describe the
+    /// frame.
+    pub fn frame_description(&self, isa: &TargetIsa) -> Option<FrameDescription> {
+        isa.frame_description(&self.func)
+    }
+
+    /// Compute the machine code ranges where the labeled values in the function are available.
+    ///
+    /// This must be called after `compile`. See `binemit::value_label_ranges()`.
+    pub fn value_label_ranges(&self, isa: &TargetIsa) -> Vec<ValueLabelRange> {
+        value_label_ranges(&self.func, isa, self.regalloc.liveness())
     }
 
     /// Run the verifier on the function.
@@ -134,8 +577,15 @@ impl Context {
     }
 
     /// Run the locations verifier on the function.
+    ///
+    /// When the `enable_regalloc_verifier` setting is true, also check that the values that are
+    /// live at the same time are assigned to different locations.
     pub fn verify_locations(&self, isa: &TargetIsa) -> verifier::Result {
-        verifier::verify_locations(isa, &self.func, None)
+        verifier::verify_locations(isa, &self.func, None)?;
+        if isa.flags().enable_regalloc_verifier() {
+            verifier::verify_interference(isa, &self.func)?;
+        }
+        Ok(())
     }
 
     /// Run the locations verifier only if the `enable_verifier` setting is true.
@@ -149,18 +599,168 @@ impl Context {
 
     /// Perform pre-legalization rewrites on the function.
     pub fn preopt(&mut self, isa: &TargetIsa) -> CtonResult {
-        do_preopt(&mut self.func);
+        do_preopt(&mut self.func, isa.flags().opt_level());
         self.verify_if(isa)?;
         Ok(())
     }
 
+    /// Remove redundant extension and masking instructions.
+    pub fn eliminate_redundant_extends<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CtonResult {
+        eliminate_redundant_extends(&mut self.func);
+        self.verify_if(fisa)
+    }
+
+    /// Remove redundant loads and forward stored values to loads.
+    pub fn eliminate_redundant_loads<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CtonResult {
+        eliminate_redundant_loads(&mut self.func);
+        self.verify_if(fisa)
+    }
+
+    /// Remove stores that are overwritten or never loaded.
+    pub fn eliminate_dead_stores<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CtonResult {
+        eliminate_dead_stores(&mut self.func);
+        self.verify_if(fisa)
+    }
+
+    /// Perform sparse conditional constant propagation on the function.
+    ///
+    /// This may remove EBBs and branches, so the control flow graph and the dominator tree are
+    /// recomputed, the code made unreachable by the folded branches is eliminated, and the loop
+    /// analysis is invalidated.
+    pub fn sccp<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        self.loop_analysis.clear();
+        do_sccp(&mut self.func, &mut self.cfg);
+        self.compute_domtree();
+        self.eliminate_unreachable_code(fisa)
+    }
+
+    /// Inline the calls to the functions provided by `oracle`.
+    ///
+    /// The inlining heuristic is tuned by the `inline_size_budget`, `inline_max_depth`, and
+    /// `inline_loop_bonus` settings. This must be called before `compile` since it expects the
+    /// function to not be legalized.
+    pub fn inline<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        oracle: &InlineOracle,
+        fisa: FOI,
+    ) -> CtonResult {
+        let fisa = fisa.into();
+        inline_calls(&mut self.func, fisa.flags, oracle);
+        self.verify_if(fisa)
+    }
+
+    /// Outline repeated instruction sequences in the function into helper functions.
+    ///
+    /// The function `names` is called with the index of each helper function to get its name. The
+    /// helper functions are returned so the embedder can compile them. This must be called before
+    /// `compile` since it expects the function to not be legalized.
+    pub fn outline<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        names: &mut FnMut(usize) -> ExternalName,
+        fisa: FOI,
+    ) -> Result<Vec<Function>, CtonError> {
+        let helpers = outline_sequences(ref_slice_mut(&mut self.func), names);
+        self.verify_if(fisa)?;
+        Ok(helpers)
+    }
+
+    /// Run the custom passes registered for `point`.
+    ///
+    /// The control flow graph and dominator tree are recomputed before and after the passes run.
+    pub fn run_custom_passes(&mut self, point: PassPoint, isa: &TargetIsa) -> CtonResult {
+        if !self.custom_passes.iter().any(|&(p, _)| p == point) {
+            return Ok(());
+        }
+        let _tt = timing::custom_passes();
+        self.flowgraph();
+        for &mut (p, ref mut pass) in &mut self.custom_passes {
+            if p != point {
+                continue;
+            }
+            dbg!("Running custom pass {}", pass.name());
+            pass.run(&mut self.func, &mut self.cfg, &mut self.domtree, isa)?;
+        }
+        self.flowgraph();
+        self.verify_if(isa)
+    }
+
+    /// Run a single custom pass.
+    ///
+    /// The control flow graph and dominator tree are recomputed before and after the pass runs.
+    pub fn run_custom_pass(&mut self, pass: &mut CustomPass, isa: &TargetIsa) -> CtonResult {
+        let _tt = timing::custom_passes();
+        self.flowgraph();
+        dbg!("Running custom pass {}", pass.name());
+        pass.run(&mut self.func, &mut self.cfg, &mut self.domtree, isa)?;
+        self.flowgraph();
+        self.verify_if(isa)
+    }
+
     /// Run the legalizer for `isa` on the function.
     pub fn legalize(&mut self, isa: &TargetIsa) -> CtonResult {
         // Legalization invalidates the domtree and loop_analysis by mutating the CFG.
         // TODO: Avoid doing this when legalization doesn't actually mutate the CFG.
         self.domtree.clear();
         self.loop_analysis.clear();
-        legalize_function(&mut self.func, &mut self.cfg, isa);
+        match (self.trap_handler.as_ref(), self.custom_opcodes.as_ref()) {
+            (handler, Some(opcodes)) => {
+                legalize_function_with_custom_opcodes(
+                    &mut self.func,
+                    &mut self.cfg,
+                    isa,
+                    handler,
+                    opcodes,
+                )?
+            }
+            (Some(handler), None) => {
+                legalize_function_with_trap_handler(&mut self.func, &mut self.cfg, isa, handler)?
+            }
+            (None, None) => legalize_function(&mut self.func, &mut self.cfg, isa)?,
+        }
+        self.verify_if(isa)
+    }
+
+    /// Finish legalizing the function with the `legalizer` that legalized it while it was built.
+    ///
+    /// The next call to `compile` skips the passes that run before legalization, and the
+    /// legalizer itself.
+    pub fn finish_streaming(
+        &mut self,
+        legalizer: StreamingLegalizer,
+        isa: &TargetIsa,
+    ) -> CtonResult {
+        self.domtree.clear();
+        self.loop_analysis.clear();
+        legalizer.finish(&mut self.func, &mut self.cfg, isa)?;
+        self.streamed = true;
+        self.verify_if(isa)
+    }
+
+    /// Legalize the instructions inserted after the function was legalized.
+    fn legalize_new_insts(&mut self, isa: &TargetIsa) -> CtonResult {
+        self.domtree.clear();
+        self.loop_analysis.clear();
+        legalize_new_insts(&mut self.func, &mut self.cfg, isa)?;
+        self.verify_if(isa)
+    }
+
+    /// Replace the NaN results of floating point arithmetic with the canonical NaN.
+    ///
+    /// This runs after legalization, and legalizes the instructions it inserts. It is run by
+    /// `compile` when the `enable_nan_canonicalization` setting is enabled.
+    pub fn canonicalize_nans(&mut self, isa: &TargetIsa) -> CtonResult {
+        self.domtree.clear();
+        self.loop_analysis.clear();
+        canonicalize_nans(&mut self.func, &mut self.cfg, isa)?;
         self.verify_if(isa)
     }
 
@@ -183,6 +783,18 @@ impl Context {
         )
     }
 
+    /// Compute the estimated EBB frequencies.
+    ///
+    /// This needs the control flow graph, the dominator tree, and the loop analysis.
+    pub fn compute_block_frequency(&mut self) {
+        self.block_frequency.compute(
+            &self.func,
+            &self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+        )
+    }
+
     /// Compute the control flow graph and dominator tree.
     pub fn flowgraph(&mut self) {
         self.compute_cfg();
@@ -206,17 +818,143 @@ impl Context {
         self.verify_if(fisa)
     }
 
+    /// Unroll small loops with a constant trip count.
+    ///
+    /// The size budget comes from the `unroll_threshold` setting. The loop analysis must be
+    /// computed first, and it is cleared by this pass.
+    pub fn unroll_loops<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        let fisa = fisa.into();
+        do_unroll(
+            &mut self.func,
+            &mut self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+            u32::from(fisa.flags.unroll_threshold()),
+        );
+        self.loop_analysis.clear();
+        self.verify_if(fisa)
+    }
+
+    /// Convert small diamonds in the CFG into `select` or `selectif` instructions.
+    ///
+    /// The control flow graph must be computed first, and it is kept up to date. The `selectif`
+    /// instructions are only used when an ISA is given.
+    pub fn convert_diamonds<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        let fisa = fisa.into();
+        self.domtree.clear();
+        self.loop_analysis.clear();
+        convert_diamonds(&mut self.func, &mut self.cfg, fisa.isa);
+        self.verify_if(fisa)
+    }
+
+    /// Peel the first iteration of loops testing a loop-invariant condition.
+    ///
+    /// The loop analysis must be computed first, and it is cleared by this pass along with the
+    /// dominator tree.
+    pub fn peel_loops<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        do_peel(
+            &mut self.func,
+            &mut self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+        );
+        self.loop_analysis.clear();
+        self.domtree.clear();
+        self.verify_if(fisa)
+    }
+
+    /// Check the bounds of groups of heap accesses with the same index only once.
+    pub fn fuse_heap_checks<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        fuse_heap_checks(&mut self.func);
+        self.verify_if(fisa)
+    }
+
+    /// Remove the heap bounds checks that are covered by other checks.
+    ///
+    /// The dominator tree and loop analysis must be computed first.
+    pub fn eliminate_heap_checks<'a, FOI>(&mut self, fisa: FOI) -> CtonResult
+    where
+        FOI: Into<FlagsOrIsa<'a>>,
+    {
+        let fisa = fisa.into();
+        eliminate_heap_checks(
+            &mut self.func,
+            &self.cfg,
+            &self.domtree,
+            &self.loop_analysis,
+            fisa.flags,
+        );
+        self.verify_if(fisa)
+    }
+
+    /// Insert calls to the function entry and exit hooks.
+    pub fn insert_entry_exit_hooks<'a, FOI>(&mut self, fisa: FOI) -> CtonResult
+    where
+        FOI: Into<FlagsOrIsa<'a>>,
+    {
+        insert_entry_exit_hooks(&mut self.func);
+        self.verify_if(fisa)
+    }
+
+    /// Insert the fuel checks for the fuel counter in `Function::fuel`.
+    ///
+    /// This is run by `compile` before the optimizations when the function has a fuel counter.
+    pub fn insert_fuel_checks(&mut self, isa: &TargetIsa) -> CtonResult {
+        self.compute_domtree();
+        self.compute_loop_analysis();
+        insert_fuel_checks(&mut self.func, &self.loop_analysis, isa);
+        self.verify_if(isa)
+    }
+
     /// Perform unreachable code elimination.
+    ///
+    /// The control flow graph and dominator tree must be valid. They are kept up to date, along
+    /// with the loop analysis if it has been computed.
     pub fn eliminate_unreachable_code<'a, FOI>(&mut self, fisa: FOI) -> CtonResult
     where
         FOI: Into<FlagsOrIsa<'a>>,
     {
-        eliminate_unreachable_code(&mut self.func, &mut self.cfg, &self.domtree);
+        eliminate_unreachable_code(
+            &mut self.func,
+            &mut self.cfg,
+            &self.domtree,
+            &mut self.loop_analysis,
+        );
+        self.verify_if(fisa)
+    }
+
+    /// Simplify the control flow graph by threading jumps and merging and removing EBBs.
+    ///
+    /// The control flow graph and dominator tree must be valid, and they are kept up to date.
+    pub fn simplify_cfg<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CtonResult {
+        self.loop_analysis.clear();
+        simplify_cfg(&mut self.func, &mut self.cfg, &mut self.domtree);
         self.verify_if(fisa)
     }
 
+    /// Share the materializations of large constants that appear more than once.
+    ///
+    /// The dominator tree must be computed first.
+    pub fn pool_constants(&mut self, isa: &TargetIsa) -> CtonResult {
+        pool_constants(&mut self.func, &self.cfg, &self.domtree, isa);
+        self.verify_if(isa)
+    }
+
+    /// Reorder the instructions in each EBB to hide their latencies.
+    ///
+    /// Nothing is done for ISAs without instruction latencies.
+    pub fn schedule_instructions(&mut self, isa: &TargetIsa) -> CtonResult {
+        schedule_instructions(&mut self.func, isa);
+        self.verify_if(isa)
+    }
+
     /// Run the register allocator.
+    ///
+    /// The dominator tree is computed first if no earlier pass has computed it.
     pub fn regalloc(&mut self, isa: &TargetIsa) -> CtonResult {
+        if !self.domtree.is_valid() {
+            self.compute_domtree();
+        }
         self.regalloc.run(
             isa,
             &mut self.func,
@@ -233,6 +971,23 @@ impl Context {
         Ok(())
     }
 
+    /// Remove self-moves and fold address computations into memory operands after register
+    /// allocation.
+    pub fn peephole(&mut self, isa: &TargetIsa) -> CtonResult {
+        do_peephole(&mut self.func, isa);
+        self.verify_if(isa)?;
+        self.verify_locations_if(isa)?;
+        Ok(())
+    }
+
+    /// Switch instructions to the shortest encodings allowed by their allocated registers.
+    pub fn shrink_instructions(&mut self, isa: &TargetIsa) -> CtonResult {
+        shrink_instructions(&mut self.func, isa);
+        self.verify_if(isa)?;
+        self.verify_locations_if(isa)?;
+        Ok(())
+    }
+
     /// Run the branch relaxation pass and return the final code size.
     pub fn relax_branches(&mut self, isa: &TargetIsa) -> Result<CodeOffset, CtonError> {
         let code_size = relax_branches(&mut self.func, isa)?;
@@ -242,3 +997,46 @@ impl Context {
         Ok(code_size)
     }
 }
+
+/// Count the instructions and spill instructions in the layout of `func`.
+fn count_insts(func: &Function) -> (usize, usize) {
+    let mut insts = 0;
+    let mut spills = 0;
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            insts += 1;
+            if func.dfg[inst].opcode() == Opcode::Spill {
+                spills += 1;
+            }
+        }
+    }
+    (insts, spills)
+}
+
+/// Get the text of every instruction in the layout of `func`, for detecting the instructions that
+/// a pass changes.
+fn snapshot_insts(func: &Function) -> EntityMap<Inst, String> {
+    let mut snapshot = EntityMap::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            snapshot[inst] = func.dfg.display_inst(inst, None).to_string();
+        }
+    }
+    snapshot
+}
+
+/// Record `pass` as the origin of the instructions in `func` that are new or different from
+/// `snapshot`.
+fn record_origins(func: &mut Function, snapshot: &EntityMap<Inst, String>, pass: &'static str) {
+    let mut changed = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if func.dfg.display_inst(inst, None).to_string() != snapshot[inst] {
+                changed.push(inst);
+            }
+        }
+    }
+    for inst in changed {
+        func.inst_origins[inst] = Some(pass);
+    }
+}
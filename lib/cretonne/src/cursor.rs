@@ -1,9 +1,14 @@
 //! Cursor library.
 //!
 //! This module defines cursor data types that can be used for inserting instructions.
+//!
+//! New instructions are given the source location set with `Cursor::set_srcloc()`. When no source
+//! location has been set, they inherit the source location of the instruction they are inserted
+//! before, or the last instruction of the EBB when appending.
 
 use ir;
 use isa::TargetIsa;
+use std::vec::Vec;
 
 /// The possible positions of a cursor.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -30,6 +35,9 @@ pub trait Cursor {
     fn set_position(&mut self, pos: CursorPosition);
 
     /// Get the source location that should be assigned to new instructions.
+    ///
+    /// The default source location means that new instructions inherit the source location of
+    /// their neighbours.
     fn srcloc(&self) -> ir::SourceLoc;
 
     /// Set the source location that should be assigned to new instructions.
@@ -629,24 +637,51 @@ impl<'c, 'f> ir::InstInserterBase<'c> for &'c mut FuncCursor<'f> {
     }
 
     fn insert_built_inst(self, inst: ir::Inst, _: ir::Type) -> &'c mut ir::DataFlowGraph {
+        let srcloc = new_srcloc(self.func, self.pos, self.srcloc);
         self.insert_inst(inst);
-        if !self.srcloc.is_default() {
-            self.func.srclocs[inst] = self.srcloc;
+        if !srcloc.is_default() {
+            self.func.srclocs[inst] = srcloc;
         }
         &mut self.func.dfg
     }
 }
 
 
+/// Get the source location for an instruction inserted at `pos`.
+///
+/// This is `srcloc` unless it is the default, in which case the source location of the
+/// neighbouring instruction is inherited.
+fn new_srcloc(func: &ir::Function, pos: CursorPosition, srcloc: ir::SourceLoc) -> ir::SourceLoc {
+    if !srcloc.is_default() {
+        return srcloc;
+    }
+    let neighbour = match pos {
+        CursorPosition::At(inst) => Some(inst),
+        CursorPosition::After(ebb) => func.layout.last_inst(ebb),
+        CursorPosition::Nowhere | CursorPosition::Before(..) => None,
+    };
+    match neighbour {
+        Some(inst) => func.srclocs[inst],
+        None => srcloc,
+    }
+}
+
 /// Encoding cursor.
 ///
 /// An `EncCursor` can be used to insert instructions that are immediately assigned an encoding.
 /// The cursor holds a mutable reference to the whole function which can be re-borrowed from the
 /// public `pos.func` member.
+///
+/// By default, inserting an instruction that can't be encoded panics. Passes that run between
+/// legalization and register allocation can use `with_deferred_legalization()` to insert
+/// unencoded instructions instead. The function must then be legalized again before register
+/// allocation.
 pub struct EncCursor<'f> {
     pos: CursorPosition,
     srcloc: ir::SourceLoc,
     built_inst: Option<ir::Inst>,
+    defer_illegal: bool,
+    illegal_insts: Vec<ir::Inst>,
 
     /// The referenced function.
     pub func: &'f mut ir::Function,
@@ -662,11 +697,30 @@ impl<'f> EncCursor<'f> {
             pos: CursorPosition::Nowhere,
             srcloc: Default::default(),
             built_inst: None,
+            defer_illegal: false,
+            illegal_insts: Vec::new(),
             func,
             isa,
         }
     }
 
+    /// Exchange this cursor for one that inserts instructions without a legal encoding instead of
+    /// panicking.
+    ///
+    /// The illegal instructions are left unencoded and recorded in `illegal_insts()` so the
+    /// caller can legalize the function again.
+    pub fn with_deferred_legalization(mut self) -> Self {
+        self.defer_illegal = true;
+        self
+    }
+
+    /// Get the instructions inserted by this cursor that don't have a legal encoding.
+    ///
+    /// This is always empty unless the cursor was created with `with_deferred_legalization()`.
+    pub fn illegal_insts(&self) -> &[ir::Inst] {
+        &self.illegal_insts
+    }
+
     /// Use the source location of `inst` for future instructions.
     pub fn use_srcloc(&mut self, inst: ir::Inst) {
         self.srcloc = self.func.srclocs[inst];
@@ -676,7 +730,7 @@ impl<'f> EncCursor<'f> {
     /// position.
     ///
     /// The builder will panic if it is used to insert an instruction that can't be encoded for
-    /// `self.isa`.
+    /// `self.isa`, unless legalization has been deferred.
     pub fn ins(&mut self) -> ir::InsertBuilder<&mut EncCursor<'f>> {
         ir::InsertBuilder::new(self)
     }
@@ -738,14 +792,14 @@ impl<'c, 'f> ir::InstInserterBase<'c> for &'c mut EncCursor<'f> {
         ctrl_typevar: ir::Type,
     ) -> &'c mut ir::DataFlowGraph {
         // Insert the instruction and remember the reference.
+        let srcloc = new_srcloc(self.func, self.pos, self.srcloc);
         self.insert_inst(inst);
         self.built_inst = Some(inst);
 
-        if !self.srcloc.is_default() {
-            self.func.srclocs[inst] = self.srcloc;
+        if !srcloc.is_default() {
+            self.func.srclocs[inst] = srcloc;
         }
         // Assign an encoding.
-        // XXX Is there a way to describe this error to the user?
         #[cfg_attr(feature = "cargo-clippy", allow(match_wild_err_arm))]
         match self.isa.encode(
             &self.func.dfg,
@@ -753,9 +807,71 @@ impl<'c, 'f> ir::InstInserterBase<'c> for &'c mut EncCursor<'f> {
             ctrl_typevar,
         ) {
             Ok(e) => self.func.encodings[inst] = e,
+            Err(_) if self.defer_illegal => {
+                self.func.encodings[inst] = Default::default();
+                self.illegal_insts.push(inst);
+            }
             Err(_) => panic!("can't encode {}", self.display_inst(inst)),
         }
 
         &mut self.func.dfg
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::{I32, I64};
+    use ir::{Function, InstBuilder, SourceLoc};
+    use isa;
+    use settings;
+
+    #[test]
+    fn inherit_srcloc() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+
+        // Nothing to inherit from.
+        let v0 = pos.ins().iconst(I32, 0);
+        let i0 = pos.func.dfg.value_def(v0).unwrap_inst();
+        assert!(pos.func.srclocs[i0].is_default());
+
+        pos.set_srcloc(SourceLoc::new(3));
+        let v1 = pos.ins().iadd(v0, v0);
+        let i1 = pos.func.dfg.value_def(v1).unwrap_inst();
+        assert_eq!(pos.func.srclocs[i1], SourceLoc::new(3));
+
+        // Appending inherits from the last instruction.
+        pos.set_srcloc(Default::default());
+        let i2 = pos.ins().return_(&[v1]);
+        assert_eq!(pos.func.srclocs[i2], SourceLoc::new(3));
+
+        // Inserting before an instruction inherits from that instruction.
+        pos.func.srclocs[i2] = SourceLoc::new(7);
+        pos.goto_inst(i2);
+        let v3 = pos.ins().iadd(v1, v1);
+        let i3 = pos.func.dfg.value_def(v3).unwrap_inst();
+        assert_eq!(pos.func.srclocs[i3], SourceLoc::new(7));
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn deferred_legalization() {
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&settings::builder()));
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let mut pos = EncCursor::new(&mut func, &*isa).with_deferred_legalization();
+        pos.insert_ebb(ebb0);
+
+        // A 32-bit target can encode `iconst.i32` but not `iconst.i64`.
+        pos.ins().iconst(I32, 1);
+        let legal = pos.built_inst();
+        pos.ins().iconst(I64, 1);
+        let illegal = pos.built_inst();
+        assert!(pos.func.encodings[legal].is_legal());
+        assert!(!pos.func.encodings[illegal].is_legal());
+        assert_eq!(pos.illegal_insts(), &[illegal]);
+    }
+}
@@ -0,0 +1,176 @@
+//! Embedder-defined IR passes.
+//!
+//! Embedders often have domain-specific knowledge that enables optimizations Cretonne can't
+//! perform on its own, like caching loads from a VM context pointer. The `CustomPass` trait lets
+//! such passes be registered with a `Context` and run at fixed points in the compilation
+//! pipeline without forking the crate.
+
+use dominator_tree::DominatorTree;
+use flowgraph::ControlFlowGraph;
+use ir::Function;
+use isa::TargetIsa;
+use result::CtonResult;
+
+/// Points in the compilation pipeline where custom passes can be inserted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PassPoint {
+    /// After pre-legalization rewrites, before legalization.
+    ///
+    /// The function may still contain instructions that are not legal for the target ISA.
+    PreLegalize,
+
+    /// Immediately after legalization, before any optimizations.
+    ///
+    /// All instructions are legal and have encodings.
+    PostLegalize,
+
+    /// After all IR-level optimizations, just before register allocation.
+    ///
+    /// Any instructions inserted by a pass at this point must be legal and encoded.
+    PreRegalloc,
+}
+
+/// An embedder-defined pass over the IR of a function.
+///
+/// The control flow graph and dominator tree are valid when `run` is called. A pass that changes
+/// the control flow doesn't need to update them; the `Context` recomputes both analyses after
+/// running the passes registered for a pipeline point.
+pub trait CustomPass {
+    /// Short name identifying this pass in debug output.
+    fn name(&self) -> &str;
+
+    /// Run the pass on `func`.
+    ///
+    /// Returning an error aborts the compilation of the function.
+    fn run(
+        &mut self,
+        func: &mut Function,
+        cfg: &mut ControlFlowGraph,
+        domtree: &mut DominatorTree,
+        isa: &TargetIsa,
+    ) -> CtonResult;
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::{types, AbiParam, InstBuilder};
+    use isa;
+    use result::{CompileError, CtonError};
+    use settings::{self, Configurable};
+    use std::boxed::Box;
+    use std::string::ToString;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct CountInsts(Rc<Cell<usize>>);
+
+    impl CustomPass for CountInsts {
+        fn name(&self) -> &str {
+            "count_insts"
+        }
+
+        fn run(
+            &mut self,
+            func: &mut Function,
+            cfg: &mut ControlFlowGraph,
+            _domtree: &mut DominatorTree,
+            _isa: &TargetIsa,
+        ) -> CtonResult {
+            assert!(cfg.is_valid());
+            let mut pos = FuncCursor::new(func);
+            while let Some(_ebb) = pos.next_ebb() {
+                while let Some(_inst) = pos.next_inst() {
+                    self.0.set(self.0.get() + 1);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_at_pass_point() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.returns.push(AbiParam::new(types::I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().iconst(types::I32, 3);
+            pos.ins().return_(&[v0]);
+        }
+
+        let count = Rc::new(Cell::new(0));
+        ctx.add_custom_pass(PassPoint::PreLegalize, Box::new(CountInsts(count.clone())));
+        ctx.compile(&*isa).unwrap();
+        assert_eq!(count.get(), 2);
+    }
+
+    /// A pass that breaks the function, or panics.
+    struct Break {
+        panic: bool,
+    }
+
+    impl CustomPass for Break {
+        fn name(&self) -> &str {
+            "break"
+        }
+
+        fn run(
+            &mut self,
+            func: &mut Function,
+            _cfg: &mut ControlFlowGraph,
+            _domtree: &mut DominatorTree,
+            _isa: &TargetIsa,
+        ) -> CtonResult {
+            assert!(!self.panic, "broken pass");
+            let ebb = func.layout.entry_block().unwrap();
+            let ret = func.layout.last_inst(ebb).unwrap();
+            func.layout.remove_inst(ret);
+            Ok(())
+        }
+    }
+
+    fn try_compile_with(pass: Break, snapshot: bool) -> CompileError {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        if snapshot {
+            flag_builder.enable("snapshot_ir_on_error").unwrap();
+        }
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().iconst(types::I32, 3);
+            pos.ins().return_(&[]);
+        }
+        ctx.add_custom_pass(PassPoint::PostLegalize, Box::new(pass));
+        ctx.try_compile(&*isa).unwrap_err()
+    }
+
+    #[test]
+    fn failing_pass() {
+        let err = try_compile_with(Break { panic: false }, true);
+        assert_eq!(err.pass, Some("Embedder-defined passes"));
+        assert!(err.verifier_errors().is_some(), "{}", err);
+        assert!(err.ir.unwrap().contains("iconst.i32 3"));
+    }
+
+    #[test]
+    fn panicking_pass() {
+        let err = try_compile_with(Break { panic: true }, false);
+        assert!(err.is_panic());
+        assert_eq!(err.error, CtonError::Panic("broken pass".to_string()));
+        assert_eq!(err.pass, Some("Embedder-defined passes"));
+        assert_eq!(err.ir, None);
+    }
+}
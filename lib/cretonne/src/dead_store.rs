@@ -0,0 +1,279 @@
+//! Dead store elimination.
+//!
+//! Frontends use stack slots for temporaries, and once redundant load elimination has forwarded
+//! the stored values to the loads, many of the stores are never read. This pass removes the stores
+//! that can't be observed:
+//!
+//! - A store to a private stack slot that is never loaded.
+//! - A store to a private stack slot that isn't loaded before the function returns.
+//! - A store that is overwritten by a later store to the same bytes in the same EBB, without a
+//!   load in between that may read them.
+//!
+//! A stack slot is private when its address is only used to compute the addresses of loads and
+//! stores, so all its accesses are visible to the alias analysis of `redundant_load`, and calls
+//! and the caller can't read it.
+//!
+//! Other memory can be read by a call or when an instruction traps, so a store to it is only
+//! removed when it is `notrap` and `aligned` and nothing between it and the overwriting store may
+//! trap or read memory.
+
+use cursor::{Cursor, FuncCursor};
+use entity::EntityMap;
+use ir::{DataFlowGraph, Function, Inst, InstructionData, Opcode, StackSlot, StackSlotKind,
+         Value, ValueDef};
+use redundant_load::{access, Access, Location, Region};
+use std::vec::Vec;
+use timing;
+
+/// The uses of a stack slot.
+#[derive(Clone, Copy, Default)]
+struct SlotUses {
+    /// The address of the slot may be used by something other than loads and stores.
+    escapes: bool,
+    /// The slot is loaded somewhere in the function.
+    loaded: bool,
+}
+
+/// Get the stack slot that `addr` points into, following constant additions.
+fn slot_of_addr(dfg: &DataFlowGraph, addr: Value) -> Option<StackSlot> {
+    let mut addr = dfg.resolve_aliases(addr);
+    loop {
+        let def = match dfg.value_def(addr) {
+            ValueDef::Result(def, _) => def,
+            ValueDef::Param(..) => return None,
+        };
+        match dfg[def] {
+            InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, .. } => {
+                addr = dfg.resolve_aliases(arg);
+            }
+            InstructionData::StackLoad {
+                opcode: Opcode::StackAddr,
+                stack_slot,
+                ..
+            } => return Some(stack_slot),
+            _ => return None,
+        }
+    }
+}
+
+/// Can the address argument number `argnum` of `inst` point into a stack slot without letting the
+/// address escape?
+fn is_private_use(dfg: &DataFlowGraph, inst: Inst, argnum: usize) -> bool {
+    match dfg[inst] {
+        InstructionData::BinaryImm { opcode: Opcode::IaddImm, .. } => true,
+        InstructionData::Load { flags, .. } => !flags.volatile(),
+        InstructionData::Store { flags, .. } => argnum == 1 && !flags.volatile(),
+        _ => false,
+    }
+}
+
+/// Find the uses of the stack slots in `func`.
+///
+/// Only explicit stack slots can be private.
+fn analyze_slots(func: &Function) -> EntityMap<StackSlot, SlotUses> {
+    let mut uses: EntityMap<StackSlot, SlotUses> = EntityMap::new();
+    for ss in func.stack_slots.keys() {
+        uses[ss].escapes = func.stack_slots[ss].kind != StackSlotKind::ExplicitSlot;
+    }
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for (argnum, &arg) in func.dfg.inst_args(inst).iter().enumerate() {
+                if let Some(ss) = slot_of_addr(&func.dfg, arg) {
+                    if !is_private_use(&func.dfg, inst, argnum) {
+                        uses[ss].escapes = true;
+                    }
+                }
+            }
+            if let Access::Load(loc, ..) = access(&func.dfg, inst) {
+                if let Region::Stack(ss) = loc.region {
+                    uses[ss].loaded = true;
+                }
+            }
+        }
+    }
+    uses
+}
+
+/// Can `inst` trap?
+fn may_trap(dfg: &DataFlowGraph, inst: Inst) -> bool {
+    match dfg[inst] {
+        InstructionData::Load { flags, .. } |
+        InstructionData::Store { flags, .. } => !flags.notrap(),
+        ref data => data.opcode().can_trap(),
+    }
+}
+
+/// Can the store `inst` to memory that isn't private be removed when it is overwritten?
+fn is_removable(dfg: &DataFlowGraph, inst: Inst) -> bool {
+    match dfg[inst] {
+        InstructionData::Store { flags, .. } => flags.notrap() && flags.aligned(),
+        _ => false,
+    }
+}
+
+/// Remove the stores in `func` that are never read.
+pub fn eliminate_dead_stores(func: &mut Function) {
+    let _tt = timing::dead_store();
+    let slots = analyze_slots(func);
+    let is_private = |region: Region| match region {
+        Region::Stack(ss) => !slots[ss].escapes,
+        _ => false,
+    };
+
+    // The locations that are written again later in the EBB before they may be read.
+    let mut overwritten: Vec<Location> = Vec::new();
+    let mut pos = FuncCursor::new(func);
+
+    while let Some(ebb) = pos.next_ebb() {
+        overwritten.clear();
+        // The private stack slots that may be loaded before the function returns, when it returns
+        // at the end of the EBB.
+        let mut loaded_before_exit: Option<Vec<StackSlot>> = None;
+
+        pos.goto_bottom(ebb);
+        while let Some(inst) = pos.prev_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
+                loaded_before_exit = Some(Vec::new());
+            }
+
+            match access(&pos.func.dfg, inst) {
+                Access::Store(loc, ..) => {
+                    let covered = overwritten.iter().any(|o| o.covers(&loc));
+                    let dead = match loc.region {
+                        Region::Stack(ss) if !slots[ss].escapes => {
+                            let exits_unread = loaded_before_exit.as_ref().map_or(
+                                false,
+                                |loaded| !loaded.contains(&ss),
+                            );
+                            !slots[ss].loaded || exits_unread || covered
+                        }
+                        _ => covered && is_removable(&pos.func.dfg, inst),
+                    };
+                    if dead {
+                        pos.remove_inst();
+                        continue;
+                    }
+                    overwritten.push(loc);
+                }
+                Access::Load(loc, ..) => {
+                    overwritten.retain(|o| {
+                        !o.may_alias(&loc) || (is_private(o.region) && o.region != loc.region)
+                    });
+                    if let (Region::Stack(ss), Some(loaded)) =
+                        (loc.region, loaded_before_exit.as_mut())
+                    {
+                        loaded.push(ss);
+                    }
+                }
+                // Calls and unknown side effects may read any memory except the private slots.
+                Access::Clobber => overwritten.retain(|o| is_private(o.region)),
+                Access::None => {
+                    let reads = match pos.func.dfg[inst] {
+                        InstructionData::Barrier { .. } => true,
+                        _ => opcode.can_load(),
+                    };
+                    if reads {
+                        overwritten.retain(|o| is_private(o.region));
+                    }
+                }
+            }
+
+            // Memory other than the stack is visible after a trap.
+            if may_trap(&pos.func.dfg, inst) {
+                overwritten.retain(|o| is_private(o.region));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, CallConv, ExtFuncData, ExternalName, InstBuilder, MemFlags, Signature,
+             StackSlotData};
+
+    fn count_stores(func: &Function) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode().can_store())
+            .count()
+    }
+
+    #[test]
+    fn stack_slots() {
+        let mut func = Function::new();
+        let ss0 = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        let ss1 = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        let ss2 = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I64));
+        let sig = func.import_signature(sig);
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("f"),
+            signature: sig,
+            colocated: false,
+        });
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            // Never loaded.
+            pos.ins().stack_store(x, ss0, 0);
+            // Overwritten, then loaded in the next EBB.
+            pos.ins().stack_store(x, ss1, 0);
+            pos.ins().stack_store(x, ss1, 0);
+            // Escapes to the call.
+            let addr = pos.ins().stack_addr(I64, ss2, 0);
+            pos.ins().store(MemFlags::new(), x, addr, 0);
+            pos.ins().call(callee, &[addr]);
+            pos.ins().jump(ebb1, &[]);
+
+            pos.insert_ebb(ebb1);
+            let v = pos.ins().stack_load(I32, ss1, 0);
+            // Not loaded before the return.
+            pos.ins().stack_store(v, ss1, 0);
+            pos.ins().return_(&[]);
+        }
+
+        eliminate_dead_stores(&mut func);
+        assert_eq!(count_stores(&func), 2);
+    }
+
+    #[test]
+    fn overwritten_heap_stores() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let mut flags = MemFlags::new();
+            flags.set_notrap();
+            flags.set_aligned();
+            // Overwritten.
+            pos.ins().store(flags, x, p, 0);
+            pos.ins().store(flags, x, p, 0);
+            // Read by the load.
+            pos.ins().store(flags, x, p, 8);
+            pos.ins().load(I32, flags, p, 8);
+            pos.ins().store(flags, x, p, 8);
+            // May trap.
+            pos.ins().store(MemFlags::new(), x, p, 16);
+            pos.ins().store(flags, x, p, 16);
+            // Observable by the trap.
+            pos.ins().store(flags, x, p, 24);
+            pos.ins().trapz(x, ::ir::TrapCode::User(0));
+            pos.ins().store(flags, x, p, 24);
+            pos.ins().return_(&[]);
+        }
+
+        eliminate_dead_stores(&mut func);
+        assert_eq!(count_stores(&func), 7);
+    }
+}
@@ -0,0 +1,293 @@
+//! Structural deduplication of functions.
+//!
+//! Generic code is often instantiated several times with identical bodies, and a module only
+//! needs one copy of each. This module computes a canonical form of a function body that doesn't
+//! depend on how its entities happen to be numbered, so two functions that differ only in their
+//! names and in the numbering of their EBBs and values compare equal and hash to the same value.
+//!
+//! The canonical form numbers EBBs in layout order and values in the order they are defined:
+//! first the EBB parameters, then the instruction results. Aliases are resolved, so an argument
+//! that refers to an alias is the same as one that refers to the aliased value. Preamble entities
+//! like stack slots, signatures, and external functions are compared by their contents in
+//! declaration order. Source locations, encodings, and value locations are ignored.
+//!
+//! A module layer can use a `Deduplicator` to find the functions that are identical to one it has
+//! already seen, and emit an alias to the earlier function instead of compiling a duplicate.
+
+use entity::{EntityMap, EntityRef};
+use ir::{Ebb, ExternalName, Function, Value, ValueListPool};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::string::String;
+
+/// The canonical form of a function, independent of entity numbering.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CanonicalForm(String);
+
+/// Canonical numbers of the EBBs and values in a function.
+struct Numbering {
+    ebbs: EntityMap<Ebb, Option<u32>>,
+    values: EntityMap<Value, Option<u32>>,
+}
+
+impl Numbering {
+    fn new(func: &Function) -> Self {
+        let mut numbering = Self {
+            ebbs: EntityMap::new(),
+            values: EntityMap::new(),
+        };
+        let mut next_value = 0;
+        for (num, ebb) in func.layout.ebbs().enumerate() {
+            numbering.ebbs[ebb] = Some(num as u32);
+            let results = func.layout.ebb_insts(ebb).flat_map(
+                |inst| func.dfg.inst_results(inst),
+            );
+            for &value in func.dfg.ebb_params(ebb).iter().chain(results) {
+                numbering.values[value] = Some(next_value);
+                next_value += 1;
+            }
+        }
+        numbering
+    }
+
+    /// Write the canonical number of `ebb`, or `?` if it isn't in the layout.
+    fn write_ebb(&self, out: &mut String, ebb: Ebb) {
+        match self.ebbs.get(ebb).and_then(|&n| n) {
+            Some(n) => write!(out, " e{}", n),
+            None => write!(out, " e?"),
+        }.unwrap()
+    }
+
+    /// Write the canonical number of `value`, or `?` if it isn't defined in the layout.
+    fn write_value(&self, out: &mut String, func: &Function, value: Value) {
+        let value = func.dfg.resolve_aliases(value);
+        match self.values.get(value).and_then(|&n| n) {
+            Some(n) => write!(out, " v{}", n),
+            None => write!(out, " v?"),
+        }.unwrap()
+    }
+}
+
+/// Compute the canonical form of `func`.
+fn canonicalize(func: &Function) -> CanonicalForm {
+    let numbering = Numbering::new(func);
+    let mut out = String::new();
+
+    // The preamble. Entity references in instructions are compared by number, so the entities
+    // must be compared in declaration order.
+    writeln!(out, "{}", func.signature).unwrap();
+    for ss in func.stack_slots.keys() {
+        writeln!(out, "{}", func.stack_slots[ss]).unwrap();
+    }
+    for gv in func.global_vars.keys() {
+        writeln!(out, "{}", func.global_vars[gv]).unwrap();
+    }
+    for heap in func.heaps.keys() {
+        writeln!(out, "{}", func.heaps[heap]).unwrap();
+    }
+    for sig in func.dfg.signatures.keys() {
+        writeln!(out, "{}", func.dfg.signatures[sig]).unwrap();
+    }
+    for ext in func.dfg.ext_funcs.keys() {
+        writeln!(out, "{}", func.dfg.ext_funcs[ext]).unwrap();
+    }
+    for constant in func.constants.keys() {
+        writeln!(out, "{}", func.constants[constant]).unwrap();
+    }
+    if let Some(gv) = func.stack_limit {
+        writeln!(out, "stack_limit {}", gv).unwrap();
+    }
+    if let Some(gv) = func.fuel {
+        writeln!(out, "fuel {}", gv).unwrap();
+    }
+    for jt in func.jump_tables.keys() {
+        let jt = &func.jump_tables[jt];
+        write!(out, "jt {}:", jt.len()).unwrap();
+        for (idx, ebb) in jt.entries() {
+            write!(out, " {}", idx).unwrap();
+            numbering.write_ebb(&mut out, ebb);
+        }
+        out.push('\n');
+    }
+
+    // The body. The instruction data is written with its value and EBB operands cleared, and the
+    // canonical numbers of the operands are written separately.
+    let mut no_lists = ValueListPool::new();
+    for ebb in func.layout.ebbs() {
+        out.push_str("ebb(");
+        for &param in func.dfg.ebb_params(ebb) {
+            write!(out, " {}", func.dfg.value_type(param)).unwrap();
+        }
+        out.push_str(" )\n");
+
+        for inst in func.layout.ebb_insts(ebb) {
+            let mut data = func.dfg[inst].clone();
+            data.take_value_list();
+            for arg in data.arguments_mut(&mut no_lists) {
+                *arg = Value::new(0);
+            }
+            let dest = data.branch_destination_mut().map(|dest| {
+                let ebb = *dest;
+                *dest = Ebb::new(0);
+                ebb
+            });
+
+            write!(out, "{:?}.{} ->", data, func.dfg.ctrl_typevar(inst)).unwrap();
+            for &result in func.dfg.inst_results(inst) {
+                write!(out, " {}", func.dfg.value_type(result)).unwrap();
+            }
+            out.push_str(" :");
+            for &arg in func.dfg.inst_args(inst) {
+                numbering.write_value(&mut out, func, arg);
+            }
+            if let Some(dest) = dest {
+                numbering.write_ebb(&mut out, dest);
+            }
+            out.push('\n');
+        }
+    }
+
+    CanonicalForm(out)
+}
+
+/// Get the canonical form of `func` as text.
+///
+/// The text is only meant to be compared and hashed, and it may change between Cretonne versions.
+pub fn canonical_text(func: &Function) -> String {
+    canonicalize(func).0
+}
+
+/// Compute a hash of `func` that doesn't depend on its name or on the numbering of its EBBs and
+/// values.
+///
+/// Functions that are `equivalent` have the same hash.
+pub fn canonical_hash(func: &Function) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    canonicalize(func).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Are `a` and `b` identical apart from their names and the numbering of their EBBs and values?
+pub fn equivalent(a: &Function, b: &Function) -> bool {
+    canonicalize(a) == canonicalize(b)
+}
+
+/// Detect functions that are identical to functions seen earlier.
+pub struct Deduplicator {
+    seen: HashMap<CanonicalForm, ExternalName>,
+}
+
+impl Deduplicator {
+    /// Create a new deduplicator that hasn't seen any functions.
+    pub fn new() -> Self {
+        Self { seen: HashMap::new() }
+    }
+
+    /// Check if `func` is identical to a function seen earlier.
+    ///
+    /// Returns the name of the earlier function, which `func` can be emitted as an alias of.
+    /// Otherwise, `func` is remembered under its own name and `None` is returned.
+    pub fn insert(&mut self, func: &Function) -> Option<ExternalName> {
+        let name = &func.name;
+        let earlier = self.seen.entry(canonicalize(func)).or_insert_with(
+            || name.clone(),
+        );
+        if earlier == name { None } else { Some(earlier.clone()) }
+    }
+
+    /// Get the number of distinct functions seen.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Have no functions been seen yet?
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+
+    /// Build a function that counts down by `step`, creating `spare` unused EBBs first to change
+    /// the numbering.
+    fn build(name: &str, spare: usize, step: i64) -> Function {
+        let mut func = Function::new();
+        func.name = ExternalName::testcase(name);
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        for _ in 0..spare {
+            let ebb = func.dfg.make_ebb();
+            func.dfg.append_ebb_param(ebb, I32);
+        }
+        let entry = func.dfg.make_ebb();
+        let header = func.dfg.make_ebb();
+        let exit = func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(entry);
+            let n = pos.func.dfg.append_ebb_param(entry, I32);
+            pos.ins().jump(header, &[n]);
+
+            pos.insert_ebb(header);
+            let i = pos.func.dfg.append_ebb_param(header, I32);
+            let next = pos.ins().iadd_imm(i, -step);
+            pos.ins().brz(next, exit, &[]);
+            pos.ins().jump(header, &[next]);
+
+            pos.insert_ebb(exit);
+            pos.ins().return_(&[n]);
+        }
+        func
+    }
+
+    #[test]
+    fn renumbering() {
+        let a = build("a", 0, 1);
+        let b = build("b", 3, 1);
+        assert!(equivalent(&a, &b));
+        assert_eq!(canonical_hash(&a), canonical_hash(&b));
+
+        let c = build("c", 0, 2);
+        assert!(!equivalent(&a, &c));
+        assert_ne!(canonical_hash(&a), canonical_hash(&c));
+    }
+
+    #[test]
+    fn aliases() {
+        let a = build("a", 0, 1);
+        let mut b = build("b", 0, 1);
+
+        // Return a copy of the entry parameter, then turn the copy into an alias.
+        let n = b.dfg.ebb_params(b.layout.entry_block().unwrap())[0];
+        let ret = b.layout.last_inst(b.layout.last_ebb().unwrap()).unwrap();
+        let copy = {
+            let mut pos = FuncCursor::new(&mut b).at_inst(ret);
+            pos.ins().copy(n)
+        };
+        b.dfg.inst_args_mut(ret)[0] = copy;
+        assert!(!equivalent(&a, &b));
+
+        let copy_inst = b.dfg.value_def(copy).unwrap_inst();
+        b.layout.remove_inst(copy_inst);
+        b.dfg.clear_results(copy_inst);
+        b.dfg.change_to_alias(copy, n);
+        assert!(equivalent(&a, &b));
+    }
+
+    #[test]
+    fn deduplicator() {
+        let mut dedup = Deduplicator::new();
+        assert_eq!(dedup.insert(&build("a", 0, 1)), None);
+        assert_eq!(dedup.insert(&build("b", 2, 1)), Some(ExternalName::testcase("a")));
+        assert_eq!(dedup.insert(&build("c", 0, 2)), None);
+        assert_eq!(dedup.insert(&build("a", 0, 1)), None);
+        assert_eq!(dedup.len(), 2);
+    }
+}
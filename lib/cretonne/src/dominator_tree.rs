@@ -463,6 +463,38 @@ impl DominatorTree {
 
     }
 
+    /// Update the dominator tree after `ebb` has been removed from the layout.
+    ///
+    /// The EBBs that were immediately dominated by an instruction in `ebb` must have been updated
+    /// with `recompute_idom()` before calling this method, unless the instructions were moved to
+    /// another EBB dominating them.
+    pub fn remove_ebb(&mut self, ebb: Ebb) {
+        if !self.is_reachable(ebb) {
+            return;
+        }
+        let postorder_index = self.postorder
+            .as_slice()
+            .binary_search_by(|probe| self.rpo_cmp_ebb(ebb, *probe))
+            .expect("the ebb is not declared to the dominator tree");
+        self.postorder.remove(postorder_index);
+        self.nodes[ebb] = Default::default();
+    }
+
+    /// Recompute the immediate dominator of `ebb` after its predecessors have been changed in
+    /// `cfg`.
+    ///
+    /// This can be used when branches to another EBB are redirected to `ebb` such that the
+    /// dominators of all the other EBBs are unchanged. The EBB doesn't become reachable if it
+    /// wasn't already.
+    pub fn recompute_idom(&mut self, ebb: Ebb, cfg: &ControlFlowGraph, layout: &Layout) {
+        // The entry block and unreachable blocks don't have an immediate dominator.
+        if self.nodes[ebb].idom.is_none() {
+            return;
+        }
+        let idom = self.compute_idom(ebb, cfg, layout);
+        self.nodes[ebb].idom = idom.into();
+    }
+
     // Insert new_ebb just after ebb in the RPO. This function checks
     // if there is a gap in rpo numbers; if yes it returns the number in the gap and if
     // not it renumbers.
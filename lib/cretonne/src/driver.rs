@@ -0,0 +1,173 @@
+//! Compiling many functions in parallel.
+//!
+//! Cretonne compiles one function at a time, but the functions of a module are independent of
+//! each other, and a `TargetIsa` can be shared by all the compilation threads. The `compile_all`
+//! driver distributes a set of functions over a pool of worker threads, each of which reuses a
+//! single `Context` for all the functions it compiles.
+
+use binemit::{NullTrapSink, Relocations, TrapReport};
+use context::Context;
+use ir::Function;
+use isa::TargetIsa;
+use result::CtonError;
+use std::cmp::min;
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::vec::{self, Vec};
+use timing;
+
+/// The machine code and metadata produced for a single function by `compile_all`.
+#[derive(Clone, Debug)]
+pub struct CompiledFunction {
+    /// The machine code of the function. Relocations have not been applied.
+    pub code: Vec<u8>,
+
+    /// The relocations in `code`.
+    pub relocs: Relocations,
+
+    /// The explicit trap instructions in `code`.
+    pub traps: TrapReport,
+}
+
+/// The result of compiling a single function.
+type CompileResult = Result<CompiledFunction, CtonError>;
+
+/// Compile all of `funcs` for `isa`, using up to `num_threads` worker threads.
+///
+/// The functions are compiled with a default `Context`, so no custom passes or trap handler are
+/// used. Returns the compilation results in the same order as `funcs`. The pass timings of all the
+/// workers are added to the current thread's timings.
+///
+/// A panic in one of the worker threads is propagated to the caller after all the workers have
+/// finished.
+pub fn compile_all(
+    isa: &Arc<Box<TargetIsa>>,
+    funcs: Vec<Function>,
+    num_threads: usize,
+) -> Vec<CompileResult> {
+    let count = funcs.len();
+    let queue = Arc::new(Mutex::new(funcs.into_iter().enumerate()));
+    let handles: Vec<_> = (0..min(num_threads, count).max(1))
+        .map(|_| {
+            let isa = isa.clone();
+            let queue = queue.clone();
+            thread::spawn(move || worker(&**isa, &queue))
+        })
+        .collect();
+
+    let mut results: Vec<Option<CompileResult>> = (0..count).map(|_| None).collect();
+    let mut panicked = None;
+    for handle in handles {
+        match handle.join() {
+            Ok((compiled, times)) => {
+                timing::add_to_current(&times);
+                for (idx, result) in compiled {
+                    results[idx] = Some(result);
+                }
+            }
+            Err(payload) => panicked = Some(payload),
+        }
+    }
+    if let Some(payload) = panicked {
+        panic::resume_unwind(payload);
+    }
+    results
+        .into_iter()
+        .map(|result| result.expect("function wasn't compiled"))
+        .collect()
+}
+
+/// The work queue shared by the worker threads.
+type Queue = Mutex<::std::iter::Enumerate<vec::IntoIter<Function>>>;
+
+/// Compile functions from `queue` until it is empty.
+///
+/// Returns the results tagged with the index of their function, and the pass timings of this
+/// thread.
+fn worker(isa: &TargetIsa, queue: &Queue) -> (Vec<(usize, CompileResult)>, timing::PassTimes) {
+    let mut ctx = Context::new();
+    let mut compiled = Vec::new();
+    loop {
+        // Don't hold the lock while compiling.
+        let next = queue.lock().unwrap().next();
+        let (idx, func) = match next {
+            Some(job) => job,
+            None => break,
+        };
+        ctx.clear();
+        ctx.func = func;
+        compiled.push((idx, compile_function(&mut ctx, isa)));
+    }
+    (compiled, timing::take_current())
+}
+
+/// Compile the function in `ctx` and emit its machine code.
+///
+/// This is the work done by `compile_all` for each function.
+pub fn compile_function(
+    ctx: &mut Context,
+    isa: &TargetIsa,
+) -> Result<CompiledFunction, CtonError> {
+    let size = ctx.compile(isa)?;
+    let mut code = vec![0; size as usize];
+    let mut relocs = Relocations::new();
+    ctx.emit_to_memory(code.as_mut_ptr(), &mut relocs, &mut NullTrapSink {}, isa);
+    Ok(CompiledFunction {
+        code,
+        relocs,
+        traps: ctx.trap_report(isa),
+    })
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::{types, AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    /// Build a function returning `n`, or one that fails verification if `valid` is false.
+    fn func(n: i64, valid: bool) -> Function {
+        let mut func = Function::new();
+        func.signature.returns.push(AbiParam::new(types::I32));
+        let ebb0 = func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().iconst(types::I32, n);
+            if valid {
+                pos.ins().return_(&[v0]);
+            } else {
+                pos.ins().return_(&[]);
+            }
+        }
+        func
+    }
+
+    #[test]
+    fn compile() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = Arc::new(
+            isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder)),
+        );
+
+        let funcs: Vec<_> = (0..20).map(|n| func(n, n != 7)).collect();
+        let results = compile_all(&isa, funcs, 4);
+        assert_eq!(results.len(), 20);
+        for (n, result) in results.into_iter().enumerate() {
+            if n == 7 {
+                assert!(result.is_err());
+                continue;
+            }
+            let compiled = result.unwrap();
+            assert!(!compiled.code.is_empty());
+            assert!(compiled.relocs.is_empty());
+        }
+
+        assert!(compile_all(&isa, Vec::new(), 4).is_empty());
+        assert_eq!(compile_all(&isa, vec![func(1, true)], 0).len(), 1);
+    }
+}
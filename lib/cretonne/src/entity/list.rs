@@ -61,6 +61,7 @@ use std::vec::Vec;
 /// The index stored in an `EntityList` points to part 2, the list elements. The value 0 is
 /// reserved for the empty list which isn't allocated in the vector.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct EntityList<T: EntityRef> {
     index: u32,
     unused: PhantomData<T>,
@@ -91,6 +92,7 @@ impl<T: EntityRef> Eq for EntityList<T> {}
 
 /// A memory pool for storing lists of `T`.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct ListPool<T: EntityRef> {
     // The main array containing the lists.
     data: Vec<T>,
@@ -99,6 +101,17 @@ pub struct ListPool<T: EntityRef> {
     free: Vec<usize>,
 }
 
+/// Memory statistics of a `ListPool`, counted in elements of the pool vector.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListPoolStats {
+    /// Number of elements in the pool vector, used or not.
+    pub allocated: usize,
+    /// Number of elements in the blocks on the free lists.
+    pub free: usize,
+    /// Number of elements the pool vector can hold without reallocating.
+    pub capacity: usize,
+}
+
 /// Lists are allocated in sizes that are powers of two, starting from 4.
 /// Each power of two is assigned a size class number, so the size is `4 << SizeClass`.
 type SizeClass = u8;
@@ -140,6 +153,67 @@ impl<T: EntityRef> ListPool<T> {
         self.free.clear();
     }
 
+    /// Release the memory that the pool isn't using to the allocator.
+    ///
+    /// Only the memory following the last allocated block can be released. Use `compact()` to
+    /// also release the free blocks between the lists.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.free.shrink_to_fit();
+    }
+
+    /// Get the number of bytes allocated for this pool.
+    pub fn heap_size(&self) -> usize {
+        self.data.capacity() * mem::size_of::<T>() + self.free.capacity() * mem::size_of::<usize>()
+    }
+
+    /// Get the memory statistics of this pool.
+    ///
+    /// This walks the free lists, so it takes time proportional to the number of free blocks.
+    pub fn stats(&self) -> ListPoolStats {
+        let mut free = 0;
+        for (sclass, &head) in self.free.iter().enumerate() {
+            let mut next = head;
+            while next > 0 {
+                free += sclass_size(sclass as SizeClass);
+                next = self.data[next].index();
+            }
+        }
+        ListPoolStats {
+            allocated: self.data.len(),
+            free,
+            capacity: self.data.capacity(),
+        }
+    }
+
+    /// Move the contents of `lists` next to each other at the start of the pool, and release the
+    /// rest of the pool's memory.
+    ///
+    /// The `lists` must include every list that is still in use. Any other list allocated from
+    /// this pool is invalidated, like after `clear()`. Each list must be passed once, and the
+    /// clones aliasing the same memory are invalidated as well.
+    pub fn compact<'a, I>(&mut self, lists: I)
+    where
+        I: IntoIterator<Item = &'a mut EntityList<T>>,
+        T: 'a,
+    {
+        let mut data = Vec::new();
+        for list in lists {
+            let elems = list.as_slice(self);
+            if elems.is_empty() {
+                list.index = 0;
+                continue;
+            }
+            let block = data.len();
+            data.push(T::new(elems.len()));
+            data.extend_from_slice(elems);
+            data.resize(block + sclass_size(sclass_for_length(elems.len())), T::new(0));
+            list.index = (block + 1) as u32;
+        }
+        self.data = data;
+        self.free = Vec::new();
+    }
+
     /// Read the length of a list field, if it exists.
     fn len_of(&self, list: &EntityList<T>) -> Option<usize> {
         let idx = list.index as usize;
@@ -446,6 +520,53 @@ impl<T: EntityRef> EntityList<T> {
         pool.data[block] = T::new(len - 1);
     }
 
+    /// Shortens the list to `len` elements, dropping the rest.
+    ///
+    /// This does nothing if the list isn't longer than `len`.
+    pub fn truncate(&mut self, len: usize, pool: &mut ListPool<T>) {
+        let old_len = self.len(pool);
+        if len >= old_len {
+            return;
+        }
+        if len == 0 {
+            self.clear(pool);
+            return;
+        }
+
+        // Do we need to reallocate to a smaller size class?
+        let mut block = self.index as usize - 1;
+        let sclass = sclass_for_length(old_len);
+        let new_sclass = sclass_for_length(len);
+        if new_sclass != sclass {
+            block = pool.realloc(block, sclass, new_sclass, len + 1);
+            self.index = (block + 1) as u32;
+        }
+        pool.data[block] = T::new(len);
+    }
+
+    /// Sorts the elements of the list by their entity index.
+    pub fn sort(&mut self, pool: &mut ListPool<T>) {
+        self.as_mut_slice(pool).sort_unstable_by_key(|e| e.index());
+    }
+
+    /// Removes the consecutive repeated elements of the list, like `Vec::dedup()`.
+    ///
+    /// Sort the list first to remove all the duplicates.
+    pub fn dedup(&mut self, pool: &mut ListPool<T>) {
+        let len = {
+            let seq = self.as_mut_slice(pool);
+            let mut len = 0;
+            for i in 0..seq.len() {
+                if len == 0 || seq[i] != seq[len - 1] {
+                    seq[len] = seq[i];
+                    len += 1;
+                }
+            }
+            len
+        };
+        self.truncate(len, pool);
+    }
+
     /// Removes the element at `index` in constant time by switching it with the last element of
     /// the list.
     pub fn swap_remove(&mut self, index: usize, pool: &mut ListPool<T>) {
@@ -678,4 +799,75 @@ mod tests {
         list.as_mut_slice(pool)[3] = i4;
         assert_eq!(list.as_slice(pool), &[i2, i1, i3, i4]);
     }
+
+    #[test]
+    fn sort_dedup() {
+        let pool = &mut ListPool::<Inst>::new();
+        let mut list = EntityList::<Inst>::new();
+        list.extend([5, 1, 3, 1, 5, 5, 2].iter().map(|&i| Inst::new(i)), pool);
+        list.sort(pool);
+        list.dedup(pool);
+        let v: Vec<usize> = list.as_slice(pool).iter().map(|i| i.index()).collect();
+        assert_eq!(v, [1, 2, 3, 5]);
+
+        list.truncate(6, pool);
+        assert_eq!(list.len(pool), 4);
+        list.truncate(3, pool);
+        assert_eq!(list.len(pool), 3);
+        assert_eq!(list.get(2, pool), Some(Inst::new(3)));
+        list.truncate(0, pool);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn compact() {
+        let pool = &mut ListPool::<Inst>::new();
+        let mut lists: Vec<EntityList<Inst>> = Vec::new();
+        for n in 0..20 {
+            let mut list = EntityList::new();
+            list.extend((0..n).map(Inst::new), pool);
+            lists.push(list);
+        }
+        // Free every other list.
+        for list in lists.iter_mut().step_by(2) {
+            list.clear(pool);
+        }
+        let before = pool.stats();
+        assert!(before.free > 0);
+        assert!(before.allocated <= before.capacity);
+
+        pool.compact(lists.iter_mut());
+        let after = pool.stats();
+        assert_eq!(after.free, 0);
+        assert_eq!(after.allocated, before.allocated - before.free);
+        for (n, list) in lists.iter().enumerate() {
+            if n % 2 == 0 {
+                assert!(list.is_empty());
+            } else {
+                let v: Vec<usize> = list.as_slice(pool).iter().map(|i| i.index()).collect();
+                assert_eq!(v, (0..n).collect::<Vec<usize>>());
+            }
+        }
+
+        // The compacted pool still allocates and frees correctly.
+        lists[1].push(Inst::new(7), pool);
+        assert_eq!(lists[1].as_slice(pool), &[Inst::new(0), Inst::new(7)]);
+        lists[3].clear(pool);
+        assert_eq!(pool.stats().free, 4);
+    }
+
+    #[cfg(feature = "enable-serde")]
+    #[test]
+    fn serde() {
+        use entity::{EntityMap, EntitySet, PrimaryMap};
+        use serde::Serialize;
+        use serde::de::DeserializeOwned;
+
+        fn serializable<T: Serialize + DeserializeOwned>() {}
+        serializable::<PrimaryMap<Inst, u32>>();
+        serializable::<EntityMap<Inst, u32>>();
+        serializable::<EntitySet<Inst>>();
+        serializable::<EntityList<Inst>>();
+        serializable::<ListPool<Inst>>();
+    }
 }
@@ -2,6 +2,7 @@
 
 use entity::{EntityRef, Keys};
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Index, IndexMut};
 use std::vec::Vec;
 
@@ -14,6 +15,7 @@ use std::vec::Vec;
 /// The map does not track if an entry for a key has been inserted or not. Instead it behaves as if
 /// all keys have a default entry from the beginning.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct EntityMap<K, V>
 where
     K: EntityRef,
@@ -68,6 +70,11 @@ where
         self.elems.clear()
     }
 
+    /// Get the number of bytes allocated for the entries of this map.
+    pub fn heap_size(&self) -> usize {
+        self.elems.capacity() * mem::size_of::<V>()
+    }
+
     /// Iterate over all the keys in this map.
     pub fn keys(&self) -> Keys<K> {
         Keys::new(self.elems.len())
@@ -28,6 +28,11 @@
 //! - [`EntityList`](struct.EntityList.html) is a compact representation of lists of entity
 //!   references allocated from an associated memory pool. It has a much smaller footprint than
 //!   `Vec`.
+//!
+//! With the `enable-serde` feature, the `PrimaryMap`, `EntityMap`, `EntitySet`, `EntityList`, and
+//! `ListPool` containers implement serde's `Serialize` and `Deserialize`, and so do the entity
+//! references in `ir::entities`. The containers are serialized as their underlying vectors, so
+//! the entity reference `k` still maps to the same element after deserializing.
 
 mod keys;
 mod list;
@@ -37,10 +42,10 @@ mod sparse;
 mod set;
 
 pub use self::keys::Keys;
-pub use self::list::{EntityList, ListPool};
+pub use self::list::{EntityList, ListPool, ListPoolStats};
 pub use self::map::EntityMap;
 pub use self::primary::PrimaryMap;
-pub use self::set::EntitySet;
+pub use self::set::{EntitySet, SetIter};
 pub use self::sparse::{SparseSet, SparseMap, SparseMapValue};
 
 /// A type wrapping a small integer index should implement `EntityRef` so it can be used as the key
@@ -1,6 +1,7 @@
 //! Densely numbered entity references as mapping keys.
 use entity::{EntityRef, Keys};
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Index, IndexMut};
 use std::vec::Vec;
 
@@ -14,6 +15,7 @@ use std::vec::Vec;
 /// There should only be a single `PrimaryMap` instance for a given `EntityRef` type, otherwise
 /// conflicting references will be created. Using unknown keys for indexing will cause a panic.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct PrimaryMap<K, V>
 where
     K: EntityRef,
@@ -54,6 +56,11 @@ where
         self.elems.len()
     }
 
+    /// Get the number of bytes allocated for the elements of this map.
+    pub fn heap_size(&self) -> usize {
+        self.elems.capacity() * mem::size_of::<V>()
+    }
+
     /// Iterate over all the keys in this map.
     pub fn keys(&self) -> Keys<K> {
         Keys::new(self.elems.len())
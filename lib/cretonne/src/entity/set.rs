@@ -9,6 +9,7 @@ use std::vec::Vec;
 /// The `EntitySet` data structure uses the dense index space to implement a set with a bitvector.
 /// Like `EntityMap`, an `EntitySet` is used to associate secondary information with entities.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct EntitySet<K>
 where
     K: EntityRef,
@@ -53,6 +54,11 @@ where
         self.elems.clear()
     }
 
+    /// Get the number of bytes allocated for this set.
+    pub fn heap_size(&self) -> usize {
+        self.elems.capacity()
+    }
+
     /// Iterate over all the keys in this set.
     pub fn keys(&self) -> Keys<K> {
         Keys::new(self.len)
@@ -61,6 +67,11 @@ where
     /// Resize the set to have `n` entries by adding default entries as needed.
     pub fn resize(&mut self, n: usize) {
         self.elems.resize((n + 7) / 8, 0);
+        // Drop the members beyond the new size from the last byte, so the bulk operations never
+        // see them.
+        if n % 8 != 0 {
+            self.elems[n / 8] &= (1 << (n % 8)) - 1;
+        }
         self.len = n
     }
 
@@ -74,6 +85,109 @@ where
         self.elems[index / 8] |= 1 << (index % 8);
         result
     }
+
+    /// Remove the element at `k`.
+    ///
+    /// Returns true if `k` was in the set.
+    pub fn remove(&mut self, k: K) -> bool {
+        let result = self.contains(k);
+        if result {
+            let index = k.index();
+            self.elems[index / 8] &= !(1 << (index % 8));
+        }
+        result
+    }
+
+    /// Get the number of members in this set.
+    pub fn count(&self) -> usize {
+        self.elems.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Iterate over the members of this set in index order.
+    ///
+    /// Unlike `keys()`, this skips the keys that aren't in the set.
+    pub fn iter(&self) -> SetIter<K> {
+        SetIter {
+            elems: &self.elems,
+            next: 0,
+            base: 0,
+            bits: 0,
+            unused: PhantomData,
+        }
+    }
+
+    /// Add the members of `other` to this set.
+    ///
+    /// Returns true if this set changed.
+    pub fn union_with(&mut self, other: &Self) -> bool {
+        if other.len > self.len {
+            self.resize(other.len);
+        }
+        let mut changed = false;
+        for (a, &b) in self.elems.iter_mut().zip(&other.elems) {
+            changed |= b & !*a != 0;
+            *a |= b;
+        }
+        changed
+    }
+
+    /// Remove the members that aren't in `other` from this set.
+    ///
+    /// Returns true if this set changed.
+    pub fn intersect_with(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (i, a) in self.elems.iter_mut().enumerate() {
+            let b = other.elems.get(i).cloned().unwrap_or(0);
+            changed |= *a & !b != 0;
+            *a &= b;
+        }
+        changed
+    }
+
+    /// Remove the members of `other` from this set.
+    ///
+    /// Returns true if this set changed.
+    pub fn difference_with(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (a, &b) in self.elems.iter_mut().zip(&other.elems) {
+            changed |= *a & b != 0;
+            *a &= !b;
+        }
+        changed
+    }
+}
+
+/// Iterator over the members of an `EntitySet`.
+pub struct SetIter<'a, K>
+where
+    K: EntityRef + 'a,
+{
+    elems: &'a [u8],
+    // Index of the next byte to load from `elems`.
+    next: usize,
+    // Key index of the first bit in `bits`.
+    base: usize,
+    // The members of the current byte that haven't been returned yet.
+    bits: u8,
+    unused: PhantomData<K>,
+}
+
+impl<'a, K> Iterator for SetIter<'a, K>
+where
+    K: EntityRef,
+{
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        while self.bits == 0 {
+            self.bits = *self.elems.get(self.next)?;
+            self.base = self.next * 8;
+            self.next += 1;
+        }
+        let bit = self.bits.trailing_zeros() as usize;
+        self.bits &= self.bits - 1;
+        Some(K::new(self.base + bit))
+    }
 }
 
 #[cfg(test)]
@@ -140,4 +254,43 @@ mod tests {
         m.clear();
         assert!(m.is_empty());
     }
+
+    fn set_of(members: &[u32]) -> EntitySet<E> {
+        let mut set = EntitySet::new();
+        for &m in members {
+            set.insert(E(m));
+        }
+        set
+    }
+
+    #[test]
+    fn bulk() {
+        let mut a = set_of(&[1, 3, 9, 17]);
+        let b = set_of(&[3, 4, 9]);
+        assert_eq!(a.count(), 4);
+        assert_eq!(a.iter().collect::<Vec<E>>(), [E(1), E(3), E(9), E(17)]);
+        assert_eq!(EntitySet::<E>::new().iter().next(), None);
+
+        let mut union = b.clone();
+        assert!(union.union_with(&a));
+        assert!(!union.union_with(&b));
+        assert_eq!(union.iter().collect::<Vec<E>>(), [E(1), E(3), E(4), E(9), E(17)]);
+        assert!(union.contains(E(17)));
+
+        let mut inter = a.clone();
+        assert!(inter.intersect_with(&b));
+        assert!(!inter.intersect_with(&b));
+        assert_eq!(inter.iter().collect::<Vec<E>>(), [E(3), E(9)]);
+
+        assert!(a.difference_with(&b));
+        assert!(!a.difference_with(&b));
+        assert_eq!(a.iter().collect::<Vec<E>>(), [E(1), E(17)]);
+        assert_eq!(a.count(), 2);
+
+        // Shrinking the set drops the members beyond the new size.
+        a.resize(10);
+        assert_eq!(a.count(), 1);
+        a.resize(20);
+        assert!(!a.contains(E(17)));
+    }
 }
@@ -0,0 +1,130 @@
+//! Function entry and exit hooks.
+//!
+//! When the `enable_entry_exit_hooks` setting is enabled, the generated code calls the
+//! `EnterFunction` library routine when a function is entered and the `ExitFunction` routine
+//! before the function returns or makes a tail call. Both routines receive the index of the
+//! function's user name as an `i32` argument so an embedder can attribute the calls to the source
+//! functions without rewriting the generated code.
+//!
+//! The hooks are inserted as ordinary calls before legalization, so the hook routines must follow
+//! the native calling convention and preserve all the registers it designates as callee-saved.
+
+use cursor::{Cursor, FuncCursor};
+use ir::{self, ExternalName, Function, InstBuilder, LibCall};
+use ir::types::I32;
+use timing;
+
+/// Get the index passed to the hooks for `func`.
+///
+/// Functions without a user name get the index 0.
+fn function_index(func: &Function) -> u32 {
+    match func.name {
+        ExternalName::User { index, .. } => index,
+        _ => 0,
+    }
+}
+
+/// Import the hook routine `libcall` into `func`, using the signature `sig`.
+fn import_hook(func: &mut Function, libcall: LibCall, sig: ir::SigRef) -> ir::FuncRef {
+    func.import_function(ir::ExtFuncData {
+        name: ExternalName::LibCall(libcall),
+        signature: sig,
+        colocated: false,
+    })
+}
+
+/// Insert calls to the entry and exit hooks into `func`.
+pub fn insert_entry_exit_hooks(func: &mut Function) {
+    let _tt = timing::entry_exit_hooks();
+    let entry = match func.layout.entry_block() {
+        Some(ebb) => ebb,
+        None => return,
+    };
+
+    let mut sig = ir::Signature::new(ir::CallConv::Native);
+    sig.params.push(ir::AbiParam::new(I32));
+    let sig = func.import_signature(sig);
+    let enter = import_hook(func, LibCall::EnterFunction, sig);
+    let exit = import_hook(func, LibCall::ExitFunction, sig);
+    let index = function_index(func) as i64;
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
+                let arg = pos.ins().iconst(I32, index);
+                pos.ins().call(exit, &[arg]);
+            }
+        }
+    }
+
+    pos.goto_first_insertion_point(entry);
+    let arg = pos.ins().iconst(I32, index);
+    pos.ins().call(enter, &[arg]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::Opcode;
+    use settings;
+    use std::vec::Vec;
+    use verifier::verify_function;
+
+    /// Get the callee and the constant argument of every call in `func`, in layout order.
+    fn calls(func: &Function) -> Vec<(ExternalName, i64)> {
+        let mut calls = Vec::new();
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                if let ir::InstructionData::Call { func_ref, ref args, .. } = func.dfg[inst] {
+                    let arg = args.first(&func.dfg.value_lists).unwrap();
+                    let def = func.dfg.value_def(arg).unwrap_inst();
+                    let imm = match func.dfg[def] {
+                        ir::InstructionData::UnaryImm { imm, .. } => imm.into(),
+                        _ => panic!("Unexpected hook argument"),
+                    };
+                    calls.push((func.dfg.ext_funcs[func_ref].name.clone(), imm));
+                }
+            }
+        }
+        calls
+    }
+
+    #[test]
+    fn hooks() {
+        let mut func = Function::with_name_signature(
+            ExternalName::user(0, 7),
+            ir::Signature::new(ir::CallConv::Native),
+        );
+        func.signature.params.push(ir::AbiParam::new(I32));
+        func.signature.returns.push(ir::AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().brz(v0, ebb1, &[]);
+            pos.ins().return_(&[v0]);
+            pos.insert_ebb(ebb1);
+            let v1 = pos.ins().iadd_imm(v0, 1);
+            pos.ins().return_(&[v1]);
+        }
+
+        insert_entry_exit_hooks(&mut func);
+        verify_function(&func, &settings::Flags::new(&settings::builder())).unwrap();
+
+        let enter = ExternalName::LibCall(LibCall::EnterFunction);
+        let exit = ExternalName::LibCall(LibCall::ExitFunction);
+        assert_eq!(calls(&func), [(enter, 7), (exit.clone(), 7), (exit, 7)]);
+
+        // The exit hooks are called right before the returns.
+        for ebb in func.layout.ebbs() {
+            let last = func.layout.last_inst(ebb).unwrap();
+            let prev = func.layout.prev_inst(last).unwrap();
+            assert_eq!(func.dfg[last].opcode(), Opcode::Return);
+            assert_eq!(func.dfg[prev].opcode(), Opcode::Call);
+        }
+    }
+}
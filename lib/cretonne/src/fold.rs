@@ -0,0 +1,273 @@
+//! Constant folding of pure instructions.
+//!
+//! This module holds the one definition of what the pure integer and boolean instructions compute
+//! on constant operands. The optimization passes that fold constants all evaluate instructions
+//! through `evaluate`, so they can't disagree with each other about the result of an
+//! instruction.
+//!
+//! Constants are represented as `i64` values sign-extended from their type, see `normalize`.
+//! Booleans are 0 or 1.
+
+use cursor::FuncCursor;
+use ir::condcodes::IntCC;
+use ir::dfg::ValueDef;
+use ir::{DataFlowGraph, Inst, InstBuilder, InstructionData, Opcode, Type, Value};
+
+/// Is `ty` a type whose constant values can be folded?
+///
+/// Constants are evaluated on `i64`, so wider types like `i128` are not folded.
+pub fn is_foldable(ty: Type) -> bool {
+    !ty.is_vector() && (ty.is_int() || ty.is_bool()) && ty.bits() <= 64
+}
+
+/// Sign-extend the low `bits` of `x`.
+fn sext(x: i64, bits: u16) -> i64 {
+    if bits >= 64 {
+        x
+    } else {
+        let shift = 64 - bits;
+        (x << shift) >> shift
+    }
+}
+
+/// Zero-extend the low `bits` of `x`.
+fn zext(x: i64, bits: u16) -> u64 {
+    if bits >= 64 {
+        x as u64
+    } else {
+        (x as u64) & ((1 << bits) - 1)
+    }
+}
+
+/// Normalize `x` to the representation of constants of type `ty`.
+pub fn normalize(x: i64, ty: Type) -> i64 {
+    if ty.is_bool() {
+        (x != 0) as i64
+    } else {
+        sext(x, ty.bits())
+    }
+}
+
+/// Evaluate a binary integer operation on constants of type `ty`.
+///
+/// Returns `None` for operations that aren't handled or would trap.
+fn eval_binary(opcode: Opcode, ty: Type, x: i64, y: i64) -> Option<i64> {
+    let bits = ty.bits();
+    let shift = (y as u32) & u32::from(bits - 1);
+    let value = match opcode {
+        Opcode::Iadd | Opcode::IaddImm => x.wrapping_add(y),
+        Opcode::Isub => x.wrapping_sub(y),
+        Opcode::IrsubImm => y.wrapping_sub(x),
+        Opcode::Imul | Opcode::ImulImm => x.wrapping_mul(y),
+        Opcode::Band | Opcode::BandImm => x & y,
+        Opcode::Bor | Opcode::BorImm => x | y,
+        Opcode::Bxor | Opcode::BxorImm => x ^ y,
+        Opcode::Ishl | Opcode::IshlImm => x.wrapping_shl(shift),
+        Opcode::Ushr | Opcode::UshrImm => zext(x, bits).wrapping_shr(shift) as i64,
+        Opcode::Sshr | Opcode::SshrImm => x.wrapping_shr(shift),
+        Opcode::Udiv | Opcode::UdivImm => zext(x, bits).checked_div(zext(y, bits))? as i64,
+        Opcode::Urem | Opcode::UremImm => zext(x, bits).checked_rem(zext(y, bits))? as i64,
+        Opcode::Sdiv | Opcode::SdivImm => {
+            if y == -1 && x == sext(1 << (bits - 1), bits) {
+                return None;
+            }
+            x.checked_div(y)?
+        }
+        Opcode::Srem | Opcode::SremImm => {
+            if y == -1 {
+                return None;
+            }
+            x.checked_rem(y)?
+        }
+        _ => return None,
+    };
+    Some(normalize(value, ty))
+}
+
+/// Evaluate an integer comparison on constants of type `ty`.
+pub fn eval_compare(cond: IntCC, ty: Type, x: i64, y: i64) -> i64 {
+    let bits = ty.bits();
+    let (ux, uy) = (zext(x, bits), zext(y, bits));
+    let result = match cond {
+        IntCC::Equal => x == y,
+        IntCC::NotEqual => x != y,
+        IntCC::SignedLessThan => x < y,
+        IntCC::SignedGreaterThanOrEqual => x >= y,
+        IntCC::SignedGreaterThan => x > y,
+        IntCC::SignedLessThanOrEqual => x <= y,
+        IntCC::UnsignedLessThan => ux < uy,
+        IntCC::UnsignedGreaterThanOrEqual => ux >= uy,
+        IntCC::UnsignedGreaterThan => ux > uy,
+        IntCC::UnsignedLessThanOrEqual => ux <= uy,
+        IntCC::Overflow => sub_overflows(x, y, bits),
+        IntCC::NotOverflow => !sub_overflows(x, y, bits),
+    };
+    result as i64
+}
+
+/// Does `x - y` overflow as a signed `bits`-bit subtraction?
+fn sub_overflows(x: i64, y: i64, bits: u16) -> bool {
+    let (diff, overflow) = x.overflowing_sub(y);
+    overflow || sext(diff, bits) != diff
+}
+
+/// Evaluate the single result of the pure instruction `inst`.
+///
+/// The constant values of the arguments are provided by `arg`, which returns `None` for an
+/// argument that isn't constant. It is called once for each argument the instruction depends on,
+/// even when an earlier argument isn't constant.
+///
+/// Returns `None` if the instruction isn't a pure instruction with a foldable result, if one of
+/// its arguments isn't constant, or if it would trap.
+pub fn evaluate<F>(dfg: &DataFlowGraph, inst: Inst, mut arg: F) -> Option<i64>
+where
+    F: FnMut(Value) -> Option<i64>,
+{
+    let results = dfg.inst_results(inst);
+    if results.len() != 1 || !is_foldable(dfg.value_type(results[0])) {
+        return None;
+    }
+    let ty = dfg.value_type(results[0]);
+
+    match dfg[inst] {
+        InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => {
+            Some(normalize(imm.into(), ty))
+        }
+        InstructionData::UnaryBool { opcode: Opcode::Bconst, imm } => Some(imm as i64),
+        InstructionData::Unary { opcode, arg: x } => {
+            let from = dfg.value_type(x).bits();
+            let f: fn(i64, Type, u16) -> i64 = match opcode {
+                Opcode::Copy | Opcode::Bint | Opcode::Sextend => |x, _, _| x,
+                Opcode::Bnot if ty.is_bool() => |x, _, _| x ^ 1,
+                Opcode::Bnot => |x, ty, _| normalize(!x, ty),
+                Opcode::Uextend => |x, _, from| zext(x, from) as i64,
+                Opcode::Ireduce => |x, ty, _| sext(x, ty.bits()),
+                _ => return None,
+            };
+            arg(x).map(|x| f(x, ty, from))
+        }
+        InstructionData::Binary { opcode, args } => {
+            let (x, y) = (arg(args[0]), arg(args[1]));
+            eval_binary(opcode, ty, x?, y?)
+        }
+        InstructionData::BinaryImm { opcode, arg: x, imm } => {
+            eval_binary(opcode, ty, arg(x)?, sext(imm.into(), ty.bits()))
+        }
+        InstructionData::IntCompare { cond, args, .. } => {
+            let arg_ty = dfg.value_type(args[0]);
+            let (x, y) = (arg(args[0]), arg(args[1]));
+            Some(eval_compare(cond, arg_ty, x?, y?))
+        }
+        InstructionData::IntCompareImm { cond, arg: x, imm, .. } => {
+            let arg_ty = dfg.value_type(x);
+            let y = sext(imm.into(), arg_ty.bits());
+            Some(eval_compare(cond, arg_ty, arg(x)?, y))
+        }
+        InstructionData::Ternary { opcode: Opcode::Select, args } => {
+            let c = arg(args[0])?;
+            arg(args[if c != 0 { 1 } else { 2 }])
+        }
+        _ => None,
+    }
+}
+
+/// Get the constant value of `value` if it is defined by an `iconst` or `bconst` instruction.
+pub fn constant_value(dfg: &DataFlowGraph, value: Value) -> Option<i64> {
+    let value = dfg.resolve_aliases(value);
+    let ty = dfg.value_type(value);
+    match dfg.value_def(value) {
+        ValueDef::Result(inst, 0) => {
+            match dfg[inst] {
+                InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => {
+                    Some(normalize(imm.into(), ty))
+                }
+                InstructionData::UnaryBool { opcode: Opcode::Bconst, imm } => Some(imm as i64),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Replace `inst` which `pos` points at with a constant if all of its arguments are constants.
+///
+/// Returns true if the instruction was replaced.
+pub fn fold_inst(pos: &mut FuncCursor, inst: Inst) -> bool {
+    match pos.func.dfg[inst].opcode() {
+        Opcode::Iconst | Opcode::Bconst => return false,
+        _ => {}
+    }
+    let c = {
+        let dfg = &pos.func.dfg;
+        match evaluate(dfg, inst, |value| constant_value(dfg, value)) {
+            Some(c) => c,
+            None => return false,
+        }
+    };
+    let ty = pos.func.dfg.value_type(pos.func.dfg.first_result(inst));
+    if ty.is_bool() {
+        pos.func.dfg.replace(inst).bconst(ty, c != 0);
+    } else {
+        pos.func.dfg.replace(inst).iconst(ty, c);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::Cursor;
+    use ir::types::{B1, I32, I64, I8};
+    use ir::Function;
+    use std::string::ToString;
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval_binary(Opcode::Iadd, I8, 127, 1), Some(-128));
+        assert_eq!(eval_binary(Opcode::Ushr, I8, -1, 4), Some(15));
+        assert_eq!(eval_binary(Opcode::Sshr, I32, -16, 2), Some(-4));
+        assert_eq!(eval_binary(Opcode::Udiv, I32, 7, 0), None);
+        assert_eq!(eval_binary(Opcode::Sdiv, I32, i64::from(i32::min_value()), -1), None);
+        assert_eq!(eval_binary(Opcode::IrsubImm, I32, 3, 10), Some(7));
+        assert_eq!(eval_compare(IntCC::UnsignedLessThan, I32, -1, 1), 0);
+        assert_eq!(eval_compare(IntCC::SignedLessThan, I32, -1, 1), 1);
+        assert_eq!(eval_compare(IntCC::Overflow, I32, -0x8000_0000, 1), 1);
+        assert_eq!(eval_compare(IntCC::Overflow, I32, -0x7fff_ffff, 1), 0);
+        assert_eq!(eval_compare(IntCC::NotOverflow, I64, i64::min_value(), 1), 0);
+    }
+
+    #[test]
+    fn fold() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let v0 = pos.ins().iconst(I32, 0x7fff_ffff);
+        let v1 = pos.ins().iadd_imm(v0, 1);
+        let v2 = pos.ins().icmp_imm(IntCC::SignedLessThan, v1, 0);
+        let v3 = pos.ins().udiv_imm(v1, 0);
+        let v4 = pos.ins().iadd(v1, x);
+        let v5 = pos.ins().bconst(B1, false);
+        let v6 = pos.ins().select(v5, v4, v0);
+        pos.ins().return_(&[v6]);
+
+        let mut folded = |value: Value| {
+            let inst = pos.func.dfg.value_def(value).unwrap_inst();
+            pos.goto_inst(inst);
+            fold_inst(&mut pos, inst)
+        };
+        assert!(!folded(v0));
+        assert!(folded(v1));
+        assert!(folded(v2));
+        assert!(!folded(v3));
+        assert!(!folded(v4));
+        assert!(folded(v6));
+
+        let dfg = &pos.func.dfg;
+        let def = |value: Value| dfg.display_inst(dfg.value_def(value).unwrap_inst(), None);
+        assert_eq!(def(v1).to_string(), "v2 = iconst.i32 0xffff_ffff_8000_0000");
+        assert_eq!(def(v2).to_string(), "v3 = bconst.b1 true");
+        assert_eq!(def(v6).to_string(), "v7 = iconst.i32 0x7fff_ffff");
+    }
+}
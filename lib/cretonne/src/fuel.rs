@@ -0,0 +1,147 @@
+//! Fuel metering.
+//!
+//! An embedder running untrusted code can bound its execution by giving it a fixed amount of
+//! fuel. When `Function::fuel` is set, the generated code consumes fuel from a pointer-sized
+//! signed counter in memory, and traps with `TrapCode::OutOfFuel` once the counter is negative:
+//!
+//! - The header of each loop consumes the number of instructions in the loop, so each iteration
+//!   pays for its body.
+//! - Each call consumes one unit of fuel before the call, which bounds the recursion.
+//!
+//! The checks are inserted before the optimizations, so the fuel consumed by a function doesn't
+//! depend on the opt level or the pipeline it is compiled with.
+
+use cursor::{Cursor, FuncCursor};
+use ir::condcodes::IntCC;
+use ir::types::{I32, I64};
+use ir::{Ebb, Function, GlobalVar, InstBuilder, MemFlags, TrapCode, Type};
+use isa::TargetIsa;
+use loop_analysis::LoopAnalysis;
+use std::vec::Vec;
+use timing;
+
+/// Insert code at `pos` consuming `amount` units of fuel from the counter at the address of
+/// `fuel`.
+fn consume_fuel(pos: &mut FuncCursor, fuel: GlobalVar, ty: Type, amount: i64) {
+    let mut flags = MemFlags::new();
+    flags.set_notrap();
+    flags.set_aligned();
+    let addr = pos.ins().global_addr(ty, fuel);
+    let left = pos.ins().load(ty, flags, addr, 0);
+    let left = pos.ins().iadd_imm(left, -amount);
+    pos.ins().store(flags, left, addr, 0);
+    let exhausted = pos.ins().icmp_imm(IntCC::SignedLessThan, left, 0);
+    pos.ins().trapnz(exhausted, TrapCode::OutOfFuel);
+}
+
+/// Insert fuel checks into the loops and before the calls of `func`.
+pub fn insert_fuel_checks(func: &mut Function, loop_analysis: &LoopAnalysis, isa: &TargetIsa) {
+    let _tt = timing::fuel();
+    let fuel = match func.fuel {
+        Some(gv) => gv,
+        None => return,
+    };
+    let ty = if isa.flags().is_64bit() { I64 } else { I32 };
+
+    // Count the instructions in the loops before inserting any.
+    let mut headers: Vec<(Ebb, i64)> = Vec::new();
+    for lp in loop_analysis.loops() {
+        let size = func.layout
+            .ebbs()
+            .filter(|&ebb| loop_analysis.is_in_loop(ebb, lp))
+            .map(|ebb| func.layout.ebb_insts(ebb).count() as i64)
+            .sum();
+        headers.push((loop_analysis.loop_header(lp), size));
+    }
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            if pos.func.dfg[inst].opcode().is_call() {
+                pos.use_srcloc(inst);
+                consume_fuel(&mut pos, fuel, ty, 1);
+            }
+        }
+    }
+
+    for (header, size) in headers {
+        pos.goto_first_insertion_point(header);
+        if let Some(first) = pos.current_inst() {
+            pos.use_srcloc(first);
+        }
+        consume_fuel(&mut pos, fuel, ty, size);
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use dominator_tree::DominatorTree;
+    use flowgraph::ControlFlowGraph;
+    use ir::{AbiParam, CallConv, ExtFuncData, ExternalName, GlobalVarData, InstructionData, Opcode,
+             Signature};
+    use ir::immediates::Offset32;
+    use isa;
+    use settings::{self, Configurable};
+    use verifier::verify_function;
+
+    #[test]
+    fn loops_and_calls() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        let gv = func.create_global_var(GlobalVarData::VmCtx { offset: Offset32::new(0) });
+        func.fuel = Some(gv);
+        let sig = func.import_signature(Signature::new(CallConv::Native));
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("f"),
+            signature: sig,
+            colocated: false,
+        });
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let i = func.dfg.append_ebb_param(ebb1, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().jump(ebb1, &[x]);
+            pos.insert_ebb(ebb1);
+            pos.ins().call(callee, &[]);
+            let j = pos.ins().iadd_imm(i, -1);
+            pos.ins().brnz(j, ebb1, &[j]);
+            pos.ins().jump(ebb2, &[]);
+            pos.insert_ebb(ebb2);
+            pos.ins().return_(&[]);
+        }
+
+        let cfg = ControlFlowGraph::with_function(&func);
+        let domtree = DominatorTree::with_function(&func, &cfg);
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(&func, &cfg, &domtree);
+        insert_fuel_checks(&mut func, &loop_analysis, &*isa);
+        verify_function(&func, &*isa).unwrap();
+
+        // The loop header consumes its 4 instructions, and the call consumes 1. The last
+        // `iadd_imm` is the loop's own.
+        let amounts: Vec<i64> = func.layout
+            .ebb_insts(ebb1)
+            .filter_map(|inst| match func.dfg[inst] {
+                InstructionData::BinaryImm { opcode: Opcode::IaddImm, imm, .. } => {
+                    Some(imm.into())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(amounts, [-4, -1, -1]);
+        let traps = func.layout
+            .ebb_insts(ebb1)
+            .filter(|&inst| func.dfg[inst].opcode() == Opcode::Trapnz)
+            .count();
+        assert_eq!(traps, 2);
+    }
+}
@@ -0,0 +1,109 @@
+//! Support for fuzzing harnesses.
+//!
+//! The fuzzing entry points in the `cton_reader::fuzz` and `cton_wasm::fuzz` modules accept
+//! arbitrary bytes and report every failure as a `FuzzError`. Panics inside Cretonne are caught
+//! and reported too, so a harness can tell the rejection of malformed input from a real bug
+//! without depending on Cretonne internals.
+//!
+//! This module is only available with the `fuzz` feature.
+
+use result::{panic_message, CtonError};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::string::{String, ToString};
+
+/// A failure reported by a fuzzing entry point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FuzzError {
+    /// The input was rejected by a parser.
+    Parse(String),
+
+    /// The input was parsed, but the resulting IL failed verification.
+    Verifier(String),
+
+    /// Code generation or translation returned an error.
+    Compile(String),
+
+    /// Cretonne panicked. This is always a bug.
+    Panic(String),
+}
+
+impl FuzzError {
+    /// Is this a panic rather than an error reported in the normal way?
+    pub fn is_panic(&self) -> bool {
+        match *self {
+            FuzzError::Panic(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for FuzzError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FuzzError::Parse(ref msg) => write!(f, "parse error: {}", msg),
+            FuzzError::Verifier(ref msg) => write!(f, "verifier error: {}", msg),
+            FuzzError::Compile(ref msg) => write!(f, "compile error: {}", msg),
+            FuzzError::Panic(ref msg) => write!(f, "panic: {}", msg),
+        }
+    }
+}
+
+impl From<CtonError> for FuzzError {
+    fn from(e: CtonError) -> FuzzError {
+        match e {
+            CtonError::Verifier(e) => FuzzError::Verifier(e.to_string()),
+            CtonError::Panic(msg) => FuzzError::Panic(msg),
+            e => FuzzError::Compile(e.to_string()),
+        }
+    }
+}
+
+/// Run the fuzzing entry point `f`, reporting a panic as `FuzzError::Panic`.
+pub fn catch_panic<F>(f: F) -> Result<(), FuzzError>
+where
+    F: FnOnce() -> Result<(), FuzzError>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => Err(FuzzError::Panic(panic_message(&*payload))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use verifier;
+
+    #[test]
+    fn catch() {
+        assert_eq!(catch_panic(|| Ok(())), Ok(()));
+        assert_eq!(
+            catch_panic(|| Err(FuzzError::Parse("bad".to_string()))),
+            Err(FuzzError::Parse("bad".to_string()))
+        );
+        let err = catch_panic(|| panic!("oops {}", 1)).unwrap_err();
+        assert_eq!(err, FuzzError::Panic("oops 1".to_string()));
+        assert!(err.is_panic());
+    }
+
+    #[test]
+    fn from_cton_error() {
+        let e = CtonError::Verifier(
+            verifier::VerifierError {
+                location: ::ir::entities::AnyEntity::Function,
+                message: "bad".to_string(),
+                pass: None,
+                origin: None,
+            }.into(),
+        );
+        assert!(match FuzzError::from(e) {
+            FuzzError::Verifier(_) => true,
+            _ => false,
+        });
+        assert_eq!(
+            FuzzError::from(CtonError::CodeTooLarge),
+            FuzzError::Compile("Code for function is too large".to_string())
+        );
+    }
+}
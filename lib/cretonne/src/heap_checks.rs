@@ -0,0 +1,459 @@
+//! Fusion and elimination of heap bounds checks.
+//!
+//! A WebAssembly function often accesses several fields of the same structure in linear memory,
+//! and `cretonne-wasm` translates each access into a `heap_addr` instruction with the same index
+//! and a size depending on the access offset:
+//!
+//! ```cton
+//!     v1 = heap_addr.i64 heap0, v0, 1
+//!     v2 = load.i32 v1
+//!     v3 = heap_addr.i64 heap0, v0, 0x1_0001
+//!     v4 = load.i32 v3+0x1_0000
+//! ```
+//!
+//! This pass widens the first bounds check in such a group to cover all the accesses, and the
+//! other `heap_addr` instructions are replaced by the first one. This is only done when nothing
+//! observable can happen between the accesses: A store, call, branch, or another trap ends the
+//! group. An out-of-bounds access can then trap at the first access in its group instead of the
+//! faulting one, with the same trap code.
+//!
+//! The `eliminate_heap_checks` pass removes bounds checks that can't fail without changing where
+//! traps happen. It only applies to heaps that don't move, i.e. static heaps and `readonly`
+//! dynamic heaps:
+//!
+//! - A `heap_addr` instruction is redundant when a dominating `heap_addr` with the same heap and
+//!   index checks at least as many bytes.
+//! - In a counted loop like the ones handled by loop unrolling, the checks of `heap_addr`
+//!   instructions indexed by the induction variable are replaced by a single check of the largest
+//!   index in front of the loop when that check is known to succeed. When fusion of heap checks
+//!   is enabled, this is also done when the check could fail, as long as the loop doesn't have
+//!   any other observable effects.
+
+use cursor::{Cursor, FuncCursor};
+use dominator_tree::DominatorTree;
+use flowgraph::ControlFlowGraph;
+use fold::normalize;
+use ir::{Function, Heap, HeapData, HeapStyle, Inst, InstBuilder, InstructionData, Opcode, Type,
+         Value};
+use loop_analysis::{Loop, LoopAnalysis};
+use scoped_hash_map::{Entry, ScopedHashMap};
+use settings::Flags;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::vec::Vec;
+use timing;
+use unroll::analyze_loop;
+
+/// Fuse the bounds checks of `heap_addr` instructions with the same heap and index in `func`.
+pub fn fuse_heap_checks(func: &mut Function) {
+    let _tt = timing::fuse_heap_checks();
+
+    // The first `heap_addr` instruction of each group in the current region.
+    let mut groups: HashMap<(Heap, Value), Inst> = HashMap::new();
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        groups.clear();
+        while let Some(inst) = pos.next_inst() {
+            if let InstructionData::HeapAddr { heap, arg, imm, .. } = pos.func.dfg[inst] {
+                let key = (heap, pos.func.dfg.resolve_aliases(arg));
+                let first = *groups.entry(key).or_insert(inst);
+                if first == inst ||
+                    pos.func.dfg.ctrl_typevar(first) != pos.func.dfg.ctrl_typevar(inst)
+                {
+                    continue;
+                }
+
+                if let InstructionData::HeapAddr { imm: ref mut first_imm, .. } =
+                    pos.func.dfg[first]
+                {
+                    let (first_size, size): (u32, u32) = ((*first_imm).into(), imm.into());
+                    *first_imm = max(first_size, size).into();
+                }
+                pos.func.dfg.replace_with_aliases(inst, first);
+                pos.remove_inst_and_step_back();
+                continue;
+            }
+
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.can_store() || opcode.can_trap() || opcode.is_call() ||
+                opcode.is_branch() || opcode.other_side_effects()
+            {
+                groups.clear();
+            }
+        }
+    }
+}
+
+/// Remove the bounds checks of `heap_addr` instructions in `func` that can't fail.
+///
+/// The CFG, dominator tree, and loop analysis must be valid, and they remain valid.
+pub fn eliminate_heap_checks(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    flags: &Flags,
+) {
+    let _tt = timing::eliminate_heap_checks();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    // A mispredicted loop exit could execute an iteration beyond the hoisted check.
+    if !flags.enable_heap_access_spectre_mitigation() {
+        for lp in loop_analysis.loops() {
+            hoist_loop_checks(func, cfg, loop_analysis, lp, flags.fuse_heap_checks());
+        }
+    }
+    remove_dominated_checks(func, domtree);
+}
+
+/// Does the heap keep its base address and bound while the function executes?
+fn is_fixed(heap: &HeapData) -> bool {
+    match heap.style {
+        HeapStyle::Static { .. } => true,
+        HeapStyle::Dynamic { .. } => heap.readonly,
+    }
+}
+
+/// Replace the checks indexed by the induction variable of `lp` with a check in front of the loop.
+fn hoist_loop_checks(
+    func: &mut Function,
+    cfg: &ControlFlowGraph,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+    may_trap_early: bool,
+) {
+    let lp = match analyze_loop(func, cfg, loop_analysis, lp) {
+        Some(lp) => lp,
+        None => return,
+    };
+
+    // The hoisted checks are inserted before the jump entering the loop.
+    let mut entry = None;
+    for (pred_ebb, pred_inst) in cfg.pred_iter(lp.header) {
+        if pred_ebb != lp.header {
+            if entry.is_some() {
+                return;
+            }
+            entry = Some(pred_inst);
+        }
+    }
+    let entry = match entry {
+        Some(inst) if func.dfg[inst].opcode() == Opcode::Jump => inst,
+        _ => return,
+    };
+
+    // Compute the largest unsigned value of the induction variable.
+    let param = lp.induction.param;
+    let ty = func.dfg.value_type(param);
+    let mask = u64::max_value() >> (64 - ty.bits());
+    let mut value = lp.induction.init;
+    let mut max_index = 0;
+    for _ in 0..lp.trip_count {
+        max_index = max(max_index, value as u64 & mask);
+        value = normalize(value.wrapping_add(lp.induction.step), ty);
+    }
+
+    // Group the checks by heap and address type, and find the largest access size of each.
+    let mut groups: HashMap<(Heap, Type), u32> = HashMap::new();
+    let mut checks = Vec::new();
+    let mut observable = false;
+    for &inst in &lp.body {
+        if let InstructionData::HeapAddr { heap, arg, imm, .. } = func.dfg[inst] {
+            if func.dfg.resolve_aliases(arg) == param && is_fixed(&func.heaps[heap]) {
+                let key = (heap, func.dfg.ctrl_typevar(inst));
+                let size = groups.entry(key).or_insert(0);
+                *size = max(*size, imm.into());
+                checks.push((inst, key));
+            }
+            continue;
+        }
+        let opcode = func.dfg[inst].opcode();
+        observable |= opcode.can_store() || opcode.can_trap() || opcode.is_call() ||
+            opcode.other_side_effects();
+    }
+
+    // Insert one check per group in front of the loop, and compute the heap base from it.
+    let mut bases = HashMap::new();
+    let mut pos = FuncCursor::new(func).at_inst(entry);
+    for (&(heap, addr_ty), &size) in &groups {
+        let heap_data = &pos.func.heaps[heap];
+        let end = max_index + u64::from(size);
+        // Accesses below the minimum size or the bound of a static heap never trap.
+        let safe_size: i64 = match heap_data.style {
+            HeapStyle::Static { bound } => bound.into(),
+            HeapStyle::Dynamic { .. } => heap_data.min_size.into(),
+        };
+        let in_bounds = end <= safe_size as u64;
+        if !in_bounds && (!may_trap_early || observable) {
+            continue;
+        }
+
+        let index = normalize(max_index as i64, ty);
+        let index = pos.ins().iconst(ty, index);
+        let addr = pos.ins().heap_addr(addr_ty, heap, index, size);
+        let base = pos.ins().iadd_imm(addr, -(max_index as i64));
+        bases.insert((heap, addr_ty), base);
+    }
+
+    // The checks in the loop are now just address computations.
+    for (inst, key) in checks {
+        if let Some(&base) = bases.get(&key) {
+            pos.goto_inst(inst);
+            let mut offset = param;
+            if ty != key.1 {
+                offset = pos.ins().uextend(key.1, offset);
+            }
+            pos.func.dfg.replace(inst).iadd(base, offset);
+        }
+    }
+}
+
+/// Remove `heap_addr` instructions covered by a dominating check of the same heap and index.
+fn remove_dominated_checks(func: &mut Function, domtree: &DominatorTree) {
+    // The dominating `heap_addr` instructions, and the number of bytes they check.
+    let mut checks: ScopedHashMap<(Heap, Value, Type), (Inst, u32)> = ScopedHashMap::new();
+    let mut scope_stack: Vec<Inst> = Vec::new();
+    let mut pos = FuncCursor::new(func);
+
+    for &ebb in domtree.cfg_postorder().iter().rev() {
+        // Pop any scopes that we just exited.
+        while let Some(&current) = scope_stack.last() {
+            if domtree.dominates(current, ebb, &pos.func.layout) {
+                break;
+            }
+            scope_stack.pop();
+            checks.decrement_depth();
+        }
+        scope_stack.push(pos.func.layout.first_inst(ebb).unwrap());
+        checks.increment_depth();
+
+        pos.goto_top(ebb);
+        while let Some(inst) = pos.next_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_branch() && !opcode.is_terminator() {
+                scope_stack.push(pos.func.layout.next_inst(inst).unwrap());
+                checks.increment_depth();
+            }
+
+            let (heap, arg, size) = match pos.func.dfg[inst] {
+                InstructionData::HeapAddr { heap, arg, imm, .. } => (heap, arg, imm.into()),
+                _ => continue,
+            };
+            if !is_fixed(&pos.func.heaps[heap]) {
+                continue;
+            }
+            let key = (
+                heap,
+                pos.func.dfg.resolve_aliases(arg),
+                pos.func.dfg.ctrl_typevar(inst),
+            );
+            match checks.entry(key) {
+                Entry::Occupied(entry) => {
+                    let (first, first_size) = *entry.get();
+                    if first_size < size {
+                        continue;
+                    }
+                    // Pick a new representative if this instruction was representing the scope.
+                    let old = scope_stack.last_mut().unwrap();
+                    if *old == inst {
+                        *old = pos.func.layout.next_inst(inst).unwrap();
+                    }
+                    pos.func.dfg.replace_with_aliases(inst, first);
+                    pos.remove_inst_and_step_back();
+                }
+                Entry::Vacant(entry) => entry.insert((inst, size)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::condcodes::IntCC;
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, HeapBase, MemFlags};
+    use settings::{self, Configurable};
+    use verifier::verify_function;
+
+    fn static_heap(func: &mut Function, bound: i64) -> Heap {
+        func.create_heap(HeapData {
+            base: HeapBase::ReservedReg,
+            min_size: 0.into(),
+            guard_size: 0x1_0000.into(),
+            style: HeapStyle::Static { bound: bound.into() },
+            readonly: false,
+        })
+    }
+
+    fn run(func: &mut Function, flags: &Flags) {
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(func, &cfg, &domtree);
+        eliminate_heap_checks(func, &cfg, &domtree, &loop_analysis, flags);
+        verify_function(func, flags).unwrap();
+    }
+
+    fn count_checks(func: &Function) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == Opcode::HeapAddr)
+            .count()
+    }
+
+    /// Build a function storing to `heap[i]` for `i` in `0..trip_count`.
+    fn store_loop(func: &mut Function, heap: Heap, trip_count: i64, store: bool) {
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let i = func.dfg.append_ebb_param(ebb1, I32);
+        let mut pos = FuncCursor::new(func);
+        pos.insert_ebb(ebb0);
+        let zero = pos.ins().iconst(I32, 0);
+        pos.ins().jump(ebb1, &[zero]);
+
+        pos.insert_ebb(ebb1);
+        let addr = pos.ins().heap_addr(I64, heap, i, 4);
+        if store {
+            pos.ins().store(MemFlags::new(), i, addr, 0);
+        } else {
+            pos.ins().load(I32, MemFlags::new(), addr, 0);
+        }
+        let i2 = pos.ins().iadd_imm(i, 1);
+        let c = pos.ins().icmp_imm(IntCC::UnsignedLessThan, i2, trip_count);
+        pos.ins().brnz(c, ebb1, &[i2]);
+        pos.ins().jump(ebb2, &[]);
+
+        pos.insert_ebb(ebb2);
+        pos.ins().return_(&[]);
+    }
+
+    #[test]
+    fn fuse() {
+        let mut func = Function::new();
+        let heap = func.create_heap(HeapData {
+            base: HeapBase::ReservedReg,
+            min_size: 0.into(),
+            guard_size: 0x1_0000.into(),
+            style: HeapStyle::Static { bound: 0x1_0000_0000.into() },
+            readonly: false,
+        });
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let (a0, a1, a2);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            a0 = pos.ins().heap_addr(I64, heap, v0, 1);
+            let x = pos.ins().load(I32, MemFlags::new(), a0, 0);
+            a1 = pos.ins().heap_addr(I64, heap, v0, 0x1_0001);
+            pos.ins().load(I32, MemFlags::new(), a1, 0x1_0000);
+            pos.ins().store(MemFlags::new(), x, a1, 0x1_0000);
+            a2 = pos.ins().heap_addr(I64, heap, v0, 0x2_0001);
+            pos.ins().return_(&[]);
+        }
+        fuse_heap_checks(&mut func);
+
+        assert_eq!(func.dfg.resolve_aliases(a1), a0);
+        assert_ne!(func.dfg.resolve_aliases(a2), a0);
+        let first = func.layout.first_inst(ebb0).unwrap();
+        assert_eq!(
+            func.dfg.display_inst(first, None).to_string(),
+            "v1 = heap_addr.i64 heap0, v0, 0x0001_0001"
+        );
+    }
+
+    #[test]
+    fn dominated() {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        let heap = static_heap(&mut func, 0x1_0000);
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let (a0, a1, a2);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            a0 = pos.ins().heap_addr(I64, heap, v0, 8);
+            pos.ins().store(MemFlags::new(), v0, a0, 0);
+            pos.ins().jump(ebb1, &[]);
+
+            pos.insert_ebb(ebb1);
+            a1 = pos.ins().heap_addr(I64, heap, v0, 4);
+            pos.ins().store(MemFlags::new(), v0, a1, 4);
+            // Checks more bytes than the dominating check.
+            a2 = pos.ins().heap_addr(I64, heap, v0, 12);
+            pos.ins().store(MemFlags::new(), v0, a2, 8);
+            pos.ins().return_(&[]);
+        }
+        run(&mut func, &Flags::new(&settings::builder()));
+
+        assert_eq!(func.dfg.resolve_aliases(a1), a0);
+        assert_eq!(func.dfg.resolve_aliases(a2), a2);
+        assert_eq!(count_checks(&func), 2);
+    }
+
+    #[test]
+    fn hoist_in_bounds() {
+        // The largest index is 99, so the accesses are always within the bound.
+        let mut func = Function::new();
+        let heap = static_heap(&mut func, 103);
+        store_loop(&mut func, heap, 100, true);
+        run(&mut func, &Flags::new(&settings::builder()));
+
+        let ebb0 = func.layout.entry_block().unwrap();
+        assert_eq!(count_checks(&func), 1);
+        let check = func.layout
+            .ebb_insts(ebb0)
+            .find(|&inst| func.dfg[inst].opcode() == Opcode::HeapAddr)
+            .unwrap();
+        assert_eq!(
+            func.dfg.display_inst(check, None).to_string(),
+            "v6 = heap_addr.i64 heap0, v5, 4"
+        );
+
+        // Not when the last access is out of bounds.
+        let mut func = Function::new();
+        let heap = static_heap(&mut func, 102);
+        store_loop(&mut func, heap, 100, true);
+        run(&mut func, &Flags::new(&settings::builder()));
+        let ebb0 = func.layout.entry_block().unwrap();
+        assert!(func.layout.ebb_insts(ebb0).all(|inst| {
+            func.dfg[inst].opcode() != Opcode::HeapAddr
+        }));
+    }
+
+    #[test]
+    fn hoist_early_trap() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("fuse_heap_checks").unwrap();
+        let flags = Flags::new(&flag_builder);
+
+        // A loop that only loads can trap before the first iteration instead.
+        let mut func = Function::new();
+        let heap = static_heap(&mut func, 102);
+        store_loop(&mut func, heap, 100, false);
+        run(&mut func, &flags);
+        let ebb0 = func.layout.entry_block().unwrap();
+        assert!(func.layout.ebb_insts(ebb0).any(|inst| {
+            func.dfg[inst].opcode() == Opcode::HeapAddr
+        }));
+        assert_eq!(count_checks(&func), 1);
+
+        // But the stores of the earlier iterations are observable.
+        let mut func = Function::new();
+        let heap = static_heap(&mut func, 102);
+        store_loop(&mut func, heap, 100, true);
+        run(&mut func, &flags);
+        let ebb0 = func.layout.entry_block().unwrap();
+        assert!(func.layout.ebb_insts(ebb0).all(|inst| {
+            func.dfg[inst].opcode() != Opcode::HeapAddr
+        }));
+    }
+}
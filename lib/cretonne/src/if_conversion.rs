@@ -0,0 +1,358 @@
+//! If-conversion of small diamonds.
+//!
+//! Frontends translate conditional expressions like min, max, and clamp into a conditional branch
+//! over one or two short arms that each pass a value to a merge EBB:
+//!
+//! ```cton
+//!     brz v0, ebb2
+//!     v1 = iadd_imm v5, 1
+//!     jump ebb3(v1)
+//! ebb2:
+//!     v2 = ishl_imm v5, 1
+//!     jump ebb3(v2)
+//! ebb3(v3: i32):
+//! ```
+//!
+//! When the arms are cheap and have no side effects, this pass executes both arms
+//! unconditionally and picks the merged values with `select` instructions:
+//!
+//! ```cton
+//!     v1 = iadd_imm v5, 1
+//!     v2 = ishl_imm v5, 1
+//!     v4 = select v0, v1, v2
+//!     jump ebb3(v4)
+//! ```
+//!
+//! This removes branches that are hard to predict, and the jump can be folded away by CFG
+//! simplification. When the branch condition is an integer comparison and the target ISA can
+//! encode it, the values are selected with `selectif` on the flags of an `ifcmp` instead, which
+//! becomes a conditional move.
+//!
+//! Only diamonds merging integer and boolean values are converted, since selecting floating point
+//! values usually needs branches anyway.
+
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::{Ebb, Function, Inst, InstBuilder, InstructionData, Opcode, Value, ValueDef};
+use ir::condcodes::IntCC;
+use isa::TargetIsa;
+use std::vec::Vec;
+use timing;
+
+/// Maximum number of instructions in each arm of a converted diamond.
+const MAX_ARM_INSTS: usize = 3;
+
+/// A conditional branch over one or two arms that can be converted into selects.
+struct Diamond {
+    /// The conditional branch.
+    branch: Inst,
+
+    /// The jump to the merge EBB following the branch in the same EBB.
+    jump: Inst,
+
+    /// The EBB branched to by `branch`, if it isn't the merge EBB.
+    arm: Option<Ebb>,
+
+    /// The values passed to the merge EBB when the branch is taken.
+    taken: Vec<Value>,
+}
+
+/// Can `inst` be executed speculatively in a converted arm?
+fn is_cheap(func: &Function, inst: Inst) -> bool {
+    let opcode = func.dfg[inst].opcode();
+    !(opcode.is_call() || opcode.is_branch() || opcode.is_terminator() || opcode.can_trap() ||
+          opcode.other_side_effects() || opcode.can_store() || opcode.can_load() ||
+          opcode.writes_cpu_flags()) &&
+        func.dfg.inst_results(inst).iter().all(|&v| {
+            !func.dfg.value_type(v).is_flags()
+        })
+}
+
+/// Get the jump ending `ebb` after at most `MAX_ARM_INSTS` cheap instructions, and the
+/// instruction before them.
+fn arm_end(func: &Function, ebb: Ebb) -> Option<(Inst, Option<Inst>)> {
+    let jump = func.layout.last_inst(ebb)?;
+    if func.dfg[jump].opcode() != Opcode::Jump {
+        return None;
+    }
+    let mut prev = func.layout.prev_inst(jump);
+    let mut count = 0;
+    while let Some(inst) = prev {
+        if !is_cheap(func, inst) {
+            break;
+        }
+        count += 1;
+        if count > MAX_ARM_INSTS {
+            return None;
+        }
+        prev = func.layout.prev_inst(inst);
+    }
+    Some((jump, prev))
+}
+
+/// Find a diamond ending `ebb`.
+fn find_diamond(func: &Function, cfg: &ControlFlowGraph, ebb: Ebb) -> Option<Diamond> {
+    let (jump, branch) = arm_end(func, ebb)?;
+    let branch = branch?;
+    match func.dfg[branch].opcode() {
+        Opcode::Brz | Opcode::Brnz => {}
+        _ => return None,
+    }
+    let merge = func.dfg[jump].branch_destination()?;
+    let target = func.dfg[branch].branch_destination()?;
+    if merge == ebb || target == ebb || cfg.pred_iter(merge).count() != 2 {
+        return None;
+    }
+
+    // Values passed to the merge EBB by the branch, or by the arm it branches to.
+    let (arm, taken) = if target == merge {
+        (None, func.dfg.inst_variable_args(branch).to_vec())
+    } else {
+        if !func.dfg.ebb_params(target).is_empty() || cfg.pred_iter(target).count() != 1 {
+            return None;
+        }
+        let (arm_jump, before) = arm_end(func, target)?;
+        if before.is_some() || func.dfg[arm_jump].branch_destination() != Some(merge) {
+            return None;
+        }
+        (Some(target), func.dfg.inst_variable_args(arm_jump).to_vec())
+    };
+
+    let convertible = func.dfg.ebb_params(merge).iter().all(|&param| {
+        let ty = func.dfg.value_type(param);
+        (ty.is_int() || ty.is_bool()) && ty.lane_count() == 1
+    });
+    if !convertible {
+        return None;
+    }
+
+    Some(Diamond {
+        branch,
+        jump,
+        arm,
+        taken,
+    })
+}
+
+/// Insert the comparison defining the boolean `cond` as an `ifcmp` or `ifcmp_imm` at `pos`.
+///
+/// Returns the flags and the condition code, or `None` if `cond` isn't defined by an integer
+/// comparison or `isa` can't encode the flags comparison.
+fn flags_compare(pos: &mut FuncCursor, isa: &TargetIsa, cond: Value) -> Option<(Value, IntCC)> {
+    let def = match pos.func.dfg.value_def(cond) {
+        ValueDef::Result(def, 0) => def,
+        _ => return None,
+    };
+    let (cc, flags) = match pos.func.dfg[def] {
+        InstructionData::IntCompare { opcode: Opcode::Icmp, cond, args } => {
+            (cond, pos.ins().ifcmp(args[0], args[1]))
+        }
+        InstructionData::IntCompareImm { opcode: Opcode::IcmpImm, cond, arg, imm } => {
+            (cond, pos.ins().ifcmp_imm(arg, imm))
+        }
+        _ => return None,
+    };
+    if is_encodable(pos.func, isa, pos.func.dfg.value_def(flags).unwrap_inst()) {
+        Some((flags, cc))
+    } else {
+        pos.func.layout.remove_inst(pos.func.dfg.value_def(flags).unwrap_inst());
+        None
+    }
+}
+
+/// Does `isa` have an encoding for `inst` without legalization?
+fn is_encodable(func: &Function, isa: &TargetIsa, inst: Inst) -> bool {
+    isa.encode(&func.dfg, &func.dfg[inst], func.dfg.ctrl_typevar(inst))
+        .is_ok()
+}
+
+/// Convert `diamond` into selects.
+fn convert(func: &mut Function, cfg: &mut ControlFlowGraph, isa: Option<&TargetIsa>, d: Diamond) {
+    let ebb = func.layout.pp_ebb(d.branch);
+    let (cond, taken_if_true) = match func.dfg[d.branch] {
+        InstructionData::Branch { opcode, ref args, .. } => {
+            (args.first(&func.dfg.value_lists).unwrap(), opcode == Opcode::Brnz)
+        }
+        _ => panic!("Expected brz/brnz: {}", func.dfg.display_inst(d.branch, None)),
+    };
+    let fallthrough = func.dfg.inst_variable_args(d.jump).to_vec();
+
+    // Hoist the instructions of the arm above the branch.
+    let mut pos = FuncCursor::new(func).at_inst(d.branch);
+    if let Some(arm) = d.arm {
+        let arm_jump = pos.func.layout.last_inst(arm).unwrap();
+        while let Some(inst) = pos.func.layout.first_inst(arm) {
+            if inst == arm_jump {
+                break;
+            }
+            pos.func.layout.remove_inst(inst);
+            pos.insert_inst(inst);
+        }
+    }
+
+    pos.goto_inst(d.jump);
+    pos.use_srcloc(d.branch);
+    let mut flags = None;
+    let mut uses_flags = false;
+    let mut merged = Vec::with_capacity(fallthrough.len());
+    for (&taken, &fall) in d.taken.iter().zip(&fallthrough) {
+        if taken == fall {
+            merged.push(taken);
+            continue;
+        }
+        let (tval, fval) = if taken_if_true {
+            (taken, fall)
+        } else {
+            (fall, taken)
+        };
+        if flags.is_none() {
+            flags = Some(isa.and_then(|isa| flags_compare(&mut pos, isa, cond)));
+        }
+        if let Some(Some((f, cc))) = flags {
+            let ty = pos.func.dfg.value_type(tval);
+            let sel = pos.ins().selectif(ty, cc, f, tval, fval);
+            let sel_inst = pos.func.dfg.value_def(sel).unwrap_inst();
+            if is_encodable(pos.func, isa.unwrap(), sel_inst) {
+                uses_flags = true;
+                merged.push(sel);
+                continue;
+            }
+            pos.func.layout.remove_inst(sel_inst);
+        }
+        merged.push(pos.ins().select(cond, tval, fval));
+    }
+    if let Some(Some((f, _))) = flags {
+        if !uses_flags {
+            pos.func.layout.remove_inst(pos.func.dfg.value_def(f).unwrap_inst());
+        }
+    }
+
+    let merge = pos.func.dfg[d.jump].branch_destination().unwrap();
+    pos.func.dfg.replace(d.jump).jump(merge, &merged);
+    pos.func.layout.remove_inst(d.branch);
+    cfg.recompute_ebb(pos.func, ebb);
+    if let Some(arm) = d.arm {
+        let arm_jump = pos.func.layout.last_inst(arm).unwrap();
+        pos.func.layout.remove_inst(arm_jump);
+        cfg.recompute_ebb(pos.func, arm);
+        pos.func.layout.remove_ebb(arm);
+    }
+}
+
+/// Convert the small diamonds in `func` into selects.
+///
+/// The control flow graph is kept up to date. The `selectif` instructions are only used when
+/// `isa` is given.
+pub fn convert_diamonds(func: &mut Function, cfg: &mut ControlFlowGraph, isa: Option<&TargetIsa>) {
+    let _tt = timing::if_conversion();
+    debug_assert!(cfg.is_valid());
+    let mut next = func.layout.entry_block();
+    while let Some(ebb) = next {
+        if let Some(diamond) = find_diamond(func, cfg, ebb) {
+            convert(func, cfg, isa, diamond);
+        }
+        next = func.layout.next_ebb(ebb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::{AbiParam, MemFlags};
+    use ir::types::I32;
+    use isa;
+    use settings::{self, Configurable};
+    use verifier::verify_function;
+
+    /// Build a function returning `a + 1` if `a < b` and `arm(a)` otherwise.
+    ///
+    /// The `arm` closure is called in a separate arm EBB when `diamond` is set. Otherwise it is
+    /// called before the branch, which passes its result to the merge EBB directly.
+    fn build<F>(diamond: bool, arm: F) -> Function
+    where
+        F: Fn(&mut FuncCursor, Value) -> Value,
+    {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let a = func.dfg.append_ebb_param(ebb0, I32);
+        let b = func.dfg.append_ebb_param(ebb0, I32);
+        let res = func.dfg.append_ebb_param(ebb2, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let cond = pos.ins().icmp(IntCC::SignedLessThan, a, b);
+            if diamond {
+                pos.ins().brz(cond, ebb1, &[]);
+            } else {
+                let other = arm(&mut pos, a);
+                pos.ins().brz(cond, ebb2, &[other]);
+            }
+            let inc = pos.ins().iadd_imm(a, 1);
+            pos.ins().jump(ebb2, &[inc]);
+
+            if diamond {
+                pos.insert_ebb(ebb1);
+                let other = arm(&mut pos, a);
+                pos.ins().jump(ebb2, &[other]);
+            }
+
+            pos.insert_ebb(ebb2);
+            pos.ins().return_(&[res]);
+        }
+        func
+    }
+
+    fn run(func: &mut Function, isa: Option<&TargetIsa>) {
+        let mut cfg = ControlFlowGraph::with_function(func);
+        convert_diamonds(func, &mut cfg, isa);
+        let flags = settings::Flags::new(&settings::builder());
+        verify_function(&*func, &flags).unwrap();
+        assert!(cfg.is_valid());
+    }
+
+    fn count(func: &Function, opcode: Opcode) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == opcode)
+            .count()
+    }
+
+    #[test]
+    fn select() {
+        let mut func = build(true, |pos, a| pos.ins().ishl_imm(a, 1));
+        run(&mut func, None);
+        assert_eq!(func.layout.ebbs().count(), 2);
+        assert_eq!(count(&func, Opcode::Brz), 0);
+        assert_eq!(count(&func, Opcode::Select), 1);
+        assert_eq!(count(&func, Opcode::IshlImm), 1);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn selectif() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let mut func = build(false, |_, a| a);
+        run(&mut func, Some(&*isa));
+        assert_eq!(func.layout.ebbs().count(), 2);
+        assert_eq!(count(&func, Opcode::Brz), 0);
+        assert_eq!(count(&func, Opcode::Ifcmp), 1);
+        assert_eq!(count(&func, Opcode::Selectif), 1);
+    }
+
+    #[test]
+    fn side_effects() {
+        let mut func = build(true, |pos, a| pos.ins().load(I32, MemFlags::new(), a, 0));
+        run(&mut func, None);
+        assert_eq!(func.layout.ebbs().count(), 3);
+        assert_eq!(count(&func, Opcode::Brz), 1);
+        assert_eq!(count(&func, Opcode::Select), 0);
+    }
+}
@@ -0,0 +1,730 @@
+//! Function inlining.
+//!
+//! Cretonne compiles one function at a time, so it can't see the bodies of the functions called
+//! from the function being compiled. An embedder that has the IR of the callees can use the
+//! inliner to splice a callee body into the caller at a `call` site. The callee IR and the
+//! decision to inline a given call site are provided through the `InlineOracle` trait.
+//!
+//! By default, a call site is inlined when the callee fits in the size budget given by the
+//! `inline_size_budget` setting, with the `inline_loop_bonus` setting added for calls in loops.
+//! The oracle sees the cost and budget of each call site and can veto or force inlining it. Calls
+//! in inlined bodies are inlined too, up to the `inline_max_depth` setting.
+//!
+//! When the caller has a profile, the call sites that never ran get no budget, and the call sites
+//! that ran at least as often as the caller get the loop bonus too. The profile of an inlined
+//! callee is scaled to the count of the call site and merged into the caller's profile.
+//!
+//! Inlining copies the callee's instructions, EBBs, stack slots, global variables, heaps, jump
+//! tables, and external function references into the caller. Each `return` in the callee becomes
+//! a jump to a new EBB in the caller which receives the return values.
+//!
+//! The callee signatures must be as declared by the frontend, so inlining must happen before
+//! legalization.
+
+use dominator_tree::DominatorTree;
+use entity::{EntityMap, EntityRef};
+use flowgraph::ControlFlowGraph;
+use ir::{AbiParam, Ebb, ExtFuncData, ExternalName, Function, GlobalVarData, HeapBase, HeapData,
+         HeapStyle, Inst, InstBuilder, InstructionData, JumpTableData, Opcode, Value, ValueList,
+         ValueListPool};
+use loop_analysis::LoopAnalysis;
+use packed_option::PackedOption;
+use settings::Flags;
+use std::vec::Vec;
+use timing;
+
+/// A call site considered for inlining.
+#[derive(Clone, Copy, Debug)]
+pub struct CallSite {
+    /// The call instruction in the caller.
+    pub inst: Inst,
+
+    /// The inlining depth of the call. Calls in the original function have depth 1, and calls in
+    /// a body inlined at depth `n` have depth `n + 1`.
+    pub depth: u8,
+
+    /// Is the call inside a loop? Calls in inlined bodies inherit this from the call site they
+    /// were inlined into.
+    pub in_loop: bool,
+
+    /// The estimated cost of inlining the callee, as computed by `inline_cost()`.
+    pub cost: usize,
+
+    /// The number of times the call ran according to the profile of the caller, if it is known.
+    pub count: Option<u64>,
+
+    /// The size budget for this call site, from the `inline_size_budget` setting plus the
+    /// `inline_loop_bonus` setting for calls in loops and hot calls. Calls that never ran have no
+    /// budget.
+    pub budget: usize,
+}
+
+/// The embedder's decision for a call site.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InlineDecision {
+    /// Inline the call if the cost of the callee fits in the budget.
+    Heuristic,
+
+    /// Inline the call regardless of the cost of the callee.
+    Always,
+
+    /// Never inline the call.
+    Never,
+}
+
+/// Embedder callbacks controlling the inliner.
+pub trait InlineOracle {
+    /// Get the IR of the function called `name`, or `None` if it isn't available for inlining.
+    ///
+    /// The returned function must not be legalized.
+    fn callee(&self, name: &ExternalName) -> Option<&Function>;
+
+    /// Decide if the call `site` in `caller` should be replaced by the body of `callee`.
+    ///
+    /// The default is to use the size heuristic, which inlines callees whose cost is at most the
+    /// budget of the call site.
+    fn decide(&self, caller: &Function, site: &CallSite, callee: &Function) -> InlineDecision {
+        let _ = (caller, site, callee);
+        InlineDecision::Heuristic
+    }
+
+    /// Translate an external name used by `callee` into the name to use in the caller.
+    ///
+    /// The default is to use names unchanged, which is correct when the caller and callee share a
+    /// namespace.
+    fn translate_name(&self, name: &ExternalName) -> ExternalName {
+        name.clone()
+    }
+}
+
+/// Estimate the cost of inlining `func` as its number of instructions.
+pub fn inline_cost(func: &Function) -> usize {
+    func.layout
+        .ebbs()
+        .map(|ebb| func.layout.ebb_insts(ebb).count())
+        .sum()
+}
+
+/// Inline the direct calls in `func` that are accepted by `oracle`.
+///
+/// The calls in the inlined bodies are considered in turn until the `inline_max_depth` setting is
+/// reached. This guarantees termination for recursive functions. Returns the number of call sites
+/// that were inlined.
+pub fn inline_calls(func: &mut Function, flags: &Flags, oracle: &InlineOracle) -> usize {
+    let _tt = timing::inline();
+    let max_depth = flags.inline_max_depth();
+    let size_budget = usize::from(flags.inline_size_budget());
+    let loop_bonus = usize::from(flags.inline_loop_bonus());
+    if max_depth == 0 {
+        return 0;
+    }
+
+    // The loop analysis is only needed to give a bonus to the calls in loops.
+    let mut loop_analysis = LoopAnalysis::new();
+    if loop_bonus > 0 {
+        let cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        loop_analysis.compute(func, &cfg, &domtree);
+    }
+
+    // Call sites to consider, with their depth and whether they are in a loop.
+    let mut calls = Vec::new();
+    for ebb in func.layout.ebbs() {
+        let in_loop = loop_analysis.is_valid() && loop_analysis.innermost_loop(ebb).is_some();
+        for inst in func.layout.ebb_insts(ebb) {
+            if func.dfg[inst].opcode() == Opcode::Call {
+                calls.push((inst, 1, in_loop));
+            }
+        }
+    }
+    calls.reverse();
+
+    // The number of times the function ran in the profile. Inlining doesn't change the entry block.
+    let entry_count = func.layout
+        .entry_block()
+        .and_then(|ebb| func.profile.ebb_count(ebb))
+        .filter(|&count| count > 0);
+
+    let mut inlined = 0;
+    while let Some((call, depth, in_loop)) = calls.pop() {
+        let (name, sig) = match func.dfg[call] {
+            InstructionData::Call { func_ref, .. } => {
+                let ext = &func.dfg.ext_funcs[func_ref];
+                (ext.name.clone(), ext.signature)
+            }
+            _ => continue,
+        };
+        let callee = match oracle.callee(&name) {
+            Some(callee) => callee,
+            None => continue,
+        };
+        // A callee whose signature doesn't match the declaration can't be inlined. Neither can a
+        // callee with tail calls, since those would return from the caller too.
+        let decl = &func.dfg.signatures[sig];
+        if !same_abi(&callee.signature.params, &decl.params) ||
+            !same_abi(&callee.signature.returns, &decl.returns) ||
+            callee.layout.entry_block().is_none() || has_tail_calls(callee)
+        {
+            continue;
+        }
+
+        let count = func.profile_count(call);
+        let hot = match (count, entry_count) {
+            (Some(count), Some(entry_count)) => count >= entry_count,
+            _ => false,
+        };
+        let site = CallSite {
+            inst: call,
+            depth,
+            in_loop,
+            count,
+            cost: inline_cost(callee),
+            budget: if entry_count.is_some() && count == Some(0) {
+                0
+            } else if in_loop || hot {
+                size_budget + loop_bonus
+            } else {
+                size_budget
+            },
+        };
+        let accept = match oracle.decide(func, &site, callee) {
+            InlineDecision::Heuristic => site.cost <= site.budget,
+            InlineDecision::Always => true,
+            InlineDecision::Never => false,
+        };
+        if accept {
+            let insts = splice(func, call, count, callee, oracle);
+            inlined += 1;
+            if depth < max_depth {
+                calls.extend(insts.into_iter().rev().filter_map(|inst| {
+                    if func.dfg[inst].opcode() == Opcode::Call {
+                        Some((inst, depth + 1, in_loop))
+                    } else {
+                        None
+                    }
+                }));
+            }
+        }
+    }
+    inlined
+}
+
+/// Does `func` contain any tail calls?
+fn has_tail_calls(func: &Function) -> bool {
+    func.layout.ebbs().any(|ebb| {
+        func.layout.last_inst(ebb).map_or(false, |inst| {
+            let opcode = func.dfg[inst].opcode();
+            opcode.is_call() && opcode.is_terminator()
+        })
+    })
+}
+
+/// Do the declared parameters `a` and `b` have the same types and purposes?
+fn same_abi(a: &[AbiParam], b: &[AbiParam]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b).all(|(x, y)| {
+            x.value_type == y.value_type && x.purpose == y.purpose
+        })
+}
+
+/// Create a value list in `pool` with the values in `values`.
+fn copy_list(values: &[Value], pool: &mut ValueListPool) -> ValueList {
+    let mut list = ValueList::new();
+    list.extend(values.iter().cloned(), pool);
+    list
+}
+
+/// Replace the `call` instruction in `func` with the body of `callee`.
+///
+/// Returns the instructions copied from the callee.
+fn splice(
+    func: &mut Function,
+    call: Inst,
+    count: Option<u64>,
+    callee: &Function,
+    oracle: &InlineOracle,
+) -> Vec<Inst> {
+    // Copy the callee's entities, recording the new references in vectors indexed by the callee
+    // references.
+    let sigs: Vec<_> = callee
+        .dfg
+        .signatures
+        .keys()
+        .map(|sig| func.import_signature(callee.dfg.signatures[sig].clone()))
+        .collect();
+    let funcs: Vec<_> = callee
+        .dfg
+        .ext_funcs
+        .keys()
+        .map(|fnref| {
+            let ext = &callee.dfg.ext_funcs[fnref];
+            func.import_function(ExtFuncData {
+                name: oracle.translate_name(&ext.name),
+                signature: sigs[ext.signature.index()],
+                colocated: ext.colocated,
+            })
+        })
+        .collect();
+    let slots: Vec<_> = callee
+        .stack_slots
+        .keys()
+        .map(|ss| func.create_stack_slot(callee.stack_slots[ss].clone()))
+        .collect();
+
+    // Global variables may refer to each other, so the references are fixed up after all of them
+    // have been created.
+    let gvs: Vec<_> = callee
+        .global_vars
+        .keys()
+        .map(|gv| func.create_global_var(callee.global_vars[gv].clone()))
+        .collect();
+    for &gv in &gvs {
+        let data = match func.global_vars[gv] {
+            GlobalVarData::VmCtx { offset } => GlobalVarData::VmCtx { offset },
+            GlobalVarData::Deref { base, offset } => GlobalVarData::Deref {
+                base: gvs[base.index()],
+                offset,
+            },
+            GlobalVarData::Sym {
+                ref name,
+                colocated,
+                readonly,
+            } => GlobalVarData::Sym {
+                name: oracle.translate_name(name),
+                colocated,
+                readonly,
+            },
+            GlobalVarData::TLS { ref name, model } => GlobalVarData::TLS {
+                name: oracle.translate_name(name),
+                model,
+            },
+        };
+        func.global_vars[gv] = data;
+    }
+
+    let heaps: Vec<_> = callee
+        .heaps
+        .keys()
+        .map(|heap| {
+            let heap = &callee.heaps[heap];
+            func.create_heap(HeapData {
+                base: match heap.base {
+                    HeapBase::ReservedReg => HeapBase::ReservedReg,
+                    HeapBase::GlobalVar(gv) => HeapBase::GlobalVar(gvs[gv.index()]),
+                },
+                min_size: heap.min_size,
+                guard_size: heap.guard_size,
+                style: match heap.style {
+                    HeapStyle::Dynamic { bound_gv } => HeapStyle::Dynamic {
+                        bound_gv: gvs[bound_gv.index()],
+                    },
+                    HeapStyle::Static { bound } => HeapStyle::Static { bound },
+                },
+                readonly: heap.readonly,
+            })
+        })
+        .collect();
+
+    let ebbs: Vec<Ebb> = (0..callee.dfg.num_ebbs())
+        .map(|_| func.dfg.make_ebb())
+        .collect();
+    let tables: Vec<_> = callee
+        .jump_tables
+        .keys()
+        .map(|jt| {
+            let jt = &callee.jump_tables[jt];
+            let mut data = JumpTableData::with_capacity(jt.len());
+            for (idx, dest) in jt.entries() {
+                data.set_entry(idx, ebbs[dest.index()]);
+            }
+            func.create_jump_table(data)
+        })
+        .collect();
+    let constants: Vec<_> = callee
+        .constants
+        .keys()
+        .map(|c| func.create_constant(callee.constants[c].clone()))
+        .collect();
+
+    // Split the caller's EBB after the call. The call results become parameters of the new EBB
+    // which is the target of the jumps replacing the callee's returns.
+    let return_ebb = func.dfg.make_ebb();
+    let next = func.layout.next_inst(call).expect(
+        "A call can't terminate an EBB",
+    );
+    func.layout.split_ebb(return_ebb, next);
+    let results = func.dfg.detach_results(call);
+    let results = results.as_slice(&func.dfg.value_lists).to_vec();
+    for result in results {
+        func.dfg.attach_ebb_param(return_ebb, result);
+    }
+    if let Some(count) = count {
+        func.profile.set_ebb_count(return_ebb, count);
+    }
+
+    // The callee counts are scaled by the number of times the call ran per callee invocation.
+    let callee_count = callee.layout.entry_block().and_then(
+        |ebb| callee.profile.ebb_count(ebb),
+    );
+    let scale = match (count, callee_count) {
+        (Some(count), Some(entry_count)) if entry_count > 0 => {
+            Some(count as f64 / entry_count as f64)
+        }
+        _ => None,
+    };
+    let scaled = |count: Option<u64>| match (count, scale) {
+        (Some(count), Some(scale)) => Some((count as f64 * scale).round() as u64),
+        _ => None,
+    };
+
+    // Copy the callee EBBs and instructions. Instruction arguments are remapped after all the
+    // values have been created since uses don't have to follow definitions in the layout.
+    let srcloc = func.srclocs[call];
+    let mut values = EntityMap::<Value, PackedOption<Value>>::new();
+    let mut insts = Vec::new();
+    for ebb in callee.layout.ebbs() {
+        let new_ebb = ebbs[ebb.index()];
+        func.layout.insert_ebb(new_ebb, return_ebb);
+        if let Some(count) = scaled(callee.profile.ebb_count(ebb)) {
+            func.profile.set_ebb_count(new_ebb, count);
+        }
+        for &param in callee.dfg.ebb_params(ebb) {
+            let ty = callee.dfg.value_type(param);
+            values[param] = func.dfg.append_ebb_param(new_ebb, ty).into();
+        }
+
+        for inst in callee.layout.ebb_insts(ebb) {
+            let new_inst = if callee.dfg[inst].opcode().is_return() {
+                let args = copy_list(callee.dfg.inst_args(inst), &mut func.dfg.value_lists);
+                func.dfg.make_inst(InstructionData::Jump {
+                    opcode: Opcode::Jump,
+                    destination: return_ebb,
+                    args,
+                })
+            } else {
+                let mut data = callee.dfg[inst].clone();
+                if let Some(list) = data.take_value_list() {
+                    data.put_value_list(copy_list(
+                        list.as_slice(&callee.dfg.value_lists),
+                        &mut func.dfg.value_lists,
+                    ));
+                }
+                match data {
+                    InstructionData::Call { ref mut func_ref, .. } |
+                    InstructionData::Invoke { ref mut func_ref, .. } |
+                    InstructionData::FuncAddr { ref mut func_ref, .. } => {
+                        *func_ref = funcs[func_ref.index()];
+                    }
+                    InstructionData::IndirectCall { ref mut sig_ref, .. } => {
+                        *sig_ref = sigs[sig_ref.index()];
+                    }
+                    InstructionData::UnaryGlobalVar { ref mut global_var, .. } => {
+                        *global_var = gvs[global_var.index()];
+                    }
+                    InstructionData::CallGlobalVar {
+                        ref mut func_ref,
+                        ref mut global_var,
+                        ..
+                    } => {
+                        *func_ref = funcs[func_ref.index()];
+                        *global_var = gvs[global_var.index()];
+                    }
+                    InstructionData::HeapAddr { ref mut heap, .. } => {
+                        *heap = heaps[heap.index()];
+                    }
+                    InstructionData::StackLoad { ref mut stack_slot, .. } |
+                    InstructionData::StackStore { ref mut stack_slot, .. } => {
+                        *stack_slot = slots[stack_slot.index()];
+                    }
+                    InstructionData::BranchTable { ref mut table, .. } |
+                    InstructionData::BranchTableBase { ref mut table, .. } => {
+                        *table = tables[table.index()];
+                    }
+                    InstructionData::UnaryConst { ref mut constant, .. } => {
+                        *constant = constants[constant.index()];
+                    }
+                    _ => {}
+                }
+                if let Some(dest) = data.branch_destination_mut() {
+                    *dest = ebbs[dest.index()];
+                }
+
+                let new_inst = func.dfg.make_inst(data);
+                func.dfg.make_inst_results(new_inst, callee.dfg.ctrl_typevar(inst));
+                for (&old, &new) in callee.dfg.inst_results(inst).iter().zip(
+                    func.dfg.inst_results(new_inst),
+                )
+                {
+                    values[old] = new.into();
+                }
+                new_inst
+            };
+            func.layout.append_inst(new_inst, new_ebb);
+            func.srclocs[new_inst] = srcloc;
+            for annotation in callee.annotations.inst(inst) {
+                func.annotations.annotate_inst(new_inst, annotation.clone());
+            }
+            if let Some(taken) = scaled(callee.profile.branch_count(inst)) {
+                func.profile.set_branch_count(new_inst, taken);
+            }
+            insts.push(new_inst);
+        }
+    }
+
+    for &inst in &insts {
+        for arg in func.dfg.inst_args_mut(inst) {
+            *arg = values[callee.dfg.resolve_aliases(*arg)].expect(
+                "Callee value not defined",
+            );
+        }
+    }
+
+    // Finally, turn the call into a jump to the inlined entry block.
+    let entry = ebbs[callee.layout.entry_block().unwrap().index()];
+    let args = func.dfg.inst_args(call).to_vec();
+    func.dfg.replace(call).jump(entry, &args);
+    insts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{CallConv, Signature};
+    use settings::{self, Configurable};
+    use verifier::verify_function;
+
+    struct Oracle(Function);
+
+    impl InlineOracle for Oracle {
+        fn callee(&self, name: &ExternalName) -> Option<&Function> {
+            if *name == self.0.name {
+                Some(&self.0)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn signature() -> Signature {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        sig
+    }
+
+    // Return `a` if it is nonzero, `b` otherwise.
+    fn callee() -> Function {
+        let mut func = Function::with_name_signature(ExternalName::testcase("pick"), signature());
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let a = func.dfg.append_ebb_param(ebb0, I32);
+        let b = func.dfg.append_ebb_param(ebb0, I32);
+        let x = func.dfg.append_ebb_param(ebb1, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        pos.ins().brz(a, ebb1, &[b]);
+        pos.ins().return_(&[a]);
+        pos.insert_ebb(ebb1);
+        pos.ins().return_(&[x]);
+        func
+    }
+
+    fn caller() -> Function {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("caller"), sig);
+        let sigref = func.import_signature(signature());
+        let fnref = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("pick"),
+            signature: sigref,
+            colocated: false,
+        });
+
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let c = pos.ins().iconst(I32, 7);
+        let call = pos.ins().call(fnref, &[v0, c]);
+        let v1 = pos.func.dfg.first_result(call);
+        let v2 = pos.ins().iadd(v1, v1);
+        pos.ins().return_(&[v2]);
+        func
+    }
+
+    fn flags(budget: &str, depth: &str, bonus: &str) -> Flags {
+        let mut b = settings::builder();
+        b.set("inline_size_budget", budget).unwrap();
+        b.set("inline_max_depth", depth).unwrap();
+        b.set("inline_loop_bonus", bonus).unwrap();
+        Flags::new(&b)
+    }
+
+    #[test]
+    fn inline_branches() {
+        let oracle = Oracle(callee());
+        let mut func = caller();
+        let flags = Flags::new(&settings::builder());
+        assert_eq!(inline_calls(&mut func, &flags, &oracle), 1);
+
+        verify_function(&func, &flags).unwrap();
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                assert!(!func.dfg[inst].opcode().is_call());
+            }
+        }
+        // The caller's EBB was split and the two callee EBBs were inserted.
+        assert_eq!(func.layout.ebbs().count(), 4);
+    }
+
+    #[test]
+    fn decisions() {
+        struct Decide(Function, InlineDecision);
+
+        impl InlineOracle for Decide {
+            fn callee(&self, _: &ExternalName) -> Option<&Function> {
+                Some(&self.0)
+            }
+
+            fn decide(&self, _: &Function, site: &CallSite, _: &Function) -> InlineDecision {
+                assert_eq!(site.cost, 3);
+                assert_eq!(site.depth, 1);
+                self.1
+            }
+        }
+
+        assert_eq!(inline_cost(&callee()), 3);
+        let small = flags("2", "1", "0");
+        let large = flags("3", "1", "0");
+
+        let mut func = caller();
+        let oracle = Decide(callee(), InlineDecision::Heuristic);
+        assert_eq!(inline_calls(&mut func, &small, &oracle), 0);
+        assert_eq!(inline_calls(&mut func, &large, &oracle), 1);
+
+        let mut func = caller();
+        let oracle = Decide(callee(), InlineDecision::Always);
+        assert_eq!(inline_calls(&mut func, &small, &oracle), 1);
+
+        let mut func = caller();
+        let oracle = Decide(callee(), InlineDecision::Never);
+        assert_eq!(inline_calls(&mut func, &large, &oracle), 0);
+    }
+
+    #[test]
+    fn depth() {
+        // A recursive function calling itself until it runs out of inlining depth.
+        let mut rec = Function::with_name_signature(ExternalName::testcase("pick"), signature());
+        let sigref = rec.import_signature(signature());
+        let fnref = rec.import_function(ExtFuncData {
+            name: ExternalName::testcase("pick"),
+            signature: sigref,
+            colocated: false,
+        });
+        let ebb0 = rec.dfg.make_ebb();
+        let a = rec.dfg.append_ebb_param(ebb0, I32);
+        let b = rec.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut rec);
+            pos.insert_ebb(ebb0);
+            let call = pos.ins().call(fnref, &[b, a]);
+            let v = pos.func.dfg.first_result(call);
+            pos.ins().return_(&[v]);
+        }
+
+        let oracle = Oracle(rec);
+        let mut func = caller();
+        assert_eq!(inline_calls(&mut func, &flags("20", "3", "0"), &oracle), 3);
+        verify_function(&func, &Flags::new(&settings::builder())).unwrap();
+
+        // Only the call from the last inlined body remains.
+        let calls = func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode().is_call())
+            .count();
+        assert_eq!(calls, 1);
+
+        let mut func = caller();
+        assert_eq!(inline_calls(&mut func, &flags("20", "0", "0"), &oracle), 0);
+    }
+
+    #[test]
+    fn loop_bonus() {
+        // A caller with one call before a loop and one call in the loop.
+        let mut func = caller();
+        let fnref = func.dfg.ext_funcs.keys().next().unwrap();
+        let ebb1 = func.dfg.make_ebb();
+        let v1 = func.dfg.append_ebb_param(ebb1, I32);
+        {
+            let ebb0 = func.layout.entry_block().unwrap();
+            let ret = func.layout.last_inst(ebb0).unwrap();
+            let arg = func.dfg.inst_args(ret)[0];
+            func.dfg.replace(ret).jump(ebb1, &[arg]);
+            func.layout.append_ebb(ebb1);
+            let mut pos = FuncCursor::new(&mut func).at_bottom(ebb1);
+            let call = pos.ins().call(fnref, &[v1, v1]);
+            let v2 = pos.func.dfg.first_result(call);
+            pos.ins().brnz(v2, ebb1, &[v2]);
+            pos.ins().return_(&[v2]);
+        }
+
+        let oracle = Oracle(callee());
+        let mut copy = func.clone();
+        assert_eq!(inline_calls(&mut copy, &flags("2", "1", "0"), &oracle), 0);
+        let mut copy = func.clone();
+        assert_eq!(inline_calls(&mut copy, &flags("2", "1", "1"), &oracle), 1);
+        verify_function(&copy, &Flags::new(&settings::builder())).unwrap();
+
+        // The call in the loop was the one inlined.
+        let entry = copy.layout.entry_block().unwrap();
+        assert!(copy.layout.ebb_insts(entry).any(|inst| {
+            copy.dfg[inst].opcode().is_call()
+        }));
+    }
+
+    #[test]
+    fn profile() {
+        let mut callee = callee();
+        let callee_entry = callee.layout.entry_block().unwrap();
+        let brz = callee.layout.first_inst(callee_entry).unwrap();
+        let ebb1 = callee.layout.next_ebb(callee_entry).unwrap();
+        callee.profile.set_ebb_count(callee_entry, 5);
+        callee.profile.set_branch_count(brz, 2);
+        callee.profile.set_ebb_count(ebb1, 2);
+        let oracle = Oracle(callee);
+
+        // The call runs every time the caller runs, so it gets the bonus.
+        let mut func = caller();
+        let entry = func.layout.entry_block().unwrap();
+        func.profile.set_ebb_count(entry, 10);
+        assert_eq!(inline_calls(&mut func, &flags("2", "1", "1"), &oracle), 1);
+        verify_function(&func, &Flags::new(&settings::builder())).unwrap();
+
+        // The callee counts are doubled, and the caller continues with its own count.
+        let ebbs: Vec<_> = func.layout.ebbs().collect();
+        assert_eq!(ebbs.len(), 4);
+        let counts: Vec<_> = ebbs.iter().map(|&ebb| func.profile.ebb_count(ebb)).collect();
+        assert_eq!(counts, [Some(10), Some(10), Some(4), Some(10)]);
+        let brz = func.layout.first_inst(ebbs[1]).unwrap();
+        assert_eq!(func.profile.branch_count(brz), Some(4));
+
+        // The call never runs, so it isn't inlined even though it fits in the budget.
+        let mut func = caller();
+        let entry = func.layout.entry_block().unwrap();
+        let call = func.layout
+            .ebb_insts(entry)
+            .find(|&inst| func.dfg[inst].opcode().is_call())
+            .unwrap();
+        let cold = func.dfg.make_ebb();
+        func.layout.split_ebb(cold, call);
+        FuncCursor::new(&mut func).at_bottom(entry).ins().jump(cold, &[]);
+        func.profile.set_ebb_count(entry, 10);
+        func.profile.set_ebb_count(cold, 0);
+        assert_eq!(inline_calls(&mut func, &flags("20", "1", "0"), &oracle), 0);
+    }
+}
@@ -0,0 +1,231 @@
+//! Interprocedural facts.
+//!
+//! Cretonne compiles one function at a time, so it can't discover facts that hold across function
+//! boundaries on its own. A frontend with whole-program knowledge can record such facts in a
+//! `FactTable`, keyed by the external names of the declared functions. The facts can then be used
+//! to specialize a function before it is compiled:
+//!
+//! - `specialize_call_sites` replaces the results of calls to functions with known-constant return
+//!   values with constants.
+//! - `specialize_params` replaces the parameters of the function being compiled with constants
+//!   when all of its callers are known to pass the same value.
+//!
+//! Both utilities operate on the signatures as declared by the frontend, so they must run before
+//! legalization.
+
+use cursor::{Cursor, FuncCursor};
+use ir::immediates::Imm64;
+use ir::{ExternalName, Function, InstBuilder, InstructionData, Type};
+use std::collections::HashMap;
+use std::vec::Vec;
+
+/// Facts known about a single function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FunctionFacts {
+    /// Known constant value of each return value, or `None` if nothing is known.
+    pub const_returns: Vec<Option<Imm64>>,
+
+    /// Known constant value passed for each parameter by all callers, or `None`.
+    pub const_params: Vec<Option<Imm64>>,
+}
+
+impl FunctionFacts {
+    /// Create an empty set of facts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that return value number `idx` is always `value`.
+    pub fn set_const_return(&mut self, idx: usize, value: Imm64) {
+        set_fact(&mut self.const_returns, idx, value)
+    }
+
+    /// Record that all callers pass `value` as parameter number `idx`.
+    pub fn set_const_param(&mut self, idx: usize, value: Imm64) {
+        set_fact(&mut self.const_params, idx, value)
+    }
+
+    /// Get the known constant value of return value number `idx`.
+    pub fn const_return(&self, idx: usize) -> Option<Imm64> {
+        self.const_returns.get(idx).and_then(|&v| v)
+    }
+
+    /// Get the known constant value of parameter number `idx`.
+    pub fn const_param(&self, idx: usize) -> Option<Imm64> {
+        self.const_params.get(idx).and_then(|&v| v)
+    }
+}
+
+fn set_fact(facts: &mut Vec<Option<Imm64>>, idx: usize, value: Imm64) {
+    if facts.len() <= idx {
+        facts.resize(idx + 1, None);
+    }
+    facts[idx] = Some(value);
+}
+
+/// Table of facts about the functions declared by a frontend.
+#[derive(Clone, Debug, Default)]
+pub struct FactTable {
+    facts: HashMap<ExternalName, FunctionFacts>,
+}
+
+impl FactTable {
+    /// Create a new empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove all facts from the table.
+    pub fn clear(&mut self) {
+        self.facts.clear()
+    }
+
+    /// Get the facts recorded for `name`, if any.
+    pub fn get(&self, name: &ExternalName) -> Option<&FunctionFacts> {
+        self.facts.get(name)
+    }
+
+    /// Get a mutable reference to the facts for `name`, creating an empty entry if needed.
+    pub fn entry(&mut self, name: ExternalName) -> &mut FunctionFacts {
+        self.facts.entry(name).or_insert_with(FunctionFacts::new)
+    }
+}
+
+/// Replace the results of calls with known-constant return values by constants.
+///
+/// The call instructions are preserved since the callees may have side effects. Returns the
+/// number of call results that were replaced.
+pub fn specialize_call_sites(func: &mut Function, table: &FactTable) -> usize {
+    let mut replaced = 0;
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            let facts = match pos.func.dfg[inst] {
+                InstructionData::Call { func_ref, .. } => {
+                    match table.get(&pos.func.dfg.ext_funcs[func_ref].name) {
+                        Some(facts) => facts,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            let results = pos.func.dfg.inst_results(inst).to_vec();
+            for (idx, result) in results.into_iter().enumerate() {
+                let imm = match facts.const_return(idx) {
+                    Some(imm) => imm,
+                    None => continue,
+                };
+                let ty = pos.func.dfg.value_type(result);
+                if !ty.is_int() {
+                    continue;
+                }
+
+                // Give the call a fresh result and redefine the old one as a constant so all its
+                // uses see the constant.
+                pos.func.dfg.replace_result(result, ty);
+                pos.goto_after_inst(inst);
+                pos.ins().with_result(result).iconst(ty, imm);
+                pos.goto_inst(inst);
+                replaced += 1;
+            }
+        }
+    }
+    replaced
+}
+
+/// Replace the parameters of `func` that are known to be constant by constants.
+///
+/// The function signature is not changed. Returns the number of parameters that were replaced.
+pub fn specialize_params(func: &mut Function, facts: &FunctionFacts) -> usize {
+    let entry = match func.layout.entry_block() {
+        Some(ebb) => ebb,
+        None => return 0,
+    };
+
+    let mut replaced = 0;
+    let params = func.dfg.ebb_params(entry).to_vec();
+    let mut pos = FuncCursor::new(func);
+    pos.goto_first_insertion_point(entry);
+    for (idx, param) in params.into_iter().enumerate() {
+        let imm = match facts.const_param(idx) {
+            Some(imm) => imm,
+            None => continue,
+        };
+        let ty: Type = pos.func.dfg.value_type(param);
+        if !ty.is_int() {
+            continue;
+        }
+
+        // Keep a dummy parameter in place so the EBB still matches the signature.
+        pos.func.dfg.replace_ebb_param(param, ty);
+        pos.ins().with_result(param).iconst(ty, imm);
+        replaced += 1;
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::I32;
+    use ir::{AbiParam, CallConv, ExtFuncData, Signature};
+    use std::string::ToString;
+
+    #[test]
+    fn call_sites() {
+        let mut func = Function::new();
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I32));
+        let sigref = func.import_signature(sig);
+        let callee = ExternalName::testcase("callee");
+        let fnref = func.import_function(ExtFuncData {
+            name: callee.clone(),
+            signature: sigref,
+            colocated: false,
+        });
+
+        let ebb0 = func.dfg.make_ebb();
+        let v0;
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let call = pos.ins().call(fnref, &[]);
+            v0 = pos.func.dfg.first_result(call);
+            pos.ins().return_(&[v0]);
+        }
+
+        let mut table = FactTable::new();
+        assert_eq!(specialize_call_sites(&mut func, &table), 0);
+
+        table.entry(callee).set_const_return(0, Imm64::new(42));
+        assert_eq!(specialize_call_sites(&mut func, &table), 1);
+        let def = func.dfg.value_def(v0).unwrap_inst();
+        assert_eq!(func.dfg.display_inst(def, None).to_string(), "v0 = iconst.i32 42");
+    }
+
+    #[test]
+    fn params() {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.params.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let v1 = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v2 = pos.ins().iadd(v0, v1);
+            pos.ins().return_(&[v2]);
+        }
+
+        let mut facts = FunctionFacts::new();
+        facts.set_const_param(1, Imm64::new(7));
+        assert_eq!(specialize_params(&mut func, &facts), 1);
+        assert_eq!(func.dfg.ebb_params(ebb0).len(), 2);
+        assert_eq!(func.dfg.ebb_params(ebb0)[0], v0);
+        let def = func.dfg.value_def(v1).unwrap_inst();
+        assert_eq!(func.layout.first_inst(ebb0), Some(def));
+        assert_eq!(func.dfg.display_inst(def, None).to_string(), "v1 = iconst.i32 7");
+    }
+}
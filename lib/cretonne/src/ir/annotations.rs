@@ -0,0 +1,205 @@
+//! Annotations on instructions, EBBs, and functions.
+//!
+//! Frontends can attach facts that Cretonne doesn't interpret to the entities of a function, like
+//! "this load is a null check", and read them back in their custom passes. An annotation is a
+//! name made of ASCII letters, digits, and underscores. In the textual IL format, the annotations
+//! are written as a comment starting with `@`-prefixed names after the entity:
+//!
+//! ```text
+//! function %f(i64) { ; @hot
+//! ebb0(v0: i64): ; @entry
+//!     v1 = load.i32 v0 ; @null_check
+//! ```
+//!
+//! The annotations of an instruction stay with its `Inst` entity, so they are preserved when a
+//! pass rewrites the instruction in place. The instructions created by the passes have no
+//! annotations, except for the copies made by loop unrolling, loop peeling, and inlining, which
+//! get the annotations of the original instructions. A pass can drop the annotations explicitly
+//! with `AnnotationTable::remove_inst()`.
+
+use ir::{Ebb, Inst};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::string::String;
+use std::vec::Vec;
+
+/// A single annotation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Annotation(String);
+
+impl Annotation {
+    /// Create an annotation named `name`.
+    ///
+    /// Returns `None` if `name` is empty or contains characters other than ASCII letters, digits,
+    /// and underscores, which couldn't be read back from the textual IL format.
+    pub fn new(name: &str) -> Option<Self> {
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            Some(Annotation(name.into()))
+        } else {
+            None
+        }
+    }
+
+    /// Get the name of this annotation.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}", self.0)
+    }
+}
+
+/// The annotations of an entity, in the order they were added.
+pub type AnnotationList = Vec<Annotation>;
+
+/// Side table of the annotations attached to the entities of a function.
+///
+/// Few entities are annotated, so the table only has entries for those, rather than an
+/// `EntityMap` entry for every instruction.
+#[derive(Clone, Debug)]
+pub struct AnnotationTable {
+    function: AnnotationList,
+    ebbs: BTreeMap<Ebb, AnnotationList>,
+    insts: BTreeMap<Inst, AnnotationList>,
+}
+
+/// Add `annotation` to `list` if it isn't already there.
+fn add(list: &mut AnnotationList, annotation: Annotation) {
+    if !list.contains(&annotation) {
+        list.push(annotation);
+    }
+}
+
+/// Does `list` contain the annotation `name`?
+fn contains(list: &[Annotation], name: &str) -> bool {
+    list.iter().any(|a| a.name() == name)
+}
+
+impl AnnotationTable {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self {
+            function: AnnotationList::new(),
+            ebbs: BTreeMap::new(),
+            insts: BTreeMap::new(),
+        }
+    }
+
+    /// Remove all the annotations.
+    pub fn clear(&mut self) {
+        self.function.clear();
+        self.ebbs.clear();
+        self.insts.clear();
+    }
+
+    /// Annotate the function with `annotation`.
+    pub fn annotate_function(&mut self, annotation: Annotation) {
+        add(&mut self.function, annotation)
+    }
+
+    /// Annotate `ebb` with `annotation`.
+    pub fn annotate_ebb(&mut self, ebb: Ebb, annotation: Annotation) {
+        add(self.ebbs.entry(ebb).or_insert_with(AnnotationList::new), annotation)
+    }
+
+    /// Annotate `inst` with `annotation`.
+    pub fn annotate_inst(&mut self, inst: Inst, annotation: Annotation) {
+        add(self.insts.entry(inst).or_insert_with(AnnotationList::new), annotation)
+    }
+
+    /// Get the annotations of the function.
+    pub fn function(&self) -> &[Annotation] {
+        &self.function
+    }
+
+    /// Get the annotations of `ebb`.
+    pub fn ebb(&self, ebb: Ebb) -> &[Annotation] {
+        self.ebbs.get(&ebb).map_or(&[], |list| list)
+    }
+
+    /// Get the annotations of `inst`.
+    pub fn inst(&self, inst: Inst) -> &[Annotation] {
+        self.insts.get(&inst).map_or(&[], |list| list)
+    }
+
+    /// Is `inst` annotated with `name`?
+    pub fn inst_has(&self, inst: Inst, name: &str) -> bool {
+        contains(self.inst(inst), name)
+    }
+
+    /// Is `ebb` annotated with `name`?
+    pub fn ebb_has(&self, ebb: Ebb, name: &str) -> bool {
+        contains(self.ebb(ebb), name)
+    }
+
+    /// Remove the annotations of `inst`, and return them.
+    pub fn remove_inst(&mut self, inst: Inst) -> AnnotationList {
+        self.insts.remove(&inst).unwrap_or_default()
+    }
+
+    /// Give `to` the annotations of `from`, in addition to its own.
+    pub fn copy_inst(&mut self, from: Inst, to: Inst) {
+        if from == to {
+            return;
+        }
+        for annotation in self.inst(from).to_vec() {
+            self.annotate_inst(to, annotation);
+        }
+    }
+}
+
+/// Get the annotations at the start of the comment `text`, which includes the leading `;`.
+///
+/// The annotations are the `@`-prefixed words at the start of the comment, and the rest of the
+/// comment is ignored. Returns `None` if a word starting with `@` isn't a valid annotation.
+pub fn parse_annotations(text: &str) -> Option<AnnotationList> {
+    let mut list = AnnotationList::new();
+    for word in text.trim_left_matches(';').split_whitespace() {
+        if !word.starts_with('@') {
+            break;
+        }
+        add(&mut list, Annotation::new(&word[1..])?);
+    }
+    Some(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::EntityRef;
+    use std::string::ToString;
+
+    #[test]
+    fn annotations() {
+        assert_eq!(Annotation::new(""), None);
+        assert_eq!(Annotation::new("null check"), None);
+        let null_check = Annotation::new("null_check").unwrap();
+        assert_eq!(null_check.to_string(), "@null_check");
+
+        let mut table = AnnotationTable::new();
+        let i0 = Inst::new(0);
+        let i1 = Inst::new(1);
+        table.annotate_inst(i0, null_check.clone());
+        table.annotate_inst(i0, null_check.clone());
+        assert_eq!(table.inst(i0), &[null_check.clone()]);
+        assert!(table.inst_has(i0, "null_check"));
+        assert!(table.inst(Inst::new(100)).is_empty());
+
+        table.copy_inst(i0, i1);
+        assert!(table.inst_has(i1, "null_check"));
+        assert_eq!(table.remove_inst(i0), [null_check]);
+        assert!(!table.inst_has(i0, "null_check"));
+    }
+
+    #[test]
+    fn parse() {
+        let list = parse_annotations("; @hot @null_check ; from Legalization").unwrap();
+        let names: Vec<&str> = list.iter().map(Annotation::name).collect();
+        assert_eq!(names, ["hot", "null_check"]);
+        assert_eq!(parse_annotations("; check: @foo"), Some(AnnotationList::new()));
+        assert_eq!(parse_annotations("; @bad-name"), None);
+    }
+}
@@ -0,0 +1,221 @@
+//! Immediate operands of the atomic memory instructions and compiler barriers.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// A memory ordering constraint on an atomic memory operation.
+///
+/// The orderings have the same meaning as in the C++11 memory model.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum AtomicOrdering {
+    /// Only the atomicity of the operation is guaranteed.
+    Relaxed,
+
+    /// Later memory accesses can't be moved before the operation.
+    Acquire,
+
+    /// Earlier memory accesses can't be moved after the operation.
+    Release,
+
+    /// Both `Acquire` and `Release`.
+    AcqRel,
+
+    /// Like `AcqRel`, and all `SeqCst` operations have a single total order.
+    SeqCst,
+}
+
+impl AtomicOrdering {
+    /// Does this ordering have the acquire semantics for loads?
+    pub fn is_acquire(self) -> bool {
+        match self {
+            AtomicOrdering::Acquire | AtomicOrdering::AcqRel | AtomicOrdering::SeqCst => true,
+            AtomicOrdering::Relaxed | AtomicOrdering::Release => false,
+        }
+    }
+
+    /// Does this ordering have the release semantics for stores?
+    pub fn is_release(self) -> bool {
+        match self {
+            AtomicOrdering::Release | AtomicOrdering::AcqRel | AtomicOrdering::SeqCst => true,
+            AtomicOrdering::Relaxed | AtomicOrdering::Acquire => false,
+        }
+    }
+}
+
+impl Display for AtomicOrdering {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use self::AtomicOrdering::*;
+        f.write_str(match *self {
+            Relaxed => "relaxed",
+            Acquire => "acquire",
+            Release => "release",
+            AcqRel => "acq_rel",
+            SeqCst => "seq_cst",
+        })
+    }
+}
+
+impl FromStr for AtomicOrdering {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::AtomicOrdering::*;
+        match s {
+            "relaxed" => Ok(Relaxed),
+            "acquire" => Ok(Acquire),
+            "release" => Ok(Release),
+            "acq_rel" => Ok(AcqRel),
+            "seq_cst" => Ok(SeqCst),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The operation performed by an `atomic_rmw` instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum AtomicRmwOp {
+    /// Wrapping addition.
+    Add,
+
+    /// Wrapping subtraction.
+    Sub,
+
+    /// Bitwise and.
+    And,
+
+    /// Bitwise or.
+    Or,
+
+    /// Bitwise xor.
+    Xor,
+
+    /// Replace the value in memory.
+    Xchg,
+}
+
+impl Display for AtomicRmwOp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use self::AtomicRmwOp::*;
+        f.write_str(match *self {
+            Add => "add",
+            Sub => "sub",
+            And => "and",
+            Or => "or",
+            Xor => "xor",
+            Xchg => "xchg",
+        })
+    }
+}
+
+impl FromStr for AtomicRmwOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::AtomicRmwOp::*;
+        match s {
+            "add" => Ok(Add),
+            "sub" => Ok(Sub),
+            "and" => Ok(And),
+            "or" => Ok(Or),
+            "xor" => Ok(Xor),
+            "xchg" => Ok(Xchg),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The memory accesses that a `barrier` instruction keeps in place.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum BarrierKind {
+    /// Loads can't be moved across the barrier.
+    Loads,
+
+    /// Stores can't be moved across the barrier.
+    Stores,
+
+    /// No memory accesses can be moved across the barrier.
+    All,
+}
+
+impl BarrierKind {
+    /// Does this barrier keep loads in place?
+    pub fn orders_loads(self) -> bool {
+        self != BarrierKind::Stores
+    }
+
+    /// Does this barrier keep stores in place?
+    pub fn orders_stores(self) -> bool {
+        self != BarrierKind::Loads
+    }
+}
+
+impl Display for BarrierKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BarrierKind::Loads => "loads",
+            BarrierKind::Stores => "stores",
+            BarrierKind::All => "all",
+        })
+    }
+}
+
+impl FromStr for BarrierKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loads" => Ok(BarrierKind::Loads),
+            "stores" => Ok(BarrierKind::Stores),
+            "all" => Ok(BarrierKind::All),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        use super::AtomicOrdering::*;
+        for &o in &[Relaxed, Acquire, Release, AcqRel, SeqCst] {
+            assert_eq!(o.to_string().parse(), Ok(o));
+        }
+        assert_eq!(AcqRel.to_string(), "acq_rel");
+        assert_eq!("acqrel".parse::<AtomicOrdering>(), Err(()));
+
+        for &op in &[
+            AtomicRmwOp::Add,
+            AtomicRmwOp::Sub,
+            AtomicRmwOp::And,
+            AtomicRmwOp::Or,
+            AtomicRmwOp::Xor,
+            AtomicRmwOp::Xchg,
+        ]
+        {
+            assert_eq!(op.to_string().parse(), Ok(op));
+        }
+        assert_eq!("nand".parse::<AtomicRmwOp>(), Err(()));
+
+        for &kind in &[BarrierKind::Loads, BarrierKind::Stores, BarrierKind::All] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+        assert_eq!("load".parse::<BarrierKind>(), Err(()));
+    }
+
+    #[test]
+    fn semantics() {
+        assert!(AtomicOrdering::SeqCst.is_acquire());
+        assert!(AtomicOrdering::SeqCst.is_release());
+        assert!(AtomicOrdering::Acquire.is_acquire());
+        assert!(!AtomicOrdering::Acquire.is_release());
+        assert!(!AtomicOrdering::Relaxed.is_acquire());
+        assert!(!AtomicOrdering::Relaxed.is_release());
+        assert!(BarrierKind::All.orders_loads());
+        assert!(BarrierKind::All.orders_stores());
+        assert!(!BarrierKind::Stores.orders_loads());
+        assert!(!BarrierKind::Loads.orders_stores());
+    }
+}
@@ -0,0 +1,129 @@
+//! Constant pool entries.
+//!
+//! Constants that are too large or too expensive to materialize with immediate operands can be
+//! placed in the function's constant pool and loaded with the `const_load` instruction. Each
+//! entry is referenced by an `ir::Constant` entity, and the pool is emitted after the code of the
+//! function.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::vec::Vec;
+
+/// The contents of a constant pool entry.
+///
+/// The bytes are stored in little-endian order, and they are reversed when the constant is emitted
+/// for a big-endian target. In the textual IL, a constant is written as a hexadecimal number with
+/// two digits for every byte, so the number of digits determines the size of the constant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ConstantData {
+    bytes: Vec<u8>,
+}
+
+impl ConstantData {
+    /// Create a constant from its bytes in little-endian order.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        debug_assert!(!bytes.is_empty(), "Empty constant");
+        Self { bytes }
+    }
+
+    /// Create a constant of `size` bytes from the low bits of `bits`.
+    pub fn from_bits(bits: u64, size: usize) -> Self {
+        debug_assert!(size > 0 && size <= 8, "Bad constant size {}", size);
+        Self::new((0..size).map(|i| (bits >> (8 * i)) as u8).collect())
+    }
+
+    /// Get the bytes of the constant in little-endian order.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Get the size of the constant in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Get the alignment of the constant in the pool.
+    ///
+    /// This is the size rounded up to a power of two, but at most 16 bytes.
+    pub fn alignment(&self) -> u32 {
+        (self.len() as u32).next_power_of_two().min(16)
+    }
+}
+
+impl Display for ConstantData {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Write the most significant byte first, grouping the digits four by four like `Imm64`.
+        write!(f, "0x")?;
+        let digits = 2 * self.len();
+        for (i, byte) in self.bytes.iter().rev().enumerate() {
+            write!(f, "{:02x}", byte)?;
+            let left = digits - 2 * (i + 1);
+            if left > 0 && left % 4 == 0 {
+                write!(f, "_")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ConstantData {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        if !s.starts_with("0x") {
+            return Err("constant must be a hexadecimal number");
+        }
+        let mut digits = Vec::new();
+        for ch in s[2..].chars() {
+            match ch.to_digit(16) {
+                Some(d) => digits.push(d as u8),
+                None if ch == '_' => {}
+                None => return Err("invalid character in hexadecimal constant"),
+            }
+        }
+        if digits.is_empty() || digits.len() % 2 != 0 {
+            return Err("constant needs two hexadecimal digits per byte");
+        }
+        Ok(Self::new(
+            digits.chunks(2).rev().map(|d| (d[0] << 4) | d[1]).collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        assert_eq!(ConstantData::from_bits(0x12, 1).to_string(), "0x12");
+        assert_eq!(ConstantData::from_bits(0x1234, 2).to_string(), "0x1234");
+        assert_eq!(ConstantData::from_bits(0x12345, 3).to_string(), "0x01_2345");
+        assert_eq!(
+            ConstantData::from_bits(0x3ff0_0000_0000_0000, 8).to_string(),
+            "0x3ff0_0000_0000_0000"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let c: ConstantData = "0x3ff0_0000_0000_0000".parse().unwrap();
+        assert_eq!(c.bytes(), &[0, 0, 0, 0, 0, 0, 0xf0, 0x3f]);
+        assert_eq!(c.alignment(), 8);
+
+        let c: ConstantData = "0x0102".parse().unwrap();
+        assert_eq!(c.bytes(), &[2, 1]);
+
+        let c: ConstantData = "0x000102030405060708090a0b0c0d0e0f".parse().unwrap();
+        assert_eq!(c.len(), 16);
+        assert_eq!(c.bytes()[0], 0x0f);
+        assert_eq!(c.alignment(), 16);
+        assert_eq!(c.to_string(), "0x0001_0203_0405_0607_0809_0a0b_0c0d_0e0f");
+
+        assert!("12".parse::<ConstantData>().is_err());
+        assert!("0x".parse::<ConstantData>().is_err());
+        assert!("0x123".parse::<ConstantData>().is_err());
+        assert!("0x12g4".parse::<ConstantData>().is_err());
+    }
+}
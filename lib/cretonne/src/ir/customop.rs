@@ -0,0 +1,62 @@
+//! Opcodes of the embedder-defined operations.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// The opcode of a `custom` instruction.
+///
+/// Custom opcodes are numbered in a range reserved for the embedder, separate from the built-in
+/// `Opcode` values. Cretonne doesn't know what they compute; the legalizer replaces each `custom`
+/// instruction by the expansion registered for its opcode in a `CustomOpcodes` registry.
+///
+/// Custom opcodes are written as `op` followed by their number, like `op3`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
+pub struct CustomOpcode(u16);
+
+impl CustomOpcode {
+    /// Create a custom opcode from its number.
+    pub fn new(number: u16) -> Self {
+        CustomOpcode(number)
+    }
+
+    /// Get the number of this custom opcode.
+    pub fn number(self) -> u16 {
+        self.0
+    }
+}
+
+impl Display for CustomOpcode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "op{}", self.0)
+    }
+}
+
+impl FromStr for CustomOpcode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with("op") || s.len() > 3 && s[2..].starts_with('0') {
+            return Err(());
+        }
+        s[2..].parse().map(CustomOpcode).map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        for &n in &[0, 7, 65535] {
+            let op = CustomOpcode::new(n);
+            assert_eq!(op.to_string().parse(), Ok(op));
+        }
+        assert_eq!(CustomOpcode::new(12).to_string(), "op12");
+        assert_eq!("op".parse::<CustomOpcode>(), Err(()));
+        assert_eq!("op01".parse::<CustomOpcode>(), Err(()));
+        assert_eq!("op65536".parse::<CustomOpcode>(), Err(()));
+        assert_eq!("user1".parse::<CustomOpcode>(), Err(()));
+    }
+}
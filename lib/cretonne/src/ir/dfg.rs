@@ -7,7 +7,8 @@ use ir::builder::ReplaceBuilder;
 use ir::extfunc::ExtFuncData;
 use ir::instructions::{InstructionData, CallInfo, BranchInfo};
 use ir::types;
-use ir::{Ebb, Inst, Value, Type, SigRef, Signature, FuncRef, ValueList, ValueListPool};
+use ir::{Ebb, Inst, Value, Type, SigRef, Signature, FuncRef, ValueList, ValueListPool,
+         MemoryUsage};
 use packed_option::ReservedValue;
 use write::write_operands;
 use std::fmt;
@@ -15,6 +16,7 @@ use std::iter;
 use std::mem;
 use std::ops::{Index, IndexMut};
 use std::u16;
+use std::vec::Vec;
 
 /// A data flow graph defines all instructions and extended basic blocks in a function as well as
 /// the data flow dependencies between them. The DFG also tracks values which can be either
@@ -58,6 +60,12 @@ pub struct DataFlowGraph {
     /// well as the external function references.
     pub signatures: PrimaryMap<SigRef, Signature>,
 
+    /// Signatures before the legalizer changed them to return values in memory.
+    ///
+    /// Calls with these signatures pass the address of a stack slot as the `StructReturn`
+    /// argument, and load their results from the stack slot. See `Function::old_signature`.
+    pub old_signatures: EntityMap<SigRef, Option<Signature>>,
+
     /// External function references. These are functions that can be called directly.
     pub ext_funcs: PrimaryMap<FuncRef, ExtFuncData>,
 }
@@ -72,6 +80,7 @@ impl DataFlowGraph {
             value_lists: ValueListPool::new(),
             values: PrimaryMap::new(),
             signatures: PrimaryMap::new(),
+            old_signatures: EntityMap::new(),
             ext_funcs: PrimaryMap::new(),
         }
     }
@@ -84,9 +93,53 @@ impl DataFlowGraph {
         self.value_lists.clear();
         self.values.clear();
         self.signatures.clear();
+        self.old_signatures.clear();
         self.ext_funcs.clear();
     }
 
+    /// Get the memory allocated for the tables of this data flow graph.
+    ///
+    /// Only the `insts`, `values`, `value_lists`, and `other` fields of the result are set.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            insts: self.insts.heap_size() + self.results.heap_size(),
+            values: self.values.heap_size(),
+            value_lists: self.value_lists.heap_size(),
+            other: self.ebbs.heap_size() + self.signatures.heap_size() +
+                self.ext_funcs.heap_size(),
+            ..MemoryUsage::default()
+        }
+    }
+
+    /// Compact the `value_lists` pool, releasing the memory of the lists freed by the passes.
+    ///
+    /// This invalidates the value lists that aren't referenced by the instructions and EBBs of
+    /// this data flow graph, like the ones returned by `detach_results()`.
+    pub fn compact_value_lists(&mut self) {
+        let mut lists = Vec::new();
+        for inst in self.insts.keys() {
+            lists.push(self.insts[inst].take_value_list().unwrap_or_default());
+            lists.push(self.results[inst].take());
+        }
+        for ebb in self.ebbs.keys() {
+            lists.push(self.ebbs[ebb].params.take());
+        }
+        self.value_lists.compact(lists.iter_mut());
+
+        let mut lists = lists.into_iter();
+        for inst in self.insts.keys() {
+            let args = lists.next().unwrap();
+            // Only the instruction formats with a value list gave one to compact.
+            if self.insts[inst].take_value_list().is_some() {
+                self.insts[inst].put_value_list(args);
+            }
+            self.results[inst] = lists.next().unwrap();
+        }
+        for ebb in self.ebbs.keys() {
+            self.ebbs[ebb].params = lists.next().unwrap();
+        }
+    }
+
     /// Get the total number of instructions created in this function, whether they are currently
     /// inserted in the layout or not.
     ///
@@ -242,6 +295,18 @@ impl DataFlowGraph {
         self.values[dest] = ValueData::Alias { ty, original };
     }
 
+    /// Create a placeholder value of type `ty` for a forward reference.
+    ///
+    /// The placeholder can be used as an instruction argument before the value it stands for has
+    /// been created. It must then be turned into an alias of the real value with
+    /// `change_to_alias()` before the placeholder's definition is queried.
+    pub fn make_placeholder_value(&mut self, ty: Type) -> Value {
+        self.make_value(ValueData::Alias {
+            ty,
+            original: Value::reserved_value(),
+        })
+    }
+
     /// Replace the results of one instruction with aliases to the results of another.
     ///
     /// Change all the results of `dest_inst` to behave as aliases of
@@ -439,7 +504,7 @@ impl DataFlowGraph {
         self.results[inst].clear(&mut self.value_lists);
 
         // Get the call signature if this is a function call.
-        if let Some(sig) = self.call_signature(inst) {
+        if let Some(sig) = self.call_results_signature(inst) {
             // Create result values corresponding to the call return types.
             debug_assert_eq!(self.insts[inst].opcode().constraints().fixed_results(), 0);
             let num_results = self.signatures[sig].returns.len();
@@ -595,6 +660,18 @@ impl DataFlowGraph {
         }
     }
 
+    /// Get the signature whose return values become the results of the call instruction `inst`.
+    ///
+    /// Returns `None` if `inst` is not a call instruction, or if it is a tail call. A tail call
+    /// returns directly to the caller of the current function, so it has no results.
+    pub fn call_results_signature(&self, inst: Inst) -> Option<SigRef> {
+        if self.insts[inst].opcode().is_terminator() {
+            None
+        } else {
+            self.call_signature(inst)
+        }
+    }
+
     /// Check if `inst` is a branch.
     pub fn analyze_branch(&self, inst: Inst) -> BranchInfo {
         self.insts[inst].analyze_branch(&self.value_lists)
@@ -621,7 +698,7 @@ impl DataFlowGraph {
         }
 
         // Not a fixed result, try to extract a return type from the call signature.
-        self.call_signature(inst).and_then(|sigref| {
+        self.call_results_signature(inst).and_then(|sigref| {
             self.signatures[sigref]
                 .returns
                 .get(result_idx - fixed_results)
@@ -881,7 +958,7 @@ impl DataFlowGraph {
         reuse: &[Value],
     ) -> usize {
         // Get the call signature if this is a function call.
-        if let Some(sig) = self.call_signature(inst) {
+        if let Some(sig) = self.call_results_signature(inst) {
             assert_eq!(self.insts[inst].opcode().constraints().fixed_results(), 0);
             for res_idx in 0..self.signatures[sig].returns.len() {
                 let ty = self.signatures[sig].returns[res_idx].value_type;
@@ -1134,4 +1211,68 @@ mod tests {
         // This does not see through copies.
         assert_eq!(pos.func.dfg.resolve_aliases(c3), c3);
     }
+
+    #[test]
+    fn compact_value_lists() {
+        use ir::InstBuilder;
+
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let v0 = pos.ins().iconst(types::I32, 1);
+        let (v1, c) = pos.ins().iadd_cout(v0, v0);
+        let jump = pos.ins().jump(ebb1, &[v0, v1, v0]);
+        pos.insert_ebb(ebb1);
+        let p0 = pos.func.dfg.append_ebb_param(ebb1, types::I32);
+        let p1 = pos.func.dfg.append_ebb_param(ebb1, types::I32);
+        let p2 = pos.func.dfg.append_ebb_param(ebb1, types::I32);
+        pos.ins().return_(&[]);
+
+        // Growing the parameter list of `ebb1` reallocates it, freeing its first block. The
+        // results of `iadd` are leaked.
+        let p3 = pos.func.dfg.append_ebb_param(ebb1, types::I32);
+        assert!(pos.func.dfg.value_lists.stats().free > 0);
+        let iadd = pos.func.dfg.value_def(c).unwrap_inst();
+        pos.func.dfg.detach_results(iadd);
+        pos.func.dfg.attach_result(iadd, v1);
+
+        pos.func.dfg.compact_value_lists();
+        let dfg = &pos.func.dfg;
+        assert_eq!(dfg.value_lists.stats().free, 0);
+        assert_eq!(dfg.inst_args(jump), &[v0, v1, v0]);
+        assert_eq!(dfg.inst_results(iadd), &[v1]);
+        assert_eq!(dfg.ebb_params(ebb1), &[p0, p1, p2, p3]);
+        assert!(dfg.ebb_params(ebb0).is_empty());
+        // The results of `iconst` and `iadd`, the jump arguments, and the parameters of `ebb1`
+        // remain.
+        assert_eq!(dfg.value_lists.stats().allocated, 4 + 4 + 4 + 8);
+    }
+
+    #[test]
+    fn memory_usage() {
+        use ir::InstBuilder;
+        use std::mem;
+        use super::ValueData;
+        // There is an entry in the value table for every value.
+        assert_eq!(mem::size_of::<ValueData>(), 8);
+
+        let mut func = Function::new();
+        assert_eq!(func.memory_usage().total(), 0);
+        let ebb0 = func.dfg.make_ebb();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let mut v = pos.func.dfg.append_ebb_param(ebb0, types::I32);
+        for _ in 0..100 {
+            v = pos.ins().iadd_imm(v, 1);
+        }
+        let usage = pos.func.memory_usage();
+        assert!(usage.insts >= 100 * mem::size_of::<InstructionData>());
+        assert!(usage.values >= 101 * mem::size_of::<ValueData>());
+        assert!(usage.layout > 0);
+        assert_eq!(usage.encodings, 0);
+        assert_eq!(usage.total(), usage.insts + usage.values + usage.value_lists + usage.layout +
+                   usage.srclocs + usage.other);
+    }
 }
@@ -24,6 +24,7 @@ use std::u32;
 
 /// An opaque reference to an extended basic block in a function.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct Ebb(u32);
 entity_impl!(Ebb, "ebb");
 
@@ -38,6 +39,7 @@ impl Ebb {
 
 /// An opaque reference to an SSA value.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct Value(u32);
 entity_impl!(Value, "v");
 
@@ -57,11 +59,43 @@ impl Value {
 
 /// An opaque reference to an instruction in a function.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct Inst(u32);
 entity_impl!(Inst, "inst");
 
+/// A label attached to SSA values by a frontend.
+///
+/// Value labels typically identify source-level variables. Multiple SSA values can carry the
+/// same label, and the code generator reports the machine code ranges where a labeled value is
+/// available along with its location. The label numbers are chosen by the frontend.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct ValueLabel(u32);
+entity_impl!(ValueLabel, "vl");
+
+impl ValueLabel {
+    /// Create a new value label from its number.
+    pub fn with_number(n: u32) -> Option<ValueLabel> {
+        if n < u32::MAX {
+            Some(ValueLabel(n))
+        } else {
+            None
+        }
+    }
+}
+
+/// A source file referenced by source locations.
+///
+/// The files are listed in the function's `SourceFiles` table along with the file and line of
+/// each source location that has one.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct FileId(u32);
+entity_impl!(FileId, "file");
+
 /// An opaque reference to a stack slot.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct StackSlot(u32);
 entity_impl!(StackSlot, "ss");
 
@@ -80,6 +114,7 @@ impl StackSlot {
 
 /// An opaque reference to a global variable.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct GlobalVar(u32);
 entity_impl!(GlobalVar, "gv");
 
@@ -98,6 +133,7 @@ impl GlobalVar {
 
 /// An opaque reference to a jump table.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct JumpTable(u32);
 entity_impl!(JumpTable, "jt");
 
@@ -116,6 +152,7 @@ impl JumpTable {
 
 /// A reference to an external function.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct FuncRef(u32);
 entity_impl!(FuncRef, "fn");
 
@@ -130,6 +167,7 @@ impl FuncRef {
 
 /// A reference to a function signature.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct SigRef(u32);
 entity_impl!(SigRef, "sig");
 
@@ -144,6 +182,7 @@ impl SigRef {
 
 /// A reference to a heap.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub struct Heap(u32);
 entity_impl!(Heap, "heap");
 
@@ -156,6 +195,21 @@ impl Heap {
     }
 }
 
+/// A reference to a constant in the function's constant pool.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct Constant(u32);
+entity_impl!(Constant, "const");
+
+impl Constant {
+    /// Create a new constant reference from its number.
+    ///
+    /// This method is for use by the parser.
+    pub fn with_number(n: u32) -> Option<Constant> {
+        if n < u32::MAX { Some(Constant(n)) } else { None }
+    }
+}
+
 /// A reference to any of the entities defined in this module.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum AnyEntity {
@@ -179,6 +233,8 @@ pub enum AnyEntity {
     SigRef(SigRef),
     /// A heap.
     Heap(Heap),
+    /// A constant pool entry.
+    Constant(Constant),
 }
 
 impl fmt::Display for AnyEntity {
@@ -194,6 +250,7 @@ impl fmt::Display for AnyEntity {
             AnyEntity::FuncRef(r) => r.fmt(f),
             AnyEntity::SigRef(r) => r.fmt(f),
             AnyEntity::Heap(r) => r.fmt(f),
+            AnyEntity::Constant(r) => r.fmt(f),
         }
     }
 }
@@ -258,6 +315,12 @@ impl From<Heap> for AnyEntity {
     }
 }
 
+impl From<Constant> for AnyEntity {
+    fn from(r: Constant) -> AnyEntity {
+        AnyEntity::Constant(r)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
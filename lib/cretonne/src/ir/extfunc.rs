@@ -35,6 +35,14 @@ pub struct Signature {
     /// This can be computed from the legalized `params` array as the maximum (offset plus
     /// byte size) of the `ArgumentLoc::Stack(offset)` argument.
     pub argument_bytes: Option<u32>,
+
+    /// The number of fixed parameters of a variadic function, or `None` if the function isn't
+    /// variadic.
+    ///
+    /// A call to a variadic function uses a signature listing the types of the actual arguments.
+    /// The parameters after the fixed ones are passed as variadic arguments, which some ABIs treat
+    /// differently. In the text format, a `...` marker follows the fixed parameters.
+    pub fixed_params: Option<usize>,
 }
 
 impl Signature {
@@ -45,6 +53,7 @@ impl Signature {
             returns: Vec::new(),
             call_conv,
             argument_bytes: None,
+            fixed_params: None,
         }
     }
 
@@ -54,6 +63,12 @@ impl Signature {
         self.returns.clear();
         self.call_conv = call_conv;
         self.argument_bytes = None;
+        self.fixed_params = None;
+    }
+
+    /// Is this the signature of a variadic function?
+    pub fn is_variadic(&self) -> bool {
+        self.fixed_params.is_some()
     }
 
     /// Compute the size of the stack arguments and mark signature as legalized.
@@ -82,6 +97,12 @@ impl Signature {
     pub fn special_param_index(&self, purpose: ArgumentPurpose) -> Option<usize> {
         self.params.iter().rposition(|arg| arg.purpose == purpose)
     }
+
+    /// Does this signature take a `StructReturn` pointer to the memory receiving its return
+    /// values?
+    pub fn uses_struct_return_param(&self) -> bool {
+        self.special_param_index(ArgumentPurpose::StructReturn).is_some()
+    }
 }
 
 /// Wrapper type capable of displaying a `Signature` with correct register names.
@@ -103,7 +124,17 @@ fn write_list(f: &mut fmt::Formatter, args: &[AbiParam], regs: Option<&RegInfo>)
 impl<'a> fmt::Display for DisplaySignature<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "(")?;
-        write_list(f, &self.0.params, self.1)?;
+        match self.0.fixed_params {
+            Some(n) => {
+                let (fixed, variadic) = self.0.params.split_at(n);
+                write_list(f, fixed, self.1)?;
+                write!(f, "{}...", if fixed.is_empty() { "" } else { ", " })?;
+                for arg in variadic {
+                    write!(f, ", {}", arg.display(self.1))?;
+                }
+            }
+            None => write_list(f, &self.0.params, self.1)?,
+        }
         write!(f, ")")?;
         if !self.0.returns.is_empty() {
             write!(f, " -> ")?;
@@ -291,10 +322,26 @@ pub enum ArgumentPurpose {
     /// This is a special-purpose argument used to identify the calling convention expected by the
     /// caller in an indirect call. The callee can verify that the expected signature ID matches.
     SignatureId,
+
+    /// The number of vector registers used by the arguments of a variadic call.
+    ///
+    /// The x86-64 System V ABI passes an upper bound of the number of vector registers holding
+    /// arguments in `%al` when calling a variadic function. The legalizer appends this argument to
+    /// the signatures of such calls and computes the value at each call site.
+    VarArgCount,
 }
 
 /// Text format names of the `ArgumentPurpose` variants.
-static PURPOSE_NAMES: [&str; 7] = ["normal", "sret", "link", "fp", "csr", "vmctx", "sigid"];
+static PURPOSE_NAMES: [&str; 8] = [
+    "normal",
+    "sret",
+    "link",
+    "fp",
+    "csr",
+    "vmctx",
+    "sigid",
+    "vacount",
+];
 
 impl fmt::Display for ArgumentPurpose {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -313,6 +360,7 @@ impl FromStr for ArgumentPurpose {
             "csr" => Ok(ArgumentPurpose::CalleeSaved),
             "vmctx" => Ok(ArgumentPurpose::VMContext),
             "sigid" => Ok(ArgumentPurpose::SignatureId),
+            "vacount" => Ok(ArgumentPurpose::VarArgCount),
             _ => Err(()),
         }
     }
@@ -327,10 +375,17 @@ pub struct ExtFuncData {
     pub name: ExternalName,
     /// Call signature of function.
     pub signature: SigRef,
+    /// Will this function be defined nearby, such that it will always be a certain distance away,
+    /// after linking? If so, references to it can avoid going through a GOT or PLT. Note that
+    /// symbols meant to be preemptible cannot be considered colocated.
+    pub colocated: bool,
 }
 
 impl fmt::Display for ExtFuncData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.colocated {
+            write!(f, "colocated ")?;
+        }
         write!(f, "{} {}", self.signature, self.name)
     }
 }
@@ -350,15 +405,29 @@ pub enum CallConv {
 
     /// A JIT-compiled WebAssembly function in the SpiderMonkey VM.
     SpiderWASM,
+
+    /// The Windows x64 calling convention.
+    ///
+    /// This is the native calling convention on 64-bit Windows. It can be used on other operating
+    /// systems to call Windows code.
+    WindowsFastcall,
+
+    /// A calling convention defined by the embedder.
+    ///
+    /// The number identifies a `CallConvDescriptor` registered with the `isa::Builder` of the
+    /// target ISA. See `isa::Builder::register_call_conv()`.
+    Custom(u8),
 }
 
 impl fmt::Display for CallConv {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::CallConv::*;
-        f.write_str(match *self {
-            Native => "native",
-            SpiderWASM => "spiderwasm",
-        })
+        match *self {
+            Native => f.write_str("native"),
+            SpiderWASM => f.write_str("spiderwasm"),
+            WindowsFastcall => f.write_str("windows_fastcall"),
+            Custom(n) => write!(f, "custom{}", n),
+        }
     }
 }
 
@@ -370,6 +439,8 @@ impl FromStr for CallConv {
         match s {
             "native" => Ok(Native),
             "spiderwasm" => Ok(SpiderWASM),
+            "windows_fastcall" => Ok(WindowsFastcall),
+            _ if s.starts_with("custom") => s[6..].parse().map(Custom).map_err(|_| ()),
             _ => Err(()),
         }
     }
@@ -401,6 +472,8 @@ mod tests {
             ArgumentPurpose::FramePointer,
             ArgumentPurpose::CalleeSaved,
             ArgumentPurpose::VMContext,
+            ArgumentPurpose::SignatureId,
+            ArgumentPurpose::VarArgCount,
         ];
         for (&e, &n) in all_purpose.iter().zip(PURPOSE_NAMES.iter()) {
             assert_eq!(e.to_string(), n);
@@ -410,9 +483,18 @@ mod tests {
 
     #[test]
     fn call_conv() {
-        for &cc in &[CallConv::Native, CallConv::SpiderWASM] {
+        for &cc in &[
+            CallConv::Native,
+            CallConv::SpiderWASM,
+            CallConv::WindowsFastcall,
+            CallConv::Custom(3),
+        ]
+        {
             assert_eq!(Ok(cc), cc.to_string().parse())
         }
+        assert_eq!(CallConv::Custom(12).to_string(), "custom12");
+        assert_eq!("custom".parse::<CallConv>(), Err(()));
+        assert_eq!("custom256".parse::<CallConv>(), Err(()));
     }
 
     #[test]
@@ -445,4 +527,19 @@ mod tests {
             "(i32 [24], i32x4 [8]) -> f32, b8 spiderwasm"
         );
     }
+
+    #[test]
+    fn variadic_signatures() {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.fixed_params = Some(0);
+        assert!(sig.is_variadic());
+        assert_eq!(sig.to_string(), "(...) native");
+        sig.params.push(AbiParam::new(I32));
+        assert_eq!(sig.to_string(), "(..., i32) native");
+        sig.fixed_params = Some(1);
+        sig.params.push(AbiParam::new(F32));
+        assert_eq!(sig.to_string(), "(i32, ..., f32) native");
+        sig.clear(CallConv::Native);
+        assert!(!sig.is_variadic());
+    }
 }
@@ -8,6 +8,7 @@ use ir::LibCall;
 use std::cmp;
 use std::fmt::{self, Write};
 use std::str::FromStr;
+use std::vec::Vec;
 
 const TESTCASE_NAME_LENGTH: usize = 16;
 
@@ -21,7 +22,7 @@ const TESTCASE_NAME_LENGTH: usize = 16;
 /// External names can also serve as a primitive testing and debugging tool.
 /// In particular, many `.cton` test files use function names to identify
 /// functions.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ExternalName {
     /// A name in a user-defined symbol table. Cretonne does not interpret
     /// these numbers in any way.
@@ -41,6 +42,9 @@ pub enum ExternalName {
     },
     /// A well-known runtime library function.
     LibCall(LibCall),
+    /// A symbol name of any length, like the names produced by the `mangle` module. Cretonne does
+    /// not interpret the bytes of the name.
+    Name(Vec<u8>),
 }
 
 impl ExternalName {
@@ -82,6 +86,18 @@ impl ExternalName {
             index: index,
         }
     }
+
+    /// Create a new external name from the bytes of a symbol name.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use cretonne::ir::ExternalName;
+    /// let name = ExternalName::name("_ZN4core3mem4swapE");
+    /// assert_eq!(name.to_string(), "%_ZN4core3mem4swapE");
+    /// ```
+    pub fn name<T: AsRef<[u8]>>(v: T) -> ExternalName {
+        ExternalName::Name(v.as_ref().to_vec())
+    }
 }
 
 impl Default for ExternalName {
@@ -102,6 +118,13 @@ impl fmt::Display for ExternalName {
                 Ok(())
             }
             ExternalName::LibCall(lc) => write!(f, "%{}", lc),
+            ExternalName::Name(ref bytes) => {
+                f.write_char('%')?;
+                for &byte in bytes {
+                    f.write_char(byte as char)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -110,9 +133,11 @@ impl FromStr for ExternalName {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Try to parse as a libcall name, otherwise it's a test case.
+        // Try to parse as a libcall name, otherwise it's a test case, or a symbol name if it's too
+        // long for a test case.
         match s.parse() {
             Ok(lc) => Ok(ExternalName::LibCall(lc)),
+            Err(_) if s.len() > TESTCASE_NAME_LENGTH => Ok(ExternalName::name(s)),
             Err(_) => Ok(ExternalName::testcase(s.as_bytes())),
         }
     }
@@ -160,5 +185,14 @@ mod tests {
             ExternalName::LibCall(LibCall::FloorF32).to_string(),
             "%FloorF32"
         );
+        assert_eq!("abc".parse(), Ok(ExternalName::testcase("abc")));
+        assert_eq!(
+            "longname123456789".parse(),
+            Ok(ExternalName::name("longname123456789"))
+        );
+        assert_eq!(
+            ExternalName::name("longname123456789").to_string(),
+            "%longname123456789"
+        );
     }
 }
@@ -4,15 +4,51 @@
 //! instructions.
 
 use binemit::CodeOffset;
-use entity::{PrimaryMap, EntityMap};
+use entity::{PrimaryMap, EntityMap, EntitySet};
 use ir;
+use ir::instructions::BranchInfo;
 use ir::{ExternalName, CallConv, Signature, DataFlowGraph, Layout};
-use ir::{InstEncodings, ValueLocations, JumpTables, StackSlots, EbbOffsets, SourceLocs};
+use ir::{InstEncodings, ValueLocations, JumpTables, StackSlots, EbbOffsets, ConstantOffsets,
+         JumpTableOffsets, SourceLocs, SourceFiles, SourcePosition, Safepoints, ValueLabels,
+         InstOrigins, AnnotationTable};
 use ir::{Ebb, JumpTableData, JumpTable, StackSlotData, StackSlot, SigRef, ExtFuncData, FuncRef,
-         GlobalVarData, GlobalVar, HeapData, Heap};
+         GlobalVarData, GlobalVar, HeapData, Heap, ConstantData, Constant, Inst, ProfileData};
 use isa::{TargetIsa, EncInfo};
+use settings;
 use std::fmt;
-use write::write_function;
+use write::{write_function, write_function_with_annotations, Annotations};
+
+/// Memory allocated for the tables of a `Function`, in bytes.
+///
+/// See `Function::memory_usage()`. The sizes include the unused capacity of the tables, but not
+/// the memory owned by their entries, like the names of external functions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The instructions and their result lists in the data flow graph.
+    pub insts: usize,
+    /// The values in the data flow graph.
+    pub values: usize,
+    /// The pool of value lists used by the instructions and EBBs.
+    pub value_lists: usize,
+    /// The layout of EBBs and instructions.
+    pub layout: usize,
+    /// The instruction encodings.
+    pub encodings: usize,
+    /// The value locations.
+    pub locations: usize,
+    /// The source locations of the instructions.
+    pub srclocs: usize,
+    /// All the other tables.
+    pub other: usize,
+}
+
+impl MemoryUsage {
+    /// Get the total number of bytes.
+    pub fn total(&self) -> usize {
+        self.insts + self.values + self.value_lists + self.layout + self.encodings +
+            self.locations + self.srclocs + self.other
+    }
+}
 
 /// A function.
 ///
@@ -26,6 +62,13 @@ pub struct Function {
     /// Signature of this function.
     pub signature: Signature,
 
+    /// Signature of this function before the legalizer changed it to return values in memory.
+    ///
+    /// This is only set when the return values don't fit in the return registers of the calling
+    /// convention, and the legalized signature takes a `StructReturn` pointer instead. The
+    /// return instructions then store their arguments in the memory it points to.
+    pub old_signature: Option<Signature>,
+
     /// Stack slots allocated in this function.
     pub stack_slots: StackSlots,
 
@@ -35,9 +78,25 @@ pub struct Function {
     /// Heaps referenced.
     pub heaps: PrimaryMap<ir::Heap, ir::HeapData>,
 
+    /// Global variable whose address is the stack limit of this function.
+    ///
+    /// When set, the prologue traps with `TrapCode::StackOverflow` if the stack frame of the
+    /// function would extend below the limit. The limit is typically loaded from the VM context
+    /// with a `deref` global variable.
+    pub stack_limit: Option<ir::GlobalVar>,
+
+    /// Global variable whose address is the fuel counter of this function.
+    ///
+    /// When set, the loops and calls of the function consume fuel from the pointer-sized counter,
+    /// and trap with `TrapCode::OutOfFuel` when it runs out. See the `fuel` module.
+    pub fuel: Option<ir::GlobalVar>,
+
     /// Jump tables used in this function.
     pub jump_tables: JumpTables,
 
+    /// Constant pool entries used in this function.
+    pub constants: PrimaryMap<ir::Constant, ir::ConstantData>,
+
     /// Data flow graph containing the primary definition of all instructions, EBBs and values.
     pub dfg: DataFlowGraph,
 
@@ -58,11 +117,77 @@ pub struct Function {
     /// in the textual IL format.
     pub offsets: EbbOffsets,
 
+    /// Code offsets of the jump tables used by `jump_table_base` instructions, relative to the
+    /// start of the function.
+    ///
+    /// Like `offsets`, this is computed by `binemit::relax_branches`. The jump tables are placed
+    /// after the code of the function, and the other jump tables are not emitted. Their offset is
+    /// 0.
+    pub jump_table_offsets: JumpTableOffsets,
+
+    /// Code offsets of the constant pool entries, relative to the start of the function.
+    ///
+    /// Like `offsets`, this is computed by `binemit::relax_branches`. The constant pool is placed
+    /// after the code of the function.
+    pub constant_offsets: ConstantOffsets,
+
     /// Source locations.
     ///
     /// Track the original source location for each instruction. The source locations are not
     /// interpreted by Cretonne, only preserved.
     pub srclocs: SourceLocs,
+
+    /// Source files and the file and line positions of source locations.
+    ///
+    /// Frontends that need to distinguish source files map the source locations in `srclocs` to
+    /// positions in this table. It is not included in the textual IL format.
+    pub source_files: SourceFiles,
+
+    /// The compilation pass that created or last rewrote each instruction.
+    ///
+    /// This is only recorded by `Context::compile` when the `track_inst_origins` setting is
+    /// enabled. The origins are printed as comments, and they are not parsed from the textual IL
+    /// format.
+    pub inst_origins: InstOrigins,
+
+    /// Values holding references to garbage-collected objects.
+    ///
+    /// GC references must be pointer-sized integers. The register allocator keeps the references
+    /// that are live across a call in stack slots, and records them in `safepoints`.
+    pub gc_refs: EntitySet<ir::Value>,
+
+    /// The GC references that are live across each call instruction.
+    ///
+    /// This is computed by the register allocator when `gc_refs` is not empty, and it is used to
+    /// emit stack maps with `binemit::emit_stackmaps()`. It is not included in the textual IL
+    /// format.
+    pub safepoints: Safepoints,
+
+    /// Labels attached to values by the frontend.
+    ///
+    /// Value labels are not interpreted by Cretonne. After compilation, the machine code ranges
+    /// where each labeled value is available can be computed with
+    /// `Context::value_label_ranges()`.
+    pub value_labels: ValueLabels,
+
+    /// Annotations attached to the function, its EBBs, and its instructions by the frontend.
+    ///
+    /// Annotations are not interpreted by Cretonne. They are included in the textual IL format as
+    /// comments. See `AnnotationTable`.
+    pub annotations: AnnotationTable,
+
+    /// Execution counts of the EBBs and branches from a profile of the function.
+    ///
+    /// The counts guide the block frequency analysis, the layout of the EBBs, and the inliner.
+    /// They are not included in the textual IL format. See `ProfileData`.
+    pub profile: ProfileData,
+
+    /// Shared settings overriding the flags of the ISA when compiling this function.
+    ///
+    /// `Context::compile` uses the flags of the ISA with these values, so a hot function can be
+    /// recompiled with a higher `opt_level`. The overrides are not included in the textual IL
+    /// format.
+    pub settings: settings::Overrides,
 }
 
 impl Function {
@@ -71,32 +196,60 @@ impl Function {
         Self {
             name,
             signature: sig,
+            old_signature: None,
             stack_slots: StackSlots::new(),
             global_vars: PrimaryMap::new(),
             heaps: PrimaryMap::new(),
+            stack_limit: None,
+            fuel: None,
             jump_tables: PrimaryMap::new(),
+            constants: PrimaryMap::new(),
             dfg: DataFlowGraph::new(),
             layout: Layout::new(),
             encodings: EntityMap::new(),
             locations: EntityMap::new(),
             offsets: EntityMap::new(),
+            jump_table_offsets: EntityMap::new(),
+            constant_offsets: EntityMap::new(),
             srclocs: EntityMap::new(),
+            source_files: SourceFiles::new(),
+            inst_origins: EntityMap::new(),
+            gc_refs: EntitySet::new(),
+            safepoints: EntityMap::new(),
+            value_labels: EntityMap::new(),
+            annotations: AnnotationTable::new(),
+            profile: ProfileData::new(),
+            settings: settings::Overrides::new(),
         }
     }
 
     /// Clear all data structures in this function.
     pub fn clear(&mut self) {
         self.signature.clear(ir::CallConv::Native);
+        self.old_signature = None;
         self.stack_slots.clear();
         self.global_vars.clear();
         self.heaps.clear();
+        self.stack_limit = None;
+        self.fuel = None;
         self.jump_tables.clear();
+        self.constants.clear();
         self.dfg.clear();
         self.layout.clear();
         self.encodings.clear();
         self.locations.clear();
         self.offsets.clear();
+        self.jump_table_offsets.clear();
+        self.constant_offsets.clear();
         self.srclocs.clear();
+        self.source_files.clear();
+        self.inst_origins.clear();
+        self.gc_refs.clear();
+        self.safepoints.clear();
+        self.value_labels.clear();
+        self.annotations.clear();
+        self.profile.clear();
+        self.settings.clear();
     }
 
     /// Create a new empty, anonymous function with a native calling convention.
@@ -140,9 +293,67 @@ impl Function {
         self.heaps.push(data)
     }
 
+    /// Adds a constant to the constant pool, or reuses an existing entry with the same contents.
+    pub fn create_constant(&mut self, data: ConstantData) -> Constant {
+        match self.constants.keys().find(|&c| self.constants[c] == data) {
+            Some(c) => c,
+            None => self.constants.push(data),
+        }
+    }
+
+    /// Get the number of times `inst` ran according to the profile, if it is known.
+    ///
+    /// This is the count of its EBB minus the number of times the branches before it in the EBB
+    /// were taken.
+    pub fn profile_count(&self, inst: Inst) -> Option<u64> {
+        let ebb = self.layout.inst_ebb(inst)?;
+        let mut count = self.profile.ebb_count(ebb)?;
+        for prev in self.layout.ebb_insts(ebb).take_while(|&i| i != inst) {
+            match self.dfg.analyze_branch(prev) {
+                BranchInfo::NotABranch => {}
+                BranchInfo::SingleDest(_, _) => {
+                    count = count.saturating_sub(self.profile.branch_count(prev)?)
+                }
+                BranchInfo::Table(_) => return None,
+            }
+        }
+        Some(count)
+    }
+
+    /// Get the memory allocated for the tables of this function.
+    ///
+    /// The tables indexed by instructions and values dominate the memory usage of large functions.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let dfg = self.dfg.memory_usage();
+        let other = self.global_vars.heap_size() + self.heaps.heap_size() +
+            self.jump_tables.heap_size() + self.constants.heap_size() + self.offsets.heap_size() +
+            self.jump_table_offsets.heap_size() + self.constant_offsets.heap_size() +
+            self.inst_origins.heap_size() + self.gc_refs.heap_size() +
+            self.safepoints.heap_size() + self.value_labels.heap_size() +
+            self.profile.heap_size();
+        MemoryUsage {
+            layout: self.layout.heap_size(),
+            encodings: self.encodings.heap_size(),
+            locations: self.locations.heap_size(),
+            srclocs: self.srclocs.heap_size(),
+            other: dfg.other + other,
+            ..dfg
+        }
+    }
+
     /// Return an object that can display this function with correct ISA-specific annotations.
     pub fn display<'a, I: Into<Option<&'a TargetIsa>>>(&'a self, isa: I) -> DisplayFunction<'a> {
-        DisplayFunction(self, isa.into())
+        DisplayFunction(self, isa.into(), Annotations::default())
+    }
+
+    /// Return an object that can display this function with ISA-specific annotations and the
+    /// requested code size `annotations`.
+    pub fn display_with_annotations<'a>(
+        &'a self,
+        isa: &'a TargetIsa,
+        annotations: Annotations,
+    ) -> DisplayFunction<'a> {
+        DisplayFunction(self, Some(isa), annotations)
     }
 
     /// Find a presumed unique special-purpose function parameter value.
@@ -155,6 +366,31 @@ impl Function {
         })
     }
 
+    /// Mark `value` as a reference to a garbage-collected object.
+    ///
+    /// The register allocator will make sure the reference is in a stack slot whenever it is live
+    /// across a call, and it will appear in the call's stack map.
+    pub fn mark_gc_ref(&mut self, value: ir::Value) {
+        self.gc_refs.insert(value);
+    }
+
+    /// Is `value` a reference to a garbage-collected object?
+    ///
+    /// Aliases of GC references are also GC references.
+    pub fn is_gc_ref(&self, value: ir::Value) -> bool {
+        self.gc_refs.contains(value) || self.gc_refs.contains(self.dfg.resolve_aliases(value))
+    }
+
+    /// Get the source file and line of `inst`, if it is known.
+    pub fn source_position(&self, inst: ir::Inst) -> Option<SourcePosition> {
+        self.source_files.position(self.srclocs[inst])
+    }
+
+    /// Attach `label` to `value`.
+    pub fn set_value_label(&mut self, value: ir::Value, label: ir::ValueLabel) {
+        self.value_labels[value] = label.into();
+    }
+
     /// Get an iterator over the instructions in `ebb`, including offsets and encoded instruction
     /// sizes.
     ///
@@ -179,11 +415,11 @@ impl Function {
 }
 
 /// Wrapper type capable of displaying a `Function` with correct ISA annotations.
-pub struct DisplayFunction<'a>(&'a Function, Option<&'a TargetIsa>);
+pub struct DisplayFunction<'a>(&'a Function, Option<&'a TargetIsa>, Annotations);
 
 impl<'a> fmt::Display for DisplayFunction<'a> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write_function(fmt, self.0, self.1)
+        write_function_with_annotations(fmt, self.0, self.1, self.2)
     }
 }
 
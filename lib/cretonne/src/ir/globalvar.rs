@@ -3,6 +3,7 @@
 use ir::{ExternalName, GlobalVar};
 use ir::immediates::Offset32;
 use std::fmt;
+use std::str::FromStr;
 
 /// Information about a global variable declaration.
 #[derive(Clone)]
@@ -32,14 +33,36 @@ pub enum GlobalVarData {
     Sym {
         /// The symbolic name.
         name: ExternalName,
+
+        /// Will this variable be defined nearby, such that it will always be a certain distance
+        /// away, after linking? If so, references to it can avoid going through a GOT. Note that
+        /// symbols meant to be preemptible cannot be colocated.
+        colocated: bool,
+
+        /// Is the variable in read-only memory? This tells an object file writer which section
+        /// the symbol is in, it doesn't change the code generated for the function.
+        readonly: bool,
+    },
+
+    /// Variable is thread-local, identified by a symbolic name like `Sym`.
+    ///
+    /// Every thread has its own copy of the variable, and its address is computed using the
+    /// platform's thread-local storage access `model`.
+    TLS {
+        /// The symbolic name.
+        name: ExternalName,
+
+        /// The access model used to compute the address.
+        model: TLSModel,
     },
 }
 
 impl GlobalVarData {
-    /// Assume that `self` is an `GlobalVarData::Sym` and return its name.
+    /// Assume that `self` is an `GlobalVarData::Sym` or `GlobalVarData::TLS` and return its name.
     pub fn symbol_name(&self) -> &ExternalName {
         match *self {
-            GlobalVarData::Sym { ref name } => name,
+            GlobalVarData::Sym { ref name, .. } |
+            GlobalVarData::TLS { ref name, .. } => name,
             _ => panic!("only symbols have names"),
         }
     }
@@ -50,7 +73,87 @@ impl fmt::Display for GlobalVarData {
         match *self {
             GlobalVarData::VmCtx { offset } => write!(f, "vmctx{}", offset),
             GlobalVarData::Deref { base, offset } => write!(f, "deref({}){}", base, offset),
-            GlobalVarData::Sym { ref name } => write!(f, "globalsym {}", name),
+            GlobalVarData::Sym {
+                ref name,
+                colocated,
+                readonly,
+            } => {
+                write!(f, "globalsym ")?;
+                if colocated {
+                    write!(f, "colocated ")?;
+                }
+                if readonly {
+                    write!(f, "readonly ")?;
+                }
+                write!(f, "{}", name)
+            }
+            GlobalVarData::TLS { ref name, model } => write!(f, "tls {} {}", model, name),
+        }
+    }
+}
+
+/// Thread-local storage access models.
+///
+/// The access model determines the code sequence used to compute the address of a thread-local
+/// variable, and the relocations the linker must resolve. The names follow the ELF TLS
+/// specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TLSModel {
+    /// The variable may be defined in any module, including dynamically loaded ones. The address
+    /// is computed by calling `__tls_get_addr`.
+    GeneralDynamic,
+
+    /// The variable is defined in the executable or in a module loaded at startup. The address
+    /// is computed from the thread pointer and an offset loaded from the GOT.
+    InitialExec,
+}
+
+impl fmt::Display for TLSModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TLSModel::GeneralDynamic => "general_dynamic",
+            TLSModel::InitialExec => "initial_exec",
+        })
+    }
+}
+
+impl FromStr for TLSModel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "general_dynamic" => Ok(TLSModel::GeneralDynamic),
+            "initial_exec" => Ok(TLSModel::InitialExec),
+            _ => Err(()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        let gv = GlobalVarData::TLS {
+            name: ExternalName::user(0, 1),
+            model: TLSModel::InitialExec,
+        };
+        assert_eq!(gv.to_string(), "tls initial_exec u0:1");
+        let gv = GlobalVarData::Sym {
+            name: ExternalName::testcase("foo"),
+            colocated: true,
+            readonly: false,
+        };
+        assert_eq!(gv.to_string(), "globalsym colocated %foo");
+        let gv = GlobalVarData::Sym {
+            name: ExternalName::testcase("foo"),
+            colocated: true,
+            readonly: true,
+        };
+        assert_eq!(gv.to_string(), "globalsym colocated readonly %foo");
+        assert_eq!("general_dynamic".parse(), Ok(TLSModel::GeneralDynamic));
+        assert_eq!("local_exec".parse::<TLSModel>(), Err(()));
+    }
+}
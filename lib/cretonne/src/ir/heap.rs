@@ -19,6 +19,12 @@ pub struct HeapData {
 
     /// Heap style, with additional style-specific info.
     pub style: HeapStyle,
+
+    /// The heap base address and bound don't change while the function executes.
+    ///
+    /// The loads of the base address and bound global variables generated by `heap_addr` are
+    /// then marked `readonly`, `notrap`, and `speculatable`, so they can be hoisted out of loops.
+    pub readonly: bool,
 }
 
 /// Method for determining the base address of a heap.
@@ -65,6 +71,10 @@ impl fmt::Display for HeapData {
             HeapStyle::Dynamic { bound_gv } => write!(f, ", bound {}", bound_gv)?,
             HeapStyle::Static { bound } => write!(f, ", bound {}", bound)?,
         }
-        write!(f, ", guard {}", self.guard_size)
+        write!(f, ", guard {}", self.guard_size)?;
+        if self.readonly {
+            write!(f, ", readonly")?;
+        }
+        Ok(())
     }
 }
@@ -193,6 +193,8 @@ impl InstructionData {
                 ref args,
                 ..
             } => BranchInfo::SingleDest(destination, &args.as_slice(pool)[2..]),
+            // The arguments of an `invoke` are passed to the callee, not to the landing pad.
+            InstructionData::Invoke { destination, .. } => BranchInfo::SingleDest(destination, &[]),
             InstructionData::BranchTable { table, .. } => BranchInfo::Table(table),
             _ => {
                 debug_assert!(!self.opcode().is_branch());
@@ -211,7 +213,8 @@ impl InstructionData {
             InstructionData::Branch { destination, .. } |
             InstructionData::BranchInt { destination, .. } |
             InstructionData::BranchFloat { destination, .. } |
-            InstructionData::BranchIcmp { destination, .. } => Some(destination),
+            InstructionData::BranchIcmp { destination, .. } |
+            InstructionData::Invoke { destination, .. } => Some(destination),
             InstructionData::BranchTable { .. } => None,
             _ => {
                 debug_assert!(!self.opcode().is_branch());
@@ -230,7 +233,8 @@ impl InstructionData {
             InstructionData::Branch { ref mut destination, .. } |
             InstructionData::BranchInt { ref mut destination, .. } |
             InstructionData::BranchFloat { ref mut destination, .. } |
-            InstructionData::BranchIcmp { ref mut destination, .. } => Some(destination),
+            InstructionData::BranchIcmp { ref mut destination, .. } |
+            InstructionData::Invoke { ref mut destination, .. } => Some(destination),
             InstructionData::BranchTable { .. } => None,
             _ => {
                 debug_assert!(!self.opcode().is_branch());
@@ -239,17 +243,34 @@ impl InstructionData {
         }
     }
 
+    /// Get the trap code of a trap instruction.
+    ///
+    /// Instructions that can only trap implicitly, like loads and integer divisions, return
+    /// `None`.
+    pub fn trap_code(&self) -> Option<ir::TrapCode> {
+        match *self {
+            InstructionData::Trap { code, .. } |
+            InstructionData::CondTrap { code, .. } |
+            InstructionData::IntCondTrap { code, .. } |
+            InstructionData::FloatCondTrap { code, .. } |
+            InstructionData::BinaryTrap { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
     /// Return information about a call instruction.
     ///
     /// Any instruction that can call another function reveals its call signature here.
     pub fn analyze_call<'a>(&'a self, pool: &'a ValueListPool) -> CallInfo<'a> {
         match *self {
-            InstructionData::Call { func_ref, ref args, .. } => {
+            InstructionData::Call { func_ref, ref args, .. } |
+            InstructionData::Invoke { func_ref, ref args, .. } => {
                 CallInfo::Direct(func_ref, args.as_slice(pool))
             }
             InstructionData::IndirectCall { sig_ref, ref args, .. } => {
                 CallInfo::Indirect(sig_ref, &args.as_slice(pool)[1..])
             }
+            InstructionData::CallGlobalVar { func_ref, .. } => CallInfo::Direct(func_ref, &[]),
             _ => {
                 debug_assert!(!self.opcode().is_call());
                 CallInfo::NotACall
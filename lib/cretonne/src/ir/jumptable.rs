@@ -14,7 +14,7 @@ use std::vec::Vec;
 ///
 /// All jump tables use 0-based indexing and are expected to be densely populated. They don't need
 /// to be completely populated, though. Individual entries can be missing.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct JumpTableData {
     // Table entries, using `None` as a placeholder for missing entries.
     table: Vec<PackedOption<Ebb>>,
@@ -3,7 +3,7 @@
 //! The order of extended basic blocks in a function and the order of instructions in an EBB is
 //! determined by the `Layout` data structure defined in this module.
 
-use entity::EntityMap;
+use entity::{EntityMap, EntitySet};
 use ir::{Ebb, Inst};
 use ir::progpoint::{ProgramOrder, ExpandedProgramPoint};
 use packed_option::PackedOption;
@@ -39,6 +39,9 @@ pub struct Layout {
 
     // Last EBB in the layout order, or `None` when no EBBs have been laid out.
     last_ebb: Option<Ebb>,
+
+    // EBBs marked as cold. Few EBBs are cold, so this isn't part of `EbbNode`.
+    cold: EntitySet<Ebb>,
 }
 
 impl Layout {
@@ -49,6 +52,7 @@ impl Layout {
             insts: EntityMap::new(),
             first_ebb: None,
             last_ebb: None,
+            cold: EntitySet::new(),
         }
     }
 
@@ -58,6 +62,12 @@ impl Layout {
         self.insts.clear();
         self.first_ebb = None;
         self.last_ebb = None;
+        self.cold.clear();
+    }
+
+    /// Get the number of bytes allocated for the layout.
+    pub fn heap_size(&self) -> usize {
+        self.ebbs.heap_size() + self.insts.heap_size() + self.cold.heap_size()
     }
 }
 
@@ -455,6 +465,62 @@ impl Layout {
     pub fn next_ebb(&self, ebb: Ebb) -> Option<Ebb> {
         self.ebbs[ebb].next.expand()
     }
+
+    /// Move `ebb` and all of its instructions to the end of the layout.
+    pub fn move_ebb_to_end(&mut self, ebb: Ebb) {
+        debug_assert!(self.is_ebb_inserted(ebb), "EBB not in the layout");
+        if self.last_ebb == Some(ebb) {
+            return;
+        }
+
+        // Unlink `ebb` from its current position. It isn't the last EBB, so `next` exists.
+        let prev = self.ebbs[ebb].prev;
+        let next = self.ebbs[ebb].next.unwrap();
+        match prev.expand() {
+            None => self.first_ebb = Some(next),
+            Some(p) => self.ebbs[p].next = next.into(),
+        }
+        self.ebbs[next].prev = prev;
+
+        // Link it back in after the last EBB.
+        let last = self.last_ebb.expect("Layout has more than one EBB");
+        self.ebbs[last].next = ebb.into();
+        self.ebbs[ebb].prev = last.into();
+        self.ebbs[ebb].next = None.into();
+        self.last_ebb = Some(ebb);
+
+        // Nothing follows `ebb` now, so it can be renumbered with major strides.
+        let mut seq = self.last_ebb_seq(last) + MAJOR_STRIDE;
+        self.ebbs[ebb].seq = seq;
+        let mut next_inst = self.ebbs[ebb].first_inst.expand();
+        while let Some(inst) = next_inst {
+            seq += MAJOR_STRIDE;
+            self.insts[inst].seq = seq;
+            next_inst = self.insts[inst].next.expand();
+        }
+    }
+}
+
+/// Layout hints.
+///
+/// These hints don't affect the semantics of the program, but they are used when deciding the
+/// final order of the EBBs in the emitted code.
+impl Layout {
+    /// Is `ebb` marked as cold, i.e., rarely executed?
+    pub fn is_cold(&self, ebb: Ebb) -> bool {
+        self.cold.contains(ebb)
+    }
+
+    /// Mark `ebb` as cold or not.
+    ///
+    /// Cold EBBs are moved after all the other EBBs by branch relaxation.
+    pub fn set_cold(&mut self, ebb: Ebb, cold: bool) {
+        if cold {
+            self.cold.insert(ebb);
+        } else {
+            self.cold.remove(ebb);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -1088,6 +1154,40 @@ mod tests {
         assert_eq!(v1, [i2, i3]);
     }
 
+    #[test]
+    fn move_ebb_to_end() {
+        let mut layout = Layout::new();
+
+        let e0 = Ebb::new(0);
+        let e1 = Ebb::new(1);
+        let e2 = Ebb::new(2);
+
+        let i0 = Inst::new(0);
+        let i1 = Inst::new(1);
+        let i2 = Inst::new(2);
+
+        layout.append_ebb(e0);
+        layout.append_ebb(e1);
+        layout.append_ebb(e2);
+        layout.append_inst(i0, e0);
+        layout.append_inst(i1, e1);
+        layout.append_inst(i2, e2);
+
+        layout.move_ebb_to_end(e1);
+        verify(&mut layout, &[(e0, &[i0]), (e2, &[i2]), (e1, &[i1])]);
+
+        layout.move_ebb_to_end(e0);
+        verify(&mut layout, &[(e2, &[i2]), (e1, &[i1]), (e0, &[i0])]);
+        assert_eq!(layout.entry_block(), Some(e2));
+
+        layout.move_ebb_to_end(e0);
+        verify(&mut layout, &[(e2, &[i2]), (e1, &[i1]), (e0, &[i0])]);
+
+        assert!(!layout.is_cold(e1));
+        layout.set_cold(e1, true);
+        assert!(layout.is_cold(e1));
+    }
+
     #[test]
     fn split_ebb() {
         let mut layout = Layout::new();
@@ -1172,4 +1272,14 @@ mod tests {
         assert_eq!(layout.is_ebb_gap(i1, e1), false);
         assert_eq!(layout.is_ebb_gap(i2, e1), false);
     }
+
+    #[test]
+    fn node_sizes() {
+        use std::mem;
+        use super::{EbbNode, InstNode};
+        // The layout has a node for every instruction and EBB, so the nodes should stay small.
+        // Rarely used information like the `cold` flag goes in side tables.
+        assert_eq!(mem::size_of::<InstNode>(), 16);
+        assert_eq!(mem::size_of::<EbbNode>(), 20);
+    }
 }
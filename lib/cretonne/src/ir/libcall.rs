@@ -1,8 +1,10 @@
 //! Naming well-known routines in the runtime library.
 
-use ir::{types, Opcode, Type};
+use ir::{types, CallConv, ExternalName, GlobalVarData, Opcode, Type};
 use std::fmt;
 use std::str::FromStr;
+use std::string::String;
+use std::vec::Vec;
 
 /// The name of a runtime library routine.
 ///
@@ -12,7 +14,7 @@ use std::str::FromStr;
 /// convention in the embedding VM's runtime library.
 ///
 /// This list is likely to grow over time.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum LibCall {
     /// ceil.f32
     CeilF32,
@@ -30,9 +32,21 @@ pub enum LibCall {
     NearestF32,
     /// nearest.f64
     NearestF64,
+    /// fma.f32
+    FmaF32,
+    /// fma.f64
+    FmaF64,
+    /// `__tls_get_addr` for ELF general-dynamic TLS accesses
+    ElfTlsGetAddr,
+    /// Function entry hook, see the `enable_entry_exit_hooks` setting
+    EnterFunction,
+    /// Function exit hook, see the `enable_entry_exit_hooks` setting
+    ExitFunction,
+    /// Stack probe for large frames, see the `enable_probestack` setting
+    Probestack,
 }
 
-const NAME: [&str; 8] = [
+const NAME: [&str; 14] = [
     "CeilF32",
     "CeilF64",
     "FloorF32",
@@ -41,6 +55,47 @@ const NAME: [&str; 8] = [
     "TruncF64",
     "NearestF32",
     "NearestF64",
+    "FmaF32",
+    "FmaF64",
+    "ElfTlsGetAddr",
+    "EnterFunction",
+    "ExitFunction",
+    "Probestack",
+];
+
+const ALL: [LibCall; 14] = [
+    LibCall::CeilF32,
+    LibCall::CeilF64,
+    LibCall::FloorF32,
+    LibCall::FloorF64,
+    LibCall::TruncF32,
+    LibCall::TruncF64,
+    LibCall::NearestF32,
+    LibCall::NearestF64,
+    LibCall::FmaF32,
+    LibCall::FmaF64,
+    LibCall::ElfTlsGetAddr,
+    LibCall::EnterFunction,
+    LibCall::ExitFunction,
+    LibCall::Probestack,
+];
+
+/// Symbol names of the C library routines.
+const C_SYMBOL: [&str; 14] = [
+    "ceilf",
+    "ceil",
+    "floorf",
+    "floor",
+    "truncf",
+    "trunc",
+    "nearbyintf",
+    "nearbyint",
+    "fmaf",
+    "fma",
+    "__tls_get_addr",
+    "__cretonne_func_enter",
+    "__cretonne_func_exit",
+    "__chkstk",
 ];
 
 impl fmt::Display for LibCall {
@@ -62,6 +117,12 @@ impl FromStr for LibCall {
             "TruncF64" => Ok(LibCall::TruncF64),
             "NearestF32" => Ok(LibCall::NearestF32),
             "NearestF64" => Ok(LibCall::NearestF64),
+            "FmaF32" => Ok(LibCall::FmaF32),
+            "FmaF64" => Ok(LibCall::FmaF64),
+            "ElfTlsGetAddr" => Ok(LibCall::ElfTlsGetAddr),
+            "EnterFunction" => Ok(LibCall::EnterFunction),
+            "ExitFunction" => Ok(LibCall::ExitFunction),
+            "Probestack" => Ok(LibCall::Probestack),
             _ => Err(()),
         }
     }
@@ -80,6 +141,7 @@ impl LibCall {
                     Opcode::Floor => LibCall::FloorF32,
                     Opcode::Trunc => LibCall::TruncF32,
                     Opcode::Nearest => LibCall::NearestF32,
+                    Opcode::Fma => LibCall::FmaF32,
                     _ => return None,
                 }
             }
@@ -89,12 +151,134 @@ impl LibCall {
                     Opcode::Floor => LibCall::FloorF64,
                     Opcode::Trunc => LibCall::TruncF64,
                     Opcode::Nearest => LibCall::NearestF64,
+                    Opcode::Fma => LibCall::FmaF64,
                     _ => return None,
                 }
             }
             _ => return None,
         })
     }
+
+    /// Get all the library calls, in declaration order.
+    pub fn all() -> &'static [LibCall] {
+        &ALL
+    }
+
+    /// Get the default symbol name of the library routine for callers using `call_conv`.
+    ///
+    /// All the supported calling conventions currently use the C library names. Embedders with a
+    /// differently named runtime library can override them with `LibCallNames`.
+    pub fn symbol_name(self, call_conv: CallConv) -> &'static str {
+        match call_conv {
+            CallConv::Native |
+            CallConv::SpiderWASM |
+            CallConv::WindowsFastcall |
+            CallConv::Custom(_) => C_SYMBOL[self as usize],
+        }
+    }
+}
+
+/// A mapping from library calls to the symbol names of the runtime library routines.
+///
+/// The legalizer refers to library routines with `ExternalName::LibCall` names, which end up in
+/// the relocations of the generated code. An embedder can use a `LibCallNames` table to resolve
+/// them while linking, starting from the default names for a calling convention and overriding
+/// the names that its runtime library spells differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibCallNames {
+    names: Vec<String>,
+}
+
+impl LibCallNames {
+    /// Create a table of the default symbol names for `call_conv`.
+    pub fn new(call_conv: CallConv) -> Self {
+        Self::with_prefix(call_conv, "")
+    }
+
+    /// Create a table of the default symbol names for `call_conv`, all prefixed with `prefix`.
+    pub fn with_prefix(call_conv: CallConv, prefix: &str) -> Self {
+        Self {
+            names: ALL.iter()
+                .map(|lc| {
+                    let mut name = String::from(prefix);
+                    name.push_str(lc.symbol_name(call_conv));
+                    name
+                })
+                .collect(),
+        }
+    }
+
+    /// Get the symbol name of `libcall`.
+    pub fn get(&self, libcall: LibCall) -> &str {
+        &self.names[libcall as usize]
+    }
+
+    /// Override the symbol name of `libcall`.
+    pub fn set<S: Into<String>>(&mut self, libcall: LibCall, name: S) {
+        self.names[libcall as usize] = name.into();
+    }
+
+    /// Get the symbol name of the external name `name` if it is a library call.
+    pub fn resolve(&self, name: &ExternalName) -> Option<&str> {
+        match *name {
+            ExternalName::LibCall(lc) => Some(self.get(lc)),
+            _ => None,
+        }
+    }
+}
+
+/// How the generated code calls a library routine.
+#[derive(Clone)]
+pub enum LibCallLinkage {
+    /// Call the routine directly, with a relocation against the given name.
+    ///
+    /// By default, the routines are called directly by their `ExternalName::LibCall` names.
+    Direct(ExternalName),
+
+    /// Load the address of the routine from the global variable described by the data, and call
+    /// the loaded address.
+    ///
+    /// This lets a runtime route the library calls through a dispatch table, like a table in the
+    /// VM context. The global variable is created in each function calling the routine, so it
+    /// can't be a `Deref` variable which would refer to another global variable of the function.
+    Indirect(GlobalVarData),
+}
+
+/// The linkages of the library routines, set by the embedder with
+/// `isa::Builder::set_libcall_linkage()`.
+#[derive(Clone)]
+pub struct LibCallLinkages {
+    linkages: Vec<LibCallLinkage>,
+}
+
+impl LibCallLinkages {
+    /// Create a table calling all the routines directly by their `ExternalName::LibCall` names.
+    pub fn new() -> Self {
+        Self {
+            linkages: ALL.iter()
+                .map(|&lc| LibCallLinkage::Direct(ExternalName::LibCall(lc)))
+                .collect(),
+        }
+    }
+
+    /// Get the linkage of `libcall`.
+    pub fn get(&self, libcall: LibCall) -> &LibCallLinkage {
+        &self.linkages[libcall as usize]
+    }
+
+    /// Set the linkage of `libcall`.
+    pub fn set(&mut self, libcall: LibCall, linkage: LibCallLinkage) {
+        if let LibCallLinkage::Indirect(GlobalVarData::Deref { .. }) = linkage {
+            panic!("{} can't be called through a Deref global variable", libcall);
+        }
+        self.linkages[libcall as usize] = linkage;
+    }
+}
+
+impl Default for LibCallLinkages {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -112,4 +296,25 @@ mod test {
     fn parsing() {
         assert_eq!("FloorF32".parse(), Ok(LibCall::FloorF32));
     }
+
+    #[test]
+    fn names() {
+        for &lc in LibCall::all() {
+            assert_eq!(lc.to_string().parse(), Ok(lc));
+        }
+
+        let mut names = LibCallNames::with_prefix(CallConv::Native, "rt_");
+        assert_eq!(names.get(LibCall::FloorF64), "rt_floor");
+        names.set(LibCall::NearestF32, "rt_roundevenf");
+        assert_eq!(names.get(LibCall::NearestF32), "rt_roundevenf");
+        assert_eq!(
+            names.resolve(&ExternalName::LibCall(LibCall::ElfTlsGetAddr)),
+            Some("rt___tls_get_addr")
+        );
+        assert_eq!(names.resolve(&ExternalName::user(0, 1)), None);
+        assert_eq!(
+            LibCallNames::new(CallConv::SpiderWASM).get(LibCall::TruncF32),
+            "truncf"
+        );
+    }
 }
@@ -5,15 +5,19 @@ use std::fmt;
 enum FlagBit {
     Notrap,
     Aligned,
+    Readonly,
+    Speculatable,
+    Volatile,
 }
 
-const NAMES: [&str; 2] = ["notrap", "aligned"];
+const NAMES: [&str; 5] = ["notrap", "aligned", "readonly", "speculatable", "volatile"];
 
 /// Flags for memory operations like load/store.
 ///
 /// Each of these flags introduce a limited form of undefined behavior. The flags each enable
 /// certain optimizations that need to make additional assumptions. Generally, the semantics of a
-/// program does not change when a flag is removed, but adding a flag will.
+/// program does not change when a flag is removed, but adding a flag will. The exception is the
+/// `volatile` flag which disables optimizations instead.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct MemFlags {
     bits: u8,
@@ -79,6 +83,54 @@ impl MemFlags {
     pub fn set_aligned(&mut self) {
         self.set(FlagBit::Aligned)
     }
+
+    /// Test if the `readonly` flag is set.
+    ///
+    /// Loads with this flag read memory that is not written while the function executes, so they
+    /// always return the same value for the same address. Combined with `speculatable`, this
+    /// makes it possible to hoist the load out of a loop.
+    pub fn readonly(self) -> bool {
+        self.read(FlagBit::Readonly)
+    }
+
+    /// Set the `readonly` flag.
+    pub fn set_readonly(&mut self) {
+        self.set(FlagBit::Readonly)
+    }
+
+    /// Test if the `speculatable` flag is set.
+    ///
+    /// The `notrap` flag only promises that the memory is accessible when the load is executed
+    /// where it appears in the program, which may depend on a preceding bounds check. The
+    /// `speculatable` flag promises that the memory is always accessible, so the load can be
+    /// executed unconditionally or moved above the branches guarding it.
+    ///
+    /// Only loads can be speculatable, and the verifier requires their address to be derived from
+    /// the VM context or a global variable declared by the embedder.
+    pub fn speculatable(self) -> bool {
+        self.read(FlagBit::Speculatable)
+    }
+
+    /// Set the `speculatable` flag.
+    pub fn set_speculatable(&mut self) {
+        self.set(FlagBit::Speculatable)
+    }
+
+    /// Test if the `volatile` flag is set.
+    ///
+    /// Volatile accesses are observable side effects, e.g. because they access device memory or
+    /// memory shared with another process. They are never removed, duplicated, or reordered with
+    /// respect to other volatile accesses, and stored values are not forwarded to later loads.
+    ///
+    /// A volatile access can't also be `readonly` or `speculatable`.
+    pub fn volatile(self) -> bool {
+        self.read(FlagBit::Volatile)
+    }
+
+    /// Set the `volatile` flag.
+    pub fn set_volatile(&mut self) {
+        self.set(FlagBit::Volatile)
+    }
 }
 
 impl fmt::Display for MemFlags {
@@ -10,34 +10,45 @@ pub mod jumptable;
 pub mod dfg;
 pub mod layout;
 pub mod function;
+mod annotations;
+mod atomics;
 mod builder;
+mod constant;
+mod customop;
 mod extfunc;
 mod extname;
 mod globalvar;
 mod heap;
 mod libcall;
 mod memflags;
+mod profile;
 mod progpoint;
 mod sourceloc;
 mod trapcode;
 mod valueloc;
 
+pub use ir::annotations::{Annotation, AnnotationList, AnnotationTable, parse_annotations};
+pub use ir::atomics::{AtomicOrdering, AtomicRmwOp, BarrierKind};
 pub use ir::builder::{InstBuilder, InstBuilderBase, InstInserterBase, InsertBuilder};
+pub use ir::constant::ConstantData;
+pub use ir::customop::CustomOpcode;
 pub use ir::dfg::{DataFlowGraph, ValueDef};
-pub use ir::entities::{Ebb, Inst, Value, StackSlot, GlobalVar, JumpTable, FuncRef, SigRef, Heap};
+pub use ir::entities::{Ebb, Inst, Value, StackSlot, GlobalVar, JumpTable, FuncRef, SigRef, Heap,
+                       Constant, ValueLabel, FileId};
 pub use ir::extfunc::{Signature, CallConv, AbiParam, ArgumentExtension, ArgumentPurpose,
                       ExtFuncData};
 pub use ir::extname::ExternalName;
-pub use ir::function::Function;
-pub use ir::globalvar::GlobalVarData;
+pub use ir::function::{Function, MemoryUsage};
+pub use ir::globalvar::{GlobalVarData, TLSModel};
 pub use ir::heap::{HeapData, HeapStyle, HeapBase};
 pub use ir::instructions::{Opcode, InstructionData, VariableArgs, ValueList, ValueListPool};
 pub use ir::jumptable::JumpTableData;
 pub use ir::layout::Layout;
-pub use ir::libcall::LibCall;
+pub use ir::libcall::{LibCall, LibCallLinkage, LibCallLinkages, LibCallNames};
 pub use ir::memflags::MemFlags;
+pub use ir::profile::ProfileData;
 pub use ir::progpoint::{ProgramPoint, ProgramOrder, ExpandedProgramPoint};
-pub use ir::sourceloc::SourceLoc;
+pub use ir::sourceloc::{SourceLoc, SourcePosition, SourceFiles};
 pub use ir::stackslot::{StackSlots, StackSlotKind, StackSlotData};
 pub use ir::trapcode::TrapCode;
 pub use ir::types::Type;
@@ -45,6 +56,7 @@ pub use ir::valueloc::{ValueLoc, ArgumentLoc};
 
 use binemit;
 use entity::{PrimaryMap, EntityMap};
+use packed_option::PackedOption;
 use isa;
 
 /// Map of value locations.
@@ -59,5 +71,20 @@ pub type InstEncodings = EntityMap<Inst, isa::Encoding>;
 /// Code offsets for EBBs.
 pub type EbbOffsets = EntityMap<Ebb, binemit::CodeOffset>;
 
+/// Code offsets for jump tables.
+pub type JumpTableOffsets = EntityMap<JumpTable, binemit::CodeOffset>;
+
+/// Code offsets for constant pool entries.
+pub type ConstantOffsets = EntityMap<Constant, binemit::CodeOffset>;
+
 /// Source locations for instructions.
 pub type SourceLocs = EntityMap<Inst, SourceLoc>;
+
+/// Descriptions of the passes that created or last rewrote instructions.
+pub type InstOrigins = EntityMap<Inst, Option<&'static str>>;
+
+/// Labels attached to values.
+pub type ValueLabels = EntityMap<Value, PackedOption<ValueLabel>>;
+
+/// Live GC reference values at safepoint instructions.
+pub type Safepoints = EntityMap<Inst, ValueList>;
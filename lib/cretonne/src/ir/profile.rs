@@ -0,0 +1,131 @@
+//! Execution counts from a profile.
+//!
+//! A JIT compiler with several tiers can count how often the EBBs and branches of a function run
+//! in a lower tier, and attach the counts to the function it compiles in the next tier. Cretonne
+//! uses them instead of its static estimates:
+//!
+//! - The block frequency analysis derives the branch probabilities and the EBB frequencies from
+//!   the counts, so the register allocator keeps the values used in hot code in registers and
+//!   spills the values that are only used in code that never ran.
+//! - The EBBs that never ran are laid out after the other EBBs like cold EBBs.
+//! - The inliner gives a larger budget to the call sites that run at least once per call of the
+//!   function, and it doesn't inline the call sites that never ran.
+//!
+//! The counts are attached to entities, so they stay with the EBBs and instructions that the
+//! passes keep or rewrite in place. The EBBs and branches created by the passes have no counts,
+//! and the static estimates are used for them instead.
+
+use entity::EntityMap;
+use ir::{Ebb, Inst};
+
+/// Execution counts of the EBBs and conditional branches of a function.
+#[derive(Clone, Debug)]
+pub struct ProfileData {
+    ebbs: EntityMap<Ebb, Option<u64>>,
+    branches: EntityMap<Inst, Option<u64>>,
+
+    // Number of EBBs with a non-zero count.
+    executed: usize,
+}
+
+impl ProfileData {
+    /// Create an empty profile.
+    pub fn new() -> Self {
+        Self {
+            ebbs: EntityMap::new(),
+            branches: EntityMap::new(),
+            executed: 0,
+        }
+    }
+
+    /// Remove all the counts.
+    pub fn clear(&mut self) {
+        self.ebbs.clear();
+        self.branches.clear();
+        self.executed = 0;
+    }
+
+    /// Are there no counts at all?
+    pub fn is_empty(&self) -> bool {
+        self.ebbs.keys().all(|ebb| self.ebbs[ebb].is_none()) &&
+            self.branches.keys().all(|inst| self.branches[inst].is_none())
+    }
+
+    /// Set the number of times `ebb` was entered.
+    pub fn set_ebb_count(&mut self, ebb: Ebb, count: u64) {
+        if self.ebb_count(ebb).map_or(false, |old| old > 0) {
+            self.executed -= 1;
+        }
+        if count > 0 {
+            self.executed += 1;
+        }
+        self.ebbs[ebb] = Some(count);
+    }
+
+    /// Get the number of times `ebb` was entered, if it is known.
+    pub fn ebb_count(&self, ebb: Ebb) -> Option<u64> {
+        self.ebbs.get(ebb).and_then(|&count| count)
+    }
+
+    /// Set the number of times the conditional branch `inst` was taken.
+    ///
+    /// For an `invoke` instruction, this is the number of times the callee unwound to the landing
+    /// pad. Unconditional branches are always taken when they are reached, so they don't need a
+    /// count.
+    pub fn set_branch_count(&mut self, inst: Inst, taken: u64) {
+        self.branches[inst] = Some(taken);
+    }
+
+    /// Get the number of times the conditional branch `inst` was taken, if it is known.
+    pub fn branch_count(&self, inst: Inst) -> Option<u64> {
+        self.branches.get(inst).and_then(|&count| count)
+    }
+
+    /// Does the profile show that `ebb` never ran?
+    ///
+    /// This requires a count of 0 for `ebb`, and a non-zero count for another EBB. When all the
+    /// counts are 0, the function didn't run at all in the profile, which doesn't say anything
+    /// about its EBBs.
+    pub fn never_executed(&self, ebb: Ebb) -> bool {
+        self.executed > 0 && self.ebb_count(ebb) == Some(0)
+    }
+
+    /// Get the memory allocated for the counts.
+    pub fn heap_size(&self) -> usize {
+        self.ebbs.heap_size() + self.branches.heap_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::EntityRef;
+
+    #[test]
+    fn counts() {
+        let ebb0 = Ebb::new(0);
+        let ebb1 = Ebb::new(1);
+        let inst = Inst::new(3);
+        let mut profile = ProfileData::new();
+        assert!(profile.is_empty());
+        assert_eq!(profile.ebb_count(ebb1), None);
+
+        profile.set_ebb_count(ebb1, 0);
+        profile.set_branch_count(inst, 7);
+        assert!(!profile.is_empty());
+        assert_eq!(profile.ebb_count(ebb0), None);
+        assert_eq!(profile.ebb_count(ebb1), Some(0));
+        assert_eq!(profile.branch_count(inst), Some(7));
+
+        // The function never ran, so nothing is known about `ebb1`.
+        assert!(!profile.never_executed(ebb1));
+        profile.set_ebb_count(ebb0, 10);
+        assert!(profile.never_executed(ebb1));
+        assert!(!profile.never_executed(ebb0));
+        profile.set_ebb_count(ebb0, 0);
+        assert!(!profile.never_executed(ebb1));
+
+        profile.clear();
+        assert!(profile.is_empty());
+    }
+}
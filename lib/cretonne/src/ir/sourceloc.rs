@@ -2,8 +2,16 @@
 //!
 //! Cretonne tracks the original source location of each instruction, and preserves the source
 //! location when instructions are transformed.
+//!
+//! Source locations are compact 32-bit numbers. Frontends that compile more than one source file
+//! into a function can map source locations to a file and line in the function's `SourceFiles`
+//! table. Source locations without an entry in the table are still preserved.
 
+use entity::PrimaryMap;
+use ir::FileId;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::string::String;
 
 /// A source location.
 ///
@@ -12,7 +20,7 @@ use std::fmt;
 ///
 /// The default source location uses the all-ones bit pattern `!0`. It is used for instructions
 /// that can't be given a real source location.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SourceLoc(u32);
 
 impl SourceLoc {
@@ -48,8 +56,88 @@ impl fmt::Display for SourceLoc {
     }
 }
 
+/// A position in a source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    /// The source file.
+    pub file: FileId,
+    /// The line number in `file`.
+    pub line: u32,
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Table of source files and the positions of source locations.
+///
+/// This is the file table needed for emitting line number information like DWARF line programs.
+/// Each source location can be mapped to a file and a line number. Source locations that aren't
+/// in the table have no known position.
+#[derive(Clone, Debug)]
+pub struct SourceFiles {
+    names: PrimaryMap<FileId, String>,
+    positions: BTreeMap<SourceLoc, SourcePosition>,
+}
+
+impl SourceFiles {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self {
+            names: PrimaryMap::new(),
+            positions: BTreeMap::new(),
+        }
+    }
+
+    /// Clear all files and positions.
+    pub fn clear(&mut self) {
+        self.names.clear();
+        self.positions.clear();
+    }
+
+    /// Is this table empty?
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.positions.is_empty()
+    }
+
+    /// Get the `FileId` of the file named `name`, adding it to the table if necessary.
+    pub fn add_file(&mut self, name: &str) -> FileId {
+        match self.names.keys().find(|&file| self.names[file] == name) {
+            Some(file) => file,
+            None => self.names.push(name.into()),
+        }
+    }
+
+    /// Get the name of `file`.
+    pub fn file_name(&self, file: FileId) -> &str {
+        &self.names[file]
+    }
+
+    /// Get the number of files in the table.
+    pub fn num_files(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Map the source location `loc` to `line` in `file`.
+    ///
+    /// The default source location can't be given a position.
+    pub fn set_position(&mut self, loc: SourceLoc, file: FileId, line: u32) {
+        debug_assert!(!loc.is_default(), "Can't set the position of the default srcloc");
+        debug_assert!(self.names.is_valid(file), "Invalid {}", file);
+        self.positions.insert(loc, SourcePosition { file, line });
+    }
+
+    /// Get the position of the source location `loc`, if it is known.
+    pub fn position(&self, loc: SourceLoc) -> Option<SourcePosition> {
+        self.positions.get(&loc).cloned()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::SourceFiles;
     use ir::SourceLoc;
     use std::string::ToString;
 
@@ -60,4 +148,26 @@ mod tests {
         assert_eq!(SourceLoc::new(16).to_string(), "@0010");
         assert_eq!(SourceLoc::new(0xabcdef).to_string(), "@abcdef");
     }
+
+    #[test]
+    fn source_files() {
+        let mut files = SourceFiles::new();
+        assert!(files.is_empty());
+
+        let a = files.add_file("a.rs");
+        let b = files.add_file("b/c.rs");
+        assert_eq!(files.add_file("a.rs"), a);
+        assert_eq!(files.num_files(), 2);
+        assert_eq!(files.file_name(b), "b/c.rs");
+
+        files.set_position(SourceLoc::new(3), b, 17);
+        files.set_position(SourceLoc::new(4), a, 1);
+        assert_eq!(files.position(SourceLoc::new(3)).unwrap().to_string(), "file1:17");
+        assert_eq!(files.position(SourceLoc::new(4)).unwrap().file, a);
+        assert_eq!(files.position(SourceLoc::new(5)), None);
+        assert_eq!(files.position(SourceLoc::default()), None);
+
+        files.clear();
+        assert!(files.is_empty());
+    }
 }
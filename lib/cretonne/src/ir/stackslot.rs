@@ -31,7 +31,7 @@ pub type StackOffset = i32;
 const MIN_SPILL_SLOT_SIZE: StackSize = 4;
 
 /// Get the spill slot size to use for `ty`.
-fn spill_size(ty: Type) -> StackSize {
+pub fn spill_size(ty: Type) -> StackSize {
     cmp::max(MIN_SPILL_SLOT_SIZE, ty.bytes())
 }
 
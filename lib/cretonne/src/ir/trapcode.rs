@@ -20,6 +20,9 @@ pub enum TrapCode {
     /// pages.
     HeapOutOfBounds,
 
+    /// An atomic heap access was not aligned to the size of the accessed type.
+    HeapMisaligned,
+
     /// Other bounds checking error.
     OutOfBounds,
 
@@ -42,6 +45,9 @@ pub enum TrapCode {
     /// This trap is resumable.
     Interrupt,
 
+    /// The fuel counter of the function ran out. See `Function::fuel`.
+    OutOfFuel,
+
     /// A user-defined trap code.
     User(u16),
 }
@@ -52,6 +58,7 @@ impl Display for TrapCode {
         let identifier = match *self {
             StackOverflow => "stk_ovf",
             HeapOutOfBounds => "heap_oob",
+            HeapMisaligned => "heap_misaligned",
             OutOfBounds => "oob",
             IndirectCallToNull => "icall_null",
             BadSignature => "bad_sig",
@@ -59,6 +66,7 @@ impl Display for TrapCode {
             IntegerDivisionByZero => "int_divz",
             BadConversionToInteger => "bad_toint",
             Interrupt => "interrupt",
+            OutOfFuel => "out_of_fuel",
             User(x) => return write!(f, "user{}", x),
         };
         f.write_str(identifier)
@@ -73,6 +81,7 @@ impl FromStr for TrapCode {
         match s {
             "stk_ovf" => Ok(StackOverflow),
             "heap_oob" => Ok(HeapOutOfBounds),
+            "heap_misaligned" => Ok(HeapMisaligned),
             "oob" => Ok(OutOfBounds),
             "icall_null" => Ok(IndirectCallToNull),
             "bad_sig" => Ok(BadSignature),
@@ -80,6 +89,7 @@ impl FromStr for TrapCode {
             "int_divz" => Ok(IntegerDivisionByZero),
             "bad_toint" => Ok(BadConversionToInteger),
             "interrupt" => Ok(Interrupt),
+            "out_of_fuel" => Ok(OutOfFuel),
             _ if s.starts_with("user") => s[4..].parse().map(User).map_err(|_| ()),
             _ => Err(()),
         }
@@ -92,15 +102,17 @@ mod tests {
     use std::string::ToString;
 
     // Everything but user-defined codes.
-    const CODES: [TrapCode; 8] = [
+    const CODES: [TrapCode; 10] = [
         TrapCode::StackOverflow,
         TrapCode::HeapOutOfBounds,
+        TrapCode::HeapMisaligned,
         TrapCode::OutOfBounds,
         TrapCode::IndirectCallToNull,
         TrapCode::BadSignature,
         TrapCode::IntegerOverflow,
         TrapCode::IntegerDivisionByZero,
         TrapCode::BadConversionToInteger,
+        TrapCode::OutOfFuel,
     ];
 
     #[test]
@@ -0,0 +1,262 @@
+//! Minimal function construction API.
+//!
+//! The `IrBuilder` constructs a function directly in SSA form. It is intended for tools that
+//! translate from an IR that is already in SSA form, such as the IR of another compiler, and don't
+//! need the variable tracking and SSA construction of the `cretonne-frontend` crate.
+//!
+//! The builder only provides EBBs, EBB parameters, and instructions inserted with the
+//! `InstBuilder` methods, plus forward references to values that will be defined later. Each EBB
+//! is inserted in the layout the first time the builder switches to it, so the EBBs appear in the
+//! function in the order they are first visited.
+//!
+//! The methods of `IrBuilder` are part of Cretonne's stable API. New methods may be added, but the
+//! existing ones won't change in a backwards-incompatible way between minor releases.
+//!
+//! # Example
+//!
+//! ```
+//! use cretonne::ir::{AbiParam, CallConv, Function, InstBuilder, Signature};
+//! use cretonne::ir::types::I32;
+//! use cretonne::ir_builder::IrBuilder;
+//! use cretonne::settings;
+//!
+//! // A loop counting down from the function argument:
+//! //
+//! //   ebb0(v0):          jump ebb1(v0)
+//! //   ebb1(v1):          v2 = iadd_imm v1, -1
+//! //                      brnz v2, ebb1(v2)
+//! //                      return v2
+//! let mut sig = Signature::new(CallConv::Native);
+//! sig.params.push(AbiParam::new(I32));
+//! sig.returns.push(AbiParam::new(I32));
+//! let mut func = Function::with_name_signature(Default::default(), sig);
+//! {
+//!     let mut builder = IrBuilder::new(&mut func);
+//!     let ebb0 = builder.create_ebb();
+//!     let ebb1 = builder.create_ebb();
+//!     let v0 = builder.append_ebb_param(ebb0, I32);
+//!     let v1 = builder.append_ebb_param(ebb1, I32);
+//!
+//!     builder.switch_to_ebb(ebb0);
+//!     builder.ins().jump(ebb1, &[v0]);
+//!
+//!     builder.switch_to_ebb(ebb1);
+//!     let v2 = builder.ins().iadd_imm(v1, -1);
+//!     builder.ins().brnz(v2, ebb1, &[v2]);
+//!     builder.ins().return_(&[v2]);
+//!     builder.finish().unwrap();
+//! }
+//! cretonne::verify_function(&func, &settings::Flags::new(&settings::builder())).unwrap();
+//! ```
+
+use cursor::{Cursor, FuncCursor};
+use ir::{self, Ebb, Function, Type, Value};
+use result::{CtonError, CtonResult};
+use std::string::String;
+use std::vec::Vec;
+use verifier;
+
+/// Builder for constructing a function that is already in SSA form.
+pub struct IrBuilder<'f> {
+    pos: FuncCursor<'f>,
+
+    /// Forward references that haven't been defined yet.
+    pending: Vec<Value>,
+}
+
+impl<'f> IrBuilder<'f> {
+    /// Create a builder that appends EBBs and instructions to `func`.
+    ///
+    /// The signature and name of `func` should already be set. The function may already contain
+    /// code, and the new EBBs are added after the existing ones.
+    pub fn new(func: &'f mut Function) -> IrBuilder<'f> {
+        IrBuilder {
+            pos: FuncCursor::new(func),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Get the function being built.
+    ///
+    /// This gives access to the less common parts of the function, like stack slots, global
+    /// variables, heaps, and jump tables.
+    pub fn func(&mut self) -> &mut Function {
+        self.pos.func
+    }
+
+    /// Create a new EBB.
+    ///
+    /// The EBB is not inserted in the layout until the builder switches to it.
+    pub fn create_ebb(&mut self) -> Ebb {
+        self.pos.func.dfg.make_ebb()
+    }
+
+    /// Append a parameter of type `ty` to `ebb`.
+    ///
+    /// The entry EBB needs parameters matching the function signature.
+    pub fn append_ebb_param(&mut self, ebb: Ebb, ty: Type) -> Value {
+        self.pos.func.dfg.append_ebb_param(ebb, ty)
+    }
+
+    /// Append instructions to `ebb` from now on.
+    ///
+    /// The first EBB the builder switches to becomes the entry block unless the function already
+    /// had EBBs.
+    pub fn switch_to_ebb(&mut self, ebb: Ebb) {
+        if !self.pos.func.layout.is_ebb_inserted(ebb) {
+            self.pos.func.layout.append_ebb(ebb);
+        }
+        self.pos.goto_bottom(ebb);
+    }
+
+    /// Get the EBB that instructions are currently appended to.
+    pub fn current_ebb(&self) -> Option<Ebb> {
+        self.pos.current_ebb()
+    }
+
+    /// Set the source location given to the following instructions.
+    pub fn set_srcloc(&mut self, srcloc: ir::SourceLoc) {
+        self.pos.set_srcloc(srcloc);
+    }
+
+    /// Create an instruction builder that appends an instruction to the current EBB.
+    pub fn ins(&mut self) -> ir::InsertBuilder<&mut FuncCursor<'f>> {
+        debug_assert!(
+            self.pos.current_ebb().is_some(),
+            "Call switch_to_ebb() before inserting instructions"
+        );
+        self.pos.ins()
+    }
+
+    /// Declare a signature for use by call instructions.
+    pub fn import_signature(&mut self, signature: ir::Signature) -> ir::SigRef {
+        self.pos.func.import_signature(signature)
+    }
+
+    /// Declare an external function for use by call instructions.
+    pub fn import_function(&mut self, data: ir::ExtFuncData) -> ir::FuncRef {
+        self.pos.func.import_function(data)
+    }
+
+    /// Create a forward reference to a value of type `ty` that hasn't been created yet.
+    ///
+    /// The returned value can be used as an instruction argument right away, and it must be given
+    /// a definition with `define_value()` before the builder is finished.
+    pub fn declare_value(&mut self, ty: Type) -> Value {
+        let value = self.pos.func.dfg.make_placeholder_value(ty);
+        self.pending.push(value);
+        value
+    }
+
+    /// Define the forward reference `declared` as the value `value`.
+    ///
+    /// Panics if `declared` isn't a pending forward reference, if `value` is another pending
+    /// forward reference, or if the types of the two values differ.
+    pub fn define_value(&mut self, declared: Value, value: Value) {
+        assert!(
+            !self.pending.contains(&value),
+            "{} is a forward reference without a definition",
+            value
+        );
+        let idx = self.pending.iter().position(|&v| v == declared).unwrap_or_else(|| {
+            panic!("{} is not a pending forward reference", declared)
+        });
+        assert_eq!(
+            self.pos.func.dfg.value_type(declared),
+            self.pos.func.dfg.value_type(value),
+            "{} and {} have different types",
+            declared,
+            value
+        );
+        self.pending.swap_remove(idx);
+        self.pos.func.dfg.change_to_alias(declared, value);
+    }
+
+    /// Finish building the function.
+    ///
+    /// This replaces the uses of forward references with their definitions. It fails with a
+    /// verifier error if a forward reference was never defined.
+    pub fn finish(self) -> CtonResult {
+        if let Some(&value) = self.pending.first() {
+            return Err(CtonError::Verifier(
+                verifier::VerifierError {
+                    location: value.into(),
+                    message: String::from("forward reference was never defined"),
+                    pass: None,
+                    origin: None,
+                }.into(),
+            ));
+        }
+
+        let func = self.pos.func;
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                func.dfg.resolve_aliases_in_arguments(inst);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::condcodes::IntCC;
+    use ir::types::I32;
+    use ir::{AbiParam, CallConv, InstBuilder, Signature};
+    use settings;
+    use verifier::verify_function;
+
+    #[test]
+    fn forward_references() {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        let mut func = Function::with_name_signature(Default::default(), sig);
+        let (v0, v2, v3) = {
+            let mut builder = IrBuilder::new(&mut func);
+            let ebb0 = builder.create_ebb();
+            let ebb1 = builder.create_ebb();
+            let ebb2 = builder.create_ebb();
+            let v0 = builder.append_ebb_param(ebb0, I32);
+
+            // Visit `ebb2` before `ebb1`, so it uses a value defined in `ebb1` before that value
+            // has been created.
+            builder.switch_to_ebb(ebb0);
+            builder.ins().jump(ebb1, &[]);
+            builder.switch_to_ebb(ebb2);
+            let fwd = builder.declare_value(I32);
+            let v2 = builder.ins().iadd(fwd, fwd);
+            builder.ins().return_(&[v2]);
+            builder.switch_to_ebb(ebb1);
+            assert_eq!(builder.current_ebb(), Some(ebb1));
+            let v3 = builder.ins().iadd_imm(v0, 1);
+            let c = builder.ins().icmp_imm(IntCC::Equal, v3, 0);
+            builder.ins().brnz(c, ebb2, &[]);
+            builder.ins().return_(&[v3]);
+            builder.define_value(fwd, v3);
+            builder.finish().unwrap();
+            (v0, v2, v3)
+        };
+
+        verify_function(&func, &settings::Flags::new(&settings::builder())).unwrap();
+        let def = func.dfg.value_def(v2).unwrap_inst();
+        assert_eq!(func.dfg.inst_args(def), [v3, v3]);
+        assert_eq!(func.dfg.ebb_params(func.layout.entry_block().unwrap()), [v0]);
+    }
+
+    #[test]
+    fn undefined_forward_reference() {
+        let mut func = Function::new();
+        let mut builder = IrBuilder::new(&mut func);
+        let ebb0 = builder.create_ebb();
+        builder.switch_to_ebb(ebb0);
+        let fwd = builder.declare_value(I32);
+        builder.ins().iadd(fwd, fwd);
+        builder.ins().return_(&[]);
+        match builder.finish() {
+            Err(CtonError::Verifier(errors)) => assert_eq!(errors.0[0].location, fwd.into()),
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+}
@@ -6,21 +6,23 @@ mod binemit;
 mod enc_tables;
 mod registers;
 
-use binemit::{CodeSink, MemoryCodeSink, emit_function};
+use binemit::{CodeSink, MemoryCodeSink, emit_function, emit_function_checked, no_padding};
 use super::super::settings as shared_settings;
 use isa::enc_tables::{self as shared_enc_tables, lookup_enclist, Encodings};
 use isa::Builder as IsaBuilder;
-use isa::{TargetIsa, RegInfo, RegClass, EncInfo};
+use isa::{TargetIsa, RegInfo, RegClass, EncInfo, CallConvDescriptor};
 use ir;
 use regalloc;
 use std::fmt;
 use std::boxed::Box;
+use std::vec::Vec;
 
 #[allow(dead_code)]
 struct Isa {
     shared_flags: shared_settings::Flags,
     isa_flags: settings::Flags,
     cpumode: &'static [shared_enc_tables::Level1Entry<u16>],
+    libcalls: ir::LibCallLinkages,
 }
 
 /// Get an ISA builder for creating ARM32 targets.
@@ -28,12 +30,16 @@ pub fn isa_builder() -> IsaBuilder {
     IsaBuilder {
         setup: settings::builder(),
         constructor: isa_constructor,
+        call_convs: None,
+        libcalls: ir::LibCallLinkages::new(),
     }
 }
 
 fn isa_constructor(
     shared_flags: shared_settings::Flags,
     builder: &shared_settings::Builder,
+    _call_convs: Vec<CallConvDescriptor>,
+    libcalls: ir::LibCallLinkages,
 ) -> Box<TargetIsa> {
     let level1 = if shared_flags.is_compressed() {
         &enc_tables::LEVEL1_T32[..]
@@ -44,6 +50,7 @@ fn isa_constructor(
         isa_flags: settings::Flags::new(&shared_flags, builder),
         shared_flags,
         cpumode: level1,
+        libcalls,
     })
 }
 
@@ -60,6 +67,10 @@ impl TargetIsa for Isa {
         registers::INFO.clone()
     }
 
+    fn libcall_linkage(&self, libcall: ir::LibCall) -> &ir::LibCallLinkage {
+        self.libcalls.get(libcall)
+    }
+
     fn encoding_info(&self) -> EncInfo {
         enc_tables::INFO.clone()
     }
@@ -107,7 +118,11 @@ impl TargetIsa for Isa {
     }
 
     fn emit_function(&self, func: &ir::Function, sink: &mut MemoryCodeSink) {
-        emit_function(func, binemit::emit_inst, sink)
+        if self.shared_flags.check_emission() {
+            emit_function_checked(self, func, binemit::emit_inst, no_padding, sink)
+        } else {
+            emit_function(func, binemit::emit_inst, no_padding, sink)
+        }
     }
 }
 
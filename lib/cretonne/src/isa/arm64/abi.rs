@@ -1,26 +1,518 @@
 //! ARM 64 ABI implementation.
+//!
+//! This module implements the AAPCS64 calling convention through the `legalize_signature()` and
+//! `prologue_epilogue()` entry points.
 
-use ir;
-use isa::RegClass;
+use abi::{ArgAction, ValueConversion, ArgAssigner, legalize_args, legalize_struct_return};
+use cursor::{Cursor, EncCursor, CursorPosition};
+use ir::{self, AbiParam, ArgumentPurpose, ArgumentLoc, ArgumentExtension, CallConv, InstBuilder,
+         InstructionData, ValueLoc};
+use ir::condcodes::IntCC;
+use ir::immediates::Imm64;
+use ir::stackslot::{StackSize, StackOffset, StackSlotKind};
+use isa::{RegClass, RegUnit, StackRef, TargetIsa};
+use predicates::is_signed_int;
 use regalloc::AllocatableSet;
+use result;
 use settings as shared_settings;
-use super::registers::{GPR, FPR};
+use stack_layout::layout_stack;
+use super::registers::{GPR, FPR, RU};
+use std::i32;
+use std::vec::Vec;
+
+/// The AAPCS64 stack pointer is always 16-byte aligned.
+const STACK_ALIGN: StackSize = 16;
+
+/// Spill slots are addressed with the scaled 12-bit offset of `ldr` and `str`. The smallest spill
+/// slots are 4 bytes, so this is the largest offset from the stack pointer that can be encoded for
+/// all of them.
+const MAX_SLOT_OFFSET: StackOffset = 4 * 0x1000;
+
+/// The number of argument registers of each kind: `x0` - `x7` and `v0` - `v7`.
+const ARG_REGS: usize = 8;
+
+/// The indirect result location register.
+const STRUCT_RETURN_REG: RU = RU::x8;
+
+/// Callee-saved general purpose registers.
+static CSR_GPRS: [RU; 10] = [
+    RU::x19,
+    RU::x20,
+    RU::x21,
+    RU::x22,
+    RU::x23,
+    RU::x24,
+    RU::x25,
+    RU::x26,
+    RU::x27,
+    RU::x28,
+];
+
+/// Callee-saved floating point registers. Only the low 64 bits of `v8` - `v15` are preserved.
+static CSR_FPRS: [RU; 8] = [RU::v8, RU::v9, RU::v10, RU::v11, RU::v12, RU::v13, RU::v14, RU::v15];
+
+struct Args {
+    gpr_used: usize,
+    fpr_used: usize,
+    offset: u32,
+}
+
+impl Args {
+    fn new() -> Args {
+        Args {
+            gpr_used: 0,
+            fpr_used: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl ArgAssigner for Args {
+    fn assign(&mut self, arg: &AbiParam) -> ArgAction {
+        let ty = arg.value_type;
+
+        // Check for a legal type.
+        // SIMD instructions are not supported yet, so break all vectors down.
+        if ty.is_vector() {
+            return ValueConversion::VectorSplit.into();
+        }
+
+        // Large integers and booleans are broken down to fit in a register.
+        if !ty.is_float() && ty.bits() > 64 {
+            return ValueConversion::IntSplit.into();
+        }
+
+        // Small integers are extended to the size of a register.
+        if ty.is_int() && ty.bits() < 64 {
+            match arg.extension {
+                ArgumentExtension::None => {}
+                ArgumentExtension::Uext => return ValueConversion::Uext(ir::types::I64).into(),
+                ArgumentExtension::Sext => return ValueConversion::Sext(ir::types::I64).into(),
+            }
+        }
+
+        // The address of a returned struct is passed in `x8`, outside the argument registers.
+        if arg.purpose == ArgumentPurpose::StructReturn {
+            return ArgumentLoc::Reg(STRUCT_RETURN_REG as RegUnit).into();
+        }
+
+        // Try to use a GPR or an FPR.
+        if ty.is_float() {
+            if self.fpr_used < ARG_REGS {
+                self.fpr_used += 1;
+                return ArgumentLoc::Reg(FPR.unit(self.fpr_used - 1)).into();
+            }
+        } else if self.gpr_used < ARG_REGS {
+            self.gpr_used += 1;
+            return ArgumentLoc::Reg(GPR.unit(self.gpr_used - 1)).into();
+        }
+
+        // Assign a stack location. Each argument occupies an 8-byte slot.
+        let loc = ArgumentLoc::Stack(self.offset as i32);
+        self.offset += 8;
+        debug_assert!(self.offset <= i32::MAX as u32);
+        loc.into()
+    }
+}
 
 /// Legalize `sig`.
 pub fn legalize_signature(
-    _sig: &mut ir::Signature,
+    sig: &mut ir::Signature,
     _flags: &shared_settings::Flags,
     _current: bool,
 ) {
-    unimplemented!()
+    // Return values use the same registers as the arguments. If they don't fit, they are
+    // returned in memory pointed to by `x8`.
+    legalize_struct_return(sig, &mut Args::new(), ir::types::I64);
+
+    // Variadic arguments are passed like the fixed ones, but the fixed parameters may have been
+    // split, so recompute their number.
+    let mut args = Args::new();
+    match sig.fixed_params {
+        Some(fixed) => {
+            let mut variadic = sig.params.split_off(fixed);
+            legalize_args(&mut sig.params, &mut args);
+            sig.fixed_params = Some(sig.params.len());
+            legalize_args(&mut variadic, &mut args);
+            sig.params.append(&mut variadic);
+        }
+        None => legalize_args(&mut sig.params, &mut args),
+    }
 }
 
 /// Get register class for a type appearing in a legalized signature.
 pub fn regclass_for_abi_type(ty: ir::Type) -> RegClass {
-    if ty.is_int() { GPR } else { FPR }
+    if ty.is_int() || ty.is_bool() {
+        GPR
+    } else {
+        FPR
+    }
 }
 
 /// Get the set of allocatable registers for `func`.
 pub fn allocatable_registers(_func: &ir::Function) -> AllocatableSet {
-    unimplemented!()
+    let mut regs = AllocatableSet::new();
+    // The intra-procedure-call scratch registers are used by the encoding recipes.
+    regs.take(GPR, RU::x16 as RegUnit);
+    regs.take(GPR, RU::x17 as RegUnit);
+    // The platform register.
+    regs.take(GPR, RU::x18 as RegUnit);
+    // The frame pointer and the link register.
+    regs.take(GPR, RU::x29 as RegUnit);
+    regs.take(GPR, RU::x30 as RegUnit);
+    // The stack pointer / zero register.
+    regs.take(GPR, RU::x31 as RegUnit);
+    regs
+}
+
+/// Compute the stack frame layout and insert the prologue and epilogues of `func`.
+pub fn prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    check_tail_calls(func)?;
+    match func.signature.call_conv {
+        CallConv::Native => native_prologue_epilogue(func, isa),
+        CallConv::SpiderWASM => spiderwasm_prologue_epilogue(func, isa),
+        CallConv::WindowsFastcall |
+        CallConv::Custom(_) => Err(result::CtonError::InvalidInput),
+    }
+}
+
+/// Check that the tail calls in `func` are supported.
+///
+/// A tail call branches to the callee after the epilogue has released the stack frame, so all of
+/// the callee's arguments must be passed in registers. The SpiderWASM epilogues are inserted by
+/// the embedder, so tail calls can't be used with that calling convention.
+fn check_tail_calls(func: &ir::Function) -> result::CtonResult {
+    for ebb in func.layout.ebbs() {
+        let inst = match func.layout.last_inst(ebb) {
+            Some(inst) => inst,
+            None => continue,
+        };
+        if !func.dfg[inst].opcode().is_terminator() {
+            continue;
+        }
+        if let Some(sig) = func.dfg.call_signature(inst) {
+            if func.signature.call_conv == CallConv::SpiderWASM ||
+                func.dfg.signatures[sig].params.iter().any(
+                    |arg| !arg.location.is_reg(),
+                )
+            {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn spiderwasm_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    let bytes = StackSize::from(isa.flags().spiderwasm_prologue_words()) * 8;
+
+    // The embedder inserts the prologue, so it has to check the stack limit too.
+    if func.stack_limit.is_some() {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+
+    let mut ss = ir::StackSlotData::new(StackSlotKind::IncomingArg, bytes);
+    ss.offset = Some(-(bytes as StackOffset));
+    func.stack_slots.push(ss);
+
+    layout_stack(&mut func.stack_slots, STACK_ALIGN)?;
+    check_slot_offsets(func)
+}
+
+/// Insert an AAPCS64 prologue and epilogue.
+///
+/// The prologue saves the frame pointer and the link register as a pair and points the frame
+/// pointer at them, so the frame records form a chain. Then the callee-saved registers that are
+/// used by `func` are saved in pairs, and finally the stack pointer is adjusted for the rest of
+/// the frame.
+fn native_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    let gprs = used_registers(func, &CSR_GPRS);
+    let fprs = used_registers(func, &CSR_FPRS);
+
+    // Each push occupies 16 bytes, so an unpaired register gets a whole slot.
+    let csr_stack_size = (16 * (1 + (gprs.len() + 1) / 2 + (fprs.len() + 1) / 2)) as i32;
+    func.create_stack_slot(ir::StackSlotData {
+        kind: StackSlotKind::IncomingArg,
+        size: csr_stack_size as u32,
+        offset: Some(-csr_stack_size),
+    });
+
+    let total_stack_size = layout_stack(&mut func.stack_slots, STACK_ALIGN)? as i32;
+    check_slot_offsets(func)?;
+    let local_stack_size = i64::from(total_stack_size - csr_stack_size);
+
+    // Add the saved registers to the function signature.
+    let fp_arg = AbiParam::special_reg(
+        ir::types::I64,
+        ArgumentPurpose::FramePointer,
+        RU::x29 as RegUnit,
+    );
+    let lr_arg = AbiParam::special_reg(ir::types::I64, ArgumentPurpose::Link, RU::x30 as RegUnit);
+    func.signature.params.push(fp_arg);
+    func.signature.returns.push(fp_arg);
+    func.signature.params.push(lr_arg);
+    func.signature.returns.push(lr_arg);
+    let csrs = CalleeSaved::new(&gprs, &fprs);
+    for &(reg, ty) in &csrs.regs {
+        let csr_arg = AbiParam::special_reg(ty, ArgumentPurpose::CalleeSaved, reg);
+        func.signature.params.push(csr_arg);
+        func.signature.returns.push(csr_arg);
+    }
+
+    // Set up the cursor and insert the prologue.
+    let entry_ebb = func.layout.entry_block().expect("missing entry block");
+    let mut pos = EncCursor::new(func, isa).at_first_insertion_point(entry_ebb);
+    if let Some(stack_limit) = pos.func.stack_limit {
+        insert_stack_check(&mut pos, i64::from(total_stack_size), stack_limit)?;
+    }
+    insert_prologue(&mut pos, local_stack_size, &csrs);
+
+    // Reset the cursor and insert the epilogues.
+    let mut pos = pos.at_position(CursorPosition::Nowhere);
+    while let Some(ebb) = pos.next_ebb() {
+        pos.goto_last_inst(ebb);
+        if let Some(inst) = pos.current_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
+                insert_epilogue(inst, local_stack_size, &mut pos, &csrs);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The callee-saved registers that are saved by a prologue, and the types used to save them.
+///
+/// The general purpose registers come first, and each kind is saved in pairs.
+struct CalleeSaved {
+    regs: Vec<(RegUnit, ir::Type)>,
+    gprs: usize,
+}
+
+impl CalleeSaved {
+    fn new(gprs: &[RegUnit], fprs: &[RegUnit]) -> CalleeSaved {
+        let regs = gprs.iter()
+            .map(|&r| (r, ir::types::I64))
+            .chain(fprs.iter().map(|&r| (r, ir::types::F64)))
+            .collect();
+        CalleeSaved {
+            regs,
+            gprs: gprs.len(),
+        }
+    }
+
+    /// Get the groups of registers that are saved together, in push order.
+    fn pairs(&self) -> Vec<&[(RegUnit, ir::Type)]> {
+        let (gprs, fprs) = self.regs.split_at(self.gprs);
+        gprs.chunks(2).chain(fprs.chunks(2)).collect()
+    }
+}
+
+/// Get the registers in `csrs` that are used by `func`.
+///
+/// This runs after register allocation, so a register is used if a value is assigned to it or
+/// temporarily diverted to it.
+fn used_registers(func: &ir::Function, csrs: &[RU]) -> Vec<RegUnit> {
+    let mut used: Vec<RegUnit> = Vec::new();
+    {
+        let mut mark = |reg: RegUnit| if csrs.iter().any(|&r| r as RegUnit == reg) &&
+            !used.contains(&reg)
+        {
+            used.push(reg);
+        };
+        for value in func.locations.keys() {
+            if let ValueLoc::Reg(reg) = func.locations[value] {
+                mark(reg);
+            }
+        }
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                match func.dfg[inst] {
+                    InstructionData::RegMove { dst, .. } |
+                    InstructionData::RegFill { dst, .. } => mark(dst),
+                    _ => {}
+                }
+            }
+        }
+    }
+    used.sort();
+    used
+}
+
+/// Check that the stack slots accessed by spills, fills, and stack arguments can be addressed
+/// relative to the stack pointer.
+fn check_slot_offsets(func: &ir::Function) -> result::CtonResult {
+    for ss in func.stack_slots.keys() {
+        let slot = &func.stack_slots[ss];
+        match slot.kind {
+            StackSlotKind::SpillSlot |
+            StackSlotKind::IncomingArg |
+            StackSlotKind::OutgoingArg |
+            StackSlotKind::EmergencySlot => {}
+            StackSlotKind::ExplicitSlot => continue,
+        }
+        let offset = StackRef::sp(ss, &func.stack_slots).offset;
+        if offset < 0 || offset + slot.size as StackOffset > MAX_SLOT_OFFSET {
+            return Err(result::CtonError::ImplLimitExceeded);
+        }
+    }
+    Ok(())
+}
+
+/// Insert the prologue at the cursor position in the entry block.
+fn insert_prologue(pos: &mut EncCursor, stack_size: i64, csrs: &CalleeSaved) {
+    let ebb = pos.current_ebb().expect("missing ebb under cursor");
+    let fp = pos.func.dfg.append_ebb_param(ebb, ir::types::I64);
+    pos.func.locations[fp] = ValueLoc::Reg(RU::x29 as RegUnit);
+    let lr = pos.func.dfg.append_ebb_param(ebb, ir::types::I64);
+    pos.func.locations[lr] = ValueLoc::Reg(RU::x30 as RegUnit);
+
+    // stp x29, x30, [sp, #-16]!
+    // mov x29, sp
+    pos.ins().a64_push_pair(fp, lr);
+    pos.ins().copy_special(RU::x31 as RegUnit, RU::x29 as RegUnit);
+
+    for pair in csrs.pairs() {
+        let mut saved = Vec::new();
+        for &(reg, ty) in pair {
+            let csr_arg = pos.func.dfg.append_ebb_param(ebb, ty);
+            pos.func.locations[csr_arg] = ValueLoc::Reg(reg);
+            saved.push(csr_arg);
+        }
+        if saved.len() == 2 {
+            pos.ins().a64_push_pair(saved[0], saved[1]);
+        } else {
+            pos.ins().a64_push(saved[0]);
+        }
+    }
+
+    if stack_size > 0 {
+        pos.ins().adjust_sp_imm(Imm64::new(-stack_size));
+    }
+}
+
+/// Insert an epilogue before the `return` instruction or tail call `inst`.
+///
+/// The restored registers are passed as return values of a `return` instruction. A tail call
+/// passes its arguments to the callee instead, and the callee saves the registers again.
+fn insert_epilogue(inst: ir::Inst, stack_size: i64, pos: &mut EncCursor, csrs: &CalleeSaved) {
+    if stack_size > 0 {
+        pos.ins().adjust_sp_imm(Imm64::new(stack_size));
+    }
+
+    // Pop the registers in the reverse order of the pushes, stepping backward each time to
+    // preserve the correct order.
+    let is_return = pos.func.dfg[inst].opcode().is_return();
+    let (fp, lr) = pos.ins().a64_pop_pair(ir::types::I64);
+    pos.prev_inst();
+    pos.func.locations[fp] = ValueLoc::Reg(RU::x29 as RegUnit);
+    pos.func.locations[lr] = ValueLoc::Reg(RU::x30 as RegUnit);
+    if is_return {
+        pos.func.dfg.append_inst_arg(inst, fp);
+        pos.func.dfg.append_inst_arg(inst, lr);
+    }
+
+    for pair in csrs.pairs() {
+        let ty = pair[0].1;
+        let mut values = Vec::new();
+        if pair.len() == 2 {
+            let (a, b) = pos.ins().a64_pop_pair(ty);
+            values.push(a);
+            values.push(b);
+        } else {
+            values.push(pos.ins().a64_pop(ty));
+        }
+        pos.prev_inst();
+
+        for (&value, &(reg, _)) in values.iter().zip(pair) {
+            pos.func.locations[value] = ValueLoc::Reg(reg);
+            if is_return {
+                pos.func.dfg.append_inst_arg(inst, value);
+            }
+        }
+    }
+}
+
+/// Insert a check that the stack frame of `frame_size` bytes doesn't extend below the address of
+/// the global variable `stack_limit`.
+///
+/// The check comes before the prologue, so it can only use the scratch register `x17`. The
+/// `ifcmp_sp` instruction uses `x16` itself.
+fn insert_stack_check(
+    pos: &mut EncCursor,
+    frame_size: i64,
+    stack_limit: ir::GlobalVar,
+) -> result::CtonResult {
+    if !is_signed_int(frame_size, 24, 0) {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+    let limit = insert_stack_limit_addr(pos, stack_limit)?;
+    let threshold = add_offset(pos, limit, frame_size)?;
+    let flags = pos.ins().ifcmp_sp(threshold);
+    pos.func.locations[flags] = ValueLoc::Reg(RU::nzcv as RegUnit);
+    pos.ins().trapif(
+        IntCC::UnsignedGreaterThan,
+        flags,
+        ir::TrapCode::StackOverflow,
+    );
+    Ok(())
+}
+
+/// Compute the address of the global variable `gv` in `x17`.
+fn insert_stack_limit_addr(
+    pos: &mut EncCursor,
+    gv: ir::GlobalVar,
+) -> Result<ir::Value, result::CtonError> {
+    let x17 = ValueLoc::Reg(RU::x17 as RegUnit);
+    let addr = match pos.func.global_vars[gv] {
+        ir::GlobalVarData::VmCtx { offset } => {
+            let vmctx = pos.func.special_param(ArgumentPurpose::VMContext).ok_or(
+                result::CtonError::InvalidInput,
+            )?;
+            if let ValueLoc::Stack(_) = pos.func.locations[vmctx] {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+            let base = pos.ins().copy(vmctx);
+            pos.func.locations[base] = x17;
+            add_offset(pos, base, offset.into())?
+        }
+        ir::GlobalVarData::Deref { base, offset } => {
+            let base = insert_stack_limit_addr(pos, base)?;
+            let mut flags = ir::MemFlags::new();
+            flags.set_notrap();
+            flags.set_aligned();
+            let ptr = pos.ins().load(ir::types::I64, flags, base, 0);
+            pos.func.locations[ptr] = x17;
+            add_offset(pos, ptr, offset.into())?
+        }
+        ir::GlobalVarData::Sym { .. } => {
+            // The address is stored inline, which doesn't work for PIC.
+            if pos.isa.flags().is_pic() {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+            let addr = pos.ins().globalsym_addr(ir::types::I64, gv);
+            pos.func.locations[addr] = x17;
+            addr
+        }
+        ir::GlobalVarData::TLS { .. } => return Err(result::CtonError::ImplLimitExceeded),
+    };
+    Ok(addr)
+}
+
+/// Add `offset` to `value` in `x17`.
+fn add_offset(
+    pos: &mut EncCursor,
+    value: ir::Value,
+    offset: i64,
+) -> Result<ir::Value, result::CtonError> {
+    if offset == 0 {
+        return Ok(value);
+    }
+    if !is_signed_int(offset, 24, 0) {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+    let sum = pos.ins().iadd_imm(value, offset);
+    pos.func.locations[sum] = ValueLoc::Reg(RU::x17 as RegUnit);
+    Ok(sum)
 }
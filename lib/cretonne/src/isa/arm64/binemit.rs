@@ -1,7 +1,543 @@
 //! Emitting binary ARM64 machine code.
 
-use binemit::{CodeSink, bad_encoding};
-use ir::{Function, Inst};
+use binemit::{CodeSink, Reloc, SymbolInfo, bad_encoding};
+use ir::{Constant, Ebb, Function, Inst, InstructionData, TrapCode};
+use ir::condcodes::{CondCode, IntCC, FloatCC};
+use isa::{RegUnit, StackRef, StackBaseMask};
+use predicates::is_signed_int;
 use regalloc::RegDiversions;
 
 include!(concat!(env!("OUT_DIR"), "/binemit-arm64.rs"));
+
+/// Register number 31 is the stack pointer in address computations.
+const SP: RegUnit = 31;
+
+/// Register number 31 is the zero register in most other instructions.
+const ZR: RegUnit = 31;
+
+/// The link register holding the return address.
+const LR: RegUnit = 30;
+
+/// Reserved scratch registers used by macros.
+const TMP0: RegUnit = 16;
+const TMP1: RegUnit = 17;
+
+/// The permanently undefined instruction `udf #0`.
+const UDF: u32 = 0;
+
+/// Encoding bits for `b` and `b.cond`.
+const B: u16 = 0x14;
+const BCOND: u16 = 0x54;
+
+/// Encoding bits for a 64-bit add immediate.
+const ADD_X: u16 = 0b100;
+
+/// Encoding bits for a 64-bit bitfield move.
+const BFM_X: u16 = 1 << 14;
+
+/// Index modes for loads and stores that update the base register.
+const IDX_PRE: u32 = 0b11;
+const IDX_POST: u32 = 0b01;
+
+/// Expand R3 encoding bits into an instruction template.
+fn r3_template(bits: u16) -> u32 {
+    let bits = u32::from(bits);
+    ((bits & 0x7ff) << 21) | (((bits >> 11) & 0x1f) << 10)
+}
+
+/// Three-register instructions.
+///
+///   31      20 15      9  4
+///   template rm template rn rd
+///         21 16       10  5  0
+///
+/// Encoding bits: `template[31:21] | template[14:10] << 11`.
+fn put_rrr<CS: CodeSink + ?Sized>(bits: u16, rn: RegUnit, rm: RegUnit, rd: RegUnit, sink: &mut CS) {
+    let rn = u32::from(rn) & 0x1f;
+    let rm = u32::from(rm) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    sink.put4(r3_template(bits) | (rm << 16) | (rn << 5) | rd);
+}
+
+/// Four-register instructions with `ra` in bits 14:10.
+fn put_rrrr<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    rm: RegUnit,
+    ra: RegUnit,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let rn = u32::from(rn) & 0x1f;
+    let rm = u32::from(rm) & 0x1f;
+    let ra = u32::from(ra) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    sink.put4(r3_template(bits) | (rm << 16) | (ra << 10) | (rn << 5) | rd);
+}
+
+/// Conditional select: R3 with a condition in bits 15:12.
+fn put_csel<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    rm: RegUnit,
+    cond: u32,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let rn = u32::from(rn) & 0x1f;
+    let rm = u32::from(rm) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    sink.put4(r3_template(bits) | (rm << 16) | (cond << 12) | (rn << 5) | rd);
+}
+
+/// Materialize a condition as 0 or 1: `csinc wd, wzr, wzr, !cond`.
+fn put_cset<CS: CodeSink + ?Sized>(cond: u32, rd: RegUnit, sink: &mut CS) {
+    let rd = u32::from(rd) & 0x1f;
+
+    sink.put4(0x1a9f07e0 | ((cond ^ 1) << 12) | rd);
+}
+
+/// Two-register instruction with a full 32-bit template.
+fn put_rr<CS: CodeSink + ?Sized>(template: u32, rn: RegUnit, rd: RegUnit, sink: &mut CS) {
+    let rn = u32::from(rn) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    sink.put4(template | (rn << 5) | rd);
+}
+
+/// Floating point instructions with one source register.
+///
+///   31 28    23 21 20     14 9  4
+///   sf 11110 ty 1  opcode 1  rn rd
+///           24 22     15 14  5  0
+///
+/// Encoding bits: `opcode[20:15] | ty << 6 | bit14 << 9 | sf << 8`.
+fn put_fp<CS: CodeSink + ?Sized>(bits: u16, rn: RegUnit, rd: RegUnit, sink: &mut CS) {
+    let bits = u32::from(bits);
+    let rn = u32::from(rn) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    let mut i = 0x1e200000;
+    i |= (bits & 0x3f) << 15;
+    i |= ((bits >> 6) & 0x3) << 22;
+    i |= ((bits >> 8) & 0x1) << 31;
+    i |= ((bits >> 9) & 0x1) << 14;
+    i |= rn << 5;
+    i |= rd;
+
+    sink.put4(i);
+}
+
+/// Add/subtract with a 12-bit immediate, optionally shifted.
+///
+///   31 29 28     22 21    9  4
+///   sf op S 10001 sh imm12 rn rd
+///           24    22    10  5  0
+///
+/// Encoding bits: `S | op << 1 | sf << 2`.
+///
+/// A negative immediate flips the `op` bit. Immediates with the low 12 bits
+/// clear are encoded with `sh` set.
+fn put_addsub_imm<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    imm: i64,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let bits = u32::from(bits);
+    let rn = u32::from(rn) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    let (op, imm) = if imm < 0 {
+        (((bits >> 1) & 1) ^ 1, imm.wrapping_neg() as u64)
+    } else {
+        ((bits >> 1) & 1, imm as u64)
+    };
+    let (sh, imm12) = if imm < 0x1000 {
+        (0, imm as u32)
+    } else {
+        debug_assert!(imm & 0xfff == 0 && imm < 0x100_0000, "bad add imm {:#x}", imm);
+        (1, (imm >> 12) as u32)
+    };
+
+    let mut i = 0x11000000;
+    i |= (bits & 1) << 29;
+    i |= op << 30;
+    i |= ((bits >> 2) & 1) << 31;
+    i |= sh << 22;
+    i |= imm12 << 10;
+    i |= rn << 5;
+    i |= rd;
+
+    sink.put4(i);
+}
+
+/// Add a 24-bit immediate with two add/subtract instructions.
+fn put_addsub_imm2<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    imm: i64,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let abs = imm.wrapping_abs();
+    let (lo, hi) = (abs & 0xfff, abs & !0xfff);
+    let sign = if imm < 0 { -1 } else { 1 };
+    put_addsub_imm(bits, rn, sign * lo, rd, sink);
+    put_addsub_imm(bits, rd, sign * hi, rd, sink);
+}
+
+/// Bitfield moves.
+///
+///   31 28     22 21   15   9  4
+///   sf opc 100110 N immr imms rn rd
+///       29     23 22   16   10  5  0
+///
+/// Encoding bits: `imms | immr << 6 | opc << 12 | sf << 14`. The `N` bit is
+/// always the same as `sf`.
+fn put_bfm<CS: CodeSink + ?Sized>(bits: u16, rn: RegUnit, rd: RegUnit, sink: &mut CS) {
+    let bits = u32::from(bits);
+    let rn = u32::from(rn) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+    let sf = (bits >> 14) & 1;
+
+    let mut i = 0x13000000;
+    i |= (bits & 0x3f) << 10;
+    i |= ((bits >> 6) & 0x3f) << 16;
+    i |= ((bits >> 12) & 0x3) << 29;
+    i |= sf << 31;
+    i |= sf << 22;
+    i |= rn << 5;
+    i |= rd;
+
+    sink.put4(i);
+}
+
+/// Get the number of bits in the operands of a bitfield move.
+fn bfm_width(bits: u16) -> i64 {
+    if bits & BFM_X != 0 { 64 } else { 32 }
+}
+
+/// Shift left by an immediate: `ubfm rd, rn, #(-s mod N), #(N-1-s)`.
+fn put_lsl_imm<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    imm: i64,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let n = bfm_width(bits);
+    let s = imm & (n - 1);
+    let immr = (n - s) & (n - 1);
+    let imms = n - 1 - s;
+    put_bfm(bits | ((immr as u16) << 6) | imms as u16, rn, rd, sink);
+}
+
+/// Shift right by an immediate: `ubfm` or `sbfm rd, rn, #s, #(N-1)`.
+fn put_shr_imm<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    imm: i64,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let n = bfm_width(bits);
+    let s = imm & (n - 1);
+    put_bfm(bits | ((s as u16) << 6) | (n - 1) as u16, rn, rd, sink);
+}
+
+/// Rotate right by an immediate: `extr rd, rn, rn, #imm`.
+///
+/// Encoding bits: `template[31:16]`.
+fn put_extr<CS: CodeSink + ?Sized>(bits: u16, rn: RegUnit, imm: i64, rd: RegUnit, sink: &mut CS) {
+    let template = u32::from(bits) << 16;
+    let rn = u32::from(rn) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+    let n = if template & 0x8000_0000 != 0 { 64 } else { 32 };
+    let s = (imm & (n - 1)) as u32;
+
+    sink.put4(template | (rn << 16) | (s << 10) | (rn << 5) | rd);
+}
+
+/// Move wide immediate.
+///
+///   31 28     22 20    4
+///   sf opc 100101 hw imm16 rd
+///       29     23 21     5  0
+fn put_movw<CS: CodeSink + ?Sized>(sf: u32, opc: u32, hw: u32, imm16: u32, rd: u32, sink: &mut CS) {
+    sink.put4(0x12800000 | (sf << 31) | (opc << 29) | (hw << 21) | (imm16 << 5) | rd);
+}
+
+/// Materialize `imm` with `count` move wide instructions.
+///
+/// Encoding bits: `opc | sf << 2` of the first instruction. The first
+/// instruction is switched to `movn` for negative immediates unless all four
+/// half-words are written.
+fn put_mov_imm<CS: CodeSink + ?Sized>(bits: u16, imm: i64, count: u32, rd: RegUnit, sink: &mut CS) {
+    let sf = u32::from((bits >> 2) & 1);
+    let rd = u32::from(rd) & 0x1f;
+
+    if imm < 0 && count < 4 {
+        put_movw(sf, 0b00, 0, (!imm & 0xffff) as u32, rd, sink);
+    } else {
+        put_movw(sf, u32::from(bits & 0x3), 0, (imm & 0xffff) as u32, rd, sink);
+    }
+    for hw in 1..count {
+        let imm16 = ((imm >> (16 * hw)) & 0xffff) as u32;
+        put_movw(sf, 0b11, hw, imm16, rd, sink);
+    }
+}
+
+/// Load a 64-bit value stored inline after the instruction, and branch over it.
+///
+/// The caller emits the 8-byte value.
+fn put_ldr_inline<CS: CodeSink + ?Sized>(rd: RegUnit, sink: &mut CS) {
+    let rd = u32::from(rd) & 0x1f;
+    // ldr xd, #8
+    sink.put4(0x58000040 | rd);
+    // b #12
+    sink.put4(0x14000003);
+}
+
+/// Loads and stores with an immediate offset.
+///
+///   31   29  26 25 23  21    9  4
+///   size 111 V  01 opc imm12 rn rt
+///     30  27 26 24  22    10  5  0
+///
+/// Encoding bits: `template[31:22]`.
+///
+/// Offsets that can't be scaled by the access size use the unscaled form
+/// with a 9-bit signed offset in bits 20:12.
+fn put_ldst<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rn: RegUnit,
+    offset: i32,
+    rt: RegUnit,
+    sink: &mut CS,
+) {
+    let template = u32::from(bits) << 22;
+    let scale = template >> 30;
+    let rn = u32::from(rn) & 0x1f;
+    let rt = u32::from(rt) & 0x1f;
+
+    let mut i = if offset >= 0 && offset & ((1 << scale) - 1) == 0 && (offset >> scale) < 0x1000 {
+        template | ((offset as u32 >> scale) << 10)
+    } else {
+        debug_assert!(is_signed_int(offset, 9, 0), "bad ldst offset {}", offset);
+        (template & !(1 << 24)) | ((offset as u32 & 0x1ff) << 12)
+    };
+    i |= rn << 5;
+    i |= rt;
+
+    sink.put4(i);
+}
+
+/// Pre- or post-indexed loads and stores that update the base register.
+fn put_ldst_idx<CS: CodeSink + ?Sized>(
+    bits: u16,
+    mode: u32,
+    rn: RegUnit,
+    offset: i32,
+    rt: RegUnit,
+    sink: &mut CS,
+) {
+    let template = u32::from(bits) << 22;
+    let rn = u32::from(rn) & 0x1f;
+    let rt = u32::from(rt) & 0x1f;
+
+    let mut i = template & !(1 << 24);
+    i |= (offset as u32 & 0x1ff) << 12;
+    i |= mode << 10;
+    i |= rn << 5;
+    i |= rt;
+
+    sink.put4(i);
+}
+
+/// Pre- or post-indexed load and store pairs of 64-bit registers.
+///
+///   31  29  26 25   22 21   14  9  4
+///   opc 101 V  mode L  imm7 rt2 rn rt
+///    30  27 26   23 22   15  10  5  0
+///
+/// Encoding bits: `template[31:22]` with the signed offset mode.
+fn put_ldst_pair<CS: CodeSink + ?Sized>(
+    bits: u16,
+    mode: u32,
+    rn: RegUnit,
+    offset: i32,
+    rt: RegUnit,
+    rt2: RegUnit,
+    sink: &mut CS,
+) {
+    let template = u32::from(bits) << 22;
+    let rn = u32::from(rn) & 0x1f;
+    let rt = u32::from(rt) & 0x1f;
+    let rt2 = u32::from(rt2) & 0x1f;
+    debug_assert!(offset & 7 == 0 && is_signed_int(offset, 10, 3));
+
+    let mut i = template & !(0x3 << 23);
+    i |= mode << 23;
+    i |= ((offset >> 3) as u32 & 0x7f) << 15;
+    i |= rt2 << 10;
+    i |= rn << 5;
+    i |= rt;
+
+    sink.put4(i);
+}
+
+/// Load-acquire and store-release instructions.
+///
+///   31     20 15 14   9  4
+///   template rs 1 11111 rn rt
+///         21 16 15   10  5  0
+///
+/// Encoding bits: `template[31:16]`.
+fn put_ldst_excl<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rs: RegUnit,
+    rn: RegUnit,
+    rt: RegUnit,
+    sink: &mut CS,
+) {
+    let rs = u32::from(rs) & 0x1f;
+    let rn = u32::from(rn) & 0x1f;
+    let rt = u32::from(rt) & 0x1f;
+
+    sink.put4((u32::from(bits) << 16) | (rs << 16) | 0xfc00 | (rn << 5) | rt);
+}
+
+/// Compare-and-swap loop. The encoding bits are the `sf` bit.
+///
+/// ```text
+///     ldaxr x16, [p]
+///     cmp x16, e
+///     b.ne 1f
+///     stlxr w17, x, [p]
+///     cbnz w17, 0b
+/// 1:  mov a, x16
+/// ```
+fn put_cas_loop<CS: CodeSink + ?Sized>(
+    bits: u16,
+    p: RegUnit,
+    e: RegUnit,
+    x: RegUnit,
+    a: RegUnit,
+    sink: &mut CS,
+) {
+    let sf = u32::from(bits & 1);
+    let size = (0b10 | sf) << 14;
+    put_ldst_excl(size as u16 | 0x0840, ZR, p, TMP0, sink);
+    put_rrr(0x358 | (sf << 10) as u16, TMP0, e, ZR, sink);
+    put_b19(BCOND, 12, icc2cond(IntCC::NotEqual), sink);
+    put_ldst_excl(size as u16 | 0x0800, TMP1, p, x, sink);
+    put_b19(0x35, -16, u32::from(TMP1), sink);
+    put_rrr(0x150 | (sf << 10) as u16, ZR, TMP0, a, sink);
+}
+
+/// Branch to a register: `br`, `blr`, and `ret`.
+///
+/// Encoding bits: `template[31:16]`.
+fn put_br<CS: CodeSink + ?Sized>(bits: u16, rn: RegUnit, sink: &mut CS) {
+    let rn = u32::from(rn) & 0x1f;
+
+    sink.put4((u32::from(bits) << 16) | (rn << 5));
+}
+
+/// Get the displacement from the current position to `destination`.
+fn branch_disp<CS: CodeSink + ?Sized>(destination: Ebb, func: &Function, sink: &CS) -> i64 {
+    i64::from(func.offsets[destination]) - i64::from(sink.offset())
+}
+
+/// Get the displacement from the current position to `constant` in the
+/// constant pool.
+fn const_disp<CS: CodeSink + ?Sized>(constant: Constant, func: &Function, sink: &CS) -> i64 {
+    i64::from(func.constant_offsets[constant]) - i64::from(sink.offset())
+}
+
+/// Unconditional branches with a 26-bit word displacement.
+///
+///   31 30    25
+///   op 00101 imm26
+///       26       0
+///
+/// Encoding bits: `template[31:24]`.
+fn put_b26<CS: CodeSink + ?Sized>(bits: u16, disp: i64, sink: &mut CS) {
+    debug_assert!(is_signed_int(disp, 28, 2), "B26 out of range {:#x}", disp);
+    let imm26 = (disp >> 2) as u32 & 0x3ff_ffff;
+
+    sink.put4((u32::from(bits) << 24) | imm26);
+}
+
+/// Conditional branches and literal loads with a 19-bit word displacement.
+///
+///   31       23    4
+///   template imm19 rt
+///         24     5  0
+///
+/// The `rt` field is the condition code for `b.cond`.
+///
+/// Encoding bits: `template[31:24]`.
+fn put_b19<CS: CodeSink + ?Sized>(bits: u16, disp: i64, rt: u32, sink: &mut CS) {
+    debug_assert!(is_signed_int(disp, 21, 2), "B19 out of range {:#x}", disp);
+    let imm19 = (disp >> 2) as u32 & 0x7_ffff;
+
+    sink.put4((u32::from(bits) << 24) | (imm19 << 5) | (rt & 0x1f));
+}
+
+/// Get the condition code for an integer condition.
+fn icc2cond(cond: IntCC) -> u32 {
+    use ir::condcodes::IntCC::*;
+    match cond {
+        Equal => 0x0,
+        NotEqual => 0x1,
+        UnsignedGreaterThanOrEqual => 0x2,
+        UnsignedLessThan => 0x3,
+        // 0x4 = Negative.
+        // 0x5 = !Negative.
+        Overflow => 0x6,
+        NotOverflow => 0x7,
+        UnsignedGreaterThan => 0x8,
+        UnsignedLessThanOrEqual => 0x9,
+        SignedGreaterThanOrEqual => 0xa,
+        SignedLessThan => 0xb,
+        SignedGreaterThan => 0xc,
+        SignedLessThanOrEqual => 0xd,
+    }
+}
+
+/// Get the condition code for a floating point condition.
+///
+/// The fcmp instruction sets the NZCV flags like this:
+///
+///    UN: 0011
+///    GT: 0010
+///    LT: 1000
+///    EQ: 0110
+///
+#[cfg_attr(rustfmt, rustfmt_skip)]
+fn fcc2cond(cond: FloatCC) -> u32 {
+    use ir::condcodes::FloatCC::*;
+    match cond {
+        Ordered                       => 0x7, // EQ|LT|GT => vc (V=0)
+        Unordered                     => 0x6, // UN       => vs (V=1)
+        Equal                         => 0x0, // EQ       => eq (Z=1)
+        NotEqual                      => 0x1, // UN|LT|GT => ne (Z=0)
+        LessThan                      => 0x4, // LT       => mi (N=1)
+        LessThanOrEqual               => 0x9, // LT|EQ    => ls (C=0|Z=1)
+        GreaterThan                   => 0xc, // GT       => gt (Z=0&N=V)
+        GreaterThanOrEqual            => 0xa, // GT|EQ    => ge (N=V)
+        UnorderedOrLessThan           => 0xb, // UN|LT    => lt (N!=V)
+        UnorderedOrLessThanOrEqual    => 0xd, // UN|LT|EQ => le (Z=1|N!=V)
+        UnorderedOrGreaterThan        => 0x8, // UN|GT    => hi (C=1&Z=0)
+        UnorderedOrGreaterThanOrEqual => 0x2, // UN|GT|EQ => cs (C=1)
+        OrderedNotEqual |                     // LT|GT
+        UnorderedOrEqual                      // UN|EQ
+        => panic!("{} not supported", cond),
+    }
+}
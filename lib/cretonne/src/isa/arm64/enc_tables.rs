@@ -1,10 +1,284 @@
 //! Encoding tables for ARM64 ISA.
 
-use ir;
-use isa;
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::{self, InstBuilder};
+use ir::condcodes::{IntCC, FloatCC};
+use ir::immediates::{Ieee32, Ieee64, Offset32};
 use isa::constraints::*;
 use isa::enc_tables::*;
 use isa::encoding::RecipeSizing;
+use isa;
+use legalizer::expand_stack_check;
+use predicates;
+use super::registers::*;
 
 include!(concat!(env!("OUT_DIR"), "/encoding-arm64.rs"));
 include!(concat!(env!("OUT_DIR"), "/legalize-arm64.rs"));
+
+/// Expand the `sdiv` and `srem` instructions using `a64_sdiv`.
+///
+/// The ARM64 `sdiv` instruction doesn't trap, so the division by zero and overflow checks are
+/// explicit.
+fn expand_sdivrem(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (x, y, is_srem) = match func.dfg[inst] {
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Sdiv,
+            args,
+        } => (args[0], args[1], false),
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Srem,
+            args,
+        } => (args[0], args[1], true),
+        _ => panic!("Need sdiv/srem: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let is_zero = pos.ins().ifcmp_imm(y, 0);
+    pos.ins().trapif(
+        IntCC::Equal,
+        is_zero,
+        ir::TrapCode::IntegerDivisionByZero,
+    );
+
+    if is_srem {
+        // The remainder of `INT_MIN / -1` is 0, which is what the wrapping quotient gives.
+        let quot = pos.ins().a64_sdiv(x, y);
+        let prod = pos.ins().imul(quot, y);
+        pos.func.dfg.replace(inst).isub(x, prod);
+        return;
+    }
+
+    // Trap when x == INT_MIN and y == -1, which is the case when both `x ^ INT_MIN` and `y + 1`
+    // are 0.
+    let xmin = pos.ins().bxor_imm(x, -1i64 << (ty.lane_bits() - 1));
+    let yp1 = pos.ins().iadd_imm(y, 1);
+    let both = pos.ins().bor(xmin, yp1);
+    let is_ovf = pos.ins().ifcmp_imm(both, 0);
+    pos.ins().trapif(
+        IntCC::Equal,
+        is_ovf,
+        ir::TrapCode::IntegerOverflow,
+    );
+    pos.func.dfg.replace(inst).a64_sdiv(x, y);
+}
+
+/// Expand the `udiv` and `urem` instructions using `a64_udiv`.
+fn expand_udivrem(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (x, y, is_urem) = match func.dfg[inst] {
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Udiv,
+            args,
+        } => (args[0], args[1], false),
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Urem,
+            args,
+        } => (args[0], args[1], true),
+        _ => panic!("Need udiv/urem: {}", func.dfg.display_inst(inst, None)),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let is_zero = pos.ins().ifcmp_imm(y, 0);
+    pos.ins().trapif(
+        IntCC::Equal,
+        is_zero,
+        ir::TrapCode::IntegerDivisionByZero,
+    );
+
+    if is_urem {
+        let quot = pos.ins().a64_udiv(x, y);
+        let prod = pos.ins().imul(quot, y);
+        pos.func.dfg.replace(inst).isub(x, prod);
+    } else {
+        pos.func.dfg.replace(inst).a64_udiv(x, y);
+    }
+}
+
+/// Expand `fcvt_to_sint` using `a64_fcvtzs`.
+///
+/// The `fcvtzs` instruction saturates instead of trapping, so the input range is checked before
+/// the conversion. Unlike Intel, this doesn't need any branches.
+fn expand_fcvt_to_sint(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let x;
+    match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::FcvtToSint,
+            arg,
+        } => x = arg,
+        _ => panic!("Need fcvt_to_sint: {}", func.dfg.display_inst(inst, None)),
+    }
+    let xty = func.dfg.value_type(x);
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    // Determine the largest floating point number that is too small to convert to INT_MIN.
+    let mut overflow_cc = FloatCC::LessThan;
+    let output_bits = ty.lane_bits();
+    let (flimit, fmax) = match xty {
+        ir::types::F32 => {
+            let flimit = pos.ins().f32const(if output_bits < 32 {
+                overflow_cc = FloatCC::LessThanOrEqual;
+                Ieee32::fcvt_to_sint_negative_overflow(output_bits)
+            } else {
+                Ieee32::pow2(output_bits - 1).neg()
+            });
+            (flimit, pos.ins().f32const(Ieee32::pow2(output_bits - 1)))
+        }
+        ir::types::F64 => {
+            let flimit = pos.ins().f64const(if output_bits < 64 {
+                overflow_cc = FloatCC::LessThanOrEqual;
+                Ieee64::fcvt_to_sint_negative_overflow(output_bits)
+            } else {
+                Ieee64::pow2(output_bits - 1).neg()
+            });
+            (flimit, pos.ins().f64const(Ieee64::pow2(output_bits - 1)))
+        }
+        _ => panic!("Can't convert {}", xty),
+    };
+
+    // The comparison with the lower limit also detects NaN.
+    let low = pos.ins().ffcmp(x, flimit);
+    pos.ins().trapff(
+        FloatCC::Unordered,
+        low,
+        ir::TrapCode::BadConversionToInteger,
+    );
+    pos.ins().trapff(overflow_cc, low, ir::TrapCode::IntegerOverflow);
+
+    // 2^(N-1) is the smallest positive value that is too large.
+    let high = pos.ins().ffcmp(x, fmax);
+    pos.ins().trapff(
+        FloatCC::GreaterThanOrEqual,
+        high,
+        ir::TrapCode::IntegerOverflow,
+    );
+
+    pos.func.dfg.replace(inst).a64_fcvtzs(ty, x);
+}
+
+/// Expand `fcvt_to_uint` using `a64_fcvtzu`.
+///
+/// Valid inputs are in the range `(-1, 2^N)`, where the values in `(-1, 0]` truncate to 0.
+fn expand_fcvt_to_uint(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let x;
+    match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::FcvtToUint,
+            arg,
+        } => x = arg,
+        _ => panic!("Need fcvt_to_uint: {}", func.dfg.display_inst(inst, None)),
+    }
+    let xty = func.dfg.value_type(x);
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let (fmin, fmax) = match xty {
+        ir::types::F32 => {
+            (
+                pos.ins().f32const(Ieee32::with_float(-1.0)),
+                pos.ins().f32const(Ieee32::pow2(ty.lane_bits())),
+            )
+        }
+        ir::types::F64 => {
+            (
+                pos.ins().f64const(Ieee64::with_float(-1.0)),
+                pos.ins().f64const(Ieee64::pow2(ty.lane_bits())),
+            )
+        }
+        _ => panic!("Can't convert {}", xty),
+    };
+
+    // The comparison with the lower limit also detects NaN.
+    let low = pos.ins().ffcmp(x, fmin);
+    pos.ins().trapff(
+        FloatCC::Unordered,
+        low,
+        ir::TrapCode::BadConversionToInteger,
+    );
+    pos.ins().trapff(
+        FloatCC::LessThanOrEqual,
+        low,
+        ir::TrapCode::IntegerOverflow,
+    );
+
+    let high = pos.ins().ffcmp(x, fmax);
+    pos.ins().trapff(
+        FloatCC::GreaterThanOrEqual,
+        high,
+        ir::TrapCode::IntegerOverflow,
+    );
+
+    pos.func.dfg.replace(inst).a64_fcvtzu(ty, x);
+}
+
+/// Expand a load or store whose offset can't be encoded by adding the offset to the address
+/// first.
+fn expand_mem_offset(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (addr, offset) = match func.dfg[inst] {
+        ir::InstructionData::Load { arg, offset, .. } => (arg, offset),
+        ir::InstructionData::Store { args, offset, .. } => (args[1], offset),
+        _ => panic!("Need load/store: {}", func.dfg.display_inst(inst, None)),
+    };
+    let offset: i64 = offset.into();
+    if offset == 0 {
+        panic!("Can't encode {}", func.dfg.display_inst(inst, None));
+    }
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    let base = pos.ins().iadd_imm(addr, offset);
+
+    match pos.func.dfg[inst] {
+        ir::InstructionData::Load {
+            ref mut arg,
+            ref mut offset,
+            ..
+        } => {
+            *arg = base;
+            *offset = Offset32::new(0);
+        }
+        ir::InstructionData::Store {
+            ref mut args,
+            ref mut offset,
+            ..
+        } => {
+            args[1] = base;
+            *offset = Offset32::new(0);
+        }
+        _ => unreachable!(),
+    }
+}
@@ -6,20 +6,24 @@ mod binemit;
 mod enc_tables;
 mod registers;
 
-use binemit::{CodeSink, MemoryCodeSink, emit_function};
+use binemit::{CodeSink, MemoryCodeSink, emit_function, emit_function_checked, no_padding};
 use super::super::settings as shared_settings;
 use isa::enc_tables::{lookup_enclist, Encodings};
 use isa::Builder as IsaBuilder;
-use isa::{TargetIsa, RegInfo, RegClass, EncInfo};
+use isa::{TargetIsa, RegInfo, RegClass, EncInfo, CallConvDescriptor};
 use ir;
 use regalloc;
+use result;
+use timing;
 use std::fmt;
 use std::boxed::Box;
+use std::vec::Vec;
 
 #[allow(dead_code)]
 struct Isa {
     shared_flags: shared_settings::Flags,
     isa_flags: settings::Flags,
+    libcalls: ir::LibCallLinkages,
 }
 
 /// Get an ISA builder for creating ARM64 targets.
@@ -27,16 +31,21 @@ pub fn isa_builder() -> IsaBuilder {
     IsaBuilder {
         setup: settings::builder(),
         constructor: isa_constructor,
+        call_convs: None,
+        libcalls: ir::LibCallLinkages::new(),
     }
 }
 
 fn isa_constructor(
     shared_flags: shared_settings::Flags,
     builder: &shared_settings::Builder,
+    _call_convs: Vec<CallConvDescriptor>,
+    libcalls: ir::LibCallLinkages,
 ) -> Box<TargetIsa> {
     Box::new(Isa {
         isa_flags: settings::Flags::new(&shared_flags, builder),
         shared_flags,
+        libcalls,
     })
 }
 
@@ -53,6 +62,10 @@ impl TargetIsa for Isa {
         registers::INFO.clone()
     }
 
+    fn libcall_linkage(&self, libcall: ir::LibCall) -> &ir::LibCallLinkage {
+        self.libcalls.get(libcall)
+    }
+
     fn encoding_info(&self) -> EncInfo {
         enc_tables::INFO.clone()
     }
@@ -89,6 +102,11 @@ impl TargetIsa for Isa {
         abi::allocatable_registers(func)
     }
 
+    fn prologue_epilogue(&self, func: &mut ir::Function) -> result::CtonResult {
+        let _tt = timing::prologue_epilogue();
+        abi::prologue_epilogue(func, self)
+    }
+
     fn emit_inst(
         &self,
         func: &ir::Function,
@@ -100,7 +118,11 @@ impl TargetIsa for Isa {
     }
 
     fn emit_function(&self, func: &ir::Function, sink: &mut MemoryCodeSink) {
-        emit_function(func, binemit::emit_inst, sink)
+        if self.shared_flags.check_emission() {
+            emit_function_checked(self, func, binemit::emit_inst, no_padding, sink)
+        } else {
+            emit_function(func, binemit::emit_inst, no_padding, sink)
+        }
     }
 }
 
@@ -0,0 +1,55 @@
+//! Calling conventions defined by the embedder.
+//!
+//! Besides the built-in calling conventions, an embedder can describe its own conventions with a
+//! `CallConvDescriptor` and register them with `isa::Builder::register_call_conv()`. Functions and
+//! signatures then refer to them as `CallConv::Custom(n)`.
+
+use isa::RegUnit;
+use std::string::String;
+use std::vec::Vec;
+
+/// Description of a custom calling convention.
+///
+/// The registers are given as register units of the target ISA. Arguments and return values are
+/// assigned to the listed registers in order, and the remaining arguments are passed on the stack.
+/// Integer and floating point values are assigned independently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallConvDescriptor {
+    /// Name of the calling convention, used for diagnostics.
+    pub name: String,
+
+    /// Registers for integer and boolean arguments, in order.
+    pub int_args: Vec<RegUnit>,
+
+    /// Registers for floating point and vector arguments, in order.
+    pub float_args: Vec<RegUnit>,
+
+    /// Registers for integer and boolean return values, in order.
+    pub int_returns: Vec<RegUnit>,
+
+    /// Registers for floating point and vector return values, in order.
+    pub float_returns: Vec<RegUnit>,
+
+    /// Registers that must be preserved by the callee.
+    pub callee_saved: Vec<RegUnit>,
+
+    /// Required alignment of the stack pointer at calls, in bytes.
+    pub stack_align: u32,
+}
+
+impl CallConvDescriptor {
+    /// Create a descriptor for a convention named `name` that passes everything on the stack and
+    /// doesn't preserve any registers.
+    pub fn new(name: &str, stack_align: u32) -> Self {
+        debug_assert!(stack_align.is_power_of_two(), "Stack alignment must be a power of two");
+        Self {
+            name: name.into(),
+            int_args: Vec::new(),
+            float_args: Vec::new(),
+            int_returns: Vec::new(),
+            float_returns: Vec::new(),
+            callee_saved: Vec::new(),
+            stack_align,
+        }
+    }
+}
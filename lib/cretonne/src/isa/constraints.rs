@@ -9,7 +9,7 @@
 
 use binemit::CodeOffset;
 use isa::{RegClass, RegUnit};
-use ir::{Function, ValueLoc, Inst};
+use ir::{Function, InstructionData, ValueLoc, Inst};
 use regalloc::RegDiversions;
 
 /// Register constraint for a single value operand or instruction result.
@@ -51,6 +51,11 @@ impl OperandConstraint {
             }
         }
     }
+
+    /// Does satisfying this constraint also satisfy `other`?
+    pub fn implies(&self, other: &OperandConstraint) -> bool {
+        self.kind == other.kind && other.regclass.has_subclass(self.regclass)
+    }
 }
 
 /// The different kinds of operand constraints.
@@ -148,7 +153,27 @@ impl RecipeConstraints {
             }
         }
 
-        true
+        // The destination register of a diversion is encoded like the register of its operand,
+        // so it must be in the same register class.
+        match func.dfg[inst] {
+            InstructionData::RegMove { dst, .. } |
+            InstructionData::RegFill { dst, .. } => {
+                self.ins.first().map_or(true, |c| c.regclass.contains(dst))
+            }
+            _ => true,
+        }
+    }
+
+    /// Do operands satisfying these constraints always satisfy `other` too?
+    ///
+    /// This is the case when `other` accepts the same kinds of operands in the same or larger
+    /// register classes, and doesn't clobber flags unless these constraints do.
+    pub fn implies(&self, other: &RecipeConstraints) -> bool {
+        fn all_imply(a: &[OperandConstraint], b: &[OperandConstraint]) -> bool {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.implies(y))
+        }
+        all_imply(self.ins, other.ins) && all_imply(self.outs, other.outs) &&
+            (self.clobbers_flags || !other.clobbers_flags)
     }
 }
 
@@ -1,23 +1,38 @@
 //! Intel ABI implementation.
 
 use ir;
-use isa::{RegClass, RegUnit, TargetIsa};
+use isa::{CallConvDescriptor, RegClass, RegUnit, TargetIsa};
 use regalloc::AllocatableSet;
 use settings as shared_settings;
 use super::registers::{GPR, FPR, RU};
-use abi::{ArgAction, ValueConversion, ArgAssigner, legalize_args};
-use ir::{AbiParam, ArgumentPurpose, ArgumentLoc, ArgumentExtension, CallConv, InstBuilder};
+use abi::{ArgAction, ValueConversion, ArgAssigner, legalize_args, legalize_struct_return};
+use ir::{AbiParam, ArgumentPurpose, ArgumentLoc, ArgumentExtension, CallConv, InstBuilder,
+         InstructionData, ProgramOrder, ValueDef, ValueLoc};
+use ir::instructions::BranchInfo;
+use ir::condcodes::IntCC;
 use ir::stackslot::{StackSize, StackOffset};
 use ir::immediates::Imm64;
 use stack_layout::layout_stack;
+use std::cmp;
 use std::i32;
 use cursor::{Cursor, EncCursor, CursorPosition};
 use result;
+use std::vec::Vec;
 
 
 /// Argument registers for x86-64
 static ARG_GPRS: [RU; 6] = [RU::rdi, RU::rsi, RU::rdx, RU::rcx, RU::r8, RU::r9];
 
+/// Argument registers for the Windows x64 calling convention.
+static WINDOWS_ARG_GPRS: [RU; 4] = [RU::rcx, RU::rdx, RU::r8, RU::r9];
+
+/// Size of the shadow space the caller reserves above the return address for a Windows x64 callee
+/// to save its register arguments.
+const WINDOWS_SHADOW_SPACE: u32 = 32;
+
+/// Stack frames smaller than a page don't need to be probed on Windows.
+const WINDOWS_PAGE_SIZE: StackSize = 4096;
+
 /// Return value registers.
 static RET_GPRS: [RU; 3] = [RU::rax, RU::rdx, RU::rcx];
 
@@ -25,28 +40,75 @@ struct Args {
     pointer_bytes: u32,
     pointer_bits: u16,
     pointer_type: ir::Type,
-    gpr: &'static [RU],
+    gpr: Vec<RegUnit>,
     gpr_used: usize,
-    fpr_limit: usize,
+    fpr: Vec<RegUnit>,
     fpr_used: usize,
+    /// Do integer and floating point arguments share the register positions?
+    positional: bool,
     offset: u32,
     call_conv: CallConv,
 }
 
 impl Args {
-    fn new(bits: u16, gpr: &'static [RU], fpr_limit: usize, call_conv: CallConv) -> Args {
+    fn new(bits: u16, gpr: &[RU], fpr_limit: usize, call_conv: CallConv) -> Args {
+        Self::with_registers(
+            bits,
+            gpr.iter().map(|&r| r as RegUnit).collect(),
+            (0..fpr_limit).map(|i| FPR.unit(i)).collect(),
+            call_conv,
+        )
+    }
+
+    fn with_registers(
+        bits: u16,
+        gpr: Vec<RegUnit>,
+        fpr: Vec<RegUnit>,
+        call_conv: CallConv,
+    ) -> Args {
         Args {
             pointer_bytes: u32::from(bits) / 8,
             pointer_bits: bits,
             pointer_type: ir::Type::int(bits).unwrap(),
             gpr,
             gpr_used: 0,
-            fpr_limit,
+            fpr,
             fpr_used: 0,
+            positional: false,
             offset: 0,
             call_conv: call_conv,
         }
     }
+
+    /// Create an argument assigner for the Windows x64 calling convention.
+    ///
+    /// The N'th argument goes in the N'th integer or floating point argument register, depending on
+    /// its type, and the stack arguments follow the shadow space.
+    fn windows(bits: u16, call_conv: CallConv) -> Args {
+        let mut args = Self::new(bits, &WINDOWS_ARG_GPRS, 4, call_conv);
+        args.positional = true;
+        args.offset = WINDOWS_SHADOW_SPACE;
+        args
+    }
+
+    /// Take the next register from `regs`, or `None` if they have all been used.
+    fn next_reg(&mut self, float: bool) -> Option<RegUnit> {
+        let (regs, used) = if float {
+            (&self.fpr, self.fpr_used)
+        } else {
+            (&self.gpr, self.gpr_used)
+        };
+        let reg = *regs.get(used)?;
+        if self.positional {
+            self.gpr_used = used + 1;
+            self.fpr_used = used + 1;
+        } else if float {
+            self.fpr_used += 1;
+        } else {
+            self.gpr_used += 1;
+        }
+        Some(reg)
+    }
 }
 
 impl ArgAssigner for Args {
@@ -54,8 +116,13 @@ impl ArgAssigner for Args {
         let ty = arg.value_type;
 
         // Check for a legal type.
-        // We don't support SIMD yet, so break all vectors down.
+        // 128-bit vectors are passed in XMM registers on x86-64. Break all other vectors down.
         if ty.is_vector() {
+            if ty.bits() == 128 {
+                if let Some(reg) = self.next_reg(true) {
+                    return ArgumentLoc::Reg(reg).into();
+                }
+            }
             return ValueConversion::VectorSplit.into();
         }
 
@@ -90,17 +157,8 @@ impl ArgAssigner for Args {
             }
         }
 
-        // Try to use a GPR.
-        if !ty.is_float() && self.gpr_used < self.gpr.len() {
-            let reg = self.gpr[self.gpr_used] as RegUnit;
-            self.gpr_used += 1;
-            return ArgumentLoc::Reg(reg).into();
-        }
-
-        // Try to use an FPR.
-        if ty.is_float() && self.fpr_used < self.fpr_limit {
-            let reg = FPR.unit(self.fpr_used);
-            self.fpr_used += 1;
+        // Try to use a GPR or an FPR.
+        if let Some(reg) = self.next_reg(ty.is_float()) {
             return ArgumentLoc::Reg(reg).into();
         }
 
@@ -113,22 +171,89 @@ impl ArgAssigner for Args {
 }
 
 /// Legalize `sig`.
-pub fn legalize_signature(sig: &mut ir::Signature, flags: &shared_settings::Flags, _current: bool) {
-    let bits;
-    let mut args;
+///
+/// The custom calling conventions are described by `call_convs`.
+pub fn legalize_signature(
+    sig: &mut ir::Signature,
+    flags: &shared_settings::Flags,
+    call_convs: &[CallConvDescriptor],
+    current: bool,
+) {
+    let bits = if flags.is_64bit() { 64 } else { 32 };
+    let (mut args, mut rets) = match sig.call_conv {
+        CallConv::Custom(n) => {
+            let desc = call_convs.get(usize::from(n)).unwrap_or_else(|| {
+                panic!("Calling convention {} is not registered", sig.call_conv)
+            });
+            (
+                Args::with_registers(
+                    bits,
+                    desc.int_args.clone(),
+                    desc.float_args.clone(),
+                    sig.call_conv,
+                ),
+                Args::with_registers(
+                    bits,
+                    desc.int_returns.clone(),
+                    desc.float_returns.clone(),
+                    sig.call_conv,
+                ),
+            )
+        }
+        CallConv::WindowsFastcall if bits == 64 => (
+            Args::windows(bits, sig.call_conv),
+            Args::new(bits, &RET_GPRS, 2, sig.call_conv),
+        ),
+        _ if bits == 64 => (
+            Args::new(bits, &ARG_GPRS, 8, sig.call_conv),
+            Args::new(bits, &RET_GPRS, 2, sig.call_conv),
+        ),
+        _ => (
+            Args::new(bits, &[], 0, sig.call_conv),
+            Args::new(bits, &RET_GPRS, 2, sig.call_conv),
+        ),
+    };
 
-    if flags.is_64bit() {
-        bits = 64;
-        args = Args::new(bits, &ARG_GPRS, 8, sig.call_conv);
+    // Return values that don't fit in the registers are returned in memory on x86-64. The
+    // pointer to the memory is passed as the first argument, and returned in `%rax`. The i386
+    // ABI also requires the callee to pop the pointer from the stack, which isn't supported.
+    let returns_in_memory = if bits == 64 {
+        legalize_struct_return(sig, &mut rets, ir::types::I64)
     } else {
-        bits = 32;
-        args = Args::new(bits, &[], 0, sig.call_conv);
+        legalize_args(&mut sig.returns, &mut rets);
+        false
+    };
+    if returns_in_memory {
+        sig.returns.push(ir::AbiParam::special_reg(
+            ir::types::I64,
+            ArgumentPurpose::StructReturn,
+            RU::rax as RegUnit,
+        ));
     }
 
-    legalize_args(&mut sig.params, &mut args);
-
-    let mut rets = Args::new(bits, &RET_GPRS, 2, sig.call_conv);
-    legalize_args(&mut sig.returns, &mut rets);
+    match sig.fixed_params {
+        Some(fixed) => {
+            // Variadic arguments are assigned like the fixed ones, but the fixed parameters may
+            // have been split, so recompute their number.
+            let mut variadic = sig.params.split_off(fixed);
+            legalize_args(&mut sig.params, &mut args);
+            sig.fixed_params = Some(sig.params.len());
+            legalize_args(&mut variadic, &mut args);
+            sig.params.append(&mut variadic);
+
+            // System V callers pass the number of vector registers used in `%al`.
+            if bits == 64 && sig.call_conv == CallConv::Native && !current &&
+                sig.special_param_index(ArgumentPurpose::VarArgCount).is_none()
+            {
+                sig.params.push(ir::AbiParam::special_reg(
+                    ir::types::I32,
+                    ArgumentPurpose::VarArgCount,
+                    RU::rax as RegUnit,
+                ));
+            }
+        }
+        None => legalize_args(&mut sig.params, &mut args),
+    }
 }
 
 /// Get register class for a type appearing in a legalized signature.
@@ -142,7 +267,7 @@ pub fn regclass_for_abi_type(ty: ir::Type) -> RegClass {
 
 /// Get the set of allocatable registers for `func`.
 pub fn allocatable_registers(
-    _func: &ir::Function,
+    func: &ir::Function,
     flags: &shared_settings::Flags,
 ) -> AllocatableSet {
     let mut regs = AllocatableSet::new();
@@ -157,25 +282,184 @@ pub fn allocatable_registers(
         }
     }
 
+    // The Windows x64 calling convention preserves `%xmm6` - `%xmm15`. The prologue only saves
+    // GPRs, so keep those registers unused.
+    if func.signature.call_conv == CallConv::WindowsFastcall {
+        for i in 6..16 {
+            regs.take(FPR, FPR.unit(i));
+        }
+    }
+
     regs
 }
 
-/// Get the set of callee-saved registers.
-pub fn callee_saved_registers(flags: &shared_settings::Flags) -> &'static [RU] {
-    if flags.is_64bit() {
-        &[RU::rbx, RU::r12, RU::r13, RU::r14, RU::r15]
-    } else {
+/// Get the set of callee-saved registers for the `call_conv` calling convention.
+pub fn callee_saved_registers(
+    flags: &shared_settings::Flags,
+    call_conv: CallConv,
+) -> Vec<RegUnit> {
+    let csrs: &[RU] = if !flags.is_64bit() {
         &[RU::rbx, RU::rsi, RU::rdi]
-    }
+    } else if call_conv == CallConv::WindowsFastcall {
+        &[RU::rbx, RU::rsi, RU::rdi, RU::r12, RU::r13, RU::r14, RU::r15]
+    } else {
+        &[RU::rbx, RU::r12, RU::r13, RU::r14, RU::r15]
+    };
+    csrs.iter().map(|&r| r as RegUnit).collect()
 }
 
 pub fn prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    check_tail_calls(func)?;
+    reserve_shadow_space(func);
     match func.signature.call_conv {
-        ir::CallConv::Native => native_prologue_epilogue(func, isa),
+        ir::CallConv::Native => {
+            let csrs = callee_saved_registers(isa.flags(), func.signature.call_conv);
+            native_prologue_epilogue(func, isa, 16, &csrs)
+        }
+        ir::CallConv::WindowsFastcall => windows_prologue_epilogue(func, isa),
         ir::CallConv::SpiderWASM => spiderwasm_prologue_epilogue(func, isa),
+        ir::CallConv::Custom(_) => {
+            let desc = isa.call_conv_descriptor(func.signature.call_conv).ok_or(
+                result::CtonError::InvalidInput,
+            )?;
+            // The stack check needs `%rax` before the prologue.
+            if func.stack_limit.is_some() &&
+                desc.int_args.contains(&(RU::rax as RegUnit))
+            {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+            // `%rbp` is always saved as the frame pointer.
+            let csrs: Vec<RegUnit> = desc.callee_saved
+                .iter()
+                .cloned()
+                .filter(|&r| r != RU::rbp as RegUnit)
+                .collect();
+            native_prologue_epilogue(func, isa, desc.stack_align, &csrs)
+        }
+    }
+}
+
+/// Check that the tail calls in `func` are supported.
+///
+/// A tail call jumps to the callee after the epilogue has released the stack frame, so all of the
+/// callee's arguments must be passed in registers. The SpiderWASM epilogues are inserted by the
+/// embedder, so tail calls can't be used with that calling convention. A Windows x64 callee may
+/// write to the shadow space above its return address, so only Windows x64 functions, whose
+/// callers provided the shadow space, can tail call them.
+fn check_tail_calls(func: &ir::Function) -> result::CtonResult {
+    for ebb in func.layout.ebbs() {
+        let inst = match func.layout.last_inst(ebb) {
+            Some(inst) => inst,
+            None => continue,
+        };
+        if !func.dfg[inst].opcode().is_terminator() {
+            continue;
+        }
+        if let Some(sig) = func.dfg.call_signature(inst) {
+            let callee = &func.dfg.signatures[sig];
+            if func.signature.call_conv == CallConv::SpiderWASM ||
+                (callee.call_conv == CallConv::WindowsFastcall &&
+                     func.signature.call_conv != CallConv::WindowsFastcall) ||
+                callee.params.iter().any(|arg| !arg.location.is_reg())
+            {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reserve the shadow space for the Windows x64 functions called by `func`.
+///
+/// The shadow space is at the bottom of the outgoing argument area, so it is reserved even when
+/// there are no stack arguments.
+fn reserve_shadow_space(func: &mut ir::Function) {
+    let calls_windows = func.layout.ebbs().any(|ebb| {
+        func.layout.ebb_insts(ebb).any(|inst| match func.dfg.call_signature(inst) {
+            Some(sig) => func.dfg.signatures[sig].call_conv == CallConv::WindowsFastcall,
+            None => false,
+        })
+    });
+    if calls_windows {
+        let mut ss = ir::StackSlotData::new(ir::StackSlotKind::OutgoingArg, WINDOWS_SHADOW_SPACE);
+        ss.offset = Some(0);
+        func.stack_slots.push(ss);
     }
 }
 
+/// Insert a prologue and epilogue for the Windows x64 calling convention.
+///
+/// The native prologue shape can be described by Windows unwind codes: Each push and the stack
+/// allocation has an unwind code, and `%rbp` is set to the stack pointer right after it is pushed,
+/// so the frame register offset is 0. The epilogues deallocate the frame with an `add` to `%rsp`
+/// before popping the saved registers, which is one of the epilogue forms recognized by the
+/// Windows unwinder.
+///
+/// Windows requires stack frames larger than a page to be probed one page at a time. When the
+/// `enable_probestack` setting is enabled, the prologue calls the `Probestack` library routine
+/// before allocating such a frame. See `insert_probestack()`.
+fn windows_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    if !isa.flags().is_64bit() {
+        return Err(result::CtonError::InvalidInput);
+    }
+    let csrs = callee_saved_registers(isa.flags(), func.signature.call_conv);
+    native_prologue_epilogue(func, isa, 16, &csrs)?;
+    if isa.flags().enable_probestack() {
+        insert_probestack(func, isa)?;
+    }
+    Ok(())
+}
+
+/// Insert a call to the `Probestack` routine before the stack allocation in the prologue of
+/// `func`, if the allocation is a page or more.
+///
+/// The routine follows the conventions of the Windows `__chkstk`: It takes the allocation size in
+/// `%rax` and touches the pages below the stack pointer in order, without moving it or changing
+/// any register other than `%rax`, `%r10` and `%r11`. None of these registers holds an argument
+/// or a callee-saved register at this point.
+///
+/// The routine must be linked directly, since the prologue can't load its address from a global
+/// variable.
+fn insert_probestack(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    let entry = func.layout.entry_block().expect("missing entry block");
+    let mut alloc = None;
+    for inst in func.layout.ebb_insts(entry) {
+        if let InstructionData::UnaryImm { opcode: ir::Opcode::AdjustSpImm, imm } = func.dfg[inst] {
+            let imm: i64 = imm.into();
+            alloc = Some((inst, -imm));
+            break;
+        }
+    }
+    let (inst, size) = match alloc {
+        Some((inst, size)) if size >= i64::from(WINDOWS_PAGE_SIZE) => (inst, size),
+        _ => return Ok(()),
+    };
+    let name = match *isa.libcall_linkage(ir::LibCall::Probestack) {
+        ir::LibCallLinkage::Direct(ref name) => name.clone(),
+        ir::LibCallLinkage::Indirect(_) => return Err(result::CtonError::ImplLimitExceeded),
+    };
+
+    let mut sig = ir::Signature::new(CallConv::Native);
+    sig.params.push(AbiParam::special_reg(
+        ir::types::I64,
+        ArgumentPurpose::Normal,
+        RU::rax as RegUnit,
+    ));
+    sig.compute_argument_bytes();
+    let signature = func.import_signature(sig);
+    let probestack = func.import_function(ir::ExtFuncData {
+        name,
+        signature,
+        colocated: false,
+    });
+
+    let mut pos = EncCursor::new(func, isa).at_inst(inst);
+    let size = pos.ins().iconst(ir::types::I64, size);
+    pos.func.locations[size] = ValueLoc::Reg(RU::rax as RegUnit);
+    pos.ins().call(probestack, &[size]);
+    Ok(())
+}
+
 pub fn spiderwasm_prologue_epilogue(
     func: &mut ir::Function,
     isa: &TargetIsa,
@@ -185,6 +469,11 @@ pub fn spiderwasm_prologue_epilogue(
     let word_size = if isa.flags().is_64bit() { 8 } else { 4 };
     let bytes = StackSize::from(isa.flags().spiderwasm_prologue_words()) * word_size;
 
+    // The embedder inserts the prologue, so it has to check the stack limit too.
+    if func.stack_limit.is_some() {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+
     let mut ss = ir::StackSlotData::new(ir::StackSlotKind::IncomingArg, bytes);
     ss.offset = Some(-(bytes as StackOffset));
     func.stack_slots.push(ss);
@@ -194,17 +483,25 @@ pub fn spiderwasm_prologue_epilogue(
 }
 
 /// Insert a System V-compatible prologue and epilogue.
-pub fn native_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
-    // The original 32-bit x86 ELF ABI had a 4-byte aligned stack pointer, but
-    // newer versions use a 16-byte aligned stack pointer.
-    let stack_align = 16;
+///
+/// The prologue saves the registers in `csrs` and aligns the stack frame to `stack_align` bytes.
+/// The original 32-bit x86 ELF ABI had a 4-byte aligned stack pointer, but newer versions use a
+/// 16-byte aligned stack pointer like x86-64.
+///
+/// The prologue is shrink-wrapped when the entry block begins with early exits that don't need a
+/// stack frame. See `shrink_wrap()`.
+pub fn native_prologue_epilogue(
+    func: &mut ir::Function,
+    isa: &TargetIsa,
+    stack_align: u32,
+    csrs: &[RegUnit],
+) -> result::CtonResult {
     let word_size = if isa.flags().is_64bit() { 8 } else { 4 };
     let csr_type = if isa.flags().is_64bit() {
         ir::types::I64
     } else {
         ir::types::I32
     };
-    let csrs = callee_saved_registers(isa.flags());
 
     // The reserved stack area is composed of:
     //   return address + frame pointer + all callee-saved registers
@@ -213,6 +510,8 @@ pub fn native_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> res
     // instruction. Each of the others we will then push explicitly. Then we
     // will adjust the stack pointer to make room for the rest of the required
     // space for this frame.
+    let placement = shrink_wrap(func, csrs);
+
     let csr_stack_size = ((csrs.len() + 2) * word_size as usize) as i32;
     func.create_stack_slot(ir::StackSlotData {
         kind: ir::StackSlotKind::IncomingArg,
@@ -233,8 +532,7 @@ pub fn native_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> res
     func.signature.returns.push(fp_arg);
 
     for csr in csrs.iter() {
-        let csr_arg =
-            ir::AbiParam::special_reg(csr_type, ir::ArgumentPurpose::CalleeSaved, *csr as RegUnit);
+        let csr_arg = ir::AbiParam::special_reg(csr_type, ir::ArgumentPurpose::CalleeSaved, *csr);
         func.signature.params.push(csr_arg);
         func.signature.returns.push(csr_arg);
     }
@@ -242,21 +540,179 @@ pub fn native_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> res
     // Set up the cursor and insert the prologue
     let entry_ebb = func.layout.entry_block().expect("missing entry block");
     let mut pos = EncCursor::new(func, isa).at_first_insertion_point(entry_ebb);
+    if let Some(stack_limit) = pos.func.stack_limit {
+        // The return address has already been pushed by the caller.
+        let frame_size = i64::from(total_stack_size) - i64::from(word_size);
+        insert_stack_check(&mut pos, frame_size, stack_limit, csr_type)?;
+    }
+    let mut frameless = Vec::new();
+    if let Some((inst, exits)) = placement {
+        pos.goto_inst(inst);
+        frameless = exits;
+    }
     insert_native_prologue(&mut pos, local_stack_size, csr_type, csrs);
 
+    // The frameless exits return the saved registers unchanged.
+    let saved = {
+        let params = pos.func.dfg.ebb_params(entry_ebb);
+        params[params.len() - csrs.len() - 1..].to_vec()
+    };
+    for &ebb in &frameless {
+        let inst = pos.func.layout.last_inst(ebb).expect("empty frameless exit");
+        for &value in &saved {
+            pos.func.dfg.append_inst_arg(inst, value);
+        }
+    }
+
     // Reset the cursor and insert the epilogue
     let mut pos = pos.at_position(CursorPosition::Nowhere);
-    insert_native_epilogues(&mut pos, local_stack_size, csr_type, csrs);
+    insert_native_epilogues(&mut pos, local_stack_size, csr_type, csrs, &frameless);
 
     Ok(())
 }
 
+/// Find a shrink-wrapped placement of the prologue in `func`.
+///
+/// Functions often begin with a few checks that return early without needing a stack frame. This
+/// looks for a prefix of the entry block that doesn't need the frame, and whose branches lead to
+/// frameless exits: EBBs that only contain instructions that don't need the frame either and end
+/// with a `return`. If the frameless exits can only be reached from the prefix, the prologue is
+/// inserted after the prefix and the frameless exits return without an epilogue.
+///
+/// Returns the instruction that the prologue should be inserted before and the frameless exits,
+/// or `None` if the prologue should be at the top of the entry block.
+///
+/// The stack limit check must happen before any code runs, and Windows unwind information can't
+/// describe frameless code after the prologue, so those functions are not shrink-wrapped.
+fn shrink_wrap(func: &ir::Function, csrs: &[RegUnit]) -> Option<(ir::Inst, Vec<ir::Ebb>)> {
+    if func.stack_limit.is_some() || func.signature.call_conv == CallConv::WindowsFastcall {
+        return None;
+    }
+    let entry = func.layout.entry_block()?;
+
+    let mut prologue = None;
+    let mut exits = Vec::new();
+    for inst in func.layout.ebb_insts(entry) {
+        if func.dfg[inst].opcode().is_terminator() || needs_frame(func, inst, csrs) {
+            prologue = Some(inst);
+            break;
+        }
+        match func.dfg.analyze_branch(inst) {
+            BranchInfo::NotABranch => {}
+            BranchInfo::SingleDest(dest, _) if is_frameless_exit(func, dest, csrs) => {
+                if !exits.contains(&dest) {
+                    exits.push(dest);
+                }
+            }
+            _ => {
+                prologue = Some(inst);
+                break;
+            }
+        }
+    }
+    let prologue = prologue?;
+    if exits.is_empty() {
+        return None;
+    }
+
+    let in_prefix = |inst: ir::Inst| {
+        func.layout.inst_ebb(inst) == Some(entry) &&
+            func.layout.cmp(inst, prologue) == cmp::Ordering::Less
+    };
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if in_prefix(inst) {
+                continue;
+            }
+            // The frameless exits must not be reachable after the prologue.
+            match func.dfg.analyze_branch(inst) {
+                BranchInfo::SingleDest(dest, _) if exits.contains(&dest) => return None,
+                BranchInfo::Table(jt) => {
+                    if exits.iter().any(|&exit| func.jump_tables[jt].branches_to(exit)) {
+                        return None;
+                    }
+                }
+                _ => {}
+            }
+            // The prologue clobbers the CPU flags.
+            if exits.contains(&ebb) {
+                continue;
+            }
+            for &arg in func.dfg.inst_args(inst) {
+                if let ValueDef::Result(def, _) = func.dfg.value_def(arg) {
+                    if func.dfg.value_type(arg).is_flags() && in_prefix(def) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    Some((prologue, exits))
+}
+
+/// Is `ebb` an exit that can return without a stack frame?
+fn is_frameless_exit(func: &ir::Function, ebb: ir::Ebb, csrs: &[RegUnit]) -> bool {
+    if Some(ebb) == func.layout.entry_block() {
+        return false;
+    }
+    match func.layout.last_inst(ebb) {
+        Some(inst) if func.dfg[inst].opcode() == ir::Opcode::Return => {}
+        _ => return false,
+    }
+    func.layout.ebb_insts(ebb).all(|inst| {
+        let is_branch = match func.dfg.analyze_branch(inst) {
+            BranchInfo::NotABranch => false,
+            _ => true,
+        };
+        !is_branch && !needs_frame(func, inst, csrs)
+    })
+}
+
+/// Does `inst` need the stack frame set up by the prologue?
+///
+/// This is the case for calls, instructions that access the stack, and instructions that read or
+/// write a register in `csrs` or the frame pointer.
+fn needs_frame(func: &ir::Function, inst: ir::Inst, csrs: &[RegUnit]) -> bool {
+    let saved = |reg: RegUnit| reg == RU::rbp as RegUnit || csrs.contains(&reg);
+    let needs = |loc: ValueLoc| match loc {
+        ValueLoc::Reg(reg) => saved(reg),
+        ValueLoc::Stack(_) => true,
+        ValueLoc::Unassigned => false,
+    };
+
+    let data = &func.dfg[inst];
+    if data.opcode().is_call() {
+        return true;
+    }
+    match *data {
+        InstructionData::StackLoad { .. } |
+        InstructionData::StackStore { .. } |
+        InstructionData::RegSpill { .. } |
+        InstructionData::RegFill { .. } |
+        InstructionData::CopySpecial { .. } => return true,
+        InstructionData::RegMove { src, dst, .. } if saved(src) || saved(dst) => return true,
+        _ => {}
+    }
+    match data.opcode() {
+        ir::Opcode::AdjustSpImm |
+        ir::Opcode::IfcmpSp |
+        ir::Opcode::StackCheck |
+        ir::Opcode::X86Push |
+        ir::Opcode::X86Pop => return true,
+        _ => {}
+    }
+    func.dfg.inst_args(inst).iter().chain(func.dfg.inst_results(inst)).any(
+        |&value| needs(func.locations[value]),
+    )
+}
+
 /// Insert the prologue for a given function.
 fn insert_native_prologue(
     pos: &mut EncCursor,
     stack_size: i64,
     csr_type: ir::types::Type,
-    csrs: &'static [RU],
+    csrs: &[RegUnit],
 ) {
     // Append param to entry EBB
     let ebb = pos.current_ebb().expect("missing ebb under cursor");
@@ -274,7 +730,7 @@ fn insert_native_prologue(
         let csr_arg = pos.func.dfg.append_ebb_param(ebb, csr_type);
 
         // Assign it a location
-        pos.func.locations[csr_arg] = ir::ValueLoc::Reg(*reg as RegUnit);
+        pos.func.locations[csr_arg] = ir::ValueLoc::Reg(*reg);
 
         // Remember it so we can push it momentarily
         pos.ins().x86_push(csr_arg);
@@ -285,30 +741,111 @@ fn insert_native_prologue(
     }
 }
 
-/// Find all `return` instructions and insert epilogues before them.
+/// Insert a check that the stack frame of `frame_size` bytes doesn't extend below the address of
+/// the global variable `stack_limit`.
+///
+/// The check comes before the prologue, so it can only use `%rax` which is neither an argument
+/// register nor callee-saved.
+fn insert_stack_check(
+    pos: &mut EncCursor,
+    frame_size: i64,
+    stack_limit: ir::GlobalVar,
+    addr_type: ir::types::Type,
+) -> result::CtonResult {
+    let limit = insert_stack_limit_addr(pos, stack_limit, addr_type)?;
+    let threshold = pos.ins().iadd_imm(limit, frame_size);
+    pos.func.locations[threshold] = ValueLoc::Reg(RU::rax as RegUnit);
+    let flags = pos.ins().ifcmp_sp(threshold);
+    pos.func.locations[flags] = ValueLoc::Reg(RU::eflags as RegUnit);
+    pos.ins().trapif(
+        IntCC::UnsignedGreaterThan,
+        flags,
+        ir::TrapCode::StackOverflow,
+    );
+    Ok(())
+}
+
+/// Compute the address of the global variable `gv` in `%rax`.
+fn insert_stack_limit_addr(
+    pos: &mut EncCursor,
+    gv: ir::GlobalVar,
+    addr_type: ir::types::Type,
+) -> Result<ir::Value, result::CtonError> {
+    let rax = ValueLoc::Reg(RU::rax as RegUnit);
+    let addr = match pos.func.global_vars[gv] {
+        ir::GlobalVarData::VmCtx { offset } => {
+            let vmctx = pos.func.special_param(ArgumentPurpose::VMContext).ok_or(
+                result::CtonError::InvalidInput,
+            )?;
+            if let ValueLoc::Stack(_) = pos.func.locations[vmctx] {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+            let base = pos.ins().copy(vmctx);
+            pos.func.locations[base] = rax;
+            add_offset(pos, base, offset.into())
+        }
+        ir::GlobalVarData::Deref { base, offset } => {
+            let base = insert_stack_limit_addr(pos, base, addr_type)?;
+            let mut flags = ir::MemFlags::new();
+            flags.set_notrap();
+            flags.set_aligned();
+            let ptr = pos.ins().load(addr_type, flags, base, 0);
+            pos.func.locations[ptr] = rax;
+            add_offset(pos, ptr, offset.into())
+        }
+        ir::GlobalVarData::Sym { .. } => {
+            let addr = pos.ins().globalsym_addr(addr_type, gv);
+            pos.func.locations[addr] = rax;
+            addr
+        }
+        ir::GlobalVarData::TLS { .. } => return Err(result::CtonError::ImplLimitExceeded),
+    };
+    Ok(addr)
+}
+
+/// Add `offset` to `value` in `%rax`.
+fn add_offset(pos: &mut EncCursor, value: ir::Value, offset: i64) -> ir::Value {
+    if offset == 0 {
+        return value;
+    }
+    let sum = pos.ins().iadd_imm(value, offset);
+    pos.func.locations[sum] = ValueLoc::Reg(RU::rax as RegUnit);
+    sum
+}
+
+/// Find all `return` instructions and tail calls outside the `frameless` exits and insert epilogues
+/// before them.
 fn insert_native_epilogues(
     pos: &mut EncCursor,
     stack_size: i64,
     csr_type: ir::types::Type,
-    csrs: &'static [RU],
+    csrs: &[RegUnit],
+    frameless: &[ir::Ebb],
 ) {
     while let Some(ebb) = pos.next_ebb() {
+        if frameless.contains(&ebb) {
+            continue;
+        }
         pos.goto_last_inst(ebb);
         if let Some(inst) = pos.current_inst() {
-            if pos.func.dfg[inst].opcode().is_return() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
                 insert_native_epilogue(inst, stack_size, pos, csr_type, csrs);
             }
         }
     }
 }
 
-/// Insert an epilogue given a specific `return` instruction.
+/// Insert an epilogue given a specific `return` instruction or tail call.
+///
+/// The restored registers are passed as return values of a `return` instruction. A tail call
+/// passes its arguments to the callee instead, and the callee saves the registers again.
 fn insert_native_epilogue(
     inst: ir::Inst,
     stack_size: i64,
     pos: &mut EncCursor,
     csr_type: ir::types::Type,
-    csrs: &'static [RU],
+    csrs: &[RegUnit],
 ) {
     if stack_size > 0 {
         pos.ins().adjust_sp_imm(Imm64::new(stack_size));
@@ -319,14 +856,19 @@ fn insert_native_epilogue(
     let fp_ret = pos.ins().x86_pop(csr_type);
     pos.prev_inst();
 
+    let is_return = pos.func.dfg[inst].opcode().is_return();
     pos.func.locations[fp_ret] = ir::ValueLoc::Reg(RU::rbp as RegUnit);
-    pos.func.dfg.append_inst_arg(inst, fp_ret);
+    if is_return {
+        pos.func.dfg.append_inst_arg(inst, fp_ret);
+    }
 
     for reg in csrs.iter() {
         let csr_ret = pos.ins().x86_pop(csr_type);
         pos.prev_inst();
 
-        pos.func.locations[csr_ret] = ir::ValueLoc::Reg(*reg as RegUnit);
-        pos.func.dfg.append_inst_arg(inst, csr_ret);
+        pos.func.locations[csr_ret] = ir::ValueLoc::Reg(*reg);
+        if is_return {
+            pos.func.dfg.append_inst_arg(inst, csr_ret);
+        }
     }
 }
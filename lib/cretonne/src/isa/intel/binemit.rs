@@ -1,7 +1,7 @@
 //! Emitting binary Intel machine code.
 
-use binemit::{CodeSink, Reloc, bad_encoding};
-use ir::{Function, Inst, Ebb, InstructionData, Opcode};
+use binemit::{CodeOffset, CodeSink, Reloc, SymbolInfo, bad_encoding};
+use ir::{Function, Inst, Ebb, GlobalVarData, InstructionData, Opcode, TrapCode};
 use ir::condcodes::{CondCode, IntCC, FloatCC};
 use isa::{RegUnit, StackRef, StackBase, StackBaseMask};
 use regalloc::RegDiversions;
@@ -9,6 +9,31 @@ use super::registers::RU;
 
 include!(concat!(env!("OUT_DIR"), "/binemit-intel.rs"));
 
+/// Recommended multi-byte NOP instructions, indexed by size - 1.
+const NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+/// Emit `bytes` bytes of padding as a sequence of NOP instructions.
+pub fn emit_padding<CS: CodeSink + ?Sized>(bytes: CodeOffset, sink: &mut CS) {
+    let mut left = bytes as usize;
+    while left > 0 {
+        let nop = NOPS[left.min(NOPS.len()) - 1];
+        for &b in nop {
+            sink.put1(b);
+        }
+        left -= nop.len();
+    }
+}
+
 // Convert a stack base to the corresponding register.
 fn stk_base(base: StackBase) -> RegUnit {
     let ru = match base {
@@ -148,6 +173,31 @@ fn put_rexmp3<CS: CodeSink + ?Sized>(bits: u16, rex: u8, sink: &mut CS) {
     sink.put1(bits as u8);
 }
 
+// Emit a three-byte VEX prefix followed by the opcode.
+//
+// The VEX prefix replaces the mandatory prefix, the REX prefix, and the 0F 3[8A] escape bytes, so
+// the pp+mm bits of the encoding are used directly. The `vvvv` field encodes an additional
+// register operand, and the VEX.L bit is always 0.
+fn put_vex3<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rm: RegUnit,
+    reg: RegUnit,
+    vvvv: RegUnit,
+    sink: &mut CS,
+) {
+    debug_assert_ne!(bits & 0x0c00, 0, "Invalid encoding bits for VEX");
+    let r = ((reg >> 3) & 1) as u8;
+    let b = ((rm >> 3) & 1) as u8;
+    let mm = ((bits >> 10) & 3) as u8;
+    let w = ((bits >> 15) & 1) as u8;
+    let pp = ((bits >> 8) & 3) as u8;
+    sink.put1(0xc4);
+    // The R, X, and B bits are stored inverted.
+    sink.put1(((r ^ 1) << 7) | (1 << 6) | ((b ^ 1) << 5) | mm);
+    sink.put1((w << 7) | ((!vvvv as u8 & 0xf) << 3) | pp);
+    sink.put1(bits as u8);
+}
+
 /// Emit a ModR/M byte for reg-reg operands.
 fn modrm_rr<CS: CodeSink + ?Sized>(rm: RegUnit, reg: RegUnit, sink: &mut CS) {
     let reg = reg as u8 & 7;
@@ -236,8 +286,8 @@ fn sib_noindex<CS: CodeSink + ?Sized>(base: RegUnit, sink: &mut CS) {
 fn icc2opc(cond: IntCC) -> u16 {
     use ir::condcodes::IntCC::*;
     match cond {
-        // 0x0 = Overflow.
-        // 0x1 = !Overflow.
+        Overflow => 0x0,
+        NotOverflow => 0x1,
         UnsignedLessThan => 0x2,
         UnsignedGreaterThanOrEqual => 0x3,
         Equal => 0x4,
@@ -509,3 +509,402 @@ fn expand_fcvt_to_uint(
     cfg.recompute_ebb(pos.func, large);
     cfg.recompute_ebb(pos.func, done);
 }
+
+/// Expand a `splat` of a 128-bit vector.
+///
+/// The scalar is moved into lane 0 and copied to the other lanes with `x86_pshufd`. The 8-bit and
+/// 16-bit lanes are first replicated into a 32-bit lane.
+fn expand_splat(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    isa: &isa::TargetIsa,
+) {
+    use ir::types::*;
+
+    let x = match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::Splat,
+            arg,
+        } => arg,
+        _ => panic!("Need splat: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    match ty.lane_type() {
+        I8 | I16 => {
+            let factor = if ty.lane_type() == I8 {
+                0x0101_0101
+            } else {
+                0x0001_0001
+            };
+            let wide = pos.ins().uextend(I32, x);
+            let factor = pos.ins().iconst(I32, factor);
+            let word = pos.ins().imul(wide, factor);
+            let words = pos.ins().splat(I32X4, word);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, words);
+        }
+        I32 | F32 => {
+            let v = pos.ins().x86_scalar_to_vector(ty, x);
+            pos.func.dfg.replace(inst).x86_pshufd(v, 0x00);
+        }
+        I64 if !isa.flags().is_64bit() => {
+            // The i64 value lives in two 32-bit registers.
+            let (lo, hi) = pos.ins().isplit(x);
+            let dwords = pos.ins().x86_scalar_to_vector(I32X4, lo);
+            let dwords = pos.ins().insertlane(dwords, 1, hi);
+            let dwords = pos.ins().x86_pshufd(dwords, 0x44);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, dwords);
+        }
+        I64 | F64 => {
+            let v = pos.ins().x86_scalar_to_vector(ty, x);
+            pos.func.dfg.replace(inst).x86_pshufd(v, 0x44);
+        }
+        _ => panic!("Unsupported splat: {}", pos.func.dfg.display_inst(inst, None)),
+    }
+}
+
+/// Expand an `extractlane` from a 128-bit vector without the SSE 4.1 `pextr*` instructions.
+///
+/// The 32-bit and 64-bit lanes are shuffled into lane 0 first. The 8-bit lanes are extracted from
+/// their 16-bit lane with `pextrw`.
+fn expand_extractlane(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    isa: &isa::TargetIsa,
+) {
+    use ir::types::*;
+
+    let (x, lane) = match func.dfg[inst] {
+        ir::InstructionData::ExtractLane {
+            opcode: ir::Opcode::Extractlane,
+            arg,
+            lane,
+        } => (arg, lane),
+        _ => panic!("Need extractlane: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.value_type(x);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    match ty.lane_type() {
+        I8 => {
+            let words = pos.ins().raw_bitcast(I16X8, x);
+            let word = pos.ins().extractlane(words, lane / 2);
+            let mut wide = pos.ins().uextend(I32, word);
+            if lane % 2 == 1 {
+                wide = pos.ins().ushr_imm(wide, 8);
+            }
+            pos.func.dfg.replace(inst).ireduce(I8, wide);
+        }
+        I32 | F32 => {
+            let v = pos.ins().x86_pshufd(x, lane);
+            pos.func.dfg.replace(inst).extractlane(v, 0);
+        }
+        I64 if !isa.flags().is_64bit() => {
+            // Produce the i64 value in two 32-bit registers.
+            let dwords = pos.ins().raw_bitcast(I32X4, x);
+            let lo = pos.ins().extractlane(dwords, 2 * lane);
+            let hi = pos.ins().extractlane(dwords, 2 * lane + 1);
+            pos.func.dfg.replace(inst).iconcat(lo, hi);
+        }
+        I64 | F64 => {
+            let v = pos.ins().x86_pshufd(x, 0xee);
+            pos.func.dfg.replace(inst).extractlane(v, 0);
+        }
+        _ => panic!("Unsupported extractlane: {}", pos.func.dfg.display_inst(inst, None)),
+    }
+}
+
+/// Expand an `insertlane` into a 128-bit vector without the SSE 4.1 `pinsr*` and `insertps`
+/// instructions.
+///
+/// The new lane is inserted as 16-bit pieces with `pinsrw`.
+fn expand_insertlane(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    isa: &isa::TargetIsa,
+) {
+    use ir::types::*;
+
+    let (x, lane, y) = match func.dfg[inst] {
+        ir::InstructionData::InsertLane {
+            opcode: ir::Opcode::Insertlane,
+            args,
+            lane,
+        } => (args[0], lane, args[1]),
+        _ => panic!("Need insertlane: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.value_type(x);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    match ty.lane_type() {
+        I8 => {
+            // Merge the byte into the 16-bit lane containing it.
+            let byte = pos.ins().uextend(I32, y);
+            let words = pos.ins().raw_bitcast(I16X8, x);
+            let word = pos.ins().extractlane(words, lane / 2);
+            let word = pos.ins().uextend(I32, word);
+            let merged = if lane % 2 == 0 {
+                let high = pos.ins().band_imm(word, 0xff00);
+                pos.ins().bor(high, byte)
+            } else {
+                let low = pos.ins().band_imm(word, 0x00ff);
+                let byte = pos.ins().ishl_imm(byte, 8);
+                pos.ins().bor(low, byte)
+            };
+            let merged = pos.ins().ireduce(I16, merged);
+            let words = pos.ins().insertlane(words, lane / 2, merged);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, words);
+        }
+        I32 => {
+            // There are no moves for 16-bit values, so keep them short-lived.
+            let words = pos.ins().raw_bitcast(I16X8, x);
+            let hi = pos.ins().ushr_imm(y, 16);
+            let lo = pos.ins().ireduce(I16, y);
+            let words = pos.ins().insertlane(words, 2 * lane, lo);
+            let hi = pos.ins().ireduce(I16, hi);
+            let words = pos.ins().insertlane(words, 2 * lane + 1, hi);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, words);
+        }
+        F32 => {
+            let bits = pos.ins().bitcast(I32, y);
+            let dwords = pos.ins().raw_bitcast(I32X4, x);
+            let dwords = pos.ins().insertlane(dwords, lane, bits);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, dwords);
+        }
+        I64 => {
+            let (lo, hi) = if isa.flags().is_64bit() {
+                let lo = pos.ins().ireduce(I32, y);
+                let hi = pos.ins().ushr_imm(y, 32);
+                (lo, pos.ins().ireduce(I32, hi))
+            } else {
+                pos.ins().isplit(y)
+            };
+            let dwords = pos.ins().raw_bitcast(I32X4, x);
+            let dwords = pos.ins().insertlane(dwords, 2 * lane, lo);
+            let dwords = pos.ins().insertlane(dwords, 2 * lane + 1, hi);
+            pos.func.dfg.replace(inst).raw_bitcast(ty, dwords);
+        }
+        _ => panic!("Unsupported insertlane: {}", pos.func.dfg.display_inst(inst, None)),
+    }
+}
+
+/// Get a boolean vector of type `ty` with all lanes true.
+fn vector_true(pos: &mut FuncCursor, ty: ir::Type, x: ir::Value) -> ir::Value {
+    use ir::types::I32X4;
+
+    // Compare a vector to itself. The 32-bit lanes are always supported.
+    let dwords = pos.ins().raw_bitcast(I32X4, x);
+    let ones = pos.ins().icmp(IntCC::Equal, dwords, dwords);
+    pos.ins().raw_bitcast(ty, ones)
+}
+
+/// Expand an `icmp` of 128-bit vectors.
+///
+/// Only the `eq` and `sgt` condition codes are supported by `pcmpeq*` and `pcmpgt*`. The other
+/// condition codes are expressed in terms of those, and the 64-bit lane comparisons are emulated
+/// with 32-bit lanes when SSE 4.1 or SSE 4.2 is not available.
+fn expand_vector_icmp(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    use ir::types::*;
+
+    let (cond, x, y) = match func.dfg[inst] {
+        ir::InstructionData::IntCompare {
+            opcode: ir::Opcode::Icmp,
+            cond,
+            args,
+        } => (cond, args[0], args[1]),
+        _ => panic!("Need icmp: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.value_type(x);
+    let bool_ty = ty.as_bool();
+    assert!(ty.is_vector(), "Need vector icmp: {}", func.dfg.display_inst(inst, None));
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let signed_cond = match cond {
+        IntCC::UnsignedLessThan => Some(IntCC::SignedLessThan),
+        IntCC::UnsignedGreaterThanOrEqual => Some(IntCC::SignedGreaterThanOrEqual),
+        IntCC::UnsignedGreaterThan => Some(IntCC::SignedGreaterThan),
+        IntCC::UnsignedLessThanOrEqual => Some(IntCC::SignedLessThanOrEqual),
+        _ => None,
+    };
+    if let Some(signed_cond) = signed_cond {
+        // Flip the sign bits and compare as signed integers.
+        let ones = vector_true(&mut pos, I16X8, x);
+        let ones = pos.ins().raw_bitcast(I16X8, ones);
+        let sign = match ty.lane_type() {
+            I8 => {
+                let high = pos.ins().ishl_imm(ones, 15);
+                let low = pos.ins().ushr_imm(high, 8);
+                pos.ins().bor(high, low)
+            }
+            lane_ty => {
+                let ones = pos.ins().raw_bitcast(ty, ones);
+                pos.ins().ishl_imm(ones, i64::from(lane_ty.bits()) - 1)
+            }
+        };
+        let sign = pos.ins().raw_bitcast(ty, sign);
+        let x = pos.ins().bxor(x, sign);
+        let y = pos.ins().bxor(y, sign);
+        pos.func.dfg.replace(inst).icmp(signed_cond, x, y);
+        return;
+    }
+
+    match cond {
+        IntCC::Equal => {
+            // Only `pcmpeqq` needs SSE 4.1. Both halves of a 64-bit lane must be equal.
+            assert_eq!(ty, I64X2, "Unsupported icmp: {}", pos.func.dfg.display_inst(inst, None));
+            let xd = pos.ins().raw_bitcast(I32X4, x);
+            let yd = pos.ins().raw_bitcast(I32X4, y);
+            let eq = pos.ins().icmp(IntCC::Equal, xd, yd);
+            let swapped = pos.ins().x86_pshufd(eq, 0xb1);
+            let both = pos.ins().band(eq, swapped);
+            pos.func.dfg.replace(inst).raw_bitcast(bool_ty, both);
+        }
+        IntCC::SignedGreaterThan => {
+            // Only `pcmpgtq` needs SSE 4.2. Compute the sign bit of `y - x` corrected for
+            // overflow, and broadcast it to the whole lane.
+            assert_eq!(ty, I64X2, "Unsupported icmp: {}", pos.func.dfg.display_inst(inst, None));
+            let diff = pos.ins().isub(y, x);
+            let xor = pos.ins().bxor(y, x);
+            let ovf = pos.ins().bxor(diff, y);
+            let ovf = pos.ins().band(xor, ovf);
+            let lt = pos.ins().bxor(diff, ovf);
+            let lt = pos.ins().raw_bitcast(I32X4, lt);
+            let mask = pos.ins().sshr_imm(lt, 31);
+            let mask = pos.ins().x86_pshufd(mask, 0xf5);
+            pos.func.dfg.replace(inst).raw_bitcast(bool_ty, mask);
+        }
+        IntCC::SignedLessThan => {
+            pos.func.dfg.replace(inst).icmp(
+                IntCC::SignedGreaterThan,
+                y,
+                x,
+            );
+        }
+        IntCC::NotEqual => {
+            let eq = pos.ins().icmp(IntCC::Equal, x, y);
+            let ones = vector_true(&mut pos, bool_ty, x);
+            pos.func.dfg.replace(inst).bxor(eq, ones);
+        }
+        IntCC::SignedGreaterThanOrEqual => {
+            let lt = pos.ins().icmp(IntCC::SignedGreaterThan, y, x);
+            let ones = vector_true(&mut pos, bool_ty, x);
+            pos.func.dfg.replace(inst).bxor(lt, ones);
+        }
+        IntCC::SignedLessThanOrEqual => {
+            let gt = pos.ins().icmp(IntCC::SignedGreaterThan, x, y);
+            let ones = vector_true(&mut pos, bool_ty, x);
+            pos.func.dfg.replace(inst).bxor(gt, ones);
+        }
+        _ => panic!("Unsupported icmp: {}", pos.func.dfg.display_inst(inst, None)),
+    }
+}
+
+/// Expand a `vselect` of 128-bit vectors with bitwise operations.
+fn expand_vselect(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (c, x, y) = match func.dfg[inst] {
+        ir::InstructionData::Ternary {
+            opcode: ir::Opcode::Vselect,
+            args,
+        } => (args[0], args[1], args[2]),
+        _ => panic!("Need vselect: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.value_type(x);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    // The lanes of a boolean vector are all ones or all zeros.
+    let mask = if pos.func.dfg.value_type(c) == ty {
+        c
+    } else {
+        pos.ins().raw_bitcast(ty, c)
+    };
+    let t = pos.ins().band(x, mask);
+    let f = pos.ins().band_not(y, mask);
+    pos.func.dfg.replace(inst).bor(t, f);
+}
+
+/// Expand a `tls_addr` according to the TLS access model of the global variable.
+///
+/// Only the ELF access models on x86-64 are supported.
+fn expand_tls_addr(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    isa: &isa::TargetIsa,
+) {
+    let gv = match func.dfg[inst] {
+        ir::InstructionData::UnaryGlobalVar {
+            opcode: ir::Opcode::TlsAddr,
+            global_var,
+        } => global_var,
+        _ => panic!("Need tls_addr: {}", func.dfg.display_inst(inst, None)),
+    };
+    assert!(isa.flags().is_64bit(), "TLS is only supported on x86-64");
+
+    let model = match func.global_vars[gv] {
+        ir::GlobalVarData::TLS { model, .. } => model,
+        _ => panic!("tls_addr of non-TLS global variable {}", gv),
+    };
+
+    match model {
+        ir::TLSModel::InitialExec => {
+            func.dfg.replace(inst).x86_elf_tls_ie(gv);
+        }
+        ir::TLSModel::GeneralDynamic => {
+            let fref = tls_get_addr_ref(func, isa);
+            func.dfg.replace(inst).x86_elf_tls_gd(fref, gv);
+        }
+    }
+}
+
+/// Get a reference to `__tls_get_addr`, importing it into `func` on first use.
+///
+/// The signatures in `func` have already been legalized, so the new signature is legalized here
+/// too. The `x86_elf_tls_gd` sequence needs a direct call, so a linkage through a global variable
+/// is ignored.
+fn tls_get_addr_ref(func: &mut ir::Function, isa: &isa::TargetIsa) -> ir::FuncRef {
+    let name = match *isa.libcall_linkage(ir::LibCall::ElfTlsGetAddr) {
+        ir::LibCallLinkage::Direct(ref name) => name.clone(),
+        ir::LibCallLinkage::Indirect(_) => ir::ExternalName::LibCall(ir::LibCall::ElfTlsGetAddr),
+    };
+    if let Some(fref) = func.dfg.ext_funcs.keys().find(|&fref| {
+        func.dfg.ext_funcs[fref].name == name
+    })
+    {
+        return fref;
+    }
+
+    let mut sig = ir::Signature::new(ir::CallConv::Native);
+    sig.returns.push(ir::AbiParam::new(ir::types::I64));
+    isa.legalize_signature(&mut sig, false);
+    sig.compute_argument_bytes();
+    let signature = func.import_signature(sig);
+    func.import_function(ir::ExtFuncData {
+        name,
+        signature,
+        colocated: false,
+    })
+}
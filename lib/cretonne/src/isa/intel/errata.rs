@@ -0,0 +1,155 @@
+//! Workarounds for Intel CPU errata.
+//!
+//! The workarounds are enabled by the `avoid_*` settings in `meta/isa/intel/settings.py`, and the
+//! CPU presets enable the workarounds needed by the corresponding CPUs.
+
+use binemit::CodeOffset;
+use ir::{Ebb, Function, Opcode};
+use isa::EncInfo;
+use std::cmp;
+use std::vec::Vec;
+
+/// Size of the code blocks affected by the JCC erratum.
+const JCC_BLOCK: CodeOffset = 32;
+
+/// Is `opcode` encoded as a jump, call, or return affected by the JCC erratum?
+///
+/// The conditional traps are encoded as a conditional jump over a `ud2` instruction.
+fn is_jump(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::Trapz | Opcode::Trapnz | Opcode::Trapif | Opcode::Trapff => true,
+        _ => opcode.is_branch() || opcode.is_call() || opcode.is_return(),
+    }
+}
+
+/// Does `opcode` read the CPU flags produced by the previous instruction?
+///
+/// A flags-producing instruction is macro-fused with an adjacent conditional branch, and the
+/// erratum applies to the fused pair.
+fn is_fused_branch(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::Brif | Opcode::Brff | Opcode::Trapif | Opcode::Trapff => true,
+        _ => false,
+    }
+}
+
+/// Compute the padding needed before `ebb` to work around the Skylake JCC erratum.
+///
+/// The jumps in `ebb` must not cross or end at a 32-byte boundary when the EBB is placed at
+/// `offset` plus the padding. The EBB is never placed before its offset from a previous
+/// relaxation iteration. If no placement within 32 bytes works for all the jumps in the EBB, no
+/// padding is inserted. The entry block is never padded.
+pub fn jcc_erratum_padding(
+    func: &Function,
+    encinfo: &EncInfo,
+    ebb: Ebb,
+    offset: CodeOffset,
+) -> CodeOffset {
+    if Some(ebb) == func.layout.entry_block() {
+        return 0;
+    }
+
+    // Collect the ranges of the jumps relative to the EBB header.
+    let mut jumps = Vec::new();
+    let mut inst_offset = 0;
+    let mut prev_offset = None;
+    for inst in func.layout.ebb_insts(ebb) {
+        let size = encinfo.bytes(func.encodings[inst]);
+        let opcode = func.dfg[inst].opcode();
+        if size > 0 && is_jump(opcode) {
+            let start = match prev_offset {
+                Some(prev) if is_fused_branch(opcode) => prev,
+                _ => inst_offset,
+            };
+            jumps.push((start, inst_offset + size));
+        }
+        if size > 0 {
+            prev_offset = Some(inst_offset);
+        }
+        inst_offset += size;
+    }
+    if jumps.is_empty() {
+        return 0;
+    }
+
+    let min_offset = cmp::max(offset, func.offsets[ebb]);
+    (min_offset..min_offset + JCC_BLOCK)
+        .find(|&base| {
+            jumps.iter().all(|&(start, end)| {
+                (base + start) / JCC_BLOCK == (base + end) / JCC_BLOCK
+            })
+        })
+        .unwrap_or(min_offset) - offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::condcodes::IntCC;
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+    use Context;
+
+    /// Count the jumps outside the entry block that cross or end at a 32-byte boundary.
+    fn misplaced_jumps(func: &Function, encinfo: &EncInfo) -> usize {
+        func.layout
+            .ebbs()
+            .skip(1)
+            .flat_map(|ebb| func.inst_offsets(ebb, encinfo))
+            .filter(|&(offset, inst, size)| {
+                size > 0 && is_jump(func.dfg[inst].opcode()) &&
+                    offset / JCC_BLOCK != (offset + size) / JCC_BLOCK
+            })
+            .count()
+    }
+
+    #[test]
+    fn jcc_erratum() {
+        // A loop with a compare and branch at the end.
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            for _ in 0..5 {
+                pos.ins().iadd_imm(v0, 1000);
+            }
+            pos.ins().jump(ebb1, &[]);
+            pos.insert_ebb(ebb1);
+            let v1 = pos.ins().iadd_imm(v0, 1000);
+            let v2 = pos.ins().iadd_imm(v1, 1000);
+            let v3 = pos.ins().iadd_imm(v2, 1000);
+            let c = pos.ins().icmp_imm(IntCC::NotEqual, v3, 0);
+            pos.ins().brnz(c, ebb1, &[]);
+            pos.ins().jump(ebb2, &[]);
+            pos.insert_ebb(ebb2);
+            pos.ins().return_(&[]);
+        }
+
+        let compile = |preset: &str| {
+            let mut isa_builder = isa::lookup("intel").unwrap();
+            isa_builder.enable(preset).unwrap();
+            // Keep the optional passes from changing the code layout.
+            let mut flag_builder = settings::builder();
+            flag_builder.set("opt_level", "fastest").unwrap();
+            let isa = isa_builder.finish(settings::Flags::new(&flag_builder));
+            let mut ctx = Context::for_function(func.clone());
+            let code_size = ctx.compile(&*isa).unwrap();
+            (misplaced_jumps(&ctx.func, &isa.encoding_info()), ctx.func, code_size)
+        };
+
+        let (plain_misplaced, plain, plain_size) = compile("haswell");
+        let (padded_misplaced, padded, padded_size) = compile("skylake");
+        assert!(plain_misplaced > 0);
+        assert_eq!(padded_misplaced, 0);
+        assert!(padded_size > plain_size);
+        assert_eq!(padded.offsets[ebb0], plain.offsets[ebb0]);
+    }
+}
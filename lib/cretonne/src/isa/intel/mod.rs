@@ -4,25 +4,33 @@ pub mod settings;
 mod abi;
 mod binemit;
 mod enc_tables;
+mod errata;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod native;
 mod registers;
+mod unwind;
 
-use binemit::{CodeSink, MemoryCodeSink, emit_function};
+use binemit::{CodeOffset, CodeSink, MemoryCodeSink, emit_function, emit_function_checked,
+              FrameDescription, FrameUnwindKind, FrameUnwindSink};
 use super::super::settings as shared_settings;
 use isa::enc_tables::{self as shared_enc_tables, lookup_enclist, Encodings};
 use isa::Builder as IsaBuilder;
-use isa::{TargetIsa, RegInfo, RegClass, EncInfo};
+use isa::{CallConvDescriptor, TargetIsa, RegInfo, RegClass, EncInfo};
 use ir;
 use regalloc;
 use result;
 use timing;
 use std::fmt;
 use std::boxed::Box;
+use std::vec::Vec;
 
 #[allow(dead_code)]
 struct Isa {
     shared_flags: shared_settings::Flags,
     isa_flags: settings::Flags,
     cpumode: &'static [shared_enc_tables::Level1Entry<u16>],
+    call_convs: Vec<CallConvDescriptor>,
+    libcalls: ir::LibCallLinkages,
 }
 
 /// Get an ISA builder for creating Intel targets.
@@ -30,12 +38,16 @@ pub fn isa_builder() -> IsaBuilder {
     IsaBuilder {
         setup: settings::builder(),
         constructor: isa_constructor,
+        call_convs: Some(Vec::new()),
+        libcalls: ir::LibCallLinkages::new(),
     }
 }
 
 fn isa_constructor(
     shared_flags: shared_settings::Flags,
     builder: &shared_settings::Builder,
+    call_convs: Vec<CallConvDescriptor>,
+    libcalls: ir::LibCallLinkages,
 ) -> Box<TargetIsa> {
     let level1 = if shared_flags.is_64bit() {
         &enc_tables::LEVEL1_I64[..]
@@ -46,6 +58,8 @@ fn isa_constructor(
         isa_flags: settings::Flags::new(&shared_flags, builder),
         shared_flags,
         cpumode: level1,
+        call_convs,
+        libcalls,
     })
 }
 
@@ -62,6 +76,10 @@ impl TargetIsa for Isa {
         registers::INFO.clone()
     }
 
+    fn libcall_linkage(&self, libcall: ir::LibCall) -> &ir::LibCallLinkage {
+        self.libcalls.get(libcall)
+    }
+
     fn encoding_info(&self) -> EncInfo {
         enc_tables::INFO.clone()
     }
@@ -87,7 +105,14 @@ impl TargetIsa for Isa {
     }
 
     fn legalize_signature(&self, sig: &mut ir::Signature, current: bool) {
-        abi::legalize_signature(sig, &self.shared_flags, current)
+        abi::legalize_signature(sig, &self.shared_flags, &self.call_convs, current)
+    }
+
+    fn call_conv_descriptor(&self, call_conv: ir::CallConv) -> Option<&CallConvDescriptor> {
+        match call_conv {
+            ir::CallConv::Custom(n) => self.call_convs.get(usize::from(n)),
+            _ => None,
+        }
     }
 
     fn regclass_for_abi_type(&self, ty: ir::Type) -> RegClass {
@@ -109,13 +134,42 @@ impl TargetIsa for Isa {
     }
 
     fn emit_function(&self, func: &ir::Function, sink: &mut MemoryCodeSink) {
-        emit_function(func, binemit::emit_inst, sink)
+        if self.shared_flags.check_emission() {
+            emit_function_checked(self, func, binemit::emit_inst, binemit::emit_padding, sink)
+        } else {
+            emit_function(func, binemit::emit_inst, binemit::emit_padding, sink)
+        }
+    }
+
+    fn ebb_padding(&self, func: &ir::Function, ebb: ir::Ebb, offset: CodeOffset) -> CodeOffset {
+        if self.isa_flags.avoid_jcc_erratum() {
+            errata::jcc_erratum_padding(func, &enc_tables::INFO, ebb, offset)
+        } else {
+            0
+        }
+    }
+
+    fn emit_padding(&self, bytes: CodeOffset, sink: &mut CodeSink) {
+        binemit::emit_padding(bytes, sink)
     }
 
     fn prologue_epilogue(&self, func: &mut ir::Function) -> result::CtonResult {
         let _tt = timing::prologue_epilogue();
         abi::prologue_epilogue(func, self)
     }
+
+    fn emit_unwind_info(
+        &self,
+        func: &ir::Function,
+        kind: FrameUnwindKind,
+        sink: &mut FrameUnwindSink,
+    ) {
+        unwind::emit_unwind_info(func, self, kind, sink)
+    }
+
+    fn frame_description(&self, func: &ir::Function) -> Option<FrameDescription> {
+        unwind::frame_description(func, self)
+    }
 }
 
 impl fmt::Display for Isa {
@@ -0,0 +1,223 @@
+//! Detection of the features of the host CPU.
+//!
+//! The `has_*` settings in `meta/isa/intel/settings.py` correspond to CPUID bits. This module reads
+//! those bits on the host and enables the matching settings, along with the workarounds for the
+//! errata of the host CPU model.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::{__cpuid_count, _xgetbv, CpuidResult};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__cpuid_count, _xgetbv, CpuidResult};
+
+use isa::Builder as IsaBuilder;
+use isa::LookupError;
+use settings::Configurable;
+
+/// Settings enabled by bits in ECX of CPUID leaf 1.
+const LEAF1_ECX: &[(u32, &str)] = &[
+    (0, "has_sse3"),
+    (9, "has_ssse3"),
+    (19, "has_sse41"),
+    (20, "has_sse42"),
+    (23, "has_popcnt"),
+];
+
+/// Settings enabled by bits in ECX of CPUID leaf 1 that need the AVX register state.
+const LEAF1_ECX_AVX: &[(u32, &str)] = &[(28, "has_avx"), (12, "has_fma")];
+
+/// Settings enabled by bits in EBX of CPUID leaf 7, sub-leaf 0.
+const LEAF7_EBX: &[(u32, &str)] = &[(3, "has_bmi1"), (8, "has_bmi2")];
+
+/// Settings enabled by bits in ECX of CPUID leaf 0x8000_0001.
+const EXT_LEAF1_ECX: &[(u32, &str)] = &[(5, "has_lzcnt")];
+
+/// Workarounds for the errata of specific CPU models.
+///
+/// Each entry lists the CPU vendor, the display family and display models of the affected CPUs,
+/// and the setting that enables the workaround.
+const ERRATA: &[(&[u8; 12], u32, &[u32], &str)] = &[
+    // Skylake, Cascade Lake, Kaby Lake, Coffee Lake, Whiskey Lake, and Comet Lake.
+    (
+        b"GenuineIntel",
+        6,
+        &[0x4e, 0x55, 0x5e, 0x8e, 0x9e, 0xa5, 0xa6],
+        "avoid_jcc_erratum",
+    ),
+];
+
+// The CPUID intrinsics are safe functions in newer versions of Rust.
+#[allow(unused_unsafe)]
+fn cpuid(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    // All the x86 CPUs that can run Rust code support the CPUID instruction.
+    unsafe { __cpuid_count(leaf, sub_leaf) }
+}
+
+/// Enable the settings for the bits of `reg` listed in `bits`.
+fn enable_bits(builder: &mut IsaBuilder, reg: u32, bits: &[(u32, &str)]) {
+    for &(bit, setting) in bits {
+        if reg & (1 << bit) != 0 {
+            builder.enable(setting).unwrap();
+        }
+    }
+}
+
+/// Does the operating system save the AVX registers on context switches?
+///
+/// The AVX instructions can only be used when CPUID reports OSXSAVE and XCR0 has both the SSE and
+/// AVX state bits set. The CPUID bits for AVX and FMA alone don't guarantee that.
+fn os_supports_avx(leaf1: &CpuidResult) -> bool {
+    // XGETBV is an invalid instruction unless OSXSAVE is set, so XCR0 is only read after checking.
+    leaf1.ecx & OSXSAVE != 0 && avx_state_enabled(leaf1.ecx, unsafe { _xgetbv(0) })
+}
+
+/// The OSXSAVE bit in ECX of CPUID leaf 1.
+const OSXSAVE: u32 = 1 << 27;
+
+/// Are the SSE and AVX register states enabled, given ECX of CPUID leaf 1 and the value of XCR0?
+fn avx_state_enabled(leaf1_ecx: u32, xcr0: u64) -> bool {
+    const XCR0_SSE_AVX: u64 = 0b110;
+    leaf1_ecx & OSXSAVE != 0 && xcr0 & XCR0_SSE_AVX == XCR0_SSE_AVX
+}
+
+/// Get the vendor string from CPUID leaf 0. It is stored in EBX, EDX, ECX order.
+fn vendor(leaf0: &CpuidResult) -> [u8; 12] {
+    let mut vendor = [0; 12];
+    for (i, reg) in [leaf0.ebx, leaf0.edx, leaf0.ecx].iter().enumerate() {
+        for b in 0..4 {
+            vendor[i * 4 + b] = (reg >> (b * 8)) as u8;
+        }
+    }
+    vendor
+}
+
+/// Enable the workarounds for the errata of the CPU with `vendor` and the leaf 1 EAX `signature`.
+fn enable_errata(builder: &mut IsaBuilder, vendor: &[u8; 12], signature: u32) {
+    let (family, model) = family_model(signature);
+    for &(errata_vendor, errata_family, models, setting) in ERRATA {
+        if vendor == errata_vendor && family == errata_family && models.contains(&model) {
+            builder.enable(setting).unwrap();
+        }
+    }
+}
+
+/// Get the display family and model of the CPU from EAX of CPUID leaf 1.
+fn family_model(eax: u32) -> (u32, u32) {
+    let base_family = (eax >> 8) & 0xf;
+    let mut family = base_family;
+    let mut model = (eax >> 4) & 0xf;
+    // The extended family only applies to base family 0xf, and the extended model to base
+    // families 6 and 0xf.
+    if base_family == 0xf {
+        family += (eax >> 20) & 0xff;
+    }
+    if base_family == 0x6 || base_family == 0xf {
+        model += ((eax >> 16) & 0xf) << 4;
+    }
+    (family, model)
+}
+
+/// Get an ISA builder for the host CPU with the settings for its features enabled.
+///
+/// Returns `LookupError::Unsupported` if the host doesn't support SSE2, which Cretonne requires
+/// for floating point.
+pub fn isa_builder() -> Result<IsaBuilder, LookupError> {
+    let mut builder = super::isa_builder();
+
+    let leaf0 = cpuid(0, 0);
+    let leaf1 = cpuid(1, 0);
+    if leaf1.edx & (1 << 26) == 0 {
+        return Err(LookupError::Unsupported);
+    }
+    enable_bits(&mut builder, leaf1.ecx, LEAF1_ECX);
+    if os_supports_avx(&leaf1) {
+        enable_bits(&mut builder, leaf1.ecx, LEAF1_ECX_AVX);
+    }
+    if leaf0.eax >= 7 {
+        enable_bits(&mut builder, cpuid(7, 0).ebx, LEAF7_EBX);
+    }
+    if cpuid(0x8000_0000, 0).eax >= 0x8000_0001 {
+        enable_bits(&mut builder, cpuid(0x8000_0001, 0).ecx, EXT_LEAF1_ECX);
+    }
+    enable_errata(&mut builder, &vendor(&leaf0), leaf1.eax);
+
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::settings::Flags;
+    use settings;
+
+    fn isa_flags(builder: &IsaBuilder) -> Flags {
+        Flags::new(&settings::Flags::new(&settings::builder()), &builder.setup)
+    }
+
+    #[test]
+    fn display_family_model() {
+        // Skylake client.
+        assert_eq!(family_model(0x0005_06e3), (6, 0x5e));
+        // Zen 2.
+        assert_eq!(family_model(0x0083_0f10), (0x17, 0x31));
+        // Pentium 4.
+        assert_eq!(family_model(0x0000_0f29), (0xf, 2));
+    }
+
+    #[test]
+    fn vendor_string() {
+        // "GenuineIntel" as returned by CPUID leaf 0.
+        let leaf0 = CpuidResult {
+            eax: 0x16,
+            ebx: 0x756e_6547,
+            ecx: 0x6c65_746e,
+            edx: 0x4965_6e69,
+        };
+        assert_eq!(&vendor(&leaf0), b"GenuineIntel");
+    }
+
+    #[test]
+    fn errata() {
+        let skylake = |vendor, signature| {
+            let mut builder = super::super::isa_builder();
+            enable_errata(&mut builder, vendor, signature);
+            isa_flags(&builder).avoid_jcc_erratum()
+        };
+        assert!(skylake(b"GenuineIntel", 0x0005_06e3));
+        // Same display model in a different family.
+        assert!(!skylake(b"GenuineIntel", 0x0005_0fe3));
+        // Same signature from a different vendor.
+        assert!(!skylake(b"AuthenticAMD", 0x0005_06e3));
+        // Ice Lake.
+        assert!(!skylake(b"GenuineIntel", 0x0007_06e5));
+    }
+
+    #[test]
+    fn avx_state() {
+        assert!(avx_state_enabled(OSXSAVE, 0b111));
+        assert!(!avx_state_enabled(0, 0b111));
+        // The OS only saves the SSE state.
+        assert!(!avx_state_enabled(OSXSAVE, 0b011));
+    }
+
+    #[test]
+    fn host() {
+        let isa = isa_builder().unwrap().finish(settings::Flags::new(&settings::builder()));
+        assert_eq!(isa.name(), "intel");
+    }
+
+    #[test]
+    fn host_matches_std() {
+        // The standard library detects the same features, including the OS support for AVX.
+        let flags = isa_flags(&isa_builder().unwrap());
+        assert_eq!(flags.has_sse3(), is_x86_feature_detected!("sse3"));
+        assert_eq!(flags.has_ssse3(), is_x86_feature_detected!("ssse3"));
+        assert_eq!(flags.has_sse41(), is_x86_feature_detected!("sse4.1"));
+        assert_eq!(flags.has_sse42(), is_x86_feature_detected!("sse4.2"));
+        assert_eq!(flags.has_popcnt(), is_x86_feature_detected!("popcnt"));
+        assert_eq!(flags.has_avx(), is_x86_feature_detected!("avx"));
+        assert_eq!(flags.has_fma(), is_x86_feature_detected!("fma"));
+        assert_eq!(flags.has_bmi1(), is_x86_feature_detected!("bmi1"));
+        assert_eq!(flags.has_bmi2(), is_x86_feature_detected!("bmi2"));
+        assert_eq!(flags.has_lzcnt(), is_x86_feature_detected!("lzcnt"));
+    }
+}
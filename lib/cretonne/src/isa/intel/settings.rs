@@ -29,6 +29,14 @@ mod tests {
         let f2 = Flags::new(&shared, &b2);
         assert_eq!(f2.has_sse41(), true);
         assert_eq!(f2.has_bmi1(), true);
+        assert_eq!(f2.avoid_jcc_erratum(), false);
+
+        // Skylake is a Haswell that needs the JCC erratum workaround.
+        let mut b3 = builder();
+        b3.enable("skylake").unwrap();
+        let f3 = Flags::new(&shared, &b3);
+        assert_eq!(f3.has_bmi1(), true);
+        assert_eq!(f3.avoid_jcc_erratum(), true);
     }
     #[test]
     fn display_presets() {
@@ -0,0 +1,533 @@
+//! Unwind information for Intel 64-bit functions.
+//!
+//! The frame layout is recovered from the prologue and epilogue instructions inserted by
+//! `abi::native_prologue_epilogue()`:
+//!
+//! - The prologue pushes `%rbp`, copies `%rsp` to `%rbp`, pushes the callee-saved registers, and
+//!   then allocates the rest of the frame with `adjust_sp_imm`.
+//! - Each epilogue deallocates the frame, pops the callee-saved registers and `%rbp`, and returns.
+//!
+//! A shrink-wrapped prologue follows a prefix of the entry block that can branch to frameless
+//! exits. The exits return without a stack frame and without an epilogue.
+
+use binemit::{CodeOffset, FrameDescription, FrameUnwindKind, FrameUnwindSink};
+use ir::{CallConv, Function, InstructionData, Opcode, ValueLoc};
+use isa::{RegUnit, TargetIsa};
+use super::registers::RU;
+use std::vec::Vec;
+
+/// A change to the stack frame made by a prologue instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FrameOp {
+    /// A register was pushed onto the stack.
+    Push(RegUnit),
+    /// The frame pointer `%rbp` was set to the stack pointer.
+    SetFramePointer,
+    /// The stack pointer was decremented by a number of bytes.
+    Alloc(u32),
+}
+
+/// The frame changes made by the prologue and epilogues of a function.
+struct Frame {
+    /// Prologue operations and the code offset following each instruction.
+    prologue: Vec<(CodeOffset, FrameOp)>,
+
+    /// For each epilogue, the code offsets following the instruction popping `%rbp` and the return
+    /// instruction.
+    epilogues: Vec<(CodeOffset, CodeOffset)>,
+
+    /// For each frameless exit, the code offsets of the EBB header and following the return
+    /// instruction.
+    frameless: Vec<(CodeOffset, CodeOffset)>,
+}
+
+/// Emit unwind information for `func` in the format `kind` to `sink`.
+pub fn emit_unwind_info(
+    func: &Function,
+    isa: &TargetIsa,
+    kind: FrameUnwindKind,
+    sink: &mut FrameUnwindSink,
+) {
+    // The SpiderWASM prologue is inserted by the embedder, so we can't describe it, and the
+    // formats below are only defined for 64-bit code.
+    if func.signature.call_conv == CallConv::SpiderWASM || !isa.flags().is_64bit() {
+        return;
+    }
+
+    let frame = match analyze_frame(func, isa) {
+        Some(frame) => frame,
+        None => return,
+    };
+    match kind {
+        FrameUnwindKind::SystemV => emit_system_v(&frame, sink),
+        FrameUnwindKind::Windows => emit_windows(&frame, sink),
+    }
+}
+
+/// Describe the stack frame of `func` for stack walking.
+pub fn frame_description(func: &Function, isa: &TargetIsa) -> Option<FrameDescription> {
+    if func.signature.call_conv == CallConv::SpiderWASM {
+        return None;
+    }
+    let frame = analyze_frame(func, isa)?;
+    let word_size = if isa.flags().is_64bit() { 8 } else { 4 };
+
+    // Track the number of bytes below the return address as the prologue runs.
+    let mut depth = 0;
+    let mut fp_depth = None;
+    for &(_, op) in &frame.prologue {
+        match op {
+            FrameOp::Push(reg) => {
+                depth += word_size;
+                if reg == RU::rbp as RegUnit {
+                    fp_depth = Some(depth);
+                }
+            }
+            FrameOp::SetFramePointer => {}
+            FrameOp::Alloc(bytes) => depth += bytes,
+        }
+    }
+
+    Some(FrameDescription {
+        prologue_end: frame.prologue.last().map_or(0, |&(offset, _)| offset),
+        frame_size: depth + word_size,
+        return_address_offset: depth,
+        frame_pointer_offset: fp_depth.map(|d| depth - d),
+    })
+}
+
+/// Find the frame changes in the prologue and epilogues of `func`.
+fn analyze_frame(func: &Function, isa: &TargetIsa) -> Option<Frame> {
+    let encinfo = isa.encoding_info();
+    let entry = func.layout.entry_block()?;
+    let mut frame = Frame {
+        prologue: Vec::new(),
+        epilogues: Vec::new(),
+        frameless: Vec::new(),
+    };
+
+    for (offset, inst, size) in func.inst_offsets(entry, &encinfo) {
+        let op = match func.dfg[inst] {
+            InstructionData::Unary { opcode: Opcode::X86Push, arg } => {
+                match func.locations[arg] {
+                    ValueLoc::Reg(reg) => FrameOp::Push(reg),
+                    _ => break,
+                }
+            }
+            InstructionData::CopySpecial { src, dst, .. }
+                if src == RU::rsp as RegUnit && dst == RU::rbp as RegUnit => {
+                FrameOp::SetFramePointer
+            }
+            InstructionData::UnaryImm { opcode: Opcode::AdjustSpImm, imm } => {
+                let imm: i64 = imm.into();
+                if imm >= 0 {
+                    break;
+                }
+                FrameOp::Alloc(-imm as u32)
+            }
+            // The stack probe of a large frame is called between the pushes and the allocation.
+            InstructionData::UnaryImm { opcode: Opcode::Iconst, .. } |
+            InstructionData::Call { .. } if !frame.prologue.is_empty() => continue,
+            // The stack limit check or the prefix of a shrink-wrapped prologue comes before the
+            // first push.
+            _ if frame.prologue.is_empty() => continue,
+            _ => break,
+        };
+        frame.prologue.push((offset + size, op));
+        if let FrameOp::Alloc(_) = op {
+            break;
+        }
+    }
+    if frame.prologue.is_empty() {
+        return None;
+    }
+
+    for ebb in func.layout.ebbs() {
+        let mut pop_fp = None;
+        let mut ret = None;
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            let opcode = func.dfg[inst].opcode();
+            if opcode == Opcode::X86Pop {
+                let result = func.dfg.first_result(inst);
+                if func.locations[result] == ValueLoc::Reg(RU::rbp as RegUnit) {
+                    pop_fp = Some(offset + size);
+                }
+            } else if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
+                // Tail calls have an epilogue too.
+                if let Some(pop_fp) = pop_fp {
+                    frame.epilogues.push((pop_fp, offset + size));
+                }
+                ret = Some(offset + size);
+            }
+        }
+        // A return without an epilogue is a frameless exit.
+        if let (None, Some(ret)) = (pop_fp, ret) {
+            frame.frameless.push((func.offsets[ebb], ret));
+        }
+    }
+
+    Some(frame)
+}
+
+// DWARF call frame instructions.
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_SAME_VALUE: u8 = 0x08;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+
+/// Get the DWARF register number of a general purpose register.
+fn dwarf_reg(reg: RegUnit) -> u8 {
+    // The register units of the GPRs follow the hardware encoding.
+    const DWARF_GPRS: [u8; 16] = [0, 2, 1, 3, 7, 6, 4, 5, 8, 9, 10, 11, 12, 13, 14, 15];
+    DWARF_GPRS[reg as usize]
+}
+
+/// Append `value` to `out` as an unsigned LEB128 number.
+fn put_uleb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append instructions advancing the location from `*loc` to `offset`.
+fn advance_loc(out: &mut Vec<u8>, loc: &mut CodeOffset, offset: CodeOffset) {
+    let delta = offset - *loc;
+    *loc = offset;
+    if delta == 0 {
+        return;
+    }
+    if delta < 0x40 {
+        out.push(DW_CFA_ADVANCE_LOC | delta as u8);
+    } else if delta <= 0xff {
+        out.push(DW_CFA_ADVANCE_LOC1);
+        out.push(delta as u8);
+    } else if delta <= 0xffff {
+        out.push(DW_CFA_ADVANCE_LOC2);
+        out.extend_from_slice(&[delta as u8, (delta >> 8) as u8]);
+    } else {
+        out.push(DW_CFA_ADVANCE_LOC4);
+        out.extend_from_slice(
+            &[delta as u8, (delta >> 8) as u8, (delta >> 16) as u8, (delta >> 24) as u8],
+        );
+    }
+}
+
+/// Emit DWARF call frame instructions.
+///
+/// On entry, the CFA is `%rsp + 8` and the return address is saved at `CFA - 8`.
+fn emit_system_v(frame: &Frame, sink: &mut FrameUnwindSink) {
+    let mut cfi = Vec::new();
+    let mut loc = 0;
+    let mut cfa_offset = 8;
+    let mut fp_set = false;
+
+    for &(offset, op) in &frame.prologue {
+        advance_loc(&mut cfi, &mut loc, offset);
+        match op {
+            FrameOp::Push(reg) => {
+                cfa_offset += 8;
+                if !fp_set {
+                    cfi.push(DW_CFA_DEF_CFA_OFFSET);
+                    put_uleb128(&mut cfi, cfa_offset);
+                }
+                // The offset is factored by the data alignment factor of -8.
+                cfi.push(DW_CFA_OFFSET | dwarf_reg(reg));
+                put_uleb128(&mut cfi, cfa_offset / 8);
+            }
+            FrameOp::SetFramePointer => {
+                fp_set = true;
+                cfi.push(DW_CFA_DEF_CFA_REGISTER);
+                put_uleb128(&mut cfi, u32::from(dwarf_reg(RU::rbp as RegUnit)));
+            }
+            FrameOp::Alloc(size) => {
+                if !fp_set {
+                    cfa_offset += size;
+                    cfi.push(DW_CFA_DEF_CFA_OFFSET);
+                    put_uleb128(&mut cfi, cfa_offset);
+                }
+            }
+        }
+    }
+
+    // While the frame pointer is live, the CFA is computed from `%rbp` and the epilogue
+    // instructions popping the callee-saved registers don't affect it. After `%rbp` is popped, only
+    // the return address remains on the stack until the return.
+    //
+    // The frameless exits only have the return address on the stack, and the registers saved by
+    // the prologue still hold the caller's values. The exits and epilogues are visited in code
+    // order since the advance instructions can't move backwards.
+    let mut ranges: Vec<(CodeOffset, CodeOffset, bool)> = frame
+        .frameless
+        .iter()
+        .map(|&(start, end)| (start, end, true))
+        .collect();
+    if fp_set {
+        ranges.extend(frame.epilogues.iter().map(|&(pop_fp, ret)| (pop_fp, ret, false)));
+    }
+    ranges.sort();
+    for (start, end, is_frameless) in ranges {
+        advance_loc(&mut cfi, &mut loc, start);
+        cfi.push(DW_CFA_REMEMBER_STATE);
+        cfi.push(DW_CFA_DEF_CFA);
+        put_uleb128(&mut cfi, u32::from(dwarf_reg(RU::rsp as RegUnit)));
+        put_uleb128(&mut cfi, 8);
+        if is_frameless {
+            for &(_, op) in &frame.prologue {
+                if let FrameOp::Push(reg) = op {
+                    cfi.push(DW_CFA_SAME_VALUE);
+                    put_uleb128(&mut cfi, u32::from(dwarf_reg(reg)));
+                }
+            }
+        }
+        advance_loc(&mut cfi, &mut loc, end);
+        cfi.push(DW_CFA_RESTORE_STATE);
+    }
+
+    sink.bytes(&cfi);
+}
+
+// Windows x64 unwind operation codes.
+const UWOP_PUSH_NONVOL: u8 = 0;
+const UWOP_ALLOC_LARGE: u8 = 1;
+const UWOP_ALLOC_SMALL: u8 = 2;
+const UWOP_SET_FPREG: u8 = 3;
+
+/// Emit a Windows x64 `UNWIND_INFO` structure describing the prologue.
+///
+/// Windows identifies epilogues from the code itself, so they are not described. The frameless
+/// exits of a shrink-wrapped prologue can't be described, so no information is emitted for
+/// shrink-wrapped functions. Functions using the Windows x64 calling convention are never
+/// shrink-wrapped.
+fn emit_windows(frame: &Frame, sink: &mut FrameUnwindSink) {
+    if !frame.frameless.is_empty() {
+        return;
+    }
+    let prologue_size = frame.prologue.last().map_or(0, |&(offset, _)| offset);
+    if prologue_size > 0xff {
+        // The prologue is too large to be described.
+        return;
+    }
+
+    // Each unwind code is one or more 16-bit slots.
+    let mut codes: Vec<Vec<u8>> = Vec::new();
+    let mut frame_register = 0;
+    for &(offset, op) in &frame.prologue {
+        let offset = offset as u8;
+        let code = match op {
+            FrameOp::Push(reg) => vec![offset, UWOP_PUSH_NONVOL | (reg as u8) << 4],
+            FrameOp::SetFramePointer => {
+                frame_register = RU::rbp as u8;
+                vec![offset, UWOP_SET_FPREG]
+            }
+            FrameOp::Alloc(size) if size <= 128 => {
+                vec![offset, UWOP_ALLOC_SMALL | ((size / 8 - 1) as u8) << 4]
+            }
+            FrameOp::Alloc(size) if size <= 0x7fff8 => {
+                let scaled = size / 8;
+                vec![offset, UWOP_ALLOC_LARGE, scaled as u8, (scaled >> 8) as u8]
+            }
+            FrameOp::Alloc(size) => {
+                vec![
+                    offset,
+                    UWOP_ALLOC_LARGE | 1 << 4,
+                    size as u8,
+                    (size >> 8) as u8,
+                    (size >> 16) as u8,
+                    (size >> 24) as u8,
+                ]
+            }
+        };
+        codes.push(code);
+    }
+
+    // The codes are stored in reverse order of the prologue instructions.
+    let slots: Vec<u8> = codes.iter().rev().flat_map(|c| c.iter().cloned()).collect();
+    let slot_count = slots.len() / 2;
+
+    let mut info = Vec::with_capacity(4 + slots.len() + 2);
+    // Version 1, no flags.
+    info.push(1);
+    info.push(prologue_size as u8);
+    info.push(slot_count as u8);
+    // The frame register offset is always 0 since `%rbp` is set right after it is pushed.
+    info.push(frame_register);
+    info.extend_from_slice(&slots);
+    // The slot array is padded to an even number of entries.
+    if slot_count % 2 != 0 {
+        info.extend_from_slice(&[0, 0]);
+    }
+
+    sink.bytes(&info);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{AbiParam, ExtFuncData, ExternalName, InstBuilder, Signature, StackSlotData,
+             StackSlotKind, Value};
+    use isa;
+    use settings::{self, Configurable};
+    use std::boxed::Box;
+
+    struct Bytes(Vec<u8>);
+
+    impl FrameUnwindSink for Bytes {
+        fn bytes(&mut self, data: &[u8]) {
+            self.0.extend_from_slice(data);
+        }
+    }
+
+    /// Compile a function taking and returning an `i64`, with a body generated by `body`.
+    fn compile_with(body: fn(&mut FuncCursor, Value)) -> (Context, Box<TargetIsa>) {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I64));
+        ctx.func.signature.returns.push(AbiParam::new(I64));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let v0 = ctx.func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            body(&mut pos, v0);
+        }
+        ctx.compile(&*isa).unwrap();
+        (ctx, isa)
+    }
+
+    fn compile() -> (Context, Box<TargetIsa>) {
+        compile_with(|pos, v0| {
+            let v1 = pos.ins().iadd(v0, v0);
+            pos.ins().return_(&[v1]);
+        })
+    }
+
+    #[test]
+    fn system_v() {
+        let (ctx, isa) = compile();
+        let mut cfi = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::SystemV, &mut cfi);
+
+        // push %rbp; mov %rsp, %rbp; push %rbx
+        assert_eq!(
+            cfi.0[..11],
+            [0x42, 0x0e, 0x10, 0x86, 0x02, 0x43, 0x0d, 0x06, 0x42, 0x83, 0x03]
+        );
+        assert_eq!(cfi.0.last(), Some(&DW_CFA_RESTORE_STATE));
+    }
+
+    #[test]
+    fn windows() {
+        let (ctx, isa) = compile();
+        let mut info = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::Windows, &mut info);
+        let info = info.0;
+
+        assert_eq!(info[0], 1);
+        assert_eq!(info[3], RU::rbp as u8);
+        let slot_count = info[2] as usize;
+        assert_eq!(info.len(), 4 + 2 * (slot_count + slot_count % 2));
+
+        // The last codes describe `push %rbp` and `mov %rsp, %rbp`.
+        let last = 4 + 2 * slot_count;
+        assert_eq!(info[last - 4..last], [5, UWOP_SET_FPREG, 2, (RU::rbp as u8) << 4]);
+    }
+
+    #[test]
+    fn probed_frame() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let mut ctx = Context::new();
+        ctx.func.signature = Signature::new(CallConv::WindowsFastcall);
+        ctx.func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8192));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().return_(&[]);
+        }
+        ctx.compile(&*isa).unwrap();
+
+        // The stack probe between the pushes and the allocation is skipped, and the last unwind
+        // code, which comes first, describes the allocation at the end of the prologue.
+        let mut info = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::Windows, &mut info);
+        let info = info.0;
+        assert_eq!(info[4..8], [info[1], UWOP_ALLOC_LARGE, 0x01, 0x04]);
+    }
+
+    #[test]
+    fn frame() {
+        let (ctx, isa) = compile();
+        let frame = ctx.frame_description(&*isa).unwrap();
+
+        // The return address is at the top of the frame, and `%rbp` is pushed right below it.
+        assert_eq!(frame.return_address_offset, frame.frame_size - 8);
+        assert_eq!(frame.frame_pointer_offset, Some(frame.frame_size - 16));
+        assert_eq!(frame.frame_size % 16, 0);
+
+        // The Windows unwind information records the size of the prologue.
+        let mut cfi = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::Windows, &mut cfi);
+        assert_eq!(u32::from(cfi.0[1]), frame.prologue_end);
+    }
+
+    #[test]
+    fn shrink_wrapped() {
+        // The early return in `ebb1` doesn't need the frame set up for the call.
+        let (ctx, isa) = compile_with(|pos, v0| {
+            let mut sig = Signature::new(CallConv::Native);
+            sig.params.push(AbiParam::new(I64));
+            sig.returns.push(AbiParam::new(I64));
+            let signature = pos.func.import_signature(sig);
+            let callee = pos.func.import_function(ExtFuncData {
+                name: ExternalName::testcase("g"),
+                signature,
+                colocated: false,
+            });
+            let ebb1 = pos.func.dfg.make_ebb();
+            pos.ins().brz(v0, ebb1, &[]);
+            let call = pos.ins().call(callee, &[v0]);
+            let v1 = pos.func.dfg.first_result(call);
+            pos.ins().return_(&[v1]);
+            pos.insert_ebb(ebb1);
+            pos.ins().return_(&[v0]);
+        });
+        let entry = ctx.func.layout.entry_block().unwrap();
+        let first = ctx.func.layout.first_inst(entry).unwrap();
+        assert_eq!(ctx.func.dfg[first].opcode(), Opcode::Brz);
+
+        // The frameless exit is described with the callee-saved registers unchanged.
+        let mut cfi = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::SystemV, &mut cfi);
+        let rbp = dwarf_reg(RU::rbp as RegUnit);
+        assert!(cfi.0.windows(2).any(|w| w == [DW_CFA_SAME_VALUE, rbp]));
+
+        // Windows can't describe the frameless exit.
+        let mut info = Bytes(Vec::new());
+        ctx.emit_unwind_info(&*isa, FrameUnwindKind::Windows, &mut info);
+        assert!(info.0.is_empty());
+
+        let frame = ctx.frame_description(&*isa).unwrap();
+        assert!(frame.prologue_end > 2);
+    }
+}
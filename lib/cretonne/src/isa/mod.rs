@@ -39,9 +39,15 @@
 //!
 //! The configured target ISA trait object is a `Box<TargetIsa>` which can be used for multiple
 //! concurrent function compilations.
+//!
+//! Embedders can also register their own calling conventions with the builder before calling
+//! `finish()`, and choose how the generated code calls the runtime library routines. See
+//! `Builder::register_call_conv()` and `Builder::set_libcall_linkage()`.
 
+pub use isa::call_conv::CallConvDescriptor;
 pub use isa::constraints::{RecipeConstraints, OperandConstraint, ConstraintKind, BranchRange};
 pub use isa::encoding::{Encoding, EncInfo};
+pub use isa::overrides::OverriddenIsa;
 pub use isa::registers::{RegInfo, RegUnit, RegClass, RegClassIndex, regs_overlap};
 pub use isa::stack::{StackBase, StackBaseMask, StackRef};
 
@@ -55,6 +61,7 @@ use timing;
 use isa::enc_tables::Encodings;
 use std::fmt;
 use std::boxed::Box;
+use std::vec::Vec;
 
 #[cfg(build_riscv)]
 mod riscv;
@@ -69,9 +76,11 @@ mod arm32;
 mod arm64;
 
 pub mod registers;
+mod call_conv;
 mod encoding;
 mod enc_tables;
 mod constraints;
+mod overrides;
 mod stack;
 
 /// Returns a builder that can create a corresponding `TargetIsa`
@@ -104,6 +113,33 @@ pub fn lookup(name: &str) -> Result<Builder, LookupError> {
     }
 }
 
+/// Look for a supported ISA for the host machine.
+///
+/// Return a builder for the host ISA with the ISA-specific settings configured for the features of
+/// the host CPU. On Intel, the features are detected with CPUID. The shared settings are not
+/// affected, so `is_64bit` still needs to be enabled for 64-bit hosts.
+pub fn lookup_native() -> Result<Builder, LookupError> {
+    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+        native_intel()
+    } else if cfg!(target_arch = "arm") {
+        lookup("arm32")
+    } else if cfg!(target_arch = "aarch64") {
+        lookup("arm64")
+    } else {
+        Err(LookupError::Unknown)
+    }
+}
+
+#[cfg(all(build_intel, any(target_arch = "x86", target_arch = "x86_64")))]
+fn native_intel() -> Result<Builder, LookupError> {
+    intel::native::isa_builder()
+}
+
+#[cfg(not(all(build_intel, any(target_arch = "x86", target_arch = "x86_64"))))]
+fn native_intel() -> Result<Builder, LookupError> {
+    Err(LookupError::Unsupported)
+}
+
 /// Describes reason for target lookup failure
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum LookupError {
@@ -118,14 +154,52 @@ pub enum LookupError {
 /// Modify the ISA-specific settings before creating the `TargetIsa` trait object with `finish`.
 pub struct Builder {
     setup: settings::Builder,
-    constructor: fn(settings::Flags, &settings::Builder) -> Box<TargetIsa>,
+    constructor: fn(settings::Flags,
+                    &settings::Builder,
+                    Vec<CallConvDescriptor>,
+                    ir::LibCallLinkages)
+                    -> Box<TargetIsa>,
+    /// Registered custom calling conventions, or `None` if the ISA doesn't support them.
+    call_convs: Option<Vec<CallConvDescriptor>>,
+    /// How the generated code calls the runtime library routines.
+    libcalls: ir::LibCallLinkages,
 }
 
 impl Builder {
+    /// Register a custom calling convention described by `desc`.
+    ///
+    /// Returns the `CallConv` to use in signatures with this convention, or `None` if the ISA
+    /// doesn't support custom calling conventions or too many have been registered already.
+    pub fn register_call_conv(&mut self, desc: CallConvDescriptor) -> Option<ir::CallConv> {
+        let call_convs = self.call_convs.as_mut()?;
+        if call_convs.len() > usize::from(u8::max_value()) {
+            return None;
+        }
+        call_convs.push(desc);
+        Some(ir::CallConv::Custom((call_convs.len() - 1) as u8))
+    }
+
+    /// Set how the generated code calls the runtime library routine `libcall`.
+    ///
+    /// By default, the library routines are called directly by their `ExternalName::LibCall`
+    /// names. Embedders can rename them, or call them through a global variable instead, like an
+    /// entry of a dispatch table in the VM context, when their runtime isn't linked with the
+    /// code. The entry and exit hooks and the library calls inserted by the legalizer use the
+    /// linkage as configured. The stack probe must be called directly, and ELF TLS accesses only
+    /// honor a `Direct` linkage of `__tls_get_addr`.
+    pub fn set_libcall_linkage(&mut self, libcall: ir::LibCall, linkage: ir::LibCallLinkage) {
+        self.libcalls.set(libcall, linkage);
+    }
+
     /// Combine the ISA-specific settings with the provided ISA-independent settings and allocate a
     /// fully configured `TargetIsa` trait object.
     pub fn finish(self, shared_flags: settings::Flags) -> Box<TargetIsa> {
-        (self.constructor)(shared_flags, &self.setup)
+        (self.constructor)(
+            shared_flags,
+            &self.setup,
+            self.call_convs.unwrap_or_default(),
+            self.libcalls,
+        )
     }
 }
 
@@ -151,16 +225,38 @@ pub type Legalize = fn(ir::Inst,
 
 /// Methods that are specialized to a target ISA. Implies a Display trait that shows the
 /// shared flags, as well as any isa-specific flags.
-pub trait TargetIsa: fmt::Display {
+///
+/// A `TargetIsa` is immutable once it has been constructed, so a single instance can be shared by
+/// all the compilation threads in a process. Implementations must not use interior mutability.
+pub trait TargetIsa: fmt::Display + Send + Sync {
     /// Get the name of this ISA.
     fn name(&self) -> &'static str;
 
     /// Get the ISA-independent flags that were used to make this trait object.
     fn flags(&self) -> &settings::Flags;
 
+    /// Get the byte order of the machine code and data of this ISA.
+    ///
+    /// Code sinks must be created with this byte order. The default implementation is for
+    /// little-endian ISAs.
+    fn endianness(&self) -> binemit::Endianness {
+        binemit::Endianness::Little
+    }
+
     /// Get a data structure describing the registers in this ISA.
     fn register_info(&self) -> RegInfo;
 
+    /// Get the descriptor of the custom calling convention `call_conv`.
+    ///
+    /// Returns `None` for the built-in calling conventions and for custom conventions that were
+    /// not registered with the builder of this ISA.
+    fn call_conv_descriptor(&self, _call_conv: ir::CallConv) -> Option<&CallConvDescriptor> {
+        None
+    }
+
+    /// Get how the code generated for this ISA calls the runtime library routine `libcall`.
+    fn libcall_linkage(&self, libcall: ir::LibCall) -> &ir::LibCallLinkage;
+
     /// Returns an iterartor over legal encodings for the instruction.
     fn legal_encodings<'a>(
         &'a self,
@@ -188,6 +284,15 @@ pub trait TargetIsa: fmt::Display {
     /// Get a data structure describing the instruction encodings in this ISA.
     fn encoding_info(&self) -> EncInfo;
 
+    /// Get the function returning the number of cycles before the results of an instruction with
+    /// a given opcode can be used, for instruction scheduling.
+    ///
+    /// Returns `None` if the instructions shouldn't be scheduled, which is the default. CPUs that
+    /// execute instructions out of order schedule them by themselves.
+    fn instruction_latencies(&self) -> Option<fn(ir::Opcode) -> u32> {
+        None
+    }
+
     /// Legalize a function signature.
     ///
     /// This is used to legalize both the signature of the function being compiled and any called
@@ -246,6 +351,11 @@ pub trait TargetIsa: fmt::Display {
         use stack_layout::layout_stack;
         use ir::stackslot::{StackSize, StackOffset};
 
+        // Stack limit checks need target-specific prologue code.
+        if func.stack_limit.is_some() {
+            return Err(result::CtonError::ImplLimitExceeded);
+        }
+
         let word_size = if self.flags().is_64bit() { 8 } else { 4 };
 
         // Account for the SpiderMonkey standard prologue pushes.
@@ -276,4 +386,203 @@ pub trait TargetIsa: fmt::Display {
     ///
     /// This is more performant than calling `emit_inst` for each instruction.
     fn emit_function(&self, func: &ir::Function, sink: &mut binemit::MemoryCodeSink);
+
+    /// Get the number of padding bytes to insert before `ebb` when the preceding code ends at
+    /// `offset`.
+    ///
+    /// Workarounds for CPU errata can require instructions to be placed away from certain
+    /// addresses. The padding is computed by `binemit::relax_branches()`, and it must never move
+    /// `ebb` before the offset computed in a previous relaxation iteration, `func.offsets[ebb]`.
+    fn ebb_padding(
+        &self,
+        _func: &ir::Function,
+        _ebb: ir::Ebb,
+        _offset: binemit::CodeOffset,
+    ) -> binemit::CodeOffset {
+        0
+    }
+
+    /// Emit `bytes` bytes of padding that can be executed without effect.
+    fn emit_padding(&self, bytes: binemit::CodeOffset, _sink: &mut binemit::CodeSink) {
+        panic!("{} can't emit {} bytes of padding", self.name(), bytes);
+    }
+
+    /// Emit unwind information for `func` in the format `kind` to `sink`.
+    ///
+    /// This can only be used after the code layout has been computed by the
+    /// `binemit::relax_branches()` function. Nothing is emitted if the ISA doesn't support the
+    /// requested format for `func`.
+    fn emit_unwind_info(
+        &self,
+        _func: &ir::Function,
+        _kind: binemit::FrameUnwindKind,
+        _sink: &mut binemit::FrameUnwindSink,
+    ) {
+    }
+
+    /// Describe the stack frame of `func` for walking the stack with `binemit::FrameWalker`.
+    ///
+    /// This can only be used after the code layout has been computed by the
+    /// `binemit::relax_branches()` function. Returns `None` if the ISA can't describe the frame.
+    fn frame_description(&self, _func: &ir::Function) -> Option<binemit::FrameDescription> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::{types, AbiParam, InstBuilder};
+    use settings::Configurable;
+    use std::sync::Arc;
+    use std::thread;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+
+    #[test]
+    fn send_sync() {
+        assert_send_sync::<TargetIsa>();
+        assert_send_sync::<Box<TargetIsa>>();
+        assert_send_sync::<settings::Flags>();
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn shared_across_threads() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa: Arc<Box<TargetIsa>> =
+            Arc::new(lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder)));
+
+        let workers: Vec<_> = (0..4)
+            .map(|n| {
+                let isa = isa.clone();
+                thread::spawn(move || {
+                    let mut ctx = Context::new();
+                    ctx.func.signature.returns.push(AbiParam::new(types::I32));
+                    let ebb0 = ctx.func.dfg.make_ebb();
+                    {
+                        let mut pos = FuncCursor::new(&mut ctx.func);
+                        pos.insert_ebb(ebb0);
+                        let v0 = pos.ins().iconst(types::I32, n);
+                        pos.ins().return_(&[v0]);
+                    }
+                    ctx.compile(&**isa).unwrap()
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            assert!(worker.join().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    #[cfg(build_riscv)]
+    fn no_custom_call_convs() {
+        let mut b = lookup("riscv").unwrap();
+        assert_eq!(b.register_call_conv(CallConvDescriptor::new("none", 16)), None);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn custom_call_conv() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let flags = settings::Flags::new(&flag_builder);
+
+        let reginfo = lookup("intel").unwrap().finish(flags.clone()).register_info();
+        let reg = |name| reginfo.parse_regunit(name).unwrap();
+        let mut desc = CallConvDescriptor::new("fast", 16);
+        desc.int_args = vec![reg("rax"), reg("rbx")];
+        desc.int_returns = vec![reg("rcx")];
+        desc.callee_saved = vec![reg("r12")];
+
+        let mut builder = lookup("intel").unwrap();
+        let call_conv = builder.register_call_conv(desc).unwrap();
+        assert_eq!(call_conv, ir::CallConv::Custom(0));
+        let isa = builder.finish(flags);
+        assert_eq!(isa.call_conv_descriptor(call_conv).unwrap().name, "fast");
+        assert!(isa.call_conv_descriptor(ir::CallConv::Custom(1)).is_none());
+        assert!(isa.call_conv_descriptor(ir::CallConv::Native).is_none());
+
+        let mut sig = ir::Signature::new(call_conv);
+        for _ in 0..3 {
+            sig.params.push(AbiParam::new(types::I64));
+        }
+        sig.returns.push(AbiParam::new(types::I64));
+        isa.legalize_signature(&mut sig, false);
+        let locs: Vec<_> = sig.params.iter().map(|p| p.location).collect();
+        assert_eq!(
+            locs,
+            [
+                ir::ArgumentLoc::Reg(reg("rax")),
+                ir::ArgumentLoc::Reg(reg("rbx")),
+                ir::ArgumentLoc::Stack(0),
+            ]
+        );
+        assert_eq!(sig.returns[0].location, ir::ArgumentLoc::Reg(reg("rcx")));
+
+        // The prologue saves the custom callee-saved registers.
+        let mut ctx = Context::new();
+        ctx.func.signature = ir::Signature::new(call_conv);
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().return_(&[]);
+        }
+        ctx.compile(&*isa).unwrap();
+        let csrs: Vec<_> = ctx.func
+            .signature
+            .params
+            .iter()
+            .filter(|p| p.purpose == ir::ArgumentPurpose::CalleeSaved)
+            .map(|p| p.location)
+            .collect();
+        assert_eq!(csrs, [ir::ArgumentLoc::Reg(reg("r12"))]);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn libcall_linkage() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        flag_builder.enable("enable_entry_exit_hooks").unwrap();
+        let mut builder = lookup("intel").unwrap();
+        let enter = ir::ExternalName::testcase("enter");
+        let exit_ptr = ir::GlobalVarData::Sym {
+            name: ir::ExternalName::testcase("exit_ptr"),
+            colocated: false,
+            readonly: true,
+        };
+        builder.set_libcall_linkage(
+            ir::LibCall::EnterFunction,
+            ir::LibCallLinkage::Direct(enter.clone()),
+        );
+        builder.set_libcall_linkage(
+            ir::LibCall::ExitFunction,
+            ir::LibCallLinkage::Indirect(exit_ptr),
+        );
+        let isa = builder.finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        let ebb0 = ctx.func.dfg.make_ebb();
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            pos.ins().return_(&[]);
+        }
+        ctx.compile(&*isa).unwrap();
+        let text = ctx.func.display(&*isa).to_string();
+        assert!(text.contains("%enter"), "{}", text);
+        assert!(text.contains("%exit_ptr"), "{}", text);
+        assert!(text.contains("call_indirect"), "{}", text);
+        assert!(!text.contains("call fn1"), "{}", text);
+    }
+
 }
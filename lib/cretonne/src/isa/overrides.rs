@@ -0,0 +1,204 @@
+//! A target ISA with overridden shared settings.
+
+use binemit;
+use ir;
+use isa::enc_tables::Encodings;
+use isa::{CallConvDescriptor, EncInfo, Encoding, Legalize, RegClass, RegInfo, TargetIsa};
+use regalloc;
+use result;
+use settings;
+use std::fmt;
+
+/// A view of a `TargetIsa` with different shared flags.
+///
+/// `Context::compile` uses this view to compile a function with the settings in
+/// `Function::settings`. All the methods are forwarded to the underlying ISA, except `flags()`,
+/// so only the settings that the passes read through `flags()` can be changed. These are listed
+/// in `settings::FUNCTION_SETTINGS`.
+pub struct OverriddenIsa<'a> {
+    isa: &'a TargetIsa,
+    flags: settings::Flags,
+}
+
+impl<'a> OverriddenIsa<'a> {
+    /// Create a view of `isa` with the values in `overrides` for its shared settings.
+    pub fn new(isa: &'a TargetIsa, overrides: &settings::Overrides) -> Self {
+        Self {
+            isa,
+            flags: isa.flags().with_overrides(overrides),
+        }
+    }
+}
+
+impl<'a> TargetIsa for OverriddenIsa<'a> {
+    fn name(&self) -> &'static str {
+        self.isa.name()
+    }
+
+    fn flags(&self) -> &settings::Flags {
+        &self.flags
+    }
+
+    fn endianness(&self) -> binemit::Endianness {
+        self.isa.endianness()
+    }
+
+    fn register_info(&self) -> RegInfo {
+        self.isa.register_info()
+    }
+
+    fn call_conv_descriptor(&self, call_conv: ir::CallConv) -> Option<&CallConvDescriptor> {
+        self.isa.call_conv_descriptor(call_conv)
+    }
+
+    fn libcall_linkage(&self, libcall: ir::LibCall) -> &ir::LibCallLinkage {
+        self.isa.libcall_linkage(libcall)
+    }
+
+    fn legal_encodings<'b>(
+        &'b self,
+        dfg: &'b ir::DataFlowGraph,
+        inst: &'b ir::InstructionData,
+        ctrl_typevar: ir::Type,
+    ) -> Encodings<'b> {
+        self.isa.legal_encodings(dfg, inst, ctrl_typevar)
+    }
+
+    fn encode(
+        &self,
+        dfg: &ir::DataFlowGraph,
+        inst: &ir::InstructionData,
+        ctrl_typevar: ir::Type,
+    ) -> Result<Encoding, Legalize> {
+        self.isa.encode(dfg, inst, ctrl_typevar)
+    }
+
+    fn encoding_info(&self) -> EncInfo {
+        self.isa.encoding_info()
+    }
+
+    fn instruction_latencies(&self) -> Option<fn(ir::Opcode) -> u32> {
+        self.isa.instruction_latencies()
+    }
+
+    fn legalize_signature(&self, sig: &mut ir::Signature, current: bool) {
+        self.isa.legalize_signature(sig, current)
+    }
+
+    fn regclass_for_abi_type(&self, ty: ir::Type) -> RegClass {
+        self.isa.regclass_for_abi_type(ty)
+    }
+
+    fn allocatable_registers(&self, func: &ir::Function) -> regalloc::AllocatableSet {
+        self.isa.allocatable_registers(func)
+    }
+
+    fn prologue_epilogue(&self, func: &mut ir::Function) -> result::CtonResult {
+        self.isa.prologue_epilogue(func)
+    }
+
+    fn emit_inst(
+        &self,
+        func: &ir::Function,
+        inst: ir::Inst,
+        divert: &mut regalloc::RegDiversions,
+        sink: &mut binemit::CodeSink,
+    ) {
+        self.isa.emit_inst(func, inst, divert, sink)
+    }
+
+    fn emit_function(&self, func: &ir::Function, sink: &mut binemit::MemoryCodeSink) {
+        self.isa.emit_function(func, sink)
+    }
+
+    fn ebb_padding(
+        &self,
+        func: &ir::Function,
+        ebb: ir::Ebb,
+        offset: binemit::CodeOffset,
+    ) -> binemit::CodeOffset {
+        self.isa.ebb_padding(func, ebb, offset)
+    }
+
+    fn emit_padding(&self, bytes: binemit::CodeOffset, sink: &mut binemit::CodeSink) {
+        self.isa.emit_padding(bytes, sink)
+    }
+
+    fn emit_unwind_info(
+        &self,
+        func: &ir::Function,
+        kind: binemit::FrameUnwindKind,
+        sink: &mut binemit::FrameUnwindSink,
+    ) {
+        self.isa.emit_unwind_info(func, kind, sink)
+    }
+
+    fn frame_description(&self, func: &ir::Function) -> Option<binemit::FrameDescription> {
+        self.isa.frame_description(func)
+    }
+}
+
+impl<'a> fmt::Display for OverriddenIsa<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The ISA shows its shared flags first, followed by its own.
+        let shown = self.isa.to_string();
+        let shared_len = self.isa.flags().to_string().len();
+        write!(f, "{}{}", self.flags, &shown[shared_len..])
+    }
+}
+
+#[cfg(all(test, build_riscv))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, Function, InstBuilder};
+    use isa;
+    use pipeline::{Pipeline, PipelinePass, Stage};
+    use settings::{Configurable, OptLevel};
+    use std::string::ToString;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn function_settings() {
+        let isa = isa::lookup("riscv").unwrap().finish(settings::Flags::new(&settings::builder()));
+
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let y = pos.ins().iadd_imm(x, 1);
+            pos.ins().return_(&[y]);
+        }
+        func.settings.set("opt_level", "best").unwrap();
+        func.settings.set("avoid_div_traps", "true").unwrap();
+
+        let overridden = OverriddenIsa::new(&*isa, &func.settings);
+        let shown = overridden.to_string();
+        assert!(shown.contains("opt_level = \"best\""), "{}", shown);
+        assert!(shown.contains("[riscv]"), "{}", shown);
+
+        // Only the pipeline for the overridden opt level runs.
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran_in_pass = ran.clone();
+        let mut pipeline = Pipeline::empty();
+        pipeline.push(
+            PipelinePass::from_fn("check_flags", move |_, isa| {
+                assert!(isa.flags().avoid_div_traps());
+                ran_in_pass.store(true, Ordering::SeqCst);
+                Ok(())
+            }),
+            Stage::PreLegalize,
+        );
+        let mut ctx = Context::for_function(func);
+        ctx.set_pipeline(OptLevel::Best, Some(pipeline));
+        ctx.compile(&*isa).unwrap();
+        assert!(ran.load(Ordering::SeqCst));
+    }
+}
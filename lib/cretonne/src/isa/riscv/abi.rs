@@ -1,18 +1,70 @@
 //! RISC-V ABI implementation.
 //!
-//! This module implements the RISC-V calling convention through the primary `legalize_signature()`
-//! entry point.
+//! This module implements the RISC-V calling convention through the `legalize_signature()` and
+//! `prologue_epilogue()` entry points.
 //!
-//! This doesn't support the soft-float ABI at the moment.
+//! This doesn't support the soft-float ABI at the moment. Floating point arguments that don't fit
+//! in the floating point argument registers are passed on the stack instead of in the integer
+//! argument registers.
 
-use abi::{ArgAction, ValueConversion, ArgAssigner, legalize_args};
-use ir::{self, Type, AbiParam, ArgumentLoc, ArgumentExtension, ArgumentPurpose};
-use isa::RegClass;
+use abi::{ArgAction, ValueConversion, ArgAssigner, legalize_args, legalize_struct_return};
+use cursor::{Cursor, EncCursor, CursorPosition};
+use ir::{self, Type, AbiParam, ArgumentLoc, ArgumentExtension, ArgumentPurpose, CallConv,
+         InstBuilder, InstructionData, ValueLoc};
+use ir::immediates::Imm64;
+use ir::stackslot::{StackSize, StackOffset, StackSlotKind};
+use isa::{RegClass, RegUnit, StackRef, TargetIsa};
 use regalloc::AllocatableSet;
+use result;
 use settings as shared_settings;
-use super::registers::{GPR, FPR};
+use stack_layout::layout_stack;
+use super::registers::{GPR, FPR, RU};
 use super::settings;
 use std::i32;
+use std::vec::Vec;
+
+/// The stack pointer is always 16-byte aligned.
+const STACK_ALIGN: StackSize = 16;
+
+/// Loads, stores, and stack pointer adjustments have a signed 12-bit immediate. This is the
+/// largest stack frame that keeps the stack pointer aligned.
+const MAX_FRAME_SIZE: StackSize = 2032;
+
+/// Spill slots and stack arguments must be addressable with a signed 12-bit offset from the stack
+/// pointer.
+const MAX_SLOT_OFFSET: StackOffset = 2048;
+
+/// Callee-saved general purpose registers.
+static CSR_GPRS: [RU; 12] = [
+    RU::x8,
+    RU::x9,
+    RU::x18,
+    RU::x19,
+    RU::x20,
+    RU::x21,
+    RU::x22,
+    RU::x23,
+    RU::x24,
+    RU::x25,
+    RU::x26,
+    RU::x27,
+];
+
+/// Callee-saved floating point registers.
+static CSR_FPRS: [RU; 12] = [
+    RU::f8,
+    RU::f9,
+    RU::f18,
+    RU::f19,
+    RU::f20,
+    RU::f21,
+    RU::f22,
+    RU::f23,
+    RU::f24,
+    RU::f25,
+    RU::f26,
+    RU::f27,
+];
 
 struct Args {
     pointer_bits: u16,
@@ -20,6 +72,7 @@ struct Args {
     pointer_type: Type,
     regs: u32,
     reg_limit: u32,
+    fregs: u32,
     offset: u32,
 }
 
@@ -31,6 +84,7 @@ impl Args {
             pointer_type: Type::int(bits).unwrap(),
             regs: 0,
             reg_limit: if enable_e { 6 } else { 8 },
+            fregs: 0,
             offset: 0,
         }
     }
@@ -67,22 +121,24 @@ impl ArgAssigner for Args {
             }
         }
 
-        if self.regs < self.reg_limit {
-            // Assign to a register.
-            let reg = if ty.is_float() {
-                FPR.unit(10 + self.regs as usize)
-            } else {
-                GPR.unit(10 + self.regs as usize)
-            };
+        // Integers use `a0` - `a7` and floats use `fa0` - `fa7`.
+        if ty.is_float() {
+            if self.fregs < 8 {
+                self.fregs += 1;
+                return ArgumentLoc::Reg(FPR.unit(9 + self.fregs as usize)).into();
+            }
+        } else if self.regs < self.reg_limit {
             self.regs += 1;
-            ArgumentLoc::Reg(reg).into()
-        } else {
-            // Assign a stack location.
-            let loc = ArgumentLoc::Stack(self.offset as i32);
-            self.offset += self.pointer_bytes;
-            debug_assert!(self.offset <= i32::MAX as u32);
-            loc.into()
+            return ArgumentLoc::Reg(GPR.unit(9 + self.regs as usize)).into();
         }
+
+        // Assign a stack location. A `f64` needs a naturally aligned 8-byte slot in RV32.
+        let size = u32::from(ty.bytes()).max(self.pointer_bytes);
+        self.offset = align(self.offset, size);
+        let loc = ArgumentLoc::Stack(self.offset as i32);
+        self.offset += size;
+        debug_assert!(self.offset <= i32::MAX as u32);
+        loc.into()
     }
 }
 
@@ -95,12 +151,14 @@ pub fn legalize_signature(
 ) {
     let bits = if flags.is_64bit() { 64 } else { 32 };
 
+    // Return values that don't fit in the registers are returned in memory pointed to by the
+    // first argument.
+    let mut rets = Args::new(bits, isa_flags.enable_e());
+    legalize_struct_return(sig, &mut rets, Type::int(bits).unwrap());
+
     let mut args = Args::new(bits, isa_flags.enable_e());
     legalize_args(&mut sig.params, &mut args);
 
-    let mut rets = Args::new(bits, isa_flags.enable_e());
-    legalize_args(&mut sig.returns, &mut rets);
-
     if current {
         let ptr = Type::int(bits).unwrap();
 
@@ -127,7 +185,8 @@ pub fn allocatable_registers(_func: &ir::Function, isa_flags: &settings::Flags)
     regs.take(GPR, GPR.unit(2)); // Stack pointer.
     regs.take(GPR, GPR.unit(3)); // Global pointer.
     regs.take(GPR, GPR.unit(4)); // Thread pointer.
-    // TODO: %x8 is the frame pointer. Reserve it?
+    // %x8 is the optional frame pointer. Frames are addressed relative to the stack pointer, so it
+    // is allocated like the other callee-saved registers.
 
     // Remove %x16 and up for RV32E.
     if isa_flags.enable_e() {
@@ -138,3 +197,217 @@ pub fn allocatable_registers(_func: &ir::Function, isa_flags: &settings::Flags)
 
     regs
 }
+
+/// Compute the stack frame layout and insert the prologue and epilogues of `func`.
+pub fn prologue_epilogue(
+    func: &mut ir::Function,
+    isa: &TargetIsa,
+    isa_flags: &settings::Flags,
+) -> result::CtonResult {
+    check_tail_calls(func)?;
+    match func.signature.call_conv {
+        CallConv::Native => native_prologue_epilogue(func, isa, isa_flags),
+        CallConv::SpiderWASM => spiderwasm_prologue_epilogue(func, isa),
+        CallConv::WindowsFastcall |
+        CallConv::Custom(_) => Err(result::CtonError::InvalidInput),
+    }
+}
+
+/// Check that the tail calls in `func` are supported.
+///
+/// A tail call jumps to the callee after the epilogue has released the stack frame, so all of the
+/// callee's arguments must be passed in registers. The SpiderWASM epilogues are inserted by the
+/// embedder, so tail calls can't be used with that calling convention.
+fn check_tail_calls(func: &ir::Function) -> result::CtonResult {
+    for ebb in func.layout.ebbs() {
+        let inst = match func.layout.last_inst(ebb) {
+            Some(inst) => inst,
+            None => continue,
+        };
+        if !func.dfg[inst].opcode().is_terminator() {
+            continue;
+        }
+        if let Some(sig) = func.dfg.call_signature(inst) {
+            if func.signature.call_conv == CallConv::SpiderWASM ||
+                func.dfg.signatures[sig].params.iter().any(
+                    |arg| !arg.location.is_reg(),
+                )
+            {
+                return Err(result::CtonError::ImplLimitExceeded);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn spiderwasm_prologue_epilogue(func: &mut ir::Function, isa: &TargetIsa) -> result::CtonResult {
+    let word_size = if isa.flags().is_64bit() { 8 } else { 4 };
+    let bytes = StackSize::from(isa.flags().spiderwasm_prologue_words()) * word_size;
+
+    // The embedder inserts the prologue, so it has to check the stack limit too.
+    if func.stack_limit.is_some() {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+
+    let mut ss = ir::StackSlotData::new(StackSlotKind::IncomingArg, bytes);
+    ss.offset = Some(-(bytes as StackOffset));
+    func.stack_slots.push(ss);
+
+    layout_stack(&mut func.stack_slots, STACK_ALIGN)?;
+    check_slot_offsets(func)
+}
+
+/// Insert a standard RISC-V prologue and epilogue.
+///
+/// The prologue allocates the whole stack frame with a single stack pointer adjustment, and then
+/// stores the callee-saved registers used by `func` in spill slots. The link register doesn't
+/// need to be saved explicitly since the register allocator spills all values that are live
+/// across calls.
+fn native_prologue_epilogue(
+    func: &mut ir::Function,
+    isa: &TargetIsa,
+    isa_flags: &settings::Flags,
+) -> result::CtonResult {
+    // Stack limit checks aren't supported yet.
+    if func.stack_limit.is_some() {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+
+    let gpr_type = if isa.flags().is_64bit() {
+        ir::types::I64
+    } else {
+        ir::types::I32
+    };
+    let fpr_type = if isa_flags.use_d() {
+        ir::types::F64
+    } else {
+        ir::types::F32
+    };
+    let mut csrs = Vec::new();
+    for reg in used_registers(func, &CSR_GPRS) {
+        csrs.push((reg, gpr_type));
+    }
+    for reg in used_registers(func, &CSR_FPRS) {
+        csrs.push((reg, fpr_type));
+    }
+    let slots: Vec<_> = csrs.iter()
+        .map(|&(_, ty)| func.stack_slots.make_spill_slot(ty))
+        .collect();
+
+    let frame_size = layout_stack(&mut func.stack_slots, STACK_ALIGN)?;
+    if frame_size > MAX_FRAME_SIZE {
+        return Err(result::CtonError::ImplLimitExceeded);
+    }
+    check_slot_offsets(func)?;
+
+    // Add the saved registers to the function signature.
+    for &(reg, ty) in &csrs {
+        let csr_arg = AbiParam::special_reg(ty, ArgumentPurpose::CalleeSaved, reg);
+        func.signature.params.push(csr_arg);
+        func.signature.returns.push(csr_arg);
+    }
+
+    // Set up the cursor and insert the prologue.
+    let entry_ebb = func.layout.entry_block().expect("missing entry block");
+    let mut pos = EncCursor::new(func, isa).at_first_insertion_point(entry_ebb);
+    let frame_size = i64::from(frame_size);
+    if frame_size > 0 {
+        pos.ins().adjust_sp_imm(Imm64::new(-frame_size));
+    }
+    let mut saved = Vec::new();
+    for (&(reg, ty), &ss) in csrs.iter().zip(&slots) {
+        let csr_arg = pos.func.dfg.append_ebb_param(entry_ebb, ty);
+        pos.func.locations[csr_arg] = ValueLoc::Reg(reg);
+        let spilled = pos.ins().spill(csr_arg);
+        pos.func.locations[spilled] = ValueLoc::Stack(ss);
+        saved.push((reg, spilled));
+    }
+
+    // Reset the cursor and insert the epilogues.
+    let mut pos = pos.at_position(CursorPosition::Nowhere);
+    while let Some(ebb) = pos.next_ebb() {
+        pos.goto_last_inst(ebb);
+        if let Some(inst) = pos.current_inst() {
+            let opcode = pos.func.dfg[inst].opcode();
+            if opcode.is_return() || (opcode.is_call() && opcode.is_terminator()) {
+                insert_epilogue(inst, frame_size, &mut pos, &saved);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the registers in `csrs` that are used by `func`.
+///
+/// This runs after register allocation, so a register is used if a value is assigned to it or
+/// temporarily diverted to it.
+fn used_registers(func: &ir::Function, csrs: &[RU]) -> Vec<RegUnit> {
+    let mut used: Vec<RegUnit> = Vec::new();
+    {
+        let mut mark = |reg: RegUnit| if csrs.iter().any(|&r| r as RegUnit == reg) &&
+            !used.contains(&reg)
+        {
+            used.push(reg);
+        };
+        for value in func.locations.keys() {
+            if let ValueLoc::Reg(reg) = func.locations[value] {
+                mark(reg);
+            }
+        }
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                match func.dfg[inst] {
+                    InstructionData::RegMove { dst, .. } |
+                    InstructionData::RegFill { dst, .. } => mark(dst),
+                    _ => {}
+                }
+            }
+        }
+    }
+    used.sort();
+    used
+}
+
+/// Check that the stack slots accessed by spills, fills, and stack arguments can be addressed
+/// relative to the stack pointer.
+fn check_slot_offsets(func: &ir::Function) -> result::CtonResult {
+    for ss in func.stack_slots.keys() {
+        let slot = &func.stack_slots[ss];
+        match slot.kind {
+            StackSlotKind::SpillSlot |
+            StackSlotKind::IncomingArg |
+            StackSlotKind::OutgoingArg |
+            StackSlotKind::EmergencySlot => {}
+            StackSlotKind::ExplicitSlot => continue,
+        }
+        let offset = StackRef::sp(ss, &func.stack_slots).offset;
+        if offset < 0 || offset + slot.size as StackOffset > MAX_SLOT_OFFSET {
+            return Err(result::CtonError::ImplLimitExceeded);
+        }
+    }
+    Ok(())
+}
+
+/// Insert an epilogue before the `return` instruction or tail call `inst`.
+///
+/// The restored registers are passed as return values of a `return` instruction. A tail call
+/// passes its arguments to the callee instead, and the callee saves the registers again.
+fn insert_epilogue(
+    inst: ir::Inst,
+    frame_size: i64,
+    pos: &mut EncCursor,
+    saved: &[(RegUnit, ir::Value)],
+) {
+    let is_return = pos.func.dfg[inst].opcode().is_return();
+    for &(reg, spilled) in saved {
+        let value = pos.ins().fill(spilled);
+        pos.func.locations[value] = ValueLoc::Reg(reg);
+        if is_return {
+            pos.func.dfg.append_inst_arg(inst, value);
+        }
+    }
+    if frame_size > 0 {
+        pos.ins().adjust_sp_imm(Imm64::new(frame_size));
+    }
+}
@@ -1,14 +1,17 @@
 //! Emitting binary RISC-V machine code.
 
-use binemit::{CodeSink, Reloc, bad_encoding};
-use ir::{Function, Inst, InstructionData};
+use binemit::{CodeSink, Reloc, SymbolInfo, bad_encoding};
+use ir::{Function, Inst, InstructionData, TrapCode};
 use isa::{RegUnit, StackRef, StackBaseMask};
-use predicates::is_signed_int;
+use predicates::{is_signed_int, is_unsigned_int};
 use regalloc::RegDiversions;
 use std::u32;
 
 include!(concat!(env!("OUT_DIR"), "/binemit-riscv.rs"));
 
+/// The stack pointer register, `x2`.
+const SP: RegUnit = 2;
+
 /// R-type instructions.
 ///
 ///   31     24  19  14     11 6
@@ -37,6 +40,69 @@ fn put_r<CS: CodeSink + ?Sized>(bits: u16, rs1: RegUnit, rs2: RegUnit, rd: RegUn
     sink.put4(i);
 }
 
+/// R4-type fused multiply-add instructions.
+///
+///   31  26  24  19  14     11 6
+///   rs3 fmt rs2 rs1 funct3 rd opcode
+///    27  25  20  15     12  7      0
+///
+/// Encoding bits: `opcode[6:2] | (funct3 << 5) | (fmt << 8)`.
+fn put_r4<CS: CodeSink + ?Sized>(
+    bits: u16,
+    rs1: RegUnit,
+    rs2: RegUnit,
+    rs3: RegUnit,
+    rd: RegUnit,
+    sink: &mut CS,
+) {
+    let bits = u32::from(bits);
+    let opcode5 = bits & 0x1f;
+    let funct3 = (bits >> 5) & 0x7;
+    let fmt = (bits >> 8) & 0x3;
+    let rs1 = u32::from(rs1) & 0x1f;
+    let rs2 = u32::from(rs2) & 0x1f;
+    let rs3 = u32::from(rs3) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    // 0-6: opcode
+    let mut i = 0x3;
+    i |= opcode5 << 2;
+    i |= rd << 7;
+    i |= funct3 << 12;
+    i |= rs1 << 15;
+    i |= rs2 << 20;
+    i |= fmt << 25;
+    i |= rs3 << 27;
+
+    sink.put4(i);
+}
+
+/// R-type OP-FP instructions with a fixed rs2 field selecting the operation.
+///
+///   31     24  19  14     11 6
+///   funct7 rs2 rs1 funct3 rd opcode
+///       25  20  15     12  7      0
+///
+/// Encoding bits: `rs2 | (funct3 << 5) | (funct7 << 8)`. The opcode is always OP-FP.
+fn put_rfixed<CS: CodeSink + ?Sized>(bits: u16, rs1: RegUnit, rd: RegUnit, sink: &mut CS) {
+    let bits = u32::from(bits);
+    let rs2 = bits & 0x1f;
+    let funct3 = (bits >> 5) & 0x7;
+    let funct7 = (bits >> 8) & 0x7f;
+    let rs1 = u32::from(rs1) & 0x1f;
+    let rd = u32::from(rd) & 0x1f;
+
+    // 0-6: opcode
+    let mut i = 0x53;
+    i |= rd << 7;
+    i |= funct3 << 12;
+    i |= rs1 << 15;
+    i |= rs2 << 20;
+    i |= funct7 << 25;
+
+    sink.put4(i);
+}
+
 /// R-type instructions with a shift amount instead of rs2.
 ///
 ///   31     25    19  14     11 6
@@ -98,6 +164,35 @@ fn put_i<CS: CodeSink + ?Sized>(bits: u16, rs1: RegUnit, imm: i64, rd: RegUnit,
     sink.put4(i);
 }
 
+/// S-type store instructions.
+///
+///   31  24  19  14     11  6
+///   imm rs2 rs1 funct3 imm opcode
+///    25  20  15     12   7      0
+///
+/// Encoding bits: `opcode[6:2] | (funct3 << 5)`
+fn put_s<CS: CodeSink + ?Sized>(bits: u16, rs1: RegUnit, rs2: RegUnit, imm: i64, sink: &mut CS) {
+    let bits = u32::from(bits);
+    let opcode5 = bits & 0x1f;
+    let funct3 = (bits >> 5) & 0x7;
+    let rs1 = u32::from(rs1) & 0x1f;
+    let rs2 = u32::from(rs2) & 0x1f;
+
+    debug_assert!(is_signed_int(imm, 12, 0), "S out of range {:#x}", imm);
+    let imm = imm as u32;
+
+    // 0-6: opcode
+    let mut i = 0x3;
+    i |= opcode5 << 2;
+    i |= (imm & 0x1f) << 7;
+    i |= funct3 << 12;
+    i |= rs1 << 15;
+    i |= rs2 << 20;
+    i |= ((imm >> 5) & 0x7f) << 25;
+
+    sink.put4(i);
+}
+
 /// U-type instructions.
 ///
 ///   31  11 6
@@ -180,3 +275,186 @@ fn put_uj<CS: CodeSink + ?Sized>(bits: u16, imm: i64, rd: RegUnit, sink: &mut CS
 
     sink.put4(i);
 }
+
+// Compressed instructions.
+//
+// The encoding bits of the compressed recipes are the 16-bit instruction with all the operand
+// fields cleared.
+
+/// Get the 3-bit register field of a compressed instruction accessing `x8`-`x15` or `f8`-`f15`.
+fn creg(reg: RegUnit) -> u16 {
+    debug_assert!(reg & 0x1f >= 8 && reg & 0x1f < 16, "not a compressed register");
+    u16::from(reg) & 0x7
+}
+
+/// CR-type register instructions.
+///
+///   15     11     6   1
+///   funct4 rd/rs1 rs2 op
+///       12      7   2  0
+fn put_cr<CS: CodeSink + ?Sized>(bits: u16, rd: RegUnit, rs2: RegUnit, sink: &mut CS) {
+    let rd = u16::from(rd) & 0x1f;
+    let rs2 = u16::from(rs2) & 0x1f;
+
+    let mut i = bits;
+    i |= rd << 7;
+    i |= rs2 << 2;
+
+    sink.put2(i);
+}
+
+/// CI-type immediate instructions with a 6-bit immediate.
+///
+/// The immediate is signed, except for shift amounts.
+///
+///   15     12  11     6   1
+///   funct3 imm rd/rs1 imm op
+///       13  12      7   2  0
+fn put_ci<CS: CodeSink + ?Sized>(bits: u16, rd: RegUnit, imm: i64, sink: &mut CS) {
+    let rd = u16::from(rd) & 0x1f;
+
+    debug_assert!(
+        is_signed_int(imm, 6, 0) || is_unsigned_int(imm, 6, 0),
+        "CI out of range {:#x}",
+        imm
+    );
+    let imm = imm as u16;
+
+    let mut i = bits;
+    i |= (imm & 0x1f) << 2;
+    i |= rd << 7;
+    i |= ((imm >> 5) & 0x1) << 12;
+
+    sink.put2(i);
+}
+
+/// The CI-type `c.addi16sp` instruction adding a multiple of 16 to the stack pointer.
+///
+///   15     12  11 6   1
+///   funct3 imm sp imm op
+///       13  12  7   2  0
+fn put_ci16sp<CS: CodeSink + ?Sized>(bits: u16, imm: i64, sink: &mut CS) {
+    debug_assert!(is_signed_int(imm, 10, 4), "CI16SP out of range {:#x}", imm);
+    let imm = imm as u16;
+
+    let mut i = bits;
+    i |= SP << 7;
+
+    // The immediate bits are scrambled.
+    i |= ((imm >> 5) & 0x1) << 2;
+    i |= ((imm >> 7) & 0x3) << 3;
+    i |= ((imm >> 6) & 0x1) << 5;
+    i |= ((imm >> 4) & 0x1) << 6;
+    i |= ((imm >> 9) & 0x1) << 12;
+
+    sink.put2(i);
+}
+
+/// CA-type arithmetic instructions.
+///
+///   15     9       6      4    1
+///   funct6 rd'/rs1' funct2 rs2' op
+///       10        7      5    2  0
+fn put_ca<CS: CodeSink + ?Sized>(bits: u16, rd: RegUnit, rs2: RegUnit, sink: &mut CS) {
+    let mut i = bits;
+    i |= creg(rd) << 7;
+    i |= creg(rs2) << 2;
+
+    sink.put2(i);
+}
+
+/// CB-type immediate instructions.
+///
+/// The 6-bit immediate is signed, except for shift amounts.
+///
+///   15     12  11     9        6   1
+///   funct3 imm funct2 rd'/rs1' imm op
+///       13  12     10        7   2  0
+fn put_cb<CS: CodeSink + ?Sized>(bits: u16, rd: RegUnit, imm: i64, sink: &mut CS) {
+    debug_assert!(
+        is_signed_int(imm, 6, 0) || is_unsigned_int(imm, 6, 0),
+        "CB out of range {:#x}",
+        imm
+    );
+    let imm = imm as u16;
+
+    let mut i = bits;
+    i |= (imm & 0x1f) << 2;
+    i |= creg(rd) << 7;
+    i |= ((imm >> 5) & 0x1) << 12;
+
+    sink.put2(i);
+}
+
+/// CB-type branch instructions.
+///
+///   15     12     9    6   1
+///   funct3 offset rs1' offset op
+///       13     10    7      2  0
+fn put_cbranch<CS: CodeSink + ?Sized>(bits: u16, imm: i64, rs1: RegUnit, sink: &mut CS) {
+    debug_assert!(is_signed_int(imm, 9, 1), "CB out of range {:#x}", imm);
+    let imm = imm as u16;
+
+    let mut i = bits;
+    i |= creg(rs1) << 7;
+
+    // The displacement is completely hashed up.
+    i |= ((imm >> 5) & 0x1) << 2;
+    i |= ((imm >> 1) & 0x3) << 3;
+    i |= ((imm >> 6) & 0x3) << 5;
+    i |= ((imm >> 3) & 0x3) << 10;
+    i |= ((imm >> 8) & 0x1) << 12;
+
+    sink.put2(i);
+}
+
+/// CJ-type jump instructions.
+///
+///   15     12          1
+///   funct3 jump target op
+///       13           2  0
+fn put_cj<CS: CodeSink + ?Sized>(bits: u16, imm: i64, sink: &mut CS) {
+    debug_assert!(is_signed_int(imm, 12, 1), "CJ out of range {:#x}", imm);
+    let imm = imm as u16;
+
+    let mut i = bits;
+
+    // The displacement is completely hashed up.
+    i |= ((imm >> 5) & 0x1) << 2;
+    i |= ((imm >> 1) & 0x7) << 3;
+    i |= ((imm >> 7) & 0x1) << 6;
+    i |= ((imm >> 6) & 0x1) << 7;
+    i |= ((imm >> 10) & 0x1) << 8;
+    i |= ((imm >> 8) & 0x3) << 9;
+    i |= ((imm >> 4) & 0x1) << 11;
+    i |= ((imm >> 11) & 0x1) << 12;
+
+    sink.put2(i);
+}
+
+/// CL-type loads and CS-type stores.
+///
+///   15     12  9    6   4        1
+///   funct3 imm rs1' imm rd'/rs2' op
+///       13  10    7   5        2  0
+///
+/// The offset is scaled by the access size. Word accesses put offset bits 2 and 6 in the low
+/// immediate field, double word accesses put offset bits 7:6 there.
+fn put_cl<CS: CodeSink + ?Sized>(bits: u16, rs1: RegUnit, imm: i64, rd: RegUnit, sink: &mut CS) {
+    let imm = imm as u16;
+
+    let mut i = bits;
+    i |= creg(rd) << 2;
+    i |= creg(rs1) << 7;
+    i |= ((imm >> 3) & 0x7) << 10;
+    if (bits >> 13) & 0x3 == 0b10 {
+        debug_assert_eq!(imm & 0x3, 0, "misaligned word offset");
+        i |= ((imm >> 6) & 0x1) << 5;
+        i |= ((imm >> 2) & 0x1) << 6;
+    } else {
+        debug_assert_eq!(imm & 0x7, 0, "misaligned double word offset");
+        i |= ((imm >> 6) & 0x3) << 5;
+    }
+
+    sink.put2(i);
+}
@@ -1,6 +1,11 @@
 //! Encoding tables for RISC-V.
 
-use ir;
+use bitset::BitSet;
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::{self, InstBuilder};
+use ir::condcodes::{CondCode, FloatCC, IntCC};
+use ir::immediates::{Ieee32, Ieee64, Offset32};
 use isa;
 use isa::constraints::*;
 use isa::enc_tables::*;
@@ -16,3 +21,304 @@ use super::registers::*;
 // - `INFO`
 include!(concat!(env!("OUT_DIR"), "/encoding-riscv.rs"));
 include!(concat!(env!("OUT_DIR"), "/legalize-riscv.rs"));
+
+/// Expand an `iconst` that can't be materialized by a single `lui` or `addi` instruction.
+///
+/// The constant is split into a `lui` part and a sign-extended 12-bit `addi` part. Constants that
+/// don't fit in 32 bits are built by shifting a smaller constant.
+fn expand_iconst(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let imm: i64 = match func.dfg[inst] {
+        ir::InstructionData::UnaryImm {
+            opcode: ir::Opcode::Iconst,
+            imm,
+        } => imm.into(),
+        _ => panic!("Need iconst: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+    let is_i32 = ty == ir::types::I32;
+
+    // 32-bit constants are kept sign-extended, and the 32-bit `addiw` instruction wraps around.
+    let imm = if is_i32 { i64::from(imm as i32) } else { imm };
+    let lo = (imm << 52) >> 52;
+    let hi = imm.wrapping_sub(lo);
+    let hi = if is_i32 { i64::from(hi as i32) } else { hi };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    if predicates::is_signed_int(imm, 12, 0) || predicates::is_signed_int(imm, 32, 12) {
+        // The constant just wasn't sign-extended.
+        pos.func.dfg.replace(inst).iconst(ty, imm);
+    } else if lo != 0 {
+        let hi = pos.ins().iconst(ty, hi);
+        pos.func.dfg.replace(inst).iadd_imm(hi, lo);
+    } else {
+        let shift = imm.trailing_zeros();
+        let base = pos.ins().iconst(ty, imm >> shift);
+        pos.func.dfg.replace(inst).ishl_imm(base, i64::from(shift));
+    }
+}
+
+/// Expand the `sdiv` and `srem` instructions using `rv_div` and `rv_rem`.
+///
+/// The RISC-V division instructions don't trap, so the division by zero and overflow checks are
+/// explicit.
+fn expand_sdivrem(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (x, y, is_srem) = match func.dfg[inst] {
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Sdiv,
+            args,
+        } => (args[0], args[1], false),
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Srem,
+            args,
+        } => (args[0], args[1], true),
+        _ => panic!("Need sdiv/srem: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    pos.ins().trapz(y, ir::TrapCode::IntegerDivisionByZero);
+
+    if is_srem {
+        // The remainder of `INT_MIN / -1` is 0, which is what `rem` computes.
+        pos.func.dfg.replace(inst).rv_rem(x, y);
+        return;
+    }
+
+    // Trap when x == INT_MIN and y == -1, which is the case when both `x ^ INT_MIN` and `y + 1`
+    // are 0.
+    let xmin = pos.ins().bxor_imm(x, -1i64 << (ty.lane_bits() - 1));
+    let yp1 = pos.ins().iadd_imm(y, 1);
+    let both = pos.ins().bor(xmin, yp1);
+    pos.ins().trapz(both, ir::TrapCode::IntegerOverflow);
+    pos.func.dfg.replace(inst).rv_div(x, y);
+}
+
+/// Expand the `udiv` and `urem` instructions using `rv_divu` and `rv_remu`.
+fn expand_udivrem(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (x, y, is_urem) = match func.dfg[inst] {
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Udiv,
+            args,
+        } => (args[0], args[1], false),
+        ir::InstructionData::Binary {
+            opcode: ir::Opcode::Urem,
+            args,
+        } => (args[0], args[1], true),
+        _ => panic!("Need udiv/urem: {}", func.dfg.display_inst(inst, None)),
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    pos.ins().trapz(y, ir::TrapCode::IntegerDivisionByZero);
+
+    if is_urem {
+        pos.func.dfg.replace(inst).rv_remu(x, y);
+    } else {
+        pos.func.dfg.replace(inst).rv_divu(x, y);
+    }
+}
+
+/// Expand `fcvt_to_sint` using `rv_fcvt_to_sint`.
+///
+/// The `fcvt` instructions saturate instead of trapping, so the input range is checked before the
+/// conversion.
+fn expand_fcvt_to_sint(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let x;
+    match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::FcvtToSint,
+            arg,
+        } => x = arg,
+        _ => panic!("Need fcvt_to_sint: {}", func.dfg.display_inst(inst, None)),
+    }
+    let xty = func.dfg.value_type(x);
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    // Determine the largest floating point number that is too small to convert to INT_MIN.
+    let mut overflow_cc = FloatCC::LessThan;
+    let output_bits = ty.lane_bits();
+    let (flimit, fmax) = match xty {
+        ir::types::F32 => {
+            let flimit = pos.ins().f32const(if output_bits < 32 {
+                overflow_cc = FloatCC::LessThanOrEqual;
+                Ieee32::fcvt_to_sint_negative_overflow(output_bits)
+            } else {
+                Ieee32::pow2(output_bits - 1).neg()
+            });
+            (flimit, pos.ins().f32const(Ieee32::pow2(output_bits - 1)))
+        }
+        ir::types::F64 => {
+            let flimit = pos.ins().f64const(if output_bits < 64 {
+                overflow_cc = FloatCC::LessThanOrEqual;
+                Ieee64::fcvt_to_sint_negative_overflow(output_bits)
+            } else {
+                Ieee64::pow2(output_bits - 1).neg()
+            });
+            (flimit, pos.ins().f64const(Ieee64::pow2(output_bits - 1)))
+        }
+        _ => panic!("Can't convert {}", xty),
+    };
+
+    // NaN is the only value that isn't equal to itself.
+    let is_ord = pos.ins().fcmp(FloatCC::Equal, x, x);
+    pos.ins().trapz(is_ord, ir::TrapCode::BadConversionToInteger);
+
+    let low = pos.ins().fcmp(overflow_cc, x, flimit);
+    pos.ins().trapnz(low, ir::TrapCode::IntegerOverflow);
+
+    // 2^(N-1) is the smallest positive value that is too large.
+    let high = pos.ins().fcmp(FloatCC::GreaterThanOrEqual, x, fmax);
+    pos.ins().trapnz(high, ir::TrapCode::IntegerOverflow);
+
+    pos.func.dfg.replace(inst).rv_fcvt_to_sint(ty, x);
+}
+
+/// Expand `fcvt_to_uint` using `rv_fcvt_to_uint`.
+///
+/// Valid inputs are in the range `(-1, 2^N)`, where the values in `(-1, 0]` truncate to 0.
+fn expand_fcvt_to_uint(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let x;
+    match func.dfg[inst] {
+        ir::InstructionData::Unary {
+            opcode: ir::Opcode::FcvtToUint,
+            arg,
+        } => x = arg,
+        _ => panic!("Need fcvt_to_uint: {}", func.dfg.display_inst(inst, None)),
+    }
+    let xty = func.dfg.value_type(x);
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let (fmin, fmax) = match xty {
+        ir::types::F32 => {
+            (
+                pos.ins().f32const(Ieee32::with_float(-1.0)),
+                pos.ins().f32const(Ieee32::pow2(ty.lane_bits())),
+            )
+        }
+        ir::types::F64 => {
+            (
+                pos.ins().f64const(Ieee64::with_float(-1.0)),
+                pos.ins().f64const(Ieee64::pow2(ty.lane_bits())),
+            )
+        }
+        _ => panic!("Can't convert {}", xty),
+    };
+
+    let is_ord = pos.ins().fcmp(FloatCC::Equal, x, x);
+    pos.ins().trapz(is_ord, ir::TrapCode::BadConversionToInteger);
+
+    let low = pos.ins().fcmp(FloatCC::LessThanOrEqual, x, fmin);
+    pos.ins().trapnz(low, ir::TrapCode::IntegerOverflow);
+
+    let high = pos.ins().fcmp(FloatCC::GreaterThanOrEqual, x, fmax);
+    pos.ins().trapnz(high, ir::TrapCode::IntegerOverflow);
+
+    pos.func.dfg.replace(inst).rv_fcvt_to_uint(ty, x);
+}
+
+/// Expand a load or store whose offset can't be encoded by adding the offset to the address
+/// first.
+fn expand_mem_offset(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    let (addr, offset) = match func.dfg[inst] {
+        ir::InstructionData::Load { arg, offset, .. } => (arg, offset),
+        ir::InstructionData::Store { args, offset, .. } => (args[1], offset),
+        _ => panic!("Need load/store: {}", func.dfg.display_inst(inst, None)),
+    };
+    let offset: i64 = offset.into();
+    if offset == 0 {
+        panic!("Can't encode {}", func.dfg.display_inst(inst, None));
+    }
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    let base = pos.ins().iadd_imm(addr, offset);
+
+    match pos.func.dfg[inst] {
+        ir::InstructionData::Load {
+            ref mut arg,
+            ref mut offset,
+            ..
+        } => {
+            *arg = base;
+            *offset = Offset32::new(0);
+        }
+        ir::InstructionData::Store {
+            ref mut args,
+            ref mut offset,
+            ..
+        } => {
+            args[1] = base;
+            *offset = Offset32::new(0);
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Expand a `br_icmp` with a condition that the branch instructions can't test by swapping the
+/// operands.
+fn expand_br_icmp(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &isa::TargetIsa,
+) {
+    match func.dfg[inst] {
+        ir::InstructionData::BranchIcmp {
+            opcode: ir::Opcode::BrIcmp,
+            ref mut cond,
+            ..
+        } => {
+            match *cond {
+                IntCC::SignedGreaterThan |
+                IntCC::SignedLessThanOrEqual |
+                IntCC::UnsignedGreaterThan |
+                IntCC::UnsignedLessThanOrEqual => {}
+                _ => panic!("Can't encode br_icmp {}", cond),
+            }
+            *cond = cond.reverse();
+        }
+        _ => panic!("Need br_icmp: {}", func.dfg.display_inst(inst, None)),
+    }
+    func.dfg.inst_args_mut(inst).swap(0, 1);
+}
@@ -0,0 +1,21 @@
+//! Instruction latencies for scheduling.
+//!
+//! The latencies are those of a typical in-order RISC-V pipeline with a single issue slot, like
+//! the Rocket core.
+
+use ir::Opcode;
+
+/// Get the number of cycles before the results of an instruction with `opcode` can be used.
+pub fn latency(opcode: Opcode) -> u32 {
+    match opcode {
+        Opcode::Imul | Opcode::ImulImm | Opcode::Umulhi | Opcode::Smulhi => 4,
+        Opcode::Udiv | Opcode::Sdiv | Opcode::Urem | Opcode::Srem | Opcode::UdivImm |
+        Opcode::SdivImm | Opcode::UremImm | Opcode::SremImm => 33,
+        Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fma => 4,
+        Opcode::Fdiv | Opcode::Sqrt => 20,
+        Opcode::FcvtToUint | Opcode::FcvtToSint | Opcode::FcvtFromUint |
+        Opcode::FcvtFromSint | Opcode::Fpromote | Opcode::Fdemote => 2,
+        _ if opcode.can_load() => 3,
+        _ => 1,
+    }
+}
@@ -26,6 +26,7 @@ mod tests {
                     supports_a = false\n\
                     supports_f = false\n\
                     supports_d = false\n\
+                    supports_c = false\n\
                     enable_m = true\n\
                     enable_e = false\n"
         );
@@ -51,4 +52,17 @@ mod tests {
         let f = Flags::new(&shared, &b);
         assert_eq!(f.full_float(), false);
     }
+
+    #[test]
+    fn compressed() {
+        let mut b = builder();
+        b.enable("supports_c").unwrap();
+        let f = Flags::new(&settings::Flags::new(&settings::builder()), &b);
+        assert_eq!(f.use_c(), false);
+
+        let mut sb = settings::builder();
+        sb.enable("is_compressed").unwrap();
+        let f = Flags::new(&settings::Flags::new(&sb), &b);
+        assert_eq!(f.use_c(), true);
+    }
 }
@@ -0,0 +1,95 @@
+//! Legalization of atomic read-modify-write instructions.
+//!
+//! This module exports the `expand_atomic_rmw` function which rewrites an `atomic_rmw`
+//! instruction that the target can't encode in terms of `atomic_cas`.
+
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::condcodes::IntCC;
+use ir::{self, AtomicOrdering, AtomicRmwOp, InstBuilder};
+use isa::TargetIsa;
+
+/// Expand an `atomic_rmw` instruction.
+///
+/// A `sub` is first rewritten as an `add` of the negated operand since more targets have an
+/// atomic add instruction. The other operations become a compare-and-swap loop.
+pub fn expand_atomic_rmw(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    _isa: &TargetIsa,
+) {
+    let (op, flags, ordering, addr, arg) = match func.dfg[inst] {
+        ir::InstructionData::AtomicRmw {
+            opcode: ir::Opcode::AtomicRmw,
+            op,
+            flags,
+            ordering,
+            args,
+        } => (op, flags, ordering, args[0], args[1]),
+        _ => panic!("Wanted atomic_rmw: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.ctrl_typevar(inst);
+
+    if op == AtomicRmwOp::Sub {
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        pos.use_srcloc(inst);
+        let neg = pos.ins().irsub_imm(arg, 0);
+        pos.func.dfg.replace(inst).atomic_rmw(
+            AtomicRmwOp::Add,
+            flags,
+            ordering,
+            addr,
+            neg,
+        );
+        return;
+    }
+
+    // Replace `result = atomic_rmw op, p, x` with:
+    //
+    //   v0 = atomic_load relaxed p
+    //   jump loop_ebb(v0)
+    // loop_ebb(old):
+    //   new = op old, x
+    //   prev = atomic_cas p, old, new
+    //   ok = icmp eq prev, old
+    //   brz ok, loop_ebb(prev)
+    //   jump done_ebb(prev)
+    // done_ebb(result):
+    //
+    // The initial load doesn't need to be ordered since the successful `atomic_cas` provides the
+    // ordering.
+    let old_ebb = func.layout.pp_ebb(inst);
+    let result = func.dfg.first_result(inst);
+    func.dfg.clear_results(inst);
+    let loop_ebb = func.dfg.make_ebb();
+    let old = func.dfg.append_ebb_param(loop_ebb, ty);
+    let done_ebb = func.dfg.make_ebb();
+    func.dfg.attach_ebb_param(done_ebb, result);
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    let initial = pos.ins().atomic_load(ty, flags, AtomicOrdering::Relaxed, addr);
+    pos.func.dfg.replace(inst).jump(loop_ebb, &[initial]);
+
+    let mut pos = FuncCursor::new(pos.func).after_inst(inst);
+    pos.use_srcloc(inst);
+    pos.insert_ebb(loop_ebb);
+    let new = match op {
+        AtomicRmwOp::Add => pos.ins().iadd(old, arg),
+        AtomicRmwOp::Sub => pos.ins().isub(old, arg),
+        AtomicRmwOp::And => pos.ins().band(old, arg),
+        AtomicRmwOp::Or => pos.ins().bor(old, arg),
+        AtomicRmwOp::Xor => pos.ins().bxor(old, arg),
+        AtomicRmwOp::Xchg => arg,
+    };
+    let prev = pos.ins().atomic_cas(flags, ordering, addr, old, new);
+    let ok = pos.ins().icmp(IntCC::Equal, prev, old);
+    pos.ins().brz(ok, loop_ebb, &[prev]);
+    pos.ins().jump(done_ebb, &[prev]);
+    pos.insert_ebb(done_ebb);
+
+    cfg.recompute_ebb(pos.func, old_ebb);
+    cfg.recompute_ebb(pos.func, loop_ebb);
+    cfg.recompute_ebb(pos.func, done_ebb);
+}
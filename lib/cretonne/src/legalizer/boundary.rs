@@ -17,11 +17,14 @@
 //! Between the two phases, preamble signatures and call/return arguments don't match. This
 //! intermediate state doesn't type check.
 
-use abi::{legalize_abi_value, ValueConversion};
+use abi::{legalize_abi_value, struct_return_layout, ValueConversion};
 use cursor::{Cursor, FuncCursor};
 use flowgraph::ControlFlowGraph;
 use ir::{Function, DataFlowGraph, Inst, InstBuilder, Ebb, Type, Value, Signature, SigRef,
-         AbiParam, ArgumentPurpose, ArgumentLoc, ValueLoc};
+         AbiParam, ArgumentPurpose, ArgumentLoc, ValueLoc, MemFlags, StackSlotData,
+         StackSlotKind};
+use ir::condcodes::IntCC;
+use ir::types::{B1, I32, I64};
 use ir::instructions::CallInfo;
 use isa::TargetIsa;
 use legalizer::split::{isplit, vsplit};
@@ -32,12 +35,23 @@ use std::vec::Vec;
 /// This changes all signatures to be ABI-compliant with full `ArgumentLoc` annotations. It doesn't
 /// change the entry block arguments, calls, or return instructions, so this can leave the function
 /// in a state with type discrepancies.
+///
+/// The signatures that now return values in memory are remembered in `func.old_signature` and
+/// `func.dfg.old_signatures`.
 pub fn legalize_signatures(func: &mut Function, isa: &TargetIsa) {
+    let old = func.signature.clone();
     isa.legalize_signature(&mut func.signature, true);
     func.signature.compute_argument_bytes();
+    if returns_in_memory(&old, &func.signature) {
+        func.old_signature = Some(old);
+    }
     for sig in func.dfg.signatures.keys() {
+        let old = func.dfg.signatures[sig].clone();
         isa.legalize_signature(&mut func.dfg.signatures[sig], false);
         func.dfg.signatures[sig].compute_argument_bytes();
+        if returns_in_memory(&old, &func.dfg.signatures[sig]) {
+            func.dfg.old_signatures[sig] = Some(old);
+        }
     }
 
     if let Some(entry) = func.layout.entry_block() {
@@ -46,6 +60,11 @@ pub fn legalize_signatures(func: &mut Function, isa: &TargetIsa) {
     }
 }
 
+/// Did the legalization of the `old` signature into `new` move the return values to memory?
+fn returns_in_memory(old: &Signature, new: &Signature) -> bool {
+    !old.uses_struct_return_param() && new.uses_struct_return_param()
+}
+
 /// Legalize the entry block parameters after `func`'s signature has been legalized.
 ///
 /// The legalized signature may contain more parameters than the original signature, and the
@@ -63,7 +82,7 @@ fn legalize_entry_params(func: &mut Function, entry: Ebb) {
     // Insert position for argument conversion code.
     // We want to insert instructions before the first instruction in the entry block.
     // If the entry block is empty, append instructions to it instead.
-    let mut pos = FuncCursor::new(func).at_first_inst(entry);
+    let mut pos = FuncCursor::new(func).at_first_insertion_point(entry);
 
     // Keep track of the argument types in the ABI-legalized signature.
     let mut abi_arg = 0;
@@ -72,6 +91,15 @@ fn legalize_entry_params(func: &mut Function, entry: Ebb) {
     // ones. We do this by detaching the entry EBB parameters first.
     let ebb_params = pos.func.dfg.detach_ebb_params(entry);
     let mut old_arg = 0;
+
+    // The `StructReturn` parameter added for return values in memory comes first.
+    if pos.func.old_signature.is_some() {
+        let abi_type = pos.func.signature.params[0];
+        debug_assert_eq!(abi_type.purpose, ArgumentPurpose::StructReturn);
+        pos.func.dfg.append_ebb_param(entry, abi_type.value_type);
+        has_sret = true;
+        abi_arg += 1;
+    }
     while let Some(arg) = ebb_params.get(old_arg, &pos.func.dfg.value_lists) {
         old_arg += 1;
 
@@ -154,6 +182,10 @@ fn legalize_entry_params(func: &mut Function, entry: Ebb) {
                 debug_assert!(!has_sigid, "Multiple sigid parameters found");
                 has_sigid = true;
             }
+            // Only calls to variadic functions pass a vector register count.
+            ArgumentPurpose::VarArgCount => {
+                panic!("Unexpected vacount parameter {}", arg);
+            }
         }
 
         // Just create entry block values to match here. We will use them in `handle_return_abi()`
@@ -377,8 +409,10 @@ fn check_call_signature(dfg: &DataFlowGraph, inst: Inst) -> Result<(), SigRef> {
     };
     let sig = &dfg.signatures[sig_ref];
 
+    // A tail call has no results since the callee returns directly to our caller.
+    let tail_call = dfg[inst].opcode().is_terminator();
     if check_arg_types(dfg, args, &sig.params[..]) &&
-        check_arg_types(dfg, dfg.inst_results(inst), &sig.returns[..])
+        (tail_call || check_arg_types(dfg, dfg.inst_results(inst), &sig.returns[..]))
     {
         // All types check out.
         Ok(())
@@ -500,13 +534,39 @@ pub fn handle_call_abi(mut inst: Inst, func: &mut Function, cfg: &ControlFlowGra
         Err(s) => s,
     };
 
+    // A call returning values in memory passes a pointer to the memory receiving them.
+    if pos.func.dfg.old_signatures[sig_ref].is_some() {
+        pass_struct_return(pos, inst, sig_ref);
+    }
+
+    // A variadic call may need to pass the number of vector registers used for arguments.
+    let vacount = pos.func.dfg.signatures[sig_ref].special_param_index(
+        ArgumentPurpose::VarArgCount,
+    );
+    if let Some(idx) = vacount {
+        let (ty, count) = {
+            let params = &pos.func.dfg.signatures[sig_ref].params;
+            let count = params
+                .iter()
+                .filter(|p| {
+                    p.location.is_reg() && (p.value_type.is_float() || p.value_type.is_vector())
+                })
+                .count();
+            (params[idx].value_type, count)
+        };
+        let arg = pos.ins().iconst(ty, count as i64);
+        pos.func.dfg.append_inst_arg(inst, arg);
+    }
+
     // OK, we need to fix the call arguments to match the ABI signature.
     let abi_args = pos.func.dfg.signatures[sig_ref].params.len();
     legalize_inst_arguments(pos, cfg, abi_args, |func, abi_arg| {
         func.dfg.signatures[sig_ref].params[abi_arg]
     });
 
-    if !pos.func.dfg.signatures[sig_ref].returns.is_empty() {
+    if !pos.func.dfg.signatures[sig_ref].returns.is_empty() &&
+        !pos.func.dfg[inst].opcode().is_terminator()
+    {
         inst = legalize_inst_results(pos, |func, abi_res| {
             func.dfg.signatures[sig_ref].returns[abi_res]
         });
@@ -537,6 +597,11 @@ pub fn handle_return_abi(inst: Inst, func: &mut Function, cfg: &ControlFlowGraph
         return false;
     }
 
+    // Return values in memory are stored through the `sret` parameter instead.
+    if func.old_signature.is_some() && !func.dfg.inst_variable_args(inst).is_empty() {
+        store_struct_return(inst, func);
+    }
+
     // Count the special-purpose return values (`link`, `sret`, and `vmctx`) that were appended to
     // the legalized signature.
     let special_args = func.signature
@@ -608,6 +673,166 @@ pub fn handle_return_abi(inst: Inst, func: &mut Function, cfg: &ControlFlowGraph
     true
 }
 
+/// Get the flags of the memory accesses to return values in memory.
+fn struct_return_flags() -> MemFlags {
+    let mut flags = MemFlags::new();
+    flags.set_notrap();
+    flags.set_aligned();
+    flags
+}
+
+/// Store the arguments of the return instruction `inst` in the memory pointed to by the `sret`
+/// parameter of `func`, and remove them from the instruction.
+fn store_struct_return(inst: Inst, func: &mut Function) {
+    let types: Vec<Type> = func.old_signature
+        .as_ref()
+        .expect("return values in memory")
+        .returns
+        .iter()
+        .map(|r| r.value_type)
+        .collect();
+    let (offsets, _) = struct_return_layout(&types);
+    let idx = func.signature
+        .special_param_index(ArgumentPurpose::StructReturn)
+        .expect("missing sret parameter");
+    let sret = func.dfg.ebb_params(func.layout.entry_block().unwrap())[idx];
+    let values = func.dfg.inst_variable_args(inst).to_vec();
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    for (&value, &offset) in values.iter().zip(&offsets) {
+        store_return_value(&mut pos, value, sret, offset as i32);
+    }
+
+    let fixed_values = pos.func.dfg[inst]
+        .opcode()
+        .constraints()
+        .fixed_value_arguments();
+    let mut vlist = pos.func.dfg[inst].take_value_list().unwrap();
+    vlist.truncate(fixed_values, &mut pos.func.dfg.value_lists);
+    pos.func.dfg[inst].put_value_list(vlist);
+}
+
+/// Pass the `sret` argument of the call `inst` to a function returning values in memory, and
+/// load the results of the call from the memory.
+///
+/// The memory is a stack slot of the caller. A tail call passes the memory of the caller's own
+/// return values instead, so the callee returns them directly to the caller's caller.
+fn pass_struct_return(pos: &mut FuncCursor, inst: Inst, sig_ref: SigRef) {
+    let types: Vec<Type> = pos.func.dfg.old_signatures[sig_ref]
+        .as_ref()
+        .expect("return values in memory")
+        .returns
+        .iter()
+        .map(|r| r.value_type)
+        .collect();
+    let (offsets, size) = struct_return_layout(&types);
+    let ptr_type = {
+        let sig = &pos.func.dfg.signatures[sig_ref];
+        let idx = sig.special_param_index(ArgumentPurpose::StructReturn).unwrap();
+        sig.params[idx].value_type
+    };
+
+    let tail_call = pos.func.dfg[inst].opcode().is_terminator();
+    let mut slot = None;
+    let sret = if tail_call {
+        let idx = pos.func
+            .signature
+            .special_param_index(ArgumentPurpose::StructReturn)
+            .expect("tail call returning values in memory from a function that doesn't");
+        pos.func.dfg.ebb_params(pos.func.layout.entry_block().unwrap())[idx]
+    } else {
+        let ss = pos.func.create_stack_slot(
+            StackSlotData::new(StackSlotKind::ExplicitSlot, size),
+        );
+        slot = Some(ss);
+        pos.ins().stack_addr(ptr_type, ss, 0)
+    };
+
+    // The `sret` argument comes before the other arguments.
+    let fixed_values = pos.func.dfg[inst]
+        .opcode()
+        .constraints()
+        .fixed_value_arguments();
+    let mut vlist = pos.func.dfg[inst].take_value_list().unwrap();
+    vlist.insert(fixed_values, sret, &mut pos.func.dfg.value_lists);
+    pos.func.dfg[inst].put_value_list(vlist);
+
+    let ss = match slot {
+        Some(ss) => ss,
+        None => return,
+    };
+
+    // The call now only returns the special-purpose values of the legalized signature. The
+    // original results are loaded from the stack slot.
+    let results = pos.func.dfg.detach_results(inst);
+    for i in 0..pos.func.dfg.signatures[sig_ref].returns.len() {
+        let ty = pos.func.dfg.signatures[sig_ref].returns[i].value_type;
+        pos.func.dfg.append_result(inst, ty);
+    }
+    pos.goto_after_inst(inst);
+    let addr = pos.ins().stack_addr(ptr_type, ss, 0);
+    for (i, &offset) in offsets.iter().enumerate() {
+        let res = results.get(i, &pos.func.dfg.value_lists).unwrap();
+        load_return_value(pos, res, addr, offset as i32);
+    }
+    pos.goto_inst(inst);
+}
+
+/// Is `ty` stored in memory as a wider integer with a narrowing store?
+///
+/// Booleans are stored as integers of the same size with the value 0 or 1.
+fn is_narrow_in_memory(ty: Type) -> bool {
+    ty.is_bool() || (ty.is_int() && ty.bits() < 32)
+}
+
+/// Store the return value `value` at `addr + offset`.
+fn store_return_value(pos: &mut FuncCursor, value: Value, addr: Value, offset: i32) {
+    let flags = struct_return_flags();
+    let ty = pos.func.dfg.value_type(value);
+    if !is_narrow_in_memory(ty) {
+        pos.ins().store(flags, value, addr, offset);
+        return;
+    }
+
+    let wide = if ty.bits() > 32 { I64 } else { I32 };
+    let value = if ty.is_bool() {
+        pos.ins().bint(wide, value)
+    } else {
+        pos.ins().uextend(wide, value)
+    };
+    match ty.bytes() {
+        1 => pos.ins().istore8(flags, value, addr, offset),
+        2 => pos.ins().istore16(flags, value, addr, offset),
+        _ => pos.ins().store(flags, value, addr, offset),
+    };
+}
+
+/// Load the return value `res` from `addr + offset`.
+fn load_return_value(pos: &mut FuncCursor, res: Value, addr: Value, offset: i32) {
+    let flags = struct_return_flags();
+    let ty = pos.func.dfg.value_type(res);
+    if !is_narrow_in_memory(ty) {
+        pos.ins().with_result(res).load(ty, flags, addr, offset);
+        return;
+    }
+
+    let wide = if ty.bits() > 32 { I64 } else { I32 };
+    let value = match ty.bytes() {
+        1 => pos.ins().uload8(I32, flags, addr, offset),
+        2 => pos.ins().uload16(I32, flags, addr, offset),
+        _ => pos.ins().load(wide, flags, addr, offset),
+    };
+    if ty.is_int() {
+        pos.ins().with_result(res).ireduce(ty, value);
+    } else if ty == B1 {
+        pos.ins().with_result(res).icmp_imm(IntCC::NotEqual, value, 0);
+    } else {
+        let b = pos.ins().icmp_imm(IntCC::NotEqual, value, 0);
+        pos.ins().with_result(res).bextend(ty, b);
+    }
+}
+
 /// Assign stack slots to incoming function parameters on the stack.
 ///
 /// Values that are passed into the function on the stack must be assigned to an `IncomingArg`
@@ -0,0 +1,371 @@
+//! Lowering of `br_table` instructions.
+//!
+//! The entries of the jump table are grouped into cases, which are runs of consecutive entries
+//! with the same destination. The dense part of the table is dispatched with an indirect branch
+//! through a new jump table when the ISA supports it, and the remaining sparse cases are tested
+//! one by one:
+//!
+//! - A case covering more than one entry is tested with a single unsigned comparison, `x - lo <=
+//!   hi - lo`. The same trick is used for the bounds check of the dense part.
+//! - When the `br_table` is followed by a `jump`, the entries branching to the same EBB as the jump
+//!   are treated like missing entries, since falling through reaches the same destination. This
+//!   makes a table sparse in the common case where the default destination appears in the table.
+//! - The dense part is found by dropping the cases at the ends of the table that are farthest from
+//!   their neighbors until enough of the entries in between are present.
+//!
+//! Once the whole function has been legalized, `merge_jump_tables()` makes the indirect branches
+//! through identical jump tables use the same table, so it is only emitted once.
+
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::condcodes::IntCC;
+use ir::types::{I32, I64};
+use ir::{self, Ebb, InstBuilder, InstructionData, JumpTable, JumpTableData, MemFlags, Opcode};
+use isa::TargetIsa;
+use std::ops::Range;
+use std::vec::Vec;
+
+/// Minimum number of cases dispatched through a jump table. Fewer cases are tested one by one.
+const MIN_JUMP_TABLE_CASES: usize = 4;
+
+/// Minimum percentage of the entries of a jump table that must be present in the `br_table`.
+const MIN_JUMP_TABLE_DENSITY: u64 = 40;
+
+/// A run of consecutive jump table entries with the same destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Case {
+    lo: u64,
+    hi: u64,
+    dest: Ebb,
+}
+
+impl Case {
+    /// Get the number of entries in this case.
+    fn len(&self) -> u64 {
+        self.hi - self.lo + 1
+    }
+}
+
+/// Expand a `br_table` instruction into compare-and-branch instructions and an indirect branch.
+pub fn expand_br_table(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+) {
+    let (arg, table) = match func.dfg[inst] {
+        InstructionData::BranchTable {
+            opcode: Opcode::BrTable,
+            arg,
+            table,
+        } => (arg, table),
+        _ => panic!("Expected br_table: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ebb = func.layout.pp_ebb(inst);
+    let next = func.layout.next_inst(inst).expect(
+        "br_table can't be the last instruction in an EBB",
+    );
+
+    // `br_table` falls through when nothing matches, so the entries branching to the destination
+    // of a following jump don't need to be tested.
+    let default = if func.dfg[next].opcode() == Opcode::Jump &&
+        func.dfg.inst_variable_args(next).is_empty()
+    {
+        func.dfg[next].branch_destination()
+    } else {
+        None
+    };
+
+    // The entries that don't fit in the type of `arg` are unreachable.
+    let bits = func.dfg.value_type(arg).bits();
+    let max_index = if bits < 64 {
+        (1 << bits) - 1
+    } else {
+        u64::max_value()
+    };
+    let cases = collect_cases(&func.jump_tables[table], default, max_index);
+
+    let ptr_ty = if isa.flags().is_64bit() { I64 } else { I32 };
+    let core = if jump_tables_supported(func, isa, table, ptr_ty) {
+        dense_core(&cases)
+    } else {
+        None
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    let core = match core {
+        Some(core) => core,
+        None => {
+            emit_compare_chain(&mut pos, arg, &cases);
+            pos.remove_inst();
+            cfg.recompute_ebb(pos.func, ebb);
+            return;
+        }
+    };
+
+    // The indirect branch terminates the EBB, so the instructions following the `br_table` are
+    // moved to a new EBB which starts with the tests of the sparse cases. The default jump is
+    // simply removed when there is nothing else to test.
+    let sparse: Vec<Case> = cases[..core.start]
+        .iter()
+        .chain(&cases[core.end..])
+        .cloned()
+        .collect();
+    let rest = if sparse.is_empty() && default.is_some() {
+        pos.func.layout.remove_inst(next);
+        None
+    } else {
+        let rest = pos.func.dfg.make_ebb();
+        pos.func.layout.split_ebb(rest, next);
+        Some(rest)
+    };
+    let missing = match default {
+        Some(dest) => dest,
+        None => rest.unwrap(),
+    };
+    let out_of_bounds = if sparse.is_empty() {
+        missing
+    } else {
+        rest.unwrap()
+    };
+
+    // Bounds check.
+    let lo = cases[core.start].lo;
+    let hi = cases[core.end - 1].hi;
+    let idx = if lo == 0 {
+        arg
+    } else {
+        pos.ins().iadd_imm(arg, (lo as i64).wrapping_neg())
+    };
+    let oob = pos.ins().icmp_imm(
+        IntCC::UnsignedGreaterThan,
+        idx,
+        (hi - lo) as i64,
+    );
+    pos.ins().brnz(oob, out_of_bounds, &[]);
+
+    // Indirect branch through a dense jump table.
+    let mut data = JumpTableData::with_capacity((hi - lo + 1) as usize);
+    for case in &cases[core] {
+        while (data.len() as u64) < case.lo - lo {
+            data.push_entry(missing);
+        }
+        for _ in 0..case.len() {
+            data.push_entry(case.dest);
+        }
+    }
+    let jt = pos.func.create_jump_table(data);
+
+    let idx_ty = pos.func.dfg.value_type(idx);
+    let idx = if idx_ty.bits() < ptr_ty.bits() {
+        pos.ins().uextend(ptr_ty, idx)
+    } else if idx_ty.bits() > ptr_ty.bits() {
+        pos.ins().ireduce(ptr_ty, idx)
+    } else {
+        idx
+    };
+    let mut flags = MemFlags::new();
+    flags.set_notrap();
+    flags.set_aligned();
+    flags.set_readonly();
+    let base = pos.ins().jump_table_base(ptr_ty, jt);
+    let offset = pos.ins().ishl_imm(idx, 2);
+    let entry_addr = pos.ins().iadd(base, offset);
+    let entry = if ptr_ty == I64 {
+        pos.ins().sload32(flags, entry_addr, 0)
+    } else {
+        pos.ins().load(I32, flags, entry_addr, 0)
+    };
+    let dest = pos.ins().iadd(base, entry);
+    pos.ins().indirect_jump_table_br(dest, jt);
+    pos.remove_inst();
+
+    cfg.recompute_ebb(pos.func, ebb);
+
+    // The sparse cases are only tested when the bounds check fails.
+    if let Some(rest) = rest {
+        pos.goto_inst(next);
+        emit_compare_chain(&mut pos, arg, &sparse);
+        cfg.recompute_ebb(pos.func, rest);
+    }
+}
+
+/// Get the cases of the jump table `data`, leaving out the entries branching to `default` and the
+/// entries above `max_index`.
+fn collect_cases(data: &JumpTableData, default: Option<Ebb>, max_index: u64) -> Vec<Case> {
+    let mut cases: Vec<Case> = Vec::new();
+    for (idx, dest) in data.entries() {
+        let idx = idx as u64;
+        if Some(dest) == default || idx > max_index {
+            continue;
+        }
+        if let Some(last) = cases.last_mut() {
+            if last.dest == dest && last.hi + 1 == idx {
+                last.hi = idx;
+                continue;
+            }
+        }
+        cases.push(Case {
+            lo: idx,
+            hi: idx,
+            dest,
+        });
+    }
+    cases
+}
+
+/// Find the range of `cases` that should be dispatched through a jump table, if any.
+fn dense_core(cases: &[Case]) -> Option<Range<usize>> {
+    let mut core = 0..cases.len();
+    let mut entries: u64 = cases.iter().map(Case::len).sum();
+    while core.len() >= MIN_JUMP_TABLE_CASES {
+        let span = cases[core.end - 1].hi - cases[core.start].lo + 1;
+        if entries * 100 >= span * MIN_JUMP_TABLE_DENSITY {
+            return Some(core);
+        }
+
+        // Drop the case at the end that is farthest from its neighbor.
+        let first_gap = cases[core.start + 1].lo - cases[core.start].hi;
+        let last_gap = cases[core.end - 1].lo - cases[core.end - 2].hi;
+        if first_gap > last_gap {
+            entries -= cases[core.start].len();
+            core.start += 1;
+        } else {
+            entries -= cases[core.end - 1].len();
+            core.end -= 1;
+        }
+    }
+    None
+}
+
+/// Can `isa` dispatch through jump tables?
+fn jump_tables_supported(
+    func: &ir::Function,
+    isa: &TargetIsa,
+    table: JumpTable,
+    ptr_ty: ir::Type,
+) -> bool {
+    let data = InstructionData::BranchTableBase {
+        opcode: Opcode::JumpTableBase,
+        table,
+    };
+    isa.encode(&func.dfg, &data, ptr_ty).is_ok()
+}
+
+/// Insert instructions testing `x` against each of `cases` in turn at `pos`.
+fn emit_compare_chain(pos: &mut FuncCursor, x: ir::Value, cases: &[Case]) {
+    for case in cases {
+        let t = if case.lo == case.hi {
+            pos.ins().icmp_imm(IntCC::Equal, x, case.lo as i64)
+        } else {
+            let offset = if case.lo == 0 {
+                x
+            } else {
+                pos.ins().iadd_imm(x, (case.lo as i64).wrapping_neg())
+            };
+            pos.ins().icmp_imm(
+                IntCC::UnsignedLessThanOrEqual,
+                offset,
+                (case.hi - case.lo) as i64,
+            )
+        };
+        pos.ins().brnz(t, case.dest, &[]);
+    }
+}
+
+/// Make the indirect branches of `func` through identical jump tables use the same table.
+///
+/// The jump tables are emitted after the code of the function, and each table used by a
+/// `jump_table_base` instruction takes space.
+pub fn merge_jump_tables(func: &mut ir::Function) {
+    let mut used: Vec<JumpTable> = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            let table = match func.dfg[inst] {
+                InstructionData::BranchTableBase { ref mut table, .. } |
+                InstructionData::BranchTable {
+                    opcode: Opcode::IndirectJumpTableBr,
+                    ref mut table,
+                    ..
+                } => table,
+                _ => continue,
+            };
+            let jump_tables = &func.jump_tables;
+            match used.iter().find(
+                |&&jt| jump_tables[jt] == jump_tables[*table],
+            ) {
+                Some(&jt) => *table = jt,
+                None => used.push(*table),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::EntityRef;
+
+    fn case(lo: u64, hi: u64, dest: usize) -> Case {
+        Case {
+            lo,
+            hi,
+            dest: Ebb::new(dest),
+        }
+    }
+
+    #[test]
+    fn cases() {
+        let mut data = JumpTableData::new();
+        for (idx, &dest) in [1, 1, 2, 0, 0, 1, 1].iter().enumerate() {
+            data.set_entry(idx, Ebb::new(dest));
+        }
+        data.set_entry(300, Ebb::new(1));
+        data.clear_entry(1);
+
+        assert_eq!(
+            collect_cases(&data, None, u64::max_value()),
+            [
+                case(0, 0, 1),
+                case(2, 2, 2),
+                case(3, 4, 0),
+                case(5, 6, 1),
+                case(300, 300, 1),
+            ]
+        );
+        assert_eq!(
+            collect_cases(&data, Some(Ebb::new(0)), 255),
+            [case(0, 0, 1), case(2, 2, 2), case(5, 6, 1)]
+        );
+    }
+
+    #[test]
+    fn core() {
+        // Too few cases.
+        let cases = [case(0, 0, 1), case(1, 1, 2), case(2, 9, 3)];
+        assert_eq!(dense_core(&cases), None);
+
+        // The outliers at both ends are dropped.
+        let cases = [
+            case(0, 0, 1),
+            case(100, 100, 2),
+            case(102, 102, 3),
+            case(103, 103, 4),
+            case(105, 106, 5),
+            case(1000, 1000, 6),
+        ];
+        assert_eq!(dense_core(&cases), Some(1..5));
+
+        // Too sparse everywhere.
+        let cases = [
+            case(0, 0, 1),
+            case(10, 10, 2),
+            case(20, 20, 3),
+            case(30, 30, 4),
+            case(40, 40, 5),
+        ];
+        assert_eq!(dense_core(&cases), None);
+    }
+}
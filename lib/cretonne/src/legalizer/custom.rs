@@ -0,0 +1,192 @@
+//! Expansion of embedder-defined operations.
+//!
+//! A frontend for a domain-specific language can represent its own operations with `custom`
+//! instructions instead of expanding them right away. The operations then stay visible as single
+//! instructions to the passes that run before legalization, like the embedder's custom passes,
+//! which can recognize patterns of them. The legalizer replaces each `custom` instruction by the
+//! expansion registered for its opcode in a `CustomOpcodes` registry.
+
+use flowgraph::ControlFlowGraph;
+use ir;
+use isa::TargetIsa;
+use result::{CtonError, CtonResult};
+use std::string::String;
+use std::vec::Vec;
+
+/// Expansion of an embedder-defined operation into standard instructions.
+///
+/// The expansion is called with a `custom` instruction of the opcode it was registered for, and
+/// must replace it by instructions that compute the same results, updating the control flow graph
+/// when it changes the branches. The inserted instructions are legalized in turn, so they don't
+/// need to be legal for `isa`, and they can include other `custom` instructions.
+///
+/// Return `false` if the operation can't be expanded, which fails the compilation.
+pub type CustomExpansion = fn(ir::Inst, &mut ir::Function, &mut ControlFlowGraph, &TargetIsa)
+                              -> bool;
+
+/// Registry of the embedder-defined opcodes and their expansions.
+#[derive(Clone, Default)]
+pub struct CustomOpcodes {
+    opcodes: Vec<(String, CustomExpansion)>,
+}
+
+impl CustomOpcodes {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an operation named `name`, which is expanded by `expansion`.
+    ///
+    /// Returns the opcode to use in `custom` instructions for this operation, or `None` if all the
+    /// custom opcodes have been registered already.
+    pub fn register(
+        &mut self,
+        name: &str,
+        expansion: CustomExpansion,
+    ) -> Option<ir::CustomOpcode> {
+        if self.opcodes.len() > usize::from(u16::max_value()) {
+            return None;
+        }
+        self.opcodes.push((name.into(), expansion));
+        Some(ir::CustomOpcode::new((self.opcodes.len() - 1) as u16))
+    }
+
+    /// Get the name of the operation registered as `op`, for diagnostics.
+    pub fn name(&self, op: ir::CustomOpcode) -> Option<&str> {
+        self.opcodes.get(usize::from(op.number())).map(
+            |&(ref name, _)| name.as_str(),
+        )
+    }
+
+    /// Get the expansion of the operation registered as `op`.
+    fn expansion(&self, op: ir::CustomOpcode) -> Option<CustomExpansion> {
+        self.opcodes.get(usize::from(op.number())).map(
+            |&(_, expansion)| expansion,
+        )
+    }
+}
+
+/// Expand the `custom` instruction `inst` with its registered expansion.
+///
+/// Fail with `CtonError::InvalidInput` if no expansion was registered for its opcode, or if the
+/// expansion fails.
+pub fn expand_custom(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+    custom_opcodes: Option<&CustomOpcodes>,
+) -> CtonResult {
+    let op = match func.dfg[inst] {
+        ir::InstructionData::Custom { op, .. } => op,
+        _ => panic!("Expected custom: {}", func.dfg.display_inst(inst, None)),
+    };
+    let expansion = match custom_opcodes.and_then(|opcodes| opcodes.expansion(op)) {
+        Some(expansion) => expansion,
+        None => {
+            dbg!("No expansion registered for {}", op);
+            return Err(CtonError::InvalidInput);
+        }
+    };
+    if expansion(inst, func, cfg, isa) {
+        Ok(())
+    } else {
+        dbg!(
+            "Can't expand {} ({})",
+            func.dfg.display_inst(inst, isa),
+            custom_opcodes.and_then(|opcodes| opcodes.name(op)).unwrap_or("?")
+        );
+        Err(CtonError::InvalidInput)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+    use std::string::ToString;
+    use isa;
+    use settings;
+
+    /// Expand `custom.i32 opN(x, y)` into `x + y + 1`.
+    fn expand_add_inc(
+        inst: ir::Inst,
+        func: &mut ir::Function,
+        _cfg: &mut ControlFlowGraph,
+        _isa: &TargetIsa,
+    ) -> bool {
+        let (x, y) = {
+            let args = func.dfg.inst_args(inst);
+            (args[0], args[1])
+        };
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        pos.use_srcloc(inst);
+        let sum = pos.ins().iadd(x, y);
+        pos.func.dfg.replace(inst).iadd_imm(sum, 1);
+        true
+    }
+
+    /// Refuse to expand anything.
+    fn expand_nothing(
+        _inst: ir::Inst,
+        _func: &mut ir::Function,
+        _cfg: &mut ControlFlowGraph,
+        _isa: &TargetIsa,
+    ) -> bool {
+        false
+    }
+
+    fn context_with(op: ir::CustomOpcode) -> Context {
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let x = ctx.func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let a = pos.ins().custom(I32, op, &[x, x]);
+            pos.ins().return_(&[a]);
+        }
+        ctx
+    }
+
+    #[test]
+    fn register() {
+        let mut opcodes = CustomOpcodes::new();
+        let add_inc = opcodes.register("add_inc", expand_add_inc).unwrap();
+        let nothing = opcodes.register("nothing", expand_nothing).unwrap();
+        assert_eq!(add_inc, ir::CustomOpcode::new(0));
+        assert_eq!(nothing, ir::CustomOpcode::new(1));
+        assert_eq!(opcodes.name(nothing), Some("nothing"));
+        assert_eq!(opcodes.name(ir::CustomOpcode::new(2)), None);
+    }
+
+    #[test]
+    #[cfg(build_riscv)]
+    fn expand() {
+        let isa = isa::lookup("riscv").unwrap().finish(settings::Flags::new(&settings::builder()));
+        let mut opcodes = CustomOpcodes::new();
+        let add_inc = opcodes.register("add_inc", expand_add_inc).unwrap();
+        let nothing = opcodes.register("nothing", expand_nothing).unwrap();
+
+        let mut ctx = context_with(add_inc);
+        ctx.set_custom_opcodes(Some(opcodes.clone()));
+        ctx.compute_cfg();
+        ctx.legalize(&*isa).unwrap();
+        let text = ctx.func.display(&*isa).to_string();
+        assert!(text.contains("= iadd v0, v0"), "{}", text);
+        assert!(!text.contains("custom"), "{}", text);
+
+        // Without a registry, or with an expansion that fails, the operation can't be compiled.
+        let mut ctx = context_with(add_inc);
+        assert_eq!(ctx.compile(&*isa), Err(CtonError::InvalidInput));
+        let mut ctx = context_with(nothing);
+        ctx.set_custom_opcodes(Some(opcodes));
+        assert_eq!(ctx.compile(&*isa), Err(CtonError::InvalidInput));
+    }
+}
@@ -0,0 +1,123 @@
+//! Recomputation of clobbered CPU flags.
+//!
+//! A single `ifcmp` or `ffcmp` can feed several flags consumers like `brif`, `selectif`, and
+//! `trapif`. The flags value lives in the CPU flags register, so it doesn't survive the
+//! instructions between the consumers whose encodings clobber the flags, like most arithmetic on
+//! Intel. Such instructions are only known after legalization, so the legalizer recomputes the
+//! flags before a use that they don't reach intact, instead of requiring the producer of the IL to
+//! repeat the comparison for each consumer.
+
+use cursor::{Cursor, FuncCursor};
+use entity::EntityMap;
+use flowgraph::ControlFlowGraph;
+use ir::{self, Ebb, Inst, Opcode, Value, ValueDef};
+use ir::instructions::BranchInfo;
+use isa::{EncInfo, TargetIsa};
+
+/// The flags value held by the CPU flags register, if any.
+///
+/// The first value is the flags value in the original program, and the second value is the
+/// equivalent flags value that is actually held by the register, which is different when the
+/// flags have been recomputed.
+type HeldFlags = Option<(Value, Value)>;
+
+/// Recompute the flags values used by instructions that the flags don't reach intact.
+///
+/// The flags held at the start of an EBB are only known when it has a single predecessor that
+/// comes before it in the layout. Otherwise the flags values used in the EBB are recomputed
+/// before their first use.
+pub fn recompute_clobbered_flags(func: &mut ir::Function, cfg: &ControlFlowGraph, isa: &TargetIsa) {
+    let encinfo = isa.encoding_info();
+    let mut entry_flags: EntityMap<Ebb, HeldFlags> = EntityMap::new();
+    let mut pos = FuncCursor::new(func);
+
+    while let Some(ebb) = pos.next_ebb() {
+        let mut held = entry_flags[ebb];
+        while let Some(inst) = pos.next_inst() {
+            for argidx in 0..pos.func.dfg.inst_args(inst).len() {
+                let arg = pos.func.dfg.inst_args(inst)[argidx];
+                if !pos.func.dfg.value_type(arg).is_flags() {
+                    continue;
+                }
+                let value = match held {
+                    Some((orig, value)) if orig == arg => value,
+                    _ => match recompute(&mut pos, arg, inst) {
+                        Some(value) => {
+                            held = Some((arg, value));
+                            value
+                        }
+                        // Leave it to the verifier to report the clobbered flags.
+                        None => continue,
+                    },
+                };
+                pos.func.dfg.inst_args_mut(inst)[argidx] = value;
+            }
+
+            update_held_flags(&mut held, pos.func, &encinfo, inst);
+
+            match pos.func.dfg.analyze_branch(inst) {
+                BranchInfo::NotABranch => {}
+                BranchInfo::SingleDest(dest, _) => inherit(&mut entry_flags, cfg, dest, held),
+                BranchInfo::Table(jt) => {
+                    for (_, dest) in pos.func.jump_tables[jt].entries() {
+                        inherit(&mut entry_flags, cfg, dest, held);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Update the flags held by the CPU flags register after `inst`.
+fn update_held_flags(held: &mut HeldFlags, func: &ir::Function, encinfo: &EncInfo, inst: Inst) {
+    let clobbers = encinfo
+        .operand_constraints(func.encodings[inst])
+        .map_or(false, |c| c.clobbers_flags);
+    if clobbers {
+        *held = None;
+    }
+    for &res in func.dfg.inst_results(inst) {
+        if func.dfg.value_type(res).is_flags() {
+            *held = Some((res, res));
+        }
+    }
+}
+
+/// Let `dest` start with the flags held at a branch to it if it has no other predecessors.
+fn inherit(
+    entry_flags: &mut EntityMap<Ebb, HeldFlags>,
+    cfg: &ControlFlowGraph,
+    dest: Ebb,
+    held: HeldFlags,
+) {
+    if cfg.pred_iter(dest).count() == 1 {
+        entry_flags[dest] = held;
+    }
+}
+
+/// Insert a copy of the instruction defining `flags` before `inst`, and return the new value.
+///
+/// Only comparisons without other results can be recomputed. Returns `None` for other flags
+/// values.
+fn recompute(pos: &mut FuncCursor, flags: Value, inst: Inst) -> Option<Value> {
+    let def = match pos.func.dfg.value_def(flags) {
+        ValueDef::Result(def, 0) => def,
+        _ => return None,
+    };
+    match pos.func.dfg[def].opcode() {
+        Opcode::Ifcmp | Opcode::IfcmpImm | Opcode::Ffcmp => {}
+        _ => return None,
+    }
+    if pos.func.dfg.inst_results(def).len() != 1 {
+        return None;
+    }
+
+    let data = pos.func.dfg[def].clone();
+    let ctrl_typevar = pos.func.dfg.ctrl_typevar(def);
+    let copy = pos.func.dfg.make_inst(data);
+    pos.func.dfg.make_inst_results(copy, ctrl_typevar);
+    pos.insert_inst(copy);
+    pos.func.encodings[copy] = pos.func.encodings[def];
+    pos.func.srclocs[copy] = pos.func.srclocs[inst];
+    Some(pos.func.dfg.first_result(copy))
+}
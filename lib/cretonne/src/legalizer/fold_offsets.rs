@@ -0,0 +1,180 @@
+//! Folding of constant address offsets into memory instructions.
+//!
+//! Addresses of struct fields and global variables are typically computed with `iadd_imm`
+//! instructions, and the same constants can be encoded for free in the displacement field of the
+//! loads and stores using the addresses. This module folds chains of `iadd_imm` instructions
+//! feeding a load or store into its offset, and removes the additions that become unused.
+
+use entity::EntityMap;
+use ir::immediates::Offset32;
+use ir::{Function, Inst, InstructionData, Opcode, Value, ValueDef};
+use isa::TargetIsa;
+use std::i32;
+use std::vec::Vec;
+
+/// Fold constant additions to the address of the load or store `inst` into its offset.
+///
+/// The folding only happens if the combined offset fits in a 32-bit displacement and the
+/// resulting instruction can be encoded by `isa`. The address values that are no longer used by
+/// `inst` are added to `folded` so they can be removed with `remove_dead_adds()` once they are
+/// unused.
+pub fn fold_address_offset(
+    inst: Inst,
+    func: &mut Function,
+    isa: &TargetIsa,
+    folded: &mut Vec<Value>,
+) {
+    let (addr, offset) = match func.dfg[inst] {
+        InstructionData::Load { arg, offset, .. } => (arg, offset),
+        InstructionData::Store { args, offset, .. } => (args[1], offset),
+        _ => return,
+    };
+
+    let mut base = func.dfg.resolve_aliases(addr);
+    let mut total: i64 = offset.into();
+    let mut chain = Vec::new();
+    while let ValueDef::Result(def, _) = func.dfg.value_def(base) {
+        let (arg, imm) = match func.dfg[def] {
+            InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, imm } => (arg, imm),
+            _ => break,
+        };
+        let imm: i64 = imm.into();
+        // The sum must be representable as a sign-extended 32-bit displacement.
+        match total.checked_add(imm) {
+            Some(sum) if sum >= i64::from(i32::MIN) && sum <= i64::from(i32::MAX) => total = sum,
+            _ => break,
+        }
+        chain.push(base);
+        base = func.dfg.resolve_aliases(arg);
+    }
+    if chain.is_empty() {
+        return;
+    }
+
+    let mut data = func.dfg[inst].clone();
+    match data {
+        InstructionData::Load {
+            ref mut arg,
+            ref mut offset,
+            ..
+        } => {
+            *arg = base;
+            *offset = Offset32::new(total as i32);
+        }
+        InstructionData::Store {
+            ref mut args,
+            ref mut offset,
+            ..
+        } => {
+            args[1] = base;
+            *offset = Offset32::new(total as i32);
+        }
+        _ => unreachable!(),
+    }
+    if isa.encode(&func.dfg, &data, func.dfg.ctrl_typevar(inst)).is_err() {
+        return;
+    }
+
+    func.dfg[inst] = data;
+    folded.extend(chain);
+}
+
+/// Remove the `iadd_imm` instructions defining the values in `folded` that have no more uses.
+pub fn remove_dead_adds(func: &mut Function, folded: &[Value]) {
+    if folded.is_empty() {
+        return;
+    }
+
+    let mut uses = EntityMap::<Value, u32>::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+
+    // Removing an addition can make the previous addition in a chain unused too.
+    let mut worklist = folded.to_vec();
+    while let Some(value) = worklist.pop() {
+        if uses[value] != 0 {
+            continue;
+        }
+        let inst = match func.dfg.value_def(value) {
+            ValueDef::Result(inst, _) if func.layout.inst_ebb(inst).is_some() => inst,
+            _ => continue,
+        };
+        let arg = match func.dfg[inst] {
+            InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, .. } => {
+                func.dfg.resolve_aliases(arg)
+            }
+            _ => continue,
+        };
+        func.layout.remove_inst(inst);
+        uses[arg] -= 1;
+        worklist.push(arg);
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, Function, InstBuilder, MemFlags};
+    use isa;
+    use settings::{self, Configurable};
+    use std::string::ToString;
+    use Context;
+
+    fn legalize(func: Function) -> Function {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let mut ctx = Context::for_function(func);
+        ctx.compute_cfg();
+        ctx.legalize(&*isa).unwrap();
+        ctx.func
+    }
+
+    fn load_with_offsets(offsets: &[i64]) -> Function {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I64));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let mut addr = func.dfg.append_ebb_param(ebb0, I64);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        for &offset in offsets {
+            addr = pos.ins().iadd_imm(addr, offset);
+        }
+        let v = pos.ins().load(I32, MemFlags::new(), addr, 4);
+        pos.ins().return_(&[v]);
+        func
+    }
+
+    #[test]
+    fn fold_chain() {
+        let func = legalize(load_with_offsets(&[8, 16]));
+        let ebb0 = func.layout.entry_block().unwrap();
+        let first = func.layout.first_inst(ebb0).unwrap();
+        assert_eq!(
+            func.dfg.display_inst(first, None).to_string(),
+            "v3 = load.i32 v0+28"
+        );
+        assert_eq!(func.layout.ebb_insts(ebb0).count(), 2);
+    }
+
+    #[test]
+    fn displacement_overflow() {
+        let func = legalize(load_with_offsets(&[0x7fff_fff0, 16]));
+        // Only the last addition can be folded.
+        let ebb0 = func.layout.entry_block().unwrap();
+        let insts: Vec<_> = func.layout
+            .ebb_insts(ebb0)
+            .map(|inst| func.dfg.display_inst(inst, None).to_string())
+            .collect();
+        assert_eq!(insts[0], "v1 = iadd_imm.i64 v0, 0x7fff_fff0");
+        assert_eq!(insts[1], "v3 = load.i32 v1+20");
+        assert_eq!(insts.len(), 3);
+    }
+}
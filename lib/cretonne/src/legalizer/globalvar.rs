@@ -28,6 +28,7 @@ pub fn expand_global_addr(
         ir::GlobalVarData::VmCtx { offset } => vmctx_addr(inst, func, offset.into()),
         ir::GlobalVarData::Deref { base, offset } => deref_addr(inst, func, base, offset.into()),
         ir::GlobalVarData::Sym { .. } => globalsym(inst, func, gv),
+        ir::GlobalVarData::TLS { .. } => tls(inst, func, gv),
     }
 }
 
@@ -62,3 +63,11 @@ fn globalsym(inst: ir::Inst, func: &mut ir::Function, gv: ir::GlobalVar) {
     let ptr_ty = func.dfg.value_type(func.dfg.first_result(inst));
     func.dfg.replace(inst).globalsym_addr(ptr_ty, gv);
 }
+
+/// Expand a `global_addr` instruction for a thread-local global.
+///
+/// The access sequence depends on the TLS model, so the ISA must legalize `tls_addr` further.
+fn tls(inst: ir::Inst, func: &mut ir::Function, gv: ir::GlobalVar) {
+    let ptr_ty = func.dfg.value_type(func.dfg.first_result(inst));
+    func.dfg.replace(inst).tls_addr(ptr_ty, gv);
+}
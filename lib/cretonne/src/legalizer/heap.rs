@@ -2,6 +2,11 @@
 //!
 //! This module exports the `expand_heap_addr` function which transforms a `heap_addr`
 //! instruction into code that depends on the kind of heap referenced.
+//!
+//! With the `enable_heap_access_spectre_mitigation` setting, the computed address is also
+//! replaced by a null pointer with a `selectif` when the bounds check fails. The conditional move
+//! doesn't depend on branch prediction, so a mispredicted bounds check can't be used to access
+//! memory outside the heap speculatively.
 
 use cursor::{Cursor, FuncCursor};
 use flowgraph::ControlFlowGraph;
@@ -14,7 +19,7 @@ pub fn expand_heap_addr(
     inst: ir::Inst,
     func: &mut ir::Function,
     cfg: &mut ControlFlowGraph,
-    _isa: &TargetIsa,
+    isa: &TargetIsa,
 ) {
     // Unpack the instruction.
     let (heap, offset, size) = match func.dfg[inst] {
@@ -30,16 +35,20 @@ pub fn expand_heap_addr(
         _ => panic!("Wanted heap_addr: {}", func.dfg.display_inst(inst, None)),
     };
 
+    let spectre = isa.flags().enable_heap_access_spectre_mitigation();
     match func.heaps[heap].style {
         ir::HeapStyle::Dynamic { bound_gv } => {
-            dynamic_addr(inst, heap, offset, size, bound_gv, func)
+            dynamic_addr(inst, heap, offset, size, bound_gv, spectre, func)
         }
         ir::HeapStyle::Static { bound } => {
-            static_addr(inst, heap, offset, size, bound.into(), func, cfg)
+            static_addr(inst, heap, offset, size, bound.into(), spectre, func, cfg)
         }
     }
 }
 
+/// An out-of-bounds condition `cc(lhs, rhs)` to guard a heap address with.
+type SpectreGuard = (IntCC, ir::Value, ir::Value);
+
 /// Expand a `heap_addr` for a dynamic heap.
 fn dynamic_addr(
     inst: ir::Inst,
@@ -47,6 +56,7 @@ fn dynamic_addr(
     offset: ir::Value,
     size: u32,
     bound_gv: ir::GlobalVar,
+    spectre: bool,
     func: &mut ir::Function,
 ) {
     let size = i64::from(size);
@@ -58,39 +68,29 @@ fn dynamic_addr(
 
     // Start with the bounds check. Trap if `offset + size > bound`.
     let bound_addr = pos.ins().global_addr(addr_ty, bound_gv);
-    let bound = pos.ins().load(offset_ty, MemFlags::new(), bound_addr, 0);
+    let flags = heap_flags(pos.func, heap);
+    let bound = pos.ins().load(offset_ty, flags, bound_addr, 0);
 
-    let oob;
-    if size == 1 {
+    let (cc, lhs, rhs) = if size == 1 {
         // `offset > bound - 1` is the same as `offset >= bound`.
-        oob = pos.ins().icmp(
-            IntCC::UnsignedGreaterThanOrEqual,
-            offset,
-            bound,
-        );
+        (IntCC::UnsignedGreaterThanOrEqual, offset, bound)
     } else if size <= min_size {
         // We know that bound >= min_size, so here we can compare `offset > bound - size` without
         // wrapping.
         let adj_bound = pos.ins().iadd_imm(bound, -size);
-        oob = pos.ins().icmp(
-            IntCC::UnsignedGreaterThan,
-            offset,
-            adj_bound,
-        );
+        (IntCC::UnsignedGreaterThan, offset, adj_bound)
     } else {
         // We need an overflow check for the adjusted offset.
         let size_val = pos.ins().iconst(offset_ty, size);
         let (adj_offset, overflow) = pos.ins().iadd_cout(offset, size_val);
         pos.ins().trapnz(overflow, ir::TrapCode::HeapOutOfBounds);
-        oob = pos.ins().icmp(
-            IntCC::UnsignedGreaterThan,
-            adj_offset,
-            bound,
-        );
-    }
+        (IntCC::UnsignedGreaterThan, adj_offset, bound)
+    };
+    let oob = pos.ins().icmp(cc, lhs, rhs);
     pos.ins().trapnz(oob, ir::TrapCode::HeapOutOfBounds);
 
-    offset_addr(inst, heap, addr_ty, offset, offset_ty, pos.func);
+    let guard = if spectre { Some((cc, lhs, rhs)) } else { None };
+    offset_addr(inst, heap, addr_ty, offset, offset_ty, guard, pos.func);
 }
 
 /// Expand a `heap_addr` for a static heap.
@@ -100,6 +100,7 @@ fn static_addr(
     offset: ir::Value,
     size: u32,
     bound: i64,
+    spectre: bool,
     func: &mut ir::Function,
     cfg: &mut ControlFlowGraph,
 ) {
@@ -129,37 +130,36 @@ fn static_addr(
 
     // We may be able to omit the check entirely for 32-bit offsets if the heap bound is 4 GB or
     // more.
+    let mut guard = None;
     if offset_ty != ir::types::I32 || limit < 0xffff_ffff {
-        let oob = if limit & 1 == 1 {
+        let (cc, imm) = if limit & 1 == 1 {
             // Prefer testing `offset >= limit - 1` when limit is odd because an even number is
             // likely to be a convenient constant on ARM and other RISC architectures.
-            pos.ins().icmp_imm(
-                IntCC::UnsignedGreaterThanOrEqual,
-                offset,
-                limit - 1,
-            )
+            (IntCC::UnsignedGreaterThanOrEqual, limit - 1)
         } else {
-            pos.ins().icmp_imm(
-                IntCC::UnsignedGreaterThan,
-                offset,
-                limit,
-            )
+            (IntCC::UnsignedGreaterThan, limit)
         };
+        let oob = pos.ins().icmp_imm(cc, offset, imm);
         pos.ins().trapnz(oob, ir::TrapCode::HeapOutOfBounds);
+        if spectre {
+            let imm = pos.ins().iconst(offset_ty, imm);
+            guard = Some((cc, offset, imm));
+        }
     }
 
-    offset_addr(inst, heap, addr_ty, offset, offset_ty, pos.func);
+    offset_addr(inst, heap, addr_ty, offset, offset_ty, guard, pos.func);
 }
 
 /// Emit code for the base address computation of a `heap_addr` instruction.
 ///
-///
+/// If a `guard` is given, the address is replaced by 0 when the guard condition holds.
 fn offset_addr(
     inst: ir::Inst,
     heap: ir::Heap,
     addr_ty: ir::Type,
     mut offset: ir::Value,
     offset_ty: ir::Type,
+    guard: Option<SpectreGuard>,
     func: &mut ir::Function,
 ) {
     let mut pos = FuncCursor::new(func).at_inst(inst);
@@ -175,8 +175,37 @@ fn offset_addr(
         ir::HeapBase::ReservedReg => unimplemented!(),
         ir::HeapBase::GlobalVar(base_gv) => {
             let base_addr = pos.ins().global_addr(addr_ty, base_gv);
-            let base = pos.ins().load(addr_ty, MemFlags::new(), base_addr, 0);
-            pos.func.dfg.replace(inst).iadd(base, offset);
+            let flags = heap_flags(pos.func, heap);
+            let base = pos.ins().load(addr_ty, flags, base_addr, 0);
+            match guard {
+                None => {
+                    pos.func.dfg.replace(inst).iadd(base, offset);
+                }
+                Some((cc, lhs, rhs)) => {
+                    let addr = pos.ins().iadd(base, offset);
+                    let zero = pos.ins().iconst(addr_ty, 0);
+                    // Compare right before the select so the flags aren't clobbered.
+                    let flags = pos.ins().ifcmp(lhs, rhs);
+                    pos.func.dfg.replace(inst).selectif(
+                        addr_ty,
+                        cc,
+                        flags,
+                        zero,
+                        addr,
+                    );
+                }
+            }
         }
     }
 }
+
+/// Get the flags to use for loading the base address or bound of `heap`.
+fn heap_flags(func: &ir::Function, heap: ir::Heap) -> MemFlags {
+    let mut flags = MemFlags::new();
+    if func.heaps[heap].readonly {
+        flags.set_readonly();
+        flags.set_notrap();
+        flags.set_speculatable();
+    }
+    flags
+}
@@ -1,10 +1,13 @@
 //! Expanding instructions as runtime library calls.
 
+use cursor::{Cursor, FuncCursor};
 use ir;
 use ir::InstBuilder;
+use ir::types::{I32, I64};
+use isa::TargetIsa;
 
 /// Try to expand `inst` as a library call, returning true is successful.
-pub fn expand_as_libcall(inst: ir::Inst, func: &mut ir::Function) -> bool {
+pub fn expand_as_libcall(inst: ir::Inst, func: &mut ir::Function, isa: &TargetIsa) -> bool {
     // Does the opcode/ctrl_type combo even have a well-known runtime library name.
     let libcall =
         match ir::LibCall::for_inst(func.dfg[inst].opcode(), func.dfg.ctrl_typevar(inst)) {
@@ -12,7 +15,8 @@ pub fn expand_as_libcall(inst: ir::Inst, func: &mut ir::Function) -> bool {
             None => return false,
         };
 
-    let funcref = find_funcref(libcall, func).unwrap_or_else(|| make_funcref(libcall, inst, func));
+    let funcref =
+        find_funcref(libcall, func).unwrap_or_else(|| make_funcref(libcall, inst, func, isa));
 
     // Now we convert `inst` to a call. First save the arguments.
     let mut args = Vec::new();
@@ -20,8 +24,6 @@ pub fn expand_as_libcall(inst: ir::Inst, func: &mut ir::Function) -> bool {
     // The replace builder will preserve the instruction result values.
     func.dfg.replace(inst).call(funcref, &args);
 
-    // TODO: ask the ISA to legalize the signature.
-
     true
 }
 
@@ -43,7 +45,15 @@ fn find_funcref(libcall: ir::LibCall, func: &ir::Function) -> Option<ir::FuncRef
 }
 
 /// Create a funcref for `libcall` with a signature matching `inst`.
-fn make_funcref(libcall: ir::LibCall, inst: ir::Inst, func: &mut ir::Function) -> ir::FuncRef {
+///
+/// The signatures of `func` have already been legalized, so the new signature is legalized for
+/// `isa` here.
+fn make_funcref(
+    libcall: ir::LibCall,
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    isa: &TargetIsa,
+) -> ir::FuncRef {
     // Start with a native calling convention. We'll give the ISA a chance to change it.
     let mut sig = ir::Signature::new(ir::CallConv::Native);
     for &v in func.dfg.inst_args(inst) {
@@ -52,10 +62,62 @@ fn make_funcref(libcall: ir::LibCall, inst: ir::Inst, func: &mut ir::Function) -
     for &v in func.dfg.inst_results(inst) {
         sig.returns.push(ir::AbiParam::new(func.dfg.value_type(v)));
     }
+    isa.legalize_signature(&mut sig, false);
+    sig.compute_argument_bytes();
     let sigref = func.import_signature(sig);
 
     func.import_function(ir::ExtFuncData {
         name: ir::ExternalName::LibCall(libcall),
         signature: sigref,
+        colocated: false,
     })
 }
+
+/// Apply the linkage configured in `isa` to the library routine called by `inst`, if any.
+///
+/// A direct call is redirected to the configured name. A call through a global variable is
+/// replaced by an indirect call to the address loaded from the variable, and returns true so the
+/// inserted instructions get legalized.
+pub fn redirect_libcall(inst: ir::Inst, func: &mut ir::Function, isa: &TargetIsa) -> bool {
+    let fref = match func.dfg[inst] {
+        ir::InstructionData::Call { func_ref, .. } => func_ref,
+        _ => return false,
+    };
+    let libcall = match func.dfg.ext_funcs[fref].name {
+        ir::ExternalName::LibCall(lc) => lc,
+        _ => return false,
+    };
+
+    match *isa.libcall_linkage(libcall) {
+        ir::LibCallLinkage::Direct(ref name) => {
+            func.dfg.ext_funcs[fref].name = name.clone();
+            false
+        }
+        ir::LibCallLinkage::Indirect(ref data) => {
+            let gv = func.create_global_var(data.clone());
+            let sigref = func.dfg.ext_funcs[fref].signature;
+            let ptr_ty = if isa.flags().is_64bit() { I64 } else { I32 };
+            let is_tail_call = func.dfg[inst].opcode() == ir::Opcode::ReturnCall;
+            let mut args = Vec::new();
+            args.extend_from_slice(func.dfg.inst_args(inst));
+
+            let mut pos = FuncCursor::new(func).at_inst(inst);
+            pos.use_srcloc(inst);
+            let addr = pos.ins().global_addr(ptr_ty, gv);
+            let mut flags = ir::MemFlags::new();
+            flags.set_notrap();
+            flags.set_aligned();
+            let callee = pos.ins().load(ptr_ty, flags, addr, 0);
+            if is_tail_call {
+                pos.func.dfg.replace(inst).return_call_indirect(
+                    sigref,
+                    callee,
+                    &args,
+                );
+            } else {
+                pos.func.dfg.replace(inst).call_indirect(sigref, callee, &args);
+            }
+            true
+        }
+    }
+}
@@ -8,7 +8,8 @@
 //! can be legal for one ISA and illegal for another.
 //!
 //! Besides transforming instructions, the legalizer also fills out the `function.encodings` map
-//! which provides a legal encoding recipe for every instruction.
+//! which provides a legal encoding recipe for every instruction. Once the encodings are known, the
+//! CPU flags values used after an instruction that clobbers the flags are recomputed.
 //!
 //! The legalizer does not deal with register allocation constraints. These constraints are derived
 //! from the encoding recipes, and solved later by the register allocator.
@@ -17,44 +18,175 @@ use cursor::{Cursor, FuncCursor};
 use flowgraph::ControlFlowGraph;
 use ir::{self, InstBuilder};
 use isa::TargetIsa;
+use predicates;
 use bitset::BitSet;
+use result::CtonResult;
+use std::vec::Vec;
 use timing;
 
+mod atomics;
 mod boundary;
+mod br_table;
+mod custom;
+mod flags;
+mod fold_offsets;
 mod globalvar;
 mod heap;
 mod libcall;
 mod split;
-
+mod streaming;
+mod trace;
+mod traps;
+mod unaligned;
+
+use self::atomics::expand_atomic_rmw;
+use self::br_table::{expand_br_table, merge_jump_tables};
+use self::custom::expand_custom;
 use self::globalvar::expand_global_addr;
 use self::heap::expand_heap_addr;
-use self::libcall::expand_as_libcall;
+use self::libcall::{expand_as_libcall, redirect_libcall};
+use self::trace::ExpansionTrace;
+use self::traps::convert_trap;
+use self::unaligned::expand_unaligned_vector_access;
+
+pub use self::custom::{CustomExpansion, CustomOpcodes};
+pub use self::streaming::StreamingLegalizer;
+pub use self::trace::{Expansion, LegalizerError};
+pub use self::traps::TrapHandler;
 
 /// Legalize `func` for `isa`.
 ///
 /// - Transform any instructions that don't have a legal representation in `isa`.
 /// - Fill out `func.encodings`.
 ///
-pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa: &TargetIsa) {
+/// Fail with a `CtonError::Legalizer` error if the expansions exceed the limit set by the
+/// `legalizer_expansion_limit` setting.
+pub fn legalize_function(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+) -> CtonResult {
+    legalize(func, cfg, isa, None, None, false)
+}
+
+/// Legalize `func` for `isa` like `legalize_function()`, and replace the trap instructions with
+/// the trap codes selected by `trap_handler` by calls to the handler.
+pub fn legalize_function_with_trap_handler(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+    trap_handler: &TrapHandler,
+) -> CtonResult {
+    legalize(func, cfg, isa, Some(trap_handler), None, false)
+}
+
+/// Legalize `func` for `isa` like `legalize_function()`, and expand the `custom` instructions with
+/// the expansions registered in `custom_opcodes`.
+///
+/// The trap instructions are converted like in `legalize_function_with_trap_handler()` if
+/// `trap_handler` is given. Fail with `CtonError::InvalidInput` if a `custom` instruction can't be
+/// expanded.
+pub fn legalize_function_with_custom_opcodes(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+    trap_handler: Option<&TrapHandler>,
+    custom_opcodes: &CustomOpcodes,
+) -> CtonResult {
+    legalize(func, cfg, isa, trap_handler, Some(custom_opcodes), false)
+}
+
+/// Legalize the instructions that were inserted into `func` after it was legalized for `isa`.
+///
+/// This is for passes that run after legalization and insert instructions that may not be legal.
+/// The instructions that already have an encoding are left alone, and the signatures are not
+/// legalized again.
+pub fn legalize_new_insts(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+) -> CtonResult {
+    legalize(func, cfg, isa, None, None, true)
+}
+
+fn legalize(
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+    trap_handler: Option<&TrapHandler>,
+    custom_opcodes: Option<&CustomOpcodes>,
+    only_new: bool,
+) -> CtonResult {
     let _tt = timing::legalize();
     debug_assert!(cfg.is_valid());
 
-    boundary::legalize_signatures(func, isa);
+    if !only_new {
+        boundary::legalize_signatures(func, isa);
+    }
 
     func.encodings.resize(func.dfg.num_insts());
 
+    // The expansion budget is proportional to the size of the input function.
+    let limit = usize::from(isa.flags().legalizer_expansion_limit());
+    let mut legalizer = Legalizer::new(limit * func.dfg.num_insts().max(1), only_new);
     let mut pos = FuncCursor::new(func);
 
     // Process EBBs in layout order. Some legalization actions may split the current EBB or append
     // new ones to the end. We need to make sure we visit those new EBBs too.
     while let Some(_ebb) = pos.next_ebb() {
+        legalizer.legalize_ebb(&mut pos, cfg, isa, trap_handler, custom_opcodes)?;
+    }
+
+    legalizer.finish(pos.func, cfg, isa);
+    Ok(())
+}
+
+/// State of the legalizer shared by the EBBs of a function.
+struct Legalizer {
+    trace: ExpansionTrace,
+    folded: Vec<ir::Value>,
+    only_new: bool,
+}
+
+impl Legalizer {
+    /// Create a legalizer allowing `limit` expansions. If `only_new` is set, the instructions that
+    /// already have a legal encoding are skipped.
+    fn new(limit: usize, only_new: bool) -> Self {
+        Self {
+            trace: ExpansionTrace::new(limit),
+            folded: Vec::new(),
+            only_new,
+        }
+    }
+
+    /// Legalize the instructions following `pos` in its EBB.
+    fn legalize_ebb(
+        &mut self,
+        pos: &mut FuncCursor,
+        cfg: &mut ControlFlowGraph,
+        isa: &TargetIsa,
+        trap_handler: Option<&TrapHandler>,
+        custom_opcodes: Option<&CustomOpcodes>,
+    ) -> CtonResult {
         // Keep track of the cursor position before the instruction being processed, so we can
         // double back when replacing instructions.
         let mut prev_pos = pos.position();
 
         while let Some(inst) = pos.next_inst() {
+            if self.only_new && pos.func.encodings[inst].is_legal() {
+                prev_pos = pos.position();
+                continue;
+            }
+
             let opcode = pos.func.dfg[inst].opcode();
 
+            // Calls to library routines follow the linkage configured by the embedder.
+            if opcode.is_call() && redirect_libcall(inst, pos.func, isa) {
+                // Go back and legalize the inserted address load.
+                pos.set_position(prev_pos);
+                continue;
+            }
+
             // Check for ABI boundaries that need to be converted to the legalized signature.
             if opcode.is_call() && boundary::handle_call_abi(inst, pos.func, cfg) {
                 // Go back and legalize the inserted argument conversion instructions.
@@ -72,6 +204,38 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
                 split::simplify_branch_arguments(&mut pos.func.dfg, inst);
             }
 
+            if opcode == ir::Opcode::Isplit && split::simplify_isplit(pos.func, cfg, inst) {
+                pos.set_position(prev_pos);
+                continue;
+            }
+
+            if opcode == ir::Opcode::Custom {
+                // Embedder-defined operations have no encodings, they are always expanded.
+                self.trace.expand(inst, pos.func, isa.name())?;
+                expand_custom(inst, pos.func, cfg, isa, custom_opcodes)?;
+                pos.set_position(prev_pos);
+                continue;
+            }
+
+            if let Some(handler) = trap_handler {
+                if opcode.can_trap() && convert_trap(inst, pos.func, cfg, isa, handler) {
+                    // Go back and legalize the inserted handler call.
+                    pos.set_position(prev_pos);
+                    continue;
+                }
+            }
+
+            if (opcode == ir::Opcode::Load || opcode == ir::Opcode::Store) &&
+                expand_unaligned_vector_access(inst, pos.func, isa)
+            {
+                pos.set_position(prev_pos);
+                continue;
+            }
+
+            if opcode.can_load() || opcode.can_store() {
+                fold_offsets::fold_address_offset(inst, pos.func, isa, &mut self.folded);
+            }
+
             match isa.encode(
                 &pos.func.dfg,
                 &pos.func.dfg[inst],
@@ -79,13 +243,14 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
             ) {
                 Ok(encoding) => pos.func.encodings[inst] = encoding,
                 Err(action) => {
-                    // We should transform the instruction into legal equivalents.
+                    // We should transform the instruction into legal equivalents. Unsound
+                    // legalization patterns could make us loop here, so the number of expansions
+                    // is limited.
+                    self.trace.expand(inst, pos.func, isa.name())?;
                     let changed = action(inst, pos.func, cfg, isa);
                     // If the current instruction was replaced, we need to double back and revisit
                     // the expanded sequence. This is both to assign encodings and possible to
                     // expand further.
-                    // There's a risk of infinite looping here if the legalization patterns are
-                    // unsound. Should we attempt to detect that?
                     if changed {
                         pos.set_position(prev_pos);
                         continue;
@@ -93,7 +258,7 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
 
                     // We don't have any pattern expansion for this instruction either.
                     // Try converting it to a library call as a last resort.
-                    if expand_as_libcall(inst, pos.func) {
+                    if expand_as_libcall(inst, pos.func, isa) {
                         pos.set_position(prev_pos);
                         continue;
                     }
@@ -103,6 +268,15 @@ pub fn legalize_function(func: &mut ir::Function, cfg: &mut ControlFlowGraph, is
             // Remember this position in case we need to double back.
             prev_pos = pos.position();
         }
+
+        Ok(())
+    }
+
+    /// Clean up after all the EBBs have been legalized.
+    fn finish(&self, func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa: &TargetIsa) {
+        fold_offsets::remove_dead_adds(func, &self.folded);
+        merge_jump_tables(func);
+        flags::recompute_clobbered_flags(func, cfg, isa);
     }
 }
 
@@ -163,43 +337,6 @@ fn expand_cond_trap(
     cfg.recompute_ebb(pos.func, new_ebb);
 }
 
-/// Jump tables.
-fn expand_br_table(
-    inst: ir::Inst,
-    func: &mut ir::Function,
-    cfg: &mut ControlFlowGraph,
-    _isa: &TargetIsa,
-) {
-    use ir::condcodes::IntCC;
-
-    let (arg, table) = match func.dfg[inst] {
-        ir::InstructionData::BranchTable {
-            opcode: ir::Opcode::BrTable,
-            arg,
-            table,
-        } => (arg, table),
-        _ => panic!("Expected br_table: {}", func.dfg.display_inst(inst, None)),
-    };
-
-    // This is a poor man's jump table using just a sequence of conditional branches.
-    // TODO: Lower into a jump table load and indirect branch.
-    let table_size = func.jump_tables[table].len();
-    let mut pos = FuncCursor::new(func).at_inst(inst);
-    pos.use_srcloc(inst);
-
-    for i in 0..table_size {
-        if let Some(dest) = pos.func.jump_tables[table].get_entry(i) {
-            let t = pos.ins().icmp_imm(IntCC::Equal, arg, i as i64);
-            pos.ins().brnz(t, dest, &[]);
-        }
-    }
-
-    // `br_table` falls through when nothing matches.
-    let ebb = pos.current_ebb().unwrap();
-    pos.remove_inst();
-    cfg.recompute_ebb(pos.func, ebb);
-}
-
 /// Expand the select instruction.
 ///
 /// Conditional moves are available in some ISAs for some register classes. The remaining selects
@@ -245,13 +382,14 @@ fn expand_fconst(
     inst: ir::Inst,
     func: &mut ir::Function,
     _cfg: &mut ControlFlowGraph,
-    _isa: &TargetIsa,
+    isa: &TargetIsa,
 ) {
     let ty = func.dfg.value_type(func.dfg.first_result(inst));
     debug_assert!(!ty.is_vector(), "Only scalar fconst supported: {}", ty);
 
-    // In the future, we may want to generate constant pool entries for these constants, but for
-    // now use an `iconst` and a bit cast.
+    // A 64-bit constant is loaded from the constant pool when the target can do that directly.
+    // Otherwise, and for 32-bit constants which fit in a single immediate, use an `iconst` and a
+    // bit cast.
     let mut pos = FuncCursor::new(func).at_inst(inst);
     pos.use_srcloc(inst);
     let ival = match pos.func.dfg[inst] {
@@ -262,12 +400,106 @@ fn expand_fconst(
         ir::InstructionData::UnaryIeee64 {
             opcode: ir::Opcode::F64const,
             imm,
-        } => pos.ins().iconst(ir::types::I64, imm.bits() as i64),
+        } => {
+            if can_load_constant(pos.func, isa, ty) {
+                let data = ir::ConstantData::from_bits(imm.bits(), 8);
+                let constant = pos.func.create_constant(data);
+                pos.func.dfg.replace(inst).const_load(ty, constant);
+                return;
+            }
+            pos.ins().iconst(ir::types::I64, imm.bits() as i64)
+        }
         _ => panic!("Expected fconst: {}", pos.func.dfg.display_inst(inst, None)),
     };
     pos.func.dfg.replace(inst).bitcast(ty, ival);
 }
 
+/// Can `isa` encode a `const_load` instruction of type `ty`?
+fn can_load_constant(func: &ir::Function, isa: &TargetIsa, ty: ir::Type) -> bool {
+    // The encodings don't depend on the referenced constant.
+    let data = ir::InstructionData::UnaryConst {
+        opcode: ir::Opcode::ConstLoad,
+        constant: ir::Constant::with_number(0).unwrap(),
+    };
+    isa.legal_encodings(&func.dfg, &data, ty).next().is_some()
+}
+
+/// Expand illegal `const_load` instructions.
+///
+/// The constant is materialized with immediate operands instead. Vector constants are built one
+/// lane at a time.
+fn expand_const_load(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    _cfg: &mut ControlFlowGraph,
+    _isa: &TargetIsa,
+) {
+    let constant = match func.dfg[inst] {
+        ir::InstructionData::UnaryConst {
+            opcode: ir::Opcode::ConstLoad,
+            constant,
+        } => constant,
+        _ => panic!("Expected const_load: {}", func.dfg.display_inst(inst, None)),
+    };
+    let ty = func.dfg.value_type(func.dfg.first_result(inst));
+    let data = func.constants[constant].clone();
+    let lane_ty = ty.lane_type();
+    let lane_bytes = lane_ty.bytes() as usize;
+    let lane_bits = |lane: usize| {
+        data.bytes()[lane * lane_bytes..(lane + 1) * lane_bytes]
+            .iter()
+            .rev()
+            .fold(0u64, |bits, &byte| (bits << 8) | u64::from(byte))
+    };
+
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+    if !ty.is_vector() {
+        let bits = lane_bits(0);
+        match ty {
+            ir::types::F32 => {
+                pos.func.dfg.replace(inst).f32const(
+                    ir::immediates::Ieee32::with_bits(bits as u32),
+                );
+            }
+            ir::types::F64 => {
+                pos.func.dfg.replace(inst).f64const(
+                    ir::immediates::Ieee64::with_bits(bits),
+                );
+            }
+            _ if ty.is_bool() => {
+                pos.func.dfg.replace(inst).bconst(ty, bits != 0);
+            }
+            _ => {
+                pos.func.dfg.replace(inst).iconst(ty, bits as i64);
+            }
+        }
+        return;
+    }
+
+    let lanes = ty.lane_count() as usize;
+    let mut vector = None;
+    for lane in 0..lanes {
+        let bits = lane_bits(lane);
+        let value = match lane_ty {
+            ir::types::F32 => {
+                pos.ins().f32const(ir::immediates::Ieee32::with_bits(bits as u32))
+            }
+            ir::types::F64 => pos.ins().f64const(ir::immediates::Ieee64::with_bits(bits)),
+            _ if lane_ty.is_bool() => pos.ins().bconst(lane_ty, bits != 0),
+            _ => pos.ins().iconst(lane_ty, bits as i64),
+        };
+        vector = Some(match vector {
+            None => pos.ins().splat(ty, value),
+            Some(v) if lane + 1 == lanes => {
+                pos.func.dfg.replace(inst).insertlane(v, lane as u8, value);
+                return;
+            }
+            Some(v) => pos.ins().insertlane(v, lane as u8, value),
+        });
+    }
+}
+
 /// Expand the stack check instruction.
 pub fn expand_stack_check(
     inst: ir::Inst,
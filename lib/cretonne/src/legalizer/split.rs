@@ -341,3 +341,32 @@ pub fn simplify_branch_arguments(dfg: &mut ir::DataFlowGraph, branch: Inst) {
 
     dfg.inst_args_mut(branch).copy_from_slice(&new_args);
 }
+
+/// Replace an explicit `isplit` instruction by the halves of its argument when the argument has
+/// been narrowed.
+///
+/// The argument of an `isplit` inserted by the ABI legalization or written in the source is
+/// usually defined by an `iconcat` after legalizing, or it is a non-entry EBB argument that can be
+/// split too. Other arguments can't be split without the `isplit` itself, so the instruction is
+/// left alone.
+///
+/// Return `true` if the `isplit` instruction was removed.
+pub fn simplify_isplit(func: &mut ir::Function, cfg: &ControlFlowGraph, inst: Inst) -> bool {
+    let arg = func.dfg.resolve_aliases(func.dfg.inst_args(inst)[0]);
+    let splittable = match func.dfg.value_def(arg) {
+        ValueDef::Result(def, _) => func.dfg[def].opcode() == Opcode::Iconcat,
+        ValueDef::Param(ebb, _) => func.layout.entry_block() != Some(ebb),
+    };
+    if !splittable {
+        return false;
+    }
+
+    let srcloc = func.srclocs[inst];
+    let (lo, hi) = isplit(func, cfg, CursorPosition::At(inst), srcloc, arg);
+    let results = func.dfg.inst_results(inst).to_vec();
+    func.dfg.clear_results(inst);
+    func.dfg.change_to_alias(results[0], lo);
+    func.dfg.change_to_alias(results[1], hi);
+    func.layout.remove_inst(inst);
+    true
+}
@@ -0,0 +1,206 @@
+//! Legalization of a function while it is being built.
+//!
+//! Machine-generated code like wasm can have enormous functions. Instead of legalizing the whole
+//! function after it has been built, a frontend can legalize and encode each EBB as soon as it is
+//! complete with a `StreamingLegalizer`, while the instructions are still in the cache. The work
+//! left for `Context::compile` is the register allocation and the code emission.
+//!
+//! An EBB is complete when it won't be modified anymore. With the `FunctionBuilder` of the
+//! `cretonne-frontend` crate, that is once the EBB is filled, and it and all its successors are
+//! sealed, since the SSA construction can add EBB parameters and branch arguments until then.
+
+use super::{boundary, CustomOpcodes, Legalizer, TrapHandler};
+use cursor::{Cursor, FuncCursor};
+use entity::EntityRef;
+use flowgraph::ControlFlowGraph;
+use ir::{Ebb, Function};
+use isa::TargetIsa;
+use result::CtonResult;
+use timing;
+
+/// Legalizer for the EBBs of a function that is still being built.
+///
+/// Create the legalizer once the entry block and its parameters have been created, call
+/// `legalize_ebb()` for each complete EBB, and hand the legalizer to `Context::finish_streaming()`
+/// once the function is built. The passes that `Context::compile` runs before legalization are
+/// skipped for the function.
+pub struct StreamingLegalizer {
+    legalizer: Legalizer,
+    cfg: ControlFlowGraph,
+    limit: usize,
+    trap_handler: Option<TrapHandler>,
+    custom_opcodes: Option<CustomOpcodes>,
+}
+
+impl StreamingLegalizer {
+    /// Start legalizing `func` for `isa` by legalizing its signatures and entry block parameters.
+    pub fn new(func: &mut Function, isa: &TargetIsa) -> Self {
+        let _tt = timing::legalize();
+        debug_assert!(
+            func.layout.entry_block().is_some(),
+            "the entry block must be created before the legalizer"
+        );
+        boundary::legalize_signatures(func, isa);
+
+        // The expansion budget grows with the legalized instructions.
+        let limit = usize::from(isa.flags().legalizer_expansion_limit());
+        Self {
+            legalizer: Legalizer::new(limit, true),
+            cfg: ControlFlowGraph::with_function(func),
+            limit,
+            trap_handler: None,
+            custom_opcodes: None,
+        }
+    }
+
+    /// Replace the trap instructions with the trap codes selected by `handler` by calls to the
+    /// handler, like `legalize_function_with_trap_handler()`.
+    pub fn set_trap_handler(&mut self, handler: Option<TrapHandler>) {
+        self.trap_handler = handler;
+    }
+
+    /// Expand the `custom` instructions with the expansions registered in `custom_opcodes`, like
+    /// `legalize_function_with_custom_opcodes()`.
+    pub fn set_custom_opcodes(&mut self, custom_opcodes: Option<CustomOpcodes>) {
+        self.custom_opcodes = custom_opcodes;
+    }
+
+    /// Legalize the complete EBB `ebb` of `func`.
+    ///
+    /// The EBBs created by the expansions of its instructions are legalized too. `ebb` must not be
+    /// modified afterwards.
+    pub fn legalize_ebb(&mut self, func: &mut Function, isa: &TargetIsa, ebb: Ebb) -> CtonResult {
+        let _tt = timing::legalize();
+        let num_ebbs = func.dfg.num_ebbs();
+        self.cfg.recompute_ebb(func, ebb);
+        self.visit(func, isa, ebb)?;
+
+        // Expansions splitting an EBB insert the new EBBs after it or at the end of the layout.
+        let mut index = num_ebbs;
+        while index < func.dfg.num_ebbs() {
+            let new_ebb = Ebb::new(index);
+            if func.layout.is_ebb_inserted(new_ebb) {
+                self.visit(func, isa, new_ebb)?;
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Legalize the instructions of `ebb`, raising the expansion limit in proportion.
+    fn visit(&mut self, func: &mut Function, isa: &TargetIsa, ebb: Ebb) -> CtonResult {
+        let num_insts = func.layout.ebb_insts(ebb).count();
+        self.legalizer.trace.raise_limit(self.limit * num_insts);
+        let mut pos = FuncCursor::new(func).at_top(ebb);
+        self.legalizer.legalize_ebb(
+            &mut pos,
+            &mut self.cfg,
+            isa,
+            self.trap_handler.as_ref(),
+            self.custom_opcodes.as_ref(),
+        )
+    }
+
+    /// Finish the legalization of `func` once it is built, and compute its control flow graph in
+    /// `cfg`.
+    ///
+    /// The EBBs that weren't passed to `legalize_ebb()` are legalized now.
+    pub fn finish(
+        mut self,
+        func: &mut Function,
+        cfg: &mut ControlFlowGraph,
+        isa: &TargetIsa,
+    ) -> CtonResult {
+        let _tt = timing::legalize();
+        cfg.compute(func);
+        self.legalizer.trace.raise_limit(self.limit * func.dfg.num_insts());
+        let mut pos = FuncCursor::new(func);
+        while let Some(_ebb) = pos.next_ebb() {
+            self.legalizer.legalize_ebb(
+                &mut pos,
+                cfg,
+                isa,
+                self.trap_handler.as_ref(),
+                self.custom_opcodes.as_ref(),
+            )?;
+        }
+        self.legalizer.finish(pos.func, cfg, isa);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, InstBuilder};
+    use isa;
+    use settings::{self, Configurable};
+
+    #[test]
+    #[cfg(build_riscv)]
+    fn ebb_at_a_time() {
+        let isa = isa::lookup("riscv").unwrap().finish(settings::Flags::new(&settings::builder()));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(I32));
+        ctx.func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let ebb1 = ctx.func.dfg.make_ebb();
+        let x = ctx.func.dfg.append_ebb_param(ebb0, I32);
+        let y = ctx.func.dfg.append_ebb_param(ebb1, I32);
+        let mut pos = FuncCursor::new(&mut ctx.func);
+        pos.insert_ebb(ebb0);
+
+        // The signature is legalized up front: `riscv` adds a link parameter.
+        let mut legalizer = StreamingLegalizer::new(pos.func, &*isa);
+        assert_eq!(pos.func.signature.params.len(), 2);
+        assert_eq!(pos.func.dfg.ebb_params(ebb0).len(), 2);
+
+        let z = pos.ins().bnot(x);
+        pos.ins().jump(ebb1, &[z]);
+        legalizer.legalize_ebb(pos.func, &*isa, ebb0).unwrap();
+        for inst in pos.func.layout.ebb_insts(ebb0) {
+            assert!(pos.func.encodings[inst].is_legal());
+        }
+
+        // `ebb1` is left to `finish()`.
+        pos.insert_ebb(ebb1);
+        let w = pos.ins().iadd_imm(y, 1);
+        let ret = pos.ins().return_(&[w]);
+        assert!(!pos.func.encodings[ret].is_legal());
+
+        ctx.finish_streaming(legalizer, &*isa).unwrap();
+        for ebb in ctx.func.layout.ebbs() {
+            for inst in ctx.func.layout.ebb_insts(ebb) {
+                assert!(ctx.func.encodings[inst].is_legal());
+            }
+        }
+        ctx.compile(&*isa).unwrap();
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn expansion_limit() {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("legalizer_expansion_limit", "1").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I64));
+        func.signature.returns.push(AbiParam::new(I64));
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I64);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let mut legalizer = StreamingLegalizer::new(pos.func, &*isa);
+
+        // Narrowing an `i64` multiplication for a 32-bit target takes several expansions.
+        let v1 = pos.ins().imul(v0, v0);
+        let v2 = pos.ins().imul(v1, v1);
+        let v3 = pos.ins().imul(v2, v2);
+        pos.ins().return_(&[v3]);
+        assert!(legalizer.legalize_ebb(pos.func, &*isa, ebb0).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Expansion tracing and the iteration limit for the legalizer.
+//!
+//! The legalizer revisits the instructions produced by an expansion until they all have legal
+//! encodings. An unsound set of legalization patterns can keep reintroducing illegal instructions,
+//! so the number of expansions is limited by the `legalizer_expansion_limit` setting. When the
+//! limit is exceeded, legalization fails with a `LegalizerError` which includes the most recent
+//! expansions.
+
+use ir::{self, Opcode, Type};
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt::{self, Display, Formatter};
+use std::vec::Vec;
+
+/// Number of expansions kept in the trace of a `LegalizerError`.
+const TRACE_LENGTH: usize = 16;
+
+/// A single expansion performed by the legalizer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    /// The instruction that was expanded.
+    pub inst: ir::Inst,
+    /// The opcode of the expanded instruction.
+    pub opcode: Opcode,
+    /// The controlling type variable of the expanded instruction.
+    pub ctrl_type: Type,
+}
+
+impl Display for Expansion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.inst, self.opcode)?;
+        if !self.ctrl_type.is_void() {
+            write!(f, ".{}", self.ctrl_type)?;
+        }
+        Ok(())
+    }
+}
+
+/// A legalization failure.
+///
+/// The expansions of an instruction didn't converge to legal instructions within the limit set
+/// by `legalizer_expansion_limit`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LegalizerError {
+    /// The expansion that exceeded the limit.
+    pub expansion: Expansion,
+    /// The name of the target ISA.
+    pub isa: &'static str,
+    /// The number of expansions performed.
+    pub count: usize,
+    /// The most recent expansions before the limit was exceeded, oldest first.
+    pub trace: Vec<Expansion>,
+}
+
+impl Display for LegalizerError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: no legal encoding for {} after {} expansions",
+            self.isa,
+            self.expansion,
+            self.count
+        )?;
+        for expansion in &self.trace {
+            write!(f, "\n  expanded {}", expansion)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for LegalizerError {
+    fn description(&self) -> &str {
+        "Legalization didn't converge"
+    }
+}
+
+/// Bookkeeping of the expansions performed while legalizing a function.
+pub struct ExpansionTrace {
+    limit: usize,
+    count: usize,
+    recent: VecDeque<Expansion>,
+}
+
+impl ExpansionTrace {
+    /// Create a trace allowing `limit` expansions, or any number of expansions if `limit` is 0.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            count: 0,
+            recent: VecDeque::with_capacity(TRACE_LENGTH),
+        }
+    }
+
+    /// Allow `amount` more expansions, unless any number of expansions is allowed.
+    pub fn raise_limit(&mut self, amount: usize) {
+        if self.limit != 0 {
+            self.limit += amount;
+        }
+    }
+
+    /// Record the expansion of `inst` in `func` which is about to happen.
+    ///
+    /// Return an error if this expansion exceeds the limit.
+    pub fn expand(
+        &mut self,
+        inst: ir::Inst,
+        func: &ir::Function,
+        isa: &'static str,
+    ) -> Result<(), LegalizerError> {
+        let expansion = Expansion {
+            inst,
+            opcode: func.dfg[inst].opcode(),
+            ctrl_type: func.dfg.ctrl_typevar(inst),
+        };
+        dbg!("Legalizing {}", func.dfg.display_inst(inst, None));
+
+        self.count += 1;
+        if self.limit != 0 && self.count > self.limit {
+            return Err(LegalizerError {
+                expansion,
+                isa,
+                count: self.limit,
+                trace: self.recent.iter().cloned().collect(),
+            });
+        }
+
+        if self.recent.len() == TRACE_LENGTH {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(expansion);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, Function, InstBuilder};
+    use isa;
+    use result::CtonError;
+    use settings::{self, Configurable};
+    use std::string::ToString;
+    use Context;
+
+    #[test]
+    fn limit() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let inst = {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().iconst(I32, 1);
+            pos.func.dfg.value_def(v0).unwrap_inst()
+        };
+
+        let mut trace = ExpansionTrace::new(2);
+        assert_eq!(trace.expand(inst, &func, "test"), Ok(()));
+        assert_eq!(trace.expand(inst, &func, "test"), Ok(()));
+        let err = trace.expand(inst, &func, "test").unwrap_err();
+        assert_eq!(err.expansion.opcode, Opcode::Iconst);
+        assert_eq!(err.expansion.ctrl_type, I32);
+        assert_eq!(err.trace.len(), 2);
+        assert_eq!(
+            err.to_string(),
+            "test: no legal encoding for inst0: iconst.i32 after 2 expansions\n  \
+             expanded inst0: iconst.i32\n  \
+             expanded inst0: iconst.i32"
+        );
+
+        let mut trace = ExpansionTrace::new(0);
+        for _ in 0..100 {
+            assert_eq!(trace.expand(inst, &func, "test"), Ok(()));
+        }
+        assert_eq!(trace.recent.len(), TRACE_LENGTH);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn legalize_with_limit() {
+        // Narrowing an `i64` multiplication for a 32-bit target takes several expansions.
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I64));
+        func.signature.returns.push(AbiParam::new(I64));
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v1 = pos.ins().imul(v0, v0);
+            pos.ins().return_(&[v1]);
+        }
+
+        let mut flag_builder = settings::builder();
+        flag_builder.set("legalizer_expansion_limit", "1").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+        let mut ctx = Context::for_function(func);
+        ctx.compute_cfg();
+        match ctx.legalize(&*isa) {
+            Err(CtonError::Legalizer(err)) => {
+                assert_eq!(err.isa, "intel");
+                assert_eq!(err.trace.len(), err.count);
+                assert!(err.trace.iter().any(|e| {
+                    e.opcode == Opcode::Imul && e.ctrl_type == I64
+                }));
+            }
+            res => panic!("Unexpected legalization result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Conversion of trap instructions into calls to an embedder-defined trap handler.
+//!
+//! Some runtimes can't handle signals, for example when running inside a sandbox. They can ask
+//! the legalizer to replace the trap instructions with selected trap codes by a call to a handler
+//! function. The handler is not expected to return, but a trap instruction is still placed after
+//! the call in case it does.
+
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::{self, InstBuilder};
+use isa::TargetIsa;
+use std::vec::Vec;
+
+/// A function to call instead of trapping.
+///
+/// The handler is called with the index of the trap code in `codes` as an `i32` argument. When
+/// the function being compiled has a `vmctx` parameter, it is passed to the handler too.
+///
+/// Only explicit trap instructions are converted. Implicit traps, like the faults of loads from
+/// the heap guard pages or the native trapping divisions, still cause signals. Use the
+/// `avoid_div_traps` setting and dynamic heaps to get explicit traps for those.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrapHandler {
+    /// Name of the handler function.
+    pub name: ir::ExternalName,
+
+    /// The trap codes to convert into handler calls.
+    pub codes: Vec<ir::TrapCode>,
+}
+
+/// Convert the trap instruction `inst` into a call to `handler` if it has one of the handler's
+/// trap codes.
+///
+/// Return `true` if the function was changed.
+pub fn convert_trap(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+    handler: &TrapHandler,
+) -> bool {
+    let index = match func.dfg[inst].trap_code().and_then(|code| {
+        handler.codes.iter().position(|&c| c == code)
+    }) {
+        Some(index) => index,
+        None => return false,
+    };
+
+    // A conditional trap becomes a branch to a cold EBB at the end of the function which calls the
+    // handler and then traps unconditionally.
+    let trap_ebb = match func.dfg[inst] {
+        ir::InstructionData::Trap { .. } => None,
+        ir::InstructionData::CondTrap { opcode, arg, .. } => {
+            let trap_ebb = func.dfg.make_ebb();
+            match opcode {
+                ir::Opcode::Trapz => func.dfg.replace(inst).brz(arg, trap_ebb, &[]),
+                ir::Opcode::Trapnz => func.dfg.replace(inst).brnz(arg, trap_ebb, &[]),
+                _ => panic!("Expected cond trap: {}", func.dfg.display_inst(inst, None)),
+            };
+            Some(trap_ebb)
+        }
+        ir::InstructionData::IntCondTrap { cond, arg, .. } => {
+            let trap_ebb = func.dfg.make_ebb();
+            func.dfg.replace(inst).brif(cond, arg, trap_ebb, &[]);
+            Some(trap_ebb)
+        }
+        ir::InstructionData::FloatCondTrap { cond, arg, .. } => {
+            let trap_ebb = func.dfg.make_ebb();
+            func.dfg.replace(inst).brff(cond, arg, trap_ebb, &[]);
+            Some(trap_ebb)
+        }
+        // Arithmetic trapping on overflow is expanded into a conditional trap, which is converted
+        // when it is legalized.
+        ir::InstructionData::BinaryTrap { .. } => return false,
+        _ => panic!("Expected trap: {}", func.dfg.display_inst(inst, None)),
+    };
+
+    let trap_ebb = match trap_ebb {
+        Some(trap_ebb) => trap_ebb,
+        None => {
+            // The handler call has already been inserted before an unconditional trap.
+            if is_handler_call(func, inst, handler) {
+                return false;
+            }
+            let mut pos = FuncCursor::new(func).at_inst(inst);
+            pos.use_srcloc(inst);
+            insert_handler_call(&mut pos, isa, handler, index);
+            return true;
+        }
+    };
+
+    let code = handler.codes[index];
+    let old_ebb = func.layout.pp_ebb(inst);
+    func.layout.set_cold(trap_ebb, true);
+    let mut pos = FuncCursor::new(func);
+    pos.use_srcloc(inst);
+    pos.insert_ebb(trap_ebb);
+    insert_handler_call(&mut pos, isa, handler, index);
+    pos.ins().trap(code);
+
+    cfg.recompute_ebb(pos.func, old_ebb);
+    cfg.recompute_ebb(pos.func, trap_ebb);
+    true
+}
+
+/// Insert a call to `handler` for the trap code `handler.codes[index]` at `pos`.
+fn insert_handler_call(
+    pos: &mut FuncCursor,
+    isa: &TargetIsa,
+    handler: &TrapHandler,
+    index: usize,
+) {
+    let callee = handler_ref(pos.func, isa, handler);
+    let mut args = Vec::new();
+    args.push(pos.ins().iconst(ir::types::I32, index as i64));
+    if let Some(vmctx) = pos.func.special_param(ir::ArgumentPurpose::VMContext) {
+        args.push(vmctx);
+    }
+    pos.ins().call(callee, &args);
+}
+
+/// Is the instruction before `inst` a call to `handler`?
+fn is_handler_call(func: &ir::Function, inst: ir::Inst, handler: &TrapHandler) -> bool {
+    match func.layout.prev_inst(inst).map(|prev| &func.dfg[prev]) {
+        Some(&ir::InstructionData::Call { func_ref, .. }) => {
+            func.dfg.ext_funcs[func_ref].name == handler.name
+        }
+        _ => false,
+    }
+}
+
+/// Get a reference to the handler function, importing it into `func` on first use.
+///
+/// The signatures in `func` have already been legalized, so the handler signature is legalized
+/// here too.
+fn handler_ref(func: &mut ir::Function, isa: &TargetIsa, handler: &TrapHandler) -> ir::FuncRef {
+    if let Some(fref) = func.dfg.ext_funcs.keys().find(|&fref| {
+        func.dfg.ext_funcs[fref].name == handler.name
+    })
+    {
+        return fref;
+    }
+
+    let mut sig = ir::Signature::new(func.signature.call_conv);
+    sig.params.push(ir::AbiParam::new(ir::types::I32));
+    if let Some(vmctx) = func.special_param(ir::ArgumentPurpose::VMContext) {
+        sig.params.push(ir::AbiParam::special(
+            func.dfg.value_type(vmctx),
+            ir::ArgumentPurpose::VMContext,
+        ));
+    }
+    isa.legalize_signature(&mut sig, false);
+    sig.compute_argument_bytes();
+    let signature = func.import_signature(sig);
+    func.import_function(ir::ExtFuncData {
+        name: handler.name.clone(),
+        signature,
+        colocated: false,
+    })
+}
@@ -0,0 +1,142 @@
+//! Legalization of unaligned vector memory accesses.
+//!
+//! This module exports the `expand_unaligned_vector_access` function which handles vector loads
+//! and stores without the `aligned` flag according to the `unaligned_vector_access` setting.
+
+use cursor::{Cursor, FuncCursor};
+use ir::{self, InstBuilder};
+use isa::TargetIsa;
+use settings::UnalignedVectorAccess;
+
+/// Apply the `unaligned_vector_access` setting to the vector load or store `inst`.
+///
+/// With the `trap` setting, the `aligned` flag is added to the instruction. With the `split`
+/// setting, the instruction is replaced by scalar accesses of the individual lanes.
+///
+/// Return `true` if the instruction was replaced.
+pub fn expand_unaligned_vector_access(
+    inst: ir::Inst,
+    func: &mut ir::Function,
+    isa: &TargetIsa,
+) -> bool {
+    let mode = isa.flags().unaligned_vector_access();
+    if mode == UnalignedVectorAccess::Native {
+        return false;
+    }
+
+    let ty = func.dfg.ctrl_typevar(inst);
+    if !ty.is_vector() {
+        return false;
+    }
+
+    let (flags, addr, stored, offset) = match func.dfg[inst] {
+        ir::InstructionData::Load {
+            opcode: ir::Opcode::Load,
+            flags,
+            arg,
+            offset,
+        } => (flags, arg, None, offset),
+        ir::InstructionData::Store {
+            opcode: ir::Opcode::Store,
+            flags,
+            args,
+            offset,
+        } => (flags, args[1], Some(args[0]), offset),
+        _ => return false,
+    };
+    if flags.aligned() {
+        return false;
+    }
+
+    if mode == UnalignedVectorAccess::Trap {
+        match func.dfg[inst] {
+            ir::InstructionData::Load { ref mut flags, .. } |
+            ir::InstructionData::Store { ref mut flags, .. } => flags.set_aligned(),
+            _ => unreachable!(),
+        }
+        return false;
+    }
+
+    let lane_ty = ty.lane_type();
+    let lane_bytes = lane_ty.bytes() as i32;
+    let last_lane = ty.lane_count() as u8 - 1;
+    let mut pos = FuncCursor::new(func).at_inst(inst);
+    pos.use_srcloc(inst);
+
+    // Make sure the offsets of all the lanes are representable.
+    let mut addr = addr;
+    let mut offset: i32 = offset.into();
+    if offset
+        .checked_add(i32::from(last_lane) * lane_bytes)
+        .is_none()
+    {
+        addr = pos.ins().iadd_imm(addr, i64::from(offset));
+        offset = 0;
+    }
+    let lane_offset = |lane: u8| offset + i32::from(lane) * lane_bytes;
+
+    match stored {
+        None => {
+            // Replace `v = load.ty p` with lane loads that are inserted into a vector one by one.
+            let first = load_lane(&mut pos, lane_ty, flags, addr, offset);
+            let mut vector = pos.ins().splat(ty, first);
+            for lane in 1..last_lane {
+                let value = load_lane(&mut pos, lane_ty, flags, addr, lane_offset(lane));
+                vector = pos.ins().insertlane(vector, lane, value);
+            }
+            let value = load_lane(&mut pos, lane_ty, flags, addr, lane_offset(last_lane));
+            pos.func.dfg.replace(inst).insertlane(vector, last_lane, value);
+        }
+        Some(vector) => {
+            // Replace `store x, p` with a store of each lane extracted from `x`.
+            for lane in 0..last_lane + 1 {
+                let value = pos.ins().extractlane(vector, lane);
+                store_lane(&mut pos, flags, value, addr, lane_offset(lane));
+            }
+            pos.remove_inst();
+        }
+    }
+    true
+}
+
+/// Load a single lane of type `lane_ty`.
+///
+/// Lanes narrower than 32 bits are loaded with an extending load since not all targets can load
+/// them directly.
+fn load_lane(
+    pos: &mut FuncCursor,
+    lane_ty: ir::Type,
+    flags: ir::MemFlags,
+    addr: ir::Value,
+    offset: i32,
+) -> ir::Value {
+    let value = match lane_ty.bits() {
+        8 => pos.ins().uload8(ir::types::I32, flags, addr, offset),
+        16 => pos.ins().uload16(ir::types::I32, flags, addr, offset),
+        _ => return pos.ins().load(lane_ty, flags, addr, offset),
+    };
+    pos.ins().ireduce(lane_ty, value)
+}
+
+/// Store the lane value `value`, the counterpart of `load_lane()`.
+fn store_lane(
+    pos: &mut FuncCursor,
+    flags: ir::MemFlags,
+    value: ir::Value,
+    addr: ir::Value,
+    offset: i32,
+) {
+    match pos.func.dfg.value_type(value).bits() {
+        8 => {
+            let wide = pos.ins().uextend(ir::types::I32, value);
+            pos.ins().istore8(flags, wide, addr, offset);
+        }
+        16 => {
+            let wide = pos.ins().uextend(ir::types::I32, value);
+            pos.ins().istore16(flags, wide, addr, offset);
+        }
+        _ => {
+            pos.ins().store(flags, value, addr, offset);
+        }
+    }
+}
@@ -35,10 +35,19 @@
                 useless_let_if_seq,
                 len_without_is_empty))]
 
+#[cfg(all(test, feature = "enable-serde"))]
+extern crate serde;
+#[cfg(feature = "enable-serde")]
+#[macro_use]
+extern crate serde_derive;
+
 pub use context::Context;
-pub use legalizer::legalize_function;
+pub use driver::{compile_all, compile_function, CompiledFunction};
+pub use legalizer::{legalize_function, legalize_function_with_custom_opcodes,
+                    legalize_function_with_trap_handler, CustomExpansion, CustomOpcodes,
+                    Expansion, LegalizerError, StreamingLegalizer, TrapHandler};
 pub use verifier::verify_function;
-pub use write::write_function;
+pub use write::{write_function, write_function_with_annotations, Annotations};
 
 /// Version number of the cretonne crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -50,14 +59,25 @@ pub mod entity;
 
 pub mod bforest;
 pub mod binemit;
+pub mod block_frequency;
 pub mod cfg_printer;
 pub mod cursor;
+pub mod custom_pass;
+pub mod dedup;
 pub mod dominator_tree;
 pub mod flowgraph;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod inline;
+pub mod ipo;
 pub mod ir;
+pub mod ir_builder;
 pub mod isa;
 pub mod loop_analysis;
+pub mod mangle;
+pub mod outline;
 pub mod packed_option;
+pub mod pipeline;
 pub mod print_errors;
 pub mod result;
 pub mod settings;
@@ -66,20 +86,39 @@ pub mod verifier;
 
 mod abi;
 mod bitset;
+mod const_pool;
 mod constant_hash;
 mod context;
+mod dead_store;
 mod divconst_magic_numbers;
+mod driver;
+mod entry_exit_hooks;
+mod fold;
+mod fuel;
+mod heap_checks;
+mod if_conversion;
 mod iterators;
 mod legalizer;
 mod licm;
+mod nan_canonicalization;
 mod partition_slice;
+mod peel;
+mod peephole;
 mod predicates;
 mod preopt;
+mod redundant_extend;
+mod redundant_load;
 mod ref_slice;
 mod regalloc;
+mod rewrite;
+mod sccp;
+mod schedule;
 mod scoped_hash_map;
+mod shrink;
 mod simple_gvn;
+mod simplify_cfg;
 mod stack_layout;
 mod topo_order;
 mod unreachable_code;
+mod unroll;
 mod write;
@@ -1,7 +1,7 @@
 //! A Loop Invariant Code Motion optimization pass
 
 use cursor::{Cursor, FuncCursor};
-use ir::{Function, Ebb, Inst, Value, Type, InstBuilder, Layout};
+use ir::{Function, Ebb, Inst, Value, Type, InstBuilder, Layout, DataFlowGraph, InstructionData};
 use flowgraph::ControlFlowGraph;
 use std::collections::HashSet;
 use dominator_tree::DominatorTree;
@@ -152,7 +152,7 @@ fn remove_loop_invariant_instructions(
         pos.goto_top(*ebb);
         #[cfg_attr(feature = "cargo-clippy", allow(block_in_if_condition_stmt))]
         while let Some(inst) = pos.next_inst() {
-            if pos.func.dfg.has_results(inst) &&
+            if pos.func.dfg.has_results(inst) && is_hoistable(&pos.func.dfg, inst) &&
                 pos.func.dfg.inst_args(inst).into_iter().all(|arg| {
                     !loop_values.contains(arg)
                 })
@@ -174,6 +174,21 @@ fn remove_loop_invariant_instructions(
     invariant_inst
 }
 
+/// Test whether `inst` can be moved out of its loop when its arguments are loop-invariant.
+///
+/// Instructions with side effects must stay in the loop. Loads are only hoisted when the memory
+/// they read is `readonly` and the access is `speculatable`, since the load may be guarded by a
+/// branch inside the loop.
+fn is_hoistable(dfg: &DataFlowGraph, inst: Inst) -> bool {
+    if let InstructionData::Load { flags, .. } = dfg[inst] {
+        return flags.readonly() && flags.speculatable();
+    }
+    let opcode = dfg[inst].opcode();
+    !(opcode.can_load() || opcode.can_store() || opcode.can_trap() || opcode.is_call() ||
+          opcode.is_branch() || opcode.is_terminator() || opcode.other_side_effects() ||
+          opcode.writes_cpu_flags())
+}
+
 /// Return ebbs from a loop in post-order, starting from an entry point in the block.
 fn postorder_ebbs_loop(loop_analysis: &LoopAnalysis, cfg: &ControlFlowGraph, lp: Loop) -> Vec<Ebb> {
     let mut grey = HashSet::new();
@@ -70,6 +70,11 @@ impl LoopAnalysis {
         self.loops[lp].parent.expand()
     }
 
+    /// Get the innermost loop containing `ebb`, if any.
+    pub fn innermost_loop(&self, ebb: Ebb) -> Option<Loop> {
+        self.ebb_loop_map[ebb].expand()
+    }
+
     /// Determine if an Ebb belongs to a loop by running a finger along the loop tree.
     ///
     /// Returns `true` if `ebb` is in loop `lp`.
@@ -127,6 +132,14 @@ impl LoopAnalysis {
         self.valid = false;
     }
 
+    /// Update the loop analysis after the unreachable `ebb` has been removed from the layout.
+    ///
+    /// Unreachable EBBs that branch into a loop are counted as part of the loop by `compute()`,
+    /// since the loop blocks are discovered by walking the predecessors of the back edges.
+    pub fn remove_ebb(&mut self, ebb: Ebb) {
+        self.ebb_loop_map[ebb] = None.into();
+    }
+
     // Traverses the CFG in reverse postorder and create a loop object for every EBB having a
     // back edge.
     fn find_loop_headers(
@@ -0,0 +1,326 @@
+//! Symbol name mangling.
+//!
+//! Frontends need to give the functions and data they define symbol names that are unique in a
+//! module, and that a linker and a debugger can make sense of. This module converts a structured
+//! `SymbolName` made of a module path, an item name, and an optional type signature hash into the
+//! bytes of an `ExternalName::Name`, and converts the bytes back for display.
+//!
+//! The mangled names follow the legacy Rust scheme, which is a subset of the Itanium C++ ABI
+//! scheme, so existing tools like `c++filt` can demangle them:
+//!
+//! ```text
+//! mangled   := prefix "_ZN" component+ "E"
+//! component := length text
+//! hash      := "17h" hex{16}
+//! ```
+//!
+//! The components are the path, the item, and finally the hash if there is one. The text of a
+//! component only uses ASCII letters, digits, and `_`, so the mangled name is also a valid name in
+//! the textual IL. In the text, `_` is written as `__`, and any other byte as `_` followed by two
+//! lower case hex digits. A leading digit is escaped the same way so it can't be confused with the
+//! length, and so is the `h` of an item that would otherwise look like a hash.
+//!
+//! The `prefix` is the one the object file format adds to C symbol names, see `SymbolFormat`.
+
+use ir::{ExternalName, Signature};
+use std::fmt::{self, Write};
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// The object file format, which determines the prefix of symbol names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolFormat {
+    /// ELF, used on Linux and most other Unix systems. Symbol names have no prefix.
+    Elf,
+    /// Mach-O, used on macOS and iOS. Symbol names are prefixed with `_`.
+    MachO,
+    /// COFF, used on Windows. Symbol names have no prefix.
+    Coff,
+}
+
+impl SymbolFormat {
+    /// Get the symbol format of the host.
+    pub fn host() -> Self {
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            SymbolFormat::MachO
+        } else if cfg!(windows) {
+            SymbolFormat::Coff
+        } else {
+            SymbolFormat::Elf
+        }
+    }
+
+    /// The prefix added to symbol names.
+    fn prefix(self) -> &'static str {
+        match self {
+            SymbolFormat::Elf | SymbolFormat::Coff => "",
+            SymbolFormat::MachO => "_",
+        }
+    }
+}
+
+/// A structured symbol name.
+///
+/// The `Display` implementation shows the name like a Rust path, `a::b::item::h0123456789abcdef`.
+/// The alternate form `{:#}` leaves out the hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolName {
+    /// The path of the module defining the item, outermost module first.
+    pub path: Vec<String>,
+    /// The name of the item.
+    pub item: String,
+    /// A hash distinguishing items with the same path and name, like the `signature_hash()` of a
+    /// function type.
+    pub hash: Option<u64>,
+}
+
+impl SymbolName {
+    /// Create a symbol name for `item` in the module at `path`, without a hash.
+    pub fn new<I, S>(path: I, item: &str) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            path: path.into_iter().map(Into::into).collect(),
+            item: item.to_string(),
+            hash: None,
+        }
+    }
+
+    /// Add the `signature_hash()` of `sig` to the name.
+    pub fn with_signature(mut self, sig: &Signature) -> Self {
+        self.hash = Some(signature_hash(sig));
+        self
+    }
+
+    /// Mangle the name for `format`.
+    pub fn mangle(&self, format: SymbolFormat) -> ExternalName {
+        let mut s = String::from(format.prefix());
+        s.push_str("_ZN");
+        for component in &self.path {
+            push_component(&mut s, component, false);
+        }
+        push_component(&mut s, &self.item, self.hash.is_some());
+        if let Some(hash) = self.hash {
+            write!(s, "17h{:016x}", hash).unwrap();
+        }
+        s.push('E');
+        ExternalName::name(s)
+    }
+
+    /// Demangle a name produced by `mangle()` for any symbol format.
+    ///
+    /// Returns `None` if `name` is not a valid mangled name.
+    pub fn demangle(name: &ExternalName) -> Option<Self> {
+        let bytes = match *name {
+            ExternalName::Name(ref bytes) => bytes.as_slice(),
+            _ => return None,
+        };
+        let mut rest = if bytes.starts_with(b"__ZN") {
+            &bytes[4..]
+        } else if bytes.starts_with(b"_ZN") {
+            &bytes[3..]
+        } else {
+            return None;
+        };
+
+        let mut components = Vec::new();
+        loop {
+            match rest.first() {
+                Some(&b'E') if rest.len() == 1 => break,
+                Some(b) if b.is_ascii_digit() => {}
+                _ => return None,
+            }
+            let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+            let len: usize = ::std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+            rest = &rest[digits..];
+            if len == 0 || len > rest.len() {
+                return None;
+            }
+            components.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+
+        let hash = match components.last() {
+            Some(text) if is_hash(text) => {
+                let hex = ::std::str::from_utf8(&text[1..]).ok()?;
+                Some(u64::from_str_radix(hex, 16).ok()?)
+            }
+            _ => None,
+        };
+        if hash.is_some() {
+            components.pop();
+        }
+        let mut path = components
+            .into_iter()
+            .map(unescape)
+            .collect::<Option<Vec<_>>>()?;
+        let item = path.pop()?;
+        Some(Self { path, item, hash })
+    }
+}
+
+impl fmt::Display for SymbolName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for component in &self.path {
+            write!(f, "{}::", component)?;
+        }
+        f.write_str(&self.item)?;
+        match self.hash {
+            Some(hash) if !f.alternate() => write!(f, "::h{:016x}", hash),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Compute a hash of the function signature `sig` for a `SymbolName`.
+///
+/// The hash is computed from the text of the signature with a fixed hash function, so it is the
+/// same in every run. It should be computed from the signature the frontend declared, since
+/// legalization changes it.
+pub fn signature_hash(sig: &Signature) -> u64 {
+    fnv1a(sig.to_string().as_bytes())
+}
+
+/// Compute the 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Is `text` the text of a hash component?
+fn is_hash(text: &[u8]) -> bool {
+    text.len() == 17 && text[0] == b'h' &&
+        text[1..].iter().all(|&b| b.is_ascii_digit() || b'a' <= b && b <= b'f')
+}
+
+/// Append the component `text` to `s`, escaping `_` and the bytes that aren't letters or digits.
+///
+/// When `has_hash` is false, an item that would look like a hash has its `h` escaped.
+fn push_component(s: &mut String, text: &str, has_hash: bool) {
+    let mut escaped = String::new();
+    for (i, &byte) in text.as_bytes().iter().enumerate() {
+        let escape = match byte {
+            b'_' => {
+                escaped.push_str("__");
+                continue;
+            }
+            b'h' if i == 0 => !has_hash && is_hash(text.as_bytes()),
+            _ if byte.is_ascii_digit() => i == 0,
+            _ => !byte.is_ascii_alphabetic(),
+        };
+        if escape {
+            write!(escaped, "_{:02x}", byte).unwrap();
+        } else {
+            escaped.push(byte as char);
+        }
+    }
+    write!(s, "{}{}", escaped.len(), escaped).unwrap();
+}
+
+/// Undo the escaping of `push_component`.
+fn unescape(text: &[u8]) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.iter();
+    while let Some(&byte) = iter.next() {
+        if byte != b'_' {
+            bytes.push(byte);
+            continue;
+        }
+        match iter.next() {
+            Some(&b'_') => bytes.push(b'_'),
+            Some(&hi) => {
+                let lo = *iter.next()?;
+                let hex = [hi, lo];
+                let hex = ::std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            None => return None,
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::{AbiParam, CallConv};
+    use ir::types::{I32, I64};
+
+    fn mangled(name: &SymbolName, format: SymbolFormat) -> String {
+        match name.mangle(format) {
+            ExternalName::Name(bytes) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("not a symbol name"),
+        }
+    }
+
+    #[test]
+    fn mangle() {
+        let name = SymbolName::new(vec!["core", "mem"], "swap");
+        assert_eq!(mangled(&name, SymbolFormat::Elf), "_ZN4core3mem4swapE");
+        assert_eq!(mangled(&name, SymbolFormat::MachO), "__ZN4core3mem4swapE");
+        assert_eq!(mangled(&name, SymbolFormat::Coff), "_ZN4core3mem4swapE");
+        assert_eq!(name.to_string(), "core::mem::swap");
+
+        let name = SymbolName {
+            hash: Some(0x0123_4567_89ab_cdef),
+            ..SymbolName::new(Vec::<String>::new(), "main")
+        };
+        assert_eq!(
+            mangled(&name, SymbolFormat::Elf),
+            "_ZN4main17h0123456789abcdefE"
+        );
+        assert_eq!(name.to_string(), "main::h0123456789abcdef");
+        assert_eq!(format!("{:#}", name), "main");
+    }
+
+    #[test]
+    fn escapes() {
+        let name = SymbolName::new(vec!["my_mod", "9lives"], "<T as Fn>::call");
+        assert_eq!(
+            mangled(&name, SymbolFormat::Elf),
+            "_ZN7my__mod8_39lives27_3cT_20as_20Fn_3e_3a_3acallE"
+        );
+        assert_eq!(SymbolName::demangle(&name.mangle(SymbolFormat::Elf)), Some(name));
+
+        // An item that looks like a hash.
+        let name = SymbolName::new(vec!["m"], "h0123456789abcdef");
+        assert_eq!(
+            mangled(&name, SymbolFormat::Elf),
+            "_ZN1m19_680123456789abcdefE"
+        );
+        assert_eq!(SymbolName::demangle(&name.mangle(SymbolFormat::Elf)), Some(name));
+    }
+
+    #[test]
+    fn demangle() {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I64));
+        let name = SymbolName::new(vec!["a", "b"], "f").with_signature(&sig);
+        assert_eq!(name.hash, Some(signature_hash(&sig)));
+        for &format in &[SymbolFormat::Elf, SymbolFormat::MachO, SymbolFormat::Coff] {
+            assert_eq!(SymbolName::demangle(&name.mangle(format)), Some(name.clone()));
+        }
+
+        let bad = |s: &str| SymbolName::demangle(&ExternalName::name(s));
+        assert_eq!(bad("_ZN3fooE"), Some(SymbolName::new(Vec::<String>::new(), "foo")));
+        assert_eq!(bad("_ZNE"), None);
+        assert_eq!(bad("_ZN3fo"), None);
+        assert_eq!(bad("_ZN3fooEx"), None);
+        assert_eq!(bad("_ZN3fo_E"), None);
+        assert_eq!(bad("_ZN3f_zE"), None);
+        assert_eq!(bad("foo"), None);
+        assert_eq!(SymbolName::demangle(&ExternalName::testcase("_ZN3fooE")), None);
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+        let sig = Signature::new(CallConv::Native);
+        assert_eq!(signature_hash(&sig), fnv1a(b"() native"));
+    }
+}
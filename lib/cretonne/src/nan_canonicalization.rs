@@ -0,0 +1,185 @@
+//! NaN canonicalization.
+//!
+//! The bit pattern of a NaN computed by a floating point instruction depends on the target: Intel
+//! CPUs produce a negative quiet NaN where ARM CPUs produce a positive one, and they propagate the
+//! payloads of NaN operands differently. When the `enable_nan_canonicalization` setting is
+//! enabled, the NaN results of arithmetic instructions are replaced with the canonical quiet NaN,
+//! so the results are bit-identical on all targets.
+//!
+//! The pass runs after legalization so it also sees the arithmetic instructions and library calls
+//! that the legalizer introduced when expanding other instructions. The inserted code is then
+//! legalized in turn.
+
+use cursor::{Cursor, FuncCursor};
+use flowgraph::ControlFlowGraph;
+use ir::{ExternalName, Function, Inst, InstBuilder, InstructionData, Opcode, Type, Value};
+use ir::condcodes::FloatCC;
+use ir::immediates::{Ieee32, Ieee64};
+use ir::types::{F32, F64};
+use isa::TargetIsa;
+use legalizer::legalize_new_insts;
+use result::CtonResult;
+use std::vec::Vec;
+use timing;
+
+/// The bits of the canonical `f32` NaN.
+const CANON_NAN_F32: u32 = 0x7fc0_0000;
+
+/// The bits of the canonical `f64` NaN.
+const CANON_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// Can the results of `inst` be NaNs that aren't canonical?
+///
+/// The sign manipulating instructions `fneg`, `fabs`, and `fcopysign` are not included, since
+/// they only change the sign bit of their operand.
+fn computes_nans(func: &Function, inst: Inst) -> bool {
+    match func.dfg[inst] {
+        InstructionData::Call { func_ref, .. } => {
+            match func.dfg.ext_funcs[func_ref].name {
+                ExternalName::LibCall(_) => true,
+                _ => false,
+            }
+        }
+        ref data => {
+            match data.opcode() {
+                Opcode::Fadd | Opcode::Fsub | Opcode::Fmul | Opcode::Fdiv | Opcode::Sqrt |
+                Opcode::Fma | Opcode::Fmin | Opcode::Fmax | Opcode::Ceil | Opcode::Floor |
+                Opcode::Trunc | Opcode::Nearest | Opcode::Fpromote | Opcode::Fdemote => true,
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Replace the value `result` of type `ty` by the canonical NaN if it is a NaN.
+///
+/// The instruction defining `result` gets a new result, and `result` is redefined by a `select`
+/// after it, so the uses of `result` don't need to be rewritten.
+///
+/// The results of calls are tied to the ABI locations of the legalized signature, so a call keeps
+/// its result and the uses of `result` are rewritten to use the `select` instead.
+fn canonicalize(func: &mut Function, inst: Inst, result: Value, ty: Type) {
+    let is_call = func.dfg[inst].opcode().is_call();
+    let mut pos = FuncCursor::new(func).after_inst(inst);
+    pos.use_srcloc(inst);
+    let raw = if is_call {
+        result
+    } else {
+        pos.func.dfg.replace_result(result, ty)
+    };
+    let is_nan = pos.ins().fcmp(FloatCC::Unordered, raw, raw);
+    let nan = if ty == F32 {
+        pos.ins().f32const(Ieee32::with_bits(CANON_NAN_F32))
+    } else {
+        pos.ins().f64const(Ieee64::with_bits(CANON_NAN_F64))
+    };
+    if !is_call {
+        pos.ins().with_result(result).select(is_nan, nan, raw);
+        return;
+    }
+
+    let canon = pos.ins().select(is_nan, nan, raw);
+    let checks = [
+        pos.func.dfg.value_def(is_nan).unwrap_inst(),
+        pos.func.dfg.value_def(canon).unwrap_inst(),
+    ];
+    let func = pos.func;
+    for ebb in func.layout.ebbs() {
+        for user in func.layout.ebb_insts(ebb) {
+            if checks.contains(&user) {
+                continue;
+            }
+            for arg in func.dfg.inst_args_mut(user) {
+                if *arg == result {
+                    *arg = canon;
+                }
+            }
+        }
+    }
+}
+
+/// Canonicalize the NaN results of the floating point arithmetic in the legalized `func`.
+pub fn canonicalize_nans(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    isa: &TargetIsa,
+) -> CtonResult {
+    let _tt = timing::canonicalize_nans();
+    let mut results = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if computes_nans(func, inst) {
+                for &result in func.dfg.inst_results(inst) {
+                    let ty = func.dfg.value_type(result);
+                    if ty == F32 || ty == F64 {
+                        results.push((inst, result, ty));
+                    }
+                }
+            }
+        }
+    }
+
+    if results.is_empty() {
+        return Ok(());
+    }
+    for (inst, result, ty) in results {
+        canonicalize(func, inst, result, ty);
+    }
+    legalize_new_insts(func, cfg, isa)
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use ir::AbiParam;
+    use isa;
+    use settings::{self, Configurable};
+
+    fn compile(enable: bool) -> Context {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        if enable {
+            flag_builder.enable("enable_nan_canonicalization").unwrap();
+        }
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut ctx = Context::new();
+        ctx.func.signature.params.push(AbiParam::new(F64));
+        ctx.func.signature.params.push(AbiParam::new(F64));
+        ctx.func.signature.returns.push(AbiParam::new(F64));
+        let ebb0 = ctx.func.dfg.make_ebb();
+        let x = ctx.func.dfg.append_ebb_param(ebb0, F64);
+        let y = ctx.func.dfg.append_ebb_param(ebb0, F64);
+        {
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let sum = pos.ins().fadd(x, y);
+            let neg = pos.ins().fneg(sum);
+            pos.ins().return_(&[neg]);
+        }
+        ctx.compile(&*isa).unwrap();
+        ctx
+    }
+
+    fn count(func: &Function, opcode: Opcode) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == opcode)
+            .count()
+    }
+
+    #[test]
+    fn fadd() {
+        // Only the `fadd` result is checked, and the `select` is legalized into branches.
+        let ctx = compile(true);
+        assert_eq!(count(&ctx.func, Opcode::Fcmp), 1);
+        assert_eq!(count(&ctx.func, Opcode::Select), 0);
+        assert!(ctx.func.layout.ebbs().count() > 1);
+
+        let ctx = compile(false);
+        assert_eq!(count(&ctx.func, Opcode::Fcmp), 0);
+        assert_eq!(ctx.func.layout.ebbs().count(), 1);
+    }
+}
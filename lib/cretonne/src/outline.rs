@@ -0,0 +1,417 @@
+//! Outlining of repeated instruction sequences.
+//!
+//! Frontends that expand the same operations inline produce functions containing many copies of
+//! identical instruction sequences. When optimizing for size, it pays off to move such a sequence
+//! into a helper function and replace each copy with a call. This module finds sequences of
+//! consecutive pure instructions that appear several times in a function, or in a set of
+//! functions compiled together, and outlines the sequences where the calls take up less space than
+//! the instructions they replace.
+//!
+//! The helper functions are returned to the embedder which chooses their names and is responsible
+//! for compiling them. Their signatures use the native calling convention and are not legalized,
+//! so outlining must happen before legalization.
+
+use cursor::{Cursor, FuncCursor};
+use entity::{EntityMap, EntityRef};
+use ir::instructions::InstructionFormat;
+use ir::{AbiParam, CallConv, DataFlowGraph, ExtFuncData, ExternalName, Function, Inst,
+         InstBuilder, InstructionData, Signature, Type, Value, ValueListPool};
+use std::collections::HashMap;
+use std::vec::Vec;
+use timing;
+
+/// Minimum number of instructions in an outlined sequence.
+pub const MIN_LEN: usize = 3;
+
+/// Maximum number of instructions in an outlined sequence.
+pub const MAX_LEN: usize = 16;
+
+/// Can `inst` be moved into a helper function?
+fn is_outlinable(dfg: &DataFlowGraph, inst: Inst) -> bool {
+    let data = &dfg[inst];
+    let opcode = data.opcode();
+    if opcode.is_call() || opcode.is_branch() || opcode.is_terminator() || opcode.can_trap() ||
+        opcode.other_side_effects() || opcode.can_load() || opcode.can_store() ||
+        opcode.writes_cpu_flags()
+    {
+        return false;
+    }
+
+    // Only formats without references to other entities in the function can be copied verbatim.
+    match InstructionFormat::from(data) {
+        InstructionFormat::Unary |
+        InstructionFormat::UnaryImm |
+        InstructionFormat::UnaryIeee32 |
+        InstructionFormat::UnaryIeee64 |
+        InstructionFormat::UnaryBool |
+        InstructionFormat::Binary |
+        InstructionFormat::BinaryImm |
+        InstructionFormat::Ternary |
+        InstructionFormat::InsertLane |
+        InstructionFormat::ExtractLane |
+        InstructionFormat::IntCompare |
+        InstructionFormat::IntCompareImm |
+        InstructionFormat::FloatCompare => {}
+        _ => return false,
+    }
+
+    // CPU flags can't be passed to or returned from a function.
+    dfg.inst_args(inst)
+        .iter()
+        .chain(dfg.inst_results(inst))
+        .all(|&v| !dfg.value_type(v).is_flags())
+}
+
+/// An instruction argument in a sequence, relative to the start of the sequence.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    /// Parameter number `n` of the helper function.
+    Input(usize),
+    /// Result number `n` of the instructions in the sequence.
+    Result(usize),
+}
+
+/// The shape of a sequence of instructions, independent of the values it operates on.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Shape {
+    /// The instructions and their controlling type variables, with zeroed arguments.
+    insts: Vec<(InstructionData, Type)>,
+    /// The arguments of all the instructions, in order.
+    operands: Vec<Operand>,
+    /// The types of the helper function parameters.
+    inputs: Vec<Type>,
+}
+
+/// A copy of a sequence in one of the functions.
+struct Occurrence {
+    func: usize,
+    insts: Vec<Inst>,
+    inputs: Vec<Value>,
+    results: Vec<Value>,
+}
+
+/// A sequence with all its non-overlapping occurrences.
+struct Candidate {
+    shape: Shape,
+    occurrences: Vec<Occurrence>,
+    /// Results that are used after the sequence in any of the occurrences.
+    live_out: Vec<bool>,
+    /// Run number and index of the last instruction of the last occurrence, for overlap checks.
+    last_end: (usize, usize),
+}
+
+impl Candidate {
+    /// Get the number of instructions saved by outlining this candidate.
+    fn benefit(&self) -> isize {
+        let len = self.shape.insts.len() as isize;
+        let count = self.occurrences.len() as isize;
+        // Each occurrence is replaced by a call, and the helper also needs a return.
+        count * (len - 1) - (len + 1)
+    }
+}
+
+/// Count the uses of every value in `func`.
+fn count_uses(func: &Function) -> EntityMap<Value, u32> {
+    let mut uses = EntityMap::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// Find all the sequences of outlinable instructions in `funcs` that occur more than once.
+fn find_candidates(funcs: &[Function]) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut index = HashMap::new();
+    // The outlinable formats don't use value lists.
+    let mut no_lists = ValueListPool::new();
+    let mut run_number = 0;
+
+    for (fidx, func) in funcs.iter().enumerate() {
+        let dfg = &func.dfg;
+        let uses = count_uses(func);
+        for ebb in func.layout.ebbs() {
+            let mut insts = func.layout.ebb_insts(ebb).peekable();
+            while insts.peek().is_some() {
+                let run: Vec<Inst> = insts
+                    .by_ref()
+                    .skip_while(|&inst| !is_outlinable(dfg, inst))
+                    .take_while(|&inst| is_outlinable(dfg, inst))
+                    .collect();
+                run_number += 1;
+
+                for start in 0..run.len() {
+                    let mut shape = Shape {
+                        insts: Vec::new(),
+                        operands: Vec::new(),
+                        inputs: Vec::new(),
+                    };
+                    let mut inputs = Vec::new();
+                    let mut results = Vec::new();
+                    let mut internal_uses = Vec::new();
+
+                    for (end, &inst) in run.iter().enumerate().skip(start).take(MAX_LEN) {
+                        for &arg in dfg.inst_args(inst) {
+                            let arg = dfg.resolve_aliases(arg);
+                            let operand = if let Some(n) = results.iter().position(|&v| v == arg) {
+                                internal_uses[n] += 1;
+                                Operand::Result(n)
+                            } else if let Some(n) = inputs.iter().position(|&v| v == arg) {
+                                Operand::Input(n)
+                            } else {
+                                inputs.push(arg);
+                                shape.inputs.push(dfg.value_type(arg));
+                                Operand::Input(inputs.len() - 1)
+                            };
+                            shape.operands.push(operand);
+                        }
+                        let mut data = dfg[inst].clone();
+                        for arg in data.arguments_mut(&mut no_lists) {
+                            *arg = Value::new(0);
+                        }
+                        shape.insts.push((data, dfg.ctrl_typevar(inst)));
+                        for &result in dfg.inst_results(inst) {
+                            results.push(result);
+                            internal_uses.push(0);
+                        }
+
+                        if end + 1 - start < MIN_LEN {
+                            continue;
+                        }
+                        let cidx = *index.entry(shape.clone()).or_insert_with(|| {
+                            candidates.push(Candidate {
+                                shape: shape.clone(),
+                                occurrences: Vec::new(),
+                                live_out: vec![false; results.len()],
+                                last_end: (0, 0),
+                            });
+                            candidates.len() - 1
+                        });
+                        let cand = &mut candidates[cidx];
+                        if cand.last_end.0 == run_number && cand.last_end.1 >= start {
+                            continue;
+                        }
+                        for (n, &result) in results.iter().enumerate() {
+                            if uses[result] > internal_uses[n] {
+                                cand.live_out[n] = true;
+                            }
+                        }
+                        cand.occurrences.push(Occurrence {
+                            func: fidx,
+                            insts: run[start..end + 1].to_vec(),
+                            inputs: inputs.clone(),
+                            results: results.clone(),
+                        });
+                        cand.last_end = (run_number, end);
+                    }
+                }
+            }
+        }
+    }
+
+    candidates
+}
+
+/// Create the helper function for `cand` and replace all its occurrences with calls.
+fn outline(funcs: &mut [Function], cand: &Candidate, name: ExternalName) -> Function {
+    let first = &cand.occurrences[0];
+    let mut sig = Signature::new(CallConv::Native);
+    sig.params.extend(cand.shape.inputs.iter().map(|&ty| AbiParam::new(ty)));
+    sig.returns.extend(
+        first
+            .results
+            .iter()
+            .zip(&cand.live_out)
+            .filter(|&(_, &live)| live)
+            .map(|(&v, _)| AbiParam::new(funcs[first.func].dfg.value_type(v))),
+    );
+
+    let mut helper = Function::with_name_signature(name.clone(), sig.clone());
+    let ebb = helper.dfg.make_ebb();
+    let params: Vec<Value> = cand.shape
+        .inputs
+        .iter()
+        .map(|&ty| helper.dfg.append_ebb_param(ebb, ty))
+        .collect();
+    {
+        let mut pos = FuncCursor::new(&mut helper);
+        pos.insert_ebb(ebb);
+        let mut results = Vec::new();
+        let mut operands = cand.shape.operands.iter();
+        for &(ref data, ctrl_typevar) in &cand.shape.insts {
+            let mut data = data.clone();
+            for (arg, &operand) in data.arguments_mut(&mut pos.func.dfg.value_lists).iter_mut().zip(
+                &mut operands,
+            )
+            {
+                *arg = match operand {
+                    Operand::Input(n) => params[n],
+                    Operand::Result(n) => results[n],
+                };
+            }
+            let inst = pos.func.dfg.make_inst(data);
+            pos.func.dfg.make_inst_results(inst, ctrl_typevar);
+            pos.insert_inst(inst);
+            results.extend_from_slice(pos.func.dfg.inst_results(inst));
+        }
+        let outputs: Vec<Value> = results
+            .iter()
+            .zip(&cand.live_out)
+            .filter(|&(_, &live)| live)
+            .map(|(&v, _)| v)
+            .collect();
+        pos.ins().return_(&outputs);
+    }
+
+    let mut fnrefs = HashMap::new();
+    for occ in &cand.occurrences {
+        let func = &mut funcs[occ.func];
+        let fnref = *fnrefs.entry(occ.func).or_insert_with(|| {
+            let signature = func.import_signature(sig.clone());
+            func.import_function(ExtFuncData {
+                name: name.clone(),
+                signature,
+                colocated: false,
+            })
+        });
+        let call = {
+            let mut pos = FuncCursor::new(func).at_inst(occ.insts[0]);
+            pos.use_srcloc(occ.insts[0]);
+            pos.ins().call(fnref, &occ.inputs)
+        };
+        let mut outputs = func.dfg.inst_results(call).to_vec().into_iter();
+        for &inst in &occ.insts {
+            func.dfg.clear_results(inst);
+            func.layout.remove_inst(inst);
+        }
+        for (&result, &live) in occ.results.iter().zip(&cand.live_out) {
+            if live {
+                func.dfg.change_to_alias(result, outputs.next().unwrap());
+            }
+        }
+    }
+
+    helper
+}
+
+/// Outline repeated instruction sequences in `funcs` into helper functions.
+///
+/// The function `names` is called with the index of each new helper function to get its name.
+/// Returns the helper functions that are called from the rewritten `funcs`.
+pub fn outline_sequences(
+    funcs: &mut [Function],
+    names: &mut FnMut(usize) -> ExternalName,
+) -> Vec<Function> {
+    let _tt = timing::outline();
+    let mut helpers = Vec::new();
+    loop {
+        // Outline the most profitable candidate first. Ties are broken by the order of the first
+        // occurrences to keep the result deterministic.
+        let best = find_candidates(funcs).into_iter().fold(
+            None,
+            |best: Option<Candidate>, cand| match best {
+                Some(ref b) if b.benefit() >= cand.benefit() => best,
+                _ => Some(cand),
+            },
+        );
+        match best {
+            Some(ref cand) if cand.benefit() > 0 => {
+                let name = names(helpers.len());
+                helpers.push(outline(funcs, cand, name));
+            }
+            _ => return helpers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::I32;
+    use settings;
+    use std::string::ToString;
+    use verifier::verify_function;
+
+    /// Create a function computing `((x + y) * x) ^ y` twice from different parameters.
+    fn repeated() -> Function {
+        let mut func = Function::new();
+        for _ in 0..4 {
+            func.signature.params.push(AbiParam::new(I32));
+        }
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let p: Vec<Value> = (0..4)
+            .map(|_| func.dfg.append_ebb_param(ebb0, I32))
+            .collect();
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let mut sums = Vec::new();
+        for pair in p.chunks(2) {
+            let v0 = pos.ins().iadd(pair[0], pair[1]);
+            let v1 = pos.ins().imul(v0, pair[0]);
+            let v2 = pos.ins().bxor(v1, pair[1]);
+            sums.push(pos.ins().iadd_imm(v2, 1));
+        }
+        let sum = pos.ins().iadd(sums[0], sums[1]);
+        pos.ins().return_(&[sum]);
+        func
+    }
+
+    #[test]
+    fn outline_twice() {
+        let mut funcs = vec![repeated()];
+        let helpers = outline_sequences(&mut funcs, &mut |n| ExternalName::user(1, n as u32));
+        assert_eq!(helpers.len(), 1);
+
+        let flags = settings::Flags::new(&settings::builder());
+        let helper = &helpers[0];
+        assert_eq!(helper.signature.to_string(), "(i32, i32) -> i32 native");
+        let ebb = helper.layout.entry_block().unwrap();
+        assert_eq!(helper.layout.ebb_insts(ebb).count(), 5);
+        verify_function(helper, &flags).unwrap();
+
+        let func = &funcs[0];
+        verify_function(func, &flags).unwrap();
+        let ebb = func.layout.entry_block().unwrap();
+        let insts: Vec<_> = func.layout
+            .ebb_insts(ebb)
+            .map(|inst| func.dfg.display_inst(inst, None).to_string())
+            .collect();
+        assert_eq!(insts[0], "v13 = call fn0(v0, v1)");
+        assert_eq!(insts[1], "v14 = call fn0(v2, v3)");
+        assert_eq!(insts.len(), 4);
+    }
+
+    #[test]
+    fn too_short() {
+        // A sequence of three instructions must occur three times to be worth outlining.
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            for _ in 0..2 {
+                let v0 = pos.ins().iadd_imm(x, 1);
+                let v1 = pos.ins().imul(v0, x);
+                pos.ins().bxor(v1, x);
+            }
+            pos.ins().return_(&[]);
+        }
+        let mut funcs = vec![func.clone(), func];
+        let helpers = outline_sequences(&mut funcs, &mut |n| ExternalName::user(1, n as u32));
+        // Two functions with two occurrences each make it worthwhile.
+        assert_eq!(helpers.len(), 1);
+        let ebb = funcs[1].layout.entry_block().unwrap();
+        assert_eq!(funcs[1].layout.ebb_insts(ebb).count(), 3);
+
+        let mut funcs = vec![funcs[0].clone()];
+        let helpers = outline_sequences(&mut funcs, &mut |n| ExternalName::user(1, n as u32));
+        assert!(helpers.is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+//! Loop peeling.
+//!
+//! This pass peels the first iteration off loops that test a condition computed from values
+//! defined outside the loop. Such tests are common in code translated from managed languages,
+//! where a loop body checks if something has been lazily initialized:
+//!
+//! ```cton
+//! ebb1(v1: i32):
+//!     v2 = load.i32 v0
+//!     brz v2, ebb2
+//!     jump ebb3
+//! ebb2:
+//!     call fn0(v0)
+//!     jump ebb3
+//! ebb3:
+//!     ...
+//!     brnz v5, ebb1(v4)
+//!     jump ebb4
+//! ```
+//!
+//! A copy of the loop body is inserted before the loop, and the branches entering the loop are
+//! redirected to the copy. The copy executes the first iteration and branches to the original
+//! loop header where it would have looped back. The peeled iteration dominates the remaining
+//! loop, so GVN can reuse the values it computes, and LICM gets a natural pre-header.
+//!
+//! Only innermost loops are peeled, and the values defined in a loop can only be used outside the
+//! loop as EBB arguments.
+
+use dominator_tree::DominatorTree;
+use entity::EntityMap;
+use flowgraph::ControlFlowGraph;
+use ir::instructions::BranchInfo;
+use ir::{Ebb, Function, Value, ValueDef, ValueList};
+use loop_analysis::{Loop, LoopAnalysis};
+use packed_option::PackedOption;
+use std::collections::HashSet;
+use std::vec::Vec;
+use timing;
+
+/// The largest loop that is peeled, in instructions.
+const MAX_PEEL_SIZE: usize = 64;
+
+/// Peel the first iteration of innermost loops containing a loop-invariant conditional branch.
+///
+/// The CFG is updated. The dominator tree and the loop analysis must be recomputed.
+pub fn do_peel(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+) {
+    let _tt = timing::peel();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    // Innermost loops are disjoint, so they can all be analyzed up front.
+    let candidates: Vec<Vec<Ebb>> = loop_analysis
+        .loops()
+        .filter(|&lp| {
+            !loop_analysis.loops().any(
+                |other| loop_analysis.loop_parent(other) == Some(lp),
+            )
+        })
+        .filter_map(|lp| analyze_loop(func, domtree, loop_analysis, lp))
+        .collect();
+
+    for ebbs in &candidates {
+        dbg!("Peeling loop at {}", ebbs[0]);
+        peel(func, cfg, ebbs);
+    }
+}
+
+/// Is `value` defined in the loop `lp`?
+fn defined_in_loop(
+    func: &Function,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+    value: Value,
+) -> bool {
+    let ebb = match func.dfg.value_def(value) {
+        ValueDef::Result(inst, _) => func.layout.inst_ebb(inst).expect("Not in layout"),
+        ValueDef::Param(ebb, _) => ebb,
+    };
+    loop_analysis.is_in_loop(ebb, lp)
+}
+
+/// Check if the loop `lp` should be peeled, and return its EBBs in reverse post-order with the
+/// header first.
+fn analyze_loop(
+    func: &Function,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+) -> Option<Vec<Ebb>> {
+    let dfg = &func.dfg;
+
+    // The entry block can't be entered by redirecting branches.
+    if func.layout.entry_block() == Some(loop_analysis.loop_header(lp)) {
+        return None;
+    }
+
+    let ebbs: Vec<Ebb> = domtree
+        .cfg_postorder()
+        .iter()
+        .rev()
+        .cloned()
+        .filter(|&ebb| loop_analysis.is_in_loop(ebb, lp))
+        .collect();
+    debug_assert_eq!(ebbs.first(), Some(&loop_analysis.loop_header(lp)));
+    let size: usize = ebbs.iter().map(|&ebb| func.layout.ebb_insts(ebb).count()).sum();
+    if size > MAX_PEEL_SIZE {
+        return None;
+    }
+
+    // Values computed in the loop from values defined outside the loop. Loads are included since
+    // the point is to find the tests that give the same result after the first iteration.
+    let mut invariant: HashSet<Value> = HashSet::new();
+    let mut invariant_test = false;
+    for &ebb in &ebbs {
+        for inst in func.layout.ebb_insts(ebb) {
+            let opcode = dfg[inst].opcode();
+            if let BranchInfo::Table(_) = dfg[inst].analyze_branch(&dfg.value_lists) {
+                return None;
+            }
+
+            let args = if opcode.is_branch() {
+                dfg.inst_fixed_args(inst)
+            } else {
+                dfg.inst_args(inst)
+            };
+            let mut inside = false;
+            let is_invariant = args.iter().all(|&arg| {
+                let arg = dfg.resolve_aliases(arg);
+                if invariant.contains(&arg) {
+                    inside = true;
+                    true
+                } else {
+                    !defined_in_loop(func, loop_analysis, lp, arg)
+                }
+            });
+            if !is_invariant {
+                continue;
+            }
+
+            if opcode.is_branch() {
+                invariant_test |= inside && !opcode.is_terminator();
+            } else if !(opcode.is_call() || opcode.is_terminator() || opcode.can_store() ||
+                            opcode.other_side_effects())
+            {
+                invariant.extend(dfg.inst_results(inst));
+            }
+        }
+    }
+    if !invariant_test {
+        return None;
+    }
+
+    // The peeled iteration doesn't dominate the code after the loop.
+    for ebb in func.layout.ebbs() {
+        if loop_analysis.is_in_loop(ebb, lp) {
+            continue;
+        }
+        for inst in func.layout.ebb_insts(ebb) {
+            if dfg.inst_args(inst).iter().any(|&arg| {
+                defined_in_loop(func, loop_analysis, lp, dfg.resolve_aliases(arg))
+            })
+            {
+                return None;
+            }
+        }
+    }
+
+    Some(ebbs)
+}
+
+/// Insert a copy of the loop consisting of `ebbs` before its header, and make the branches
+/// entering the loop go to the copy.
+fn peel(func: &mut Function, cfg: &mut ControlFlowGraph, ebbs: &[Ebb]) {
+    let header = ebbs[0];
+    let mut ebb_map = EntityMap::<Ebb, PackedOption<Ebb>>::new();
+    let mut values = EntityMap::<Value, PackedOption<Value>>::new();
+
+    for &ebb in ebbs {
+        let copy = func.dfg.make_ebb();
+        for i in 0..func.dfg.num_ebb_params(ebb) {
+            let param = func.dfg.ebb_params(ebb)[i];
+            let ty = func.dfg.value_type(param);
+            values[param] = func.dfg.append_ebb_param(copy, ty).into();
+        }
+        func.layout.insert_ebb(copy, header);
+        ebb_map[ebb] = copy.into();
+    }
+
+    // Copy the instructions first since a value can be used in an EBB before the EBB defining it
+    // when they are ordered by the layout.
+    let mut copies = Vec::with_capacity(ebbs.len());
+    for &ebb in ebbs {
+        let copy = ebb_map[ebb].unwrap();
+        let mut next = func.layout.first_inst(ebb);
+        while let Some(inst) = next {
+            next = func.layout.next_inst(inst);
+            let mut data = func.dfg[inst].clone();
+            if let Some(list) = data.take_value_list() {
+                let args = list.as_slice(&func.dfg.value_lists).to_vec();
+                let mut copy = ValueList::new();
+                copy.extend(args, &mut func.dfg.value_lists);
+                data.put_value_list(copy);
+            }
+            let new_inst = func.dfg.make_inst(data);
+            let ctrl_typevar = func.dfg.ctrl_typevar(inst);
+            func.dfg.make_inst_results(new_inst, ctrl_typevar);
+            for (&old, &new) in func.dfg.inst_results(inst).iter().zip(
+                func.dfg.inst_results(new_inst),
+            )
+            {
+                values[old] = new.into();
+            }
+
+            func.layout.append_inst(new_inst, copy);
+            func.srclocs[new_inst] = func.srclocs[inst];
+            func.annotations.copy_inst(inst, new_inst);
+            copies.push(new_inst);
+        }
+    }
+
+    // Rewrite the copies to use the copied values. Branches to the header now leave the peeled
+    // iteration.
+    for &inst in &copies {
+        for i in 0..func.dfg.inst_args(inst).len() {
+            let arg = func.dfg.resolve_aliases(func.dfg.inst_args(inst)[i]);
+            func.dfg.inst_args_mut(inst)[i] = values[arg].expand().unwrap_or(arg);
+        }
+        if let Some(dest) = func.dfg[inst].branch_destination_mut() {
+            if *dest != header {
+                *dest = ebb_map[*dest].expand().unwrap_or(*dest);
+            }
+        }
+    }
+
+    let entering: Vec<_> = cfg.pred_iter(header)
+        .filter(|&(pred_ebb, _)| ebb_map[pred_ebb].is_none())
+        .map(|(_, pred_inst)| pred_inst)
+        .collect();
+    for inst in entering {
+        *func.dfg[inst].branch_destination_mut().unwrap() = ebb_map[header].unwrap();
+    }
+
+    cfg.compute(func);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder, MemFlags, Opcode};
+    use settings;
+    use verifier::verify_function;
+
+    /// Build a function with a loop that stores to `v0` if it contains 0.
+    fn lazy_init_loop() -> Function {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let ebb4 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let v1 = func.dfg.append_ebb_param(ebb0, I32);
+        let i = func.dfg.append_ebb_param(ebb1, I32);
+        let res = func.dfg.append_ebb_param(ebb4, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().jump(ebb1, &[v1]);
+
+            pos.insert_ebb(ebb1);
+            let flag = pos.ins().load(I32, MemFlags::new(), v0, 0);
+            pos.ins().brz(flag, ebb2, &[]);
+            pos.ins().jump(ebb3, &[]);
+
+            pos.insert_ebb(ebb2);
+            let one = pos.ins().iconst(I32, 1);
+            pos.ins().store(MemFlags::new(), one, v0, 0);
+            pos.ins().jump(ebb3, &[]);
+
+            pos.insert_ebb(ebb3);
+            let i2 = pos.ins().iadd_imm(i, -1);
+            pos.ins().brnz(i2, ebb1, &[i2]);
+            pos.ins().jump(ebb4, &[i2]);
+
+            pos.insert_ebb(ebb4);
+            pos.ins().return_(&[res]);
+        }
+        func
+    }
+
+    fn run(func: &mut Function) {
+        let mut cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(func, &cfg, &domtree);
+        do_peel(func, &mut cfg, &domtree, &loop_analysis);
+        let flags = settings::Flags::new(&settings::builder());
+        verify_function(func, &flags).unwrap();
+    }
+
+    fn count(func: &Function, opcode: Opcode) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == opcode)
+            .count()
+    }
+
+    #[test]
+    fn lazy_init() {
+        let mut func = lazy_init_loop();
+        run(&mut func);
+        assert_eq!(func.layout.ebbs().count(), 8);
+        assert_eq!(count(&func, Opcode::Load), 2);
+        assert_eq!(count(&func, Opcode::Brnz), 2);
+    }
+
+    #[test]
+    fn variant_test() {
+        // The branch on the induction variable is not an invariant test.
+        let mut func = lazy_init_loop();
+        let load = func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .find(|&inst| func.dfg[inst].opcode() == Opcode::Load)
+            .unwrap();
+        let ebb1 = func.layout.inst_ebb(load).unwrap();
+        let i = func.dfg.ebb_params(ebb1)[0];
+        func.dfg.inst_args_mut(load)[0] = i;
+        run(&mut func);
+        assert_eq!(func.layout.ebbs().count(), 5);
+    }
+}
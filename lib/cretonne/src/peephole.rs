@@ -0,0 +1,225 @@
+//! Peephole optimizations after register allocation.
+//!
+//! Some redundancies only appear once the values have been assigned to registers. This pass scans
+//! the encoded instructions after register allocation and rewrites them:
+//!
+//! - A `regmove` from a register to itself is removed.
+//! - A `copy` whose result is assigned to the location of its argument is removed, and its result
+//!   becomes an alias of the argument.
+//! - An `iadd_imm` computing the address of the following load or store is folded into the offset
+//!   of the memory operand, when the address has no other uses and the memory instruction has an
+//!   encoding for the allocated registers.
+//!
+//! The IL has no arithmetic instructions with memory operands, so loads can't be folded into the
+//! instructions using them. A memory instruction with a folded address gets the smallest encoding
+//! for its new offset, and the `ShrinkInstructions` pass that runs after this one can shrink the
+//! other instructions.
+
+use cursor::{Cursor, FuncCursor};
+use entity::EntityMap;
+use ir::{Function, Inst, InstructionData, Opcode, Value, ValueDef};
+use ir::immediates::Offset32;
+use isa::{EncInfo, TargetIsa};
+use regalloc::RegDiversions;
+use timing;
+
+/// Count the uses of each value in `func`.
+fn count_uses(func: &Function) -> EntityMap<Value, u32> {
+    let mut uses = EntityMap::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// Is `inst` a move that doesn't change the location of its value?
+fn is_self_move(func: &Function, divert: &RegDiversions, inst: Inst) -> bool {
+    match func.dfg[inst] {
+        InstructionData::RegMove { src, dst, .. } => src == dst,
+        InstructionData::Unary { opcode: Opcode::Copy, arg } => {
+            let result = func.dfg.first_result(inst);
+            divert.get(arg, &func.locations) == func.locations[result]
+        }
+        _ => false,
+    }
+}
+
+/// Get the address argument and the offset of the memory instruction `inst`.
+fn memory_operand(func: &Function, inst: Inst) -> Option<(Value, Offset32)> {
+    match func.dfg[inst] {
+        InstructionData::Load { arg, offset, .. } => Some((arg, offset)),
+        InstructionData::Store { args, offset, .. } => Some((args[1], offset)),
+        _ => None,
+    }
+}
+
+/// Set the address argument and the offset of the memory instruction `inst`.
+fn set_memory_operand(func: &mut Function, inst: Inst, addr: Value, new_offset: Offset32) {
+    match func.dfg[inst] {
+        InstructionData::Load {
+            ref mut arg,
+            ref mut offset,
+            ..
+        } => {
+            *arg = addr;
+            *offset = new_offset;
+        }
+        InstructionData::Store {
+            ref mut args,
+            ref mut offset,
+            ..
+        } => {
+            args[1] = addr;
+            *offset = new_offset;
+        }
+        _ => panic!("Not a memory instruction"),
+    }
+}
+
+/// Try to fold the `iadd_imm` computing the address of the memory instruction `inst` into its
+/// offset, and re-encode `inst`.
+///
+/// Returns the `iadd_imm` instruction, which must be removed when the fold succeeds.
+fn fold_address(
+    func: &mut Function,
+    isa: &TargetIsa,
+    encinfo: &EncInfo,
+    divert: &RegDiversions,
+    uses: &EntityMap<Value, u32>,
+    inst: Inst,
+) -> Option<Inst> {
+    let (addr, offset) = memory_operand(func, inst)?;
+    let def = match func.dfg.value_def(addr) {
+        ValueDef::Result(def, _) => def,
+        ValueDef::Param(..) => return None,
+    };
+    // The base of the address is only known to be in the same location right before `inst`.
+    if func.layout.prev_inst(inst) != Some(def) || uses[addr] != 1 {
+        return None;
+    }
+    let (base, imm) = match func.dfg[def] {
+        InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, imm } => (arg, imm),
+        _ => return None,
+    };
+    let imm: i64 = imm.into();
+    let old_offset: i64 = offset.into();
+    let new_offset = old_offset.wrapping_add(imm);
+    if new_offset != i64::from(new_offset as i32) {
+        return None;
+    }
+    set_memory_operand(func, inst, base, Offset32::new(new_offset as i32));
+
+    // Don't clobber flags that could be live across the instruction.
+    let clobbers_flags = encinfo.operand_constraints(func.encodings[inst]).map_or(
+        true,
+        |c| c.clobbers_flags,
+    );
+    let ctrl_type = func.dfg.ctrl_typevar(inst);
+    let new_enc = isa.legal_encodings(&func.dfg, &func.dfg[inst], ctrl_type)
+        .filter(|&e| match encinfo.operand_constraints(e) {
+            Some(c) => (clobbers_flags || !c.clobbers_flags) && c.satisfied(inst, divert, func),
+            None => false,
+        })
+        .min_by_key(|&e| encinfo.bytes(e));
+    match new_enc {
+        Some(enc) => {
+            func.encodings[inst] = enc;
+            Some(def)
+        }
+        None => {
+            set_memory_operand(func, inst, addr, offset);
+            None
+        }
+    }
+}
+
+/// Run the peephole optimizations on `func` after register allocation.
+pub fn do_peephole(func: &mut Function, isa: &TargetIsa) {
+    let _tt = timing::peephole();
+    let encinfo = isa.encoding_info();
+    let uses = count_uses(func);
+    let mut divert = RegDiversions::new();
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        divert.clear();
+        while let Some(inst) = pos.next_inst() {
+            if !pos.func.encodings[inst].is_legal() {
+                continue;
+            }
+
+            if is_self_move(pos.func, &divert, inst) {
+                if pos.func.dfg[inst].opcode() == Opcode::Copy {
+                    let arg = pos.func.dfg.inst_args(inst)[0];
+                    let result = pos.func.dfg.first_result(inst);
+                    pos.func.dfg.clear_results(inst);
+                    pos.func.dfg.change_to_alias(result, arg);
+                }
+                pos.remove_inst_and_step_back();
+                continue;
+            }
+
+            if let Some(def) = fold_address(pos.func, isa, &encinfo, &divert, &uses, inst) {
+                pos.func.layout.remove_inst(def);
+            }
+
+            divert.apply(&pos.func.dfg[inst]);
+        }
+    }
+}
+
+#[cfg(all(test, build_intel))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, InstBuilder, MemFlags};
+    use isa;
+    use pipeline::{BuiltinPass, Pipeline, Stage};
+    use settings::{self, Configurable, OptLevel};
+
+    #[test]
+    fn fold_address() {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        let isa = isa::lookup("intel").unwrap().finish(settings::Flags::new(&flag_builder));
+
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I64));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let addr = pos.ins().iadd_imm(p, 8);
+            let v = pos.ins().load(I32, MemFlags::new(), addr, 4);
+            pos.ins().return_(&[v]);
+        }
+
+        // Only run the peephole pass, so the address isn't folded before register allocation.
+        let mut pipeline = Pipeline::empty();
+        pipeline.push(BuiltinPass::Peephole.into(), Stage::PostRegalloc);
+        let mut ctx = Context::for_function(func);
+        ctx.set_pipeline(OptLevel::Default, Some(pipeline));
+        ctx.compile(&*isa).unwrap();
+
+        let opcodes: Vec<Opcode> = ctx.func
+            .layout
+            .ebb_insts(ebb0)
+            .map(|inst| ctx.func.dfg[inst].opcode())
+            .collect();
+        assert!(!opcodes.contains(&Opcode::IaddImm), "{:?}", opcodes);
+        let load = ctx.func
+            .layout
+            .ebb_insts(ebb0)
+            .find(|&inst| ctx.func.dfg[inst].opcode() == Opcode::Load)
+            .unwrap();
+        assert_eq!(memory_operand(&ctx.func, load), Some((p, Offset32::new(12))));
+    }
+}
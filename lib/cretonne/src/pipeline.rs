@@ -0,0 +1,521 @@
+//! Configurable compilation pipeline.
+//!
+//! `Context::compile` runs the function through a sequence of optimization passes around the
+//! passes that are always needed to generate code: legalization, register allocation, and
+//! prologue/epilogue insertion. A `Pipeline` describes the optimization passes, so embedders can
+//! add, remove, or reorder them and insert their own passes without forking the crate.
+//!
+//! The optimization passes are divided into three stages that run before legalization, between
+//! legalization and register allocation, and after register allocation. Each built-in pass only
+//! works in one of the stages, but custom passes can be inserted in any of them. The custom
+//! passes registered for a `PassPoint` with `Context::add_custom_pass` still run at that point,
+//! independently of the pipeline.
+
+use custom_pass::CustomPass;
+use dominator_tree::DominatorTree;
+use flowgraph::ControlFlowGraph;
+use ir::Function;
+use isa::TargetIsa;
+use result::CtonResult;
+use settings::{Flags, OptLevel};
+use std::boxed::Box;
+use std::fmt;
+use std::vec::Vec;
+
+/// The stages of the pipeline.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Before legalization.
+    ///
+    /// The function may contain instructions that are not legal for the target ISA.
+    PreLegalize,
+
+    /// After legalization, before register allocation.
+    ///
+    /// All instructions are legal and have encodings, and any instructions inserted by a pass
+    /// must be legal and encoded too.
+    PostLegalize,
+
+    /// After register allocation and prologue/epilogue insertion, before branch relaxation.
+    ///
+    /// Passes must keep the instructions encoded and the value locations valid.
+    PostRegalloc,
+}
+
+/// The built-in optimization passes that can be configured in a pipeline.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BuiltinPass {
+    /// Unreachable code elimination. See `Context::eliminate_unreachable_code`.
+    EliminateUnreachableCode,
+
+    /// Pre-legalization rewriting. See `Context::preopt`.
+    Preopt,
+
+    /// Sparse conditional constant propagation. See `Context::sccp`.
+    Sccp,
+
+    /// Redundant extension elimination. See `Context::eliminate_redundant_extends`.
+    EliminateRedundantExtends,
+
+    /// Redundant load elimination. See `Context::eliminate_redundant_loads`.
+    EliminateRedundantLoads,
+
+    /// Dead store elimination. See `Context::eliminate_dead_stores`.
+    EliminateDeadStores,
+
+    /// Conversion of small diamonds into selects. See `Context::convert_diamonds`.
+    ConvertDiamonds,
+
+    /// Loop unrolling. See `Context::unroll_loops`.
+    UnrollLoops,
+
+    /// Loop peeling. See `Context::peel_loops`.
+    PeelLoops,
+
+    /// Fusion of heap bounds checks. See `Context::fuse_heap_checks`.
+    FuseHeapChecks,
+
+    /// Heap bounds check elimination. See `Context::eliminate_heap_checks`.
+    EliminateHeapChecks,
+
+    /// Entry and exit hook insertion. See `Context::insert_entry_exit_hooks`.
+    InsertEntryExitHooks,
+
+    /// Global value numbering. See `Context::simple_gvn`.
+    SimpleGvn,
+
+    /// Loop invariant code motion. See `Context::licm`.
+    ///
+    /// This pass is not in the default pipeline until its known bugs are fixed.
+    Licm,
+
+    /// CFG simplification. See `Context::simplify_cfg`.
+    SimplifyCfg,
+
+    /// Sharing of repeated constants. See `Context::pool_constants`.
+    PoolConstants,
+
+    /// Instruction scheduling. See `Context::schedule_instructions`.
+    ScheduleInstructions,
+
+    /// Peephole optimizations after register allocation. See `Context::peephole`.
+    Peephole,
+
+    /// Instruction encoding shrinking. See `Context::shrink_instructions`.
+    ShrinkInstructions,
+}
+
+impl BuiltinPass {
+    /// Get the stage where this pass runs.
+    pub fn stage(self) -> Stage {
+        match self {
+            BuiltinPass::EliminateUnreachableCode |
+            BuiltinPass::Preopt |
+            BuiltinPass::Sccp |
+            BuiltinPass::EliminateRedundantExtends |
+            BuiltinPass::EliminateRedundantLoads |
+            BuiltinPass::EliminateDeadStores |
+            BuiltinPass::ConvertDiamonds |
+            BuiltinPass::UnrollLoops |
+            BuiltinPass::PeelLoops |
+            BuiltinPass::FuseHeapChecks |
+            BuiltinPass::EliminateHeapChecks |
+            BuiltinPass::InsertEntryExitHooks => Stage::PreLegalize,
+            BuiltinPass::SimpleGvn |
+            BuiltinPass::Licm |
+            BuiltinPass::SimplifyCfg |
+            BuiltinPass::PoolConstants |
+            BuiltinPass::ScheduleInstructions => Stage::PostLegalize,
+            BuiltinPass::Peephole |
+            BuiltinPass::ShrinkInstructions => Stage::PostRegalloc,
+        }
+    }
+}
+
+/// A custom pass made from a function or closure.
+///
+/// The control flow graph and dominator tree are recomputed after the function runs, so it
+/// doesn't need to keep them up to date.
+pub struct FnPass<F> {
+    name: &'static str,
+    func: F,
+}
+
+impl<F> FnPass<F>
+where
+    F: FnMut(&mut Function, &TargetIsa) -> CtonResult,
+{
+    /// Create a pass called `name` that runs `func`.
+    pub fn new(name: &'static str, func: F) -> Self {
+        Self { name, func }
+    }
+}
+
+impl<F> CustomPass for FnPass<F>
+where
+    F: FnMut(&mut Function, &TargetIsa) -> CtonResult,
+{
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn run(
+        &mut self,
+        func: &mut Function,
+        _cfg: &mut ControlFlowGraph,
+        _domtree: &mut DominatorTree,
+        isa: &TargetIsa,
+    ) -> CtonResult {
+        (self.func)(func, isa)
+    }
+}
+
+/// A pass in a pipeline.
+pub enum PipelinePass {
+    /// A built-in optimization pass.
+    Builtin(BuiltinPass),
+
+    /// An embedder-defined pass.
+    Custom(Box<CustomPass>),
+}
+
+impl PipelinePass {
+    /// Create a custom pass called `name` that runs `func`.
+    pub fn from_fn<F>(name: &'static str, func: F) -> Self
+    where
+        F: FnMut(&mut Function, &TargetIsa) -> CtonResult + 'static,
+    {
+        PipelinePass::Custom(Box::new(FnPass::new(name, func)))
+    }
+
+    fn is(&self, pass: BuiltinPass) -> bool {
+        match *self {
+            PipelinePass::Builtin(p) => p == pass,
+            PipelinePass::Custom(_) => false,
+        }
+    }
+}
+
+impl fmt::Debug for PipelinePass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipelinePass::Builtin(pass) => write!(f, "{:?}", pass),
+            PipelinePass::Custom(ref pass) => write!(f, "Custom({})", pass.name()),
+        }
+    }
+}
+
+impl From<BuiltinPass> for PipelinePass {
+    fn from(pass: BuiltinPass) -> Self {
+        PipelinePass::Builtin(pass)
+    }
+}
+
+/// The optimization passes run by `Context::compile`, in order.
+#[derive(Debug)]
+pub struct Pipeline {
+    pre_legalize: Vec<PipelinePass>,
+    post_legalize: Vec<PipelinePass>,
+    post_regalloc: Vec<PipelinePass>,
+}
+
+impl Pipeline {
+    /// Create a pipeline without any optimization passes.
+    pub fn empty() -> Self {
+        Self {
+            pre_legalize: Vec::new(),
+            post_legalize: Vec::new(),
+            post_regalloc: Vec::new(),
+        }
+    }
+
+    /// Create the default pipeline for the opt level and other settings in `flags`.
+    ///
+    /// This is the pipeline used by `Context::compile` when no pipeline has been configured for
+    /// the opt level.
+    pub fn new(flags: &Flags) -> Self {
+        let opt_level = flags.opt_level();
+        let best = opt_level == OptLevel::Best;
+        let smallest = opt_level == OptLevel::Smallest;
+
+        let mut pipeline = Self::empty();
+        let mut add = |pass: BuiltinPass, enabled: bool| if enabled {
+            pipeline.push(pass.into(), pass.stage());
+        };
+        add(BuiltinPass::EliminateUnreachableCode, true);
+        add(BuiltinPass::Preopt, true);
+        add(BuiltinPass::EliminateRedundantExtends, true);
+        add(BuiltinPass::EliminateRedundantLoads, best || smallest);
+        add(BuiltinPass::EliminateDeadStores, best || smallest);
+        add(BuiltinPass::ConvertDiamonds, opt_level != OptLevel::Fastest);
+        add(
+            BuiltinPass::UnrollLoops,
+            flags.unroll_threshold() > 0 && !smallest,
+        );
+        add(BuiltinPass::PeelLoops, best);
+        add(BuiltinPass::FuseHeapChecks, flags.fuse_heap_checks());
+        add(BuiltinPass::EliminateHeapChecks, best);
+        add(
+            BuiltinPass::InsertEntryExitHooks,
+            flags.enable_entry_exit_hooks(),
+        );
+        add(BuiltinPass::SimpleGvn, best || smallest);
+        add(BuiltinPass::SimplifyCfg, true);
+        add(BuiltinPass::PoolConstants, opt_level != OptLevel::Fastest);
+        add(
+            BuiltinPass::ScheduleInstructions,
+            opt_level != OptLevel::Fastest,
+        );
+        add(BuiltinPass::Peephole, opt_level != OptLevel::Fastest);
+        add(
+            BuiltinPass::ShrinkInstructions,
+            smallest || flags.is_compressed(),
+        );
+        pipeline
+    }
+
+    /// Get the passes in `stage`, in the order they run.
+    pub fn passes(&self, stage: Stage) -> &[PipelinePass] {
+        match stage {
+            Stage::PreLegalize => &self.pre_legalize,
+            Stage::PostLegalize => &self.post_legalize,
+            Stage::PostRegalloc => &self.post_regalloc,
+        }
+    }
+
+    /// Get the passes in `stage` for running them.
+    pub(crate) fn passes_mut(&mut self, stage: Stage) -> &mut Vec<PipelinePass> {
+        match stage {
+            Stage::PreLegalize => &mut self.pre_legalize,
+            Stage::PostLegalize => &mut self.post_legalize,
+            Stage::PostRegalloc => &mut self.post_regalloc,
+        }
+    }
+
+    /// Does the pipeline run the built-in `pass`?
+    pub fn contains(&self, pass: BuiltinPass) -> bool {
+        self.passes(pass.stage()).iter().any(|p| p.is(pass))
+    }
+
+    /// Add `pass` at the end of `stage`.
+    ///
+    /// Panics if `pass` is a built-in pass that doesn't run in `stage`.
+    pub fn push(&mut self, pass: PipelinePass, stage: Stage) {
+        check_stage(&pass, stage);
+        self.passes_mut(stage).push(pass);
+    }
+
+    /// Insert `pass` before the built-in pass `before`.
+    ///
+    /// Returns `false` without inserting `pass` if `before` isn't in the pipeline. Panics if `pass`
+    /// is a built-in pass that doesn't run in the same stage as `before`.
+    pub fn insert_before(&mut self, before: BuiltinPass, pass: PipelinePass) -> bool {
+        self.insert_at(before, 0, pass)
+    }
+
+    /// Insert `pass` after the built-in pass `after`.
+    ///
+    /// Returns `false` without inserting `pass` if `after` isn't in the pipeline. Panics if `pass`
+    /// is a built-in pass that doesn't run in the same stage as `after`.
+    pub fn insert_after(&mut self, after: BuiltinPass, pass: PipelinePass) -> bool {
+        self.insert_at(after, 1, pass)
+    }
+
+    fn insert_at(&mut self, anchor: BuiltinPass, delta: usize, pass: PipelinePass) -> bool {
+        let stage = anchor.stage();
+        check_stage(&pass, stage);
+        let passes = self.passes_mut(stage);
+        match passes.iter().position(|p| p.is(anchor)) {
+            Some(idx) => {
+                passes.insert(idx + delta, pass);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove all occurrences of the built-in `pass`.
+    ///
+    /// Returns `true` if the pass was in the pipeline.
+    pub fn remove(&mut self, pass: BuiltinPass) -> bool {
+        let passes = self.passes_mut(pass.stage());
+        let len = passes.len();
+        passes.retain(|p| !p.is(pass));
+        passes.len() != len
+    }
+
+    /// Remove the custom passes called `name`.
+    ///
+    /// Returns `true` if any passes were removed.
+    pub fn remove_custom(&mut self, name: &str) -> bool {
+        let mut removed = false;
+        for &stage in &[Stage::PreLegalize, Stage::PostLegalize, Stage::PostRegalloc] {
+            self.passes_mut(stage).retain(|p| match *p {
+                PipelinePass::Custom(ref pass) if pass.name() == name => {
+                    removed = true;
+                    false
+                }
+                _ => true,
+            });
+        }
+        removed
+    }
+}
+
+/// Panic if `pass` is a built-in pass that doesn't run in `stage`.
+fn check_stage(pass: &PipelinePass, stage: Stage) {
+    if let PipelinePass::Builtin(p) = *pass {
+        assert_eq!(p.stage(), stage, "{:?} can't run in {:?}", p, stage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::{types, AbiParam, InstBuilder, Opcode};
+    use isa;
+    use settings::{self, Configurable};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn flags(opt_level: &str) -> Flags {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        flag_builder.set("opt_level", opt_level).unwrap();
+        Flags::new(&flag_builder)
+    }
+
+    fn names(pipeline: &Pipeline, stage: Stage) -> Vec<String> {
+        pipeline
+            .passes(stage)
+            .iter()
+            .map(|p| format!("{:?}", p))
+            .collect()
+    }
+
+    #[test]
+    fn default_pipelines() {
+        let fastest = Pipeline::new(&flags("fastest"));
+        assert_eq!(
+            names(&fastest, Stage::PreLegalize),
+            [
+                "EliminateUnreachableCode",
+                "Preopt",
+                "EliminateRedundantExtends",
+            ]
+        );
+        assert_eq!(names(&fastest, Stage::PostLegalize), ["SimplifyCfg"]);
+        assert!(fastest.passes(Stage::PostRegalloc).is_empty());
+
+        let smallest = Pipeline::new(&flags("smallest"));
+        assert!(smallest.contains(BuiltinPass::SimpleGvn));
+        assert!(smallest.contains(BuiltinPass::ShrinkInstructions));
+        assert!(!smallest.contains(BuiltinPass::UnrollLoops));
+        assert!(!smallest.contains(BuiltinPass::Licm));
+
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_compressed").unwrap();
+        let compressed = Pipeline::new(&Flags::new(&flag_builder));
+        assert!(compressed.contains(BuiltinPass::ShrinkInstructions));
+    }
+
+    #[test]
+    fn edit() {
+        let mut pipeline = Pipeline::new(&flags("fastest"));
+        assert!(pipeline.insert_after(
+            BuiltinPass::Preopt,
+            PipelinePass::from_fn("peephole", |_, _| Ok(())),
+        ));
+        assert!(pipeline.insert_before(
+            BuiltinPass::SimplifyCfg,
+            BuiltinPass::SimpleGvn.into(),
+        ));
+        assert!(!pipeline.insert_before(
+            BuiltinPass::PeelLoops,
+            BuiltinPass::Sccp.into(),
+        ));
+        assert!(pipeline.remove(BuiltinPass::EliminateRedundantExtends));
+        assert!(!pipeline.remove(BuiltinPass::EliminateRedundantExtends));
+        pipeline.push(
+            PipelinePass::from_fn("late", |_, _| Ok(())),
+            Stage::PostRegalloc,
+        );
+        assert_eq!(
+            names(&pipeline, Stage::PreLegalize),
+            ["EliminateUnreachableCode", "Preopt", "Custom(peephole)"]
+        );
+        assert_eq!(
+            names(&pipeline, Stage::PostLegalize),
+            ["SimpleGvn", "SimplifyCfg"]
+        );
+        assert!(pipeline.remove_custom("late"));
+        assert!(pipeline.passes(Stage::PostRegalloc).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "SimpleGvn can't run in PreLegalize")]
+    fn wrong_stage() {
+        Pipeline::empty().push(BuiltinPass::SimpleGvn.into(), Stage::PreLegalize);
+    }
+
+    #[test]
+    #[cfg(build_intel)]
+    fn compile() {
+        let isa = isa::lookup("intel").unwrap().finish(flags("best"));
+
+        fn build(ctx: &mut Context) {
+            ctx.clear();
+            ctx.func.signature.params.push(AbiParam::new(types::I32));
+            ctx.func.signature.returns.push(AbiParam::new(types::I32));
+            let ebb0 = ctx.func.dfg.make_ebb();
+            let v0 = ctx.func.dfg.append_ebb_param(ebb0, types::I32);
+            let mut pos = FuncCursor::new(&mut ctx.func);
+            pos.insert_ebb(ebb0);
+            let v1 = pos.ins().iadd_imm(v0, 4);
+            pos.ins().return_(&[v1]);
+        }
+        let mut ctx = Context::new();
+
+        // Record the opcodes and encodings seen by a pass between preopt and legalization.
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut pipeline = Pipeline::new(isa.flags());
+        let seen2 = seen.clone();
+        pipeline.insert_after(
+            BuiltinPass::Preopt,
+            PipelinePass::from_fn("record", move |func, _| {
+                let mut pos = FuncCursor::new(func);
+                while let Some(_ebb) = pos.next_ebb() {
+                    while let Some(inst) = pos.next_inst() {
+                        let opcode = pos.func.dfg[inst].opcode();
+                        let encoded = pos.func.encodings[inst].is_legal();
+                        seen2.borrow_mut().push((opcode, encoded));
+                    }
+                }
+                Ok(())
+            }),
+        );
+        assert!(ctx.set_pipeline(OptLevel::Best, Some(pipeline)).is_none());
+        build(&mut ctx);
+        ctx.compile(&*isa).unwrap();
+        assert_eq!(
+            *seen.borrow(),
+            [(Opcode::IaddImm, false), (Opcode::Return, false)]
+        );
+
+        // The pipeline is kept for the next function.
+        seen.borrow_mut().clear();
+        build(&mut ctx);
+        ctx.compile(&*isa).unwrap();
+        assert_eq!(seen.borrow().len(), 2);
+
+        // A pipeline for another opt level isn't used.
+        seen.borrow_mut().clear();
+        let pipeline = ctx.set_pipeline(OptLevel::Best, None).unwrap();
+        ctx.set_pipeline(OptLevel::Fastest, Some(pipeline));
+        build(&mut ctx);
+        ctx.compile(&*isa).unwrap();
+        assert!(seen.borrow().is_empty());
+    }
+}
@@ -9,6 +9,8 @@
 //! Some of these predicates may be unused in certain ISA configurations, so we suppress the
 //! dead code warning.
 
+use ir;
+
 /// Check that `x` is the same as `y`.
 #[allow(dead_code)]
 pub fn is_equal<T: Eq + Copy, O: Into<T> + Copy>(x: T, y: O) -> bool {
@@ -31,6 +33,18 @@ pub fn is_unsigned_int<T: Into<i64>>(x: T, wd: u8, sc: u8) -> bool {
     u == (u & m)
 }
 
+/// Check that `func_ref` refers to a function that is colocated with the current one.
+#[allow(dead_code)]
+pub fn is_colocated_func(func_ref: ir::FuncRef, dfg: &ir::DataFlowGraph) -> bool {
+    dfg.ext_funcs[func_ref].colocated
+}
+
+/// Check that `flags` has the `aligned` flag set.
+#[allow(dead_code)]
+pub fn is_aligned(flags: ir::MemFlags) -> bool {
+    flags.aligned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
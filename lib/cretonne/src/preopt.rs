@@ -6,10 +6,14 @@ use cursor::{Cursor, FuncCursor};
 use ir::dfg::ValueDef;
 use ir::{Function, InstructionData, Value, DataFlowGraph, InstBuilder, Type};
 use ir::Inst;
-use ir::types::{I32, I64};
+use ir::types::{I8, I16, I32, I64};
+use ir::immediates::Imm64;
 use ir::instructions::Opcode;
 use divconst_magic_numbers::{MU32, MU64, MS32, MS64};
 use divconst_magic_numbers::{magicU32, magicU64, magicS32, magicS64};
+use fold::fold_inst;
+use rewrite::rewrite_inst;
+use settings::OptLevel;
 use timing;
 
 
@@ -152,6 +156,61 @@ fn get_div_info(inst: Inst, dfg: &DataFlowGraph) -> Option<DivRemByConstInfo> {
     None
 }
 
+// Division and remainder of 8- and 16-bit integers by a constant are done
+// on 32-bit integers. If `inst` is such an operation, extend the dividend to
+// 32 bits, insert a 32-bit `*_imm` operation before `inst`, and replace
+// `inst` with an `ireduce` of its result. The new 32-bit instruction is
+// returned so it can be transformed in turn. The 32-bit operation traps on
+// the same dividends: division by zero always traps, and for signed
+// division by -1 the dividend is shifted into the high bits so that only
+// the smallest integer of the narrow type overflows.
+fn widen_narrow_divrem(pos: &mut FuncCursor, inst: Inst) -> Option<Inst> {
+    let (opcode, argL, argRs) = match pos.func.dfg[inst] {
+        InstructionData::BinaryImm { opcode, arg, imm } => (opcode, arg, imm.into()),
+        InstructionData::Binary { opcode, args } => {
+            (opcode, args[0], get_const(args[1], &pos.func.dfg)?)
+        }
+        _ => return None,
+    };
+    let (isSigned, wideOpcode) = match opcode {
+        Opcode::Udiv | Opcode::UdivImm => (false, Opcode::UdivImm),
+        Opcode::Urem | Opcode::UremImm => (false, Opcode::UremImm),
+        Opcode::Sdiv | Opcode::SdivImm => (true, Opcode::SdivImm),
+        Opcode::Srem | Opcode::SremImm => (true, Opcode::SremImm),
+        _other => return None,
+    };
+    let argL_ty = pos.func.dfg.value_type(argL);
+    if argL_ty != I8 && argL_ty != I16 {
+        return None;
+    }
+
+    // Truncate the divisor to the operation size and extend it back.
+    let shift = 64 - argL_ty.bits();
+    let d = if isSigned {
+        argRs << shift >> shift
+    } else {
+        ((argRs as u64) << shift >> shift) as i64
+    };
+    let highBits = wideOpcode == Opcode::SdivImm && d == -1;
+    let highShift = i64::from(32 - argL_ty.bits());
+
+    let mut wideL = if isSigned {
+        pos.ins().sextend(I32, argL)
+    } else {
+        pos.ins().uextend(I32, argL)
+    };
+    if highBits {
+        wideL = pos.ins().ishl_imm(wideL, highShift);
+    }
+    let (wideInst, dfg) = pos.ins().BinaryImm(wideOpcode, I32, Imm64::new(d), wideL);
+    let mut wideResult = dfg.first_result(wideInst);
+    if highBits {
+        wideResult = pos.ins().sshr_imm(wideResult, highShift);
+    }
+    pos.func.dfg.replace(inst).ireduce(argL_ty, wideResult);
+    Some(wideInst)
+}
+
 // Actually do the transformation given a bundle containing the relevant
 // information. `divrem_info` describes a div or rem by a constant, that
 // `pos` currently points at, and `inst` is the associated instruction.
@@ -500,8 +559,10 @@ fn get_const(value: Value, dfg: &DataFlowGraph) -> Option<i64> {
 //
 // The main pre-opt pass.
 
-pub fn do_preopt(func: &mut Function) {
+pub fn do_preopt(func: &mut Function, opt_level: OptLevel) {
     let _tt = timing::preopt();
+    // The magic number sequences are much longer than a division instruction.
+    let expand_divrem = opt_level != OptLevel::Smallest;
     let mut pos = FuncCursor::new(func);
     while let Some(_ebb) = pos.next_ebb() {
 
@@ -509,13 +570,29 @@ pub fn do_preopt(func: &mut Function) {
 
             //-- BEGIN -- division by constants ----------------
 
-            let mb_dri = get_div_info(inst, &pos.func.dfg);
-            if let Some(divrem_info) = mb_dri {
-                do_divrem_transformation(&divrem_info, &mut pos, inst);
-                continue;
+            if expand_divrem {
+                let mb_dri = get_div_info(inst, &pos.func.dfg);
+                if let Some(divrem_info) = mb_dri {
+                    do_divrem_transformation(&divrem_info, &mut pos, inst);
+                    continue;
+                }
+
+                if let Some(wideInst) = widen_narrow_divrem(&mut pos, inst) {
+                    if let Some(divrem_info) = get_div_info(wideInst, &pos.func.dfg) {
+                        pos.goto_inst(wideInst);
+                        do_divrem_transformation(&divrem_info, &mut pos, wideInst);
+                        pos.goto_inst(inst);
+                    }
+                    continue;
+                }
             }
 
             //-- END -- division by constants ------------------
+
+            if fold_inst(&mut pos, inst) {
+                continue;
+            }
+            rewrite_inst(&mut pos, inst);
         }
     }
 }
@@ -1,28 +1,90 @@
 //! Utility routines for pretty-printing error messages.
 
 use ir;
+use ir::entities::AnyEntity;
 use verifier;
 use result::CtonError;
 use isa::TargetIsa;
-use std::fmt::Write;
+use std::fmt::{self, Write};
+use std::string::{String, ToString};
+use std::vec::Vec;
+use write::{write_ebb_header, write_instruction};
 
-/// Pretty-print a verifier error.
+/// Pretty-print verifier errors.
+///
+/// Each message is followed by the last pass that changed the function and the pass that created
+/// the offending instruction if it is known. Then come excerpts of the EBBs containing the
+/// offending entities with the problems highlighted, and the whole function.
 pub fn pretty_verifier_error(
     func: &ir::Function,
     isa: Option<&TargetIsa>,
-    err: &verifier::Error,
+    errors: &verifier::VerifierErrors,
 ) -> String {
-    let mut msg = err.to_string();
-    match err.location {
-        ir::entities::AnyEntity::Inst(inst) => {
-            write!(msg, "\n{}: {}\n\n", inst, func.dfg.display_inst(inst, isa)).unwrap()
+    let mut msg = String::new();
+    for err in &errors.0 {
+        writeln!(msg, "{}", err).unwrap();
+        if let Some(pass) = err.pass {
+            writeln!(msg, "after pass: {}", pass).unwrap();
+        }
+        if let Some(origin) = err.origin {
+            writeln!(msg, "instruction from pass: {}", origin).unwrap();
+        }
+    }
+    msg.push('\n');
+
+    // The EBBs to show, in the order of their first error.
+    let mut ebbs = Vec::new();
+    for err in &errors.0 {
+        match err.location {
+            AnyEntity::Inst(inst) => {
+                match func.layout.inst_ebb(inst) {
+                    Some(ebb) => ebbs.push(ebb),
+                    // The instruction isn't in the layout, so there's no EBB to show.
+                    None => {
+                        write!(msg, "{}: {}\n\n", inst, func.dfg.display_inst(inst, isa)).unwrap()
+                    }
+                }
+            }
+            AnyEntity::Ebb(ebb) if func.layout.is_ebb_inserted(ebb) => ebbs.push(ebb),
+            _ => {}
+        }
+    }
+    for (i, &ebb) in ebbs.iter().enumerate() {
+        if !ebbs[..i].contains(&ebb) {
+            write_ebb_excerpt(&mut msg, func, isa, ebb, errors).unwrap();
+            msg.push('\n');
         }
-        _ => msg.push('\n'),
     }
     write!(msg, "{}", func.display(isa)).unwrap();
     msg
 }
 
+/// Write the EBB `ebb` to `w` with markers pointing at the instructions or the EBB header with
+/// `errors`.
+fn write_ebb_excerpt(
+    w: &mut String,
+    func: &ir::Function,
+    isa: Option<&TargetIsa>,
+    ebb: ir::Ebb,
+    errors: &verifier::VerifierErrors,
+) -> fmt::Result {
+    write_ebb_header(w, func, isa, ebb, 4)?;
+    for err in &errors.0 {
+        if err.location == AnyEntity::Ebb(ebb) {
+            writeln!(w, "; ^~~~ {}", err.message)?;
+        }
+    }
+    for i in func.layout.ebb_insts(ebb) {
+        write_instruction(w, func, isa, i, 4)?;
+        for err in &errors.0 {
+            if err.location == AnyEntity::Inst(i) {
+                writeln!(w, "    ; ^~~~ {}", err.message)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Pretty-print a Cretonne error.
 pub fn pretty_error(func: &ir::Function, isa: Option<&TargetIsa>, err: CtonError) -> String {
     if let CtonError::Verifier(e) = err {
@@ -31,3 +93,82 @@ pub fn pretty_error(func: &ir::Function, isa: Option<&TargetIsa>, err: CtonError
         err.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{Function, InstBuilder};
+
+    #[test]
+    fn excerpt() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let inst;
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().jump(ebb1, &[]);
+            pos.insert_ebb(ebb1);
+            let v0 = pos.ins().iconst(I32, 1);
+            let v1 = pos.ins().iadd_imm(v0, 2);
+            inst = pos.func.dfg.value_def(v1).unwrap_inst();
+            pos.ins().return_(&[]);
+        }
+
+        let err = verifier::VerifierError {
+            location: inst.into(),
+            message: "bad add".to_string(),
+            pass: Some("Legalization"),
+            origin: None,
+        };
+        let text = pretty_verifier_error(&func, None, &err.into());
+        assert!(text.starts_with(
+            "inst2: bad add\n\
+             after pass: Legalization\n\
+             \n\
+             ebb1:\n    \
+             v0 = iconst.i32 1\n    \
+             v1 = iadd_imm v0, 2\n    \
+             ; ^~~~ bad add\n    \
+             return\n\n",
+        ));
+        assert!(text.ends_with(&func.display(None).to_string()));
+    }
+
+    #[test]
+    fn origin() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let inst;
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().iconst(I32, 1);
+            inst = pos.func.dfg.value_def(v0).unwrap_inst();
+            pos.ins().return_(&[]);
+        }
+        func.inst_origins[inst] = Some("Loop unrolling");
+
+        let mut err = verifier::VerifierError {
+            location: func.dfg.first_result(inst).into(),
+            message: "bad constant".to_string(),
+            pass: Some("Legalization"),
+            origin: None,
+        };
+        err.set_origin(&func);
+        assert_eq!(err.origin, Some("Loop unrolling"));
+        let text = pretty_verifier_error(&func, None, &err.into());
+        assert!(text.starts_with(
+            "v0: bad constant\n\
+             after pass: Legalization\n\
+             instruction from pass: Loop unrolling\n\
+             \n\
+             function u0:0() native {\n\
+             ebb0:\n    \
+             v0 = iconst.i32 1 ; from Loop unrolling\n",
+        ));
+    }
+}
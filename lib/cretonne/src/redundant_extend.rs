@@ -0,0 +1,246 @@
+//! Redundant extension elimination.
+//!
+//! WebAssembly code compiled for 64-bit targets is full of conversions between 32-bit and 64-bit
+//! integers, and many of them don't change any bits. This pass tracks the known state of the high
+//! bits of integer values and removes `uextend`, `sextend`, `ireduce`, and `band_imm`
+//! instructions that are no-ops:
+//!
+//! - `uextend(ireduce(x))` is `x` when the high bits of `x` are known to be zero.
+//! - `sextend(ireduce(x))` is `x` when `x` is known to be sign-extended from the narrow type.
+//! - `ireduce(uextend(x))` and `ireduce(sextend(x))` are `x`.
+//! - `band_imm x, mask` is `x` when all the bits of `x` that can be set are also set in `mask`.
+
+use cursor::{Cursor, FuncCursor};
+use ir::{DataFlowGraph, Function, InstructionData, Opcode, Value, ValueDef};
+use std::cmp::{max, min};
+use timing;
+
+/// Maximum number of definitions to look through when computing the known bits of a value.
+const MAX_DEPTH: usize = 8;
+
+/// Get the number of low bits in `value` that can be nonzero.
+///
+/// All the bits above the returned number are known to be zero.
+fn zero_ext_bits(dfg: &DataFlowGraph, value: Value, depth: usize) -> u16 {
+    let value = dfg.resolve_aliases(value);
+    let bits = dfg.value_type(value).bits();
+    let inst = match dfg.value_def(value) {
+        ValueDef::Result(inst, 0) if depth > 0 => inst,
+        _ => return bits,
+    };
+
+    let known = match dfg[inst] {
+        InstructionData::Unary { opcode: Opcode::Uextend, arg } => {
+            zero_ext_bits(dfg, arg, depth - 1)
+        }
+        InstructionData::Unary { opcode: Opcode::Bint, .. } => 1,
+        InstructionData::Load { opcode: Opcode::Uload8, .. } => 8,
+        InstructionData::Load { opcode: Opcode::Uload16, .. } => 16,
+        InstructionData::Load { opcode: Opcode::Uload32, .. } => 32,
+        InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => {
+            let imm: i64 = imm.into();
+            if imm >= 0 {
+                64 - imm.leading_zeros() as u16
+            } else {
+                bits
+            }
+        }
+        InstructionData::Binary { opcode: Opcode::Band, args } => {
+            min(
+                zero_ext_bits(dfg, args[0], depth - 1),
+                zero_ext_bits(dfg, args[1], depth - 1),
+            )
+        }
+        InstructionData::Binary { opcode: Opcode::Bor, args } |
+        InstructionData::Binary { opcode: Opcode::Bxor, args } => {
+            max(
+                zero_ext_bits(dfg, args[0], depth - 1),
+                zero_ext_bits(dfg, args[1], depth - 1),
+            )
+        }
+        InstructionData::BinaryImm { opcode: Opcode::BandImm, arg, imm } => {
+            let imm: i64 = imm.into();
+            let arg_bits = zero_ext_bits(dfg, arg, depth - 1);
+            if imm >= 0 {
+                min(arg_bits, 64 - imm.leading_zeros() as u16)
+            } else {
+                arg_bits
+            }
+        }
+        InstructionData::BinaryImm { opcode: Opcode::UshrImm, arg, imm } => {
+            let imm: i64 = imm.into();
+            let shift = (imm as u16) & (bits - 1);
+            zero_ext_bits(dfg, arg, depth - 1).saturating_sub(shift)
+        }
+        _ => bits,
+    };
+    min(known, bits)
+}
+
+/// Get the number of low bits in `value` that determine it by sign extension.
+///
+/// All the bits above the returned number are known to be copies of the bit below them.
+fn sign_ext_bits(dfg: &DataFlowGraph, value: Value, depth: usize) -> u16 {
+    let value = dfg.resolve_aliases(value);
+    let bits = dfg.value_type(value).bits();
+    let inst = match dfg.value_def(value) {
+        ValueDef::Result(inst, 0) if depth > 0 => inst,
+        _ => return bits,
+    };
+
+    let known = match dfg[inst] {
+        InstructionData::Unary { opcode: Opcode::Sextend, arg } => {
+            sign_ext_bits(dfg, arg, depth - 1)
+        }
+        InstructionData::Load { opcode: Opcode::Sload8, .. } => 8,
+        InstructionData::Load { opcode: Opcode::Sload16, .. } => 16,
+        InstructionData::Load { opcode: Opcode::Sload32, .. } => 32,
+        InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => {
+            let imm: i64 = imm.into();
+            let redundant = if imm < 0 {
+                (!imm).leading_zeros()
+            } else {
+                imm.leading_zeros()
+            };
+            65 - redundant as u16
+        }
+        InstructionData::BinaryImm { opcode: Opcode::SshrImm, arg, imm } => {
+            let imm: i64 = imm.into();
+            let shift = (imm as u16) & (bits - 1);
+            sign_ext_bits(dfg, arg, depth - 1).saturating_sub(shift)
+        }
+        _ => bits,
+    };
+
+    // A value with zero high bits is also sign-extended from one bit higher.
+    min(min(known, zero_ext_bits(dfg, value, depth) + 1), bits)
+}
+
+/// Find the value that the result of `inst` is known to be equal to, if `inst` is redundant.
+fn redundant_value(dfg: &DataFlowGraph, data: &InstructionData) -> Option<Value> {
+    match *data {
+        InstructionData::Unary { opcode, arg } => {
+            let narrow = dfg.value_type(arg).bits();
+            let (inner_opcode, source) = match dfg.value_def(dfg.resolve_aliases(arg)) {
+                ValueDef::Result(inner, 0) => {
+                    match dfg[inner] {
+                        InstructionData::Unary { opcode, arg } => (opcode, arg),
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            };
+            match (opcode, inner_opcode) {
+                (Opcode::Uextend, Opcode::Ireduce)
+                    if zero_ext_bits(dfg, source, MAX_DEPTH) <= narrow => Some(source),
+                (Opcode::Sextend, Opcode::Ireduce)
+                    if sign_ext_bits(dfg, source, MAX_DEPTH) <= narrow => Some(source),
+                (Opcode::Ireduce, Opcode::Uextend) |
+                (Opcode::Ireduce, Opcode::Sextend) => Some(source),
+                _ => None,
+            }
+        }
+        InstructionData::BinaryImm { opcode: Opcode::BandImm, arg, imm } => {
+            let imm: i64 = imm.into();
+            let known = zero_ext_bits(dfg, arg, MAX_DEPTH);
+            let mask = if known >= 64 {
+                !0
+            } else {
+                (1u64 << known) - 1
+            };
+            if (imm as u64) & mask == mask {
+                Some(arg)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Remove redundant extension and masking instructions from `func`.
+pub fn eliminate_redundant_extends(func: &mut Function) {
+    let _tt = timing::redundant_extend();
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        while let Some(inst) = pos.next_inst() {
+            let source = match redundant_value(&pos.func.dfg, &pos.func.dfg[inst]) {
+                Some(source) => source,
+                None => continue,
+            };
+            let result = pos.func.dfg.first_result(inst);
+            if pos.func.dfg.value_type(result) != pos.func.dfg.value_type(source) {
+                continue;
+            }
+            pos.func.dfg.clear_results(inst);
+            pos.func.dfg.change_to_alias(result, source);
+            pos.remove_inst_and_step_back();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::{I32, I64};
+    use ir::{InstBuilder, MemFlags};
+
+    fn count_insts(func: &Function) -> usize {
+        func.layout
+            .ebbs()
+            .map(|ebb| func.layout.ebb_insts(ebb).count())
+            .sum()
+    }
+
+    #[test]
+    fn wrap_and_extend() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let (v2, v3, v5);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            // Known zero-extended from 32 bits.
+            let v0 = pos.ins().uload32(MemFlags::new(), p, 0);
+            let v1 = pos.ins().ireduce(I32, v0);
+            v2 = pos.ins().uextend(I64, v1);
+            // Unknown high bits.
+            let w = pos.ins().ireduce(I32, p);
+            v3 = pos.ins().uextend(I64, w);
+            // Known sign-extended from 32 bits.
+            let v4 = pos.ins().sload32(MemFlags::new(), p, 0);
+            let w = pos.ins().ireduce(I32, v4);
+            v5 = pos.ins().sextend(I64, w);
+            pos.ins().return_(&[v2, v3, v5]);
+        }
+
+        eliminate_redundant_extends(&mut func);
+        assert_eq!(count_insts(&func), 7);
+        assert_ne!(func.dfg.resolve_aliases(v2), v2);
+        assert_eq!(func.dfg.resolve_aliases(v3), v3);
+        assert_ne!(func.dfg.resolve_aliases(v5), v5);
+    }
+
+    #[test]
+    fn masks() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let (v1, v2, v3);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().uload16(I64, MemFlags::new(), p, 0);
+            v1 = pos.ins().band_imm(v0, 0xffff);
+            v2 = pos.ins().band_imm(v0, 0xff);
+            v3 = pos.ins().band_imm(p, 0xffff_ffff);
+            pos.ins().return_(&[v1, v2, v3]);
+        }
+
+        eliminate_redundant_extends(&mut func);
+        assert_ne!(func.dfg.resolve_aliases(v1), v1);
+        assert_eq!(func.dfg.resolve_aliases(v2), v2);
+        assert_eq!(func.dfg.resolve_aliases(v3), v3);
+    }
+}
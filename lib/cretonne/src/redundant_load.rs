@@ -0,0 +1,437 @@
+//! Redundant load elimination and store-to-load forwarding.
+//!
+//! WebAssembly code tends to reload the same linear memory address many times, and GVN can't
+//! remove those loads since it doesn't reason about memory. This pass tracks the known contents
+//! of memory through each EBB and replaces loads of a known value with the value itself:
+//!
+//! - A load from the same location as an earlier load is replaced by the earlier result.
+//! - A load from the location written by an earlier store is replaced by the stored value.
+//!
+//! Stores invalidate the known values they may alias according to a simple alias analysis:
+//!
+//! - Accesses with the same base address alias only if their byte ranges overlap.
+//! - Accesses to different heaps or to different stack slots never alias, and heap memory never
+//!   aliases stack slots.
+//! - Memory read by `readonly` loads is never written.
+//!
+//! Calls and other instructions with unknown side effects invalidate everything except the
+//! `readonly` loads.
+
+use cursor::{Cursor, FuncCursor};
+use ir::{DataFlowGraph, Function, Heap, Inst, InstructionData, Opcode, StackSlot, Type, Value,
+         ValueDef};
+use std::vec::Vec;
+use timing;
+
+/// The memory region an address points into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// The heap addressed by a `heap_addr` instruction.
+    Heap(Heap),
+    /// A stack slot addressed by `stack_addr` or accessed by `stack_load` and `stack_store`.
+    Stack(StackSlot),
+    /// Anything else.
+    Unknown,
+}
+
+/// The base of an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Base {
+    /// An address value.
+    Value(Value),
+    /// The start of a stack slot, used by `stack_load` and `stack_store`.
+    Slot(StackSlot),
+}
+
+/// A range of bytes accessed by a memory instruction.
+#[derive(Clone, Copy, Debug)]
+pub struct Location {
+    /// The region containing the bytes.
+    pub region: Region,
+    base: Base,
+    offset: i64,
+    size: i64,
+}
+
+impl Location {
+    /// Find the region and base of `addr`, folding constant additions into `offset`.
+    fn from_addr(dfg: &DataFlowGraph, addr: Value, offset: i64, size: u32) -> Self {
+        let mut base = dfg.resolve_aliases(addr);
+        let mut offset = offset;
+        let region;
+        loop {
+            let def = match dfg.value_def(base) {
+                ValueDef::Result(def, _) => def,
+                ValueDef::Param(..) => {
+                    region = Region::Unknown;
+                    break;
+                }
+            };
+            match dfg[def] {
+                InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, imm } => {
+                    let imm: i64 = imm.into();
+                    offset = offset.wrapping_add(imm);
+                    base = dfg.resolve_aliases(arg);
+                }
+                InstructionData::HeapAddr { heap, .. } => {
+                    region = Region::Heap(heap);
+                    break;
+                }
+                InstructionData::StackLoad {
+                    opcode: Opcode::StackAddr,
+                    stack_slot,
+                    offset: slot_offset,
+                } => {
+                    region = Region::Stack(stack_slot);
+                    let slot_offset: i64 = slot_offset.into();
+                    offset = offset.wrapping_add(slot_offset);
+                    break;
+                }
+                _ => {
+                    region = Region::Unknown;
+                    break;
+                }
+            }
+        }
+        Self {
+            region,
+            base: Base::Value(base),
+            offset,
+            size: i64::from(size),
+        }
+    }
+
+    /// Can the memory at `self` and `other` overlap?
+    pub fn may_alias(&self, other: &Self) -> bool {
+        if self.base == other.base {
+            return self.offset < other.offset + other.size &&
+                other.offset < self.offset + self.size;
+        }
+        match (self.region, other.region) {
+            (Region::Unknown, _) |
+            (_, Region::Unknown) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Is `self` the exact same range of bytes as `other`?
+    fn same_as(&self, other: &Self) -> bool {
+        self.base == other.base && self.offset == other.offset && self.size == other.size
+    }
+
+    /// Does `self` include all the bytes of `other`?
+    pub fn covers(&self, other: &Self) -> bool {
+        self.base == other.base && self.offset <= other.offset &&
+            other.offset + other.size <= self.offset + self.size
+    }
+}
+
+/// A memory access performed by an instruction.
+pub enum Access {
+    /// A load with the given opcode, producing a result of the given type.
+    Load(Location, Opcode, Type, bool),
+    /// A store of a value.
+    Store(Location, Opcode, Value),
+    /// Some other instruction that may write any memory.
+    Clobber,
+    /// An instruction that doesn't write memory.
+    None,
+}
+
+/// Get the number of bytes accessed by a load or store with `opcode` and controlling type `ty`.
+fn access_size(opcode: Opcode, ty: Type) -> u32 {
+    match opcode {
+        Opcode::Uload8 | Opcode::Sload8 | Opcode::Istore8 => 1,
+        Opcode::Uload16 | Opcode::Sload16 | Opcode::Istore16 => 2,
+        Opcode::Uload32 | Opcode::Sload32 | Opcode::Istore32 => 4,
+        _ => ty.bytes(),
+    }
+}
+
+/// Classify the memory access performed by `inst`.
+pub fn access(dfg: &DataFlowGraph, inst: Inst) -> Access {
+    match dfg[inst] {
+        // Volatile accesses must happen, and nothing can be assumed about the memory afterwards.
+        InstructionData::Load { flags, .. } |
+        InstructionData::Store { flags, .. } if flags.volatile() => Access::Clobber,
+        InstructionData::Load {
+            opcode,
+            arg,
+            flags,
+            offset,
+        } => {
+            let ty = dfg.value_type(dfg.first_result(inst));
+            let loc = Location::from_addr(dfg, arg, offset.into(), access_size(opcode, ty));
+            Access::Load(loc, opcode, ty, flags.readonly())
+        }
+        InstructionData::Store {
+            opcode,
+            args,
+            offset,
+            ..
+        } => {
+            let ty = dfg.value_type(args[0]);
+            let loc = Location::from_addr(dfg, args[1], offset.into(), access_size(opcode, ty));
+            Access::Store(loc, opcode, args[0])
+        }
+        InstructionData::StackLoad {
+            opcode: Opcode::StackLoad,
+            stack_slot,
+            offset,
+        } => {
+            let ty = dfg.value_type(dfg.first_result(inst));
+            let loc = Location {
+                region: Region::Stack(stack_slot),
+                base: Base::Slot(stack_slot),
+                offset: offset.into(),
+                size: i64::from(ty.bytes()),
+            };
+            Access::Load(loc, Opcode::Load, ty, false)
+        }
+        InstructionData::StackStore {
+            arg,
+            stack_slot,
+            offset,
+            ..
+        } => {
+            let loc = Location {
+                region: Region::Stack(stack_slot),
+                base: Base::Slot(stack_slot),
+                offset: offset.into(),
+                size: i64::from(dfg.value_type(arg).bytes()),
+            };
+            Access::Store(loc, Opcode::Store, arg)
+        }
+        // Loads can be forwarded across a barrier that only keeps the stores in place.
+        InstructionData::Barrier { kind, .. } => {
+            if kind.orders_loads() {
+                Access::Clobber
+            } else {
+                Access::None
+            }
+        }
+        _ => {
+            let opcode = dfg[inst].opcode();
+            if opcode.can_store() || opcode.is_call() || opcode.other_side_effects() {
+                Access::Clobber
+            } else {
+                Access::None
+            }
+        }
+    }
+}
+
+/// A known value in memory.
+struct Known {
+    loc: Location,
+    /// The opcode of a load producing `value` from `loc`.
+    opcode: Opcode,
+    value: Value,
+    readonly: bool,
+}
+
+/// Remove redundant loads from `func`, forwarding stored values to loads where possible.
+pub fn eliminate_redundant_loads(func: &mut Function) {
+    let _tt = timing::redundant_load();
+    let mut known: Vec<Known> = Vec::new();
+    let mut pos = FuncCursor::new(func);
+
+    while let Some(_ebb) = pos.next_ebb() {
+        // Memory contents are only tracked within an EBB.
+        known.clear();
+
+        while let Some(inst) = pos.next_inst() {
+            match access(&pos.func.dfg, inst) {
+                Access::Load(loc, opcode, ty, readonly) => {
+                    let found = known
+                        .iter()
+                        .find(|k| {
+                            k.opcode == opcode && k.loc.same_as(&loc) &&
+                                pos.func.dfg.value_type(k.value) == ty
+                        })
+                        .map(|k| k.value);
+                    let result = pos.func.dfg.first_result(inst);
+                    match found {
+                        Some(value) => {
+                            pos.func.dfg.clear_results(inst);
+                            pos.func.dfg.change_to_alias(result, value);
+                            pos.remove_inst_and_step_back();
+                        }
+                        None => {
+                            known.push(Known {
+                                loc,
+                                opcode,
+                                value: result,
+                                readonly,
+                            })
+                        }
+                    }
+                }
+                Access::Store(loc, opcode, value) => {
+                    known.retain(|k| k.readonly || !k.loc.may_alias(&loc));
+                    // Only full-width stores can be forwarded to loads.
+                    if opcode == Opcode::Store {
+                        known.push(Known {
+                            loc,
+                            opcode: Opcode::Load,
+                            value,
+                            readonly: false,
+                        });
+                    }
+                }
+                Access::Clobber => known.retain(|k| k.readonly),
+                Access::None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::types::{I32, I64};
+    use ir::{AbiParam, BarrierKind, CallConv, ExtFuncData, ExternalName, HeapBase, HeapData,
+             HeapStyle, InstBuilder, MemFlags, Signature, StackSlotData, StackSlotKind};
+
+    fn count_loads(func: &Function) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode().can_load())
+            .count()
+    }
+
+    fn heap(func: &mut Function) -> Heap {
+        func.create_heap(HeapData {
+            base: HeapBase::ReservedReg,
+            min_size: 0.into(),
+            guard_size: 0.into(),
+            style: HeapStyle::Static { bound: 0x1_0000.into() },
+            readonly: false,
+        })
+    }
+
+    #[test]
+    fn forward_and_reuse() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let (v1, v2, v3, v4);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let flags = MemFlags::new();
+            v1 = pos.ins().load(I32, flags, p, 0);
+            v2 = pos.ins().load(I32, flags, p, 0);
+            pos.ins().store(flags, x, p, 4);
+            // Same base, non-overlapping: `v1` is still known.
+            v3 = pos.ins().load(I32, flags, p, 0);
+            v4 = pos.ins().load(I32, flags, p, 4);
+            pos.ins().return_(&[v1, v2, v3, v4]);
+        }
+
+        eliminate_redundant_loads(&mut func);
+        assert_eq!(count_loads(&func), 1);
+        assert_eq!(func.dfg.resolve_aliases(v2), v1);
+        assert_eq!(func.dfg.resolve_aliases(v3), v1);
+        assert_eq!(func.dfg.resolve_aliases(v4), x);
+    }
+
+    #[test]
+    fn aliasing() {
+        let mut func = Function::new();
+        let heap0 = heap(&mut func);
+        let heap1 = heap(&mut func);
+        let ss0 = func.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8));
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let q = func.dfg.append_ebb_param(ebb0, I32);
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        let sig = func.import_signature(sig);
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::testcase("f"),
+            signature: sig,
+            colocated: false,
+        });
+        let (v0, v1, v2, v3, v4, v5);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let flags = MemFlags::new();
+            let h0 = pos.ins().heap_addr(I64, heap0, q, 4);
+            let h1 = pos.ins().heap_addr(I64, heap1, q, 4);
+            v0 = pos.ins().load(I32, flags, h0, 0);
+            v1 = pos.ins().stack_load(I32, ss0, 0);
+            // Doesn't alias heap0 or the stack slot.
+            pos.ins().store(flags, x, h1, 0);
+            v2 = pos.ins().load(I32, flags, h0, 0);
+            v3 = pos.ins().stack_load(I32, ss0, 0);
+            // May alias anything.
+            pos.ins().store(flags, x, p, 0);
+            v4 = pos.ins().load(I32, flags, h0, 0);
+            pos.ins().call(callee, &[v4]);
+            v5 = pos.ins().load(I32, flags, h0, 0);
+            pos.ins().return_(&[v0, v1, v2, v3, v4, v5]);
+        }
+
+        eliminate_redundant_loads(&mut func);
+        assert_eq!(func.dfg.resolve_aliases(v2), v0);
+        assert_eq!(func.dfg.resolve_aliases(v3), v1);
+        assert_eq!(func.dfg.resolve_aliases(v4), v4);
+        assert_eq!(func.dfg.resolve_aliases(v5), v5);
+    }
+
+    #[test]
+    fn barriers() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let (v1, v2, v3);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let flags = MemFlags::new();
+            v1 = pos.ins().load(I32, flags, p, 0);
+            pos.ins().barrier(BarrierKind::Stores);
+            v2 = pos.ins().load(I32, flags, p, 0);
+            pos.ins().barrier(BarrierKind::Loads);
+            v3 = pos.ins().load(I32, flags, p, 0);
+            pos.ins().return_(&[v1, v2, v3]);
+        }
+
+        eliminate_redundant_loads(&mut func);
+        assert_eq!(func.dfg.resolve_aliases(v2), v1);
+        assert_eq!(func.dfg.resolve_aliases(v3), v3);
+    }
+
+    #[test]
+    fn volatile() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let (v1, v2, v3, v4);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let flags = MemFlags::new();
+            let mut volatile = MemFlags::new();
+            volatile.set_volatile();
+            v1 = pos.ins().load(I32, volatile, p, 0);
+            v2 = pos.ins().load(I32, volatile, p, 0);
+            pos.ins().store(volatile, x, p, 4);
+            v3 = pos.ins().load(I32, flags, p, 4);
+            v4 = pos.ins().load(I32, flags, p, 4);
+            pos.ins().return_(&[v1, v2, v3, v4]);
+        }
+
+        eliminate_redundant_loads(&mut func);
+        assert_eq!(count_loads(&func), 3);
+        assert_eq!(func.dfg.resolve_aliases(v2), v2);
+        assert_eq!(func.dfg.resolve_aliases(v3), v3);
+        assert_eq!(func.dfg.resolve_aliases(v4), v3);
+    }
+}
@@ -7,10 +7,17 @@
 //! For values that want to be in registers, the affinity hint includes a register class or
 //! subclass. This is just a hint, and the register allocator is allowed to pick a register from a
 //! larger register class instead.
+//!
+//! Register affinities also carry a `Preference` which tells the spiller how much a value gains
+//! from staying in a register. The weight counts the uses of the value, scaled by how often they
+//! are expected to execute, and values that are live across calls are marked as better off in
+//! callee-saved registers or on the stack. A preference can also name a concrete register the
+//! value is headed for, like the ABI register of a call argument, so the coloring pass can pick it
+//! up front instead of moving the value later.
 
 use std::fmt;
 use ir::{AbiParam, ArgumentLoc};
-use isa::{TargetIsa, RegInfo, RegClassIndex, OperandConstraint, ConstraintKind};
+use isa::{TargetIsa, RegInfo, RegClassIndex, RegUnit, OperandConstraint, ConstraintKind};
 
 /// Preferred register allocation for an SSA value.
 #[derive(Clone, Copy, Debug)]
@@ -25,7 +32,21 @@ pub enum Affinity {
     Stack,
 
     /// This value prefers a register from the given register class.
-    Reg(RegClassIndex),
+    Reg(RegClassIndex, Preference),
+}
+
+/// How strongly a value prefers to be in a register.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Preference {
+    /// Number of uses of the value, weighted by the estimated frequency of the EBBs using it.
+    pub weight: u32,
+
+    /// The value is live across a call, so it would have to be saved around the call if it were
+    /// assigned a caller-saved register.
+    pub avoid_caller_saved: bool,
+
+    /// A register that a use of the value is fixed to. Assigning this register avoids a move.
+    pub hint: Option<RegUnit>,
 }
 
 impl Default for Affinity {
@@ -43,15 +64,20 @@ impl Affinity {
         if constraint.kind == ConstraintKind::Stack {
             Affinity::Stack
         } else {
-            Affinity::Reg(constraint.regclass.into())
+            Affinity::reg(constraint.regclass.into())
         }
     }
 
+    /// Create a register affinity for `rc` with no uses.
+    pub fn reg(rc: RegClassIndex) -> Affinity {
+        Affinity::Reg(rc, Preference::default())
+    }
+
     /// Create an affinity that matches an ABI argument for `isa`.
     pub fn abi(arg: &AbiParam, isa: &TargetIsa) -> Affinity {
         match arg.location {
             ArgumentLoc::Unassigned => Affinity::None,
-            ArgumentLoc::Reg(_) => Affinity::Reg(isa.regclass_for_abi_type(arg.value_type).into()),
+            ArgumentLoc::Reg(_) => Affinity::reg(isa.regclass_for_abi_type(arg.value_type).into()),
             ArgumentLoc::Stack(_) => Affinity::Stack,
         }
     }
@@ -67,11 +93,59 @@ impl Affinity {
     /// Is this the `Reg` affinity?
     pub fn is_reg(self) -> bool {
         match self {
-            Affinity::Reg(_) => true,
+            Affinity::Reg(..) => true,
             _ => false,
         }
     }
 
+    /// Get the register preference of a `Reg` affinity.
+    pub fn preference(self) -> Option<Preference> {
+        match self {
+            Affinity::Reg(_, pref) => Some(pref),
+            _ => None,
+        }
+    }
+
+    /// Count a use of the value in the weight of a `Reg` affinity.
+    pub fn add_use(&mut self) {
+        self.add_weight(1)
+    }
+
+    /// Stop counting a use of the value in the weight of a `Reg` affinity.
+    pub fn remove_use(&mut self) {
+        if let Affinity::Reg(_, ref mut pref) = *self {
+            pref.weight = pref.weight.saturating_sub(1);
+        }
+    }
+
+    /// Add `weight` to the weight of a `Reg` affinity.
+    pub fn add_weight(&mut self, weight: u32) {
+        if let Affinity::Reg(_, ref mut pref) = *self {
+            pref.weight = pref.weight.saturating_add(weight);
+        }
+    }
+
+    /// Mark a `Reg` affinity as belonging to a value that is live across a call.
+    pub fn set_avoid_caller_saved(&mut self) {
+        if let Affinity::Reg(_, ref mut pref) = *self {
+            pref.avoid_caller_saved = true;
+        }
+    }
+
+    /// Set the preferred register of a `Reg` affinity, unless it already has one.
+    pub fn set_hint(&mut self, reg: RegUnit) {
+        if let Affinity::Reg(_, ref mut pref) = *self {
+            if pref.hint.is_none() {
+                pref.hint = Some(reg);
+            }
+        }
+    }
+
+    /// Get the preferred register of a `Reg` affinity.
+    pub fn hint(self) -> Option<RegUnit> {
+        self.preference().and_then(|pref| pref.hint)
+    }
+
     /// Is this the `Stack` affinity?
     pub fn is_stack(self) -> bool {
         match self {
@@ -87,7 +161,7 @@ impl Affinity {
     pub fn merge(&mut self, constraint: &OperandConstraint, reg_info: &RegInfo) {
         match *self {
             Affinity::None => *self = Affinity::new(constraint),
-            Affinity::Reg(rc) => {
+            Affinity::Reg(rc, pref) => {
                 // If the preferred register class is a subclass of the constraint, there's no need
                 // to change anything.
                 if constraint.kind != ConstraintKind::Stack &&
@@ -97,7 +171,7 @@ impl Affinity {
                     // just keep our previous affinity.
                     if let Some(subclass) = constraint.regclass.intersect_index(reg_info.rc(rc)) {
                         // This constraint shrinks our preferred register class.
-                        *self = Affinity::Reg(subclass);
+                        *self = Affinity::Reg(subclass, pref);
                     }
                 }
             }
@@ -120,7 +194,7 @@ impl<'a> fmt::Display for DisplayAffinity<'a> {
         match self.0 {
             Affinity::None => write!(f, "none"),
             Affinity::Stack => write!(f, "stack"),
-            Affinity::Reg(rci) => {
+            Affinity::Reg(rci, _) => {
                 match self.1 {
                     Some(regs) => write!(f, "{}", regs.rc(rci)),
                     None => write!(f, "{}", rci),
@@ -298,13 +298,16 @@ impl<'a> Context<'a> {
 
         // Create a live range for the new value.
         // TODO: Should we handle ghost values?
-        let affinity = Affinity::new(
+        let mut affinity = Affinity::new(
             &self.encinfo
                 .operand_constraints(pos.func.encodings[inst])
                 .expect("Bad copy encoding")
                 .outs
                 [0],
         );
+        if let Some(reg) = self.liveness[param].affinity.hint() {
+            affinity.set_hint(reg);
+        }
         self.liveness.create_dead(new_val, ebb, affinity);
         self.liveness.extend_locally(
             new_val,
@@ -348,13 +351,16 @@ impl<'a> Context<'a> {
 
         // Create a live range for the new value.
         // TODO: Handle affinity for ghost values.
-        let affinity = Affinity::new(
+        let mut affinity = Affinity::new(
             &self.encinfo
                 .operand_constraints(pos.func.encodings[inst])
                 .expect("Bad copy encoding")
                 .outs
                 [0],
         );
+        if let Some(reg) = self.liveness[pred_val].affinity.hint() {
+            affinity.set_hint(reg);
+        }
         self.liveness.create_dead(copy, inst, affinity);
         self.liveness.extend_locally(
             copy,
@@ -222,7 +222,7 @@ impl<'a> Context<'a> {
                 lv.affinity.display(&self.reginfo),
                 self.cur.func.locations[lv.value].display(&self.reginfo)
             );
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let rc = self.reginfo.rc(rci);
                 let loc = self.cur.func.locations[lv.value];
                 match loc {
@@ -252,7 +252,7 @@ impl<'a> Context<'a> {
 
         for (lv, abi) in args.iter().zip(&sig.params) {
             match lv.affinity {
-                Affinity::Reg(rci) => {
+                Affinity::Reg(rci, _) => {
                     let rc = self.reginfo.rc(rci);
                     if let ArgumentLoc::Reg(reg) = abi.location {
                         if !lv.is_dead {
@@ -308,8 +308,7 @@ impl<'a> Context<'a> {
         // Program the solver with register constraints for the input side.
         self.solver.reset(&regs.input);
         self.program_input_constraints(inst, constraints.ins);
-        let call_sig = self.cur.func.dfg.call_signature(inst);
-        if let Some(sig) = call_sig {
+        if let Some(sig) = self.cur.func.dfg.call_signature(inst) {
             program_input_abi(
                 &mut self.solver,
                 inst,
@@ -360,7 +359,7 @@ impl<'a> Context<'a> {
 
         // Get rid of the killed values.
         for lv in kills {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let rc = self.reginfo.rc(rci);
                 let reg = self.divert.reg(lv.value, &self.cur.func.locations);
                 dbg!(
@@ -402,7 +401,7 @@ impl<'a> Context<'a> {
                 &regs.global,
             );
         }
-        if let Some(sig) = call_sig {
+        if let Some(sig) = self.cur.func.dfg.call_results_signature(inst) {
             self.program_output_abi(
                 sig,
                 defs,
@@ -471,7 +470,7 @@ impl<'a> Context<'a> {
                 }
             );
 
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let rc = self.reginfo.rc(rci);
 
                 // Remove the dead defs.
@@ -605,7 +604,7 @@ impl<'a> Context<'a> {
                 ValueLoc::Reg(dest_reg) => {
                     // We've branched to `dest` before. Make sure we use the correct argument
                     // registers by reassigning `br_arg`.
-                    if let Affinity::Reg(rci) = self.liveness[br_arg].affinity {
+                    if let Affinity::Reg(rci, _) = self.liveness[br_arg].affinity {
                         let rc = self.reginfo.rc(rci);
                         let br_reg = self.divert.reg(br_arg, &self.cur.func.locations);
                         self.solver.reassign_in(br_arg, rc, br_reg, dest_reg);
@@ -658,7 +657,7 @@ impl<'a> Context<'a> {
                 "Missing live range for diverted register",
             );
             if pred(lr, self.liveness.context(&self.cur.func.layout)) {
-                if let Affinity::Reg(rci) = lr.affinity {
+                if let Affinity::Reg(rci, _) = lr.affinity {
                     let rc = self.reginfo.rc(rci);
                     // Stack diversions should not be possible here. The only live transiently
                     // during `shuffle_inputs()`.
@@ -683,7 +682,7 @@ impl<'a> Context<'a> {
     // into the constraint solver. Convert them to solver variables so they can be diverted.
     fn divert_fixed_input_conflicts(&mut self, live: &[LiveValue]) {
         for lv in live {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let toprc = self.reginfo.toprc(rci);
                 let reg = self.divert.reg(lv.value, &self.cur.func.locations);
                 if self.solver.is_fixed_input_conflict(toprc, reg) {
@@ -745,7 +744,7 @@ impl<'a> Context<'a> {
         for (i, lv) in defs.iter().enumerate() {
             let abi = self.cur.func.dfg.signatures[sig].returns[i];
             if let ArgumentLoc::Reg(reg) = abi.location {
-                if let Affinity::Reg(rci) = lv.affinity {
+                if let Affinity::Reg(rci, _) = lv.affinity {
                     let rc = self.reginfo.rc(rci);
                     self.add_fixed_output(lv.value, rc, reg, throughs);
                     if !lv.is_local && !global_regs.is_avail(rc, reg) {
@@ -775,7 +774,7 @@ impl<'a> Context<'a> {
         if !self.solver.add_fixed_output(rc, reg) {
             // The fixed output conflicts with some of the live-through registers.
             for lv in throughs {
-                if let Affinity::Reg(rci) = lv.affinity {
+                if let Affinity::Reg(rci, _) = lv.affinity {
                     let toprc2 = self.reginfo.toprc(rci);
                     let reg2 = self.divert.reg(lv.value, &self.cur.func.locations);
                     if regs_overlap(rc, reg, toprc2, reg2) {
@@ -809,7 +808,13 @@ impl<'a> Context<'a> {
                 ConstraintKind::FixedTied(_) |
                 ConstraintKind::Stack => continue,
                 ConstraintKind::Reg => {
-                    self.solver.add_def(lv.value, op.regclass, !lv.is_local);
+                    // A value that is live across a call can't stay in the argument register it
+                    // is hinted to, so it is better off without the hint.
+                    let hint = match lv.affinity.preference() {
+                        Some(pref) if !pref.avoid_caller_saved => pref.hint,
+                        _ => None,
+                    };
+                    self.solver.add_def(lv.value, op.regclass, !lv.is_local, hint);
                 }
                 ConstraintKind::Tied(num) => {
                     // Find the input operand we're tied to.
@@ -878,7 +883,7 @@ impl<'a> Context<'a> {
         dbg!("Trying to add a {} reg from {} values", rc, throughs.len());
 
         for lv in throughs {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 // The new variable gets to roam the whole top-level register class because it is
                 // not actually constrained by the instruction. We just want it out of the way.
                 let toprc2 = self.reginfo.toprc(rci);
@@ -1066,7 +1071,7 @@ impl<'a> Context<'a> {
     /// - Free killed registers.
     fn process_ghost_kills(&mut self, kills: &[LiveValue], regs: &mut AvailableRegs) {
         for lv in kills {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let rc = self.reginfo.rc(rci);
                 let loc = match self.divert.remove(lv.value) {
                     Some(loc) => loc,
@@ -1098,7 +1103,7 @@ fn program_input_abi(
 ) {
     for (abi, &value) in abi_types.iter().zip(func.dfg.inst_variable_args(inst)) {
         if let ArgumentLoc::Reg(reg) = abi.location {
-            if let Affinity::Reg(rci) =
+            if let Affinity::Reg(rci, _) =
                 liveness
                     .get(value)
                     .expect("ABI register must have live range")
@@ -4,25 +4,31 @@
 //! the register allocator algorithm. This doesn't preserve any data between functions, but it
 //! avoids allocating data structures independently for each function begin compiled.
 
+use block_frequency::BlockFrequency;
 use dominator_tree::DominatorTree;
 use flowgraph::ControlFlowGraph;
 use ir::Function;
 use isa::TargetIsa;
+use loop_analysis::LoopAnalysis;
 use regalloc::coalescing::Coalescing;
 use regalloc::coloring::Coloring;
 use regalloc::live_value_tracker::LiveValueTracker;
 use regalloc::liveness::Liveness;
 use regalloc::reload::Reload;
+use regalloc::safepoints::compute_safepoints;
 use regalloc::spilling::Spilling;
 use regalloc::virtregs::VirtRegs;
 use result::CtonResult;
 use timing;
 use topo_order::TopoOrder;
-use verifier::{verify_context, verify_liveness, verify_cssa, verify_locations};
+use verifier::{verify_context, verify_liveness, verify_cssa, verify_locations,
+               verify_interference};
 
 /// Persistent memory allocations for register allocation.
 pub struct Context {
     liveness: Liveness,
+    loops: LoopAnalysis,
+    block_freq: BlockFrequency,
     virtregs: VirtRegs,
     coalescing: Coalescing,
     topo: TopoOrder,
@@ -40,6 +46,8 @@ impl Context {
     pub fn new() -> Self {
         Self {
             liveness: Liveness::new(),
+            loops: LoopAnalysis::new(),
+            block_freq: BlockFrequency::new(),
             virtregs: VirtRegs::new(),
             coalescing: Coalescing::new(),
             topo: TopoOrder::new(),
@@ -53,6 +61,8 @@ impl Context {
     /// Clear all data structures in this context.
     pub fn clear(&mut self) {
         self.liveness.clear();
+        self.loops.clear();
+        self.block_freq.clear();
         self.virtregs.clear();
         self.coalescing.clear();
         self.topo.clear();
@@ -62,6 +72,11 @@ impl Context {
         self.coloring.clear();
     }
 
+    /// Get the liveness analysis computed by the last call to `run`.
+    pub fn liveness(&self) -> &Liveness {
+        &self.liveness
+    }
+
     /// Allocate registers in `func`.
     ///
     /// After register allocation, all values in `func` have been assigned to a register or stack
@@ -86,6 +101,12 @@ impl Context {
         // Pass: Liveness analysis.
         self.liveness.compute(isa, func, cfg);
 
+        // Weigh the uses of values by how often they are expected to execute, so the spiller
+        // prefers to keep values used in loops in registers.
+        self.loops.compute(func, cfg, domtree);
+        self.block_freq.compute(func, cfg, domtree, &self.loops);
+        self.liveness.weigh_uses(func, &self.block_freq);
+
         if isa.flags().enable_verifier() {
             verify_liveness(isa, func, cfg, &self.liveness)?;
         }
@@ -154,7 +175,13 @@ impl Context {
             verify_liveness(isa, func, cfg, &self.liveness)?;
             verify_locations(isa, func, Some(&self.liveness))?;
             verify_cssa(func, cfg, domtree, &self.liveness, &self.virtregs)?;
+            if isa.flags().enable_regalloc_verifier() {
+                verify_interference(isa, func)?;
+            }
         }
+
+        // Record the GC references live across calls.
+        compute_safepoints(func, &self.liveness);
         Ok(())
     }
 }
@@ -192,9 +192,12 @@ impl LiveValueTracker {
             let ctx = liveness.context(layout);
             // Get just the values that are live-in to `ebb`.
             for &value in idom_live_list.as_slice(&self.idom_pool) {
-                let lr = liveness.get(value).expect(
-                    "Immediate dominator value has no live range",
-                );
+                // The reload pass removes the live ranges of rematerialized values after the set
+                // was saved.
+                let lr = match liveness.get(value) {
+                    Some(lr) => lr,
+                    None => continue,
+                };
 
                 // Check if this value is live-in here.
                 if let Some(endpoint) = lr.livein_local_end(ebb, ctx) {
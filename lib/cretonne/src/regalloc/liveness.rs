@@ -175,13 +175,15 @@
 //!
 //! There is some room for improvement.
 
-use entity::SparseMap;
+use block_frequency::BlockFrequency;
+use entity::{SparseMap, SparseMapValue};
 use flowgraph::ControlFlowGraph;
 use ir::dfg::ValueDef;
-use ir::{Function, Value, Inst, Ebb, Layout, ProgramPoint};
-use isa::{TargetIsa, EncInfo};
+use ir::{ArgumentLoc, Function, Value, Inst, Ebb, Layout, ProgramOrder, ProgramPoint};
+use isa::{TargetIsa, EncInfo, ConstraintKind, RegUnit};
 use regalloc::affinity::Affinity;
 use regalloc::liverange::{LiveRange, LiveRangeForest, LiveRangeContext};
+use std::cmp::Ordering;
 use std::mem;
 use std::ops::Index;
 use std::vec::Vec;
@@ -232,7 +234,7 @@ fn get_or_create<'a>(
                 } else {
                     // Give normal EBB parameters a register affinity matching their type.
                     let rc = isa.regclass_for_abi_type(func.dfg.value_type(value));
-                    affinity = Affinity::Reg(rc.into());
+                    affinity = Affinity::reg(rc.into());
                 }
             }
         };
@@ -378,6 +380,12 @@ impl Liveness {
         mem::replace(&mut lr.affinity, Affinity::Stack)
     }
 
+    /// Remove the live range for `value` after its definition and all its uses have been removed.
+    pub fn remove(&mut self, value: Value) {
+        let old = self.ranges.remove(value);
+        debug_assert!(old.is_some(), "{} has no live range", value);
+    }
+
 
     /// Compute the live ranges of all SSA values used in `func`.
     /// This clears out any existing analysis stored in this data structure.
@@ -444,6 +452,143 @@ impl Liveness {
                 }
             }
         }
+
+        self.compute_preferences(isa, func);
+    }
+
+    /// Scale the weights of the register preferences by the estimated frequency of the uses.
+    ///
+    /// `compute` counts every use of a value once. This counts a use in an EBB with the block
+    /// frequency weight `w` as `w` uses instead, so values used in loops are less likely to be
+    /// spilled than values used once outside them. The uses in EBBs that never ran according to
+    /// the profile of `func` aren't counted at all.
+    pub fn weigh_uses(&mut self, func: &Function, block_freq: &BlockFrequency) {
+        for ebb in func.layout.ebbs() {
+            if func.profile.never_executed(ebb) {
+                for inst in func.layout.ebb_insts(ebb) {
+                    for &arg in func.dfg.inst_args(inst) {
+                        if let Some(lr) = self.ranges.get_mut(arg) {
+                            lr.affinity.remove_use();
+                        }
+                    }
+                }
+                continue;
+            }
+            let extra = block_freq.weight(ebb) - 1;
+            if extra == 0 {
+                continue;
+            }
+            for inst in func.layout.ebb_insts(ebb) {
+                for &arg in func.dfg.inst_args(inst) {
+                    if let Some(lr) = self.ranges.get_mut(arg) {
+                        lr.affinity.add_weight(extra);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hint that `value` should be assigned the register `reg`.
+    fn set_hint(&mut self, value: Value, reg: RegUnit) {
+        if let Some(lr) = self.ranges.get_mut(value) {
+            lr.affinity.set_hint(reg);
+        }
+    }
+
+    /// Compute the preferences of the live ranges with a register affinity.
+    ///
+    /// The weight of a value is its number of uses, and values that are live across a call get the
+    /// `avoid_caller_saved` hint. Values used by a fixed register operand or an ABI argument in a
+    /// register are hinted towards that register, and the hint of a tied output is passed on to
+    /// the input it is tied to.
+    fn compute_preferences(&mut self, isa: &TargetIsa, func: &Function) {
+        let enc_info = isa.encoding_info();
+        let mut calls = Vec::new();
+        let mut tied = Vec::new();
+        for ebb in func.layout.ebbs() {
+            for inst in func.layout.ebb_insts(ebb) {
+                if func.dfg[inst].opcode().is_call() {
+                    calls.push(inst);
+                }
+                let args = func.dfg.inst_args(inst);
+                for &arg in args {
+                    if let Some(lr) = self.ranges.get_mut(arg) {
+                        lr.affinity.add_use();
+                    }
+                }
+
+                let constraints = match enc_info.operand_constraints(func.encodings[inst]) {
+                    Some(constraints) => constraints,
+                    None => continue,
+                };
+                for (op, &arg) in constraints.ins.iter().zip(args) {
+                    match op.kind {
+                        ConstraintKind::FixedReg(reg) |
+                        ConstraintKind::FixedTied(reg) => self.set_hint(arg, reg),
+                        _ => {}
+                    }
+                }
+                for (op, &def) in constraints.outs.iter().zip(func.dfg.inst_results(inst)) {
+                    if let ConstraintKind::Tied(num) = op.kind {
+                        tied.push((args[num as usize], def));
+                    }
+                }
+
+                let abi_types = if let Some(sig) = func.dfg.call_signature(inst) {
+                    &func.dfg.signatures[sig].params[..]
+                } else if func.dfg[inst].opcode().is_return() {
+                    &func.signature.returns[..]
+                } else {
+                    continue;
+                };
+                let var_args = args.get(constraints.ins.len()..).unwrap_or(&[]);
+                for (abi, &arg) in abi_types.iter().zip(var_args) {
+                    if let ArgumentLoc::Reg(reg) = abi.location {
+                        self.set_hint(arg, reg);
+                    }
+                }
+            }
+        }
+
+        // Visit the tied operands backwards so hints flow up through chains of tied defs.
+        for &(arg, def) in tied.iter().rev() {
+            if let Some(reg) = self.ranges.get(def).and_then(|lr| lr.affinity.hint()) {
+                self.set_hint(arg, reg);
+            }
+        }
+
+        if calls.is_empty() {
+            return;
+        }
+
+        let layout = &func.layout;
+        let forest = &self.forest;
+        // Is there a call strictly between `from` and `to` in the layout?
+        let call_between = |from: ProgramPoint, to: ProgramPoint| {
+            let first = match calls.binary_search_by(|&call| layout.cmp(call, from)) {
+                Ok(idx) => idx + 1,
+                Err(idx) => idx,
+            };
+            calls.get(first).map_or(false, |&call| {
+                layout.cmp(call, to) == Ordering::Less
+            })
+        };
+
+        let crossing: Vec<Value> = self.ranges
+            .values()
+            .filter(|lr| {
+                let ctx = LiveRangeContext::new(layout, forest);
+                lr.affinity.is_reg() &&
+                    (call_between(lr.def(), lr.def_local_end()) ||
+                         lr.liveins(ctx).any(|(ebb, end)| call_between(ebb.into(), end.into())))
+            })
+            .map(|lr| lr.key())
+            .collect();
+        for value in crossing {
+            if let Some(lr) = self.ranges.get_mut(value) {
+                lr.affinity.set_avoid_caller_saved();
+            }
+        }
     }
 }
 
@@ -8,13 +8,14 @@ pub mod allocatable_set;
 pub mod live_value_tracker;
 pub mod coloring;
 pub mod virtregs;
+pub mod affinity;
 
-mod affinity;
 mod coalescing;
 mod context;
 mod diversion;
 mod pressure;
 mod reload;
+mod safepoints;
 mod solver;
 mod spilling;
 
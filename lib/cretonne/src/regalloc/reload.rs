@@ -8,13 +8,17 @@
 //! The secondary responsibility of the reload pass is to reuse values in registers as much as
 //! possible to minimize the number of `fill` instructions needed. This must not cause the register
 //! pressure limits to be exceeded.
+//!
+//! Spilled values computed by a cheap pure instruction like `iconst` are rematerialized instead of
+//! filled: the defining instruction is copied in front of the use. When all the uses of such a
+//! value can be rematerialized, the original definition and its `spill` are removed too.
 
 use cursor::{Cursor, EncCursor};
 use dominator_tree::DominatorTree;
-use entity::{SparseMap, SparseMapValue};
-use ir::{Ebb, Inst, Value, Function};
+use entity::{EntitySet, SparseMap, SparseMapValue};
+use ir::{Ebb, Inst, Opcode, Value, ValueDef, Function};
 use ir::{InstBuilder, AbiParam, ArgumentLoc};
-use isa::RegClass;
+use isa::{RegClass, RegUnit};
 use isa::{TargetIsa, Encoding, EncInfo, RecipeConstraints, ConstraintKind};
 use regalloc::affinity::Affinity;
 use regalloc::live_value_tracker::{LiveValue, LiveValueTracker};
@@ -27,6 +31,8 @@ use std::vec::Vec;
 pub struct Reload {
     candidates: Vec<ReloadCandidate>,
     reloads: SparseMap<Value, ReloadedValue>,
+    stack_uses: EntitySet<Value>,
+    removed: Vec<Value>,
 }
 
 /// Context data structure that gets instantiated once per pass.
@@ -44,6 +50,15 @@ struct Context<'a> {
 
     candidates: &'a mut Vec<ReloadCandidate>,
     reloads: &'a mut SparseMap<Value, ReloadedValue>,
+
+    // Spilled values with uses that can't be rematerialized, so they must be stored on the stack.
+    stack_uses: &'a mut EntitySet<Value>,
+
+    // Rematerialized values whose definitions have been removed.
+    removed: &'a mut Vec<Value>,
+
+    // Does the function have any CPU flags values?
+    has_flags: bool,
 }
 
 impl Reload {
@@ -52,6 +67,8 @@ impl Reload {
         Self {
             candidates: Vec::new(),
             reloads: SparseMap::new(),
+            stack_uses: EntitySet::new(),
+            removed: Vec::new(),
         }
     }
 
@@ -59,6 +76,8 @@ impl Reload {
     pub fn clear(&mut self) {
         self.candidates.clear();
         self.reloads.clear();
+        self.stack_uses.clear();
+        self.removed.clear();
     }
 
     /// Run the reload algorithm over `func`.
@@ -81,6 +100,9 @@ impl Reload {
             topo,
             candidates: &mut self.candidates,
             reloads: &mut self.reloads,
+            stack_uses: &mut self.stack_uses,
+            removed: &mut self.removed,
+            has_flags: false,
         };
         ctx.run(tracker)
     }
@@ -94,6 +116,8 @@ struct ReloadCandidate {
     argidx: usize,
     value: Value,
     regclass: RegClass,
+    /// The register that the operand is fixed to, if any.
+    hint: Option<RegUnit>,
 }
 
 /// A Reloaded value.
@@ -112,10 +136,117 @@ impl SparseMapValue<Value> for ReloadedValue {
 
 impl<'a> Context<'a> {
     fn run(&mut self, tracker: &mut LiveValueTracker) {
+        self.find_stack_uses();
         self.topo.reset(self.cur.func.layout.ebbs());
         while let Some(ebb) = self.topo.next(&self.cur.func.layout, self.domtree) {
             self.visit_ebb(ebb, tracker);
         }
+
+        // The removed definitions were still needed by the live value tracker until now.
+        for value in self.removed.drain(..) {
+            self.liveness.remove(value);
+        }
+    }
+
+    /// Find the spilled values with uses that can't be rematerialized.
+    ///
+    /// These are the uses that don't need a register, like branch arguments, and the uses where
+    /// `can_remat()` fails.
+    fn find_stack_uses(&mut self) {
+        self.stack_uses.clear();
+        self.has_flags = self.cur.func.layout.ebbs().any(|ebb| {
+            self.cur.func.layout.ebb_insts(ebb).any(|inst| {
+                self.cur.func.dfg.inst_results(inst).iter().any(|&v| {
+                    self.cur.func.dfg.value_type(v).is_flags()
+                })
+            })
+        });
+
+        let mut ebb_pos = self.cur.func.layout.ebbs().next();
+        while let Some(ebb) = ebb_pos {
+            let mut inst_pos = self.cur.func.layout.first_inst(ebb);
+            while let Some(inst) = inst_pos {
+                let encoding = self.cur.func.encodings[inst];
+                if let Some(constraints) = self.encinfo.operand_constraints(encoding) {
+                    self.find_candidates(inst, constraints);
+                }
+                for (argidx, &arg) in self.cur.func.dfg.inst_args(inst).iter().enumerate() {
+                    if !self.liveness[arg].affinity.is_stack() {
+                        continue;
+                    }
+                    let remat = match self.candidates.iter().find(|c| c.argidx == argidx) {
+                        Some(cand) => self.can_remat(arg, cand.regclass, ebb, inst),
+                        None => false,
+                    };
+                    if !remat {
+                        self.stack_uses.insert(arg);
+                    }
+                }
+                self.candidates.clear();
+                inst_pos = self.cur.func.layout.next_inst(inst);
+            }
+            ebb_pos = self.cur.func.layout.next_ebb(ebb);
+        }
+    }
+
+    /// Get the instruction defining `value` if it can be rematerialized. Only cheap instructions
+    /// without side effects and with a single register result are rematerialized.
+    fn remat_def(&self, value: Value) -> Option<(Inst, &'static RecipeConstraints)> {
+        let def = match self.cur.func.dfg.value_def(value) {
+            ValueDef::Result(def, 0) => def,
+            _ => return None,
+        };
+        match self.cur.func.dfg[def].opcode() {
+            Opcode::Iconst | Opcode::Bconst | Opcode::F32const | Opcode::F64const |
+            Opcode::FuncAddr | Opcode::GlobalsymAddr | Opcode::IshlImm | Opcode::UshrImm |
+            Opcode::SshrImm => {}
+            _ => return None,
+        }
+        let constraints = self.encinfo.operand_constraints(self.cur.func.encodings[def])?;
+        // An encoding that clobbers the CPU flags can't be copied to where flags may be live.
+        if constraints.outs.len() != 1 || constraints.outs[0].kind != ConstraintKind::Reg ||
+            constraints.ins.iter().any(|op| op.kind != ConstraintKind::Reg) ||
+            (constraints.clobbers_flags && self.has_flags)
+        {
+            return None;
+        }
+        Some((def, constraints))
+    }
+
+    /// Can the spilled `value` be rematerialized in a `regclass` register before `inst`?
+    ///
+    /// The arguments of the defining instruction must still be available in registers at `inst`.
+    fn can_remat(&self, value: Value, regclass: RegClass, ebb: Ebb, inst: Inst) -> bool {
+        let (def, constraints) = match self.remat_def(value) {
+            Some(def) => def,
+            None => return false,
+        };
+        if constraints.outs[0].regclass != regclass {
+            return false;
+        }
+        let ctx = self.liveness.context(&self.cur.func.layout);
+        self.cur.func.dfg.inst_args(def).iter().all(|&arg| {
+            let lr = &self.liveness[arg];
+            !lr.affinity.is_stack() && lr.reaches_use(inst, ebb, ctx)
+        })
+    }
+
+    /// Insert a copy of the instruction defining `value` before `inst`, and return the new
+    /// register value.
+    fn insert_remat(&mut self, value: Value, affinity: Affinity, ebb: Ebb, inst: Inst) -> Value {
+        let def = self.cur.func.dfg.value_def(value).unwrap_inst();
+        let data = self.cur.func.dfg[def].clone();
+        let ctrl_typevar = self.cur.func.dfg.ctrl_typevar(def);
+        let remat = self.cur.func.dfg.make_inst(data);
+        self.cur.func.dfg.make_inst_results(remat, ctrl_typevar);
+        self.cur.insert_inst(remat);
+        self.cur.func.encodings[remat] = self.cur.func.encodings[def];
+        self.cur.func.srclocs[remat] = self.cur.func.srclocs[inst];
+
+        let reg = self.cur.func.dfg.first_result(remat);
+        self.liveness.create_dead(reg, remat, affinity);
+        self.liveness.extend_locally(reg, ebb, inst, &self.cur.func.layout);
+        reg
     }
 
     fn visit_ebb(&mut self, ebb: Ebb, tracker: &mut LiveValueTracker) {
@@ -208,30 +339,41 @@ impl<'a> Context<'a> {
         self.find_candidates(inst, constraints);
 
         // Insert fill instructions before `inst` and replace `cand.value` with the filled value.
-        for cand in self.candidates.iter_mut() {
-            if let Some(reload) = self.reloads.get(cand.value) {
-                cand.value = reload.reg;
+        for idx in 0..self.candidates.len() {
+            let (value, regclass) = (self.candidates[idx].value, self.candidates[idx].regclass);
+            if let Some(reload) = self.reloads.get(value) {
+                self.candidates[idx].value = reload.reg;
                 continue;
             }
 
-            let reg = self.cur.ins().fill(cand.value);
-            let fill = self.cur.built_inst();
+            // The reloaded value is a new live range, so it takes the register hint of its use.
+            let mut affinity = Affinity::reg(regclass.into());
+            if let Some(reg) = self.candidates[idx].hint {
+                affinity.set_hint(reg);
+            }
+
+            let reg = if self.can_remat(value, regclass, ebb, inst) {
+                self.insert_remat(value, affinity, ebb, inst)
+            } else {
+                let reg = self.cur.ins().fill(value);
+                let fill = self.cur.built_inst();
+
+                // Create a live range for the new reload.
+                self.liveness.create_dead(reg, fill, affinity);
+                self.liveness.extend_locally(
+                    reg,
+                    ebb,
+                    inst,
+                    &self.cur.func.layout,
+                );
+                reg
+            };
 
             self.reloads.insert(ReloadedValue {
-                stack: cand.value,
+                stack: value,
                 reg: reg,
             });
-            cand.value = reg;
-
-            // Create a live range for the new reload.
-            let affinity = Affinity::Reg(cand.regclass.into());
-            self.liveness.create_dead(reg, fill, affinity);
-            self.liveness.extend_locally(
-                reg,
-                ebb,
-                inst,
-                &self.cur.func.layout,
-            );
+            self.candidates[idx].value = reg;
         }
 
         // Rewrite instruction arguments.
@@ -270,6 +412,13 @@ impl<'a> Context<'a> {
         // That way, we don't need to rewrite all future uses of v2.
         for (lv, op) in defs.iter().zip(constraints.outs) {
             if lv.affinity.is_stack() && op.kind != ConstraintKind::Stack {
+                // All the uses are rematerialized, so the value is never stored.
+                if !self.stack_uses.contains(lv.value) && self.remat_def(lv.value).is_some() {
+                    dbg!("Removing rematerialized {}", lv.value);
+                    self.cur.func.layout.remove_inst(inst);
+                    self.removed.push(lv.value);
+                    continue;
+                }
                 let value_type = self.cur.func.dfg.value_type(lv.value);
                 let reg = self.cur.func.dfg.replace_result(lv.value, value_type);
                 self.liveness.create_dead(reg, inst, Affinity::new(op));
@@ -307,10 +456,16 @@ impl<'a> Context<'a> {
 
         for (argidx, (op, &arg)) in constraints.ins.iter().zip(args).enumerate() {
             if op.kind != ConstraintKind::Stack && self.liveness[arg].affinity.is_stack() {
+                let hint = match op.kind {
+                    ConstraintKind::FixedReg(reg) |
+                    ConstraintKind::FixedTied(reg) => Some(reg),
+                    _ => None,
+                };
                 self.candidates.push(ReloadCandidate {
                     argidx,
                     value: arg,
                     regclass: op.regclass,
+                    hint,
                 })
             }
         }
@@ -376,13 +531,14 @@ fn handle_abi_args(
 ) {
     debug_assert_eq!(abi_types.len(), var_args.len());
     for ((abi, &arg), argidx) in abi_types.iter().zip(var_args).zip(offset..) {
-        if abi.location.is_reg() {
+        if let ArgumentLoc::Reg(reg) = abi.location {
             let lv = liveness.get(arg).expect("Missing live range for ABI arg");
             if lv.affinity.is_stack() {
                 candidates.push(ReloadCandidate {
                     argidx,
                     value: arg,
                     regclass: isa.regclass_for_abi_type(abi.value_type),
+                    hint: Some(reg),
                 });
             }
         }
@@ -0,0 +1,67 @@
+//! Safepoint computation for precise garbage collection.
+//!
+//! Every call instruction is a safepoint where a garbage collector may need to find, and possibly
+//! update, the live references to GC objects. After register allocation, this pass records the GC
+//! references that are live across each call in `Function::safepoints`.
+//!
+//! The spiller never keeps a value in a register across a call, so all the recorded references
+//! are in stack slots where the collector can find them.
+
+use entity::EntitySet;
+use ir::{Function, Value, ValueLoc};
+use regalloc::liveness::Liveness;
+use std::vec::Vec;
+use timing;
+
+/// Compute the GC references that are live across each call in `func`.
+pub fn compute_safepoints(func: &mut Function, liveness: &Liveness) {
+    let _tt = timing::ra_safepoints();
+    func.safepoints.clear();
+    if func.gc_refs.is_empty() {
+        return;
+    }
+
+    // Optimizations may have turned some of the marked references into aliases. The values that
+    // are actually live are the alias targets.
+    let mut refs = EntitySet::<Value>::new();
+    for value in func.gc_refs.keys() {
+        if func.gc_refs.contains(value) && func.dfg.value_is_valid(value) {
+            refs.insert(func.dfg.resolve_aliases(value));
+        }
+    }
+    let candidates: Vec<Value> = refs.keys().filter(|&v| refs.contains(v)).collect();
+
+    let ctx = liveness.context(&func.layout);
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            if func.dfg.call_signature(inst).is_none() {
+                continue;
+            }
+
+            let mut live = Vec::new();
+            for &value in &candidates {
+                let lr = match liveness.get(value) {
+                    Some(lr) => lr,
+                    None => continue,
+                };
+                if lr.reaches_use(inst, ebb, ctx) && !lr.killed_at(inst, ebb, ctx) {
+                    debug_assert!(
+                        match func.locations[value] {
+                            ValueLoc::Stack(_) => true,
+                            _ => false,
+                        },
+                        "GC reference {} is not spilled across {}",
+                        value,
+                        func.dfg.display_inst(inst, None)
+                    );
+                    live.push(value);
+                }
+            }
+
+            if !live.is_empty() {
+                let list = &mut func.safepoints[inst];
+                list.extend(live, &mut func.dfg.value_lists);
+            }
+        }
+    }
+}
@@ -148,6 +148,9 @@ pub struct Variable {
 
     /// Any solution must belong to the constraint register class.
     constraint: RegClass,
+
+    /// Preferred register for a defined value, used when it is available.
+    hint: Option<RegUnit>,
 }
 
 impl Variable {
@@ -161,10 +164,16 @@ impl Variable {
             is_global: false,
             domain: 0,
             solution: !0,
+            hint: None,
         }
     }
 
-    fn new_def(value: Value, constraint: RegClass, is_global: bool) -> Variable {
+    fn new_def(
+        value: Value,
+        constraint: RegClass,
+        is_global: bool,
+        hint: Option<RegUnit>,
+    ) -> Variable {
         Variable {
             value,
             constraint,
@@ -174,6 +183,7 @@ impl Variable {
             is_global,
             domain: 0,
             solution: !0,
+            hint,
         }
     }
 
@@ -828,10 +838,17 @@ impl Solver {
     /// Add a defined output value.
     ///
     /// This is similar to `add_var`, except the value doesn't have a prior register assignment.
-    pub fn add_def(&mut self, value: Value, constraint: RegClass, is_global: bool) {
+    /// The `hint` register is assigned when it is available.
+    pub fn add_def(
+        &mut self,
+        value: Value,
+        constraint: RegClass,
+        is_global: bool,
+        hint: Option<RegUnit>,
+    ) {
         debug_assert!(self.inputs_done);
         self.vars.push(
-            Variable::new_def(value, constraint, is_global),
+            Variable::new_def(value, constraint, is_global, hint),
         );
     }
 
@@ -945,7 +962,10 @@ impl Solver {
 
         for v in &mut self.vars {
             let rc = v.constraint;
-            let reg = match v.iter(&iregs, &oregs, &gregs).next() {
+            let hint = v.hint.filter(|&hint| {
+                v.iter(&iregs, &oregs, &gregs).any(|reg| reg == hint)
+            });
+            let reg = match hint.or_else(|| v.iter(&iregs, &oregs, &gregs).next()) {
                 Some(reg) => reg,
                 None => {
                     // If `v` must avoid global interference, there is not point in requesting
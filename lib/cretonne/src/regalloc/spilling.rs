@@ -14,12 +14,23 @@
 //! 2. When the same value is used more than once by an instruction, the operand constraints must
 //!    be compatible. Otherwise, the value must be copied into a new register for some of the
 //!    operands.
+//!
+//! Spilled values whose live ranges don't overlap share a spill slot, so the size of the stack
+//! frame depends on the number of values that are simultaneously on the stack rather than on the
+//! total number of spilled values.
+//!
+//! With the `fast` register allocator setting, every value is spilled as soon as it is defined.
+//! Only the short live ranges created by the reload pass remain in registers, which makes the
+//! coloring pass cheap.
 
 use cursor::{Cursor, EncCursor};
 use dominator_tree::DominatorTree;
-use ir::{InstBuilder, Function, Ebb, Inst, Value, ValueLoc, SigRef};
+use ir::{InstBuilder, InstructionData, Function, Ebb, Inst, Opcode, Value, ValueLoc, SigRef,
+         StackSlot};
+use ir::stackslot::spill_size;
 use isa::registers::{RegClassMask, RegClassIndex};
 use isa::{TargetIsa, RegInfo, EncInfo, RecipeConstraints, ConstraintKind};
+use settings::Regalloc;
 use regalloc::affinity::Affinity;
 use regalloc::live_value_tracker::{LiveValue, LiveValueTracker};
 use regalloc::liveness::Liveness;
@@ -30,10 +41,25 @@ use std::vec::Vec;
 use timing;
 use topo_order::TopoOrder;
 
+/// The number of most recently created spill slots considered for reusing.
+const MAX_SLOT_CANDIDATES: usize = 16;
+
+/// Spill slots already holding this many values are not reused.
+///
+/// Together with `MAX_SLOT_CANDIDATES`, this bounds the number of interference checks per spill.
+const MAX_SLOT_VALUES: usize = 32;
+
 /// Persistent data structures for the spilling pass.
 pub struct Spilling {
     spills: Vec<Value>,
     reg_uses: Vec<RegUse>,
+    slots: Vec<SlotValues>,
+}
+
+/// The values assigned to a spill slot so far.
+struct SlotValues {
+    slot: StackSlot,
+    values: Vec<Value>,
 }
 
 /// Context data structure that gets instantiated once per pass.
@@ -54,6 +80,9 @@ struct Context<'a> {
     // Current register pressure.
     pressure: Pressure,
 
+    // Spill all values at their definition.
+    spill_all: bool,
+
     // Values spilled for the current instruction. These values have already been removed from the
     // pressure tracker, but they are still present in the live value tracker and their affinity
     // hasn't been changed yet.
@@ -61,6 +90,9 @@ struct Context<'a> {
 
     // Uses of register values in the current instruction.
     reg_uses: &'a mut Vec<RegUse>,
+
+    // Spill slots created in this function, and the values that have been assigned to them.
+    slots: &'a mut Vec<SlotValues>,
 }
 
 impl Spilling {
@@ -69,6 +101,7 @@ impl Spilling {
         Self {
             spills: Vec::new(),
             reg_uses: Vec::new(),
+            slots: Vec::new(),
         }
     }
 
@@ -76,6 +109,7 @@ impl Spilling {
     pub fn clear(&mut self) {
         self.spills.clear();
         self.reg_uses.clear();
+        self.slots.clear();
     }
 
     /// Run the spilling algorithm over `func`.
@@ -102,9 +136,12 @@ impl Spilling {
             virtregs,
             topo,
             pressure: Pressure::new(&reginfo, &usable_regs),
+            spill_all: isa.flags().regalloc() == Regalloc::Fast,
             spills: &mut self.spills,
             reg_uses: &mut self.reg_uses,
+            slots: &mut self.slots,
         };
+        ctx.slots.clear();
         ctx.run(tracker)
     }
 }
@@ -145,7 +182,7 @@ impl<'a> Context<'a> {
     fn take_live_regs(&mut self, regs: &[LiveValue]) {
         for lv in regs {
             if !lv.is_dead {
-                if let Affinity::Reg(rci) = lv.affinity {
+                if let Affinity::Reg(rci, _) = lv.affinity {
                     let rc = self.reginfo.rc(rci);
                     self.pressure.take(rc);
                 }
@@ -156,7 +193,7 @@ impl<'a> Context<'a> {
     // Free all registers in `kills` from the pressure set.
     fn free_regs(&mut self, kills: &[LiveValue]) {
         for lv in kills {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 if !self.spills.contains(&lv.value) {
                     let rc = self.reginfo.rc(rci);
                     self.pressure.free(rc);
@@ -169,7 +206,7 @@ impl<'a> Context<'a> {
     fn free_dead_regs(&mut self, regs: &[LiveValue]) {
         for lv in regs {
             if lv.is_dead {
-                if let Affinity::Reg(rci) = lv.affinity {
+                if let Affinity::Reg(rci, _) = lv.affinity {
                     if !self.spills.contains(&lv.value) {
                         let rc = self.reginfo.rc(rci);
                         self.pressure.free(rc);
@@ -196,7 +233,7 @@ impl<'a> Context<'a> {
         // An EBB can have an arbitrary (up to 2^16...) number of parameters, so they are not
         // guaranteed to fit in registers.
         for lv in params {
-            if let Affinity::Reg(rci) = lv.affinity {
+            if let Affinity::Reg(rci, _) = lv.affinity {
                 let rc = self.reginfo.rc(rci);
                 'try_take: while let Err(mask) = self.pressure.take_transient(rc) {
                     dbg!("Need {} reg for EBB param {}", rc, lv.value);
@@ -228,6 +265,9 @@ impl<'a> Context<'a> {
 
         // The transient pressure counts for the EBB arguments are accurate. Just preserve them.
         self.pressure.preserve_transient();
+        if self.spill_all {
+            self.spill_defs(params);
+        }
         self.free_dead_regs(params);
     }
 
@@ -259,6 +299,23 @@ impl<'a> Context<'a> {
         // Update the live value tracker with this instruction.
         let (throughs, kills, defs) = tracker.process_inst(inst, &self.cur.func.dfg, self.liveness);
 
+        // The values live in to the landing pad of an `invoke` must survive the call, even when
+        // the call kills them on the normal path. The unwinder only preserves stack slots and
+        // callee-saved registers, so spill them like the values that are live across the call.
+        if call_sig.is_some() {
+            if let Some(dest) = self.cur.func.dfg[inst].branch_destination() {
+                for lv in kills {
+                    let livein = self.liveness[lv.value].is_livein(
+                        dest,
+                        self.liveness.context(&self.cur.func.layout),
+                    );
+                    if livein && lv.affinity.is_reg() && !self.spills.contains(&lv.value) {
+                        self.spill_reg(lv.value);
+                    }
+                }
+            }
+        }
+
         // Remove kills from the pressure tracker.
         self.free_regs(kills);
 
@@ -300,6 +357,30 @@ impl<'a> Context<'a> {
         // Exclude dead defs. Includes call return values.
         // This won't cause spilling.
         self.take_live_regs(defs);
+        if self.spill_all {
+            self.spill_defs(defs);
+        }
+    }
+
+    // Spill all the live register values in `defs` that can be stored on the stack.
+    fn spill_defs(&mut self, defs: &[LiveValue]) {
+        for lv in defs {
+            if !lv.is_dead && lv.affinity.is_reg() && !self.spills.contains(&lv.value) &&
+                self.can_spill(lv.value)
+            {
+                self.spill_reg(lv.value);
+            }
+        }
+    }
+
+    // Does the ISA have an encoding for spilling `value`?
+    fn can_spill(&self, value: Value) -> bool {
+        let data = InstructionData::Unary {
+            opcode: Opcode::Spill,
+            arg: value,
+        };
+        let ty = self.cur.func.dfg.value_type(value);
+        self.cur.isa.encode(&self.cur.func.dfg, &data, ty).is_ok()
     }
 
     // Collect register uses that are noteworthy in one of the following ways:
@@ -358,7 +439,7 @@ impl<'a> Context<'a> {
         {
             if abi.location.is_reg() {
                 let (rci, spilled) = match self.liveness[arg].affinity {
-                    Affinity::Reg(rci) => (rci, false),
+                    Affinity::Reg(rci, _) => (rci, false),
                     Affinity::Stack => {
                         (
                             self.cur.isa.regclass_for_abi_type(abi.value_type).into(),
@@ -422,7 +503,8 @@ impl<'a> Context<'a> {
                     // at the branch destination. It is also necessary since there can be
                     // arbitrarily many EBB arguments.
                     match {
-                        let args = if self.cur.func.dfg[inst].opcode().is_branch() {
+                        let opcode = self.cur.func.dfg[inst].opcode();
+                        let args = if opcode.is_branch() && !opcode.is_call() {
                             self.cur.func.dfg.inst_fixed_args(inst)
                         } else {
                             self.cur.func.dfg.inst_args(inst)
@@ -455,9 +537,10 @@ impl<'a> Context<'a> {
     {
         // Find the best viable spill candidate.
         //
-        // The very simple strategy implemented here is to spill the value with the earliest def in
-        // the reverse post-order. This strategy depends on a good reload pass to generate good
-        // code.
+        // Values that are live across a call are spilled first since they will be spilled at the
+        // call anyway. Then we prefer values with fewer register uses since they need fewer
+        // reloads. Among equals, spill the value with the earliest def in the reverse
+        // post-order. This strategy depends on a good reload pass to generate good code.
         //
         // We know that all candidate defs dominate the current instruction, so one of them will
         // dominate the others. That is the earliest def.
@@ -466,23 +549,30 @@ impl<'a> Context<'a> {
             .filter_map(|lv| {
                 // Viable candidates are registers in one of the `mask` classes, and not already in
                 // the spill set.
-                if let Affinity::Reg(rci) = lv.affinity {
+                if let Affinity::Reg(rci, pref) = lv.affinity {
                     let rc = self.reginfo.rc(rci);
                     if (mask & (1 << rc.toprc)) != 0 && !self.spills.contains(&lv.value) {
                         // Here, `lv` is a viable spill candidate.
-                        return Some(lv.value);
+                        return Some((lv.value, pref));
                     }
                 }
                 None
             })
-            .min_by(|&a, &b| {
-                // Find the minimum candidate according to the RPO of their defs.
-                self.domtree.rpo_cmp(
-                    self.cur.func.dfg.value_def(a),
-                    self.cur.func.dfg.value_def(b),
-                    &self.cur.func.layout,
-                )
+            .min_by(|&(a, pref_a), &(b, pref_b)| {
+                pref_b
+                    .avoid_caller_saved
+                    .cmp(&pref_a.avoid_caller_saved)
+                    .then(pref_a.weight.cmp(&pref_b.weight))
+                    .then_with(|| {
+                        // Compare the RPO of the defs.
+                        self.domtree.rpo_cmp(
+                            self.cur.func.dfg.value_def(a),
+                            self.cur.func.dfg.value_def(b),
+                            &self.cur.func.layout,
+                        )
+                    })
             })
+            .map(|(value, _)| value)
     }
 
     /// Spill `value` immediately by
@@ -494,7 +584,7 @@ impl<'a> Context<'a> {
     /// Note that this does not update the cached affinity in the live value tracker. Call
     /// `process_spills` to do that.
     fn spill_reg(&mut self, value: Value) {
-        if let Affinity::Reg(rci) = self.liveness.spill(value) {
+        if let Affinity::Reg(rci, _) = self.liveness.spill(value) {
             let rc = self.reginfo.rc(rci);
             self.pressure.free(rc);
             self.spills.push(value);
@@ -504,15 +594,75 @@ impl<'a> Context<'a> {
         }
 
         // Assign a spill slot for the whole virtual register.
-        let ss = self.cur.func.stack_slots.make_spill_slot(
-            self.cur.func.dfg.value_type(value),
-        );
+        let ss = self.assign_spill_slot(value);
         for &v in self.virtregs.congruence_class(&value) {
             self.liveness.spill(v);
             self.cur.func.locations[v] = ValueLoc::Stack(ss);
         }
     }
 
+    /// Find a spill slot for the virtual register containing `value`.
+    ///
+    /// Reuse a recent spill slot of the right size if none of the values already assigned to it
+    /// are live at the same time as the virtual register. Otherwise create a new spill slot.
+    fn assign_spill_slot(&mut self, value: Value) -> StackSlot {
+        let class = self.virtregs.congruence_class(&value);
+        let size = spill_size(self.cur.func.dfg.value_type(value));
+
+        // Skip the search when all values are spilled. Compile time matters more than frame size
+        // then.
+        let reuse = if self.spill_all {
+            None
+        } else {
+            self.slots
+                .iter()
+                .enumerate()
+                .rev()
+                .take(MAX_SLOT_CANDIDATES)
+                .find(|&(_, sv)| {
+                    sv.values.len() < MAX_SLOT_VALUES &&
+                        self.cur.func.stack_slots[sv.slot].size == size &&
+                        !sv.values.iter().any(|&a| {
+                            class.iter().any(|&b| self.interferes(a, b))
+                        })
+                })
+                .map(|(idx, _)| idx)
+        };
+
+        match reuse {
+            Some(idx) => {
+                let sv = &mut self.slots[idx];
+                dbg!("Reusing {} for {}", sv.slot, value);
+                sv.values.extend_from_slice(class);
+                sv.slot
+            }
+            None => {
+                let slot = self.cur.func.stack_slots.make_spill_slot(
+                    self.cur.func.dfg.value_type(value),
+                );
+                self.slots.push(SlotValues {
+                    slot,
+                    values: class.to_vec(),
+                });
+                slot
+            }
+        }
+    }
+
+    /// Do the live ranges of `a` and `b` overlap?
+    ///
+    /// In SSA form, two overlapping live ranges always overlap at the definition of one of the
+    /// values.
+    fn interferes(&self, a: Value, b: Value) -> bool {
+        let ctx = self.liveness.context(&self.cur.func.layout);
+        let lr_a = &self.liveness[a];
+        let lr_b = &self.liveness[b];
+        let def_a = lr_a.def();
+        let def_b = lr_b.def();
+        lr_a.overlaps_def(def_b.into(), self.cur.func.layout.pp_ebb(def_b), ctx) ||
+            lr_b.overlaps_def(def_a.into(), self.cur.func.layout.pp_ebb(def_a), ctx)
+    }
+
     /// Process any pending spills in the `self.spills` vector.
     ///
     /// It is assumed that spills are removed from the pressure tracker immediately, see
@@ -536,7 +686,12 @@ impl<'a> Context<'a> {
         let inst = self.cur.built_inst();
 
         // Update live ranges.
-        self.liveness.create_dead(copy, inst, Affinity::Reg(rci));
+        // The copy splits the live range of `value`, so it keeps the same register hint.
+        let mut affinity = Affinity::reg(rci);
+        if let Some(reg) = self.liveness[value].affinity.hint() {
+            affinity.set_hint(reg);
+        }
+        self.liveness.create_dead(copy, inst, affinity);
         self.liveness.extend_locally(
             copy,
             self.cur.func.layout.pp_ebb(inst),
@@ -1,8 +1,11 @@
 //! Result and error types representing the outcome of compiling a function.
 
+use legalizer::LegalizerError;
 use verifier;
+use std::any::Any;
 use std::error::Error as StdError;
 use std::fmt;
+use std::string::{String, ToString};
 
 /// A compilation error.
 ///
@@ -15,11 +18,17 @@ pub enum CtonError {
     /// code. This should never happen for validated WebAssembly code.
     InvalidInput,
 
-    /// An IL verifier error.
+    /// IL verifier errors.
     ///
     /// This always represents a bug, either in the code that generated IL for Cretonne, or a bug
     /// in Cretonne itself.
-    Verifier(verifier::Error),
+    Verifier(verifier::VerifierErrors),
+
+    /// The legalizer didn't converge.
+    ///
+    /// The legalization patterns for the target ISA kept producing illegal instructions. This
+    /// always represents a bug in Cretonne.
+    Legalizer(LegalizerError),
 
     /// An implementation limit was exceeded.
     ///
@@ -34,6 +43,12 @@ pub enum CtonError {
     /// Different target ISAs may impose a limit on the size of a compiled function. If that limit
     /// is exceeded, compilation fails.
     CodeTooLarge,
+
+    /// Cretonne panicked while compiling the function.
+    ///
+    /// Only `Context::try_compile` reports panics as errors. This always represents a bug in
+    /// Cretonne.
+    Panic(String),
 }
 
 /// A Cretonne compilation result.
@@ -43,6 +58,8 @@ impl fmt::Display for CtonError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             CtonError::Verifier(ref e) => write!(f, "Verifier error: {}", e),
+            CtonError::Legalizer(ref e) => write!(f, "Legalizer error: {}", e),
+            CtonError::Panic(ref msg) => write!(f, "Panic: {}", msg),
             CtonError::InvalidInput |
             CtonError::ImplLimitExceeded |
             CtonError::CodeTooLarge => f.write_str(self.description()),
@@ -54,23 +71,109 @@ impl StdError for CtonError {
     fn description(&self) -> &str {
         match *self {
             CtonError::InvalidInput => "Invalid input code",
-            CtonError::Verifier(ref e) => &e.message,
+            CtonError::Verifier(ref e) => e.description(),
+            CtonError::Legalizer(ref e) => e.description(),
             CtonError::ImplLimitExceeded => "Implementation limit exceeded",
             CtonError::CodeTooLarge => "Code for function is too large",
+            CtonError::Panic(ref msg) => msg,
         }
     }
     fn cause(&self) -> Option<&StdError> {
         match *self {
             CtonError::Verifier(ref e) => Some(e),
+            CtonError::Legalizer(ref e) => Some(e),
             CtonError::InvalidInput |
             CtonError::ImplLimitExceeded |
-            CtonError::CodeTooLarge => None,
+            CtonError::CodeTooLarge |
+            CtonError::Panic(_) => None,
         }
     }
 }
 
-impl From<verifier::Error> for CtonError {
-    fn from(e: verifier::Error) -> CtonError {
+impl From<verifier::VerifierErrors> for CtonError {
+    fn from(e: verifier::VerifierErrors) -> CtonError {
         CtonError::Verifier(e)
     }
 }
+
+impl From<verifier::VerifierError> for CtonError {
+    fn from(e: verifier::VerifierError) -> CtonError {
+        CtonError::Verifier(e.into())
+    }
+}
+
+impl From<LegalizerError> for CtonError {
+    fn from(e: LegalizerError) -> CtonError {
+        CtonError::Legalizer(e)
+    }
+}
+
+/// A failed compilation, with the information needed to report it.
+///
+/// This is returned by `Context::try_compile`, which reports panics as errors too.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompileError {
+    /// What went wrong.
+    pub error: CtonError,
+
+    /// The description of the top-level pass that failed, as in the timing report, if the failure
+    /// happened in a pass.
+    pub pass: Option<&'static str>,
+
+    /// The function in the text format as it was when compilation stopped, if the
+    /// `snapshot_ir_on_error` setting is enabled and the function could be printed.
+    pub ir: Option<String>,
+}
+
+impl CompileError {
+    /// Get the verifier errors, if verification failed.
+    pub fn verifier_errors(&self) -> Option<&verifier::VerifierErrors> {
+        match self.error {
+            CtonError::Verifier(ref errors) => Some(errors),
+            _ => None,
+        }
+    }
+
+    /// Did Cretonne panic?
+    pub fn is_panic(&self) -> bool {
+        match self.error {
+            CtonError::Panic(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pass {
+            Some(pass) => write!(f, "{} failed: {}", pass, self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl StdError for CompileError {
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+    fn cause(&self) -> Option<&StdError> {
+        Some(&self.error)
+    }
+}
+
+impl From<CompileError> for CtonError {
+    fn from(e: CompileError) -> CtonError {
+        e.error
+    }
+}
+
+/// Get the message from a panic payload.
+pub(crate) fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,415 @@
+//! Declarative rewrite rules for simple peephole optimizations.
+//!
+//! Each rule in the `RULES` table pairs a pattern with a replacement, written as trees of
+//! instructions like `imul_imm(x, 2^n) => ishl_imm(x, n)`. The rule engine matches the patterns
+//! against the data flow graph, and the first rule that matches an instruction replaces it. Rules
+//! are reapplied to the replacement until no more rules match.
+//!
+//! Patterns and replacements only handle the `Unary`, `UnaryImm`, `Binary`, and `BinaryImm`
+//! instruction formats. The operands of an instruction are its value arguments followed by its
+//! immediate, if any. A constant pattern matches both an immediate and a value defined by an
+//! `iconst` instruction.
+
+use cursor::FuncCursor;
+use fold::normalize;
+use ir::dfg::ValueDef;
+use ir::immediates::Imm64;
+use ir::instructions::{InstructionFormat, Opcode};
+use ir::{DataFlowGraph, Inst, InstBuilder, InstructionData, Type, Value};
+
+/// A variable in a rule, bound to a value or an immediate by the pattern.
+type Var = usize;
+
+/// The maximum number of variables in a rule.
+const MAX_VARS: usize = 2;
+
+/// A pattern matching an operand.
+enum Pattern {
+    /// Any value, bound to a variable. If the variable is already bound, the value must be the
+    /// same.
+    Value(Var),
+
+    /// An immediate or an `iconst` value equal to a constant.
+    Const(i64),
+
+    /// Any immediate or `iconst` value, bound to a variable.
+    AnyConst(Var),
+
+    /// A positive power of two immediate or `iconst` value. The variable is bound to its log2.
+    Pow2(Var),
+
+    /// A value defined by an instruction matching the opcode and operand patterns.
+    Inst(Opcode, &'static [Pattern]),
+}
+
+/// A replacement operand.
+enum Replacement {
+    /// The value bound to a variable.
+    Value(Var),
+
+    /// The immediate bound to a variable.
+    Imm(Var),
+
+    /// A constant immediate.
+    Const(i64),
+
+    /// A new instruction with the given opcode and operands.
+    Inst(Opcode, &'static [Replacement]),
+}
+
+/// A rewrite rule. The pattern must be an `Inst` pattern.
+struct Rule {
+    pattern: Pattern,
+    replacement: Replacement,
+}
+
+/// Match any value.
+const X: Pattern = Pattern::Value(0);
+/// Match any constant.
+const K: Pattern = Pattern::AnyConst(1);
+/// Match a power of two.
+const N: Pattern = Pattern::Pow2(1);
+
+/// Use the value matched by `X`.
+const RX: Replacement = Replacement::Value(0);
+/// Use the immediate matched by `K` or `N`.
+const RK: Replacement = Replacement::Imm(1);
+
+/// Create a rule `pattern => replacement`.
+macro_rules! rule {
+    ($op:ident($($pat:expr),*) => $rep:expr) => {
+        Rule {
+            pattern: Pattern::Inst(Opcode::$op, &[$($pat),*]),
+            replacement: $rep,
+        }
+    }
+}
+
+/// Create a replacement instruction.
+macro_rules! inst {
+    ($op:ident($($rep:expr),*)) => {
+        Replacement::Inst(Opcode::$op, &[$($rep),*])
+    }
+}
+
+/// The rewrite rules, in priority order.
+static RULES: &[Rule] = &[
+    // Identities.
+    rule!(IaddImm(X, Pattern::Const(0)) => RX),
+    rule!(Iadd(X, Pattern::Const(0)) => RX),
+    rule!(Iadd(Pattern::Const(0), X) => RX),
+    rule!(Isub(X, Pattern::Const(0)) => RX),
+    rule!(ImulImm(X, Pattern::Const(1)) => RX),
+    rule!(BandImm(X, Pattern::Const(-1)) => RX),
+    rule!(BorImm(X, Pattern::Const(0)) => RX),
+    rule!(BxorImm(X, Pattern::Const(0)) => RX),
+    rule!(IshlImm(X, Pattern::Const(0)) => RX),
+    rule!(UshrImm(X, Pattern::Const(0)) => RX),
+    rule!(SshrImm(X, Pattern::Const(0)) => RX),
+    rule!(Band(X, X) => RX),
+    rule!(Bor(X, X) => RX),
+
+    // Constant results.
+    rule!(ImulImm(X, Pattern::Const(0)) => inst!(Iconst(Replacement::Const(0)))),
+    rule!(BandImm(X, Pattern::Const(0)) => inst!(Iconst(Replacement::Const(0)))),
+    rule!(Isub(X, X) => inst!(Iconst(Replacement::Const(0)))),
+    rule!(Bxor(X, X) => inst!(Iconst(Replacement::Const(0)))),
+
+    // Strength reduction.
+    rule!(ImulImm(X, N) => inst!(IshlImm(RX, RK))),
+
+    // Use immediate forms for constant operands.
+    rule!(Iadd(X, K) => inst!(IaddImm(RX, RK))),
+    rule!(Iadd(K, X) => inst!(IaddImm(RX, RK))),
+    rule!(Imul(X, K) => inst!(ImulImm(RX, RK))),
+    rule!(Imul(K, X) => inst!(ImulImm(RX, RK))),
+];
+
+/// An operand of an instruction.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Value(Value),
+    Imm(i64),
+}
+
+/// Get the operands of `inst` if it has one of the supported formats.
+fn operands(dfg: &DataFlowGraph, inst: Inst) -> Option<([Operand; 2], usize)> {
+    let dummy = Operand::Imm(0);
+    Some(match dfg[inst] {
+        InstructionData::Unary { arg, .. } => ([Operand::Value(arg), dummy], 1),
+        InstructionData::UnaryImm { imm, .. } => ([Operand::Imm(imm.into()), dummy], 1),
+        InstructionData::Binary { args, .. } => {
+            ([Operand::Value(args[0]), Operand::Value(args[1])], 2)
+        }
+        InstructionData::BinaryImm { arg, imm, .. } => {
+            ([Operand::Value(arg), Operand::Imm(imm.into())], 2)
+        }
+        _ => return None,
+    })
+}
+
+/// Variable bindings made while matching a pattern.
+type Bindings = [Option<Operand>; MAX_VARS];
+
+/// Bind `var` to `operand`, or check that it is already bound to the same operand.
+fn bind(bindings: &mut Bindings, var: Var, operand: Operand) -> bool {
+    match bindings[var] {
+        Some(bound) => bound == operand,
+        None => {
+            bindings[var] = Some(operand);
+            true
+        }
+    }
+}
+
+/// Get the constant value of `operand` as a `ty` immediate.
+fn constant(dfg: &DataFlowGraph, operand: Operand, ty: Type) -> Option<i64> {
+    let c = match operand {
+        Operand::Imm(imm) => imm,
+        Operand::Value(value) => {
+            let inst = match dfg.value_def(value) {
+                ValueDef::Result(inst, _) => inst,
+                ValueDef::Param(..) => return None,
+            };
+            match dfg[inst] {
+                InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => imm.into(),
+                _ => return None,
+            }
+        }
+    };
+    Some(normalize(c, ty))
+}
+
+/// Match `pattern` against `operand` of an instruction with controlling type `ty`.
+fn match_pattern(
+    dfg: &DataFlowGraph,
+    pattern: &Pattern,
+    operand: Operand,
+    ty: Type,
+    bindings: &mut Bindings,
+) -> bool {
+    let operand = match operand {
+        Operand::Value(value) => Operand::Value(dfg.resolve_aliases(value)),
+        imm => imm,
+    };
+    match *pattern {
+        Pattern::Value(var) => {
+            match operand {
+                Operand::Value(_) => bind(bindings, var, operand),
+                Operand::Imm(_) => false,
+            }
+        }
+        Pattern::Const(c) => constant(dfg, operand, ty) == Some(normalize(c, ty)),
+        Pattern::AnyConst(var) => {
+            match constant(dfg, operand, ty) {
+                Some(c) => bind(bindings, var, Operand::Imm(c)),
+                None => false,
+            }
+        }
+        Pattern::Pow2(var) => {
+            match constant(dfg, operand, ty) {
+                Some(c) if c > 0 && c & (c - 1) == 0 && c.trailing_zeros() < ty.bits().into() => {
+                    bind(bindings, var, Operand::Imm(c.trailing_zeros().into()))
+                }
+                _ => false,
+            }
+        }
+        Pattern::Inst(opcode, pats) => {
+            let value = match operand {
+                Operand::Value(value) => value,
+                Operand::Imm(_) => return false,
+            };
+            match dfg.value_def(value) {
+                ValueDef::Result(inst, 0) => match_inst(dfg, inst, opcode, pats, bindings),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Match the instruction `inst` against an instruction pattern.
+fn match_inst(
+    dfg: &DataFlowGraph,
+    inst: Inst,
+    opcode: Opcode,
+    pats: &[Pattern],
+    bindings: &mut Bindings,
+) -> bool {
+    if dfg[inst].opcode() != opcode || dfg.inst_results(inst).len() != 1 {
+        return false;
+    }
+    let (ops, num_ops) = match operands(dfg, inst) {
+        Some(ops) => ops,
+        None => return false,
+    };
+    let ty = dfg.ctrl_typevar(inst);
+    num_ops == pats.len() &&
+        pats.iter().zip(&ops[..num_ops]).all(|(pat, &op)| {
+            match_pattern(dfg, pat, op, ty, bindings)
+        })
+}
+
+/// Evaluate a replacement operand.
+///
+/// New instructions are inserted at `pos`, except the root instruction which replaces `root` when
+/// it is given.
+fn build(
+    pos: &mut FuncCursor,
+    replacement: &Replacement,
+    ty: Type,
+    bindings: &Bindings,
+    root: Option<Inst>,
+) -> Operand {
+    let (opcode, reps) = match *replacement {
+        Replacement::Value(var) |
+        Replacement::Imm(var) => return bindings[var].expect("Unbound variable in rule"),
+        Replacement::Const(c) => return Operand::Imm(c),
+        Replacement::Inst(opcode, reps) => (opcode, reps),
+    };
+
+    let mut args = [Operand::Imm(0); 2];
+    for (arg, rep) in args.iter_mut().zip(reps) {
+        *arg = build(pos, rep, ty, bindings, None);
+    }
+    let value = |op| match op {
+        Operand::Value(value) => value,
+        Operand::Imm(_) => panic!("Expected a value operand in rule"),
+    };
+    let imm = |op| match op {
+        Operand::Imm(imm) => Imm64::new(imm),
+        Operand::Value(_) => panic!("Expected an immediate operand in rule"),
+    };
+
+    let (inst, dfg) = match root {
+        Some(root) => {
+            let builder = pos.func.dfg.replace(root);
+            match opcode.format() {
+                InstructionFormat::Unary => builder.Unary(opcode, ty, value(args[0])),
+                InstructionFormat::UnaryImm => builder.UnaryImm(opcode, ty, imm(args[0])),
+                InstructionFormat::Binary => {
+                    builder.Binary(opcode, ty, value(args[0]), value(args[1]))
+                }
+                InstructionFormat::BinaryImm => {
+                    builder.BinaryImm(opcode, ty, imm(args[1]), value(args[0]))
+                }
+                _ => panic!("Unsupported format for {} in rule", opcode),
+            }
+        }
+        None => {
+            let builder = pos.ins();
+            match opcode.format() {
+                InstructionFormat::Unary => builder.Unary(opcode, ty, value(args[0])),
+                InstructionFormat::UnaryImm => builder.UnaryImm(opcode, ty, imm(args[0])),
+                InstructionFormat::Binary => {
+                    builder.Binary(opcode, ty, value(args[0]), value(args[1]))
+                }
+                InstructionFormat::BinaryImm => {
+                    builder.BinaryImm(opcode, ty, imm(args[1]), value(args[0]))
+                }
+                _ => panic!("Unsupported format for {} in rule", opcode),
+            }
+        }
+    };
+    Operand::Value(dfg.first_result(inst))
+}
+
+/// Apply the first matching rule to `inst` which `pos` points at.
+///
+/// Returns true if the instruction was rewritten.
+fn apply_rule(pos: &mut FuncCursor, inst: Inst) -> bool {
+    let ty = pos.func.dfg.ctrl_typevar(inst);
+    for rule in RULES.iter() {
+        let mut bindings = [None; MAX_VARS];
+        let matched = match rule.pattern {
+            Pattern::Inst(opcode, pats) => {
+                match_inst(&pos.func.dfg, inst, opcode, pats, &mut bindings)
+            }
+            _ => panic!("Rule pattern must be an instruction"),
+        };
+        if !matched {
+            continue;
+        }
+
+        match rule.replacement {
+            Replacement::Inst(..) => {
+                build(pos, &rule.replacement, ty, &bindings, Some(inst));
+            }
+            _ => {
+                match build(pos, &rule.replacement, ty, &bindings, None) {
+                    Operand::Value(value) => {
+                        pos.func.dfg.replace(inst).copy(value);
+                    }
+                    Operand::Imm(imm) => {
+                        pos.func.dfg.replace(inst).iconst(ty, imm);
+                    }
+                }
+            }
+        }
+        return true;
+    }
+    false
+}
+
+/// Rewrite `inst` which `pos` points at by applying rules until no rule matches.
+///
+/// Returns true if the instruction was rewritten.
+pub fn rewrite_inst(pos: &mut FuncCursor, inst: Inst) -> bool {
+    let mut changed = false;
+    while apply_rule(pos, inst) {
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::Cursor;
+    use ir::types::I32;
+    use ir::{Function, InstBuilder};
+
+    /// Apply the rules to the last instruction built by `f` and return it as a string.
+    fn rewrite<F: FnOnce(&mut FuncCursor, Value) -> Value>(f: F) -> String {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_ebb(ebb0);
+        let value = f(&mut pos, v0);
+        let inst = match pos.func.dfg.value_def(value) {
+            ValueDef::Result(inst, _) => inst,
+            ValueDef::Param(..) => panic!(),
+        };
+        pos.goto_inst(inst);
+        rewrite_inst(&mut pos, inst);
+        pos.func.dfg.display_inst(inst, None).to_string()
+    }
+
+    #[test]
+    fn identities() {
+        assert_eq!(rewrite(|pos, x| pos.ins().iadd_imm(x, 0)), "v1 = copy.i32 v0");
+        assert_eq!(rewrite(|pos, x| pos.ins().isub(x, x)), "v1 = iconst.i32 0");
+        assert_eq!(
+            rewrite(|pos, x| pos.ins().band_imm(x, 0xffff_ffff)),
+            "v1 = copy.i32 v0"
+        );
+    }
+
+    #[test]
+    fn fixpoint() {
+        // imul(x, 8) => imul_imm(x, 8) => ishl_imm(x, 3)
+        assert_eq!(
+            rewrite(|pos, x| {
+                let k = pos.ins().iconst(I32, 8);
+                pos.ins().imul(x, k)
+            }),
+            "v2 = ishl_imm.i32 v0, 3"
+        );
+
+        // The constant is truncated to the type.
+        assert_eq!(
+            rewrite(|pos, x| pos.ins().imul_imm(x, 1 << 40)),
+            "v1 = iconst.i32 0"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Sparse conditional constant propagation.
+//!
+//! This pass finds the integer and boolean values that are constant on all the executable paths
+//! through a function, assuming optimistically that EBBs are unreachable until a branch to them
+//! is found to be executable. This lets constants flow through EBB parameters, and branches with
+//! constant conditions prune the EBBs that can't be reached.
+//!
+//! The function is then rewritten:
+//!
+//! - Instructions computing a constant are replaced with `iconst` or `bconst`.
+//! - EBB parameters that are constant are removed along with the corresponding branch arguments.
+//! - Conditional branches with a constant condition become jumps or are removed.
+//! - EBBs that can't be reached are removed.
+
+use cursor::{Cursor, FuncCursor};
+use entity::EntityMap;
+use flowgraph::ControlFlowGraph;
+use fold;
+use ir::instructions::BranchInfo;
+use ir::{DataFlowGraph, Ebb, Function, Inst, InstBuilder, InstructionData, Opcode, Type, Value};
+use std::vec::Vec;
+use timing;
+use unreachable_code::clear_jump_tables;
+
+/// The lattice of known values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LatticeValue {
+    /// No definition of the value has been found to be executable yet.
+    Top,
+    /// The value is this constant, sign-extended from its type. Booleans are 0 or 1.
+    Const(i64),
+    /// The value is not a constant.
+    Bottom,
+}
+
+impl Default for LatticeValue {
+    fn default() -> Self {
+        LatticeValue::Top
+    }
+}
+
+impl LatticeValue {
+    fn meet(self, other: Self) -> Self {
+        match (self, other) {
+            (LatticeValue::Top, x) | (x, LatticeValue::Top) => x,
+            (LatticeValue::Const(a), LatticeValue::Const(b)) if a == b => self,
+            _ => LatticeValue::Bottom,
+        }
+    }
+}
+
+/// The state of the constant propagation analysis.
+struct Sccp {
+    /// The known value of each value.
+    values: EntityMap<Value, LatticeValue>,
+    /// EBBs that have been found to be reachable.
+    executable: EntityMap<Ebb, bool>,
+    /// Did anything change during the current iteration?
+    changed: bool,
+}
+
+impl Sccp {
+    fn new() -> Self {
+        Self {
+            values: EntityMap::new(),
+            executable: EntityMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Get the known value of `value`.
+    fn get(&self, dfg: &DataFlowGraph, value: Value) -> LatticeValue {
+        self.values[dfg.resolve_aliases(value)]
+    }
+
+    /// Lower the known value of `value` to include `lv`.
+    fn set(&mut self, value: Value, lv: LatticeValue) {
+        let old = self.values[value];
+        let new = old.meet(lv);
+        if new != old {
+            self.values[value] = new;
+            self.changed = true;
+        }
+    }
+
+    /// Mark the edge to `dest` with `args` as executable.
+    fn mark_edge(&mut self, dfg: &DataFlowGraph, dest: Ebb, args: &[Value]) {
+        if !self.executable[dest] {
+            self.executable[dest] = true;
+            self.changed = true;
+        }
+        for (&param, &arg) in dfg.ebb_params(dest).iter().zip(args) {
+            let lv = self.get(dfg, arg);
+            self.set(param, lv);
+        }
+    }
+
+    /// Run the analysis to a fixed point.
+    fn analyze(&mut self, func: &Function) {
+        let entry = match func.layout.entry_block() {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.executable[entry] = true;
+        for &param in func.dfg.ebb_params(entry) {
+            self.values[param] = LatticeValue::Bottom;
+        }
+
+        // The lattice has a height of 3, so this converges quickly.
+        loop {
+            self.changed = false;
+            for ebb in func.layout.ebbs() {
+                if !self.executable[ebb] {
+                    continue;
+                }
+                for inst in func.layout.ebb_insts(ebb) {
+                    if !self.visit_inst(func, inst) {
+                        break;
+                    }
+                }
+            }
+            if !self.changed {
+                break;
+            }
+        }
+    }
+
+    /// Visit `inst`, updating its results and marking its outgoing edges.
+    ///
+    /// Returns `false` if the instructions following `inst` can't be reached (yet).
+    fn visit_inst(&mut self, func: &Function, inst: Inst) -> bool {
+        let dfg = &func.dfg;
+        let results = dfg.inst_results(inst);
+        if results.len() == 1 && fold::is_foldable(dfg.value_type(results[0])) {
+            let lv = self.evaluate(dfg, inst);
+            self.set(results[0], lv);
+        } else {
+            for &result in results {
+                self.set(result, LatticeValue::Bottom);
+            }
+        }
+
+        match dfg.analyze_branch(inst) {
+            BranchInfo::NotABranch => !dfg[inst].opcode().is_terminator(),
+            BranchInfo::SingleDest(dest, args) => {
+                match self.branch_taken(dfg, inst) {
+                    Some(true) => {
+                        self.mark_edge(dfg, dest, args);
+                        false
+                    }
+                    Some(false) => true,
+                    None => {
+                        self.mark_edge(dfg, dest, args);
+                        true
+                    }
+                }
+            }
+            BranchInfo::Table(jt) => {
+                for (_, dest) in func.jump_tables[jt].entries() {
+                    self.mark_edge(dfg, dest, &[]);
+                }
+                true
+            }
+        }
+    }
+
+    /// Determine if the branch `inst` is always taken (`Some(true)`) or never taken
+    /// (`Some(false)`). The latter is also returned while its condition is still unknown.
+    fn branch_taken(&self, dfg: &DataFlowGraph, inst: Inst) -> Option<bool> {
+        let opcode = dfg[inst].opcode();
+        if opcode.is_terminator() {
+            return Some(true);
+        }
+        if opcode.is_call() {
+            // An `invoke` only goes to its landing pad when the callee unwinds.
+            return None;
+        }
+        match (opcode, self.get(dfg, dfg.inst_args(inst)[0])) {
+            (Opcode::Brz, LatticeValue::Const(c)) => Some(c == 0),
+            (Opcode::Brnz, LatticeValue::Const(c)) => Some(c != 0),
+            (Opcode::Brz, LatticeValue::Top) |
+            (Opcode::Brnz, LatticeValue::Top) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Compute the known value of the single result of `inst`.
+    fn evaluate(&self, dfg: &DataFlowGraph, inst: Inst) -> LatticeValue {
+        if let InstructionData::Ternary { opcode: Opcode::Select, args } = dfg[inst] {
+            if self.get(dfg, args[0]) == LatticeValue::Bottom {
+                return self.get(dfg, args[1]).meet(self.get(dfg, args[2]));
+            }
+        }
+
+        // The result is unknown while an argument is unknown, unless another argument makes it
+        // non-constant.
+        let mut top = false;
+        let mut bottom = false;
+        let c = fold::evaluate(dfg, inst, |value| match self.get(dfg, value) {
+            LatticeValue::Const(c) => Some(c),
+            LatticeValue::Top => {
+                top = true;
+                None
+            }
+            LatticeValue::Bottom => {
+                bottom = true;
+                None
+            }
+        });
+        match c {
+            Some(c) => LatticeValue::Const(c),
+            None if top && !bottom => LatticeValue::Top,
+            None => LatticeValue::Bottom,
+        }
+    }
+
+    /// Rewrite `func` according to the analysis results.
+    fn rewrite(&self, func: &mut Function) {
+        let mut pos = FuncCursor::new(func);
+
+        // Remove the unreachable EBBs.
+        let mut removed = false;
+        while let Some(ebb) = pos.next_ebb() {
+            if self.executable[ebb] {
+                continue;
+            }
+            pos.prev_ebb();
+            while let Some(inst) = pos.func.layout.first_inst(ebb) {
+                pos.func.layout.remove_inst(inst);
+            }
+            pos.func.layout.remove_ebb(ebb);
+            removed = true;
+        }
+        if removed {
+            clear_jump_tables(pos.func);
+        }
+
+        // Replace constant EBB parameters, remembering their original positions.
+        let mut removed_params = EntityMap::<Ebb, Vec<usize>>::new();
+        while let Some(ebb) = pos.next_ebb() {
+            let params = pos.func.dfg.ebb_params(ebb).to_vec();
+            pos.next_inst();
+            for (num, &param) in params.iter().enumerate() {
+                if let LatticeValue::Const(c) = self.values[param] {
+                    pos.func.dfg.remove_ebb_param(param);
+                    let ty = pos.func.dfg.value_type(param);
+                    let value = materialize(&mut pos, ty, c);
+                    pos.func.dfg.change_to_alias(param, value);
+                    removed_params[ebb].push(num);
+                }
+            }
+        }
+
+        // Fold constant instructions and branches.
+        while let Some(_ebb) = pos.next_ebb() {
+            while let Some(inst) = pos.next_inst() {
+                if let BranchInfo::SingleDest(dest, _) = pos.func.dfg.analyze_branch(inst) {
+                    remove_branch_args(&mut pos.func.dfg, inst, &removed_params[dest]);
+                    match self.branch_taken(&pos.func.dfg, inst) {
+                        Some(true) if !pos.func.dfg[inst].opcode().is_terminator() => {
+                            let args = pos.func.dfg.inst_variable_args(inst).to_vec();
+                            pos.func.dfg.replace(inst).jump(dest, &args);
+                            while let Some(dead) = pos.next_inst() {
+                                pos.remove_inst_and_step_back();
+                                dbg!("Removing unreachable {}", dead);
+                            }
+                        }
+                        Some(false) => {
+                            pos.remove_inst_and_step_back();
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                let results = pos.func.dfg.inst_results(inst);
+                if results.len() != 1 {
+                    continue;
+                }
+                let result = results[0];
+                if let LatticeValue::Const(c) = self.values[result] {
+                    let ty = pos.func.dfg.value_type(result);
+                    match pos.func.dfg[inst].opcode() {
+                        Opcode::Iconst | Opcode::Bconst => {}
+                        _ if ty.is_bool() => {
+                            pos.func.dfg.replace(inst).bconst(ty, c != 0);
+                        }
+                        _ => {
+                            pos.func.dfg.replace(inst).iconst(ty, c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Insert an instruction computing the constant `c` of type `ty` at `pos`.
+fn materialize(pos: &mut FuncCursor, ty: Type, c: i64) -> Value {
+    if ty.is_bool() {
+        pos.ins().bconst(ty, c != 0)
+    } else {
+        pos.ins().iconst(ty, c)
+    }
+}
+
+/// Remove the variable arguments at the positions in `removed` from the branch `inst`.
+fn remove_branch_args(dfg: &mut DataFlowGraph, inst: Inst, removed: &[usize]) {
+    if removed.is_empty() {
+        return;
+    }
+    let fixed_args = dfg[inst].opcode().constraints().fixed_value_arguments();
+    let mut args = dfg[inst].take_value_list().expect(
+        "Branches must have value lists.",
+    );
+    for &num in removed.iter().rev() {
+        args.remove(fixed_args + num, &mut dfg.value_lists);
+    }
+    dfg[inst].put_value_list(args);
+}
+
+/// Perform sparse conditional constant propagation on `func`.
+///
+/// The control flow graph is recomputed since branches and EBBs may be removed.
+pub fn do_sccp(func: &mut Function, cfg: &mut ControlFlowGraph) {
+    let _tt = timing::sccp();
+    let mut sccp = Sccp::new();
+    sccp.analyze(func);
+    sccp.rewrite(func);
+    cfg.compute(func);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir::condcodes::IntCC;
+    use ir::types::{B1, I32};
+    use std::string::ToString;
+
+    #[test]
+    fn lattice() {
+        use self::LatticeValue::*;
+        assert_eq!(Top.meet(Const(3)), Const(3));
+        assert_eq!(Const(3).meet(Const(3)), Const(3));
+        assert_eq!(Const(3).meet(Const(4)), Bottom);
+        assert_eq!(Bottom.meet(Top), Bottom);
+    }
+
+    #[test]
+    fn fold_branch() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let param = func.dfg.append_ebb_param(ebb1, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v0 = pos.ins().iconst(I32, 5);
+            let v1 = pos.ins().icmp_imm(IntCC::Equal, v0, 5);
+            pos.ins().brnz(v1, ebb1, &[v0]);
+            pos.ins().jump(ebb2, &[]);
+            pos.insert_ebb(ebb1);
+            let v2 = pos.ins().iadd_imm(param, 1);
+            pos.ins().return_(&[v2]);
+            pos.insert_ebb(ebb2);
+            let v3 = pos.ins().bconst(B1, false);
+            pos.ins().brz(v3, ebb1, &[v0]);
+            pos.ins().return_(&[v0]);
+        }
+
+        let mut cfg = ControlFlowGraph::new();
+        do_sccp(&mut func, &mut cfg);
+        assert_eq!(func.layout.ebbs().collect::<Vec<_>>(), [ebb0, ebb1]);
+        assert_eq!(func.dfg.num_ebb_params(ebb1), 0);
+        let last = func.layout.last_inst(ebb0).unwrap();
+        assert_eq!(func.dfg.display_inst(last, None).to_string(), "jump ebb1");
+        let ret = func.layout.last_inst(ebb1).unwrap();
+        let v2 = func.dfg.inst_args(ret)[0];
+        let def = func.dfg.value_def(v2).unwrap_inst();
+        assert_eq!(func.dfg.display_inst(def, None).to_string(), "v3 = iconst.i32 6");
+    }
+}
@@ -0,0 +1,355 @@
+//! Instruction scheduling.
+//!
+//! CPUs that execute instructions in order stall when an instruction needs the result of a recent
+//! load or multiplication. This pass reorders the instructions between the branches of each EBB
+//! with a list scheduler, so independent instructions fill the latency of the earlier ones. It
+//! runs after legalization and before register allocation, and it does nothing for ISAs without
+//! latencies from `TargetIsa::instruction_latencies`.
+//!
+//! The scheduler builds a dependency graph for each straight-line sequence of instructions:
+//!
+//! - An instruction depends on the instructions defining its arguments, with the latency of the
+//!   definition.
+//! - Stores, calls, and instructions that may trap or have other side effects stay in order, and
+//!   loads stay in the same order relative to them.
+//! - Instructions using or clobbering the CPU flags stay in order.
+//!
+//! The instructions that can issue first are picked by the length of the longest latency path
+//! from them to the end of the sequence, and then by how much they reduce the number of live
+//! values, to keep the register pressure down.
+
+use entity::EntityMap;
+use ir::{Function, Inst, Opcode, Value, ValueDef};
+use isa::{EncInfo, TargetIsa};
+use std::cmp::Reverse;
+use std::vec::Vec;
+use timing;
+
+/// An instruction in the dependency graph of a sequence.
+struct Node {
+    inst: Inst,
+    /// The latency of the results of `inst`.
+    latency: u32,
+    /// The instructions that must be scheduled after this one, and the number of cycles after.
+    succs: Vec<(usize, u32)>,
+    /// The number of predecessors that haven't been scheduled yet.
+    unscheduled_preds: u32,
+    /// The length of the longest latency path from this instruction to the end of the sequence.
+    height: u32,
+    /// The earliest cycle where this instruction can issue without stalling.
+    earliest: u32,
+}
+
+/// Does `inst` have effects that must stay in order with the other memory accesses?
+fn has_side_effects(func: &Function, inst: Inst) -> bool {
+    let opcode = func.dfg[inst].opcode();
+    opcode.can_store() || opcode.is_call() || opcode.can_trap() || opcode.other_side_effects()
+}
+
+/// Does `inst` use, define, or clobber the CPU flags?
+fn touches_flags(func: &Function, encinfo: &EncInfo, inst: Inst) -> bool {
+    let dfg = &func.dfg;
+    encinfo
+        .operand_constraints(func.encodings[inst])
+        .map_or(false, |c| c.clobbers_flags) ||
+        dfg.inst_args(inst).iter().any(
+            |&arg| dfg.value_type(arg).is_flags(),
+        ) ||
+        dfg.inst_results(inst).iter().any(
+            |&res| dfg.value_type(res).is_flags(),
+        )
+}
+
+/// Count the uses of each value in `func`.
+fn count_uses(func: &Function) -> EntityMap<Value, u32> {
+    let mut uses = EntityMap::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+    uses
+}
+
+/// Build the dependency graph of the instructions in `seq`.
+fn build_graph(
+    func: &Function,
+    latency: fn(Opcode) -> u32,
+    encinfo: &EncInfo,
+    seq: &[Inst],
+    index: &mut EntityMap<Inst, Option<usize>>,
+) -> Vec<Node> {
+    let mut nodes: Vec<Node> = Vec::with_capacity(seq.len());
+    let mut last_effect = None;
+    let mut loads_since_effect = Vec::new();
+    let mut last_flags = None;
+
+    for (i, &inst) in seq.iter().enumerate() {
+        index[inst] = Some(i);
+        let opcode = func.dfg[inst].opcode();
+        nodes.push(Node {
+            inst,
+            latency: latency(opcode),
+            succs: Vec::new(),
+            unscheduled_preds: 0,
+            height: 0,
+            earliest: 0,
+        });
+
+        let mut preds = Vec::new();
+        for &arg in func.dfg.inst_args(inst) {
+            if let ValueDef::Result(def, _) = func.dfg.value_def(func.dfg.resolve_aliases(arg)) {
+                if let Some(j) = index[def] {
+                    preds.push((j, nodes[j].latency));
+                }
+            }
+        }
+        if has_side_effects(func, inst) {
+            preds.extend(last_effect.map(|j| (j, 0)));
+            preds.extend(loads_since_effect.drain(..).map(|j| (j, 0)));
+            last_effect = Some(i);
+        } else if opcode.can_load() {
+            preds.extend(last_effect.map(|j| (j, 0)));
+            loads_since_effect.push(i);
+        }
+        if touches_flags(func, encinfo, inst) {
+            preds.extend(last_flags.map(|j| (j, 0)));
+            last_flags = Some(i);
+        }
+
+        nodes[i].unscheduled_preds = preds.len() as u32;
+        for (j, latency) in preds {
+            nodes[j].succs.push((i, latency));
+        }
+    }
+
+    for i in (0..nodes.len()).rev() {
+        let height = nodes[i]
+            .succs
+            .iter()
+            .map(|&(s, latency)| latency + nodes[s].height)
+            .fold(nodes[i].latency, u32::max);
+        nodes[i].height = height;
+    }
+    nodes
+}
+
+/// Get the number of live values removed by scheduling `inst` next, which is negative when
+/// it defines more values than it kills.
+fn live_values_removed(func: &Function, inst: Inst, uses_left: &EntityMap<Value, u32>) -> i32 {
+    let dfg = &func.dfg;
+    let args = dfg.inst_args(inst);
+    let mut removed = 0;
+    for (i, &arg) in args.iter().enumerate() {
+        // Count each argument once.
+        if args[..i].contains(&arg) {
+            continue;
+        }
+        let uses = args.iter().filter(|&&a| a == arg).count() as u32;
+        if uses_left[dfg.resolve_aliases(arg)] == uses {
+            removed += 1;
+        }
+    }
+    removed - dfg.inst_results(inst).len() as i32
+}
+
+/// Record that the uses of values by `inst` have been scheduled.
+fn remove_uses(func: &Function, inst: Inst, uses_left: &mut EntityMap<Value, u32>) {
+    for &arg in func.dfg.inst_args(inst) {
+        uses_left[func.dfg.resolve_aliases(arg)] -= 1;
+    }
+}
+
+/// Compute the order of the instructions in `seq`.
+fn schedule_sequence(
+    func: &Function,
+    latency: fn(Opcode) -> u32,
+    encinfo: &EncInfo,
+    seq: &[Inst],
+    index: &mut EntityMap<Inst, Option<usize>>,
+    uses_left: &mut EntityMap<Value, u32>,
+) -> Vec<Inst> {
+    let mut nodes = build_graph(func, latency, encinfo, seq, index);
+    let mut ready: Vec<usize> = (0..nodes.len())
+        .filter(|&i| nodes[i].unscheduled_preds == 0)
+        .collect();
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut cycle = 0;
+
+    while !ready.is_empty() {
+        let (pos, _) = ready
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, &i)| {
+                let node = &nodes[i];
+                (
+                    node.earliest <= cycle,
+                    node.height,
+                    live_values_removed(func, node.inst, uses_left),
+                    Reverse(i),
+                )
+            })
+            .unwrap();
+        let i = ready.swap_remove(pos);
+        let inst = nodes[i].inst;
+        order.push(inst);
+        remove_uses(func, inst, uses_left);
+
+        cycle = cycle.max(nodes[i].earliest);
+        for s in 0..nodes[i].succs.len() {
+            let (succ, latency) = nodes[i].succs[s];
+            let node = &mut nodes[succ];
+            node.earliest = node.earliest.max(cycle + latency);
+            node.unscheduled_preds -= 1;
+            if node.unscheduled_preds == 0 {
+                ready.push(succ);
+            }
+        }
+        cycle += 1;
+    }
+
+    for &inst in seq {
+        index[inst] = None;
+    }
+    debug_assert_eq!(order.len(), seq.len());
+    order
+}
+
+/// Schedule the instructions in the EBBs of the legalized `func` for `isa`.
+pub fn schedule_instructions(func: &mut Function, isa: &TargetIsa) {
+    let _tt = timing::schedule();
+    let latency = match isa.instruction_latencies() {
+        Some(latency) => latency,
+        None => return,
+    };
+    let encinfo = isa.encoding_info();
+    let mut uses_left = count_uses(func);
+    let mut index = EntityMap::new();
+    let mut seq = Vec::new();
+
+    let mut ebb = func.layout.entry_block();
+    while let Some(current) = ebb {
+        let mut inst = func.layout.first_inst(current);
+        while let Some(current_inst) = inst {
+            inst = func.layout.next_inst(current_inst);
+            let opcode = func.dfg[current_inst].opcode();
+            if !opcode.is_branch() && !opcode.is_terminator() {
+                seq.push(current_inst);
+                continue;
+            }
+
+            // The branches stay in place, and the sequence before them is reordered.
+            if !seq.is_empty() {
+                let order =
+                    schedule_sequence(func, latency, &encinfo, &seq, &mut index, &mut uses_left);
+                if order != seq {
+                    for new_inst in order {
+                        func.layout.remove_inst(new_inst);
+                        func.layout.insert_inst(new_inst, current_inst);
+                    }
+                }
+            }
+            remove_uses(func, current_inst, &mut uses_left);
+            seq.clear();
+        }
+        seq.clear();
+        ebb = func.layout.next_ebb(current);
+    }
+}
+
+#[cfg(all(test, build_riscv))]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I64;
+    use ir::{InstBuilder, MemFlags};
+    use isa;
+    use settings::{self, Configurable};
+    use std::boxed::Box;
+
+    fn riscv() -> Box<TargetIsa> {
+        let mut flag_builder = settings::builder();
+        flag_builder.enable("is_64bit").unwrap();
+        isa::lookup("riscv").unwrap().finish(settings::Flags::new(&flag_builder))
+    }
+
+    fn opcodes(func: &Function) -> Vec<Opcode> {
+        let ebb = func.layout.entry_block().unwrap();
+        func.layout
+            .ebb_insts(ebb)
+            .map(|inst| func.dfg[inst].opcode())
+            .collect()
+    }
+
+    #[test]
+    fn load_latency() {
+        let isa = riscv();
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let x = func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v1 = pos.ins().load(I64, MemFlags::new(), p, 0);
+            let v2 = pos.ins().iadd(v1, x);
+            let v3 = pos.ins().iadd_imm(x, 1);
+            let v4 = pos.ins().bxor_imm(x, 2);
+            let v5 = pos.ins().bor(v3, v4);
+            let v6 = pos.ins().iadd(v2, v5);
+            pos.ins().return_(&[v6]);
+        }
+
+        schedule_instructions(&mut func, &*isa);
+        // The independent instructions fill the latency of the load.
+        assert_eq!(
+            opcodes(&func),
+            [
+                Opcode::Load,
+                Opcode::IaddImm,
+                Opcode::BxorImm,
+                Opcode::Iadd,
+                Opcode::Bor,
+                Opcode::Iadd,
+                Opcode::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_order() {
+        let isa = riscv();
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let p = func.dfg.append_ebb_param(ebb0, I64);
+        let x = func.dfg.append_ebb_param(ebb0, I64);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let v1 = pos.ins().iadd_imm(x, 1);
+            let v2 = pos.ins().iadd_imm(v1, 1);
+            pos.ins().store(MemFlags::new(), v2, p, 0);
+            // The load can't move above the store, and the multiplication fills its latency.
+            let v3 = pos.ins().load(I64, MemFlags::new(), p, 0);
+            let v4 = pos.ins().imul(x, x);
+            let v5 = pos.ins().iadd(v3, v4);
+            pos.ins().return_(&[v5]);
+        }
+
+        schedule_instructions(&mut func, &*isa);
+        assert_eq!(
+            opcodes(&func),
+            [
+                Opcode::IaddImm,
+                Opcode::IaddImm,
+                Opcode::Imul,
+                Opcode::Store,
+                Opcode::Load,
+                Opcode::Iadd,
+                Opcode::Return,
+            ]
+        );
+    }
+}
@@ -315,6 +315,100 @@ pub mod detail {
 // with an impl for all of the settings defined in `lib/cretonne/meta/base/settings.py`.
 include!(concat!(env!("OUT_DIR"), "/settings.rs"));
 
+/// The shared settings that can be overridden for an individual function.
+///
+/// The other shared settings select the instruction encodings, the ABI, or the code emitted by the
+/// ISA, which are fixed when a `TargetIsa` is constructed.
+pub const FUNCTION_SETTINGS: &[&str] = &[
+    "opt_level",
+    "regalloc",
+    "enable_verifier",
+    "enable_regalloc_verifier",
+    "track_inst_origins",
+    "snapshot_ir_on_error",
+    "avoid_div_traps",
+    "fuse_heap_checks",
+    "enable_heap_access_spectre_mitigation",
+    "unroll_threshold",
+    "inline_size_budget",
+    "inline_max_depth",
+    "inline_loop_bonus",
+    "legalizer_expansion_limit",
+    "enable_entry_exit_hooks",
+    "enable_nan_canonicalization",
+    "enable_probestack",
+];
+
+/// Shared settings values overriding the flags of the ISA when compiling one function.
+///
+/// A JIT can compile its hot functions with a higher `opt_level` than the rest without
+/// constructing another `TargetIsa`. Only the settings in `FUNCTION_SETTINGS` can be overridden,
+/// and setting any other name returns a `BadName` error.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overrides {
+    values: Vec<(&'static str, String)>,
+}
+
+impl Overrides {
+    /// Create an empty set of overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Are there no overridden settings?
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Remove all the overridden settings.
+    pub fn clear(&mut self) {
+        self.values.clear()
+    }
+
+    /// Iterate over the overridden settings as `(name, value)` pairs, in the order they were
+    /// first set.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.values.iter().map(|&(name, ref value)| (name, value.as_str()))
+    }
+}
+
+impl Configurable for Overrides {
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = match FUNCTION_SETTINGS.iter().find(|&&n| n == name) {
+            Some(&name) => name,
+            None => return Err(Error::BadName),
+        };
+        // Check the value before keeping it.
+        builder().set(name, value)?;
+        match self.values.iter().position(|&(n, _)| n == name) {
+            Some(idx) => self.values[idx].1 = value.into(),
+            None => self.values.push((name, value.into())),
+        }
+        Ok(())
+    }
+
+    fn enable(&mut self, name: &str) -> Result<()> {
+        self.set(name, "true").map_err(|e| match e {
+            Error::BadValue => Error::BadType,
+            e => e,
+        })
+    }
+}
+
+impl Flags {
+    /// Get a copy of these flags with the values in `overrides`.
+    pub fn with_overrides(&self, overrides: &Overrides) -> Flags {
+        let mut b = Builder {
+            template: &TEMPLATE,
+            bytes: self.bytes.to_vec(),
+        };
+        for (name, value) in overrides.iter() {
+            b.set(name, value).expect("Overrides are checked when set");
+        }
+        Flags::new(&b)
+    }
+}
+
 /// Wrapper containing flags and optionally a `TargetIsa` trait object.
 ///
 /// A few passes need to access the flags but only optionally a target ISA. The `FlagsOrIsa`
@@ -345,7 +439,7 @@ impl<'a> From<&'a TargetIsa> for FlagsOrIsa<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{builder, Flags};
+    use super::{builder, Flags, Overrides, OptLevel};
     use super::Error::*;
     use super::Configurable;
     use std::string::ToString;
@@ -358,15 +452,31 @@ mod tests {
             f.to_string(),
             "[shared]\n\
                     opt_level = \"default\"\n\
+                    regalloc = \"coloring\"\n\
                     enable_verifier = true\n\
+                    enable_regalloc_verifier = false\n\
+                    check_emission = false\n\
+                    track_inst_origins = false\n\
+                    snapshot_ir_on_error = false\n\
                     is_64bit = false\n\
                     is_pic = false\n\
                     return_at_end = false\n\
                     avoid_div_traps = false\n\
+                    fuse_heap_checks = false\n\
+                    enable_heap_access_spectre_mitigation = false\n\
+                    unaligned_vector_access = \"native\"\n\
                     is_compressed = false\n\
                     enable_float = true\n\
                     enable_simd = true\n\
                     enable_atomics = true\n\
+                    unroll_threshold = 0\n\
+                    inline_size_budget = 20\n\
+                    inline_max_depth = 1\n\
+                    inline_loop_bonus = 0\n\
+                    legalizer_expansion_limit = 100\n\
+                    enable_entry_exit_hooks = false\n\
+                    enable_nan_canonicalization = false\n\
+                    enable_probestack = true\n\
                     spiderwasm_prologue_words = 0\n\
                     allones_funcaddrs = false\n"
         );
@@ -400,4 +510,26 @@ mod tests {
         assert_eq!(f.enable_simd(), false);
         assert_eq!(f.opt_level(), super::OptLevel::Best);
     }
+
+    #[test]
+    fn overrides() {
+        let mut o = Overrides::new();
+        assert_eq!(o.set("is_64bit", "true"), Err(BadName));
+        assert_eq!(o.set("opt_level", "true"), Err(BadValue));
+        assert_eq!(o.enable("opt_level"), Err(BadType));
+        assert_eq!(o.set("opt_level", "fastest"), Ok(()));
+        assert_eq!(o.set("enable_verifier", "false"), Ok(()));
+        assert_eq!(o.set("opt_level", "best"), Ok(()));
+        assert_eq!(
+            o.iter().collect::<Vec<_>>(),
+            [("opt_level", "best"), ("enable_verifier", "false")]
+        );
+
+        let mut b = builder();
+        b.enable("is_64bit").unwrap();
+        let f = Flags::new(&b).with_overrides(&o);
+        assert_eq!(f.opt_level(), OptLevel::Best);
+        assert_eq!(f.enable_verifier(), false);
+        assert_eq!(f.is_64bit(), true);
+    }
 }
@@ -0,0 +1,57 @@
+//! Instruction encoding shrinking.
+//!
+//! The legalizer picks the first legal encoding for each instruction before registers are
+//! allocated, so it has to choose an encoding that works for any register. On Intel 64-bit, that
+//! means a REX prefix on most instructions even when the operands end up in the low registers.
+//!
+//! After register allocation, this pass switches each instruction to the shortest legal encoding
+//! whose operand constraints are satisfied by the allocated registers.
+
+use cursor::{Cursor, FuncCursor};
+use ir::Function;
+use isa::TargetIsa;
+use regalloc::RegDiversions;
+use timing;
+
+/// Pick the shortest encoding for every instruction in `func` after register allocation.
+pub fn shrink_instructions(func: &mut Function, isa: &TargetIsa) {
+    let _tt = timing::shrink_instructions();
+
+    let encinfo = isa.encoding_info();
+    let mut divert = RegDiversions::new();
+
+    let mut pos = FuncCursor::new(func);
+    while let Some(_ebb) = pos.next_ebb() {
+        divert.clear();
+        while let Some(inst) = pos.next_inst() {
+            let func = &mut *pos.func;
+            let enc = func.encodings[inst];
+            if !enc.is_legal() {
+                continue;
+            }
+
+            let clobbers_flags = encinfo.operand_constraints(enc).map_or(
+                true,
+                |c| c.clobbers_flags,
+            );
+            let ctrl_type = func.dfg.ctrl_typevar(inst);
+            let best_enc = isa.legal_encodings(&func.dfg, &func.dfg[inst], ctrl_type)
+                .filter(|&e| match encinfo.operand_constraints(e) {
+                    // Don't clobber flags that could be live across the instruction.
+                    Some(c) => {
+                        (clobbers_flags || !c.clobbers_flags) && c.satisfied(inst, &divert, func)
+                    }
+                    None => false,
+                })
+                .min_by_key(|&e| encinfo.bytes(e));
+
+            if let Some(best_enc) = best_enc {
+                if encinfo.bytes(best_enc) < encinfo.bytes(enc) {
+                    func.encodings[inst] = best_enc;
+                }
+            }
+
+            divert.apply(&func.dfg[inst]);
+        }
+    }
+}
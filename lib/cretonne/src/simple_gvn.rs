@@ -1,9 +1,25 @@
 //! A simple GVN pass.
+//!
+//! Loads are value numbered too, using a lightweight versioning of memory. Each memory region
+//! distinguished by `redundant_load` has a version that changes when it may be written, and a load
+//! is only redundant with an earlier load from the same address with the same version:
+//!
+//! - A store changes the version of the region it writes, and of the memory in unknown regions.
+//! - A store to an unknown region, a call, and other instructions with unknown side effects
+//!   change the version of all memory.
+//! - The memory read by `readonly` loads never changes.
+//!
+//! The versions at the start of an EBB are the versions at the branch to it when it has a single
+//! predecessor. Other EBBs start with new versions for all memory, since they may be reached by
+//! paths that write memory.
 
 use cursor::{Cursor, FuncCursor};
+use entity::EntityMap;
 use flowgraph::ControlFlowGraph;
 use dominator_tree::DominatorTree;
-use ir::{InstructionData, Function, Inst, Opcode, Type};
+use ir::{Ebb, InstructionData, Function, Inst, Opcode, Type};
+use ir::instructions::BranchInfo;
+use redundant_load::{access, Access, Region};
 use scoped_hash_map::ScopedHashMap;
 use timing;
 use std::vec::Vec;
@@ -15,6 +31,67 @@ fn trivially_unsafe_for_gvn(opcode: Opcode) -> bool {
         opcode.can_store() || opcode.can_load() || opcode.writes_cpu_flags()
 }
 
+/// The version of the memory read by a load.
+///
+/// The version of memory that isn't read by loads is `(0, 0)`.
+type MemVersion = (u32, u32);
+
+/// The versions of the memory regions at a point in the function.
+#[derive(Clone)]
+struct MemState {
+    /// The version of all memory.
+    all: u32,
+
+    /// The version of the memory in unknown regions, which may be written by any store.
+    unknown: u32,
+
+    /// The regions written since the version of all memory changed, and their versions.
+    regions: Vec<(Region, u32)>,
+}
+
+impl MemState {
+    /// Create a state with a new version for all memory, using the version counter `next`.
+    fn new(next: &mut u32) -> Self {
+        *next += 1;
+        Self {
+            all: *next,
+            unknown: 0,
+            regions: Vec::new(),
+        }
+    }
+
+    /// Get the version of the memory in `region` read by a load.
+    fn version(&self, region: Region, readonly: bool) -> MemVersion {
+        if readonly {
+            return (0, 0);
+        }
+        let version = match region {
+            Region::Unknown => self.unknown,
+            _ => {
+                self.regions
+                    .iter()
+                    .find(|&&(r, _)| r == region)
+                    .map_or(0, |&(_, v)| v)
+            }
+        };
+        (self.all, version)
+    }
+
+    /// Update the versions after a store to `region`.
+    fn store(&mut self, region: Region, next: &mut u32) {
+        if region == Region::Unknown {
+            *self = Self::new(next);
+            return;
+        }
+        *next += 1;
+        self.unknown = *next;
+        match self.regions.iter_mut().find(|&&mut (r, _)| r == region) {
+            Some(entry) => entry.1 = *next,
+            None => self.regions.push((region, *next)),
+        }
+    }
+}
+
 /// Perform simple GVN on `func`.
 ///
 pub fn do_simple_gvn(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &mut DominatorTree) {
@@ -22,9 +99,14 @@ pub fn do_simple_gvn(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &
     debug_assert!(cfg.is_valid());
     debug_assert!(domtree.is_valid());
 
-    let mut visible_values: ScopedHashMap<(InstructionData, Type), Inst> = ScopedHashMap::new();
+    let mut visible_values: ScopedHashMap<(InstructionData, Type, MemVersion), Inst> =
+        ScopedHashMap::new();
     let mut scope_stack: Vec<Inst> = Vec::new();
 
+    // The memory versions at the start of the EBBs with a single predecessor.
+    let mut entry_mem: EntityMap<Ebb, Option<MemState>> = EntityMap::new();
+    let mut next_version = 0;
+
     // Visit EBBs in a reverse post-order.
     let mut pos = FuncCursor::new(func);
 
@@ -46,6 +128,10 @@ pub fn do_simple_gvn(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &
         scope_stack.push(pos.func.layout.first_inst(ebb).unwrap());
         visible_values.increment_depth();
 
+        let mut mem = entry_mem[ebb].take().unwrap_or_else(
+            || MemState::new(&mut next_version),
+        );
+
         pos.goto_top(ebb);
         while let Some(inst) = pos.next_inst() {
             // Resolve aliases, particularly aliases we created earlier.
@@ -56,12 +142,46 @@ pub fn do_simple_gvn(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &
                 scope_stack.push(pos.func.layout.next_inst(inst).unwrap());
                 visible_values.increment_depth();
             }
-            if trivially_unsafe_for_gvn(opcode) {
+
+            let version = match access(&pos.func.dfg, inst) {
+                Access::Load(loc, _, _, readonly) => Some(mem.version(loc.region, readonly)),
+                Access::Store(loc, ..) => {
+                    mem.store(loc.region, &mut next_version);
+                    None
+                }
+                Access::Clobber => {
+                    mem = MemState::new(&mut next_version);
+                    None
+                }
+                Access::None => None,
+            };
+
+            match pos.func.dfg.analyze_branch(inst) {
+                BranchInfo::NotABranch => {}
+                BranchInfo::SingleDest(dest, _) => {
+                    if cfg.pred_iter(dest).count() == 1 {
+                        entry_mem[dest] = Some(mem.clone());
+                    }
+                }
+                BranchInfo::Table(jt) => {
+                    for (_, dest) in pos.func.jump_tables[jt].entries() {
+                        if cfg.pred_iter(dest).count() == 1 {
+                            entry_mem[dest] = Some(mem.clone());
+                        }
+                    }
+                }
+            }
+
+            if version.is_none() && trivially_unsafe_for_gvn(opcode) {
                 continue;
             }
 
             let ctrl_typevar = pos.func.dfg.ctrl_typevar(inst);
-            let key = (pos.func.dfg[inst].clone(), ctrl_typevar);
+            let key = (
+                pos.func.dfg[inst].clone(),
+                ctrl_typevar,
+                version.unwrap_or((0, 0)),
+            );
             let entry = visible_values.entry(key);
             use scoped_hash_map::Entry::*;
             match entry {
@@ -0,0 +1,336 @@
+//! Control flow graph simplification.
+//!
+//! Frontends tend to produce EBBs that contain nothing but a jump to another EBB, as well as
+//! chains of EBBs connected by unconditional jumps. All of these blocks slow down the following
+//! passes and end up as extra branches in the generated code. This pass simplifies the control
+//! flow graph:
+//!
+//! - Branches to an EBB containing only a `jump` are threaded through to the jump destination.
+//! - An EBB whose only predecessor is the terminating `jump` of another EBB is merged into it.
+//! - EBBs without predecessors are removed.
+//!
+//! The control flow graph and the dominator tree are updated incrementally, so they are still
+//! valid when the pass is done.
+
+use dominator_tree::DominatorTree;
+use flowgraph::{BasicBlock, ControlFlowGraph};
+use ir::instructions::BranchInfo;
+use ir::{Ebb, Function, Inst, InstructionData, Opcode, Value};
+use std::vec::Vec;
+use timing;
+
+/// Simplify the control flow graph of `func`.
+pub fn simplify_cfg(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &mut DominatorTree) {
+    let _tt = timing::simplify_cfg();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+
+    // Every change removes an EBB, so this terminates.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+        for ebb in ebbs {
+            if !func.layout.is_ebb_inserted(ebb) || func.layout.entry_block() == Some(ebb) {
+                continue;
+            }
+            if cfg.pred_iter(ebb).next().is_none() {
+                dbg!("Removing dead {}", ebb);
+                remove_ebb(func, cfg, domtree, ebb);
+                changed = true;
+            } else if thread_jumps(func, cfg, domtree, ebb) ||
+                       merge_into_pred(func, cfg, domtree, ebb)
+            {
+                changed = true;
+            }
+        }
+    }
+}
+
+/// Remove `ebb` and all its instructions from the function.
+fn remove_ebb(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    ebb: Ebb,
+) {
+    while let Some(inst) = func.layout.first_inst(ebb) {
+        func.layout.remove_inst(inst);
+    }
+    cfg.recompute_ebb(func, ebb);
+    func.layout.remove_ebb(ebb);
+    domtree.remove_ebb(ebb);
+}
+
+/// Redirect all the branches to `ebb` to its destination if `ebb` contains nothing but a `jump`.
+///
+/// The EBB is removed when it succeeds. Returns `false` without changing anything when `ebb`
+/// isn't a forwarding block or some of its predecessors can't be redirected.
+fn thread_jumps(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    ebb: Ebb,
+) -> bool {
+    let jump = match func.layout.first_inst(ebb) {
+        Some(inst) if func.layout.last_inst(ebb) == Some(inst) => inst,
+        _ => return false,
+    };
+    let (dest, args) = match func.dfg[jump] {
+        InstructionData::Jump {
+            opcode: Opcode::Jump,
+            destination,
+            ref args,
+        } => (destination, args.as_slice(&func.dfg.value_lists).to_vec()),
+        _ => return false,
+    };
+    if dest == ebb {
+        return false;
+    }
+
+    // Jump tables can't pass EBB arguments, and a `fallthrough` must reach the next EBB. The
+    // arguments of an `invoke` belong to the callee, so its landing pad is left alone.
+    let preds: Vec<BasicBlock> = cfg.pred_iter(ebb).collect();
+    for &(_, inst) in &preds {
+        let opcode = func.dfg[inst].opcode();
+        match func.dfg.analyze_branch(inst) {
+            BranchInfo::SingleDest(..) if opcode != Opcode::Fallthrough &&
+                                              opcode != Opcode::Invoke => {}
+            BranchInfo::Table(_) if args.is_empty() => {}
+            _ => return false,
+        }
+    }
+
+    dbg!("Threading jumps through {}", ebb);
+    let params = func.dfg.ebb_params(ebb).to_vec();
+    for &(_, inst) in &preds {
+        redirect_branch(func, inst, ebb, dest, &params, &args);
+    }
+    for &(pred, _) in &preds {
+        cfg.recompute_ebb(func, pred);
+    }
+
+    // The destination is the only EBB that can be immediately dominated by the removed jump. Its
+    // new immediate dominator must be computed while the jump is still in the layout.
+    domtree.recompute_idom(dest, cfg, &func.layout);
+    remove_ebb(func, cfg, domtree, ebb);
+    true
+}
+
+/// Change the branch `inst` from going to `from` to going to `to`.
+///
+/// The arguments passed to `to` are `args` with the parameters `params` of `from` replaced by the
+/// arguments `inst` passed to them.
+fn redirect_branch(
+    func: &mut Function,
+    inst: Inst,
+    from: Ebb,
+    to: Ebb,
+    params: &[Value],
+    args: &[Value],
+) {
+    if let BranchInfo::Table(jt) = func.dfg.analyze_branch(inst) {
+        for entry in func.jump_tables[jt].as_mut_slice() {
+            if entry.expand() == Some(from) {
+                *entry = to.into();
+            }
+        }
+        return;
+    }
+
+    let dfg = &mut func.dfg;
+    let fixed_args = dfg[inst].opcode().constraints().fixed_value_arguments();
+    let mut list = dfg[inst].take_value_list().expect(
+        "Branches must have value lists.",
+    );
+    let old_args = list.as_slice(&dfg.value_lists)[fixed_args..].to_vec();
+    while list.len(&dfg.value_lists) > fixed_args {
+        let last = list.len(&dfg.value_lists) - 1;
+        list.remove(last, &mut dfg.value_lists);
+    }
+    for &arg in args {
+        let arg = dfg.resolve_aliases(arg);
+        let new_arg = match params.iter().position(|&p| p == arg) {
+            Some(num) => old_args[num],
+            None => arg,
+        };
+        list.push(new_arg, &mut dfg.value_lists);
+    }
+    dfg[inst].put_value_list(list);
+    *dfg[inst].branch_destination_mut().expect(
+        "Branch without destination",
+    ) = to;
+}
+
+/// Merge `ebb` into its predecessor if its only predecessor is the terminating `jump` of another
+/// EBB.
+fn merge_into_pred(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    ebb: Ebb,
+) -> bool {
+    let (pred, jump) = {
+        let mut preds = cfg.pred_iter(ebb);
+        match (preds.next(), preds.next()) {
+            (Some(bb), None) => bb,
+            _ => return false,
+        }
+    };
+    // A reachable EBB with a single predecessor is dominated by it, so the predecessor can't use
+    // the EBB parameters.
+    if pred == ebb || !domtree.is_reachable(ebb) || func.layout.last_inst(pred) != Some(jump) ||
+        func.dfg[jump].opcode() != Opcode::Jump
+    {
+        return false;
+    }
+
+    dbg!("Merging {} into {}", ebb, pred);
+    let args = func.dfg.inst_variable_args(jump).to_vec();
+    let params = func.dfg.detach_ebb_params(ebb);
+    for (num, &param) in params.as_slice(&func.dfg.value_lists).to_vec().iter().enumerate() {
+        func.dfg.change_to_alias(param, args[num]);
+    }
+
+    func.layout.remove_inst(jump);
+    while let Some(inst) = func.layout.first_inst(ebb) {
+        func.layout.remove_inst(inst);
+        func.layout.append_inst(inst, pred);
+    }
+    cfg.recompute_ebb(func, pred);
+
+    // The instructions that immediately dominate other EBBs are unchanged, only their EBB is.
+    remove_ebb(func, cfg, domtree, ebb);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{InstBuilder, JumpTableData};
+    use std::string::ToString;
+
+    /// Check that the incrementally updated `domtree` matches a freshly computed one.
+    fn check_domtree(func: &Function, cfg: &ControlFlowGraph, domtree: &DominatorTree) {
+        let fresh_cfg = ControlFlowGraph::with_function(func);
+        let fresh = DominatorTree::with_function(func, &fresh_cfg);
+        for ebb in func.layout.ebbs() {
+            assert_eq!(domtree.idom(ebb), fresh.idom(ebb), "idom of {}", ebb);
+            assert_eq!(domtree.is_reachable(ebb), fresh.is_reachable(ebb));
+            let mut preds: Vec<_> = cfg.pred_iter(ebb).collect();
+            let mut fresh_preds: Vec<_> = fresh_cfg.pred_iter(ebb).collect();
+            preds.sort();
+            fresh_preds.sort();
+            assert_eq!(preds, fresh_preds, "predecessors of {}", ebb);
+        }
+        assert_eq!(domtree.cfg_postorder().len(), fresh.cfg_postorder().len());
+    }
+
+    fn simplify(func: &mut Function) {
+        let mut cfg = ControlFlowGraph::with_function(func);
+        let mut domtree = DominatorTree::with_function(func, &cfg);
+        simplify_cfg(func, &mut cfg, &mut domtree);
+        check_domtree(func, &cfg, &domtree);
+    }
+
+    #[test]
+    fn thread_and_merge() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let ebb4 = func.dfg.make_ebb();
+        let ebb5 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let v1 = func.dfg.append_ebb_param(ebb1, I32);
+        let v2 = func.dfg.append_ebb_param(ebb3, I32);
+        let v3 = func.dfg.append_ebb_param(ebb3, I32);
+        let v4 = func.dfg.append_ebb_param(ebb4, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().brnz(v0, ebb1, &[v0]);
+            pos.ins().jump(ebb2, &[]);
+
+            // Forwarding EBB passing on its parameter and another value.
+            pos.insert_ebb(ebb1);
+            pos.ins().jump(ebb3, &[v1, v0]);
+
+            // Forwarding EBB without parameters.
+            pos.insert_ebb(ebb2);
+            pos.ins().jump(ebb3, &[v0, v0]);
+
+            pos.insert_ebb(ebb3);
+            let v5 = pos.ins().iadd(v2, v3);
+            pos.ins().jump(ebb4, &[v5]);
+
+            // Only reached from the end of ebb3.
+            pos.insert_ebb(ebb4);
+            pos.ins().return_(&[v4]);
+
+            // Dead.
+            pos.insert_ebb(ebb5);
+            pos.ins().jump(ebb4, &[v0]);
+        }
+
+        simplify(&mut func);
+        let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+        assert_eq!(ebbs, [ebb0, ebb3]);
+        let insts: Vec<_> = func.layout
+            .ebb_insts(ebb0)
+            .map(|inst| func.dfg.display_inst(inst, None).to_string())
+            .collect();
+        assert_eq!(insts, ["brnz.i32 v0, ebb3(v0, v0)", "jump ebb3(v0, v0)"]);
+        assert_eq!(func.layout.ebb_insts(ebb3).count(), 2);
+        assert_eq!(func.dfg.resolve_aliases(v4), func.dfg.first_result(
+            func.layout.first_inst(ebb3).unwrap(),
+        ));
+    }
+
+    #[test]
+    fn loops_and_tables() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let ebb3 = func.dfg.make_ebb();
+        let ebb4 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let mut jt = JumpTableData::new();
+        jt.push_entry(ebb1);
+        jt.push_entry(ebb4);
+        let jt = func.create_jump_table(jt);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            pos.ins().br_table(v0, jt);
+            pos.ins().jump(ebb1, &[]);
+
+            // Forwarding EBB reached from a jump table.
+            pos.insert_ebb(ebb1);
+            pos.ins().jump(ebb2, &[]);
+
+            // Loop header with a forwarding back edge.
+            pos.insert_ebb(ebb2);
+            pos.ins().brnz(v0, ebb3, &[]);
+            pos.ins().return_(&[]);
+
+            pos.insert_ebb(ebb3);
+            pos.ins().jump(ebb2, &[]);
+
+            // A forwarding EBB jumping to itself is left alone.
+            pos.insert_ebb(ebb4);
+            pos.ins().jump(ebb4, &[]);
+        }
+
+        simplify(&mut func);
+        let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+        assert_eq!(ebbs, [ebb0, ebb2, ebb4]);
+        assert_eq!(func.jump_tables[jt].get_entry(0), Some(ebb2));
+        let brnz = func.layout.first_inst(ebb2).unwrap();
+        assert_eq!(func.dfg[brnz].branch_destination(), Some(ebb2));
+    }
+}
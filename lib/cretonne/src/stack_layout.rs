@@ -1,9 +1,10 @@
 //! Computing stack layout.
 
-use ir::StackSlots;
+use ir::{StackSlot, StackSlots};
 use ir::stackslot::{StackSize, StackOffset, StackSlotKind};
 use result::CtonError;
 use std::cmp::{min, max};
+use std::vec::Vec;
 
 /// Compute the stack frame layout.
 ///
@@ -37,7 +38,6 @@ pub fn layout_stack(frame: &mut StackSlots, alignment: StackSize) -> Result<Stac
 
     let mut incoming_min = 0;
     let mut outgoing_max = 0;
-    let mut min_align = alignment;
 
     for ss in frame.keys() {
         let slot = &frame[ss];
@@ -59,46 +59,59 @@ pub fn layout_stack(frame: &mut StackSlots, alignment: StackSize) -> Result<Stac
             }
             StackSlotKind::SpillSlot |
             StackSlotKind::ExplicitSlot |
-            StackSlotKind::EmergencySlot => {
-                // Determine the smallest alignment of any explicit or spill slot.
-                min_align = slot.alignment(min_align);
-            }
+            StackSlotKind::EmergencySlot => {}
         }
     }
 
     // Lay out spill slots and explicit slots below the incoming arguments.
     // The offset is negative, growing downwards.
-    // Start with the smallest alignments for better packing.
+    //
+    // Start with the largest alignments so slots of the same alignment pack without padding. The
+    // padding that is still needed, typically below the incoming arguments, is recorded as holes
+    // that the smaller slots can fill later.
+    let mut slots: Vec<(StackSize, StackSlot)> = frame
+        .keys()
+        .filter(|&ss| match frame[ss].kind {
+            StackSlotKind::SpillSlot |
+            StackSlotKind::ExplicitSlot |
+            StackSlotKind::EmergencySlot => true,
+            StackSlotKind::IncomingArg |
+            StackSlotKind::OutgoingArg => false,
+        })
+        .map(|ss| (frame[ss].alignment(alignment), ss))
+        .collect();
+    // The sort is stable, so slots with the same alignment stay in order.
+    slots.sort_by(|a, b| b.0.cmp(&a.0));
+
+    // Unused ranges `lo..hi` of the frame.
+    let mut holes: Vec<(StackOffset, StackOffset)> = Vec::new();
     let mut offset = incoming_min;
-    debug_assert!(min_align.is_power_of_two());
-    while min_align <= alignment {
-        for ss in frame.keys() {
-            let slot = frame[ss].clone();
-
-            // Pick out explicit and spill slots with exact alignment `min_align`.
-            match slot.kind {
-                StackSlotKind::SpillSlot |
-                StackSlotKind::ExplicitSlot |
-                StackSlotKind::EmergencySlot => {
-                    if slot.alignment(alignment) != min_align {
-                        continue;
-                    }
-                }
-                StackSlotKind::IncomingArg |
-                StackSlotKind::OutgoingArg => continue,
+    for (align, ss) in slots {
+        let size = frame[ss].size as StackOffset;
+
+        // Aligning a negative offset can never cause overflow. We're only clearing bits.
+        let mask = -(align as StackOffset);
+        let fit = holes.iter().position(|&(lo, hi)| {
+            hi.checked_sub(size).map_or(false, |o| (o & mask) >= lo)
+        });
+        let slot_offset = match fit {
+            Some(idx) => {
+                let (lo, hi) = holes.swap_remove(idx);
+                let slot_offset = (hi - size) & mask;
+                add_hole(&mut holes, lo, slot_offset);
+                add_hole(&mut holes, slot_offset + size, hi);
+                slot_offset
             }
-
-            offset = offset.checked_sub(slot.size as StackOffset).ok_or(
-                CtonError::ImplLimitExceeded,
-            )?;
-
-            // Aligning the negative offset can never cause overflow. We're only clearing bits.
-            offset &= -(min_align as StackOffset);
-            frame.set_offset(ss, offset);
-        }
-
-        // Move on to the next higher alignment.
-        min_align *= 2;
+            None => {
+                let slot_offset = offset.checked_sub(size).ok_or(
+                    CtonError::ImplLimitExceeded,
+                )? & mask;
+                add_hole(&mut holes, slot_offset + size, offset);
+                offset = slot_offset;
+                slot_offset
+            }
+        };
+        frame.set_offset(ss, slot_offset);
     }
 
     // Finally, make room for the outgoing arguments.
@@ -112,6 +125,13 @@ pub fn layout_stack(frame: &mut StackSlots, alignment: StackSize) -> Result<Stac
     Ok(frame_size)
 }
 
+/// Record the unused range `lo..hi` in `holes` if it isn't empty.
+fn add_hole(holes: &mut Vec<(StackOffset, StackOffset)>, lo: StackOffset, hi: StackOffset) {
+    if lo < hi {
+        holes.push((lo, hi));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ir::{StackSlots, StackSlotData, StackSlotKind};
@@ -150,8 +170,8 @@ mod tests {
         assert_eq!(layout_stack(sss, 16), Ok(16));
         assert_eq!(sss[in0].offset, Some(0));
         assert_eq!(sss[in1].offset, Some(8));
-        assert_eq!(sss[ss0].offset, Some(-16));
-        assert_eq!(sss[ss1].offset, Some(-4));
+        assert_eq!(sss[ss0].offset, Some(-8));
+        assert_eq!(sss[ss1].offset, Some(-12));
 
         // An incoming argument with negative offset counts towards the total frame size, but it
         // should still pack nicely with the spill slots.
@@ -212,4 +232,24 @@ mod tests {
         assert_eq!(sss[ss1].offset, Some(-8));
         assert_eq!(sss[ss2].offset, Some(-12));
     }
+
+    #[test]
+    fn packing() {
+        let sss = &mut StackSlots::new();
+
+        // Three 4-byte slots pushed by the prologue leave the spill area misaligned.
+        sss.make_incoming_arg(types::I32, -12);
+        let ss0 = sss.make_spill_slot(types::I32);
+        let ss1 = sss.make_spill_slot(types::I64);
+        let ss2 = sss.make_spill_slot(types::I64);
+        let ss3 = sss.make_spill_slot(types::I32);
+
+        // The 8-byte slots are aligned below the padding, and the 4-byte slots fill the padding
+        // before extending the frame.
+        assert_eq!(layout_stack(sss, 8), Ok(40));
+        assert_eq!(sss[ss1].offset, Some(-24));
+        assert_eq!(sss[ss2].offset, Some(-32));
+        assert_eq!(sss[ss0].offset, Some(-16));
+        assert_eq!(sss[ss3].offset, Some(-36));
+    }
 }
@@ -4,7 +4,8 @@
 
 use std::fmt;
 
-pub use self::details::{TimingToken, PassTimes, take_current, add_to_current};
+pub use self::details::{TimingToken, PassTimes, PassStats, DisplayStats, take_current,
+                        add_to_current, last_pass, record_insts};
 
 // Each pass that can be timed is predefined with the `define_passes!` macro. Each pass has a
 // snake_case name and a plain text description used when printing out the timing report.
@@ -16,7 +17,7 @@ pub use self::details::{TimingToken, PassTimes, take_current, add_to_current};
 // - A const array of pass descriptions.
 // - A public function per pass used to start the timing of that pass.
 macro_rules! define_passes {
-    { $enum:ident, $num_passes:ident, $descriptions:ident;
+    { $enum:ident, $num_passes:ident, $names:ident, $descriptions:ident;
       $($pass:ident: $desc:expr,)+
     } => {
         #[allow(non_camel_case_types)]
@@ -25,6 +26,8 @@ macro_rules! define_passes {
 
         const $num_passes: usize = $enum::None as usize;
 
+        const $names: [&str; $num_passes] = [ $(stringify!($pass)),+ ];
+
         const $descriptions: [&str; $num_passes] = [ $($desc),+ ];
 
         $(
@@ -38,7 +41,7 @@ macro_rules! define_passes {
 
 // Pass definitions.
 define_passes!{
-    Pass, NUM_PASSES, DESCRIPTIONS;
+    Pass, NUM_PASSES, NAMES, DESCRIPTIONS;
 
     process_file: "Processing test file",
     parse_text: "Parsing textual Cretonne IL",
@@ -49,17 +52,37 @@ define_passes!{
     verify_cssa: "Verify CSSA",
     verify_liveness: "Verify live ranges",
     verify_locations: "Verify value locations",
+    verify_interference: "Verify register interference",
     verify_flags: "Verify CPU flags",
 
     compile: "Compilation passes",
     flowgraph: "Control flow graph",
     domtree: "Dominator tree",
     loop_analysis: "Loop analysis",
+    block_frequency: "Block frequency estimation",
+    inline: "Function inlining",
+    outline: "Outlining of repeated sequences",
     preopt: "Pre-legalization rewriting",
+    redundant_extend: "Redundant extension elimination",
+    redundant_load: "Redundant load elimination",
+    dead_store: "Dead store elimination",
+    sccp: "Sparse conditional constant propagation",
     legalize: "Legalization",
+    canonicalize_nans: "NaN canonicalization",
     gvn: "Global value numbering",
     licm: "Loop invariant code motion",
+    unroll: "Loop unrolling",
+    if_conversion: "If-conversion of small diamonds",
+    peel: "Loop peeling",
+    fuse_heap_checks: "Fusion of heap bounds checks",
+    eliminate_heap_checks: "Heap bounds check elimination",
     unreachable_code: "Remove unreachable blocks",
+    simplify_cfg: "CFG simplification",
+    const_pool: "Sharing of repeated constants",
+    schedule: "Instruction scheduling",
+    custom_passes: "Embedder-defined passes",
+    entry_exit_hooks: "Entry and exit hook insertion",
+    fuel: "Fuel check insertion",
 
     regalloc: "Register allocation",
     ra_liveness: "RA liveness analysis",
@@ -67,8 +90,11 @@ define_passes!{
     ra_spilling: "RA spilling",
     ra_reload: "RA reloading",
     ra_coloring: "RA coloring",
+    ra_safepoints: "RA safepoints",
 
     prologue_epilogue: "Prologue/epilogue insertion",
+    peephole: "Post-register allocation peephole optimizations",
+    shrink_instructions: "Instruction encoding shrinking",
     binemit: "Binary machine code emission",
     layout_renumber: "Layout full renumbering",
 }
@@ -77,6 +103,21 @@ impl Pass {
     pub fn idx(self) -> usize {
         self as usize
     }
+
+    /// Can this pass change the function being compiled?
+    ///
+    /// Verifiers and analyses only inspect the function, and the top-level passes just contain
+    /// other passes.
+    fn modifies_ir(self) -> bool {
+        match self {
+            Pass::None | Pass::process_file | Pass::wasm_translate_module | Pass::compile |
+            Pass::verifier | Pass::verify_cssa | Pass::verify_liveness |
+            Pass::verify_locations | Pass::verify_interference | Pass::verify_flags |
+            Pass::flowgraph | Pass::domtree | Pass::loop_analysis | Pass::block_frequency |
+            Pass::ra_liveness | Pass::binemit => false,
+            _ => true,
+        }
+    }
 }
 
 impl fmt::Display for Pass {
@@ -93,13 +134,15 @@ impl fmt::Display for Pass {
 ///
 /// This whole module can be gated on a `cfg` feature to provide a dummy implementation for
 /// performance-sensitive builds or restricted environments. The dummy implementation must provide
-/// `TimingToken` and `PassTimings` types and a `take_current` function.
+/// `TimingToken`, `PassTimes`, `PassStats`, and `DisplayStats` types and the `take_current`,
+/// `add_to_current`, `last_pass`, and `record_insts` functions.
 mod details {
-    use super::{Pass, NUM_PASSES, DESCRIPTIONS};
+    use super::{Pass, NUM_PASSES, NAMES, DESCRIPTIONS};
     use std::cell::{Cell, RefCell};
     use std::fmt;
     use std::mem;
     use std::time::{Instant, Duration};
+    use std::vec::Vec;
 
     /// A timing token is responsible for timing the currently running pass. Timing starts when it
     /// is created and ends when it is dropped.
@@ -118,21 +161,144 @@ mod details {
     }
 
     /// Accumulated timing information for a single pass.
-    #[derive(Default)]
+    #[derive(Default, Clone, Copy)]
     struct PassTime {
         /// Total time spent running this pas including children.
         total: Duration,
 
         /// Time spent running in child passes.
         child: Duration,
+
+        /// Number of times the pass has run.
+        runs: u32,
+
+        /// Instructions in the functions before and after the runs recorded by `record_insts`.
+        insts_before: u64,
+        insts_after: u64,
+
+        /// Spill instructions inserted by the pass.
+        spills: u64,
+    }
+
+    /// Statistics for a single pass, as returned by `PassTimes::stats()`.
+    #[derive(Clone, Debug)]
+    pub struct PassStats {
+        /// The name of the function starting the pass, like `regalloc`.
+        pub name: &'static str,
+
+        /// The plain text description of the pass.
+        pub description: &'static str,
+
+        /// The number of times the pass has run.
+        pub runs: u32,
+
+        /// The total time spent in the pass, including its child passes.
+        pub total: Duration,
+
+        /// The time spent in the pass itself, excluding its child passes.
+        pub self_time: Duration,
+
+        /// The number of instructions in the functions before the pass ran.
+        ///
+        /// The instruction counts are only recorded for the passes run by `Context::compile`, and
+        /// they are zero for the other passes.
+        pub insts_before: u64,
+
+        /// The number of instructions in the functions after the pass ran.
+        pub insts_after: u64,
+
+        /// The number of spill instructions inserted by the pass.
+        pub spills: u64,
     }
 
     /// Accumulated timing for all passes.
-    #[derive(Default)]
     pub struct PassTimes {
         pass: [PassTime; NUM_PASSES],
     }
 
+    impl PassTimes {
+        /// Get the name and total time of each pass that has run, in pass declaration order.
+        ///
+        /// The names are the names of the functions starting the passes, like `regalloc`.
+        pub fn totals(&self) -> Vec<(&'static str, Duration)> {
+            self.pass
+                .iter()
+                .zip(&NAMES)
+                .filter(|&(time, _)| time.total != Duration::default())
+                .map(|(time, &name)| (name, time.total))
+                .collect()
+        }
+
+        /// Get the statistics of each pass that has run, in pass declaration order.
+        pub fn stats(&self) -> Vec<PassStats> {
+            self.pass
+                .iter()
+                .zip(NAMES.iter().zip(&DESCRIPTIONS))
+                .filter(|&(time, _)| time.runs > 0)
+                .map(|(time, (&name, &description))| {
+                    PassStats {
+                        name,
+                        description,
+                        runs: time.runs,
+                        total: time.total,
+                        self_time: time.total.checked_sub(time.child).unwrap_or_default(),
+                        insts_before: time.insts_before,
+                        insts_after: time.insts_after,
+                        spills: time.spills,
+                    }
+                })
+                .collect()
+        }
+
+        /// Get an object that displays the pass statistics as a table.
+        ///
+        /// Unlike the `Display` implementation for `PassTimes` which only shows the times, this
+        /// includes the run counts, instruction counts, and spill counts of the passes.
+        pub fn display_stats(&self) -> DisplayStats {
+            DisplayStats(self)
+        }
+    }
+
+    /// Wrapper type for displaying the pass statistics of a `PassTimes`.
+    pub struct DisplayStats<'a>(&'a PassTimes);
+
+    impl<'a> fmt::Display for DisplayStats<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "======== ====== ========= ========= ======  ==========================")?;
+            writeln!(f, "    Self   Runs     Insts     After Spills  Pass")?;
+            writeln!(f, "-------- ------ --------- --------- ------  --------------------------")?;
+            for stats in self.0.stats() {
+                let self_time = stats.self_time + Duration::new(0, 500_000);
+                write!(
+                    f,
+                    "{:4}.{:03} {:6} ",
+                    self_time.as_secs(),
+                    self_time.subsec_nanos() / 1_000_000,
+                    stats.runs
+                )?;
+                if stats.insts_before == 0 && stats.insts_after == 0 {
+                    write!(f, "{:9} {:9} {:6}", "", "", "")?;
+                } else {
+                    write!(
+                        f,
+                        "{:9} {:9} {:6}",
+                        stats.insts_before,
+                        stats.insts_after,
+                        stats.spills
+                    )?;
+                }
+                writeln!(f, "  {}", stats.description)?;
+            }
+            writeln!(f, "======== ====== ========= ========= ======  ==========================")
+        }
+    }
+
+    impl Default for PassTimes {
+        fn default() -> Self {
+            Self { pass: [Default::default(); NUM_PASSES] }
+        }
+    }
+
     impl fmt::Display for PassTimes {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             writeln!(f, "======== ========  ==================================")?;
@@ -165,6 +331,7 @@ mod details {
     /// Information about passes in a single thread.
     thread_local!{
         static CURRENT_PASS: Cell<Pass> = Cell::new(Pass::None);
+        static LAST_PASS: Cell<Pass> = Cell::new(Pass::None);
         static PASS_TIME: RefCell<PassTimes> = RefCell::new(Default::default());
     }
 
@@ -173,6 +340,10 @@ mod details {
     /// This function is called by the publicly exposed pass functions.
     pub(super) fn start_pass(pass: Pass) -> TimingToken {
         let prev = CURRENT_PASS.with(|p| p.replace(pass));
+        if prev == Pass::None {
+            // Passes from a previous top-level pass didn't touch the current function.
+            LAST_PASS.with(|p| p.set(Pass::None));
+        }
         dbg!("timing: Starting {}, (during {})", pass, prev);
         TimingToken {
             start: Instant::now(),
@@ -188,9 +359,13 @@ mod details {
             dbg!("timing: Ending {}", self.pass);
             let old_cur = CURRENT_PASS.with(|p| p.replace(self.prev));
             debug_assert_eq!(self.pass, old_cur, "Timing tokens dropped out of order");
+            if self.pass.modifies_ir() {
+                LAST_PASS.with(|p| p.set(self.pass));
+            }
             PASS_TIME.with(|rc| {
                 let mut table = rc.borrow_mut();
                 table.pass[self.pass.idx()].total += duration;
+                table.pass[self.pass.idx()].runs += 1;
                 if let Some(parent) = table.pass.get_mut(self.prev.idx()) {
                     parent.child += duration;
                 }
@@ -203,6 +378,27 @@ mod details {
         PASS_TIME.with(|rc| mem::replace(&mut *rc.borrow_mut(), Default::default()))
     }
 
+    /// Get the description of the last pass that finished and could have changed the function
+    /// being compiled by the current top-level pass.
+    pub fn last_pass() -> Option<&'static str> {
+        DESCRIPTIONS.get(LAST_PASS.with(|p| p.get()).idx()).cloned()
+    }
+
+    /// Record the size of the function before and after the last pass that finished and could
+    /// have changed it.
+    ///
+    /// The sizes are the number of instructions and spill instructions in the function. This is
+    /// called by `Context::compile` after each of its passes. The last pass is forgotten, so a
+    /// second call without running another pass records nothing.
+    pub fn record_insts(before: (usize, usize), after: (usize, usize)) {
+        let pass = LAST_PASS.with(|p| p.replace(Pass::None));
+        PASS_TIME.with(|rc| if let Some(time) = rc.borrow_mut().pass.get_mut(pass.idx()) {
+            time.insts_before += before.0 as u64;
+            time.insts_after += after.0 as u64;
+            time.spills += after.1.saturating_sub(before.1) as u64;
+        })
+    }
+
     /// Add `timings` to the accumulated timings for the current thread.
     pub fn add_to_current(times: &PassTimes) {
         PASS_TIME.with(|rc| for (a, b) in rc.borrow_mut().pass.iter_mut().zip(
@@ -211,6 +407,10 @@ mod details {
         {
             a.total += b.total;
             a.child += b.child;
+            a.runs += b.runs;
+            a.insts_before += b.insts_before;
+            a.insts_after += b.insts_after;
+            a.spills += b.spills;
         })
     }
 }
@@ -224,4 +424,57 @@ mod test {
         assert_eq!(Pass::None.to_string(), "<no pass>");
         assert_eq!(Pass::regalloc.to_string(), "Register allocation");
     }
+
+    #[test]
+    fn last() {
+        {
+            let _tt = compile();
+            assert_eq!(last_pass(), None);
+            drop(legalize());
+            assert_eq!(last_pass(), Some("Legalization"));
+            drop(domtree());
+            assert_eq!(last_pass(), Some("Legalization"));
+        }
+        let _tt = compile();
+        assert_eq!(last_pass(), None);
+    }
+
+    #[test]
+    fn totals() {
+        take_current();
+        {
+            let _tt = legalize();
+            ::std::thread::sleep(::std::time::Duration::from_millis(1));
+        }
+        let times = take_current();
+        let totals = times.totals();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].0, "legalize");
+    }
+
+    #[test]
+    fn stats() {
+        take_current();
+        {
+            let _tt = compile();
+            drop(legalize());
+            record_insts((10, 0), (12, 0));
+            drop(regalloc());
+            drop(domtree());
+            record_insts((12, 0), (15, 2));
+            drop(legalize());
+            record_insts((3, 1), (4, 1));
+            // No pass ran since the last call.
+            record_insts((4, 1), (100, 100));
+        }
+        let stats = take_current().stats();
+        let names: Vec<_> = stats.iter().map(|s| s.name).collect();
+        assert_eq!(names, ["compile", "domtree", "legalize", "regalloc"]);
+        assert_eq!(stats[2].runs, 2);
+        assert_eq!((stats[2].insts_before, stats[2].insts_after), (13, 16));
+        assert_eq!(stats[2].spills, 0);
+        assert_eq!((stats[3].insts_before, stats[3].insts_after), (12, 15));
+        assert_eq!(stats[3].spills, 2);
+        assert_eq!(stats[1].insts_after, 0);
+    }
 }
@@ -1,23 +1,35 @@
 //! Unreachable code elimination.
+//!
+//! Frontends can produce EBBs that are never executed, like the code following a wasm
+//! `unreachable` instruction, and passes that fold branches can leave unreachable EBBs behind.
+//! This code still has to be optimized, legalized, and register allocated, and it can break the
+//! assumptions of passes that only expect reachable code, so it is removed early.
 
 use cursor::{Cursor, FuncCursor};
 use dominator_tree::DominatorTree;
 use flowgraph::ControlFlowGraph;
 use ir;
+use loop_analysis::LoopAnalysis;
+use std::vec::Vec;
 use timing;
 
 /// Eliminate unreachable code.
 ///
 /// This pass deletes whole EBBs that can't be reached from the entry block. It does not delete
-/// individual instructions whose results are unused.
+/// individual instructions whose results are unused. The jump table entries referring to the
+/// deleted EBBs are cleared.
 ///
-/// The reachability analysis is performed by the dominator tree analysis.
+/// The reachability analysis is performed by the dominator tree analysis. Removing unreachable
+/// EBBs doesn't change the dominator tree, and the control flow graph and the loop analysis, if
+/// it is valid, are updated.
 pub fn eliminate_unreachable_code(
     func: &mut ir::Function,
     cfg: &mut ControlFlowGraph,
     domtree: &DominatorTree,
+    loop_analysis: &mut LoopAnalysis,
 ) {
     let _tt = timing::unreachable_code();
+    let mut removed = false;
     let mut pos = FuncCursor::new(func);
     while let Some(ebb) = pos.next_ebb() {
         if domtree.is_reachable(ebb) {
@@ -38,8 +50,34 @@ pub fn eliminate_unreachable_code(
         // Once the EBB is completely empty, we can update the CFG which removes it from any
         // predecessor lists.
         cfg.recompute_ebb(pos.func, ebb);
+        if loop_analysis.is_valid() {
+            loop_analysis.remove_ebb(ebb);
+        }
 
         // Finally, remove the EBB from the layout.
         pos.func.layout.remove_ebb(ebb);
+        removed = true;
+    }
+
+    if removed {
+        clear_jump_tables(pos.func);
+    }
+}
+
+/// Clear the jump table entries referring to EBBs that are no longer in the layout.
+///
+/// The `br_table` instructions using these entries were unreachable themselves, but the jump
+/// table declarations remain in the function.
+pub fn clear_jump_tables(func: &mut ir::Function) {
+    for jt in func.jump_tables.keys() {
+        let dead: Vec<usize> = func.jump_tables[jt]
+            .entries()
+            .filter(|&(_, ebb)| !func.layout.is_ebb_inserted(ebb))
+            .map(|(idx, _)| idx)
+            .collect();
+        for idx in dead {
+            dbg!("Clearing {}[{}]", jt, idx);
+            func.jump_tables[jt].clear_entry(idx);
+        }
     }
 }
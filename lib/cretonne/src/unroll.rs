@@ -0,0 +1,469 @@
+//! Loop unrolling.
+//!
+//! This pass unrolls loops consisting of a single EBB that branches back to itself with a
+//! conditional branch:
+//!
+//! ```cton
+//! ebb1(v1: i32):
+//!     ...
+//!     v2 = iadd_imm v1, 1
+//!     v3 = icmp_imm slt v2, 4
+//!     brnz v3, ebb1(v2)
+//!     jump ebb2
+//! ```
+//!
+//! When the induction variable starts at a constant, the trip count of the loop can be computed
+//! by simulating the exit test. Loops that fit in the size budget are unrolled completely, and the
+//! back edge is removed. Larger loops are unrolled by a factor that divides the trip count so the
+//! intermediate exit tests can be omitted.
+//!
+//! The body is unrolled by inserting copies of the loop body at the top of the EBB. The EBB
+//! parameters are replaced by new parameters feeding the first copy, and the old parameters become
+//! aliases of the values flowing into the original body which now executes the last iteration.
+//! That way, uses of the loop values after the loop don't need to be rewritten.
+
+use dominator_tree::DominatorTree;
+use entity::EntityMap;
+use flowgraph::ControlFlowGraph;
+use fold::{eval_compare, normalize};
+use ir::{Ebb, Function, Inst, InstructionData, Opcode, Value, ValueDef, ValueList};
+use loop_analysis::{Loop, LoopAnalysis};
+use packed_option::PackedOption;
+use timing;
+use std::vec::Vec;
+
+/// The largest factor used for partial unrolling.
+const MAX_UNROLL_FACTOR: u32 = 8;
+
+/// The largest trip count we try to compute by simulating the loop.
+const MAX_TRIP_COUNT: u32 = 4096;
+
+/// Unroll the loops in `func` whose unrolled body would have at most `threshold` instructions.
+///
+/// The CFG is updated. The dominator tree remains valid, but the loop analysis must be recomputed
+/// since fully unrolled loops disappear.
+pub fn do_unroll(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &DominatorTree,
+    loop_analysis: &LoopAnalysis,
+    threshold: u32,
+) {
+    let _tt = timing::unroll();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+    debug_assert!(loop_analysis.is_valid());
+
+    for lp in loop_analysis.loops() {
+        let header = loop_analysis.loop_header(lp);
+        let lp = match analyze_loop(func, cfg, loop_analysis, lp) {
+            Some(lp) => lp,
+            None => continue,
+        };
+
+        if lp.body_len <= threshold / lp.trip_count {
+            dbg!("Unrolling {} completely, {} iterations", header, lp.trip_count);
+            unroll(func, &lp, lp.trip_count - 1);
+            func.layout.remove_inst(lp.backedge);
+            if let Some(inst) = lp.exit_test {
+                func.layout.remove_inst(inst);
+            }
+            cfg.recompute_ebb(func, header);
+        } else if let Some(factor) = (2..MAX_UNROLL_FACTOR + 1).rev().find(|&factor| {
+            lp.trip_count % factor == 0 && lp.body_len <= threshold / factor
+        })
+        {
+            dbg!("Unrolling {} by a factor of {}", header, factor);
+            unroll(func, &lp, factor - 1);
+        }
+    }
+}
+
+/// A loop that can be unrolled.
+pub struct SimpleLoop {
+    /// The header EBB which is the only EBB in the loop.
+    pub header: Ebb,
+
+    /// The conditional branch back to the header.
+    pub backedge: Inst,
+
+    /// The instructions before `backedge` that are executed in every iteration.
+    pub body: Vec<Inst>,
+
+    /// The instruction computing the branch condition, if it has no other uses. It is not copied,
+    /// and it is removed when the loop is unrolled completely.
+    pub exit_test: Option<Inst>,
+
+    /// The number of instructions copied for each iteration.
+    pub body_len: u32,
+
+    /// The induction variable controlling the exit test.
+    pub induction: Induction,
+
+    /// The number of times the body is executed each time the loop is entered.
+    pub trip_count: u32,
+}
+
+/// The induction variable of a `SimpleLoop`.
+pub struct Induction {
+    /// The header EBB parameter holding the value of the variable in each iteration.
+    pub param: Value,
+
+    /// The value of the variable in the first iteration, normalized to its type.
+    pub init: i64,
+
+    /// The amount added to the variable in each iteration.
+    pub step: i64,
+}
+
+/// Check if the loop `lp` can be unrolled and compute its trip count.
+pub fn analyze_loop(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    loop_analysis: &LoopAnalysis,
+    lp: Loop,
+) -> Option<SimpleLoop> {
+    let header = loop_analysis.loop_header(lp);
+
+    // The entry block parameters are the function arguments which can't be replaced.
+    if func.layout.entry_block() == Some(header) {
+        return None;
+    }
+
+    // The only predecessor in the loop must be a branch in the header itself.
+    let mut backedge = None;
+    for (pred_ebb, pred_inst) in cfg.pred_iter(header) {
+        if pred_ebb != header {
+            if loop_analysis.is_in_loop(pred_ebb, lp) {
+                return None;
+            }
+        } else if backedge.is_some() {
+            return None;
+        } else {
+            backedge = Some(pred_inst);
+        }
+    }
+    let backedge = backedge?;
+    match func.dfg[backedge].opcode() {
+        Opcode::Brz | Opcode::Brnz => {}
+        _ => return None,
+    }
+
+    // The body must be straight-line code.
+    let body: Vec<Inst> = func.layout
+        .ebb_insts(header)
+        .take_while(|&inst| inst != backedge)
+        .collect();
+    if body.is_empty() ||
+        body.iter().any(|&inst| {
+            let opcode = func.dfg[inst].opcode();
+            opcode.is_branch() || opcode.is_terminator()
+        })
+    {
+        return None;
+    }
+
+    let (induction, trip_count) = trip_count(func, cfg, header, backedge)?;
+    let exit_test = exit_test(func, backedge);
+    Some(SimpleLoop {
+        header,
+        backedge,
+        exit_test,
+        body_len: (body.len() - exit_test.iter().count()) as u32,
+        body,
+        induction,
+        trip_count,
+    })
+}
+
+/// Get the instruction computing the condition of `backedge` if it isn't used anywhere else.
+fn exit_test(func: &Function, backedge: Inst) -> Option<Inst> {
+    let cond = func.dfg.inst_args(backedge)[0];
+    let inst = match func.dfg.value_def(cond) {
+        ValueDef::Result(inst, _) => inst,
+        ValueDef::Param(..) => return None,
+    };
+    for ebb in func.layout.ebbs() {
+        for user in func.layout.ebb_insts(ebb) {
+            if user != backedge &&
+                func.dfg.inst_args(user).iter().any(|&arg| {
+                    func.dfg.resolve_aliases(arg) == cond
+                })
+            {
+                return None;
+            }
+        }
+    }
+    Some(inst)
+}
+
+/// Get the constant defined by `value`, if any.
+fn iconst_value(func: &Function, value: Value) -> Option<i64> {
+    let value = func.dfg.resolve_aliases(value);
+    if let ValueDef::Result(inst, _) = func.dfg.value_def(value) {
+        if let InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } = func.dfg[inst] {
+            return Some(imm.into());
+        }
+    }
+    None
+}
+
+/// Compute the number of iterations of a loop with an exit test like:
+///
+/// ```cton
+///     v2 = iadd_imm v1, 1
+///     v3 = icmp_imm slt v2, 4
+///     brnz v3, ebb1(v2)
+/// ```
+///
+/// Where `v1` is an EBB parameter of the header that receives a constant when entering the loop.
+fn trip_count(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    header: Ebb,
+    backedge: Inst,
+) -> Option<(Induction, u32)> {
+    let dfg = &func.dfg;
+    let (branch_cond, branch_args) = match dfg[backedge] {
+        InstructionData::Branch { opcode, ref args, .. } => {
+            (opcode == Opcode::Brnz, args.as_slice(&dfg.value_lists))
+        }
+        _ => return None,
+    };
+
+    // Find the comparison and its constant operand.
+    let (cond, lhs, limit) = match dfg.value_def(dfg.resolve_aliases(branch_args[0])) {
+        ValueDef::Result(inst, _) => {
+            match dfg[inst] {
+                InstructionData::IntCompareImm {
+                    opcode: Opcode::IcmpImm,
+                    cond,
+                    arg,
+                    imm,
+                } => (cond, arg, imm.into()),
+                InstructionData::IntCompare {
+                    opcode: Opcode::Icmp,
+                    cond,
+                    args,
+                } => (cond, args[0], iconst_value(func, args[1])?),
+                _ => return None,
+            }
+        }
+        ValueDef::Param(..) => return None,
+    };
+    let lhs = dfg.resolve_aliases(lhs);
+
+    // The compared value is either an EBB parameter or its incremented value passed to the back
+    // edge.
+    let (param, num, step) = match dfg.value_def(lhs) {
+        ValueDef::Param(ebb, num) if ebb == header => {
+            let next = dfg.resolve_aliases(branch_args[1 + num]);
+            (lhs, num, increment(func, next, lhs)?)
+        }
+        ValueDef::Result(..) => {
+            let num = branch_args[1..].iter().position(|&arg| {
+                dfg.resolve_aliases(arg) == lhs
+            })?;
+            let param = dfg.ebb_params(header)[num];
+            (param, num, increment(func, lhs, param)?)
+        }
+        _ => return None,
+    };
+    let ty = dfg.value_type(param);
+    if !ty.is_int() || ty.is_vector() {
+        return None;
+    }
+
+    // All the branches entering the loop must pass the same constant.
+    let mut init = None;
+    for (pred_ebb, pred_inst) in cfg.pred_iter(header) {
+        if pred_ebb == header {
+            continue;
+        }
+        let value = iconst_value(func, dfg.inst_variable_args(pred_inst)[num])?;
+        if init.map_or(false, |init| init != value) {
+            return None;
+        }
+        init = Some(value);
+    }
+
+    // Simulate the loop.
+    let init = normalize(init?, ty);
+    let mut value = init;
+    for count in 1..MAX_TRIP_COUNT + 1 {
+        let next = normalize(value.wrapping_add(step), ty);
+        let x = if lhs == param { value } else { next };
+        let taken = eval_compare(cond, ty, x, limit) != 0;
+        if taken != branch_cond {
+            return Some((Induction { param, init, step }, count));
+        }
+        value = next;
+    }
+    None
+}
+
+/// If `value` is computed as `iadd_imm base, step`, return `step`.
+fn increment(func: &Function, value: Value, base: Value) -> Option<i64> {
+    if let ValueDef::Result(inst, _) = func.dfg.value_def(value) {
+        if let InstructionData::BinaryImm {
+            opcode: Opcode::IaddImm,
+            arg,
+            imm,
+        } = func.dfg[inst]
+        {
+            if func.dfg.resolve_aliases(arg) == base {
+                return Some(imm.into());
+            }
+        }
+    }
+    None
+}
+
+/// Insert `copies` copies of the loop body before the original body.
+fn unroll(func: &mut Function, lp: &SimpleLoop, copies: u32) {
+    let old_params = func.dfg.ebb_params(lp.header).to_vec();
+    let backedge_args = func.dfg.inst_variable_args(lp.backedge).to_vec();
+
+    // The values of the EBB parameters in the current iteration.
+    func.dfg.detach_ebb_params(lp.header);
+    let mut params: Vec<Value> = old_params
+        .iter()
+        .map(|&param| {
+            let ty = func.dfg.value_type(param);
+            func.dfg.append_ebb_param(lp.header, ty)
+        })
+        .collect();
+
+    let mut values = EntityMap::<Value, PackedOption<Value>>::new();
+    for _ in 0..copies {
+        for (&old, &new) in old_params.iter().zip(&params) {
+            values[old] = new.into();
+        }
+
+        for &inst in &lp.body {
+            if Some(inst) == lp.exit_test {
+                continue;
+            }
+            let mut data = func.dfg[inst].clone();
+            if let Some(list) = data.take_value_list() {
+                let args = list.as_slice(&func.dfg.value_lists).to_vec();
+                let mut copy = ValueList::new();
+                copy.extend(args, &mut func.dfg.value_lists);
+                data.put_value_list(copy);
+            }
+            let new_inst = func.dfg.make_inst(data);
+            let ctrl_typevar = func.dfg.ctrl_typevar(inst);
+            func.dfg.make_inst_results(new_inst, ctrl_typevar);
+            for i in 0..func.dfg.inst_args(new_inst).len() {
+                let arg = func.dfg.resolve_aliases(func.dfg.inst_args(new_inst)[i]);
+                func.dfg.inst_args_mut(new_inst)[i] = values[arg].expand().unwrap_or(arg);
+            }
+            for (&old, &new) in func.dfg.inst_results(inst).iter().zip(
+                func.dfg.inst_results(new_inst),
+            )
+            {
+                values[old] = new.into();
+            }
+
+            func.layout.insert_inst(new_inst, lp.body[0]);
+            func.srclocs[new_inst] = func.srclocs[inst];
+            func.annotations.copy_inst(inst, new_inst);
+        }
+
+        params = backedge_args
+            .iter()
+            .map(|&arg| {
+                let arg = func.dfg.resolve_aliases(arg);
+                values[arg].expand().unwrap_or(arg)
+            })
+            .collect();
+    }
+
+    // The original body now executes the last iteration.
+    for (&old, &new) in old_params.iter().zip(&params) {
+        func.dfg.change_to_alias(old, new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursor::{Cursor, FuncCursor};
+    use ir::condcodes::IntCC;
+    use ir::types::I32;
+    use ir::{AbiParam, InstBuilder};
+    use settings;
+    use verifier::verify_function;
+
+    /// Build a function that sums `v0` four times in a loop.
+    fn sum_loop() -> Function {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let ebb2 = func.dfg.make_ebb();
+        let v0 = func.dfg.append_ebb_param(ebb0, I32);
+        let i = func.dfg.append_ebb_param(ebb1, I32);
+        let acc = func.dfg.append_ebb_param(ebb1, I32);
+        let res = func.dfg.append_ebb_param(ebb2, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let zero = pos.ins().iconst(I32, 0);
+            pos.ins().jump(ebb1, &[zero, zero]);
+
+            pos.insert_ebb(ebb1);
+            let acc2 = pos.ins().iadd(acc, v0);
+            let i2 = pos.ins().iadd_imm(i, 1);
+            let c = pos.ins().icmp_imm(IntCC::SignedLessThan, i2, 4);
+            pos.ins().brnz(c, ebb1, &[i2, acc2]);
+            pos.ins().jump(ebb2, &[acc2]);
+
+            pos.insert_ebb(ebb2);
+            pos.ins().return_(&[res]);
+        }
+        func
+    }
+
+    fn run(func: &mut Function, threshold: u32) {
+        let mut cfg = ControlFlowGraph::with_function(func);
+        let domtree = DominatorTree::with_function(func, &cfg);
+        let mut loop_analysis = LoopAnalysis::new();
+        loop_analysis.compute(func, &cfg, &domtree);
+        do_unroll(func, &mut cfg, &domtree, &loop_analysis, threshold);
+        let flags = settings::Flags::new(&settings::builder());
+        verify_function(func, &flags).unwrap();
+    }
+
+    fn count(func: &Function, opcode: Opcode) -> usize {
+        func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .filter(|&inst| func.dfg[inst].opcode() == opcode)
+            .count()
+    }
+
+    #[test]
+    fn full() {
+        let mut func = sum_loop();
+        run(&mut func, 12);
+        assert_eq!(count(&func, Opcode::Iadd), 4);
+        assert_eq!(count(&func, Opcode::Brnz), 0);
+    }
+
+    #[test]
+    fn partial() {
+        let mut func = sum_loop();
+        run(&mut func, 6);
+        assert_eq!(count(&func, Opcode::Iadd), 2);
+        assert_eq!(count(&func, Opcode::Brnz), 1);
+    }
+
+    #[test]
+    fn too_big() {
+        let mut func = sum_loop();
+        run(&mut func, 3);
+        assert_eq!(count(&func, Opcode::Iadd), 1);
+    }
+}
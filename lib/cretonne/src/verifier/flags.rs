@@ -7,7 +7,7 @@ use ir;
 use isa;
 use packed_option::PackedOption;
 use std::result;
-use verifier::{Result, Error};
+use verifier::{Result, StepResult, VerifierError};
 use timing;
 
 /// Verify that CPU flags are used correctly.
@@ -21,7 +21,9 @@ use timing;
 /// - At most one flags value can be live at a time.
 /// - A flags value can not be live across an instruction that clobbers the flags.
 ///
-///
+/// The legalizer recomputes the comparisons whose flags are clobbered before a use, so the second
+/// condition only fails for other flags values, or when the function was changed after
+/// legalization.
 pub fn verify_flags(
     func: &ir::Function,
     cfg: &ControlFlowGraph,
@@ -81,7 +83,7 @@ impl<'a> FlagsVerifier<'a> {
     }
 
     /// Check flags usage in `ebb` and return the live-in flags value, if any.
-    fn visit_ebb(&self, ebb: ir::Ebb) -> result::Result<Option<ir::Value>, Error> {
+    fn visit_ebb(&self, ebb: ir::Ebb) -> result::Result<Option<ir::Value>, VerifierError> {
         // The single currently live flags value.
         let mut live_val = None;
 
@@ -140,7 +142,7 @@ impl<'a> FlagsVerifier<'a> {
 }
 
 // Merge live flags values, or return an error on conflicting values.
-fn merge(a: &mut Option<ir::Value>, b: ir::Value, inst: ir::Inst) -> Result {
+fn merge(a: &mut Option<ir::Value>, b: ir::Value, inst: ir::Inst) -> StepResult {
     if let Some(va) = *a {
         if b != va {
             return err!(inst, "conflicting live CPU flags: {} and {}", va, b);
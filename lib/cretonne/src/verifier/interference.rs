@@ -0,0 +1,202 @@
+//! Verify that live values don't share locations.
+
+use entity::EntityRef;
+use flowgraph::ControlFlowGraph;
+use ir::{self, Ebb, Inst, Value, ValueLoc};
+use isa::{RegInfo, RegUnit, TargetIsa};
+use regalloc::affinity::Affinity;
+use regalloc::liveness::Liveness;
+use std::vec::Vec;
+use verifier::Result;
+use timing;
+
+/// Verify that no two values in `func` that are live at the same time share a register unit or a
+/// stack slot.
+///
+/// The live ranges are recomputed from scratch instead of trusting the ones maintained by the
+/// register allocator, so this also catches the passes after register allocation that clobber a
+/// live value. The check takes time proportional to the number of values times the number of
+/// EBBs.
+///
+/// - EBB parameters and the values that are live in to an EBB occupy their global locations at
+///   the top of the EBB.
+/// - The values killed by an instruction free their locations before its results are defined, so
+///   a result can reuse the register of a killed argument.
+/// - The `regmove`, `regspill`, and `regfill` instructions move their argument to a location that
+///   must be free.
+/// - A result must not be assigned to a location holding another live value.
+pub fn verify_interference(isa: &TargetIsa, func: &ir::Function) -> Result {
+    let _tt = timing::verify_interference();
+    // Computing the liveness resolves the aliases in the instruction arguments.
+    let mut func = func.clone();
+    let cfg = ControlFlowGraph::with_function(&func);
+    let mut liveness = Liveness::new();
+    liveness.compute(isa, &mut func, &cfg);
+
+    let mut verifier = InterferenceVerifier {
+        func: &func,
+        reginfo: isa.register_info(),
+        liveness: &liveness,
+        occupied: Vec::new(),
+    };
+    for ebb in func.layout.ebbs() {
+        verifier.check_ebb(ebb)?;
+    }
+    Ok(())
+}
+
+/// A register unit or a stack slot.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Unit {
+    Reg(RegUnit),
+    Stack(ir::StackSlot),
+}
+
+struct InterferenceVerifier<'a> {
+    func: &'a ir::Function,
+    reginfo: RegInfo,
+    liveness: &'a Liveness,
+    /// The units holding the live values at the current program point.
+    occupied: Vec<(Unit, Value)>,
+}
+
+impl<'a> InterferenceVerifier<'a> {
+    /// Check the locations of the values live in `ebb`.
+    fn check_ebb(&mut self, ebb: Ebb) -> Result {
+        let dfg = &self.func.dfg;
+        let ctx = self.liveness.context(&self.func.layout);
+        self.occupied.clear();
+
+        for index in 0..dfg.num_values() {
+            let value = Value::new(index);
+            let livein = self.liveness.get(value).map_or(
+                false,
+                |lr| lr.is_livein(ebb, ctx),
+            );
+            if livein {
+                self.occupy(ebb, value, self.func.locations[value])?;
+            }
+        }
+        for &param in dfg.ebb_params(ebb) {
+            self.occupy(ebb, param, self.func.locations[param])?;
+            if self.liveness[param].is_dead() {
+                self.free(param);
+            }
+        }
+
+        for inst in self.func.layout.ebb_insts(ebb) {
+            self.check_diversion(inst)?;
+
+            // Values live out to a branch destination can also end at a branch.
+            let liveness = self.liveness;
+            self.occupied.retain(|&(_, v)| !liveness[v].killed_at(inst, ebb, ctx));
+
+            for &res in dfg.inst_results(inst) {
+                self.occupy(inst, res, self.func.locations[res])?;
+            }
+            for &res in dfg.inst_results(inst) {
+                if self.liveness[res].is_dead() {
+                    self.free(res);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the value diverted by `inst` to its new location.
+    fn check_diversion(&mut self, inst: Inst) -> Result {
+        let (arg, to) = match self.func.dfg[inst] {
+            ir::InstructionData::RegMove { arg, dst, .. } |
+            ir::InstructionData::RegFill { arg, dst, .. } => (arg, ValueLoc::Reg(dst)),
+            ir::InstructionData::RegSpill { arg, dst, .. } => (arg, ValueLoc::Stack(dst)),
+            _ => return Ok(()),
+        };
+        self.free(arg);
+        self.occupy(inst, arg, to)
+    }
+
+    /// Get the units covered by `value` in `loc`.
+    fn units(&self, value: Value, loc: ValueLoc) -> Vec<Unit> {
+        match loc {
+            ValueLoc::Unassigned => Vec::new(),
+            ValueLoc::Reg(reg) => {
+                let width = match self.liveness[value].affinity {
+                    Affinity::Reg(rci, _) => self.reginfo.rc(rci).width,
+                    _ => 1,
+                };
+                (0..RegUnit::from(width)).map(|i| Unit::Reg(reg + i)).collect()
+            }
+            ValueLoc::Stack(ss) => vec![Unit::Stack(ss)],
+        }
+    }
+
+    /// Let `value` occupy `loc`, which must not hold another live value.
+    fn occupy<L: Into<ir::entities::AnyEntity>>(
+        &mut self,
+        at: L,
+        value: Value,
+        loc: ValueLoc,
+    ) -> Result {
+        for unit in self.units(value, loc) {
+            if let Some(&(_, other)) = self.occupied.iter().find(|&&(u, _)| u == unit) {
+                return err!(
+                    at,
+                    "{} is assigned to {}, which holds the live value {}",
+                    value,
+                    loc.display(&self.reginfo),
+                    other
+                );
+            }
+            self.occupied.push((unit, value));
+        }
+        Ok(())
+    }
+
+    /// Free the locations held by `value`.
+    fn free(&mut self, value: Value) {
+        self.occupied.retain(|&(_, v)| v != value);
+    }
+}
+
+#[cfg(all(test, build_riscv))]
+mod tests {
+    use super::*;
+    use Context;
+    use cursor::{Cursor, FuncCursor};
+    use ir::types::I32;
+    use ir::{AbiParam, Function, InstBuilder};
+    use isa;
+    use settings;
+
+    #[test]
+    fn clobbered_register() {
+        let isa = isa::lookup("riscv").unwrap().finish(settings::Flags::new(&settings::builder()));
+
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let y = func.dfg.append_ebb_param(ebb0, I32);
+        let (sum, prod) = {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let sum = pos.ins().iadd(x, y);
+            let prod = pos.ins().bxor(sum, x);
+            let res = pos.ins().iadd(prod, sum);
+            pos.ins().return_(&[res]);
+            (sum, prod)
+        };
+
+        let mut ctx = Context::for_function(func);
+        ctx.compile(&*isa).unwrap();
+        verify_interference(&*isa, &ctx.func).unwrap();
+
+        // `sum` is still live when `prod` is defined.
+        ctx.func.locations[prod] = ctx.func.locations[sum];
+        let err = verify_interference(&*isa, &ctx.func).unwrap_err();
+        assert!(err.0[0].message.contains("holds the live value"), "{}", err);
+    }
+}
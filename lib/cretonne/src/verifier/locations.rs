@@ -1,9 +1,10 @@
 //! Verify value locations.
 
 use ir;
-use isa;
+use isa::{self, ConstraintKind};
 use regalloc::RegDiversions;
 use regalloc::liveness::Liveness;
+use std::string::String;
 use verifier::Result;
 use timing;
 
@@ -92,17 +93,81 @@ impl<'a> LocationVerifier<'a> {
         let constraints = self.encinfo.operand_constraints(enc).expect(
             "check_enc_constraints requires a legal encoding",
         );
+        let dfg = &self.func.dfg;
+        let recipe = self.encinfo.display(enc);
+
+        for (num, (&arg, constraint)) in dfg.inst_args(inst)
+            .iter()
+            .zip(constraints.ins)
+            .enumerate()
+        {
+            let loc = divert.get(arg, &self.func.locations);
+
+            if let ConstraintKind::Tied(out_index) = constraint.kind {
+                let out_val = dfg.inst_results(inst)[out_index as usize];
+                let out_loc = self.func.locations[out_val];
+                if loc != out_loc {
+                    return err!(
+                        inst,
+                        "{}: argument {} ({} in {}) must be in the same location as result {} \
+                         ({} in {})",
+                        recipe,
+                        num,
+                        arg,
+                        loc.display(&self.reginfo),
+                        out_index,
+                        out_val,
+                        out_loc.display(&self.reginfo)
+                    );
+                }
+            }
+
+            if !constraint.satisfied(loc) {
+                return err!(
+                    inst,
+                    "{}: argument {} ({} in {}) must be {}",
+                    recipe,
+                    num,
+                    arg,
+                    loc.display(&self.reginfo),
+                    self.describe_constraint(constraint)
+                );
+            }
+        }
 
-        if constraints.satisfied(inst, divert, self.func) {
-            return Ok(());
+        for (num, (&res, constraint)) in dfg.inst_results(inst)
+            .iter()
+            .zip(constraints.outs)
+            .enumerate()
+        {
+            let loc = divert.get(res, &self.func.locations);
+            if !constraint.satisfied(loc) {
+                return err!(
+                    inst,
+                    "{}: result {} ({} in {}) must be {}",
+                    recipe,
+                    num,
+                    res,
+                    loc.display(&self.reginfo),
+                    self.describe_constraint(constraint)
+                );
+            }
         }
 
-        // TODO: We could give a better error message here.
-        err!(
-            inst,
-            "{} constraints not satisfied",
-            self.encinfo.display(enc)
-        )
+        Ok(())
+    }
+
+    /// Describe the locations allowed by `constraint`.
+    fn describe_constraint(&self, constraint: &isa::OperandConstraint) -> String {
+        match constraint.kind {
+            ConstraintKind::Reg |
+            ConstraintKind::Tied(_) => format!("in a {} register", constraint.regclass),
+            ConstraintKind::FixedReg(reg) |
+            ConstraintKind::FixedTied(reg) => {
+                format!("in {}", self.reginfo.display_regunit(reg))
+            }
+            ConstraintKind::Stack => String::from("in a stack slot"),
+        }
     }
 
     /// Check that the result values produced by a ghost instruction are not assigned a value
@@ -35,16 +35,24 @@
 //! - Branches and jumps must pass arguments to destination EBBs that match the
 //!   expected types exactly. The number of arguments must match.
 //! - All EBBs in a jump table must take no arguments.
+//! - All EBBs in a jump table must be inserted in the layout, even when the jump table isn't used.
+//! - The jump tables used by `jump_table_base` instructions must not have missing entries.
 //! - Function calls are type checked against their signature.
 //! - The entry block must take arguments that match the signature of the current
 //!   function.
 //! - All return instructions must have return value operands matching the current
 //!   function signature.
+//! - Tail calls must call a function that returns the same types as the current function.
 //!
 //! Global variables
 //!
 //! - Detect cycles in deref(base) declarations.
 //!
+//! Memory flags
+//!
+//! - Only loads can be `speculatable`, and their address must be derived from the VM context or
+//!   a global variable.
+//!
 //! TODO:
 //! Ad hoc checking
 //!
@@ -62,7 +70,8 @@ use flowgraph::ControlFlowGraph;
 use ir::entities::AnyEntity;
 use ir::instructions::{InstructionFormat, BranchInfo, ResolvedConstraint, CallInfo};
 use ir::{types, Function, ValueDef, Ebb, Inst, SigRef, FuncRef, ValueList, JumpTable, StackSlot,
-         StackSlotKind, GlobalVar, Value, Type, Opcode, ValueLoc, ArgumentLoc};
+         StackSlotKind, GlobalVar, Value, Type, Opcode, ValueLoc, ArgumentLoc, ArgumentPurpose,
+         AbiParam, InstructionData};
 use ir;
 use isa::TargetIsa;
 use iterators::IteratorExtras;
@@ -78,54 +87,163 @@ use std::string::String;
 use timing;
 
 pub use self::cssa::verify_cssa;
+pub use self::interference::verify_interference;
 pub use self::liveness::verify_liveness;
 pub use self::locations::verify_locations;
 
 // Create an `Err` variant of `Result<X>` from a location and `format!` arguments.
+//
+// The error converts into the error type of the enclosing function, so it works for both a single
+// `VerifierError` and a list of `VerifierErrors`.
 macro_rules! err {
     ( $loc:expr, $msg:expr ) => {
-        Err(::verifier::Error {
+        Err(::verifier::VerifierError {
             location: $loc.into(),
             message: String::from($msg),
-        })
+            pass: ::timing::last_pass(),
+            origin: None,
+        }.into())
     };
 
     ( $loc:expr, $fmt:expr, $( $arg:expr ),+ ) => {
-        Err(::verifier::Error {
+        Err(::verifier::VerifierError {
             location: $loc.into(),
             message: format!( $fmt, $( $arg ),+ ),
-        })
+            pass: ::timing::last_pass(),
+            origin: None,
+        }.into())
     };
 }
 
 mod cssa;
 mod flags;
+mod interference;
 mod liveness;
 mod locations;
 
 /// A verifier error.
 #[derive(Debug, PartialEq, Eq)]
-pub struct Error {
+pub struct VerifierError {
     /// The entity causing the verifier error.
     pub location: AnyEntity,
     /// Error message.
     pub message: String,
+    /// Description of the last pass that changed the function before the error was detected, if
+    /// any.
+    pub pass: Option<&'static str>,
+    /// Description of the pass that created or last rewrote the offending instruction, if the
+    /// instruction origins are being tracked.
+    pub origin: Option<&'static str>,
+}
+
+impl VerifierError {
+    /// Set `origin` from the instruction origins recorded in `func`.
+    ///
+    /// The offending instruction is the instruction at `location`, or the instruction defining the
+    /// value at `location`.
+    pub fn set_origin(&mut self, func: &Function) {
+        let inst = match self.location {
+            AnyEntity::Inst(inst) => inst,
+            AnyEntity::Value(value) if func.dfg.value_is_valid(value) => {
+                match func.dfg.value_def(value) {
+                    ValueDef::Result(inst, _) => inst,
+                    ValueDef::Param(..) => return,
+                }
+            }
+            _ => return,
+        };
+        self.origin = func.inst_origins[inst];
+    }
 }
 
-impl Display for Error {
+impl Display for VerifierError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{}: {}", self.location, self.message)
     }
 }
 
-impl std_error::Error for Error {
+impl std_error::Error for VerifierError {
     fn description(&self) -> &str {
         &self.message
     }
 }
 
+/// All the errors found by a verifier run, in the order they were found.
+///
+/// The verifier keeps going after an error in one instruction, so a function with several problems
+/// can be fixed in one go. An error that makes the rest of an instruction impossible to check
+/// still stops the checks of that instruction.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VerifierErrors(pub Vec<VerifierError>);
+
+impl VerifierErrors {
+    /// Create an empty list of errors.
+    pub fn new() -> Self {
+        VerifierErrors(Vec::new())
+    }
+
+    /// Are there no errors?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Record the error of `result`, if any. Returns `true` if there was an error.
+    pub fn record(&mut self, result: StepResult) -> bool {
+        match result {
+            Ok(()) => false,
+            Err(e) => {
+                self.0.push(e);
+                true
+            }
+        }
+    }
+
+    /// Set the `origin` of all the errors from the instruction origins recorded in `func`.
+    pub fn set_origin(&mut self, func: &Function) {
+        for e in &mut self.0 {
+            e.set_origin(func);
+        }
+    }
+
+    /// Convert into a `Result` that is `Ok` when there are no errors.
+    pub fn into_result(self) -> Result {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl From<VerifierError> for VerifierErrors {
+    fn from(e: VerifierError) -> Self {
+        VerifierErrors(vec![e])
+    }
+}
+
+impl Display for VerifierErrors {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std_error::Error for VerifierErrors {
+    fn description(&self) -> &str {
+        self.0.first().map_or("no verifier errors", |e| &e.message)
+    }
+}
+
 /// Verifier result.
-pub type Result = result::Result<(), Error>;
+pub type Result = result::Result<(), VerifierErrors>;
+
+/// The result of a single verifier check, which stops at the first error.
+pub type StepResult = result::Result<(), VerifierError>;
 
 /// Verify `func`.
 pub fn verify_function<'a, FOI: Into<FlagsOrIsa<'a>>>(func: &Function, fisa: FOI) -> Result {
@@ -143,13 +261,15 @@ pub fn verify_context<'a, FOI: Into<FlagsOrIsa<'a>>>(
 ) -> Result {
     let _tt = timing::verifier();
     let verifier = Verifier::new(func, fisa.into());
+    let mut errors = VerifierErrors::new();
     if cfg.is_valid() {
-        verifier.cfg_integrity(cfg)?;
+        errors.record(verifier.cfg_integrity(cfg));
     }
     if domtree.is_valid() {
-        verifier.domtree_integrity(domtree)?;
+        errors.record(verifier.domtree_integrity(domtree));
     }
-    verifier.run()
+    verifier.check(&mut errors);
+    errors.into_result()
 }
 
 struct Verifier<'a> {
@@ -173,8 +293,20 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    // Check for cycles in the global variable declarations.
-    fn verify_global_vars(&self) -> Result {
+    // Check for cycles in the global variable declarations, and check the stack limit and the fuel
+    // counter.
+    fn verify_global_vars(&self) -> StepResult {
+        if let Some(gv) = self.func.stack_limit {
+            if !self.func.global_vars.is_valid(gv) {
+                return err!(AnyEntity::Function, "invalid stack limit {}", gv);
+            }
+        }
+        if let Some(gv) = self.func.fuel {
+            if !self.func.global_vars.is_valid(gv) {
+                return err!(AnyEntity::Function, "invalid fuel counter {}", gv);
+            }
+        }
+
         let mut seen = SparseSet::new();
 
         for gv in self.func.global_vars.keys() {
@@ -194,7 +326,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn ebb_integrity(&self, ebb: Ebb, inst: Inst) -> Result {
+    fn ebb_integrity(&self, ebb: Ebb, inst: Inst) -> StepResult {
 
         let is_terminator = self.func.dfg[inst].opcode().is_terminator();
         let is_last_inst = self.func.layout.last_inst(ebb) == Some(inst);
@@ -234,7 +366,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn instruction_integrity(&self, inst: Inst) -> Result {
+    fn instruction_integrity(&self, inst: Inst) -> StepResult {
         let inst_data = &self.func.dfg[inst];
         let dfg = &self.func.dfg;
 
@@ -245,7 +377,7 @@ impl<'a> Verifier<'a> {
 
         let fixed_results = inst_data.opcode().constraints().fixed_results();
         // var_results is 0 if we aren't a call instruction
-        let var_results = dfg.call_signature(inst)
+        let var_results = dfg.call_results_signature(inst)
             .map(|sig| dfg.signatures[sig].returns.len())
             .unwrap_or(0);
         let total_results = fixed_results + var_results;
@@ -264,7 +396,7 @@ impl<'a> Verifier<'a> {
         self.verify_entity_references(inst)
     }
 
-    fn verify_entity_references(&self, inst: Inst) -> Result {
+    fn verify_entity_references(&self, inst: Inst) -> StepResult {
         use ir::instructions::InstructionData::*;
 
         for &arg in self.func.dfg.inst_args(inst) {
@@ -316,10 +448,27 @@ impl<'a> Verifier<'a> {
             BranchTable { table, .. } => {
                 self.verify_jump_table(inst, table)?;
             }
+            BranchTableBase { table, .. } => {
+                self.verify_jump_table(inst, table)?;
+                let data = &self.func.jump_tables[table];
+                if data.entries().count() != data.len() {
+                    return err!(inst, "missing entries in {}", table);
+                }
+            }
             Call { func_ref, ref args, .. } => {
                 self.verify_func_ref(inst, func_ref)?;
                 self.verify_value_list(inst, args)?;
             }
+            Invoke {
+                func_ref,
+                destination,
+                ref args,
+                ..
+            } => {
+                self.verify_func_ref(inst, func_ref)?;
+                self.verify_ebb(inst, destination)?;
+                self.verify_value_list(inst, args)?;
+            }
             IndirectCall { sig_ref, ref args, .. } => {
                 self.verify_sig_ref(inst, sig_ref)?;
                 self.verify_value_list(inst, args)?;
@@ -327,6 +476,10 @@ impl<'a> Verifier<'a> {
             FuncAddr { func_ref, .. } => {
                 self.verify_func_ref(inst, func_ref)?;
             }
+            CallGlobalVar { func_ref, global_var, .. } => {
+                self.verify_func_ref(inst, func_ref)?;
+                self.verify_global_var(inst, global_var)?;
+            }
             StackLoad { stack_slot, .. } |
             StackStore { stack_slot, .. } => {
                 self.verify_stack_slot(inst, stack_slot)?;
@@ -334,6 +487,9 @@ impl<'a> Verifier<'a> {
             UnaryGlobalVar { global_var, .. } => {
                 self.verify_global_var(inst, global_var)?;
             }
+            UnaryConst { constant, .. } => {
+                self.verify_constant(inst, constant)?;
+            }
             HeapAddr { heap, .. } => {
                 self.verify_heap(inst, heap)?;
             }
@@ -343,6 +499,21 @@ impl<'a> Verifier<'a> {
             RegFill { src, .. } => {
                 self.verify_stack_slot(inst, src)?;
             }
+            AtomicLoad { ordering, .. } => {
+                if ordering.is_release() && ordering != ir::AtomicOrdering::SeqCst {
+                    return err!(inst, "{} ordering on an atomic load", ordering);
+                }
+            }
+            AtomicStore { ordering, .. } => {
+                if ordering.is_acquire() && ordering != ir::AtomicOrdering::SeqCst {
+                    return err!(inst, "{} ordering on an atomic store", ordering);
+                }
+            }
+            Fence { ordering, .. } => {
+                if ordering == ir::AtomicOrdering::Relaxed {
+                    return err!(inst, "relaxed fence");
+                }
+            }
 
             // Exhaustive list so we can't forget to add new formats
             Unary { .. } |
@@ -363,19 +534,24 @@ impl<'a> Verifier<'a> {
             IntSelect { .. } |
             Load { .. } |
             Store { .. } |
+            AtomicCas { .. } |
+            AtomicRmw { .. } |
+            Barrier { .. } |
             RegMove { .. } |
             CopySpecial { .. } |
             Trap { .. } |
             CondTrap { .. } |
             IntCondTrap { .. } |
             FloatCondTrap { .. } |
+            BinaryTrap { .. } |
+            Custom { .. } |
             NullAry { .. } => {}
         }
 
         Ok(())
     }
 
-    fn verify_ebb(&self, inst: Inst, e: Ebb) -> Result {
+    fn verify_ebb(&self, inst: Inst, e: Ebb) -> StepResult {
         if !self.func.dfg.ebb_is_valid(e) || !self.func.layout.is_ebb_inserted(e) {
             return err!(inst, "invalid ebb reference {}", e);
         }
@@ -387,7 +563,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn verify_sig_ref(&self, inst: Inst, s: SigRef) -> Result {
+    fn verify_sig_ref(&self, inst: Inst, s: SigRef) -> StepResult {
         if !self.func.dfg.signatures.is_valid(s) {
             err!(inst, "invalid signature reference {}", s)
         } else {
@@ -395,7 +571,7 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_func_ref(&self, inst: Inst, f: FuncRef) -> Result {
+    fn verify_func_ref(&self, inst: Inst, f: FuncRef) -> StepResult {
         if !self.func.dfg.ext_funcs.is_valid(f) {
             err!(inst, "invalid function reference {}", f)
         } else {
@@ -403,7 +579,7 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_stack_slot(&self, inst: Inst, ss: StackSlot) -> Result {
+    fn verify_stack_slot(&self, inst: Inst, ss: StackSlot) -> StepResult {
         if !self.func.stack_slots.is_valid(ss) {
             err!(inst, "invalid stack slot {}", ss)
         } else {
@@ -411,7 +587,7 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_global_var(&self, inst: Inst, gv: GlobalVar) -> Result {
+    fn verify_global_var(&self, inst: Inst, gv: GlobalVar) -> StepResult {
         if !self.func.global_vars.is_valid(gv) {
             err!(inst, "invalid global variable {}", gv)
         } else {
@@ -419,7 +595,26 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_heap(&self, inst: Inst, heap: ir::Heap) -> Result {
+    fn verify_constant(&self, inst: Inst, constant: ir::Constant) -> StepResult {
+        if !self.func.constants.is_valid(constant) {
+            return err!(inst, "invalid constant {}", constant);
+        }
+        let ty = self.func.dfg.ctrl_typevar(inst);
+        let size = self.func.constants[constant].len();
+        if size != ty.bytes() as usize {
+            return err!(
+                inst,
+                "{} has {} bytes, but {} needs {}",
+                constant,
+                size,
+                ty,
+                ty.bytes()
+            );
+        }
+        Ok(())
+    }
+
+    fn verify_heap(&self, inst: Inst, heap: ir::Heap) -> StepResult {
         if !self.func.heaps.is_valid(heap) {
             err!(inst, "invalid heap {}", heap)
         } else {
@@ -427,7 +622,7 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_value_list(&self, inst: Inst, l: &ValueList) -> Result {
+    fn verify_value_list(&self, inst: Inst, l: &ValueList) -> StepResult {
         if !l.is_valid(&self.func.dfg.value_lists) {
             err!(inst, "invalid value list reference {:?}", l)
         } else {
@@ -435,15 +630,30 @@ impl<'a> Verifier<'a> {
         }
     }
 
-    fn verify_jump_table(&self, inst: Inst, j: JumpTable) -> Result {
+    fn verify_jump_table_entries(&self) -> StepResult {
+        for jt in self.func.jump_tables.keys() {
+            for (idx, ebb) in self.func.jump_tables[jt].entries() {
+                if !self.func.dfg.ebb_is_valid(ebb) || !self.func.layout.is_ebb_inserted(ebb) {
+                    return err!(jt, "entry {} refers to {} which is not in the layout", idx, ebb);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_jump_table(&self, inst: Inst, j: JumpTable) -> StepResult {
         if !self.func.jump_tables.is_valid(j) {
-            err!(inst, "invalid jump table reference {}", j)
-        } else {
-            Ok(())
+            return err!(inst, "invalid jump table reference {}", j);
+        }
+        for (_, ebb) in self.func.jump_tables[j].entries() {
+            if !self.func.dfg.ebb_is_valid(ebb) {
+                return err!(inst, "invalid ebb reference {} in {}", ebb, j);
+            }
         }
+        Ok(())
     }
 
-    fn verify_value(&self, loc_inst: Inst, v: Value) -> Result {
+    fn verify_value(&self, loc_inst: Inst, v: Value) -> StepResult {
         let dfg = &self.func.dfg;
         if !dfg.value_is_valid(v) {
             return err!(loc_inst, "invalid value reference {}", v);
@@ -512,7 +722,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn domtree_integrity(&self, domtree: &DominatorTree) -> Result {
+    fn domtree_integrity(&self, domtree: &DominatorTree) -> StepResult {
         // We consider two `DominatorTree`s to be equal if they return the same immediate
         // dominator for each EBB. Therefore the current domtree is valid if it matches the freshly
         // computed one.
@@ -572,7 +782,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck_entry_block_params(&self) -> Result {
+    fn typecheck_entry_block_params(&self) -> StepResult {
         if let Some(ebb) = self.func.layout.entry_block() {
             let expected_types = &self.func.signature.params;
             let ebb_param_count = self.func.dfg.num_ebb_params(ebb);
@@ -602,7 +812,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck(&self, inst: Inst) -> Result {
+    fn typecheck(&self, inst: Inst) -> StepResult {
         let inst_data = &self.func.dfg[inst];
         let constraints = inst_data.opcode().constraints();
 
@@ -630,7 +840,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck_results(&self, inst: Inst, ctrl_type: Type) -> Result {
+    fn typecheck_results(&self, inst: Inst, ctrl_type: Type) -> StepResult {
         let mut i = 0;
         for &result in self.func.dfg.inst_results(inst) {
             let result_type = self.func.dfg.value_type(result);
@@ -659,7 +869,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck_fixed_args(&self, inst: Inst, ctrl_type: Type) -> Result {
+    fn typecheck_fixed_args(&self, inst: Inst, ctrl_type: Type) -> StepResult {
         let constraints = self.func.dfg[inst].opcode().constraints();
 
         for (i, &arg) in self.func.dfg.inst_fixed_args(inst).iter().enumerate() {
@@ -694,8 +904,20 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck_variable_args(&self, inst: Inst) -> Result {
+    fn typecheck_variable_args(&self, inst: Inst) -> StepResult {
         match self.func.dfg.analyze_branch(inst) {
+            // The arguments of an `invoke` are checked against the call signature below.
+            BranchInfo::SingleDest(ebb, _) if self.func.dfg[inst].opcode().is_call() => {
+                let arg_count = self.func.dfg.num_ebb_params(ebb);
+                if arg_count != 0 {
+                    return err!(
+                        inst,
+                        "landing pad {} can't have parameters, but has {}",
+                        ebb,
+                        arg_count
+                    );
+                }
+            }
             BranchInfo::SingleDest(ebb, _) => {
                 let iter = self.func.dfg.ebb_params(ebb).iter().map(|&v| {
                     self.func.dfg.value_type(v)
@@ -743,7 +965,7 @@ impl<'a> Verifier<'a> {
         &self,
         inst: Inst,
         iter: I,
-    ) -> Result {
+    ) -> StepResult {
         let variable_args = self.func.dfg.inst_variable_args(inst);
         let mut i = 0;
 
@@ -782,7 +1004,7 @@ impl<'a> Verifier<'a> {
     ///
     /// When a signature has been legalized, all values passed as outgoing arguments on the stack
     /// must be assigned to a matching `OutgoingArg` stack slot.
-    fn check_outgoing_args(&self, inst: Inst, sig_ref: SigRef) -> Result {
+    fn check_outgoing_args(&self, inst: Inst, sig_ref: SigRef) -> StepResult {
         let sig = &self.func.dfg.signatures[sig_ref];
 
         // Before legalization, there's nothing to check.
@@ -844,7 +1066,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn typecheck_return(&self, inst: Inst) -> Result {
+    fn typecheck_return(&self, inst: Inst) -> StepResult {
         if self.func.dfg[inst].opcode().is_return() {
             let args = self.func.dfg.inst_variable_args(inst);
             let expected_types = &self.func.signature.returns;
@@ -864,13 +1086,33 @@ impl<'a> Verifier<'a> {
                     );
                 }
             }
+        } else if self.func.dfg[inst].opcode().is_terminator() {
+            // A tail call returns directly to our caller, so the callee must return the same
+            // types. Special-purpose return values are added to both signatures by the ABI.
+            if let Some(sig_ref) = self.func.dfg.call_signature(inst) {
+                let normal = |rets: &[AbiParam]| -> Vec<Type> {
+                    rets.iter()
+                        .filter(|r| r.purpose == ArgumentPurpose::Normal)
+                        .map(|r| r.value_type)
+                        .collect()
+                };
+                if normal(&self.func.dfg.signatures[sig_ref].returns) !=
+                    normal(&self.func.signature.returns)
+                {
+                    return err!(
+                        inst,
+                        "tail call to {} must return the same types as the function",
+                        sig_ref
+                    );
+                }
+            }
         }
         Ok(())
     }
 
     // Check special-purpose type constraints that can't be expressed in the normal opcode
     // constraints.
-    fn typecheck_special(&self, inst: Inst, ctrl_type: Type) -> Result {
+    fn typecheck_special(&self, inst: Inst, ctrl_type: Type) -> StepResult {
         if let ir::InstructionData::Unary { opcode, arg } = self.func.dfg[inst] {
             let arg_type = self.func.dfg.value_type(arg);
             match opcode {
@@ -916,7 +1158,7 @@ impl<'a> Verifier<'a> {
         Ok(())
     }
 
-    fn cfg_integrity(&self, cfg: &ControlFlowGraph) -> Result {
+    fn cfg_integrity(&self, cfg: &ControlFlowGraph) -> StepResult {
         let mut expected_succs = BTreeSet::<Ebb>::new();
         let mut got_succs = BTreeSet::<Ebb>::new();
         let mut expected_preds = BTreeSet::<Inst>::new();
@@ -967,7 +1209,7 @@ impl<'a> Verifier<'a> {
 
     /// If the verifier has been set up with an ISA, make sure that the recorded encoding for the
     /// instruction (if any) matches how the ISA would encode it.
-    fn verify_encoding(&self, inst: Inst) -> Result {
+    fn verify_encoding(&self, inst: Inst) -> StepResult {
         // When the encodings table is empty, we don't require any instructions to be encoded.
         //
         // Once some instructions are encoded, we require all side-effecting instructions to have a
@@ -1080,7 +1322,65 @@ impl<'a> Verifier<'a> {
 
     /// Verify the `return_at_end` property which requires that there are no internal return
     /// instructions.
-    fn verify_return_at_end(&self) -> Result {
+    /// Check that volatile accesses don't have flags that allow them to be optimized.
+    fn verify_volatile(&self, inst: Inst) -> StepResult {
+        let flags = match self.func.dfg[inst] {
+            InstructionData::Load { flags, .. } |
+            InstructionData::Store { flags, .. } => flags,
+            _ => return Ok(()),
+        };
+        if flags.volatile() {
+            if flags.readonly() {
+                return err!(inst, "volatile access can't be readonly");
+            }
+            if flags.speculatable() {
+                return err!(inst, "volatile access can't be speculatable");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `speculatable` accesses only read memory declared by the embedder.
+    fn verify_speculatable(&self, inst: Inst) -> StepResult {
+        let dfg = &self.func.dfg;
+        let mut addr = match dfg[inst] {
+            InstructionData::Load { flags, arg, .. } if flags.speculatable() => arg,
+            InstructionData::Store { flags, .. } if flags.speculatable() => {
+                return err!(inst, "stores can't be speculatable");
+            }
+            _ => return Ok(()),
+        };
+
+        loop {
+            match dfg.value_def(dfg.resolve_aliases(addr)) {
+                ValueDef::Result(def, _) => {
+                    match dfg[def] {
+                        InstructionData::UnaryGlobalVar { opcode: Opcode::GlobalAddr, .. } => {
+                            return Ok(())
+                        }
+                        InstructionData::BinaryImm { opcode: Opcode::IaddImm, arg, .. } |
+                        InstructionData::Load { arg, .. } => addr = arg,
+                        _ => break,
+                    }
+                }
+                ValueDef::Param(ebb, num) => {
+                    if Some(ebb) == self.func.layout.entry_block() &&
+                        self.func.signature.params.get(num).map(|p| p.purpose) ==
+                            Some(ArgumentPurpose::VMContext)
+                    {
+                        return Ok(());
+                    }
+                    break;
+                }
+            }
+        }
+        err!(
+            inst,
+            "speculatable load address must be derived from the VM context or a global variable"
+        )
+    }
+
+    fn verify_return_at_end(&self) -> StepResult {
         for ebb in self.func.layout.ebbs() {
             let inst = self.func.layout.last_inst(ebb).unwrap();
             if self.func.dfg[inst].opcode().is_return() &&
@@ -1094,31 +1394,53 @@ impl<'a> Verifier<'a> {
     }
 
     pub fn run(&self) -> Result {
-        self.verify_global_vars()?;
-        self.typecheck_entry_block_params()?;
+        let mut errors = VerifierErrors::new();
+        self.check(&mut errors);
+        errors.into_result()
+    }
+
+    /// Run all the checks, and record their errors in `errors`.
+    ///
+    /// An instruction that fails the integrity checks or the type checks isn't checked further,
+    /// and the function-level checks only run when all the instructions are valid.
+    fn check(&self, errors: &mut VerifierErrors) {
+        errors.record(self.verify_global_vars());
+        errors.record(self.verify_jump_table_entries());
+        errors.record(self.typecheck_entry_block_params());
         for ebb in self.func.layout.ebbs() {
             for inst in self.func.layout.ebb_insts(ebb) {
-                self.ebb_integrity(ebb, inst)?;
-                self.instruction_integrity(inst)?;
-                self.typecheck(inst)?;
-                self.verify_encoding(inst)?;
+                let malformed = errors.record(
+                    self.ebb_integrity(ebb, inst)
+                        .and_then(|()| self.instruction_integrity(inst))
+                        .and_then(|()| self.typecheck(inst)),
+                );
+                if malformed {
+                    continue;
+                }
+                errors.record(self.verify_volatile(inst));
+                errors.record(self.verify_speculatable(inst));
+                errors.record(self.verify_encoding(inst));
             }
         }
+        if !errors.is_empty() {
+            return;
+        }
 
         if self.flags.return_at_end() {
-            self.verify_return_at_end()?;
+            errors.record(self.verify_return_at_end());
         }
 
-        verify_flags(self.func, &self.expected_cfg, self.isa)?;
-
-        Ok(())
+        if let Err(e) = verify_flags(self.func, &self.expected_cfg, self.isa) {
+            errors.0.extend(e.0);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Verifier, Error};
-    use ir::Function;
+    use super::{Verifier, VerifierError};
+    use ir::{Function, JumpTableData};
+    use ir::entities::AnyEntity;
     use ir::instructions::{InstructionData, Opcode};
     use entity::EntityList;
     use settings;
@@ -1127,9 +1449,9 @@ mod tests {
         ($e:expr, $msg:expr) => (
             match $e {
                 Ok(_) => { panic!("Expected an error!") },
-                Err(Error { message, .. } ) => {
-                    if !message.contains($msg) {
-                       panic!(format!("'{}' did not contain the substring '{}'", message, $msg));
+                Err(errors) => {
+                    if !errors.0.iter().any(|e: &VerifierError| e.message.contains($msg)) {
+                       panic!(format!("'{}' did not contain the substring '{}'", errors, $msg));
                     }
                 }
             }
@@ -1166,4 +1488,64 @@ mod tests {
         let verifier = Verifier::new(&func, flags.into());
         assert_err_with_msg!(verifier.run(), "instruction format");
     }
+
+    #[test]
+    fn all_errors() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        func.layout.append_ebb(ebb0);
+        let bad_format = func.dfg.make_inst(InstructionData::UnaryImm {
+            opcode: Opcode::F32const,
+            imm: 0.into(),
+        });
+        func.layout.append_inst(bad_format, ebb0);
+        let jump = func.dfg.make_inst(InstructionData::Jump {
+            opcode: Opcode::Jump,
+            destination: ebb0,
+            args: EntityList::default(),
+        });
+        func.layout.append_inst(jump, ebb0);
+        let flags = &settings::Flags::new(&settings::builder());
+        let verifier = Verifier::new(&func, flags.into());
+
+        // The verifier keeps going after the first bad instruction.
+        let errors = verifier.run().unwrap_err();
+        let locations: Vec<AnyEntity> = errors.0.iter().map(|e| e.location).collect();
+        assert_eq!(locations, [AnyEntity::from(bad_format), AnyEntity::from(jump)]);
+        assert!(errors.0[1].message.contains("entry ebb"), "{}", errors);
+    }
+
+    #[test]
+    fn jump_table_not_in_layout() {
+        let mut func = Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        func.layout.append_ebb(ebb0);
+        let mut data = JumpTableData::new();
+        data.push_entry(ebb1);
+        let jt = func.jump_tables.push(data);
+        func.layout.append_inst(
+            func.dfg.make_inst(InstructionData::MultiAry {
+                opcode: Opcode::Return,
+                args: EntityList::default(),
+            }),
+            ebb0,
+        );
+        let flags = &settings::Flags::new(&settings::builder());
+
+        // The jump table isn't used, but its entries are still checked.
+        let errors = Verifier::new(&func, flags.into()).run().unwrap_err();
+        assert_eq!(errors.0[0].location, AnyEntity::from(jt));
+        assert!(errors.0[0].message.contains("not in the layout"), "{}", errors);
+
+        func.layout.append_ebb(ebb1);
+        func.layout.append_inst(
+            func.dfg.make_inst(InstructionData::MultiAry {
+                opcode: Opcode::Return,
+                args: EntityList::default(),
+            }),
+            ebb1,
+        );
+        assert_eq!(Verifier::new(&func, flags.into()).run(), Ok(()));
+    }
 }
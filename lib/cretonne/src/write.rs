@@ -4,27 +4,61 @@
 //! equivalent textual representation. This textual representation can be read back by the
 //! `cretonne-reader` crate.
 
-use ir::{Function, DataFlowGraph, Ebb, Inst, Value, ValueDef, Type, SigRef};
+use binemit::{RegDiversions, TextSink};
+use ir::{Annotation, Function, DataFlowGraph, Ebb, Inst, Value, ValueDef, Type, SigRef};
 use isa::{TargetIsa, RegInfo};
 use std::fmt::{self, Result, Error, Write};
 use std::result;
 use packed_option::ReservedValue;
 use std::string::String;
 
+/// Optional code size annotations written as comments after the encoded instructions.
+///
+/// The encoding recipe of an instruction is always shown in its `[...]` prefix. These annotations
+/// add the information needed for code size investigations, and the machine code itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Annotations {
+    /// Annotate each encoded instruction with its size in bytes.
+    pub sizes: bool,
+    /// Annotate each instruction with its offset from the start of the function.
+    ///
+    /// The offsets are only available after the `binemit::relax_branches()` function has computed
+    /// the EBB header offsets.
+    pub offsets: bool,
+    /// Annotate each encoded instruction with its machine code and relocations, in the format of
+    /// `binemit::TextSink`.
+    ///
+    /// Like the offsets, the machine code is only available after `binemit::relax_branches()`.
+    pub bytes: bool,
+}
+
 /// Write `func` to `w` as equivalent text.
 /// Use `isa` to emit ISA-dependent annotations.
 pub fn write_function(w: &mut Write, func: &Function, isa: Option<&TargetIsa>) -> Result {
+    write_function_with_annotations(w, func, isa, Annotations::default())
+}
+
+/// Write `func` to `w` like `write_function()`, adding the code size `annotations` that are
+/// available.
+pub fn write_function_with_annotations(
+    w: &mut Write,
+    func: &Function,
+    isa: Option<&TargetIsa>,
+    annotations: Annotations,
+) -> Result {
     let regs = isa.map(TargetIsa::register_info);
     let regs = regs.as_ref();
 
     write_spec(w, func, regs)?;
-    writeln!(w, " {{")?;
+    write!(w, " {{")?;
+    write_annotations(w, func.annotations.function())?;
+    writeln!(w, "")?;
     let mut any = write_preamble(w, func, regs)?;
     for ebb in &func.layout {
         if any {
             writeln!(w, "")?;
         }
-        write_ebb(w, func, isa, ebb)?;
+        write_annotated_ebb(w, func, isa, ebb, annotations)?;
         any = true;
     }
     writeln!(w, "}}")
@@ -87,6 +121,21 @@ fn write_preamble(
         writeln!(w, "    {} = {}", jt, func.jump_tables[jt])?;
     }
 
+    for constant in func.constants.keys() {
+        any = true;
+        writeln!(w, "    {} = {}", constant, func.constants[constant])?;
+    }
+
+    if let Some(gv) = func.stack_limit {
+        any = true;
+        writeln!(w, "    stack_limit = {}", gv)?;
+    }
+
+    if let Some(gv) = func.fuel {
+        any = true;
+        writeln!(w, "    fuel = {}", gv)?;
+    }
+
     Ok(any)
 }
 
@@ -118,6 +167,7 @@ pub fn write_ebb_header(
     //    ebb1:
     //    ebb1(v1: i32):
     //    ebb10(v4: f64, v5: b1):
+    //    ebb11 cold:
     //
 
     // The `indent` is the instruction indentation. EBB headers are 4 spaces out from that.
@@ -127,22 +177,31 @@ pub fn write_ebb_header(
     let regs = regs.as_ref();
 
     let mut args = func.dfg.ebb_params(ebb).iter().cloned();
-    match args.next() {
-        None => return writeln!(w, ":"),
-        Some(arg) => {
-            write!(w, "(")?;
+    if let Some(arg) = args.next() {
+        write!(w, "(")?;
+        write_arg(w, func, regs, arg)?;
+        // Remaining arguments.
+        for arg in args {
+            write!(w, ", ")?;
             write_arg(w, func, regs, arg)?;
         }
+        write!(w, ")")?;
     }
-    // Remaining arguments.
-    for arg in args {
-        write!(w, ", ")?;
-        write_arg(w, func, regs, arg)?;
+    if func.layout.is_cold(ebb) {
+        write!(w, " cold")?;
     }
-    writeln!(w, "):")
+    write!(w, ":")?;
+    write_annotations(w, func.annotations.ebb(ebb))?;
+    writeln!(w, "")
 }
 
-pub fn write_ebb(w: &mut Write, func: &Function, isa: Option<&TargetIsa>, ebb: Ebb) -> Result {
+fn write_annotated_ebb(
+    w: &mut Write,
+    func: &Function,
+    isa: Option<&TargetIsa>,
+    ebb: Ebb,
+    annotations: Annotations,
+) -> Result {
     // Indent all instructions if any encodings are present.
     let indent = if func.encodings.is_empty() && func.srclocs.is_empty() {
         4
@@ -151,8 +210,45 @@ pub fn write_ebb(w: &mut Write, func: &Function, isa: Option<&TargetIsa>, ebb: E
     };
 
     write_ebb_header(w, func, isa, ebb, indent)?;
+
+    // Offsets can only be annotated after the code layout has been computed.
+    let encinfo = isa.map(TargetIsa::encoding_info);
+    let mut offset = if encinfo.is_some() && annotations.offsets && !func.offsets.is_empty() {
+        Some(func.offsets[ebb])
+    } else {
+        None
+    };
+    let mut code = match isa {
+        Some(isa) if annotations.bytes && !func.offsets.is_empty() => {
+            Some((TextSink::new(func.offsets[ebb], isa.endianness()), RegDiversions::new()))
+        }
+        _ => None,
+    };
     for inst in func.layout.ebb_insts(ebb) {
-        write_instruction(w, func, isa, inst, indent)?;
+        write_value_aliases(w, func, inst, indent)?;
+        write_instruction_line(w, func, isa, inst, indent)?;
+        write_annotations(w, func.annotations.inst(inst))?;
+        let size = match (encinfo.as_ref(), func.encodings.get(inst)) {
+            (Some(encinfo), Some(&enc)) if enc.is_legal() => encinfo.bytes(enc),
+            _ => 0,
+        };
+        match offset {
+            Some(off) if annotations.sizes && size > 0 => {
+                write!(w, " ; offset {:#x}, size {}", off, size)?
+            }
+            Some(off) => write!(w, " ; offset {:#x}", off)?,
+            None if annotations.sizes && size > 0 => write!(w, " ; size {}", size)?,
+            None => {}
+        }
+        if let (Some(isa), Some(&mut (ref mut sink, ref mut divert))) = (isa, code.as_mut()) {
+            if size > 0 {
+                isa.emit_inst(func, inst, divert, sink);
+                write!(w, " ; bin: {}", sink.take_text())?;
+            }
+        }
+        write_origin(w, func, inst)?;
+        writeln!(w, "")?;
+        offset = offset.map(|off| off + size);
     }
     Ok(())
 }
@@ -209,7 +305,8 @@ fn write_value_aliases(w: &mut Write, func: &Function, inst: Inst, indent: usize
     Ok(())
 }
 
-fn write_instruction(
+/// Write `inst` to `w` on a line of its own, preceded by any value aliases it uses.
+pub fn write_instruction(
     w: &mut Write,
     func: &Function,
     isa: Option<&TargetIsa>,
@@ -218,7 +315,42 @@ fn write_instruction(
 ) -> Result {
     // Value aliases come out on lines before the instruction using them.
     write_value_aliases(w, func, inst, indent)?;
+    write_instruction_line(w, func, isa, inst, indent)?;
+    write_annotations(w, func.annotations.inst(inst))?;
+    write_origin(w, func, inst)?;
+    writeln!(w, "")
+}
+
+/// Write a comment with the annotations in `list`, if there are any.
+///
+/// The annotations must come first in the comments following an entity to be read back.
+fn write_annotations(w: &mut Write, list: &[Annotation]) -> Result {
+    if list.is_empty() {
+        return Ok(());
+    }
+    write!(w, " ;")?;
+    for annotation in list {
+        write!(w, " {}", annotation)?;
+    }
+    Ok(())
+}
+
+/// Write a comment with the pass that created or last rewrote `inst`, if it is known.
+fn write_origin(w: &mut Write, func: &Function, inst: Inst) -> Result {
+    match func.inst_origins[inst] {
+        Some(origin) => write!(w, " ; from {}", origin),
+        None => Ok(()),
+    }
+}
 
+/// Write `inst` to `w` without a line terminator.
+fn write_instruction_line(
+    w: &mut Write,
+    func: &Function,
+    isa: Option<&TargetIsa>,
+    inst: Inst,
+    indent: usize,
+) -> Result {
     // Prefix containing source location, encoding, and value locations.
     let mut s = String::with_capacity(16);
 
@@ -270,8 +402,7 @@ fn write_instruction(
         None => write!(w, "{}", opcode)?,
     }
 
-    write_operands(w, &func.dfg, isa, inst)?;
-    writeln!(w, "")
+    write_operands(w, &func.dfg, isa, inst)
 }
 
 /// Write the operands of `inst` to `w` with a prepended space.
@@ -290,6 +421,7 @@ pub fn write_operands(
         UnaryIeee64 { imm, .. } => write!(w, " {}", imm),
         UnaryBool { imm, .. } => write!(w, " {}", imm),
         UnaryGlobalVar { global_var, .. } => write!(w, " {}", global_var),
+        UnaryConst { constant, .. } => write!(w, " {}", constant),
         Binary { args, .. } => write!(w, " {}, {}", args[0], args[1]),
         BinaryImm { arg, imm, .. } => write!(w, " {}, {}", arg, imm),
         Ternary { args, .. } => write!(w, " {}, {}, {}", args[0], args[1], args[2]),
@@ -359,9 +491,24 @@ pub fn write_operands(
             write_ebb_args(w, &args[2..])
         }
         BranchTable { arg, table, .. } => write!(w, " {}, {}", arg, table),
+        BranchTableBase { table, .. } => write!(w, " {}", table),
         Call { func_ref, ref args, .. } => {
             write!(w, " {}({})", func_ref, DisplayValues(args.as_slice(pool)))
         }
+        Invoke {
+            func_ref,
+            destination,
+            ref args,
+            ..
+        } => {
+            write!(
+                w,
+                " {}({}), {}",
+                func_ref,
+                DisplayValues(args.as_slice(pool)),
+                destination
+            )
+        }
         IndirectCall { sig_ref, ref args, .. } => {
             let args = args.as_slice(pool);
             write!(
@@ -373,6 +520,7 @@ pub fn write_operands(
             )
         }
         FuncAddr { func_ref, .. } => write!(w, " {}", func_ref),
+        CallGlobalVar { func_ref, global_var, .. } => write!(w, " {}, {}", func_ref, global_var),
         StackLoad { stack_slot, offset, .. } => write!(w, " {}{}", stack_slot, offset),
         StackStore {
             arg,
@@ -388,6 +536,43 @@ pub fn write_operands(
             offset,
             ..
         } => write!(w, "{} {}, {}{}", flags, args[0], args[1], offset),
+        AtomicLoad {
+            flags,
+            ordering,
+            arg,
+            ..
+        } => write!(w, "{} {} {}", flags, ordering, arg),
+        AtomicStore {
+            flags,
+            ordering,
+            args,
+            ..
+        } => write!(w, "{} {} {}, {}", flags, ordering, args[0], args[1]),
+        AtomicCas {
+            flags,
+            ordering,
+            args,
+            ..
+        } => {
+            write!(
+                w,
+                "{} {} {}, {}, {}",
+                flags,
+                ordering,
+                args[0],
+                args[1],
+                args[2]
+            )
+        }
+        AtomicRmw {
+            op,
+            flags,
+            ordering,
+            args,
+            ..
+        } => write!(w, " {}{} {} {}, {}", op, flags, ordering, args[0], args[1]),
+        Fence { ordering, .. } => write!(w, " {}", ordering),
+        Barrier { kind, .. } => write!(w, " {}", kind),
         RegMove { arg, src, dst, .. } => {
             if let Some(isa) = isa {
                 let regs = isa.register_info();
@@ -435,6 +620,8 @@ pub fn write_operands(
         CondTrap { arg, code, .. } => write!(w, " {}, {}", arg, code),
         IntCondTrap { cond, arg, code, .. } => write!(w, " {} {}, {}", cond, arg, code),
         FloatCondTrap { cond, arg, code, .. } => write!(w, " {} {}, {}", cond, arg, code),
+        BinaryTrap { args, code, .. } => write!(w, " {}, {}, {}", args[0], args[1], code),
+        Custom { op, ref args, .. } => write!(w, " {}({})", op, DisplayValues(args.as_slice(pool))),
     }
 }
 
@@ -0,0 +1,178 @@
+//! Disassembly of the machine code generated for a function.
+//!
+//! The disassembler is Capstone, which is only available when this crate is built with the
+//! `disas` feature. Without it, `disassemble` returns an error and `write_interleaved` lists the
+//! bytes of each encoded instruction without their disassembly.
+
+use cretonne::ir::Function;
+use cretonne::isa::TargetIsa;
+use std::fmt::{self, Write};
+
+/// A disassembled machine instruction.
+pub struct Insn {
+    /// Offset of the instruction from the start of the code.
+    pub offset: u32,
+    /// The bytes of the instruction.
+    pub bytes: Vec<u8>,
+    /// The mnemonic and operands of the instruction.
+    pub text: String,
+}
+
+/// Is a disassembler available in this build?
+pub fn is_available() -> bool {
+    cfg!(feature = "disas")
+}
+
+/// Disassemble `code` which was generated for `isa`.
+///
+/// Disassembly stops at the first sequence of bytes that isn't a valid instruction.
+#[cfg(feature = "disas")]
+pub fn disassemble(isa: &TargetIsa, code: &[u8]) -> Result<Vec<Insn>, String> {
+    use capstone::prelude::*;
+
+    let cs = match isa.name() {
+        "intel" => {
+            let mode = if isa.flags().is_64bit() {
+                arch::x86::ArchMode::Mode64
+            } else {
+                arch::x86::ArchMode::Mode32
+            };
+            Capstone::new().x86().mode(mode).build()
+        }
+        "arm32" => Capstone::new().arm().mode(arch::arm::ArchMode::Arm).build(),
+        "arm64" => Capstone::new().arm64().mode(arch::arm64::ArchMode::Arm).build(),
+        name => return Err(format!("no disassembler for the {} ISA", name)),
+    }.map_err(|e| e.to_string())?;
+
+    let insns = cs.disasm_all(code, 0).map_err(|e| e.to_string())?;
+    Ok(
+        insns
+            .iter()
+            .map(|i| {
+                let text = match (i.mnemonic(), i.op_str()) {
+                    (Some(mnemonic), Some(ops)) if !ops.is_empty() => {
+                        format!("{} {}", mnemonic, ops)
+                    }
+                    (Some(mnemonic), _) => mnemonic.to_string(),
+                    (None, _) => String::from("?"),
+                };
+                Insn {
+                    offset: i.address() as u32,
+                    bytes: i.bytes().to_vec(),
+                    text,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Disassemble `code` which was generated for `isa`.
+#[cfg(not(feature = "disas"))]
+pub fn disassemble(_isa: &TargetIsa, _code: &[u8]) -> Result<Vec<Insn>, String> {
+    Err(String::from(
+        "disassembly requires building with the disas feature",
+    ))
+}
+
+/// Write the encoded instructions of `func` with the disassembly of the machine code emitted for
+/// them in `code`.
+///
+/// Each instruction is followed by the disassembled instructions that start within its encoding,
+/// so padding emitted before an instruction is listed with it. When no disassembler is available,
+/// the instruction is followed by the bytes of its encoding instead.
+pub fn write_interleaved(
+    w: &mut Write,
+    isa: &TargetIsa,
+    func: &Function,
+    code: &[u8],
+) -> Result<(), String> {
+    let insns = if is_available() {
+        disassemble(isa, code)?
+    } else {
+        split_encodings(isa, func, code)?
+    };
+    write_with_insns(w, isa, func, &insns).map_err(|e| e.to_string())
+}
+
+/// Split `code` into the bytes emitted for each encoded instruction in `func`, without
+/// disassembling them.
+///
+/// Padding before an instruction and anything after the last instruction get entries of their
+/// own.
+fn split_encodings(isa: &TargetIsa, func: &Function, code: &[u8]) -> Result<Vec<Insn>, String> {
+    let encinfo = isa.encoding_info();
+    let mut insns = Vec::new();
+    let mut end = 0;
+    for ebb in func.layout.ebbs() {
+        for (offset, _, size) in func.inst_offsets(ebb, &encinfo) {
+            if offset > end {
+                insns.push(raw_insn(code, end, offset)?);
+            }
+            end = offset + size;
+            insns.push(raw_insn(code, offset, end)?);
+        }
+    }
+    if (end as usize) < code.len() {
+        insns.push(raw_insn(code, end, code.len() as u32)?);
+    }
+    Ok(insns)
+}
+
+/// An instruction without disassembly covering `code[start..end]`.
+fn raw_insn(code: &[u8], start: u32, end: u32) -> Result<Insn, String> {
+    match code.get(start as usize..end as usize) {
+        Some(bytes) => Ok(Insn {
+            offset: start,
+            bytes: bytes.to_vec(),
+            text: String::new(),
+        }),
+        None => Err(format!(
+            "instruction at {:#x}-{:#x} is outside the {} bytes of code",
+            start,
+            end,
+            code.len()
+        )),
+    }
+}
+
+fn write_with_insns(
+    w: &mut Write,
+    isa: &TargetIsa,
+    func: &Function,
+    insns: &[Insn],
+) -> fmt::Result {
+    let encinfo = isa.encoding_info();
+    let mut next = insns.iter().peekable();
+    writeln!(w, "function {}:", func.name)?;
+    for ebb in func.layout.ebbs() {
+        writeln!(w, "{}:", ebb)?;
+        for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+            writeln!(
+                w,
+                "    [{}] {}",
+                encinfo.display(func.encodings[inst]),
+                func.dfg.display_inst(inst, isa)
+            )?;
+            while next.peek().map_or(false, |i| i.offset < offset + size) {
+                write_insn(w, next.next().unwrap())?;
+            }
+        }
+    }
+    // Anything after the last instruction, like a constant pool.
+    for insn in next {
+        write_insn(w, insn)?;
+    }
+    Ok(())
+}
+
+fn write_insn(w: &mut Write, insn: &Insn) -> fmt::Result {
+    let mut bytes = String::new();
+    for byte in &insn.bytes {
+        write!(bytes, "{:02x}", byte)?;
+    }
+    if insn.text.is_empty() {
+        writeln!(w, "        {:4x}: {}", insn.offset, bytes)
+    } else {
+        writeln!(w, "        {:4x}: {:<20} {}", insn.offset, bytes, insn.text)
+    }
+}
@@ -8,10 +8,14 @@
 // Rustfmt 0.9.0 is at odds with this lint:
         block_in_if_condition_stmt))]
 
+#[cfg(feature = "disas")]
+extern crate capstone;
 #[macro_use(dbg)]
 extern crate cretonne;
+extern crate cton_native;
 extern crate cton_reader;
 extern crate filecheck;
+extern crate memmap;
 extern crate num_cpus;
 
 use std::path::Path;
@@ -19,6 +23,8 @@ use std::time;
 use cton_reader::TestCommand;
 use runner::TestRunner;
 
+pub mod disasm;
+
 mod concurrent;
 mod runner;
 mod runone;
@@ -28,13 +34,20 @@ mod match_directive;
 mod test_binemit;
 mod test_cat;
 mod test_compile;
+mod test_disasm;
 mod test_domtree;
 mod test_legalizer;
 mod test_licm;
+mod test_peel;
 mod test_preopt;
 mod test_print_cfg;
 mod test_regalloc;
+mod test_run;
+mod test_sccp;
 mod test_simple_gvn;
+mod test_simplify_cfg;
+mod test_unreachable_code;
+mod test_unroll;
 mod test_verifier;
 
 /// The result of running the test in a file.
@@ -73,13 +86,20 @@ fn new_subtest(parsed: &TestCommand) -> subtest::Result<Box<subtest::SubTest>> {
         "binemit" => test_binemit::subtest(parsed),
         "cat" => test_cat::subtest(parsed),
         "compile" => test_compile::subtest(parsed),
+        "disasm" => test_disasm::subtest(parsed),
         "domtree" => test_domtree::subtest(parsed),
         "legalizer" => test_legalizer::subtest(parsed),
         "licm" => test_licm::subtest(parsed),
+        "peel" => test_peel::subtest(parsed),
         "preopt" => test_preopt::subtest(parsed),
         "print-cfg" => test_print_cfg::subtest(parsed),
         "regalloc" => test_regalloc::subtest(parsed),
+        "run" => test_run::subtest(parsed),
+        "sccp" => test_sccp::subtest(parsed),
+        "simplify-cfg" => test_simplify_cfg::subtest(parsed),
         "simple-gvn" => test_simple_gvn::subtest(parsed),
+        "unreachable-code" => test_unreachable_code::subtest(parsed),
+        "unroll" => test_unroll::subtest(parsed),
         "verifier" => test_verifier::subtest(parsed),
         _ => Err(format!("unknown test command '{}'", parsed.command)),
     }
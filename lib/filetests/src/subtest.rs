@@ -7,6 +7,7 @@ use cretonne::isa::TargetIsa;
 use cretonne::settings::{Flags, FlagsOrIsa};
 use cton_reader::{Details, Comment};
 use filecheck::{CheckerBuilder, Checker, NO_VARIABLES};
+use match_directive::match_directive;
 
 pub type Result<T> = result::Result<T, String>;
 
@@ -37,6 +38,37 @@ impl<'a> Context<'a> {
             isa: self.isa,
         }
     }
+
+    /// Get the comments of the function that apply to the ISA being tested.
+    ///
+    /// See `select_isa_comments` for the `isa:` directives selecting them.
+    pub fn comments(&self) -> Vec<&Comment<'a>> {
+        select_isa_comments(&self.details.comments, self.isa)
+    }
+}
+
+/// Get the comments in `comments` that apply to `isa`.
+///
+/// A test file with several ISAs can have directives that only apply to some of them. An
+/// `isa: intel arm32` directive starts a block of comments that only apply to the listed ISAs,
+/// and the block ends at the next `isa:` directive. The comments following `isa: all` apply to all
+/// the ISAs again. The `isa:` directives themselves are not returned.
+pub fn select_isa_comments<'c, 'a>(
+    comments: &'c [Comment<'a>],
+    isa: Option<&TargetIsa>,
+) -> Vec<&'c Comment<'a>> {
+    let mut selected = true;
+    let mut result = Vec::new();
+    for comment in comments {
+        if let Some(names) = match_directive(comment.text, "isa:") {
+            selected = names.split_whitespace().any(|name| {
+                name == "all" || isa.map_or(false, |isa| isa.name() == name)
+            });
+        } else if selected {
+            result.push(comment);
+        }
+    }
+    result
 }
 
 /// Common interface for implementations of test commands.
@@ -85,15 +117,17 @@ pub fn run_filecheck(text: &str, context: &Context) -> Result<()> {
 }
 
 /// Build a filechecker using the directives in the file preamble and the function's comments.
+///
+/// Only the directives applying to the ISA being tested are used.
 pub fn build_filechecker(context: &Context) -> Result<Checker> {
     let mut builder = CheckerBuilder::new();
     // Preamble comments apply to all functions.
-    for comment in context.preamble_comments {
+    for comment in select_isa_comments(context.preamble_comments, context.isa) {
         builder.directive(comment.text).map_err(|e| {
             format!("filecheck: {}", e)
         })?;
     }
-    for comment in &context.details.comments {
+    for comment in context.comments() {
         builder.directive(comment.text).map_err(|e| {
             format!("filecheck: {}", e)
         })?;
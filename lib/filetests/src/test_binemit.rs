@@ -2,18 +2,28 @@
 //!
 //! The `binemit` test command generates binary machine code for every instruction in the input
 //! functions and compares the results to the expected output.
+//!
+//! The machine code of an instruction can be given exactly by a `bin:` directive following it.
+//! When that is too fragile, the function annotated with the machine code of its instructions can
+//! be matched by filecheck directives instead, like the output of `test compile bytes`:
+//!
+//! ```text
+//! ; check: v2 = iadd v0, v1
+//! ; sameln: bin: $(rex=4[0-9a-f]) 01
+//! ; not: PCRel4
+//! ```
 
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fmt::Write;
-use cretonne::binemit;
+use cretonne::binemit::{self, CodeSink, TextSink};
 use cretonne::dbg::DisplayList;
 use cretonne::ir;
 use cretonne::ir::entities::AnyEntity;
 use cretonne::binemit::RegDiversions;
 use cretonne::print_errors::pretty_error;
+use cretonne::Annotations;
 use cton_reader::TestCommand;
-use subtest::{SubTest, Context, Result};
+use subtest::{SubTest, Context, Result, build_filechecker, run_filecheck};
 use match_directive::match_directive;
 
 struct TestBinEmit;
@@ -27,83 +37,6 @@ pub fn subtest(parsed: &TestCommand) -> Result<Box<SubTest>> {
     }
 }
 
-// Code sink that generates text.
-struct TextSink {
-    offset: binemit::CodeOffset,
-    text: String,
-}
-
-impl TextSink {
-    /// Create a new empty TextSink.
-    pub fn new() -> Self {
-        Self {
-            offset: 0,
-            text: String::new(),
-        }
-    }
-}
-
-
-
-impl binemit::CodeSink for TextSink {
-    fn offset(&self) -> binemit::CodeOffset {
-        self.offset
-    }
-
-    fn put1(&mut self, x: u8) {
-        write!(self.text, "{:02x} ", x).unwrap();
-        self.offset += 1;
-    }
-
-    fn put2(&mut self, x: u16) {
-        write!(self.text, "{:04x} ", x).unwrap();
-        self.offset += 2;
-    }
-
-    fn put4(&mut self, x: u32) {
-        write!(self.text, "{:08x} ", x).unwrap();
-        self.offset += 4;
-    }
-
-    fn put8(&mut self, x: u64) {
-        write!(self.text, "{:016x} ", x).unwrap();
-        self.offset += 8;
-    }
-
-    fn reloc_ebb(&mut self, reloc: binemit::Reloc, ebb_offset: binemit::CodeOffset) {
-        write!(self.text, "{}({}) ", reloc, ebb_offset).unwrap();
-    }
-
-    fn reloc_external(
-        &mut self,
-        reloc: binemit::Reloc,
-        name: &ir::ExternalName,
-        addend: binemit::Addend,
-    ) {
-        write!(
-            self.text,
-            "{}({}",
-            reloc,
-            name,
-        ).unwrap();
-        if addend != 0 {
-            write!(
-                self.text,
-                "{:+}",
-                addend,
-            ).unwrap();
-        }
-        write!(
-            self.text,
-            ") ",
-        ).unwrap();
-    }
-
-    fn reloc_jt(&mut self, reloc: binemit::Reloc, jt: ir::JumpTable) {
-        write!(self.text, "{}({}) ", reloc, jt).unwrap();
-    }
-}
-
 impl SubTest for TestBinEmit {
     fn name(&self) -> Cow<str> {
         Cow::from("binemit")
@@ -174,7 +107,7 @@ impl SubTest for TestBinEmit {
 
         // Collect all of the 'bin:' directives on instructions.
         let mut bins = HashMap::new();
-        for comment in &context.details.comments {
+        for comment in context.comments() {
             if let Some(want) = match_directive(comment.text, "bin:") {
                 match comment.entity {
                     AnyEntity::Inst(inst) => {
@@ -197,24 +130,29 @@ impl SubTest for TestBinEmit {
                 }
             }
         }
-        if bins.is_empty() {
-            return Err("No 'bin:' directives found".to_string());
+        let filecheck = !build_filechecker(context)?.is_empty();
+        if bins.is_empty() && !filecheck {
+            return Err("No 'bin:' or filecheck directives found".to_string());
         }
 
         // Now emit all instructions.
-        let mut sink = TextSink::new();
+        let mut sink = TextSink::new(0, isa.endianness());
         for ebb in func.layout.ebbs() {
             divert.clear();
+            if sink.offset() < func.offsets[ebb] {
+                let padding = func.offsets[ebb] - sink.offset();
+                isa.emit_padding(padding, &mut sink);
+            }
             // Correct header offsets should have been computed by `relax_branches()`.
             assert_eq!(
-                sink.offset,
+                sink.offset(),
                 func.offsets[ebb],
                 "Inconsistent {} header offset",
                 ebb
             );
             for (offset, inst, enc_bytes) in func.inst_offsets(ebb, &encinfo) {
-                assert_eq!(sink.offset, offset);
-                sink.text.clear();
+                assert_eq!(sink.offset(), offset);
+                sink.take_text();
                 let enc = func.encodings[inst];
 
                 // Send legal encodings into the emitter.
@@ -230,9 +168,9 @@ impl SubTest for TestBinEmit {
                             func.dfg.display_inst(inst, isa)
                         ));
                     }
-                    let before = sink.offset;
+                    let before = sink.offset();
                     isa.emit_inst(&func, inst, &mut divert, &mut sink);
-                    let emitted = sink.offset - before;
+                    let emitted = sink.offset() - before;
                     // Verify the encoding recipe sizes against the ISAs emit_inst implementation.
                     assert_eq!(
                         emitted,
@@ -279,7 +217,7 @@ impl SubTest for TestBinEmit {
                                 DisplayList(&encodings),
                             ));
                     }
-                    let have = sink.text.trim();
+                    let have = sink.text();
                     if have != want {
                         return Err(format!(
                             "Bad machine code for {}: {}\nWant: {}\nGot:  {}",
@@ -293,14 +231,25 @@ impl SubTest for TestBinEmit {
             }
         }
 
-        if sink.offset != code_size {
+        binemit::emit_jump_tables(&func, &mut sink);
+        binemit::emit_constants(&func, &mut sink);
+        if sink.offset() != code_size {
             return Err(format!(
                 "Expected code size {}, got {}",
                 code_size,
-                sink.offset
+                sink.offset()
             ));
         }
 
+        if filecheck {
+            let annotations = Annotations {
+                offsets: true,
+                bytes: true,
+                ..Annotations::default()
+            };
+            let text = func.display_with_annotations(isa, annotations).to_string();
+            run_filecheck(&text, context)?;
+        }
         Ok(())
     }
 }
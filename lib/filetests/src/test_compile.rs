@@ -1,25 +1,41 @@
 //! Test command for testing the code generator pipeline
 //!
-//! The `compile` test command runs each function through the full code generator pipeline
+//! The `compile` test command runs each function through the full code generator pipeline.
+//!
+//! The `sizes` and `offsets` options annotate the encoded instructions with their sizes and
+//! offsets in the output that is sent to filecheck. The `bytes` option annotates them with their
+//! machine code and relocations, like the `bin:` directives of the `binemit` test:
+//!
+//! ```text
+//! [RexOp1rr#8001,%rdi]                v2 = iadd v0, v1 ; bin: 48 01 f7
+//! ```
 
 use cretonne::binemit;
 use cretonne::ir;
 use cretonne;
+use cretonne::Annotations;
 use cretonne::print_errors::pretty_error;
-use cton_reader::TestCommand;
+use cton_reader::{TestCommand, TestOption};
 use subtest::{SubTest, Context, Result, run_filecheck};
 use std::borrow::Cow;
 use std::fmt::Write;
 
-struct TestCompile;
+struct TestCompile {
+    annotations: Annotations,
+}
 
 pub fn subtest(parsed: &TestCommand) -> Result<Box<SubTest>> {
     assert_eq!(parsed.command, "compile");
-    if !parsed.options.is_empty() {
-        Err(format!("No options allowed on {}", parsed))
-    } else {
-        Ok(Box::new(TestCompile))
+    let mut annotations = Annotations::default();
+    for option in &parsed.options {
+        match *option {
+            TestOption::Flag("sizes") => annotations.sizes = true,
+            TestOption::Flag("offsets") => annotations.offsets = true,
+            TestOption::Flag("bytes") => annotations.bytes = true,
+            _ => return Err(format!("Unknown option {} on {}", option, parsed)),
+        }
     }
+    Ok(Box::new(TestCompile { annotations }))
 }
 
 impl SubTest for TestCompile {
@@ -53,10 +69,14 @@ impl SubTest for TestCompile {
         );
 
         // Verify that the returned code size matches the emitted bytes.
-        let mut sink = SizeSink { offset: 0 };
+        let mut sink = SizeSink {
+            offset: 0,
+            endianness: isa.endianness(),
+        };
         binemit::emit_function(
             &comp_ctx.func,
             |func, inst, div, sink| isa.emit_inst(func, inst, div, sink),
+            |bytes, sink| isa.emit_padding(bytes, sink),
             &mut sink,
         );
 
@@ -70,8 +90,11 @@ impl SubTest for TestCompile {
 
         // Run final code through filecheck.
         let mut text = String::new();
-        write!(&mut text, "{}", &comp_ctx.func.display(Some(isa)))
-            .map_err(|e| e.to_string())?;
+        write!(
+            &mut text,
+            "{}",
+            &comp_ctx.func.display_with_annotations(isa, self.annotations)
+        ).map_err(|e| e.to_string())?;
         run_filecheck(&text, context)
     }
 }
@@ -79,6 +102,7 @@ impl SubTest for TestCompile {
 // Code sink that simply counts bytes.
 struct SizeSink {
     offset: binemit::CodeOffset,
+    endianness: binemit::Endianness,
 }
 
 impl binemit::CodeSink for SizeSink {
@@ -86,6 +110,10 @@ impl binemit::CodeSink for SizeSink {
         self.offset
     }
 
+    fn endianness(&self) -> binemit::Endianness {
+        self.endianness
+    }
+
     fn put1(&mut self, _: u8) {
         self.offset += 1;
     }
@@ -108,7 +136,9 @@ impl binemit::CodeSink for SizeSink {
         _reloc: binemit::Reloc,
         _name: &ir::ExternalName,
         _addend: binemit::Addend,
+        _symbol: binemit::SymbolInfo,
     ) {
     }
     fn reloc_jt(&mut self, _reloc: binemit::Reloc, _jt: ir::JumpTable) {}
+    fn trap(&mut self, _code: ir::TrapCode, _srcloc: ir::SourceLoc) {}
 }
@@ -0,0 +1,65 @@
+//! Test command for checking the disassembled machine code of compiled functions.
+//!
+//! The `disasm` test command compiles each function and emits its machine code, like `compile`.
+//! The encoded instructions are then listed with the disassembly of their machine code, and the
+//! result is sent to filecheck:
+//!
+//! ```text
+//!     [RexOp1puid#b8] v1 = iconst.i32 1
+//!            5: b801000000           mov eax, 1
+//! ```
+//!
+//! Disassembly requires the `disas` feature. Without it, each encoded instruction is followed by
+//! the bytes emitted for it, so the encodings and sizes can still be checked.
+
+use cretonne;
+use cretonne::binemit::{NullTrapSink, Relocations};
+use cretonne::ir::Function;
+use cretonne::print_errors::pretty_error;
+use cton_reader::TestCommand;
+use disasm::write_interleaved;
+use std::borrow::Cow;
+use subtest::{SubTest, Context, Result, run_filecheck};
+
+struct TestDisasm;
+
+pub fn subtest(parsed: &TestCommand) -> Result<Box<SubTest>> {
+    assert_eq!(parsed.command, "disasm");
+    if !parsed.options.is_empty() {
+        Err(format!("No options allowed on {}", parsed))
+    } else {
+        Ok(Box::new(TestDisasm))
+    }
+}
+
+impl SubTest for TestDisasm {
+    fn name(&self) -> Cow<str> {
+        Cow::from("disasm")
+    }
+
+    fn is_mutating(&self) -> bool {
+        true
+    }
+
+    fn needs_isa(&self) -> bool {
+        true
+    }
+
+    fn run(&self, func: Cow<Function>, context: &Context) -> Result<()> {
+        let isa = context.isa.expect("disasm needs an ISA");
+
+        let mut comp_ctx = cretonne::Context::new();
+        comp_ctx.func = func.into_owned();
+        let code_size = comp_ctx.compile(isa).map_err(|e| {
+            pretty_error(&comp_ctx.func, context.isa, e)
+        })?;
+
+        let mut code = vec![0; code_size as usize];
+        let mut relocs = Relocations::new();
+        comp_ctx.emit_to_memory(code.as_mut_ptr(), &mut relocs, &mut NullTrapSink {}, isa);
+
+        let mut text = String::new();
+        write_interleaved(&mut text, isa, &comp_ctx.func, &code)?;
+        run_filecheck(&text, context)
+    }
+}
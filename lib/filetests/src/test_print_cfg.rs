@@ -6,20 +6,27 @@
 use std::borrow::Cow;
 
 use cretonne::ir::Function;
-use cretonne::cfg_printer::CFGPrinter;
-use cton_reader::TestCommand;
+use cretonne::cfg_printer::{CFGAnnotations, CFGPrinter};
+use cton_reader::{TestCommand, TestOption};
 use subtest::{self, SubTest, Context, Result as STResult};
 
 /// Object implementing the `test print-cfg` sub-test.
-struct TestPrintCfg;
+struct TestPrintCfg {
+    annotations: CFGAnnotations,
+}
 
 pub fn subtest(parsed: &TestCommand) -> STResult<Box<SubTest>> {
     assert_eq!(parsed.command, "print-cfg");
-    if !parsed.options.is_empty() {
-        Err(format!("No options allowed on {}", parsed))
-    } else {
-        Ok(Box::new(TestPrintCfg))
+    let mut annotations = CFGAnnotations::default();
+    for option in &parsed.options {
+        match *option {
+            TestOption::Flag("domtree") => annotations.domtree = true,
+            TestOption::Flag("loops") => annotations.loops = true,
+            TestOption::Flag("live-ins") => annotations.live_ins = true,
+            _ => return Err(format!("Unknown option {} on {}", option, parsed)),
+        }
     }
+    Ok(Box::new(TestPrintCfg { annotations }))
 }
 
 impl SubTest for TestPrintCfg {
@@ -32,6 +39,7 @@ impl SubTest for TestPrintCfg {
     }
 
     fn run(&self, func: Cow<Function>, context: &Context) -> STResult<()> {
-        subtest::run_filecheck(&CFGPrinter::new(&func).to_string(), context)
+        let printer = CFGPrinter::with_annotations(&func, self.annotations);
+        subtest::run_filecheck(&printer.to_string(), context)
     }
 }
@@ -0,0 +1,283 @@
+//! Test command for executing compiled functions natively.
+//!
+//! The `run` test command compiles each function for the host ISA, copies the machine code into
+//! executable memory and calls it. Each `run:` directive in the function's comments describes one
+//! call and the expected return value:
+//!
+//! ```text
+//! ; run: %add(1, 2) == 3
+//! ```
+//!
+//! Only functions with the native calling convention, at most four integer or boolean arguments
+//! and a single integer or boolean return value can be executed.
+
+use cretonne;
+use cretonne::binemit::{Addend, CodeOffset, NullTrapSink, Reloc, RelocSink, SymbolInfo};
+use cretonne::ir::{self, CallConv, ExternalName, Function, JumpTable, Type};
+use cretonne::isa::TargetIsa;
+use cretonne::print_errors::pretty_error;
+use cretonne::settings;
+use cton_native;
+use cton_reader::TestCommand;
+use match_directive::match_directive;
+use memmap::{Mmap, MmapMut};
+use std::borrow::Cow;
+use std::mem;
+use subtest::{SubTest, Context, Result};
+
+struct TestRun;
+
+pub fn subtest(parsed: &TestCommand) -> Result<Box<SubTest>> {
+    assert_eq!(parsed.command, "run");
+    if !parsed.options.is_empty() {
+        Err(format!("No options allowed on {}", parsed))
+    } else {
+        Ok(Box::new(TestRun))
+    }
+}
+
+impl SubTest for TestRun {
+    fn name(&self) -> Cow<str> {
+        Cow::from("run")
+    }
+
+    fn is_mutating(&self) -> bool {
+        true
+    }
+
+    fn run(&self, func: Cow<Function>, context: &Context) -> Result<()> {
+        let invocations = context
+            .details
+            .comments
+            .iter()
+            .filter_map(|c| match_directive(c.text, "run:"))
+            .map(|text| Invocation::parse(text, &func.name))
+            .collect::<Result<Vec<_>>>()?;
+        if invocations.is_empty() {
+            return Err("no run: directives found".to_string());
+        }
+
+        let isa = match host_isa() {
+            Some(isa) => isa,
+            None => {
+                // There's nothing to execute on hosts we can't generate and call code for.
+                dbg!("Skipping run test: the host is not x86-64");
+                return Ok(());
+            }
+        };
+
+        let code = compile_to_memory(func.into_owned(), &*isa)?;
+        for invocation in &invocations {
+            invocation.check(&code)?;
+        }
+        Ok(())
+    }
+}
+
+/// Build a `TargetIsa` for the host machine, if it is supported.
+///
+/// Only x86-64 hosts are supported since `call` assumes the System V calling convention.
+fn host_isa() -> Option<Box<TargetIsa>> {
+    if !cfg!(target_arch = "x86_64") {
+        return None;
+    }
+    cton_native::builders().ok().map(|(flag_builder, isa_builder)| {
+        isa_builder.finish(settings::Flags::new(&flag_builder))
+    })
+}
+
+/// Call the function at `ptr` with `args`.
+///
+/// The Intel ISA compiles the native calling convention as System V, even on Windows, so the
+/// function is called with the `sysv64` ABI rather than the host's C ABI. All the supported
+/// argument types are passed in 64-bit integer registers, so we can always call the function as
+/// if it took and returned `i64` values. The upper bits of narrower arguments are ignored by the
+/// callee, and the caller masks out the upper bits of the return value.
+#[cfg(target_arch = "x86_64")]
+unsafe fn call(ptr: *const u8, args: &[i64]) -> i64 {
+    let a = |i: usize| args[i];
+    match args.len() {
+        0 => mem::transmute::<_, extern "sysv64" fn() -> i64>(ptr)(),
+        1 => mem::transmute::<_, extern "sysv64" fn(i64) -> i64>(ptr)(a(0)),
+        2 => mem::transmute::<_, extern "sysv64" fn(i64, i64) -> i64>(ptr)(a(0), a(1)),
+        3 => {
+            mem::transmute::<_, extern "sysv64" fn(i64, i64, i64) -> i64>(ptr)(a(0), a(1), a(2))
+        }
+        _ => {
+            mem::transmute::<_, extern "sysv64" fn(i64, i64, i64, i64) -> i64>(ptr)(
+                a(0),
+                a(1),
+                a(2),
+                a(3),
+            )
+        }
+    }
+}
+
+/// Functions are never compiled for hosts other than x86-64, see `host_isa`.
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn call(_ptr: *const u8, _args: &[i64]) -> i64 {
+    unreachable!("run tests are only executed on x86-64 hosts")
+}
+
+/// A compiled function in executable memory.
+struct CompiledFunction {
+    /// The executable code. The mapping must stay alive while the function can be called.
+    mem: Mmap,
+
+    /// Types of the function arguments.
+    params: Vec<Type>,
+
+    /// Type of the single return value.
+    ret: Type,
+}
+
+/// Compile `func` for `isa` and copy its machine code into executable memory.
+fn compile_to_memory(func: Function, isa: &TargetIsa) -> Result<CompiledFunction> {
+    check_signature(&func.signature)?;
+    let params = func.signature.params.iter().map(|p| p.value_type).collect();
+    let ret = func.signature.returns[0].value_type;
+
+    let mut comp_ctx = cretonne::Context::new();
+    comp_ctx.func = func;
+    let code_size = comp_ctx.compile(isa).map_err(|e| {
+        pretty_error(&comp_ctx.func, Some(isa), e)
+    })?;
+
+    let mut mem = MmapMut::map_anon(code_size as usize).map_err(|e| e.to_string())?;
+    let mut relocs = NoRelocs(None);
+    comp_ctx.emit_to_memory(mem.as_mut_ptr(), &mut relocs, &mut NullTrapSink {}, isa);
+    if let Some(reloc) = relocs.0 {
+        return Err(format!("run tests can't handle relocations: {}", reloc));
+    }
+
+    Ok(CompiledFunction {
+        mem: mem.make_exec().map_err(|e| e.to_string())?,
+        params,
+        ret,
+    })
+}
+
+/// Check that we know how to call a function with signature `sig`.
+fn check_signature(sig: &ir::Signature) -> Result<()> {
+    if sig.call_conv != CallConv::Native {
+        return Err(format!("can't call a function with {} calling convention", sig.call_conv));
+    }
+    if sig.params.len() > 4 {
+        return Err(format!("can't call a function with {} arguments", sig.params.len()));
+    }
+    if sig.returns.len() != 1 {
+        return Err("run tests need functions with a single return value".to_string());
+    }
+    for param in sig.params.iter().chain(&sig.returns) {
+        let ty = param.value_type;
+        if !(ty.is_int() || ty.is_bool()) || ty.bits() > 64 {
+            return Err(format!("unsupported argument type {} in run test", ty));
+        }
+    }
+    Ok(())
+}
+
+/// A `RelocSink` that remembers the first relocation it sees.
+struct NoRelocs(Option<Reloc>);
+
+impl RelocSink for NoRelocs {
+    fn reloc_ebb(&mut self, _: CodeOffset, reloc: Reloc, _: CodeOffset) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+
+    fn reloc_external(
+        &mut self,
+        _: CodeOffset,
+        reloc: Reloc,
+        _: &ExternalName,
+        _: Addend,
+        _: SymbolInfo,
+    ) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+
+    fn reloc_jt(&mut self, _: CodeOffset, reloc: Reloc, _: JumpTable) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+}
+
+/// A single `run:` directive: call the function with `args` and expect `expected`.
+struct Invocation {
+    args: Vec<i64>,
+    expected: i64,
+}
+
+impl Invocation {
+    /// Parse the text of a `run:` directive like `%add(1, 2) == 3`.
+    fn parse(text: &str, name: &ExternalName) -> Result<Invocation> {
+        let name = name.to_string();
+        let rest = if text.starts_with(&name) {
+            text[name.len()..].trim_left()
+        } else {
+            return Err(format!("run: directive must call {}: {}", name, text));
+        };
+
+        let close = rest.find(')');
+        let (args, rest) = match (rest.starts_with('('), close) {
+            (true, Some(close)) => (&rest[1..close], rest[close + 1..].trim_left()),
+            _ => return Err(format!("missing argument list in run: {}", text)),
+        };
+        let args = args.split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(parse_value)
+            .collect::<Result<Vec<_>>>()?;
+
+        if !rest.starts_with("==") {
+            return Err(format!("expected '==' in run: {}", text));
+        }
+        let expected = parse_value(rest[2..].trim())?;
+        Ok(Invocation { args, expected })
+    }
+
+    /// Call the compiled function and compare the result.
+    fn check(&self, code: &CompiledFunction) -> Result<()> {
+        if self.args.len() != code.params.len() {
+            return Err(format!(
+                "expected {} arguments, got {}",
+                code.params.len(),
+                self.args.len()
+            ));
+        }
+
+        let result = unsafe { call(code.mem.as_ptr(), &self.args) };
+
+        let mask = match code.ret.bits() {
+            64 => !0,
+            bits => (1u64 << bits) - 1,
+        };
+        if (result as u64) & mask == (self.expected as u64) & mask {
+            Ok(())
+        } else {
+            Err(format!(
+                "run {:?}: expected {}, got {}",
+                self.args,
+                self.expected,
+                result
+            ))
+        }
+    }
+}
+
+/// Parse a decimal or hexadecimal integer, or a boolean.
+fn parse_value(text: &str) -> Result<i64> {
+    let (negative, digits) = if text.starts_with('-') {
+        (true, &text[1..])
+    } else {
+        (false, text)
+    };
+    let value = match digits {
+        "true" if !negative => return Ok(1),
+        "false" if !negative => return Ok(0),
+        _ if digits.starts_with("0x") => u64::from_str_radix(&digits[2..], 16),
+        _ => digits.parse::<u64>(),
+    }.map_err(|_| format!("bad value in run: {}", text))?;
+    let value = value as i64;
+    Ok(if negative { value.wrapping_neg() } else { value })
+}
@@ -8,6 +8,9 @@
 //!
 //! This annotation means that the verifier is expected to given an error for the jump instruction
 //! containing the substring "jump to non-existent EBB".
+//!
+//! A function can have several annotations. The verifier must report all of them and no other
+//! errors.
 
 use std::borrow::{Borrow, Cow};
 use cretonne::verify_function;
@@ -41,42 +44,52 @@ impl SubTest for TestVerifier {
         let func = func.borrow();
 
         // Scan source annotations for "error:" directives.
-        let mut expected = None;
+        let mut expected = Vec::new();
         for comment in &context.details.comments {
             if let Some(tail) = match_directive(comment.text, "error:") {
-                // Currently, the verifier can only report one problem at a time.
-                // Reject more than one `error:` directives.
-                if expected.is_some() {
-                    return Err("cannot handle multiple error: directives".to_string());
-                }
-                expected = Some((comment.entity, tail));
+                expected.push((comment.entity, tail));
             }
         }
 
-        match verify_function(func, context.flags_or_isa()) {
+        let mut got = match verify_function(func, context.flags_or_isa()) {
             Ok(_) => {
-                match expected {
+                return match expected.first() {
                     None => Ok(()),
-                    Some((_, msg)) => Err(format!("passed, expected error: {}", msg)),
+                    Some(&(_, msg)) => Err(format!("passed, expected error: {}", msg)),
                 }
             }
-            Err(got) => {
-                match expected {
-                    None => Err(format!("verifier pass, got {}", got)),
-                    Some((want_loc, want_msg)) if got.message.contains(want_msg) => {
-                        if want_loc == got.location {
-                            Ok(())
-                        } else {
-                            Err(format!(
+            Err(errors) => errors,
+        };
+        if expected.is_empty() {
+            return Err(format!("verifier pass, got {}", got));
+        }
+
+        for (want_loc, want_msg) in expected {
+            let found = got.0.iter().position(|e| {
+                e.location == want_loc && e.message.contains(want_msg)
+            });
+            match found {
+                Some(idx) => {
+                    got.0.remove(idx);
+                }
+                None => {
+                    return Err(match got.0.iter().find(|e| e.message.contains(want_msg)) {
+                        Some(e) => {
+                            format!(
                                 "correct error reported on {}, but wanted {}",
-                                got.location,
+                                e.location,
                                 want_loc
-                            ))
+                            )
                         }
-                    }
-                    Some(_) => Err(format!("mismatching error: {}", got)),
+                        None => format!("mismatching error: {}", got),
+                    })
                 }
             }
         }
+        if got.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("unexpected error: {}", got))
+        }
     }
 }
@@ -3,10 +3,11 @@ use cretonne::cursor::{Cursor, FuncCursor};
 use cretonne::ir;
 use cretonne::ir::{Ebb, Type, Value, Function, Inst, JumpTable, StackSlot, JumpTableData,
                    StackSlotData, DataFlowGraph, InstructionData, ExtFuncData, FuncRef, SigRef,
-                   Signature, InstBuilderBase, GlobalVarData, GlobalVar, HeapData, Heap};
+                   Signature, InstBuilder, InstBuilderBase, GlobalVarData, GlobalVar, HeapData,
+                   Heap, StackSlotKind};
 use cretonne::ir::function::DisplayFunction;
 use cretonne::isa::TargetIsa;
-use ssa::{SSABuilder, SideEffects, Block};
+use ssa::{SSABuilder, SSAStats, SideEffects, Block};
 use cretonne::entity::{EntityRef, EntityMap, EntitySet};
 use cretonne::packed_option::PackedOption;
 
@@ -26,6 +27,7 @@ where
     ssa: SSABuilder<Variable>,
     ebbs: EntityMap<Ebb, EbbData>,
     types: EntityMap<Variable, Type>,
+    slots: EntityMap<Variable, PackedOption<StackSlot>>,
 }
 
 
@@ -43,6 +45,12 @@ where
 
     func_ctx: &'a mut FunctionBuilderContext<Variable>,
     position: Position,
+
+    /// Remove redundant EBB parameters in `finalize()`.
+    remove_redundant_params: bool,
+
+    /// SSA construction statistics of the last finalized function.
+    stats: SSAStats,
 }
 
 #[derive(Clone, Default)]
@@ -88,6 +96,7 @@ where
             ssa: SSABuilder::new(),
             ebbs: EntityMap::new(),
             types: EntityMap::new(),
+            slots: EntityMap::new(),
         }
     }
 
@@ -95,10 +104,12 @@ where
         self.ssa.clear();
         self.ebbs.clear();
         self.types.clear();
+        self.slots.clear();
     }
 
     fn is_empty(&self) -> bool {
-        self.ssa.is_empty() && self.ebbs.is_empty() && self.types.is_empty()
+        self.ssa.is_empty() && self.ebbs.is_empty() && self.types.is_empty() &&
+            self.slots.is_empty()
     }
 }
 
@@ -240,9 +251,22 @@ where
             srcloc: Default::default(),
             func_ctx: func_ctx,
             position: Position::default(),
+            remove_redundant_params: false,
+            stats: SSAStats::default(),
         }
     }
 
+    /// Remove the EBB parameters created for variables that aren't modified on the paths leading
+    /// to their EBB when the function is finalized.
+    ///
+    /// The SSA construction only avoids creating an EBB parameter when all the predecessors of an
+    /// EBB pass the same value when it's sealed. Variables that are live across loops still get a
+    /// parameter in each loop header, which this mode removes along with the branch arguments.
+    /// It costs a few passes over the remaining parameters.
+    pub fn set_remove_redundant_params(&mut self, enable: bool) {
+        self.remove_redundant_params = enable;
+    }
+
     /// Set the source location that should be assigned to all new instructions.
     pub fn set_srcloc(&mut self, srcloc: ir::SourceLoc) {
         self.srcloc = srcloc;
@@ -260,6 +284,12 @@ where
         ebb
     }
 
+    /// Marks `ebb` as rarely executed, so it is laid out after the hot code when the function is
+    /// compiled.
+    pub fn set_cold_block(&mut self, ebb: Ebb) {
+        self.func.layout.set_cold(ebb, true);
+    }
+
     /// After the call to this function, new instructions will be inserted into the designated
     /// block, in the order they are declared. You must declare the types of the Ebb arguments
     /// you will use here.
@@ -337,6 +367,54 @@ where
         );
     }
 
+    /// Declare a variable of aggregate type, like a C struct, which lives in a new stack slot of
+    /// `size` bytes instead of SSA values.
+    ///
+    /// The stack slot is aligned to `align` bytes, which must be a power of two no larger than
+    /// the stack alignment of the target. Its size is rounded up to a multiple of `align`.
+    ///
+    /// The variable is accessed with `load_var_field`, `store_var_field` and `var_addr` instead of
+    /// `use_var` and `def_var`.
+    pub fn declare_aggregate_var(&mut self, var: Variable, size: u32, align: u32) -> StackSlot {
+        debug_assert!(align.is_power_of_two(), "alignment must be a power of two");
+        debug_assert!(
+            self.func_ctx.slots[var].is_none(),
+            "this variable is already declared"
+        );
+        // Stack slots are aligned to the largest power of two dividing their size.
+        let size = (size + align - 1) & !(align - 1);
+        let data = StackSlotData::new(StackSlotKind::ExplicitSlot, size);
+        let ss = self.func.create_stack_slot(data);
+        self.func_ctx.slots[var] = ss.into();
+        ss
+    }
+
+    /// Returns the stack slot of a variable declared with `declare_aggregate_var`.
+    pub fn var_stack_slot(&self, var: Variable) -> StackSlot {
+        self.func_ctx.slots.get(var).and_then(|ss| ss.expand()).expect(
+            "this variable is not an aggregate",
+        )
+    }
+
+    /// Load a field of type `ty` at `offset` bytes into an aggregate variable.
+    pub fn load_var_field(&mut self, var: Variable, ty: Type, offset: i32) -> Value {
+        let ss = self.var_stack_slot(var);
+        self.ins().stack_load(ty, ss, offset)
+    }
+
+    /// Store `val` to the field at `offset` bytes into an aggregate variable.
+    pub fn store_var_field(&mut self, var: Variable, offset: i32, val: Value) {
+        let ss = self.var_stack_slot(var);
+        self.ins().stack_store(val, ss, offset);
+    }
+
+    /// Get the address of an aggregate variable as a value of type `addr_ty`, to pass it by
+    /// reference or to access it with regular loads and stores.
+    pub fn var_addr(&mut self, var: Variable, addr_ty: Type) -> Value {
+        let ss = self.var_stack_slot(var);
+        self.ins().stack_addr(addr_ty, ss, 0)
+    }
+
     /// Creates a jump table in the function, to be used by `br_table` instructions.
     pub fn create_jump_table(&mut self, data: JumpTableData) -> JumpTable {
         self.func.create_jump_table(data)
@@ -451,6 +529,11 @@ where
             "all blocks should be filled before dropping a FunctionBuilder"
         );
 
+        if self.remove_redundant_params {
+            self.func_ctx.ssa.remove_redundant_params(self.func);
+        }
+        self.stats = self.func_ctx.ssa.stats();
+
         // Clear the state (but preserve the allocated buffers) in preparation
         // for translation another function.
         self.func_ctx.clear();
@@ -470,6 +553,11 @@ impl<'a, Variable> FunctionBuilder<'a, Variable>
 where
     Variable: EntityRef,
 {
+    /// Get the statistics about the SSA construction of the function, once it has been finalized.
+    pub fn ssa_stats(&self) -> SSAStats {
+        self.stats
+    }
+
     /// Retrieves all the parameters for an `Ebb` currently inferred from the jump instructions
     /// inserted that target it and the SSA construction.
     pub fn ebb_params(&self, ebb: Ebb) -> &[Value] {
@@ -592,10 +680,12 @@ where
 mod tests {
 
     use cretonne::entity::EntityRef;
-    use cretonne::ir::{ExternalName, Function, CallConv, Signature, AbiParam, InstBuilder};
+    use cretonne::ir::{ExternalName, Function, CallConv, Signature, AbiParam, InstBuilder,
+                       MemFlags};
     use cretonne::ir::types::*;
     use frontend::{FunctionBuilderContext, FunctionBuilder};
     use cretonne::verifier::verify_function;
+    use ssa::SSAStats;
     use cretonne::settings;
     use Variable;
 
@@ -703,4 +793,113 @@ mod tests {
     fn sample_with_lazy_seal() {
         sample_function(true)
     }
+
+    #[test]
+    fn aggregate_var() {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I32));
+        sig.params.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::<Variable>::new();
+        let mut func = Function::with_name_signature(ExternalName::testcase("aggregate"), sig);
+        let ss = {
+            let mut builder = FunctionBuilder::<Variable>::new(&mut func, &mut fn_ctx);
+
+            let block0 = builder.create_ebb();
+            let pair = Variable::new(0);
+            let ss = builder.declare_aggregate_var(pair, 6, 4);
+            builder.append_ebb_params_for_function_params(block0);
+            builder.switch_to_block(block0);
+            builder.seal_block(block0);
+
+            // pair.0 = x; pair.1 = 1; return *(&pair) + pair.1
+            let x = builder.ebb_params(block0)[0];
+            builder.store_var_field(pair, 0, x);
+            let one = builder.ins().iconst(I16, 1);
+            builder.store_var_field(pair, 4, one);
+            let addr = builder.var_addr(pair, I64);
+            let first = builder.ins().load(I32, MemFlags::new(), addr, 0);
+            let second = builder.load_var_field(pair, I16, 4);
+            let second = builder.ins().uextend(I32, second);
+            let sum = builder.ins().iadd(first, second);
+            builder.ins().return_(&[sum]);
+
+            assert_eq!(builder.var_stack_slot(pair), ss);
+            builder.finalize();
+            ss
+        };
+
+        // The slot size is rounded up to the alignment.
+        assert_eq!(func.stack_slots[ss].size, 8);
+        let flags = settings::Flags::new(&settings::builder());
+        if let Err(err) = verify_function(&func, &flags) {
+            panic!("{}\n{}", err, func.display(None));
+        }
+    }
+
+    // A variable that isn't modified in two nested loops, with all the blocks sealed at the end.
+    fn nested_loops(remove_redundant_params: bool) -> (Function, SSAStats) {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.returns.push(AbiParam::new(I32));
+        sig.params.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::<Variable>::new();
+        let mut func = Function::with_name_signature(ExternalName::testcase("loops"), sig);
+        let stats = {
+            let mut builder = FunctionBuilder::<Variable>::new(&mut func, &mut fn_ctx);
+            builder.set_remove_redundant_params(remove_redundant_params);
+
+            let block0 = builder.create_ebb();
+            let block1 = builder.create_ebb();
+            let block2 = builder.create_ebb();
+            let x = Variable::new(0);
+            builder.declare_var(x, I32);
+            builder.append_ebb_params_for_function_params(block0);
+
+            builder.switch_to_block(block0);
+            let tmp = builder.ebb_params(block0)[0];
+            builder.def_var(x, tmp);
+            builder.ins().jump(block1, &[]);
+
+            builder.switch_to_block(block1);
+            builder.use_var(x);
+            builder.ins().jump(block2, &[]);
+
+            builder.switch_to_block(block2);
+            let arg = builder.use_var(x);
+            builder.ins().brnz(arg, block2, &[]);
+            let arg = builder.use_var(x);
+            builder.ins().brnz(arg, block1, &[]);
+            let arg = builder.use_var(x);
+            builder.ins().return_(&[arg]);
+
+            builder.seal_all_blocks();
+            builder.finalize();
+            builder.ssa_stats()
+        };
+
+        let flags = settings::Flags::new(&settings::builder());
+        if let Err(err) = verify_function(&func, &flags) {
+            panic!("{}\n{}", err, func.display(None));
+        }
+        (func, stats)
+    }
+
+    #[test]
+    fn redundant_params() {
+        let (func, stats) = nested_loops(false);
+        let block1 = func.layout.ebbs().nth(1).unwrap();
+        assert_eq!(func.dfg.num_ebb_params(block1), 1);
+        assert_eq!(stats.params_created, 2);
+        assert_eq!(stats.trivial_params_removed, 1);
+        assert_eq!(stats.redundant_params_removed, 0);
+
+        let (func, stats) = nested_loops(true);
+        for ebb in func.layout.ebbs().skip(1) {
+            assert_eq!(func.dfg.num_ebb_params(ebb), 0);
+        }
+        assert_eq!(stats.params_created, 2);
+        assert_eq!(stats.trivial_params_removed, 1);
+        assert_eq!(stats.redundant_params_removed, 1);
+    }
 }
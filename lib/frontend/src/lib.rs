@@ -137,8 +137,11 @@
 extern crate cretonne;
 
 pub use frontend::{FunctionBuilderContext, FunctionBuilder};
+pub use ssa::SSAStats;
+pub use switch::Switch;
 pub use variable::Variable;
 
 mod frontend;
 mod ssa;
+mod switch;
 mod variable;
@@ -6,7 +6,7 @@
 //! Lecture Notes in Computer Science, vol 7791. Springer, Berlin, Heidelberg
 
 use cretonne::cursor::{Cursor, FuncCursor};
-use cretonne::ir::{Ebb, Value, Inst, Type, Function, InstBuilder};
+use cretonne::ir::{Ebb, Value, ValueDef, Inst, Type, Function, InstBuilder};
 use cretonne::ir::instructions::BranchInfo;
 use cretonne::entity::{EntityRef, PrimaryMap, EntityMap};
 use cretonne::packed_option::PackedOption;
@@ -50,6 +50,24 @@ where
     results: Vec<Value>,
     // Side effects accumulated in the `use_var`/`predecessors_lookup` state machine.
     side_effects: SideEffects,
+
+    // The EBB parameters created for variables that weren't removed right away.
+    params: Vec<Value>,
+    stats: SSAStats,
+}
+
+/// Statistics about the SSA construction of a function.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SSAStats {
+    /// Number of `use_var` calls that had to look for a definition in other blocks.
+    pub nonlocal_uses: usize,
+    /// Number of EBB parameters created for variables.
+    pub params_created: usize,
+    /// Number of EBB parameters removed when sealing their EBB, because all the predecessors
+    /// passed the same value.
+    pub trivial_params_removed: usize,
+    /// Number of EBB parameters removed by `remove_redundant_params`.
+    pub redundant_params_removed: usize,
 }
 
 /// Side effects of a `use_var` or a `seal_ebb_header_block` method call.
@@ -157,6 +175,8 @@ where
             calls: Vec::new(),
             results: Vec::new(),
             side_effects: SideEffects::new(),
+            params: Vec::new(),
+            stats: SSAStats::default(),
         }
     }
 
@@ -166,6 +186,8 @@ where
         self.variables.clear();
         self.blocks.clear();
         self.ebb_headers.clear();
+        self.params.clear();
+        self.stats = SSAStats::default();
         debug_assert!(self.calls.is_empty());
         debug_assert!(self.results.is_empty());
         debug_assert!(self.side_effects.is_empty());
@@ -175,7 +197,7 @@ where
     pub fn is_empty(&self) -> bool {
         self.variables.is_empty() && self.blocks.is_empty() && self.ebb_headers.is_empty() &&
             self.calls.is_empty() &&
-            self.results.is_empty() && self.side_effects.is_empty()
+            self.results.is_empty() && self.side_effects.is_empty() && self.params.is_empty()
     }
 }
 
@@ -284,6 +306,7 @@ where
         }
 
         // Otherwise, we have to do a non-local lookup.
+        self.stats.nonlocal_uses += 1;
         debug_assert!(self.calls.is_empty());
         debug_assert!(self.results.is_empty());
         debug_assert!(self.side_effects.is_empty());
@@ -307,10 +330,12 @@ where
                         UseVarCases::SealedOnePredecessor(data.predecessors[0].0)
                     } else {
                         let val = func.dfg.append_ebb_param(data.ebb, ty);
+                        self.stats.params_created += 1;
                         UseVarCases::SealedMultiplePredecessors(val, data.ebb)
                     }
                 } else {
                     let val = func.dfg.append_ebb_param(data.ebb, ty);
+                    self.stats.params_created += 1;
                     data.undef_variables.push((var, val));
                     UseVarCases::Unsealed(val)
                 }
@@ -550,6 +575,7 @@ where
                 );
                 func.dfg.remove_ebb_param(temp_arg_val);
                 func.dfg.change_to_alias(temp_arg_val, zero);
+                self.stats.trivial_params_removed += 1;
                 zero
             }
             ZeroOneOrMore::One(pred_val) => {
@@ -569,6 +595,7 @@ where
                 }
                 func.dfg.remove_ebb_param(temp_arg_val);
                 func.dfg.change_to_alias(temp_arg_val, resolved);
+                self.stats.trivial_params_removed += 1;
                 resolved
             }
             ZeroOneOrMore::More() => {
@@ -603,6 +630,7 @@ where
                 debug_assert!(self.predecessors(dest_ebb).is_empty());
                 *self.predecessors_mut(dest_ebb) = preds;
 
+                self.params.push(temp_arg_val);
                 temp_arg_val
             }
         };
@@ -679,6 +707,76 @@ where
         }
     }
 
+    /// Remove the EBB parameters created for variables that only ever receive a single value other
+    /// than themselves.
+    ///
+    /// An EBB parameter is kept when its predecessors pass different values, but some of these
+    /// values may be parameters that are removed later. This typically happens with loops, where
+    /// a variable that isn't modified in the loop gets a parameter in each loop header. Removing
+    /// a parameter can make others redundant, so this is repeated until nothing changes.
+    ///
+    /// All the `Ebb`s must be sealed.
+    pub fn remove_redundant_params(&mut self, func: &mut Function) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let mut i = 0;
+            while i < self.params.len() {
+                let param = self.params[i];
+                match self.redundant_param_value(func, param) {
+                    Some(val) => {
+                        self.remove_param(func, param, val);
+                        self.params.swap_remove(i);
+                        self.stats.redundant_params_removed += 1;
+                        changed = true;
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+    }
+
+    /// Get the single value other than `param` passed to it by its predecessors, if any.
+    fn redundant_param_value(&self, func: &Function, param: Value) -> Option<Value> {
+        let (ebb, num) = match func.dfg.value_def(param) {
+            ValueDef::Param(ebb, num) => (ebb, num),
+            ValueDef::Result(..) => panic!("{} is not an EBB parameter", param),
+        };
+        let mut unique = None;
+        for &(_, inst) in self.predecessors(ebb) {
+            let arg = func.dfg.resolve_aliases(func.dfg.inst_variable_args(inst)[num]);
+            if arg == param || Some(arg) == unique {
+                continue;
+            }
+            if unique.is_some() {
+                return None;
+            }
+            unique = Some(arg);
+        }
+        unique
+    }
+
+    /// Remove `param` and the corresponding branch arguments, and make it an alias of `val`.
+    fn remove_param(&mut self, func: &mut Function, param: Value, val: Value) {
+        let (ebb, num) = match func.dfg.value_def(param) {
+            ValueDef::Param(ebb, num) => (ebb, num),
+            ValueDef::Result(..) => panic!("{} is not an EBB parameter", param),
+        };
+        for &(_, inst) in self.predecessors(ebb) {
+            let fixed_args = func.dfg[inst].opcode().constraints().fixed_value_arguments();
+            let mut args = func.dfg[inst].take_value_list().unwrap();
+            args.remove(fixed_args + num, &mut func.dfg.value_lists);
+            func.dfg[inst].put_value_list(args);
+        }
+        func.dfg.remove_ebb_param(param);
+        func.dfg.change_to_alias(param, val);
+    }
+
+    /// Get the statistics about the SSA construction so far.
+    pub fn stats(&self) -> SSAStats {
+        self.stats
+    }
+
     /// The main algorithm is naturally recursive: when there's a `use_var` in a
     /// block with no corresponding local defs, it recurses and performs a
     /// `use_var` in each predecessor. To avoid risking running out of callstack
@@ -1025,7 +1123,7 @@ mod tests {
         let flags = settings::Flags::new(&settings::builder());
         match verify_function(&func, &flags) {
             Ok(()) => {}
-            Err(err) => panic!(err.message),
+            Err(errors) => panic!(errors.to_string()),
         }
     }
 
@@ -1199,7 +1297,7 @@ mod tests {
         let flags = settings::Flags::new(&settings::builder());
         match verify_function(&func, &flags) {
             Ok(()) => {}
-            Err(err) => panic!(err.message),
+            Err(errors) => panic!(errors.to_string()),
         }
     }
 
@@ -1245,7 +1343,7 @@ mod tests {
         let flags = settings::Flags::new(&settings::builder());
         match verify_function(&func, &flags) {
             Ok(()) => {}
-            Err(err) => panic!(err.message),
+            Err(errors) => panic!(errors.to_string()),
         }
     }
 }
@@ -0,0 +1,286 @@
+//! Multi-way branches on an integer value.
+
+use cretonne::entity::EntityRef;
+use cretonne::ir::{Ebb, InstBuilder, JumpTableData, Value};
+use cretonne::ir::condcodes::IntCC;
+use frontend::FunctionBuilder;
+use std::collections::BTreeMap;
+use std::vec::Vec;
+
+/// A case value of a `Switch`.
+type EntryIndex = u64;
+
+/// Runs of consecutive cases shorter than this are dispatched with comparisons instead of a jump
+/// table.
+const MIN_JUMP_TABLE_SIZE: usize = 3;
+
+/// Runs of cases are searched linearly instead of with a binary search when there are at most
+/// this many of them.
+const MAX_LINEAR_RANGES: usize = 3;
+
+/// Unconditional branch to one of several EBBs depending on an integer value.
+///
+/// The cases are added with `set_entry()`, and `emit()` generates the dispatch code. The cases are
+/// grouped into runs of consecutive values. Long runs are dispatched through a `br_table` jump
+/// table, and the cases of short runs are compared with the value one by one. The runs are
+/// selected with a binary search, so both dense and sparse case sets are dispatched in a
+/// logarithmic number of branches.
+///
+/// ```rust
+/// # extern crate cretonne;
+/// # extern crate cton_frontend;
+/// # use cretonne::ir::{ExternalName, CallConv, Function, Signature, AbiParam, InstBuilder};
+/// # use cretonne::ir::types::*;
+/// # use cton_frontend::{FunctionBuilderContext, FunctionBuilder, Switch, Variable};
+/// # fn main() {
+/// let mut sig = Signature::new(CallConv::Native);
+/// sig.params.push(AbiParam::new(I32));
+/// let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+/// let mut func_ctx = FunctionBuilderContext::<Variable>::new();
+/// let mut builder = FunctionBuilder::<Variable>::new(&mut func, &mut func_ctx);
+///
+/// let entry = builder.create_ebb();
+/// builder.append_ebb_params_for_function_params(entry);
+/// builder.switch_to_block(entry);
+/// builder.seal_block(entry);
+/// let val = builder.ebb_params(entry)[0];
+///
+/// let zero = builder.create_ebb();
+/// let one = builder.create_ebb();
+/// let otherwise = builder.create_ebb();
+/// let mut switch = Switch::new();
+/// switch.set_entry(0, zero);
+/// switch.set_entry(1, one);
+/// switch.set_entry(7, one);
+/// switch.emit(&mut builder, val, otherwise);
+///
+/// for ebb in &[zero, one, otherwise] {
+///     builder.switch_to_block(*ebb);
+///     builder.seal_block(*ebb);
+///     builder.ins().return_(&[]);
+/// }
+/// builder.finalize();
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Switch {
+    cases: BTreeMap<EntryIndex, Ebb>,
+}
+
+/// A run of consecutive cases.
+struct ContiguousCaseRange {
+    /// The value of the first case.
+    first_index: EntryIndex,
+
+    /// The EBBs of the cases, starting with `first_index`.
+    ebbs: Vec<Ebb>,
+}
+
+impl Switch {
+    /// Create a switch without any cases.
+    pub fn new() -> Self {
+        Self { cases: BTreeMap::new() }
+    }
+
+    /// Branch to `ebb` when the value is `index`.
+    ///
+    /// Panics if `index` already has a case.
+    pub fn set_entry(&mut self, index: EntryIndex, ebb: Ebb) {
+        let prev = self.cases.insert(index, ebb);
+        assert!(prev.is_none(), "Case {} was set twice", index);
+    }
+
+    /// Get the cases that have been added, in increasing order.
+    pub fn entries(&self) -> &BTreeMap<EntryIndex, Ebb> {
+        &self.cases
+    }
+
+    /// Emit the dispatch code at the current position of `builder`, branching to the EBB of the
+    /// case equal to `val`, or to `otherwise` if there is none.
+    ///
+    /// The dispatch code ends the current EBB. The branch targets are not sealed, since they may
+    /// have other predecessors.
+    pub fn emit<Variable: EntityRef>(
+        self,
+        builder: &mut FunctionBuilder<Variable>,
+        val: Value,
+        otherwise: Ebb,
+    ) {
+        let ranges = self.collect_contiguous_case_ranges();
+        emit_search_tree(builder, val, otherwise, &ranges);
+    }
+
+    /// Group the cases into runs of consecutive values, in increasing order.
+    fn collect_contiguous_case_ranges(self) -> Vec<ContiguousCaseRange> {
+        let mut ranges: Vec<ContiguousCaseRange> = Vec::new();
+        for (index, ebb) in self.cases {
+            if let Some(range) = ranges.last_mut() {
+                if index - range.first_index == range.ebbs.len() as EntryIndex {
+                    range.ebbs.push(ebb);
+                    continue;
+                }
+            }
+            ranges.push(ContiguousCaseRange {
+                first_index: index,
+                ebbs: vec![ebb],
+            });
+        }
+        ranges
+    }
+}
+
+/// Emit a binary search for the range containing `val`.
+fn emit_search_tree<Variable: EntityRef>(
+    builder: &mut FunctionBuilder<Variable>,
+    val: Value,
+    otherwise: Ebb,
+    ranges: &[ContiguousCaseRange],
+) {
+    if ranges.len() <= MAX_LINEAR_RANGES {
+        return emit_linear_search(builder, val, otherwise, ranges);
+    }
+
+    // Values at or above the first case of the upper half are dispatched in a new EBB, which has
+    // no other predecessors.
+    let (lower, upper) = ranges.split_at(ranges.len() / 2);
+    let upper_ebb = builder.create_ebb();
+    let first_upper = upper[0].first_index as i64;
+    let is_upper = builder.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, val, first_upper);
+    builder.ins().brnz(is_upper, upper_ebb, &[]);
+    emit_search_tree(builder, val, otherwise, lower);
+
+    builder.switch_to_block(upper_ebb);
+    builder.seal_block(upper_ebb);
+    emit_search_tree(builder, val, otherwise, upper);
+}
+
+/// Emit the dispatch of `val` to each of `ranges` in turn, followed by a jump to `otherwise`.
+fn emit_linear_search<Variable: EntityRef>(
+    builder: &mut FunctionBuilder<Variable>,
+    val: Value,
+    otherwise: Ebb,
+    ranges: &[ContiguousCaseRange],
+) {
+    for range in ranges {
+        if range.ebbs.len() < MIN_JUMP_TABLE_SIZE {
+            for (i, &ebb) in range.ebbs.iter().enumerate() {
+                let index = range.first_index + i as EntryIndex;
+                let is_case = builder.ins().icmp_imm(IntCC::Equal, val, index as i64);
+                builder.ins().brnz(is_case, ebb, &[]);
+            }
+        } else {
+            // The jump table index wraps around for values below the range, so `br_table` falls
+            // through for them too.
+            let mut data = JumpTableData::with_capacity(range.ebbs.len());
+            for &ebb in &range.ebbs {
+                data.push_entry(ebb);
+            }
+            let jt = builder.create_jump_table(data);
+            let offset = if range.first_index == 0 {
+                val
+            } else {
+                builder.ins().iadd_imm(val, (range.first_index as i64).wrapping_neg())
+            };
+            builder.ins().br_table(offset, jt);
+        }
+    }
+    builder.ins().jump(otherwise, &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cretonne::ir::{ExternalName, Function, CallConv, Signature, AbiParam, Opcode};
+    use cretonne::ir::types::*;
+    use cretonne::settings;
+    use cretonne::verifier::verify_function;
+    use frontend::FunctionBuilderContext;
+    use std::string::ToString;
+    use Variable;
+
+    // Build a function dispatching its argument to an EBB for each of `cases`, and return the
+    // opcodes of the instructions.
+    fn switch_function(cases: &[EntryIndex]) -> (Function, Vec<Opcode>) {
+        let mut sig = Signature::new(CallConv::Native);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        let mut func = Function::with_name_signature(ExternalName::testcase("switch"), sig);
+        let mut func_ctx = FunctionBuilderContext::<Variable>::new();
+        {
+            let mut builder = FunctionBuilder::<Variable>::new(&mut func, &mut func_ctx);
+            let entry = builder.create_ebb();
+            builder.append_ebb_params_for_function_params(entry);
+            builder.switch_to_block(entry);
+            builder.seal_block(entry);
+            let val = builder.ebb_params(entry)[0];
+
+            let mut switch = Switch::new();
+            let mut targets = Vec::new();
+            for &index in cases {
+                let ebb = builder.create_ebb();
+                switch.set_entry(index, ebb);
+                targets.push((ebb, index as i64));
+            }
+            let otherwise = builder.create_ebb();
+            targets.push((otherwise, -1));
+            switch.emit(&mut builder, val, otherwise);
+
+            for (ebb, ret) in targets {
+                builder.switch_to_block(ebb);
+                builder.seal_block(ebb);
+                let ret = builder.ins().iconst(I32, ret);
+                builder.ins().return_(&[ret]);
+            }
+            builder.finalize();
+        }
+
+        let flags = settings::Flags::new(&settings::builder());
+        if let Err(err) = verify_function(&func, &flags) {
+            panic!("{}\n{}", err, func.display(None));
+        }
+        let opcodes = func.layout
+            .ebbs()
+            .flat_map(|ebb| func.layout.ebb_insts(ebb))
+            .map(|inst| func.dfg[inst].opcode())
+            .collect();
+        (func, opcodes)
+    }
+
+    fn count(opcodes: &[Opcode], opcode: Opcode) -> usize {
+        opcodes.iter().filter(|&&op| op == opcode).count()
+    }
+
+    #[test]
+    fn dense() {
+        let (func, opcodes) = switch_function(&[0, 1, 2, 3, 4]);
+        assert_eq!(func.jump_tables.len(), 1);
+        assert_eq!(count(&opcodes, Opcode::BrTable), 1);
+        assert_eq!(count(&opcodes, Opcode::IcmpImm), 0);
+        assert_eq!(count(&opcodes, Opcode::IaddImm), 0);
+    }
+
+    #[test]
+    fn sparse() {
+        let (func, opcodes) = switch_function(&[1, 10, 100, 1000, 10_000, 100_000, 1_000_000]);
+        assert_eq!(func.jump_tables.len(), 0);
+        // Seven equality comparisons and two comparisons splitting the search.
+        assert_eq!(count(&opcodes, Opcode::IcmpImm), 9);
+        assert_eq!(count(&opcodes, Opcode::Brnz), 9);
+    }
+
+    #[test]
+    fn mixed() {
+        let (func, opcodes) = switch_function(&[5, 6, 7, 8, 20, 30, 31, 40, 41, 42]);
+        assert_eq!(func.jump_tables.len(), 2);
+        assert_eq!(count(&opcodes, Opcode::BrTable), 2);
+        assert_eq!(count(&opcodes, Opcode::IaddImm), 2);
+        let text = func.display(None).to_string();
+        assert!(text.contains("v0, -40"), "{}", text);
+    }
+
+    #[test]
+    #[should_panic(expected = "Case 3 was set twice")]
+    fn duplicate() {
+        switch_function(&[3, 3]);
+    }
+}
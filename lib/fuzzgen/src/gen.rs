@@ -0,0 +1,323 @@
+//! Generation of random functions.
+//!
+//! The generator makes every decision by reading the fuzzer's input, so the fuzzer's mutations of
+//! the input steer the shape of the generated function. The generated functions are valid by
+//! construction:
+//!
+//! - Their parameters and results are `i32` or `i64`, so they can be called natively.
+//! - Each EBB only uses its own parameters and the values it defines, so all the uses are
+//!   dominated by their definitions without tracking the dominator tree.
+//! - The branches only go to later EBBs, so the functions always terminate.
+
+use cretonne::cursor::{Cursor, FuncCursor};
+use cretonne::ir::condcodes::IntCC;
+use cretonne::ir::immediates::Imm64;
+use cretonne::ir::types::{I32, I64};
+use interp::sext;
+use cretonne::ir::{AbiParam, CallConv, Ebb, ExternalName, Function, InstBuilder, Opcode,
+                   Signature, Type, Value};
+
+/// Maximum number of function parameters. The native calls support four.
+const MAX_PARAMS: usize = 4;
+
+/// Maximum number of EBBs in a function.
+const MAX_EBBS: usize = 4;
+
+/// Maximum number of parameters of the EBBs other than the entry block.
+const MAX_EBB_PARAMS: usize = 2;
+
+/// Maximum number of instructions generated in an EBB, not counting constants and terminators.
+const MAX_INSTS: usize = 16;
+
+/// The integer types of the generated values.
+const TYPES: [Type; 2] = [I32, I64];
+
+/// Binary instructions taking two operands of the controlling type.
+const BINARY: [Opcode; 11] = [
+    Opcode::Iadd,
+    Opcode::Isub,
+    Opcode::Imul,
+    Opcode::Band,
+    Opcode::Bor,
+    Opcode::Bxor,
+    Opcode::Ishl,
+    Opcode::Ushr,
+    Opcode::Sshr,
+    Opcode::Rotl,
+    Opcode::Rotr,
+];
+
+/// Binary instructions taking an operand and an immediate.
+const BINARY_IMM: [Opcode; 8] = [
+    Opcode::IaddImm,
+    Opcode::ImulImm,
+    Opcode::BandImm,
+    Opcode::BorImm,
+    Opcode::BxorImm,
+    Opcode::IshlImm,
+    Opcode::UshrImm,
+    Opcode::SshrImm,
+];
+
+/// Unary instructions.
+const UNARY: [Opcode; 4] = [Opcode::Bnot, Opcode::Clz, Opcode::Ctz, Opcode::Popcnt];
+
+/// Integer comparisons.
+const CONDS: [IntCC; 10] = [
+    IntCC::Equal,
+    IntCC::NotEqual,
+    IntCC::SignedLessThan,
+    IntCC::SignedGreaterThanOrEqual,
+    IntCC::SignedGreaterThan,
+    IntCC::SignedLessThanOrEqual,
+    IntCC::UnsignedLessThan,
+    IntCC::UnsignedGreaterThanOrEqual,
+    IntCC::UnsignedGreaterThan,
+    IntCC::UnsignedLessThanOrEqual,
+];
+
+/// The fuzzer's input, consumed by the generator to make its decisions.
+///
+/// Once the input is exhausted, all the decisions are zero. The generator still produces a valid
+/// function then, so any input is accepted, and a shorter input produces a smaller function.
+pub struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    /// Create an input reading `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Read a byte.
+    pub fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    /// Read a number below `n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        usize::from(self.byte()) % n
+    }
+
+    /// Read a 64-bit number.
+    pub fn u64(&mut self) -> u64 {
+        (0..8).fold(0, |acc, _| acc << 8 | u64::from(self.byte()))
+    }
+
+    /// Read one of the elements of `choices`.
+    fn choose<T: Copy>(&mut self, choices: &[T]) -> T {
+        choices[self.below(choices.len())]
+    }
+}
+
+/// Generate a function named `%f` from `input`.
+pub fn generate(input: &mut Input) -> Function {
+    let mut sig = Signature::new(CallConv::Native);
+    for _ in 0..input.below(MAX_PARAMS + 1) {
+        sig.params.push(AbiParam::new(input.choose(&TYPES)));
+    }
+    let ret_type = input.choose(&TYPES);
+    sig.returns.push(AbiParam::new(ret_type));
+    let mut func = Function::with_name_signature(ExternalName::testcase("f"), sig);
+
+    let num_ebbs = 1 + input.below(MAX_EBBS);
+    let ebbs: Vec<Ebb> = (0..num_ebbs).map(|_| func.dfg.make_ebb()).collect();
+    for param in 0..func.signature.params.len() {
+        let ty = func.signature.params[param].value_type;
+        func.dfg.append_ebb_param(ebbs[0], ty);
+    }
+    for &ebb in &ebbs[1..] {
+        for _ in 0..input.below(MAX_EBB_PARAMS + 1) {
+            let ty = input.choose(&TYPES);
+            func.dfg.append_ebb_param(ebb, ty);
+        }
+    }
+
+    let mut pos = FuncCursor::new(&mut func);
+    for (idx, &ebb) in ebbs.iter().enumerate() {
+        pos.insert_ebb(ebb);
+        let mut gen = EbbGenerator {
+            input: &mut *input,
+            pos: &mut pos,
+            values: Vec::new(),
+        };
+        gen.values.extend_from_slice(gen.pos.func.dfg.ebb_params(ebb));
+        for _ in 0..gen.input.below(MAX_INSTS + 1) {
+            gen.inst();
+        }
+        gen.terminator(&ebbs[idx + 1..], ret_type);
+    }
+    func
+}
+
+/// Generator for the instructions of a single EBB.
+struct EbbGenerator<'a, 'b: 'a, 'c: 'a, 'f: 'c> {
+    input: &'a mut Input<'b>,
+    pos: &'c mut FuncCursor<'f>,
+
+    /// The values that can be used in the EBB.
+    values: Vec<Value>,
+}
+
+impl<'a, 'b, 'c, 'f> EbbGenerator<'a, 'b, 'c, 'f> {
+    /// Get an operand of type `ty`: either a value defined earlier, or a new constant.
+    fn operand(&mut self, ty: Type) -> Value {
+        let candidates: Vec<Value> = self.values
+            .iter()
+            .cloned()
+            .filter(|&v| self.pos.func.dfg.value_type(v) == ty)
+            .collect();
+        if candidates.is_empty() || self.input.below(8) == 0 {
+            let imm = sext(ty, self.input.u64());
+            let value = self.pos.ins().iconst(ty, imm);
+            self.values.push(value);
+            value
+        } else {
+            self.input.choose(&candidates)
+        }
+    }
+
+    /// Get the arguments for a branch to `ebb`.
+    fn args(&mut self, ebb: Ebb) -> Vec<Value> {
+        let types: Vec<Type> = self.pos
+            .func
+            .dfg
+            .ebb_params(ebb)
+            .iter()
+            .map(|&v| self.pos.func.dfg.value_type(v))
+            .collect();
+        types.into_iter().map(|ty| self.operand(ty)).collect()
+    }
+
+    /// Generate an instruction computing a new value.
+    fn inst(&mut self) {
+        let ty = self.input.choose(&TYPES);
+        let value = match self.input.below(6) {
+            0 => {
+                let opcode = self.input.choose(&BINARY);
+                let x = self.operand(ty);
+                let y = self.operand(ty);
+                let (inst, dfg) = self.pos.ins().Binary(opcode, ty, x, y);
+                dfg.first_result(inst)
+            }
+            1 => {
+                let opcode = self.input.choose(&BINARY_IMM);
+                let imm = Imm64::new(sext(ty, self.input.u64()));
+                let x = self.operand(ty);
+                let (inst, dfg) = self.pos.ins().BinaryImm(opcode, ty, imm, x);
+                dfg.first_result(inst)
+            }
+            2 => {
+                let opcode = self.input.choose(&UNARY);
+                let x = self.operand(ty);
+                let (inst, dfg) = self.pos.ins().Unary(opcode, ty, x);
+                dfg.first_result(inst)
+            }
+            3 => {
+                let cond = self.compare();
+                self.pos.ins().bint(ty, cond)
+            }
+            4 => {
+                let cond = self.compare();
+                let x = self.operand(ty);
+                let y = self.operand(ty);
+                self.pos.ins().select(cond, x, y)
+            }
+            _ => {
+                if ty == I64 {
+                    let x = self.operand(I32);
+                    if self.input.below(2) == 0 {
+                        self.pos.ins().uextend(I64, x)
+                    } else {
+                        self.pos.ins().sextend(I64, x)
+                    }
+                } else {
+                    let x = self.operand(I64);
+                    self.pos.ins().ireduce(I32, x)
+                }
+            }
+        };
+        self.values.push(value);
+    }
+
+    /// Generate a comparison, returning its boolean result.
+    fn compare(&mut self) -> Value {
+        let cond = self.input.choose(&CONDS);
+        let ty = self.input.choose(&TYPES);
+        let x = self.operand(ty);
+        let y = self.operand(ty);
+        self.pos.ins().icmp(cond, x, y)
+    }
+
+    /// Generate the instructions ending the EBB, which can branch to any of the `later` EBBs.
+    fn terminator(&mut self, later: &[Ebb], ret_type: Type) {
+        if !later.is_empty() && self.input.below(4) != 0 {
+            let dest = self.input.choose(later);
+            let args = self.args(dest);
+            if self.input.below(2) == 0 {
+                self.pos.ins().jump(dest, &args);
+                return;
+            }
+            let ty = self.input.choose(&TYPES);
+            let cond = self.operand(ty);
+            if self.input.below(2) == 0 {
+                self.pos.ins().brz(cond, dest, &args);
+            } else {
+                self.pos.ins().brnz(cond, dest, &args);
+            }
+            if self.input.below(2) == 0 {
+                let dest = self.input.choose(later);
+                let args = self.args(dest);
+                self.pos.ins().jump(dest, &args);
+                return;
+            }
+        }
+        let value = self.operand(ret_type);
+        self.pos.ins().return_(&[value]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cretonne::settings;
+    use cretonne::verify_function;
+
+    #[test]
+    fn valid() {
+        let flags = settings::Flags::new(&settings::builder());
+        let mut state = 1u64;
+        for len in 0..200 {
+            let data: Vec<u8> = (0..len * 4)
+                .map(|_| {
+                    state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                    (state >> 56) as u8
+                })
+                .collect();
+            let func = generate(&mut Input::new(&data));
+            if let Err(err) = verify_function(&func, &flags) {
+                panic!("{}\n{}", err, func.display(None));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        let func = generate(&mut Input::new(&[]));
+        assert_eq!(
+            func.display(None).to_string(),
+            "function %f() -> i32 native {\n\
+             ebb0:\n    \
+                 v0 = iconst.i32 0\n    \
+                 return v0\n\
+             }\n"
+        );
+    }
+}
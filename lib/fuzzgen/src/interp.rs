@@ -0,0 +1,248 @@
+//! Reference interpreter.
+//!
+//! The interpreter computes the results that the code generated for a function must produce. It
+//! supports the integer instructions used by the generator and the shrinker, on types up to 64
+//! bits. Values are represented as `u64` with the bits above the width of their type cleared, and
+//! booleans are 0 or 1.
+
+use cretonne::entity::EntityMap;
+use cretonne::ir::condcodes::IntCC;
+use cretonne::ir::{Function, InstructionData, Opcode, Type, Value};
+
+/// Maximum number of instructions executed before giving up, in case the function loops.
+const MAX_STEPS: usize = 100_000;
+
+/// The result of interpreting a function.
+pub struct Outcome {
+    /// The value returned by the function.
+    pub result: u64,
+
+    /// The values computed by the instructions that were executed.
+    pub values: EntityMap<Value, u64>,
+}
+
+/// Clear the bits of `x` above the width of `ty`.
+pub fn mask(ty: Type, x: u64) -> u64 {
+    match ty.bits() {
+        64 => x,
+        bits => x & ((1 << bits) - 1),
+    }
+}
+
+/// Sign-extend the value `x` of type `ty`.
+pub fn sext(ty: Type, x: u64) -> i64 {
+    let shift = 64 - u32::from(ty.bits());
+    ((x << shift) as i64) >> shift
+}
+
+/// Evaluate the integer comparison `cond` of `x` and `y` of type `ty`.
+fn compare(cond: IntCC, ty: Type, x: u64, y: u64) -> Option<bool> {
+    let (sx, sy) = (sext(ty, x), sext(ty, y));
+    Some(match cond {
+        IntCC::Equal => x == y,
+        IntCC::NotEqual => x != y,
+        IntCC::SignedLessThan => sx < sy,
+        IntCC::SignedGreaterThanOrEqual => sx >= sy,
+        IntCC::SignedGreaterThan => sx > sy,
+        IntCC::SignedLessThanOrEqual => sx <= sy,
+        IntCC::UnsignedLessThan => x < y,
+        IntCC::UnsignedGreaterThanOrEqual => x >= y,
+        IntCC::UnsignedGreaterThan => x > y,
+        IntCC::UnsignedLessThanOrEqual => x <= y,
+        IntCC::Overflow | IntCC::NotOverflow => return None,
+    })
+}
+
+/// Evaluate the binary operation `opcode` of `x` and `y` of type `ty`.
+///
+/// The `_imm` variants of the operations are evaluated with the immediate as `y`.
+fn binary(opcode: Opcode, ty: Type, x: u64, y: u64) -> Option<u64> {
+    let bits = u32::from(ty.bits());
+    let shift = (y % u64::from(bits)) as u32;
+    let rotate = |left: bool| if shift == 0 {
+        x
+    } else if left {
+        x << shift | x >> (bits - shift)
+    } else {
+        x >> shift | x << (bits - shift)
+    };
+    Some(match opcode {
+        Opcode::Iadd | Opcode::IaddImm => x.wrapping_add(y),
+        Opcode::Isub => x.wrapping_sub(y),
+        Opcode::IrsubImm => y.wrapping_sub(x),
+        Opcode::Imul | Opcode::ImulImm => x.wrapping_mul(y),
+        Opcode::Band | Opcode::BandImm => x & y,
+        Opcode::Bor | Opcode::BorImm => x | y,
+        Opcode::Bxor | Opcode::BxorImm => x ^ y,
+        Opcode::Ishl | Opcode::IshlImm => x << shift,
+        Opcode::Ushr | Opcode::UshrImm => x >> shift,
+        Opcode::Sshr | Opcode::SshrImm => (sext(ty, x) >> shift) as u64,
+        Opcode::Rotl => rotate(true),
+        Opcode::Rotr => rotate(false),
+        _ => return None,
+    })
+}
+
+/// Evaluate the unary operation `opcode` of `x` of type `arg_ty`, producing a value of type `ty`.
+fn unary(opcode: Opcode, ty: Type, arg_ty: Type, x: u64) -> Option<u64> {
+    let bits = u32::from(arg_ty.bits());
+    Some(match opcode {
+        Opcode::Bnot => !x,
+        Opcode::Clz => u64::from(x.leading_zeros() - (64 - bits)),
+        Opcode::Ctz => u64::from(if x == 0 { bits } else { x.trailing_zeros() }),
+        Opcode::Popcnt => u64::from(x.count_ones()),
+        Opcode::Uextend | Opcode::Ireduce | Opcode::Bint | Opcode::Copy => x,
+        Opcode::Sextend => sext(arg_ty, x) as u64,
+        _ => return None,
+    }).map(|x| mask(ty, x))
+}
+
+/// Interpret `func` with the arguments `args`.
+///
+/// Returns an error if the function uses an instruction that the interpreter doesn't support, or
+/// doesn't return after a large number of steps.
+pub fn interpret(func: &Function, args: &[u64]) -> Result<Outcome, String> {
+    let dfg = &func.dfg;
+    let mut values = EntityMap::new();
+    let mut ebb = func.layout.entry_block().ok_or("function has no entry block")?;
+    let mut ebb_args: Vec<u64> = args.to_vec();
+    let mut steps = 0;
+
+    loop {
+        let params = dfg.ebb_params(ebb);
+        if params.len() != ebb_args.len() {
+            return Err(format!("{} expects {} arguments", ebb, params.len()));
+        }
+        for (&param, &arg) in params.iter().zip(&ebb_args) {
+            values[param] = mask(dfg.value_type(param), arg);
+        }
+
+        let mut next = None;
+        for inst in func.layout.ebb_insts(ebb) {
+            steps += 1;
+            if steps > MAX_STEPS {
+                return Err(String::from("too many steps"));
+            }
+
+            let data = &dfg[inst];
+            let opcode = data.opcode();
+            let ctrl_ty = dfg.ctrl_typevar(inst);
+            let arg_vals: Vec<u64> = dfg.inst_args(inst)
+                .iter()
+                .map(|&v| values[dfg.resolve_aliases(v)])
+                .collect();
+            let unsupported = || {
+                format!("unsupported instruction: {}", dfg.display_inst(inst, None))
+            };
+
+            let result = match *data {
+                InstructionData::UnaryImm { opcode: Opcode::Iconst, imm } => {
+                    let imm: i64 = imm.into();
+                    Some(mask(ctrl_ty, imm as u64))
+                }
+                InstructionData::Unary { arg, .. } => {
+                    let arg_ty = dfg.value_type(arg);
+                    let ty = dfg.value_type(dfg.first_result(inst));
+                    Some(unary(opcode, ty, arg_ty, arg_vals[0]).ok_or_else(unsupported)?)
+                }
+                InstructionData::Binary { .. } => {
+                    Some(binary(opcode, ctrl_ty, arg_vals[0], arg_vals[1])
+                        .map(|x| mask(ctrl_ty, x))
+                        .ok_or_else(unsupported)?)
+                }
+                InstructionData::BinaryImm { imm, .. } => {
+                    let imm: i64 = imm.into();
+                    Some(binary(opcode, ctrl_ty, arg_vals[0], imm as u64)
+                        .map(|x| mask(ctrl_ty, x))
+                        .ok_or_else(unsupported)?)
+                }
+                InstructionData::IntCompare { opcode: Opcode::Icmp, cond, args } => {
+                    let ty = dfg.value_type(args[0]);
+                    let result = compare(cond, ty, arg_vals[0], arg_vals[1]);
+                    Some(u64::from(result.ok_or_else(unsupported)?))
+                }
+                InstructionData::Ternary { opcode: Opcode::Select, .. } => {
+                    Some(if arg_vals[0] != 0 { arg_vals[1] } else { arg_vals[2] })
+                }
+                InstructionData::Jump { opcode: Opcode::Jump, destination, .. } => {
+                    next = Some((destination, arg_vals));
+                    break;
+                }
+                InstructionData::Branch { destination, .. } => {
+                    let taken = match opcode {
+                        Opcode::Brz => arg_vals[0] == 0,
+                        Opcode::Brnz => arg_vals[0] != 0,
+                        _ => return Err(unsupported()),
+                    };
+                    if taken {
+                        next = Some((destination, arg_vals[1..].to_vec()));
+                        break;
+                    }
+                    None
+                }
+                InstructionData::MultiAry { opcode: Opcode::Return, .. } => {
+                    let result = *arg_vals.first().ok_or("return without a value")?;
+                    return Ok(Outcome { result, values });
+                }
+                _ => return Err(unsupported()),
+            };
+
+            if let Some(result) = result {
+                values[dfg.first_result(inst)] = result;
+            }
+        }
+
+        match next {
+            Some((dest, args)) => {
+                ebb = dest;
+                ebb_args = args;
+            }
+            None => return Err(format!("{} has no terminator", ebb)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cretonne::cursor::{Cursor, FuncCursor};
+    use cretonne::ir::types::{I32, I64};
+    use cretonne::ir::{AbiParam, InstBuilder};
+
+    #[test]
+    fn operations() {
+        assert_eq!(binary(Opcode::Rotl, I32, 0x8000_0001, 33), Some(0x1_0000_0003));
+        assert_eq!(binary(Opcode::Sshr, I32, 0x8000_0000, 4), Some(0xffff_ffff_f800_0000));
+        assert_eq!(unary(Opcode::Clz, I64, I32, 1), Some(31));
+        assert_eq!(unary(Opcode::Ctz, I32, I32, 0), Some(32));
+        assert_eq!(unary(Opcode::Sextend, I64, I32, 0x8000_0000), Some(0xffff_ffff_8000_0000));
+        assert_eq!(compare(IntCC::SignedLessThan, I32, 0xffff_ffff, 0), Some(true));
+        assert_eq!(compare(IntCC::UnsignedLessThan, I32, 0xffff_ffff, 0), Some(false));
+    }
+
+    #[test]
+    fn branches() {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let y = func.dfg.append_ebb_param(ebb1, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let neg = pos.ins().irsub_imm(x, 0);
+            pos.ins().brz(x, ebb1, &[x]);
+            pos.ins().jump(ebb1, &[neg]);
+            pos.insert_ebb(ebb1);
+            let z = pos.ins().iadd_imm(y, 1);
+            pos.ins().return_(&[z]);
+        }
+
+        assert_eq!(interpret(&func, &[0]).unwrap().result, 1);
+        let outcome = interpret(&func, &[5]).unwrap();
+        assert_eq!(outcome.result, 0xffff_fffc);
+        assert_eq!(outcome.values[y], 0xffff_fffb);
+    }
+}
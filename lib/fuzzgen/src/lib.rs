@@ -0,0 +1,219 @@
+//! Random function generator and differential tester.
+//!
+//! Hand-written file tests only exercise the cases their authors thought of. This crate generates
+//! random valid functions from a fuzzer's input bytes, and checks the code generated for them:
+//!
+//! - The function is compiled for the host at each optimization level and called natively, and
+//!   its result is compared with the result computed by a reference interpreter.
+//! - The function is also compiled for the other targets listed in `CROSS_TARGETS`, where the code
+//!   can't be run. Only panics are reported there, since these targets don't support all the
+//!   generated instructions yet.
+//!
+//! A function exposing a bug is shrunk, and reported as a `.cton` test case that reproduces it:
+//!
+//! ```no_run
+//! # extern crate cton_fuzzgen;
+//! # fn main() {
+//! # let data = [0u8; 64];
+//! if let Err(failure) = cton_fuzzgen::fuzz_differential(&data) {
+//!     panic!("{}\n{}", failure.message, failure.testcase);
+//! }
+//! # }
+//! ```
+
+#![deny(missing_docs,
+        trivial_numeric_casts,
+        unused_extern_crates)]
+
+extern crate cretonne;
+extern crate cton_native;
+extern crate memmap;
+
+pub use gen::{generate, Input};
+pub use interp::{interpret, Outcome};
+pub use shrink::shrink;
+
+mod gen;
+mod interp;
+mod native;
+mod shrink;
+
+use cretonne::ir::Function;
+use cretonne::result::CtonError;
+use cretonne::settings::{self, Configurable};
+use cretonne::{verify_function, Context};
+use native::{host_isa, NativeError, NativeFunction};
+use std::fmt::Write;
+
+/// The optimization levels the functions are compiled and run at.
+const OPT_LEVELS: [&str; 4] = ["fastest", "default", "best", "smallest"];
+
+/// The targets the functions are compiled for without running them: an ISA name and whether it
+/// is 64-bit.
+const CROSS_TARGETS: [(&str, bool); 2] = [("intel", false), ("riscv", false)];
+
+/// The kinds of bugs found by `fuzz_differential`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The generated code returned a different result than the interpreter.
+    WrongResult,
+
+    /// The compilation failed with an error.
+    CompileError,
+
+    /// The compilation panicked.
+    Panic,
+}
+
+/// A bug found by `fuzz_differential`.
+#[derive(Debug)]
+pub struct Failure {
+    /// The kind of bug.
+    pub kind: FailureKind,
+
+    /// Description of the bug.
+    pub message: String,
+
+    /// A `.cton` test file reproducing the bug with the shrunk function.
+    pub testcase: String,
+}
+
+/// A bug exposed by a function.
+struct Bug {
+    kind: FailureKind,
+    message: String,
+
+    /// The header of the test file reproducing the bug.
+    header: String,
+
+    /// The `run:` directive checking the result, if the bug is a wrong result.
+    run: Option<String>,
+}
+
+/// Generate a function and its arguments from `data`, and check the code generated for it.
+///
+/// Any input generates a valid function, so all the failures are bugs.
+pub fn fuzz_differential(data: &[u8]) -> Result<(), Failure> {
+    let mut input = Input::new(data);
+    let func = generate(&mut input);
+    let args: Vec<u64> = func.signature.params.iter().map(|_| input.u64()).collect();
+    if find_bug(&func, &args).is_none() {
+        return Ok(());
+    }
+
+    let small = shrink(&func, &args, |f| find_bug(f, &args).is_some());
+    let bug = find_bug(&small, &args).expect("shrinking lost the bug");
+    let mut testcase = bug.header;
+    let _ = write!(testcase, "\n{}", small.display(None));
+    if let Some(run) = bug.run {
+        testcase.push_str(&run);
+    }
+    Err(Failure {
+        kind: bug.kind,
+        message: bug.message,
+        testcase,
+    })
+}
+
+/// Check the code generated for `func` when called with `args`.
+///
+/// Returns `None` for functions that aren't valid or can't be interpreted, which the shrinker
+/// can produce.
+fn find_bug(func: &Function, args: &[u64]) -> Option<Bug> {
+    let flags = settings::Flags::new(&settings::builder());
+    if verify_function(func, &flags).is_err() {
+        return None;
+    }
+    let expected = interpret(func, args).ok()?.result;
+
+    for &opt_level in &OPT_LEVELS {
+        let isa = match host_isa(opt_level) {
+            Some(isa) => isa,
+            None => break,
+        };
+        let header = format!("test run\nset opt_level={}\n", opt_level);
+        let got = match NativeFunction::compile(func.clone(), &*isa) {
+            Ok(native) => native.call(args),
+            Err(NativeError::Compile(err)) => {
+                let kind = match err {
+                    CtonError::Panic(_) => FailureKind::Panic,
+                    _ => FailureKind::CompileError,
+                };
+                return Some(Bug {
+                    kind,
+                    message: format!("{}: {}", opt_level, err),
+                    header,
+                    run: None,
+                })
+            }
+            Err(NativeError::Load(msg)) => panic!("can't load the compiled code: {}", msg),
+        };
+        if got != expected {
+            let args: Vec<String> = args.iter().map(|a| format!("{:#x}", a)).collect();
+            let run = format!("; run: {}({}) == {:#x}\n", func.name, args.join(", "), expected);
+            return Some(Bug {
+                kind: FailureKind::WrongResult,
+                message: format!(
+                    "{}: {}({}) returned {:#x} instead of {:#x}",
+                    opt_level,
+                    func.name,
+                    args.join(", "),
+                    got,
+                    expected
+                ),
+                run: Some(run),
+                header,
+            });
+        }
+    }
+
+    for &(name, is_64bit) in &CROSS_TARGETS {
+        let mut flag_builder = settings::builder();
+        if is_64bit {
+            flag_builder.enable("is_64bit").unwrap();
+        }
+        let isa = match cretonne::isa::lookup(name) {
+            Ok(builder) => builder.finish(settings::Flags::new(&flag_builder)),
+            Err(_) => continue,
+        };
+        let mut ctx = Context::for_function(func.clone());
+        if let Err(CtonError::Panic(msg)) = ctx.compile(&*isa) {
+            let set = if is_64bit { "set is_64bit\n" } else { "" };
+            return Some(Bug {
+                kind: FailureKind::Panic,
+                message: format!("{}: panic: {}", name, msg),
+                header: format!("test compile\n{}isa {}\n", set, name),
+                run: None,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_functions() {
+        // The harness finds compile errors and panics in the code generator that aren't fixed
+        // yet, so only check that the generated code computes the right results, and that the
+        // failures are reported with a test case.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for len in 0..100 {
+            let data: Vec<u8> = (0..len * 3)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            if let Err(failure) = fuzz_differential(&data) {
+                assert_ne!(failure.kind, FailureKind::WrongResult, "{}", failure.message);
+                assert!(failure.testcase.starts_with("test "), "{}", failure.testcase);
+                assert!(failure.testcase.contains("function %f("), "{}", failure.testcase);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Native execution of compiled functions.
+//!
+//! This works like the `run` file test command: the function is compiled for the host, its
+//! machine code is copied into executable memory, and it is called with up to four integer
+//! arguments.
+
+use cretonne::binemit::{Addend, CodeOffset, NullTrapSink, Reloc, RelocSink, SymbolInfo};
+use cretonne::ir::{ExternalName, Function, JumpTable, Type};
+use cretonne::isa::TargetIsa;
+use cretonne::result::CtonError;
+use cretonne::settings::{self, Configurable};
+use cretonne::Context;
+use cton_native;
+use memmap::{Mmap, MmapMut};
+use std::any::Any;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Build a `TargetIsa` for the host at `opt_level`, if the host is supported.
+///
+/// The native calls assume 64-bit argument registers.
+pub fn host_isa(opt_level: &str) -> Option<Box<TargetIsa>> {
+    if !cfg!(target_pointer_width = "64") {
+        return None;
+    }
+    cton_native::builders().ok().map(|(mut flag_builder, isa_builder)| {
+        flag_builder.set("opt_level", opt_level).unwrap();
+        isa_builder.finish(settings::Flags::new(&flag_builder))
+    })
+}
+
+/// A compiled function in executable memory.
+pub struct NativeFunction {
+    /// The executable code. The mapping must stay alive while the function can be called.
+    mem: Mmap,
+
+    /// Type of the return value.
+    ret: Type,
+}
+
+/// The reasons a function can't be run natively.
+pub enum NativeError {
+    /// The compilation failed.
+    Compile(CtonError),
+
+    /// The code can't be loaded.
+    Load(String),
+}
+
+impl NativeFunction {
+    /// Compile `func` for `isa` and copy its machine code into executable memory.
+    ///
+    /// The function must have at most four integer parameters and a single integer result.
+    pub fn compile(func: Function, isa: &TargetIsa) -> Result<Self, NativeError> {
+        debug_assert!(func.signature.params.len() <= 4);
+        let ret = func.signature.returns[0].value_type;
+        let mut ctx = Context::for_function(func);
+        let code_size = ctx.compile(isa).map_err(NativeError::Compile)?;
+
+        let mut mem = MmapMut::map_anon(code_size as usize).map_err(|e| {
+            NativeError::Load(e.to_string())
+        })?;
+        let mut relocs = NoRelocs(None);
+
+        // `compile` reports its panics as errors, but the emission of the code can panic too.
+        let ptr = mem.as_mut_ptr();
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            ctx.emit_to_memory(ptr, &mut relocs, &mut NullTrapSink {}, isa)
+        })).map_err(|payload| {
+            NativeError::Compile(CtonError::Panic(panic_message(&*payload)))
+        })?;
+        if let Some(reloc) = relocs.0 {
+            return Err(NativeError::Load(format!("unexpected relocation: {}", reloc)));
+        }
+
+        Ok(Self {
+            mem: mem.make_exec().map_err(|e| NativeError::Load(e.to_string()))?,
+            ret,
+        })
+    }
+
+    /// Call the function with `args`, and return its result with the upper bits cleared.
+    pub fn call(&self, args: &[u64]) -> u64 {
+        // All the argument types are passed in 64-bit integer registers, so we can always call
+        // the function as if it took and returned `i64` values. The upper bits of narrower
+        // arguments are ignored by the callee.
+        let ptr = self.mem.as_ptr();
+        let a = |i: usize| args[i];
+        let result = unsafe {
+            match args.len() {
+                0 => mem::transmute::<_, extern "C" fn() -> u64>(ptr)(),
+                1 => mem::transmute::<_, extern "C" fn(u64) -> u64>(ptr)(a(0)),
+                2 => mem::transmute::<_, extern "C" fn(u64, u64) -> u64>(ptr)(a(0), a(1)),
+                3 => {
+                    mem::transmute::<_, extern "C" fn(u64, u64, u64) -> u64>(ptr)(
+                        a(0),
+                        a(1),
+                        a(2),
+                    )
+                }
+                _ => {
+                    mem::transmute::<_, extern "C" fn(u64, u64, u64, u64) -> u64>(ptr)(
+                        a(0),
+                        a(1),
+                        a(2),
+                        a(3),
+                    )
+                }
+            }
+        };
+        ::interp::mask(self.ret, result)
+    }
+}
+
+/// Get the message from a panic payload.
+fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// A `RelocSink` that remembers the first relocation it sees.
+struct NoRelocs(Option<Reloc>);
+
+impl RelocSink for NoRelocs {
+    fn reloc_ebb(&mut self, _: CodeOffset, reloc: Reloc, _: CodeOffset) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+
+    fn reloc_external(
+        &mut self,
+        _: CodeOffset,
+        reloc: Reloc,
+        _: &ExternalName,
+        _: Addend,
+        _: SymbolInfo,
+    ) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+
+    fn reloc_jt(&mut self, _: CodeOffset, reloc: Reloc, _: JumpTable) {
+        self.0 = self.0.take().or(Some(reloc));
+    }
+}
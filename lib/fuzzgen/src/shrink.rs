@@ -0,0 +1,228 @@
+//! Shrinking of failing functions.
+//!
+//! A generated function that exposes a bug is mostly noise. The shrinker repeatedly tries to
+//! simplify the function, and keeps each simplification that still exposes the bug:
+//!
+//! - The unreachable EBBs and the instructions whose results are unused are removed, all at once
+//!   or one instruction at a time, since the bug can be in the handling of dead code.
+//! - Conditional branches are removed, or replaced by a jump.
+//! - Instructions are replaced by a constant of the value they computed in the interpreter.
+
+use cretonne::entity::EntitySet;
+use cretonne::ir::{Ebb, Function, Inst, InstBuilder, Opcode};
+use interp::{interpret, sext};
+use std::collections::HashSet;
+
+/// Shrink `func`, which fails when called with `args`.
+///
+/// `fails` tells if a simplified function still exposes the bug.
+pub fn shrink<F>(func: &Function, args: &[u64], mut fails: F) -> Function
+where
+    F: FnMut(&Function) -> bool,
+{
+    let mut func = func.clone();
+    while let Some(smaller) = candidates(&func, args).into_iter().find(|f| fails(f)) {
+        func = smaller;
+    }
+    func
+}
+
+/// Get the simplifications of `func` to try, largest first.
+fn candidates(func: &Function, args: &[u64]) -> Vec<Function> {
+    let mut candidates = Vec::new();
+    let mut cleaned = func.clone();
+    if cleanup(&mut cleaned) {
+        candidates.push(cleaned);
+    }
+    for inst in dead_insts(func) {
+        let mut removed = func.clone();
+        removed.layout.remove_inst(inst);
+        candidates.push(removed);
+    }
+    let reachable = reachable_ebbs(func);
+    for ebb in func.layout.ebbs().filter(|&ebb| !reachable.contains(ebb)) {
+        let mut removed = func.clone();
+        remove_ebb(&mut removed, ebb);
+        candidates.push(removed);
+    }
+
+    let insts: Vec<Inst> = func.layout
+        .ebbs()
+        .flat_map(|ebb| func.layout.ebb_insts(ebb))
+        .collect();
+
+    for &inst in &insts {
+        let opcode = func.dfg[inst].opcode();
+        if opcode != Opcode::Brz && opcode != Opcode::Brnz {
+            continue;
+        }
+
+        // Remove the branch, as if it was never taken.
+        let mut removed = func.clone();
+        removed.layout.remove_inst(inst);
+        candidates.push(removed);
+
+        // Replace it by a jump, as if it was always taken.
+        let mut jump = func.clone();
+        let dest = jump.dfg[inst].branch_destination().unwrap();
+        let ebb_args = jump.dfg.inst_args(inst)[1..].to_vec();
+        while let Some(next) = jump.layout.next_inst(inst) {
+            jump.layout.remove_inst(next);
+        }
+        jump.dfg.replace(inst).jump(dest, &ebb_args);
+        candidates.push(jump);
+    }
+
+    let values = interpret(func, args).map(|outcome| outcome.values).ok();
+    for &inst in &insts {
+        let opcode = func.dfg[inst].opcode();
+        if opcode == Opcode::Iconst || func.dfg.inst_results(inst).len() != 1 {
+            continue;
+        }
+        let result = func.dfg.first_result(inst);
+        let ty = func.dfg.value_type(result);
+        if !ty.is_int() {
+            continue;
+        }
+        let value = values.as_ref().map_or(0, |values| values[result]);
+        let mut constant = func.clone();
+        constant.dfg.replace(inst).iconst(ty, sext(ty, value));
+        candidates.push(constant);
+    }
+    candidates
+}
+
+/// Get the instructions of `func` whose results are unused.
+fn dead_insts(func: &Function) -> Vec<Inst> {
+    let mut used = HashSet::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            for &arg in func.dfg.inst_args(inst) {
+                used.insert(func.dfg.resolve_aliases(arg));
+            }
+        }
+    }
+    func.layout
+        .ebbs()
+        .flat_map(|ebb| func.layout.ebb_insts(ebb))
+        .filter(|&inst| {
+            let opcode = func.dfg[inst].opcode();
+            !opcode.is_branch() && !opcode.is_terminator() &&
+                func.dfg.inst_results(inst).iter().all(|v| !used.contains(v))
+        })
+        .collect()
+}
+
+/// Get the EBBs of `func` that are reachable from the entry block.
+fn reachable_ebbs(func: &Function) -> EntitySet<Ebb> {
+    let mut reachable = EntitySet::new();
+    let mut worklist: Vec<Ebb> = func.layout.entry_block().into_iter().collect();
+    while let Some(ebb) = worklist.pop() {
+        if reachable.contains(ebb) {
+            continue;
+        }
+        reachable.insert(ebb);
+        for inst in func.layout.ebb_insts(ebb) {
+            if let Some(dest) = func.dfg[inst].branch_destination() {
+                worklist.push(dest);
+            }
+        }
+    }
+    reachable
+}
+
+/// Remove `ebb` and its instructions from the layout of `func`.
+fn remove_ebb(func: &mut Function, ebb: Ebb) {
+    while let Some(inst) = func.layout.first_inst(ebb) {
+        func.layout.remove_inst(inst);
+    }
+    func.layout.remove_ebb(ebb);
+}
+
+/// Remove the unreachable EBBs and the unused instructions of `func`.
+///
+/// Returns whether anything was removed.
+fn cleanup(func: &mut Function) -> bool {
+    let mut changed = false;
+    let reachable = reachable_ebbs(func);
+    let ebbs: Vec<Ebb> = func.layout.ebbs().collect();
+    for ebb in ebbs {
+        if !reachable.contains(ebb) {
+            remove_ebb(func, ebb);
+            changed = true;
+        }
+    }
+
+    loop {
+        let dead = dead_insts(func);
+        if dead.is_empty() {
+            return changed;
+        }
+        for inst in dead {
+            func.layout.remove_inst(inst);
+        }
+        changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cretonne::cursor::{Cursor, FuncCursor};
+    use cretonne::ir::types::I32;
+    use cretonne::ir::{AbiParam, InstructionData};
+
+    #[test]
+    fn shrink_to_opcode() {
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(I32));
+        func.signature.returns.push(AbiParam::new(I32));
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        let x = func.dfg.append_ebb_param(ebb0, I32);
+        let y = func.dfg.append_ebb_param(ebb1, I32);
+        {
+            let mut pos = FuncCursor::new(&mut func);
+            pos.insert_ebb(ebb0);
+            let a = pos.ins().iadd_imm(x, 3);
+            let b = pos.ins().imul(a, x);
+            pos.ins().brnz(x, ebb1, &[b]);
+            let c = pos.ins().ishl_imm(b, 2);
+            pos.ins().return_(&[c]);
+            pos.insert_ebb(ebb1);
+            let z = pos.ins().bxor_imm(y, 1);
+            pos.ins().return_(&[z]);
+        }
+
+        // Pretend that the result of `imul` is miscompiled.
+        let uses_imul = |func: &Function| {
+            let dead = dead_insts(func);
+            func.layout.ebbs().any(|ebb| {
+                func.layout.ebb_insts(ebb).any(|inst| {
+                    func.dfg[inst].opcode() == Opcode::Imul && !dead.contains(&inst)
+                })
+            })
+        };
+        let small = shrink(&func, &[2], uses_imul);
+
+        // The `imul` of a constant and the parameter is left, with the instructions between it
+        // and the return.
+        let opcodes: Vec<Opcode> = small
+            .layout
+            .ebbs()
+            .flat_map(|ebb| small.layout.ebb_insts(ebb))
+            .map(|inst| small.dfg[inst].opcode())
+            .collect();
+        assert_eq!(opcodes.len(), 4, "{}", small.display(None));
+        assert!(opcodes.contains(&Opcode::Imul));
+        let consts: Vec<i64> = small
+            .layout
+            .ebb_insts(ebb0)
+            .filter_map(|inst| match small.dfg[inst] {
+                InstructionData::UnaryImm { imm, .. } => Some(imm.into()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(consts, [5]);
+    }
+}
@@ -7,18 +7,16 @@
 
 extern crate cretonne;
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-extern crate raw_cpuid;
-
 use cretonne::isa;
 use cretonne::settings::{self, Configurable};
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-use raw_cpuid::CpuId;
-
 /// Return `settings` and `isa` builders configured for the current host
 /// machine, or `Err(())` if the host machine is not supported
 /// in the current configuration.
+///
+/// The ISA builder is configured for the features of the host CPU by
+/// `isa::lookup_native()`. On x86, it reads CPUID with the `std::arch`
+/// intrinsics, so this crate doesn't need the `raw-cpuid` crate.
 pub fn builders() -> Result<(settings::Builder, isa::Builder), &'static str> {
     let mut flag_builder = settings::builder();
 
@@ -28,64 +26,10 @@ pub fn builders() -> Result<(settings::Builder, isa::Builder), &'static str> {
         flag_builder.enable("is_64bit").unwrap();
     }
 
-    let name = if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-        "intel"
-    } else if cfg!(target_arch = "arm") {
-        "arm32"
-    } else if cfg!(target_arch = "aarch64") {
-        "arm64"
-    } else {
-        return Err("unrecognized architecture");
-    };
-
-    let mut isa_builder = isa::lookup(name).map_err(|err| match err {
-        isa::LookupError::Unknown => panic!(),
+    let isa_builder = isa::lookup_native().map_err(|err| match err {
+        isa::LookupError::Unknown => "unrecognized architecture",
         isa::LookupError::Unsupported => "unsupported architecture",
     })?;
 
-    if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-        parse_x86_cpuid(&mut isa_builder)?;
-    }
-
     Ok((flag_builder, isa_builder))
 }
-
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn parse_x86_cpuid(isa_builder: &mut isa::Builder) -> Result<(), &'static str> {
-    let cpuid = CpuId::new();
-
-    if let Some(info) = cpuid.get_feature_info() {
-        if !info.has_sse2() {
-            return Err("x86 support requires SSE2");
-        }
-        if info.has_sse3() {
-            isa_builder.enable("has_sse3").unwrap();
-        }
-        if info.has_sse41() {
-            isa_builder.enable("has_sse41").unwrap();
-        }
-        if info.has_sse42() {
-            isa_builder.enable("has_sse42").unwrap();
-        }
-        if info.has_popcnt() {
-            isa_builder.enable("has_popcnt").unwrap();
-        }
-        if info.has_avx() {
-            isa_builder.enable("has_avx").unwrap();
-        }
-    }
-    if let Some(info) = cpuid.get_extended_feature_info() {
-        if info.has_bmi1() {
-            isa_builder.enable("has_bmi1").unwrap();
-        }
-        if info.has_bmi2() {
-            isa_builder.enable("has_bmi2").unwrap();
-        }
-    }
-    if let Some(info) = cpuid.get_extended_function_info() {
-        if info.has_lzcnt() {
-            isa_builder.enable("has_lzcnt").unwrap();
-        }
-    }
-    Ok(())
-}
@@ -0,0 +1,223 @@
+//! Syntax trees for parsed functions.
+//!
+//! Besides building a `Function`, the parser records where the parts of the function appear in
+//! the source text. Tools working on the text itself, like formatters and linters, can use the
+//! syntax tree to find the declarations, EBBs, instructions, and entity references without
+//! parsing the text again.
+//!
+//! The syntax tree of a function is available as `Details::syntax`, and it can be traversed with
+//! a `Visitor`.
+
+use cretonne::ir::entities::AnyEntity;
+use cretonne::ir::{Ebb, Inst};
+use error::Location;
+
+/// A range of bytes in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub start: usize,
+    /// Byte offset following the last character.
+    pub end: usize,
+    /// Location of the first character.
+    pub location: Location,
+}
+
+impl Span {
+    /// Get the text covered by this span in `source`, which must be the parsed text.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+
+    /// Get the span from the start of `self` to the end of `other`.
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            location: self.location,
+        }
+    }
+}
+
+/// A reference to an entity like `v3`, `ebb1`, or `ss2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    /// The entity referenced.
+    pub entity: AnyEntity,
+    /// The span of the entity name.
+    pub span: Span,
+}
+
+/// The syntax tree of a function.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionSyntax {
+    /// The whole function, from the `function` keyword to the closing brace.
+    pub span: Span,
+    /// The function name following the `function` keyword.
+    pub name: Span,
+    /// Preamble declarations of stack slots, global variables, heaps, signatures, functions, and
+    /// jump tables.
+    pub decls: Vec<DeclSyntax>,
+    /// The EBBs in the function body.
+    pub ebbs: Vec<EbbSyntax>,
+    /// Comments in the same order as `Details::comments`.
+    pub comments: Vec<CommentSyntax>,
+}
+
+/// A preamble declaration like `ss0 = explicit_slot 4`.
+#[derive(Debug, Clone)]
+pub struct DeclSyntax {
+    /// The declared entity.
+    pub entity: Reference,
+    /// The whole declaration.
+    pub span: Span,
+    /// Other entities referenced by the declaration.
+    pub references: Vec<Reference>,
+}
+
+/// An extended basic block.
+#[derive(Debug, Clone)]
+pub struct EbbSyntax {
+    /// The EBB.
+    pub ebb: Ebb,
+    /// The whole EBB, including its instructions.
+    pub span: Span,
+    /// The EBB header, including the parameters and the colon.
+    pub header: Span,
+    /// The EBB parameters.
+    pub params: Vec<Reference>,
+    /// The instructions and value aliases in the EBB.
+    pub insts: Vec<InstSyntax>,
+}
+
+/// An instruction or a value alias like `v3 -> v2`.
+#[derive(Debug, Clone)]
+pub struct InstSyntax {
+    /// The instruction, or `None` for a value alias.
+    pub inst: Option<Inst>,
+    /// The whole instruction, including any source location and encoding.
+    pub span: Span,
+    /// The opcode name without the type suffix, or `None` for a value alias.
+    pub opcode: Option<Span>,
+    /// The result values, or the alias.
+    pub results: Vec<Reference>,
+    /// The entities referenced by the encoding and the operands.
+    pub references: Vec<Reference>,
+}
+
+/// A comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// The entity this comment is attached to, see `Comment`.
+    pub entity: AnyEntity,
+    /// The comment text, including the leading `;`.
+    pub span: Span,
+}
+
+/// A visitor traversing the syntax tree of a function.
+///
+/// The default methods visit the children of each node by calling the corresponding `walk_*`
+/// function. An implementation overriding a method can call the `walk_*` function to continue the
+/// traversal.
+pub trait Visitor {
+    /// Visit a function.
+    fn visit_function(&mut self, func: &FunctionSyntax) {
+        walk_function(self, func)
+    }
+
+    /// Visit a preamble declaration.
+    fn visit_decl(&mut self, decl: &DeclSyntax) {
+        walk_decl(self, decl)
+    }
+
+    /// Visit an EBB.
+    fn visit_ebb(&mut self, ebb: &EbbSyntax) {
+        walk_ebb(self, ebb)
+    }
+
+    /// Visit an instruction or a value alias.
+    fn visit_inst(&mut self, inst: &InstSyntax) {
+        walk_inst(self, inst)
+    }
+
+    /// Visit an entity reference.
+    fn visit_reference(&mut self, _reference: &Reference) {}
+
+    /// Visit a comment.
+    fn visit_comment(&mut self, _comment: &CommentSyntax) {}
+}
+
+/// Visit the declarations, EBBs, and comments of `func`.
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, func: &FunctionSyntax) {
+    for decl in &func.decls {
+        visitor.visit_decl(decl);
+    }
+    for ebb in &func.ebbs {
+        visitor.visit_ebb(ebb);
+    }
+    for comment in &func.comments {
+        visitor.visit_comment(comment);
+    }
+}
+
+/// Visit the declared entity and the references of `decl`.
+pub fn walk_decl<V: Visitor + ?Sized>(visitor: &mut V, decl: &DeclSyntax) {
+    visitor.visit_reference(&decl.entity);
+    for reference in &decl.references {
+        visitor.visit_reference(reference);
+    }
+}
+
+/// Visit the parameters and instructions of `ebb`.
+pub fn walk_ebb<V: Visitor + ?Sized>(visitor: &mut V, ebb: &EbbSyntax) {
+    for param in &ebb.params {
+        visitor.visit_reference(param);
+    }
+    for inst in &ebb.insts {
+        visitor.visit_inst(inst);
+    }
+}
+
+/// Visit the results and references of `inst`.
+pub fn walk_inst<V: Visitor + ?Sized>(visitor: &mut V, inst: &InstSyntax) {
+    for reference in inst.results.iter().chain(&inst.references) {
+        visitor.visit_reference(reference);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse_test;
+
+    // Collect the text of all the references to values.
+    struct ValueRefs<'a> {
+        source: &'a str,
+        refs: Vec<&'a str>,
+    }
+
+    impl<'a> Visitor for ValueRefs<'a> {
+        fn visit_reference(&mut self, reference: &Reference) {
+            if let AnyEntity::Value(_) = reference.entity {
+                self.refs.push(reference.span.text(self.source));
+            }
+        }
+    }
+
+    #[test]
+    fn visit() {
+        let source = "function %f(i32) {
+                      ebb0(v0: i32):
+                          v1 = iadd_imm v0, 1
+                          v2 -> v1
+                          return v2
+                      }";
+        let tf = parse_test(source).unwrap();
+        let mut visitor = ValueRefs {
+            source,
+            refs: Vec::new(),
+        };
+        visitor.visit_function(&tf.functions[0].1.syntax);
+        assert_eq!(visitor.refs, ["v0", "v1", "v0", "v2", "v1", "v2"]);
+    }
+}
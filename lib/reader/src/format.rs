@@ -0,0 +1,563 @@
+//! Canonical formatting of .cton files.
+//!
+//! The formatter reprints the functions in a file with canonical spacing and indentation, so the
+//! text of a function doesn't depend on who wrote it. It works on the tokens and spans recorded by
+//! the parser rather than printing the parsed `Function`, so everything in the source survives,
+//! including comments, value locations, and the exact form of the preamble declarations.
+//!
+//! The text outside functions, like test commands and ISA specifications, is kept as is except
+//! for trailing whitespace and repeated blank lines.
+
+use ast::FunctionSyntax;
+use cretonne::entity::EntityRef;
+use cretonne::ir::entities::AnyEntity;
+use cretonne::ir::{Ebb, Value};
+use error::Result;
+use lexer::{Lexer, Token};
+use parser::parse_test;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::mem;
+
+/// Indentation of preamble declarations, instructions, and comments.
+const INDENT: &str = "    ";
+
+/// Column of the instruction text following a source location or an encoding.
+const ANNOTATED_COLUMN: usize = 24;
+
+/// Format the .cton source `text`.
+///
+/// Functions are printed with canonical spacing, their preamble declarations are sorted by entity
+/// kind and number, and comments stay with the entity they follow. Comments on the same line as
+/// an entity keep their column relative to the indentation when there is room for it.
+///
+/// If `renumber` is set, values and EBBs are renumbered in order of definition, and their names in
+/// comments are updated to match. Note that filecheck directives may refer to values created by
+/// the tested pass, and those are not renamed.
+pub fn format_source(text: &str, renumber: bool) -> Result<String> {
+    let test = parse_test(text)?;
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    let mut numbering = None;
+    for &(_, ref details) in &test.functions {
+        let span = details.syntax.span;
+        write_gap(&mut out, &text[pos..span.start], false, numbering.as_ref());
+        numbering = FuncFormatter::new(text, &details.syntax, renumber).write(&mut out);
+        pos = span.end;
+    }
+    write_gap(&mut out, &text[pos..], true, numbering.as_ref());
+    Ok(out)
+}
+
+/// Write the text between two functions, or before the first or after the last function.
+///
+/// When `out` ends with a function, its closing brace hasn't been terminated by a newline yet.
+/// The comments following a function belong to it, so they are renamed with its `numbering`.
+fn write_gap(out: &mut String, gap: &str, at_end: bool, numbering: Option<&Numbering>) {
+    let rename = |line: &str| numbering.map_or_else(|| line.to_string(), |n| n.rename_words(line));
+    let mut lines: Vec<&str> = gap.split('\n').map(str::trim_right).collect();
+    // The last line continues with the next function keyword.
+    if !at_end {
+        lines.pop();
+    }
+
+    // Separate functions from each other by a blank line.
+    let mut blank = false;
+    if !out.is_empty() {
+        let first = lines.remove(0).trim_left();
+        if !first.is_empty() {
+            out.push(' ');
+            out.push_str(&rename(first));
+        }
+        out.push('\n');
+        blank = true;
+    }
+
+    for line in lines {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !out.is_empty() {
+            out.push('\n');
+        }
+        blank = false;
+        out.push_str(&rename(line));
+        out.push('\n');
+    }
+    if blank && !at_end && !out.is_empty() {
+        out.push('\n');
+    }
+}
+
+/// A formatted line in a function.
+struct Line {
+    /// Source span of the entity on this line.
+    start: usize,
+    end: usize,
+    /// The formatted entity.
+    text: String,
+    /// Was the entity, or its leading comments, preceded by a blank line in the source?
+    blank_before: bool,
+    /// Comments on the lines before the entity.
+    leading: Vec<String>,
+    /// A comment on the same line, and the column where it appeared in the source.
+    trailing: Option<(usize, String)>,
+    /// Comments on the lines following the entity.
+    comments: Vec<String>,
+}
+
+impl Line {
+    fn new(span: (usize, usize), text: String) -> Line {
+        Line {
+            start: span.0,
+            end: span.1,
+            text,
+            blank_before: false,
+            leading: Vec::new(),
+            trailing: None,
+            comments: Vec::new(),
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        for comment in &self.leading {
+            out.push_str(INDENT);
+            out.push_str(comment);
+            out.push('\n');
+        }
+        out.push_str(&self.text);
+        if let Some((column, ref comment)) = self.trailing {
+            let len = self.text.chars().count();
+            let pad = if column > len { column - len } else { 1 };
+            for _ in 0..pad {
+                out.push(' ');
+            }
+            out.push_str(comment);
+        }
+        out.push('\n');
+        for comment in &self.comments {
+            out.push_str(INDENT);
+            out.push_str(comment);
+            out.push('\n');
+        }
+    }
+}
+
+/// Formatter for a single function.
+struct FuncFormatter<'a> {
+    source: &'a str,
+    syntax: &'a FunctionSyntax,
+    /// New numbers of values and EBBs, if they are being renumbered.
+    numbering: Option<Numbering>,
+}
+
+impl<'a> FuncFormatter<'a> {
+    fn new(source: &'a str, syntax: &'a FunctionSyntax, renumber: bool) -> FuncFormatter<'a> {
+        FuncFormatter {
+            source,
+            syntax,
+            numbering: if renumber {
+                Some(Numbering::new(syntax))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Write the formatted function to `out`, without a newline after the closing brace.
+    ///
+    /// Returns the new numbering of the values and EBBs, if they were renumbered.
+    fn write(mut self, out: &mut String) -> Option<Numbering> {
+        let span = self.syntax.span;
+
+        // The function header extends to the opening brace.
+        let mut header_end = span.end;
+        let mut lexer = Lexer::new(&self.source[span.start..span.end]);
+        while let Some(Ok(tok)) = lexer.next() {
+            if tok.token == Token::LBrace {
+                header_end = span.start + lexer.span().end;
+                break;
+            }
+        }
+        let mut header = Line::new((span.start, header_end), String::new());
+        self.write_tokens(&mut header.text, span.start, header_end);
+
+        let mut decls = Vec::new();
+        for decl in &self.syntax.decls {
+            let mut text = INDENT.to_string();
+            self.write_tokens(&mut text, decl.span.start, decl.span.end);
+            let line = Line::new((decl.span.start, decl.span.end), text);
+            decls.push((decl_key(decl.entity.entity), line));
+        }
+
+        let mut ebbs = Vec::new();
+        for ebb in &self.syntax.ebbs {
+            let mut lines = Vec::new();
+            let mut text = String::new();
+            self.write_tokens(&mut text, ebb.header.start, ebb.header.end);
+            lines.push(Line::new((ebb.header.start, ebb.header.end), text));
+
+            for inst in &ebb.insts {
+                // The source location and encoding come before the results and the opcode.
+                let mut prefix_end = inst.span.start;
+                if let Some(opcode) = inst.opcode {
+                    prefix_end = inst.results.first().map_or(opcode.start, |r| r.span.start);
+                }
+                let mut text = INDENT.to_string();
+                if prefix_end > inst.span.start {
+                    self.write_tokens(&mut text, inst.span.start, prefix_end);
+                    let len = text.chars().count();
+                    let pad = if ANNOTATED_COLUMN > len {
+                        ANNOTATED_COLUMN - len
+                    } else {
+                        1
+                    };
+                    for _ in 0..pad {
+                        text.push(' ');
+                    }
+                }
+                self.write_tokens(&mut text, prefix_end, inst.span.end);
+                lines.push(Line::new((inst.span.start, inst.span.end), text));
+            }
+            ebbs.push(lines);
+        }
+
+        self.attach_comments(&mut header, &mut decls, &mut ebbs);
+
+        // Sort the declarations in the order used by the IL writer.
+        decls.sort_by_key(|&(key, _)| key);
+
+        // Keep single blank lines separating groups of declarations and instructions, and put a
+        // blank line before every EBB like the IL writer.
+        header.write(out);
+        for (idx, &(_, ref line)) in decls.iter().enumerate() {
+            if idx > 0 && line.blank_before {
+                out.push('\n');
+            }
+            line.write(out);
+        }
+        let mut any = !decls.is_empty();
+        for lines in &ebbs {
+            for (idx, line) in lines.iter().enumerate() {
+                if (idx == 0 && any) || (idx > 0 && line.blank_before) {
+                    out.push('\n');
+                }
+                line.write(out);
+            }
+            any = true;
+        }
+        out.push('}');
+        self.numbering
+    }
+
+    /// Attach the comments in the function to the lines, and record blank lines.
+    ///
+    /// Comments normally belong to the preceding entity. Comments separated from the preceding
+    /// entity by a blank line belong to the following entity instead, so they stay with it when
+    /// the declarations are sorted.
+    fn attach_comments(
+        &mut self,
+        header: &mut Line,
+        decls: &mut [(DeclKey, Line)],
+        ebbs: &mut [Vec<Line>],
+    ) {
+        let span = self.syntax.span;
+        let mut comments = Vec::new();
+        let mut lexer = Lexer::new(&self.source[span.start..span.end]);
+        while let Some(Ok(tok)) = lexer.next() {
+            if let Token::Comment(text) = tok.token {
+                let start = span.start + lexer.span().start;
+                comments.push((start, start + text.len(), text.trim_right()));
+            }
+        }
+
+        // All the lines in source order.
+        let mut lines: Vec<&mut Line> = Some(header)
+            .into_iter()
+            .chain(decls.iter_mut().map(|&mut (_, ref mut line)| line))
+            .chain(ebbs.iter_mut().flat_map(|lines| lines.iter_mut()))
+            .collect();
+
+        let mut comments = comments.into_iter().peekable();
+        let mut leading = Vec::new();
+        let mut leading_blank = false;
+        for idx in 0..lines.len() {
+            let mut prev_end = lines[idx].end;
+            let next_start = lines.get(idx + 1).map_or(span.end, |line| line.start);
+            while let Some(&(start, end, text)) = comments.peek() {
+                if start > next_start {
+                    break;
+                }
+                comments.next();
+                let text = self.rename_comment(text);
+                // A comment inside a multi-line entity is treated like a trailing comment.
+                let between = &self.source[prev_end.min(start)..start];
+                let blank = between.matches('\n').count() > 1;
+                if prev_end == lines[idx].end && !between.contains('\n') {
+                    // Keep the column of the comment relative to the indentation of the line.
+                    let line_start = self.source[..start].rfind('\n').map_or(0, |nl| nl + 1);
+                    let line = &self.source[line_start..start];
+                    let indent = line.len() - line.trim_left().len();
+                    let new_indent = lines[idx].text.len() - lines[idx].text.trim_left().len();
+                    let column = line.chars().count() - indent + new_indent;
+                    lines[idx].trailing = Some((column, text));
+                } else if (blank || !leading.is_empty()) && idx + 1 < lines.len() {
+                    if leading.is_empty() {
+                        leading_blank = blank;
+                    }
+                    leading.push(text);
+                } else {
+                    lines[idx].comments.push(text);
+                }
+                prev_end = end;
+            }
+
+            if let Some(next) = lines.get_mut(idx + 1) {
+                if leading.is_empty() {
+                    let between = &self.source[prev_end..next.start];
+                    next.blank_before = between.matches('\n').count() > 1;
+                } else {
+                    next.blank_before = leading_blank;
+                    next.leading = mem::replace(&mut leading, Vec::new());
+                }
+            }
+        }
+    }
+
+    /// Write the tokens in `source[start..end]` to `out` with canonical spacing.
+    fn write_tokens(&mut self, out: &mut String, start: usize, end: usize) {
+        let text = &self.source[start..end];
+        let mut lexer = Lexer::new(text);
+        let mut prev: Option<(Token, usize)> = None;
+        let mut brackets = 0;
+        while let Some(Ok(tok)) = lexer.next() {
+            let span = lexer.span();
+            let token = tok.token;
+            if let Token::Comment(_) = token {
+                // Comments are attached to lines separately.
+                continue;
+            }
+            if let Some((prev, prev_end)) = prev {
+                if space_between(prev, token, span.start > prev_end, brackets > 0) {
+                    out.push(' ');
+                }
+            }
+            match token {
+                Token::LBracket => brackets += 1,
+                Token::RBracket if brackets > 0 => brackets -= 1,
+                _ => {}
+            }
+            match (token, self.numbering.as_mut()) {
+                (Token::Value(v), Some(n)) => write!(out, "{}", n.value(v)).unwrap(),
+                (Token::Ebb(ebb), Some(n)) => write!(out, "{}", n.ebb(ebb)).unwrap(),
+                _ => out.push_str(span.text(text)),
+            }
+            prev = Some((token, span.end));
+        }
+    }
+
+    /// Update the value and EBB names in a comment to the new numbering.
+    fn rename_comment(&self, text: &str) -> String {
+        match self.numbering {
+            Some(ref numbering) => numbering.rename_words(text),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// Should there be a space between the tokens `prev` and `next`?
+///
+/// The `had_space` flag indicates whitespace between the tokens in the source. It is used when
+/// the tokens themselves don't decide, like for the offset in `vmctx+16` versus `offset 16`.
+fn space_between(prev: Token, next: Token, had_space: bool, in_brackets: bool) -> bool {
+    match (prev, next) {
+        (_, Token::LBrace) |
+        (Token::SourceLoc(_), _) |
+        (Token::Equal, _) |
+        (_, Token::Equal) |
+        (Token::Arrow, _) |
+        (_, Token::Arrow) => true,
+        (_, Token::Comma) |
+        (_, Token::LPar) |
+        (_, Token::RPar) |
+        (_, Token::RBracket) |
+        (_, Token::Colon) |
+        (_, Token::Dot) |
+        (Token::LPar, _) |
+        (Token::LBracket, _) |
+        (Token::Dot, _) => false,
+        (Token::Comma, _) => !in_brackets,
+        // Keep address offsets like `v1+8` with their base.
+        (Token::Value(_), Token::Integer(imm)) if imm.starts_with(|c| c == '+' || c == '-') => {
+            false
+        }
+        // Keep `u1:2` external names together.
+        (Token::Colon, Token::Integer(_)) => had_space,
+        (Token::Colon, _) => true,
+        _ => had_space,
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Sort key for preamble declarations.
+type DeclKey = (u8, usize);
+
+fn decl_key(entity: AnyEntity) -> DeclKey {
+    match entity {
+        AnyEntity::StackSlot(ss) => (0, ss.index()),
+        AnyEntity::GlobalVar(gv) => (1, gv.index()),
+        AnyEntity::Heap(heap) => (2, heap.index()),
+        AnyEntity::SigRef(sig) => (3, sig.index()),
+        AnyEntity::FuncRef(fnref) => (4, fnref.index()),
+        AnyEntity::JumpTable(jt) => (5, jt.index()),
+        AnyEntity::Constant(c) => (6, c.index()),
+        _ => (7, 0),
+    }
+}
+
+/// New numbers for the values and EBBs in a function.
+struct Numbering {
+    values: HashMap<Value, Value>,
+    ebbs: HashMap<Ebb, Ebb>,
+}
+
+impl Numbering {
+    /// Number the EBBs in layout order, and the values in order of definition.
+    fn new(syntax: &FunctionSyntax) -> Numbering {
+        let mut numbering = Numbering {
+            values: HashMap::new(),
+            ebbs: HashMap::new(),
+        };
+        for ebb in &syntax.ebbs {
+            numbering.ebb(ebb.ebb);
+            for reference in ebb.params.iter().chain(
+                ebb.insts.iter().flat_map(|inst| &inst.results),
+            )
+            {
+                if let AnyEntity::Value(v) = reference.entity {
+                    numbering.value(v);
+                }
+            }
+        }
+        numbering
+    }
+
+    /// Get the new number of `v`.
+    ///
+    /// Values that are never defined are numbered after all the others.
+    fn value(&mut self, v: Value) -> Value {
+        let next = Value::new(self.values.len());
+        *self.values.entry(v).or_insert(next)
+    }
+
+    /// Get the new number of `ebb`.
+    fn ebb(&mut self, ebb: Ebb) -> Ebb {
+        let next = Ebb::new(self.ebbs.len());
+        *self.ebbs.entry(ebb).or_insert(next)
+    }
+
+    /// Get the new name of the value or EBB named `word`, if there is one.
+    fn rename(&self, word: &str) -> Option<String> {
+        if word.starts_with("ebb") {
+            let ebb = Ebb::with_number(number(&word[3..])?)?;
+            self.ebbs.get(&ebb).map(ToString::to_string)
+        } else if word.starts_with('v') {
+            let v = Value::with_number(number(&word[1..])?)?;
+            self.values.get(&v).map(ToString::to_string)
+        } else {
+            None
+        }
+    }
+
+    /// Rename the values and EBBs named in `text`.
+    fn rename_words(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(is_word_char) {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+            let word = &rest[..len];
+            match self.rename(word) {
+                Some(name) => out.push_str(&name),
+                None => out.push_str(word),
+            }
+            rest = &rest[len..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Parse the number in an entity name, rejecting leading zeros like the lexer.
+fn number(digits: &str) -> Option<u32> {
+    if digits.len() > 1 && digits.starts_with('0') {
+        return None;
+    }
+    if !digits.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spacing() {
+        let text = "test verify   \n\n\n; Comment\nfunction %f(i32,i32)->i32 {\n\
+                    fn0 = function %g ( i32 )\n  ss1=explicit_slot 8 ; slot\n\
+                    ebb0 ( v7 : i32 , v3:i32 ) :\n\
+                    @0001[-] v5 = iadd_imm.i32 v7 , -1\n\
+                    ; check: v5\n\
+                    v1=load.i32 notrap v3 +8\n\
+                    return v1 }\n; After\n";
+        assert_eq!(
+            format_source(text, false).unwrap(),
+            "test verify\n\n; Comment\nfunction %f(i32, i32) -> i32 {\n\
+             \x20   ss1 = explicit_slot 8 ; slot\n\
+             \x20   fn0 = function %g(i32)\n\n\
+             ebb0(v7: i32, v3: i32):\n\
+             \x20   @0001 [-]           v5 = iadd_imm.i32 v7, -1\n\
+             \x20   ; check: v5\n\
+             \x20   v1 = load.i32 notrap v3+8\n\
+             \x20   return v1\n}\n\n; After\n"
+        );
+    }
+
+    #[test]
+    fn renumber() {
+        let text = "function %f(i32) {
+                    ebb3(v7: i32):
+                        v2 = iadd_imm v7, 1 ; v2 is ebb3's sum
+                        jump ebb1(v2)
+                    ebb1(v9: i32):
+                        v10 -> v9
+                        return v10
+                    }";
+        assert_eq!(
+            format_source(text, true).unwrap(),
+            "function %f(i32) {
+ebb0(v0: i32):
+    v1 = iadd_imm v0, 1 ; v1 is ebb0's sum
+    jump ebb1(v1)
+
+ebb1(v2: i32):
+    v3 -> v2
+    return v3
+}
+"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let text = "function %f() {\nebb0:\n    trap user0 ; trailing\n}\n";
+        assert_eq!(format_source(text, false).unwrap(), text);
+    }
+}
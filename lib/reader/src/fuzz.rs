@@ -0,0 +1,106 @@
+//! Fuzzing entry points for the reader and the code generator.
+//!
+//! These functions accept arbitrary bytes from a fuzzer and report all failures, including
+//! panics, as a `FuzzError`. A harness should treat `FuzzError::Panic` as a bug.
+//!
+//! This module is only available with the `fuzz` feature.
+
+use cretonne::fuzz::{catch_panic, FuzzError};
+use cretonne::isa::{self, TargetIsa};
+use cretonne::settings::Flags;
+use cretonne::{verify_function, Context};
+use isaspec::IsaSpec;
+use parser::parse_test;
+use std::str;
+use std::vec::Vec;
+use testfile::TestFile;
+
+/// Target ISAs used to compile inputs that don't have any `isa` commands.
+const DEFAULT_ISAS: [&str; 2] = ["intel", "riscv"];
+
+/// Parse `data` as a .cton file.
+pub fn fuzz_parse(data: &[u8]) -> Result<(), FuzzError> {
+    catch_panic(|| parse(data).map(|_| ()))
+}
+
+/// Parse `data` as a .cton file and compile all of its functions.
+///
+/// The functions are compiled for each of the ISAs named by `isa` commands in the file. If there
+/// are none, the functions are compiled for all the default ISAs that are built, using the flags
+/// from the file's `set` commands.
+pub fn fuzz_compile(data: &[u8]) -> Result<(), FuzzError> {
+    catch_panic(|| {
+        let testfile = parse(data)?;
+        let isas = match testfile.isa_spec {
+            IsaSpec::Some(isas) => isas,
+            IsaSpec::None(flags) => {
+                let isas = default_isas(&flags);
+                if isas.is_empty() {
+                    // No default ISAs are built, so all we can do is verify the functions.
+                    for (func, _) in &testfile.functions {
+                        verify_function(func, &flags).map_err(|e| {
+                            FuzzError::Verifier(e.to_string())
+                        })?;
+                    }
+                }
+                isas
+            }
+        };
+        for (func, _) in testfile.functions {
+            for isa in &isas {
+                let mut ctx = Context::for_function(func.clone());
+                ctx.compile(&**isa)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Parse `data` as a .cton file, reporting errors as `FuzzError::Parse`.
+fn parse(data: &[u8]) -> Result<TestFile, FuzzError> {
+    let text = str::from_utf8(data).map_err(|e| FuzzError::Parse(e.to_string()))?;
+    parse_test(text).map_err(|e| FuzzError::Parse(e.to_string()))
+}
+
+/// Build the default ISAs that are available with `flags`.
+fn default_isas(flags: &Flags) -> Vec<Box<TargetIsa>> {
+    DEFAULT_ISAS
+        .iter()
+        .filter_map(|name| isa::lookup(name).ok())
+        .map(|builder| builder.finish(flags.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(fuzz_parse(b"function %f() {\nebb0:\n    return\n}"), Ok(()));
+        assert!(match fuzz_parse(b"function %f(") {
+            Err(FuzzError::Parse(_)) => true,
+            _ => false,
+        });
+        assert!(match fuzz_parse(b"\xff") {
+            Err(FuzzError::Parse(_)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn compile() {
+        let text = "function %f(i32) -> i32 {\nebb0(v0: i32):\n    v1 = iadd_imm v0, 1\n    \
+                    return v1\n}";
+        assert_eq!(fuzz_compile(text.as_bytes()), Ok(()));
+        let text = "isa riscv\nfunction %f(i32) -> i32 {\nebb0(v0: i32):\n    return v0\n}";
+        assert_eq!(fuzz_compile(text.as_bytes()), Ok(()));
+
+        // The return value has the wrong type.
+        let text = "function %f(i32) -> i64 {\nebb0(v0: i32):\n    return v0\n}";
+        assert!(match fuzz_compile(text.as_bytes()) {
+            Err(FuzzError::Verifier(_)) => true,
+            _ => false,
+        });
+    }
+}
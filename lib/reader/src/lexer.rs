@@ -7,6 +7,7 @@ use std::ascii::AsciiExt;
 use cretonne::ir::types;
 use cretonne::ir::{Value, Ebb};
 use error::Location;
+use ast::Span;
 
 /// A Token returned from the `Lexer`.
 ///
@@ -36,6 +37,7 @@ pub enum Token<'a> {
     GlobalVar(u32), // gv3
     Heap(u32), // heap2
     JumpTable(u32), // jt2
+    Constant(u32), // const2
     FuncRef(u32), // fn2
     SigRef(u32), // sig2
     UserRef(u32), // u345
@@ -121,6 +123,12 @@ pub struct Lexer<'a> {
     // Index into `source` of lookahead character.
     pos: usize,
 
+    // Index into `source` of the first character of the last token.
+    token_start: usize,
+
+    // Location of the first character of the last token.
+    token_loc: Location,
+
     // Current line number.
     line_number: usize,
 }
@@ -132,6 +140,8 @@ impl<'a> Lexer<'a> {
             chars: s.char_indices(),
             lookahead: None,
             pos: 0,
+            token_start: 0,
+            token_loc: Location::default(),
             line_number: 1,
         };
         // Advance to the first char.
@@ -319,6 +329,7 @@ impl<'a> Lexer<'a> {
             "gv" => Some(Token::GlobalVar(number)),
             "heap" => Some(Token::Heap(number)),
             "jt" => Some(Token::JumpTable(number)),
+            "const" => Some(Token::Constant(number)),
             "fn" => Some(Token::FuncRef(number)),
             "sig" => Some(Token::SigRef(number)),
             "u" => Some(Token::UserRef(number)),
@@ -339,6 +350,7 @@ impl<'a> Lexer<'a> {
             "i16" => types::I16,
             "i32" => types::I32,
             "i64" => types::I64,
+            "i128" => types::I128,
             "f32" => types::F32,
             "f64" => types::F64,
             "b1" => types::B1,
@@ -346,6 +358,7 @@ impl<'a> Lexer<'a> {
             "b16" => types::B16,
             "b32" => types::B32,
             "b64" => types::B64,
+            "b128" => types::B128,
             _ => return None,
         };
         if is_vector {
@@ -407,12 +420,25 @@ impl<'a> Lexer<'a> {
         token(Token::SourceLoc(&self.source[begin..end]), loc)
     }
 
+    /// Get the span of the last token returned by `next()`.
+    ///
+    /// This must be called before any other method that advances the lexer.
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.token_start,
+            end: self.pos,
+            location: self.token_loc,
+        }
+    }
+
     /// Get the next token or a lexical error.
     ///
     /// Return None when the end of the source is encountered.
     pub fn next(&mut self) -> Option<Result<LocatedToken<'a>, LocatedError>> {
         loop {
             let loc = self.loc();
+            self.token_start = self.pos;
+            self.token_loc = loc;
             return match self.lookahead {
                 None => None,
                 Some(';') => Some(self.scan_comment()),
@@ -2,6 +2,10 @@
 //!
 //! The `cton_reader` library supports reading .cton files. This functionality is needed for testing
 //! Cretonne, but is not essential for a JIT compiler.
+//!
+//! Besides the parsed functions, the reader provides a syntax tree with the source spans of each
+//! function, see the `ast` module. This can be used to build tools working on .cton text, like the
+//! canonical formatter in `format_source`.
 
 #![deny(missing_docs,
         trivial_numeric_casts,
@@ -11,12 +15,19 @@ extern crate cretonne;
 
 pub use error::{Location, Result, Error};
 pub use parser::{parse_functions, parse_test};
+pub use format::format_source;
 pub use testcommand::{TestCommand, TestOption};
 pub use testfile::{TestFile, Details, Comment};
 pub use isaspec::{IsaSpec, parse_options};
 pub use sourcemap::SourceMap;
 
+pub mod ast;
+
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+
 mod error;
+mod format;
 mod lexer;
 mod parser;
 mod testcommand;
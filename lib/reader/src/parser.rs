@@ -3,11 +3,12 @@
 use std::str::FromStr;
 use std::{u16, u32};
 use std::mem;
-use cretonne::ir::{Function, Ebb, Opcode, Value, Type, ExternalName, CallConv, StackSlotData,
+use cretonne::ir::{Function, Ebb, Inst, Opcode, Value, Type, ExternalName, CallConv,
+                   StackSlotData,
                    StackSlotKind, JumpTable, JumpTableData, Signature, AbiParam,
                    ArgumentExtension, ExtFuncData, SigRef, FuncRef, StackSlot, ValueLoc,
                    ArgumentLoc, MemFlags, GlobalVar, GlobalVarData, Heap, HeapData, HeapStyle,
-                   HeapBase};
+                   HeapBase, Constant, ConstantData};
 use cretonne::ir;
 use cretonne::ir::types::VOID;
 use cretonne::ir::immediates::{Imm64, Uimm32, Offset32, Ieee32, Ieee64};
@@ -18,6 +19,7 @@ use cretonne::{settings, timing};
 use cretonne::entity::EntityRef;
 use cretonne::packed_option::ReservedValue;
 use testfile::{TestFile, Details, Comment};
+use ast::{Span, Reference, FunctionSyntax, DeclSyntax, EbbSyntax, InstSyntax, CommentSyntax};
 use error::{Location, Error, Result};
 use lexer::{self, Lexer, Token};
 use testcommand::TestCommand;
@@ -49,7 +51,7 @@ pub fn parse_test(text: &str) -> Result<TestFile> {
     parser.token();
     parser.claim_gathered_comments(AnyEntity::Function);
 
-    let preamble_comments = parser.take_comments();
+    let (preamble_comments, _) = parser.take_comments();
     let functions = parser.parse_function_list(isa_spec.unique_isa())?;
 
     Ok(TestFile {
@@ -71,20 +73,33 @@ pub struct Parser<'a> {
     // Location of lookahead.
     loc: Location,
 
+    // Span of lookahead.
+    span: Span,
+
+    // End of the last consumed token.
+    consumed_end: usize,
+
+    // Entities referenced by the tokens consumed since `start_span`.
+    references: Vec<Reference>,
+
     // Are we gathering any comments that we encounter?
     gathering_comments: bool,
 
     // The gathered comments; claim them with `claim_gathered_comments`.
-    gathered_comments: Vec<&'a str>,
+    gathered_comments: Vec<(&'a str, Span)>,
 
     // Comments collected so far.
     comments: Vec<Comment<'a>>,
+
+    // Spans of `comments`.
+    comment_syntax: Vec<CommentSyntax>,
 }
 
 // Context for resolving references when parsing a single function.
 struct Context<'a> {
     function: Function,
     map: SourceMap,
+    syntax: FunctionSyntax,
 
     // Reference to the unique_isa for things like parsing ISA-specific instruction encoding
     // information. This is only `Some` if exactly one set of `isa` directives were found in the
@@ -98,6 +113,7 @@ impl<'a> Context<'a> {
         Context {
             function: f,
             map: SourceMap::new(),
+            syntax: FunctionSyntax::default(),
             unique_isa,
         }
     }
@@ -141,6 +157,8 @@ impl<'a> Context<'a> {
         while self.function.global_vars.next_key().index() <= gv.index() {
             self.function.create_global_var(GlobalVarData::Sym {
                 name: ExternalName::testcase(""),
+                colocated: false,
+                readonly: false,
             });
         }
         self.function.global_vars[gv] = data;
@@ -164,6 +182,7 @@ impl<'a> Context<'a> {
                 min_size: Imm64::new(0),
                 guard_size: Imm64::new(0),
                 style: HeapStyle::Static { bound: Imm64::new(0) },
+                readonly: false,
             });
         }
         self.function.heaps[heap] = data;
@@ -205,6 +224,7 @@ impl<'a> Context<'a> {
             self.function.import_function(ExtFuncData {
                 name: ExternalName::testcase(""),
                 signature: SigRef::reserved_value(),
+                colocated: false,
             });
         }
         self.function.dfg.ext_funcs[fn_] = data;
@@ -238,6 +258,24 @@ impl<'a> Context<'a> {
         }
     }
 
+    // Allocate a constant pool entry.
+    fn add_constant(&mut self, c: Constant, data: ConstantData, loc: &Location) -> Result<()> {
+        while self.function.constants.next_key().index() <= c.index() {
+            self.function.constants.push(ConstantData::from_bits(0, 1));
+        }
+        self.function.constants[c] = data;
+        self.map.def_constant(c, loc)
+    }
+
+    // Resolve a reference to a constant.
+    fn check_constant(&self, c: Constant, loc: &Location) -> Result<()> {
+        if !self.map.contains_constant(c) {
+            err!(loc, "undefined constant {}", c)
+        } else {
+            Ok(())
+        }
+    }
+
     // Allocate a new EBB.
     fn add_ebb(&mut self, ebb: Ebb, loc: &Location) -> Result<Ebb> {
         while self.function.dfg.num_ebbs() <= ebb.index() {
@@ -256,15 +294,61 @@ impl<'a> Parser<'a> {
             lex_error: None,
             lookahead: None,
             loc: Location { line_number: 0 },
+            span: Span::default(),
+            consumed_end: 0,
+            references: Vec::new(),
             gathering_comments: false,
             gathered_comments: Vec::new(),
             comments: Vec::new(),
+            comment_syntax: Vec::new(),
         }
     }
 
     // Consume the current lookahead token and return it.
     fn consume(&mut self) -> Token<'a> {
-        self.lookahead.take().expect("No token to consume")
+        let token = self.lookahead.take().expect("No token to consume");
+        self.consumed_end = self.span.end;
+        let entity: Option<AnyEntity> = match token {
+            Token::Value(v) => Some(v.into()),
+            Token::Ebb(ebb) => Some(ebb.into()),
+            Token::StackSlot(n) => StackSlot::with_number(n).map(Into::into),
+            Token::GlobalVar(n) => GlobalVar::with_number(n).map(Into::into),
+            Token::Heap(n) => Heap::with_number(n).map(Into::into),
+            Token::JumpTable(n) => JumpTable::with_number(n).map(Into::into),
+            Token::FuncRef(n) => FuncRef::with_number(n).map(Into::into),
+            Token::SigRef(n) => SigRef::with_number(n).map(Into::into),
+            Token::Constant(n) => Constant::with_number(n).map(Into::into),
+            _ => None,
+        };
+        if let Some(entity) = entity {
+            self.references.push(Reference {
+                entity,
+                span: self.span,
+            });
+        }
+        token
+    }
+
+    // Get the span of the lookahead token, and start collecting the entity references consumed
+    // from there.
+    fn start_span(&mut self) -> Span {
+        self.token();
+        self.references.clear();
+        self.span
+    }
+
+    // Get the span from `start` to the end of the last consumed token.
+    fn end_span(&self, start: Span) -> Span {
+        Span {
+            start: start.start,
+            end: self.consumed_end,
+            location: start.location,
+        }
+    }
+
+    // Take the entity references consumed so far.
+    fn take_references(&mut self) -> Vec<Reference> {
+        mem::replace(&mut self.references, Vec::new())
     }
 
     // Consume the whole line following the current lookahead token.
@@ -287,10 +371,13 @@ impl<'a> Parser<'a> {
                     match token {
                         Token::Comment(text) => {
                             if self.gathering_comments {
-                                self.gathered_comments.push(text);
+                                self.gathered_comments.push((text, self.lex.span()));
                             }
                         }
-                        _ => self.lookahead = Some(token),
+                        _ => {
+                            self.lookahead = Some(token);
+                            self.span = self.lex.span();
+                        }
                     }
                     self.loc = location;
                 }
@@ -317,18 +404,20 @@ impl<'a> Parser<'a> {
     fn claim_gathered_comments<E: Into<AnyEntity>>(&mut self, entity: E) {
         debug_assert!(self.gathering_comments);
         let entity = entity.into();
-        self.comments.extend(
-            self.gathered_comments.drain(..).map(|text| {
-                Comment { entity, text }
-            }),
-        );
+        for (text, span) in self.gathered_comments.drain(..) {
+            self.comments.push(Comment { entity, text });
+            self.comment_syntax.push(CommentSyntax { entity, span });
+        }
         self.gathering_comments = false;
     }
 
-    // Get the comments collected so far, clearing out the internal list.
-    fn take_comments(&mut self) -> Vec<Comment<'a>> {
+    // Get the comments collected so far and their spans, clearing out the internal lists.
+    fn take_comments(&mut self) -> (Vec<Comment<'a>>, Vec<CommentSyntax>) {
         debug_assert!(!self.gathering_comments);
-        mem::replace(&mut self.comments, Vec::new())
+        (
+            mem::replace(&mut self.comments, Vec::new()),
+            mem::replace(&mut self.comment_syntax, Vec::new()),
+        )
     }
 
     // Match and consume a token without payload.
@@ -425,6 +514,17 @@ impl<'a> Parser<'a> {
         err!(self.loc, err_msg)
     }
 
+    // Match and consume a constant reference.
+    fn match_constant(&mut self, err_msg: &str) -> Result<Constant> {
+        if let Some(Token::Constant(c)) = self.token() {
+            self.consume();
+            if let Some(c) = Constant::with_number(c) {
+                return Ok(c);
+            }
+        }
+        err!(self.loc, err_msg)
+    }
+
     // Match and consume a jump table reference.
     fn match_jt(&mut self) -> Result<JumpTable> {
         if let Some(Token::JumpTable(jt)) = self.token() {
@@ -765,12 +865,13 @@ impl<'a> Parser<'a> {
     ) -> Result<(Function, Details<'a>)> {
         // Begin gathering comments.
         // Make sure we don't include any comments before the `function` keyword.
-        self.token();
+        let start = self.start_span();
         debug_assert!(self.comments.is_empty());
         self.start_gathering_comments();
 
-        let (location, name, sig) = self.parse_function_spec(unique_isa)?;
+        let (location, name, name_span, sig) = self.parse_function_spec(unique_isa)?;
         let mut ctx = Context::new(Function::with_name_signature(name, sig), unique_isa);
+        ctx.syntax.name = name_span;
 
         // function ::= function-spec * "{" preamble function-body "}"
         self.match_token(
@@ -790,21 +891,49 @@ impl<'a> Parser<'a> {
             Token::RBrace,
             "expected '}' after function body",
         )?;
+        ctx.syntax.span = self.end_span(start);
 
         // Collect any comments following the end of the function, then stop gathering comments.
         self.start_gathering_comments();
         self.token();
         self.claim_gathered_comments(AnyEntity::Function);
+        self.parse_annotations(&mut ctx, &location)?;
 
+        let (comments, comment_syntax) = self.take_comments();
+        ctx.syntax.comments = comment_syntax;
         let details = Details {
             location,
-            comments: self.take_comments(),
+            comments,
             map: ctx.map,
+            syntax: ctx.syntax,
         };
 
         Ok((ctx.function, details))
     }
 
+    // Attach the annotations written in the comments of the function to their entities.
+    //
+    // annotations ::= ";" * { "@" name } ...
+    //
+    fn parse_annotations(&self, ctx: &mut Context, location: &Location) -> Result<()> {
+        for comment in &self.comments {
+            let list = match ir::parse_annotations(comment.text) {
+                Some(list) => list,
+                None => return err!(location, "invalid annotation in comment '{}'", comment.text),
+            };
+            let annotations = &mut ctx.function.annotations;
+            for annotation in list {
+                match comment.entity {
+                    AnyEntity::Function => annotations.annotate_function(annotation),
+                    AnyEntity::Ebb(ebb) => annotations.annotate_ebb(ebb, annotation),
+                    AnyEntity::Inst(inst) => annotations.annotate_inst(inst, annotation),
+                    _ => return err!(location, "{} can't be annotated", comment.entity),
+                }
+            }
+        }
+        Ok(())
+    }
+
     // Parse a function spec.
     //
     // function-spec ::= * "function" name signature
@@ -812,17 +941,20 @@ impl<'a> Parser<'a> {
     fn parse_function_spec(
         &mut self,
         unique_isa: Option<&TargetIsa>,
-    ) -> Result<(Location, ExternalName, Signature)> {
+    ) -> Result<(Location, ExternalName, Span, Signature)> {
         self.match_identifier("function", "expected 'function'")?;
         let location = self.loc;
 
         // function-spec ::= "function" * name signature
+        self.token();
+        let name_start = self.span;
         let name = self.parse_external_name()?;
+        let name_span = self.end_span(name_start);
 
         // function-spec ::= "function" name * signature
         let sig = self.parse_signature(unique_isa)?;
 
-        Ok((location, name, sig))
+        Ok((location, name, name_span, sig))
     }
 
     // Parse an external name.
@@ -874,9 +1006,27 @@ impl<'a> Parser<'a> {
             Token::LPar,
             "expected function signature: ( args... )",
         )?;
-        // signature ::=  "(" * [abi-param-list] ")" ["->" retlist] [callconv]
+        // signature ::=  "(" * [param-list] ")" ["->" retlist] [callconv]
+        // param-list ::= param-or-marker { "," param-or-marker }
+        // param-or-marker ::= abi-param | "..."
         if self.token() != Some(Token::RPar) {
-            sig.params = self.parse_abi_param_list(unique_isa)?;
+            loop {
+                if self.token() == Some(Token::Dot) {
+                    // The `...` marker follows the fixed parameters of a variadic function.
+                    if sig.fixed_params.is_some() {
+                        return err!(self.loc, "duplicate '...' in signature");
+                    }
+                    for _ in 0..3 {
+                        self.match_token(Token::Dot, "expected '...'")?;
+                    }
+                    sig.fixed_params = Some(sig.params.len());
+                } else {
+                    sig.params.push(self.parse_abi_param(unique_isa)?);
+                }
+                if !self.optional(Token::Comma) {
+                    break;
+                }
+            }
         }
         self.match_token(
             Token::RPar,
@@ -999,6 +1149,7 @@ impl<'a> Parser<'a> {
     // The parsed decls are added to `ctx` rather than returned.
     fn parse_preamble(&mut self, ctx: &mut Context) -> Result<()> {
         loop {
+            let start = self.start_span();
             match self.token() {
                 Some(Token::StackSlot(..)) => {
                     self.start_gathering_comments();
@@ -1039,9 +1190,41 @@ impl<'a> Parser<'a> {
                         ctx.add_jt(jt, dat, &self.loc)
                     })
                 }
+                Some(Token::Constant(..)) => {
+                    self.start_gathering_comments();
+                    self.parse_constant_decl().and_then(|(c, dat)| {
+                        ctx.add_constant(c, dat, &self.loc)
+                    })
+                }
+                Some(Token::Identifier("stack_limit")) => {
+                    self.start_gathering_comments();
+                    // The declaration doesn't declare an entity of its own.
+                    self.references.push(Reference {
+                        entity: AnyEntity::Function,
+                        span: self.span,
+                    });
+                    self.parse_stack_limit_decl(ctx)
+                }
+                Some(Token::Identifier("fuel")) => {
+                    self.start_gathering_comments();
+                    // The declaration doesn't declare an entity of its own.
+                    self.references.push(Reference {
+                        entity: AnyEntity::Function,
+                        span: self.span,
+                    });
+                    self.parse_fuel_decl(ctx)
+                }
                 // More to come..
                 _ => return Ok(()),
             }?;
+
+            let mut references = self.take_references();
+            let entity = references.remove(0);
+            ctx.syntax.decls.push(DeclSyntax {
+                entity,
+                span: self.end_span(start),
+                references,
+            });
         }
     }
 
@@ -1092,7 +1275,9 @@ impl<'a> Parser<'a> {
     // global-var-decl ::= * GlobalVar(gv) "=" global-var-desc
     // global-var-desc ::= "vmctx" offset32
     //                   | "deref" "(" GlobalVar(base) ")" offset32
-    //                   | "globalsym" name
+    //                   | "globalsym" ["colocated"] ["readonly"] name
+    //                   | "tls" tls-model name
+    // tls-model ::= "general_dynamic" | "initial_exec"
     //
     fn parse_global_var_decl(&mut self) -> Result<(GlobalVar, GlobalVarData)> {
         let gv = self.match_gv("expected global variable number: gv«n»")?;
@@ -1121,8 +1306,21 @@ impl<'a> Parser<'a> {
                 GlobalVarData::Deref { base, offset }
             }
             "globalsym" => {
+                let colocated = self.optional(Token::Identifier("colocated"));
+                let readonly = self.optional(Token::Identifier("readonly"));
+                let name = self.parse_external_name()?;
+                GlobalVarData::Sym {
+                    name,
+                    colocated,
+                    readonly,
+                }
+            }
+            "tls" => {
+                let model = self.match_any_identifier("expected TLS access model")?
+                    .parse()
+                    .map_err(|_| self.error("unknown TLS access model"))?;
                 let name = self.parse_external_name()?;
-                GlobalVarData::Sym { name }
+                GlobalVarData::TLS { name, model }
             }
             other => return err!(self.loc, "Unknown global variable kind '{}'", other),
         };
@@ -1175,6 +1373,7 @@ impl<'a> Parser<'a> {
             min_size: 0.into(),
             guard_size: 0.into(),
             style: HeapStyle::Static { bound: 0.into() },
+            readonly: false,
         };
 
         // heap-desc ::= heap-style heap-base * { "," heap-attr }
@@ -1197,6 +1396,9 @@ impl<'a> Parser<'a> {
                 "guard" => {
                     data.guard_size = self.match_imm64("expected integer guard size")?;
                 }
+                "readonly" => {
+                    data.readonly = true;
+                }
                 t => return err!(self.loc, "unknown heap attribute '{}'", t),
             }
         }
@@ -1234,8 +1436,8 @@ impl<'a> Parser<'a> {
     //
     // Two variants:
     //
-    // function-decl ::= FuncRef(fnref) "=" function-spec
-    //                   FuncRef(fnref) "=" SigRef(sig) name
+    // function-decl ::= FuncRef(fnref) "=" ["colocated"] function-spec
+    //                   FuncRef(fnref) "=" ["colocated"] SigRef(sig) name
     //
     // The first variant allocates a new signature reference. The second references an existing
     // signature which must be declared first.
@@ -1247,9 +1449,11 @@ impl<'a> Parser<'a> {
             "expected '=' in function decl",
         )?;
 
+        let colocated = self.optional(Token::Identifier("colocated"));
+
         let data = match self.token() {
             Some(Token::Identifier("function")) => {
-                let (loc, name, sig) = self.parse_function_spec(ctx.unique_isa)?;
+                let (loc, name, _, sig) = self.parse_function_spec(ctx.unique_isa)?;
                 let sigref = ctx.function.import_signature(sig);
                 ctx.map.def_entity(sigref.into(), &loc).expect(
                     "duplicate SigRef entities created",
@@ -1257,6 +1461,7 @@ impl<'a> Parser<'a> {
                 ExtFuncData {
                     name,
                     signature: sigref,
+                    colocated,
                 }
             }
             Some(Token::SigRef(sig_src)) => {
@@ -1272,6 +1477,7 @@ impl<'a> Parser<'a> {
                 ExtFuncData {
                     name,
                     signature: sig,
+                    colocated,
                 }
             }
             _ => return err!(self.loc, "expected 'function' or sig«n» in function decl"),
@@ -1284,6 +1490,49 @@ impl<'a> Parser<'a> {
         Ok((fn_, data))
     }
 
+    // Parse the stack limit decl.
+    //
+    // stack-limit-decl ::= * "stack_limit" "=" GlobalVar(gv)
+    fn parse_stack_limit_decl(&mut self, ctx: &mut Context) -> Result<()> {
+        let loc = self.loc;
+        self.consume();
+        self.match_token(
+            Token::Equal,
+            "expected '=' in stack limit declaration",
+        )?;
+        let gv = self.match_gv("expected global variable")?;
+        ctx.check_gv(gv, &self.loc)?;
+        if ctx.function.stack_limit.is_some() {
+            return err!(loc, "duplicate stack limit");
+        }
+        ctx.function.stack_limit = Some(gv);
+
+        // Collect any trailing comments.
+        self.token();
+        self.claim_gathered_comments(AnyEntity::Function);
+        Ok(())
+    }
+
+    // Parse the fuel counter decl.
+    //
+    // fuel-decl ::= * "fuel" "=" GlobalVar(gv)
+    fn parse_fuel_decl(&mut self, ctx: &mut Context) -> Result<()> {
+        let loc = self.loc;
+        self.consume();
+        self.match_token(Token::Equal, "expected '=' in fuel declaration")?;
+        let gv = self.match_gv("expected global variable")?;
+        ctx.check_gv(gv, &self.loc)?;
+        if ctx.function.fuel.is_some() {
+            return err!(loc, "duplicate fuel counter");
+        }
+        ctx.function.fuel = Some(gv);
+
+        // Collect any trailing comments.
+        self.token();
+        self.claim_gathered_comments(AnyEntity::Function);
+        Ok(())
+    }
+
     // Parse a jump table decl.
     //
     // jump-table-decl ::= * JumpTable(jt) "=" "jump_table" jt-entry {"," jt-entry}
@@ -1314,6 +1563,32 @@ impl<'a> Parser<'a> {
         err!(self.loc, "jump_table too long")
     }
 
+    // Parse a constant decl.
+    //
+    // constant-decl ::= * Constant(c) "=" Integer(hex)
+    fn parse_constant_decl(&mut self) -> Result<(Constant, ConstantData)> {
+        let c = self.match_constant("expected constant number: const«n»")?;
+        self.match_token(
+            Token::Equal,
+            "expected '=' in constant declaration",
+        )?;
+
+        // constant-decl ::= Constant(c) "=" * Integer(hex)
+        let data = match self.token() {
+            Some(Token::Integer(text)) => {
+                self.consume();
+                text.parse().map_err(|e| self.error(e))?
+            }
+            _ => return err!(self.loc, "expected hexadecimal constant"),
+        };
+
+        // Collect any trailing comments.
+        self.token();
+        self.claim_gathered_comments(c);
+
+        Ok((c, data))
+    }
+
     // jt-entry ::= * Ebb(dest) | "0"
     fn parse_jump_table_entry(&mut self) -> Result<Option<Ebb>> {
         match self.token() {
@@ -1347,23 +1622,37 @@ impl<'a> Parser<'a> {
     // Parse an extended basic block, add contents to `ctx`.
     //
     // extended-basic-block ::= * ebb-header { instruction }
-    // ebb-header           ::= Ebb(ebb) [ebb-params] ":"
+    // ebb-header           ::= Ebb(ebb) [ebb-params] ["cold"] ":"
     //
     fn parse_extended_basic_block(&mut self, ctx: &mut Context) -> Result<()> {
         // Collect comments for the next ebb.
         self.start_gathering_comments();
 
+        let start = self.start_span();
         let ebb_num = self.match_ebb("expected EBB header")?;
         let ebb = ctx.add_ebb(ebb_num, &self.loc)?;
 
-        if !self.optional(Token::Colon) {
-            // ebb-header ::= Ebb(ebb) [ * ebb-params ] ":"
+        if self.token() == Some(Token::LPar) {
+            // ebb-header ::= Ebb(ebb) [ * ebb-params ] ["cold"] ":"
             self.parse_ebb_params(ctx, ebb)?;
-            self.match_token(
-                Token::Colon,
-                "expected ':' after EBB parameters",
-            )?;
         }
+        if self.optional(Token::Identifier("cold")) {
+            // ebb-header ::= Ebb(ebb) [ebb-params] [ * "cold"] ":"
+            ctx.function.layout.set_cold(ebb, true);
+        }
+        self.match_token(
+            Token::Colon,
+            "expected ':' after EBB header",
+        )?;
+        let mut params = self.take_references();
+        params.remove(0);
+        let mut syntax = EbbSyntax {
+            ebb,
+            span: start,
+            header: self.end_span(start),
+            params,
+            insts: Vec::new(),
+        };
 
         // Collect any trailing comments.
         self.token();
@@ -1378,14 +1667,17 @@ impl<'a> Parser<'a> {
             _ => false,
         }
         {
+            let inst_start = self.start_span();
             let srcloc = self.optional_srcloc()?;
             let (encoding, result_locations) = self.parse_instruction_encoding(ctx)?;
+            let mut references = self.take_references();
 
             // We need to parse instruction results here because they are shared
             // between the parsing of value aliases and the parsing of instructions.
             //
             // inst-results ::= Value(v) { "," Value(v) }
             let results = self.parse_inst_results()?;
+            let result_refs = self.take_references();
 
             for result in &results {
                 while ctx.function.dfg.num_values() <= result.index() {
@@ -1393,10 +1685,11 @@ impl<'a> Parser<'a> {
                 }
             }
 
-            match self.token() {
+            let (inst, opcode) = match self.token() {
                 Some(Token::Arrow) => {
                     self.consume();
                     self.parse_value_alias(&results, ctx)?;
+                    (None, None)
                 }
                 Some(Token::Equal) => {
                     self.consume();
@@ -1407,7 +1700,7 @@ impl<'a> Parser<'a> {
                         result_locations,
                         ctx,
                         ebb,
-                    )?;
+                    )?
                 }
                 _ if !results.is_empty() => return err!(self.loc, "expected -> or ="),
                 _ => {
@@ -1420,9 +1713,20 @@ impl<'a> Parser<'a> {
                         ebb,
                     )?
                 }
-            }
+            };
+
+            references.append(&mut self.references);
+            syntax.insts.push(InstSyntax {
+                inst,
+                span: self.end_span(inst_start),
+                opcode,
+                results: result_refs,
+                references,
+            });
         }
 
+        syntax.span = self.end_span(start);
+        ctx.syntax.ebbs.push(syntax);
         Ok(())
     }
 
@@ -1621,6 +1925,8 @@ impl<'a> Parser<'a> {
 
     // Parse an instruction, append it to `ebb`.
     //
+    // Returns the instruction and the span of its opcode.
+    //
     // instruction ::= [inst-results "="] Opcode(opc) ["." Type] ...
     //
     fn parse_instruction(
@@ -1631,7 +1937,7 @@ impl<'a> Parser<'a> {
         result_locations: Option<Vec<ValueLoc>>,
         ctx: &mut Context,
         ebb: Ebb,
-    ) -> Result<()> {
+    ) -> Result<(Option<Inst>, Option<Span>)> {
         // Define the result values.
         for val in results {
             ctx.map.def_value(*val, &self.loc)?;
@@ -1650,6 +1956,7 @@ impl<'a> Parser<'a> {
             return err!(self.loc, "expected instruction opcode");
         };
         let opcode_loc = self.loc;
+        let opcode_span = self.span;
         self.consume();
 
         // Look for a controlling type variable annotation.
@@ -1727,7 +2034,7 @@ impl<'a> Parser<'a> {
         self.token();
         self.claim_gathered_comments(inst);
 
-        Ok(())
+        Ok((Some(inst), Some(opcode_span)))
     }
 
     // Type inference for polymorphic instructions.
@@ -1879,6 +2186,11 @@ impl<'a> Parser<'a> {
                     global_var: gv,
                 }
             }
+            InstructionFormat::UnaryConst => {
+                let constant = self.match_constant("expected constant")?;
+                ctx.check_constant(constant, &self.loc)?;
+                InstructionData::UnaryConst { opcode, constant }
+            }
             InstructionFormat::Binary => {
                 let lhs = self.match_value("expected SSA value first operand")?;
                 self.match_token(
@@ -2020,6 +2332,11 @@ impl<'a> Parser<'a> {
                 ctx.check_jt(table, &self.loc)?;
                 InstructionData::BranchTable { opcode, arg, table }
             }
+            InstructionFormat::BranchTableBase => {
+                let table = self.match_jt()?;
+                ctx.check_jt(table, &self.loc)?;
+                InstructionData::BranchTableBase { opcode, table }
+            }
             InstructionFormat::InsertLane => {
                 let lhs = self.match_value("expected SSA value first operand")?;
                 self.match_token(
@@ -2137,6 +2454,30 @@ impl<'a> Parser<'a> {
                     args: args.into_value_list(&[], &mut ctx.function.dfg.value_lists),
                 }
             }
+            InstructionFormat::Invoke => {
+                let func_ref = self.match_fn("expected function reference")?;
+                ctx.check_fn(func_ref, &self.loc)?;
+                self.match_token(
+                    Token::LPar,
+                    "expected '(' before arguments",
+                )?;
+                let args = self.parse_value_list()?;
+                self.match_token(
+                    Token::RPar,
+                    "expected ')' after arguments",
+                )?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let ebb_num = self.match_ebb("expected landing pad EBB")?;
+                InstructionData::Invoke {
+                    opcode,
+                    func_ref,
+                    destination: ebb_num,
+                    args: args.into_value_list(&[], &mut ctx.function.dfg.value_lists),
+                }
+            }
             InstructionFormat::IndirectCall => {
                 let sig_ref = self.match_sig("expected signature reference")?;
                 ctx.check_sig(sig_ref, &self.loc)?;
@@ -2165,6 +2506,21 @@ impl<'a> Parser<'a> {
                 ctx.check_fn(func_ref, &self.loc)?;
                 InstructionData::FuncAddr { opcode, func_ref }
             }
+            InstructionFormat::CallGlobalVar => {
+                let func_ref = self.match_fn("expected function reference")?;
+                ctx.check_fn(func_ref, &self.loc)?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let global_var = self.match_gv("expected global variable")?;
+                ctx.check_gv(global_var, &self.loc)?;
+                InstructionData::CallGlobalVar {
+                    opcode,
+                    func_ref,
+                    global_var,
+                }
+            }
             InstructionFormat::StackLoad => {
                 let ss = self.match_ss("expected stack slot number: ss«n»")?;
                 ctx.check_ss(ss, &self.loc)?;
@@ -2238,6 +2594,80 @@ impl<'a> Parser<'a> {
                     offset,
                 }
             }
+            InstructionFormat::AtomicLoad => {
+                let flags = self.optional_memflags();
+                let ordering = self.match_enum("expected memory ordering")?;
+                let addr = self.match_value("expected SSA value address")?;
+                InstructionData::AtomicLoad {
+                    opcode,
+                    flags,
+                    ordering,
+                    arg: addr,
+                }
+            }
+            InstructionFormat::AtomicStore => {
+                let flags = self.optional_memflags();
+                let ordering = self.match_enum("expected memory ordering")?;
+                let arg = self.match_value("expected SSA value operand")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let addr = self.match_value("expected SSA value address")?;
+                InstructionData::AtomicStore {
+                    opcode,
+                    flags,
+                    ordering,
+                    args: [arg, addr],
+                }
+            }
+            InstructionFormat::AtomicCas => {
+                let flags = self.optional_memflags();
+                let ordering = self.match_enum("expected memory ordering")?;
+                let addr = self.match_value("expected SSA value address")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let expected = self.match_value("expected SSA value operand")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let replacement = self.match_value("expected SSA value operand")?;
+                InstructionData::AtomicCas {
+                    opcode,
+                    flags,
+                    ordering,
+                    args: [addr, expected, replacement],
+                }
+            }
+            InstructionFormat::AtomicRmw => {
+                let op = self.match_enum("expected atomic operation")?;
+                let flags = self.optional_memflags();
+                let ordering = self.match_enum("expected memory ordering")?;
+                let addr = self.match_value("expected SSA value address")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let arg = self.match_value("expected SSA value operand")?;
+                InstructionData::AtomicRmw {
+                    opcode,
+                    op,
+                    flags,
+                    ordering,
+                    args: [addr, arg],
+                }
+            }
+            InstructionFormat::Fence => {
+                let ordering = self.match_enum("expected memory ordering")?;
+                InstructionData::Fence { opcode, ordering }
+            }
+            InstructionFormat::Barrier => {
+                let kind = self.match_enum("expected barrier kind")?;
+                InstructionData::Barrier { opcode, kind }
+            }
             InstructionFormat::RegMove => {
                 let arg = self.match_value("expected SSA value operand")?;
                 self.match_token(
@@ -2349,6 +2779,41 @@ impl<'a> Parser<'a> {
                     code,
                 }
             }
+            InstructionFormat::BinaryTrap => {
+                let lhs = self.match_value("expected SSA value first operand")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let rhs = self.match_value("expected SSA value second operand")?;
+                self.match_token(
+                    Token::Comma,
+                    "expected ',' between operands",
+                )?;
+                let code = self.match_enum("expected trap code")?;
+                InstructionData::BinaryTrap {
+                    opcode,
+                    args: [lhs, rhs],
+                    code,
+                }
+            }
+            InstructionFormat::Custom => {
+                let op = self.match_enum("expected custom opcode")?;
+                self.match_token(
+                    Token::LPar,
+                    "expected '(' before operands",
+                )?;
+                let args = self.parse_value_list()?;
+                self.match_token(
+                    Token::RPar,
+                    "expected ')' after operands",
+                )?;
+                InstructionData::Custom {
+                    opcode,
+                    op,
+                    args: args.into_value_list(&[], &mut ctx.function.dfg.value_lists),
+                }
+            }
         };
         Ok(idata)
     }
@@ -2453,6 +2918,32 @@ mod tests {
                 .to_string(),
             "1: expected ')' after function arguments"
         );
+
+        // Variadic signatures.
+        let sig3 = Parser::new("(i64, ..., f64, i32) -> i32 native")
+            .parse_signature(None)
+            .unwrap();
+        assert_eq!(sig3.params.len(), 3);
+        assert_eq!(sig3.fixed_params, Some(1));
+        assert_eq!(sig3.to_string(), "(i64, ..., f64, i32) -> i32 native");
+        assert_eq!(
+            Parser::new("(...)").parse_signature(None).unwrap().fixed_params,
+            Some(0)
+        );
+        assert_eq!(
+            Parser::new("(i32, ..., ...)")
+                .parse_signature(None)
+                .unwrap_err()
+                .to_string(),
+            "1: duplicate '...' in signature"
+        );
+        assert_eq!(
+            Parser::new("(i32, ..)")
+                .parse_signature(None)
+                .unwrap_err()
+                .to_string(),
+            "1: expected '...'"
+        );
     }
 
     #[test]
@@ -2498,6 +2989,8 @@ mod tests {
             "function %ebbs() native {
                                      ebb0:
                                      ebb4(v3: i32):
+                                     ebb5 cold:
+                                     ebb6(v4: i64) cold:
                                      }",
         ).parse_function(None)
             .unwrap();
@@ -2507,11 +3000,21 @@ mod tests {
 
         let ebb0 = ebbs.next().unwrap();
         assert_eq!(func.dfg.ebb_params(ebb0), &[]);
+        assert!(!func.layout.is_cold(ebb0));
 
         let ebb4 = ebbs.next().unwrap();
         let ebb4_args = func.dfg.ebb_params(ebb4);
         assert_eq!(ebb4_args.len(), 1);
         assert_eq!(func.dfg.value_type(ebb4_args[0]), types::I32);
+        assert!(!func.layout.is_cold(ebb4));
+
+        let ebb5 = ebbs.next().unwrap();
+        assert_eq!(func.dfg.ebb_params(ebb5), &[]);
+        assert!(func.layout.is_cold(ebb5));
+
+        let ebb6 = ebbs.next().unwrap();
+        assert_eq!(func.dfg.ebb_params(ebb6).len(), 1);
+        assert!(func.layout.is_cold(ebb6));
     }
 
     #[test]
@@ -2553,6 +3056,47 @@ mod tests {
         assert_eq!(comments[7].entity, AnyEntity::Function);
     }
 
+    #[test]
+    fn syntax() {
+        let text = "function %spans(i32) native {
+                        ss3 = explicit_slot 4 ; slot
+                        fn0 = function %f(i32)
+                    ebb0(v0: i32):
+                        [-] v1 = iadd_imm.i32 v0, 1
+                        v2 -> v1
+                        call fn0(v2)
+                        return
+                    }";
+        let (func, Details { syntax, .. }) = Parser::new(text).parse_function(None).unwrap();
+        assert_eq!(syntax.span.text(text), text);
+        assert_eq!(syntax.name.text(text), "%spans");
+
+        assert_eq!(syntax.decls.len(), 2);
+        assert_eq!(syntax.decls[0].span.text(text), "ss3 = explicit_slot 4");
+        assert_eq!(syntax.decls[0].entity.entity.to_string(), "ss3");
+        assert_eq!(syntax.decls[1].span.location.line_number, 3);
+        assert_eq!(syntax.comments.len(), 1);
+        assert_eq!(syntax.comments[0].span.text(text), "; slot");
+        assert_eq!(syntax.comments[0].entity.to_string(), "ss3");
+
+        let ebb = &syntax.ebbs[0];
+        assert_eq!(ebb.header.text(text), "ebb0(v0: i32):");
+        assert_eq!(ebb.params[0].span.text(text), "v0");
+        assert!(ebb.span.text(text).ends_with("return"));
+
+        let insts = &ebb.insts;
+        assert_eq!(insts.len(), 4);
+        assert_eq!(insts[0].span.text(text), "[-] v1 = iadd_imm.i32 v0, 1");
+        assert_eq!(insts[0].opcode.unwrap().text(text), "iadd_imm");
+        assert_eq!(insts[0].results[0].span.text(text), "v1");
+        assert_eq!(insts[0].references[0].span.text(text), "v0");
+        assert_eq!(insts[0].inst, func.layout.first_inst(ebb.ebb));
+        assert_eq!(insts[1].inst, None);
+        assert_eq!(insts[1].span.text(text), "v2 -> v1");
+        assert_eq!(insts[2].references[0].entity.to_string(), "fn0");
+        assert_eq!(insts[2].references[1].entity.to_string(), "v2");
+    }
+
     #[test]
     fn test_file() {
         let tf = parse_test(
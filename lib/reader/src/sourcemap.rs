@@ -7,7 +7,7 @@
 //! to parser clients.
 
 use cretonne::ir::entities::AnyEntity;
-use cretonne::ir::{StackSlot, GlobalVar, Heap, JumpTable, Ebb, Value, SigRef, FuncRef};
+use cretonne::ir::{StackSlot, GlobalVar, Heap, JumpTable, Ebb, Value, SigRef, FuncRef, Constant};
 use error::{Result, Location};
 use lexer::split_entity_name;
 use std::collections::HashMap;
@@ -61,6 +61,11 @@ impl SourceMap {
         self.locations.contains_key(&jt.into())
     }
 
+    /// Look up a constant entity.
+    pub fn contains_constant(&self, constant: Constant) -> bool {
+        self.locations.contains_key(&constant.into())
+    }
+
     /// Look up an entity by source name.
     /// Returns the entity reference corresponding to `name`, if it exists.
     pub fn lookup_str(&self, name: &str) -> Option<AnyEntity> {
@@ -121,6 +126,13 @@ impl SourceMap {
                     Some(jt.into())
                 })
             }
+            "const" => {
+                Constant::with_number(num).and_then(|c| if !self.contains_constant(c) {
+                    None
+                } else {
+                    Some(c.into())
+                })
+            }
             _ => None,
         })
     }
@@ -177,6 +189,11 @@ impl SourceMap {
         self.def_entity(entity.into(), loc)
     }
 
+    /// Define the constant `entity`.
+    pub fn def_constant(&mut self, entity: Constant, loc: &Location) -> Result<()> {
+        self.def_entity(entity.into(), loc)
+    }
+
     /// Define an entity. This can be used for instructions whose numbers never
     /// appear in source, or implicitly defined signatures.
     pub fn def_entity(&mut self, entity: AnyEntity, loc: &Location) -> Result<()> {
@@ -198,6 +215,7 @@ mod tests {
             "function %detail() {
                                ss10 = incoming_arg 13
                                jt10 = jump_table ebb0
+                               const3 = 0x1234
                              ebb0(v4: i32, v7: i32):
                                v10 = iadd v4, v7
                              }",
@@ -208,6 +226,8 @@ mod tests {
         assert_eq!(map.lookup_str("ss1"), None);
         assert_eq!(map.lookup_str("ss10").unwrap().to_string(), "ss10");
         assert_eq!(map.lookup_str("jt10").unwrap().to_string(), "jt10");
+        assert_eq!(map.lookup_str("const3").unwrap().to_string(), "const3");
+        assert_eq!(map.lookup_str("const2"), None);
         assert_eq!(map.lookup_str("ebb0").unwrap().to_string(), "ebb0");
         assert_eq!(map.lookup_str("v4").unwrap().to_string(), "v4");
         assert_eq!(map.lookup_str("v7").unwrap().to_string(), "v7");
@@ -10,6 +10,7 @@ use testcommand::TestCommand;
 use isaspec::IsaSpec;
 use sourcemap::SourceMap;
 use error::Location;
+use ast::FunctionSyntax;
 
 /// A parsed test case.
 ///
@@ -38,6 +39,8 @@ pub struct Details<'a> {
     pub comments: Vec<Comment<'a>>,
     /// Mapping of entity numbers to source locations.
     pub map: SourceMap,
+    /// Spans of the parts of the function in the source text.
+    pub syntax: FunctionSyntax,
 }
 
 /// A comment in a parsed function.
@@ -16,7 +16,8 @@
 //!
 //! - the loads and stores need the memory base address;
 //! - the `get_global` et `set_global` instructions depends on how the globals are implemented;
-//! - `current_memory` and `grow_memory` are runtime functions;
+//! - `current_memory`, `grow_memory` and the atomic wait and wake instructions are runtime
+//!   functions;
 //! - `call_indirect` has to translate the function index into the address of where this
 //!    is;
 //!
@@ -787,73 +788,259 @@ pub fn translate_operator<FE: FuncEnvironment + ?Sized>(
         Operator::F32Le | Operator::F64Le => {
             translate_fcmp(FloatCC::LessThanOrEqual, builder, state)
         }
-        Operator::Wake { .. } |
-        Operator::I32Wait { .. } |
-        Operator::I64Wait { .. } |
-        Operator::I32AtomicLoad { .. } |
-        Operator::I64AtomicLoad { .. } |
-        Operator::I32AtomicLoad8U { .. } |
-        Operator::I32AtomicLoad16U { .. } |
-        Operator::I64AtomicLoad8U { .. } |
-        Operator::I64AtomicLoad16U { .. } |
-        Operator::I64AtomicLoad32U { .. } |
-        Operator::I32AtomicStore { .. } |
-        Operator::I64AtomicStore { .. } |
-        Operator::I32AtomicStore8 { .. } |
-        Operator::I32AtomicStore16 { .. } |
-        Operator::I64AtomicStore8 { .. } |
-        Operator::I64AtomicStore16 { .. } |
-        Operator::I64AtomicStore32 { .. } |
-        Operator::I32AtomicRmwAdd { .. } |
-        Operator::I64AtomicRmwAdd { .. } |
-        Operator::I32AtomicRmw8UAdd { .. } |
-        Operator::I32AtomicRmw16UAdd { .. } |
-        Operator::I64AtomicRmw8UAdd { .. } |
-        Operator::I64AtomicRmw16UAdd { .. } |
-        Operator::I64AtomicRmw32UAdd { .. } |
-        Operator::I32AtomicRmwSub { .. } |
-        Operator::I64AtomicRmwSub { .. } |
-        Operator::I32AtomicRmw8USub { .. } |
-        Operator::I32AtomicRmw16USub { .. } |
-        Operator::I64AtomicRmw8USub { .. } |
-        Operator::I64AtomicRmw16USub { .. } |
-        Operator::I64AtomicRmw32USub { .. } |
-        Operator::I32AtomicRmwAnd { .. } |
-        Operator::I64AtomicRmwAnd { .. } |
-        Operator::I32AtomicRmw8UAnd { .. } |
-        Operator::I32AtomicRmw16UAnd { .. } |
-        Operator::I64AtomicRmw8UAnd { .. } |
-        Operator::I64AtomicRmw16UAnd { .. } |
-        Operator::I64AtomicRmw32UAnd { .. } |
-        Operator::I32AtomicRmwOr { .. } |
-        Operator::I64AtomicRmwOr { .. } |
-        Operator::I32AtomicRmw8UOr { .. } |
-        Operator::I32AtomicRmw16UOr { .. } |
-        Operator::I64AtomicRmw8UOr { .. } |
-        Operator::I64AtomicRmw16UOr { .. } |
-        Operator::I64AtomicRmw32UOr { .. } |
-        Operator::I32AtomicRmwXor { .. } |
-        Operator::I64AtomicRmwXor { .. } |
-        Operator::I32AtomicRmw8UXor { .. } |
-        Operator::I32AtomicRmw16UXor { .. } |
-        Operator::I64AtomicRmw8UXor { .. } |
-        Operator::I64AtomicRmw16UXor { .. } |
-        Operator::I64AtomicRmw32UXor { .. } |
-        Operator::I32AtomicRmwXchg { .. } |
-        Operator::I64AtomicRmwXchg { .. } |
-        Operator::I32AtomicRmw8UXchg { .. } |
-        Operator::I32AtomicRmw16UXchg { .. } |
-        Operator::I64AtomicRmw8UXchg { .. } |
-        Operator::I64AtomicRmw16UXchg { .. } |
-        Operator::I64AtomicRmw32UXchg { .. } |
-        Operator::I32AtomicRmwCmpxchg { .. } |
-        Operator::I64AtomicRmwCmpxchg { .. } |
-        Operator::I32AtomicRmw8UCmpxchg { .. } |
-        Operator::I32AtomicRmw16UCmpxchg { .. } |
-        Operator::I64AtomicRmw8UCmpxchg { .. } |
-        Operator::I64AtomicRmw16UCmpxchg { .. } |
-        Operator::I64AtomicRmw32UCmpxchg { .. } => {
-            panic!("proposed thread operators not yet supported");
+        /*********************************** Atomics *****************************************
+         * The atomic operators are sequentially consistent and trap on misaligned addresses.
+         * Waiting and waking are handled by the environment, like memory management.
+         ************************************************************************************/
+        Operator::Wake { memarg: MemoryImmediate { flags: _, offset } } => {
+            let count = state.pop1();
+            let (heap, addr) = get_atomic_addr(offset, I32, builder, state, environ);
+            state.push1(environ.translate_atomic_notify(
+                builder.cursor(),
+                0,
+                heap,
+                addr,
+                count,
+            ));
+        }
+        Operator::I32Wait { memarg: MemoryImmediate { flags: _, offset } } |
+        Operator::I64Wait { memarg: MemoryImmediate { flags: _, offset } } => {
+            let (expected, timeout) = state.pop2();
+            let ty = builder.func.dfg.value_type(expected);
+            let (heap, addr) = get_atomic_addr(offset, ty, builder, state, environ);
+            state.push1(environ.translate_atomic_wait(
+                builder.cursor(),
+                0,
+                heap,
+                addr,
+                expected,
+                timeout,
+            ));
+        }
+        Operator::I32AtomicLoad { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicLoad { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicLoad8U { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicLoad16U { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicLoad8U { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicLoad16U { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicLoad32U { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_load(offset, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicStore { memarg: MemoryImmediate { flags: _, offset } } |
+        Operator::I64AtomicStore32 { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_store(offset, I32, builder, state, environ);
+        }
+        Operator::I64AtomicStore { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_store(offset, I64, builder, state, environ);
+        }
+        Operator::I32AtomicStore8 { memarg: MemoryImmediate { flags: _, offset } } |
+        Operator::I64AtomicStore8 { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_store(offset, I8, builder, state, environ);
+        }
+        Operator::I32AtomicStore16 { memarg: MemoryImmediate { flags: _, offset } } |
+        Operator::I64AtomicStore16 { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_store(offset, I16, builder, state, environ);
+        }
+        Operator::I32AtomicRmwAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UAdd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Add;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwSub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwSub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8USub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16USub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8USub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16USub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32USub { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Sub;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UAnd { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::And;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UOr { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Or;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UXor { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xor;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UXchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            let op = ir::AtomicRmwOp::Xchg;
+            translate_atomic_rmw(offset, op, I32, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmwCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I32, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmwCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I64, I64, builder, state, environ);
+        }
+        Operator::I32AtomicRmw8UCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I8, I32, builder, state, environ);
+        }
+        Operator::I32AtomicRmw16UCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I16, I32, builder, state, environ);
+        }
+        Operator::I64AtomicRmw8UCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I8, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw16UCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I16, I64, builder, state, environ);
+        }
+        Operator::I64AtomicRmw32UCmpxchg { memarg: MemoryImmediate { flags: _, offset } } => {
+            translate_atomic_cas(offset, I32, I64, builder, state, environ);
         }
     }
 }
@@ -942,30 +1129,37 @@ fn translate_unreachable_operator(
     }
 }
 
-// Get the address+offset to use for a heap access.
+// Get the address+offset to use for a heap access of `access_size` bytes.
 fn get_heap_addr(
     heap: ir::Heap,
     addr32: ir::Value,
     offset: u32,
+    access_size: u32,
     addr_ty: ir::Type,
     builder: &mut FunctionBuilder<Variable>,
 ) -> (ir::Value, i32) {
     use std::cmp::min;
 
     let guard_size: i64 = builder.func.heaps[heap].guard_size.into();
-    debug_assert!(guard_size > 0, "Heap guard pages currently required");
-
-    // Generate `heap_addr` instructions that are friendly to CSE by checking offsets that are
-    // multiples of the guard size. Add one to make sure that we check the pointer itself is in
-    // bounds.
-    //
-    // For accesses on the outer skirts of the guard pages, we expect that we get a trap
-    // even if the access goes beyond the guard pages. This is because the first byte pointed to is
-    // inside the guard pages.
-    let check_size = min(
-        i64::from(u32::MAX),
-        1 + (i64::from(offset) / guard_size) * guard_size,
-    ) as u32;
+    let check_size = if guard_size == 0 {
+        // Without guard pages, the whole access must be bounds checked.
+        min(
+            i64::from(u32::MAX),
+            i64::from(offset) + i64::from(access_size),
+        ) as u32
+    } else {
+        // Generate `heap_addr` instructions that are friendly to CSE by checking offsets that are
+        // multiples of the guard size. Add one to make sure that we check the pointer itself is
+        // in bounds.
+        //
+        // For accesses on the outer skirts of the guard pages, we expect that we get a trap
+        // even if the access goes beyond the guard pages. This is because the first byte pointed
+        // to is inside the guard pages.
+        min(
+            i64::from(u32::MAX),
+            1 + (i64::from(offset) / guard_size) * guard_size,
+        ) as u32
+    };
     let base = builder.ins().heap_addr(addr_ty, heap, addr32, check_size);
 
     // Native load/store instructions take a signed `Offset32` immediate, so adjust the base
@@ -979,6 +1173,16 @@ fn get_heap_addr(
     }
 }
 
+// Get the number of bytes accessed by a load or store `opcode` with value type `ty`.
+fn access_size(opcode: ir::Opcode, ty: ir::Type) -> u32 {
+    match opcode {
+        ir::Opcode::Uload8 | ir::Opcode::Sload8 | ir::Opcode::Istore8 => 1,
+        ir::Opcode::Uload16 | ir::Opcode::Sload16 | ir::Opcode::Istore16 => 2,
+        ir::Opcode::Uload32 | ir::Opcode::Sload32 | ir::Opcode::Istore32 => 4,
+        _ => ty.bytes(),
+    }
+}
+
 // Translate a load instruction.
 fn translate_load<FE: FuncEnvironment + ?Sized>(
     offset: u32,
@@ -991,7 +1195,9 @@ fn translate_load<FE: FuncEnvironment + ?Sized>(
     let addr32 = state.pop1();
     // We don't yet support multiple linear memories.
     let heap = state.get_heap(builder.func, 0, environ);
-    let (base, offset) = get_heap_addr(heap, addr32, offset, environ.native_pointer(), builder);
+    let access_size = access_size(opcode, result_ty);
+    let addr_ty = environ.native_pointer();
+    let (base, offset) = get_heap_addr(heap, addr32, offset, access_size, addr_ty, builder);
     let flags = MemFlags::new();
     let (load, dfg) = builder.ins().Load(
         opcode,
@@ -1016,7 +1222,9 @@ fn translate_store<FE: FuncEnvironment + ?Sized>(
 
     // We don't yet support multiple linear memories.
     let heap = state.get_heap(builder.func, 0, environ);
-    let (base, offset) = get_heap_addr(heap, addr32, offset, environ.native_pointer(), builder);
+    let access_size = access_size(opcode, val_ty);
+    let addr_ty = environ.native_pointer();
+    let (base, offset) = get_heap_addr(heap, addr32, offset, access_size, addr_ty, builder);
     let flags = MemFlags::new();
     builder.ins().Store(
         opcode,
@@ -1028,6 +1236,138 @@ fn translate_store<FE: FuncEnvironment + ?Sized>(
     );
 }
 
+// Get the native address of an atomic access to `access_ty` at `addr32 + offset`, trapping if it
+// is misaligned.
+//
+// The atomic instructions don't have an offset immediate, so the offset is added to the address.
+// Heap bases are page aligned, so checking the native address also checks the wasm address.
+fn get_atomic_addr<FE: FuncEnvironment + ?Sized>(
+    offset: u32,
+    access_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+    state: &mut TranslationState,
+    environ: &mut FE,
+) -> (ir::Heap, ir::Value) {
+    let addr32 = state.pop1();
+    // We don't yet support multiple linear memories.
+    let heap = state.get_heap(builder.func, 0, environ);
+    let access_size = access_ty.bytes();
+    let addr_ty = environ.native_pointer();
+    let (base, offset) = get_heap_addr(heap, addr32, offset, access_size, addr_ty, builder);
+    let addr = if offset == 0 {
+        base
+    } else {
+        builder.ins().iadd_imm(base, i64::from(offset))
+    };
+    let align = i64::from(access_size);
+    if align > 1 {
+        let misalignment = builder.ins().band_imm(addr, align - 1);
+        builder.ins().trapnz(misalignment, ir::TrapCode::HeapMisaligned);
+    }
+    (heap, addr)
+}
+
+// Flags for an atomic heap access. The address has been checked by `get_atomic_addr`.
+fn atomic_flags() -> MemFlags {
+    let mut flags = MemFlags::new();
+    flags.set_aligned();
+    flags
+}
+
+// Truncate the operand `val` of a narrow atomic access to `access_ty`.
+fn narrow(
+    val: ir::Value,
+    access_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+) -> ir::Value {
+    if builder.func.dfg.value_type(val) == access_ty {
+        val
+    } else {
+        builder.ins().ireduce(access_ty, val)
+    }
+}
+
+// Zero-extend the result `val` of a narrow atomic access to `result_ty`.
+fn widen(
+    val: ir::Value,
+    result_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+) -> ir::Value {
+    if builder.func.dfg.value_type(val) == result_ty {
+        val
+    } else {
+        builder.ins().uextend(result_ty, val)
+    }
+}
+
+// Translate an atomic load of `access_ty`, zero-extended to `result_ty`.
+fn translate_atomic_load<FE: FuncEnvironment + ?Sized>(
+    offset: u32,
+    access_ty: ir::Type,
+    result_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+    state: &mut TranslationState,
+    environ: &mut FE,
+) {
+    let (_, addr) = get_atomic_addr(offset, access_ty, builder, state, environ);
+    let ordering = ir::AtomicOrdering::SeqCst;
+    let val = builder.ins().atomic_load(access_ty, atomic_flags(), ordering, addr);
+    state.push1(widen(val, result_ty, builder));
+}
+
+// Translate an atomic store of the low `access_ty` bits of a value.
+fn translate_atomic_store<FE: FuncEnvironment + ?Sized>(
+    offset: u32,
+    access_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+    state: &mut TranslationState,
+    environ: &mut FE,
+) {
+    let val = state.pop1();
+    let (_, addr) = get_atomic_addr(offset, access_ty, builder, state, environ);
+    let val = narrow(val, access_ty, builder);
+    let ordering = ir::AtomicOrdering::SeqCst;
+    builder.ins().atomic_store(atomic_flags(), ordering, val, addr);
+}
+
+// Translate an atomic read-modify-write of `access_ty`, with the old value zero-extended to
+// `result_ty`.
+fn translate_atomic_rmw<FE: FuncEnvironment + ?Sized>(
+    offset: u32,
+    op: ir::AtomicRmwOp,
+    access_ty: ir::Type,
+    result_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+    state: &mut TranslationState,
+    environ: &mut FE,
+) {
+    let val = state.pop1();
+    let (_, addr) = get_atomic_addr(offset, access_ty, builder, state, environ);
+    let val = narrow(val, access_ty, builder);
+    let ordering = ir::AtomicOrdering::SeqCst;
+    let old = builder.ins().atomic_rmw(op, atomic_flags(), ordering, addr, val);
+    state.push1(widen(old, result_ty, builder));
+}
+
+// Translate an atomic compare-and-swap of `access_ty`, with the old value zero-extended to
+// `result_ty`.
+fn translate_atomic_cas<FE: FuncEnvironment + ?Sized>(
+    offset: u32,
+    access_ty: ir::Type,
+    result_ty: ir::Type,
+    builder: &mut FunctionBuilder<Variable>,
+    state: &mut TranslationState,
+    environ: &mut FE,
+) {
+    let (expected, replacement) = state.pop2();
+    let (_, addr) = get_atomic_addr(offset, access_ty, builder, state, environ);
+    let expected = narrow(expected, access_ty, builder);
+    let replacement = narrow(replacement, access_ty, builder);
+    let ordering = ir::AtomicOrdering::SeqCst;
+    let old = builder.ins().atomic_cas(atomic_flags(), ordering, addr, expected, replacement);
+    state.push1(widen(old, result_ty, builder));
+}
+
 fn translate_icmp(
     cc: IntCC,
     builder: &mut FunctionBuilder<Variable>,
@@ -1,6 +1,6 @@
 //! "Dummy" environment for testing wasm translation.
 
-use environ::{FuncEnvironment, GlobalValue, ModuleEnvironment};
+use environ::{FuncEnvironment, GlobalValue, ModuleEnvironment, CallTarget, load_func_slot};
 use translation_utils::{Global, Memory, Table, GlobalIndex, TableIndex, SignatureIndex,
                         FunctionIndex, MemoryIndex};
 use func_translator::FuncTranslator;
@@ -66,6 +66,15 @@ pub struct DummyModuleInfo {
 
     /// The start function.
     pub start_func: Option<FunctionIndex>,
+
+    /// Call imported functions and functions that haven't been translated yet through function
+    /// table slots in the `vmctx` instead of direct calls.
+    pub func_table_calls: bool,
+
+    /// Use dynamic heaps without guard pages for the memories that don't declare a maximum size.
+    /// Their bound is stored at `vmctx-8` and reloaded after anything that can grow them. The
+    /// other memories get static heaps with guard pages.
+    pub dynamic_memories: bool,
 }
 
 impl DummyModuleInfo {
@@ -81,6 +90,8 @@ impl DummyModuleInfo {
             memories: Vec::new(),
             globals: Vec::new(),
             start_func: None,
+            func_table_calls: false,
+            dynamic_memories: false,
         }
     }
 }
@@ -158,15 +169,35 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         }
     }
 
-    fn make_heap(&mut self, func: &mut ir::Function, _index: MemoryIndex) -> ir::Heap {
-        // Create a static heap whose base address is stored at `vmctx+0`.
+    fn make_heap(&mut self, func: &mut ir::Function, index: MemoryIndex) -> ir::Heap {
+        // The heap base address is stored at `vmctx+0`.
         let gv = func.create_global_var(ir::GlobalVarData::VmCtx { offset: 0.into() });
 
+        let growable = match self.mod_info.memories.get(index) {
+            Some(memory) => memory.entity.maximum.is_none(),
+            None => false,
+        };
+        if self.mod_info.dynamic_memories && growable {
+            // Create a dynamic heap that is explicitly bounds checked against `vmctx-8`.
+            let min_pages = self.mod_info.memories[index].entity.pages_count as i64;
+            let offset = (-8).into();
+            let bound_gv = func.create_global_var(ir::GlobalVarData::VmCtx { offset });
+            return func.create_heap(ir::HeapData {
+                base: ir::HeapBase::GlobalVar(gv),
+                min_size: (min_pages * 0x1_0000).into(),
+                guard_size: 0.into(),
+                style: ir::HeapStyle::Dynamic { bound_gv },
+                readonly: false,
+            });
+        }
+
+        // Create a static heap relying on guard pages.
         func.create_heap(ir::HeapData {
             base: ir::HeapBase::GlobalVar(gv),
             min_size: 0.into(),
             guard_size: 0x8000_0000.into(),
             style: ir::HeapStyle::Static { bound: 0x1_0000_0000.into() },
+            readonly: false,
         })
     }
 
@@ -182,7 +213,11 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         // And maybe attempt some signature de-duplication.
         let signature = func.import_signature(self.vmctx_sig(sigidx));
         let name = get_func_name(index);
-        func.import_function(ir::ExtFuncData { name, signature })
+        func.import_function(ir::ExtFuncData {
+            name,
+            signature,
+            colocated: false,
+        })
     }
 
     fn translate_call_indirect(
@@ -223,10 +258,24 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
             .0
     }
 
+    fn call_target(&self, callee_index: FunctionIndex) -> CallTarget {
+        let num_imports = self.mod_info.imported_funcs.len();
+        let num_translated = num_imports + self.mod_info.function_bodies.len();
+        if !self.mod_info.func_table_calls ||
+            (callee_index >= num_imports && callee_index < num_translated)
+        {
+            return CallTarget::Direct;
+        }
+
+        // The function table slots are stored after the globals in this dummy `vmctx`.
+        let slot = 1 + self.mod_info.globals.len() + callee_index;
+        CallTarget::VmctxSlot { offset: ((slot * 8) as i32).into() }
+    }
+
     fn translate_call(
         &mut self,
         mut pos: FuncCursor,
-        _callee_index: FunctionIndex,
+        callee_index: FunctionIndex,
         callee: ir::FuncRef,
         call_args: &[ir::Value],
     ) -> ir::Inst {
@@ -241,9 +290,21 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         args.extend(call_args.iter().cloned(), &mut pos.func.dfg.value_lists);
         args.push(vmctx, &mut pos.func.dfg.value_lists);
 
-        pos.ins()
-            .Call(ir::Opcode::Call, ir::types::VOID, callee, args)
-            .0
+        match self.call_target(callee_index) {
+            CallTarget::Direct => {
+                pos.ins()
+                    .Call(ir::Opcode::Call, ir::types::VOID, callee, args)
+                    .0
+            }
+            CallTarget::VmctxSlot { offset } => {
+                let func_addr = load_func_slot(&mut pos, self.native_pointer(), offset);
+                let sig_ref = pos.func.dfg.ext_funcs[callee].signature;
+                args.insert(0, func_addr, &mut pos.func.dfg.value_lists);
+                pos.ins()
+                    .IndirectCall(ir::Opcode::CallIndirect, ir::types::VOID, sig_ref, args)
+                    .0
+            }
+        }
     }
 
     fn translate_grow_memory(
@@ -264,6 +325,29 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
     ) -> ir::Value {
         pos.ins().iconst(I32, -1)
     }
+
+    fn translate_atomic_wait(
+        &mut self,
+        mut pos: FuncCursor,
+        _index: MemoryIndex,
+        _heap: ir::Heap,
+        _addr: ir::Value,
+        _expected: ir::Value,
+        _timeout: ir::Value,
+    ) -> ir::Value {
+        pos.ins().iconst(I32, -1)
+    }
+
+    fn translate_atomic_notify(
+        &mut self,
+        mut pos: FuncCursor,
+        _index: MemoryIndex,
+        _heap: ir::Heap,
+        _addr: ir::Value,
+        _count: ir::Value,
+    ) -> ir::Value {
+        pos.ins().iconst(I32, -1)
+    }
 }
 
 impl<'data> ModuleEnvironment<'data> for DummyEnvironment {
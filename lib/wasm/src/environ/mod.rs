@@ -3,5 +3,6 @@
 mod spec;
 mod dummy;
 
-pub use environ::spec::{ModuleEnvironment, FuncEnvironment, GlobalValue};
+pub use environ::spec::{ModuleEnvironment, FuncEnvironment, GlobalValue, CallTarget,
+                        load_func_slot};
 pub use environ::dummy::DummyEnvironment;
@@ -23,6 +23,25 @@ pub enum GlobalValue {
     },
 }
 
+/// How a WebAssembly `call` instruction reaches its callee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallTarget {
+    /// Call the function directly with a `call` instruction. The call site needs a relocation
+    /// which must be patched with the address of the callee.
+    Direct,
+
+    /// Load the address of the callee from a function table slot at `offset` bytes from the
+    /// `vmctx` pointer, and call it with a `call_indirect` instruction.
+    ///
+    /// The generated code never needs to be patched, so this can be used for callees in other
+    /// modules or callees that haven't been compiled yet when the code must not be writable after
+    /// it has been loaded, or when the code is shared between processes.
+    VmctxSlot {
+        /// Offset of the function table slot from the `vmctx` pointer.
+        offset: ir::immediates::Offset32,
+    },
+}
+
 /// Environment affecting the translation of a single WebAssembly function.
 ///
 /// A `FuncEnvironment` trait object is required to translate a WebAssembly function to Cretonne
@@ -56,6 +75,12 @@ pub trait FuncEnvironment {
     /// by `index`.
     ///
     /// The index space covers both imported and locally declared memories.
+    ///
+    /// Each memory can use its own heap style. The heap accesses are translated according to its
+    /// guard size: A heap with guard pages only has the pointer itself bounds checked, and the
+    /// guard pages catch the rest of the access. A heap without guard pages has every access
+    /// explicitly bounds checked. A dynamic heap that isn't `readonly` has its bound reloaded
+    /// after calls, so it can be grown by `translate_grow_memory`.
     fn make_heap(&mut self, func: &mut ir::Function, index: MemoryIndex) -> ir::Heap;
 
     /// Set up a signature definition in the preamble of `func` that can be used for an indirect
@@ -101,9 +126,17 @@ pub trait FuncEnvironment {
         call_args: &[ir::Value],
     ) -> ir::Inst;
 
+    /// Decide how a `call` to the function `callee_index` reaches its callee.
+    ///
+    /// The default is to call all functions directly.
+    fn call_target(&self, _callee_index: FunctionIndex) -> CallTarget {
+        CallTarget::Direct
+    }
+
     /// Translate a `call` WebAssembly instruction at `pos`.
     ///
-    /// Insert instructions at `pos` for a direct call to the function `callee_index`.
+    /// Insert instructions at `pos` for a call to the function `callee_index`, either directly or
+    /// through a function table slot as decided by `call_target()`.
     ///
     /// The function reference `callee` was previously created by `make_direct_func()`.
     ///
@@ -111,11 +144,18 @@ pub trait FuncEnvironment {
     fn translate_call(
         &mut self,
         mut pos: FuncCursor,
-        _callee_index: FunctionIndex,
+        callee_index: FunctionIndex,
         callee: ir::FuncRef,
         call_args: &[ir::Value],
     ) -> ir::Inst {
-        pos.ins().call(callee, call_args)
+        match self.call_target(callee_index) {
+            CallTarget::Direct => pos.ins().call(callee, call_args),
+            CallTarget::VmctxSlot { offset } => {
+                let func_addr = load_func_slot(&mut pos, self.native_pointer(), offset);
+                let sig_ref = pos.func.dfg.ext_funcs[callee].signature;
+                pos.ins().call_indirect(sig_ref, func_addr, call_args)
+            }
+        }
     }
 
     /// Translate a `grow_memory` WebAssembly instruction.
@@ -147,6 +187,42 @@ pub trait FuncEnvironment {
         heap: ir::Heap,
     ) -> ir::Value;
 
+    /// Translate an `i32.atomic.wait` or `i64.atomic.wait` WebAssembly instruction.
+    ///
+    /// The `index` provided identifies the linear memory waited on, and `heap` is the heap
+    /// reference returned by `make_heap` for the same index. The memory should be shared; waiting
+    /// on an unshared memory traps.
+    ///
+    /// The `addr` value is the bounds checked and aligned native address of the waited on value,
+    /// and `expected` is an `i32` or `i64` value to compare it with. The `timeout` value is an
+    /// `i64` relative timeout in nanoseconds, negative for an infinite wait.
+    ///
+    /// Returns 0 if woken by a notify, 1 if the value didn't match and 2 if the timeout expired.
+    fn translate_atomic_wait(
+        &mut self,
+        pos: FuncCursor,
+        index: MemoryIndex,
+        heap: ir::Heap,
+        addr: ir::Value,
+        expected: ir::Value,
+        timeout: ir::Value,
+    ) -> ir::Value;
+
+    /// Translate an `atomic.wake` WebAssembly instruction.
+    ///
+    /// The `index`, `heap` and `addr` arguments are the same as for `translate_atomic_wait`, and
+    /// `count` is the `i32` maximum number of waiters to wake up.
+    ///
+    /// Returns the number of waiters that were woken up.
+    fn translate_atomic_notify(
+        &mut self,
+        pos: FuncCursor,
+        index: MemoryIndex,
+        heap: ir::Heap,
+        addr: ir::Value,
+        count: ir::Value,
+    ) -> ir::Value;
+
     /// Emit code at the beginning of every wasm loop.
     ///
     /// This can be used to insert explicit interrupt or safepoint checking at
@@ -156,6 +232,22 @@ pub trait FuncEnvironment {
     }
 }
 
+/// Insert a load of the function address in the function table slot at `offset` from the `vmctx`
+/// parameter of the current function.
+pub fn load_func_slot(
+    pos: &mut FuncCursor,
+    ptr: ir::Type,
+    offset: ir::immediates::Offset32,
+) -> ir::Value {
+    let vmctx = pos.func
+        .special_param(ir::ArgumentPurpose::VMContext)
+        .expect("Missing vmctx parameter");
+    let mut flags = ir::MemFlags::new();
+    flags.set_notrap();
+    flags.set_aligned();
+    pos.ins().load(ptr, flags, vmctx, offset)
+}
+
 /// An object satisfying the `ModuleEnvironment` trait can be passed as argument to the
 /// [`translate_module`](fn.translate_module.html) function. These methods should not be called
 /// by the user, they are only for `cretonne-wasm` internal use.
@@ -234,8 +234,9 @@ fn cur_srcloc(reader: &BinaryReader) -> ir::SourceLoc {
 mod tests {
     use cretonne::{ir, Context};
     use cretonne::ir::types::I32;
-    use environ::{DummyEnvironment, FuncEnvironment};
+    use environ::{DummyEnvironment, FuncEnvironment, ModuleEnvironment};
     use super::FuncTranslator;
+    use translation_utils::Memory;
 
     #[test]
     fn small1() {
@@ -336,4 +337,145 @@ mod tests {
         dbg!("{}", ctx.func.display(None));
         ctx.verify(runtime.func_env().flags()).unwrap();
     }
+
+    #[test]
+    fn call_through_table() {
+        // A call to an imported function.
+        //
+        // (func $call_through_table (param i32) (result i32)
+        //     (call $imported (get_local 0))
+        // )
+        const BODY: [u8; 6] = [
+            0x00,       // local decl count
+            0x20, 0x00, // get_local 0
+            0x10, 0x00, // call 0
+            0x0b,       // end
+        ];
+
+        let mut trans = FuncTranslator::new();
+        let mut runtime = DummyEnvironment::default();
+        let mut ctx = Context::new();
+
+        ctx.func.name = ir::ExternalName::testcase("call_through_table");
+        ctx.func.signature.params.push(ir::AbiParam::new(I32));
+        ctx.func.signature.returns.push(ir::AbiParam::new(I32));
+        runtime.declare_signature(&ctx.func.signature);
+        runtime.declare_func_import(0, "env", "imported");
+        runtime.info.func_table_calls = true;
+        ctx.func.signature.params.push(ir::AbiParam::special(
+            runtime.func_env().native_pointer(),
+            ir::ArgumentPurpose::VMContext,
+        ));
+
+        trans
+            .translate(&BODY, &mut ctx.func, &mut runtime.func_env())
+            .unwrap();
+        dbg!("{}", ctx.func.display(None));
+        ctx.verify(runtime.func_env().flags()).unwrap();
+
+        let call = ctx.func
+            .layout
+            .ebb_insts(ctx.func.layout.entry_block().unwrap())
+            .find(|&inst| ctx.func.dfg[inst].opcode().is_call())
+            .unwrap();
+        assert_eq!(ctx.func.dfg[call].opcode(), ir::Opcode::CallIndirect);
+    }
+
+    #[test]
+    fn atomics() {
+        // A sequentially consistent add, checked for alignment.
+        //
+        // (func $atomics (param i32) (result i32)
+        //     (i32.atomic.rmw.add offset=4 (get_local 0) (i32.const 1))
+        // )
+        const BODY: [u8; 10] = [
+            0x00,       // local decl count
+            0x20, 0x00, // get_local 0
+            0x41, 0x01, // i32.const 1
+            0xfe, 0x1e, // i32.atomic.rmw.add
+            0x02, 0x04, // align=4 offset=4
+            0x0b,       // end
+        ];
+
+        let mut trans = FuncTranslator::new();
+        let runtime = DummyEnvironment::default();
+        let mut ctx = Context::new();
+
+        ctx.func.name = ir::ExternalName::testcase("atomics");
+        ctx.func.signature.params.push(ir::AbiParam::new(I32));
+        ctx.func.signature.returns.push(ir::AbiParam::new(I32));
+        ctx.func.signature.params.push(ir::AbiParam::special(
+            runtime.func_env().native_pointer(),
+            ir::ArgumentPurpose::VMContext,
+        ));
+
+        trans
+            .translate(&BODY, &mut ctx.func, &mut runtime.func_env())
+            .unwrap();
+        dbg!("{}", ctx.func.display(None));
+        ctx.verify(runtime.func_env().flags()).unwrap();
+
+        let opcodes: Vec<_> = ctx.func
+            .layout
+            .ebb_insts(ctx.func.layout.entry_block().unwrap())
+            .map(|inst| ctx.func.dfg[inst].opcode())
+            .collect();
+        assert!(opcodes.contains(&ir::Opcode::Trapnz));
+        assert!(opcodes.contains(&ir::Opcode::AtomicRmw));
+    }
+
+    #[test]
+    fn bounds_checked_memory() {
+        // A load from a memory without guard pages.
+        //
+        // (memory 1)
+        // (func $bounds_checked_memory (param i32) (result i32)
+        //     (i32.load16_u offset=8 (get_local 0))
+        // )
+        const BODY: [u8; 7] = [
+            0x00,       // local decl count
+            0x20, 0x00, // get_local 0
+            0x2f,       // i32.load16_u
+            0x01, 0x08, // align=2 offset=8
+            0x0b,       // end
+        ];
+
+        let mut trans = FuncTranslator::new();
+        let mut runtime = DummyEnvironment::default();
+        let mut ctx = Context::new();
+
+        ctx.func.name = ir::ExternalName::testcase("bounds_checked_memory");
+        ctx.func.signature.params.push(ir::AbiParam::new(I32));
+        ctx.func.signature.returns.push(ir::AbiParam::new(I32));
+        ctx.func.signature.params.push(ir::AbiParam::special(
+            runtime.func_env().native_pointer(),
+            ir::ArgumentPurpose::VMContext,
+        ));
+        runtime.declare_memory(Memory {
+            pages_count: 1,
+            maximum: None,
+            shared: false,
+        });
+        runtime.info.dynamic_memories = true;
+
+        trans
+            .translate(&BODY, &mut ctx.func, &mut runtime.func_env())
+            .unwrap();
+        dbg!("{}", ctx.func.display(None));
+        ctx.verify(runtime.func_env().flags()).unwrap();
+
+        // The whole access is checked, not just the pointer.
+        let heap_addr = ctx.func
+            .layout
+            .ebb_insts(ctx.func.layout.entry_block().unwrap())
+            .find(|&inst| ctx.func.dfg[inst].opcode() == ir::Opcode::HeapAddr)
+            .unwrap();
+        match ctx.func.dfg[heap_addr] {
+            ir::InstructionData::HeapAddr { heap, imm, .. } => {
+                assert_eq!(imm, 10.into());
+                assert_eq!(ctx.func.heaps[heap].guard_size, 0.into());
+            }
+            _ => panic!("Expected heap_addr"),
+        }
+    }
 }
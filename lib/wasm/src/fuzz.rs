@@ -0,0 +1,49 @@
+//! Fuzzing entry point for the WebAssembly translator.
+//!
+//! This module is only available with the `fuzz` feature.
+
+use cretonne::fuzz::{catch_panic, FuzzError};
+use cretonne::settings;
+use cretonne::verify_function;
+use environ::DummyEnvironment;
+use module_translator::translate_module;
+
+/// Translate `data` as a WebAssembly module and verify the translated functions.
+///
+/// Malformed modules are reported as `FuzzError::Parse`. Since the translator should always
+/// produce valid IL, a `FuzzError::Verifier` is a bug, just like a `FuzzError::Panic`.
+pub fn fuzz_wasm_translate(data: &[u8]) -> Result<(), FuzzError> {
+    catch_panic(|| {
+        let flags = settings::Flags::new(&settings::builder());
+        let mut environ = DummyEnvironment::with_flags(flags.clone());
+        translate_module(data, &mut environ).map_err(FuzzError::Parse)?;
+        for func in &environ.info.function_bodies {
+            verify_function(func, &flags).map_err(|e| {
+                FuzzError::Verifier(e.to_string())
+            })?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate() {
+        assert_eq!(fuzz_wasm_translate(b"\0asm\x01\0\0\0"), Ok(()));
+
+        // A function returning the constant 42.
+        let module = b"\0asm\x01\0\0\0\
+                       \x01\x05\x01\x60\x00\x01\x7f\
+                       \x03\x02\x01\x00\
+                       \x0a\x06\x01\x04\x00\x41\x2a\x0b";
+        assert_eq!(fuzz_wasm_translate(module), Ok(()));
+
+        assert!(match fuzz_wasm_translate(b"junk") {
+            Err(FuzzError::Parse(_)) => true,
+            _ => false,
+        });
+    }
+}
@@ -22,6 +22,9 @@ extern crate cton_frontend;
 #[macro_use(dbg)]
 extern crate cretonne;
 
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+
 mod code_translator;
 mod func_translator;
 mod module_translator;
@@ -32,6 +35,7 @@ mod translation_utils;
 
 pub use func_translator::FuncTranslator;
 pub use module_translator::translate_module;
-pub use environ::{FuncEnvironment, ModuleEnvironment, DummyEnvironment, GlobalValue};
+pub use environ::{FuncEnvironment, ModuleEnvironment, DummyEnvironment, GlobalValue, CallTarget,
+                  load_func_slot};
 pub use translation_utils::{FunctionIndex, GlobalIndex, TableIndex, MemoryIndex, SignatureIndex,
                             Global, GlobalInit, Table, Memory};
@@ -0,0 +1,207 @@
+//! CLI tool to benchmark the compile time of Cretonne.
+//!
+//! Compiles all the functions in the input files a number of times, and reports the time spent in
+//! each pass, the compilation speed, and the peak memory use. The inputs can be Cretonne IL files
+//! or WebAssembly modules. With the `--json` option, the report is printed as JSON so scripts can
+//! track the compile time across commits:
+//!
+//! ```text
+//! {
+//!   "version": "0.4.1",
+//!   "isa": "intel",
+//!   "iterations": 10,
+//!   "functions": 12,
+//!   "instructions": 1520,
+//!   "total_time": 0.084210,
+//!   "insts_per_sec": 180501,
+//!   "peak_func_memory": 48212,
+//!   "peak_rss": 9437184,
+//!   "timing": { "legalize": 0.004102, "regalloc": 0.041520 }
+//! }
+//! ```
+//!
+//! The times are in seconds, summed over all the iterations. The instruction count is the number
+//! of instructions in the input functions, before compilation. `peak_func_memory` is the largest
+//! `Function::memory_usage()` of a compiled function in bytes, and `peak_rss` is the peak resident
+//! set size of the process in bytes, or `null` where it isn't known.
+
+use cretonne::ir::Function;
+use cretonne::isa::TargetIsa;
+use cretonne::print_errors::pretty_error;
+use cretonne::settings::FlagsOrIsa;
+use cretonne::timing::{self, PassTimes};
+use cretonne::{Context, VERSION};
+use cton_reader::parse_test;
+use cton_wasm::{translate_module, DummyEnvironment};
+use manifest::{quote, seconds};
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use utils::{parse_sets_and_isa, read_to_string};
+use wasm::read_wasm;
+
+/// The results of a benchmark run.
+struct Report {
+    isa: &'static str,
+    iterations: usize,
+    functions: usize,
+    insts: usize,
+    total: Duration,
+    peak_func_memory: usize,
+    peak_rss: Option<usize>,
+    times: PassTimes,
+}
+
+impl Report {
+    /// Get the number of input instructions compiled per second.
+    fn insts_per_sec(&self) -> u64 {
+        let secs = seconds(self.total);
+        if secs > 0.0 {
+            ((self.insts * self.iterations) as f64 / secs) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Print the report for humans.
+    fn print(&self) {
+        println!(
+            "Compiled {} functions ({} instructions) for {} {} times in {:.6} s",
+            self.functions,
+            self.insts,
+            self.isa,
+            self.iterations,
+            seconds(self.total)
+        );
+        println!("Speed: {} instructions/s", self.insts_per_sec());
+        println!("Peak function memory: {} bytes", self.peak_func_memory);
+        if let Some(rss) = self.peak_rss {
+            println!("Peak RSS: {} bytes", rss);
+        }
+        print!("{}", self.times);
+    }
+
+    /// Render the report as JSON.
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\n");
+        let _ = writeln!(out, "  \"version\": {},", quote(VERSION));
+        let _ = writeln!(out, "  \"isa\": {},", quote(self.isa));
+        let _ = writeln!(out, "  \"iterations\": {},", self.iterations);
+        let _ = writeln!(out, "  \"functions\": {},", self.functions);
+        let _ = writeln!(out, "  \"instructions\": {},", self.insts);
+        let _ = writeln!(out, "  \"total_time\": {:.6},", seconds(self.total));
+        let _ = writeln!(out, "  \"insts_per_sec\": {},", self.insts_per_sec());
+        let _ = writeln!(out, "  \"peak_func_memory\": {},", self.peak_func_memory);
+        let peak_rss = self.peak_rss.map_or_else(|| String::from("null"), |n| n.to_string());
+        let _ = writeln!(out, "  \"peak_rss\": {},", peak_rss);
+        let times: Vec<String> = self.times
+            .totals()
+            .iter()
+            .map(|&(pass, time)| format!("{}: {:.6}", quote(pass), seconds(time)))
+            .collect();
+        let _ = writeln!(out, "  \"timing\": {{ {} }}", times.join(", "));
+        out.push_str("}\n");
+        out
+    }
+}
+
+pub fn run(
+    files: &[String],
+    flag_json: bool,
+    flag_iterations: usize,
+    flag_set: &[String],
+    flag_isa: &str,
+) -> Result<(), String> {
+    let parsed = parse_sets_and_isa(flag_set, flag_isa)?;
+    let fisa = parsed.as_fisa();
+    let isa = match fisa.isa {
+        Some(isa) => isa,
+        None => return Err(String::from("benchmarking requires a target isa")),
+    };
+
+    let mut funcs = Vec::new();
+    for filename in files {
+        read_functions(Path::new(filename), fisa, &mut funcs)?;
+    }
+
+    // Only the compilation is timed, not the parsing of the inputs.
+    let outer_times = timing::take_current();
+    let report = bench(&funcs, isa, flag_iterations)?;
+    timing::add_to_current(&outer_times);
+    timing::add_to_current(&report.times);
+
+    if flag_json {
+        print!("{}", report.to_json());
+    } else {
+        report.print();
+    }
+    Ok(())
+}
+
+/// Read the functions in the file at `path` into `funcs`.
+fn read_functions(path: &Path, fisa: FlagsOrIsa, funcs: &mut Vec<Function>) -> Result<(), String> {
+    let name = path.to_string_lossy();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("wasm") | Some("wat") => {
+            let data = read_wasm(path).map_err(|e| format!("{}: {}", name, e))?;
+            let mut dummy_environ = DummyEnvironment::with_flags(fisa.flags.clone());
+            translate_module(&data, &mut dummy_environ).map_err(|e| format!("{}: {}", name, e))?;
+            funcs.extend(dummy_environ.info.function_bodies.iter().cloned());
+        }
+        _ => {
+            let buffer = read_to_string(path).map_err(|e| format!("{}: {}", name, e))?;
+            let test_file = parse_test(&buffer).map_err(|e| format!("{}: {}", name, e))?;
+            funcs.extend(test_file.functions.into_iter().map(|(func, _)| func));
+        }
+    }
+    Ok(())
+}
+
+/// Compile `funcs` for `isa` `iterations` times.
+fn bench(funcs: &[Function], isa: &TargetIsa, iterations: usize) -> Result<Report, String> {
+    let insts = funcs
+        .iter()
+        .map(|func| {
+            func.layout
+                .ebbs()
+                .map(|ebb| func.layout.ebb_insts(ebb).count())
+                .sum::<usize>()
+        })
+        .sum();
+    let mut total = Duration::default();
+    let mut peak_func_memory = 0;
+    let mut context = Context::new();
+
+    for _ in 0..iterations {
+        for func in funcs {
+            context.clear();
+            context.func = func.clone();
+            let start = Instant::now();
+            context.compile(isa).map_err(|err| {
+                pretty_error(&context.func, Some(isa), err)
+            })?;
+            total += start.elapsed();
+            peak_func_memory = peak_func_memory.max(context.func.memory_usage().total());
+        }
+    }
+
+    Ok(Report {
+        isa: isa.name(),
+        iterations,
+        functions: funcs.len(),
+        insts,
+        total,
+        peak_func_memory,
+        peak_rss: peak_rss(),
+        times: timing::take_current(),
+    })
+}
+
+/// Get the peak resident set size of the process in bytes, from `/proc/self/status` on Linux.
+fn peak_rss() -> Option<usize> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: usize = line["VmHWM:".len()..].trim().trim_right_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
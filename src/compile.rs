@@ -1,18 +1,42 @@
 //! CLI tool to compile cretonne IL into native code.
 //!
-//! Reads IR files into Cretonne IL and compiles it.
+//! Reads IR files into Cretonne IL and compiles it. The produced artifacts can be described in a
+//! JSON manifest, see the `manifest` module.
 
 use cton_reader::parse_test;
 use std::path::PathBuf;
-use cretonne::Context;
+use cretonne::{Annotations, Context};
 use cretonne::settings::FlagsOrIsa;
 use cretonne::{binemit, ir};
 use cretonne::print_errors::pretty_error;
+use cretonne::timing;
+use cretonne::dedup::Deduplicator;
+use cton_filetests::disasm::write_interleaved;
+use manifest::{FunctionRecord, Manifest, RelocRecord};
+use std::collections::HashMap;
 use std::path::Path;
 use utils::{read_to_string, parse_sets_and_isa};
 
 struct PrintRelocs {
     flag_print: bool,
+    relocs: Vec<RelocRecord>,
+}
+
+impl PrintRelocs {
+    fn record(
+        &mut self,
+        offset: binemit::CodeOffset,
+        r: binemit::Reloc,
+        target: String,
+        addend: i64,
+    ) {
+        self.relocs.push(RelocRecord {
+            offset,
+            kind: r.to_string(),
+            target,
+            addend,
+        });
+    }
 }
 
 impl binemit::RelocSink for PrintRelocs {
@@ -25,6 +49,8 @@ impl binemit::RelocSink for PrintRelocs {
         if self.flag_print {
             println!("reloc_ebb: {} {} at {}", r, offset, where_);
         }
+        // Relocations against EBBs are recorded with their code offset, not the EBB.
+        self.record(where_, r, format!("@{}", offset), 0);
     }
 
     fn reloc_external(
@@ -33,40 +59,79 @@ impl binemit::RelocSink for PrintRelocs {
         r: binemit::Reloc,
         name: &ir::ExternalName,
         addend: binemit::Addend,
+        symbol: binemit::SymbolInfo,
     ) {
         if self.flag_print {
-            println!("reloc_ebb: {} {} {} at {}", r, name, addend, where_);
+            println!("reloc_ebb: {} {} {} ({}) at {}", r, name, addend, symbol, where_);
         }
+        self.record(where_, r, name.to_string(), addend);
     }
 
     fn reloc_jt(&mut self, where_: binemit::CodeOffset, r: binemit::Reloc, jt: ir::JumpTable) {
         if self.flag_print {
             println!("reloc_ebb: {} {} at {}", r, jt, where_);
         }
+        self.record(where_, r, jt.to_string(), 0);
+    }
+}
+
+struct PrintTraps {
+    flag_print: bool,
+}
+
+impl binemit::TrapSink for PrintTraps {
+    fn trap(&mut self, offset: binemit::CodeOffset, _srcloc: ir::SourceLoc, code: ir::TrapCode) {
+        if self.flag_print {
+            println!("trap: {} at {}", code, offset);
+        }
     }
 }
 
 pub fn run(
     files: Vec<String>,
     flag_print: bool,
+    flag_print_size: bool,
+    flag_dedup: bool,
+    flag_disasm: bool,
     flag_set: &[String],
     flag_isa: &str,
+    flag_manifest: Option<&str>,
 ) -> Result<(), String> {
     let parsed = parse_sets_and_isa(flag_set, flag_isa)?;
+    let mut manifest = Manifest::default();
+    // Functions are only deduplicated against earlier functions compiled for the same ISA.
+    let mut dedups = if flag_dedup { Some(HashMap::new()) } else { None };
 
     for filename in files {
         let path = Path::new(&filename);
         let name = String::from(path.as_os_str().to_string_lossy());
-        handle_module(flag_print, &path.to_path_buf(), &name, parsed.as_fisa())?;
+        handle_module(
+            flag_print,
+            flag_print_size,
+            flag_disasm,
+            &path.to_path_buf(),
+            &name,
+            parsed.as_fisa(),
+            dedups.as_mut(),
+            &mut manifest,
+        )?;
+    }
+
+    if let Some(manifest_path) = flag_manifest {
+        manifest.write(manifest_path)?;
     }
     Ok(())
 }
 
 fn handle_module(
     flag_print: bool,
+    flag_print_size: bool,
+    flag_disasm: bool,
     path: &PathBuf,
     name: &str,
     fisa: FlagsOrIsa,
+    mut dedups: Option<&mut HashMap<&'static str, Deduplicator>>,
+    manifest: &mut Manifest,
 ) -> Result<(), String> {
     let buffer = read_to_string(&path).map_err(
         |e| format!("{}: {}", name, e),
@@ -84,20 +149,76 @@ fn handle_module(
     };
 
     for (func, _) in test_file.functions {
+        // Time each function separately, and add the times back for the `-T` report.
+        let outer_times = timing::take_current();
+
+        // A function identical to an earlier one is emitted as an alias instead of compiling it.
+        let alias = dedups.as_mut().and_then(|dedups| {
+            dedups
+                .entry(isa.name())
+                .or_insert_with(Deduplicator::new)
+                .insert(&func)
+        });
+        if let Some(target) = alias {
+            if flag_print {
+                println!("function {} is an alias of {}", func.name, target);
+            }
+            let times = timing::take_current();
+            timing::add_to_current(&outer_times);
+            timing::add_to_current(&times);
+            manifest.push(FunctionRecord {
+                file: String::from(name),
+                name: func.name.to_string(),
+                isa: String::from(isa.name()),
+                alias_of: Some(target.to_string()),
+                code_size: 0,
+                relocs: Vec::new(),
+                traps: Default::default(),
+                times,
+            });
+            continue;
+        }
+
         let mut context = Context::new();
         context.func = func;
         let size = context.compile(isa).map_err(|err| {
             pretty_error(&context.func, Some(isa), err)
         })?;
         if flag_print {
-            println!("{}", context.func.display(isa));
+            let annotations = Annotations {
+                sizes: flag_print_size,
+                offsets: flag_print_size,
+                bytes: false,
+            };
+            println!("{}", context.func.display_with_annotations(isa, annotations));
+        }
+        if flag_print_size {
+            println!("Function {} code size: {} bytes", context.func.name, size);
         }
 
         // Encode the result as machine code.
         let mut mem = Vec::new();
-        let mut relocs = PrintRelocs { flag_print };
+        let mut relocs = PrintRelocs {
+            flag_print,
+            relocs: Vec::new(),
+        };
+        let mut traps = PrintTraps { flag_print };
         mem.resize(size as usize, 0);
-        context.emit_to_memory(mem.as_mut_ptr(), &mut relocs, &*isa);
+        context.emit_to_memory(mem.as_mut_ptr(), &mut relocs, &mut traps, &*isa);
+
+        let times = timing::take_current();
+        timing::add_to_current(&outer_times);
+        timing::add_to_current(&times);
+        manifest.push(FunctionRecord {
+            file: String::from(name),
+            name: context.func.name.to_string(),
+            isa: String::from(isa.name()),
+            alias_of: None,
+            code_size: size,
+            relocs: relocs.relocs,
+            traps: context.trap_report(isa),
+            times,
+        });
 
         if flag_print {
             print!(".byte ");
@@ -112,6 +233,12 @@ fn handle_module(
             }
             println!();
         }
+
+        if flag_disasm {
+            let mut text = String::new();
+            write_interleaved(&mut text, isa, &context.func, &mem)?;
+            print!("{}", text);
+        }
     }
 
     Ok(())
@@ -10,16 +10,21 @@ extern crate tempdir;
 extern crate term;
 
 use cretonne::{VERSION, timing};
+use cretonne::cfg_printer::CFGAnnotations;
 use docopt::Docopt;
 use std::io::{self, Write};
 use std::process;
 
 mod utils;
 mod cat;
+mod fmt;
 mod print_cfg;
 mod rsfilecheck;
 mod wasm;
 mod compile;
+mod manifest;
+mod bench;
+mod reduce;
 
 const USAGE: &str = "
 Cretonne code generator utility
@@ -27,26 +32,50 @@ Cretonne code generator utility
 Usage:
     cton-util test [-vT] <file>...
     cton-util cat <file>...
+    cton-util fmt [-ir] <file>...
     cton-util filecheck [-v] <file>
-    cton-util print-cfg <file>...
-    cton-util compile [-vpT] [--set <set>]... [--isa <isa>] <file>...
-    cton-util wasm [-ctvpTs] [--set <set>]... [--isa <isa>] <file>...
+    cton-util print-cfg [--domtree] [--loops] [--live-ins] <file>...
+    cton-util compile [-vpsdDST] [--set <set>]... [--isa <isa>] [--manifest <path>] <file>...
+    cton-util wasm [-ctvpTsS] [--set <set>]... [--isa <isa>] <file>...
+    cton-util bench [-jTS] [--iterations <n>] [--set <set>]... [--isa <isa>] <file>...
+    cton-util reduce [-v] [--timeout <secs>] <file> [--] <command>...
     cton-util --help | --version
 
 Options:
     -v, --verbose   be more verbose
     -T, --time-passes
                     print pass timing report
+    -S, --stats     print the run counts, instruction counts, and spill counts
+                    of the compilation passes
     -t, --just-decode
                     just decode WebAssembly to Cretonne IL
     -s, --print-size
-                    prints generated code size
+                    prints generated code size, and the sizes and offsets of
+                    instructions in the printed Cretonne IL
     -c, --check-translation
                     just checks the correctness of Cretonne IL translated from WebAssembly
     -p, --print     print the resulting Cretonne IL
+    -i, --in-place  rewrite the files instead of printing them
+    -r, --renumber  renumber values and EBBs in order of definition
+    -d, --dedup     emit functions identical to an earlier function as aliases
+                    of it instead of compiling them again
+    -D, --disasm    print the disassembled machine code of each instruction
+                    (requires building with the disas feature)
+    --domtree       add the dominator tree edges to the printed CFG
+    --loops         group the EBBs of each loop in the printed CFG
+    --live-ins      list the values live into each EBB in the printed CFG
     -h, --help      print this help message
     --set=<set>     configure Cretonne settings
     --isa=<isa>     specify the Cretonne ISA
+    -j, --json      print the benchmark report as JSON
+    -n, --iterations=<n>
+                    number of times to compile the functions [default: 10]
+    --timeout=<secs>
+                    number of seconds after which the reduction command is
+                    killed and considered to succeed [default: 10]
+    --manifest=<path>
+                    write a JSON manifest of the compiled functions to
+                    <path>, or to stdout if <path> is -
     --version       print the Cretonne version
 
 ";
@@ -55,18 +84,34 @@ Options:
 struct Args {
     cmd_test: bool,
     cmd_cat: bool,
+    cmd_fmt: bool,
     cmd_filecheck: bool,
     cmd_print_cfg: bool,
     cmd_compile: bool,
     cmd_wasm: bool,
+    cmd_bench: bool,
+    cmd_reduce: bool,
     arg_file: Vec<String>,
+    arg_command: Vec<String>,
     flag_just_decode: bool,
     flag_check_translation: bool,
     flag_print: bool,
+    flag_in_place: bool,
+    flag_renumber: bool,
+    flag_dedup: bool,
+    flag_disasm: bool,
+    flag_domtree: bool,
+    flag_loops: bool,
+    flag_live_ins: bool,
     flag_verbose: bool,
     flag_set: Vec<String>,
     flag_isa: String,
+    flag_manifest: Option<String>,
+    flag_json: bool,
+    flag_iterations: usize,
+    flag_timeout: u64,
     flag_time_passes: bool,
+    flag_stats: bool,
     flag_print_size: bool,
 }
 
@@ -89,16 +134,29 @@ fn cton_util() -> CommandResult {
         cton_filetests::run(args.flag_verbose, &args.arg_file).map(|_time| ())
     } else if args.cmd_cat {
         cat::run(&args.arg_file)
+    } else if args.cmd_fmt {
+        fmt::run(&args.arg_file, args.flag_in_place, args.flag_renumber)
     } else if args.cmd_filecheck {
         rsfilecheck::run(&args.arg_file, args.flag_verbose)
     } else if args.cmd_print_cfg {
-        print_cfg::run(&args.arg_file)
+        print_cfg::run(
+            &args.arg_file,
+            CFGAnnotations {
+                domtree: args.flag_domtree,
+                loops: args.flag_loops,
+                live_ins: args.flag_live_ins,
+            },
+        )
     } else if args.cmd_compile {
         compile::run(
             args.arg_file,
             args.flag_print,
+            args.flag_print_size,
+            args.flag_dedup,
+            args.flag_disasm,
             &args.flag_set,
             &args.flag_isa,
+            args.flag_manifest.as_ref().map(|s| s.as_str()),
         )
     } else if args.cmd_wasm {
         wasm::run(
@@ -111,13 +169,32 @@ fn cton_util() -> CommandResult {
             &args.flag_isa,
             args.flag_print_size,
         )
+    } else if args.cmd_bench {
+        bench::run(
+            &args.arg_file,
+            args.flag_json,
+            args.flag_iterations,
+            &args.flag_set,
+            &args.flag_isa,
+        )
+    } else if args.cmd_reduce {
+        reduce::run(
+            &args.arg_file[0],
+            &args.arg_command,
+            args.flag_timeout,
+            args.flag_verbose,
+        )
     } else {
         // Debugging / shouldn't happen with proper command line handling above.
         Err(format!("Unhandled args: {:?}", args))
     };
 
+    let times = timing::take_current();
     if args.flag_time_passes {
-        print!("{}", timing::take_current());
+        print!("{}", times);
+    }
+    if args.flag_stats {
+        print!("{}", times.display_stats());
     }
 
     result
@@ -0,0 +1,36 @@
+//! The `fmt` sub-command.
+//!
+//! Read a sequence of Cretonne IL files and print them with canonical formatting, or rewrite the
+//! files in place. Unlike `cat`, this preserves comments and the structure of the files.
+
+use cton_reader::format_source;
+use std::fs::File;
+use std::io::Write;
+use CommandResult;
+use utils::read_to_string;
+
+pub fn run(files: &[String], in_place: bool, renumber: bool) -> CommandResult {
+    for f in files {
+        fmt_one(f, in_place, renumber)?
+    }
+    Ok(())
+}
+
+fn fmt_one(filename: &str, in_place: bool, renumber: bool) -> CommandResult {
+    let buffer = read_to_string(&filename).map_err(
+        |e| format!("{}: {}", filename, e),
+    )?;
+    let formatted = format_source(&buffer, renumber).map_err(
+        |e| format!("{}: {}", filename, e),
+    )?;
+
+    if !in_place {
+        print!("{}", formatted);
+    } else if formatted != buffer {
+        File::create(filename)
+            .and_then(|mut file| file.write_all(formatted.as_bytes()))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,191 @@
+//! Machine-readable manifest of compiled functions.
+//!
+//! The `compile` command can write a JSON manifest describing the code generated for each input
+//! function, so scripts tracking the code quality across commits don't need to parse the output
+//! meant for humans. The manifest has this shape:
+//!
+//! ```text
+//! {
+//!   "version": "0.4.1",
+//!   "functions": [
+//!     {
+//!       "file": "foo.cton",
+//!       "name": "%foo",
+//!       "isa": "intel",
+//!       "alias_of": null,
+//!       "code_size": 42,
+//!       "relocations": [
+//!         { "offset": 7, "kind": "Abs8", "target": "%bar", "addend": 0 }
+//!       ],
+//!       "traps": [ { "offset": 30, "code": "heap_oob" } ],
+//!       "timing": { "legalize": 0.000021, "regalloc": 0.000112 }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The target of a relocation against an EBB is `@N` where `N` is the code offset of the EBB, and
+//! the target of a relocation against a jump table is `jtN`. The pass timings are in seconds.
+//!
+//! When the `--dedup` option is used, a function that is identical to an earlier function isn't
+//! compiled. Its `alias_of` is the name of the earlier function, and it has no code of its own.
+
+use cretonne::binemit::{CodeOffset, TrapReport};
+use cretonne::timing::PassTimes;
+use cretonne::VERSION;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// A relocation in the code of a function.
+pub struct RelocRecord {
+    pub offset: CodeOffset,
+    pub kind: String,
+    pub target: String,
+    pub addend: i64,
+}
+
+/// The artifacts produced for a single function.
+pub struct FunctionRecord {
+    pub file: String,
+    pub name: String,
+    pub isa: String,
+    pub alias_of: Option<String>,
+    pub code_size: CodeOffset,
+    pub relocs: Vec<RelocRecord>,
+    pub traps: TrapReport,
+    pub times: PassTimes,
+}
+
+/// The artifacts produced for all the compiled functions.
+#[derive(Default)]
+pub struct Manifest {
+    functions: Vec<FunctionRecord>,
+}
+
+impl Manifest {
+    /// Add the artifacts of a function.
+    pub fn push(&mut self, func: FunctionRecord) {
+        self.functions.push(func);
+    }
+
+    /// Write the manifest to the file at `path`, or to stdout if `path` is `-`.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let json = self.to_json();
+        let res = if path == "-" {
+            io::stdout().write_all(json.as_bytes())
+        } else {
+            File::create(path).and_then(|mut file| file.write_all(json.as_bytes()))
+        };
+        res.map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Render the manifest as JSON.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\n");
+        out.push_str(&format!("  \"version\": {},\n", quote(VERSION)));
+        out.push_str("  \"functions\": [");
+        for (idx, func) in self.functions.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            out.push_str("\n    ");
+            write_function(&mut out, func);
+        }
+        if !self.functions.is_empty() {
+            out.push_str("\n  ");
+        }
+        out.push_str("]\n}\n");
+        out
+    }
+}
+
+/// Append the JSON object describing `func` to `out`.
+fn write_function(out: &mut String, func: &FunctionRecord) {
+    out.push_str("{\n");
+    let _ = writeln!(out, "      \"file\": {},", quote(&func.file));
+    let _ = writeln!(out, "      \"name\": {},", quote(&func.name));
+    let _ = writeln!(out, "      \"isa\": {},", quote(&func.isa));
+    let alias_of = func.alias_of.as_ref().map_or_else(|| String::from("null"), |s| quote(s));
+    let _ = writeln!(out, "      \"alias_of\": {},", alias_of);
+    let _ = writeln!(out, "      \"code_size\": {},", func.code_size);
+
+    let relocs: Vec<String> = func.relocs
+        .iter()
+        .map(|r| {
+            format!(
+                "{{ \"offset\": {}, \"kind\": {}, \"target\": {}, \"addend\": {} }}",
+                r.offset,
+                quote(&r.kind),
+                quote(&r.target),
+                r.addend
+            )
+        })
+        .collect();
+    let _ = writeln!(out, "      \"relocations\": {},", list(&relocs));
+
+    let traps: Vec<String> = func.traps
+        .sites
+        .iter()
+        .map(|site| {
+            format!(
+                "{{ \"offset\": {}, \"code\": {} }}",
+                site.offset,
+                quote(&site.code.to_string())
+            )
+        })
+        .collect();
+    let _ = writeln!(out, "      \"traps\": {},", list(&traps));
+
+    let times: Vec<String> = func.times
+        .totals()
+        .iter()
+        .map(|&(pass, time)| format!("{}: {:.6}", quote(pass), seconds(time)))
+        .collect();
+    let _ = writeln!(out, "      \"timing\": {{ {} }}", times.join(", "));
+    out.push_str("    }");
+}
+
+/// Format a JSON array of the already formatted `items`.
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        return String::from("[]");
+    }
+    let mut out = String::from("[\n");
+    for (idx, item) in items.iter().enumerate() {
+        if idx > 0 {
+            out.push_str(",\n");
+        }
+        out.push_str("        ");
+        out.push_str(item);
+    }
+    out.push_str("\n      ]");
+    out
+}
+
+/// Get `time` in seconds.
+pub fn seconds(time: Duration) -> f64 {
+    time.as_secs() as f64 + f64::from(time.subsec_nanos()) * 1e-9
+}
+
+/// Format `s` as a JSON string literal.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -4,21 +4,21 @@
 //! in graphviz format.
 
 use CommandResult;
-use cretonne::cfg_printer::CFGPrinter;
+use cretonne::cfg_printer::{CFGAnnotations, CFGPrinter};
 use cton_reader::parse_functions;
 use utils::read_to_string;
 
-pub fn run(files: &[String]) -> CommandResult {
+pub fn run(files: &[String], annotations: CFGAnnotations) -> CommandResult {
     for (i, f) in files.into_iter().enumerate() {
         if i != 0 {
             println!();
         }
-        print_cfg(f)?
+        print_cfg(f, annotations)?
     }
     Ok(())
 }
 
-fn print_cfg(filename: &str) -> CommandResult {
+fn print_cfg(filename: &str, annotations: CFGAnnotations) -> CommandResult {
     let buffer = read_to_string(filename).map_err(
         |e| format!("{}: {}", filename, e),
     )?;
@@ -30,7 +30,7 @@ fn print_cfg(filename: &str) -> CommandResult {
         if idx != 0 {
             println!();
         }
-        print!("{}", CFGPrinter::new(&func));
+        print!("{}", CFGPrinter::with_annotations(&func, annotations));
     }
 
     Ok(())
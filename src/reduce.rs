@@ -0,0 +1,477 @@
+//! CLI tool to reduce a test case.
+//!
+//! Reads a Cretonne IL file exposing a bug, and a command that fails when it is run on that file.
+//! The functions in the file are repeatedly simplified, and each simplification is kept if the
+//! command still fails:
+//!
+//! - Functions and EBBs are removed. The jumps to a removed EBB become traps.
+//! - Conditional branches are removed, or replaced by a jump.
+//! - Instructions are removed, or replaced by a constant when their result is used.
+//! - EBB parameters, including the function parameters, are replaced by constants.
+//!
+//! The simplifications are tried on large groups of entities first, and then on smaller and
+//! smaller groups, so functions with thousands of instructions shrink quickly. The reduced file is
+//! printed when no simplification is left.
+//!
+//! The command is run with the path of a copy of the simplified file appended to its arguments.
+//! Use `--` to keep its options from being parsed by `cton-util`:
+//!
+//! ```text
+//! cton-util reduce bug.cton -- cton-util compile --isa intel
+//! ```
+//!
+//! Any failure of the command counts, so a command that must fail in a specific way is best
+//! wrapped in a script checking its output. The text outside the functions, like the test
+//! commands and the `run:` directives following the functions, is kept as is, but the comments
+//! inside the functions are lost. The comments following a removed function are removed with it.
+
+use cretonne::cursor::{Cursor, FuncCursor};
+use cretonne::ir::immediates::{Ieee32, Ieee64};
+use cretonne::ir::instructions::BranchInfo;
+use cretonne::ir::types::{F32, F64};
+use cretonne::ir::{ArgumentPurpose, DataFlowGraph, Ebb, Function, Inst, InstBuilder, Opcode,
+                   TrapCode, Type, Value, ValueDef};
+use cretonne::isa::TargetIsa;
+use cretonne::settings::FlagsOrIsa;
+use cretonne::verify_function;
+use cton_reader::ast::Span;
+use cton_reader::{parse_test, IsaSpec, TestFile};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
+use utils::read_to_string;
+use CommandResult;
+
+pub fn run(
+    filename: &str,
+    command: &[String],
+    flag_timeout: u64,
+    flag_verbose: bool,
+) -> CommandResult {
+    let text = read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    let TestFile {
+        isa_spec,
+        functions,
+        ..
+    } = parse_test(&text).map_err(|e| format!("{}: {}", filename, e))?;
+    let fisa = match (isa_spec.unique_isa(), &isa_spec) {
+        (Some(isa), _) => FlagsOrIsa::from(isa),
+        (None, &IsaSpec::Some(ref isas)) => FlagsOrIsa::from(isas[0].flags()),
+        (None, &IsaSpec::None(ref flags)) => FlagsOrIsa::from(flags),
+    };
+
+    // The candidates are written with the same file name, which may matter to the command.
+    let tmpdir = TempDir::new("cton-reduce").map_err(|e| e.to_string())?;
+    let name = Path::new(filename).file_name().ok_or("missing file name")?;
+
+    let mut reducer = Reducer {
+        text: &text,
+        spans: functions.iter().map(|&(_, ref d)| d.syntax.span).collect(),
+        verified: functions
+            .iter()
+            .map(|&(ref func, _)| verify_function(func, fisa).is_ok())
+            .collect(),
+        funcs: functions.into_iter().map(|(func, _)| Some(func)).collect(),
+        isa: fisa.isa,
+        fisa,
+        command,
+        path: tmpdir.path().join(name),
+        timeout: Duration::from_secs(flag_timeout),
+        verbose: flag_verbose,
+        runs: 0,
+    };
+
+    let original = reducer.render(None);
+    if !reducer.test(&original)? {
+        return Err(format!("{}: the command doesn't fail on this file", filename));
+    }
+    reducer.reduce()?;
+    print!("{}", reducer.render(None));
+    Ok(())
+}
+
+/// A test file being reduced.
+struct Reducer<'a> {
+    /// The original text of the file.
+    text: &'a str,
+
+    /// The spans of the functions in `text`.
+    spans: Vec<Span>,
+
+    /// Whether each of the original functions passes the verifier. The simplifications of those
+    /// must pass it too, or the command could fail on a simplification for an unrelated reason.
+    verified: Vec<bool>,
+
+    /// The simplified functions, or `None` for the removed ones.
+    funcs: Vec<Option<Function>>,
+
+    /// The ISA the functions are printed for.
+    isa: Option<&'a TargetIsa>,
+
+    /// The flags and ISA the functions are verified with.
+    fisa: FlagsOrIsa<'a>,
+
+    /// The command and arguments to run on the simplified file.
+    command: &'a [String],
+
+    /// The path the simplified file is written to.
+    path: PathBuf,
+
+    /// The time after which the command is killed and considered to succeed.
+    timeout: Duration,
+
+    verbose: bool,
+
+    /// The number of times the command was run.
+    runs: usize,
+}
+
+impl<'a> Reducer<'a> {
+    /// Render the file with the simplified functions. If `replace` is `(idx, func)`, the function
+    /// `idx` is replaced by `func`.
+    fn render(&self, replace: Option<(usize, Option<&Function>)>) -> String {
+        let mut out = String::with_capacity(self.text.len());
+        let mut pos = 0;
+        for (idx, span) in self.spans.iter().enumerate() {
+            out.push_str(&self.text[pos..span.start]);
+            let func = match replace {
+                Some((i, func)) if i == idx => func,
+                _ => self.funcs[idx].as_ref(),
+            };
+            match func {
+                Some(func) => {
+                    out.push_str(func.display(self.isa).to_string().trim_right());
+                    pos = span.end;
+                }
+                // The comments following a removed function belong to it.
+                None => pos = self.spans.get(idx + 1).map_or(self.text.len(), |s| s.start),
+            }
+        }
+        out.push_str(&self.text[pos..]);
+        out
+    }
+
+    /// Run the command on `text`, and return whether it failed.
+    fn test(&mut self, text: &str) -> Result<bool, String> {
+        self.runs += 1;
+        File::create(&self.path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg(&self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.command[0], e))?;
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                return Ok(!status.success());
+            }
+            if start.elapsed() > self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(false);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Check if the command still fails when the function `idx` is replaced by `func`.
+    fn fails(&mut self, idx: usize, func: Option<&Function>) -> Result<bool, String> {
+        if let Some(func) = func {
+            if self.verified[idx] && verify_function(func, self.fisa).is_err() {
+                return Ok(false);
+            }
+        }
+        let text = self.render(Some((idx, func)));
+        self.test(&text)
+    }
+
+    /// Simplify the functions until no simplification is left.
+    fn reduce(&mut self) -> Result<(), String> {
+        for idx in 0..self.funcs.len() {
+            if self.funcs.iter().filter(|f| f.is_some()).count() > 1 && self.fails(idx, None)? {
+                self.funcs[idx] = None;
+            }
+        }
+
+        for idx in 0..self.funcs.len() {
+            while self.funcs[idx].is_some() {
+                let mut changed = self.pass(idx, removable_ebbs, remove_ebbs)?;
+                changed |= self.pass(idx, conditional_branches, replace_by_jumps)?;
+                changed |= self.pass(idx, simplifiable_insts, simplify_insts)?;
+                changed |= self.pass(idx, removable_params, remove_params)?;
+                if self.verbose {
+                    let func = self.funcs[idx].as_ref().unwrap();
+                    let insts: usize = func.layout
+                        .ebbs()
+                        .map(|ebb| func.layout.ebb_insts(ebb).count())
+                        .sum();
+                    eprintln!(
+                        "{}: {} instructions left after {} runs",
+                        func.name,
+                        insts,
+                        self.runs
+                    );
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the simplification `apply` to the entities of function `idx` listed by `items`.
+    ///
+    /// The simplification is applied to groups of entities, starting with a single group of all
+    /// of them, and halving the size of the groups until it is applied to each entity alone.
+    /// Returns whether the function changed.
+    fn pass<T, I, A>(&mut self, idx: usize, items: I, apply: A) -> Result<bool, String>
+    where
+        T: Copy,
+        I: Fn(&Function) -> Vec<T>,
+        A: Fn(&mut Function, &[T]) -> bool,
+    {
+        let mut changed = false;
+        let mut size = items(self.funcs[idx].as_ref().unwrap()).len();
+        while size > 0 {
+            let mut start = 0;
+            loop {
+                let mut candidate = self.funcs[idx].clone().unwrap();
+                let list = items(&candidate);
+                if start >= list.len() {
+                    break;
+                }
+                let end = list.len().min(start + size);
+                if apply(&mut candidate, &list[start..end]) && self.fails(idx, Some(&candidate))? {
+                    // The simplified entities are no longer listed, so the next group starts at
+                    // the same position.
+                    self.funcs[idx] = Some(candidate);
+                    changed = true;
+                } else {
+                    start = end;
+                }
+            }
+            size /= 2;
+        }
+        Ok(changed)
+    }
+}
+
+/// Can a zero constant of type `ty` be created?
+fn has_zero(ty: Type) -> bool {
+    ty.lane_count() == 1 && (ty.is_int() || ty.is_bool() || ty == F32 || ty == F64)
+}
+
+/// Build a zero constant of type `ty`, which must satisfy `has_zero`.
+fn zero<'f, B: InstBuilder<'f>>(ins: B, ty: Type) -> Value {
+    if ty.is_int() {
+        ins.iconst(ty, 0)
+    } else if ty.is_bool() {
+        ins.bconst(ty, false)
+    } else if ty == F32 {
+        ins.f32const(Ieee32::with_bits(0))
+    } else {
+        ins.f64const(Ieee64::with_bits(0))
+    }
+}
+
+/// Get the instructions of `func` in layout order.
+fn insts(func: &Function) -> Vec<Inst> {
+    func.layout
+        .ebbs()
+        .flat_map(|ebb| func.layout.ebb_insts(ebb))
+        .collect()
+}
+
+/// Get the values used by the instructions of `func`.
+fn used_values(func: &Function) -> HashSet<Value> {
+    insts(func)
+        .into_iter()
+        .flat_map(|inst| func.dfg.inst_args(inst))
+        .map(|&arg| func.dfg.resolve_aliases(arg))
+        .collect()
+}
+
+/// Get the destination of `inst` if it is a branch or jump to a single EBB.
+fn branch_destination(dfg: &DataFlowGraph, inst: Inst) -> Option<Ebb> {
+    match dfg.analyze_branch(inst) {
+        BranchInfo::SingleDest(dest, _) => Some(dest),
+        _ => None,
+    }
+}
+
+/// Get the EBBs that can be removed: all but the entry block and the jump table destinations.
+fn removable_ebbs(func: &Function) -> Vec<Ebb> {
+    func.layout
+        .ebbs()
+        .skip(1)
+        .filter(|&ebb| {
+            !func.jump_tables.keys().any(|jt| func.jump_tables[jt].branches_to(ebb))
+        })
+        .collect()
+}
+
+/// Remove `ebbs` from `func`, turning the jumps to them into traps.
+fn remove_ebbs(func: &mut Function, ebbs: &[Ebb]) -> bool {
+    for inst in insts(func) {
+        match branch_destination(&func.dfg, inst) {
+            Some(dest) if ebbs.contains(&dest) => {}
+            _ => continue,
+        }
+        if func.dfg[inst].opcode().is_terminator() {
+            func.dfg.replace(inst).trap(TrapCode::User(0));
+        } else {
+            func.layout.remove_inst(inst);
+        }
+    }
+    for &ebb in ebbs {
+        while let Some(inst) = func.layout.first_inst(ebb) {
+            func.layout.remove_inst(inst);
+        }
+        func.layout.remove_ebb(ebb);
+    }
+    true
+}
+
+/// Get the conditional branches of `func`.
+fn conditional_branches(func: &Function) -> Vec<Inst> {
+    insts(func)
+        .into_iter()
+        .filter(|&inst| {
+            !func.dfg[inst].opcode().is_terminator() &&
+                branch_destination(&func.dfg, inst).is_some()
+        })
+        .collect()
+}
+
+/// Replace the conditional branches `insts` by jumps, removing the instructions following them.
+fn replace_by_jumps(func: &mut Function, insts: &[Inst]) -> bool {
+    for &inst in insts {
+        // An earlier replacement may have removed the branch.
+        if func.layout.inst_ebb(inst).is_none() {
+            continue;
+        }
+        while let Some(next) = func.layout.next_inst(inst) {
+            func.layout.remove_inst(next);
+        }
+        let dest = branch_destination(&func.dfg, inst).unwrap();
+        let args = func.dfg.inst_variable_args(inst).to_vec();
+        func.dfg.replace(inst).jump(dest, &args);
+    }
+    true
+}
+
+/// Is `opcode` a constant?
+fn is_constant(opcode: Opcode) -> bool {
+    match opcode {
+        Opcode::Iconst | Opcode::Bconst | Opcode::F32const | Opcode::F64const => true,
+        _ => false,
+    }
+}
+
+/// Get the instructions that can be removed or replaced by a constant: all the instructions with
+/// unused results except the terminators, and the other instructions computing a single value.
+fn simplifiable_insts(func: &Function) -> Vec<Inst> {
+    let used = used_values(func);
+    insts(func)
+        .into_iter()
+        .filter(|&inst| {
+            let opcode = func.dfg[inst].opcode();
+            let results = func.dfg.inst_results(inst);
+            if opcode.is_terminator() {
+                false
+            } else if results.iter().all(|v| !used.contains(v)) {
+                true
+            } else {
+                results.len() == 1 && !is_constant(opcode) &&
+                    has_zero(func.dfg.value_type(results[0]))
+            }
+        })
+        .collect()
+}
+
+/// Remove the instructions `insts` if their results are unused, or replace them by a constant.
+fn simplify_insts(func: &mut Function, insts: &[Inst]) -> bool {
+    let used = used_values(func);
+    for &inst in insts {
+        let results = func.dfg.inst_results(inst).to_vec();
+        if results.iter().all(|v| !used.contains(v)) {
+            func.layout.remove_inst(inst);
+        } else {
+            let ty = func.dfg.value_type(results[0]);
+            zero(func.dfg.replace(inst), ty);
+        }
+    }
+    true
+}
+
+/// Get the EBB parameters that can be replaced by a constant.
+///
+/// The entry block parameters can only be removed along with the function parameters, so they
+/// are skipped when the signature has been legalized.
+fn removable_params(func: &Function) -> Vec<Value> {
+    let entry = func.layout.entry_block();
+    let sig_params = &func.signature.params;
+    func.layout
+        .ebbs()
+        .flat_map(|ebb| func.dfg.ebb_params(ebb).iter().cloned().enumerate())
+        .filter(|&(num, param)| {
+            let ebb = func.dfg.value_def(param).unwrap_ebb();
+            if Some(ebb) == entry &&
+                (func.dfg.num_ebb_params(ebb) != sig_params.len() ||
+                     sig_params[num].purpose != ArgumentPurpose::Normal)
+            {
+                return false;
+            }
+            has_zero(func.dfg.value_type(param))
+        })
+        .map(|(_, param)| param)
+        .collect()
+}
+
+/// Replace the EBB parameters `params` by constants, removing the corresponding arguments of the
+/// branches.
+fn remove_params(func: &mut Function, params: &[Value]) -> bool {
+    let entry = func.layout.entry_block();
+    for &param in params {
+        let (ebb, num) = match func.dfg.value_def(param) {
+            ValueDef::Param(ebb, num) => (ebb, num),
+            ValueDef::Result(..) => panic!("{} must be an EBB parameter", param),
+        };
+        if Some(ebb) == entry {
+            func.signature.params.remove(num);
+        }
+        for inst in insts(func) {
+            if branch_destination(&func.dfg, inst) == Some(ebb) {
+                let fixed_args = func.dfg[inst]
+                    .opcode()
+                    .constraints()
+                    .fixed_value_arguments();
+                let mut args = func.dfg[inst].take_value_list().expect(
+                    "Branches must have value lists.",
+                );
+                args.remove(fixed_args + num, &mut func.dfg.value_lists);
+                func.dfg[inst].put_value_list(args);
+            }
+        }
+
+        func.dfg.remove_ebb_param(param);
+        let ty = func.dfg.value_type(param);
+        let value = {
+            let mut pos = FuncCursor::new(func).at_first_insertion_point(ebb);
+            zero(pos.ins(), ty)
+        };
+        func.dfg.change_to_alias(param, value);
+    }
+    true
+}
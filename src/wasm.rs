@@ -62,6 +62,31 @@ pub fn run(
     Ok(())
 }
 
+/// Read the WebAssembly module at `path`, converting it to the binary format with `wat2wasm` if it
+/// is in the text format.
+pub fn read_wasm(path: &Path) -> Result<Vec<u8>, String> {
+    let mut data = read_to_end(path).map_err(|err| String::from(err.description()))?;
+    if !data.starts_with(&[b'\0', b'a', b's', b'm']) {
+        let tmp_dir = TempDir::new("cretonne-wasm").unwrap();
+        let file_path = tmp_dir.path().join("module.wasm");
+        File::create(file_path.clone()).unwrap();
+        Command::new("wat2wasm")
+            .arg(path)
+            .arg("-o")
+            .arg(file_path.to_str().unwrap())
+            .output()
+            .or_else(|e| if let io::ErrorKind::NotFound = e.kind() {
+                return Err(String::from("wat2wasm not found"));
+            } else {
+                return Err(String::from(e.description()));
+            })?;
+        data = read_to_end(file_path).map_err(
+            |err| String::from(err.description()),
+        )?;
+    }
+    Ok(data)
+}
+
 fn handle_module(
     flag_verbose: bool,
     flag_just_decode: bool,
@@ -81,28 +106,7 @@ fn handle_module(
     vprint!(flag_verbose, "Translating... ");
     terminal.reset().unwrap();
 
-    let mut data = read_to_end(path.clone()).map_err(|err| {
-        String::from(err.description())
-    })?;
-    if !data.starts_with(&[b'\0', b'a', b's', b'm']) {
-        let tmp_dir = TempDir::new("cretonne-wasm").unwrap();
-        let file_path = tmp_dir.path().join("module.wasm");
-        File::create(file_path.clone()).unwrap();
-        Command::new("wat2wasm")
-            .arg(path.clone())
-            .arg("-o")
-            .arg(file_path.to_str().unwrap())
-            .output()
-            .or_else(|e| if let io::ErrorKind::NotFound = e.kind() {
-                return Err(String::from("wat2wasm not found"));
-            } else {
-                return Err(String::from(e.description()));
-            })?;
-        data = read_to_end(file_path).map_err(
-            |err| String::from(err.description()),
-        )?;
-    }
-
+    let data = read_wasm(path)?;
     let mut dummy_environ = DummyEnvironment::with_flags(fisa.flags.clone());
     translate_module(&data, &mut dummy_environ)?;
 